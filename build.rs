@@ -0,0 +1,24 @@
+//! Build script
+//!
+//! With the `ffi` feature, generates the C header of the `ffi` module into
+//! `OUT_DIR`. The copy in `include/rust_ia64.h` is checked against it by the
+//! `ffi` tests.
+
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+    #[cfg(feature = "ffi")]
+    {
+        println!("cargo:rerun-if-changed=cbindgen.toml");
+        println!("cargo:rerun-if-changed=src/ffi.rs");
+        let dir = std::env::var("CARGO_MANIFEST_DIR").unwrap();
+        let out = std::env::var("OUT_DIR").unwrap();
+        let config = cbindgen::Config::from_file(format!("{}/cbindgen.toml", dir))
+            .expect("cbindgen.toml is readable");
+        cbindgen::Builder::new()
+            .with_src(format!("{}/src/ffi.rs", dir))
+            .with_config(config)
+            .generate()
+            .expect("the ffi module has a C interface")
+            .write_to_file(format!("{}/rust_ia64.h", out));
+    }
+}
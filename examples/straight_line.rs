@@ -0,0 +1,59 @@
+//! Time straight-line guest code
+//!
+//! Runs a long block of nop bundles ending in an exit system call and
+//! reports the bundle rate. Build with and without `--features decode-ahead`
+//! to compare:
+//!
+//! ```text
+//! cargo run --release --example straight_line
+//! cargo run --release --example straight_line --features decode-ahead
+//! ```
+
+use rust_ia64::cpu::syscall::SyscallNumber;
+use rust_ia64::emulator::{Emulator, StopReason, SYSCALL_BREAK_IMM, SYSCALL_NUMBER_REG};
+use std::time::Instant;
+
+/// Address the guest code is loaded at
+const BASE: u64 = 0x10000;
+
+/// Number of nop bundles before the exit
+const BUNDLES: usize = 1 << 18;
+
+/// Encode an MII bundle from three slots
+fn encode_mii(slots: [u64; 3]) -> [u8; 16] {
+    let bits = ((slots[0] as u128) << 5) | ((slots[1] as u128) << 46) | ((slots[2] as u128) << 87);
+    bits.to_le_bytes()
+}
+
+fn main() {
+    let nop = 0x01 << 27;
+    let syscall = ((SYSCALL_BREAK_IMM & 0xFFFFF) << 6) | (((SYSCALL_BREAK_IMM >> 20) & 1) << 36);
+
+    let mut image = Vec::with_capacity((BUNDLES + 1) * 16);
+    for _ in 0..BUNDLES {
+        image.extend_from_slice(&encode_mii([nop, nop, nop]));
+    }
+    image.extend_from_slice(&encode_mii([syscall, nop, nop]));
+
+    let mut emu = Emulator::new();
+    emu.load_flat_image(BASE, &image, BASE)
+        .expect("failed to load image");
+    emu.cpu.gr[SYSCALL_NUMBER_REG] = SyscallNumber::Exit as u64;
+
+    let start = Instant::now();
+    let reason = emu.run().expect("guest faulted");
+    let elapsed = start.elapsed();
+    assert_eq!(reason, StopReason::Exited(0));
+
+    println!(
+        "decode-ahead {}: {} bundles in {:.2?} ({:.0} bundles/s)",
+        if cfg!(feature = "decode-ahead") {
+            "on"
+        } else {
+            "off"
+        },
+        BUNDLES + 1,
+        elapsed,
+        (BUNDLES + 1) as f64 / elapsed.as_secs_f64()
+    );
+}
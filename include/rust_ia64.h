@@ -0,0 +1,218 @@
+/* rust-ia64 C bindings; generated by cbindgen from src/ffi.rs, do not edit */
+
+#ifndef RUST_IA64_H
+#define RUST_IA64_H
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+// Version of the C interface, bumped on incompatible changes
+#define IA64_FFI_VERSION 1
+
+// Outcome of a call
+typedef enum Ia64Status {
+  // The call succeeded
+  IA64_STATUS_OK = 0,
+  // The call failed; see [`ia64_last_error`]
+  IA64_STATUS_ERROR = 1,
+  // A required pointer was null or a string was not UTF-8
+  IA64_STATUS_INVALID_ARGUMENT = 2,
+} Ia64Status;
+
+// Why the machine stopped
+typedef enum Ia64StopKind {
+  // The machine has not stopped, e.g. a run hit its bundle limit
+  IA64_STOP_KIND_NONE = 0,
+  // The guest called exit; `code` is the status
+  IA64_STOP_KIND_EXITED = 1,
+  // The guest executed a break that is not a system call; `code` is its
+  // immediate
+  IA64_STOP_KIND_BREAK = 2,
+  // The guest reached a panic hook
+  IA64_STOP_KIND_PANIC = 3,
+  // The guest looped without making progress
+  IA64_STOP_KIND_LIVELOCK = 4,
+  // A data debug register matched; `addr` is the address accessed and
+  // `code` the value
+  IA64_STOP_KIND_DATA_MATCH = 5,
+  // A watchdog set to stop the machine expired
+  IA64_STOP_KIND_WATCHDOG_EXPIRED = 6,
+} Ia64StopKind;
+
+// Machine driven through the C interface
+typedef struct Ia64Emulator Ia64Emulator;
+
+// Stop of the machine
+typedef struct Ia64Stop {
+  // Why the machine stopped
+  enum Ia64StopKind kind;
+  // Exit status, break immediate or matched value, by kind
+  uint64_t code;
+  // Address of a data match
+  uint64_t addr;
+} Ia64Stop;
+
+// Callback receiving what the guest writes to descriptor `fd`, 1 or 2
+typedef void (*Ia64ConsoleCallback)(void *user, int32_t fd, const uint8_t *data, size_t len);
+
+// Callback told of each stop of the machine
+typedef void (*Ia64StopCallback)(void *user, const struct Ia64Stop *stop);
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+// Version of the C interface the library implements
+uint32_t ia64_version(void);
+
+// Create a machine with empty memory
+//
+// Free it with [`ia64_emulator_free`].
+struct Ia64Emulator *ia64_emulator_new(void);
+
+// Free a machine
+//
+// # Safety
+//
+// `emu` must be null or a handle from [`ia64_emulator_new`] that has not
+// been freed.
+void ia64_emulator_free(struct Ia64Emulator *emu);
+
+// Description of the last failure on `emu`, or null
+//
+// The string stays valid until the next call on the handle.
+//
+// # Safety
+//
+// `emu` must be null or a live handle.
+const char *ia64_last_error(const struct Ia64Emulator *emu);
+
+// Load an ELF executable of `len` bytes and start execution at its entry
+// point, relocating a position-independent image to `base`
+//
+// # Safety
+//
+// `emu` must be a live handle and `data` point to `len` readable bytes.
+enum Ia64Status ia64_load_elf(struct Ia64Emulator *emu,
+                              const uint8_t *data,
+                              size_t len,
+                              uint64_t base);
+
+// Load a flat binary image of `len` bytes at `base` and start execution
+// at `entry`
+//
+// # Safety
+//
+// `emu` must be a live handle and `data` point to `len` readable bytes.
+enum Ia64Status ia64_load_flat_image(struct Ia64Emulator *emu,
+                                     uint64_t base,
+                                     const uint8_t *data,
+                                     size_t len,
+                                     uint64_t entry);
+
+// Map the stack and place the guest's `argc` arguments on it, after
+// loading an image
+//
+// # Safety
+//
+// `emu` must be a live handle and `argv` point to `argc` NUL-terminated
+// strings.
+enum Ia64Status ia64_setup_process(struct Ia64Emulator *emu, const char *const *argv, size_t argc);
+
+// Execute the bundle at the instruction pointer
+//
+// `stop`, if not null, receives the stop the bundle caused, of kind
+// [`Ia64StopKind::None`] if it did not stop the machine.
+//
+// # Safety
+//
+// `emu` must be a live handle and `stop` null or writable.
+enum Ia64Status ia64_step(struct Ia64Emulator *emu, struct Ia64Stop *stop);
+
+// Run until the machine stops or, if `max_bundles` is not 0, that many
+// bundles have executed
+//
+// `stop`, if not null, receives the stop, of kind [`Ia64StopKind::None`]
+// if the limit was reached first.
+//
+// # Safety
+//
+// `emu` must be a live handle and `stop` null or writable.
+enum Ia64Status ia64_run(struct Ia64Emulator *emu, uint64_t max_bundles, struct Ia64Stop *stop);
+
+// Read the register `name` into `value`
+//
+// Floating-point registers read as the bits of their double value, and
+// `pr` as the 64 predicates.
+//
+// # Safety
+//
+// `emu` must be a live handle, `name` a NUL-terminated string and `value`
+// writable.
+enum Ia64Status ia64_read_register(struct Ia64Emulator *emu, const char *name, uint64_t *value);
+
+// Write `value` to the register `name`
+//
+// Application registers are written as by `mov ar`.
+//
+// # Safety
+//
+// `emu` must be a live handle and `name` a NUL-terminated string.
+enum Ia64Status ia64_write_register(struct Ia64Emulator *emu, const char *name, uint64_t value);
+
+// Read `len` bytes of guest memory at `addr` into `data`
+//
+// Reads leave the caches and devices alone.
+//
+// # Safety
+//
+// `emu` must be a live handle and `data` point to `len` writable bytes.
+enum Ia64Status ia64_read_memory(struct Ia64Emulator *emu,
+                                 uint64_t addr,
+                                 uint8_t *data,
+                                 size_t len);
+
+// Write `len` bytes from `data` to guest memory at `addr`
+//
+// # Safety
+//
+// `emu` must be a live handle and `data` point to `len` readable bytes.
+enum Ia64Status ia64_write_memory(struct Ia64Emulator *emu,
+                                  uint64_t addr,
+                                  const uint8_t *data,
+                                  size_t len);
+
+// Pass what the guest writes to standard output and standard error to
+// `callback`, or stop passing it on if `callback` is null
+//
+// `user` is handed to each call.
+//
+// # Safety
+//
+// `emu` must be a live handle, and `callback` safe to call with `user`
+// while it is registered.
+enum Ia64Status ia64_set_console_callback(struct Ia64Emulator *emu,
+                                          Ia64ConsoleCallback callback,
+                                          void *user);
+
+// Tell `callback` of each stop of the machine, or stop telling it if
+// `callback` is null
+//
+// `user` is handed to each call.
+//
+// # Safety
+//
+// `emu` must be a live handle, and `callback` safe to call with `user`
+// while it is registered.
+enum Ia64Status ia64_set_stop_callback(struct Ia64Emulator *emu,
+                                       Ia64StopCallback callback,
+                                       void *user);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* RUST_IA64_H */
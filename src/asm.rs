@@ -0,0 +1,1741 @@
+//! Assembler encoding instruction text into bundles
+//!
+//! [`BundleBuilder`] takes the instructions of one bundle in the syntax the
+//! disassembler produces, such as `(p6) ld8.acq r4 = [r5]`, and emits the
+//! 128-bit encoding:
+//!
+//! ```
+//! use rust_ia64::asm::BundleBuilder;
+//! use rust_ia64::decoder::Bundle;
+//!
+//! let bundle = BundleBuilder::new()
+//!     .insn("adds r4 = 8, r3")
+//!     .insn("ld8 r5 = [r3]")
+//!     .insn("br.ret.sptk.many b0 ;;")
+//!     .build()
+//!     .unwrap();
+//! let bundle = Bundle::new(bundle).unwrap();
+//! assert_eq!(bundle.template().to_string(), "MMB;");
+//! assert_eq!(bundle.disassemble()[1], "ld8 r5 = [r3]");
+//! ```
+//!
+//! `;;` after an instruction ends its instruction group. Unless a template
+//! is given, the builder picks the first one whose units and stops fit the
+//! instructions; an MLX bundle holds two, the long one last. IP-relative
+//! targets are absolute addresses, encoded relative to the address set
+//! with [`BundleBuilder::at`].
+//!
+//! Operands follow the disassembler: registers `r`, `f`, `p` and `b` with
+//! their numbers, `ar.` and `cr.` names, memory operands as `[r3]`, and
+//! immediates in decimal or `0x` hex. Plain `mov` to or from an application
+//! register picks the I unit for the ones only it reaches (ar.pfs, ar.lc
+//! and ar.ec), and the M unit otherwise.
+
+use crate::decoder::disasm::{ar_name, cr_name, CMP_RELATIONS, TBIT_RELATIONS};
+use crate::decoder::{BundleTemplate, Unit};
+use crate::EmulatorError;
+
+/// An encoded instruction, before it is placed in a bundle
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Encoded {
+    /// Unit the instruction needs: [`Unit::A`] fits an M or I slot, and
+    /// [`Unit::X`] takes the L and X slots of an MLX bundle
+    pub unit: Unit,
+    /// The 41-bit slot
+    pub bits: u64,
+    /// The L slot of an X-unit instruction
+    pub l_slot: u64,
+    /// Whether an instruction group ends after the instruction
+    pub stop: bool,
+}
+
+/// Pack a template and three slots into a bundle
+pub fn encode_bundle(template: BundleTemplate, slots: [u64; 3]) -> [u8; 16] {
+    let mask = (1u128 << 41) - 1;
+    let bits = template as u128
+        | ((slots[0] as u128 & mask) << 5)
+        | ((slots[1] as u128 & mask) << 46)
+        | ((slots[2] as u128 & mask) << 87);
+    bits.to_le_bytes()
+}
+
+/// Builder of one bundle from instruction text
+#[derive(Debug, Clone, Default)]
+pub struct BundleBuilder {
+    /// Template, chosen from the instructions when unset
+    template: Option<BundleTemplate>,
+    /// Address of the bundle
+    ip: u64,
+    /// Instruction text, in slot order
+    instructions: Vec<String>,
+}
+
+impl BundleBuilder {
+    /// Create an empty builder for a bundle at address 0
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the address of the bundle, which IP-relative targets are
+    /// encoded against
+    pub fn at(mut self, ip: u64) -> Self {
+        self.ip = ip;
+        self
+    }
+
+    /// Use `template` instead of choosing one
+    pub fn template(mut self, template: BundleTemplate) -> Self {
+        self.template = Some(template);
+        self
+    }
+
+    /// Add the next instruction
+    pub fn insn(mut self, text: &str) -> Self {
+        self.instructions.push(text.to_string());
+        self
+    }
+
+    /// End an instruction group after the last instruction added
+    pub fn stop(mut self) -> Self {
+        if let Some(last) = self.instructions.last_mut() {
+            last.push_str(" ;;");
+        }
+        self
+    }
+
+    /// Encode the bundle
+    pub fn build(&self) -> Result<[u8; 16], EmulatorError> {
+        let encoded = self
+            .instructions
+            .iter()
+            .map(|text| assemble(text, self.ip))
+            .collect::<Result<Vec<_>, _>>()?;
+        let candidates = match self.template {
+            Some(template) => vec![template],
+            None => BundleTemplate::ALL.to_vec(),
+        };
+        for template in candidates {
+            if let Some(slots) = place(template, &encoded) {
+                return Ok(encode_bundle(template, slots));
+            }
+        }
+        let units: String = encoded.iter().map(|e| e.unit.letter()).collect();
+        Err(error(match self.template {
+            Some(template) => format!("instructions ({}) do not fit template {}", units, template),
+            None => format!("no template fits instructions ({})", units),
+        }))
+    }
+}
+
+/// Place instructions in the slots of `template`, if their units and stops
+/// fit
+fn place(template: BundleTemplate, encoded: &[Encoded]) -> Option<[u64; 3]> {
+    let units = template.units();
+    let stops = template.stops();
+    let mut slots = [0; 3];
+    let mut slot = 0;
+    for insn in encoded {
+        let fits = match (insn.unit, *units.get(slot)?) {
+            (Unit::A, slot_unit) => matches!(slot_unit, Unit::M | Unit::I),
+            (Unit::X, slot_unit) => slot_unit == Unit::L,
+            (unit, slot_unit) => unit == slot_unit,
+        };
+        if !fits {
+            return None;
+        }
+        if insn.unit == Unit::X {
+            slots[slot] = insn.l_slot;
+            slot += 1;
+        }
+        if stops[slot] != insn.stop {
+            return None;
+        }
+        slots[slot] = insn.bits;
+        slot += 1;
+    }
+    (slot == 3).then_some(slots)
+}
+
+/// Assembly error with `msg`
+fn error(msg: String) -> EmulatorError {
+    EmulatorError::AssemblyError(msg)
+}
+
+/// Instruction text split into its parts
+struct Parsed<'a> {
+    /// Mnemonic without completers
+    base: &'a str,
+    /// Completers, without their dots
+    completers: Vec<&'a str>,
+    /// Operands left of `=`
+    dests: Vec<&'a str>,
+    /// Operands right of `=`, or all of them without one
+    srcs: Vec<&'a str>,
+}
+
+/// Encode one instruction of a bundle at `ip`
+pub fn assemble(text: &str, ip: u64) -> Result<Encoded, EmulatorError> {
+    let mut line = text.trim();
+    let stop = match line.strip_suffix(";;") {
+        Some(rest) => {
+            line = rest.trim_end();
+            true
+        }
+        None => false,
+    };
+
+    let mut qp = 0;
+    if let Some(rest) = line.strip_prefix('(') {
+        let (predicate, rest) = rest
+            .split_once(')')
+            .ok_or_else(|| error(format!("unclosed predicate in '{}'", text)))?;
+        qp = register(predicate.trim(), 'p', 64)?;
+        line = rest.trim_start();
+    }
+
+    let (mnemonic, operands) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+    fn list(text: &str) -> Vec<&str> {
+        match text.trim() {
+            "" => vec![],
+            text => text.split(',').map(str::trim).collect(),
+        }
+    }
+    let (dests, srcs) = match operands.split_once('=') {
+        Some((dests, srcs)) => (list(dests), list(srcs)),
+        None => (vec![], list(operands)),
+    };
+    let mut parts = mnemonic.split('.');
+    let parsed = Parsed {
+        base: parts.next().unwrap_or_default(),
+        completers: parts.collect(),
+        dests,
+        srcs,
+    };
+
+    let (unit, bits, l_slot) = encode(&parsed, ip)
+        .map_err(|e| match e {
+            EmulatorError::AssemblyError(msg) => error(format!("{}: {}", text.trim(), msg)),
+            e => e,
+        })?
+        .ok_or_else(|| error(format!("cannot encode '{}'", text.trim())))?;
+    Ok(Encoded {
+        unit,
+        bits: bits | qp,
+        l_slot,
+        stop,
+    })
+}
+
+/// Register `prefix` followed by a number below `limit`, e.g. `r12`
+fn register(text: &str, prefix: char, limit: u64) -> Result<u64, EmulatorError> {
+    text.strip_prefix(prefix)
+        .and_then(|n| n.parse::<u64>().ok())
+        .filter(|&n| n < limit)
+        .ok_or_else(|| error(format!("expected a {} register, found '{}'", prefix, text)))
+}
+
+/// General register
+fn gr(text: &str) -> Result<u64, EmulatorError> {
+    register(text, 'r', 128)
+}
+
+/// Floating-point register
+fn fr(text: &str) -> Result<u64, EmulatorError> {
+    register(text, 'f', 128)
+}
+
+/// Predicate register
+fn pr(text: &str) -> Result<u64, EmulatorError> {
+    register(text, 'p', 64)
+}
+
+/// Branch register
+fn br(text: &str) -> Result<u64, EmulatorError> {
+    register(text, 'b', 8)
+}
+
+/// Whether `text` names a general register
+fn is_gr(text: &str) -> bool {
+    gr(text).is_ok()
+}
+
+/// Memory operand `[r3]`
+fn address(text: &str) -> Result<u64, EmulatorError> {
+    text.strip_prefix('[')
+        .and_then(|t| t.strip_suffix(']'))
+        .ok_or_else(|| error(format!("expected a memory operand, found '{}'", text)))
+        .and_then(|r| gr(r.trim()))
+}
+
+/// Decimal or `0x` hex immediate, optionally negative
+fn immediate(text: &str) -> Result<i128, EmulatorError> {
+    let (negative, digits) = match text.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, text),
+    };
+    let value = match digits.strip_prefix("0x") {
+        Some(hex) => i128::from_str_radix(hex, 16),
+        None => digits.parse(),
+    }
+    .map_err(|_| error(format!("expected an immediate, found '{}'", text)))?;
+    Ok(if negative { -value } else { value })
+}
+
+/// Immediate that fits `width` bits as a signed value, or as an unsigned
+/// one when `unsigned` is set, truncated to its encoding
+fn imm(text: &str, width: u32, unsigned: bool) -> Result<u64, EmulatorError> {
+    let value = immediate(text)?;
+    let (min, max) = if unsigned {
+        (0, (1i128 << width) - 1)
+    } else {
+        (-(1i128 << (width - 1)), (1i128 << (width - 1)) - 1)
+    };
+    if value < min || value > max {
+        return Err(error(format!(
+            "immediate {} does not fit {} bits",
+            text, width
+        )));
+    }
+    Ok(value as u64 & (u64::MAX >> (64 - width)))
+}
+
+/// Immediate that must lie in `range`
+fn small(text: &str, range: std::ops::RangeInclusive<u64>) -> Result<u64, EmulatorError> {
+    let value = imm(text, 7, true)?;
+    if !range.contains(&value) {
+        return Err(error(format!("{} is outside {:?}", text, range)));
+    }
+    Ok(value)
+}
+
+/// Application register by name, `ar.lc` or `ar42`
+fn ar(text: &str) -> Result<u64, EmulatorError> {
+    (0..128)
+        .find(|&n| ar_name(n) == text)
+        .ok_or_else(|| error(format!("unknown application register '{}'", text)))
+}
+
+/// Control register by name, `cr.iva` or `cr3`
+fn cr(text: &str) -> Result<u64, EmulatorError> {
+    (0..128)
+        .find(|&n| cr_name(n) == text)
+        .ok_or_else(|| error(format!("unknown control register '{}'", text)))
+}
+
+/// Bundle offset from `ip` to an absolute target, in `width` bits
+fn offset(text: &str, ip: u64, width: u32) -> Result<u64, EmulatorError> {
+    let target = imm(text, 64, true)?;
+    let delta = target.wrapping_sub(ip) as i64;
+    if delta % 16 != 0 {
+        return Err(error(format!("target {} is not a bundle address", text)));
+    }
+    let bundles = delta >> 4;
+    if bundles < -(1 << (width - 1)) || bundles >= 1 << (width - 1) {
+        return Err(error(format!("target {} is out of range", text)));
+    }
+    Ok(bundles as u64 & ((1 << width) - 1))
+}
+
+/// imm20b (bits 13-32) and s (bit 36) of a 21-bit offset
+fn imm20b(offset: u64) -> u64 {
+    ((offset & 0xFFFFF) << 13) | (((offset >> 20) & 1) << 36)
+}
+
+/// imm7b (bits 13-19) and s (bit 36) of an 8-bit immediate
+fn imm7b(value: u64) -> u64 {
+    ((value & 0x7F) << 13) | (((value >> 7) & 1) << 36)
+}
+
+/// Check the operand counts
+fn operands(p: &Parsed, dests: usize, srcs: usize) -> Result<(), EmulatorError> {
+    if p.dests.len() != dests || p.srcs.len() != srcs {
+        return Err(error(format!(
+            "expected {} destination and {} source operands",
+            dests, srcs
+        )));
+    }
+    Ok(())
+}
+
+/// Encode an instruction, without its predicate; `None` if the mnemonic
+/// or operand forms are not known
+fn encode(p: &Parsed, ip: u64) -> Result<Option<(Unit, u64, u64)>, EmulatorError> {
+    let completers = p.completers.join(".");
+    let found = match p.base {
+        "break" | "nop" | "hint" => break_nop(p, &completers)?,
+        "add" | "sub" | "addp4" | "and" | "andcm" | "or" | "xor" | "shladd" | "shladdp4"
+        | "adds" | "addl" => alu(p, &completers)?.map(|bits| (Unit::A, bits, 0)),
+        "cmp" | "cmp4" => compare(p, &completers)?.map(|bits| (Unit::A, bits, 0)),
+        "mov" => mov(p, &completers)?,
+        "movl" if completers.is_empty() => {
+            operands(p, 1, 1)?;
+            let r1 = gr(p.dests[0])?;
+            let value = imm(p.srcs[0], 64, true).or_else(|_| imm(p.srcs[0], 64, false))?;
+            let x = (6 << 37)
+                | ((value >> 63) << 36)
+                | (((value >> 7) & 0x1FF) << 27)
+                | (((value >> 16) & 0x1F) << 22)
+                | (((value >> 21) & 1) << 21)
+                | ((value & 0x7F) << 13)
+                | (r1 << 6);
+            Some((Unit::X, x, (value >> 22) & ((1 << 41) - 1)))
+        }
+        "br" | "brl" => branch(p, ip)?,
+        "cover" | "clrrrb" | "rfi" | "bsw" | "epc" => {
+            operands(p, 0, 0)?;
+            let x6 = match (p.base, completers.as_str()) {
+                ("cover", "") => 0x02,
+                ("clrrrb", "") => 0x04,
+                ("clrrrb", "pr") => 0x05,
+                ("rfi", "") => 0x08,
+                ("bsw", "0") => 0x0C,
+                ("bsw", "1") => 0x0D,
+                ("epc", "") => 0x10,
+                _ => return Ok(None),
+            };
+            Some((Unit::B, x6 << 27, 0))
+        }
+        _ => {
+            let found = integer(p, &completers)?.map(|bits| (Unit::I, bits, 0));
+            let found = match found {
+                Some(found) => Some(found),
+                None => memory(p, &completers, ip)?,
+            };
+            match found {
+                Some(found) => Some(found),
+                None => floating(p)?.map(|bits| (Unit::F, bits, 0)),
+            }
+        }
+    };
+    Ok(found)
+}
+
+/// break, nop and hint on every unit
+fn break_nop(p: &Parsed, completers: &str) -> Result<Option<(Unit, u64, u64)>, EmulatorError> {
+    operands(p, 0, 1)?;
+    let unit = match completers {
+        "m" => Unit::M,
+        "i" => Unit::I,
+        "f" => Unit::F,
+        "b" => Unit::B,
+        "x" => Unit::X,
+        _ => return Ok(None),
+    };
+    let value = imm(p.srcs[0], if unit == Unit::X { 62 } else { 21 }, true)?;
+    let fields = (value & 0xFFFFF) << 6 | ((value >> 20) & 1) << 36;
+    let bits = match (unit, p.base) {
+        (Unit::B, "break") => fields,
+        (Unit::B, "nop") => (2 << 37) | fields,
+        (Unit::B, _) => (2 << 37) | (0x01 << 27) | fields,
+        (_, "break") => fields,
+        (_, "nop") => (0x01 << 27) | fields,
+        _ => (0x01 << 27) | (1 << 26) | fields,
+    };
+    Ok(Some((unit, bits, value >> 21)))
+}
+
+/// A-unit arithmetic and logical instructions
+fn alu(p: &Parsed, completers: &str) -> Result<Option<u64>, EmulatorError> {
+    if !completers.is_empty() {
+        return Ok(None);
+    }
+    operands(p, 1, p.srcs.len().max(1))?;
+    let r1 = gr(p.dests[0])? << 6;
+    let a = |x2a: u64, x4: u64, x2b: u64| (8 << 37) | (x2a << 34) | (x4 << 29) | (x2b << 27) | r1;
+    let regs = |r2: &str, r3: &str| -> Result<u64, EmulatorError> {
+        Ok((gr(r2)? << 13) | (gr(r3)? << 20))
+    };
+    let imm14 = |text: &str| -> Result<u64, EmulatorError> {
+        let value = imm(text, 14, false)?;
+        Ok(((value & 0x7F) << 13) | (((value >> 7) & 0x3F) << 27) | (((value >> 13) & 1) << 36))
+    };
+    let logical = ["and", "andcm", "or", "xor"]
+        .iter()
+        .position(|&m| m == p.base);
+
+    Ok(Some(match (p.base, p.srcs.as_slice()) {
+        ("add", [r2, r3]) => a(0, 0, 0) | regs(r2, r3)?,
+        ("add", [r2, r3, "1"]) => a(0, 0, 1) | regs(r2, r3)?,
+        ("sub", [r2, r3, "1"]) => a(0, 1, 0) | regs(r2, r3)?,
+        ("sub", [r2, r3]) if is_gr(r2) => a(0, 1, 1) | regs(r2, r3)?,
+        ("sub", [value, r3]) => a(0, 9, 1) | imm7b(imm(value, 8, false)?) | (gr(r3)? << 20),
+        ("addp4", [r2, r3]) if is_gr(r2) => a(0, 2, 0) | regs(r2, r3)?,
+        ("addp4", [value, r3]) => (8 << 37) | (3 << 34) | r1 | imm14(value)? | (gr(r3)? << 20),
+        ("adds", [value, r3]) => (8 << 37) | (2 << 34) | r1 | imm14(value)? | (gr(r3)? << 20),
+        (_, [r2, r3]) if logical.is_some() && is_gr(r2) => {
+            a(0, 3, logical.unwrap_or_default() as u64) | regs(r2, r3)?
+        }
+        (_, [value, r3]) if logical.is_some() => {
+            a(0, 0xB, logical.unwrap_or_default() as u64)
+                | imm7b(imm(value, 8, false)?)
+                | (gr(r3)? << 20)
+        }
+        ("shladd" | "shladdp4", [r2, count, r3]) => {
+            let x4 = if p.base == "shladd" { 4 } else { 6 };
+            a(0, x4, small(count, 1..=4)? - 1) | regs(r2, r3)?
+        }
+        ("addl", [value, r3]) => addl(r1, value, r3)?,
+        _ => return Ok(None),
+    }))
+}
+
+/// addl, whose r3 is one of r0-r3
+fn addl(r1: u64, value: &str, r3: &str) -> Result<u64, EmulatorError> {
+    let r3 = gr(r3)?;
+    if r3 > 3 {
+        return Err(error("addl adds to r0-r3 only".to_string()));
+    }
+    let value = imm(value, 22, false)?;
+    Ok((9 << 37)
+        | ((value & 0x7F) << 13)
+        | (((value >> 7) & 0x1FF) << 27)
+        | (((value >> 16) & 0x1F) << 22)
+        | (((value >> 21) & 1) << 36)
+        | (r3 << 20)
+        | r1)
+}
+
+/// cmp and cmp4, register and immediate forms
+fn compare(p: &Parsed, relation: &str) -> Result<Option<u64>, EmulatorError> {
+    operands(p, 2, 2)?;
+    let Some((table, index)) = CMP_RELATIONS
+        .iter()
+        .enumerate()
+        .find_map(|(table, relations)| {
+            Some((table, relations.iter().position(|&r| r == relation)?))
+        })
+    else {
+        return Ok(None);
+    };
+    let index = index as u64;
+    let size = if p.base == "cmp4" { 1 } else { 0 };
+    let mut bits = ((0xC + table as u64) << 37)
+        | (pr(p.dests[1])? << 27)
+        | (gr(p.srcs[1])? << 20)
+        | (((index >> 1) & 1) << 33)
+        | ((index & 1) << 12)
+        | (pr(p.dests[0])? << 6);
+    if is_gr(p.srcs[0]) {
+        let r2 = gr(p.srcs[0])?;
+        if index >= 4 && r2 != 0 {
+            return Err(error(format!("cmp.{} compares against r0 only", relation)));
+        }
+        bits |= (size << 34) | ((index >> 2) << 36) | (r2 << 13);
+    } else {
+        if index >= 4 {
+            return Err(error(format!("cmp.{} has no immediate form", relation)));
+        }
+        bits |= ((2 + size) << 34) | imm7b(imm(p.srcs[0], 8, false)?);
+    }
+    Ok(Some(bits))
+}
+
+/// Indexed register files reached by the system moves
+const REGISTER_FILES: [&str; 8] = ["rr", "dbr", "ibr", "pkr", "pmc", "pmd", "msr", "cpuid"];
+
+/// Indexed register file operand such as `rr[r3]`: the file and r3
+fn indexed(text: &str) -> Option<(u64, u64)> {
+    let (file, rest) = text.split_once('[')?;
+    let file = REGISTER_FILES.iter().position(|&f| f == file)? as u64;
+    let r3 = gr(rest.strip_suffix(']')?).ok()?;
+    Some((file, r3))
+}
+
+/// The forms of mov
+fn mov(p: &Parsed, completers: &str) -> Result<Option<(Unit, u64, u64)>, EmulatorError> {
+    let i0 = |x6: u64| x6 << 27;
+    let m1 = |x6: u64| (1 << 37) | (x6 << 27);
+    if p.dests.len() != 1 {
+        return Ok(None);
+    }
+    let dest = p.dests[0];
+
+    // Application registers, with the unit given or chosen
+    let ar_unit = |n: u64| match completers {
+        "i" => Some(Unit::I),
+        "m" => Some(Unit::M),
+        "" if (64..128).contains(&n) => Some(Unit::I),
+        "" => Some(Unit::M),
+        _ => None,
+    };
+    if let Ok(n) = ar(dest) {
+        operands(p, 1, 1)?;
+        let Some(unit) = ar_unit(n) else {
+            return Ok(None);
+        };
+        let src = p.srcs[0];
+        let bits = match (unit, is_gr(src)) {
+            (Unit::I, true) => i0(0x2A) | (gr(src)? << 13),
+            (Unit::I, false) => i0(0x0A) | imm7b(imm(src, 8, false)?),
+            (_, true) => m1(0x2A) | (gr(src)? << 13),
+            _ => (0x28 << 27) | imm7b(imm(src, 8, false)?),
+        };
+        return Ok(Some((unit, bits | (n << 20), 0)));
+    }
+    if let Some(src) = p.srcs.first().and_then(|&s| ar(s).ok()) {
+        operands(p, 1, 1)?;
+        let Some(unit) = ar_unit(src) else {
+            return Ok(None);
+        };
+        let x6 = if unit == Unit::I { i0(0x32) } else { m1(0x22) };
+        return Ok(Some((unit, x6 | (src << 20) | (gr(dest)? << 6), 0)));
+    }
+    if !completers.is_empty() {
+        return Ok(None);
+    }
+
+    if let Ok(r1) = gr(dest) {
+        operands(p, 1, 1)?;
+        let src = p.srcs[0];
+        let r1 = r1 << 6;
+        return Ok(Some(match src {
+            "ip" => (Unit::I, i0(0x30) | r1, 0),
+            "pr" => (Unit::I, i0(0x33) | r1, 0),
+            "psr" => (Unit::M, m1(0x25) | r1, 0),
+            "psr.um" => (Unit::M, m1(0x21) | r1, 0),
+            _ if src.starts_with('b') => (Unit::I, i0(0x31) | (br(src)? << 13) | r1, 0),
+            _ if src.starts_with("cr") => (Unit::M, m1(0x24) | (cr(src)? << 20) | r1, 0),
+            _ if is_gr(src) => (Unit::A, (8 << 37) | (2 << 34) | (gr(src)? << 20) | r1, 0),
+            _ => match indexed(src) {
+                Some((file, r3)) => (Unit::M, m1(0x10 | file) | (r3 << 20) | r1, 0),
+                None => (Unit::A, addl(r1, src, "r0")?, 0),
+            },
+        }));
+    }
+
+    Ok(Some(match (dest, p.srcs.as_slice()) {
+        ("pr", [r2, mask]) => {
+            let mask = imm(mask, 17, false).or_else(|_| imm(mask, 17, true))?;
+            if mask & 1 != 0 {
+                return Err(error("the mask cannot include p0".to_string()));
+            }
+            let bits = (3 << 33)
+                | (((mask >> 1) & 0x7F) << 6)
+                | (((mask >> 8) & 0xFF) << 24)
+                | (((mask >> 16) & 1) << 36)
+                | (gr(r2)? << 13);
+            (Unit::I, bits, 0)
+        }
+        ("pr.rot", [value]) => {
+            let value = imm(value, 44, false).or_else(|_| imm(value, 64, true))?;
+            if value & 0xFFFF != 0 {
+                return Err(error(
+                    "pr.rot takes the rotating predicates only".to_string(),
+                ));
+            }
+            let bits =
+                (2 << 33) | (((value >> 16) & 0x7FF_FFFF) << 6) | (((value >> 43) & 1) << 36);
+            (Unit::I, bits, 0)
+        }
+        ("psr.l", [r2]) => (Unit::M, m1(0x2D) | (gr(r2)? << 13), 0),
+        ("psr.um", [r2]) => (Unit::M, m1(0x29) | (gr(r2)? << 13), 0),
+        (_, [r2]) if dest.starts_with('b') => {
+            (Unit::I, (7 << 33) | (br(dest)? << 6) | (gr(r2)? << 13), 0)
+        }
+        (_, [r2]) if dest.starts_with("cr") => {
+            (Unit::M, m1(0x2C) | (cr(dest)? << 20) | (gr(r2)? << 13), 0)
+        }
+        (_, [r2]) => match indexed(dest) {
+            Some((file, r3)) if file < 7 => (Unit::M, m1(file) | (r3 << 20) | (gr(r2)? << 13), 0),
+            _ => return Ok(None),
+        },
+        _ => return Ok(None),
+    }))
+}
+
+/// Split branch completers into the rest and the hint bits: prediction
+/// (bits 33-34), prefetch (bit 12) and deallocation (bit 35)
+fn branch_hints<'a>(completers: &[&'a str]) -> (Vec<&'a str>, u64, u64) {
+    let mut rest = Vec::new();
+    let mut hint = 0;
+    let mut fields = 0;
+    for &completer in completers {
+        match completer {
+            "sptk" => hint = 0,
+            "spnt" => hint = 1,
+            "dptk" => hint = 2,
+            "dpnt" => hint = 3,
+            "few" => fields &= !(1 << 12),
+            "many" => fields |= 1 << 12,
+            "clr" => fields |= 1 << 35,
+            _ => rest.push(completer),
+        }
+    }
+    (rest, hint, fields)
+}
+
+/// br and brl
+fn branch(p: &Parsed, ip: u64) -> Result<Option<(Unit, u64, u64)>, EmulatorError> {
+    let (rest, hint, fields) = branch_hints(&p.completers);
+    let kind = rest.join(".");
+    let target = p.srcs.first().copied().unwrap_or_default();
+    let indirect = target.starts_with('b');
+
+    if p.base == "brl" {
+        let major = match kind.as_str() {
+            "cond" => 0xC,
+            "call" => 0xD,
+            _ => return Ok(None),
+        };
+        operands(p, usize::from(major == 0xD), 1)?;
+        let b1 = match p.dests.first() {
+            Some(b1) => br(b1)?,
+            None => 0,
+        };
+        let offset = offset(target, ip, 60)?;
+        let x = (major << 37)
+            | ((offset >> 59) << 36)
+            | ((offset & 0xFFFFF) << 13)
+            | (hint << 33)
+            | fields
+            | (b1 << 6);
+        return Ok(Some((Unit::X, x, ((offset >> 20) << 2) & ((1 << 41) - 1))));
+    }
+
+    let bits = match (kind.as_str(), indirect) {
+        ("call", true) => {
+            operands(p, 1, 1)?;
+            (1 << 37)
+                | ((hint * 2 + 1) << 32)
+                | fields
+                | (br(p.dests[0])? << 6)
+                | (br(target)? << 13)
+        }
+        ("call", false) => {
+            operands(p, 1, 1)?;
+            (5 << 37)
+                | (hint << 33)
+                | fields
+                | (br(p.dests[0])? << 6)
+                | imm20b(offset(target, ip, 21)?)
+        }
+        ("cond" | "ia" | "ret", true) => {
+            operands(p, 0, 1)?;
+            let (x6, btype) = match kind.as_str() {
+                "cond" => (0x20, 0),
+                "ia" => (0x20, 1),
+                _ => (0x21, 4),
+            };
+            (x6 << 27) | (hint << 33) | fields | (btype << 6) | (br(target)? << 13)
+        }
+        (_, false) => {
+            operands(p, 0, 1)?;
+            let btype = match kind.as_str() {
+                "cond" => 0,
+                "wexit" => 2,
+                "wtop" => 3,
+                "cloop" => 5,
+                "cexit" => 6,
+                "ctop" => 7,
+                _ => return Ok(None),
+            };
+            (4 << 37) | (hint << 33) | fields | (btype << 6) | imm20b(offset(target, ip, 21)?)
+        }
+        _ => return Ok(None),
+    };
+    Ok(Some((Unit::B, bits, 0)))
+}
+
+/// I-unit instructions other than mov
+fn integer(p: &Parsed, completers: &str) -> Result<Option<u64>, EmulatorError> {
+    let r1 = || -> Result<u64, EmulatorError> { Ok(gr(p.dests[0])? << 6) };
+    let major5 = |x2: u64, x: u64| (5 << 37) | (x2 << 34) | (x << 33);
+
+    Ok(Some(match (p.base, completers) {
+        ("zxt1" | "zxt2" | "zxt4" | "sxt1" | "sxt2" | "sxt4", "") => {
+            operands(p, 1, 1)?;
+            let log2 = match &p.base[3..] {
+                "1" => 0,
+                "2" => 1,
+                _ => 2,
+            };
+            let x6 = if p.base.starts_with('z') { 0x10 } else { 0x14 } + log2;
+            (x6 << 27) | (gr(p.srcs[0])? << 20) | r1()?
+        }
+        ("czx1" | "czx2", "l" | "r") => {
+            operands(p, 1, 1)?;
+            let x6 = if completers == "l" { 0x18 } else { 0x1C } + u64::from(p.base == "czx2");
+            (x6 << 27) | (gr(p.srcs[0])? << 20) | r1()?
+        }
+        ("dep", "") => {
+            operands(p, 1, 4)?;
+            let pos = small(p.srcs[2], 0..=63)?;
+            let len = imm(p.srcs[3], 7, true)?;
+            if is_gr(p.srcs[0]) {
+                if !(1..=16).contains(&len) {
+                    return Err(error("dep inserts 1-16 bits from a register".to_string()));
+                }
+                (4 << 37)
+                    | ((63 - pos) << 31)
+                    | ((len - 1) << 27)
+                    | (gr(p.srcs[1])? << 20)
+                    | (gr(p.srcs[0])? << 13)
+                    | r1()?
+            } else {
+                let sign = match immediate(p.srcs[0])? {
+                    0 => 0,
+                    -1 => 1,
+                    _ => return Err(error("dep inserts an immediate of 0 or -1".to_string())),
+                };
+                major5(3, 1)
+                    | (sign << 36)
+                    | (field_len(len)? << 27)
+                    | (gr(p.srcs[1])? << 20)
+                    | ((63 - pos) << 14)
+                    | r1()?
+            }
+        }
+        ("dep", "z") => {
+            operands(p, 1, 3)?;
+            let pos = small(p.srcs[1], 0..=63)?;
+            let len = field_len(imm(p.srcs[2], 7, true)?)?;
+            let source = if is_gr(p.srcs[0]) {
+                gr(p.srcs[0])? << 13
+            } else {
+                (1 << 26) | imm7b(imm(p.srcs[0], 8, false)?)
+            };
+            major5(1, 1) | (len << 27) | ((63 - pos) << 20) | source | r1()?
+        }
+        ("extr", "" | "u") => {
+            operands(p, 1, 3)?;
+            let signed = u64::from(completers.is_empty());
+            let pos = small(p.srcs[1], 0..=63)?;
+            let len = field_len(imm(p.srcs[2], 7, true)?)?;
+            major5(1, 0)
+                | (len << 27)
+                | (gr(p.srcs[0])? << 20)
+                | (pos << 14)
+                | (signed << 13)
+                | r1()?
+        }
+        ("shrp", "") => {
+            operands(p, 1, 3)?;
+            let count = small(p.srcs[2], 0..=63)?;
+            major5(3, 0) | (count << 27) | (gr(p.srcs[1])? << 20) | (gr(p.srcs[0])? << 13) | r1()?
+        }
+        ("tbit" | "tnat", _) => {
+            let Some(index) = TBIT_RELATIONS.iter().position(|&r| r == completers) else {
+                return Ok(None);
+            };
+            let tbit = p.base == "tbit";
+            operands(p, 2, if tbit { 2 } else { 1 })?;
+            let index = index as u64;
+            let bits = major5(0, (index >> 1) & 1)
+                | ((index >> 2) << 36)
+                | (pr(p.dests[1])? << 27)
+                | (gr(p.srcs[0])? << 20)
+                | ((index & 1) << 12)
+                | (pr(p.dests[0])? << 6);
+            if tbit {
+                bits | (small(p.srcs[1], 0..=63)? << 14)
+            } else {
+                bits | (1 << 13)
+            }
+        }
+        ("shl", "") | ("shr", "" | "u") => {
+            // shl takes the value in r2 and the count in r3, the right
+            // shifts the other way round
+            operands(p, 1, 2)?;
+            let (x2c, x2b, r2, r3) = match (p.base, completers) {
+                ("shl", _) => (1, 0, p.srcs[0], p.srcs[1]),
+                (_, "u") => (0, 0, p.srcs[1], p.srcs[0]),
+                _ => (0, 2, p.srcs[1], p.srcs[0]),
+            };
+            (7 << 37)
+                | (1 << 36)
+                | (1 << 33)
+                | (x2c << 30)
+                | (x2b << 28)
+                | (gr(r3)? << 20)
+                | (gr(r2)? << 13)
+                | r1()?
+        }
+        _ => return Ok(None),
+    }))
+}
+
+/// len6d of extr, dep.z and dep with an immediate: 1-64 bits
+fn field_len(len: u64) -> Result<u64, EmulatorError> {
+    if !(1..=64).contains(&len) {
+        return Err(error(format!("field length {} is outside 1-64", len)));
+    }
+    Ok(len - 1)
+}
+
+/// M-unit instructions
+fn memory(
+    p: &Parsed,
+    completers: &str,
+    ip: u64,
+) -> Result<Option<(Unit, u64, u64)>, EmulatorError> {
+    let m0 = |x6: u64| x6 << 27;
+    let m1 = |x6: u64| (1 << 37) | (x6 << 27);
+
+    // chk.s and chk.a, the first also on the I unit
+    if p.base == "chk" {
+        operands(p, 0, 2)?;
+        let target = p.srcs[1];
+        return Ok(Some(match completers {
+            "s.i" | "s.m" | "s" => {
+                let offset = offset(target, ip, 21)?;
+                let fields = ((offset & 0x7F) << 6)
+                    | (((offset >> 7) & 0x1FFF) << 20)
+                    | (((offset >> 20) & 1) << 36);
+                let float = completers == "s" && fr(p.srcs[0]).is_ok();
+                let r2 = if float {
+                    fr(p.srcs[0])?
+                } else {
+                    gr(p.srcs[0])?
+                };
+                match completers {
+                    "s.i" => (Unit::I, (1 << 33) | fields | (r2 << 13), 0),
+                    _ => {
+                        let x3 = if float { 3 } else { 1 };
+                        (Unit::M, m1(0) | (x3 << 33) | fields | (r2 << 13), 0)
+                    }
+                }
+            }
+            "a.nc" | "a.clr" => {
+                let float = fr(p.srcs[0]).is_ok();
+                let r1 = if float {
+                    fr(p.srcs[0])?
+                } else {
+                    gr(p.srcs[0])?
+                };
+                let x3 = 4 + if float { 2 } else { 0 } + u64::from(completers == "a.clr");
+                (
+                    Unit::M,
+                    (x3 << 33) | imm20b(offset(target, ip, 21)?) | (r1 << 6),
+                    0,
+                )
+            }
+            _ => return Ok(None),
+        }));
+    }
+
+    let mnemonic = if completers.is_empty() {
+        p.base.to_string()
+    } else {
+        format!("{}.{}", p.base, completers)
+    };
+    let simple = match mnemonic.as_str() {
+        "loadrs" => Some(0x0A),
+        "flushrs" => Some(0x0C),
+        "invala" => Some(0x10),
+        "fwb" => Some(0x20),
+        "mf" => Some(0x22),
+        "mf.a" => Some(0x23),
+        "srlz.d" => Some(0x30),
+        "srlz.i" => Some(0x31),
+        "sync.i" => Some(0x33),
+        _ => None,
+    };
+    if let Some(x6) = simple {
+        operands(p, 0, 0)?;
+        return Ok(Some((Unit::M, m0(x6), 0)));
+    }
+
+    let r = |index: usize| -> Result<u64, EmulatorError> {
+        p.srcs
+            .get(index)
+            .ok_or_else(|| error("missing operand".to_string()))
+            .and_then(|s| gr(s))
+    };
+    Ok(Some(match mnemonic.as_str() {
+        "invala.e" => {
+            operands(p, 0, 1)?;
+            match fr(p.srcs[0]) {
+                Ok(f1) => (Unit::M, m0(0x13) | (f1 << 6), 0),
+                Err(_) => (Unit::M, m0(0x12) | (r(0)? << 6), 0),
+            }
+        }
+        "sum" | "rum" | "ssm" | "rsm" => {
+            operands(p, 0, 1)?;
+            let x4 = 4 + ["sum", "rum", "ssm", "rsm"]
+                .iter()
+                .position(|&m| m == p.base)
+                .unwrap_or_default() as u64;
+            let value = imm(p.srcs[0], 24, true)?;
+            let bits = (x4 << 27)
+                | ((value & 0x1F_FFFF) << 6)
+                | (((value >> 21) & 3) << 31)
+                | (((value >> 23) & 1) << 36);
+            (Unit::M, bits, 0)
+        }
+        "alloc" => {
+            operands(p, 1, 5)?;
+            if p.srcs[0] != "ar.pfs" {
+                return Err(error("alloc saves ar.pfs".to_string()));
+            }
+            let sizes = p.srcs[1..]
+                .iter()
+                .map(|s| imm(s, 8, true))
+                .collect::<Result<Vec<_>, _>>()?;
+            let (sol, sof, rotating) = (
+                sizes[0] + sizes[1],
+                sizes[0] + sizes[1] + sizes[2],
+                sizes[3],
+            );
+            if sof > 96 || rotating > sof || rotating % 8 != 0 {
+                return Err(error("invalid frame sizes".to_string()));
+            }
+            let r1 = gr(p.dests[0])?;
+            (
+                Unit::M,
+                m1(0) | (6 << 33) | ((rotating / 8) << 27) | (sol << 20) | (sof << 13) | (r1 << 6),
+                0,
+            )
+        }
+        "ptc.l" | "ptc.g" | "ptc.ga" | "ptr.d" | "ptr.i" => {
+            operands(p, 0, 2)?;
+            let x6 = match mnemonic.as_str() {
+                "ptc.l" => 0x09,
+                "ptc.g" => 0x0A,
+                "ptc.ga" => 0x0B,
+                "ptr.d" => 0x0C,
+                _ => 0x0D,
+            };
+            (Unit::M, m1(x6) | (r(0)? << 20) | (r(1)? << 13), 0)
+        }
+        "itr.d" | "itr.i" => {
+            operands(p, 1, 1)?;
+            let (file, x6) = if mnemonic == "itr.d" {
+                ("dtr", 0x0E)
+            } else {
+                ("itr", 0x0F)
+            };
+            let r3 = p.dests[0]
+                .strip_prefix(file)
+                .and_then(|rest| rest.strip_prefix('['))
+                .and_then(|rest| rest.strip_suffix(']'))
+                .ok_or_else(|| error(format!("expected {}[r3]", file)))?;
+            (Unit::M, m1(x6) | (gr(r3)? << 20) | (r(0)? << 13), 0)
+        }
+        "probe.r" | "probe.w" => {
+            operands(p, 1, 2)?;
+            let r1 = gr(p.dests[0])? << 6;
+            let write = u64::from(mnemonic == "probe.w");
+            if is_gr(p.srcs[1]) {
+                (
+                    Unit::M,
+                    m1(0x18 + write) | (r(0)? << 20) | (r(1)? << 13) | r1,
+                    0,
+                )
+            } else {
+                (
+                    Unit::M,
+                    m1(0x38 + write) | (r(0)? << 20) | (small(p.srcs[1], 0..=3)? << 13) | r1,
+                    0,
+                )
+            }
+        }
+        "probe.rw.fault" | "probe.r.fault" | "probe.w.fault" => {
+            operands(p, 0, 2)?;
+            let x6 = match mnemonic.as_str() {
+                "probe.rw.fault" => 0x31,
+                "probe.r.fault" => 0x32,
+                _ => 0x33,
+            };
+            (
+                Unit::M,
+                m1(x6) | (r(0)? << 20) | (small(p.srcs[1], 0..=3)? << 13),
+                0,
+            )
+        }
+        "thash" | "ttag" | "tpa" | "tak" => {
+            operands(p, 1, 1)?;
+            let x6 = match p.base {
+                "thash" => 0x1A,
+                "ttag" => 0x1B,
+                "tpa" => 0x1E,
+                _ => 0x1F,
+            };
+            (Unit::M, m1(x6) | (r(0)? << 20) | (gr(p.dests[0])? << 6), 0)
+        }
+        "itc.d" | "itc.i" => {
+            operands(p, 0, 1)?;
+            let x6 = if mnemonic == "itc.d" { 0x2E } else { 0x2F };
+            (Unit::M, m1(x6) | (r(0)? << 13), 0)
+        }
+        "fc" | "ptc.e" => {
+            operands(p, 0, 1)?;
+            let x6 = if mnemonic == "fc" { 0x30 } else { 0x34 };
+            (Unit::M, m1(x6) | (r(0)? << 20), 0)
+        }
+        _ => match memory_access(p, completers)? {
+            Some(bits) => (Unit::M, bits, 0),
+            None => return Ok(None),
+        },
+    }))
+}
+
+/// Encoding of load and store completers in x6 bits 2-5, with the hint
+fn access_kind(completers: &[&str], store: bool) -> Option<(u64, u64)> {
+    let (hint, kind) = match completers.last() {
+        Some(&"nt1") => (1, &completers[..completers.len() - 1]),
+        Some(&"nt2") => (2, &completers[..completers.len() - 1]),
+        Some(&"nta") => (3, &completers[..completers.len() - 1]),
+        _ => (0, completers),
+    };
+    let kind = match (kind.join(".").as_str(), store) {
+        ("", false) => 0x0,
+        ("s", false) => 0x1,
+        ("a", false) => 0x2,
+        ("sa", false) => 0x3,
+        ("bias", false) => 0x4,
+        ("acq", false) => 0x5,
+        ("fill", false) => 0x6,
+        ("c.clr", false) => 0x8,
+        ("c.nc", false) => 0x9,
+        ("c.clr.acq", false) => 0xA,
+        ("", true) => 0xC,
+        ("rel", true) => 0xD,
+        ("spill", true) => 0xE,
+        _ => return None,
+    };
+    Some((kind, hint))
+}
+
+/// Loads, stores, lfetch, semaphores and register-file transfers
+/// (majors 4-7)
+fn memory_access(p: &Parsed, completers: &str) -> Result<Option<u64>, EmulatorError> {
+    let comps = &p.completers;
+    let size_of = |digits: &str| match digits {
+        "1" => Some(0),
+        "2" => Some(1),
+        "4" => Some(2),
+        "8" => Some(3),
+        _ => None,
+    };
+    let fp_size = |text: &str| match text {
+        "e" => Some(0),
+        "8" => Some(1),
+        "s" => Some(2),
+        "d" => Some(3),
+        _ => None,
+    };
+    let transfer_kind = |text: &str| ["sig", "exp", "s", "d"].iter().position(|&k| k == text);
+    let access = |major: u64, x6: u64, hint: u64, m: u64, x: u64| {
+        (major << 37) | (m << 36) | (x6 << 30) | (hint << 28) | (x << 27)
+    };
+    // Post-increment of loads (imm7b) and stores (imm7a), with i in bit 27
+    // and s in bit 36
+    let load_imm = |value: u64| {
+        ((value & 0x7F) << 13) | (((value >> 7) & 1) << 27) | (((value >> 8) & 1) << 36)
+    };
+    let store_imm = |value: u64| {
+        ((value & 0x7F) << 6) | (((value >> 7) & 1) << 27) | (((value >> 8) & 1) << 36)
+    };
+
+    // Semaphores and transfers
+    if let Some(rest) = p
+        .base
+        .strip_prefix("cmpxchg")
+        .or_else(|| p.base.strip_prefix("xchg"))
+    {
+        let Some(size) = size_of(rest) else {
+            return Ok(None);
+        };
+        let hint = match comps.last() {
+            Some(&"nt1") => 1,
+            Some(&"nta") => 3,
+            _ => 0,
+        };
+        let order: Vec<&str> = comps
+            .iter()
+            .copied()
+            .filter(|&c| !matches!(c, "nt1" | "nta"))
+            .collect();
+        let x6 = match (p.base.starts_with('c'), order.as_slice()) {
+            (true, ["acq"]) => size,
+            (true, ["rel"]) => 4 + size,
+            (false, []) => 8 + size,
+            _ => return Ok(None),
+        };
+        let ccv = if p.base.starts_with('c') { 3 } else { 2 };
+        operands(p, 1, ccv)?;
+        if ccv == 3 && p.srcs[2] != "ar.ccv" {
+            return Err(error("cmpxchg compares with ar.ccv".to_string()));
+        }
+        return Ok(Some(
+            access(4, x6, hint, 0, 1)
+                | (address(p.srcs[0])? << 20)
+                | (gr(p.srcs[1])? << 13)
+                | (gr(p.dests[0])? << 6),
+        ));
+    }
+    if let Some(rest) = p.base.strip_prefix("fetchadd") {
+        operands(p, 1, 2)?;
+        let x6 = match (rest, completers) {
+            ("4", "acq") => 0x12,
+            ("8", "acq") => 0x13,
+            ("4", "rel") => 0x16,
+            ("8", "rel") => 0x17,
+            _ => return Ok(None),
+        };
+        let inc = immediate(p.srcs[1])?;
+        let Some(i2b) = [16, 8, 4, 1].iter().position(|&m| m == inc.abs()) else {
+            return Err(error(
+                "fetchadd adds -16, -8, -4, -1, 1, 4, 8 or 16".to_string(),
+            ));
+        };
+        let s = u64::from(inc < 0);
+        return Ok(Some(
+            access(4, x6, 0, 0, 1)
+                | (s << 15)
+                | ((i2b as u64) << 13)
+                | (address(p.srcs[0])? << 20)
+                | (gr(p.dests[0])? << 6),
+        ));
+    }
+    if p.base == "getf" || p.base == "setf" {
+        operands(p, 1, 1)?;
+        let Some(kind) = transfer_kind(completers) else {
+            return Ok(None);
+        };
+        let x6 = 0x1C + kind as u64;
+        return Ok(Some(if p.base == "getf" {
+            access(4, x6, 0, 0, 1) | (fr(p.srcs[0])? << 13) | (gr(p.dests[0])? << 6)
+        } else {
+            access(6, x6, 0, 0, 1) | (gr(p.srcs[0])? << 13) | (fr(p.dests[0])? << 6)
+        }));
+    }
+    if let Some(rest) = p.base.strip_prefix("ldfp") {
+        let size = match rest {
+            "8" => 1,
+            "s" => 2,
+            "d" => 3,
+            _ => return Ok(None),
+        };
+        let Some((kind, hint)) =
+            access_kind(comps, false).filter(|&(kind, _)| matches!(kind, 0..=3 | 8 | 9))
+        else {
+            return Ok(None);
+        };
+        operands(p, 2, p.srcs.len().clamp(1, 2))?;
+        let m = u64::from(p.srcs.len() == 2);
+        if m == 1 {
+            let expected = if size == 2 { 8 } else { 16 };
+            if immediate(p.srcs[1])? != expected {
+                return Err(error(format!(
+                    "ldfp{} increments the base by {}",
+                    rest, expected
+                )));
+            }
+        }
+        return Ok(Some(
+            access(6, (kind << 2) | size, hint, m, 1)
+                | (address(p.srcs[0])? << 20)
+                | (fr(p.dests[1])? << 13)
+                | (fr(p.dests[0])? << 6),
+        ));
+    }
+    if p.base == "lfetch" {
+        let mut x6 = 0x2C;
+        let mut hint = 0;
+        for &completer in comps {
+            match completer {
+                "fault" => x6 |= 2,
+                "excl" => x6 |= 1,
+                "nt1" => hint = 1,
+                "nt2" => hint = 2,
+                "nta" => hint = 3,
+                _ => return Ok(None),
+            }
+        }
+        operands(p, 0, p.srcs.len().clamp(1, 2))?;
+        let r3 = address(p.srcs[0])? << 20;
+        return Ok(Some(match p.srcs.get(1) {
+            None => access(6, x6, hint, 0, 0) | r3,
+            Some(r2) if is_gr(r2) => access(6, x6, hint, 1, 0) | r3 | (gr(r2)? << 13),
+            Some(value) => access(7, x6, hint, 0, 0) | r3 | load_imm(imm(value, 9, false)?),
+        }));
+    }
+
+    // Integer and floating-point loads and stores: ld8, ldfd, ldf.fill
+    // ldf.fill and stf.spill take no size
+    let (integer, store, size) = if let Some(rest) = p.base.strip_prefix("ldf") {
+        (
+            false,
+            false,
+            if rest.is_empty() {
+                Some(4)
+            } else {
+                fp_size(rest)
+            },
+        )
+    } else if let Some(rest) = p.base.strip_prefix("stf") {
+        (
+            false,
+            true,
+            if rest.is_empty() {
+                Some(4)
+            } else {
+                fp_size(rest)
+            },
+        )
+    } else if let Some(rest) = p.base.strip_prefix("ld") {
+        (true, false, size_of(rest))
+    } else if let Some(rest) = p.base.strip_prefix("st") {
+        (true, true, size_of(rest))
+    } else {
+        return Ok(None);
+    };
+    let Some(size) = size else {
+        return Ok(None);
+    };
+    let Some((kind, hint)) = access_kind(comps, store) else {
+        return Ok(None);
+    };
+    let fill = matches!(kind, 0x6 | 0xE);
+    let x6 = match kind {
+        _ if fill && !matches!((integer, size), (true, 3) | (false, 4)) => return Ok(None),
+        _ if size == 4 && !fill => return Ok(None),
+        0x6 => 0x1B,
+        0xE => 0x3B,
+        0x4 | 0x5 | 0xA | 0xD if !integer => return Ok(None),
+        _ => (kind << 2) | size,
+    };
+    let (major, imm_major) = if integer { (4, 5) } else { (6, 7) };
+    let reg = |text: &str| if integer { gr(text) } else { fr(text) };
+
+    Ok(Some(if store {
+        operands(p, 1, p.srcs.len().clamp(1, 2))?;
+        let bits = (address(p.dests[0])? << 20) | (reg(p.srcs[0])? << 13);
+        match p.srcs.get(1) {
+            None => access(major, x6, hint, 0, 0) | bits,
+            Some(value) => {
+                access(imm_major, x6, hint, 0, 0) | bits | store_imm(imm(value, 9, false)?)
+            }
+        }
+    } else {
+        operands(p, 1, p.srcs.len().clamp(1, 2))?;
+        let bits = (address(p.srcs[0])? << 20) | (reg(p.dests[0])? << 6);
+        match p.srcs.get(1) {
+            None => access(major, x6, hint, 0, 0) | bits,
+            Some(r2) if is_gr(r2) => access(major, x6, hint, 1, 0) | bits | (gr(r2)? << 13),
+            Some(value) => {
+                access(imm_major, x6, hint, 0, 0) | bits | load_imm(imm(value, 9, false)?)
+            }
+        }
+    }))
+}
+
+/// F-unit instructions
+fn floating(p: &Parsed) -> Result<Option<u64>, EmulatorError> {
+    // Status field, written last as .s1-.s3 and omitted for s0
+    let (comps, sf) = match p.completers.last() {
+        Some(&sf) if sf.len() == 2 && sf.starts_with('s') && sf.as_bytes()[1].is_ascii_digit() => {
+            let n = u64::from(sf.as_bytes()[1] - b'0');
+            if n > 3 {
+                return Ok(None);
+            }
+            (&p.completers[..p.completers.len() - 1], n)
+        }
+        _ => (&p.completers[..], 0),
+    };
+    let completers = comps.join(".");
+    let f = |index: usize| -> Result<u64, EmulatorError> {
+        p.srcs
+            .get(index)
+            .ok_or_else(|| error("missing operand".to_string()))
+            .and_then(|s| fr(s))
+    };
+    let f1 = || -> Result<u64, EmulatorError> { Ok(fr(p.dests[0])? << 6) };
+    let sf = sf << 34;
+
+    Ok(Some(match (p.base, completers.as_str()) {
+        ("fmerge", "s" | "ns" | "se") => {
+            operands(p, 1, 2)?;
+            let x6 = 0x10
+                + ["s", "ns", "se"]
+                    .iter()
+                    .position(|&c| c == completers)
+                    .unwrap_or_default() as u64;
+            (x6 << 27) | (f(1)? << 20) | (f(0)? << 13) | f1()?
+        }
+        ("fcvt", "fx" | "fxu" | "fx.trunc" | "fxu.trunc" | "xf") => {
+            operands(p, 1, 1)?;
+            let x6 = match completers.as_str() {
+                "fx" => 0x18,
+                "fxu" => 0x19,
+                "fx.trunc" => 0x1A,
+                "fxu.trunc" => 0x1B,
+                _ if sf == 0 => 0x1C,
+                _ => return Ok(None),
+            };
+            sf | (x6 << 27) | (f(0)? << 13) | f1()?
+        }
+        ("fand" | "fandcm" | "for" | "fxor", "") => {
+            operands(p, 1, 2)?;
+            let x6 = 0x2C
+                + ["fand", "fandcm", "for", "fxor"]
+                    .iter()
+                    .position(|&m| m == p.base)
+                    .unwrap_or_default() as u64;
+            (x6 << 27) | (f(1)? << 20) | (f(0)? << 13) | f1()?
+        }
+        ("fcmp", _) => {
+            operands(p, 2, 2)?;
+            let (relation, unc) = match completers.strip_suffix(".unc") {
+                Some(relation) => (relation, 1),
+                None => (completers.as_str(), 0),
+            };
+            let Some(index) = ["eq", "lt", "le", "unord"]
+                .iter()
+                .position(|&r| r == relation)
+            else {
+                return Ok(None);
+            };
+            let index = index as u64;
+            (4 << 37)
+                | ((index & 1) << 36)
+                | sf
+                | ((index >> 1) << 33)
+                | (pr(p.dests[1])? << 27)
+                | (f(1)? << 20)
+                | (f(0)? << 13)
+                | (unc << 12)
+                | (pr(p.dests[0])? << 6)
+        }
+        ("fma" | "fms" | "fnma" | "fpma" | "fpms" | "fpnma", _) => {
+            operands(p, 1, 3)?;
+            let base = p.base.replacen("fp", "f", 1);
+            let index = ["fma", "fms", "fnma"]
+                .iter()
+                .position(|&m| m == base)
+                .unwrap_or_default() as u64;
+            let (odd, x) = match (p.base.starts_with("fp"), completers.as_str()) {
+                (false, "") => (0, 0),
+                (false, "s") => (0, 1),
+                (false, "d") => (1, 0),
+                (true, "") => (1, 1),
+                _ => return Ok(None),
+            };
+            ((8 + index * 2 + odd) << 37)
+                | (x << 36)
+                | sf
+                | (f(1)? << 27)
+                | (f(0)? << 20)
+                | (f(2)? << 13)
+                | f1()?
+        }
+        ("fselect", "") => {
+            operands(p, 1, 3)?;
+            (0xE << 37) | (f(1)? << 27) | (f(0)? << 20) | (f(2)? << 13) | f1()?
+        }
+        ("xma", "l" | "hu" | "h") => {
+            operands(p, 1, 3)?;
+            let x2 = match completers.as_str() {
+                "l" => 0,
+                "hu" => 2,
+                _ => 3,
+            };
+            (0xE << 37)
+                | (1 << 36)
+                | (x2 << 34)
+                | (f(1)? << 27)
+                | (f(0)? << 20)
+                | (f(2)? << 13)
+                | f1()?
+        }
+        _ => return Ok(None),
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::decoder::disasm::disassemble_slot;
+    use crate::decoder::Bundle;
+
+    /// Assemble `text` at `ip` and disassemble it again
+    fn round_trip(text: &str, ip: u64) -> String {
+        let encoded = assemble(text, ip).unwrap();
+        let unit = match encoded.unit {
+            Unit::A => Unit::I,
+            unit => unit,
+        };
+        disassemble_slot(unit, encoded.bits, encoded.l_slot, ip)
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let texts = [
+            "break.m 0x42",
+            "nop.i 0x0",
+            "hint.f 0x1",
+            "nop.b 0x100000",
+            "break.x 0x3fffffffffffffff",
+            "add r1 = r2, r3",
+            "add r1 = r2, r3, 1",
+            "sub r1 = r2, r3",
+            "sub r8 = -1, r9",
+            "(p7) and r4 = r5, r6",
+            "xor r4 = 127, r6",
+            "shladd r8 = r9, 3, r10",
+            "adds r12 = -16, r12",
+            "addl r2 = -2097152, r1",
+            "mov r8 = 42",
+            "mov r1 = r3",
+            "cmp.eq.unc p6, p7 = r14, r15",
+            "cmp4.gt.and p1, p2 = r0, r3",
+            "cmp.ltu p1, p2 = -128, r3",
+            "zxt4 r8 = r9",
+            "czx2.r r8 = r9",
+            "mov.i ar.lc = r9",
+            "mov.i ar.ec = -3",
+            "mov.m r9 = ar.bsp",
+            "mov.m ar.ccv = 5",
+            "mov r3 = ip",
+            "mov r3 = b6",
+            "mov b6 = r3",
+            "mov r4 = pr",
+            "mov pr = r4, 0x1fffe",
+            "mov pr.rot = 0xffffffffffff0000",
+            "mov r1 = cr.ivr",
+            "mov cr.iva = r7",
+            "mov r1 = psr",
+            "mov psr.l = r2",
+            "mov rr[r3] = r2",
+            "mov r1 = cpuid[r3]",
+            "dep r1 = r2, r3, 8, 16",
+            "dep r1 = -1, r3, 63, 1",
+            "dep.z r1 = r2, 4, 60",
+            "dep.z r1 = -5, 4, 8",
+            "extr r8 = r9, 0, 64",
+            "extr.u r8 = r9, 4, 8",
+            "shrp r8 = r9, r10, 13",
+            "tbit.nz.and p1, p2 = r3, 63",
+            "tnat.z.unc p1, p2 = r3",
+            "shl r1 = r2, r3",
+            "shr r1 = r3, r2",
+            "shr.u r1 = r3, r2",
+            "sum 0x800001",
+            "rsm 0x6000",
+            "flushrs",
+            "mf.a",
+            "srlz.i",
+            "invala.e f9",
+            "alloc r32 = ar.pfs, 0, 5, 3, 8",
+            "ptc.ga r3, r2",
+            "itr.d dtr[r3] = r2",
+            "probe.w r1 = r3, 2",
+            "probe.rw.fault r3, 3",
+            "tpa r1 = r3",
+            "fc r3",
+            "itc.i r2",
+            "(p6) ld8.acq r4 = [r5]",
+            "ld4.s.nta r8 = [r9], r10",
+            "ld2.c.clr.acq r8 = [r9], -256",
+            "ld8.fill r8 = [r9]",
+            "st8 [r3] = r2, -8",
+            "st4.rel.nta [r3] = r2",
+            "st8.spill [r3] = r2, 16",
+            "ldfd f8 = [r3]",
+            "ldfe.sa.nt1 f8 = [r3], r4",
+            "ldf.fill f8 = [r3], 255",
+            "stfs [r3] = f2",
+            "stf.spill [r3] = f2",
+            "lfetch.fault.excl.nt2 [r3], 64",
+            "lfetch [r3], r9",
+            "cmpxchg8.acq r1 = [r3], r2, ar.ccv",
+            "xchg4.nta r1 = [r3], r2",
+            "fetchadd8.rel r1 = [r3], -16",
+            "getf.sig r1 = f2",
+            "setf.d f1 = r2",
+            "ldfp8 f8, f9 = [r3]",
+            "ldfps.a f8, f9 = [r3], 8",
+            "fmerge.ns f1 = f2, f3",
+            "fcvt.fx.trunc.s1 f1 = f2",
+            "fcvt.xf f1 = f2",
+            "fxor f1 = f2, f3",
+            "fcmp.unord.unc.s2 p1, p2 = f2, f3",
+            "fma.s.s1 f9 = f7, f8, f0",
+            "fpnma.s3 f9 = f7, f8, f10",
+            "fms.d f9 = f7, f8, f10",
+            "fselect f1 = f3, f4, f2",
+            "xma.hu f1 = f3, f4, f2",
+            "cover",
+            "clrrrb.pr",
+            "bsw.1",
+            "br.ret.sptk.many b0",
+            "br.ia.sptk.few b6",
+            "(p3) br.cond.dpnt.many.clr b6",
+            "br.call.spnt.few b0 = b6",
+            "br.call.dptk.few b0 = 0x4020",
+            "br.cloop.sptk.few 0x3ff0",
+            "(p6) br.cond.dptk.few 0x0",
+            "movl r8 = 0x8000000000000001",
+            "brl.cond.sptk.few 0x7ffffff0",
+            "brl.call.dpnt.many b1 = 0x0",
+        ];
+        for text in texts {
+            assert_eq!(round_trip(text, 0x4000), text);
+        }
+
+        // chk targets, on both the M and I units
+        for text in [
+            "chk.s.i r2, 0x3f00",
+            "chk.s.m r2, 0x14000",
+            "chk.s f2, 0x4000",
+            "chk.a.clr f6, 0x3ff0",
+        ] {
+            assert_eq!(round_trip(text, 0x4000), text);
+        }
+    }
+
+    #[test]
+    fn test_known_encodings() {
+        let bits = |text: &str| assemble(text, 0).unwrap().bits;
+        assert_eq!(
+            bits("ld8 r4 = [r3]"),
+            (4 << 37) | (0x03 << 30) | (3 << 20) | (4 << 6)
+        );
+        assert_eq!(
+            bits("st8 [r3] = r2"),
+            (4 << 37) | (0x33 << 30) | (3 << 20) | (2 << 13)
+        );
+        assert_eq!(
+            bits("alloc r32 = ar.pfs, 0, 2, 2, 0"),
+            (1 << 37) | (6 << 33) | (2 << 20) | (4 << 13) | (32 << 6)
+        );
+        assert_eq!(bits("br.ret.sptk.few b0"), (0x21 << 27) | (4 << 6));
+        assert_eq!(bits("br.call.sptk.few b0 = 0x20"), (5 << 37) | (2 << 13));
+    }
+
+    #[test]
+    fn test_defaults_and_errors() {
+        // Branch hints default to sptk.few, and mov to ar.pfs picks the I unit
+        assert_eq!(
+            round_trip("br.cond 0x4010", 0x4000),
+            "br.cond.sptk.few 0x4010"
+        );
+        let mov = assemble("mov ar.pfs = r2", 0).unwrap();
+        assert_eq!(mov.unit, Unit::I);
+        assert_eq!(assemble("mov ar.rsc = r2", 0).unwrap().unit, Unit::M);
+
+        for text in [
+            "add r1 = r2",
+            "adds r1 = 8192, r2",
+            "addl r1 = 1, r5",
+            "ld8 r1 = r2",
+            "ld3 r1 = [r2]",
+            "br.cond.sptk.few 0x4008",
+            "cmp.gt.and p1, p2 = r1, r3",
+            "fetchadd8.acq r1 = [r3], 2",
+            "frobnicate r1",
+            "(p64) nop.m 0",
+        ] {
+            assert!(
+                matches!(assemble(text, 0x4000), Err(EmulatorError::AssemblyError(_))),
+                "{}",
+                text
+            );
+        }
+    }
+
+    #[test]
+    fn test_execute() {
+        use crate::cpu::registers::ar::AR;
+        use crate::emulator::{Emulator, StopReason};
+
+        // Sum 5 + 4 + ... + 1 in a counted loop
+        let program = [
+            BundleBuilder::new()
+                .insn("mov r8 = 0")
+                .insn("mov r9 = 5")
+                .insn("nop.i 0x0 ;;"),
+            BundleBuilder::new()
+                .insn("add r8 = r8, r9")
+                .insn("adds r9 = -1, r9")
+                .insn("br.cloop.sptk.few 0x10010 ;;"),
+            BundleBuilder::new()
+                .insn("nop.m 0x0")
+                .insn("movl r10 = 0x123456789abcdef0 ;;"),
+            BundleBuilder::new()
+                .insn("break.m 0x1234")
+                .insn("nop.i 0x0")
+                .insn("nop.i 0x0"),
+        ];
+        let image: Vec<u8> = program
+            .iter()
+            .enumerate()
+            .flat_map(|(i, builder)| builder.clone().at(0x10000 + i as u64 * 16).build().unwrap())
+            .collect();
+
+        let mut emu = Emulator::new();
+        emu.load_flat_image(0x10000, &image, 0x10000).unwrap();
+        emu.cpu.write_ar(AR::LC, 4).unwrap();
+        assert_eq!(emu.run().unwrap(), StopReason::Break(0x1234));
+        assert_eq!(emu.cpu.gr[8], 15);
+        assert_eq!(emu.cpu.gr[10], 0x1234_5678_9abc_def0);
+    }
+
+    #[test]
+    fn test_bundle_builder() {
+        let data = BundleBuilder::new()
+            .at(0x10000)
+            .insn("ld8 r4 = [r3]")
+            .insn("(p2) mov r14 = ip")
+            .stop()
+            .insn("nop.i 0x0")
+            .build()
+            .unwrap();
+        let bundle = Bundle::new(data).unwrap();
+        assert_eq!(bundle.template(), BundleTemplate::MIStopI);
+        assert_eq!(
+            bundle.disassemble(),
+            ["ld8 r4 = [r3]", "(p2) mov r14 = ip ;;", "nop.i 0x0"]
+        );
+
+        // The long instruction takes the L and X slots
+        let data = BundleBuilder::new()
+            .insn("nop.m 0x0")
+            .insn("movl r8 = 0x123456789abcdef0 ;;")
+            .build()
+            .unwrap();
+        let bundle = Bundle::new(data).unwrap();
+        assert_eq!(bundle.template(), BundleTemplate::MLXStop);
+        assert_eq!(bundle.disassemble()[1], "movl r8 = 0x123456789abcdef0 ;;");
+
+        // A-unit instructions fit M slots, and a given template is kept
+        let data = BundleBuilder::new()
+            .template(BundleTemplate::MMB)
+            .insn("add r1 = r2, r3")
+            .insn("adds r4 = 1, r4")
+            .insn("br.call.sptk.many b0 = 0x40")
+            .at(0x20)
+            .build()
+            .unwrap();
+        let bundle = Bundle::new(data).unwrap();
+        assert_eq!(bundle.template(), BundleTemplate::MMB);
+        assert_eq!(
+            bundle.disassemble_at(0x20)[2],
+            "br.call.sptk.many b0 = 0x40"
+        );
+
+        assert!(BundleBuilder::new()
+            .insn("br.ret.sptk.many b0")
+            .insn("nop.m 0x0")
+            .insn("nop.i 0x0")
+            .build()
+            .is_err());
+        assert!(BundleBuilder::new()
+            .template(BundleTemplate::MFB)
+            .insn("nop.m 0x0")
+            .insn("nop.i 0x0")
+            .insn("nop.b 0x0")
+            .build()
+            .is_err());
+        assert_eq!(
+            encode_bundle(BundleTemplate::MII, [0x01 << 27; 3]),
+            BundleBuilder::new()
+                .insn("nop.m 0")
+                .insn("nop.i 0")
+                .insn("nop.i 0")
+                .build()
+                .unwrap()
+        );
+    }
+}
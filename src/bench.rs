@@ -0,0 +1,270 @@
+//! Per-instruction microbenchmarks
+//!
+//! [`bench_instruction`] times many executions of one instruction through
+//! the interpreter's dispatch, on a CPU with representative register
+//! contents, to show the overhead each instruction class adds. The
+//! instruction's translation is kept between executions, as the decode
+//! cache keeps it. Memory,
+//! devices and system calls are not involved: breaks are decoded and
+//! reported to the harness, which ignores them.
+//!
+//! [`bench_decode`] times the same loop with the bundle decoded on every
+//! iteration and translated afresh, as the emulator does on a decode cache
+//! miss, to show what decoding adds on top of dispatch.
+//!
+//! Allocations are counted when the program installs [`CountingAllocator`]
+//! as its global allocator, as the command-line front end does when built
+//! with the `count-allocations` feature; otherwise reports leave them out.
+//!
+//! Instructions are written as for [`crate::asm`], such as `mov r14 = ip`,
+//! and assembled into a bundle with no-ops in the other slots.
+
+use crate::asm::{self, BundleBuilder};
+use crate::cpu::Cpu;
+use crate::decoder::{BundleTemplate, Unit};
+use crate::emulator::{decode_bundle, execute_instruction, execute_translated, Translation};
+use crate::memory::Memory;
+use crate::EmulatorError;
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::fmt;
+use std::hint::black_box;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+/// Executions timed when no count is given
+pub const DEFAULT_ITERATIONS: u64 = 1_000_000;
+
+/// Address of the bundle holding the instruction
+const BENCH_IP: u64 = 0x4000_0000_0000_0000;
+
+/// Allocations made through [`CountingAllocator`]
+static ALLOCATIONS: AtomicU64 = AtomicU64::new(0);
+
+/// Global allocator that counts allocations and forwards them to the
+/// system allocator
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CountingAllocator;
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc_zeroed(layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+/// Allocations made through [`CountingAllocator`] so far
+pub fn allocations() -> u64 {
+    ALLOCATIONS.load(Ordering::Relaxed)
+}
+
+/// Instruction to benchmark, in a bundle of its own
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BenchInstruction {
+    /// Unit the instruction issues to
+    pub unit: Unit,
+    /// Bundle holding the instruction, with no-ops in the other slots
+    bundle: [u8; 16],
+    /// Slot of the instruction in the bundle
+    slot: usize,
+}
+
+impl BenchInstruction {
+    /// Assemble an instruction written as for [`crate::asm`]
+    pub fn parse(text: &str) -> Result<Self, EmulatorError> {
+        let unit = asm::assemble(text, BENCH_IP)?.unit;
+        let (template, slot) = match unit {
+            Unit::M => (BundleTemplate::MII, 0),
+            Unit::I | Unit::A => (BundleTemplate::MII, 1),
+            Unit::F => (BundleTemplate::MFI, 1),
+            Unit::B => (BundleTemplate::MIB, 2),
+            Unit::L | Unit::X => (BundleTemplate::MLX, 2),
+        };
+        let mut builder = BundleBuilder::new().at(BENCH_IP).template(template);
+        for (i, slot_unit) in template.units().into_iter().enumerate() {
+            builder = match slot_unit {
+                _ if i == slot => builder.insn(text),
+                // The X slot's instruction takes the L slot too
+                Unit::L => builder,
+                _ => builder.insn(&format!(
+                    "nop.{} 0",
+                    slot_unit.letter().to_ascii_lowercase()
+                )),
+            };
+        }
+        Ok(Self {
+            unit,
+            bundle: builder.build()?,
+            slot,
+        })
+    }
+}
+
+/// Timing of a benchmarked instruction
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BenchReport {
+    /// Executions timed
+    pub iterations: u64,
+    /// Time they took
+    pub elapsed: Duration,
+    /// Allocations they made, when [`CountingAllocator`] counts them
+    pub allocations: Option<u64>,
+}
+
+impl BenchReport {
+    /// Nanoseconds per execution
+    pub fn ns_per_op(&self) -> f64 {
+        if self.iterations == 0 {
+            return 0.0;
+        }
+        self.elapsed.as_nanos() as f64 / self.iterations as f64
+    }
+
+    /// Allocations per execution, when counted
+    pub fn allocations_per_op(&self) -> Option<f64> {
+        let allocations = self.allocations?;
+        if self.iterations == 0 {
+            return Some(0.0);
+        }
+        Some(allocations as f64 / self.iterations as f64)
+    }
+}
+
+impl fmt::Display for BenchReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} iterations, {:.1} ns/op",
+            self.iterations,
+            self.ns_per_op()
+        )?;
+        match self.allocations_per_op() {
+            Some(allocations) => write!(f, ", {:.2} allocations/op", allocations),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Time `iterations` executions of an instruction
+///
+/// The instruction executes once untimed first, so an unimplemented one is
+/// an error rather than a meaningless timing.
+pub fn bench_instruction(
+    instruction: &BenchInstruction,
+    iterations: u64,
+) -> Result<BenchReport, EmulatorError> {
+    let slot = instruction.slot;
+    let (itype, bits) = decode_bundle(instruction.bundle)?.slots[slot];
+    let translation = Translation::new();
+    time(slot, iterations, |cpu, memory| {
+        execute_translated(
+            cpu,
+            memory,
+            black_box(&itype),
+            black_box(bits),
+            &translation,
+        )
+    })
+}
+
+/// Time `iterations` decodes of an instruction's bundle, each followed by
+/// an execution of the instruction
+pub fn bench_decode(
+    instruction: &BenchInstruction,
+    iterations: u64,
+) -> Result<BenchReport, EmulatorError> {
+    let slot = instruction.slot;
+    time(slot, iterations, |cpu, memory| {
+        let (itype, bits) = decode_bundle(black_box(instruction.bundle))?.slots[slot];
+        execute_instruction(cpu, memory, &itype, bits)
+    })
+}
+
+/// Time `iterations` calls of `run` on a CPU at the benchmark bundle, after
+/// one untimed call
+///
+/// Nothing is mapped in the memory, so loads and stores fault.
+fn time<T>(
+    slot: usize,
+    iterations: u64,
+    mut run: impl FnMut(&mut Cpu, &mut Memory) -> Result<T, EmulatorError>,
+) -> Result<BenchReport, EmulatorError> {
+    let mut memory = Memory::new();
+    let mut cpu = Cpu::new();
+    cpu.pr[0] = true;
+    for reg in 1..32 {
+        cpu.gr[reg] = (reg as u64).wrapping_mul(0x0101_0101_0101_0101);
+    }
+    cpu.slot = slot as u8;
+    cpu.ip = BENCH_IP;
+    run(&mut cpu, &mut memory)?;
+
+    // Setting up the CPU allocated, so a zero count means nothing counts
+    let before = allocations();
+    let counting = before > 0;
+    let start = Instant::now();
+    for _ in 0..iterations {
+        // Checks may branch; every execution starts from the same bundle
+        cpu.ip = BENCH_IP;
+        black_box(run(&mut cpu, &mut memory)?);
+    }
+    let elapsed = start.elapsed();
+
+    Ok(BenchReport {
+        iterations,
+        elapsed,
+        allocations: counting.then(|| allocations() - before),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::mov_ip;
+
+    #[test]
+    fn test_parse() {
+        let instruction = BenchInstruction::parse("mov r14 = ip").unwrap();
+        assert_eq!((instruction.unit, instruction.slot), (Unit::I, 1));
+        let decoded = decode_bundle(instruction.bundle).unwrap();
+        assert_eq!(decoded.slots[1].1, mov_ip(0, 14));
+        assert_eq!(BenchInstruction::parse("nop.b 0").unwrap().slot, 2);
+        assert_eq!(BenchInstruction::parse("break.x 5").unwrap().slot, 2);
+
+        for text in ["nop.l 0", "break.i 0x200000", "mov r128 = ip", "frob r1"] {
+            assert!(BenchInstruction::parse(text).is_err(), "{}", text);
+        }
+    }
+
+    #[test]
+    fn test_bench_instruction() {
+        for text in ["nop.b 0", "break.x 5", "mov r8 = ip", "add r8 = r9, r10"] {
+            let instruction = BenchInstruction::parse(text).unwrap();
+            let report = bench_instruction(&instruction, 100).unwrap();
+            assert_eq!(report.iterations, 100);
+            // The test harness does not install the counting allocator
+            assert_eq!(
+                report.to_string(),
+                format!("100 iterations, {:.1} ns/op", report.ns_per_op())
+            );
+            assert_eq!(bench_decode(&instruction, 10).unwrap().iterations, 10);
+        }
+
+        let unimplemented = BenchInstruction::parse("fetchadd4.acq r8 = [r9], 1").unwrap();
+        assert!(bench_instruction(&unimplemented, 100).is_err());
+        assert!(bench_decode(&unimplemented, 100).is_err());
+    }
+}
@@ -0,0 +1,665 @@
+//! Disassembler interface in the style of the capstone crate
+//!
+//! Tools written against capstone's Rust bindings iterate decoded
+//! instructions, print their mnemonic and operand string, and ask for
+//! details: operands, groups and the registers read and written. This
+//! module offers the same shape over the native decoder, so such tools can
+//! switch with few changes:
+//!
+//! ```
+//! use rust_ia64::capstone_compat::Capstone;
+//!
+//! let cs = Capstone::new().ia64().detail(true).build().unwrap();
+//! # let code = [0u8; 16];
+//! for insn in cs.disasm_all(&code, 0x4000).unwrap().iter() {
+//!     let detail = cs.insn_detail(insn).unwrap();
+//!     println!("{} reads {:?}", insn, detail.regs_read());
+//! }
+//! ```
+//!
+//! An instruction is one slot of a bundle; its address is the bundle
+//! address plus the slot number, as GNU objdump prints them, and its bytes
+//! are the whole bundle. The L and X slots of an MLX bundle form one
+//! instruction at slot 1. As with capstone, disassembly stops at the first
+//! bundle holding an instruction it cannot name, unless skipdata is on, in
+//! which case such slots come back as `.slot` pseudo-instructions.
+
+use crate::decoder::{InstructionType, Unit};
+use crate::emulator::{
+    decode_bundle, imm21, imm21_check_a, imm21_check_s, major_opcode, r1, r2, sign_extend, x3, x6,
+    BUNDLE_SIZE,
+};
+use crate::EmulatorError;
+use std::fmt;
+
+/// Register identifier
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct RegId(pub u16);
+
+impl RegId {
+    /// No register
+    pub const INVALID: RegId = RegId(0);
+    /// Instruction pointer
+    pub const IP: RegId = RegId(329);
+
+    /// General register `n`
+    pub fn gr(n: u8) -> Self {
+        RegId(1 + n as u16)
+    }
+
+    /// Floating-point register `n`
+    pub fn fr(n: u8) -> Self {
+        RegId(129 + n as u16)
+    }
+
+    /// Predicate register `n`
+    pub fn pr(n: u8) -> Self {
+        RegId(257 + n as u16)
+    }
+
+    /// Branch register `n`
+    pub fn br(n: u8) -> Self {
+        RegId(321 + n as u16)
+    }
+
+    /// Assembler name, e.g. `r12`
+    fn name(self) -> Option<String> {
+        match self.0 {
+            1..=128 => Some(format!("r{}", self.0 - 1)),
+            129..=256 => Some(format!("f{}", self.0 - 129)),
+            257..=320 => Some(format!("p{}", self.0 - 257)),
+            321..=328 => Some(format!("b{}", self.0 - 321)),
+            329 => Some("ip".to_string()),
+            _ => None,
+        }
+    }
+}
+
+/// Instruction group identifier
+///
+/// The generic groups use capstone's numbers; execution units follow from
+/// 128, where capstone places architecture-specific groups.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct InsnGroupId(pub u8);
+
+impl InsnGroupId {
+    /// Jumps, here the IP-relative branches to recovery code
+    pub const JUMP: InsnGroupId = InsnGroupId(1);
+    /// Software interrupts
+    pub const INT: InsnGroupId = InsnGroupId(4);
+    /// Branches relative to the instruction's address
+    pub const BRANCH_RELATIVE: InsnGroupId = InsnGroupId(7);
+
+    /// Group of the instructions issued to `unit`
+    pub fn unit(unit: Unit) -> Self {
+        InsnGroupId(match unit {
+            Unit::M => 128,
+            Unit::I => 129,
+            Unit::F => 130,
+            Unit::B => 131,
+            Unit::L | Unit::X => 132,
+            Unit::A => 133,
+        })
+    }
+
+    /// Group name
+    fn name(self) -> Option<&'static str> {
+        match self.0 {
+            1 => Some("jump"),
+            4 => Some("int"),
+            7 => Some("branch_relative"),
+            128 => Some("m_unit"),
+            129 => Some("i_unit"),
+            130 => Some("f_unit"),
+            131 => Some("b_unit"),
+            132 => Some("lx_unit"),
+            133 => Some("a_unit"),
+            _ => None,
+        }
+    }
+}
+
+/// Instruction identifier
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct InsnId(pub u32);
+
+impl InsnId {
+    /// Slot that could not be identified (skipdata only)
+    pub const INVALID: InsnId = InsnId(0);
+    /// break
+    pub const BREAK: InsnId = InsnId(1);
+    /// nop
+    pub const NOP: InsnId = InsnId(2);
+    /// mov r1=ip
+    pub const MOV_IP: InsnId = InsnId(3);
+    /// chk.s
+    pub const CHK_S: InsnId = InsnId(4);
+    /// chk.a.nc
+    pub const CHK_A_NC: InsnId = InsnId(5);
+    /// chk.a.clr
+    pub const CHK_A_CLR: InsnId = InsnId(6);
+
+    /// Instruction name without unit suffix
+    fn name(self) -> Option<&'static str> {
+        match self.0 {
+            0 => Some(".slot"),
+            1 => Some("break"),
+            2 => Some("nop"),
+            3 => Some("mov"),
+            4 => Some("chk.s"),
+            5 => Some("chk.a.nc"),
+            6 => Some("chk.a.clr"),
+            _ => None,
+        }
+    }
+}
+
+/// Instruction operand
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Ia64Operand {
+    /// Register
+    Reg(RegId),
+    /// Immediate, or the absolute target of a relative branch
+    Imm(i64),
+}
+
+/// Architecture-specific details of an instruction
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Ia64InsnDetail {
+    operands: Vec<Ia64Operand>,
+}
+
+impl Ia64InsnDetail {
+    /// Operands in assembler order
+    pub fn operands(&self) -> impl Iterator<Item = Ia64Operand> + '_ {
+        self.operands.iter().copied()
+    }
+}
+
+/// Details of an instruction: registers, groups and operands
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InsnDetail {
+    regs_read: Vec<RegId>,
+    regs_write: Vec<RegId>,
+    groups: Vec<InsnGroupId>,
+    arch: Ia64InsnDetail,
+}
+
+impl InsnDetail {
+    /// Registers read, including the qualifying predicate
+    pub fn regs_read(&self) -> &[RegId] {
+        &self.regs_read
+    }
+
+    /// Registers written
+    pub fn regs_write(&self) -> &[RegId] {
+        &self.regs_write
+    }
+
+    /// Groups the instruction belongs to
+    pub fn groups(&self) -> &[InsnGroupId] {
+        &self.groups
+    }
+
+    /// IA-64 specific details
+    pub fn arch_detail(&self) -> &Ia64InsnDetail {
+        &self.arch
+    }
+}
+
+/// Disassembled instruction
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Insn {
+    address: u64,
+    bytes: [u8; 16],
+    id: InsnId,
+    predicate: u8,
+    mnemonic: String,
+    op_str: String,
+    detail: Option<InsnDetail>,
+}
+
+impl Insn {
+    /// Address: the bundle address plus the slot number
+    pub fn address(&self) -> u64 {
+        self.address
+    }
+
+    /// Bytes of the bundle holding the instruction
+    pub fn bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    /// Instruction identifier
+    pub fn id(&self) -> InsnId {
+        self.id
+    }
+
+    /// Mnemonic with completers, e.g. `break.m`
+    pub fn mnemonic(&self) -> Option<&str> {
+        Some(&self.mnemonic)
+    }
+
+    /// Operands as text
+    pub fn op_str(&self) -> Option<&str> {
+        Some(&self.op_str)
+    }
+
+    /// Qualifying predicate register; 0 means always executed
+    pub fn predicate(&self) -> u8 {
+        self.predicate
+    }
+}
+
+impl fmt::Display for Insn {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:#x}: ", self.address)?;
+        if self.predicate != 0 {
+            write!(f, "(p{}) ", self.predicate)?;
+        }
+        write!(f, "{}", self.mnemonic)?;
+        if !self.op_str.is_empty() {
+            write!(f, " {}", self.op_str)?;
+        }
+        Ok(())
+    }
+}
+
+/// Instructions returned by a disassembly
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Instructions(Vec<Insn>);
+
+impl Instructions {
+    /// Iterate over the instructions
+    pub fn iter(&self) -> std::slice::Iter<'_, Insn> {
+        self.0.iter()
+    }
+
+    /// Number of instructions
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    /// Whether nothing was disassembled
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl<'a> IntoIterator for &'a Instructions {
+    type Item = &'a Insn;
+    type IntoIter = std::slice::Iter<'a, Insn>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.iter()
+    }
+}
+
+/// Builder for a [`Capstone`] handle
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CapstoneBuilder {
+    detail: bool,
+    skipdata: bool,
+}
+
+impl CapstoneBuilder {
+    /// Select IA-64, the only architecture; kept for source compatibility
+    pub fn ia64(self) -> Self {
+        self
+    }
+
+    /// Compute instruction details
+    pub fn detail(mut self, detail: bool) -> Self {
+        self.detail = detail;
+        self
+    }
+
+    /// Return unidentified slots as `.slot` instead of stopping
+    pub fn skipdata(mut self, skipdata: bool) -> Self {
+        self.skipdata = skipdata;
+        self
+    }
+
+    /// Build the handle
+    pub fn build(self) -> Result<Capstone, EmulatorError> {
+        Ok(Capstone {
+            detail: self.detail,
+            skipdata: self.skipdata,
+        })
+    }
+}
+
+/// Disassembler handle
+#[derive(Debug, Clone)]
+pub struct Capstone {
+    detail: bool,
+    skipdata: bool,
+}
+
+impl Capstone {
+    /// Start building a handle
+    #[allow(clippy::new_ret_no_self)]
+    pub fn new() -> CapstoneBuilder {
+        CapstoneBuilder::default()
+    }
+
+    /// Disassemble every whole bundle in `code`, which starts at `addr`
+    pub fn disasm_all(&self, code: &[u8], addr: u64) -> Result<Instructions, EmulatorError> {
+        self.disasm(code, addr, usize::MAX)
+    }
+
+    /// Disassemble at most `count` instructions
+    pub fn disasm_count(
+        &self,
+        code: &[u8],
+        addr: u64,
+        count: usize,
+    ) -> Result<Instructions, EmulatorError> {
+        self.disasm(code, addr, count)
+    }
+
+    /// Details of an instruction disassembled with detail on
+    pub fn insn_detail(&self, insn: &Insn) -> Result<InsnDetail, EmulatorError> {
+        insn.detail.clone().ok_or_else(|| {
+            EmulatorError::DecodeError("Instruction details were not requested".to_string())
+        })
+    }
+
+    /// Name of a register
+    pub fn reg_name(&self, reg: RegId) -> Option<String> {
+        reg.name()
+    }
+
+    /// Name of an instruction
+    pub fn insn_name(&self, id: InsnId) -> Option<String> {
+        id.name().map(str::to_string)
+    }
+
+    /// Name of a group
+    pub fn group_name(&self, group: InsnGroupId) -> Option<String> {
+        group.name().map(str::to_string)
+    }
+
+    fn disasm(&self, code: &[u8], addr: u64, count: usize) -> Result<Instructions, EmulatorError> {
+        let mut insns = Vec::new();
+        'bundles: for (i, chunk) in code.chunks_exact(BUNDLE_SIZE as usize).enumerate() {
+            let bundle_ip = addr + i as u64 * BUNDLE_SIZE;
+            let bytes: [u8; 16] = chunk.try_into().unwrap();
+            let Ok(decoded) = decode_bundle(bytes) else {
+                break;
+            };
+
+            let mut bundle = Vec::new();
+            for (slot, (itype, bits)) in decoded.slots.iter().enumerate() {
+                if matches!(itype, InstructionType::L(_)) {
+                    continue;
+                }
+                let slot = if matches!(itype, InstructionType::X(_)) {
+                    1
+                } else {
+                    slot
+                };
+                let address = bundle_ip + slot as u64;
+                let insn = match identify(itype, *bits, bundle_ip) {
+                    Some(insn) => insn,
+                    None if self.skipdata => Identified {
+                        id: InsnId::INVALID,
+                        mnemonic: ".slot".to_string(),
+                        operands: vec![Ia64Operand::Imm(*bits as i64)],
+                        reads: vec![],
+                        writes: vec![],
+                        groups: vec![],
+                    },
+                    None => break 'bundles,
+                };
+                bundle.push(insn.finish(address, bytes, itype.unit(), *bits, self.detail));
+            }
+            for insn in bundle {
+                if insns.len() == count {
+                    break 'bundles;
+                }
+                insns.push(insn);
+            }
+        }
+        Ok(Instructions(insns))
+    }
+}
+
+/// Instruction named from its encoding, before formatting
+struct Identified {
+    id: InsnId,
+    mnemonic: String,
+    operands: Vec<Ia64Operand>,
+    reads: Vec<RegId>,
+    writes: Vec<RegId>,
+    groups: Vec<InsnGroupId>,
+}
+
+impl Identified {
+    fn finish(
+        mut self,
+        address: u64,
+        bytes: [u8; 16],
+        unit: Unit,
+        bits: u64,
+        detail: bool,
+    ) -> Insn {
+        let predicate = (bits & 0x3F) as u8;
+        let separator = match self.id {
+            InsnId::MOV_IP => "=",
+            _ => ", ",
+        };
+        let op_str = self
+            .operands
+            .iter()
+            .map(|operand| match operand {
+                Ia64Operand::Reg(reg) => reg.name().unwrap_or_default(),
+                Ia64Operand::Imm(imm) => format!("{:#x}", imm),
+            })
+            .collect::<Vec<_>>()
+            .join(separator);
+
+        let detail = detail.then(|| {
+            if predicate != 0 {
+                self.reads.insert(0, RegId::pr(predicate));
+            }
+            self.groups.push(InsnGroupId::unit(unit));
+            InsnDetail {
+                regs_read: self.reads,
+                regs_write: self.writes,
+                groups: self.groups,
+                arch: Ia64InsnDetail {
+                    operands: self.operands,
+                },
+            }
+        });
+        Insn {
+            address,
+            bytes,
+            id: self.id,
+            predicate,
+            mnemonic: self.mnemonic,
+            op_str,
+            detail,
+        }
+    }
+}
+
+/// Name an instruction slot, following the emulator's per-unit decoding
+fn identify(itype: &InstructionType, bits: u64, bundle_ip: u64) -> Option<Identified> {
+    let unit = match itype.unit() {
+        Unit::A => Unit::I,
+        unit => unit,
+    };
+    let suffix = match unit {
+        Unit::M => "m",
+        Unit::I => "i",
+        Unit::F => "f",
+        Unit::B => "b",
+        _ => "x",
+    };
+    let simple = |id: InsnId, imm: u64, groups: Vec<InsnGroupId>| Identified {
+        id,
+        mnemonic: format!("{}.{}", id.name().unwrap_or_default(), suffix),
+        operands: vec![Ia64Operand::Imm(imm as i64)],
+        reads: vec![],
+        writes: vec![],
+        groups,
+    };
+    let check = |id: InsnId, mnemonic: &str, reg: RegId, imm: u64| Identified {
+        id,
+        mnemonic: mnemonic.to_string(),
+        operands: vec![
+            Ia64Operand::Reg(reg),
+            Ia64Operand::Imm(bundle_ip.wrapping_add((sign_extend(imm, 21) << 4) as u64) as i64),
+        ],
+        reads: vec![reg],
+        writes: vec![],
+        groups: vec![InsnGroupId::JUMP, InsnGroupId::BRANCH_RELATIVE],
+    };
+    let int = vec![InsnGroupId::INT];
+
+    Some(match (unit, major_opcode(bits), x3(bits), x6(bits)) {
+        (Unit::M | Unit::I, 0, 0, 0x00) | (Unit::B, 0, _, 0x00) => {
+            simple(InsnId::BREAK, imm21(bits), int)
+        }
+        (Unit::F, 0, x, 0x00) if x & 1 == 0 => simple(InsnId::BREAK, imm21(bits), int),
+        (Unit::X, 0, 0, 0x00) => {
+            let l_slot = match itype {
+                InstructionType::X(format) => format.l_slot,
+                _ => 0,
+            };
+            simple(InsnId::BREAK, (l_slot << 21) | imm21(bits), int)
+        }
+        (Unit::M | Unit::I | Unit::X, 0, 0, 0x01) | (Unit::B, 2, _, 0x00) => {
+            simple(InsnId::NOP, imm21(bits), vec![])
+        }
+        (Unit::F, 0, x, 0x01) if x & 1 == 0 => simple(InsnId::NOP, imm21(bits), vec![]),
+        (Unit::I, 0, 0, 0x30) => Identified {
+            id: InsnId::MOV_IP,
+            mnemonic: "mov".to_string(),
+            operands: vec![
+                Ia64Operand::Reg(RegId::gr(r1(bits))),
+                Ia64Operand::Reg(RegId::IP),
+            ],
+            reads: vec![RegId::IP],
+            writes: vec![RegId::gr(r1(bits))],
+            groups: vec![],
+        },
+        (Unit::I, 0, 1, _) => check(
+            InsnId::CHK_S,
+            "chk.s.i",
+            RegId::gr(r2(bits)),
+            imm21_check_s(bits),
+        ),
+        (Unit::M, 1, 1 | 3, _) => {
+            let reg = match x3(bits) {
+                3 => RegId::fr(r2(bits)),
+                _ => RegId::gr(r2(bits)),
+            };
+            check(InsnId::CHK_S, "chk.s.m", reg, imm21_check_s(bits))
+        }
+        (Unit::M, 0, 4..=7, _) => {
+            let reg = match x3(bits) {
+                4 | 5 => RegId::gr(r1(bits)),
+                _ => RegId::fr(r1(bits)),
+            };
+            let id = match x3(bits) & 1 {
+                0 => InsnId::CHK_A_NC,
+                _ => InsnId::CHK_A_CLR,
+            };
+            check(id, id.name().unwrap_or_default(), reg, imm21_check_a(bits))
+        }
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{encode_break_nop, encode_bundle};
+
+    #[test]
+    fn test_disasm() {
+        let mov_ip = (2 | (0x30 << 27)) | (14 << 6);
+        // chk.a.clr r6, two bundles back
+        let chk_a = ((0x1FFFFE & 0xFFFFF) << 13) | (1 << 36) | (5 << 33) | (6 << 6);
+        let code = [
+            encode_bundle(
+                0,
+                [
+                    encode_break_nop(0, 0, 0x42),
+                    mov_ip,
+                    encode_break_nop(0, 1, 0),
+                ],
+            ),
+            encode_bundle(4, [chk_a, 0x155, encode_break_nop(0, 0, 0x7)]),
+        ]
+        .concat();
+
+        let cs = Capstone::new().ia64().detail(true).build().unwrap();
+        let insns = cs.disasm_all(&code, 0x4000).unwrap();
+        let text: Vec<String> = insns.iter().map(ToString::to_string).collect();
+        assert_eq!(
+            text,
+            [
+                "0x4000: break.m 0x42",
+                "0x4001: (p2) mov r14=ip",
+                "0x4002: nop.i 0x0",
+                "0x4010: chk.a.clr r6, 0x3ff0",
+                "0x4011: break.x 0x2aa00007",
+            ]
+        );
+
+        let insns: Vec<&Insn> = insns.iter().collect();
+        assert_eq!(insns[1].bytes(), &code[..16]);
+        let detail = cs.insn_detail(insns[1]).unwrap();
+        assert_eq!(detail.regs_read(), [RegId::pr(2), RegId::IP]);
+        assert_eq!(detail.regs_write(), [RegId::gr(14)]);
+        assert_eq!(cs.reg_name(detail.regs_write()[0]).unwrap(), "r14");
+
+        let detail = cs.insn_detail(insns[3]).unwrap();
+        assert!(detail.groups().contains(&InsnGroupId::JUMP));
+        assert_eq!(
+            cs.group_name(*detail.groups().last().unwrap()).unwrap(),
+            "m_unit"
+        );
+        assert_eq!(
+            detail.arch_detail().operands().collect::<Vec<_>>(),
+            [Ia64Operand::Reg(RegId::gr(6)), Ia64Operand::Imm(0x3ff0)]
+        );
+        assert_eq!(cs.insn_name(insns[0].id()).unwrap(), "break");
+        assert_eq!(cs.disasm_count(&code, 0x4000, 2).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_unknown_slots() {
+        let code = [
+            encode_bundle(
+                0,
+                [
+                    encode_break_nop(0, 1, 0),
+                    8 << 37,
+                    encode_break_nop(0, 1, 0),
+                ],
+            ),
+            encode_bundle(0, [encode_break_nop(0, 1, 0); 3]),
+        ]
+        .concat();
+
+        // Disassembly stops at the bundle with an unknown slot
+        let cs = Capstone::new().build().unwrap();
+        assert!(cs.disasm_all(&code, 0).unwrap().is_empty());
+        assert!(cs
+            .insn_detail(&cs.disasm_all(&code[16..], 0).unwrap().0[0])
+            .is_err());
+
+        let cs = Capstone::new().skipdata(true).build().unwrap();
+        let insns = cs.disasm_all(&code, 0).unwrap();
+        assert_eq!(insns.len(), 6);
+        assert_eq!(
+            insns.iter().nth(1).unwrap().to_string(),
+            "0x1: .slot 0x10000000000"
+        );
+    }
+}
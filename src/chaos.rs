@@ -0,0 +1,213 @@
+//! Deterministic chaos scheduling
+//!
+//! Guest races often hide behind the emulator's perfectly regular timing:
+//! the interval timer fires on exactly the bundle ITC reaches ITM, and
+//! device hotplug lands on the next bundle boundary. In chaos mode both are
+//! held back by a pseudo-random number of bundles, up to a configured
+//! maximum, so the guest sees interrupts arrive at different points in its
+//! critical sections. Chaos mode also varies what the guest cannot see
+//! directly but may depend on by accident: every cache level and the TLB
+//! pick their victims at random, and the cycles each bundle takes, which
+//! drive the cycle counters and AR.ITC, vary by up to a configured
+//! latency jitter either way. The delays, victims and latencies come from
+//! generators seeded by [`ChaosConfig::seed`], so a run that exposes a bug
+//! can be repeated exactly by giving the same seed.
+
+/// Largest delay used when none is given, in bundles
+pub const DEFAULT_MAX_DELAY: u64 = 1000;
+
+/// Largest latency jitter used when none is given, in cycles
+pub const DEFAULT_LATENCY_JITTER: u64 = 2;
+
+/// Stream constant separating the device delays from the timer delays
+const DEVICE_STREAM: u64 = 0x6465_7669_6365_7321;
+
+/// Stream constant of the cache victim choices
+const CACHE_STREAM: u64 = 0x6361_6368_6520_7669;
+
+/// Stream constant of the TLB victim choices
+const TLB_STREAM: u64 = 0x746C_6220_7669_6374;
+
+/// Stream constant of the bundle latencies
+const LATENCY_STREAM: u64 = 0x6C61_7465_6E63_7921;
+
+/// Chaos mode settings
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChaosConfig {
+    /// Seed of the delay generator
+    pub seed: u64,
+    /// Largest delay, in bundles (timer ticks in instruction-count mode)
+    pub max_delay: u64,
+    /// Largest change to the cycles a bundle takes, either way
+    pub latency_jitter: u64,
+}
+
+impl ChaosConfig {
+    /// Chaos with the given seed and the default maximum delay and jitter
+    pub fn new(seed: u64) -> Self {
+        Self {
+            seed,
+            max_delay: DEFAULT_MAX_DELAY,
+            latency_jitter: DEFAULT_LATENCY_JITTER,
+        }
+    }
+
+    /// Generator for timer interrupt delays
+    pub(crate) fn timer(&self) -> Chaos {
+        Chaos::new(self.seed, self.max_delay)
+    }
+
+    /// Generator for device event delays
+    pub(crate) fn devices(&self) -> Chaos {
+        Chaos::new(self.seed ^ DEVICE_STREAM, self.max_delay)
+    }
+
+    /// Seed of the cache replacement generators
+    pub(crate) fn cache_seed(&self) -> u64 {
+        self.seed ^ CACHE_STREAM
+    }
+
+    /// Seed of the TLB replacement generator
+    pub(crate) fn tlb_seed(&self) -> u64 {
+        self.seed ^ TLB_STREAM
+    }
+
+    /// Generator for bundle latencies
+    pub(crate) fn latency(&self) -> Chaos {
+        Chaos::new(self.seed ^ LATENCY_STREAM, self.latency_jitter)
+    }
+}
+
+/// Seeded generator of delivery delays (SplitMix64)
+#[derive(Debug, Clone)]
+pub struct Chaos {
+    /// Generator state
+    state: u64,
+    /// Largest delay returned
+    max_delay: u64,
+}
+
+impl Chaos {
+    /// Create a generator
+    pub fn new(seed: u64, max_delay: u64) -> Self {
+        Self {
+            state: seed,
+            max_delay,
+        }
+    }
+
+    /// Next pseudo-random value
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// Next delay, from zero to the maximum inclusive
+    pub fn delay(&mut self) -> u64 {
+        match self.max_delay.checked_add(1) {
+            Some(range) => self.next_u64() % range,
+            None => self.next_u64(),
+        }
+    }
+
+    /// `base` moved up or down by the next delay, but never below one
+    pub fn jitter(&mut self, base: u64) -> u64 {
+        let delay = self.delay();
+        if self.next_u64() & 1 == 0 {
+            base.saturating_add(delay)
+        } else {
+            base.saturating_sub(delay).max(1)
+        }
+    }
+}
+
+/// Events held back by random delays, released in arrival order
+#[derive(Debug)]
+pub(crate) struct Deferred<T> {
+    /// Delay generator
+    chaos: Chaos,
+    /// Bundles counted so far
+    now: u64,
+    /// Held events with the bundle count at which they are released
+    pending: Vec<(u64, T)>,
+}
+
+impl<T> Deferred<T> {
+    /// Hold events back with delays from `chaos`
+    pub fn new(chaos: Chaos) -> Self {
+        Self {
+            chaos,
+            now: 0,
+            pending: Vec::new(),
+        }
+    }
+
+    /// Hold an event back
+    ///
+    /// An event is never released before one that arrived earlier, so
+    /// ordering between events (e.g. attach then detach) is kept.
+    pub fn defer(&mut self, event: T) {
+        let due = self.now + self.chaos.delay();
+        let due = self.pending.last().map_or(due, |&(last, _)| due.max(last));
+        self.pending.push((due, event));
+    }
+
+    /// Count a bundle and take the events due
+    pub fn tick(&mut self) -> Vec<T> {
+        self.now += 1;
+        let ready = self.pending.partition_point(|&(due, _)| due <= self.now);
+        self.pending
+            .drain(..ready)
+            .map(|(_, event)| event)
+            .collect()
+    }
+
+    /// Take every held event, due or not
+    pub fn take_all(&mut self) -> Vec<T> {
+        self.pending.drain(..).map(|(_, event)| event).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reproducible() {
+        let delays = |seed| {
+            let mut chaos = Chaos::new(seed, 10);
+            (0..32).map(|_| chaos.delay()).collect::<Vec<_>>()
+        };
+        assert_eq!(delays(7), delays(7));
+        assert_ne!(delays(7), delays(8));
+        assert!(delays(7).iter().all(|&d| d <= 10));
+        assert!(Chaos::new(1, 0).delay() == 0);
+    }
+
+    #[test]
+    fn test_jitter() {
+        let mut chaos = Chaos::new(5, 3);
+        let latencies: Vec<u64> = (0..64).map(|_| chaos.jitter(10)).collect();
+        assert!(latencies.iter().all(|l| (7..=13).contains(l)));
+        assert!(latencies.iter().any(|&l| l < 10) && latencies.iter().any(|&l| l > 10));
+        assert!((0..64).all(|_| chaos.jitter(1) >= 1));
+        assert_eq!(Chaos::new(5, 0).jitter(4), 4);
+    }
+
+    #[test]
+    fn test_deferred_keeps_order() {
+        let mut deferred = Deferred::new(Chaos::new(3, 50));
+        for event in 0..20 {
+            deferred.defer(event);
+        }
+        let mut released = Vec::new();
+        for _ in 0..=51 {
+            released.extend(deferred.tick());
+        }
+        assert!(deferred.take_all().is_empty());
+        assert_eq!(released, (0..20).collect::<Vec<_>>());
+    }
+}
@@ -0,0 +1,882 @@
+//! Machine configuration files
+//!
+//! This module loads a TOML description of the machine: memory map, images
+//! to load into it, cache geometry and replacement, timer setup, the W^X and
+//! uninitialized memory policies, the identity the guest sees through
+//! uname and getpid, a host directory shared with it and its devices. Errors
+//! name the offending key, e.g. `memory[1].size`, so a long configuration
+//! can be fixed without guessing.
+//!
+//! ```toml
+//! entry = 0x4000000000000000
+//! wx_policy = "log"
+//! uninit_policy = "warn"
+//!
+//! [cpu]
+//! itc_frequency = 400_000_000
+//! strict_decode = true
+//! strict_types = true
+//! unaligned = "fixup"
+//! dispersal = "itanium2"
+//!
+//! [cache]
+//! victim = 8
+//!
+//! [cache.l1]
+//! size = 16384
+//! associativity = 4
+//! line_size = 64
+//! replacement = "pseudo-lru"
+//!
+//! [guest]
+//! hostname = "itanium"
+//! release = "2.6.32"
+//! pid = 100
+//!
+//! [hostfs]
+//! root = "share"
+//! mode = "read-write"
+//!
+//! [efi]
+//! variables = "nvram.json"
+//! get_variable = 0x4000000000002000
+//! get_next_variable_name = 0x4000000000002040
+//! set_variable = 0x4000000000002080
+//! reset_system = 0x40000000000020c0
+//!
+//! [panic]
+//! functions = { panic = 0x4000000000001000 }
+//! ports = { "panic port" = 0x4000000000008000 }
+//!
+//! [[memory]]
+//! name = "firmware"
+//! base = 0x4000000000000000
+//! size = 0x10000
+//! permissions = "rx"
+//! image = "firmware.bin"
+//! kind = "firmware-code"
+//!
+//! [[memory]]
+//! name = "handoff"
+//! base = 0x4000000000010000
+//! size = 0x1000
+//! permissions = "rw"
+//! initialized = true
+//!
+//! [[memory]]
+//! name = "mailbox"
+//! base = 0x4000000000200000
+//! size = 0x1000
+//! permissions = "rw"
+//! shared = true
+//!
+//! [[memory]]
+//! name = "sealed"
+//! base = 0x4000000000300000
+//! size = 0x10000
+//! permissions = "rw"
+//! transform = { kind = "xor", key = "5a3c" }
+//!
+//! [[flash]]
+//! name = "nvram"
+//! base = 0x4000000000100000
+//! file = "nvram.bin"
+//! size = 0x40000
+//!
+//! [[uart]]
+//! name = "console"
+//! base = 0x4000000000180000
+//! baud = 115200
+//! flow_control = true
+//!
+//! [[watchdog]]
+//! base = 0x4000000000190000
+//! timeout = 400_000_000
+//! action = "reset"
+//! ```
+
+use crate::cpu::dispersal::MachineModel;
+use crate::cpu::hostfs::HostFsMode;
+use crate::cpu::syscall::GuestIdentity;
+use crate::cpu::timer::TimerMode;
+use crate::cpu::timing::Latencies;
+use crate::cpu::unaligned::AlignmentPolicy;
+use crate::crash::DEFAULT_TRACE_LEN;
+use crate::device::flash::DEFAULT_BLOCK_SIZE;
+use crate::device::watchdog::WatchdogAction;
+use crate::emulator::BUNDLE_SIZE;
+use crate::firmware::memmap::RegionKind;
+use crate::firmware::variables::VariableService;
+use crate::memory::transform::{Transform, XorPad};
+use crate::memory::uninit::UninitPolicy;
+use crate::memory::{CacheGeometry, Permissions, WxPolicy, PAGE_SIZE};
+use crate::EmulatorError;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+/// Complete machine configuration
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MachineConfig {
+    /// Initial instruction pointer
+    pub entry: Option<u64>,
+    /// Policy for writes to executable memory
+    pub wx_policy: WxPolicy,
+    /// Policy for loads of never-written memory
+    pub uninit_policy: UninitPolicy,
+    /// CPU setup
+    pub cpu: CpuConfig,
+    /// Cache geometry
+    pub cache: CacheConfig,
+    /// Identity reported to the guest
+    pub guest: GuestIdentity,
+    /// Host directory shared with the guest
+    pub hostfs: Option<HostFsConfig>,
+    /// Guest panic detection
+    pub panic: PanicConfig,
+    /// EFI runtime services
+    pub efi: EfiConfig,
+    /// Memory map
+    pub memory: Vec<RegionConfig>,
+    /// Firmware flash devices
+    pub flash: Vec<FlashConfig>,
+    /// Serial ports
+    pub uart: Vec<UartConfig>,
+    /// Watchdog timers
+    pub watchdog: Vec<WatchdogConfig>,
+    /// Cycle-approximate timing with these latencies, if present
+    pub timing: Option<Latencies>,
+}
+
+/// CPU setup
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CpuConfig {
+    /// Derive AR.ITC from host time at this frequency (Hz)
+    pub itc_frequency: Option<u64>,
+    /// ITC ticks per retired bundle when counting instructions
+    pub ticks_per_bundle: Option<u64>,
+    /// Fault on slots whose opcode is not valid for their template's unit
+    pub strict_decode: bool,
+    /// Fault on values that do not fit the width they are narrowed to
+    pub strict_types: bool,
+    /// Handling of unaligned loads and stores
+    pub unaligned: AlignmentPolicy,
+    /// Model instruction dispersal on this machine
+    pub dispersal: Option<MachineModel>,
+    /// Fail on split issues under the dispersal model
+    pub strict_dispersal: bool,
+}
+
+impl CpuConfig {
+    /// Timer mode selected by this configuration
+    pub fn timer_mode(&self) -> TimerMode {
+        match (self.itc_frequency, self.ticks_per_bundle) {
+            (Some(frequency), _) => TimerMode::HostTime { frequency },
+            (None, Some(ticks_per_bundle)) => TimerMode::Instructions { ticks_per_bundle },
+            (None, None) => TimerMode::default(),
+        }
+    }
+}
+
+/// Geometry of the three cache levels
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CacheConfig {
+    /// First-level cache
+    pub l1: CacheGeometry,
+    /// Second-level cache
+    pub l2: CacheGeometry,
+    /// Third-level cache
+    pub l3: CacheGeometry,
+    /// Lines in a victim cache between L1 and L2, none when zero
+    pub victim: usize,
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        Self {
+            l1: CacheGeometry::L1,
+            l2: CacheGeometry::L2,
+            l3: CacheGeometry::L3,
+            victim: 0,
+        }
+    }
+}
+
+/// Host directory shared with the guest (see `cpu::hostfs`)
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct HostFsConfig {
+    /// Directory the guest sees as `/`
+    pub root: PathBuf,
+    /// "read-only" or "read-write"
+    #[serde(default)]
+    pub mode: HostFsMode,
+}
+
+/// Guest panic detection
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PanicConfig {
+    /// Bundles of execution trace included in a report
+    pub trace: usize,
+    /// Functions whose entry is a panic, by name
+    pub functions: BTreeMap<String, u64>,
+    /// Ports whose writes are a panic, by name
+    pub ports: BTreeMap<String, u64>,
+}
+
+impl Default for PanicConfig {
+    fn default() -> Self {
+        Self {
+            trace: DEFAULT_TRACE_LEN,
+            functions: BTreeMap::new(),
+            ports: BTreeMap::new(),
+        }
+    }
+}
+
+/// EFI runtime services
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct EfiConfig {
+    /// File holding the non-volatile variables
+    pub variables: Option<PathBuf>,
+    /// Entry point served as `GetVariable`
+    pub get_variable: Option<u64>,
+    /// Entry point served as `GetNextVariableName`
+    pub get_next_variable_name: Option<u64>,
+    /// Entry point served as `SetVariable`
+    pub set_variable: Option<u64>,
+    /// Entry point served as `ResetSystem`
+    pub reset_system: Option<u64>,
+}
+
+impl EfiConfig {
+    /// Configured variable services and their entry points
+    pub fn variable_services(&self) -> impl Iterator<Item = (VariableService, u64)> {
+        [
+            (VariableService::GetVariable, self.get_variable),
+            (
+                VariableService::GetNextVariableName,
+                self.get_next_variable_name,
+            ),
+            (VariableService::SetVariable, self.set_variable),
+        ]
+        .into_iter()
+        .filter_map(|(service, address)| Some((service, address?)))
+    }
+}
+
+/// One region of the memory map
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RegionConfig {
+    /// Region name shown by the debugger
+    pub name: Option<String>,
+    /// Base address
+    pub base: u64,
+    /// Size in bytes
+    pub size: u64,
+    /// Access permissions: "none", "r", "rw", "rx" or "rwx"
+    pub permissions: Permissions,
+    /// Image copied to the start of the region
+    pub image: Option<PathBuf>,
+    /// Use reported in the firmware memory map, e.g. "ram" or "pal-code"
+    #[serde(default)]
+    pub kind: RegionKind,
+    /// Share the contents with host threads (see `memory::shared`)
+    #[serde(default)]
+    pub shared: bool,
+    /// Count the whole region as written under the uninitialized memory
+    /// policy, e.g. memory the firmware cleared
+    #[serde(default)]
+    pub initialized: bool,
+    /// Pass the backing store through a transform (see
+    /// `memory::transform`)
+    pub transform: Option<TransformConfig>,
+}
+
+/// Transform of a region's backing store
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TransformConfig {
+    /// "xor" or "aes-ctr", the latter with the `aes-ctr` feature
+    pub kind: TransformKind,
+    /// XOR pad or 128-bit AES key, in hexadecimal
+    pub key: String,
+    /// Upper half of the AES-CTR counter blocks
+    #[serde(default)]
+    pub nonce: u64,
+}
+
+/// Kind of backing store transform
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum TransformKind {
+    /// XOR with a repeating pad
+    Xor,
+    /// AES-128 in counter mode
+    AesCtr,
+}
+
+impl TransformConfig {
+    /// Build the transform, or say what is wrong with the key
+    pub fn build(&self) -> Result<Box<dyn Transform>, String> {
+        let key = hex_bytes(&self.key).ok_or("key is not a string of hex byte pairs")?;
+        match self.kind {
+            TransformKind::Xor => match XorPad::new(key) {
+                Some(pad) => Ok(Box::new(pad)),
+                None => Err("key must not be empty".to_string()),
+            },
+            #[cfg(feature = "aes-ctr")]
+            TransformKind::AesCtr => {
+                let key = key.try_into().map_err(|_| "key must be 16 bytes")?;
+                Ok(Box::new(crate::memory::transform::AesCtr::new(
+                    key, self.nonce,
+                )))
+            }
+            #[cfg(not(feature = "aes-ctr"))]
+            TransformKind::AesCtr => Err("aes-ctr needs the aes-ctr feature".to_string()),
+        }
+    }
+}
+
+/// Bytes spelled by pairs of hexadecimal digits
+fn hex_bytes(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..text.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(text.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+/// A file-backed firmware flash device
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FlashConfig {
+    /// Device name shown in the memory map
+    pub name: Option<String>,
+    /// Base address
+    pub base: u64,
+    /// Backing file, updated as the guest programs and erases
+    pub file: PathBuf,
+    /// Size in bytes, defaulting to the file's length
+    pub size: Option<u64>,
+    /// Erase block size in bytes
+    #[serde(default = "default_block_size")]
+    pub block_size: u64,
+    /// Refuse program and erase commands
+    #[serde(default)]
+    pub write_protect: bool,
+}
+
+/// A 16550-compatible serial port
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct UartConfig {
+    /// Device name shown in the memory map and used to find its port
+    pub name: Option<String>,
+    /// Base address
+    pub base: u64,
+    /// Pace the line at this many bits per second
+    pub baud: Option<u32>,
+    /// AR.ITC ticks per second used to time characters, defaulting to
+    /// `cpu.itc_frequency`
+    pub clock: Option<u64>,
+    /// Hold host input while the guest deasserts RTS
+    #[serde(default)]
+    pub flow_control: bool,
+}
+
+/// A watchdog timer
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct WatchdogConfig {
+    /// Device name shown in the memory map
+    pub name: Option<String>,
+    /// Base address
+    pub base: u64,
+    /// AR.ITC ticks the guest has between keepalives
+    pub timeout: u64,
+    /// What happens when the guest misses one
+    #[serde(default)]
+    pub action: WatchdogAction,
+    /// Count down from power-on instead of waiting for the guest to enable it
+    #[serde(default)]
+    pub enabled: bool,
+}
+
+fn default_block_size() -> u64 {
+    DEFAULT_BLOCK_SIZE
+}
+
+impl MachineConfig {
+    /// Parse and validate a configuration
+    pub fn parse(text: &str) -> Result<Self, EmulatorError> {
+        let config: Self =
+            toml::from_str(text).map_err(|e| EmulatorError::ConfigError(e.to_string()))?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Load a configuration file
+    ///
+    /// Relative image, variable store, flash and shared directory paths are
+    /// resolved against the directory holding the configuration file.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, EmulatorError> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path).map_err(|e| {
+            EmulatorError::ConfigError(format!("cannot read {}: {}", path.display(), e))
+        })?;
+        let mut config = Self::parse(&text).map_err(|e| match e {
+            EmulatorError::ConfigError(msg) => {
+                EmulatorError::ConfigError(format!("{}: {}", path.display(), msg))
+            }
+            e => e,
+        })?;
+
+        let dir = path.parent().unwrap_or_else(|| Path::new(""));
+        for region in &mut config.memory {
+            if let Some(image) = &mut region.image {
+                if image.is_relative() {
+                    *image = dir.join(&*image);
+                }
+            }
+        }
+        if let Some(variables) = &mut config.efi.variables {
+            if variables.is_relative() {
+                *variables = dir.join(&*variables);
+            }
+        }
+        for flash in &mut config.flash {
+            if flash.file.is_relative() {
+                flash.file = dir.join(&flash.file);
+            }
+        }
+        if let Some(hostfs) = &mut config.hostfs {
+            if hostfs.root.is_relative() {
+                hostfs.root = dir.join(&hostfs.root);
+            }
+        }
+        Ok(config)
+    }
+
+    /// Check values that parse but describe an impossible machine
+    pub fn validate(&self) -> Result<(), EmulatorError> {
+        for (key, geometry) in [
+            ("cache.l1", self.cache.l1),
+            ("cache.l2", self.cache.l2),
+            ("cache.l3", self.cache.l3),
+        ] {
+            geometry.validate().map_err(|e| invalid(key, e))?;
+        }
+
+        match (self.cpu.itc_frequency, self.cpu.ticks_per_bundle) {
+            (Some(_), Some(_)) => {
+                return Err(invalid(
+                    "cpu",
+                    "itc_frequency and ticks_per_bundle are mutually exclusive",
+                ))
+            }
+            (Some(0), _) => return Err(invalid("cpu.itc_frequency", "must be non-zero")),
+            (_, Some(0)) => return Err(invalid("cpu.ticks_per_bundle", "must be non-zero")),
+            _ => {}
+        }
+        if self.cpu.strict_dispersal && self.cpu.dispersal.is_none() {
+            return Err(invalid("cpu.strict_dispersal", "needs cpu.dispersal"));
+        }
+        if self.timing.is_some() && self.cpu.itc_frequency.is_some() {
+            return Err(invalid(
+                "timing",
+                "counts cycles on AR.ITC, which cpu.itc_frequency ties to host time",
+            ));
+        }
+
+        for (i, region) in self.memory.iter().enumerate() {
+            if !region.base.is_multiple_of(PAGE_SIZE) {
+                return Err(invalid(
+                    &format!("memory[{}].base", i),
+                    format!("{:#x} is not page aligned", region.base),
+                ));
+            }
+            if region.size == 0 || !region.size.is_multiple_of(PAGE_SIZE) {
+                return Err(invalid(
+                    &format!("memory[{}].size", i),
+                    format!(
+                        "{:#x} is not a non-zero multiple of the page size",
+                        region.size
+                    ),
+                ));
+            }
+            if region.base.checked_add(region.size).is_none() {
+                return Err(invalid(
+                    &format!("memory[{}].size", i),
+                    "region extends past the end of the address space",
+                ));
+            }
+            if let Some(j) = self.memory[..i].iter().position(|other| {
+                region.base < other.base + other.size && other.base < region.base + region.size
+            }) {
+                return Err(invalid(
+                    &format!("memory[{}]", i),
+                    format!("overlaps memory[{}]", j),
+                ));
+            }
+            if let Some(transform) = &region.transform {
+                let key = format!("memory[{}].transform", i);
+                if region.shared {
+                    return Err(invalid(&key, "shared regions have no backing store"));
+                }
+                transform.build().map_err(|e| invalid(&key, e))?;
+            }
+        }
+
+        for (i, flash) in self.flash.iter().enumerate() {
+            if !flash.base.is_multiple_of(PAGE_SIZE) {
+                return Err(invalid(
+                    &format!("flash[{}].base", i),
+                    format!("{:#x} is not page aligned", flash.base),
+                ));
+            }
+            if !flash.block_size.is_power_of_two() || flash.block_size < 256 {
+                return Err(invalid(
+                    &format!("flash[{}].block_size", i),
+                    format!(
+                        "{:#x} is not a power of two of at least 256 bytes",
+                        flash.block_size
+                    ),
+                ));
+            }
+            if let Some(size) = flash.size {
+                if size == 0 || !size.is_multiple_of(flash.block_size) {
+                    return Err(invalid(
+                        &format!("flash[{}].size", i),
+                        format!("{:#x} is not a non-zero multiple of the block size", size),
+                    ));
+                }
+                if flash.base.checked_add(size).is_none() {
+                    return Err(invalid(
+                        &format!("flash[{}].size", i),
+                        "flash extends past the end of the address space",
+                    ));
+                }
+                if let Some(j) = self.memory.iter().position(|region| {
+                    flash.base < region.base + region.size && region.base < flash.base + size
+                }) {
+                    return Err(invalid(
+                        &format!("flash[{}]", i),
+                        format!("overlaps memory[{}]", j),
+                    ));
+                }
+            }
+        }
+
+        for (i, uart) in self.uart.iter().enumerate() {
+            if !uart.base.is_multiple_of(PAGE_SIZE) {
+                return Err(invalid(
+                    &format!("uart[{}].base", i),
+                    format!("{:#x} is not page aligned", uart.base),
+                ));
+            }
+            match uart.baud {
+                Some(0) => return Err(invalid(&format!("uart[{}].baud", i), "must be non-zero")),
+                Some(_) if uart.clock.or(self.cpu.itc_frequency).is_none() => {
+                    return Err(invalid(
+                        &format!("uart[{}].baud", i),
+                        "needs uart.clock or cpu.itc_frequency to time characters",
+                    ))
+                }
+                _ => {}
+            }
+            if uart.clock == Some(0) {
+                return Err(invalid(&format!("uart[{}].clock", i), "must be non-zero"));
+            }
+            if let Some(j) = self.memory.iter().position(|region| {
+                region.base <= uart.base && uart.base - region.base < region.size
+            }) {
+                return Err(invalid(
+                    &format!("uart[{}]", i),
+                    format!("overlaps memory[{}]", j),
+                ));
+            }
+        }
+
+        for (i, watchdog) in self.watchdog.iter().enumerate() {
+            if !watchdog.base.is_multiple_of(PAGE_SIZE) {
+                return Err(invalid(
+                    &format!("watchdog[{}].base", i),
+                    format!("{:#x} is not page aligned", watchdog.base),
+                ));
+            }
+            if watchdog.timeout == 0 {
+                return Err(invalid(
+                    &format!("watchdog[{}].timeout", i),
+                    "must be non-zero",
+                ));
+            }
+            if let Some(j) = self.memory.iter().position(|region| {
+                region.base <= watchdog.base && watchdog.base - region.base < region.size
+            }) {
+                return Err(invalid(
+                    &format!("watchdog[{}]", i),
+                    format!("overlaps memory[{}]", j),
+                ));
+            }
+        }
+
+        self.guest
+            .validate()
+            .map_err(|e| EmulatorError::ConfigError(format!("guest.{}", e)))?;
+
+        for (name, &address) in &self.panic.functions {
+            if !address.is_multiple_of(BUNDLE_SIZE) {
+                return Err(invalid(
+                    &format!("panic.functions.{}", name),
+                    format!("{:#x} is not bundle aligned", address),
+                ));
+            }
+        }
+
+        for (service, address) in self.efi.variable_services() {
+            if !address.is_multiple_of(BUNDLE_SIZE) {
+                return Err(invalid(
+                    &format!("efi.{}", service_key(service)),
+                    format!("{:#x} is not bundle aligned", address),
+                ));
+            }
+        }
+
+        if let Some(address) = self.efi.reset_system {
+            if !address.is_multiple_of(BUNDLE_SIZE) {
+                return Err(invalid(
+                    "efi.reset_system",
+                    format!("{:#x} is not bundle aligned", address),
+                ));
+            }
+        }
+
+        if let Some(entry) = self.entry {
+            if !entry.is_multiple_of(BUNDLE_SIZE) {
+                return Err(invalid(
+                    "entry",
+                    format!("{:#x} is not bundle aligned", entry),
+                ));
+            }
+            let executable = self.memory.iter().any(|region| {
+                region.permissions.can_execute()
+                    && entry >= region.base
+                    && entry - region.base < region.size
+            });
+            if !self.memory.is_empty() && !executable {
+                return Err(invalid(
+                    "entry",
+                    format!("{:#x} is not in an executable region", entry),
+                ));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Key naming a variable service's entry point
+fn service_key(service: VariableService) -> &'static str {
+    match service {
+        VariableService::GetVariable => "get_variable",
+        VariableService::GetNextVariableName => "get_next_variable_name",
+        VariableService::SetVariable => "set_variable",
+    }
+}
+
+/// Build an error naming the offending key
+fn invalid(key: &str, message: impl std::fmt::Display) -> EmulatorError {
+    EmulatorError::ConfigError(format!("{}: {}", key, message))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory::replacement::Replacement;
+
+    fn error(text: &str) -> String {
+        match MachineConfig::parse(text) {
+            Err(EmulatorError::ConfigError(msg)) => msg,
+            other => panic!("expected a configuration error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_parse() {
+        let config = MachineConfig::parse(
+            r#"
+            entry = 0x10000
+            wx_policy = "fault"
+            uninit_policy = "poison"
+
+            [cpu]
+            itc_frequency = 1_000_000
+            dispersal = "itanium2"
+
+            [guest]
+            hostname = "itanium"
+            pid = 100
+
+            [hostfs]
+            root = "share"
+            mode = "read-write"
+
+            [cache]
+            victim = 4
+
+            [cache.l2]
+            size = 131072
+            associativity = 4
+            line_size = 64
+            replacement = "fifo"
+
+            [[memory]]
+            name = "text"
+            base = 0x10000
+            size = 0x2000
+            permissions = "rx"
+
+            [[memory]]
+            base = 0x20000
+            size = 0x1000
+            permissions = "rw"
+            initialized = true
+
+            [efi]
+            variables = "nvram.json"
+            set_variable = 0x10040
+            reset_system = 0x10080
+
+            [[flash]]
+            base = 0x100000
+            file = "nvram.bin"
+            write_protect = true
+
+            [[uart]]
+            base = 0x110000
+            baud = 9600
+
+            [[watchdog]]
+            base = 0x120000
+            timeout = 1000
+            action = "interrupt"
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(config.entry, Some(0x10000));
+        assert_eq!(config.wx_policy, WxPolicy::Fault);
+        assert_eq!(config.uninit_policy, UninitPolicy::Poison);
+        assert_eq!(
+            config.cpu.timer_mode(),
+            TimerMode::HostTime {
+                frequency: 1_000_000
+            }
+        );
+        assert_eq!(config.cpu.dispersal, Some(MachineModel::Itanium2));
+        assert_eq!(config.guest.hostname, "itanium");
+        assert_eq!(config.guest.pid, 100);
+        assert_eq!(config.guest.machine, "ia64");
+        let hostfs = config.hostfs.as_ref().unwrap();
+        assert_eq!(hostfs.root, Path::new("share"));
+        assert_eq!(hostfs.mode, HostFsMode::ReadWrite);
+        assert_eq!(config.cache.l1, CacheGeometry::L1);
+        assert_eq!(config.cache.l2.size, 131072);
+        assert_eq!(config.cache.l2.replacement, Replacement::Fifo);
+        assert_eq!(config.cache.victim, 4);
+        assert_eq!(config.memory.len(), 2);
+        assert_eq!(config.memory[0].name.as_deref(), Some("text"));
+        assert_eq!(config.memory[1].permissions, Permissions::ReadWrite);
+        assert!(config.memory[1].initialized);
+        assert_eq!(
+            config.efi.variable_services().collect::<Vec<_>>(),
+            [(VariableService::SetVariable, 0x10040)]
+        );
+        assert_eq!(config.efi.reset_system, Some(0x10080));
+        assert_eq!(config.flash[0].block_size, DEFAULT_BLOCK_SIZE);
+        assert!(config.flash[0].write_protect);
+        assert_eq!(config.uart[0].baud, Some(9600));
+        assert!(!config.uart[0].flow_control);
+        assert_eq!(config.watchdog[0].action, WatchdogAction::Interrupt);
+        assert!(!config.watchdog[0].enabled);
+    }
+
+    #[test]
+    fn test_errors_name_key() {
+        assert!(error("[cpu]\nitc_freq = 1").contains("itc_freq"));
+        assert!(error("[[memory]]\nbase = 0\nsize = 4096\npermissions = \"wx\"").contains("wx"));
+        assert!(
+            error("[cache.l1]\nsize = 1000\nassociativity = 8\nline_size = 64")
+                .starts_with("cache.l1:")
+        );
+        assert!(
+            error("[[memory]]\nbase = 0\nsize = 100\npermissions = \"r\"")
+                .starts_with("memory[0].size:")
+        );
+        assert!(error(
+            "[[memory]]\nbase = 0\nsize = 8192\npermissions = \"r\"\n\
+             [[memory]]\nbase = 4096\nsize = 4096\npermissions = \"r\""
+        )
+        .starts_with("memory[1]: overlaps memory[0]"));
+        assert!(error("[cpu]\ndispersal = \"merced\"").contains("merced"));
+        assert!(error("[cpu]\nstrict_dispersal = true").starts_with("cpu.strict_dispersal:"));
+        assert!(error("[timing]\nl4 = 40").contains("l4"));
+        let timing = MachineConfig::parse("[timing]\nmemory = 200")
+            .unwrap()
+            .timing;
+        assert_eq!(timing.unwrap().memory, 200);
+        assert!(error("[cpu]\nitc_frequency = 1000\n[timing]").starts_with("timing:"));
+        assert!(error("[guest]\ngid = 1").contains("gid"));
+        assert!(
+            error(&format!("[guest]\nhostname = \"{}\"", "h".repeat(65)))
+                .starts_with("guest.hostname:")
+        );
+        assert!(
+            error("[panic]\nfunctions = { abort = 0x1004 }").starts_with("panic.functions.abort:")
+        );
+        assert!(
+            error("entry = 0x10\n[[memory]]\nbase = 0\nsize = 4096\npermissions = \"rw\"")
+                .starts_with("entry:")
+        );
+        assert!(error("[efi]\nget_variable = 0x1008").starts_with("efi.get_variable:"));
+        assert!(error("[efi]\nreset_system = 0x1008").starts_with("efi.reset_system:"));
+        assert!(
+            error("[[flash]]\nbase = 0\nfile = \"f\"\nblock_size = 1000")
+                .starts_with("flash[0].block_size:")
+        );
+        assert!(error(
+            "[[memory]]\nbase = 0\nsize = 8192\npermissions = \"r\"\n\
+             [[flash]]\nbase = 4096\nfile = \"f\"\nsize = 0x10000"
+        )
+        .starts_with("flash[0]: overlaps memory[0]"));
+        assert!(error("[[uart]]\nbase = 0\nbaud = 9600").starts_with("uart[0].baud:"));
+        assert!(error("[[uart]]\nbase = 0\nbaud = 9600\nclock = 0").starts_with("uart[0].clock:"));
+        assert!(error("[[watchdog]]\nbase = 0\ntimeout = 0").starts_with("watchdog[0].timeout:"));
+        assert!(error("[[watchdog]]\nbase = 0\ntimeout = 1\naction = \"nmi\"").contains("nmi"));
+        let region = "[[memory]]\nbase = 0\nsize = 4096\npermissions = \"rw\"\n";
+        assert!(error(&format!(
+            "{}transform = {{ kind = \"xor\", key = \"5\" }}",
+            region
+        ))
+        .starts_with("memory[0].transform:"));
+        assert!(error(&format!(
+            "{}shared = true\ntransform = {{ kind = \"xor\", key = \"55\" }}",
+            region
+        ))
+        .starts_with("memory[0].transform:"));
+        assert!(error(&format!(
+            "{}transform = {{ kind = \"rot13\", key = \"55\" }}",
+            region
+        ))
+        .contains("rot13"));
+    }
+}
@@ -0,0 +1,429 @@
+//! Guest console capture for tests
+//!
+//! A [`ConsoleCapture`] collects what the guest writes to its console: the
+//! characters a [`Uart`](crate::device::uart::Uart) transmits and the bytes
+//! it writes to standard output and standard error through system calls.
+//! [`ConsoleCapture::expect`] runs the machine until the output matches a
+//! substring or regular expression, so an end-to-end test reads as a
+//! conversation with the guest:
+//!
+//! ```no_run
+//! use rust_ia64::console::{ConsoleCapture, Pattern};
+//! use rust_ia64::device::uart::Uart;
+//! use rust_ia64::emulator::Emulator;
+//!
+//! let image = std::fs::read("kernel.bin")?;
+//! let mut emu = Emulator::new();
+//! emu.load_flat_image(0x10000, &image, 0x10000)?;
+//! let uart = Uart::new("uart");
+//! let mut console = ConsoleCapture::new();
+//! console.attach_uart(uart.port());
+//! emu.attach(0x8000_0000, Box::new(uart))?;
+//!
+//! console.expect(&mut emu, "login: ", 1_000_000)?;
+//! let found = console.expect(&mut emu, Pattern::regex(r"uptime (\d+)")?, 1_000_000)?;
+//! assert_eq!(&found.groups[1], "0");
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+//!
+//! Each match consumes the output up to its end, so the next expectation
+//! only sees what the guest wrote after it. Timeouts count retired
+//! instructions rather than host time, so a test fails the same way on a
+//! slow machine as on a fast one.
+
+use crate::cpu::syscall::{SyscallContext, SyscallNumber};
+use crate::cpu::Cpu;
+use crate::device::uart::UartPort;
+use crate::emulator::Emulator;
+use crate::memory::view::Endian;
+use crate::EmulatorError;
+use regex::bytes::Regex;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+/// Most bytes of one system call's output captured
+const MAX_CAPTURE: u64 = 1 << 20;
+
+/// Output shown after the pattern in a failed expectation
+const EXCERPT: usize = 256;
+
+/// What an expectation waits for
+#[derive(Debug, Clone)]
+pub enum Pattern {
+    /// Text appearing anywhere in the output
+    Text(String),
+    /// Regular expression matching anywhere in the output
+    Regex(Regex),
+}
+
+impl Pattern {
+    /// Compile a regular expression pattern
+    pub fn regex(pattern: &str) -> Result<Self, EmulatorError> {
+        Regex::new(pattern)
+            .map(Pattern::Regex)
+            .map_err(|e| EmulatorError::ConfigError(format!("pattern {:?}: {}", pattern, e)))
+    }
+
+    /// Start and end of the first match in `output`, with its capture
+    /// groups
+    fn find(&self, output: &[u8]) -> Option<(usize, usize, Vec<String>)> {
+        match self {
+            Pattern::Text(text) => {
+                let needle = text.as_bytes();
+                let start = if needle.is_empty() {
+                    Some(0)
+                } else {
+                    output.windows(needle.len()).position(|w| w == needle)
+                }?;
+                Some((start, start + needle.len(), vec![text.clone()]))
+            }
+            Pattern::Regex(regex) => {
+                let captures = regex.captures(output)?;
+                let whole = captures.get(0)?;
+                let groups = captures
+                    .iter()
+                    .map(|group| {
+                        group.map_or_else(String::new, |group| {
+                            String::from_utf8_lossy(group.as_bytes()).into_owned()
+                        })
+                    })
+                    .collect();
+                Some((whole.start(), whole.end(), groups))
+            }
+        }
+    }
+}
+
+impl fmt::Display for Pattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Pattern::Text(text) => write!(f, "{:?}", text),
+            Pattern::Regex(regex) => write!(f, "/{}/", regex.as_str()),
+        }
+    }
+}
+
+impl From<&str> for Pattern {
+    fn from(text: &str) -> Self {
+        Pattern::Text(text.to_string())
+    }
+}
+
+impl From<String> for Pattern {
+    fn from(text: String) -> Self {
+        Pattern::Text(text)
+    }
+}
+
+impl From<Regex> for Pattern {
+    fn from(regex: Regex) -> Self {
+        Pattern::Regex(regex)
+    }
+}
+
+/// Output matched by an expectation
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConsoleMatch {
+    /// Output between the previous match and this one
+    pub before: String,
+    /// The matching text; for a regular expression, the whole match
+    /// followed by its capture groups, empty where a group did not take
+    /// part
+    pub groups: Vec<String>,
+    /// Instructions retired when the match was seen
+    pub retired: u64,
+}
+
+impl ConsoleMatch {
+    /// The matching text
+    pub fn text(&self) -> &str {
+        &self.groups[0]
+    }
+}
+
+/// Sink for the guest's console output
+#[derive(Debug, Default)]
+pub struct ConsoleCapture {
+    /// Everything captured so far, shared with the system call handlers
+    output: Arc<Mutex<Vec<u8>>>,
+    /// UARTs whose transmitted characters are captured
+    ports: Vec<UartPort>,
+    /// Start of the output not yet consumed by a match
+    cursor: usize,
+}
+
+impl ConsoleCapture {
+    /// Create a capture with no sources attached
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Capture the characters the UART of `port` transmits
+    ///
+    /// The capture takes the port's output, so nothing else should read
+    /// it.
+    pub fn attach_uart(&mut self, port: UartPort) -> &mut Self {
+        self.ports.push(port);
+        self
+    }
+
+    /// Capture what the guest writes to descriptors 1 and 2 with `write`
+    /// and `writev`
+    ///
+    /// The handlers already registered, including those of a host
+    /// directory, still carry out the calls, so attach after
+    /// [`set_hostfs`](crate::cpu::syscall::SyscallManager::set_hostfs).
+    pub fn attach_syscalls(&mut self, cpu: &mut Cpu) -> &mut Self {
+        for number in [SyscallNumber::Write, SyscallNumber::Writev] {
+            let Some(handler) = cpu.syscall_mgr.take_handler(number) else {
+                continue;
+            };
+            let output = Arc::clone(&self.output);
+            cpu.syscall_mgr
+                .register_handler(number, move |cpu, context| {
+                    if matches!(context.params[0], 1 | 2) {
+                        let data = written(cpu, context, number);
+                        output.lock().unwrap().extend(data);
+                    }
+                    handler(cpu, context)
+                });
+        }
+        self
+    }
+
+    /// Collect the output the UARTs transmitted since the last call
+    fn poll(&mut self) {
+        let mut output = self.output.lock().unwrap();
+        for port in &self.ports {
+            output.extend(port.take_output());
+        }
+    }
+
+    /// Everything the guest wrote so far, matched or not
+    pub fn output(&mut self) -> String {
+        self.poll();
+        String::from_utf8_lossy(&self.output.lock().unwrap()).into_owned()
+    }
+
+    /// Output not yet consumed by a match
+    pub fn pending(&mut self) -> String {
+        self.poll();
+        String::from_utf8_lossy(&self.output.lock().unwrap()[self.cursor..]).into_owned()
+    }
+
+    /// Look for `pattern` in the output after the last match, consuming
+    /// the output up to the end of the match
+    fn try_match(&mut self, pattern: &Pattern, retired: u64) -> Option<ConsoleMatch> {
+        self.poll();
+        let output = self.output.lock().unwrap();
+        let pending = &output[self.cursor..];
+        let (start, end, groups) = pattern.find(pending)?;
+        let found = ConsoleMatch {
+            before: String::from_utf8_lossy(&pending[..start]).into_owned(),
+            groups,
+            retired,
+        };
+        self.cursor += end;
+        Some(found)
+    }
+
+    /// Run `emu` until the output after the last match contains `pattern`
+    ///
+    /// Output already captured is searched before the machine runs, so
+    /// with a timeout of 0 this only checks. Fails if `timeout` more
+    /// instructions retire, or the machine stops, before the pattern
+    /// appears; the error shows the output the pattern was looked for in.
+    pub fn expect(
+        &mut self,
+        emu: &mut Emulator,
+        pattern: impl Into<Pattern>,
+        timeout: u64,
+    ) -> Result<ConsoleMatch, EmulatorError> {
+        let pattern = pattern.into();
+        let deadline = emu.retired().saturating_add(timeout);
+        loop {
+            if let Some(found) = self.try_match(&pattern, emu.retired()) {
+                return Ok(found);
+            }
+            if emu.retired() >= deadline {
+                return Err(self.unmatched(&pattern, &format!("within {} instructions", timeout)));
+            }
+            if let Some(reason) = emu.step()? {
+                if let Some(found) = self.try_match(&pattern, emu.retired()) {
+                    return Ok(found);
+                }
+                return Err(self.unmatched(&pattern, &format!("before {:?}", reason)));
+            }
+        }
+    }
+
+    /// Run `emu` until the output contains each of `patterns` in turn,
+    /// with `timeout` instructions for each
+    pub fn expect_all<P: Into<Pattern>>(
+        &mut self,
+        emu: &mut Emulator,
+        patterns: impl IntoIterator<Item = P>,
+        timeout: u64,
+    ) -> Result<Vec<ConsoleMatch>, EmulatorError> {
+        patterns
+            .into_iter()
+            .map(|pattern| self.expect(emu, pattern, timeout))
+            .collect()
+    }
+
+    /// Failed expectation of `pattern`, with the end of the pending output
+    fn unmatched(&mut self, pattern: &Pattern, when: &str) -> EmulatorError {
+        let pending = self.pending();
+        let excerpt = match pending.char_indices().rev().nth(EXCERPT - 1) {
+            Some((start, _)) => format!("...{}", &pending[start..]),
+            None => pending,
+        };
+        EmulatorError::ConsoleMismatch(format!(
+            "{} not seen {}; output was {:?}",
+            pattern, when, excerpt
+        ))
+    }
+}
+
+/// Bytes a `write` or `writev` call is about to write
+///
+/// Unreadable buffers contribute nothing; the call's own handler reports
+/// the fault.
+pub(crate) fn written(cpu: &Cpu, context: &SyscallContext, number: SyscallNumber) -> Vec<u8> {
+    let [_, buf, count, ..] = context.params;
+    let buffers = match number {
+        SyscallNumber::Writev => cpu
+            .memory
+            .view(buf, count.min(1024) * 16, Endian::Little)
+            .to_vec::<u64>()
+            .unwrap_or_default()
+            .chunks_exact(2)
+            .map(|iovec| (iovec[0], iovec[1]))
+            .collect(),
+        _ => vec![(buf, count)],
+    };
+    let mut data = Vec::new();
+    for (base, len) in buffers {
+        let mut chunk = vec![0; len.min(MAX_CAPTURE) as usize];
+        if cpu.memory.peek_bytes(base, &mut chunk).is_err() {
+            break;
+        }
+        data.extend(chunk);
+    }
+    data
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm::BundleBuilder;
+    use crate::device::uart::{Uart, FCR_ENABLE, REG_IIR};
+    use crate::emulator::{SYSCALL_BREAK_IMM, SYSCALL_NUMBER_REG};
+
+    const BASE: u64 = 0x10000;
+    const MMIO: u64 = 0x80000;
+
+    /// Program sending `text` to the UART at `MMIO`, then making the system
+    /// call in r15 and breaking
+    fn program(text: &str) -> Vec<u8> {
+        let mut bundles = vec![BundleBuilder::new()
+            .insn("nop.m 0x0")
+            .insn(&format!("movl r2 = {:#x} ;;", MMIO))];
+        for byte in text.bytes() {
+            bundles.push(
+                BundleBuilder::new()
+                    .insn("nop.m 0x0")
+                    .insn(&format!("mov r3 = {}", byte))
+                    .insn("nop.i 0x0 ;;"),
+            );
+            bundles.push(
+                BundleBuilder::new()
+                    .insn("st1 [r2] = r3")
+                    .insn("nop.i 0x0")
+                    .insn("nop.i 0x0 ;;"),
+            );
+        }
+        bundles.push(
+            BundleBuilder::new()
+                .insn(&format!("break.m {:#x}", SYSCALL_BREAK_IMM))
+                .insn("nop.i 0x0")
+                .insn("nop.i 0x0 ;;"),
+        );
+        bundles.push(
+            BundleBuilder::new()
+                .insn("break.m 0x77")
+                .insn("nop.i 0x0")
+                .insn("nop.i 0x0 ;;"),
+        );
+        bundles
+            .into_iter()
+            .enumerate()
+            .flat_map(|(i, bundle)| bundle.at(BASE + 16 * i as u64).build().unwrap())
+            .collect()
+    }
+
+    /// Machine running `program(text)` with a console capture attached
+    fn setup(text: &str) -> (Emulator, ConsoleCapture) {
+        let mut emu = Emulator::new();
+        emu.load_flat_image(BASE, &program(text), BASE).unwrap();
+        emu.cpu.gr[SYSCALL_NUMBER_REG] = SyscallNumber::GetPid as u64;
+        let uart = Uart::new("uart");
+        let mut console = ConsoleCapture::new();
+        console.attach_uart(uart.port());
+        emu.attach(MMIO, Box::new(uart)).unwrap();
+        emu.memory.write_u8(MMIO + REG_IIR, FCR_ENABLE).unwrap();
+        console.attach_syscalls(&mut emu.cpu);
+        (emu, console)
+    }
+
+    #[test]
+    fn test_expect_uart() {
+        let (mut emu, mut console) = setup("boot 42\nlogin: ");
+
+        let found = console.expect(&mut emu, "boot", 1000).unwrap();
+        assert_eq!((found.before.as_str(), found.text()), ("", "boot"));
+        let found = console
+            .expect(&mut emu, Pattern::regex(r"(\d+)\n").unwrap(), 1000)
+            .unwrap();
+        assert_eq!(found.before, " ");
+        assert_eq!(found.groups, ["42\n", "42"]);
+
+        // Matched output is consumed
+        assert!(console.expect(&mut emu, "boot", 0).is_err());
+        let found = console.expect(&mut emu, "login: ", 1000).unwrap();
+        assert!(found.retired > 0);
+        assert_eq!(console.output(), "boot 42\nlogin: ");
+        assert_eq!(console.pending(), "");
+    }
+
+    #[test]
+    fn test_expect_timeout() {
+        let (mut emu, mut console) = setup("abcdef");
+
+        // Each character takes two bundles of three slots
+        let err = console.expect(&mut emu, "f", 12).unwrap_err();
+        assert!(matches!(&err, EmulatorError::ConsoleMismatch(message)
+            if message == "\"f\" not seen within 12 instructions; output was \"ab\""));
+        assert_eq!(console.expect(&mut emu, "f", 1000).unwrap().before, "abcde");
+
+        // A machine that stops fails the expectation at once
+        let err = console.expect(&mut emu, "never", 1000).unwrap_err();
+        assert!(matches!(&err, EmulatorError::ConsoleMismatch(message)
+            if message.contains("before Break(119)")));
+    }
+
+    #[test]
+    fn test_expect_syscalls() {
+        let (mut emu, mut console) = setup("");
+        emu.memory.write_bytes(BASE + 0x800, b"hello").unwrap();
+        emu.cpu.gr[SYSCALL_NUMBER_REG] = SyscallNumber::Write as u64;
+        emu.cpu.gr[32] = 1;
+        emu.cpu.gr[33] = BASE + 0x800;
+        emu.cpu.gr[34] = 5;
+
+        let found = console.expect_all(&mut emu, ["hel", "lo"], 100).unwrap();
+        assert_eq!(found[1].before, "");
+        // The original handler still completes the call
+        assert_eq!(emu.cpu.gr[8], 5);
+    }
+}
@@ -0,0 +1,383 @@
+//! ELF core dump writer
+//!
+//! This module produces ELF64 core files of the guest in the layout used by
+//! Linux/ia64, so they can be opened with a cross gdb. The core contains one
+//! PT_LOAD segment per mapped memory region and a PT_NOTE segment with the
+//! NT_PRSTATUS (general registers and faulting signal) and NT_PRFPREG notes.
+
+use crate::cpu::registers::ar::AR;
+use crate::emulator::{Emulator, StopReason};
+use crate::memory::Permissions;
+use crate::EmulatorError;
+
+/// ELF machine number for IA-64
+pub const EM_IA_64: u16 = 50;
+
+/// Signal reported for an illegal or undecodable instruction
+pub const SIGILL: i32 = 4;
+/// Signal reported for a break instruction
+pub const SIGTRAP: i32 = 5;
+/// Signal reported for a detected guest panic
+pub const SIGABRT: i32 = 6;
+/// Signal reported for a misaligned access
+pub const SIGBUS: i32 = 7;
+/// Signal reported for a floating-point fault
+pub const SIGFPE: i32 = 8;
+/// Signal reported for a memory access fault
+pub const SIGSEGV: i32 = 11;
+/// Signal reported for an expired watchdog
+pub const SIGALRM: i32 = 14;
+/// Signal reported for a detected guest livelock
+pub const SIGXCPU: i32 = 24;
+
+/// ELF core file type
+const ET_CORE: u16 = 4;
+/// Loadable segment
+const PT_LOAD: u32 = 1;
+/// Note segment
+const PT_NOTE: u32 = 4;
+/// Segment permission bits
+const PF_X: u32 = 1;
+const PF_W: u32 = 2;
+const PF_R: u32 = 4;
+/// Note types
+const NT_PRSTATUS: u32 = 1;
+const NT_PRFPREG: u32 = 2;
+
+/// ELF header size
+const EHDR_SIZE: usize = 64;
+/// Program header size
+const PHDR_SIZE: usize = 56;
+
+/// Number of entries in the ia64 `elf_gregset_t`
+const ELF_NGREG: usize = 128;
+/// Number of entries in the ia64 `elf_fpregset_t`
+const ELF_NFPREG: usize = 128;
+/// Size of `struct elf_prstatus` on ia64
+const PRSTATUS_SIZE: usize = 1144;
+/// Offset of `pr_reg` within `struct elf_prstatus`
+const PRSTATUS_REG_OFFSET: usize = 112;
+
+/// Indices into the ia64 `elf_gregset_t`
+const ELF_NAT_OFFSET: usize = 32;
+const ELF_PR_OFFSET: usize = 33;
+const ELF_BR_OFFSET: usize = 34;
+const ELF_CR_IIP_OFFSET: usize = 42;
+const ELF_CFM_OFFSET: usize = 43;
+const ELF_CR_IPSR_OFFSET: usize = 44;
+const ELF_AR_RSC_OFFSET: usize = 45;
+const ELF_AR_BSP_OFFSET: usize = 46;
+const ELF_AR_BSPSTORE_OFFSET: usize = 47;
+const ELF_AR_RNAT_OFFSET: usize = 48;
+const ELF_AR_CCV_OFFSET: usize = 49;
+const ELF_AR_UNAT_OFFSET: usize = 50;
+const ELF_AR_FPSR_OFFSET: usize = 51;
+const ELF_AR_PFS_OFFSET: usize = 52;
+
+/// Pick the signal a fatal emulator error corresponds to
+pub fn signal_for_error(error: &EmulatorError) -> i32 {
+    match error {
+        EmulatorError::MemoryError(_)
+        | EmulatorError::PrivilegeViolation
+        | EmulatorError::TlbFault { .. } => SIGSEGV,
+        EmulatorError::InvalidAlignment | EmulatorError::UnalignedReference { .. } => SIGBUS,
+        EmulatorError::DebugFault { .. } => SIGTRAP,
+        EmulatorError::FPFault { .. } => SIGFPE,
+        _ => SIGILL,
+    }
+}
+
+/// Pick the signal an unexpected stop corresponds to
+///
+/// Returns `None` for a normal exit, which does not warrant a core.
+pub fn signal_for_stop(reason: StopReason) -> Option<i32> {
+    match reason {
+        StopReason::Exited(_) => None,
+        StopReason::Break(_) => Some(SIGTRAP),
+        StopReason::Panic => Some(SIGABRT),
+        StopReason::Livelock => Some(SIGXCPU),
+        StopReason::DataMatch { .. } => Some(SIGTRAP),
+        StopReason::WatchdogExpired => Some(SIGALRM),
+    }
+}
+
+/// Build an ELF core image of the guest
+///
+/// `signal` is recorded as the signal that terminated the guest.
+pub fn build_core(emulator: &Emulator, signal: i32) -> Result<Vec<u8>, EmulatorError> {
+    let regions: Vec<_> = emulator.memory.regions().collect();
+
+    // Notes first, so the segment offsets can be computed
+    let mut notes = Vec::new();
+    push_note(&mut notes, NT_PRSTATUS, &prstatus(emulator, signal)?);
+    push_note(&mut notes, NT_PRFPREG, &fpregset(emulator));
+
+    let phnum = regions.len() + 1;
+    let notes_offset = EHDR_SIZE + phnum * PHDR_SIZE;
+    let mut data_offset = align(notes_offset + notes.len(), 8);
+
+    let mut out = Vec::new();
+    push_ehdr(&mut out, phnum as u16);
+
+    // Program headers
+    push_phdr(
+        &mut out,
+        PT_NOTE,
+        0,
+        notes_offset as u64,
+        0,
+        notes.len() as u64,
+        notes.len() as u64,
+        4,
+    );
+    for (base, permissions, data) in &regions {
+        push_phdr(
+            &mut out,
+            PT_LOAD,
+            segment_flags(*permissions),
+            data_offset as u64,
+            *base,
+            data.len() as u64,
+            data.len() as u64,
+            8,
+        );
+        data_offset = align(data_offset + data.len(), 8);
+    }
+
+    // Segment contents
+    out.extend_from_slice(&notes);
+    for (_, _, data) in &regions {
+        out.resize(align(out.len(), 8), 0);
+        out.extend_from_slice(data);
+    }
+
+    Ok(out)
+}
+
+/// Round up to a multiple of `alignment`
+fn align(value: usize, alignment: usize) -> usize {
+    value.next_multiple_of(alignment)
+}
+
+/// Map region permissions to segment flags
+fn segment_flags(permissions: Permissions) -> u32 {
+    let mut flags = 0;
+    if permissions.can_read() {
+        flags |= PF_R;
+    }
+    if permissions.can_write() {
+        flags |= PF_W;
+    }
+    if permissions.can_execute() {
+        flags |= PF_X;
+    }
+    flags
+}
+
+/// Append the ELF header
+fn push_ehdr(out: &mut Vec<u8>, phnum: u16) {
+    // e_ident: ELFCLASS64, ELFDATA2LSB, EV_CURRENT, ELFOSABI_NONE
+    out.extend_from_slice(&[0x7F, b'E', b'L', b'F', 2, 1, 1, 0]);
+    out.extend_from_slice(&[0; 8]);
+    out.extend_from_slice(&ET_CORE.to_le_bytes());
+    out.extend_from_slice(&EM_IA_64.to_le_bytes());
+    out.extend_from_slice(&1u32.to_le_bytes()); // e_version
+    out.extend_from_slice(&0u64.to_le_bytes()); // e_entry
+    out.extend_from_slice(&(EHDR_SIZE as u64).to_le_bytes()); // e_phoff
+    out.extend_from_slice(&0u64.to_le_bytes()); // e_shoff
+    out.extend_from_slice(&0u32.to_le_bytes()); // e_flags
+    out.extend_from_slice(&(EHDR_SIZE as u16).to_le_bytes());
+    out.extend_from_slice(&(PHDR_SIZE as u16).to_le_bytes());
+    out.extend_from_slice(&phnum.to_le_bytes());
+    out.extend_from_slice(&0u16.to_le_bytes()); // e_shentsize
+    out.extend_from_slice(&0u16.to_le_bytes()); // e_shnum
+    out.extend_from_slice(&0u16.to_le_bytes()); // e_shstrndx
+}
+
+/// Append a program header
+#[allow(clippy::too_many_arguments)]
+fn push_phdr(
+    out: &mut Vec<u8>,
+    kind: u32,
+    flags: u32,
+    offset: u64,
+    vaddr: u64,
+    filesz: u64,
+    memsz: u64,
+    alignment: u64,
+) {
+    out.extend_from_slice(&kind.to_le_bytes());
+    out.extend_from_slice(&flags.to_le_bytes());
+    out.extend_from_slice(&offset.to_le_bytes());
+    out.extend_from_slice(&vaddr.to_le_bytes());
+    out.extend_from_slice(&0u64.to_le_bytes()); // p_paddr
+    out.extend_from_slice(&filesz.to_le_bytes());
+    out.extend_from_slice(&memsz.to_le_bytes());
+    out.extend_from_slice(&alignment.to_le_bytes());
+}
+
+/// Append a "CORE" note
+fn push_note(out: &mut Vec<u8>, kind: u32, desc: &[u8]) {
+    const NAME: &[u8] = b"CORE\0";
+    out.extend_from_slice(&(NAME.len() as u32).to_le_bytes());
+    out.extend_from_slice(&(desc.len() as u32).to_le_bytes());
+    out.extend_from_slice(&kind.to_le_bytes());
+    out.extend_from_slice(NAME);
+    out.resize(align(out.len(), 4), 0);
+    out.extend_from_slice(desc);
+    out.resize(align(out.len(), 4), 0);
+}
+
+/// Build the ia64 `struct elf_prstatus`
+fn prstatus(emulator: &Emulator, signal: i32) -> Result<Vec<u8>, EmulatorError> {
+    let cpu = &emulator.cpu;
+    let mut desc = vec![0u8; PRSTATUS_SIZE];
+
+    // pr_info.si_signo and pr_cursig
+    desc[0..4].copy_from_slice(&signal.to_le_bytes());
+    desc[12..14].copy_from_slice(&(signal as i16).to_le_bytes());
+    // pr_pid
+    desc[32..36].copy_from_slice(&1i32.to_le_bytes());
+
+    let mut regs = [0u64; ELF_NGREG];
+    regs[..32].copy_from_slice(&cpu.gr[..32]);
+    regs[ELF_NAT_OFFSET] = cpu.nat[..32]
+        .iter()
+        .enumerate()
+        .fold(0, |bits, (reg, &nat)| bits | ((nat as u64) << reg));
+    regs[ELF_PR_OFFSET] = cpu
+        .pr
+        .iter()
+        .enumerate()
+        .fold(0, |acc, (i, &p)| acc | ((p as u64) << i));
+    regs[ELF_BR_OFFSET..ELF_BR_OFFSET + 8].copy_from_slice(&cpu.br);
+    regs[ELF_CR_IIP_OFFSET] = cpu.ip;
+    regs[ELF_CFM_OFFSET] = cpu.cfm;
+    regs[ELF_CR_IPSR_OFFSET] = cpu.get_psr();
+    regs[ELF_AR_RSC_OFFSET] = cpu.read_ar(AR::RSC)?;
+    regs[ELF_AR_BSP_OFFSET] = cpu.read_ar(AR::BSP)?;
+    regs[ELF_AR_BSPSTORE_OFFSET] = cpu.read_ar(AR::BSPSTORE)?;
+    regs[ELF_AR_RNAT_OFFSET] = cpu.read_ar(AR::RNAT)?;
+    regs[ELF_AR_CCV_OFFSET] = cpu.read_ar(AR::CCV)?;
+    regs[ELF_AR_UNAT_OFFSET] = cpu.read_ar(AR::UNAT)?;
+    regs[ELF_AR_FPSR_OFFSET] = cpu.read_ar(AR::FPSR)?;
+    regs[ELF_AR_PFS_OFFSET] = cpu.pfs;
+
+    for (i, reg) in regs.iter().enumerate() {
+        let offset = PRSTATUS_REG_OFFSET + i * 8;
+        desc[offset..offset + 8].copy_from_slice(&reg.to_le_bytes());
+    }
+
+    // pr_fpvalid
+    let offset = PRSTATUS_REG_OFFSET + ELF_NGREG * 8;
+    desc[offset..offset + 4].copy_from_slice(&1i32.to_le_bytes());
+
+    Ok(desc)
+}
+
+/// Build the ia64 `elf_fpregset_t`
+///
+/// Each register takes a 16-byte slot in the spill format stf.spill
+/// writes, which holds all 82 bits.
+fn fpregset(emulator: &Emulator) -> Vec<u8> {
+    let mut desc = vec![0u8; ELF_NFPREG * 16];
+    for (i, fr) in emulator.cpu.fr.iter().enumerate() {
+        desc[i * 16..i * 16 + 16].copy_from_slice(&fr.to_spill());
+    }
+    desc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn u16_at(data: &[u8], offset: usize) -> u16 {
+        u16::from_le_bytes(data[offset..offset + 2].try_into().unwrap())
+    }
+
+    fn u32_at(data: &[u8], offset: usize) -> u32 {
+        u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+    }
+
+    fn u64_at(data: &[u8], offset: usize) -> u64 {
+        u64::from_le_bytes(data[offset..offset + 8].try_into().unwrap())
+    }
+
+    #[test]
+    fn test_core_layout() {
+        let mut emu = Emulator::new();
+        emu.memory
+            .map(0x10000, 0x1000, Permissions::ReadExecute)
+            .unwrap();
+        emu.memory
+            .map(0x20000, 0x2000, Permissions::ReadWrite)
+            .unwrap();
+        emu.memory.write_u64(0x20010, 0x1122334455667788).unwrap();
+        emu.cpu.gr[12] = 0xdead;
+        emu.cpu.br[0] = 0x10040;
+        emu.cpu.ip = 0x10020;
+
+        let core = build_core(&emu, SIGSEGV).unwrap();
+
+        // ELF header
+        assert_eq!(&core[0..4], b"\x7FELF");
+        assert_eq!(u16_at(&core, 16), ET_CORE);
+        assert_eq!(u16_at(&core, 18), EM_IA_64);
+        assert_eq!(u16_at(&core, 56), 3);
+
+        // Note segment holds prstatus with the signal and registers
+        let note = EHDR_SIZE;
+        assert_eq!(u32_at(&core, note), PT_NOTE);
+        let notes = u64_at(&core, note + 8) as usize;
+        assert_eq!(u32_at(&core, notes + 8), NT_PRSTATUS);
+        assert_eq!(&core[notes + 12..notes + 17], b"CORE\0");
+        let prstatus = notes + 20;
+        assert_eq!(u32_at(&core, prstatus), SIGSEGV as u32);
+        let regs = prstatus + PRSTATUS_REG_OFFSET;
+        assert_eq!(u64_at(&core, regs + 12 * 8), 0xdead);
+        assert_eq!(u64_at(&core, regs + ELF_BR_OFFSET * 8), 0x10040);
+        assert_eq!(u64_at(&core, regs + ELF_CR_IIP_OFFSET * 8), 0x10020);
+        assert_eq!(u64_at(&core, regs + ELF_PR_OFFSET * 8) & 1, 1);
+
+        // FP note follows
+        let fp = prstatus + PRSTATUS_SIZE;
+        assert_eq!(u32_at(&core, fp + 8), NT_PRFPREG);
+
+        // Load segments cover each region with its permissions
+        let text = EHDR_SIZE + PHDR_SIZE;
+        assert_eq!(u32_at(&core, text), PT_LOAD);
+        assert_eq!(u32_at(&core, text + 4), PF_R | PF_X);
+        assert_eq!(u64_at(&core, text + 16), 0x10000);
+        assert_eq!(u64_at(&core, text + 32), 0x1000);
+
+        let data = text + PHDR_SIZE;
+        assert_eq!(u32_at(&core, data + 4), PF_R | PF_W);
+        assert_eq!(u64_at(&core, data + 16), 0x20000);
+        let offset = u64_at(&core, data + 8) as usize;
+        assert_eq!(offset % 8, 0);
+        assert_eq!(u64_at(&core, offset + 0x10), 0x1122334455667788);
+        assert_eq!(core.len(), offset + 0x2000);
+    }
+
+    #[test]
+    fn test_fault_signals() {
+        assert_eq!(
+            signal_for_error(&EmulatorError::MemoryError("x".to_string())),
+            SIGSEGV
+        );
+        assert_eq!(signal_for_error(&EmulatorError::InvalidAlignment), SIGBUS);
+        assert_eq!(
+            signal_for_error(&EmulatorError::DecodeError("x".to_string())),
+            SIGILL
+        );
+        assert_eq!(signal_for_stop(StopReason::Break(0)), Some(SIGTRAP));
+        assert_eq!(signal_for_stop(StopReason::Panic), Some(SIGABRT));
+        assert_eq!(signal_for_stop(StopReason::Livelock), Some(SIGXCPU));
+        assert_eq!(
+            signal_for_stop(StopReason::DataMatch { addr: 0, value: 0 }),
+            Some(SIGTRAP)
+        );
+        assert_eq!(signal_for_stop(StopReason::WatchdogExpired), Some(SIGALRM));
+        assert_eq!(signal_for_stop(StopReason::Exited(0)), None);
+    }
+}
@@ -2,6 +2,12 @@
 //!
 //! This module implements the Advanced Load Address Table for the IA-64
 //! architecture, which supports data speculation by tracking speculative loads.
+//!
+//! Entries are keyed by the address an advanced load read. Every store
+//! invalidates the entries it overlaps, whatever register it stores:
+//! ordinary and release stores, semaphores, `st8.spill` and the register
+//! stack engine's spills to the backing store. `ld.c` then loads again and
+//! `chk.a` branches to recovery code.
 
 use crate::EmulatorError;
 
@@ -69,11 +75,44 @@ impl Entry {
     }
 }
 
+/// ALAT activity counters
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct AlatStats {
+    /// Entries added by advanced loads
+    pub adds: u64,
+    /// Checks that found a valid entry
+    pub check_hits: u64,
+    /// Checks that found no valid entry and needed recovery
+    pub check_misses: u64,
+    /// Valid entries invalidated by overlapping stores
+    pub store_invalidations: u64,
+    /// Valid entries evicted to make room for new ones
+    pub capacity_evictions: u64,
+    /// Valid entries removed by invala or a clearing check
+    pub explicit_invalidations: u64,
+    /// Most valid entries held at once
+    pub peak_occupancy: u64,
+}
+
+impl AlatStats {
+    /// Fraction of checks that hit
+    pub fn hit_rate(&self) -> f64 {
+        let checks = self.check_hits + self.check_misses;
+        if checks == 0 {
+            0.0
+        } else {
+            self.check_hits as f64 / checks as f64
+        }
+    }
+}
+
 /// Advanced Load Address Table
 #[derive(Debug)]
 pub struct ALAT {
     /// ALAT entries
     entries: Vec<Entry>,
+    /// Activity counters
+    stats: AlatStats,
 }
 
 impl Default for ALAT {
@@ -87,6 +126,7 @@ impl ALAT {
     pub fn new() -> Self {
         Self {
             entries: Vec::with_capacity(MAX_ALAT_ENTRIES),
+            stats: AlatStats::default(),
         }
     }
 
@@ -106,11 +146,16 @@ impl ALAT {
         let entry = Entry::new(address, size, register, is_integer);
 
         // Add entry, removing oldest if at capacity
-        if self.entries.len() >= MAX_ALAT_ENTRIES {
-            self.entries.remove(0);
+        if self.entries.len() >= MAX_ALAT_ENTRIES
+            && self.entries.remove(0).state == EntryState::Valid
+        {
+            self.stats.capacity_evictions += 1;
         }
         self.entries.push(entry);
 
+        self.stats.adds += 1;
+        let occupancy = self.valid_entries() as u64;
+        self.stats.peak_occupancy = self.stats.peak_occupancy.max(occupancy);
         Ok(())
     }
 
@@ -121,11 +166,23 @@ impl ALAT {
         })
     }
 
+    /// Check a register for a check load or chk.a, counting the outcome
+    pub fn check(&mut self, register: u32, is_integer: bool) -> bool {
+        let hit = self.check_register(register, is_integer);
+        if hit {
+            self.stats.check_hits += 1;
+        } else {
+            self.stats.check_misses += 1;
+        }
+        hit
+    }
+
     /// Invalidate entries that overlap with store
     pub fn invalidate_overlap(&mut self, address: u64, size: u64) {
         for entry in self.entries.iter_mut() {
             if entry.overlaps(address, size as usize) {
                 entry.state = EntryState::Invalidated;
+                self.stats.store_invalidations += 1;
             }
         }
     }
@@ -141,6 +198,7 @@ impl ALAT {
 
     /// Clear all ALAT entries
     pub fn clear(&mut self) {
+        self.stats.explicit_invalidations += self.valid_entries() as u64;
         self.entries.clear();
     }
 
@@ -196,8 +254,25 @@ impl ALAT {
 
     /// Remove entry
     pub fn remove_entry(&mut self, register: u32, is_integer: bool) {
+        let valid = self.valid_entries();
         self.entries
             .retain(|e| e.register != register || e.is_integer != is_integer);
+        self.stats.explicit_invalidations += (valid - self.valid_entries()) as u64;
+    }
+
+    /// Activity counters
+    pub fn stats(&self) -> AlatStats {
+        self.stats
+    }
+
+    /// Reset the activity counters
+    ///
+    /// The peak occupancy restarts from the entries currently held.
+    pub fn reset_stats(&mut self) {
+        self.stats = AlatStats {
+            peak_occupancy: self.valid_entries() as u64,
+            ..AlatStats::default()
+        };
     }
 
     /// Purge old entries
@@ -346,6 +421,37 @@ mod tests {
         assert!(alat.get_entry_info(33, true).is_none());
     }
 
+    #[test]
+    fn test_alat_stats() {
+        let mut alat = ALAT::new();
+
+        for i in 0..MAX_ALAT_ENTRIES + 2 {
+            alat.add_entry(0x1000 + 0x10 * i as u64, 8, i as u32, true)
+                .unwrap();
+        }
+        assert!(alat.check(2, true));
+        assert!(!alat.check(0, true));
+
+        // A store over entry 2, then invala of the remaining entries
+        alat.invalidate_overlap(0x1020, 8);
+        assert!(!alat.check(2, true));
+        alat.remove_entry(3, true);
+        alat.clear();
+
+        let stats = alat.stats();
+        assert_eq!(stats.adds, MAX_ALAT_ENTRIES as u64 + 2);
+        assert_eq!(stats.check_hits, 1);
+        assert_eq!(stats.check_misses, 2);
+        assert!((stats.hit_rate() - 1.0 / 3.0).abs() < 1e-9);
+        assert_eq!(stats.capacity_evictions, 2);
+        assert_eq!(stats.store_invalidations, 1);
+        assert_eq!(stats.explicit_invalidations, MAX_ALAT_ENTRIES as u64 - 1);
+        assert_eq!(stats.peak_occupancy, MAX_ALAT_ENTRIES as u64);
+
+        alat.reset_stats();
+        assert_eq!(alat.stats(), AlatStats::default());
+    }
+
     #[test]
     fn test_alat_purge() {
         let mut alat = ALAT::new();
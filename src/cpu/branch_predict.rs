@@ -0,0 +1,160 @@
+//! Branch prediction model
+//!
+//! The emulator executes branches exactly, but a guest profiler reading the
+//! PMU expects mispredictions to be counted. This model predicts each
+//! IP-relative or indirect branch as the hardware would from its whether
+//! hint: `.sptk` and `.spnt` are static predictions, taken and not taken,
+//! while `.dptk` and `.dpnt` start from the hint and then follow a 2-bit
+//! saturating counter in a direct-mapped table indexed by the branch's
+//! address. Aliasing between branches that share a table entry is modelled
+//! as on hardware.
+
+use crate::decoder::completers::Completers;
+
+/// Entries in the prediction table
+const TABLE_SIZE: usize = 1024;
+
+/// Counter values predicting taken are at least this
+const TAKEN: u8 = 2;
+
+/// Branch prediction counters
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BranchStats {
+    /// Branches resolved
+    pub branches: u64,
+    /// Branches taken
+    pub taken: u64,
+    /// Branches whose direction was mispredicted
+    pub mispredicts: u64,
+}
+
+impl BranchStats {
+    /// Fraction of branches predicted correctly
+    pub fn accuracy(&self) -> f64 {
+        if self.branches == 0 {
+            0.0
+        } else {
+            1.0 - self.mispredicts as f64 / self.branches as f64
+        }
+    }
+}
+
+/// Branch direction predictor
+#[derive(Debug, Clone)]
+pub struct BranchPredictor {
+    /// 2-bit counters of dynamically predicted branches; `None` until a
+    /// branch using the entry resolves
+    counters: Vec<Option<u8>>,
+    /// Counters
+    stats: BranchStats,
+}
+
+impl Default for BranchPredictor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl BranchPredictor {
+    /// Create a predictor with no history
+    pub fn new() -> Self {
+        Self {
+            counters: vec![None; TABLE_SIZE],
+            stats: BranchStats::default(),
+        }
+    }
+
+    /// Resolve the branch in slot `slot` of the bundle at `ip`, predicted
+    /// from its `hints`, which `taken` or not
+    ///
+    /// Returns whether the direction was mispredicted.
+    pub fn resolve(&mut self, ip: u64, slot: u8, hints: Completers, taken: bool) -> bool {
+        let predicted = self.predict(ip, slot, hints);
+        if !is_static(hints) {
+            let hinted = is_taken_hint(hints);
+            let counter = &mut self.counters[index(ip, slot)];
+            let value = counter.unwrap_or(if hinted { TAKEN } else { TAKEN - 1 });
+            *counter = Some(if taken {
+                (value + 1).min(3)
+            } else {
+                value.saturating_sub(1)
+            });
+        }
+
+        let mispredicted = predicted != taken;
+        self.stats.branches += 1;
+        self.stats.taken += taken as u64;
+        self.stats.mispredicts += mispredicted as u64;
+        mispredicted
+    }
+
+    /// Whether the branch in slot `slot` of the bundle at `ip` is predicted
+    /// taken, without resolving it
+    pub fn predict(&self, ip: u64, slot: u8, hints: Completers) -> bool {
+        let hinted = is_taken_hint(hints);
+        if is_static(hints) {
+            hinted
+        } else {
+            self.counters[index(ip, slot)].map_or(hinted, |value| value >= TAKEN)
+        }
+    }
+
+    /// Prediction counters
+    pub fn stats(&self) -> BranchStats {
+        self.stats
+    }
+
+    /// Start the counters over, keeping the prediction history
+    pub fn reset_stats(&mut self) {
+        self.stats = BranchStats::default();
+    }
+}
+
+/// Whether the hint predicts a static direction
+fn is_static(hints: Completers) -> bool {
+    hints.contains(Completers::SPTK) || hints.contains(Completers::SPNT)
+}
+
+/// Whether the hint predicts taken
+fn is_taken_hint(hints: Completers) -> bool {
+    hints.contains(Completers::SPTK) || hints.contains(Completers::DPTK)
+}
+
+/// Table entry of a branch
+fn index(ip: u64, slot: u8) -> usize {
+    (((ip >> 4) << 2 | slot as u64) as usize) % TABLE_SIZE
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_predictions() {
+        let mut predictor = BranchPredictor::new();
+        // Static hints are always followed
+        assert!(!predictor.resolve(0x1000, 2, Completers::SPTK, true));
+        assert!(predictor.resolve(0x1000, 2, Completers::SPTK, false));
+        assert!(predictor.resolve(0x1010, 2, Completers::SPNT, true));
+
+        // A dynamic branch starts from its hint, then follows its history:
+        // a change of direction is mispredicted until the counter turns
+        let hints = Completers::DPTK;
+        assert!(predictor.predict(0x2000, 0, hints));
+        let outcomes = [true, true, true, false, true, false, false, false];
+        let mispredicts: Vec<bool> = outcomes
+            .iter()
+            .map(|&taken| predictor.resolve(0x2000, 0, hints, taken))
+            .collect();
+        assert_eq!(
+            mispredicts,
+            [false, false, false, true, false, true, true, false]
+        );
+        assert!(!predictor.predict(0x2000, 0, hints));
+
+        let stats = predictor.stats();
+        assert_eq!((stats.branches, stats.taken, stats.mispredicts), (11, 6, 5));
+        predictor.reset_stats();
+        assert_eq!(predictor.stats(), BranchStats::default());
+    }
+}
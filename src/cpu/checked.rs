@@ -0,0 +1,135 @@
+//! Checked width conversions
+//!
+//! Instruction code narrows values with `as`, which drops high bits without
+//! a word. Where dropping them is the architecture's doing, like a store of
+//! the low byte, that is what it should do; where an out-of-range value
+//! can only come from a decoder or executor bug, like a register number
+//! above 127 or a frame size that spills into the next CFM field, code
+//! narrows through these helpers instead.
+//!
+//! [`narrow`] and [`pack`] always check. [`Cpu::narrow`](super::Cpu::narrow)
+//! and [`Cpu::pack`](super::Cpu::pack) check only when
+//! [`Cpu::strict_types`](super::Cpu::strict_types) is set, and otherwise
+//! truncate as `as` would, so a run with strict types on turns a silent
+//! width mismatch into an [`EmulatorError::Truncation`] naming the value.
+
+use crate::EmulatorError;
+
+/// Integer type a `u64` can be narrowed to
+pub trait Narrow: Copy {
+    /// Width in bits
+    const BITS: u32;
+
+    /// The low bits of `value`, as `as` converts it
+    fn truncate(value: u64) -> Self;
+}
+
+macro_rules! narrow_impl {
+    ($($t:ty),*) => {
+        $(
+            impl Narrow for $t {
+                const BITS: u32 = <$t>::BITS;
+
+                fn truncate(value: u64) -> Self {
+                    value as $t
+                }
+            }
+        )*
+    };
+}
+
+narrow_impl!(u8, u16, u32, usize);
+
+/// Whether `value` fits in `bits` bits
+fn fits(value: u64, bits: u32) -> bool {
+    bits >= u64::BITS || value >> bits == 0
+}
+
+/// Fault for a `what` of `value` that does not fit in `bits` bits
+fn truncation(what: &'static str, value: u64, bits: u32) -> EmulatorError {
+    EmulatorError::Truncation { what, value, bits }
+}
+
+/// Narrow `value` to `T`, faulting if it does not fit
+pub fn narrow<T: Narrow>(value: u64, what: &'static str) -> Result<T, EmulatorError> {
+    if !fits(value, T::BITS) {
+        return Err(truncation(what, value, T::BITS));
+    }
+    Ok(T::truncate(value))
+}
+
+/// `value` for a `bits` bit wide register field, faulting if it does not
+/// fit
+pub fn pack(value: u64, bits: u32, what: &'static str) -> Result<u64, EmulatorError> {
+    if !fits(value, bits) {
+        return Err(truncation(what, value, bits));
+    }
+    Ok(value)
+}
+
+/// Narrow `value` to `T`, faulting if it does not fit only when `strict`
+pub(crate) fn narrow_if<T: Narrow>(
+    value: u64,
+    what: &'static str,
+    strict: bool,
+) -> Result<T, EmulatorError> {
+    if strict {
+        narrow(value, what)
+    } else {
+        Ok(T::truncate(value))
+    }
+}
+
+/// `value` masked to a `bits` bit wide register field, faulting if it
+/// does not fit only when `strict`
+pub(crate) fn pack_if(
+    value: u64,
+    bits: u32,
+    what: &'static str,
+    strict: bool,
+) -> Result<u64, EmulatorError> {
+    if strict {
+        pack(value, bits, what)
+    } else if bits >= u64::BITS {
+        Ok(value)
+    } else {
+        Ok(value & ((1 << bits) - 1))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_boundaries() {
+        assert_eq!(narrow::<u8>(0xFF, "register").unwrap(), 0xFF);
+        assert!(matches!(
+            narrow::<u8>(0x100, "register"),
+            Err(EmulatorError::Truncation {
+                what: "register",
+                value: 0x100,
+                bits: 8
+            })
+        ));
+        assert_eq!(narrow::<u16>(0xFFFF, "value").unwrap(), 0xFFFF);
+        assert!(narrow::<u16>(0x1_0000, "value").is_err());
+        assert_eq!(narrow::<u32>(u32::MAX as u64, "count").unwrap(), u32::MAX);
+        assert!(narrow::<u32>(1 << 32, "count").is_err());
+        assert_eq!(narrow::<usize>(u64::MAX, "index").unwrap(), usize::MAX);
+
+        assert_eq!(pack(0x7F, 7, "sof").unwrap(), 0x7F);
+        assert!(pack(0x80, 7, "sof").is_err());
+        assert_eq!(pack(u64::MAX, 64, "value").unwrap(), u64::MAX);
+    }
+
+    #[test]
+    fn test_lenient() {
+        // Without strict types, values truncate as `as` would
+        assert_eq!(narrow_if::<u8>(0x1FF, "register", false).unwrap(), 0xFF);
+        assert!(narrow_if::<u8>(0x1FF, "register", true).is_err());
+        assert_eq!(pack_if(0x81, 7, "sof", false).unwrap(), 1);
+        assert!(pack_if(0x81, 7, "sof", true).is_err());
+        assert_eq!(pack_if(u64::MAX, 64, "value", false).unwrap(), u64::MAX);
+    }
+}
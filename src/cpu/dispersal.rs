@@ -0,0 +1,363 @@
+//! Instruction dispersal model
+//!
+//! For microarchitecture studies this module models how bundles issue to
+//! functional unit ports: a cycle takes up to two bundles, each slot needs a
+//! free port of its unit's kind, and a slot that finds none splits the issue,
+//! pushing it and the rest of its bundle to the next cycle. A-type slots can
+//! issue on either an M or an I port, the L half of an MLX pair uses no port
+//! and its X half an I port. Cycles also end at taken branches and at the
+//! stops a bundle's template places between instruction groups.
+//!
+//! The model only counts; it does not change what the guest computes. It is
+//! off unless installed with [`Emulator::dispersal`](crate::emulator::Emulator::dispersal).
+//! In strict mode a split issue is an error instead, to check that
+//! hand-scheduled code fits the machine's issue width.
+
+use crate::decoder::Unit;
+use crate::EmulatorError;
+use serde::Deserialize;
+use std::fmt;
+
+/// Bundles issued per cycle
+pub const BUNDLES_PER_CYCLE: u32 = 2;
+
+/// Kind of functional unit port
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Port {
+    /// Memory
+    M,
+    /// Integer
+    I,
+    /// Floating-point
+    F,
+    /// Branch
+    B,
+}
+
+impl Port {
+    /// All port kinds
+    pub const ALL: [Port; 4] = [Port::M, Port::I, Port::F, Port::B];
+
+    /// Ports a slot of `unit` may issue on, in order of preference
+    fn for_unit(unit: Unit) -> &'static [Port] {
+        match unit {
+            Unit::A => &[Port::M, Port::I],
+            Unit::M => &[Port::M],
+            Unit::I | Unit::X => &[Port::I],
+            Unit::F => &[Port::F],
+            Unit::B => &[Port::B],
+            Unit::L => &[],
+        }
+    }
+}
+
+impl fmt::Display for Port {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Port::M => "M",
+            Port::I => "I",
+            Port::F => "F",
+            Port::B => "B",
+        };
+        f.write_str(name)
+    }
+}
+
+/// Processor whose issue rules are modelled
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum MachineModel {
+    /// Itanium: two M, two I, two F and three B ports
+    Itanium,
+    /// Itanium 2: four M, two I, two F and three B ports
+    Itanium2,
+}
+
+impl MachineModel {
+    /// Look up a model by its configuration name
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "itanium" => Some(MachineModel::Itanium),
+            "itanium2" => Some(MachineModel::Itanium2),
+            _ => None,
+        }
+    }
+
+    /// Configuration name
+    pub fn name(self) -> &'static str {
+        match self {
+            MachineModel::Itanium => "itanium",
+            MachineModel::Itanium2 => "itanium2",
+        }
+    }
+
+    /// Number of ports of a kind
+    pub fn ports(self, port: Port) -> u32 {
+        match (self, port) {
+            (MachineModel::Itanium, Port::M) => 2,
+            (MachineModel::Itanium2, Port::M) => 4,
+            (_, Port::I | Port::F) => 2,
+            (_, Port::B) => 3,
+        }
+    }
+}
+
+impl fmt::Display for MachineModel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Issue and stall counts
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DispersalStats {
+    /// Issue cycles
+    pub cycles: u64,
+    /// Bundles issued
+    pub bundles: u64,
+    /// Instructions issued to a port
+    pub instructions: u64,
+    /// Bundles split across cycles for lack of a port
+    pub split_issues: u64,
+    /// Slots that found every port of their kind busy, by [`Port::ALL`] order
+    pub port_conflicts: [u64; 4],
+    /// Cycles ended early by a taken branch
+    pub branch_breaks: u64,
+    /// Cycles ended early by a template stop
+    pub stop_breaks: u64,
+}
+
+impl DispersalStats {
+    /// Instructions issued per cycle
+    pub fn ipc(&self) -> f64 {
+        if self.cycles == 0 {
+            return 0.0;
+        }
+        self.instructions as f64 / self.cycles as f64
+    }
+
+    /// Port conflicts on ports of one kind
+    pub fn conflicts(&self, port: Port) -> u64 {
+        self.port_conflicts[port as usize]
+    }
+}
+
+impl fmt::Display for DispersalStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "issue cycles {}, bundles {}, instructions {}, ipc {:.2}",
+            self.cycles,
+            self.bundles,
+            self.instructions,
+            self.ipc()
+        )?;
+        write!(f, "split issues {}, port conflicts:", self.split_issues)?;
+        for port in Port::ALL {
+            write!(f, " {} {}", port, self.conflicts(port))?;
+        }
+        writeln!(
+            f,
+            ", taken-branch breaks {}, stop breaks {}",
+            self.branch_breaks, self.stop_breaks
+        )
+    }
+}
+
+/// Dispersal of bundles to the ports of a machine model
+#[derive(Debug, Clone)]
+pub struct Dispersal {
+    /// Machine modelled
+    machine: MachineModel,
+    /// Fail on split issues instead of counting them
+    strict: bool,
+    /// Ports in use this cycle, by [`Port::ALL`] order
+    used: [u32; 4],
+    /// Bundles issued this cycle
+    bundles: u32,
+    /// Counters
+    stats: DispersalStats,
+}
+
+impl Dispersal {
+    /// Model `machine`, failing on split issues if `strict`
+    pub fn new(machine: MachineModel, strict: bool) -> Self {
+        Self {
+            machine,
+            strict,
+            used: [0; 4],
+            bundles: 0,
+            stats: DispersalStats::default(),
+        }
+    }
+
+    /// Machine modelled
+    pub fn machine(&self) -> MachineModel {
+        self.machine
+    }
+
+    /// Issue a bundle whose slots go to `units`, with an instruction group
+    /// ending after each slot marked in `stops`
+    pub fn issue(&mut self, units: &[Unit], stops: &[bool; 3]) -> Result<(), EmulatorError> {
+        if self.bundles == 0 || self.bundles >= BUNDLES_PER_CYCLE {
+            self.start_cycle();
+        }
+        self.bundles += 1;
+        self.stats.bundles += 1;
+
+        let mut split = false;
+        for (slot, &unit) in units.iter().enumerate() {
+            let ports = Port::for_unit(unit);
+            let Some(&first) = ports.first() else {
+                continue;
+            };
+            let port = match ports
+                .iter()
+                .find(|&&port| self.used[port as usize] < self.machine.ports(port))
+            {
+                Some(&port) => port,
+                None => {
+                    self.stats.port_conflicts[first as usize] += 1;
+                    if self.strict {
+                        return Err(EmulatorError::ExecutionError(format!(
+                            "Split issue: slot {} of an {} bundle needs an {} port, \
+                             but all {} are in use this cycle on {}",
+                            slot,
+                            template(units),
+                            first,
+                            self.machine.ports(first),
+                            self.machine
+                        )));
+                    }
+                    if !split {
+                        self.stats.split_issues += 1;
+                        split = true;
+                    }
+                    self.start_cycle();
+                    self.bundles = 1;
+                    first
+                }
+            };
+            self.used[port as usize] += 1;
+            self.stats.instructions += 1;
+
+            // Slots after a mid-bundle stop issue in the next cycle
+            if stops[slot] && slot + 1 < units.len() {
+                self.stats.stop_breaks += 1;
+                self.start_cycle();
+                self.bundles = 1;
+            }
+        }
+        if stops[2] {
+            if self.bundles < BUNDLES_PER_CYCLE {
+                self.stats.stop_breaks += 1;
+            }
+            self.bundles = BUNDLES_PER_CYCLE;
+        }
+        Ok(())
+    }
+
+    /// End the cycle after a taken branch
+    pub fn end_cycle(&mut self) {
+        if self.bundles > 0 && self.bundles < BUNDLES_PER_CYCLE {
+            self.stats.branch_breaks += 1;
+        }
+        self.bundles = 0;
+    }
+
+    /// Start a new issue cycle
+    fn start_cycle(&mut self) {
+        self.stats.cycles += 1;
+        self.used = [0; 4];
+        self.bundles = 0;
+    }
+
+    /// Counters since the model was installed or last reset
+    pub fn stats(&self) -> &DispersalStats {
+        &self.stats
+    }
+
+    /// Zero the counters
+    pub fn reset_stats(&mut self) {
+        self.stats = DispersalStats::default();
+    }
+}
+
+/// Template name spelled from the slot units, e.g. `MII`
+fn template(units: &[Unit]) -> String {
+    units.iter().map(|unit| unit.letter()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MII: [Unit; 3] = [Unit::M, Unit::I, Unit::I];
+    const MMF: [Unit; 3] = [Unit::M, Unit::M, Unit::F];
+    const MLX: [Unit; 3] = [Unit::M, Unit::L, Unit::X];
+    const BBB: [Unit; 3] = [Unit::B; 3];
+    const NONE: [bool; 3] = [false; 3];
+
+    #[test]
+    fn test_dispersal() {
+        let mut model = Dispersal::new(MachineModel::Itanium2, false);
+        // MMF + MMF fits the four M ports of an Itanium 2
+        model.issue(&MMF, &NONE).unwrap();
+        model.issue(&MMF, &NONE).unwrap();
+        // MII + MII needs four I ports: the second bundle splits
+        model.issue(&MII, &NONE).unwrap();
+        model.issue(&MII, &NONE).unwrap();
+        // The split-off I slots pair with a bundle needing no I port
+        model.issue(&MMF, &NONE).unwrap();
+        let stats = model.stats();
+        assert_eq!(stats.cycles, 3);
+        assert_eq!(stats.bundles, 5);
+        assert_eq!(stats.instructions, 15);
+        assert_eq!(stats.split_issues, 1);
+        assert_eq!(stats.conflicts(Port::I), 1);
+        assert_eq!(stats.conflicts(Port::M), 0);
+
+        // Itanium has only two M ports
+        let mut model = Dispersal::new(MachineModel::Itanium, false);
+        model.issue(&MMF, &NONE).unwrap();
+        model.issue(&MMF, &NONE).unwrap();
+        assert_eq!(model.stats().conflicts(Port::M), 1);
+        // The split-off slots leave room for a BBB
+        model.issue(&BBB, &NONE).unwrap();
+        assert_eq!(model.stats().cycles, 2);
+        assert_eq!(model.stats().instructions, 9);
+
+        // A taken branch ends the cycle early
+        model.reset_stats();
+        model.end_cycle();
+        model.issue(&MLX, &NONE).unwrap();
+        model.end_cycle();
+        model.issue(&MII, &NONE).unwrap();
+        // The L slot of the MLX takes no port
+        assert_eq!(model.stats().instructions, 5);
+        assert_eq!(model.stats().cycles, 2);
+        assert_eq!(model.stats().branch_breaks, 1);
+        assert!(model.stats().to_string().contains("ipc 2.50"));
+
+        // An M;MI stop pushes the last two slots to the next cycle, and
+        // a stop at the end keeps the next bundle from pairing
+        model.reset_stats();
+        model.end_cycle();
+        model
+            .issue(&[Unit::M, Unit::M, Unit::I], &[true, false, true])
+            .unwrap();
+        model.issue(&MII, &NONE).unwrap();
+        assert_eq!(model.stats().cycles, 3);
+        assert_eq!(model.stats().stop_breaks, 2);
+        assert_eq!(model.stats().split_issues, 0);
+    }
+
+    #[test]
+    fn test_strict_dispersal() {
+        let mut model = Dispersal::new(MachineModel::Itanium2, true);
+        model.issue(&MII, &NONE).unwrap();
+        let error = model.issue(&MII, &NONE).unwrap_err().to_string();
+        assert!(error.contains("slot 1 of an MII bundle needs an I port"));
+        assert!(error.contains("all 2 are in use this cycle on itanium2"));
+    }
+}
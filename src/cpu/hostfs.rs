@@ -0,0 +1,363 @@
+//! Host directory shared with the guest
+//!
+//! Rather than building a disk image, a host directory can be handed to the
+//! guest through its file system calls. [`HostFs`] serves open, read, write,
+//! lseek, fstat and close on files below a root directory, which the guest
+//! sees as `/`.
+//!
+//! Guest paths never leave the root. `..` stops at the root the way it does
+//! at `/`, and a path whose symbolic links resolve outside the root is
+//! refused with EACCES. Files are opened through their resolved path, so a
+//! link cannot be followed out of the root after the check. In read-only
+//! mode every open that could modify the tree fails with EROFS.
+//!
+//! Open files are host state: they are not saved in snapshots.
+
+use super::syscall::{EACCES, EBADF, EEXIST, EINVAL, EIO, EISDIR, ENOENT, ENOTDIR, EROFS};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fs::{File, Metadata, OpenOptions};
+use std::io::{self, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::path::{Component, Path, PathBuf};
+
+/// open(2) access mode bits
+const O_ACCMODE: u64 = 3;
+const O_WRONLY: u64 = 1;
+const O_RDWR: u64 = 2;
+
+/// open(2) flag creating a missing file
+const O_CREAT: u64 = 0o100;
+
+/// open(2) flag failing if the file exists
+const O_EXCL: u64 = 0o200;
+
+/// open(2) flag truncating the file
+const O_TRUNC: u64 = 0o1000;
+
+/// open(2) flag appending every write
+const O_APPEND: u64 = 0o2000;
+
+/// lseek(2) origins
+const SEEK_SET: u64 = 0;
+const SEEK_CUR: u64 = 1;
+const SEEK_END: u64 = 2;
+
+/// First descriptor handed out; 0 to 2 are the standard streams
+const FIRST_FD: u64 = 3;
+
+/// Most bytes moved by one read or write
+pub const MAX_TRANSFER: u64 = 1 << 20;
+
+/// What the guest may do to the shared directory
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum HostFsMode {
+    /// Open files for reading only
+    #[default]
+    ReadOnly,
+    /// Also create, truncate and write files
+    ReadWrite,
+}
+
+/// A host directory served to the guest as its file system
+#[derive(Debug)]
+pub struct HostFs {
+    /// Canonical path of the shared directory
+    root: PathBuf,
+    /// Access allowed to the guest
+    mode: HostFsMode,
+    /// Open files by guest descriptor
+    files: BTreeMap<u64, File>,
+}
+
+impl HostFs {
+    /// Share the directory `root`
+    pub fn new(root: impl AsRef<Path>, mode: HostFsMode) -> io::Result<Self> {
+        let root = root.as_ref().canonicalize()?;
+        if !root.is_dir() {
+            return Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                format!("{} is not a directory", root.display()),
+            ));
+        }
+        Ok(Self {
+            root,
+            mode,
+            files: BTreeMap::new(),
+        })
+    }
+
+    /// Canonical path of the shared directory
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Access allowed to the guest
+    pub fn mode(&self) -> HostFsMode {
+        self.mode
+    }
+
+    /// Whether `fd` is a file opened through the share
+    pub fn is_open(&self, fd: u64) -> bool {
+        self.files.contains_key(&fd)
+    }
+
+    /// Host path of the guest path `path`
+    ///
+    /// Relative paths start at the root, as the guest's working directory
+    /// is `/`. With `create`, the last component may be missing and only
+    /// its directory has to exist. Fails with an errno.
+    pub fn resolve(&self, path: &str, create: bool) -> Result<PathBuf, u64> {
+        if path.is_empty() {
+            return Err(ENOENT);
+        }
+        let mut relative = PathBuf::new();
+        for component in Path::new(path).components() {
+            match component {
+                Component::Normal(name) => relative.push(name),
+                Component::ParentDir => {
+                    relative.pop();
+                }
+                Component::CurDir | Component::RootDir | Component::Prefix(_) => {}
+            }
+        }
+        let full = self.root.join(&relative);
+
+        let resolved = match full.canonicalize() {
+            Ok(resolved) => resolved,
+            Err(e) if e.kind() == ErrorKind::NotFound && create => {
+                // A dangling link would create its target wherever it points
+                if full.symlink_metadata().is_ok() {
+                    return Err(EACCES);
+                }
+                let name = relative.file_name().ok_or(EISDIR)?;
+                let parent = full
+                    .parent()
+                    .ok_or(ENOENT)?
+                    .canonicalize()
+                    .map_err(|e| errno(&e))?;
+                parent.join(name)
+            }
+            Err(e) => return Err(errno(&e)),
+        };
+        if resolved.starts_with(&self.root) {
+            Ok(resolved)
+        } else {
+            Err(EACCES)
+        }
+    }
+
+    /// Open `path` with open(2) `flags`, creating files with permission bits
+    /// `mode`, and return the guest descriptor
+    pub fn open(&mut self, path: &str, flags: u64, mode: u32) -> Result<u64, u64> {
+        let access = flags & O_ACCMODE;
+        let writes = access == O_WRONLY || access == O_RDWR;
+        let modifies = writes || flags & (O_CREAT | O_TRUNC) != 0;
+        if modifies && self.mode == HostFsMode::ReadOnly {
+            return Err(EROFS);
+        }
+        if access == O_ACCMODE {
+            return Err(EINVAL);
+        }
+
+        let create = flags & O_CREAT != 0;
+        let host = self.resolve(path, create)?;
+        if create && flags & O_EXCL != 0 && host.symlink_metadata().is_ok() {
+            return Err(EEXIST);
+        }
+        if writes && host.is_dir() {
+            return Err(EISDIR);
+        }
+
+        let mut options = OpenOptions::new();
+        options
+            .read(access != O_WRONLY)
+            .write(writes && flags & O_APPEND == 0)
+            .append(writes && flags & O_APPEND != 0)
+            .truncate(writes && flags & O_TRUNC != 0)
+            .create(create);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, mode & 0o7777);
+        #[cfg(not(unix))]
+        let _ = mode;
+        let file = options.open(&host).map_err(|e| errno(&e))?;
+
+        let fd = (FIRST_FD..)
+            .find(|fd| !self.files.contains_key(fd))
+            .expect("descriptor space exhausted");
+        self.files.insert(fd, file);
+        Ok(fd)
+    }
+
+    /// Read up to `len` bytes, at most [`MAX_TRANSFER`], from `fd`
+    pub fn read(&mut self, fd: u64, len: u64) -> Result<Vec<u8>, u64> {
+        let file = self.files.get_mut(&fd).ok_or(EBADF)?;
+        let mut data = vec![0; len.min(MAX_TRANSFER) as usize];
+        let read = file.read(&mut data).map_err(|e| errno(&e))?;
+        data.truncate(read);
+        Ok(data)
+    }
+
+    /// Write `data` to `fd` and return the bytes written
+    pub fn write(&mut self, fd: u64, data: &[u8]) -> Result<u64, u64> {
+        let file = self.files.get_mut(&fd).ok_or(EBADF)?;
+        file.write(data).map(|n| n as u64).map_err(|e| errno(&e))
+    }
+
+    /// Move the offset of `fd` and return the new offset
+    pub fn seek(&mut self, fd: u64, offset: u64, whence: u64) -> Result<u64, u64> {
+        let file = self.files.get_mut(&fd).ok_or(EBADF)?;
+        let position = match whence {
+            SEEK_SET => SeekFrom::Start(offset),
+            SEEK_CUR => SeekFrom::Current(offset as i64),
+            SEEK_END => SeekFrom::End(offset as i64),
+            _ => return Err(EINVAL),
+        };
+        file.seek(position).map_err(|e| errno(&e))
+    }
+
+    /// Metadata of the file open as `fd`
+    pub fn stat(&self, fd: u64) -> Result<Metadata, u64> {
+        let file = self.files.get(&fd).ok_or(EBADF)?;
+        file.metadata().map_err(|e| errno(&e))
+    }
+
+    /// Close `fd`
+    pub fn close(&mut self, fd: u64) -> Result<(), u64> {
+        self.files.remove(&fd).map(drop).ok_or(EBADF)
+    }
+}
+
+/// Guest errno for a host I/O error
+fn errno(error: &io::Error) -> u64 {
+    match error.kind() {
+        ErrorKind::NotFound => ENOENT,
+        ErrorKind::PermissionDenied => EACCES,
+        ErrorKind::AlreadyExists => EEXIST,
+        ErrorKind::InvalidInput => EINVAL,
+        ErrorKind::NotADirectory => ENOTDIR,
+        ErrorKind::IsADirectory => EISDIR,
+        ErrorKind::ReadOnlyFilesystem => EROFS,
+        _ => EIO,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Fresh directory under the system temporary directory
+    fn scratch(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("rust-ia64-hostfs-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("share/sub")).unwrap();
+        std::fs::write(dir.join("share/sub/file.txt"), b"shared").unwrap();
+        std::fs::write(dir.join("secret.txt"), b"secret").unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_path_traversal() {
+        let dir = scratch("traversal");
+        let fs = HostFs::new(dir.join("share"), HostFsMode::ReadOnly).unwrap();
+        let root = fs.root().to_path_buf();
+
+        assert_eq!(
+            fs.resolve("/sub/file.txt", false),
+            Ok(root.join("sub/file.txt"))
+        );
+        assert_eq!(
+            fs.resolve("sub/./file.txt", false),
+            Ok(root.join("sub/file.txt"))
+        );
+        // `..` stops at the root instead of reaching the host's parent
+        assert_eq!(fs.resolve("/../secret.txt", false), Err(ENOENT));
+        assert_eq!(fs.resolve("sub/../../../secret.txt", false), Err(ENOENT));
+        assert_eq!(fs.resolve("/..", false), Ok(root.clone()));
+        assert_eq!(fs.resolve("", false), Err(ENOENT));
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::symlink;
+            symlink(dir.join("secret.txt"), root.join("escape")).unwrap();
+            symlink(&dir, root.join("sub/up")).unwrap();
+            symlink(dir.join("missing"), root.join("dangling")).unwrap();
+            symlink("file.txt", root.join("sub/alias")).unwrap();
+
+            // Links may point anywhere inside the root but nowhere outside
+            assert_eq!(
+                fs.resolve("sub/alias", false),
+                Ok(root.join("sub/file.txt"))
+            );
+            assert_eq!(fs.resolve("escape", false), Err(EACCES));
+            assert_eq!(fs.resolve("sub/up/secret.txt", false), Err(EACCES));
+            assert_eq!(fs.resolve("sub/up/new.txt", true), Err(EACCES));
+            assert_eq!(fs.resolve("dangling", true), Err(EACCES));
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_read_only() {
+        let dir = scratch("read-only");
+        let mut fs = HostFs::new(dir.join("share"), HostFsMode::ReadOnly).unwrap();
+
+        let fd = fs.open("/sub/file.txt", 0, 0).unwrap();
+        assert_eq!(fd, FIRST_FD);
+        assert_eq!(fs.read(fd, 100).unwrap(), b"shared");
+        assert_eq!(fs.read(fd, 100).unwrap(), b"");
+        assert_eq!(fs.seek(fd, 2, SEEK_SET), Ok(2));
+        assert_eq!(fs.read(fd, 3).unwrap(), b"are");
+        assert_eq!(fs.stat(fd).unwrap().len(), 6);
+        assert!(fs.write(fd, b"x").is_err());
+
+        for flags in [O_WRONLY, O_RDWR, O_CREAT, O_TRUNC] {
+            assert_eq!(fs.open("/sub/file.txt", flags, 0o644), Err(EROFS));
+        }
+        assert_eq!(fs.open("/sub/missing", 0, 0), Err(ENOENT));
+        assert_eq!(fs.close(fd), Ok(()));
+        assert_eq!(fs.close(fd), Err(EBADF));
+        assert_eq!(
+            std::fs::read(dir.join("share/sub/file.txt")).unwrap(),
+            b"shared"
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_read_write() {
+        let dir = scratch("read-write");
+        let mut fs = HostFs::new(dir.join("share"), HostFsMode::ReadWrite).unwrap();
+
+        let fd = fs.open("/new.txt", O_WRONLY | O_CREAT, 0o644).unwrap();
+        assert_eq!(fs.write(fd, b"hello"), Ok(5));
+        let append = fs.open("new.txt", O_WRONLY | O_APPEND, 0).unwrap();
+        assert_eq!(append, fd + 1);
+        assert_eq!(fs.write(append, b" world"), Ok(6));
+        assert_eq!(
+            std::fs::read(dir.join("share/new.txt")).unwrap(),
+            b"hello world"
+        );
+
+        // The lowest free descriptor is reused
+        fs.close(fd).unwrap();
+        let truncated = fs.open("/new.txt", O_RDWR | O_TRUNC, 0).unwrap();
+        assert_eq!(truncated, fd);
+        assert_eq!(fs.stat(truncated).unwrap().len(), 0);
+        assert_eq!(
+            fs.open("/new.txt", O_WRONLY | O_CREAT | O_EXCL, 0o644),
+            Err(EEXIST)
+        );
+        assert_eq!(fs.open("/sub", O_WRONLY, 0), Err(EISDIR));
+
+        // Creating through `..` still lands inside the root
+        let fd = fs
+            .open("/../../outside.txt", O_WRONLY | O_CREAT, 0o644)
+            .unwrap();
+        fs.close(fd).unwrap();
+        assert!(dir.join("share/outside.txt").exists());
+        assert!(!dir.join("outside.txt").exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
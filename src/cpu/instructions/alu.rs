@@ -1,7 +1,11 @@
 //! ALU (A-type) instruction implementations
 //!
 //! This module implements the integer ALU instructions for the IA-64 architecture.
+//!
+//! NaT bits propagate: a result is NaT when any general register source is,
+//! and a compare or bit test with a NaT source writes a false predicate.
 
+use super::change::StateChange;
 use super::{Instruction, InstructionFields, RegisterType};
 use crate::cpu::Cpu;
 use crate::memory::Memory;
@@ -21,10 +25,10 @@ impl Add {
 }
 
 impl Instruction for Add {
-    fn execute(&self, cpu: &mut Cpu, _memory: &mut Memory) -> Result<(), EmulatorError> {
+    fn plan(&self, cpu: &Cpu, _memory: &mut Memory) -> Result<StateChange, EmulatorError> {
         // Check predicate
         if !cpu.get_pr(self.fields.qp as usize)? {
-            return Ok(());
+            return Ok(StateChange::default());
         }
 
         // Get source registers
@@ -37,8 +41,10 @@ impl Instruction for Add {
             }
         };
 
-        let src2 = match self.fields.sources[1] {
-            RegisterType::GR(reg) => cpu.get_gr(reg as usize)?,
+        // adds and addl take an immediate in place of the second register
+        let src2 = match (self.fields.immediate, self.fields.sources.get(1)) {
+            (Some(imm), _) => imm as u64,
+            (None, Some(RegisterType::GR(reg))) => cpu.get_gr(*reg as usize)?,
             _ => {
                 return Err(EmulatorError::ExecutionError(
                     "Invalid source register type".to_string(),
@@ -50,8 +56,10 @@ impl Instruction for Add {
         let result = src1.wrapping_add(src2);
 
         // Write result to destination
+        let nat = self.fields.sources_nat(cpu)?;
+        let mut change = StateChange::default();
         match self.fields.destinations[0] {
-            RegisterType::GR(reg) => cpu.set_gr(reg as usize, result)?,
+            RegisterType::GR(reg) => change.set_gr_nat(reg as usize, result, nat),
             _ => {
                 return Err(EmulatorError::ExecutionError(
                     "Invalid destination register type".to_string(),
@@ -59,7 +67,7 @@ impl Instruction for Add {
             }
         }
 
-        Ok(())
+        Ok(change)
     }
 }
 
@@ -77,10 +85,10 @@ impl Sub {
 }
 
 impl Instruction for Sub {
-    fn execute(&self, cpu: &mut Cpu, _memory: &mut Memory) -> Result<(), EmulatorError> {
+    fn plan(&self, cpu: &Cpu, _memory: &mut Memory) -> Result<StateChange, EmulatorError> {
         // Check predicate
         if !cpu.get_pr(self.fields.qp as usize)? {
-            return Ok(());
+            return Ok(StateChange::default());
         }
 
         // Get source registers
@@ -106,8 +114,10 @@ impl Instruction for Sub {
         let result = src1.wrapping_sub(src2);
 
         // Write result to destination
+        let nat = self.fields.sources_nat(cpu)?;
+        let mut change = StateChange::default();
         match self.fields.destinations[0] {
-            RegisterType::GR(reg) => cpu.set_gr(reg as usize, result)?,
+            RegisterType::GR(reg) => change.set_gr_nat(reg as usize, result, nat),
             _ => {
                 return Err(EmulatorError::ExecutionError(
                     "Invalid destination register type".to_string(),
@@ -115,7 +125,7 @@ impl Instruction for Sub {
             }
         }
 
-        Ok(())
+        Ok(change)
     }
 }
 
@@ -133,10 +143,10 @@ impl And {
 }
 
 impl Instruction for And {
-    fn execute(&self, cpu: &mut Cpu, _memory: &mut Memory) -> Result<(), EmulatorError> {
+    fn plan(&self, cpu: &Cpu, _memory: &mut Memory) -> Result<StateChange, EmulatorError> {
         // Check predicate
         if !cpu.get_pr(self.fields.qp as usize)? {
-            return Ok(());
+            return Ok(StateChange::default());
         }
 
         // Get source registers
@@ -162,8 +172,10 @@ impl Instruction for And {
         let result = src1 & src2;
 
         // Write result to destination
+        let nat = self.fields.sources_nat(cpu)?;
+        let mut change = StateChange::default();
         match self.fields.destinations[0] {
-            RegisterType::GR(reg) => cpu.set_gr(reg as usize, result)?,
+            RegisterType::GR(reg) => change.set_gr_nat(reg as usize, result, nat),
             _ => {
                 return Err(EmulatorError::ExecutionError(
                     "Invalid destination register type".to_string(),
@@ -171,7 +183,7 @@ impl Instruction for And {
             }
         }
 
-        Ok(())
+        Ok(change)
     }
 }
 
@@ -189,10 +201,10 @@ impl Or {
 }
 
 impl Instruction for Or {
-    fn execute(&self, cpu: &mut Cpu, _memory: &mut Memory) -> Result<(), EmulatorError> {
+    fn plan(&self, cpu: &Cpu, _memory: &mut Memory) -> Result<StateChange, EmulatorError> {
         // Check predicate
         if !cpu.get_pr(self.fields.qp as usize)? {
-            return Ok(());
+            return Ok(StateChange::default());
         }
 
         // Get source registers
@@ -218,8 +230,10 @@ impl Instruction for Or {
         let result = src1 | src2;
 
         // Write result to destination
+        let nat = self.fields.sources_nat(cpu)?;
+        let mut change = StateChange::default();
         match self.fields.destinations[0] {
-            RegisterType::GR(reg) => cpu.set_gr(reg as usize, result)?,
+            RegisterType::GR(reg) => change.set_gr_nat(reg as usize, result, nat),
             _ => {
                 return Err(EmulatorError::ExecutionError(
                     "Invalid destination register type".to_string(),
@@ -227,7 +241,7 @@ impl Instruction for Or {
             }
         }
 
-        Ok(())
+        Ok(change)
     }
 }
 
@@ -245,10 +259,10 @@ impl Xor {
 }
 
 impl Instruction for Xor {
-    fn execute(&self, cpu: &mut Cpu, _memory: &mut Memory) -> Result<(), EmulatorError> {
+    fn plan(&self, cpu: &Cpu, _memory: &mut Memory) -> Result<StateChange, EmulatorError> {
         // Check predicate
         if !cpu.get_pr(self.fields.qp as usize)? {
-            return Ok(());
+            return Ok(StateChange::default());
         }
 
         // Get source registers
@@ -274,8 +288,10 @@ impl Instruction for Xor {
         let result = src1 ^ src2;
 
         // Write result to destination
+        let nat = self.fields.sources_nat(cpu)?;
+        let mut change = StateChange::default();
         match self.fields.destinations[0] {
-            RegisterType::GR(reg) => cpu.set_gr(reg as usize, result)?,
+            RegisterType::GR(reg) => change.set_gr_nat(reg as usize, result, nat),
             _ => {
                 return Err(EmulatorError::ExecutionError(
                     "Invalid destination register type".to_string(),
@@ -283,7 +299,7 @@ impl Instruction for Xor {
             }
         }
 
-        Ok(())
+        Ok(change)
     }
 }
 
@@ -312,48 +328,140 @@ pub enum CompareType {
     GreaterEqualU,
 }
 
+/// How a compare writes its two target predicates (the ctype completer)
+///
+/// The parallel types write only for one outcome of the relation, so that
+/// several compares in one instruction group can target the same
+/// predicates to build an AND or OR of their relations.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompareCtype {
+    /// p1 gets the relation and p2 its complement
+    Normal,
+    /// As normal, and both are cleared when the qualifying predicate is
+    /// false (.unc)
+    Unc,
+    /// Both are cleared when the relation is false (.and)
+    And,
+    /// Both are set when the relation is true (.or)
+    Or,
+    /// p1 is set and p2 cleared when the relation is true (.or.andcm)
+    OrAndcm,
+}
+
 /// Compare instruction
+///
+/// Compares its two sources, or an immediate and its one source, and
+/// writes the two predicates in its destinations as its ctype says. A NaT
+/// source clears both targets of the normal and `.and` types and leaves
+/// those of the `.or` types alone. Writes to p0 are ignored.
+///
+/// The `cmp4` forms compare the low 32 bits only, sign-extended for the
+/// signed relations and zero-extended for the unsigned ones.
 #[derive(Debug)]
 pub struct Compare {
     fields: InstructionFields,
-    ctype: CompareType,
+    relation: CompareType,
+    ctype: CompareCtype,
+    word: bool,
 }
 
 impl Compare {
-    /// Create new compare instruction
-    pub fn new(fields: InstructionFields, ctype: CompareType) -> Self {
-        Self { fields, ctype }
+    /// Create new compare instruction of the normal type
+    pub fn new(fields: InstructionFields, relation: CompareType) -> Self {
+        Self::with_ctype(fields, relation, CompareCtype::Normal)
+    }
+
+    /// Create new compare instruction of the given type
+    pub fn with_ctype(
+        fields: InstructionFields,
+        relation: CompareType,
+        ctype: CompareCtype,
+    ) -> Self {
+        Self {
+            fields,
+            relation,
+            ctype,
+            word: false,
+        }
+    }
+
+    /// Compare the low 32 bits of the operands only, as `cmp4` does
+    pub fn cmp4(mut self) -> Self {
+        self.word = true;
+        self
+    }
+
+    /// Target predicates p1 and p2
+    fn targets(&self) -> Result<(usize, usize), EmulatorError> {
+        match self.fields.destinations[..] {
+            [RegisterType::PR(p1), RegisterType::PR(p2)] => Ok((p1 as usize, p2 as usize)),
+            _ => Err(EmulatorError::ExecutionError(
+                "Invalid destination register type".to_string(),
+            )),
+        }
     }
 }
 
 impl Instruction for Compare {
-    fn execute(&self, cpu: &mut Cpu, _memory: &mut Memory) -> Result<(), EmulatorError> {
-        // Check predicate
-        if !cpu.get_pr(self.fields.qp as usize)? {
-            return Ok(());
+    fn plan(&self, cpu: &Cpu, _memory: &mut Memory) -> Result<StateChange, EmulatorError> {
+        let (p1, p2) = self.targets()?;
+        if p1 == p2 {
+            return Err(EmulatorError::ExecutionError(
+                "Illegal operation: compare targets the same predicate twice".to_string(),
+            ));
         }
-
-        // Get source values
-        let src1 = match self.fields.sources[0] {
-            RegisterType::GR(reg) => cpu.get_gr(reg as usize)?,
-            _ => {
-                return Err(EmulatorError::ExecutionError(
-                    "Invalid source register type".to_string(),
-                ))
+        let mut change = StateChange::default();
+        let write = |change: &mut StateChange, values: (bool, bool)| {
+            for (pr, value) in [(p1, values.0), (p2, values.1)] {
+                if pr != 0 {
+                    change.set_pr(pr, value);
+                }
             }
         };
 
-        let src2 = match self.fields.sources[1] {
-            RegisterType::GR(reg) => cpu.get_gr(reg as usize)?,
-            _ => {
-                return Err(EmulatorError::ExecutionError(
-                    "Invalid source register type".to_string(),
-                ))
+        // Check predicate; .unc clears both targets when it is false
+        if !cpu.get_pr(self.fields.qp as usize)? {
+            if self.ctype == CompareCtype::Unc {
+                write(&mut change, (false, false));
+            }
+            return Ok(change);
+        }
+
+        // Get source values; the immediate forms compare the immediate
+        // with the one source
+        let mut values = Vec::with_capacity(2);
+        if let Some(imm) = self.fields.immediate {
+            values.push(imm as u64);
+        }
+        for source in &self.fields.sources {
+            match *source {
+                RegisterType::GR(reg) => values.push(cpu.get_gr(reg as usize)?),
+                _ => {
+                    return Err(EmulatorError::ExecutionError(
+                        "Invalid source register type".to_string(),
+                    ))
+                }
             }
+        }
+        let [mut src1, mut src2] = values[..] else {
+            return Err(EmulatorError::ExecutionError(
+                "Compare needs two operands".to_string(),
+            ));
         };
+        if self.word {
+            let extend = |value: u64| match self.relation {
+                CompareType::LessThanU
+                | CompareType::LessEqualU
+                | CompareType::GreaterThanU
+                | CompareType::GreaterEqualU => value as u32 as u64,
+                _ => value as u32 as i32 as i64 as u64,
+            };
+            src1 = extend(src1);
+            src2 = extend(src2);
+        }
 
         // Evaluate condition
-        let result = match self.ctype {
+        let result = match self.relation {
             CompareType::Equal => src1 == src2,
             CompareType::NotEqual => src1 != src2,
             CompareType::LessThan => (src1 as i64) < (src2 as i64),
@@ -366,17 +474,18 @@ impl Instruction for Compare {
             CompareType::GreaterEqualU => src1 >= src2,
         };
 
-        // Set destination predicate register
-        match self.fields.destinations[0] {
-            RegisterType::PR(reg) => cpu.set_pr(reg as usize, result)?,
-            _ => {
-                return Err(EmulatorError::ExecutionError(
-                    "Invalid destination register type".to_string(),
-                ))
-            }
+        // Set destination predicate registers
+        let nat = self.fields.sources_nat(cpu)?;
+        match self.ctype {
+            CompareCtype::Normal | CompareCtype::Unc if nat => write(&mut change, (false, false)),
+            CompareCtype::Normal | CompareCtype::Unc => write(&mut change, (result, !result)),
+            CompareCtype::And if nat || !result => write(&mut change, (false, false)),
+            CompareCtype::Or if !nat && result => write(&mut change, (true, true)),
+            CompareCtype::OrAndcm if !nat && result => write(&mut change, (true, false)),
+            _ => {}
         }
 
-        Ok(())
+        Ok(change)
     }
 }
 
@@ -394,10 +503,10 @@ impl TestBit {
 }
 
 impl Instruction for TestBit {
-    fn execute(&self, cpu: &mut Cpu, _memory: &mut Memory) -> Result<(), EmulatorError> {
+    fn plan(&self, cpu: &Cpu, _memory: &mut Memory) -> Result<StateChange, EmulatorError> {
         // Check predicate
         if !cpu.get_pr(self.fields.qp as usize)? {
-            return Ok(());
+            return Ok(StateChange::default());
         }
 
         // Get source value and bit position
@@ -427,8 +536,10 @@ impl Instruction for TestBit {
         };
 
         // Set destination predicate register
+        let nat = self.fields.sources_nat(cpu)?;
+        let mut change = StateChange::default();
         match self.fields.destinations[0] {
-            RegisterType::PR(reg) => cpu.set_pr(reg as usize, result)?,
+            RegisterType::PR(reg) => change.set_pr(reg as usize, result && !nat),
             _ => {
                 return Err(EmulatorError::ExecutionError(
                     "Invalid destination register type".to_string(),
@@ -436,7 +547,7 @@ impl Instruction for TestBit {
             }
         }
 
-        Ok(())
+        Ok(change)
     }
 }
 
@@ -466,10 +577,10 @@ impl Shift {
 }
 
 impl Instruction for Shift {
-    fn execute(&self, cpu: &mut Cpu, _memory: &mut Memory) -> Result<(), EmulatorError> {
+    fn plan(&self, cpu: &Cpu, _memory: &mut Memory) -> Result<StateChange, EmulatorError> {
         // Check predicate
         if !cpu.get_pr(self.fields.qp as usize)? {
-            return Ok(());
+            return Ok(StateChange::default());
         }
 
         // Get source value and shift amount
@@ -499,8 +610,10 @@ impl Instruction for Shift {
         };
 
         // Write result to destination
+        let nat = self.fields.sources_nat(cpu)?;
+        let mut change = StateChange::default();
         match self.fields.destinations[0] {
-            RegisterType::GR(reg) => cpu.set_gr(reg as usize, result)?,
+            RegisterType::GR(reg) => change.set_gr_nat(reg as usize, result, nat),
             _ => {
                 return Err(EmulatorError::ExecutionError(
                     "Invalid destination register type".to_string(),
@@ -508,7 +621,7 @@ impl Instruction for Shift {
             }
         }
 
-        Ok(())
+        Ok(change)
     }
 }
 
@@ -526,10 +639,10 @@ impl Deposit {
 }
 
 impl Instruction for Deposit {
-    fn execute(&self, cpu: &mut Cpu, _memory: &mut Memory) -> Result<(), EmulatorError> {
+    fn plan(&self, cpu: &Cpu, _memory: &mut Memory) -> Result<StateChange, EmulatorError> {
         // Check predicate
         if !cpu.get_pr(self.fields.qp as usize)? {
-            return Ok(());
+            return Ok(StateChange::default());
         }
 
         // Get target and source values
@@ -563,8 +676,10 @@ impl Instruction for Deposit {
         let result = (target & !field_mask) | ((source & ((1u64 << len) - 1)) << pos);
 
         // Write result to destination
+        let nat = self.fields.sources_nat(cpu)?;
+        let mut change = StateChange::default();
         match self.fields.destinations[0] {
-            RegisterType::GR(reg) => cpu.set_gr(reg as usize, result)?,
+            RegisterType::GR(reg) => change.set_gr_nat(reg as usize, result, nat),
             _ => {
                 return Err(EmulatorError::ExecutionError(
                     "Invalid destination register type".to_string(),
@@ -572,7 +687,7 @@ impl Instruction for Deposit {
             }
         }
 
-        Ok(())
+        Ok(change)
     }
 }
 
@@ -590,10 +705,10 @@ impl Extract {
 }
 
 impl Instruction for Extract {
-    fn execute(&self, cpu: &mut Cpu, _memory: &mut Memory) -> Result<(), EmulatorError> {
+    fn plan(&self, cpu: &Cpu, _memory: &mut Memory) -> Result<StateChange, EmulatorError> {
         // Check predicate
         if !cpu.get_pr(self.fields.qp as usize)? {
-            return Ok(());
+            return Ok(StateChange::default());
         }
 
         // Get source value
@@ -615,8 +730,10 @@ impl Instruction for Extract {
         let result = (source >> pos) & ((1u64 << len) - 1);
 
         // Write result to destination
+        let nat = self.fields.sources_nat(cpu)?;
+        let mut change = StateChange::default();
         match self.fields.destinations[0] {
-            RegisterType::GR(reg) => cpu.set_gr(reg as usize, result)?,
+            RegisterType::GR(reg) => change.set_gr_nat(reg as usize, result, nat),
             _ => {
                 return Err(EmulatorError::ExecutionError(
                     "Invalid destination register type".to_string(),
@@ -624,7 +741,7 @@ impl Instruction for Extract {
             }
         }
 
-        Ok(())
+        Ok(change)
     }
 }
 
@@ -642,10 +759,10 @@ impl PopCount {
 }
 
 impl Instruction for PopCount {
-    fn execute(&self, cpu: &mut Cpu, _memory: &mut Memory) -> Result<(), EmulatorError> {
+    fn plan(&self, cpu: &Cpu, _memory: &mut Memory) -> Result<StateChange, EmulatorError> {
         // Check predicate
         if !cpu.get_pr(self.fields.qp as usize)? {
-            return Ok(());
+            return Ok(StateChange::default());
         }
 
         // Get source value
@@ -662,8 +779,10 @@ impl Instruction for PopCount {
         let result = source.count_ones() as u64;
 
         // Write result to destination
+        let nat = self.fields.sources_nat(cpu)?;
+        let mut change = StateChange::default();
         match self.fields.destinations[0] {
-            RegisterType::GR(reg) => cpu.set_gr(reg as usize, result)?,
+            RegisterType::GR(reg) => change.set_gr_nat(reg as usize, result, nat),
             _ => {
                 return Err(EmulatorError::ExecutionError(
                     "Invalid destination register type".to_string(),
@@ -671,7 +790,7 @@ impl Instruction for PopCount {
             }
         }
 
-        Ok(())
+        Ok(change)
     }
 }
 
@@ -701,10 +820,10 @@ impl ParallelAdd {
 }
 
 impl Instruction for ParallelAdd {
-    fn execute(&self, cpu: &mut Cpu, _memory: &mut Memory) -> Result<(), EmulatorError> {
+    fn plan(&self, cpu: &Cpu, _memory: &mut Memory) -> Result<StateChange, EmulatorError> {
         // Check predicate
         if !cpu.get_pr(self.fields.qp as usize)? {
-            return Ok(());
+            return Ok(StateChange::default());
         }
 
         // Get source values
@@ -761,8 +880,10 @@ impl Instruction for ParallelAdd {
         };
 
         // Write result to destination
+        let nat = self.fields.sources_nat(cpu)?;
+        let mut change = StateChange::default();
         match self.fields.destinations[0] {
-            RegisterType::GR(reg) => cpu.set_gr(reg as usize, result)?,
+            RegisterType::GR(reg) => change.set_gr_nat(reg as usize, result, nat),
             _ => {
                 return Err(EmulatorError::ExecutionError(
                     "Invalid destination register type".to_string(),
@@ -770,7 +891,7 @@ impl Instruction for ParallelAdd {
             }
         }
 
-        Ok(())
+        Ok(change)
     }
 }
 
@@ -789,10 +910,10 @@ impl SaturatingAdd {
 }
 
 impl Instruction for SaturatingAdd {
-    fn execute(&self, cpu: &mut Cpu, _memory: &mut Memory) -> Result<(), EmulatorError> {
+    fn plan(&self, cpu: &Cpu, _memory: &mut Memory) -> Result<StateChange, EmulatorError> {
         // Check predicate
         if !cpu.get_pr(self.fields.qp as usize)? {
-            return Ok(());
+            return Ok(StateChange::default());
         }
 
         // Get source values
@@ -825,8 +946,10 @@ impl Instruction for SaturatingAdd {
         };
 
         // Write result to destination
+        let nat = self.fields.sources_nat(cpu)?;
+        let mut change = StateChange::default();
         match self.fields.destinations[0] {
-            RegisterType::GR(reg) => cpu.set_gr(reg as usize, result)?,
+            RegisterType::GR(reg) => change.set_gr_nat(reg as usize, result, nat),
             _ => {
                 return Err(EmulatorError::ExecutionError(
                     "Invalid destination register type".to_string(),
@@ -834,7 +957,7 @@ impl Instruction for SaturatingAdd {
             }
         }
 
-        Ok(())
+        Ok(change)
     }
 }
 
@@ -852,10 +975,10 @@ impl RotateMask {
 }
 
 impl Instruction for RotateMask {
-    fn execute(&self, cpu: &mut Cpu, _memory: &mut Memory) -> Result<(), EmulatorError> {
+    fn plan(&self, cpu: &Cpu, _memory: &mut Memory) -> Result<StateChange, EmulatorError> {
         // Check predicate
         if !cpu.get_pr(self.fields.qp as usize)? {
-            return Ok(());
+            return Ok(StateChange::default());
         }
 
         // Get source value and rotation amount
@@ -885,8 +1008,10 @@ impl Instruction for RotateMask {
         let result = rotated & mask;
 
         // Write result to destination
+        let nat = self.fields.sources_nat(cpu)?;
+        let mut change = StateChange::default();
         match self.fields.destinations[0] {
-            RegisterType::GR(reg) => cpu.set_gr(reg as usize, result)?,
+            RegisterType::GR(reg) => change.set_gr_nat(reg as usize, result, nat),
             _ => {
                 return Err(EmulatorError::ExecutionError(
                     "Invalid destination register type".to_string(),
@@ -894,7 +1019,7 @@ impl Instruction for RotateMask {
             }
         }
 
-        Ok(())
+        Ok(change)
     }
 }
 
@@ -926,10 +1051,10 @@ impl MinMax {
 }
 
 impl Instruction for MinMax {
-    fn execute(&self, cpu: &mut Cpu, _memory: &mut Memory) -> Result<(), EmulatorError> {
+    fn plan(&self, cpu: &Cpu, _memory: &mut Memory) -> Result<StateChange, EmulatorError> {
         // Check predicate
         if !cpu.get_pr(self.fields.qp as usize)? {
-            return Ok(());
+            return Ok(StateChange::default());
         }
 
         // Get source values
@@ -968,8 +1093,10 @@ impl Instruction for MinMax {
         };
 
         // Write result to destination
+        let nat = self.fields.sources_nat(cpu)?;
+        let mut change = StateChange::default();
         match self.fields.destinations[0] {
-            RegisterType::GR(reg) => cpu.set_gr(reg as usize, result)?,
+            RegisterType::GR(reg) => change.set_gr_nat(reg as usize, result, nat),
             _ => {
                 return Err(EmulatorError::ExecutionError(
                     "Invalid destination register type".to_string(),
@@ -977,7 +1104,7 @@ impl Instruction for MinMax {
             }
         }
 
-        Ok(())
+        Ok(change)
     }
 }
 
@@ -1012,10 +1139,10 @@ impl Extend {
 }
 
 impl Instruction for Extend {
-    fn execute(&self, cpu: &mut Cpu, _memory: &mut Memory) -> Result<(), EmulatorError> {
+    fn plan(&self, cpu: &Cpu, _memory: &mut Memory) -> Result<StateChange, EmulatorError> {
         // Check predicate
         if !cpu.get_pr(self.fields.qp as usize)? {
-            return Ok(());
+            return Ok(StateChange::default());
         }
 
         // Get source value
@@ -1048,8 +1175,10 @@ impl Instruction for Extend {
         };
 
         // Write result to destination
+        let nat = self.fields.sources_nat(cpu)?;
+        let mut change = StateChange::default();
         match self.fields.destinations[0] {
-            RegisterType::GR(reg) => cpu.set_gr(reg as usize, result)?,
+            RegisterType::GR(reg) => change.set_gr_nat(reg as usize, result, nat),
             _ => {
                 return Err(EmulatorError::ExecutionError(
                     "Invalid destination register type".to_string(),
@@ -1057,7 +1186,7 @@ impl Instruction for Extend {
             }
         }
 
-        Ok(())
+        Ok(change)
     }
 }
 
@@ -1075,10 +1204,10 @@ impl Merge {
 }
 
 impl Instruction for Merge {
-    fn execute(&self, cpu: &mut Cpu, _memory: &mut Memory) -> Result<(), EmulatorError> {
+    fn plan(&self, cpu: &Cpu, _memory: &mut Memory) -> Result<StateChange, EmulatorError> {
         // Check predicate
         if !cpu.get_pr(self.fields.qp as usize)? {
-            return Ok(());
+            return Ok(StateChange::default());
         }
 
         // Get source values
@@ -1107,8 +1236,10 @@ impl Instruction for Merge {
         let result = (src1 & mask) | (src2 & !mask);
 
         // Write result to destination
+        let nat = self.fields.sources_nat(cpu)?;
+        let mut change = StateChange::default();
         match self.fields.destinations[0] {
-            RegisterType::GR(reg) => cpu.set_gr(reg as usize, result)?,
+            RegisterType::GR(reg) => change.set_gr_nat(reg as usize, result, nat),
             _ => {
                 return Err(EmulatorError::ExecutionError(
                     "Invalid destination register type".to_string(),
@@ -1116,7 +1247,7 @@ impl Instruction for Merge {
             }
         }
 
-        Ok(())
+        Ok(change)
     }
 }
 
@@ -1218,7 +1349,7 @@ mod tests {
     #[test]
     fn test_compare() {
         let (mut cpu, mut memory, mut fields) = setup_test();
-        fields.destinations = vec![RegisterType::PR(1)];
+        fields.destinations = vec![RegisterType::PR(1), RegisterType::PR(2)];
 
         // Test equal comparison
         let cmp_eq = Compare::new(fields.clone(), CompareType::Equal);
@@ -1246,6 +1377,109 @@ mod tests {
         cpu.set_gr(2, 10).unwrap();
         cmp_ltu.execute(&mut cpu, &mut memory).unwrap();
         assert!(cpu.get_pr(1).unwrap());
+        assert!(!cpu.get_pr(2).unwrap());
+
+        // Both targets the same predicate
+        fields.destinations = vec![RegisterType::PR(1), RegisterType::PR(1)];
+        assert!(Compare::new(fields, CompareType::Equal)
+            .execute(&mut cpu, &mut memory)
+            .is_err());
+    }
+
+    #[test]
+    fn test_compare_word() {
+        let (mut cpu, mut memory, mut fields) = setup_test();
+        fields.destinations = vec![RegisterType::PR(1), RegisterType::PR(2)];
+        let compare = |relation| Compare::new(fields.clone(), relation).cmp4();
+
+        // The high halves are ignored
+        cpu.set_gr(1, 0xAAAA_0000_0000_0005).unwrap();
+        cpu.set_gr(2, 0x5555_0000_0000_0005).unwrap();
+        compare(CompareType::Equal)
+            .execute(&mut cpu, &mut memory)
+            .unwrap();
+        assert!(cpu.get_pr(1).unwrap() && !cpu.get_pr(2).unwrap());
+
+        // 0xFFFFFFFF is -1 to the signed relations and 2^32-1 to the
+        // unsigned ones
+        cpu.set_gr(1, 0xFFFF_FFFF).unwrap();
+        cpu.set_gr(2, 1).unwrap();
+        compare(CompareType::LessThan)
+            .execute(&mut cpu, &mut memory)
+            .unwrap();
+        assert!(cpu.get_pr(1).unwrap());
+        compare(CompareType::LessThanU)
+            .execute(&mut cpu, &mut memory)
+            .unwrap();
+        assert!(!cpu.get_pr(1).unwrap() && cpu.get_pr(2).unwrap());
+
+        // cmp compares all 64 bits
+        Compare::new(fields, CompareType::LessThan)
+            .execute(&mut cpu, &mut memory)
+            .unwrap();
+        assert!(!cpu.get_pr(1).unwrap());
+    }
+
+    #[test]
+    fn test_compare_types() {
+        let (mut cpu, mut memory, mut fields) = setup_test();
+        fields.destinations = vec![RegisterType::PR(1), RegisterType::PR(2)];
+        let compare = |ctype| Compare::with_ctype(fields.clone(), CompareType::Equal, ctype);
+        let pair = |cpu: &Cpu| (cpu.get_pr(1).unwrap(), cpu.get_pr(2).unwrap());
+        cpu.set_gr(1, 5).unwrap();
+        cpu.set_gr(2, 6).unwrap();
+
+        // .and clears both on a false relation and leaves them otherwise
+        cpu.set_pr(1, true).unwrap();
+        cpu.set_pr(2, true).unwrap();
+        compare(CompareCtype::And)
+            .execute(&mut cpu, &mut memory)
+            .unwrap();
+        assert_eq!(pair(&cpu), (false, false));
+
+        // .or sets both on a true relation only
+        compare(CompareCtype::Or)
+            .execute(&mut cpu, &mut memory)
+            .unwrap();
+        assert_eq!(pair(&cpu), (false, false));
+        cpu.set_gr(2, 5).unwrap();
+        compare(CompareCtype::Or)
+            .execute(&mut cpu, &mut memory)
+            .unwrap();
+        assert_eq!(pair(&cpu), (true, true));
+        compare(CompareCtype::And)
+            .execute(&mut cpu, &mut memory)
+            .unwrap();
+        assert_eq!(pair(&cpu), (true, true));
+
+        // .or.andcm sets p1 and clears p2
+        compare(CompareCtype::OrAndcm)
+            .execute(&mut cpu, &mut memory)
+            .unwrap();
+        assert_eq!(pair(&cpu), (true, false));
+
+        // A NaT source leaves the .or types alone
+        cpu.set_nat(2, true).unwrap();
+        compare(CompareCtype::Or)
+            .execute(&mut cpu, &mut memory)
+            .unwrap();
+        assert_eq!(pair(&cpu), (true, false));
+        compare(CompareCtype::And)
+            .execute(&mut cpu, &mut memory)
+            .unwrap();
+        assert_eq!(pair(&cpu), (false, false));
+        cpu.set_nat(2, false).unwrap();
+
+        // With a false qualifying predicate only .unc writes, clearing both
+        cpu.set_pr(1, true).unwrap();
+        fields.qp = 3;
+        let unc = Compare::with_ctype(fields.clone(), CompareType::Equal, CompareCtype::Unc);
+        Compare::new(fields.clone(), CompareType::NotEqual)
+            .execute(&mut cpu, &mut memory)
+            .unwrap();
+        assert_eq!(pair(&cpu), (true, false));
+        unc.execute(&mut cpu, &mut memory).unwrap();
+        assert_eq!(pair(&cpu), (false, false));
     }
 
     #[test]
@@ -1273,6 +1507,43 @@ mod tests {
         assert!(!cpu.get_pr(1).unwrap());
     }
 
+    #[test]
+    fn test_nat_propagation() {
+        let (mut cpu, mut memory, mut fields) = setup_test();
+        let add = Add::new(fields.clone());
+
+        // A NaT source makes the result NaT
+        cpu.set_gr(1, 5).unwrap();
+        cpu.set_gr(2, 3).unwrap();
+        cpu.set_nat(2, true).unwrap();
+        add.execute(&mut cpu, &mut memory).unwrap();
+        assert!(cpu.get_nat(3).unwrap());
+
+        // and keeps propagating through dependent operations
+        fields.sources = vec![RegisterType::GR(3), RegisterType::GR(1)];
+        fields.destinations = vec![RegisterType::GR(4)];
+        Xor::new(fields.clone())
+            .execute(&mut cpu, &mut memory)
+            .unwrap();
+        assert!(cpu.get_nat(4).unwrap());
+
+        // A compare with a NaT source clears both predicates
+        fields.destinations = vec![RegisterType::PR(1), RegisterType::PR(2)];
+        cpu.set_pr(1, true).unwrap();
+        cpu.set_pr(2, true).unwrap();
+        Compare::new(fields, CompareType::NotEqual)
+            .execute(&mut cpu, &mut memory)
+            .unwrap();
+        assert!(!cpu.get_pr(1).unwrap());
+        assert!(!cpu.get_pr(2).unwrap());
+
+        // Clean sources clear the NaT bit
+        cpu.set_gr(2, 3).unwrap();
+        add.execute(&mut cpu, &mut memory).unwrap();
+        assert!(!cpu.get_nat(3).unwrap());
+        assert_eq!(cpu.get_gr(3).unwrap(), 8);
+    }
+
     #[test]
     fn test_predicated_execution() {
         let (mut cpu, mut memory, fields) = setup_test();
@@ -1300,7 +1571,7 @@ mod tests {
 
     #[test]
     fn test_shift() {
-        let (mut cpu, mut memory, mut fields) = setup_test();
+        let (mut cpu, mut memory, fields) = setup_test();
 
         // Test left shift
         let shift_left = Shift::new(fields.clone(), ShiftType::Left);
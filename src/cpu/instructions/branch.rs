@@ -2,8 +2,12 @@
 //!
 //! This module implements the branch instructions for the IA-64 architecture.
 
+use super::change::{FrameChange, StateChange};
+use super::system::move_source;
 use super::{Instruction, InstructionFields, RegisterType};
+use crate::cpu::registers::ar::AR;
 use crate::cpu::Cpu;
+use crate::decoder::completers::Completers;
 use crate::memory::Memory;
 use crate::EmulatorError;
 
@@ -101,31 +105,32 @@ impl Branch {
     pub fn from_decoded(
         fields: InstructionFields,
         branch_type: BranchType,
-        completers: Option<Vec<String>>,
+        completers: Completers,
     ) -> Self {
-        // Default values
-        let mut prediction = BranchPrediction::StaticTake;
-        let mut rse_behavior = BranchRSE::Normal;
-        let mut importance = BranchImportance::Normal;
-        let mut registers = BranchRegisters::Few;
-
-        // Parse completers if present
-        if let Some(completers) = completers {
-            for completer in completers {
-                match completer.as_str() {
-                    "sptk" => prediction = BranchPrediction::StaticTake,
-                    "spnt" => prediction = BranchPrediction::StaticNotTaken,
-                    "dptk" => prediction = BranchPrediction::DynamicTake,
-                    "dpnt" => prediction = BranchPrediction::DynamicNotTaken,
-                    "clr" => rse_behavior = BranchRSE::Clear,
-                    "imp" => importance = BranchImportance::Important,
-                    "few" => registers = BranchRegisters::Few,
-                    "many" => registers = BranchRegisters::Many,
-                    "" => (), // Skip empty completers
-                    _ => (),  // Ignore unknown completers
-                }
-            }
-        }
+        let prediction = if completers.contains(Completers::DPNT) {
+            BranchPrediction::DynamicNotTaken
+        } else if completers.contains(Completers::DPTK) {
+            BranchPrediction::DynamicTake
+        } else if completers.contains(Completers::SPNT) {
+            BranchPrediction::StaticNotTaken
+        } else {
+            BranchPrediction::StaticTake
+        };
+        let rse_behavior = if completers.contains(Completers::CLR) {
+            BranchRSE::Clear
+        } else {
+            BranchRSE::Normal
+        };
+        let importance = if completers.contains(Completers::IMP) {
+            BranchImportance::Important
+        } else {
+            BranchImportance::Normal
+        };
+        let registers = if completers.contains(Completers::MANY) {
+            BranchRegisters::Many
+        } else {
+            BranchRegisters::Few
+        };
 
         Self::new(
             fields,
@@ -279,12 +284,14 @@ impl Branch {
 }
 
 impl Instruction for Branch {
-    fn execute(&self, cpu: &mut Cpu, _memory: &mut Memory) -> Result<(), EmulatorError> {
+    fn plan(&self, cpu: &Cpu, _memory: &mut Memory) -> Result<StateChange, EmulatorError> {
         // Check predicate
         if !cpu.get_pr(self.fields.qp as usize)? {
-            return Ok(());
+            return Ok(StateChange::default());
         }
 
+        let mut change = StateChange::default();
+
         // Check branch condition
         if self.check_condition(cpu)? {
             // Calculate target address
@@ -297,7 +304,7 @@ impl Instruction for Branch {
 
             // Update branch register if specified
             if let Some(RegisterType::BR(reg)) = self.fields.destinations.first() {
-                cpu.set_br(*reg as usize, cpu.ip.wrapping_add(16))?; // Save return address
+                change.set_br(*reg as usize, cpu.ip.wrapping_add(16)); // Save return address
             }
 
             // Update branch prediction information
@@ -321,7 +328,7 @@ impl Instruction for Branch {
             }
 
             // Update IP
-            cpu.ip = target;
+            change.branch(target);
 
             // Handle branch importance
             if self.importance == BranchImportance::Important {
@@ -329,7 +336,259 @@ impl Instruction for Branch {
             }
         }
 
-        Ok(())
+        Ok(change)
+    }
+}
+
+/// Move to branch register instruction (mov b1=r2)
+#[derive(Debug)]
+pub struct MoveToBr {
+    fields: InstructionFields,
+}
+
+impl MoveToBr {
+    /// Create new mov to br instruction
+    pub fn new(fields: InstructionFields) -> Self {
+        Self { fields }
+    }
+}
+
+impl Instruction for MoveToBr {
+    fn plan(&self, cpu: &Cpu, _memory: &mut Memory) -> Result<StateChange, EmulatorError> {
+        let mut change = StateChange::default();
+
+        // Check predicate
+        if !cpu.get_pr(self.fields.qp as usize)? {
+            return Ok(change);
+        }
+
+        let source = self.fields.sources[0].get_reg_num() as u8;
+        let value = move_source(cpu, source, "mov to br")?;
+        change.set_br(self.fields.destinations[0].get_reg_num(), value);
+        Ok(change)
+    }
+}
+
+/// Move from branch register instruction (mov r1=b2)
+#[derive(Debug)]
+pub struct MoveFromBr {
+    fields: InstructionFields,
+}
+
+impl MoveFromBr {
+    /// Create new mov from br instruction
+    pub fn new(fields: InstructionFields) -> Self {
+        Self { fields }
+    }
+}
+
+impl Instruction for MoveFromBr {
+    fn plan(&self, cpu: &Cpu, _memory: &mut Memory) -> Result<StateChange, EmulatorError> {
+        let mut change = StateChange::default();
+
+        // Check predicate
+        if !cpu.get_pr(self.fields.qp as usize)? {
+            return Ok(change);
+        }
+
+        let value = cpu.get_br(self.fields.sources[0].get_reg_num())?;
+        change.set_gr(self.fields.destinations[0].get_reg_num(), value);
+        Ok(change)
+    }
+}
+
+/// Call instruction (br.call and brl.call)
+///
+/// The address of the next bundle goes to b1, the caller's locals are
+/// pushed onto the register stack and its outputs become the callee's
+/// frame.
+#[derive(Debug)]
+pub struct Call {
+    fields: InstructionFields,
+}
+
+impl Call {
+    /// Create new call instruction, with b1 as the destination and either
+    /// the offset of the target as the immediate or b2 as the source
+    pub fn new(fields: InstructionFields) -> Self {
+        Self { fields }
+    }
+}
+
+impl Instruction for Call {
+    fn plan(&self, cpu: &Cpu, _memory: &mut Memory) -> Result<StateChange, EmulatorError> {
+        let mut change = StateChange::default();
+
+        // Check predicate
+        if !cpu.get_pr(self.fields.qp as usize)? {
+            return Ok(change);
+        }
+
+        let target = match self.fields.immediate {
+            Some(offset) => cpu.ip.wrapping_add(offset as u64),
+            None => cpu.get_br(self.fields.sources[0].get_reg_num())? & !0xF,
+        };
+        change.set_br(
+            self.fields.destinations[0].get_reg_num(),
+            cpu.ip.wrapping_add(16),
+        );
+        change.change_frame(FrameChange::Call { target });
+        change.branch(target);
+        Ok(change)
+    }
+}
+
+/// Return instruction (br.ret)
+///
+/// The caller's frame comes back from AR.PFS and execution continues at
+/// the bundle in b2.
+#[derive(Debug)]
+pub struct Return {
+    fields: InstructionFields,
+}
+
+impl Return {
+    /// Create new return instruction, with b2 as the source
+    pub fn new(fields: InstructionFields) -> Self {
+        Self { fields }
+    }
+}
+
+impl Instruction for Return {
+    fn plan(&self, cpu: &Cpu, _memory: &mut Memory) -> Result<StateChange, EmulatorError> {
+        let mut change = StateChange::default();
+
+        // Check predicate
+        if !cpu.get_pr(self.fields.qp as usize)? {
+            return Ok(change);
+        }
+
+        let target = cpu.get_br(self.fields.sources[0].get_reg_num())? & !0xF;
+        change.change_frame(FrameChange::Return);
+        change.branch(target);
+        Ok(change)
+    }
+}
+
+/// Loop branch types
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LoopType {
+    /// Counted loop (br.cloop)
+    Cloop,
+    /// Modulo-scheduled counted loop, branching back (br.ctop)
+    Ctop,
+    /// Modulo-scheduled counted loop, branching out (br.cexit)
+    Cexit,
+    /// Modulo-scheduled while loop, branching back (br.wtop)
+    Wtop,
+    /// Modulo-scheduled while loop, branching out (br.wexit)
+    Wexit,
+}
+
+/// Loop branch instruction (br.cloop, br.ctop, br.cexit, br.wtop and
+/// br.wexit)
+///
+/// br.cloop counts AR.LC down to zero. The modulo-scheduled forms also
+/// drain AR.EC once the kernel iterations are done, write the stage
+/// predicate p63 and rotate the registers, so that p63 is p16 in the next
+/// iteration; ctop and wtop branch back while iterations remain, cexit and
+/// wexit branch out once none do. For br.wtop and br.wexit the qualifying
+/// predicate takes the place of AR.LC, so they execute whether it is set or
+/// not.
+#[derive(Debug)]
+pub struct LoopBranch {
+    fields: InstructionFields,
+    loop_type: LoopType,
+}
+
+impl LoopBranch {
+    /// Create new loop branch instruction, with the offset of its target as
+    /// the immediate
+    pub fn new(fields: InstructionFields, loop_type: LoopType) -> Self {
+        Self { fields, loop_type }
+    }
+}
+
+impl Instruction for LoopBranch {
+    fn plan(&self, cpu: &Cpu, _memory: &mut Memory) -> Result<StateChange, EmulatorError> {
+        let mut change = StateChange::default();
+        let qp = cpu.get_pr(self.fields.qp as usize)?;
+
+        // Check predicate, which the while loops take as their condition
+        let counted = !matches!(self.loop_type, LoopType::Wtop | LoopType::Wexit);
+        if counted && !qp {
+            return Ok(change);
+        }
+
+        let lc = cpu.read_ar(AR::LC)?;
+        let ec = cpu.read_ar(AR::EC)?;
+        let taken = match self.loop_type {
+            LoopType::Cloop => {
+                if lc != 0 {
+                    change.set_ar(AR::LC, lc - 1);
+                }
+                lc != 0
+            }
+            loop_type => {
+                let kernel = if counted { lc != 0 } else { qp };
+                let more = kernel || ec > 1;
+                if kernel {
+                    if counted {
+                        change.set_ar(AR::LC, lc - 1);
+                    }
+                    change.set_pr(63, counted);
+                    change.change_frame(FrameChange::Rotate);
+                } else if ec != 0 {
+                    change.set_ar(AR::EC, ec - 1);
+                    change.set_pr(63, false);
+                    change.change_frame(FrameChange::Rotate);
+                } else {
+                    change.set_pr(63, false);
+                }
+                match loop_type {
+                    LoopType::Ctop | LoopType::Wtop => more,
+                    _ => !more,
+                }
+            }
+        };
+        if taken {
+            let offset = self.fields.immediate.unwrap_or(0);
+            change.branch(cpu.ip.wrapping_add(offset as u64));
+        }
+        Ok(change)
+    }
+}
+
+/// Clear rotating register base instruction (clrrrb and clrrrb.pr)
+#[derive(Debug)]
+pub struct ClearRrb {
+    fields: InstructionFields,
+    predicates_only: bool,
+}
+
+impl ClearRrb {
+    /// Create new clrrrb instruction, or clrrrb.pr if `predicates_only`
+    pub fn new(fields: InstructionFields, predicates_only: bool) -> Self {
+        Self {
+            fields,
+            predicates_only,
+        }
+    }
+}
+
+impl Instruction for ClearRrb {
+    fn plan(&self, cpu: &Cpu, _memory: &mut Memory) -> Result<StateChange, EmulatorError> {
+        let mut change = StateChange::default();
+
+        // Check predicate
+        if !cpu.get_pr(self.fields.qp as usize)? {
+            return Ok(change);
+        }
+
+        change.change_frame(FrameChange::ClearRotatingBases {
+            predicates_only: self.predicates_only,
+        });
+        Ok(change)
     }
 }
 
@@ -491,15 +750,10 @@ mod tests {
 
     #[test]
     fn test_branch_completers() {
-        let (mut cpu, mut memory, mut fields) = setup_test();
+        let (_cpu, _memory, fields) = setup_test();
 
         // Test branch with all completers
-        let completers = Some(vec![
-            "dptk".to_string(),
-            "clr".to_string(),
-            "imp".to_string(),
-            "many".to_string(),
-        ]);
+        let completers = Completers::DPTK | Completers::CLR | Completers::IMP | Completers::MANY;
 
         let branch = Branch::from_decoded(fields.clone(), BranchType::Unconditional, completers);
 
@@ -510,7 +764,8 @@ mod tests {
         assert!(matches!(branch.registers, BranchRegisters::Many));
 
         // Test branch with default completers
-        let branch = Branch::from_decoded(fields.clone(), BranchType::Unconditional, None);
+        let branch =
+            Branch::from_decoded(fields.clone(), BranchType::Unconditional, Completers::NONE);
 
         // Verify default values
         assert!(matches!(branch.prediction, BranchPrediction::StaticTake));
@@ -519,11 +774,7 @@ mod tests {
         assert!(matches!(branch.registers, BranchRegisters::Few));
 
         // Test branch with partial completers
-        let completers = Some(vec![
-            "spnt".to_string(),
-            "".to_string(), // Empty completer should be ignored
-            "imp".to_string(),
-        ]);
+        let completers = Completers::SPNT | Completers::IMP;
 
         let branch = Branch::from_decoded(fields, BranchType::Unconditional, completers);
 
@@ -543,7 +794,7 @@ mod tests {
         fields.sources = vec![RegisterType::GR(1), RegisterType::GR(2)];
 
         // Test branch with RSE clear
-        let completers = Some(vec!["sptk".to_string(), "clr".to_string()]);
+        let completers = Completers::SPTK | Completers::CLR;
         let branch = Branch::from_decoded(fields.clone(), BranchType::Equal, completers);
 
         cpu.ip = 0x1000;
@@ -553,7 +804,7 @@ mod tests {
         assert_eq!(cpu.ip, 0x1010); // Should branch when equal
 
         // Test branch with importance flag
-        let completers = Some(vec!["dptk".to_string(), "imp".to_string()]);
+        let completers = Completers::DPTK | Completers::IMP;
         let branch = Branch::from_decoded(fields, BranchType::Equal, completers);
 
         cpu.ip = 0x1000;
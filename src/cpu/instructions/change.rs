@@ -0,0 +1,644 @@
+//! Instruction state changes
+//!
+//! An instruction's [`Instruction::plan`](super::Instruction::plan) reads the
+//! machine state and returns what the instruction would do as a
+//! [`StateChange`]: register writes, memory writes, system register writes,
+//! register frame changes, ALAT updates and a new instruction pointer. [`StateChange::apply`] checks
+//! that every write can be made before making any, so an instruction that
+//! faults, whether while planning or applying, leaves registers and memory
+//! as they were.
+//!
+//! The values an instruction loads are recorded alongside its writes, so the
+//! data debug registers can raise a Debug fault on a value loaded or stored
+//! before the instruction takes effect.
+//!
+//! Changes of several instructions can be merged and applied together, e.g.
+//! at the end of an instruction group, or compared without applying them.
+
+use crate::cpu::interrupts::InterruptVector;
+use crate::cpu::registers::ar::AR;
+use crate::cpu::registers::{CRIndex, FloatRegister};
+use crate::cpu::{Cpu, PSRFlags, NUM_BR, NUM_FR, NUM_GR, NUM_PR};
+use crate::memory::view::Endian;
+use crate::memory::Memory;
+use crate::EmulatorError;
+
+/// Register written by an instruction
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RegisterWrite {
+    /// General register and its NaT bit
+    Gr {
+        /// Register number
+        reg: usize,
+        /// Value
+        value: u64,
+        /// NaT bit
+        nat: bool,
+    },
+    /// Floating-point register
+    Fr {
+        /// Register number
+        reg: usize,
+        /// Value
+        value: FloatRegister,
+    },
+    /// Predicate register
+    Pr {
+        /// Register number
+        reg: usize,
+        /// Value
+        value: bool,
+    },
+    /// Branch register
+    Br {
+        /// Register number
+        reg: usize,
+        /// Value
+        value: u64,
+    },
+    /// Application register
+    Ar {
+        /// Register
+        reg: AR,
+        /// Value
+        value: u64,
+    },
+}
+
+/// System state written by an instruction
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SystemWrite {
+    /// Processor status register, whose PSR.i enables or disables external
+    /// interrupts
+    Psr(u64),
+    /// Previous function state (AR.PFS), which the CPU keeps with the
+    /// frame marker
+    Pfs(u64),
+    /// Control register
+    Cr {
+        /// Register
+        reg: CRIndex,
+        /// Value
+        value: u64,
+    },
+    /// Region register
+    Rr {
+        /// Region, from the top three bits of an address
+        region: usize,
+        /// Value
+        value: u64,
+    },
+    /// Performance monitor configuration register
+    Pmc {
+        /// Register index
+        index: u64,
+        /// Value
+        value: u64,
+    },
+    /// Performance monitor data register
+    Pmd {
+        /// Register index
+        index: u64,
+        /// Value
+        value: u64,
+    },
+    /// Purge the translations of a page in a region ID
+    Purge {
+        /// Region ID
+        rid: u64,
+        /// Address in the page
+        vaddr: u64,
+        /// Page size as a power of two
+        page_shift: u8,
+    },
+    /// Purge every translation
+    PurgeAll,
+}
+
+/// Register frame change made by an instruction
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrameChange {
+    /// Replace the current frame, keeping its rotating register bases
+    /// (alloc)
+    Alloc {
+        /// Size of the frame
+        sof: u32,
+        /// Size of the locals
+        sol: u32,
+        /// Size of the rotating region, in groups of eight
+        sor: u32,
+    },
+    /// Push the caller's locals and open the callee's frame (br.call)
+    Call {
+        /// Callee's entry point
+        target: u64,
+    },
+    /// Restore the caller's frame from AR.PFS (br.ret)
+    Return,
+    /// End the interruption, restoring the frame `cover` saved in CR.IFS
+    /// if its valid bit is set (rfi)
+    Rfi {
+        /// CR.IFS
+        ifs: u64,
+    },
+    /// Rotate the rotating registers by one
+    Rotate,
+    /// Clear the rotating register bases (clrrrb)
+    ClearRotatingBases {
+        /// Whether only rrb.pr is cleared (clrrrb.pr)
+        predicates_only: bool,
+    },
+}
+
+/// How a memory write is made
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WriteKind {
+    /// Ordinary store
+    Normal,
+    /// Store with release semantics (`st.rel`)
+    Release,
+    /// Unaligned store fixed up byte by byte
+    Bytewise,
+}
+
+/// Memory written by an instruction
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemoryWrite {
+    /// Address
+    pub addr: u64,
+    /// Size in bytes: 1, 2, 4 or 8
+    pub len: usize,
+    /// Value, as the register held it
+    pub value: u64,
+    /// Byte order the value is laid out in
+    pub endian: Endian,
+    /// How the write is made
+    pub kind: WriteKind,
+}
+
+/// Memory read by an instruction
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemoryRead {
+    /// Address
+    pub addr: u64,
+    /// Size in bytes
+    pub len: usize,
+    /// Value read
+    pub value: u64,
+}
+
+/// ALAT update made by an instruction
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AlatUpdate {
+    /// Allocate an entry for an advanced load
+    Add {
+        /// Address loaded
+        addr: u64,
+        /// Size in bytes
+        size: u64,
+        /// Target register
+        reg: u32,
+        /// Whether the target is a general register
+        is_integer: bool,
+    },
+    /// Count a check of a register's entry
+    Check {
+        /// Checked register
+        reg: u32,
+        /// Whether the register is a general register
+        is_integer: bool,
+    },
+    /// Remove a register's entry
+    Remove {
+        /// Register
+        reg: u32,
+        /// Whether the register is a general register
+        is_integer: bool,
+    },
+}
+
+/// Unaligned access fixed up under [`AlignmentPolicy::Fixup`](crate::cpu::unaligned::AlignmentPolicy::Fixup)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UnalignedAccess {
+    /// Address
+    pub addr: u64,
+    /// Size in bytes
+    pub len: usize,
+    /// Whether the access is a store
+    pub store: bool,
+}
+
+/// Everything an instruction changes, not yet applied
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct StateChange {
+    /// Register writes, in order
+    pub registers: Vec<RegisterWrite>,
+    /// Memory writes, in order
+    pub memory: Vec<MemoryWrite>,
+    /// System register writes and TLB purges, in order
+    pub system: Vec<SystemWrite>,
+    /// Memory reads, in order
+    pub loads: Vec<MemoryRead>,
+    /// Register frame change, made after the register writes
+    pub frame: Option<FrameChange>,
+    /// ALAT updates, in order
+    pub alat: Vec<AlatUpdate>,
+    /// Unaligned accesses to count as fixed up
+    pub fixups: Vec<UnalignedAccess>,
+    /// Interrupts raised once the change is applied, e.g. floating-point
+    /// traps, with their interruption status
+    pub interrupts: Vec<(InterruptVector, u64)>,
+    /// New instruction pointer, for a taken branch
+    pub ip: Option<u64>,
+    /// Slot to resume at in the bundle at the new instruction pointer, for
+    /// an rfi
+    pub slot: Option<u8>,
+    /// Immediate of a break, which the caller acts on once the change is
+    /// applied
+    pub break_imm: Option<u64>,
+}
+
+impl StateChange {
+    /// Whether the change does nothing
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Write a general register, clearing its NaT bit
+    pub fn set_gr(&mut self, reg: usize, value: u64) {
+        self.set_gr_nat(reg, value, false);
+    }
+
+    /// Write a general register and its NaT bit
+    pub fn set_gr_nat(&mut self, reg: usize, value: u64, nat: bool) {
+        self.registers.push(RegisterWrite::Gr { reg, value, nat });
+    }
+
+    /// Write a floating-point register with a double
+    pub fn set_fr(&mut self, reg: usize, value: f64) {
+        self.set_fr_register(reg, FloatRegister::from_f64(value));
+    }
+
+    /// Write a floating-point register in the register format
+    pub fn set_fr_register(&mut self, reg: usize, value: FloatRegister) {
+        self.registers.push(RegisterWrite::Fr { reg, value });
+    }
+
+    /// Raise an interrupt once the change is applied
+    pub fn raise(&mut self, vector: InterruptVector, isr: u64) {
+        self.interrupts.push((vector, isr));
+    }
+
+    /// Write a predicate register
+    pub fn set_pr(&mut self, reg: usize, value: bool) {
+        self.registers.push(RegisterWrite::Pr { reg, value });
+    }
+
+    /// Write a branch register
+    pub fn set_br(&mut self, reg: usize, value: u64) {
+        self.registers.push(RegisterWrite::Br { reg, value });
+    }
+
+    /// Write an application register
+    pub fn set_ar(&mut self, reg: AR, value: u64) {
+        self.registers.push(RegisterWrite::Ar { reg, value });
+    }
+
+    /// Write a system register or purge translations
+    pub fn write_system(&mut self, write: SystemWrite) {
+        self.system.push(write);
+    }
+
+    /// Change the register frame
+    pub fn change_frame(&mut self, frame: FrameChange) {
+        self.frame = Some(frame);
+    }
+
+    /// Store the low `len` bytes of `value` at `addr` in byte order
+    /// `endian`
+    pub fn write(&mut self, addr: u64, len: usize, value: u64, endian: Endian, kind: WriteKind) {
+        self.memory.push(MemoryWrite {
+            addr,
+            len,
+            value,
+            endian,
+            kind,
+        });
+    }
+
+    /// Record that `value` was loaded from `addr`
+    pub fn read(&mut self, addr: u64, len: usize, value: u64) {
+        self.loads.push(MemoryRead { addr, len, value });
+    }
+
+    /// Branch to `target`
+    pub fn branch(&mut self, target: u64) {
+        self.ip = Some(target);
+    }
+
+    /// Resume at slot `slot` of the bundle at `target`
+    pub fn resume(&mut self, target: u64, slot: u8) {
+        self.ip = Some(target);
+        self.slot = Some(slot);
+    }
+
+    /// Append the change of a later instruction
+    ///
+    /// Its writes are made after this change's, and its frame change,
+    /// branch and break win.
+    pub fn merge(&mut self, later: StateChange) {
+        self.registers.extend(later.registers);
+        self.memory.extend(later.memory);
+        self.system.extend(later.system);
+        self.loads.extend(later.loads);
+        self.alat.extend(later.alat);
+        self.fixups.extend(later.fixups);
+        self.interrupts.extend(later.interrupts);
+        if later.frame.is_some() {
+            self.frame = later.frame;
+        }
+        if later.ip.is_some() {
+            self.ip = later.ip;
+            self.slot = later.slot;
+        }
+        if later.break_imm.is_some() {
+            self.break_imm = later.break_imm;
+        }
+    }
+
+    /// Check that every register and memory write can be made
+    pub fn check(&self, memory: &Memory) -> Result<(), EmulatorError> {
+        self.check_registers()?;
+        for write in &self.memory {
+            match write.kind {
+                WriteKind::Bytewise => {
+                    for i in 0..write.len as u64 {
+                        memory.check_write(write.addr.wrapping_add(i), 1)?;
+                    }
+                }
+                _ => memory.check_write(write.addr, write.len)?,
+            }
+        }
+        Ok(())
+    }
+
+    /// Check the values loaded and stored against the data debug registers
+    ///
+    /// A match is a Debug fault unless PSR.dd disables them for the
+    /// instruction.
+    pub fn check_data_debug(&self, cpu: &Cpu) -> Result<(), EmulatorError> {
+        if cpu.system_regs.cr.contains(PSRFlags::DD) {
+            return Ok(());
+        }
+        let loads = self
+            .loads
+            .iter()
+            .map(|read| (read.addr, read.len, read.value));
+        let stores = self
+            .memory
+            .iter()
+            .map(|write| (write.addr, write.len, write.value));
+        for (addr, len, value) in loads.chain(stores) {
+            let value = match len {
+                8.. => value,
+                _ => value & ((1 << (len * 8)) - 1),
+            };
+            if cpu.check_data_match(value) {
+                return Err(EmulatorError::DebugFault { addr, value });
+            }
+        }
+        Ok(())
+    }
+
+    /// Check that every register write names a register that exists
+    fn check_registers(&self) -> Result<(), EmulatorError> {
+        for write in &self.registers {
+            let (name, reg, count) = match *write {
+                RegisterWrite::Gr { reg, .. } => ("general", reg, NUM_GR),
+                RegisterWrite::Fr { reg, .. } => ("floating point", reg, NUM_FR),
+                RegisterWrite::Pr { reg, .. } => ("predicate", reg, NUM_PR),
+                RegisterWrite::Br { reg, .. } => ("branch", reg, NUM_BR),
+                RegisterWrite::Ar { reg: AR::BSP, .. } => {
+                    return Err(EmulatorError::RegisterError("BSP is read-only".to_string()))
+                }
+                RegisterWrite::Ar { .. } => continue,
+            };
+            if reg >= count {
+                return Err(EmulatorError::CpuStateError(format!(
+                    "Invalid {} register index: {}",
+                    name, reg
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// Apply the change
+    ///
+    /// Nothing is applied unless every write can be made and no data debug
+    /// register matches. Registers a new frame displaces are spilled first,
+    /// then application and system registers are written, as the CPU may
+    /// still refuse one, e.g. at the wrong privilege level.
+    pub fn apply(mut self, cpu: &mut Cpu, memory: &mut Memory) -> Result<(), EmulatorError> {
+        self.check(memory)?;
+        self.check_data_debug(cpu)?;
+        match self.frame {
+            Some(FrameChange::Alloc { sof, .. }) => cpu.make_room(memory, sof)?,
+            Some(FrameChange::Call { .. }) => cpu.make_room(memory, (cpu.cfm & 0x7F) as u32)?,
+            _ => {}
+        }
+        self.apply_ars(cpu)?;
+        self.apply_system(cpu)?;
+        for write in std::mem::take(&mut self.memory) {
+            let MemoryWrite {
+                addr,
+                len,
+                value,
+                endian,
+                kind,
+            } = write;
+            match kind {
+                WriteKind::Bytewise => {
+                    let value = endian.convert(value, len);
+                    for i in 0..len as u64 {
+                        memory.write_u8(addr.wrapping_add(i), (value >> (i * 8)) as u8)?;
+                    }
+                }
+                WriteKind::Release => {
+                    memory.store_release(addr, len, endian.convert(value, len))?
+                }
+                WriteKind::Normal => memory.write_sized(addr, len, value, endian)?,
+            }
+            // Any store, semaphores included, invalidates the advanced
+            // loads it overlaps
+            cpu.alat_invalidate_overlap(addr, len as u64);
+        }
+        let restored = matches!(
+            self.frame,
+            Some(FrameChange::Return | FrameChange::Rfi { .. })
+        );
+        self.apply_cpu(cpu)?;
+
+        // The restored frame's mandatory loads can fault once the change
+        // is made; they stay pending and resume before the next bundle
+        if restored {
+            cpu.complete_rse_loads(memory)?;
+        }
+        Ok(())
+    }
+
+    /// Apply a change that writes no memory
+    ///
+    /// Opening a frame may spill registers to the backing store and
+    /// restoring one may load them, so those changes need memory too.
+    pub fn apply_to_cpu(mut self, cpu: &mut Cpu) -> Result<(), EmulatorError> {
+        let stacked = matches!(
+            self.frame,
+            Some(
+                FrameChange::Alloc { .. }
+                    | FrameChange::Call { .. }
+                    | FrameChange::Return
+                    | FrameChange::Rfi { .. }
+            )
+        );
+        if !self.memory.is_empty() || stacked {
+            return Err(EmulatorError::ExecutionError(
+                "Memory accesses applied without memory".to_string(),
+            ));
+        }
+        self.check_registers()?;
+        self.check_data_debug(cpu)?;
+        self.apply_ars(cpu)?;
+        self.apply_system(cpu)?;
+        self.apply_cpu(cpu)
+    }
+
+    /// Write the application registers, which the CPU may refuse
+    fn apply_ars(&mut self, cpu: &mut Cpu) -> Result<(), EmulatorError> {
+        for write in &self.registers {
+            if let RegisterWrite::Ar { reg, value } = *write {
+                cpu.write_ar(reg, value)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Write the system registers and purge translations
+    fn apply_system(&self, cpu: &mut Cpu) -> Result<(), EmulatorError> {
+        for write in &self.system {
+            match *write {
+                SystemWrite::Psr(value) => {
+                    cpu.system_regs.cr.write(CRIndex::PSR, value)?;
+                    cpu.set_interrupts_enabled(value & PSRFlags::I.bits() != 0);
+                }
+                SystemWrite::Pfs(value) => cpu.pfs = value,
+                SystemWrite::Cr { reg, value } => cpu.write_cr(reg, value)?,
+                SystemWrite::Rr { region, value } => cpu.write_rr(region, value)?,
+                SystemWrite::Pmc { index, value } => cpu.pmu.write_pmc(index, value),
+                SystemWrite::Pmd { index, value } => cpu.pmu.write_pmd(index, value),
+                SystemWrite::Purge {
+                    rid,
+                    vaddr,
+                    page_shift,
+                } => {
+                    cpu.tlb.purge(rid, vaddr, page_shift);
+                }
+                SystemWrite::PurgeAll => {
+                    cpu.tlb.purge_all();
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Make the checked register writes and the rest of the CPU updates
+    fn apply_cpu(self, cpu: &mut Cpu) -> Result<(), EmulatorError> {
+        for write in self.registers {
+            match write {
+                RegisterWrite::Gr { reg, value, nat } => {
+                    cpu.set_gr(reg, value)?;
+                    cpu.set_nat(reg, nat)?;
+                }
+                RegisterWrite::Fr { reg, value } => cpu.set_fr_register(reg, value)?,
+                RegisterWrite::Pr { reg, value } => cpu.set_pr(reg, value)?,
+                RegisterWrite::Br { reg, value } => cpu.set_br(reg, value)?,
+                RegisterWrite::Ar { .. } => {}
+            }
+        }
+        match self.frame {
+            Some(FrameChange::Alloc { sof, sol, sor }) => cpu.resize_frame(sof, sol, sor)?,
+            Some(FrameChange::Call { target }) => cpu.open_frame(target)?,
+            Some(FrameChange::Return) => cpu.close_frame()?,
+            Some(FrameChange::Rfi { ifs }) => cpu.end_interruption(ifs)?,
+            Some(FrameChange::Rotate) => cpu.rotate_registers(),
+            Some(FrameChange::ClearRotatingBases { predicates_only }) => {
+                cpu.clear_rotating_bases(predicates_only)
+            }
+            None => {}
+        }
+        for update in self.alat {
+            match update {
+                AlatUpdate::Add {
+                    addr,
+                    size,
+                    reg,
+                    is_integer,
+                } => cpu.alat_add_entry(addr, size, reg, is_integer)?,
+                AlatUpdate::Check { reg, is_integer } => {
+                    cpu.alat_check(reg, is_integer);
+                }
+                AlatUpdate::Remove { reg, is_integer } => cpu.alat_remove_entry(reg, is_integer),
+            }
+        }
+        for access in self.fixups {
+            cpu.unaligned_fixups
+                .record(cpu.ip, cpu.slot, access.addr, access.len, access.store);
+        }
+        if let Some(ip) = self.ip {
+            cpu.ip = ip;
+        }
+        if let Some(slot) = self.slot {
+            cpu.slot = slot;
+        }
+        for (vector, isr) in self.interrupts {
+            cpu.raise_interrupt(vector, isr);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_merge_and_apply() {
+        let mut cpu = Cpu::new();
+        let mut first = StateChange::default();
+        first.set_gr(1, 1);
+        first.branch(0x100);
+        let mut second = StateChange::default();
+        second.set_gr(1, 2);
+        second.set_pr(3, true);
+        first.merge(second);
+        assert_eq!(first.ip, Some(0x100));
+        assert_eq!(first.registers.len(), 3);
+
+        // A bad register index stops the whole change
+        let mut bad = first.clone();
+        bad.set_gr(NUM_GR, 0);
+        assert!(bad.apply_to_cpu(&mut cpu).is_err());
+        assert_eq!(cpu.get_gr(1).unwrap(), 0);
+
+        // Later writes win
+        first.apply_to_cpu(&mut cpu).unwrap();
+        assert_eq!(cpu.get_gr(1).unwrap(), 2);
+        assert!(cpu.get_pr(3).unwrap());
+        assert_eq!(cpu.ip, 0x100);
+
+        // Memory writes need memory
+        let mut store = StateChange::default();
+        store.write(0x1000, 8, 0, Endian::Little, WriteKind::Normal);
+        assert!(store.apply_to_cpu(&mut cpu).is_err());
+    }
+}
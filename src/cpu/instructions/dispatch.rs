@@ -0,0 +1,1046 @@
+//! Translation of decoded slots into instructions
+//!
+//! [`translate`] picks the [`Instruction`] that implements a slot from its
+//! unit and raw encoding and fills in its [`InstructionFields`], so decoded
+//! bundles execute through the implementations in the sibling modules.
+//!
+//! Covered so far:
+//!
+//! - Every unit: `break` and `nop`, and `hint.x`
+//! - A unit: `add`, `sub`, `and`, `or` and `xor` on registers (A1), `adds`
+//!   (A4), `addl` (A5), and `cmp` and `cmp4` with every relation and type
+//!   on registers (A6, A7) and against immediates (A8)
+//! - I unit: `zxt1`-`zxt4` and `sxt1`-`sxt4` (I29), `chk.s.i` (I20),
+//!   `mov r1=ip` and `mov r1=pr` (I25), moves to the predicates (I23,
+//!   I24), moves to and from branch registers (I21, I22) and application
+//!   registers (I26-I28)
+//! - M unit: integer loads with every completer, including `ld8.fill`
+//!   (M1), with register (M2) and immediate (M3) base update, integer
+//!   stores, `st.rel` and `st8.spill` (M4) with immediate base update (M5),
+//!   `xchg` (M16), `chk.s.m` (M20, M21), `chk.a` (M22, M23), `ssm` and
+//!   `rsm` (M44), moves to and from application, control, region and
+//!   performance monitor registers (M29-M31, M32, M33, M42, M43), `ptc.l`
+//!   and `ptc.e` (M45, M47), `thash` and `ttag` (M46), and `alloc` (M34)
+//! - F unit: `fma`, `fms` and `fnma` with every precision completer (F1)
+//! - B unit: IP-relative and indirect `br.cond` (B1, B4), the loop
+//!   branches `br.cloop`, `br.ctop`, `br.cexit`, `br.wtop` and `br.wexit`
+//!   (B1, B2), IP-relative and indirect `br.call` (B3, B5), `br.ret` (B4),
+//!   and `epc`, `clrrrb` and `rfi` (B8)
+//! - X unit: `movl` (X2), `brl.cond` (X3) and `brl.call` (X4)
+//!
+//! Other encodings, among them `cmpxchg`, `fetchadd` and the parallel
+//! floating-point multiply-adds, translate to nothing, and the run loop
+//! stops on them as unimplemented.
+
+use super::alu::{
+    Add, And, Compare, CompareCtype, CompareType, Extend, ExtensionSize, Or, Sub, Xor,
+};
+use super::branch::{
+    Branch, BranchType, Call, ClearRrb, LoopBranch, LoopType, MoveFromBr, MoveToBr, Return,
+};
+use super::float::{FmaOp, FusedMultiplyAdd};
+use super::memory::{
+    AdvancedCheck, Load, LoadSize, Semaphore, SemaphoreOp, SpeculationCheck, Store, StoreSize,
+};
+use super::system::{
+    Alloc, Break, Epc, MoveFromAr, MoveFromCr, MoveFromIp, MoveFromMonitor, MoveFromPr, MoveFromRr,
+    MoveToAr, MoveToCr, MoveToMonitor, MoveToPr, MoveToRr, Nop, PurgeTranslation,
+    PurgeTranslationCache, Rfi, SystemMask, VhptHash,
+};
+use super::{AddressingMode, Instruction, InstructionFields, RegisterType};
+use crate::cpu::registers::Precision;
+use crate::decoder::completers::Completers;
+use crate::decoder::instruction_format::{XFormat, XOperation};
+use crate::decoder::{InstructionType, Unit};
+use crate::emulator::{imm21, imm21_check_a, imm21_check_s, sign_extend};
+
+/// Instruction implementing a decoded slot, if one is covered
+pub fn translate(itype: &InstructionType, bits: u64) -> Option<Box<dyn Instruction>> {
+    let major = field(bits, 37, 4);
+    match (itype.unit(), major) {
+        (Unit::M | Unit::I | Unit::A, 8..=0xE) => translate_alu(bits),
+        (Unit::I | Unit::A, 0) => translate_misc(bits),
+        (Unit::M, 0 | 1) => translate_system(bits),
+        (Unit::M, 4 | 5) => translate_memory(bits),
+        (Unit::F, 0) => translate_float_misc(bits),
+        (Unit::F, 8..=0xD) => translate_fma(bits),
+        (Unit::B, 0..=2 | 4 | 5) => translate_branch(bits),
+        (Unit::X, _) => match itype {
+            InstructionType::X(format) => translate_long(format),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// A-unit integer arithmetic, logic and compares (majors 8-E)
+fn translate_alu(bits: u64) -> Option<Box<dyn Instruction>> {
+    let (r1, r2, r3) = (gr(bits, 6), gr(bits, 13), gr(bits, 20));
+    match field(bits, 37, 4) {
+        8 => {
+            // x2a in bits 34-35, ve in bit 33, x4 in bits 29-32, x2b in
+            // bits 27-28
+            let x2a = field(bits, 34, 2);
+            let ve = field(bits, 33, 1);
+            if ve != 0 {
+                return None;
+            }
+            if x2a == 2 {
+                // adds r1=imm14,r3
+                let imm = field(bits, 36, 1) << 13 | field(bits, 27, 6) << 7 | field(bits, 13, 7);
+                return Some(Box::new(Add::new(fields(
+                    bits,
+                    vec![r3],
+                    vec![r1],
+                    Some(sign_extend(imm, 14)),
+                    None,
+                ))));
+            }
+            if x2a != 0 {
+                return None;
+            }
+            let alu = fields(bits, vec![r2, r3], vec![r1], None, None);
+            let instruction: Box<dyn Instruction> = match (field(bits, 29, 4), field(bits, 27, 2)) {
+                (0, 0) => Box::new(Add::new(alu)),
+                (1, 1) => Box::new(Sub::new(alu)),
+                (3, 0) => Box::new(And::new(alu)),
+                (3, 2) => Box::new(Or::new(alu)),
+                (3, 3) => Box::new(Xor::new(alu)),
+                _ => return None,
+            };
+            Some(instruction)
+        }
+        9 => {
+            // addl r1=imm22,r3, with r3 limited to r0-r3
+            let imm = field(bits, 36, 1) << 21
+                | field(bits, 22, 5) << 16
+                | field(bits, 27, 9) << 7
+                | field(bits, 13, 7);
+            let r3 = RegisterType::GR(field(bits, 20, 2) as u8);
+            Some(Box::new(Add::new(fields(
+                bits,
+                vec![r3],
+                vec![r1],
+                Some(sign_extend(imm, 22)),
+                None,
+            ))))
+        }
+        major @ 0xC..=0xE => {
+            // x2 (bits 34-35) picks the register or immediate form and, odd,
+            // cmp4; tb (bit 36), ta (bit 33) and c (bit 12) pick the
+            // relation and type as in CMP_RELATIONS
+            let x2 = field(bits, 34, 2);
+            let index = field(bits, 36, 1) << 2 | field(bits, 33, 1) << 1 | field(bits, 12, 1);
+            let (sources, immediate) = match x2 >> 1 {
+                0 => (vec![r2, r3], None),
+                // imm8 is s in bit 36 and imm7b in bits 13-19, so tb is
+                // not there to pick the second half
+                _ => {
+                    let imm = field(bits, 36, 1) << 7 | field(bits, 13, 7);
+                    (vec![r3], Some(sign_extend(imm, 8)))
+                }
+            };
+            let index = if immediate.is_some() {
+                index & 3
+            } else {
+                index
+            };
+            let parallel = match major {
+                0xC => CompareCtype::And,
+                0xD => CompareCtype::Or,
+                _ => CompareCtype::OrAndcm,
+            };
+            let (relation, ctype) = match index {
+                0 | 1 => {
+                    let relation = match major {
+                        0xC => CompareType::LessThan,
+                        0xD => CompareType::LessThanU,
+                        _ => CompareType::Equal,
+                    };
+                    let ctype = match index {
+                        0 => CompareCtype::Normal,
+                        _ => CompareCtype::Unc,
+                    };
+                    (relation, ctype)
+                }
+                // The rest compare r0 with r3
+                2 => (CompareType::Equal, parallel),
+                3 => (CompareType::NotEqual, parallel),
+                4 => (CompareType::GreaterThan, parallel),
+                5 => (CompareType::LessEqual, parallel),
+                6 => (CompareType::GreaterEqual, parallel),
+                _ => (CompareType::LessThan, parallel),
+            };
+            let destinations = vec![
+                RegisterType::PR(field(bits, 6, 6) as u8),
+                RegisterType::PR(field(bits, 27, 6) as u8),
+            ];
+            let fields = fields(bits, sources, destinations, immediate, None);
+            let compare = Compare::with_ctype(fields, relation, ctype);
+            Some(Box::new(match x2 & 1 {
+                0 => compare,
+                _ => compare.cmp4(),
+            }))
+        }
+        _ => None,
+    }
+}
+
+/// F-unit fused multiply-adds (majors 8-D)
+fn translate_fma(bits: u64) -> Option<Box<dyn Instruction>> {
+    // Even majors are the forms without a completer and, with x in bit 36
+    // set, .s; odd majors are .d and the parallel forms
+    let major = field(bits, 37, 4);
+    let op = match major {
+        8 | 9 => FmaOp::Add,
+        0xA | 0xB => FmaOp::Subtract,
+        _ => FmaOp::NegateMultiply,
+    };
+    let precision = match (major & 1, field(bits, 36, 1)) {
+        (0, 0) => Precision::Dynamic,
+        (0, _) => Precision::Single,
+        (_, 0) => Precision::Double,
+        _ => return None,
+    };
+    let fr = |start| RegisterType::FR(field(bits, start, 7) as u8);
+    let fields = fields(bits, vec![fr(20), fr(27), fr(13)], vec![fr(6)], None, None);
+    let sf = field(bits, 34, 2) as usize;
+    Some(Box::new(FusedMultiplyAdd::new(fields, op, precision, sf)))
+}
+
+/// F-unit break.f and nop.f (major 0), whose x bit sits in the low bit
+/// of x3
+fn translate_float_misc(bits: u64) -> Option<Box<dyn Instruction>> {
+    if field(bits, 33, 1) != 0 {
+        return None;
+    }
+    match field(bits, 27, 6) {
+        0x00 => Some(break_instruction(bits, imm21(bits))),
+        0x01 => Some(Box::new(Nop)),
+        _ => None,
+    }
+}
+
+/// I-unit breaks, nops, checks and moves (major 0)
+fn translate_misc(bits: u64) -> Option<Box<dyn Instruction>> {
+    // x3 in bits 33-35, and x6 in bits 27-32 when x3 is 0
+    let (r1, r2) = (gr(bits, 6), gr(bits, 13));
+    let ar3 = field(bits, 20, 7) as u8;
+    match field(bits, 33, 3) {
+        // chk.s.i r2,target25
+        1 => Some(Box::new(SpeculationCheck::new(fields(
+            bits,
+            vec![r2],
+            vec![],
+            Some(sign_extend(imm21_check_s(bits), 21) << 4),
+            None,
+        )))),
+        // mov b1=r2
+        7 => {
+            let b1 = RegisterType::BR(field(bits, 6, 3) as u8);
+            Some(Box::new(MoveToBr::new(fields(
+                bits,
+                vec![r2],
+                vec![b1],
+                None,
+                None,
+            ))))
+        }
+        // mov pr.rot=imm44, imm27a in bits 6-32 and s in bit 36 giving
+        // predicate bits 16-43, s extending to the rest
+        2 => {
+            let imm = field(bits, 6, 27) << 16 | field(bits, 36, 1) << 43;
+            let fields = fields(bits, vec![], vec![], Some(sign_extend(imm, 44)), None);
+            Some(Box::new(MoveToPr::new(fields, !0xFFFF)))
+        }
+        // mov pr=r2,mask17, with mask7a in bits 6-12, mask8c in bits 24-31
+        // and s in bit 36 giving mask bits 1-16, s extending to the rest
+        3 => {
+            let mask = field(bits, 6, 7) << 1 | field(bits, 24, 8) << 8 | field(bits, 36, 1) << 16;
+            let fields = fields(bits, vec![r2], vec![], None, None);
+            Some(Box::new(MoveToPr::new(
+                fields,
+                sign_extend(mask, 17) as u64,
+            )))
+        }
+        0 => match field(bits, 27, 6) {
+            0x00 => Some(break_instruction(bits, imm21(bits))),
+            0x01 => Some(Box::new(Nop)),
+            // mov.i ar3=r2, mov.i ar3=imm8 and mov.i r1=ar3
+            0x2A => Some(Box::new(MoveToAr::new(
+                fields(bits, vec![r2], vec![], None, None),
+                ar3,
+                true,
+            ))),
+            0x0A => Some(Box::new(MoveToAr::new(
+                fields(bits, vec![], vec![], Some(move_imm8(bits)), None),
+                ar3,
+                true,
+            ))),
+            0x32 => Some(Box::new(MoveFromAr::new(
+                fields(bits, vec![], vec![r1], None, None),
+                ar3,
+                true,
+            ))),
+            // mov r1=pr
+            0x33 => Some(Box::new(MoveFromPr::new(fields(
+                bits,
+                vec![],
+                vec![r1],
+                None,
+                None,
+            )))),
+            // mov r1=ip
+            0x30 => Some(Box::new(MoveFromIp::new(fields(
+                bits,
+                vec![RegisterType::IP],
+                vec![r1],
+                None,
+                None,
+            )))),
+            // mov r1=b2
+            0x31 => {
+                let b2 = RegisterType::BR(field(bits, 13, 3) as u8);
+                Some(Box::new(MoveFromBr::new(fields(
+                    bits,
+                    vec![b2],
+                    vec![r1],
+                    None,
+                    None,
+                ))))
+            }
+            _ => translate_extend(bits),
+        },
+        _ => None,
+    }
+}
+
+/// M-unit breaks, nops, checks and system instructions (majors 0 and 1)
+fn translate_system(bits: u64) -> Option<Box<dyn Instruction>> {
+    // x3 in bits 33-35, and x6 in bits 27-32 when x3 is 0
+    let (r1, r2, r3) = (gr(bits, 6), gr(bits, 13), gr(bits, 20));
+    let sources = |sources| fields(bits, sources, vec![], None, None);
+    let moves = |sources| fields(bits, sources, vec![r1], None, None);
+    let cr3 = field(bits, 20, 7) as u8;
+    let ar3 = field(bits, 20, 7) as u8;
+    let instruction: Box<dyn Instruction> = match (field(bits, 37, 4), field(bits, 33, 3)) {
+        // chk.a.nc and chk.a.clr, on r1 (x3 4 and 5) or f1 (x3 6 and 7)
+        (0, x3 @ 4..=7) => {
+            let target = match x3 {
+                4 | 5 => r1,
+                _ => RegisterType::FR(field(bits, 6, 7) as u8),
+            };
+            let fields = fields(
+                bits,
+                vec![target],
+                vec![],
+                Some(sign_extend(imm21_check_a(bits), 21) << 4),
+                None,
+            );
+            Box::new(AdvancedCheck::new(fields, x3 & 1 != 0))
+        }
+        // chk.s.m r2 (x3 1) or f2 (x3 3)
+        // alloc r1=ar.pfs,i,l,o,r, with sof in bits 13-19, sol in bits
+        // 20-26 and sor in bits 27-30
+        (1, 6) => {
+            let (sof, sol, sor) = (field(bits, 13, 7), field(bits, 20, 7), field(bits, 27, 4));
+            let fields = fields(bits, vec![], vec![r1], None, None);
+            Box::new(Alloc::new(fields, sof as u32, sol as u32, sor as u32))
+        }
+        (1, x3 @ (1 | 3)) => {
+            let source = match x3 {
+                3 => RegisterType::FR(field(bits, 13, 7) as u8),
+                _ => r2,
+            };
+            Box::new(SpeculationCheck::new(fields(
+                bits,
+                vec![source],
+                vec![],
+                Some(sign_extend(imm21_check_s(bits), 21) << 4),
+                None,
+            )))
+        }
+        (0, 0) => match field(bits, 27, 6) {
+            0x00 => break_instruction(bits, imm21(bits)),
+            0x01 => Box::new(Nop),
+            // mov.m ar3=imm8
+            0x28 => {
+                let fields = fields(bits, vec![], vec![], Some(move_imm8(bits)), None);
+                Box::new(MoveToAr::new(fields, ar3, false))
+            }
+            // ssm imm24 and rsm imm24, with x4 in the low bits of x6 and
+            // the immediate in imm21a (bits 6-26), i2d (bits 31-32) and i
+            // (bit 36)
+            x6 if matches!(x6 & 0xF, 6 | 7) => {
+                let imm24 =
+                    field(bits, 6, 21) | field(bits, 31, 2) << 21 | field(bits, 36, 1) << 23;
+                let fields = fields(bits, vec![], vec![], Some(imm24 as i64), None);
+                Box::new(SystemMask::new(fields, x6 & 0xF == 6))
+            }
+            _ => return None,
+        },
+        (1, 0) => match field(bits, 27, 6) {
+            // mov cr3=r2 and mov r1=cr3
+            0x2C => Box::new(MoveToCr::new(sources(vec![r2]), cr3)),
+            0x24 => Box::new(MoveFromCr::new(moves(vec![]), cr3)),
+            // mov.m ar3=r2 and mov.m r1=ar3
+            0x2A => Box::new(MoveToAr::new(sources(vec![r2]), ar3, false)),
+            0x22 => Box::new(MoveFromAr::new(moves(vec![]), ar3, false)),
+            // mov rr[r3]=r2 and mov r1=rr[r3]
+            0x00 => Box::new(MoveToRr::new(sources(vec![r2, r3]))),
+            0x10 => Box::new(MoveFromRr::new(moves(vec![r3]))),
+            // thash r1=r3 and ttag r1=r3
+            x6 @ (0x1A | 0x1B) => Box::new(VhptHash::new(moves(vec![r3]), x6 == 0x1B)),
+            // ptc.l r3,r2 and ptc.e r3
+            0x09 => Box::new(PurgeTranslation::new(sources(vec![r2, r3]))),
+            0x34 => Box::new(PurgeTranslationCache::new(sources(vec![r3]))),
+            // mov pmc[r3]=r2, mov pmd[r3]=r2, mov r1=pmc[r3] and
+            // mov r1=pmd[r3]
+            x6 @ (0x04 | 0x05) => Box::new(MoveToMonitor::new(sources(vec![r2, r3]), x6 & 1 != 0)),
+            x6 @ (0x14 | 0x15) => Box::new(MoveFromMonitor::new(moves(vec![r3]), x6 & 1 != 0)),
+            _ => return None,
+        },
+        _ => return None,
+    };
+    Some(instruction)
+}
+
+/// Immediate of mov ar3=imm8, imm7b in bits 13-19 and s in bit 36
+fn move_imm8(bits: u64) -> i64 {
+    sign_extend(field(bits, 13, 7) | field(bits, 36, 1) << 7, 8)
+}
+
+/// Break instruction with immediate `imm`
+fn break_instruction(bits: u64, imm: u64) -> Box<dyn Instruction> {
+    Box::new(Break::new(fields(
+        bits,
+        vec![],
+        vec![],
+        Some(imm as i64),
+        None,
+    )))
+}
+
+/// I-unit zero and sign extension (major 0)
+fn translate_extend(bits: u64) -> Option<Box<dyn Instruction>> {
+    let (size, sign) = match field(bits, 27, 6) {
+        0x10 => (ExtensionSize::Byte, false),
+        0x11 => (ExtensionSize::Half, false),
+        0x12 => (ExtensionSize::Word, false),
+        0x14 => (ExtensionSize::Byte, true),
+        0x15 => (ExtensionSize::Half, true),
+        0x16 => (ExtensionSize::Word, true),
+        _ => return None,
+    };
+    let fields = fields(bits, vec![gr(bits, 20)], vec![gr(bits, 6)], None, None);
+    Some(Box::new(Extend::new(fields, size, sign)))
+}
+
+/// M-unit integer loads, stores and exchanges (major 4), and their forms
+/// with an immediate base update (major 5)
+fn translate_memory(bits: u64) -> Option<Box<dyn Instruction>> {
+    // In major 4, m in bit 36 selects the register post-increment forms and
+    // x in bit 27 the semaphores; in major 5 the 9-bit increment is s in
+    // bit 36, i in bit 27 and imm7 in bits 13-19 for loads and bits 6-12
+    // for stores. x6 is in bits 30-35
+    let x6 = field(bits, 30, 6);
+    let r3 = field(bits, 20, 7) as u8;
+    let imm = |imm7: u32| {
+        let imm9 = field(bits, 36, 1) << 8 | field(bits, 27, 1) << 7 | field(bits, imm7, 7);
+        sign_extend(imm9, 9)
+    };
+    let (load_address, store_address) = match (field(bits, 37, 4), field(bits, 36, 1)) {
+        (5, _) => (
+            AddressingMode::PostIncrement(r3, imm(13)),
+            Some(AddressingMode::PostIncrement(r3, imm(6))),
+        ),
+        (_, 1) => (
+            AddressingMode::PostIndex(r3, field(bits, 13, 7) as u8),
+            None,
+        ),
+        _ => (
+            AddressingMode::Indirect(r3),
+            Some(AddressingMode::Indirect(r3)),
+        ),
+    };
+    let address = Some(load_address);
+    let completers = Completers::decode_memory(bits);
+    let size = match x6 & 0x3 {
+        0 => LoadSize::Byte,
+        1 => LoadSize::Half,
+        2 => LoadSize::Word,
+        _ => LoadSize::Double,
+    };
+
+    if field(bits, 37, 4) == 4 && field(bits, 27, 1) != 0 {
+        // xchg r1=[r3],r2
+        if !(0x08..=0x0B).contains(&x6) || field(bits, 36, 1) != 0 {
+            return None;
+        }
+        let fields = fields(bits, vec![gr(bits, 13)], vec![gr(bits, 6)], None, address);
+        return Some(Box::new(Semaphore::from_decoded(
+            fields,
+            SemaphoreOp::Xchg,
+            size,
+            completers | Completers::ACQ,
+        )));
+    }
+
+    match x6 {
+        // ld, ld.s, ld.a, ld.sa, ld.bias, ld.acq, ld8.fill, ld.c.clr,
+        // ld.c.nc and ld.c.clr.acq
+        0x00..=0x17 | 0x1B | 0x20..=0x2B => {
+            let fields = fields(bits, vec![], vec![gr(bits, 6)], None, address);
+            Some(Box::new(Load::from_decoded(fields, size, completers)))
+        }
+        // st, st.rel and st8.spill, which have no register update form
+        0x30..=0x37 | 0x3B if store_address.is_some() => {
+            let size = match x6 & 0x3 {
+                0 => StoreSize::Byte,
+                1 => StoreSize::Half,
+                2 => StoreSize::Word,
+                _ => StoreSize::Double,
+            };
+            let fields = fields(bits, vec![gr(bits, 13)], vec![], None, store_address);
+            Some(Box::new(Store::from_decoded(fields, size, completers)))
+        }
+        _ => None,
+    }
+}
+
+/// B-unit conditional branches (majors 0 and 4), and break.b, nop.b and
+/// epc (majors 0 and 2)
+fn translate_branch(bits: u64) -> Option<Box<dyn Instruction>> {
+    // x6 in bits 27-32 of majors 0 and 2; break.b's immediate fills btype
+    let (b1, b2) = (field(bits, 6, 3) as u8, field(bits, 13, 3) as u8);
+    match (field(bits, 37, 4), field(bits, 27, 6)) {
+        (0, 0x00) => return Some(break_instruction(bits, imm21(bits))),
+        (0, 0x10) => return Some(Box::new(Epc::new(fields(bits, vec![], vec![], None, None)))),
+        // rfi, which may resume partway through the bundle it returns to
+        (0, 0x08) => return Some(Box::new(Rfi::new(fields(bits, vec![], vec![], None, None)))),
+        // clrrrb and clrrrb.pr
+        (0, x6 @ (0x04 | 0x05)) => {
+            let fields = fields(bits, vec![], vec![], None, None);
+            return Some(Box::new(ClearRrb::new(fields, x6 == 0x05)));
+        }
+        // br.ret b2, with btype 4
+        (0, 0x21) if field(bits, 6, 3) == 4 => {
+            let fields = fields(bits, vec![RegisterType::BR(b2)], vec![], None, None);
+            return Some(Box::new(Return::new(fields)));
+        }
+        // br.call b1=b2 and br.call b1=target25
+        (1, _) => {
+            let fields = fields(
+                bits,
+                vec![RegisterType::BR(b2)],
+                vec![RegisterType::BR(b1)],
+                None,
+                None,
+            );
+            return Some(Box::new(Call::new(fields)));
+        }
+        (5, _) => {
+            let offset = sign_extend(imm21_check_a(bits), 21) << 4;
+            let fields = fields(bits, vec![], vec![RegisterType::BR(b1)], Some(offset), None);
+            return Some(Box::new(Call::new(fields)));
+        }
+        (2, 0x00) => return Some(Box::new(Nop)),
+        (2, _) => return None,
+        _ => {}
+    }
+    // btype in bits 6-8; 0 is br.cond, the rest of major 4 are the loop
+    // branches
+    let loop_type = match field(bits, 6, 3) {
+        0 => None,
+        2 => Some(LoopType::Wexit),
+        3 => Some(LoopType::Wtop),
+        5 => Some(LoopType::Cloop),
+        6 => Some(LoopType::Cexit),
+        7 => Some(LoopType::Ctop),
+        _ => return None,
+    };
+    if let Some(loop_type) = loop_type {
+        if field(bits, 37, 4) != 4 {
+            return None;
+        }
+        let offset = sign_extend(imm21_check_a(bits), 21) << 4;
+        let fields = fields(bits, vec![], vec![], Some(offset), None);
+        return Some(Box::new(LoopBranch::new(fields, loop_type)));
+    }
+    let completers = Completers::decode_branch(bits);
+    let fields = match field(bits, 37, 4) {
+        // br.cond target25, with imm20b in bits 13-32 and s in bit 36
+        4 => {
+            let imm = field(bits, 36, 1) << 20 | field(bits, 13, 20);
+            fields(bits, vec![], vec![], Some(sign_extend(imm, 21) << 4), None)
+        }
+        // br.cond b2, with b2 in bits 13-15
+        _ if field(bits, 27, 6) == 0x20 => {
+            let b2 = RegisterType::BR(field(bits, 13, 3) as u8);
+            fields(bits, vec![b2], vec![], None, None)
+        }
+        _ => return None,
+    };
+    Some(Box::new(Branch::from_decoded(
+        fields,
+        BranchType::Unconditional,
+        completers,
+    )))
+}
+
+/// X-unit long immediate moves and branches, with the upper immediate bits
+/// from the L slot
+fn translate_long(format: &XFormat) -> Option<Box<dyn Instruction>> {
+    let bits = format.x_slot;
+    match format.operation()? {
+        XOperation::Break(imm) => Some(break_instruction(bits, imm)),
+        XOperation::Nop(_) | XOperation::Hint(_) => Some(Box::new(Nop)),
+        // movl is an add to r0 of a 64-bit immediate
+        XOperation::Movl { r1, imm } => Some(Box::new(Add::new(fields(
+            bits,
+            vec![RegisterType::GR(0)],
+            vec![RegisterType::GR(r1)],
+            Some(imm as i64),
+            None,
+        )))),
+        XOperation::Brl(offset) => Some(Box::new(Branch::from_decoded(
+            fields(bits, vec![], vec![], Some(offset), None),
+            BranchType::Unconditional,
+            Completers::decode_branch(bits),
+        ))),
+        XOperation::BrlCall { b1, offset } => Some(Box::new(Call::new(fields(
+            bits,
+            vec![],
+            vec![RegisterType::BR(b1)],
+            Some(offset),
+            None,
+        )))),
+    }
+}
+
+/// Fields of an instruction with the slot's qualifying predicate and major
+/// opcode
+fn fields(
+    bits: u64,
+    sources: Vec<RegisterType>,
+    destinations: Vec<RegisterType>,
+    immediate: Option<i64>,
+    addressing: Option<AddressingMode>,
+) -> InstructionFields {
+    InstructionFields::new(
+        field(bits, 0, 6) as u8,
+        field(bits, 37, 4) as u8,
+        sources,
+        destinations,
+        immediate,
+        addressing,
+    )
+}
+
+/// General register named by the 7-bit field at `start`
+fn gr(bits: u64, start: u32) -> RegisterType {
+    RegisterType::GR(field(bits, start, 7) as u8)
+}
+
+/// `len` bits of a slot from bit `start`
+fn field(bits: u64, start: u32, len: u32) -> u64 {
+    (bits >> start) & ((1 << len) - 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::registers::ar::AR;
+    use crate::cpu::registers::CRIndex;
+    use crate::cpu::{Cpu, PSRFlags};
+    use crate::decoder::instruction_format::{BFormat, FFormat, IFormat, MFormat};
+    use crate::memory::{Memory, Permissions};
+    use crate::EmulatorError;
+
+    const DATA: u64 = 0x1000;
+
+    fn run(unit: Unit, bits: u64, cpu: &mut Cpu, memory: &mut Memory) {
+        let itype = match unit {
+            Unit::M => InstructionType::M(MFormat::default()),
+            Unit::F => InstructionType::F(FFormat::default()),
+            Unit::B => InstructionType::B(BFormat::default()),
+            _ => InstructionType::I(IFormat::default()),
+        };
+        translate(&itype, bits)
+            .expect("instruction is translated")
+            .execute(cpu, memory)
+            .unwrap();
+    }
+
+    #[test]
+    fn test_translate() {
+        let mut cpu = Cpu::new();
+        cpu.pr[0] = true;
+        let mut memory = Memory::new();
+        memory.map(DATA, 0x1000, Permissions::ReadWrite).unwrap();
+        cpu.gr[2] = 0x1122_3344_5566_7788;
+        cpu.gr[3] = DATA;
+
+        // st8 [r3]=r2; ld4 r4=[r3]; sxt2 r5=r4
+        run(
+            Unit::M,
+            4 << 37 | 0x33 << 30 | 3 << 20 | 2 << 13,
+            &mut cpu,
+            &mut memory,
+        );
+        run(
+            Unit::M,
+            4 << 37 | 0x02 << 30 | 3 << 20 | 4 << 6,
+            &mut cpu,
+            &mut memory,
+        );
+        assert_eq!(cpu.gr[4], 0x5566_7788);
+        run(
+            Unit::I,
+            0x15 << 27 | 4 << 20 | 5 << 6,
+            &mut cpu,
+            &mut memory,
+        );
+        assert_eq!(cpu.gr[5], 0x7788);
+
+        // adds r6=-1,r5; sub r7=r6,r5
+        let minus_one = 1 << 36 | 0x3F << 27 | 0x7F << 13;
+        run(
+            Unit::I,
+            8 << 37 | 2 << 34 | minus_one | 5 << 20 | 6 << 6,
+            &mut cpu,
+            &mut memory,
+        );
+        assert_eq!(cpu.gr[6], 0x7787);
+        run(
+            Unit::I,
+            8 << 37 | 1 << 29 | 1 << 27 | 5 << 20 | 6 << 13 | 7 << 6,
+            &mut cpu,
+            &mut memory,
+        );
+        assert_eq!(cpu.gr[7] as i64, -1);
+
+        // cmp.ltu p1,p2=r6,r5 writes both predicates
+        cpu.pr[2] = true;
+        run(
+            Unit::I,
+            0xD << 37 | 2 << 27 | 5 << 20 | 6 << 13 | 1 << 6,
+            &mut cpu,
+            &mut memory,
+        );
+        assert!(cpu.pr[1] && !cpu.pr[2]);
+
+        // xchg8 r8=[r3],r7 returns the stored value
+        run(
+            Unit::M,
+            4 << 37 | 0x0B << 30 | 1 << 27 | 3 << 20 | 7 << 13 | 8 << 6,
+            &mut cpu,
+            &mut memory,
+        );
+        assert_eq!(cpu.gr[8], 0x1122_3344_5566_7788);
+        assert_eq!(memory.read_u64(DATA).unwrap(), u64::MAX);
+
+        // ld8 r9=[r3],8; st8 [r3]=r2,-8; ld8 r11=[r3],r10 update the base
+        // after the access
+        run(
+            Unit::M,
+            5 << 37 | 0x03 << 30 | 3 << 20 | 8 << 13 | 9 << 6,
+            &mut cpu,
+            &mut memory,
+        );
+        assert_eq!((cpu.gr[9], cpu.gr[3]), (u64::MAX, DATA + 8));
+        run(
+            Unit::M,
+            5 << 37 | 1 << 36 | 0x33 << 30 | 1 << 27 | 3 << 20 | 2 << 13 | 0x78 << 6,
+            &mut cpu,
+            &mut memory,
+        );
+        assert_eq!(memory.read_u64(DATA + 8).unwrap(), 0x1122_3344_5566_7788);
+        assert_eq!(cpu.gr[3], DATA);
+        cpu.gr[10] = 8;
+        run(
+            Unit::M,
+            4 << 37 | 1 << 36 | 0x03 << 30 | 3 << 20 | 10 << 13 | 11 << 6,
+            &mut cpu,
+            &mut memory,
+        );
+        assert_eq!((cpu.gr[11], cpu.gr[3]), (u64::MAX, DATA + 8));
+
+        // Stores have no register update form
+        let itype = InstructionType::M(MFormat::default());
+        assert!(translate(&itype, 4 << 37 | 1 << 36 | 0x33 << 30).is_none());
+    }
+
+    #[test]
+    fn test_translate_system() {
+        let mut cpu = Cpu::new();
+        cpu.pr[0] = true;
+        cpu.set_privilege_level(0);
+        let mut memory = Memory::new();
+        let itype = InstructionType::M(MFormat::default());
+
+        // break.m 0x42 leaves the immediate to the caller
+        let change = translate(&itype, 0x42 << 6)
+            .expect("instruction is translated")
+            .plan(&cpu, &mut memory)
+            .unwrap();
+        assert_eq!(change.break_imm, Some(0x42));
+
+        // ssm psr.i, then mov r8=cr.ivr acknowledges the pending vector
+        run(
+            Unit::M,
+            0x06 << 27 | PSRFlags::I.bits() << 6,
+            &mut cpu,
+            &mut memory,
+        );
+        assert!(cpu.system_regs.cr.contains(PSRFlags::I));
+        cpu.raise_external_interrupt(0x30).unwrap();
+        run(
+            Unit::M,
+            1 << 37 | 0x24 << 27 | 65 << 20 | 8 << 6,
+            &mut cpu,
+            &mut memory,
+        );
+        assert_eq!(cpu.gr[8], 0x30);
+        assert_eq!(cpu.read_cr(CRIndex::IRR0), 0);
+
+        // mov cr.itm=r2 is privileged
+        cpu.set_privilege_level(3);
+        let result = translate(&itype, 1 << 37 | 0x2C << 27 | 1 << 20 | 2 << 13)
+            .expect("instruction is translated")
+            .execute(&mut cpu, &mut memory);
+        assert!(matches!(result, Err(EmulatorError::PrivilegeViolation)));
+    }
+
+    #[test]
+    fn test_translate_alloc() {
+        const STACK: u64 = 0x10000;
+
+        let mut cpu = Cpu::new();
+        cpu.pr[0] = true;
+        let mut memory = Memory::new();
+        cpu.write_ar(AR::RSC, 0).unwrap();
+        cpu.write_ar(AR::BSPSTORE, STACK).unwrap();
+
+        // A caller filling the register file leaves its callee no room
+        cpu.alloc_frame(&mut memory, 96, 96, 0).unwrap();
+        cpu.handle_call(&mut memory, 0).unwrap();
+        let pfs = cpu.pfs;
+
+        // alloc r32=ar.pfs,2,0,0,0 faults spilling to the unmapped backing
+        // store and leaves the frame as it was
+        let alloc = 1 << 37 | 6 << 33 | 2 << 20 | 2 << 13 | 32 << 6;
+        let itype = InstructionType::M(MFormat::default());
+        let result = translate(&itype, alloc)
+            .expect("instruction is translated")
+            .execute(&mut cpu, &mut memory);
+        assert!(result.is_err());
+        assert_eq!(cpu.cfm, 0);
+
+        // Once the backing store is mapped it completes
+        memory.map(STACK, 0x1000, Permissions::ReadWrite).unwrap();
+        run(Unit::M, alloc, &mut cpu, &mut memory);
+        assert_eq!(cpu.cfm, 2 | 2 << 7);
+        assert_eq!(cpu.get_gr(32).unwrap(), pfs);
+        assert_eq!(cpu.read_ar(AR::BSPSTORE).unwrap(), STACK + 16);
+    }
+
+    #[test]
+    fn test_translate_call_return() {
+        const STACK: u64 = 0x10000;
+
+        let mut cpu = Cpu::new();
+        cpu.pr[0] = true;
+        let mut memory = Memory::new();
+        memory.map(STACK, 0x1000, Permissions::ReadWrite).unwrap();
+        cpu.write_ar(AR::RSC, 0).unwrap();
+        cpu.write_ar(AR::BSPSTORE, STACK).unwrap();
+        cpu.ip = 0x4000;
+
+        // br.call b0=.+0x100 from a caller with locals r32 and r33
+        cpu.alloc_frame(&mut memory, 3, 2, 0).unwrap();
+        cpu.set_gr(32, 32).unwrap();
+        cpu.set_gr(33, 33).unwrap();
+        run(Unit::B, 5 << 37 | 0x10 << 13, &mut cpu, &mut memory);
+        assert_eq!(cpu.ip, 0x4100);
+        assert_eq!(cpu.get_br(0).unwrap(), 0x4010);
+        assert_eq!(cpu.cfm & 0x7F, 1);
+
+        // The callee flushes the locals and switches stacks and back,
+        // leaving them only in the backing store, which br.ret b0 finds
+        // unmapped; the return is made all the same, with the loads left
+        // pending
+        cpu.flush_rse(&mut memory).unwrap();
+        let bspstore = cpu.read_ar(AR::BSPSTORE).unwrap();
+        let rnat = cpu.read_ar(AR::RNAT).unwrap();
+        cpu.write_ar(AR::BSPSTORE, STACK + 0x1000).unwrap();
+        cpu.write_ar(AR::BSPSTORE, bspstore).unwrap();
+        cpu.write_ar(AR::RNAT, rnat).unwrap();
+        memory.unmap(STACK).unwrap();
+        let itype = InstructionType::B(BFormat::default());
+        let result = translate(&itype, 0x21 << 27 | 4 << 6)
+            .expect("instruction is translated")
+            .execute(&mut cpu, &mut memory);
+        assert!(result.is_err());
+        assert_eq!(cpu.ip, 0x4010);
+        assert_eq!(cpu.cfm & 0x7F, 3);
+        assert_eq!(cpu.rse.pending_loads(), 2);
+
+        // Once the page is back the loads resume
+        memory.map(STACK, 0x1000, Permissions::ReadWrite).unwrap();
+        memory.write_u64(STACK, 32).unwrap();
+        memory.write_u64(STACK + 8, 33).unwrap();
+        cpu.complete_rse_loads(&mut memory).unwrap();
+        assert_eq!(cpu.get_gr(32).unwrap(), 32);
+        assert_eq!(cpu.get_gr(33).unwrap(), 33);
+    }
+
+    #[test]
+    fn test_translate_moves() {
+        let mut cpu = Cpu::new();
+        cpu.pr[0] = true;
+        let mut memory = Memory::new();
+
+        // mov pr=r2,-1 faults on a NaT source and writes no predicate
+        let mov_to_pr = 3 << 33 | 1 << 36 | 0xFF << 24 | 0x7F << 6 | 2 << 13;
+        let itype = InstructionType::I(IFormat::default());
+        cpu.gr[2] = !0;
+        cpu.nat[2] = true;
+        let result = translate(&itype, mov_to_pr)
+            .expect("instruction is translated")
+            .execute(&mut cpu, &mut memory);
+        assert!(matches!(result, Err(EmulatorError::NatConsumption(_))));
+        assert!(!cpu.get_pr(1).unwrap());
+
+        // Without it every predicate but p0 is written, and mov r3=pr
+        // reads them back
+        cpu.gr[2] = 0xF0;
+        cpu.nat[2] = false;
+        run(Unit::I, mov_to_pr, &mut cpu, &mut memory);
+        run(Unit::I, 0x33 << 27 | 3 << 6, &mut cpu, &mut memory);
+        assert_eq!(cpu.gr[3], 0xF1);
+
+        // mov.i ar.pfs=r2 and mov.i r4=ar.pfs
+        run(
+            Unit::I,
+            0x2A << 27 | 64 << 20 | 2 << 13,
+            &mut cpu,
+            &mut memory,
+        );
+        assert_eq!(cpu.pfs, 0xF0);
+        run(
+            Unit::I,
+            0x32 << 27 | 64 << 20 | 4 << 6,
+            &mut cpu,
+            &mut memory,
+        );
+        assert_eq!(cpu.gr[4], 0xF0);
+
+        // mov.i ar.lc=-1 sign-extends its immediate
+        run(
+            Unit::I,
+            0x0A << 27 | 65 << 20 | 1 << 36 | 0x7F << 13,
+            &mut cpu,
+            &mut memory,
+        );
+        assert_eq!(cpu.read_ar(AR::LC).unwrap(), !0);
+    }
+
+    #[test]
+    fn test_translate_fma() {
+        let mut cpu = Cpu::new();
+        cpu.pr[0] = true;
+        let mut memory = Memory::new();
+        cpu.set_fr(4, 3.0).unwrap();
+        cpu.set_fr(5, 0.1).unwrap();
+        cpu.set_fr(6, 1.0).unwrap();
+        let fma = |major: u64, x: u64| major << 37 | x << 36 | 5 << 27 | 4 << 20 | 6 << 13 | 7 << 6;
+
+        // fma.d f7=f4,f5,f6, fms.s and fnma
+        run(Unit::F, fma(9, 0), &mut cpu, &mut memory);
+        assert_eq!(cpu.get_fr(7).unwrap(), 3.0f64.mul_add(0.1, 1.0));
+        run(Unit::F, fma(0xA, 1), &mut cpu, &mut memory);
+        assert_eq!(
+            cpu.get_fr(7).unwrap(),
+            3.0f64.mul_add(0.1, -1.0) as f32 as f64
+        );
+        run(Unit::F, fma(0xC, 0), &mut cpu, &mut memory);
+        assert_eq!(cpu.get_fr(7).unwrap(), 3.0f64.mul_add(-0.1, 1.0));
+
+        // The parallel forms are not covered
+        let itype = InstructionType::F(FFormat::default());
+        assert!(translate(&itype, fma(9, 1)).is_none());
+    }
+
+    #[test]
+    fn test_translate_compare() {
+        let mut cpu = Cpu::new();
+        cpu.pr[0] = true;
+        let mut memory = Memory::new();
+        cpu.gr[3] = -5i64 as u64;
+        let cmp = |major: u64, index: u64| {
+            major << 37 | (index >> 2) << 36 | (index >> 1 & 1) << 33 | (index & 1) << 12
+        };
+        let targets = |p1: u64, p2: u64| p2 << 27 | 3 << 20 | p1 << 6;
+
+        // cmp.eq.and p1,p2=r0,r3 clears both
+        cpu.pr[1] = true;
+        cpu.pr[2] = true;
+        run(Unit::I, cmp(0xC, 2) | targets(1, 2), &mut cpu, &mut memory);
+        assert!(!cpu.pr[1] && !cpu.pr[2]);
+
+        // cmp.ne.or p1,p2=r0,r3 sets both
+        run(Unit::I, cmp(0xD, 3) | targets(1, 2), &mut cpu, &mut memory);
+        assert!(cpu.pr[1] && cpu.pr[2]);
+
+        // cmp.gt.or.andcm p3,p4=r0,r3 sets p3 and clears p4
+        cpu.pr[4] = true;
+        run(Unit::I, cmp(0xE, 4) | targets(3, 4), &mut cpu, &mut memory);
+        assert!(cpu.pr[3] && !cpu.pr[4]);
+
+        // (p5) cmp.lt.unc p1,p2=r0,r3 clears both with p5 false
+        run(
+            Unit::I,
+            cmp(0xC, 1) | targets(1, 2) | 5,
+            &mut cpu,
+            &mut memory,
+        );
+        assert!(!cpu.pr[1] && !cpu.pr[2]);
+
+        // cmp.eq p1,p2=-5,r3 and cmp.ltu p1,p2=-6,r3 compare an immediate
+        let imm = |imm: i64| 2 << 34 | (imm as u64 >> 7 & 1) << 36 | (imm as u64 & 0x7F) << 13;
+        run(
+            Unit::I,
+            cmp(0xE, 0) | imm(-5) | targets(1, 2),
+            &mut cpu,
+            &mut memory,
+        );
+        assert!(cpu.pr[1] && !cpu.pr[2]);
+        run(
+            Unit::I,
+            cmp(0xD, 0) | imm(-6) | targets(1, 2),
+            &mut cpu,
+            &mut memory,
+        );
+        assert!(cpu.pr[1] && !cpu.pr[2]);
+
+        // cmp4.lt p1,p2=r4,r3 sees -5 below 1 and cmp4.ltu sees 2^32-5
+        // above it, whatever the high halves hold
+        cpu.gr[3] = 0x1234_5678_0000_0001;
+        cpu.gr[4] = 0xFFFF_FFFB;
+        let cmp4 = |major| cmp(major, 0) | 1 << 34 | 4 << 13 | targets(1, 2);
+        run(Unit::I, cmp4(0xC), &mut cpu, &mut memory);
+        assert!(cpu.pr[1] && !cpu.pr[2]);
+        run(Unit::I, cmp4(0xD), &mut cpu, &mut memory);
+        assert!(!cpu.pr[1] && cpu.pr[2]);
+
+        // cmp4.eq p1,p2=1,r3
+        run(
+            Unit::I,
+            cmp(0xE, 0) | 1 << 34 | imm(1) | targets(1, 2),
+            &mut cpu,
+            &mut memory,
+        );
+        assert!(cpu.pr[1] && !cpu.pr[2]);
+    }
+}
@@ -1,13 +1,140 @@
 //! Floating-point (F-type) instruction implementations
 //!
 //! This module implements the floating-point instructions for the IA-64 architecture.
-
+//!
+//! The arithmetic instructions are the fused multiply-add family; `fadd`,
+//! `fsub` and `fmul` are its pseudo-ops with f1 or f0 as an operand. Each
+//! result is rounded once under a status field of AR.FPSR, whose flags
+//! collect the exceptions raised. Enabled invalid, denormal and zero-divide
+//! exceptions fault the instruction; enabled overflow, underflow and
+//! inexact exceptions raise a floating-point trap after it completes.
+
+use super::change::StateChange;
 use super::{Instruction, InstructionFields, RegisterType};
+use crate::cpu::interrupts::InterruptVector;
+use crate::cpu::registers::ar::AR;
+use crate::cpu::registers::{FloatRegister, FpExceptions, Fpsr, Precision};
 use crate::cpu::Cpu;
 use crate::memory::Memory;
 use crate::EmulatorError;
 
-/// Floating-point add instruction
+/// First ISR bit of the overflow, underflow and inexact trap codes
+const FP_TRAP_ISR_SHIFT: u32 = 11;
+
+/// Read a floating-point source operand
+fn source(cpu: &Cpu, operand: RegisterType) -> Result<FloatRegister, EmulatorError> {
+    match operand {
+        RegisterType::FR(reg) => cpu.get_fr_register(reg as usize),
+        _ => Err(EmulatorError::ExecutionError(
+            "Invalid source register type".to_string(),
+        )),
+    }
+}
+
+/// Plan `dst = a * b + c`, rounded once under status field `sf`
+///
+/// The exceptions raised are added to the field's flags, unless an enabled
+/// one faults the instruction first.
+fn multiply_add(
+    cpu: &Cpu,
+    dst: RegisterType,
+    (a, b, c): (FloatRegister, FloatRegister, FloatRegister),
+    precision: Precision,
+    sf: usize,
+) -> Result<StateChange, EmulatorError> {
+    let RegisterType::FR(dst) = dst else {
+        return Err(EmulatorError::ExecutionError(
+            "Invalid destination register type".to_string(),
+        ));
+    };
+    let old = Fpsr(cpu.read_ar(AR::FPSR)?);
+    let mut status = old.status(sf);
+    let (result, raised) = a.fma(b, c, &status.environment(precision));
+    let enabled = raised & old.enabled_traps(sf);
+    let faults = enabled & FpExceptions::FAULTS;
+    if !faults.is_empty() {
+        return Err(EmulatorError::FPFault {
+            isr: faults.bits() as u64,
+        });
+    }
+
+    let mut change = StateChange::default();
+    change.set_fr_register(dst as usize, result);
+    let mut fpsr = old;
+    status.raise(raised);
+    fpsr.set_status(sf, status);
+    if fpsr != old {
+        change.set_ar(AR::FPSR, fpsr.0);
+    }
+    let traps = enabled & FpExceptions::TRAPS;
+    if !traps.is_empty() {
+        let isr = ((traps.bits() >> 3) as u64) << FP_TRAP_ISR_SHIFT;
+        change.raise(InterruptVector::FPTrap, isr);
+    }
+    Ok(change)
+}
+
+/// Operation of the fused multiply-add family
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FmaOp {
+    /// fma: f3 * f4 + f2
+    Add,
+    /// fms: f3 * f4 - f2
+    Subtract,
+    /// fnma: -(f3 * f4) + f2
+    NegateMultiply,
+}
+
+/// Fused multiply-add instructions: fma, fms and fnma
+///
+/// Sources are f3, f4 and f2, in that order.
+#[derive(Debug)]
+pub struct FusedMultiplyAdd {
+    fields: InstructionFields,
+    op: FmaOp,
+    precision: Precision,
+    sf: usize,
+}
+
+impl FusedMultiplyAdd {
+    /// Create new fused multiply-add instruction rounding to `precision`
+    /// under status field `sf`
+    pub fn new(fields: InstructionFields, op: FmaOp, precision: Precision, sf: usize) -> Self {
+        Self {
+            fields,
+            op,
+            precision,
+            sf,
+        }
+    }
+}
+
+impl Instruction for FusedMultiplyAdd {
+    fn plan(&self, cpu: &Cpu, _memory: &mut Memory) -> Result<StateChange, EmulatorError> {
+        // Check predicate
+        if !cpu.get_pr(self.fields.qp as usize)? {
+            return Ok(StateChange::default());
+        }
+
+        let mut a = source(cpu, self.fields.sources[0])?;
+        let b = source(cpu, self.fields.sources[1])?;
+        let mut c = source(cpu, self.fields.sources[2])?;
+        match self.op {
+            FmaOp::Add => {}
+            FmaOp::Subtract => c = c.negate(),
+            FmaOp::NegateMultiply => a = a.negate(),
+        }
+        multiply_add(
+            cpu,
+            self.fields.destinations[0],
+            (a, b, c),
+            self.precision,
+            self.sf,
+        )
+    }
+}
+
+/// Floating-point add instruction, `fma f1=f3,f1,f2` under sf0
 #[derive(Debug)]
 pub struct FAdd {
     fields: InstructionFields,
@@ -21,49 +148,25 @@ impl FAdd {
 }
 
 impl Instruction for FAdd {
-    fn execute(&self, cpu: &mut Cpu, _memory: &mut Memory) -> Result<(), EmulatorError> {
+    fn plan(&self, cpu: &Cpu, _memory: &mut Memory) -> Result<StateChange, EmulatorError> {
         // Check predicate
         if !cpu.get_pr(self.fields.qp as usize)? {
-            return Ok(());
+            return Ok(StateChange::default());
         }
 
-        // Get source registers
-        let src1 = match self.fields.sources[0] {
-            RegisterType::FR(reg) => cpu.get_fr(reg as usize)?,
-            _ => {
-                return Err(EmulatorError::ExecutionError(
-                    "Invalid source register type".to_string(),
-                ))
-            }
-        };
-
-        let src2 = match self.fields.sources[1] {
-            RegisterType::FR(reg) => cpu.get_fr(reg as usize)?,
-            _ => {
-                return Err(EmulatorError::ExecutionError(
-                    "Invalid source register type".to_string(),
-                ))
-            }
-        };
-
-        // Perform floating-point addition
-        let result = src1 + src2;
-
-        // Write result to destination
-        match self.fields.destinations[0] {
-            RegisterType::FR(reg) => cpu.set_fr(reg as usize, result)?,
-            _ => {
-                return Err(EmulatorError::ExecutionError(
-                    "Invalid destination register type".to_string(),
-                ))
-            }
-        }
-
-        Ok(())
+        let a = source(cpu, self.fields.sources[0])?;
+        let b = source(cpu, self.fields.sources[1])?;
+        multiply_add(
+            cpu,
+            self.fields.destinations[0],
+            (a, FloatRegister::ONE, b),
+            Precision::Dynamic,
+            0,
+        )
     }
 }
 
-/// Floating-point subtract instruction
+/// Floating-point subtract instruction, `fms f1=f3,f1,f2` under sf0
 #[derive(Debug)]
 pub struct FSub {
     fields: InstructionFields,
@@ -77,49 +180,25 @@ impl FSub {
 }
 
 impl Instruction for FSub {
-    fn execute(&self, cpu: &mut Cpu, _memory: &mut Memory) -> Result<(), EmulatorError> {
+    fn plan(&self, cpu: &Cpu, _memory: &mut Memory) -> Result<StateChange, EmulatorError> {
         // Check predicate
         if !cpu.get_pr(self.fields.qp as usize)? {
-            return Ok(());
-        }
-
-        // Get source registers
-        let src1 = match self.fields.sources[0] {
-            RegisterType::FR(reg) => cpu.get_fr(reg as usize)?,
-            _ => {
-                return Err(EmulatorError::ExecutionError(
-                    "Invalid source register type".to_string(),
-                ))
-            }
-        };
-
-        let src2 = match self.fields.sources[1] {
-            RegisterType::FR(reg) => cpu.get_fr(reg as usize)?,
-            _ => {
-                return Err(EmulatorError::ExecutionError(
-                    "Invalid source register type".to_string(),
-                ))
-            }
-        };
-
-        // Perform floating-point subtraction
-        let result = src1 - src2;
-
-        // Write result to destination
-        match self.fields.destinations[0] {
-            RegisterType::FR(reg) => cpu.set_fr(reg as usize, result)?,
-            _ => {
-                return Err(EmulatorError::ExecutionError(
-                    "Invalid destination register type".to_string(),
-                ))
-            }
+            return Ok(StateChange::default());
         }
 
-        Ok(())
+        let a = source(cpu, self.fields.sources[0])?;
+        let b = source(cpu, self.fields.sources[1])?;
+        multiply_add(
+            cpu,
+            self.fields.destinations[0],
+            (a, FloatRegister::ONE, b.negate()),
+            Precision::Dynamic,
+            0,
+        )
     }
 }
 
-/// Floating-point multiply instruction
+/// Floating-point multiply instruction, `fma f1=f3,f4,f0` under sf0
 #[derive(Debug)]
 pub struct FMul {
     fields: InstructionFields,
@@ -133,45 +212,21 @@ impl FMul {
 }
 
 impl Instruction for FMul {
-    fn execute(&self, cpu: &mut Cpu, _memory: &mut Memory) -> Result<(), EmulatorError> {
+    fn plan(&self, cpu: &Cpu, _memory: &mut Memory) -> Result<StateChange, EmulatorError> {
         // Check predicate
         if !cpu.get_pr(self.fields.qp as usize)? {
-            return Ok(());
+            return Ok(StateChange::default());
         }
 
-        // Get source registers
-        let src1 = match self.fields.sources[0] {
-            RegisterType::FR(reg) => cpu.get_fr(reg as usize)?,
-            _ => {
-                return Err(EmulatorError::ExecutionError(
-                    "Invalid source register type".to_string(),
-                ))
-            }
-        };
-
-        let src2 = match self.fields.sources[1] {
-            RegisterType::FR(reg) => cpu.get_fr(reg as usize)?,
-            _ => {
-                return Err(EmulatorError::ExecutionError(
-                    "Invalid source register type".to_string(),
-                ))
-            }
-        };
-
-        // Perform floating-point multiplication
-        let result = src1 * src2;
-
-        // Write result to destination
-        match self.fields.destinations[0] {
-            RegisterType::FR(reg) => cpu.set_fr(reg as usize, result)?,
-            _ => {
-                return Err(EmulatorError::ExecutionError(
-                    "Invalid destination register type".to_string(),
-                ))
-            }
-        }
-
-        Ok(())
+        let a = source(cpu, self.fields.sources[0])?;
+        let b = source(cpu, self.fields.sources[1])?;
+        multiply_add(
+            cpu,
+            self.fields.destinations[0],
+            (a, b, FloatRegister::ZERO),
+            Precision::Dynamic,
+            0,
+        )
     }
 }
 
@@ -189,10 +244,10 @@ impl FDiv {
 }
 
 impl Instruction for FDiv {
-    fn execute(&self, cpu: &mut Cpu, _memory: &mut Memory) -> Result<(), EmulatorError> {
+    fn plan(&self, cpu: &Cpu, _memory: &mut Memory) -> Result<StateChange, EmulatorError> {
         // Check predicate
         if !cpu.get_pr(self.fields.qp as usize)? {
-            return Ok(());
+            return Ok(StateChange::default());
         }
 
         // Get source registers
@@ -225,8 +280,9 @@ impl Instruction for FDiv {
         let result = src1 / src2;
 
         // Write result to destination
+        let mut change = StateChange::default();
         match self.fields.destinations[0] {
-            RegisterType::FR(reg) => cpu.set_fr(reg as usize, result)?,
+            RegisterType::FR(reg) => change.set_fr(reg as usize, result),
             _ => {
                 return Err(EmulatorError::ExecutionError(
                     "Invalid destination register type".to_string(),
@@ -234,7 +290,7 @@ impl Instruction for FDiv {
             }
         }
 
-        Ok(())
+        Ok(change)
     }
 }
 
@@ -257,7 +313,8 @@ mod tests {
         let fields = InstructionFields {
             qp: 0,
             major_op: 0,
-            sources: vec![RegisterType::FR(1), RegisterType::FR(2)],
+            // f0 and f1 are hardwired, so sources start at f4
+            sources: vec![RegisterType::FR(4), RegisterType::FR(2)],
             destinations: vec![RegisterType::FR(3)],
             immediate: None,
             addressing: None,
@@ -266,24 +323,25 @@ mod tests {
     }
 
     #[test]
+    #[allow(clippy::approx_constant)]
     fn test_fadd() {
         let (mut cpu, mut memory, fields) = setup_test();
         let fadd = FAdd::new(fields);
 
         // Test basic addition
-        cpu.set_fr(1, 3.14).unwrap();
+        cpu.set_fr(4, 3.14).unwrap();
         cpu.set_fr(2, 2.86).unwrap();
         fadd.execute(&mut cpu, &mut memory).unwrap();
         assert!((cpu.get_fr(3).unwrap() - 6.0).abs() < f64::EPSILON);
 
         // Test with negative numbers
-        cpu.set_fr(1, -1.5).unwrap();
+        cpu.set_fr(4, -1.5).unwrap();
         cpu.set_fr(2, -2.5).unwrap();
         fadd.execute(&mut cpu, &mut memory).unwrap();
         assert!((cpu.get_fr(3).unwrap() - (-4.0)).abs() < f64::EPSILON);
 
         // Test with infinity
-        cpu.set_fr(1, f64::INFINITY).unwrap();
+        cpu.set_fr(4, f64::INFINITY).unwrap();
         cpu.set_fr(2, 1.0).unwrap();
         fadd.execute(&mut cpu, &mut memory).unwrap();
         assert!(cpu.get_fr(3).unwrap().is_infinite());
@@ -295,13 +353,13 @@ mod tests {
         let fsub = FSub::new(fields);
 
         // Test basic subtraction
-        cpu.set_fr(1, 5.0).unwrap();
+        cpu.set_fr(4, 5.0).unwrap();
         cpu.set_fr(2, 3.0).unwrap();
         fsub.execute(&mut cpu, &mut memory).unwrap();
         assert!((cpu.get_fr(3).unwrap() - 2.0).abs() < f64::EPSILON);
 
         // Test with negative numbers
-        cpu.set_fr(1, -1.5).unwrap();
+        cpu.set_fr(4, -1.5).unwrap();
         cpu.set_fr(2, 2.5).unwrap();
         fsub.execute(&mut cpu, &mut memory).unwrap();
         assert!((cpu.get_fr(3).unwrap() - (-4.0)).abs() < f64::EPSILON);
@@ -313,19 +371,19 @@ mod tests {
         let fmul = FMul::new(fields);
 
         // Test basic multiplication
-        cpu.set_fr(1, 2.5).unwrap();
+        cpu.set_fr(4, 2.5).unwrap();
         cpu.set_fr(2, 4.0).unwrap();
         fmul.execute(&mut cpu, &mut memory).unwrap();
         assert!((cpu.get_fr(3).unwrap() - 10.0).abs() < f64::EPSILON);
 
         // Test with zero
-        cpu.set_fr(1, 1.5).unwrap();
+        cpu.set_fr(4, 1.5).unwrap();
         cpu.set_fr(2, 0.0).unwrap();
         fmul.execute(&mut cpu, &mut memory).unwrap();
         assert!((cpu.get_fr(3).unwrap() - 0.0).abs() < f64::EPSILON);
 
         // Test with infinity
-        cpu.set_fr(1, f64::INFINITY).unwrap();
+        cpu.set_fr(4, f64::INFINITY).unwrap();
         cpu.set_fr(2, 2.0).unwrap();
         fmul.execute(&mut cpu, &mut memory).unwrap();
         assert!(cpu.get_fr(3).unwrap().is_infinite());
@@ -337,18 +395,18 @@ mod tests {
         let fdiv = FDiv::new(fields);
 
         // Test basic division
-        cpu.set_fr(1, 10.0).unwrap();
+        cpu.set_fr(4, 10.0).unwrap();
         cpu.set_fr(2, 2.0).unwrap();
         fdiv.execute(&mut cpu, &mut memory).unwrap();
         assert!((cpu.get_fr(3).unwrap() - 5.0).abs() < f64::EPSILON);
 
         // Test division by zero
-        cpu.set_fr(1, 1.0).unwrap();
+        cpu.set_fr(4, 1.0).unwrap();
         cpu.set_fr(2, 0.0).unwrap();
         assert!(fdiv.execute(&mut cpu, &mut memory).is_err());
 
         // Test with infinity
-        cpu.set_fr(1, f64::INFINITY).unwrap();
+        cpu.set_fr(4, f64::INFINITY).unwrap();
         cpu.set_fr(2, 2.0).unwrap();
         fdiv.execute(&mut cpu, &mut memory).unwrap();
         assert!(cpu.get_fr(3).unwrap().is_infinite());
@@ -361,7 +419,7 @@ mod tests {
         let fadd = FAdd::new(fields);
 
         // Set initial values
-        cpu.set_fr(1, 2.0).unwrap();
+        cpu.set_fr(4, 2.0).unwrap();
         cpu.set_fr(2, 3.0).unwrap();
         cpu.set_fr(3, 0.0).unwrap();
 
@@ -375,4 +433,53 @@ mod tests {
         fadd.execute(&mut cpu, &mut memory).unwrap();
         assert!((cpu.get_fr(3).unwrap() - 5.0).abs() < f64::EPSILON);
     }
+
+    #[test]
+    fn test_fma_status() {
+        let (mut cpu, mut memory, mut fields) = setup_test();
+        fields.sources.push(RegisterType::FR(5));
+        let fma = FusedMultiplyAdd::new(fields.clone(), FmaOp::Add, Precision::Double, 0);
+        cpu.set_fr(4, 1.0).unwrap();
+        cpu.set_fr(2, 3.0).unwrap();
+        cpu.set_fr(5, 0.0).unwrap();
+
+        // Inexact results set sf0.i without trapping by default
+        let sf0 = |cpu: &Cpu| Fpsr(cpu.read_ar(AR::FPSR).unwrap()).status(0).flags();
+        fma.execute(&mut cpu, &mut memory).unwrap();
+        assert!(sf0(&cpu).is_empty());
+        cpu.set_fr(4, 0.1).unwrap();
+        fma.execute(&mut cpu, &mut memory).unwrap();
+        assert_eq!(cpu.get_fr(3).unwrap(), 0.1 * 3.0);
+        assert_eq!(sf0(&cpu), FpExceptions::INEXACT);
+
+        // An enabled inexact trap fires once the result is written
+        let fpsr = cpu.read_ar(AR::FPSR).unwrap();
+        cpu.write_ar(AR::FPSR, fpsr & !(FpExceptions::INEXACT.bits() as u64))
+            .unwrap();
+        let change = fma.plan(&cpu, &mut memory).unwrap();
+        assert_eq!(change.interrupts, vec![(InterruptVector::FPTrap, 1 << 13)]);
+
+        // An enabled invalid operation faults, leaving f3 and the flags
+        cpu.write_ar(AR::FPSR, fpsr & !(FpExceptions::INVALID.bits() as u64))
+            .unwrap();
+        cpu.set_fr(4, f64::INFINITY).unwrap();
+        cpu.set_fr(2, 0.0).unwrap();
+        let result = fma.execute(&mut cpu, &mut memory);
+        assert!(matches!(result, Err(EmulatorError::FPFault { isr: 1 })));
+        assert_eq!(cpu.get_fr(3).unwrap(), 0.1 * 3.0);
+        assert_eq!(sf0(&cpu), FpExceptions::INEXACT);
+
+        // fms and fnma negate the addend and the product
+        cpu.set_fr(4, 2.0).unwrap();
+        cpu.set_fr(2, 3.0).unwrap();
+        cpu.set_fr(5, 1.0).unwrap();
+        FusedMultiplyAdd::new(fields.clone(), FmaOp::Subtract, Precision::Dynamic, 0)
+            .execute(&mut cpu, &mut memory)
+            .unwrap();
+        assert_eq!(cpu.get_fr(3).unwrap(), 5.0);
+        FusedMultiplyAdd::new(fields, FmaOp::NegateMultiply, Precision::Dynamic, 0)
+            .execute(&mut cpu, &mut memory)
+            .unwrap();
+        assert_eq!(cpu.get_fr(3).unwrap(), -5.0);
+    }
 }
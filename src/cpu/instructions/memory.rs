@@ -2,8 +2,11 @@
 //!
 //! This module implements the memory access instructions for the IA-64 architecture.
 
-use super::{AddressingMode, Instruction, InstructionFields, RegisterType};
+use super::change::{AlatUpdate, StateChange, UnalignedAccess, WriteKind};
+use super::{Instruction, InstructionFields, RegisterType};
+use crate::cpu::registers::ar::AR;
 use crate::cpu::Cpu;
+use crate::decoder::completers::Completers;
 use crate::memory::Memory;
 use crate::EmulatorError;
 
@@ -33,6 +36,47 @@ pub enum CacheHint {
     Bias,
 }
 
+impl MemoryOrdering {
+    /// Ordering named by decoded completers
+    fn from_completers(completers: Completers) -> Self {
+        if completers.contains(Completers::FENCE) {
+            Self::Fence
+        } else if completers.contains(Completers::REL) {
+            Self::Release
+        } else if completers.contains(Completers::ACQ) {
+            Self::Acquire
+        } else {
+            Self::None
+        }
+    }
+}
+
+impl CacheHint {
+    /// Cache hint named by decoded completers
+    fn from_completers(completers: Completers) -> Self {
+        if completers.contains(Completers::BIAS) {
+            Self::Bias
+        } else if completers.contains(Completers::NTA) {
+            Self::NonTemporalAll
+        } else if completers.contains(Completers::NT1) {
+            Self::NonTemporal1
+        } else {
+            Self::Normal
+        }
+    }
+}
+
+impl From<CacheHint> for crate::memory::CacheHint {
+    fn from(hint: CacheHint) -> Self {
+        match hint {
+            CacheHint::Normal => Self::Normal,
+            CacheHint::NonTemporal1 => Self::NonTemporal1,
+            CacheHint::NonTemporalAll => Self::NonTemporalAll,
+            CacheHint::Bias => Self::Bias,
+        }
+    }
+}
+
 /// Memory speculation completers
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum MemorySpeculation {
@@ -48,6 +92,23 @@ pub enum MemorySpeculation {
     CheckClr,
 }
 
+impl MemorySpeculation {
+    /// Speculation named by decoded completers
+    fn from_completers(completers: Completers) -> Self {
+        if completers.contains(Completers::C_CLR) {
+            Self::CheckClr
+        } else if completers.contains(Completers::C_NC) {
+            Self::CheckNoClr
+        } else if completers.contains(Completers::A) {
+            Self::Advanced
+        } else if completers.contains(Completers::S) {
+            Self::Speculative
+        } else {
+            Self::None
+        }
+    }
+}
+
 /// Semaphore operation types
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum SemaphoreOp {
@@ -80,6 +141,8 @@ pub struct Load {
     ordering: MemoryOrdering,
     cache_hint: CacheHint,
     speculation: MemorySpeculation,
+    /// Restore the NaT bit from AR.UNAT (ld8.fill)
+    fill: bool,
 }
 
 /// Load sizes
@@ -95,6 +158,18 @@ pub enum LoadSize {
     Double,
 }
 
+impl LoadSize {
+    /// Size in bytes
+    pub fn bytes(self) -> usize {
+        match self {
+            LoadSize::Byte => 1,
+            LoadSize::Half => 2,
+            LoadSize::Word => 4,
+            LoadSize::Double => 8,
+        }
+    }
+}
+
 /// Semaphore instruction
 #[derive(Debug)]
 pub struct Semaphore {
@@ -113,6 +188,28 @@ pub struct Prefetch {
     cache_hint: CacheHint,
 }
 
+/// Speculation check instruction (chk.s)
+///
+/// Branches to the recovery code at IP + immediate if the source register
+/// is NaT, or a floating-point register holds NaTVal, i.e. a speculative
+/// load feeding it deferred a fault. The I-unit form checks general
+/// registers, the M-unit form general or floating-point registers.
+#[derive(Debug)]
+pub struct SpeculationCheck {
+    fields: InstructionFields,
+}
+
+/// Advanced load check instruction (chk.a)
+///
+/// Branches to the recovery code at IP + immediate if the ALAT has no entry
+/// for the target register, i.e. a store may have overwritten the data an
+/// advanced load fetched. The `clr` form also removes the entry it finds.
+#[derive(Debug)]
+pub struct AdvancedCheck {
+    fields: InstructionFields,
+    clear: bool,
+}
+
 impl Load {
     /// Create new LOAD instruction
     pub fn new(fields: InstructionFields, size: LoadSize) -> Self {
@@ -122,66 +219,78 @@ impl Load {
             ordering: MemoryOrdering::None,
             cache_hint: CacheHint::Normal,
             speculation: MemorySpeculation::None,
+            fill: false,
         }
     }
 
     /// Create new LOAD instruction with completers
-    pub fn from_decoded(
-        fields: InstructionFields,
-        size: LoadSize,
-        completers: Option<Vec<String>>,
-    ) -> Self {
+    pub fn from_decoded(fields: InstructionFields, size: LoadSize, completers: Completers) -> Self {
         let mut load = Self::new(fields, size);
 
-        // Parse completers if present
-        if let Some(completers) = completers {
-            for completer in completers {
-                match completer.as_str() {
-                    // Memory ordering
-                    "acq" => load.ordering = MemoryOrdering::Acquire,
-                    "rel" => load.ordering = MemoryOrdering::Release,
-                    "fence" => load.ordering = MemoryOrdering::Fence,
-                    // Cache hints
-                    "nt1" => load.cache_hint = CacheHint::NonTemporal1,
-                    "nta" => load.cache_hint = CacheHint::NonTemporalAll,
-                    "bias" => load.cache_hint = CacheHint::Bias,
-                    // Speculation
-                    "s" => load.speculation = MemorySpeculation::Speculative,
-                    "a" => load.speculation = MemorySpeculation::Advanced,
-                    "c.nc" => load.speculation = MemorySpeculation::CheckNoClr,
-                    "c.clr" => load.speculation = MemorySpeculation::CheckClr,
-                    "" => (), // Skip empty completers
-                    _ => (),  // Ignore unknown completers
-                }
-            }
-        }
+        load.ordering = MemoryOrdering::from_completers(completers);
+        load.cache_hint = CacheHint::from_completers(completers);
+        load.speculation = MemorySpeculation::from_completers(completers);
+        load.fill = completers.contains(Completers::FILL);
 
         load
     }
 
     /// Calculate effective address
     fn calc_effective_address(&self, cpu: &Cpu) -> Result<u64, EmulatorError> {
-        match self.fields.addressing.unwrap() {
-            AddressingMode::Indirect(reg) => cpu.get_gr(reg as usize),
-            AddressingMode::IndirectOffset(reg, offset) => {
-                let base = cpu.get_gr(reg as usize)?;
-                Ok(base.wrapping_add(offset as u64))
-            }
-            AddressingMode::IndirectIndex(base, index) => {
-                let base_val = cpu.get_gr(base as usize)?;
-                let index_val = cpu.get_gr(index as usize)?;
-                Ok(base_val.wrapping_add(index_val))
+        self.fields.addressing.unwrap().effective_address(cpu)
+    }
+
+    /// Defer a fault of ld.s: the target becomes NaT for chk.s to detect
+    fn defer(&self, mut change: StateChange) -> Result<StateChange, EmulatorError> {
+        match self.fields.destinations[0] {
+            RegisterType::GR(reg) => {
+                change.fixups.clear();
+                change.set_gr_nat(reg as usize, 0, true);
+                Ok(change)
             }
-            AddressingMode::Absolute(addr) => Ok(addr),
+            _ => Err(EmulatorError::ExecutionError(
+                "Invalid destination register type".to_string(),
+            )),
         }
     }
 }
 
 impl Instruction for Load {
-    fn execute(&self, cpu: &mut Cpu, memory: &mut Memory) -> Result<(), EmulatorError> {
+    fn plan(&self, cpu: &Cpu, memory: &mut Memory) -> Result<StateChange, EmulatorError> {
         // Check predicate
         if !cpu.get_pr(self.fields.qp as usize)? {
-            return Ok(());
+            return Ok(StateChange::default());
+        }
+
+        // The post-increment forms update the base whether or not the load
+        // itself is skipped or deferred
+        let mut change = self.access(cpu, memory)?;
+        let addressing = self.fields.addressing.unwrap();
+        if let Some((base, value, nat)) = addressing.base_update(cpu)? {
+            if self.fields.destinations[0] == RegisterType::GR(base as u8) {
+                return Err(EmulatorError::ExecutionError(
+                    "Illegal operation: load target is the updated base".to_string(),
+                ));
+            }
+            change.set_gr_nat(base, value, nat);
+        }
+        Ok(change)
+    }
+}
+
+impl Load {
+    /// Plan the load itself, without the base update
+    fn access(&self, cpu: &Cpu, memory: &mut Memory) -> Result<StateChange, EmulatorError> {
+        let mut change = StateChange::default();
+
+        // A NaT address faults, except that ld.s defers it
+        if self.fields.addressing.unwrap().is_nat(cpu)? {
+            if self.speculation == MemorySpeculation::Speculative {
+                return self.defer(change);
+            }
+            return Err(EmulatorError::NatConsumption(
+                "load from a NaT address".to_string(),
+            ));
         }
 
         // Calculate effective address
@@ -202,9 +311,9 @@ impl Instruction for Load {
 
         // Handle speculation
         match self.speculation {
-            MemorySpeculation::Speculative => {
-                // TODO: Implement speculative load tracking
-            }
+            // ld.s needs no bookkeeping: a deferred fault leaves the NaT
+            // bit that chk.s tests
+            MemorySpeculation::Speculative => {}
             MemorySpeculation::Advanced => {
                 // Add entry to ALAT
                 let reg = match self.fields.destinations[0] {
@@ -215,7 +324,12 @@ impl Instruction for Load {
                         ))
                     }
                 };
-                cpu.alat_add_entry(addr, self.size as u64, reg as u32, true)?;
+                change.alat.push(AlatUpdate::Add {
+                    addr,
+                    size: self.size.bytes() as u64,
+                    reg: reg as u32,
+                    is_integer: true,
+                });
             }
             MemorySpeculation::CheckNoClr | MemorySpeculation::CheckClr => {
                 // Check ALAT for entry
@@ -227,25 +341,81 @@ impl Instruction for Load {
                         ))
                     }
                 };
-                if !cpu.alat_check_register(reg as u32, true) {
-                    // No valid entry found, handle recovery
-                    return Ok(()); // Skip the load
+                change.alat.push(AlatUpdate::Check {
+                    reg: reg as u32,
+                    is_integer: true,
+                });
+                if cpu.alat_check_register(reg as u32, true) {
+                    // No store has touched the data since the advanced
+                    // load, so the register already holds it
+                    if matches!(self.speculation, MemorySpeculation::CheckClr) {
+                        change.alat.push(AlatUpdate::Remove {
+                            reg: reg as u32,
+                            is_integer: true,
+                        });
+                    }
+                    return Ok(change);
                 }
-                // Clear ALAT entry if requested
-                if matches!(self.speculation, MemorySpeculation::CheckClr) {
-                    cpu.alat_invalidate_overlap(addr, self.size as u64); // Invalidate based on memory address
+                // Otherwise load again; ld.c.nc tracks the new value
+                if matches!(self.speculation, MemorySpeculation::CheckNoClr) {
+                    change.alat.push(AlatUpdate::Add {
+                        addr,
+                        size: self.size.bytes() as u64,
+                        reg: reg as u32,
+                        is_integer: true,
+                    });
                 }
             }
             _ => (), // Normal load
         }
 
-        // Perform load based on size
-        let value = match self.size {
-            LoadSize::Byte => memory.read_u8(addr)? as u64,
-            LoadSize::Half => memory.read_u16(addr)? as u64,
-            LoadSize::Word => memory.read_u32(addr)? as u64,
-            LoadSize::Double => memory.read_u64(addr)?,
+        if self.fill {
+            // The NaT bit comes from bit addr{8:3} of AR.UNAT
+            let reg = match self.fields.destinations[0] {
+                RegisterType::GR(reg) => reg as usize,
+                _ => {
+                    return Err(EmulatorError::ExecutionError(
+                        "Invalid destination register type".to_string(),
+                    ))
+                }
+            };
+            cpu.require_alignment(addr, 8, false)?;
+            let value = memory.read_sized(addr, 8, cpu.data_endian())?;
+            let nat = cpu.read_ar(AR::UNAT)? & unat_mask(addr) != 0;
+            change.read(addr, 8, value);
+            change.set_gr_nat(reg, value, nat);
+            return Ok(change);
+        }
+
+        // Perform load based on size, in the byte order PSR.be selects;
+        // acquire loads of memory shared with host threads are host acquire
+        // loads, and unaligned loads the alignment policy fixes up are done
+        // byte by byte
+        let len = self.size.bytes();
+        let endian = cpu.data_endian();
+        let value = match cpu.check_alignment(addr, len, false) {
+            Err(e) => Err(e),
+            Ok(true) => {
+                change.fixups.push(UnalignedAccess {
+                    addr,
+                    len,
+                    store: false,
+                });
+                read_bytewise(memory, addr, len).map(|value| endian.convert(value, len))
+            }
+            Ok(false) if self.ordering == MemoryOrdering::Acquire => memory
+                .load_acquire(addr, len)
+                .map(|value| endian.convert(value, len)),
+            Ok(false) => memory.read_sized(addr, len, endian),
+        };
+        let value = match value {
+            Ok(value) => value,
+            Err(_) if self.speculation == MemorySpeculation::Speculative => {
+                return self.defer(change);
+            }
+            Err(e) => return Err(e),
         };
+        change.read(addr, self.size.bytes(), value);
 
         // Apply cache hints
         match self.cache_hint {
@@ -263,7 +433,7 @@ impl Instruction for Load {
 
         // Write to destination register
         match self.fields.destinations[0] {
-            RegisterType::GR(reg) => cpu.set_gr(reg as usize, value)?,
+            RegisterType::GR(reg) => change.set_gr(reg as usize, value),
             _ => {
                 return Err(EmulatorError::ExecutionError(
                     "Invalid destination register type".to_string(),
@@ -271,7 +441,7 @@ impl Instruction for Load {
             }
         }
 
-        Ok(())
+        Ok(change)
     }
 }
 
@@ -282,6 +452,8 @@ pub struct Store {
     size: StoreSize,
     ordering: MemoryOrdering,
     cache_hint: CacheHint,
+    /// Save the NaT bit to AR.UNAT (st8.spill)
+    spill: bool,
 }
 
 /// Store sizes
@@ -297,6 +469,18 @@ pub enum StoreSize {
     Double,
 }
 
+impl StoreSize {
+    /// Size in bytes
+    pub fn bytes(self) -> usize {
+        match self {
+            StoreSize::Byte => 1,
+            StoreSize::Half => 2,
+            StoreSize::Word => 4,
+            StoreSize::Double => 8,
+        }
+    }
+}
+
 impl Store {
     /// Create new STORE instruction
     pub fn new(fields: InstructionFields, size: StoreSize) -> Self {
@@ -305,6 +489,7 @@ impl Store {
             size,
             ordering: MemoryOrdering::None,
             cache_hint: CacheHint::Normal,
+            spill: false,
         }
     }
 
@@ -312,65 +497,51 @@ impl Store {
     pub fn from_decoded(
         fields: InstructionFields,
         size: StoreSize,
-        completers: Option<Vec<String>>,
+        completers: Completers,
     ) -> Self {
         let mut store = Self::new(fields, size);
 
-        // Parse completers if present
-        if let Some(completers) = completers {
-            for completer in completers {
-                match completer.as_str() {
-                    // Memory ordering
-                    "acq" => store.ordering = MemoryOrdering::Acquire,
-                    "rel" => store.ordering = MemoryOrdering::Release,
-                    "fence" => store.ordering = MemoryOrdering::Fence,
-                    // Cache hints
-                    "nt1" => store.cache_hint = CacheHint::NonTemporal1,
-                    "nta" => store.cache_hint = CacheHint::NonTemporalAll,
-                    "bias" => store.cache_hint = CacheHint::Bias,
-                    "" => (), // Skip empty completers
-                    _ => (),  // Ignore unknown completers
-                }
-            }
-        }
+        store.ordering = MemoryOrdering::from_completers(completers);
+        store.cache_hint = CacheHint::from_completers(completers);
+        store.spill = completers.contains(Completers::SPILL);
 
         store
     }
 
     /// Calculate effective address
     fn calc_effective_address(&self, cpu: &Cpu) -> Result<u64, EmulatorError> {
-        match self.fields.addressing.unwrap() {
-            AddressingMode::Indirect(reg) => cpu.get_gr(reg as usize),
-            AddressingMode::IndirectOffset(reg, offset) => {
-                let base = cpu.get_gr(reg as usize)?;
-                Ok(base.wrapping_add(offset as u64))
-            }
-            AddressingMode::IndirectIndex(base, index) => {
-                let base_val = cpu.get_gr(base as usize)?;
-                let index_val = cpu.get_gr(index as usize)?;
-                Ok(base_val.wrapping_add(index_val))
-            }
-            AddressingMode::Absolute(addr) => Ok(addr),
-        }
+        self.fields.addressing.unwrap().effective_address(cpu)
     }
 }
 
 impl Instruction for Store {
-    fn execute(&self, cpu: &mut Cpu, memory: &mut Memory) -> Result<(), EmulatorError> {
+    fn plan(&self, cpu: &Cpu, memory: &mut Memory) -> Result<StateChange, EmulatorError> {
         // Check predicate
         if !cpu.get_pr(self.fields.qp as usize)? {
-            return Ok(());
+            return Ok(StateChange::default());
         }
 
         // Get value to store
-        let value = match self.fields.sources[0] {
-            RegisterType::GR(reg) => cpu.get_gr(reg as usize)?,
+        let reg = match self.fields.sources[0] {
+            RegisterType::GR(reg) => reg as usize,
             _ => {
                 return Err(EmulatorError::ExecutionError(
                     "Invalid source register type".to_string(),
                 ))
             }
         };
+        let value = cpu.get_gr(reg)?;
+        if !self.spill && cpu.get_nat(reg)? {
+            return Err(EmulatorError::NatConsumption(format!(
+                "store from r{}",
+                reg
+            )));
+        }
+        if self.fields.addressing.unwrap().is_nat(cpu)? {
+            return Err(EmulatorError::NatConsumption(
+                "store to a NaT address".to_string(),
+            ));
+        }
 
         // Calculate effective address
         let addr = self.calc_effective_address(cpu)?;
@@ -402,22 +573,36 @@ impl Instruction for Store {
             _ => (), // Normal caching
         }
 
-        // Perform store based on size
-        match self.size {
-            StoreSize::Byte => memory.write_u8(addr, value as u8)?,
-            StoreSize::Half => memory.write_u16(addr, value as u16)?,
-            StoreSize::Word => memory.write_u32(addr, value as u32)?,
-            StoreSize::Double => memory.write_u64(addr, value)?,
+        // Perform store based on size, in the byte order PSR.be selects
+        let mut change = StateChange::default();
+        let len = self.size.bytes();
+        let endian = cpu.data_endian();
+        if self.spill {
+            // The NaT bit goes to bit addr{8:3} of AR.UNAT
+            cpu.require_alignment(addr, 8, true)?;
+            change.write(addr, 8, value, endian, WriteKind::Normal);
+            let unat = cpu.read_ar(AR::UNAT)?;
+            let mask = unat_mask(addr);
+            let nat = cpu.get_nat(reg)?;
+            change.set_ar(AR::UNAT, if nat { unat | mask } else { unat & !mask });
+        } else if cpu.check_alignment(addr, len, true)? {
+            change.write(addr, len, value, endian, WriteKind::Bytewise);
+            change.fixups.push(UnalignedAccess {
+                addr,
+                len,
+                store: true,
+            });
+        } else if self.ordering == MemoryOrdering::Release {
+            change.write(addr, len, value, endian, WriteKind::Release);
+        } else {
+            change.write(addr, len, value, endian, WriteKind::Normal);
         }
 
-        // Invalidate any overlapping ALAT entries
-        for reg in &self.fields.destinations {
-            if let RegisterType::GR(reg) = reg {
-                cpu.alat_invalidate_overlap((*reg as u64) << 3, 8); // 8 bytes for 64-bit register
-            }
+        if let Some((base, value, nat)) = self.fields.addressing.unwrap().base_update(cpu)? {
+            change.set_gr_nat(base, value, nat);
         }
 
-        Ok(())
+        Ok(change)
     }
 }
 
@@ -438,54 +623,34 @@ impl Semaphore {
         fields: InstructionFields,
         op: SemaphoreOp,
         size: LoadSize,
-        completers: Option<Vec<String>>,
+        completers: Completers,
     ) -> Self {
         let mut sem = Self::new(fields, op, size);
 
-        // Parse completers if present
-        if let Some(completers) = completers {
-            for completer in completers {
-                match completer.as_str() {
-                    // Memory ordering
-                    "acq" => sem.ordering = MemoryOrdering::Acquire,
-                    "rel" => sem.ordering = MemoryOrdering::Release,
-                    "fence" => sem.ordering = MemoryOrdering::Fence,
-                    // Cache hints
-                    "nt1" => sem.cache_hint = CacheHint::NonTemporal1,
-                    "nta" => sem.cache_hint = CacheHint::NonTemporalAll,
-                    "bias" => sem.cache_hint = CacheHint::Bias,
-                    "" => (), // Skip empty completers
-                    _ => (),  // Ignore unknown completers
-                }
-            }
-        }
+        sem.ordering = MemoryOrdering::from_completers(completers);
+        sem.cache_hint = CacheHint::from_completers(completers);
 
         sem
     }
 
     /// Calculate effective address
     fn calc_effective_address(&self, cpu: &Cpu) -> Result<u64, EmulatorError> {
-        match self.fields.addressing.unwrap() {
-            AddressingMode::Indirect(reg) => cpu.get_gr(reg as usize),
-            AddressingMode::IndirectOffset(reg, offset) => {
-                let base = cpu.get_gr(reg as usize)?;
-                Ok(base.wrapping_add(offset as u64))
-            }
-            AddressingMode::IndirectIndex(base, index) => {
-                let base_val = cpu.get_gr(base as usize)?;
-                let index_val = cpu.get_gr(index as usize)?;
-                Ok(base_val.wrapping_add(index_val))
-            }
-            AddressingMode::Absolute(addr) => Ok(addr),
-        }
+        self.fields.addressing.unwrap().effective_address(cpu)
     }
 }
 
 impl Instruction for Semaphore {
-    fn execute(&self, cpu: &mut Cpu, memory: &mut Memory) -> Result<(), EmulatorError> {
+    fn plan(&self, cpu: &Cpu, memory: &mut Memory) -> Result<StateChange, EmulatorError> {
         // Check predicate
         if !cpu.get_pr(self.fields.qp as usize)? {
-            return Ok(());
+            return Ok(StateChange::default());
+        }
+
+        // Semaphores have no speculative form, so any NaT operand faults
+        if self.fields.addressing.unwrap().is_nat(cpu)? || self.fields.sources_nat(cpu)? {
+            return Err(EmulatorError::NatConsumption(
+                "semaphore operand".to_string(),
+            ));
         }
 
         // Calculate effective address
@@ -519,27 +684,28 @@ impl Instruction for Semaphore {
             }
         };
 
-        // Perform atomic operation
+        // Perform atomic operation; the old value is read now and the
+        // destination and memory are written together when applied
+        let mut change = StateChange::default();
+        let len = self.size.bytes();
+        cpu.require_alignment(addr, len, true)?;
+        let endian = cpu.data_endian();
+        let kind = match self.ordering {
+            // Release ordering completes the write before later accesses
+            MemoryOrdering::Release | MemoryOrdering::Fence => WriteKind::Release,
+            _ => WriteKind::Normal,
+        };
         match self.op {
             SemaphoreOp::Xchg => {
                 // Read old value
-                let old_value = match self.size {
-                    LoadSize::Byte => memory.read_u8(addr)? as u64,
-                    LoadSize::Half => memory.read_u16(addr)? as u64,
-                    LoadSize::Word => memory.read_u32(addr)? as u64,
-                    LoadSize::Double => memory.read_u64(addr)?,
-                };
+                let old_value = memory.read_sized(addr, len, endian)?;
 
                 // Write new value
-                match self.size {
-                    LoadSize::Byte => memory.write_u8(addr, src1 as u8)?,
-                    LoadSize::Half => memory.write_u16(addr, src1 as u16)?,
-                    LoadSize::Word => memory.write_u32(addr, src1 as u32)?,
-                    LoadSize::Double => memory.write_u64(addr, src1)?,
-                }
+                change.read(addr, len, old_value);
+                change.write(addr, len, src1, endian, kind);
 
                 // Store old value in destination register
-                cpu.set_gr(dst, old_value)?;
+                change.set_gr(dst, old_value);
             }
             SemaphoreOp::Cmpxchg => {
                 // Get compare value from second source register
@@ -553,55 +719,29 @@ impl Instruction for Semaphore {
                 };
 
                 // Read current value
-                let current = match self.size {
-                    LoadSize::Byte => memory.read_u8(addr)? as u64,
-                    LoadSize::Half => memory.read_u16(addr)? as u64,
-                    LoadSize::Word => memory.read_u32(addr)? as u64,
-                    LoadSize::Double => memory.read_u64(addr)?,
-                };
+                let current = memory.read_sized(addr, len, endian)?;
 
                 // Store current value in destination register
-                cpu.set_gr(dst, current)?;
+                change.read(addr, len, current);
+                change.set_gr(dst, current);
 
                 // If compare matches, write new value
                 if current == src2 {
-                    match self.size {
-                        LoadSize::Byte => memory.write_u8(addr, src1 as u8)?,
-                        LoadSize::Half => memory.write_u16(addr, src1 as u16)?,
-                        LoadSize::Word => memory.write_u32(addr, src1 as u32)?,
-                        LoadSize::Double => memory.write_u64(addr, src1)?,
-                    }
+                    change.write(addr, len, src1, endian, kind);
                 }
             }
             SemaphoreOp::Fetchadd => {
                 // Read current value
-                let current = match self.size {
-                    LoadSize::Byte => memory.read_u8(addr)? as u64,
-                    LoadSize::Half => memory.read_u16(addr)? as u64,
-                    LoadSize::Word => memory.read_u32(addr)? as u64,
-                    LoadSize::Double => memory.read_u64(addr)?,
-                };
+                let current = memory.read_sized(addr, len, endian)?;
 
                 // Store current value in destination register
-                cpu.set_gr(dst, current)?;
+                change.read(addr, len, current);
+                change.set_gr(dst, current);
 
                 // Add increment and write back
                 let new_value = current.wrapping_add(src1);
-                match self.size {
-                    LoadSize::Byte => memory.write_u8(addr, new_value as u8)?,
-                    LoadSize::Half => memory.write_u16(addr, new_value as u16)?,
-                    LoadSize::Word => memory.write_u32(addr, new_value as u32)?,
-                    LoadSize::Double => memory.write_u64(addr, new_value)?,
-                }
-            }
-        }
-
-        // Handle memory ordering
-        match self.ordering {
-            MemoryOrdering::Release | MemoryOrdering::Fence => {
-                memory.fence()?;
+                change.write(addr, len, new_value, endian, kind);
             }
-            _ => (), // Normal memory access
         }
 
         // Apply cache hints
@@ -618,7 +758,7 @@ impl Instruction for Semaphore {
             _ => (), // Normal caching
         }
 
-        Ok(())
+        Ok(change)
     }
 }
 
@@ -636,99 +776,159 @@ impl Prefetch {
     pub fn from_decoded(
         fields: InstructionFields,
         prefetch_type: PrefetchType,
-        completers: Option<Vec<String>>,
+        completers: Completers,
     ) -> Self {
         let mut prefetch = Self::new(fields, prefetch_type);
 
-        // Parse completers if present
-        if let Some(completers) = completers {
-            for completer in completers {
-                match completer.as_str() {
-                    // Cache hints
-                    "nt1" => prefetch.cache_hint = CacheHint::NonTemporal1,
-                    "nta" => prefetch.cache_hint = CacheHint::NonTemporalAll,
-                    "bias" => prefetch.cache_hint = CacheHint::Bias,
-                    "" => (), // Skip empty completers
-                    _ => (),  // Ignore unknown completers
-                }
-            }
-        }
+        prefetch.cache_hint = CacheHint::from_completers(completers);
 
         prefetch
     }
 
     /// Calculate effective address
     fn calc_effective_address(&self, cpu: &Cpu) -> Result<u64, EmulatorError> {
-        match self.fields.addressing.unwrap() {
-            AddressingMode::Indirect(reg) => cpu.get_gr(reg as usize),
-            AddressingMode::IndirectOffset(reg, offset) => {
-                let base = cpu.get_gr(reg as usize)?;
-                Ok(base.wrapping_add(offset as u64))
-            }
-            AddressingMode::IndirectIndex(base, index) => {
-                let base_val = cpu.get_gr(base as usize)?;
-                let index_val = cpu.get_gr(index as usize)?;
-                Ok(base_val.wrapping_add(index_val))
-            }
-            AddressingMode::Absolute(addr) => Ok(addr),
-        }
+        self.fields.addressing.unwrap().effective_address(cpu)
     }
 }
 
 impl Instruction for Prefetch {
-    fn execute(&self, cpu: &mut Cpu, memory: &mut Memory) -> Result<(), EmulatorError> {
+    fn plan(&self, cpu: &Cpu, memory: &mut Memory) -> Result<StateChange, EmulatorError> {
         // Check predicate
         if !cpu.get_pr(self.fields.qp as usize)? {
-            return Ok(());
+            return Ok(StateChange::default());
         }
 
         // Calculate effective address
         let addr = self.calc_effective_address(cpu)?;
 
-        // Handle different prefetch types
+        // Handle different prefetch types; a prefetch only fills caches, so
+        // it changes no architectural state
         match self.prefetch_type {
             PrefetchType::Normal => {
-                // Basic prefetch - just touch the memory location
-                let _ = memory.read_u8(addr);
+                // Non-faulting prefetch - drop it if the line is inaccessible
+                let _ = memory.prefetch(addr, self.cache_hint.into());
             }
             PrefetchType::Fault => {
                 // Must generate fault if access invalid
-                memory.read_u8(addr)?;
+                memory.prefetch(addr, self.cache_hint.into())?;
             }
             PrefetchType::Exclusive => {
                 // Prefetch for exclusive access - invalidate other caches
-                let _ = memory.read_u8(addr);
+                let _ = memory.prefetch(addr, self.cache_hint.into());
                 // TODO: Implement cache invalidation for other processors
             }
             PrefetchType::WriteBack => {
                 // Write back if cache line is dirty
-                let _ = memory.read_u8(addr);
+                let _ = memory.prefetch(addr, self.cache_hint.into());
                 // TODO: Implement cache line writeback
             }
         }
 
-        // Apply cache hints
-        match self.cache_hint {
-            CacheHint::NonTemporal1 => {
-                // TODO: Implement L1 cache bypass
-            }
-            CacheHint::NonTemporalAll => {
-                // TODO: Implement all cache levels bypass
+        Ok(StateChange::default())
+    }
+}
+
+impl SpeculationCheck {
+    /// Create new chk.s instruction
+    pub fn new(fields: InstructionFields) -> Self {
+        Self { fields }
+    }
+}
+
+impl Instruction for SpeculationCheck {
+    fn plan(&self, cpu: &Cpu, _memory: &mut Memory) -> Result<StateChange, EmulatorError> {
+        let mut change = StateChange::default();
+
+        // Check predicate
+        if !cpu.get_pr(self.fields.qp as usize)? {
+            return Ok(change);
+        }
+
+        let nat = match self.fields.sources[0] {
+            RegisterType::GR(reg) => cpu.get_nat(reg as usize)?,
+            RegisterType::FR(reg) => cpu.get_fr_register(reg as usize)?.is_natval(),
+            _ => {
+                return Err(EmulatorError::ExecutionError(
+                    "Invalid source register type".to_string(),
+                ))
             }
-            CacheHint::Bias => {
-                // TODO: Implement cache bias hint
+        };
+        if nat {
+            change.branch(recovery_target(cpu, &self.fields, "chk.s")?);
+        }
+        Ok(change)
+    }
+}
+
+impl AdvancedCheck {
+    /// Create new chk.a instruction, removing the entry it finds if `clear`
+    pub fn new(fields: InstructionFields, clear: bool) -> Self {
+        Self { fields, clear }
+    }
+}
+
+impl Instruction for AdvancedCheck {
+    fn plan(&self, cpu: &Cpu, _memory: &mut Memory) -> Result<StateChange, EmulatorError> {
+        let mut change = StateChange::default();
+
+        // Check predicate
+        if !cpu.get_pr(self.fields.qp as usize)? {
+            return Ok(change);
+        }
+
+        let (reg, is_integer) = match self.fields.sources[0] {
+            RegisterType::GR(reg) => (reg as u32, true),
+            RegisterType::FR(reg) => (reg as u32, false),
+            _ => {
+                return Err(EmulatorError::ExecutionError(
+                    "Invalid source register type".to_string(),
+                ))
             }
-            _ => (), // Normal caching
+        };
+        change.alat.push(AlatUpdate::Check { reg, is_integer });
+        if !cpu.alat_check_register(reg, is_integer) {
+            change.branch(recovery_target(cpu, &self.fields, "chk.a")?);
+        } else if self.clear {
+            change.alat.push(AlatUpdate::Remove { reg, is_integer });
         }
+        Ok(change)
+    }
+}
+
+/// Bit of AR.UNAT holding the NaT bit of a register spilled to `addr`
+fn unat_mask(addr: u64) -> u64 {
+    1 << ((addr >> 3) & 0x3F)
+}
 
-        Ok(())
+/// Load `len` bytes at `addr` one at a time, little-endian
+fn read_bytewise(memory: &mut Memory, addr: u64, len: usize) -> Result<u64, EmulatorError> {
+    let mut value = 0;
+    for i in 0..len as u64 {
+        value |= (memory.read_u8(addr.wrapping_add(i))? as u64) << (i * 8);
     }
+    Ok(value)
+}
+
+/// Recovery code of a failed check, IP-relative
+fn recovery_target(
+    cpu: &Cpu,
+    fields: &InstructionFields,
+    name: &str,
+) -> Result<u64, EmulatorError> {
+    let offset = fields.immediate.ok_or_else(|| {
+        EmulatorError::ExecutionError(format!("{} without a recovery offset", name))
+    })?;
+    Ok(cpu.ip.wrapping_add(offset as u64))
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::cpu::instructions::AddressingMode;
+    use crate::cpu::PSRFlags;
+    use crate::memory::view::Endian;
     use crate::memory::{Memory, Permissions};
+    use std::sync::atomic::Ordering;
 
     fn setup_test() -> (Cpu, Memory, InstructionFields) {
         let mut cpu = Cpu::new();
@@ -753,10 +953,10 @@ mod tests {
 
     #[test]
     fn test_load_completers() {
-        let (mut cpu, mut memory, fields) = setup_test();
+        let (_cpu, _memory, fields) = setup_test();
 
         // Test load with memory ordering completers
-        let completers = Some(vec!["acq".to_string(), "nt1".to_string(), "s".to_string()]);
+        let completers = Completers::ACQ | Completers::NT1 | Completers::S;
 
         let load = Load::from_decoded(fields.clone(), LoadSize::Double, completers);
 
@@ -766,19 +966,15 @@ mod tests {
         assert!(matches!(load.speculation, MemorySpeculation::Speculative));
 
         // Test load with default completers
-        let load = Load::from_decoded(fields.clone(), LoadSize::Double, None);
+        let load = Load::from_decoded(fields.clone(), LoadSize::Double, Completers::NONE);
 
         // Verify default values
         assert!(matches!(load.ordering, MemoryOrdering::None));
         assert!(matches!(load.cache_hint, CacheHint::Normal));
         assert!(matches!(load.speculation, MemorySpeculation::None));
 
-        // Test load with mixed completers
-        let completers = Some(vec![
-            "fence".to_string(),
-            "".to_string(), // Empty completer should be ignored
-            "nta".to_string(),
-        ]);
+        // Test load with several completers
+        let completers = Completers::FENCE | Completers::NTA;
 
         let load = Load::from_decoded(fields, LoadSize::Double, completers);
 
@@ -788,12 +984,113 @@ mod tests {
         assert!(matches!(load.speculation, MemorySpeculation::None));
     }
 
+    #[test]
+    fn test_shared_acquire_release() {
+        let (mut cpu, mut memory, mut fields) = setup_test();
+        let host = memory
+            .map_shared(0x9000, 0x100, Permissions::ReadWrite)
+            .unwrap();
+
+        // st4.rel publishes to the host, ld8.acq reads what it published
+        fields.addressing = Some(AddressingMode::Absolute(0x9008));
+        fields.sources = vec![RegisterType::GR(3)];
+        cpu.set_gr(3, 0x1234_5678).unwrap();
+        Store::from_decoded(fields.clone(), StoreSize::Word, Completers::REL)
+            .execute(&mut cpu, &mut memory)
+            .unwrap();
+        assert_eq!(host.load(8, 4, Ordering::Acquire).unwrap(), 0x1234_5678);
+
+        host.store(0x10, 8, u64::MAX, Ordering::Release).unwrap();
+        fields.addressing = Some(AddressingMode::Absolute(0x9010));
+        let load = Load::from_decoded(fields.clone(), LoadSize::Double, Completers::ACQ);
+        load.execute(&mut cpu, &mut memory).unwrap();
+        assert_eq!(cpu.get_gr(2).unwrap(), u64::MAX);
+
+        // Misaligned ordered accesses fault
+        fields.addressing = Some(AddressingMode::Absolute(0x9014));
+        let load = Load::from_decoded(fields, LoadSize::Double, Completers::ACQ);
+        assert!(load.execute(&mut cpu, &mut memory).is_err());
+    }
+
+    #[test]
+    fn test_speculative_load_nat() {
+        let (mut cpu, mut memory, mut fields) = setup_test();
+        let speculative = Completers::S;
+
+        // A faulting speculative load sets the target NaT instead of failing
+        fields.addressing = Some(AddressingMode::Absolute(0x8000));
+        let load = Load::from_decoded(fields.clone(), LoadSize::Double, speculative);
+        cpu.set_gr(2, 7).unwrap();
+        load.execute(&mut cpu, &mut memory).unwrap();
+        assert_eq!(cpu.get_gr(2).unwrap(), 0);
+        assert!(cpu.get_nat(2).unwrap());
+        assert!(Load::new(fields.clone(), LoadSize::Double)
+            .execute(&mut cpu, &mut memory)
+            .is_err());
+
+        // Storing a NaT faults; st8.spill saves it to AR.UNAT instead
+        fields.addressing = Some(AddressingMode::Absolute(0x1008));
+        fields.sources = vec![RegisterType::GR(2)];
+        assert!(Store::new(fields.clone(), StoreSize::Double)
+            .execute(&mut cpu, &mut memory)
+            .is_err());
+        let spill = Completers::SPILL;
+        Store::from_decoded(fields.clone(), StoreSize::Double, spill)
+            .execute(&mut cpu, &mut memory)
+            .unwrap();
+        assert_eq!(
+            cpu.read_ar(crate::cpu::registers::ar::AR::UNAT).unwrap(),
+            1 << 1
+        );
+
+        // ld8.fill restores it
+        let fill = Completers::FILL;
+        let mut fill_fields = fields.clone();
+        fill_fields.destinations = vec![RegisterType::GR(3)];
+        Load::from_decoded(fill_fields, LoadSize::Double, fill)
+            .execute(&mut cpu, &mut memory)
+            .unwrap();
+        assert!(cpu.get_nat(3).unwrap());
+
+        // A successful speculative load clears the NaT bit
+        Load::from_decoded(fields, LoadSize::Double, speculative)
+            .execute(&mut cpu, &mut memory)
+            .unwrap();
+        assert!(!cpu.get_nat(2).unwrap());
+    }
+
+    #[test]
+    fn test_nat_address() {
+        let (mut cpu, mut memory, mut fields) = setup_test();
+        cpu.set_gr(1, 0x1000).unwrap();
+        cpu.set_nat(1, true).unwrap();
+        fields.addressing = Some(AddressingMode::Indirect(1));
+
+        // Loading through a NaT address faults
+        let result = Load::new(fields.clone(), LoadSize::Double).execute(&mut cpu, &mut memory);
+        assert!(matches!(result, Err(EmulatorError::NatConsumption(_))));
+
+        // ld.s defers it to the target
+        Load::from_decoded(fields.clone(), LoadSize::Double, Completers::S)
+            .execute(&mut cpu, &mut memory)
+            .unwrap();
+        assert!(cpu.get_nat(2).unwrap());
+
+        // Stores and semaphores fault
+        fields.sources = vec![RegisterType::GR(3)];
+        let result = Store::new(fields.clone(), StoreSize::Double).execute(&mut cpu, &mut memory);
+        assert!(matches!(result, Err(EmulatorError::NatConsumption(_))));
+        let xchg = Semaphore::new(fields, SemaphoreOp::Xchg, LoadSize::Double);
+        let result = xchg.execute(&mut cpu, &mut memory);
+        assert!(matches!(result, Err(EmulatorError::NatConsumption(_))));
+    }
+
     #[test]
     fn test_store_completers() {
-        let (mut cpu, mut memory, fields) = setup_test();
+        let (_cpu, _memory, fields) = setup_test();
 
         // Test store with memory ordering completers
-        let completers = Some(vec!["rel".to_string(), "bias".to_string()]);
+        let completers = Completers::REL | Completers::BIAS;
 
         let store = Store::from_decoded(fields.clone(), StoreSize::Double, completers);
 
@@ -802,18 +1099,14 @@ mod tests {
         assert!(matches!(store.cache_hint, CacheHint::Bias));
 
         // Test store with default completers
-        let store = Store::from_decoded(fields.clone(), StoreSize::Double, None);
+        let store = Store::from_decoded(fields.clone(), StoreSize::Double, Completers::NONE);
 
         // Verify default values
         assert!(matches!(store.ordering, MemoryOrdering::None));
         assert!(matches!(store.cache_hint, CacheHint::Normal));
 
-        // Test store with mixed completers
-        let completers = Some(vec![
-            "fence".to_string(),
-            "".to_string(), // Empty completer should be ignored
-            "nt1".to_string(),
-        ]);
+        // Test store with several completers
+        let completers = Completers::FENCE | Completers::NT1;
 
         let store = Store::from_decoded(fields, StoreSize::Double, completers);
 
@@ -823,12 +1116,11 @@ mod tests {
     }
 
     #[test]
-    #[ignore = "ALAT speculation behavior needs to be fixed"]
     fn test_memory_speculation() {
         let (mut cpu, mut memory, fields) = setup_test();
 
         // Test advanced load
-        let completers = Some(vec!["a".to_string()]);
+        let completers = Completers::A;
         let load = Load::from_decoded(fields.clone(), LoadSize::Double, completers);
 
         // Write test value to memory
@@ -842,7 +1134,7 @@ mod tests {
         assert_eq!(cpu.get_gr(2).unwrap(), 0x1234_5678_9ABC_DEF0);
 
         // Test check load with clear
-        let completers = Some(vec!["c.clr".to_string()]);
+        let completers = Completers::C_CLR;
         let check_load = Load::from_decoded(fields.clone(), LoadSize::Double, completers);
 
         // Execute check load
@@ -852,7 +1144,7 @@ mod tests {
         assert!(!cpu.alat_check_register(2, true));
 
         // Test check load with no clear
-        let completers = Some(vec!["c.nc".to_string()]);
+        let completers = Completers::C_NC;
         let check_load = Load::from_decoded(fields, LoadSize::Double, completers);
 
         // Add new ALAT entry
@@ -865,6 +1157,47 @@ mod tests {
         assert!(cpu.alat_check_register(2, true));
     }
 
+    #[test]
+    fn test_store_invalidates_alat() {
+        let (mut cpu, mut memory, mut fields) = setup_test();
+        memory.write_u64(0x1000, 1).unwrap();
+        let advanced = Load::from_decoded(fields.clone(), LoadSize::Double, Completers::A);
+        let check = Load::from_decoded(fields.clone(), LoadSize::Double, Completers::C_NC);
+        advanced.execute(&mut cpu, &mut memory).unwrap();
+
+        // A store elsewhere leaves the entry, so ld.c keeps the old value
+        fields.addressing = Some(AddressingMode::Absolute(0x1800));
+        cpu.set_gr(1, 2).unwrap();
+        Store::new(fields.clone(), StoreSize::Double)
+            .execute(&mut cpu, &mut memory)
+            .unwrap();
+        memory.write_u64(0x1000, 3).unwrap();
+        check.execute(&mut cpu, &mut memory).unwrap();
+        assert_eq!(cpu.get_gr(2).unwrap(), 1);
+
+        // A one-byte store into the loaded data invalidates it, whatever
+        // register it stores, and ld.c loads again, tracking the new value
+        fields.addressing = Some(AddressingMode::Absolute(0x1007));
+        Store::new(fields.clone(), StoreSize::Byte)
+            .execute(&mut cpu, &mut memory)
+            .unwrap();
+        assert!(!cpu.alat_check_register(2, true));
+        check.execute(&mut cpu, &mut memory).unwrap();
+        assert_eq!(cpu.get_gr(2).unwrap(), 0x0200_0000_0000_0003);
+        assert!(cpu.alat_check_register(2, true));
+
+        // as do semaphores and st8.spill
+        fields.destinations = vec![RegisterType::GR(4)];
+        fields.addressing = Some(AddressingMode::Absolute(0x1004));
+        Semaphore::new(fields.clone(), SemaphoreOp::Xchg, LoadSize::Word)
+            .execute(&mut cpu, &mut memory)
+            .unwrap();
+        assert!(!cpu.alat_check_register(2, true));
+        advanced.execute(&mut cpu, &mut memory).unwrap();
+        cpu.spill_gr(&mut memory, 1, 0x1000).unwrap();
+        assert!(!cpu.alat_check_register(2, true));
+    }
+
     #[test]
     fn test_load_addressing_modes() {
         let (mut cpu, mut memory, mut fields) = setup_test();
@@ -892,6 +1225,17 @@ mod tests {
         fields.addressing = Some(AddressingMode::Absolute(0x1100));
         let load = Load::new(fields.clone(), LoadSize::Double);
         load.execute(&mut cpu, &mut memory).unwrap();
+
+        // Test IP-relative addressing from the executing bundle
+        memory.write_u64(0x1230, 0xfeed).unwrap();
+        cpu.ip = 0x1200;
+        fields.addressing = Some(AddressingMode::IpRelative(0x30));
+        let load = Load::new(fields.clone(), LoadSize::Double);
+        load.execute(&mut cpu, &mut memory).unwrap();
+        assert_eq!(
+            cpu.get_gr(fields.destinations[0].get_reg_num()).unwrap(),
+            0xfeed
+        );
     }
 
     #[test]
@@ -972,6 +1316,100 @@ mod tests {
         store.execute(&mut cpu, &mut memory).unwrap();
     }
 
+    #[test]
+    fn test_unaligned_policy() {
+        use crate::cpu::unaligned::AlignmentPolicy;
+
+        let (mut cpu, mut memory, mut fields) = setup_test();
+        fields.addressing = Some(AddressingMode::Absolute(0x1003));
+        memory.write_u64(0x1003, 0x1122_3344_5566_7788).unwrap();
+        let load = Load::new(fields.clone(), LoadSize::Double);
+        let store = Store::new(fields.clone(), StoreSize::Word);
+
+        // Allowed as before by default
+        load.execute(&mut cpu, &mut memory).unwrap();
+        assert_eq!(cpu.get_gr(2).unwrap(), 0x1122_3344_5566_7788);
+
+        cpu.alignment_policy = AlignmentPolicy::Fault;
+        let err = load.execute(&mut cpu, &mut memory).unwrap_err();
+        assert!(err.to_string().contains("Unaligned data reference"));
+        // A speculative load defers the fault
+        let speculative = Load::from_decoded(fields.clone(), LoadSize::Double, Completers::S);
+        speculative.execute(&mut cpu, &mut memory).unwrap();
+        assert!(cpu.get_nat(2).unwrap());
+
+        cpu.alignment_policy = AlignmentPolicy::Fixup;
+        cpu.ip = 0x4000;
+        cpu.slot = 1;
+        load.execute(&mut cpu, &mut memory).unwrap();
+        assert_eq!(cpu.get_gr(2).unwrap(), 0x1122_3344_5566_7788);
+        cpu.set_gr(1, 0xAABB_CCDD).unwrap();
+        store.execute(&mut cpu, &mut memory).unwrap();
+        assert_eq!(memory.read_u64(0x1003).unwrap(), 0x1122_3344_AABB_CCDD);
+
+        let sites = cpu.unaligned_fixups.sites();
+        assert_eq!(sites.len(), 1);
+        assert_eq!(
+            (sites[0].ip, sites[0].slot, sites[0].loads, sites[0].stores),
+            (0x4000, 1, 1, 1)
+        );
+        // Aligned accesses are not counted
+        fields.addressing = Some(AddressingMode::Absolute(0x1008));
+        Load::new(fields, LoadSize::Double)
+            .execute(&mut cpu, &mut memory)
+            .unwrap();
+        assert_eq!(cpu.unaligned_fixups.total(), 2);
+    }
+
+    #[test]
+    fn test_alignment_check() {
+        use crate::cpu::unaligned::AlignmentPolicy;
+
+        let (mut cpu, mut memory, mut fields) = setup_test();
+        fields.addressing = Some(AddressingMode::Absolute(0x1004));
+        cpu.alignment_policy = AlignmentPolicy::Fixup;
+        let load = Load::new(fields.clone(), LoadSize::Double);
+        load.execute(&mut cpu, &mut memory).unwrap();
+
+        // PSR.ac faults the access the policy would fix up
+        cpu.system_regs.cr.set(PSRFlags::AC, true);
+        assert!(matches!(
+            load.execute(&mut cpu, &mut memory),
+            Err(EmulatorError::UnalignedReference {
+                addr: 0x1004,
+                len: 8,
+                store: false
+            })
+        ));
+        let store = Store::new(fields.clone(), StoreSize::Word);
+        store.execute(&mut cpu, &mut memory).unwrap();
+        assert!(matches!(
+            Store::new(fields.clone(), StoreSize::Double).execute(&mut cpu, &mut memory),
+            Err(EmulatorError::UnalignedReference { store: true, .. })
+        ));
+
+        // Spills, fills and semaphores fault whatever PSR.ac says
+        cpu.system_regs.cr.set(PSRFlags::AC, false);
+        let spill = Store::from_decoded(fields.clone(), StoreSize::Double, Completers::SPILL);
+        assert!(matches!(
+            spill.execute(&mut cpu, &mut memory),
+            Err(EmulatorError::UnalignedReference { store: true, .. })
+        ));
+        let fill = Load::from_decoded(fields.clone(), LoadSize::Double, Completers::FILL);
+        assert!(matches!(
+            fill.execute(&mut cpu, &mut memory),
+            Err(EmulatorError::UnalignedReference { store: false, .. })
+        ));
+        memory.write_u64(0x1000, 0).unwrap();
+        let sem = Semaphore::new(fields.clone(), SemaphoreOp::Fetchadd, LoadSize::Double);
+        assert!(sem.execute(&mut cpu, &mut memory).is_err());
+        assert_eq!(memory.read_u64(0x1000).unwrap(), 0);
+        let sem = Semaphore::new(fields, SemaphoreOp::Xchg, LoadSize::Word);
+        cpu.set_gr(1, 7).unwrap();
+        sem.execute(&mut cpu, &mut memory).unwrap();
+        assert_eq!(memory.read_u32(0x1004).unwrap(), 7);
+    }
+
     #[test]
     fn test_predicated_memory_operations() {
         let (mut cpu, mut memory, mut fields) = setup_test();
@@ -1068,12 +1506,81 @@ mod tests {
         assert_eq!(memory.read_u64(0x1000).unwrap(), 0x1010); // New value in memory
     }
 
+    #[test]
+    fn test_big_endian() {
+        let (mut cpu, mut memory, mut fields) = setup_test();
+        fields.addressing = Some(AddressingMode::Absolute(0x1000));
+        cpu.system_regs.cr.set(PSRFlags::BE, true);
+        assert_eq!(cpu.data_endian(), Endian::Big);
+
+        // Stores lay the most significant byte first
+        cpu.set_gr(1, 0x1122_3344).unwrap();
+        let store = Store::new(fields.clone(), StoreSize::Word);
+        store.execute(&mut cpu, &mut memory).unwrap();
+        assert_eq!(memory.read_u32(0x1000).unwrap(), 0x4433_2211);
+
+        // and loads read them back in register order
+        let load = Load::new(fields.clone(), LoadSize::Half);
+        load.execute(&mut cpu, &mut memory).unwrap();
+        assert_eq!(cpu.get_gr(2).unwrap(), 0x1122);
+        let load = Load::new(fields.clone(), LoadSize::Word);
+        load.execute(&mut cpu, &mut memory).unwrap();
+        assert_eq!(cpu.get_gr(2).unwrap(), 0x1122_3344);
+
+        // Semaphores read and write in the same order
+        memory.write_u64(0x1000, 0).unwrap();
+        memory.write_u8(0x1007, 0x10).unwrap();
+        cpu.set_gr(1, 1).unwrap();
+        let sem = Semaphore::new(fields.clone(), SemaphoreOp::Fetchadd, LoadSize::Double);
+        sem.execute(&mut cpu, &mut memory).unwrap();
+        assert_eq!(cpu.get_gr(2).unwrap(), 0x10);
+        assert_eq!(memory.read_u8(0x1007).unwrap(), 0x11);
+
+        // Clearing PSR.be goes back to little-endian
+        cpu.system_regs.cr.set(PSRFlags::BE, false);
+        let load = Load::new(fields, LoadSize::Double);
+        load.execute(&mut cpu, &mut memory).unwrap();
+        assert_eq!(cpu.get_gr(2).unwrap(), 0x1100_0000_0000_0000);
+    }
+
+    #[test]
+    fn test_semaphore_fault_is_atomic() {
+        let (mut cpu, mut memory, mut fields) = setup_test();
+        memory.map(0x2000, 4096, Permissions::Read).unwrap();
+        fields.addressing = Some(AddressingMode::Absolute(0x2000));
+        cpu.set_gr(1, 0x10).unwrap();
+        cpu.set_gr(2, 0x55).unwrap();
+
+        // Planning reads the old value but changes nothing
+        let sem = Semaphore::new(fields.clone(), SemaphoreOp::Fetchadd, LoadSize::Double);
+        let change = sem.plan(&cpu, &mut memory).unwrap();
+        let mut expected = StateChange::default();
+        expected.read(0x2000, 8, 0);
+        expected.set_gr(2, 0);
+        expected.write(0x2000, 8, 0x10, Endian::Little, WriteKind::Normal);
+        assert_eq!(change, expected);
+        assert_eq!(cpu.get_gr(2).unwrap(), 0x55);
+
+        // The write faults, so the destination keeps its value too
+        assert!(sem.execute(&mut cpu, &mut memory).is_err());
+        assert_eq!(cpu.get_gr(2).unwrap(), 0x55);
+        assert_eq!(memory.read_u64(0x2000).unwrap(), 0);
+
+        // As does a spill, whose UNAT update waits on the store
+        let mut spill_fields = fields.clone();
+        spill_fields.destinations.clear();
+        cpu.set_nat(1, true).unwrap();
+        let spill = Store::from_decoded(spill_fields, StoreSize::Double, Completers::SPILL);
+        assert!(spill.execute(&mut cpu, &mut memory).is_err());
+        assert_eq!(cpu.read_ar(AR::UNAT).unwrap(), 0);
+    }
+
     #[test]
     fn test_semaphore_completers() {
-        let (mut cpu, mut memory, fields) = setup_test();
+        let (_cpu, _memory, fields) = setup_test();
 
         // Test semaphore with memory ordering completers
-        let completers = Some(vec!["acq".to_string(), "nt1".to_string()]);
+        let completers = Completers::ACQ | Completers::NT1;
 
         let sem = Semaphore::from_decoded(
             fields.clone(),
@@ -1087,19 +1594,19 @@ mod tests {
         assert!(matches!(sem.cache_hint, CacheHint::NonTemporal1));
 
         // Test semaphore with default completers
-        let sem =
-            Semaphore::from_decoded(fields.clone(), SemaphoreOp::Xchg, LoadSize::Double, None);
+        let sem = Semaphore::from_decoded(
+            fields.clone(),
+            SemaphoreOp::Xchg,
+            LoadSize::Double,
+            Completers::NONE,
+        );
 
         // Verify default values
         assert!(matches!(sem.ordering, MemoryOrdering::None));
         assert!(matches!(sem.cache_hint, CacheHint::Normal));
 
-        // Test semaphore with mixed completers
-        let completers = Some(vec![
-            "rel".to_string(),
-            "".to_string(), // Empty completer should be ignored
-            "nta".to_string(),
-        ]);
+        // Test semaphore with several completers
+        let completers = Completers::REL | Completers::NTA;
 
         let sem = Semaphore::from_decoded(fields, SemaphoreOp::Xchg, LoadSize::Double, completers);
 
@@ -1134,14 +1641,19 @@ mod tests {
         // Test write back prefetch
         let prefetch = Prefetch::new(fields.clone(), PrefetchType::WriteBack);
         prefetch.execute(&mut cpu, &mut memory).unwrap();
+
+        // Only the first prefetch of the line brought it into the cache
+        let stats = memory.stats().prefetch;
+        assert_eq!(stats.issued, 4);
+        assert_eq!(stats.redundant, 3);
     }
 
     #[test]
     fn test_prefetch_completers() {
-        let (mut cpu, mut memory, fields) = setup_test();
+        let (_cpu, _memory, fields) = setup_test();
 
         // Test prefetch with cache hint completers
-        let completers = Some(vec!["nt1".to_string()]);
+        let completers = Completers::NT1;
 
         let prefetch = Prefetch::from_decoded(fields.clone(), PrefetchType::Normal, completers);
 
@@ -1149,16 +1661,14 @@ mod tests {
         assert!(matches!(prefetch.cache_hint, CacheHint::NonTemporal1));
 
         // Test prefetch with default completers
-        let prefetch = Prefetch::from_decoded(fields.clone(), PrefetchType::Normal, None);
+        let prefetch =
+            Prefetch::from_decoded(fields.clone(), PrefetchType::Normal, Completers::NONE);
 
         // Verify default values
         assert!(matches!(prefetch.cache_hint, CacheHint::Normal));
 
-        // Test prefetch with mixed completers
-        let completers = Some(vec![
-            "".to_string(), // Empty completer should be ignored
-            "nta".to_string(),
-        ]);
+        // Test prefetch with a hint
+        let completers = Completers::NTA;
 
         let prefetch = Prefetch::from_decoded(fields, PrefetchType::Normal, completers);
 
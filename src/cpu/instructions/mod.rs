@@ -1,21 +1,41 @@
 //! CPU instruction implementations
 //!
 //! This module contains implementations of the IA-64 instruction set.
+//! Instructions plan their effect as a [`StateChange`] that the caller
+//! applies, as described in the `change` module.
 
+use crate::cpu::registers::ar::AR;
+use crate::cpu::registers::CRIndex;
 use crate::cpu::Cpu;
 use crate::memory::Memory;
 use crate::EmulatorError;
+use change::StateChange;
 
 pub mod alu;
 pub mod branch;
+pub mod change;
+pub mod dispatch;
 pub mod float;
 pub mod memory;
 pub mod system;
 
 /// Common trait for all instructions
-pub trait Instruction {
+///
+/// Instructions are shared with the decode cache, which keeps each slot's
+/// translation for later executions.
+pub trait Instruction: Send + Sync {
+    /// Work out the instruction's effect without applying it
+    ///
+    /// Memory is borrowed mutably because loads go through the caches, but
+    /// only [`StateChange::apply`] stores to it.
+    fn plan(&self, cpu: &Cpu, memory: &mut Memory) -> Result<StateChange, EmulatorError>;
+
     /// Execute the instruction
-    fn execute(&self, cpu: &mut Cpu, memory: &mut Memory) -> Result<(), EmulatorError>;
+    ///
+    /// A fault leaves registers and memory as they were.
+    fn execute(&self, cpu: &mut Cpu, memory: &mut Memory) -> Result<(), EmulatorError> {
+        self.plan(cpu, memory)?.apply(cpu, memory)
+    }
 }
 
 /// Instruction completion type
@@ -69,6 +89,8 @@ pub enum RegisterType {
     DBR(u8),
     /// Data descriptor register
     DDR(u8),
+    /// Instruction pointer (address of the executing bundle)
+    IP,
 }
 
 /// Addressing modes
@@ -82,6 +104,102 @@ pub enum AddressingMode {
     IndirectIndex(u8, u8),
     /// Absolute address
     Absolute(u64),
+    /// Offset from the address of the executing bundle
+    IpRelative(i64),
+    /// Register indirect, adding an immediate to the register afterwards
+    PostIncrement(u8, i64),
+    /// Register indirect, adding a second register to the first afterwards
+    PostIndex(u8, u8),
+}
+
+impl AddressingMode {
+    /// Calculate the effective address
+    pub fn effective_address(&self, cpu: &Cpu) -> Result<u64, EmulatorError> {
+        match *self {
+            AddressingMode::Indirect(reg)
+            | AddressingMode::PostIncrement(reg, _)
+            | AddressingMode::PostIndex(reg, _) => cpu.get_gr(reg as usize),
+            AddressingMode::IndirectOffset(reg, offset) => {
+                let base = cpu.get_gr(reg as usize)?;
+                Ok(base.wrapping_add(offset as u64))
+            }
+            AddressingMode::IndirectIndex(base, index) => {
+                let base_val = cpu.get_gr(base as usize)?;
+                let index_val = cpu.get_gr(index as usize)?;
+                Ok(base_val.wrapping_add(index_val))
+            }
+            AddressingMode::Absolute(addr) => Ok(addr),
+            AddressingMode::IpRelative(offset) => {
+                Ok(read_operand(cpu, RegisterType::IP)?.wrapping_add(offset as u64))
+            }
+        }
+    }
+
+    /// Whether a register the address is formed from is NaT
+    pub fn is_nat(&self, cpu: &Cpu) -> Result<bool, EmulatorError> {
+        match *self {
+            AddressingMode::Indirect(reg)
+            | AddressingMode::IndirectOffset(reg, _)
+            | AddressingMode::PostIncrement(reg, _)
+            | AddressingMode::PostIndex(reg, _) => cpu.get_nat(reg as usize),
+            AddressingMode::IndirectIndex(base, index) => {
+                Ok(cpu.get_nat(base as usize)? || cpu.get_nat(index as usize)?)
+            }
+            AddressingMode::Absolute(_) | AddressingMode::IpRelative(_) => Ok(false),
+        }
+    }
+
+    /// Base register update of the post-increment forms, as the register,
+    /// its new value and its new NaT bit
+    ///
+    /// The increment register's NaT bit propagates to the base rather than
+    /// faulting, as only the base forms the address.
+    pub fn base_update(&self, cpu: &Cpu) -> Result<Option<(usize, u64, bool)>, EmulatorError> {
+        match *self {
+            AddressingMode::PostIncrement(reg, inc) => Ok(Some((
+                reg as usize,
+                cpu.get_gr(reg as usize)?.wrapping_add(inc as u64),
+                cpu.get_nat(reg as usize)?,
+            ))),
+            AddressingMode::PostIndex(reg, index) => Ok(Some((
+                reg as usize,
+                cpu.get_gr(reg as usize)?
+                    .wrapping_add(cpu.get_gr(index as usize)?),
+                cpu.get_nat(reg as usize)? || cpu.get_nat(index as usize)?,
+            ))),
+            _ => Ok(None),
+        }
+    }
+}
+
+/// Read the value of a source operand
+///
+/// IP reads as the address of the executing bundle; the slot is not part of
+/// the value.
+pub fn read_operand(cpu: &Cpu, operand: RegisterType) -> Result<u64, EmulatorError> {
+    match operand {
+        RegisterType::GR(reg) => cpu.get_gr(reg as usize),
+        RegisterType::PR(reg) => Ok(cpu.get_pr(reg as usize)? as u64),
+        RegisterType::BR(reg) => cpu.get_br(reg as usize),
+        RegisterType::AR(reg) => match AR::from_bits(reg) {
+            Some(ar) => cpu.read_ar(ar),
+            None => Err(EmulatorError::RegisterError(format!(
+                "Invalid application register: {}",
+                reg
+            ))),
+        },
+        RegisterType::CR(reg) => match CRIndex::from_bits(reg) {
+            Some(cr) => Ok(cpu.read_cr(cr)),
+            None => Err(EmulatorError::RegisterError(format!(
+                "Invalid control register: {}",
+                reg
+            ))),
+        },
+        RegisterType::IP => Ok(cpu.ip & !0xF),
+        _ => Err(EmulatorError::ExecutionError(
+            "Invalid source register type".to_string(),
+        )),
+    }
 }
 
 /// Common instruction format fields
@@ -120,6 +238,20 @@ impl InstructionFields {
             addressing,
         }
     }
+
+    /// Whether any general register source is NaT
+    ///
+    /// A result computed from a NaT source is itself NaT.
+    pub fn sources_nat(&self, cpu: &Cpu) -> Result<bool, EmulatorError> {
+        for source in &self.sources {
+            if let RegisterType::GR(reg) = *source {
+                if cpu.get_nat(reg as usize)? {
+                    return Ok(true);
+                }
+            }
+        }
+        Ok(false)
+    }
 }
 
 impl RegisterType {
@@ -136,6 +268,7 @@ impl RegisterType {
             RegisterType::PKR(n) => *n as usize,
             RegisterType::DBR(n) => *n as usize,
             RegisterType::DDR(n) => *n as usize,
+            RegisterType::IP => 0,
         }
     }
 }
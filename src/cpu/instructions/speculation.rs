@@ -106,7 +106,7 @@ impl Instruction for CheckLoad {
         };
 
         // Check ALAT for entry
-        if !cpu.alat_check_register(reg, is_integer) {
+        if !cpu.alat_check(reg, is_integer) {
             // No valid entry found, branch to recovery code
             if let Some(recovery_addr) = self.fields.recovery_addr {
                 cpu.ip = recovery_addr;
@@ -3,6 +3,8 @@
 //! This module implements the IA-64 interrupt and exception handling system,
 //! including hardware interrupts, software interrupts, faults, and traps.
 
+use crate::cpu::PSR_RI_SHIFT;
+use crate::decoder::{BundleTemplate, Unit};
 use crate::EmulatorError;
 
 /// Interrupt vector numbers
@@ -69,10 +71,54 @@ pub enum InterruptVector {
     TakenBranchTrap = 28,
     /// Single step trap
     SingleStepTrap = 29,
+    /// Page not present fault
+    PageNotPresentFault = 30,
+}
+
+impl InterruptVector {
+    /// Every vector, by number
+    const ALL: [InterruptVector; 31] = [
+        InterruptVector::ExtInt,
+        InterruptVector::VirtualMemoryFault,
+        InterruptVector::InstructionTLBFault,
+        InterruptVector::DataTLBFault,
+        InterruptVector::AltInstructionTLBFault,
+        InterruptVector::AltDataTLBFault,
+        InterruptVector::DataNestedTLBFault,
+        InterruptVector::InstructionKeyMissFault,
+        InterruptVector::DataKeyMissFault,
+        InterruptVector::DirtyBitFault,
+        InterruptVector::InstructionAccessBitFault,
+        InterruptVector::DataAccessBitFault,
+        InterruptVector::BreakFault,
+        InterruptVector::ExternalReset,
+        InterruptVector::NatConsumptionFault,
+        InterruptVector::ReservedRegisterFault,
+        InterruptVector::DisabledFPRegisterFault,
+        InterruptVector::UnimplementedDataAddressFault,
+        InterruptVector::PrivilegedOperationFault,
+        InterruptVector::DisabledISATransitionFault,
+        InterruptVector::IllegalOperationFault,
+        InterruptVector::IllegalDependencyFault,
+        InterruptVector::DebugFault,
+        InterruptVector::UnalignedReferenceFault,
+        InterruptVector::UnsupportedDataReferenceFault,
+        InterruptVector::FPFault,
+        InterruptVector::FPTrap,
+        InterruptVector::LowerPrivilegeTransferTrap,
+        InterruptVector::TakenBranchTrap,
+        InterruptVector::SingleStepTrap,
+        InterruptVector::PageNotPresentFault,
+    ];
+
+    /// Vector with the given number
+    pub fn from_bits(bits: u8) -> Option<Self> {
+        Self::ALL.get(bits as usize).copied()
+    }
 }
 
 /// Interrupt state information
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct InterruptState {
     /// Interrupt vector number
     pub vector: InterruptVector,
@@ -86,15 +132,33 @@ pub struct InterruptState {
     pub info: u64,
 }
 
+impl InterruptState {
+    /// Slot of the interrupted instruction as ISR.ei reports it
+    ///
+    /// PSR.ri numbers the L+X instruction of an MLX bundle slot 1, where
+    /// execution restarts, but ISR.ei reports it in slot 2, the X slot
+    /// holding its opcode.
+    pub fn excepting_slot(&self) -> u8 {
+        let ri = ((self.psr >> PSR_RI_SHIFT) & 0x3) as u8;
+        let long = BundleTemplate::from_bits(self.bundle[0] & 0x1F)
+            .is_some_and(|template| template.units()[1] == Unit::L);
+        if long && ri == 1 {
+            2
+        } else {
+            ri
+        }
+    }
+}
+
 /// Interrupt handler table entry
-#[derive(Debug, Clone)]
-struct HandlerEntry {
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HandlerEntry {
     /// Handler function address
-    address: u64,
+    pub address: u64,
     /// Minimum privilege level required
-    min_privilege: u8,
+    pub min_privilege: u8,
     /// Whether handler is enabled
-    enabled: bool,
+    pub enabled: bool,
 }
 
 /// Interrupt handler table
@@ -188,6 +252,28 @@ impl InterruptTable {
     }
 }
 
+/// Saved state of an interrupt controller
+///
+/// Holds everything the controller needs to carry on exactly where it was,
+/// in the middle of nested interruptions included.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct InterruptControllerState {
+    /// Handler table, indexed by vector number
+    pub handlers: Vec<HandlerEntry>,
+    /// Pending interrupts, the next to deliver last
+    pub pending: Vec<InterruptState>,
+    /// Interrupt being handled
+    pub current: Option<InterruptState>,
+    /// Interrupts whose handlers a nested one interrupted, outermost first
+    pub interrupted: Vec<InterruptState>,
+    /// Interrupt nesting level
+    pub nesting_level: u32,
+    /// Whether interrupts are enabled
+    pub interrupts_enabled: bool,
+    /// Interrupts raised since creation
+    pub raised: u64,
+}
+
 /// Interrupt controller state
 #[derive(Debug)]
 pub struct InterruptController {
@@ -197,10 +283,14 @@ pub struct InterruptController {
     pending: Vec<InterruptState>,
     /// Currently executing interrupt
     current: Option<InterruptState>,
+    /// Interrupts whose handlers a nested one interrupted, outermost first
+    interrupted: Vec<InterruptState>,
     /// Interrupt nesting level
     nesting_level: u32,
     /// Whether interrupts are enabled
     interrupts_enabled: bool,
+    /// Interrupts raised since creation
+    raised: u64,
 }
 
 impl Default for InterruptController {
@@ -216,8 +306,10 @@ impl InterruptController {
             table: InterruptTable::new(),
             pending: Vec::new(),
             current: None,
+            interrupted: Vec::new(),
             nesting_level: 0,
             interrupts_enabled: false,
+            raised: 0,
         }
     }
 
@@ -239,6 +331,12 @@ impl InterruptController {
     /// Raise interrupt
     pub fn raise_interrupt(&mut self, state: InterruptState) {
         self.pending.push(state);
+        self.raised += 1;
+    }
+
+    /// Number of interrupts raised since creation
+    pub fn raised(&self) -> u64 {
+        self.raised
     }
 
     /// Check and handle pending interrupts
@@ -252,7 +350,7 @@ impl InterruptController {
             // Save current state if this is a nested interrupt
             if self.nesting_level > 0 {
                 if let Some(current) = self.current.take() {
-                    self.pending.push(current);
+                    self.interrupted.push(current);
                 }
             }
 
@@ -285,8 +383,14 @@ impl InterruptController {
         self.nesting_level -= 1;
         self.current = None;
 
-        // Restore previous interrupt state if any
-        if !self.pending.is_empty() {
+        // Go back to the handler a nested interrupt interrupted, then to
+        // the next pending interrupt
+        if let Some(state) = self.interrupted.pop() {
+            if let Ok(Some(handler_addr)) = self.table.get_handler_address(state.vector) {
+                self.current = Some(state);
+                return Some(handler_addr);
+            }
+        } else if !self.pending.is_empty() {
             if let Some(state) = self.pending.pop() {
                 if let Ok(Some(handler_addr)) = self.table.get_handler_address(state.vector) {
                     self.current = Some(state);
@@ -298,6 +402,19 @@ impl InterruptController {
         None
     }
 
+    /// End the current interrupt the way rfi does
+    ///
+    /// rfi returns to the interrupted code through CR.IIP, so no handler is
+    /// entered: the interrupt a nested one interrupted becomes current
+    /// again, and pending interrupts wait for delivery.
+    pub fn complete_interrupt(&mut self) {
+        if self.nesting_level == 0 {
+            return;
+        }
+        self.nesting_level -= 1;
+        self.current = self.interrupted.pop();
+    }
+
     /// Get current interrupt state
     pub fn current_interrupt(&self) -> Option<&InterruptState> {
         self.current.as_ref()
@@ -312,6 +429,38 @@ impl InterruptController {
     pub fn clear_pending(&mut self) {
         self.pending.clear();
     }
+
+    /// Save the controller's state
+    pub fn state(&self) -> InterruptControllerState {
+        InterruptControllerState {
+            handlers: self.table.handlers.clone(),
+            pending: self.pending.clone(),
+            current: self.current.clone(),
+            interrupted: self.interrupted.clone(),
+            nesting_level: self.nesting_level,
+            interrupts_enabled: self.interrupts_enabled,
+            raised: self.raised,
+        }
+    }
+
+    /// Return to a saved state
+    pub fn restore_state(&mut self, state: &InterruptControllerState) -> Result<(), EmulatorError> {
+        if state.handlers.len() != self.table.handlers.len() {
+            return Err(EmulatorError::ExecutionError(format!(
+                "Interrupt table has {} entries, expected {}",
+                state.handlers.len(),
+                self.table.handlers.len()
+            )));
+        }
+        self.table.handlers = state.handlers.clone();
+        self.pending = state.pending.clone();
+        self.current = state.current.clone();
+        self.interrupted = state.interrupted.clone();
+        self.nesting_level = state.nesting_level;
+        self.interrupts_enabled = state.interrupts_enabled;
+        self.raised = state.raised;
+        Ok(())
+    }
 }
 
 #[cfg(test)]
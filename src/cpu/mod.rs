@@ -4,29 +4,141 @@
 //! including register management and instruction execution.
 
 use crate::cpu::alat::ALAT;
+use crate::cpu::branch_predict::BranchPredictor;
+use crate::cpu::checked::Narrow;
+use crate::cpu::instructions::system;
 use crate::cpu::interrupts::{InterruptController, InterruptState, InterruptVector};
+use crate::cpu::pmu::Pmu;
+use crate::cpu::registers::ar::AR;
+use crate::cpu::registers::cr::CRIndex;
+use crate::cpu::registers::rr::{RegionFields, NUM_RR};
 use crate::cpu::registers::CRFile;
+use crate::cpu::registers::FloatRegister;
 use crate::cpu::registers::RegisterState;
 use crate::cpu::rse::{RSEConfig, RSE};
+use crate::cpu::rse_check::{RseChecker, RseViolation};
+use crate::cpu::rse_profile::RseProfiler;
+use crate::cpu::sample_profile::SampleProfiler;
 use crate::cpu::syscall::{SyscallContext, SyscallManager, SyscallNumber};
-use crate::memory::Memory;
+use crate::cpu::timer::{IntervalTimer, TimerMode};
+use crate::cpu::tlb::{AddressSpace, Tlb, TlbEntry};
+use crate::cpu::unaligned::{AlignmentPolicy, UnalignedFixups};
+use crate::memory::view::Endian;
+use crate::memory::{Memory, Permissions};
+use crate::timeline::{Channel, Phase, Timeline};
 use crate::EmulatorError;
+use std::ops::Range;
+use std::time::Instant;
 
 pub mod alat;
+pub mod branch_predict;
+pub mod checked;
+pub mod dispersal;
+pub mod hostfs;
 pub mod instructions;
 pub mod interrupts;
+pub mod pmu;
 /// Register management module containing implementations for various register types
 /// including general purpose registers, floating point registers, predicate registers,
 /// branch registers, application registers, control registers, region registers,
 /// protection key registers, debug break registers, and data debug registers.
 pub mod registers;
 pub mod rse;
+pub mod rse_check;
+pub mod rse_profile;
+pub mod sample_profile;
+pub mod stdio;
+pub mod strace;
 pub mod syscall;
+pub mod timer;
+pub mod timing;
+pub mod tlb;
+pub mod unaligned;
+pub mod vhpt;
+
+/// Mask bit of CR.ITV
+const ITV_MASK: u64 = 1 << 16;
+
+/// External interrupt vector of the non-maskable interrupt
+pub const NMI_VECTOR: u64 = 2;
+
+/// External interrupt vector CR.IVR reads when none is pending
+pub const SPURIOUS_VECTOR: u64 = 15;
+
+/// Offset of the external interrupt entry in the vector table at CR.IVA
+pub const IVT_EXTINT_OFFSET: u64 = 0x3000;
+
+/// Valid bit of CR.IFS
+const IFS_VALID: u64 = 1 << 63;
+
+/// Shift of PSR.cpl, the current privilege level (bits 33:32)
+pub const PSR_CPL_SHIFT: u64 = 32;
+
+/// Shift of PSR.ri, the slot execution resumes at (bits 42:41)
+pub const PSR_RI_SHIFT: u64 = 41;
+
+/// Shift of ISR.ei, the slot of the excepting instruction (bits 42:41)
+const ISR_EI_SHIFT: u64 = 41;
+
+/// Frame marker in CR.IFS (bits 37:0)
+const IFS_IFM_MASK: u64 = (1 << 38) - 1;
+
+/// Shift of AR.PFS.ppl, the caller's privilege level (bits 63:62)
+const PFS_PPL_SHIFT: u64 = 62;
+
+/// Shift of AR.PFS.pec, the caller's epilog count (bits 57:52)
+const PFS_PEC_SHIFT: u64 = 52;
+
+/// Previous frame marker in AR.PFS (bits 37:0)
+const PFS_PFM_MASK: u64 = (1 << 38) - 1;
+
+/// Shift of CFM.sor, the size of the rotating region in groups of eight
+/// (bits 17:14)
+const CFM_SOR_SHIFT: u64 = 14;
+
+/// Shift of CFM.rrb.gr, the rotating general register base (bits 24:18)
+const CFM_RRB_GR_SHIFT: u64 = 18;
+
+/// Shift of CFM.rrb.fr, the rotating floating point register base
+/// (bits 31:25)
+const CFM_RRB_FR_SHIFT: u64 = 25;
+
+/// Shift of CFM.rrb.pr, the rotating predicate register base (bits 37:32)
+const CFM_RRB_PR_SHIFT: u64 = 32;
+
+/// Every rotating register base in the CFM
+const CFM_RRB_MASK: u64 = IFS_IFM_MASK & !((1 << CFM_RRB_GR_SHIFT) - 1);
+
+/// First rotating floating point register
+const FIRST_ROTATING_FR: usize = 32;
+
+/// Number of rotating floating point registers, f32-f127
+const NUM_ROTATING_FR: usize = 96;
+
+/// First rotating predicate register
+const FIRST_ROTATING_PR: usize = 16;
+
+/// Number of rotating predicate registers, p16-p63
+const NUM_ROTATING_PR: usize = 48;
+
+/// Privilege level the user programs the emulator runs live at
+pub const USER_PRIVILEGE_LEVEL: u8 = 3;
 
 /// Number of general purpose registers in IA-64
 pub const NUM_GR: usize = 128;
+/// First stacked general register
+pub const FIRST_STACKED_GR: usize = 32;
+/// Number of physical stacked registers available to frames and the RSE
+pub const NUM_STACKED_GR: u32 = 96;
 /// Number of floating point registers in IA-64
 pub const NUM_FR: usize = 128;
+/// Floating point registers at reset: f1 is hardwired to +1.0, the rest
+/// read as +0.0
+pub const INITIAL_FR: [FloatRegister; NUM_FR] = {
+    let mut fr = [FloatRegister::ZERO; NUM_FR];
+    fr[1] = FloatRegister::ONE;
+    fr
+};
 /// Number of predicate registers in IA-64
 pub const NUM_PR: usize = 64;
 /// Number of branch registers in IA-64
@@ -73,8 +185,8 @@ impl PSR {
 pub enum PSRFlags {
     /// User mask
     UM = 1 << 0,
-    /// Secure bit
-    SECURE = 1 << 1,
+    /// Alignment check for data references
+    AC = 1 << 1,
     /// Register stack engine enable
     RSE = 1 << 2,
     /// Big-endian memory access enable
@@ -89,6 +201,8 @@ pub enum PSRFlags {
     IC = 1 << 13,
     /// Interrupt enable
     I = 1 << 14,
+    /// Secure performance monitors
+    SP = 1 << 20,
     /// Data debug fault disable
     DD = 1 << 39,
     /// Instruction debug fault disable
@@ -107,14 +221,20 @@ impl PSRFlags {
 pub struct Cpu {
     /// General registers (r0-r127)
     pub gr: [u64; NUM_GR],
+    /// NaT bits of the general registers
+    pub nat: [bool; NUM_GR],
     /// Floating point registers (f0-f127)
-    pub fr: [u64; NUM_FR],
+    pub fr: [FloatRegister; NUM_FR],
     /// Predicate registers (p0-p63)
     pub pr: [bool; NUM_PR],
     /// Branch registers (b0-b7)
     pub br: [u64; NUM_BR],
     /// Instruction pointer
     pub ip: u64,
+    /// Slot of the executing instruction within its bundle (PSR.ri)
+    pub slot: u8,
+    /// Bytes of the executing bundle, kept with the interruptions it raises
+    pub bundle: [u8; 16],
     /// Previous function state
     pub pfs: u64,
     /// Current frame marker
@@ -131,18 +251,49 @@ pub struct Cpu {
     pub syscall_mgr: SyscallManager,
     /// Register Stack Engine
     pub rse: RSE,
+    /// Per-function register stack profile, if enabled
+    pub rse_profiler: Option<RseProfiler>,
+    /// Sampling profiler of guest execution, if enabled
+    pub sample_profiler: Option<SampleProfiler>,
+    /// Register stack invariant checker, if enabled
+    pub rse_checker: Option<RseChecker>,
+    /// Timeline interrupts are recorded on, if enabled
+    pub(crate) timeline: Option<Timeline>,
+    /// Handling of unaligned loads and stores
+    pub alignment_policy: AlignmentPolicy,
+    /// Unaligned accesses fixed up under [`AlignmentPolicy::Fixup`]
+    pub unaligned_fixups: UnalignedFixups,
+    /// Fault on values that do not fit the width they are narrowed to,
+    /// instead of truncating them; see [`checked`]
+    pub strict_types: bool,
+    /// Interval timer (AR.ITC / CR.ITM)
+    pub timer: IntervalTimer,
+    /// Performance monitoring unit
+    pub pmu: Pmu,
+    /// Branch prediction model feeding the PMU
+    pub branch_predictor: BranchPredictor,
+    /// Translation lookaside buffer
+    pub tlb: Tlb,
+    /// Address ranges whose epc promotes to privilege level 0, like the
+    /// execute-promote pages of the architecture
+    pub promote_pages: Vec<Range<u64>>,
     /// Memory
     pub memory: Memory,
+    /// Exit status recorded by the guest's exit system call
+    pub exit_code: Option<i32>,
 }
 
 impl Default for Cpu {
     fn default() -> Self {
         let mut cpu = Self {
             gr: [0; NUM_GR],
-            fr: [0; NUM_FR],
+            nat: [false; NUM_GR],
+            fr: INITIAL_FR,
             pr: [false; NUM_PR],
             br: [0; NUM_BR],
             ip: 0,
+            slot: 0,
+            bundle: [0; 16],
             pfs: 0,
             cfm: 0,
             user_mask: 0,
@@ -151,8 +302,22 @@ impl Default for Cpu {
             interrupt_ctrl: InterruptController::new(),
             syscall_mgr: SyscallManager::new(),
             rse: RSE::new(),
+            rse_profiler: None,
+            sample_profiler: None,
+            timeline: None,
+            rse_checker: None,
+            alignment_policy: AlignmentPolicy::default(),
+            unaligned_fixups: UnalignedFixups::default(),
+            strict_types: false,
+            timer: IntervalTimer::new(),
+            pmu: Pmu::new(),
+            branch_predictor: BranchPredictor::new(),
+            tlb: Tlb::new(),
+            promote_pages: Vec::new(),
             memory: Memory::new(),
+            exit_code: None,
         };
+        cpu.set_privilege_level(USER_PRIVILEGE_LEVEL);
         cpu.syscall_mgr.init_default_handlers();
         cpu
     }
@@ -168,18 +333,32 @@ impl Cpu {
     pub fn reset(&mut self) -> Result<(), EmulatorError> {
         // Reset registers
         self.gr = [0; NUM_GR];
-        self.fr = [0; NUM_FR];
+        self.nat = [false; NUM_GR];
+        self.fr = INITIAL_FR;
         self.pr = [false; NUM_PR];
         self.br = [0; NUM_BR];
 
         // Reset instruction pointer
         self.ip = 0;
+        self.slot = 0;
+        self.bundle = [0; 16];
 
         // Reset current frame marker
         self.cfm = 0;
 
-        // Reset system registers
+        // Forget any previous exit status
+        self.exit_code = None;
+
+        // Reset system registers, back at user level
         self.system_regs.cr = PSR::empty().into();
+        self.set_privilege_level(USER_PRIVILEGE_LEVEL);
+
+        // Disable the performance counters and forget branch history
+        self.pmu = Pmu::new();
+        self.branch_predictor = BranchPredictor::new();
+
+        // Drop every translation
+        self.tlb.purge_all();
 
         Ok(())
     }
@@ -192,10 +371,12 @@ impl Cpu {
                 reg
             )));
         }
-        Ok(self.gr[reg])
+        Ok(self.gr[self.physical_gr(reg)])
     }
 
     /// Set the value of a general register
+    ///
+    /// Writing a value clears the register's NaT bit.
     pub fn set_gr(&mut self, reg: usize, value: u64) -> Result<(), EmulatorError> {
         if reg >= NUM_GR {
             return Err(EmulatorError::CpuStateError(format!(
@@ -205,11 +386,76 @@ impl Cpu {
         }
         // r0 is always 0 in IA-64
         if reg != 0 {
+            let reg = self.physical_gr(reg);
             self.gr[reg] = value;
+            self.nat[reg] = false;
+        }
+        Ok(())
+    }
+
+    /// Get the NaT bit of a general register
+    pub fn get_nat(&self, reg: usize) -> Result<bool, EmulatorError> {
+        if reg >= NUM_GR {
+            return Err(EmulatorError::CpuStateError(format!(
+                "Invalid general register index: {}",
+                reg
+            )));
+        }
+        Ok(self.nat[self.physical_gr(reg)])
+    }
+
+    /// Set the NaT bit of a general register
+    pub fn set_nat(&mut self, reg: usize, nat: bool) -> Result<(), EmulatorError> {
+        if reg >= NUM_GR {
+            return Err(EmulatorError::CpuStateError(format!(
+                "Invalid general register index: {}",
+                reg
+            )));
+        }
+        // r0 is never NaT
+        if reg != 0 {
+            let reg = self.physical_gr(reg);
+            self.nat[reg] = nat;
         }
         Ok(())
     }
 
+    /// Store a general register and its NaT bit (st8.spill)
+    ///
+    /// The NaT bit goes to bit `addr{8:3}` of AR.UNAT.
+    pub fn spill_gr(
+        &mut self,
+        memory: &mut Memory,
+        reg: usize,
+        addr: u64,
+    ) -> Result<(), EmulatorError> {
+        self.require_alignment(addr, 8, true)?;
+        let value = self.get_gr(reg)?;
+        let nat = self.get_nat(reg)?;
+        memory.write_sized(addr, 8, value, self.data_endian())?;
+        self.alat.invalidate_overlap(addr, 8);
+
+        let mask = 1 << ((addr >> 3) & 0x3F);
+        let unat = self.read_ar(AR::UNAT)?;
+        self.write_ar(AR::UNAT, if nat { unat | mask } else { unat & !mask })
+    }
+
+    /// Load a general register and its NaT bit (ld8.fill)
+    ///
+    /// The NaT bit comes from bit `addr{8:3}` of AR.UNAT.
+    pub fn fill_gr(
+        &mut self,
+        memory: &mut Memory,
+        reg: usize,
+        addr: u64,
+    ) -> Result<(), EmulatorError> {
+        self.require_alignment(addr, 8, false)?;
+        let value = memory.read_sized(addr, 8, self.data_endian())?;
+        let nat = self.read_ar(AR::UNAT)? & (1 << ((addr >> 3) & 0x3F)) != 0;
+        self.set_gr(reg, value)?;
+        self.set_nat(reg, nat)
+    }
+
     /// Get the value of a floating point register
     pub fn get_fr(&self, reg: usize) -> Result<f64, EmulatorError> {
         if reg >= NUM_FR {
@@ -218,18 +464,39 @@ impl Cpu {
                 reg
             )));
         }
-        Ok(f64::from_bits(self.fr[reg]))
+        Ok(self.fr[self.physical_fr(reg)].to_f64())
     }
 
     /// Set the value of a floating point register
     pub fn set_fr(&mut self, reg: usize, value: f64) -> Result<(), EmulatorError> {
+        self.set_fr_register(reg, FloatRegister::from_f64(value))
+    }
+
+    /// Get a floating point register in the 82-bit register format
+    pub fn get_fr_register(&self, reg: usize) -> Result<FloatRegister, EmulatorError> {
+        self.fr.get(self.physical_fr(reg)).copied().ok_or_else(|| {
+            EmulatorError::CpuStateError(format!("Invalid floating point register index: {}", reg))
+        })
+    }
+
+    /// Set a floating point register in the 82-bit register format
+    ///
+    /// Writes to the hardwired f0 and f1 are ignored.
+    pub fn set_fr_register(
+        &mut self,
+        reg: usize,
+        value: FloatRegister,
+    ) -> Result<(), EmulatorError> {
         if reg >= NUM_FR {
             return Err(EmulatorError::CpuStateError(format!(
                 "Invalid floating point register index: {}",
                 reg
             )));
         }
-        self.fr[reg] = value.to_bits();
+        if reg > 1 {
+            let reg = self.physical_fr(reg);
+            self.fr[reg] = value;
+        }
         Ok(())
     }
 
@@ -241,7 +508,7 @@ impl Cpu {
                 reg
             )));
         }
-        Ok(self.pr[reg])
+        Ok(self.pr[self.physical_pr(reg)])
     }
 
     /// Set the value of a predicate register
@@ -252,10 +519,102 @@ impl Cpu {
                 reg
             )));
         }
+        let reg = self.physical_pr(reg);
         self.pr[reg] = value;
         Ok(())
     }
 
+    /// Number of rotating general registers, CFM.sor groups of eight from
+    /// r32
+    pub fn rotating_gr_count(&self) -> usize {
+        ((self.cfm >> CFM_SOR_SHIFT) & 0xF) as usize * 8
+    }
+
+    /// Rotating register bases of the CFM: rrb.gr, rrb.fr and rrb.pr
+    pub fn rotating_bases(&self) -> (usize, usize, usize) {
+        (
+            ((self.cfm >> CFM_RRB_GR_SHIFT) & 0x7F) as usize,
+            ((self.cfm >> CFM_RRB_FR_SHIFT) & 0x7F) as usize,
+            ((self.cfm >> CFM_RRB_PR_SHIFT) & 0x3F) as usize,
+        )
+    }
+
+    /// Physical register named by general register `reg`
+    fn physical_gr(&self, reg: usize) -> usize {
+        let (rrb, _, _) = self.rotating_bases();
+        rotate(reg, FIRST_STACKED_GR, self.rotating_gr_count(), rrb)
+    }
+
+    /// Physical register named by floating point register `reg`
+    fn physical_fr(&self, reg: usize) -> usize {
+        let (_, rrb, _) = self.rotating_bases();
+        rotate(reg, FIRST_ROTATING_FR, NUM_ROTATING_FR, rrb)
+    }
+
+    /// Physical register named by predicate register `reg`
+    fn physical_pr(&self, reg: usize) -> usize {
+        let (_, _, rrb) = self.rotating_bases();
+        rotate(reg, FIRST_ROTATING_PR, NUM_ROTATING_PR, rrb)
+    }
+
+    /// Name of physical general register `phys` under the current rotation
+    pub(crate) fn gr_name(&self, phys: usize) -> usize {
+        (0..NUM_GR)
+            .find(|&reg| self.physical_gr(reg) == phys)
+            .unwrap_or(phys)
+    }
+
+    /// Name of physical floating-point register `phys` under the current
+    /// rotation
+    pub(crate) fn fr_name(&self, phys: usize) -> usize {
+        (0..NUM_FR)
+            .find(|&reg| self.physical_fr(reg) == phys)
+            .unwrap_or(phys)
+    }
+
+    /// Name of physical predicate register `phys` under the current rotation
+    pub(crate) fn pr_name(&self, phys: usize) -> usize {
+        (0..NUM_PR)
+            .find(|&reg| self.physical_pr(reg) == phys)
+            .unwrap_or(phys)
+    }
+
+    /// Rotate the rotating registers by one (br.ctop, br.cexit, br.wtop and
+    /// br.wexit)
+    ///
+    /// Each rotating register base is decremented, wrapping within its
+    /// region, so the value named r32 before the rotation is named r33
+    /// after it. Without a rotating general register region rrb.gr stays 0.
+    pub fn rotate_registers(&mut self) {
+        let (gr, fr, pr) = self.rotating_bases();
+        let gr = match self.rotating_gr_count() {
+            0 => 0,
+            size => (gr + size - 1) % size,
+        };
+        let fr = (fr + NUM_ROTATING_FR - 1) % NUM_ROTATING_FR;
+        let pr = (pr + NUM_ROTATING_PR - 1) % NUM_ROTATING_PR;
+        self.set_rotating_bases(gr, fr, pr);
+    }
+
+    /// Clear the rotating register bases (clrrrb), or only rrb.pr
+    /// (clrrrb.pr)
+    pub fn clear_rotating_bases(&mut self, predicates_only: bool) {
+        let (gr, fr, _) = self.rotating_bases();
+        if predicates_only {
+            self.set_rotating_bases(gr, fr, 0);
+        } else {
+            self.set_rotating_bases(0, 0, 0);
+        }
+    }
+
+    /// Replace the rotating register bases of the CFM
+    fn set_rotating_bases(&mut self, gr: usize, fr: usize, pr: usize) {
+        self.cfm = (self.cfm & !CFM_RRB_MASK)
+            | ((gr as u64) << CFM_RRB_GR_SHIFT)
+            | ((fr as u64) << CFM_RRB_FR_SHIFT)
+            | ((pr as u64) << CFM_RRB_PR_SHIFT);
+    }
+
     /// Get the value of a branch register
     pub fn get_br(&self, reg: usize) -> Result<u64, EmulatorError> {
         if reg >= NUM_BR {
@@ -295,6 +654,11 @@ impl Cpu {
         self.alat.check_register(register, is_integer)
     }
 
+    /// Check a register for a check load or chk.a, counting the outcome
+    pub fn alat_check(&mut self, register: u32, is_integer: bool) -> bool {
+        self.alat.check(register, is_integer)
+    }
+
     /// Invalidate overlapping ALAT entries
     pub fn alat_invalidate_overlap(&mut self, address: u64, size: u64) {
         self.alat.invalidate_overlap(address, size)
@@ -341,19 +705,44 @@ impl Cpu {
         self.system_regs.cr.set(PSRFlags::I, enabled);
     }
 
+    /// Record an event on the timeline's interrupt channel, if enabled,
+    /// at the current AR.ITC
+    fn record_interrupt(&self, name: &str, phase: Phase, args: &[(&'static str, u64)]) {
+        if let Some(timeline) = &self.timeline {
+            timeline.set_time(self.timer.read_itc());
+            timeline.record(Channel::Interrupts, name, phase, args);
+        }
+    }
+
     /// Raise interrupt
+    ///
+    /// The saved PSR holds the executing slot in PSR.ri.
     pub fn raise_interrupt(&mut self, vector: InterruptVector, info: u64) {
+        let psr = self.system_regs.cr.get_psr() & !(0x3 << PSR_RI_SHIFT);
         let state = InterruptState {
             vector,
             ip: self.ip,
-            psr: self.system_regs.cr.get_psr(),
-            bundle: [0; 16], // Current bundle would be filled in by decoder
+            psr: psr | ((self.slot as u64 & 0x3) << PSR_RI_SHIFT),
+            bundle: self.bundle,
             info,
         };
+        self.record_interrupt(&format!("{:?}", vector), Phase::Instant, &[("info", info)]);
         self.interrupt_ctrl.raise_interrupt(state);
     }
 
+    /// Number of interrupts raised since the CPU was created
+    pub fn interrupts_raised(&self) -> u64 {
+        self.interrupt_ctrl.raised()
+    }
+
     /// Check and handle pending interrupts
+    ///
+    /// With interruption collection on (PSR.ic), delivery saves the
+    /// interrupted PSR in CR.IPSR and its bundle address in CR.IIP for rfi,
+    /// records the interrupted slot in CR.ISR.ei and clears CR.IFS.v, so
+    /// that rfi keeps the current frame unless the handler covers it. The
+    /// handler runs at privilege level 0 with interrupts and interruption
+    /// collection off.
     pub fn check_interrupts(&mut self) -> Option<u64> {
         // Only check if interrupts are enabled in PSR
         if !self.system_regs.cr.contains(PSRFlags::I) {
@@ -361,9 +750,33 @@ impl Cpu {
         }
 
         if let Some(handler_addr) = self.interrupt_ctrl.check_interrupts() {
+            if self.system_regs.cr.contains(PSRFlags::IC) {
+                if let Some(state) = self.interrupt_ctrl.current_interrupt() {
+                    let (psr, ip) = (state.psr, state.ip);
+                    let ei = state.excepting_slot() as u64;
+                    let cr = &mut self.system_regs.cr;
+                    let isr = cr.read(CRIndex::ISR) & !(0x3 << ISR_EI_SHIFT);
+                    let ifs = cr.read(CRIndex::IFS) & !IFS_VALID;
+                    // Writes to IPSR, IIP, ISR and IFS do not fail
+                    let _ = cr.write(CRIndex::IPSR, psr);
+                    let _ = cr.write(CRIndex::IIP, ip);
+                    let _ = cr.write(CRIndex::ISR, isr | (ei << ISR_EI_SHIFT));
+                    let _ = cr.write(CRIndex::IFS, ifs);
+                }
+            }
             // Switch to privileged mode
+            self.set_privilege_level(0);
             self.system_regs.cr.set(PSRFlags::I, false); // Disable interrupts
-            self.system_regs.cr.set(PSRFlags::IC, true); // Set interrupt collection
+            self.system_regs.cr.set(PSRFlags::IC, false); // Stop interrupt collection
+            self.system_regs
+                .cr
+                .update(|psr| psr & !(0x3 << PSR_RI_SHIFT));
+            self.check_rse(RseChecker::interrupted);
+            let args = [
+                ("handler", handler_addr),
+                ("ip", self.read_cr(CRIndex::IIP)),
+            ];
+            self.record_interrupt("handler", Phase::Begin, &args);
 
             // Return handler address
             Some(handler_addr)
@@ -384,8 +797,8 @@ impl Cpu {
             }
         };
 
-        // Restore saved state
-        self.system_regs.cr = PSR::from_bits_truncate(state.psr).into();
+        // Restore saved state, including the interrupted privilege level
+        self.system_regs.cr.write(CRIndex::PSR, state.psr)?;
 
         // Get next handler or return to interrupted code
         let next_ip = self
@@ -395,10 +808,44 @@ impl Cpu {
 
         // Update instruction pointer
         self.ip = next_ip;
+        self.record_interrupt("handler", Phase::End, &[]);
 
         Ok(())
     }
 
+    /// Return from interruption (rfi)
+    ///
+    /// PSR comes back from CR.IPSR, with the interrupted privilege level
+    /// and interruption collection, and execution resumes at the bundle in
+    /// CR.IIP, in the slot in IPSR.ri. If CR.IFS.v is set, the frame that
+    /// cover saved there becomes current again: the handler's frame is
+    /// discarded and the interrupted frame's registers are popped from the
+    /// register stack, loading those already spilled from the backing
+    /// store. With IFS.v clear the handler's frame stays current.
+    pub fn rfi(&mut self, memory: &mut Memory) -> Result<(), EmulatorError> {
+        system::interruption_return(self)?.apply(self, memory)
+    }
+
+    /// End the current interruption, once PSR is restored, and make the
+    /// frame saved in `ifs` current again if its valid bit is set
+    ///
+    /// The interrupted frame's locals no longer in the register file are
+    /// left as mandatory loads for
+    /// [`complete_rse_loads`](Self::complete_rse_loads).
+    pub(crate) fn end_interruption(&mut self, ifs: u64) -> Result<(), EmulatorError> {
+        self.check_rse(RseChecker::rfi);
+        self.record_interrupt("handler", Phase::End, &[]);
+        self.interrupt_ctrl.complete_interrupt();
+        if ifs & IFS_VALID == 0 {
+            return Ok(());
+        }
+        let ifm = ifs & IFS_IFM_MASK;
+        let missing = self.pop_locals((ifm & 0x7F) as usize);
+        self.cfm = ifm;
+        self.rse
+            .begin_mandatory_loads(self.narrow(missing as u64, "mandatory loads")?)
+    }
+
     /// Get current interrupt state
     pub fn current_interrupt(&self) -> Option<&InterruptState> {
         self.interrupt_ctrl.current_interrupt()
@@ -418,13 +865,15 @@ impl Cpu {
     pub fn init(&mut self) -> Result<(), EmulatorError> {
         // Initialize registers
         self.gr = [0; NUM_GR];
-        self.fr = [0; NUM_FR];
+        self.nat = [false; NUM_GR];
+        self.fr = INITIAL_FR;
         self.pr = [false; NUM_PR];
         self.br = [0; NUM_BR];
 
         // Initialize special registers
         self.ip = 0;
         self.system_regs.cr = PSR::empty().into();
+        self.set_privilege_level(USER_PRIVILEGE_LEVEL);
 
         // Initialize ALAT
         self.alat = ALAT::new();
@@ -439,23 +888,40 @@ impl Cpu {
     }
 
     /// Execute system call
-    pub fn do_syscall(&mut self, syscall_num: u64) -> Result<(), EmulatorError> {
-        // Begin syscall
+    ///
+    /// Returns the completed context with the return value and error code.
+    /// The call is counted in the manager's statistics, with the virtual
+    /// and host time it took.
+    pub fn do_syscall(&mut self, syscall_num: u64) -> Result<SyscallContext, EmulatorError> {
         let mut syscall_mgr = std::mem::take(&mut self.syscall_mgr);
-        syscall_mgr.begin_syscall(self, syscall_num)?;
-
-        // Execute syscall
-        let mut context = syscall_mgr
-            .current
-            .take()
-            .ok_or(EmulatorError::NoSyscallContext)?;
-        syscall_mgr.execute_syscall(self, &mut context)?;
-        syscall_mgr.current = Some(context);
-
-        // End syscall
-        syscall_mgr.end_syscall(self)?;
+        let (start_itc, start) = (self.timer.read_itc(), Instant::now());
+        let result = (|| {
+            // Begin syscall
+            syscall_mgr.begin_syscall(self, syscall_num)?;
+
+            // Execute syscall
+            let mut context = syscall_mgr
+                .current
+                .take()
+                .ok_or(EmulatorError::NoSyscallContext)?;
+            syscall_mgr.execute_syscall(self, &mut context)?;
+            syscall_mgr.current = Some(context);
+
+            // End syscall
+            syscall_mgr.end_syscall(self)
+        })();
+
+        let failed = result
+            .as_ref()
+            .map_or(true, |context| context.error.is_some());
+        let ticks = self.timer.read_itc().wrapping_sub(start_itc);
+        syscall_mgr
+            .stats
+            .record(syscall_num, ticks, start.elapsed(), failed);
+
+        // Put the manager back even if the call failed
         self.syscall_mgr = syscall_mgr;
-        Ok(())
+        result
     }
 
     /// Register system call handler
@@ -480,6 +946,15 @@ impl Cpu {
         self.system_regs.cr.get_psr()
     }
 
+    /// Byte order of data memory references, big-endian while PSR.be is set
+    pub fn data_endian(&self) -> Endian {
+        if self.get_psr() & PSRFlags::BE.bits() != 0 {
+            Endian::Big
+        } else {
+            Endian::Little
+        }
+    }
+
     /// Get interruption status register
     pub fn get_isr(&self) -> u64 {
         self.system_regs.cr.get_isr()
@@ -516,7 +991,9 @@ impl Cpu {
         memory: &mut Memory,
         count: u32,
     ) -> Result<(), EmulatorError> {
-        self.rse.allocate_registers(memory, count)
+        self.rse.allocate_registers(memory, count)?;
+        self.sync_rse();
+        Ok(())
     }
 
     /// Deallocate registers from current frame
@@ -525,12 +1002,165 @@ impl Cpu {
         memory: &mut Memory,
         count: u32,
     ) -> Result<(), EmulatorError> {
-        self.rse.deallocate_registers(memory, count)
+        self.rse.deallocate_registers(memory, count)?;
+        self.sync_rse();
+        Ok(())
     }
 
     /// Flush RSE
     pub fn flush_rse(&mut self, memory: &mut Memory) -> Result<(), EmulatorError> {
-        self.rse.flush(memory)
+        self.check_rse(RseChecker::flushrs);
+        self.rse.flush(memory)?;
+        self.sync_rse();
+        Ok(())
+    }
+
+    /// Load registers below BSP into the register stack (loadrs)
+    ///
+    /// Only allowed in enforced lazy mode.
+    pub fn loadrs(&mut self, memory: &mut Memory, distance: u64) -> Result<(), EmulatorError> {
+        self.check_enforced_lazy("loadrs")?;
+        self.rse.loadrs(memory, distance)?;
+        self.sync_rse();
+        Ok(())
+    }
+
+    /// Read application register
+    ///
+    /// RSC, BSP, BSPSTORE and RNAT reflect the current RSE state and ITC the
+    /// interval timer.
+    pub fn read_ar(&self, index: AR) -> Result<u64, EmulatorError> {
+        match index {
+            AR::ITC => Ok(self.timer.read_itc()),
+            AR::RSC => Ok(self.rse.get_rsc()),
+            AR::BSP => Ok(self.rse.get_bsp()),
+            AR::BSPSTORE => Ok(self.rse.get_bspstore()),
+            AR::RNAT => Ok(self.rse.get_rnat()),
+            _ => self.system_regs.ar.read(index),
+        }
+    }
+
+    /// Write application register
+    ///
+    /// BSP is read-only; RSC, BSPSTORE and RNAT update the RSE state.
+    /// BSPSTORE and RNAT can only be written in enforced lazy mode.
+    pub fn write_ar(&mut self, index: AR, value: u64) -> Result<(), EmulatorError> {
+        match index {
+            AR::BSP => Err(EmulatorError::RegisterError("BSP is read-only".to_string())),
+            AR::RSC => {
+                let cpl = self.privilege_level();
+                self.rse.set_rsc(value, cpl)
+            }
+            AR::BSPSTORE => {
+                self.check_enforced_lazy("write to AR.BSPSTORE")?;
+                self.rse.set_bspstore(value)
+            }
+            AR::ITC => {
+                self.timer.write_itc(value);
+                Ok(())
+            }
+            AR::RNAT => {
+                self.check_enforced_lazy("write to AR.RNAT")?;
+                self.rse.set_rnat(value);
+                Ok(())
+            }
+            // Only the low six bits of AR.EC are implemented
+            AR::EC => self.system_regs.ar.write(index, value & 0x3F),
+            _ => self.system_regs.ar.write(index, value),
+        }
+    }
+
+    /// Read control register
+    pub fn read_cr(&self, index: CRIndex) -> u64 {
+        match index {
+            CRIndex::ITM => self.timer.itm(),
+            _ => self.system_regs.cr.read(index),
+        }
+    }
+
+    /// Write control register
+    ///
+    /// Writing ITM arms the interval timer match, and writing IVA points
+    /// external interrupt delivery at the table's entry for them.
+    pub fn write_cr(&mut self, index: CRIndex, value: u64) -> Result<(), EmulatorError> {
+        match index {
+            CRIndex::ITM => self.timer.set_itm(value),
+            CRIndex::IVA => self.interrupt_ctrl.register_handler(
+                InterruptVector::ExtInt,
+                value.wrapping_add(IVT_EXTINT_OFFSET),
+                0,
+            )?,
+            CRIndex::IFS => self.check_rse(RseChecker::write_ifs),
+            _ => {}
+        }
+        self.system_regs.cr.write(index, value)
+    }
+
+    /// Select the AR.ITC time source
+    pub fn set_timer_mode(&mut self, mode: TimerMode) {
+        self.timer.set_mode(mode);
+    }
+
+    /// Narrow `value` to `T`, faulting with strict types on if it does not
+    /// fit
+    pub fn narrow<T: Narrow>(&self, value: u64, what: &'static str) -> Result<T, EmulatorError> {
+        checked::narrow_if(value, what, self.strict_types)
+    }
+
+    /// `value` for a `bits` bit wide register field, faulting with strict
+    /// types on if it does not fit
+    pub fn pack(&self, value: u64, bits: u32, what: &'static str) -> Result<u64, EmulatorError> {
+        checked::pack_if(value, bits, what, self.strict_types)
+    }
+
+    /// Advance the interval timer by `cycles` the processor took
+    ///
+    /// When ITC reaches ITM and ITV is unmasked, the ITV vector is set in
+    /// IRR and an external interrupt is raised.
+    pub fn tick_timer(&mut self, cycles: u64) -> Result<(), EmulatorError> {
+        self.timer.advance(cycles);
+        if !self.timer.poll() {
+            return Ok(());
+        }
+
+        let itv = self.system_regs.cr.read(CRIndex::ITV);
+        if itv & ITV_MASK != 0 {
+            return Ok(());
+        }
+
+        self.raise_external_interrupt(itv & 0xFF)
+    }
+
+    /// Set external interrupt `vector` in IRR and raise an external
+    /// interrupt with it as the info
+    pub fn raise_external_interrupt(&mut self, vector: u64) -> Result<(), EmulatorError> {
+        let irr = match vector / 64 {
+            0 => CRIndex::IRR0,
+            1 => CRIndex::IRR1,
+            2 => CRIndex::IRR2,
+            _ => CRIndex::IRR3,
+        };
+        let pending = self.system_regs.cr.read(irr) | (1 << (vector % 64));
+        self.system_regs.cr.write(irr, pending)?;
+        self.raise_interrupt(InterruptVector::ExtInt, vector);
+        Ok(())
+    }
+
+    /// What reading CR.IVR returns: the highest external interrupt vector
+    /// pending in IRR, or the spurious vector if none is
+    ///
+    /// The read acknowledges the vector, so a pending one comes with the
+    /// IRR register to write and its value with the vector cleared.
+    pub fn interrupt_vector(&self) -> (u64, Option<(CRIndex, u64)>) {
+        let irr = [CRIndex::IRR0, CRIndex::IRR1, CRIndex::IRR2, CRIndex::IRR3];
+        for (word, &index) in irr.iter().enumerate().rev() {
+            let pending = self.system_regs.cr.read(index);
+            if pending != 0 {
+                let bit = 63 - pending.leading_zeros() as u64;
+                return (word as u64 * 64 + bit, Some((index, pending & !(1 << bit))));
+            }
+        }
+        (SPURIOUS_VECTOR, None)
     }
 
     /// Handle branch with alloc
@@ -549,31 +1179,388 @@ impl Cpu {
             self.rse.allocate_registers(memory, to_allocate)?;
         } else if to_deallocate > 0 {
             self.rse.deallocate_registers(memory, to_deallocate)?;
+            self.sync_rse();
         }
 
-        self.cfm = (sof as u64) | ((sol as u64) << 7) | ((sor as u64) << 14);
+        self.cfm = self.frame_marker(sof, sol, sor)?;
         Ok(())
     }
 
-    /// Handle return
+    /// CFM.sof, CFM.sol and CFM.sor of a frame, without rotating register
+    /// bases
+    fn frame_marker(&self, sof: u32, sol: u32, sor: u32) -> Result<u64, EmulatorError> {
+        Ok(self.pack(sof as u64, 7, "sof")?
+            | (self.pack(sol as u64, 7, "sol")? << 7)
+            | (self.pack(sor as u64, 4, "sor")? << CFM_SOR_SHIFT))
+    }
+
+    /// Allocate a new frame for the current function (alloc)
+    ///
+    /// Registers of older frames that no longer fit in the physical register
+    /// file are spilled to the backing store. The rotating register bases
+    /// are kept, so the rotating region can only be resized while they are
+    /// all zero.
+    pub fn alloc_frame(
+        &mut self,
+        memory: &mut Memory,
+        sof: u32,
+        sol: u32,
+        sor: u32,
+    ) -> Result<(), EmulatorError> {
+        self.check_frame(sof, sol, sor)?;
+        self.make_room(memory, sof)?;
+        self.resize_frame(sof, sol, sor)
+    }
+
+    /// Check that alloc can replace the current frame with one of `sof`
+    /// registers, `sol` of them locals and `sor` groups of eight rotating
+    pub fn check_frame(&self, sof: u32, sol: u32, sor: u32) -> Result<(), EmulatorError> {
+        if sof > NUM_STACKED_GR || sol > sof || sor * 8 > sof {
+            return Err(EmulatorError::CpuStateError(format!(
+                "Invalid frame: sof={} sol={} sor={}",
+                sof, sol, sor
+            )));
+        }
+
+        let rrb = self.cfm & CFM_RRB_MASK;
+        if rrb != 0 && sor as usize * 8 != self.rotating_gr_count() {
+            return Err(EmulatorError::ExecutionError(format!(
+                "Illegal operation: alloc resizes the rotating region to sor={} with \
+                 rotating register bases {:#x}",
+                sor,
+                rrb >> CFM_RRB_GR_SHIFT
+            )));
+        }
+        Ok(())
+    }
+
+    /// Replace the current frame with one [`check_frame`](Self::check_frame)
+    /// accepted, once [`make_room`](Self::make_room) has spilled what it
+    /// displaces
+    pub(crate) fn resize_frame(
+        &mut self,
+        sof: u32,
+        sol: u32,
+        sor: u32,
+    ) -> Result<(), EmulatorError> {
+        self.check_rse(RseChecker::alloc);
+        self.cfm = self.frame_marker(sof, sol, sor)? | (self.cfm & CFM_RRB_MASK);
+        if let Some(profiler) = &mut self.rse_profiler {
+            profiler.alloc(sof);
+        }
+        self.evict_excess()
+    }
+
+    /// Handle call (br.call)
+    ///
+    /// The caller's locals are pushed onto the register stack with their NaT
+    /// bits, and its outputs become the callee's frame starting at r32.
+    /// AR.PFS keeps the caller's frame marker, epilog count and privilege
+    /// level.
+    /// `target` is the callee's entry point, used by the profilers.
+    pub fn handle_call(&mut self, memory: &mut Memory, target: u64) -> Result<(), EmulatorError> {
+        self.make_room(memory, (self.cfm & 0x7F) as u32)?;
+        self.open_frame(target)
+    }
+
+    /// Push the caller's locals and make its outputs the callee's frame,
+    /// once [`make_room`](Self::make_room) has spilled what they displace
+    pub(crate) fn open_frame(&mut self, target: u64) -> Result<(), EmulatorError> {
+        let sof = (self.cfm & 0x7F) as usize;
+        let sol = ((self.cfm >> 7) & 0x7F) as usize;
+
+        if let Some(profiler) = &mut self.rse_profiler {
+            profiler.enter(&self.rse, target);
+        }
+        if let Some(profiler) = &mut self.sample_profiler {
+            profiler.enter(target);
+        }
+
+        for reg in FIRST_STACKED_GR..FIRST_STACKED_GR + sol {
+            self.rse.push_register(self.gr[reg], self.nat[reg]);
+        }
+        for reg in FIRST_STACKED_GR..FIRST_STACKED_GR + sof - sol {
+            self.gr[reg] = self.gr[reg + sol];
+            self.nat[reg] = self.nat[reg + sol];
+        }
+
+        let ec = self.read_ar(AR::EC)?;
+        self.pfs =
+            self.cfm | (ec << PFS_PEC_SHIFT) | ((self.privilege_level() as u64) << PFS_PPL_SHIFT);
+        self.cfm = (sof - sol) as u64;
+        self.evict_excess()
+    }
+
+    /// Allocate a new empty frame (cover)
+    ///
+    /// The current frame is pushed onto the register stack, as a call pushes
+    /// the caller's locals, so that flushrs writes it to the backing store.
+    /// With interruption collection off (PSR.ic clear), as in an
+    /// interruption handler, the covered frame marker is saved in CR.IFS
+    /// with its valid bit set.
+    pub fn cover(&mut self, memory: &mut Memory) -> Result<(), EmulatorError> {
+        self.check_rse(RseChecker::cover);
+        let sof = (self.cfm & 0x7F) as usize;
+        for reg in FIRST_STACKED_GR..FIRST_STACKED_GR + sof {
+            self.rse.push_register(self.gr[reg], self.nat[reg]);
+        }
+        if !self.system_regs.cr.contains(PSRFlags::IC) {
+            self.system_regs
+                .cr
+                .write(CRIndex::IFS, self.cfm | IFS_VALID)?;
+        }
+        self.cfm = 0;
+        self.spill_excess(memory)
+    }
+
+    /// Handle return (br.ret)
+    ///
+    /// The callee's frame becomes the caller's outputs again and the caller's
+    /// locals are popped from the register stack, reloading them and their
+    /// NaT bits from the backing store if they were spilled. The caller's
+    /// frame marker comes back whole, rotating register bases included,
+    /// along with its epilog count.
+    /// Returning to a
+    /// less privileged caller drops to its privilege level from AR.PFS.ppl;
+    /// a return never raises the privilege level.
     pub fn handle_return(&mut self, memory: &mut Memory) -> Result<(), EmulatorError> {
+        self.close_frame()?;
+        self.complete_rse_loads(memory)
+    }
+
+    /// Restore the caller's frame from AR.PFS, leaving the locals no longer
+    /// in the register file as mandatory loads for
+    /// [`complete_rse_loads`](Self::complete_rse_loads)
+    pub(crate) fn close_frame(&mut self) -> Result<(), EmulatorError> {
         // Get previous frame state from PFS
         let prev_sof = self.pfs & 0x7F;
         let prev_sol = (self.pfs >> 7) & 0x7F;
-        let prev_sor = (self.pfs >> 14) & 0x7F;
 
-        // Get current frame size
-        let curr_sof = self.cfm & 0x7F;
+        if let Some(profiler) = &mut self.rse_profiler {
+            profiler.leave(&self.rse);
+        }
+        if let Some(profiler) = &mut self.sample_profiler {
+            profiler.leave();
+        }
 
-        // Deallocate current frame
-        self.deallocate_registers(memory, curr_sof as u32)?;
+        let sol = prev_sol as usize;
+        let outputs = (prev_sof - prev_sol) as usize;
+        for reg in (FIRST_STACKED_GR..FIRST_STACKED_GR + outputs).rev() {
+            self.gr[reg + sol] = self.gr[reg];
+            self.nat[reg + sol] = self.nat[reg];
+        }
+        let missing = self.pop_locals(sol);
+
+        // Restore previous frame and privilege level
+        self.cfm = self.pfs & PFS_PFM_MASK;
+        self.write_ar(AR::EC, (self.pfs >> PFS_PEC_SHIFT) & 0x3F)?;
+        let ppl = ((self.pfs >> PFS_PPL_SHIFT) & 0x3) as u8;
+        self.set_privilege_level(ppl.max(self.privilege_level()));
+        self.rse
+            .begin_mandatory_loads(self.narrow(missing as u64, "mandatory loads")?)
+    }
 
-        // Restore previous frame
-        self.cfm = prev_sof | (prev_sol << 7) | (prev_sor << 14);
+    /// Pop up to `count` locals from the dirty registers into r32 and up,
+    /// highest first, returning how many are left to load
+    fn pop_locals(&mut self, count: usize) -> usize {
+        let mut missing = count;
+        while missing > 0 {
+            let Some((value, nat)) = self.rse.pop_dirty() else {
+                break;
+            };
+            missing -= 1;
+            self.gr[FIRST_STACKED_GR + missing] = value;
+            self.nat[FIRST_STACKED_GR + missing] = nat;
+        }
+        missing
+    }
 
+    /// Perform the mandatory loads pending for the current frame
+    ///
+    /// Locals that a return found only in the backing store are loaded with
+    /// their NaT bits, highest first. If a load faults, the rest stay pending
+    /// and calling this again resumes them.
+    pub fn complete_rse_loads(&mut self, memory: &mut Memory) -> Result<(), EmulatorError> {
+        while self.rse.pending_loads() > 0 {
+            let result = self.rse.mandatory_load(memory);
+            self.sync_rse();
+            let (value, nat) = result?;
+            let reg = FIRST_STACKED_GR + self.rse.pending_loads() as usize;
+            self.gr[reg] = value;
+            self.nat[reg] = nat;
+        }
+        self.sync_rse();
         Ok(())
     }
 
+    /// Current privilege level (PSR.cpl): 0 is the most privileged, user
+    /// code runs at 3
+    pub fn privilege_level(&self) -> u8 {
+        ((self.get_psr() >> PSR_CPL_SHIFT) & 0x3) as u8
+    }
+
+    /// Set PSR.cpl
+    pub fn set_privilege_level(&mut self, cpl: u8) {
+        self.system_regs
+            .cr
+            .update(|psr| (psr & !(0x3 << PSR_CPL_SHIFT)) | ((cpl as u64 & 0x3) << PSR_CPL_SHIFT));
+    }
+
+    /// Enter privileged code (epc)
+    ///
+    /// Code on a promotion page, such as the kernel's gate page, is raised
+    /// to privilege level 0; elsewhere the level is left alone. epc faults
+    /// when AR.PFS.ppl is more privileged than the current level, which
+    /// would let the matching br.ret return at a level the caller never had.
+    pub fn enter_privileged_code(&mut self) -> Result<(), EmulatorError> {
+        let cpl = self.epc_privilege()?;
+        self.set_privilege_level(cpl);
+        Ok(())
+    }
+
+    /// Privilege level epc leaves the CPU at, or the fault it raises
+    pub fn epc_privilege(&self) -> Result<u8, EmulatorError> {
+        let ppl = ((self.pfs >> PFS_PPL_SHIFT) & 0x3) as u8;
+        if ppl < self.privilege_level() {
+            return Err(EmulatorError::ExecutionError(format!(
+                "Illegal operation: epc with AR.PFS.ppl {} below PSR.cpl {}",
+                ppl,
+                self.privilege_level()
+            )));
+        }
+        if self
+            .promote_pages
+            .iter()
+            .any(|page| page.contains(&self.ip))
+        {
+            return Ok(0);
+        }
+        Ok(self.privilege_level())
+    }
+
+    /// Raise an illegal operation fault unless AR.RSC.mode is enforced lazy
+    fn check_enforced_lazy(&self, operation: &str) -> Result<(), EmulatorError> {
+        if self.rse.is_enforced_lazy() {
+            return Ok(());
+        }
+        Err(EmulatorError::ExecutionError(format!(
+            "Illegal operation: {} requires AR.RSC.mode 0, found {}",
+            operation,
+            self.rse.get_rsc() & 0x3
+        )))
+    }
+
+    /// Spill the oldest dirty registers that no longer fit beside the frame,
+    /// and give up the oldest clean registers to what room is left
+    fn spill_excess(&mut self, memory: &mut Memory) -> Result<(), EmulatorError> {
+        self.make_room(memory, (self.cfm & 0x7F) as u32)?;
+        self.evict_excess()
+    }
+
+    /// Spill the oldest dirty registers until a frame of `sof` registers
+    /// fits beside the rest in the physical register file
+    ///
+    /// The spills are made before the frame changes, so an instruction whose
+    /// spill faults can be restarted; the spills already made stay made.
+    pub(crate) fn make_room(&mut self, memory: &mut Memory, sof: u32) -> Result<(), EmulatorError> {
+        let excess = (self.rse.dirty_count() + sof).saturating_sub(NUM_STACKED_GR);
+        let result = self.rse.spill_registers(memory, excess);
+        self.sync_rse();
+        result
+    }
+
+    /// Invalidate the clean registers that no longer fit beside the current
+    /// frame
+    fn evict_excess(&mut self) -> Result<(), EmulatorError> {
+        let sof = (self.cfm & 0x7F) as u32;
+        let crowded =
+            (self.rse.dirty_count() + self.rse.clean_count() + sof).saturating_sub(NUM_STACKED_GR);
+        self.rse.evict_clean(crowded)
+    }
+
+    /// Start sampling guest execution with `profiler`, or stop with `None`
+    pub fn set_sample_profiler(&mut self, profiler: Option<SampleProfiler>) {
+        self.sample_profiler = profiler;
+    }
+
+    /// Start or stop profiling register stack use per function
+    pub fn set_rse_profiling(&mut self, enabled: bool) {
+        self.rse_profiler = enabled.then(|| RseProfiler::new(&self.rse));
+    }
+
+    /// Apply the alignment policy to a load or store of `len` bytes at
+    /// `addr` by the current instruction
+    ///
+    /// With PSR.ac set every unaligned access faults, whatever the policy.
+    /// Returns whether the access must be fixed up byte by byte; the caller
+    /// records the fixup once the access is made.
+    pub(crate) fn check_alignment(
+        &self,
+        addr: u64,
+        len: usize,
+        store: bool,
+    ) -> Result<bool, EmulatorError> {
+        if addr.is_multiple_of(len as u64) {
+            return Ok(false);
+        }
+        if self.system_regs.cr.contains(PSRFlags::AC) {
+            return Err(EmulatorError::UnalignedReference { addr, len, store });
+        }
+        match self.alignment_policy {
+            AlignmentPolicy::Allow => Ok(false),
+            AlignmentPolicy::Fault => Err(EmulatorError::UnalignedReference { addr, len, store }),
+            AlignmentPolicy::Fixup => Ok(true),
+        }
+    }
+
+    /// Fault a `len` byte access at `addr` that is not naturally aligned
+    ///
+    /// Semaphores, spills and fills cannot be done in pieces, so they fault
+    /// whatever the alignment policy and PSR.ac say.
+    pub(crate) fn require_alignment(
+        &self,
+        addr: u64,
+        len: usize,
+        store: bool,
+    ) -> Result<(), EmulatorError> {
+        if addr.is_multiple_of(len as u64) {
+            Ok(())
+        } else {
+            Err(EmulatorError::UnalignedReference { addr, len, store })
+        }
+    }
+
+    /// Start or stop checking register stack invariants
+    pub fn set_rse_checking(&mut self, enabled: bool) {
+        self.rse_checker = enabled.then(RseChecker::new);
+    }
+
+    /// Register stack invariant violations found so far
+    pub fn rse_violations(&self) -> &[RseViolation] {
+        self.rse_checker
+            .as_ref()
+            .map_or(&[], |checker| checker.violations())
+    }
+
+    /// Pass the current state to the invariant checker, if enabled
+    fn check_rse(&mut self, event: fn(&mut RseChecker, &Cpu)) {
+        if let Some(mut checker) = self.rse_checker.take() {
+            event(&mut checker, self);
+            self.rse_checker = Some(checker);
+        }
+    }
+
+    /// Charge RSE activity so far to the profiled function, and invalidate
+    /// the ALAT entries its spills overwrote
+    fn sync_rse(&mut self) {
+        if let Some(profiler) = &mut self.rse_profiler {
+            profiler.sync(&self.rse);
+        }
+        if let Some((start, end)) = self.rse.take_spilled() {
+            self.alat.invalidate_overlap(start, end - start);
+        }
+    }
+
     /// Check memory protection key
     pub fn check_protection_key(&self, key: u32, read: bool, write: bool, execute: bool) -> bool {
         if read && !self.system_regs.pkr.check_read(key) {
@@ -621,6 +1608,100 @@ impl Cpu {
         self.system_regs.rr.is_enabled(region)
     }
 
+    /// Write a region register
+    ///
+    /// Translations are tagged by region ID, so nothing is purged: those of
+    /// the RID being replaced stay in the TLB until it is installed again.
+    pub fn write_rr(&mut self, region: usize, value: u64) -> Result<(), EmulatorError> {
+        let old = self.system_regs.rr.get_rid(region)?;
+        let fields = RegionFields::from_bits(value);
+        self.system_regs.rr.write(region, fields)?;
+        if fields.rid != old {
+            self.tlb.note_switch(fields.rid);
+        }
+        Ok(())
+    }
+
+    /// Switch to another address space by installing its region IDs,
+    /// returning the address space it replaces
+    pub fn switch_address_space(
+        &mut self,
+        space: &AddressSpace,
+    ) -> Result<AddressSpace, EmulatorError> {
+        let mut previous = AddressSpace::new();
+        for region in 0..NUM_RR {
+            let Some(rid) = space.rid(region) else {
+                continue;
+            };
+            let old = self.system_regs.rr.get_rid(region)?;
+            previous = previous.with_rid(region, old)?;
+            if rid != old {
+                self.system_regs.rr.set_rid(region, rid)?;
+                self.tlb.note_switch(rid);
+            }
+        }
+        Ok(previous)
+    }
+
+    /// Insert a translation for the page at `vaddr`, tagged with the region
+    /// ID its region register holds
+    pub fn insert_translation(
+        &mut self,
+        vaddr: u64,
+        paddr: u64,
+        page_shift: u8,
+        permissions: Permissions,
+    ) -> Result<(), EmulatorError> {
+        let rid = self.get_region_id(vaddr)?;
+        self.tlb.insert(TlbEntry {
+            rid,
+            vaddr,
+            paddr,
+            page_shift,
+            permissions,
+        })
+    }
+
+    /// Translate a virtual address through the TLB, in the region ID its
+    /// region register holds
+    pub fn translate(&mut self, vaddr: u64) -> Result<Option<u64>, EmulatorError> {
+        let rid = self.get_region_id(vaddr)?;
+        Ok(self.tlb.translate(rid, vaddr))
+    }
+
+    /// Translate a virtual address, walking the VHPT on a TLB miss and
+    /// inserting what the walk finds
+    ///
+    /// A walk that faults leaves the address in CR.IFA, its VHPT entry
+    /// address in CR.IHA and its region's page size and ID in CR.ITIR, as
+    /// a miss handler expects them.
+    pub fn translate_walk(
+        &mut self,
+        memory: &mut Memory,
+        vaddr: u64,
+        instruction: bool,
+    ) -> Result<u64, EmulatorError> {
+        if let Some(paddr) = self.translate(vaddr)? {
+            return Ok(paddr);
+        }
+        match vhpt::walk(self, memory, vaddr, instruction) {
+            Ok(entry) => {
+                self.tlb.insert(entry)?;
+                let paddr = entry.paddr & !((1 << entry.page_shift) - 1);
+                Ok(paddr | (vaddr & ((1 << entry.page_shift) - 1)))
+            }
+            Err(error @ EmulatorError::TlbFault { .. }) => {
+                let region = self.system_regs.rr.read((vaddr >> 61) as usize)?;
+                let iha = vhpt::thash(self, vaddr)?;
+                self.write_cr(CRIndex::IFA, vaddr)?;
+                self.write_cr(CRIndex::IHA, iha)?;
+                self.write_cr(CRIndex::ITIR, (region.ps as u64) << 2 | region.rid << 8)?;
+                Err(error)
+            }
+            Err(error) => Err(error),
+        }
+    }
+
     /// Updates the frame markers for the current frame
     pub fn update_frame_markers(
         &mut self,
@@ -636,7 +1717,7 @@ impl Cpu {
         }
 
         // Update frame markers
-        self.cfm = (sof as u64) | ((sol as u64) << 7) | ((sor as u64) << 14);
+        self.cfm = self.frame_marker(sof, sol, sor)?;
         Ok(())
     }
 
@@ -658,7 +1739,7 @@ pub struct ProcessorState {
     /// General registers
     pub gr: [u64; NUM_GR],
     /// Floating-point registers
-    pub fr: [u64; NUM_FR],
+    pub fr: [FloatRegister; NUM_FR],
     /// Predicate registers
     pub pr: [bool; NUM_PR],
     /// Branch registers
@@ -671,10 +1752,25 @@ pub struct ProcessorState {
     pub psr: u64,
 }
 
+/// Register that `reg` names in the rotating region of `size` registers
+/// from `first` with base `rrb`; registers outside the region do not rotate
+fn rotate(reg: usize, first: usize, size: usize, rrb: usize) -> usize {
+    if reg < first || reg >= first + size {
+        return reg;
+    }
+    first + (reg - first + rrb) % size
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::cpu::instructions::memory::{Load, LoadSize, SpeculationCheck};
+    use crate::cpu::instructions::{AddressingMode, Instruction, InstructionFields, RegisterType};
+    use crate::cpu::rse::BackingStoreCursor;
     use crate::cpu::syscall::SyscallNumber;
+    use crate::cpu::tlb::AddressSpace;
+    use crate::decoder::completers::Completers;
+    use crate::memory::Permissions;
 
     #[test]
     fn test_syscall() {
@@ -698,4 +1794,446 @@ mod tests {
         assert_eq!(cpu.gr[8], count);
         assert_eq!(cpu.gr[9], 0); // no error
     }
+
+    #[test]
+    fn test_rse_application_registers() {
+        let mut cpu = Cpu::default();
+
+        // Backing store registers are only writable in enforced lazy mode
+        assert!(cpu.write_ar(AR::BSPSTORE, 0x2000).is_err());
+        assert!(cpu.write_ar(AR::RNAT, 0x5).is_err());
+        cpu.write_ar(AR::RSC, 0).unwrap();
+
+        cpu.write_ar(AR::BSPSTORE, 0x2000).unwrap();
+        assert_eq!(cpu.read_ar(AR::BSPSTORE).unwrap(), 0x2000);
+        assert_eq!(cpu.read_ar(AR::BSP).unwrap(), 0x2000);
+        assert!(cpu.write_ar(AR::BSP, 0x3000).is_err());
+
+        cpu.write_ar(AR::RNAT, 0x5).unwrap();
+        assert_eq!(cpu.read_ar(AR::RNAT).unwrap(), 0x5);
+        assert_eq!(cpu.get_rse_rnat(), 0x5);
+
+        cpu.write_ar(AR::CCV, 42).unwrap();
+        assert_eq!(cpu.read_ar(AR::CCV).unwrap(), 42);
+    }
+
+    #[test]
+    fn test_rse_spill_invalidates_alat() {
+        let mut cpu = Cpu::default();
+        let mut memory = Memory::new();
+        memory.map(0x2000, 0x1000, Permissions::ReadWrite).unwrap();
+        cpu.write_ar(AR::RSC, 0).unwrap();
+        cpu.write_ar(AR::BSPSTORE, 0x2000).unwrap();
+        cpu.alat_add_entry(0x2008, 8, 4, true).unwrap();
+        cpu.alat_add_entry(0x2100, 8, 5, true).unwrap();
+
+        // flushrs in a callee stores the caller's two locals at 0x2000 and
+        // 0x2008
+        cpu.alloc_frame(&mut memory, 2, 2, 0).unwrap();
+        cpu.handle_call(&mut memory, 0).unwrap();
+        cpu.flush_rse(&mut memory).unwrap();
+        assert_eq!(cpu.read_ar(AR::BSPSTORE).unwrap(), 0x2010);
+        assert!(!cpu.alat_check_register(4, true));
+        assert!(cpu.alat_check_register(5, true));
+    }
+
+    #[test]
+    fn test_interval_timer_match() {
+        let mut cpu = Cpu::default();
+        cpu.set_timer_mode(TimerMode::Instructions {
+            ticks_per_bundle: 2,
+        });
+        cpu.write_ar(AR::ITC, 100).unwrap();
+        cpu.write_cr(CRIndex::ITV, 0xEF).unwrap();
+        cpu.write_cr(CRIndex::ITM, 110).unwrap();
+        assert_eq!(cpu.read_cr(CRIndex::ITM), 110);
+
+        cpu.tick_timer(4).unwrap();
+        assert_eq!(cpu.read_ar(AR::ITC).unwrap(), 108);
+        assert_eq!(cpu.system_regs.cr.get_irr()[3], 0);
+
+        cpu.tick_timer(1).unwrap();
+        assert_eq!(cpu.system_regs.cr.get_irr()[3], 1 << (0xEF - 192));
+
+        // Masked timer does not pend the vector
+        cpu.write_cr(CRIndex::IRR3, 0).unwrap();
+        cpu.write_cr(CRIndex::ITV, ITV_MASK | 0xEF).unwrap();
+        cpu.write_cr(CRIndex::ITM, 120).unwrap();
+        cpu.tick_timer(10).unwrap();
+        assert_eq!(cpu.system_regs.cr.get_irr()[3], 0);
+    }
+
+    #[test]
+    fn test_spill_fill_unat() {
+        let mut cpu = Cpu::default();
+        let mut memory = Memory::new();
+        memory.map(0x1000, 0x1000, Permissions::ReadWrite).unwrap();
+
+        cpu.set_gr(4, 0x44).unwrap();
+        cpu.set_nat(4, true).unwrap();
+        cpu.set_gr(5, 0x55).unwrap();
+        cpu.spill_gr(&mut memory, 4, 0x1018).unwrap();
+        cpu.spill_gr(&mut memory, 5, 0x1020).unwrap();
+        assert_eq!(cpu.read_ar(AR::UNAT).unwrap(), 1 << 3);
+        assert!(cpu.spill_gr(&mut memory, 4, 0x1004).is_err());
+
+        cpu.set_gr(4, 0).unwrap();
+        cpu.fill_gr(&mut memory, 6, 0x1018).unwrap();
+        cpu.fill_gr(&mut memory, 7, 0x1020).unwrap();
+        assert_eq!((cpu.gr[6], cpu.nat[6]), (0x44, true));
+        assert_eq!((cpu.gr[7], cpu.nat[7]), (0x55, false));
+
+        // r0 never becomes NaT
+        cpu.fill_gr(&mut memory, 0, 0x1018).unwrap();
+        assert!(!cpu.get_nat(0).unwrap());
+    }
+
+    #[test]
+    fn test_return_load_pending() {
+        const OLD_STACK: u64 = 0x10000;
+        const NEW_STACK: u64 = 0x20000;
+
+        let mut cpu = Cpu::default();
+        let mut memory = Memory::new();
+        memory
+            .map(OLD_STACK, 0x1000, Permissions::ReadWrite)
+            .unwrap();
+        cpu.write_ar(AR::RSC, 0).unwrap();
+        cpu.write_ar(AR::BSPSTORE, OLD_STACK).unwrap();
+
+        // A caller with locals r32-r35, one of them NaT
+        cpu.alloc_frame(&mut memory, 6, 4, 0).unwrap();
+        for reg in 32..36 {
+            cpu.set_gr(reg, reg as u64).unwrap();
+        }
+        cpu.set_nat(34, true).unwrap();
+        cpu.handle_call(&mut memory, 0).unwrap();
+        let caller_pfs = cpu.pfs;
+
+        // The callee flushes, switches to a new stack and back
+        cpu.flush_rse(&mut memory).unwrap();
+        let bspstore = cpu.read_ar(AR::BSPSTORE).unwrap();
+        let rnat = cpu.read_ar(AR::RNAT).unwrap();
+        cpu.write_ar(AR::BSPSTORE, NEW_STACK).unwrap();
+        cpu.write_ar(AR::BSPSTORE, bspstore).unwrap();
+        cpu.write_ar(AR::RNAT, rnat).unwrap();
+
+        // The return's mandatory loads fault while the stack is unmapped
+        memory.unmap(OLD_STACK).unwrap();
+        cpu.pfs = caller_pfs;
+        assert!(cpu.handle_return(&mut memory).is_err());
+        assert_eq!(cpu.rse.pending_loads(), 4);
+        assert_eq!(cpu.cfm & 0x7F, 6);
+
+        // Once the page is back the loads resume
+        memory
+            .map(OLD_STACK, 0x1000, Permissions::ReadWrite)
+            .unwrap();
+        memory.write_u64(OLD_STACK, 32).unwrap();
+        cpu.complete_rse_loads(&mut memory).unwrap();
+        assert_eq!(cpu.rse.pending_loads(), 0);
+        assert_eq!((cpu.gr[32], cpu.nat[32]), (32, false));
+        assert!(cpu.nat[34]);
+        assert_eq!(cpu.rse.get_bspstore(), OLD_STACK);
+
+        // Other modes cannot switch backing stores
+        cpu.write_ar(AR::RSC, 0x3).unwrap();
+        assert!(cpu.write_ar(AR::BSPSTORE, NEW_STACK).is_err());
+        assert!(cpu.loadrs(&mut memory, 0).is_err());
+    }
+
+    #[test]
+    fn test_return_into_clean_registers() {
+        const STACK: u64 = 0x10000;
+
+        let mut cpu = Cpu::default();
+        let mut memory = Memory::new();
+        memory.map(STACK, 0x1000, Permissions::ReadWrite).unwrap();
+        cpu.rse.set_bspstore(STACK).unwrap();
+
+        cpu.alloc_frame(&mut memory, 4, 4, 0).unwrap();
+        for reg in 32..36 {
+            cpu.set_gr(reg, reg as u64 * 0x11).unwrap();
+        }
+        cpu.set_nat(33, true).unwrap();
+        cpu.handle_call(&mut memory, 0).unwrap();
+
+        // flushrs stores the locals but leaves them in the register file
+        cpu.flush_rse(&mut memory).unwrap();
+        assert_eq!(memory.read_u64(STACK + 8).unwrap(), 0x231);
+        assert_eq!(cpu.read_ar(AR::RNAT).unwrap(), 1 << 1);
+        assert_eq!(cpu.rse.clean_count(), 4);
+
+        // The return takes them from there without reading the stack
+        memory.protect(STACK, Permissions::None).unwrap();
+        cpu.handle_return(&mut memory).unwrap();
+        assert_eq!(cpu.gr[32..36], [0x220, 0x231, 0x242, 0x253]);
+        assert!(cpu.nat[33] && !cpu.nat[32]);
+        assert_eq!(cpu.rse.fill_count(), 0);
+        assert_eq!(cpu.rse.get_bspstore(), STACK);
+
+        // Deep calls crowd the oldest clean registers out of the file
+        memory.protect(STACK, Permissions::ReadWrite).unwrap();
+        cpu.handle_call(&mut memory, 0).unwrap();
+        cpu.flush_rse(&mut memory).unwrap();
+        cpu.alloc_frame(&mut memory, 94, 94, 0).unwrap();
+        assert_eq!(cpu.rse.clean_count(), 2);
+        assert_eq!(cpu.rse.get_bspload(), STACK + 2 * 8);
+    }
+
+    #[test]
+    fn test_interruption_frame_marker() {
+        const STACK: u64 = 0x10000;
+        const HANDLER: u64 = 0x8000;
+        const INTERRUPTED: u64 = 8 | 6 << 7;
+
+        let mut cpu = Cpu::default();
+        let mut memory = Memory::new();
+        memory.map(STACK, 0x1000, Permissions::ReadWrite).unwrap();
+        cpu.rse.set_bspstore(STACK).unwrap();
+        cpu.register_interrupt_handler(InterruptVector::ExtInt, HANDLER, 0)
+            .unwrap();
+        cpu.alloc_frame(&mut memory, 8, 6, 0).unwrap();
+        for reg in 32..40 {
+            cpu.set_gr(reg, reg as u64).unwrap();
+        }
+        let interrupt = |cpu: &mut Cpu| {
+            cpu.system_regs.cr.set(PSRFlags::IC, true);
+            cpu.set_interrupts_enabled(true);
+            cpu.raise_interrupt(InterruptVector::ExtInt, 0);
+            assert_eq!(cpu.check_interrupts(), Some(HANDLER));
+        };
+
+        // Delivery clears IFS.v; cover saves the frame in IFS and sets it,
+        // and rfi brings the frame back from the register stack
+        cpu.write_cr(CRIndex::IFS, IFS_VALID | 3).unwrap();
+        interrupt(&mut cpu);
+        assert_eq!(cpu.read_cr(CRIndex::IFS), 3);
+        cpu.cover(&mut memory).unwrap();
+        assert_eq!(cpu.read_cr(CRIndex::IFS), INTERRUPTED | IFS_VALID);
+        cpu.alloc_frame(&mut memory, 4, 4, 0).unwrap();
+        cpu.set_gr(32, 0xdead).unwrap();
+        cpu.rfi(&mut memory).unwrap();
+        assert_eq!(cpu.cfm, INTERRUPTED);
+        assert_eq!(cpu.gr[32..40], [32, 33, 34, 35, 36, 37, 38, 39]);
+
+        // Without cover IFS.v stays clear and rfi keeps the handler's
+        // frame, even with a frame marker left in IFS
+        interrupt(&mut cpu);
+        assert_eq!(cpu.read_cr(CRIndex::IFS), INTERRUPTED);
+        cpu.alloc_frame(&mut memory, 2, 2, 0).unwrap();
+        cpu.rfi(&mut memory).unwrap();
+        assert_eq!(cpu.cfm, 2 | 2 << 7);
+
+        // With collection on, cover leaves IFS alone
+        cpu.cover(&mut memory).unwrap();
+        assert_eq!(cpu.read_cr(CRIndex::IFS), INTERRUPTED);
+    }
+
+    #[test]
+    fn test_rotating_registers() {
+        let mut cpu = Cpu::default();
+        let mut memory = Memory::new();
+        cpu.alloc_frame(&mut memory, 16, 16, 1).unwrap();
+
+        // A rotation moves each rotating register's value up by one
+        cpu.set_gr(32, 1).unwrap();
+        cpu.set_fr(32, 2.0).unwrap();
+        cpu.set_pr(16, true).unwrap();
+        cpu.rotate_registers();
+        assert_eq!(cpu.get_gr(33).unwrap(), 1);
+        assert_eq!(cpu.get_fr(33).unwrap(), 2.0);
+        assert!(cpu.get_pr(17).unwrap() && !cpu.get_pr(16).unwrap());
+
+        // r39 wraps around to r32; r40 is outside the rotating region
+        cpu.set_gr(39, 7).unwrap();
+        cpu.set_gr(40, 8).unwrap();
+        cpu.rotate_registers();
+        assert_eq!(cpu.get_gr(32).unwrap(), 7);
+        assert_eq!(cpu.get_gr(34).unwrap(), 1);
+        assert_eq!(cpu.get_gr(40).unwrap(), 8);
+        assert_eq!(cpu.rotating_bases(), (6, 94, 46));
+
+        // alloc keeps the bases, but cannot resize the region under them
+        assert!(cpu.alloc_frame(&mut memory, 16, 16, 2).is_err());
+        cpu.alloc_frame(&mut memory, 24, 16, 1).unwrap();
+        assert_eq!(cpu.rotating_bases(), (6, 94, 46));
+
+        // The callee starts unrotated and the return restores the caller's
+        // bases
+        cpu.handle_call(&mut memory, 0).unwrap();
+        assert_eq!(cpu.rotating_bases(), (0, 0, 0));
+        cpu.handle_return(&mut memory).unwrap();
+        assert_eq!(cpu.rotating_bases(), (6, 94, 46));
+        assert_eq!(cpu.get_gr(34).unwrap(), 1);
+
+        cpu.clear_rotating_bases(true);
+        assert_eq!(cpu.rotating_bases(), (6, 94, 0));
+        cpu.clear_rotating_bases(false);
+        assert_eq!(cpu.rotating_bases(), (0, 0, 0));
+        assert_eq!(cpu.get_gr(32).unwrap(), 1);
+    }
+
+    #[test]
+    fn test_address_space_switch() {
+        const USER: u64 = 0x2000_0000_0000_4000;
+        let mut cpu = Cpu::new();
+        let process_a = AddressSpace::new().with_rid(1, 0x100).unwrap();
+        let process_b = AddressSpace::new().with_rid(1, 0x200).unwrap();
+
+        cpu.switch_address_space(&process_a).unwrap();
+        cpu.insert_translation(USER, 0x10_0000, 14, Permissions::ReadWrite)
+            .unwrap();
+        let previous = cpu.switch_address_space(&process_b).unwrap();
+        assert_eq!(previous.rid(1), Some(0x100));
+        assert_eq!(previous.rid(0), None);
+        assert_eq!(cpu.translate(USER).unwrap(), None);
+        cpu.insert_translation(USER, 0x20_0000, 14, Permissions::ReadWrite)
+            .unwrap();
+
+        // Switching back finds process A's translation without a purge
+        cpu.switch_address_space(&process_a).unwrap();
+        assert_eq!(cpu.translate(USER + 8).unwrap(), Some(0x10_0008));
+        cpu.write_rr(1, 0x200 << 8).unwrap();
+        assert_eq!(cpu.translate(USER).unwrap(), Some(0x20_0000));
+        let stats = cpu.tlb.stats();
+        assert_eq!((stats.rid_switches, stats.rid_reuses), (4, 2));
+        assert_eq!(stats.purged, 0);
+        assert!(AddressSpace::new().with_rid(8, 1).is_err());
+        assert!(AddressSpace::new().with_rid(0, 1 << 24).is_err());
+    }
+
+    #[test]
+    fn test_nat_survives_register_stack() {
+        const STACK_A: u64 = 0x10000;
+        const STACK_B: u64 = 0x18000;
+        const SAVE_AREA: u64 = 0x20000;
+        const DEPTH: u64 = 12;
+        const LOCALS: u32 = 20;
+
+        let mut cpu = Cpu::default();
+        let mut memory = Memory::new();
+        memory
+            .map(STACK_A, 0x10000, Permissions::ReadWrite)
+            .unwrap();
+        memory
+            .map(SAVE_AREA, 0x1000, Permissions::ReadWrite)
+            .unwrap();
+        cpu.set_pr(0, true).unwrap();
+        cpu.write_ar(AR::RSC, 0).unwrap();
+        cpu.write_ar(AR::BSPSTORE, STACK_A).unwrap();
+
+        // Speculative loads from an unmapped page defer their faults
+        cpu.alloc_frame(&mut memory, 8, 6, 0).unwrap();
+        for reg in [33, 4] {
+            let fields = InstructionFields::new(
+                0,
+                0,
+                vec![],
+                vec![RegisterType::GR(reg)],
+                None,
+                Some(AddressingMode::Absolute(0xdead_0000)),
+            );
+            Load::from_decoded(fields, LoadSize::Double, Completers::S)
+                .execute(&mut cpu, &mut memory)
+                .unwrap();
+        }
+        assert!(cpu.nat[33] && cpu.nat[4]);
+        cpu.set_gr(32, 0x32).unwrap();
+
+        // A deep call chain spills the locals across several NaT collections
+        let local = |depth: u64, i: u64| (depth << 8) | i;
+        let local_nat = |depth: u64, i: u64| i == depth % 19;
+        for depth in 0..DEPTH {
+            cpu.handle_call(&mut memory, 0).unwrap();
+            cpu.alloc_frame(&mut memory, LOCALS + 2, LOCALS, 0).unwrap();
+            for i in 0..19 {
+                let reg = FIRST_STACKED_GR + i as usize;
+                cpu.set_gr(reg, local(depth, i)).unwrap();
+                cpu.set_nat(reg, local_nat(depth, i)).unwrap();
+            }
+            // alloc loc19 = ar.pfs
+            cpu.set_gr(51, cpu.pfs).unwrap();
+        }
+        assert!(cpu.rse.get_bspstore() > STACK_A + 0x200 * 2);
+
+        // Switch contexts: flush the stack, save AR.BSPSTORE, AR.RNAT and
+        // the preserved static registers through AR.UNAT
+        cpu.handle_call(&mut memory, 0).unwrap();
+        cpu.flush_rse(&mut memory).unwrap();
+        let saved_pfs = cpu.pfs;
+        let saved_bspstore = cpu.read_ar(AR::BSPSTORE).unwrap();
+        let saved_rnat = cpu.read_ar(AR::RNAT).unwrap();
+        for reg in 4..8 {
+            cpu.spill_gr(&mut memory, reg, SAVE_AREA + 8 * reg as u64)
+                .unwrap();
+        }
+        let saved_unat = cpu.read_ar(AR::UNAT).unwrap();
+
+        // The other context clobbers every register and the collections
+        cpu.write_ar(AR::BSPSTORE, STACK_B).unwrap();
+        cpu.write_ar(AR::RNAT, 0).unwrap();
+        cpu.write_ar(AR::UNAT, 0).unwrap();
+        for reg in 1..NUM_GR {
+            cpu.set_gr(reg, 0xbad).unwrap();
+        }
+        cpu.alloc_frame(&mut memory, 16, 16, 0).unwrap();
+        cpu.handle_call(&mut memory, 0).unwrap();
+        cpu.flush_rse(&mut memory).unwrap();
+
+        // Switch back, reloading part of the stack eagerly with loadrs
+        cpu.write_ar(AR::BSPSTORE, saved_bspstore).unwrap();
+        cpu.write_ar(AR::RNAT, saved_rnat).unwrap();
+        cpu.write_ar(AR::UNAT, saved_unat).unwrap();
+        for reg in 4..8 {
+            cpu.fill_gr(&mut memory, reg, SAVE_AREA + 8 * reg as u64)
+                .unwrap();
+        }
+        let loaded = BackingStoreCursor::new(saved_bspstore)
+            .unwrap()
+            .skip(-70)
+            .unwrap();
+        cpu.loadrs(&mut memory, saved_bspstore - loaded.addr())
+            .unwrap();
+        cpu.pfs = saved_pfs;
+        cpu.handle_return(&mut memory).unwrap();
+
+        // Unwind the chain; every frame gets its locals and NaT bits back
+        for depth in (0..DEPTH).rev() {
+            for i in 0..19 {
+                let reg = FIRST_STACKED_GR + i as usize;
+                assert_eq!(cpu.gr[reg], local(depth, i), "depth {} r{}", depth, reg);
+                assert_eq!(
+                    cpu.nat[reg],
+                    local_nat(depth, i),
+                    "depth {} r{}",
+                    depth,
+                    reg
+                );
+            }
+            // mov ar.pfs = loc19; br.ret
+            cpu.pfs = cpu.gr[51];
+            cpu.handle_return(&mut memory).unwrap();
+        }
+        assert_eq!(cpu.read_ar(AR::BSP).unwrap(), STACK_A);
+        assert_eq!((cpu.gr[32], cpu.nat[32]), (0x32, false));
+        assert!(cpu.nat[4] && !cpu.nat[5]);
+
+        // chk.s finds the deferred fault and branches to recovery
+        cpu.ip = 0x4000;
+        for (reg, ip) in [(32, 0x4000), (33, 0x4100)] {
+            let fields = InstructionFields::new(
+                0,
+                0,
+                vec![RegisterType::GR(reg)],
+                vec![],
+                Some(0x100),
+                None,
+            );
+            SpeculationCheck::new(fields)
+                .execute(&mut cpu, &mut memory)
+                .unwrap();
+            assert_eq!(cpu.ip, ip);
+        }
+    }
 }
@@ -0,0 +1,232 @@
+//! Performance monitoring unit
+//!
+//! The PMU follows the Itanium 2 layout of the architected registers:
+//! PMC0 holds the freeze bit and the overflow bits of the generic counters,
+//! PMC4-PMC7 configure the generic counters PMD4-PMD7, and the counters are
+//! 47 bits wide. Each generic counter counts the event its PMC selects at
+//! the privilege levels in the PMC's level mask: CPU cycles, one per
+//! bundle, retired instructions, one per slot, data reads that missed the
+//! first, second or third cache level of the memory model, or branches the
+//! branch prediction model mispredicted. Cache misses are counted per byte
+//! read, so an access filling a line counts once. Other events count
+//! nothing, and other PMC and PMD indices are unimplemented; they read as
+//! zero and ignore writes.
+//!
+//! Access control lives with the instructions: writing either register
+//! file and reading PMCs is privileged, while PMDs can be read at user level
+//! unless secured (see `instructions::system::mov_from_pmd`).
+
+/// First generic counter
+pub const FIRST_GENERIC: usize = 4;
+
+/// Number of generic counters
+pub const NUM_GENERIC: usize = 4;
+
+/// Width of the generic counters in bits
+pub const COUNTER_WIDTH: u32 = 47;
+
+/// PMC0: counting is frozen
+pub const PMC0_FREEZE: u64 = 1 << 0;
+
+/// PMC privilege level mask, one bit per level
+pub const PMC_PLM_MASK: u64 = 0xF;
+
+/// PMC: the counter is a privileged monitor
+pub const PMC_PM: u64 = 1 << 6;
+
+/// PMC event select field position
+pub const PMC_ES_SHIFT: u32 = 8;
+
+/// Event counting CPU cycles
+pub const EVENT_CPU_CYCLES: u64 = 0x12;
+
+/// Event counting retired instructions
+pub const EVENT_INST_RETIRED: u64 = 0x08;
+
+/// Event counting data reads that missed L1D
+pub const EVENT_L1D_READ_MISSES: u64 = 0xC7;
+
+/// Event counting data reads that missed L2
+pub const EVENT_L2_MISSES: u64 = 0xCB;
+
+/// Event counting data reads that missed L3
+pub const EVENT_L3_MISSES: u64 = 0xDC;
+
+/// Event counting mispredicted branches
+pub const EVENT_BR_MISPRED_DETAIL: u64 = 0x5B;
+
+/// Implemented PMC and PMD indices
+const NUM_REGISTERS: usize = FIRST_GENERIC + NUM_GENERIC;
+
+/// Mask of a generic counter's value
+const COUNTER_MASK: u64 = (1 << COUNTER_WIDTH) - 1;
+
+/// Events of one retired bundle
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PmuEvents {
+    /// CPU cycles
+    pub cycles: u64,
+    /// Retired instructions
+    pub instructions: u64,
+    /// Data reads that missed L1D
+    pub l1d_misses: u64,
+    /// Data reads that missed L2
+    pub l2_misses: u64,
+    /// Data reads that missed L3
+    pub l3_misses: u64,
+    /// Mispredicted branches
+    pub branch_mispredicts: u64,
+}
+
+/// Performance monitor configuration and data registers
+#[derive(Debug, Clone, Default)]
+pub struct Pmu {
+    /// Configuration registers
+    pmc: [u64; NUM_REGISTERS],
+    /// Data registers
+    pmd: [u64; NUM_REGISTERS],
+}
+
+impl Pmu {
+    /// Create a PMU with every counter disabled
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Read a configuration register
+    pub fn read_pmc(&self, index: u64) -> u64 {
+        self.pmc.get(index as usize).copied().unwrap_or(0)
+    }
+
+    /// Write a configuration register
+    pub fn write_pmc(&mut self, index: u64, value: u64) {
+        if let Some(pmc) = self.pmc.get_mut(index as usize) {
+            *pmc = value;
+        }
+    }
+
+    /// Read a data register
+    pub fn read_pmd(&self, index: u64) -> u64 {
+        if is_generic(index) {
+            self.pmd[index as usize]
+        } else {
+            0
+        }
+    }
+
+    /// Write a data register, truncated to the counter width
+    pub fn write_pmd(&mut self, index: u64, value: u64) {
+        if is_generic(index) {
+            self.pmd[index as usize] = value & COUNTER_MASK;
+        }
+    }
+
+    /// Whether a data register belongs to a privileged monitor
+    pub fn is_privileged_monitor(&self, index: u64) -> bool {
+        is_generic(index) && self.pmc[index as usize] & PMC_PM != 0
+    }
+
+    /// Count the cycles and instructions of a retired bundle at privilege
+    /// level `cpl`
+    pub fn count(&mut self, cpl: u8, cycles: u64, instructions: u64) {
+        self.count_events(
+            cpl,
+            &PmuEvents {
+                cycles,
+                instructions,
+                ..PmuEvents::default()
+            },
+        );
+    }
+
+    /// Count the events of a retired bundle at privilege level `cpl`
+    ///
+    /// A counter that wraps sets its overflow bit in PMC0.
+    pub fn count_events(&mut self, cpl: u8, events: &PmuEvents) {
+        if self.pmc[0] & PMC0_FREEZE != 0 {
+            return;
+        }
+        for index in FIRST_GENERIC..NUM_REGISTERS {
+            let pmc = self.pmc[index];
+            if pmc & PMC_PLM_MASK & (1 << cpl) == 0 {
+                continue;
+            }
+            let count = match (pmc >> PMC_ES_SHIFT) & 0xFF {
+                EVENT_CPU_CYCLES => events.cycles,
+                EVENT_INST_RETIRED => events.instructions,
+                EVENT_L1D_READ_MISSES => events.l1d_misses,
+                EVENT_L2_MISSES => events.l2_misses,
+                EVENT_L3_MISSES => events.l3_misses,
+                EVENT_BR_MISPRED_DETAIL => events.branch_mispredicts,
+                _ => continue,
+            };
+            let value = self.pmd[index] + count;
+            if value > COUNTER_MASK {
+                self.pmc[0] |= 1 << index;
+            }
+            self.pmd[index] = value & COUNTER_MASK;
+        }
+    }
+}
+
+/// Whether an index names a generic counter
+fn is_generic(index: u64) -> bool {
+    (FIRST_GENERIC as u64..NUM_REGISTERS as u64).contains(&index)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_counting() {
+        let mut pmu = Pmu::new();
+        // PMD4 counts user cycles, PMD5 kernel instructions
+        pmu.write_pmc(4, (EVENT_CPU_CYCLES << PMC_ES_SHIFT) | 0x8);
+        pmu.write_pmc(5, (EVENT_INST_RETIRED << PMC_ES_SHIFT) | 0x1 | PMC_PM);
+        pmu.count(3, 1, 3);
+        pmu.count(0, 1, 2);
+        assert_eq!((pmu.read_pmd(4), pmu.read_pmd(5)), (1, 2));
+        assert!(pmu.is_privileged_monitor(5) && !pmu.is_privileged_monitor(4));
+
+        // Wrapping sets the overflow bit; freezing stops counting
+        pmu.write_pmd(4, u64::MAX);
+        assert_eq!(pmu.read_pmd(4), COUNTER_MASK);
+        pmu.count(3, 1, 0);
+        assert_eq!((pmu.read_pmd(4), pmu.read_pmc(0)), (0, 1 << 4));
+        pmu.write_pmc(0, PMC0_FREEZE);
+        pmu.count(3, 1, 0);
+        assert_eq!(pmu.read_pmd(4), 0);
+
+        // Unimplemented registers read as zero and ignore writes
+        pmu.write_pmd(2, 5);
+        pmu.write_pmc(200, 5);
+        assert_eq!((pmu.read_pmd(2), pmu.read_pmc(200)), (0, 0));
+    }
+
+    #[test]
+    fn test_model_events() {
+        let mut pmu = Pmu::new();
+        let events = [
+            EVENT_L1D_READ_MISSES,
+            EVENT_L2_MISSES,
+            EVENT_L3_MISSES,
+            EVENT_BR_MISPRED_DETAIL,
+        ];
+        for (index, event) in (FIRST_GENERIC..).zip(events) {
+            pmu.write_pmc(index as u64, (event << PMC_ES_SHIFT) | PMC_PLM_MASK);
+        }
+        let bundle = PmuEvents {
+            cycles: 1,
+            instructions: 3,
+            l1d_misses: 8,
+            l2_misses: 2,
+            l3_misses: 1,
+            branch_mispredicts: 1,
+        };
+        pmu.count_events(3, &bundle);
+        pmu.count_events(0, &bundle);
+        let counts: Vec<u64> = (4..8).map(|index| pmu.read_pmd(index)).collect();
+        assert_eq!(counts, [16, 4, 2, 2]);
+    }
+}
@@ -1,9 +1,14 @@
+use super::fpsr::DEFAULT_FPSR;
 use crate::EmulatorError;
 
 /// Number of application registers
 pub const NUM_AR: usize = 128;
 
 /// Application Register numbers
+///
+/// Only architected registers have variants. Earlier versions had
+/// `PFD1`-`PFD17` and `PFC1`-`PFC7` at 65-81 and 89-95. Those are reserved
+/// numbers, apart from 65 and 66, which are [`AR::LC`] and [`AR::EC`].
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[repr(u8)]
 pub enum AR {
@@ -39,55 +44,10 @@ pub enum AR {
     /// Interval Time Counter Register
     ITC = 44,
 
-    /// Performance Data Register 1
-    PFD1 = 65,
-    /// Performance Data Register 2
-    PFD2 = 66,
-    /// Performance Data Register 3
-    PFD3 = 67,
-    /// Performance Data Register 4
-    PFD4 = 68,
-    /// Performance Data Register 5
-    PFD5 = 69,
-    /// Performance Data Register 6
-    PFD6 = 70,
-    /// Performance Data Register 7
-    PFD7 = 71,
-    /// Performance Data Register 8
-    PFD8 = 72,
-    /// Performance Data Register 9
-    PFD9 = 73,
-    /// Performance Data Register 10
-    PFD10 = 74,
-    /// Performance Data Register 11
-    PFD11 = 75,
-    /// Performance Data Register 12
-    PFD12 = 76,
-    /// Performance Data Register 13
-    PFD13 = 77,
-    /// Performance Data Register 14
-    PFD14 = 78,
-    /// Performance Data Register 15
-    PFD15 = 79,
-    /// Performance Data Register 16
-    PFD16 = 80,
-    /// Performance Data Register 17
-    PFD17 = 81,
-
-    /// Performance Counter Register 1
-    PFC1 = 89,
-    /// Performance Counter Register 2
-    PFC2 = 90,
-    /// Performance Counter Register 3
-    PFC3 = 91,
-    /// Performance Counter Register 4
-    PFC4 = 92,
-    /// Performance Counter Register 5
-    PFC5 = 93,
-    /// Performance Counter Register 6
-    PFC6 = 94,
-    /// Performance Counter Register 7
-    PFC7 = 95,
+    /// Loop Count Register
+    LC = 65,
+    /// Epilog Count Register
+    EC = 66,
 
     /// CPUID Register 1
     CPUID1 = 97,
@@ -107,15 +67,15 @@ impl AR {
             // 1. The bit patterns are validated by the match arms
             // 2. The enum variants are repr(u64) and can hold these values
             // 3. The ranges are non-overlapping and exhaustive
-            0..=7 => Some(unsafe { std::mem::transmute::<u8, AR>(bits) }),
+            1..=7 => Some(unsafe { std::mem::transmute::<u8, AR>(bits) }),
             16..=19 => Some(unsafe { std::mem::transmute::<u8, AR>(bits) }),
             32 => Some(Self::CCV),
             36 => Some(Self::UNAT),
             40 => Some(Self::FPSR),
             44 => Some(Self::ITC),
-            64..=81 => Some(unsafe { std::mem::transmute::<u8, AR>(bits) }),
-            88..=95 => Some(unsafe { std::mem::transmute::<u8, AR>(bits) }),
-            96..=100 => Some(unsafe { std::mem::transmute::<u8, AR>(bits) }),
+            65 => Some(Self::LC),
+            66 => Some(Self::EC),
+            97..=100 => Some(unsafe { std::mem::transmute::<u8, AR>(bits) }),
             _ => None,
         }
     }
@@ -135,9 +95,11 @@ impl Default for ARFile {
 }
 
 impl ARFile {
-    /// Create new register file
+    /// Create new register file, with AR.FPSR at its process start value
     pub fn new() -> Self {
-        Self { regs: [0; NUM_AR] }
+        let mut regs = [0; NUM_AR];
+        regs[AR::FPSR as usize] = DEFAULT_FPSR;
+        Self { regs }
     }
 
     /// Read register value
@@ -40,6 +40,8 @@ pub enum CRIndex {
     TPHA = 26,
     /// External Interrupt Vector Register
     XIVA = 27,
+    /// Interruption Instruction Pointer: bundle address rfi returns to
+    IIP = 28,
     /// Local ID
     LID = 64,
     /// Task Priority Register
@@ -74,7 +76,7 @@ impl CRIndex {
             // 3. The ranges are non-overlapping and exhaustive
             0..=2 => Some(unsafe { std::mem::transmute::<u8, CRIndex>(bits) }),
             8 => Some(Self::PTA),
-            16..=27 => Some(unsafe { std::mem::transmute::<u8, CRIndex>(bits) }),
+            16..=28 => Some(unsafe { std::mem::transmute::<u8, CRIndex>(bits) }),
             64..=69 => Some(unsafe { std::mem::transmute::<u8, CRIndex>(bits) }),
             72..=74 => Some(unsafe { std::mem::transmute::<u8, CRIndex>(bits) }),
             80..=81 => Some(unsafe { std::mem::transmute::<u8, CRIndex>(bits) }),
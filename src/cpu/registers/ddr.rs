@@ -4,32 +4,27 @@ use crate::EmulatorError;
 pub const NUM_DDR: usize = 8;
 
 /// Debug data register fields
-#[derive(Debug, Clone, Copy)]
+///
+/// A register matches a value loaded or stored when the value agrees with
+/// `data` in every bit set in `mask`. A zero mask leaves the register
+/// unused.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
 pub struct DataFields {
     /// Data value
     pub data: u64,
-    /// Data mask
+    /// Bits of the value compared
     pub mask: u64,
 }
 
 impl DataFields {
-    /// Create from raw bits
-    pub fn from_bits(bits: u64) -> Self {
-        Self {
-            data: bits,
-            mask: (bits >> 56) & 0xFF,
-        }
-    }
-
-    /// Convert to raw bits
-    pub fn to_bits(&self) -> u64 {
-        self.data | (self.mask << 56)
+    /// Whether the register is in use
+    pub fn is_enabled(&self) -> bool {
+        self.mask != 0
     }
 
     /// Check if value matches data pattern
     pub fn matches(&self, value: u64) -> bool {
-        let mask = !(self.mask << 56);
-        (value & mask) == (self.data & mask)
+        self.is_enabled() && (value & self.mask) == (self.data & self.mask)
     }
 }
 
@@ -37,7 +32,7 @@ impl DataFields {
 #[derive(Debug)]
 pub struct DDRFile {
     /// Register values
-    regs: [u64; NUM_DDR],
+    regs: [DataFields; NUM_DDR],
 }
 
 impl Default for DDRFile {
@@ -49,7 +44,9 @@ impl Default for DDRFile {
 impl DDRFile {
     /// Create new register file
     pub fn new() -> Self {
-        Self { regs: [0; NUM_DDR] }
+        Self {
+            regs: [DataFields::default(); NUM_DDR],
+        }
     }
 
     /// Read register value
@@ -60,7 +57,7 @@ impl DDRFile {
                 index
             )));
         }
-        Ok(DataFields::from_bits(self.regs[index]))
+        Ok(self.regs[index])
     }
 
     /// Write register value
@@ -71,40 +68,33 @@ impl DDRFile {
                 index
             )));
         }
-        self.regs[index] = fields.to_bits();
+        self.regs[index] = fields;
         Ok(())
     }
 
     /// Check if any data register matches value
     pub fn check_match(&self, value: u64) -> bool {
-        for i in 0..NUM_DDR {
-            if let Ok(fields) = self.read(i) {
-                if fields.matches(value) {
-                    return true;
-                }
-            }
-        }
-        false
+        self.regs.iter().any(|fields| fields.matches(value))
     }
 
-    /// Set a new data match value
-    pub fn set_match(&mut self, data: u64, mask: u64) -> Result<(), EmulatorError> {
+    /// Set a new data match value, returning the register it uses
+    pub fn set_match(&mut self, data: u64, mask: u64) -> Result<usize, EmulatorError> {
         // Find first unused register
-        let mut target_index = None;
-        for i in 0..NUM_DDR {
-            if let Ok(fields) = self.read(i) {
-                if fields.mask == 0 {
-                    target_index = Some(i);
-                    break;
-                }
-            }
+        if mask == 0 {
+            return Err(EmulatorError::RegisterError(
+                "Debug data match needs a non-zero mask".to_string(),
+            ));
         }
-
-        let index = target_index.ok_or_else(|| {
-            EmulatorError::RegisterError("No free debug data registers".to_string())
-        })?;
-
-        self.write(index, DataFields { data, mask })
+        let index = self
+            .regs
+            .iter()
+            .position(|fields| !fields.is_enabled())
+            .ok_or_else(|| {
+                EmulatorError::RegisterError("No free debug data registers".to_string())
+            })?;
+
+        self.write(index, DataFields { data, mask })?;
+        Ok(index)
     }
 
     /// Clear a data match
@@ -121,3 +111,31 @@ impl DDRFile {
         self.write(index, fields)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_data_match_mask() {
+        let mut ddr = DDRFile::new();
+        assert!(!ddr.check_match(0));
+
+        // Only the masked low 16 bits are compared
+        let index = ddr.set_match(0xBEEF, 0xFFFF).unwrap();
+        assert_eq!(index, 0);
+        assert!(ddr.check_match(0xBEEF));
+        assert!(ddr.check_match(0x1234_BEEF));
+        assert!(!ddr.check_match(0xBEEE));
+
+        assert!(ddr.set_match(0, 0).is_err());
+        assert_eq!(ddr.set_match(0, u64::MAX).unwrap(), 1);
+        assert!(ddr.check_match(0));
+
+        ddr.clear_match(0).unwrap();
+        ddr.clear_match(1).unwrap();
+        assert!(!ddr.check_match(0xBEEF));
+        assert!(!ddr.check_match(0));
+        assert!(ddr.clear_match(NUM_DDR).is_err());
+    }
+}
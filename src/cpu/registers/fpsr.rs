@@ -0,0 +1,303 @@
+//! Floating-point status register
+//!
+//! AR.FPSR holds six trap disable bits in bits 5:0 followed by four 13-bit
+//! status fields, sf0 to sf3. Each floating-point instruction names a
+//! status field, which picks its rounding mode, precision and exponent
+//! range and collects the exceptions it raises.
+
+use std::ops::{BitAnd, BitOr, BitOrAssign, Not};
+
+/// FPSR at process start: every trap disabled, sf0 rounding to nearest in
+/// double-extended precision, sf1 to sf3 with traps disabled and sf1 with
+/// the widest exponent range
+pub const DEFAULT_FPSR: u64 = 0x0009_804C_0270_033F;
+
+/// Number of status fields
+pub const NUM_STATUS_FIELDS: usize = 4;
+
+/// First bit of sf0
+const STATUS_FIELD_SHIFT: u32 = 6;
+
+/// Width of a status field
+const STATUS_FIELD_BITS: u32 = 13;
+
+/// First flag bit within a status field
+const FLAGS_SHIFT: u32 = 7;
+
+/// Set of floating-point exceptions, in the order of the trap disable bits
+/// and of each status field's flags
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FpExceptions(u8);
+
+impl FpExceptions {
+    /// No exception
+    pub const NONE: Self = Self(0);
+    /// Invalid operation (v)
+    pub const INVALID: Self = Self(1 << 0);
+    /// Denormal or unnormal operand (d)
+    pub const DENORMAL: Self = Self(1 << 1);
+    /// Zero divide (z)
+    pub const ZERO_DIVIDE: Self = Self(1 << 2);
+    /// Overflow (o)
+    pub const OVERFLOW: Self = Self(1 << 3);
+    /// Underflow (u)
+    pub const UNDERFLOW: Self = Self(1 << 4);
+    /// Inexact result (i)
+    pub const INEXACT: Self = Self(1 << 5);
+    /// Exceptions reported as faults, before the result is written
+    pub const FAULTS: Self = Self(0x07);
+    /// Exceptions reported as traps, after the result is written
+    pub const TRAPS: Self = Self(0x38);
+    /// Every exception
+    pub const ALL: Self = Self(0x3F);
+
+    /// Create from the six exception bits
+    pub fn from_bits(bits: u8) -> Self {
+        Self(bits) & Self::ALL
+    }
+
+    /// Raw bits
+    pub fn bits(self) -> u8 {
+        self.0
+    }
+
+    /// Whether every exception in `other` is in the set
+    pub fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    /// Whether the set is empty
+    pub fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+}
+
+impl BitOr for FpExceptions {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self {
+        Self(self.0 | rhs.0)
+    }
+}
+
+impl BitOrAssign for FpExceptions {
+    fn bitor_assign(&mut self, rhs: Self) {
+        self.0 |= rhs.0;
+    }
+}
+
+impl BitAnd for FpExceptions {
+    type Output = Self;
+
+    fn bitand(self, rhs: Self) -> Self {
+        Self(self.0 & rhs.0)
+    }
+}
+
+impl Not for FpExceptions {
+    type Output = Self;
+
+    fn not(self) -> Self {
+        Self(!self.0 & Self::ALL.0)
+    }
+}
+
+/// Rounding mode of a status field (sf.rc)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rounding {
+    /// To nearest, ties to even
+    Nearest,
+    /// Toward minus infinity
+    Down,
+    /// Toward plus infinity
+    Up,
+    /// Toward zero
+    Zero,
+}
+
+/// Precision an instruction's completer asks for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Precision {
+    /// No completer: the status field's precision control decides
+    Dynamic,
+    /// `.s`
+    Single,
+    /// `.d`
+    Double,
+}
+
+/// Precision and exponent range a result is rounded to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Environment {
+    /// Rounding mode
+    pub rounding: Rounding,
+    /// Significand bits, including the integer bit
+    pub precision: u32,
+    /// Exponent bits
+    pub exponent_bits: u32,
+    /// Flush tiny results to zero
+    pub flush_to_zero: bool,
+}
+
+/// One 13-bit status field
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StatusField(u16);
+
+impl StatusField {
+    /// Create from the 13 field bits
+    pub fn from_bits(bits: u16) -> Self {
+        Self(bits & ((1 << STATUS_FIELD_BITS) - 1))
+    }
+
+    /// Raw bits
+    pub fn bits(self) -> u16 {
+        self.0
+    }
+
+    /// Flush-to-zero mode (sf.ftz)
+    pub fn flush_to_zero(self) -> bool {
+        self.0 & 1 != 0
+    }
+
+    /// Widest range exponent (sf.wre)
+    pub fn widest_range(self) -> bool {
+        self.0 & 2 != 0
+    }
+
+    /// Precision control (sf.pc)
+    pub fn precision_control(self) -> u8 {
+        (self.0 >> 2) as u8 & 3
+    }
+
+    /// Rounding control (sf.rc)
+    pub fn rounding(self) -> Rounding {
+        match (self.0 >> 4) & 3 {
+            0 => Rounding::Nearest,
+            1 => Rounding::Down,
+            2 => Rounding::Up,
+            _ => Rounding::Zero,
+        }
+    }
+
+    /// Whether the field disables every trap (sf.td)
+    pub fn traps_disabled(self) -> bool {
+        self.0 & (1 << 6) != 0
+    }
+
+    /// Exceptions raised since the flags were last cleared
+    pub fn flags(self) -> FpExceptions {
+        FpExceptions::from_bits((self.0 >> FLAGS_SHIFT) as u8)
+    }
+
+    /// Add exceptions to the flags
+    pub fn raise(&mut self, exceptions: FpExceptions) {
+        self.0 |= (exceptions.bits() as u16) << FLAGS_SHIFT;
+    }
+
+    /// Rounding environment of an instruction with the given precision
+    /// completer
+    ///
+    /// `.s` and `.d` round to single and double precision and range; without
+    /// a completer precision control picks the precision and the range is
+    /// double-extended. sf.wre widens the range to the register format's in
+    /// either case.
+    pub fn environment(self, precision: Precision) -> Environment {
+        let (precision, exponent_bits) = match precision {
+            Precision::Single => (24, 8),
+            Precision::Double => (53, 11),
+            Precision::Dynamic => match self.precision_control() {
+                0 => (24, 15),
+                2 => (53, 15),
+                _ => (64, 15),
+            },
+        };
+        Environment {
+            rounding: self.rounding(),
+            precision,
+            exponent_bits: if self.widest_range() {
+                17
+            } else {
+                exponent_bits
+            },
+            flush_to_zero: self.flush_to_zero(),
+        }
+    }
+}
+
+/// Floating-point status register value
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Fpsr(pub u64);
+
+impl Default for Fpsr {
+    fn default() -> Self {
+        Self(DEFAULT_FPSR)
+    }
+}
+
+impl Fpsr {
+    /// Exceptions whose traps are disabled (traps.vd to traps.id)
+    pub fn disabled_traps(self) -> FpExceptions {
+        FpExceptions::from_bits(self.0 as u8)
+    }
+
+    /// Status field `sf`, 0 to 3
+    pub fn status(self, sf: usize) -> StatusField {
+        StatusField::from_bits((self.0 >> Self::shift(sf)) as u16)
+    }
+
+    /// Replace status field `sf`
+    pub fn set_status(&mut self, sf: usize, field: StatusField) {
+        let mask = ((1u64 << STATUS_FIELD_BITS) - 1) << Self::shift(sf);
+        self.0 = (self.0 & !mask) | ((field.bits() as u64) << Self::shift(sf));
+    }
+
+    /// Exceptions that interrupt an instruction using status field `sf`
+    pub fn enabled_traps(self, sf: usize) -> FpExceptions {
+        if self.status(sf).traps_disabled() {
+            FpExceptions::NONE
+        } else {
+            !self.disabled_traps()
+        }
+    }
+
+    /// First bit of status field `sf`
+    fn shift(sf: usize) -> u32 {
+        STATUS_FIELD_SHIFT + STATUS_FIELD_BITS * (sf % NUM_STATUS_FIELDS) as u32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_status_fields() {
+        let mut fpsr = Fpsr::default();
+        assert_eq!(fpsr.disabled_traps(), FpExceptions::ALL);
+        assert!(fpsr.enabled_traps(0).is_empty());
+
+        let sf0 = fpsr.status(0);
+        assert_eq!(sf0.rounding(), Rounding::Nearest);
+        assert_eq!(sf0.environment(Precision::Dynamic).precision, 64);
+        assert_eq!(sf0.environment(Precision::Single).exponent_bits, 8);
+        let sf1 = fpsr.status(1);
+        assert!(sf1.widest_range() && sf1.traps_disabled());
+        assert_eq!(sf1.environment(Precision::Double).exponent_bits, 17);
+
+        // Flags stay in their own field
+        let mut sf2 = fpsr.status(2);
+        sf2.raise(FpExceptions::INEXACT | FpExceptions::OVERFLOW);
+        fpsr.set_status(2, sf2);
+        assert_eq!(
+            fpsr.status(2).flags(),
+            FpExceptions::INEXACT | FpExceptions::OVERFLOW
+        );
+        assert!(fpsr.status(1).flags().is_empty());
+        assert!(fpsr.status(3).flags().is_empty());
+
+        // Enabling a trap applies to fields without sf.td
+        fpsr.0 &= !(FpExceptions::ZERO_DIVIDE.bits() as u64);
+        assert_eq!(fpsr.enabled_traps(0), FpExceptions::ZERO_DIVIDE);
+        assert!(fpsr.enabled_traps(1).is_empty());
+    }
+}
@@ -0,0 +1,817 @@
+//! Floating-point registers
+//!
+//! IA-64 floating-point registers hold 82 bits: a sign, a 17-bit exponent
+//! biased by 0xFFFF and a 64-bit significand with an explicit integer bit.
+//! Every memory format widens exactly into the register format, so loads
+//! convert without rounding; stores of the narrower formats round to
+//! nearest even. f0 and f1 are hardwired to +0.0 and +1.0.
+//!
+//! Arithmetic is done exactly and rounded once, to the precision and
+//! exponent range a status field of AR.FPSR selects.
+
+use super::fpsr::{Environment, FpExceptions, Rounding};
+use std::cmp::Ordering;
+
+/// Exponent bias of the register format
+const BIAS: i32 = 0xFFFF;
+
+/// Exponent of infinities and NaNs
+const EXP_MAX: u32 = 0x1FFFF;
+
+/// Integer bit of the significand
+const INTEGER_BIT: u64 = 1 << 63;
+
+/// Exponent bias of the double-extended memory format
+const EXTENDED_BIAS: i32 = 0x3FFF;
+
+/// Quiet bit of a NaN's significand
+const QUIET_BIT: u64 = 1 << 62;
+
+/// Value of a floating-point register in the 82-bit register format
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct FloatRegister {
+    /// Sign, set for negative values
+    pub sign: bool,
+    /// Biased exponent, 17 bits
+    pub exponent: u32,
+    /// Significand, with the integer bit at bit 63
+    pub significand: u64,
+}
+
+impl FloatRegister {
+    /// +0.0, the value of f0
+    pub const ZERO: Self = Self {
+        sign: false,
+        exponent: 0,
+        significand: 0,
+    };
+
+    /// +1.0, the value of f1
+    pub const ONE: Self = Self {
+        sign: false,
+        exponent: BIAS as u32,
+        significand: INTEGER_BIT,
+    };
+
+    /// NaTVal, the deferred exception token of a failed speculative load
+    pub const NATVAL: Self = Self {
+        sign: false,
+        exponent: 0x1FFFE,
+        significand: 0,
+    };
+
+    /// QNaN Indefinite, the result of an invalid operation
+    pub const INDEFINITE: Self = Self {
+        sign: true,
+        exponent: EXP_MAX,
+        significand: INTEGER_BIT | QUIET_BIT,
+    };
+
+    /// +Infinity
+    pub const INFINITY: Self = Self {
+        sign: false,
+        exponent: EXP_MAX,
+        significand: INTEGER_BIT,
+    };
+
+    /// Whether the register holds NaTVal
+    pub fn is_natval(&self) -> bool {
+        *self == Self::NATVAL
+    }
+
+    /// Whether the register holds an infinity or a NaN
+    fn is_special(&self) -> bool {
+        self.exponent == EXP_MAX
+    }
+
+    /// Whether the register holds a NaN
+    pub fn is_nan(&self) -> bool {
+        self.is_special() && self.significand << 1 != 0
+    }
+
+    /// Whether the register holds an infinity
+    pub fn is_infinite(&self) -> bool {
+        self.is_special() && self.significand << 1 == 0
+    }
+
+    /// Whether the register holds a zero, of any exponent
+    pub fn is_zero(&self) -> bool {
+        !self.is_special() && self.significand == 0
+    }
+
+    /// Whether the register holds a non-zero finite value without its
+    /// integer bit, i.e. a denormal or unnormal
+    fn is_unnormal(&self) -> bool {
+        !self.is_special() && self.significand != 0 && self.significand & INTEGER_BIT == 0
+    }
+
+    /// The value with its sign flipped; NaNs are left alone
+    pub fn negate(self) -> Self {
+        if self.is_nan() {
+            return self;
+        }
+        Self {
+            sign: !self.sign,
+            ..self
+        }
+    }
+
+    /// Fused multiply-add, `self * b + c` rounded once to `env`
+    ///
+    /// Returns the result and the exceptions the operation raised. NaTVal
+    /// operands give NaTVal without raising any.
+    pub fn fma(self, b: Self, c: Self, env: &Environment) -> (Self, FpExceptions) {
+        let operands = [self, b, c];
+        if operands.iter().any(Self::is_natval) {
+            return (Self::NATVAL, FpExceptions::NONE);
+        }
+
+        // The first NaN propagates, quieted; signaling NaNs are invalid
+        if let Some(nan) = operands.into_iter().find(Self::is_nan) {
+            let signaling = operands
+                .iter()
+                .any(|x| x.is_nan() && x.significand & QUIET_BIT == 0);
+            let flags = if signaling {
+                FpExceptions::INVALID
+            } else {
+                FpExceptions::NONE
+            };
+            let quiet = Self {
+                significand: nan.significand | QUIET_BIT,
+                ..nan
+            };
+            return (quiet, flags);
+        }
+
+        let mut flags = FpExceptions::NONE;
+        if operands.iter().any(Self::is_unnormal) {
+            flags |= FpExceptions::DENORMAL;
+        }
+        let sign = self.sign != b.sign;
+        let product_infinite = self.is_infinite() || b.is_infinite();
+        let product_zero = self.is_zero() || b.is_zero();
+        if (product_infinite && product_zero)
+            || (product_infinite && c.is_infinite() && sign != c.sign)
+        {
+            return (Self::INDEFINITE, flags | FpExceptions::INVALID);
+        }
+        if product_infinite {
+            return (
+                Self {
+                    sign,
+                    ..Self::INFINITY
+                },
+                flags,
+            );
+        }
+        if c.is_infinite() {
+            return (c, flags);
+        }
+
+        // Sum the exact product and addend
+        let product = (!product_zero).then(|| {
+            let significand = Wide::from(self.significand as u128 * b.significand as u128);
+            let exponent = self.exponent as i32 + b.exponent as i32 - 2 * (BIAS + 63);
+            (sign, significand, exponent)
+        });
+        let addend = (!c.is_zero()).then(|| {
+            let exponent = c.exponent as i32 - BIAS - 63;
+            (c.sign, Wide::from(c.significand as u128), exponent)
+        });
+        let (sign, significand, exponent) = match (product, addend) {
+            (None, None) => {
+                // Zeros of opposite signs sum to +0, or -0 rounding down
+                let sign = if sign == c.sign {
+                    sign
+                } else {
+                    env.rounding == Rounding::Down
+                };
+                return (Self { sign, ..Self::ZERO }, flags);
+            }
+            (Some(x), None) | (None, Some(x)) => normalize(x),
+            (Some(x), Some(y)) => match exact_sum(x, y) {
+                Some(sum) => sum,
+                None => {
+                    let sign = env.rounding == Rounding::Down;
+                    return (Self { sign, ..Self::ZERO }, flags);
+                }
+            },
+        };
+        let (result, rounding_flags) = round(sign, significand, exponent, env);
+        (result, flags | rounding_flags)
+    }
+
+    /// Register value of a double
+    pub fn from_f64(value: f64) -> Self {
+        Self::from_double(value.to_bits())
+    }
+
+    /// Value as a double, rounded to nearest even
+    pub fn to_f64(self) -> f64 {
+        f64::from_bits(self.to_double())
+    }
+
+    /// Load a single-precision memory value (ldfs)
+    pub fn from_single(bits: u32) -> Self {
+        Self::from_ieee(bits as u64, 23, 8)
+    }
+
+    /// Single-precision memory value (stfs), rounded to nearest even
+    pub fn to_single(self) -> u32 {
+        self.to_ieee(23, 8) as u32
+    }
+
+    /// Load a double-precision memory value (ldfd)
+    pub fn from_double(bits: u64) -> Self {
+        Self::from_ieee(bits, 52, 11)
+    }
+
+    /// Double-precision memory value (stfd), rounded to nearest even
+    pub fn to_double(self) -> u64 {
+        self.to_ieee(52, 11)
+    }
+
+    /// Load a double-extended memory value (ldfe), stored little-endian
+    /// as the significand followed by the sign and 15-bit exponent
+    pub fn from_extended(bytes: [u8; 10]) -> Self {
+        let significand = u64::from_le_bytes(bytes[..8].try_into().unwrap());
+        let sign_exponent = u16::from_le_bytes([bytes[8], bytes[9]]);
+        let exponent = match (sign_exponent & 0x7FFF) as u32 {
+            0x7FFF => EXP_MAX,
+            0 if significand == 0 => 0,
+            // Denormals keep the exponent of the smallest normal
+            0 => (1 - EXTENDED_BIAS + BIAS) as u32,
+            biased => (biased as i32 - EXTENDED_BIAS + BIAS) as u32,
+        };
+        Self {
+            sign: sign_exponent & 0x8000 != 0,
+            exponent,
+            significand,
+        }
+    }
+
+    /// Double-extended memory value (stfe)
+    ///
+    /// Register exponents beyond the format's range become infinities or
+    /// denormals; a denormal's low significand bits are dropped.
+    pub fn to_extended(self) -> [u8; 10] {
+        let (exponent, significand) = if self.is_special() {
+            (0x7FFF, self.significand)
+        } else if self.significand == 0 {
+            (0, 0)
+        } else {
+            let (unbiased, significand) = self.normalized();
+            if unbiased > EXTENDED_BIAS {
+                (0x7FFF, INTEGER_BIT)
+            } else if unbiased >= 1 - EXTENDED_BIAS {
+                ((unbiased + EXTENDED_BIAS) as u16, significand)
+            } else {
+                let shift = (1 - EXTENDED_BIAS - unbiased) as u32;
+                (0, significand.checked_shr(shift).unwrap_or(0))
+            }
+        };
+        let sign_exponent = exponent | ((self.sign as u16) << 15);
+        let mut bytes = [0; 10];
+        bytes[..8].copy_from_slice(&significand.to_le_bytes());
+        bytes[8..].copy_from_slice(&sign_exponent.to_le_bytes());
+        bytes
+    }
+
+    /// Load the 16-byte spill format (ldf.fill), which holds the register
+    /// exactly: the significand, then the exponent and sign in bits 16:0
+    /// and 17 of the second doubleword
+    pub fn from_spill(bytes: [u8; 16]) -> Self {
+        let significand = u64::from_le_bytes(bytes[..8].try_into().unwrap());
+        let high = u64::from_le_bytes(bytes[8..].try_into().unwrap());
+        Self {
+            sign: high & (1 << 17) != 0,
+            exponent: (high & 0x1FFFF) as u32,
+            significand,
+        }
+    }
+
+    /// 16-byte spill format (stf.spill)
+    pub fn to_spill(self) -> [u8; 16] {
+        let high = (self.exponent as u64 & 0x1FFFF) | ((self.sign as u64) << 17);
+        let mut bytes = [0; 16];
+        bytes[..8].copy_from_slice(&self.significand.to_le_bytes());
+        bytes[8..].copy_from_slice(&high.to_le_bytes());
+        bytes
+    }
+
+    /// Unbiased exponent and significand shifted so the integer bit is set,
+    /// for a non-zero finite value
+    fn normalized(&self) -> (i32, u64) {
+        let shift = self.significand.leading_zeros();
+        (
+            self.exponent as i32 - BIAS - shift as i32,
+            self.significand << shift,
+        )
+    }
+
+    /// Widen an IEEE memory value with `frac_bits` fraction and `exp_bits`
+    /// exponent bits
+    fn from_ieee(bits: u64, frac_bits: u32, exp_bits: u32) -> Self {
+        let bias = (1 << (exp_bits - 1)) - 1;
+        let exp_max = (1 << exp_bits) - 1;
+        let sign = bits >> (frac_bits + exp_bits) & 1 != 0;
+        let biased = (bits >> frac_bits) as u32 & exp_max;
+        let fraction = (bits & ((1 << frac_bits) - 1)) << (63 - frac_bits);
+        let (exponent, significand) = match biased {
+            0 if fraction == 0 => (0, 0),
+            // Denormals stay unnormalized at the smallest normal exponent
+            0 => ((1 - bias + BIAS) as u32, fraction),
+            _ if biased == exp_max => (EXP_MAX, INTEGER_BIT | fraction),
+            _ => ((biased as i32 - bias + BIAS) as u32, INTEGER_BIT | fraction),
+        };
+        Self {
+            sign,
+            exponent,
+            significand,
+        }
+    }
+
+    /// Narrow to an IEEE memory value with `frac_bits` fraction and
+    /// `exp_bits` exponent bits, rounding to nearest even
+    fn to_ieee(self, frac_bits: u32, exp_bits: u32) -> u64 {
+        let bias = (1 << (exp_bits - 1)) - 1;
+        let exp_max = (1u64 << exp_bits) - 1;
+        let sign = (self.sign as u64) << (frac_bits + exp_bits);
+        if self.is_special() {
+            let mut fraction = (self.significand << 1) >> (64 - frac_bits);
+            // Keep a NaN whose payload does not fit a NaN
+            if self.significand << 1 != 0 && fraction == 0 {
+                fraction = 1 << (frac_bits - 1);
+            }
+            return sign | (exp_max << frac_bits) | fraction;
+        }
+        if self.significand == 0 {
+            return sign;
+        }
+
+        let (mut unbiased, significand) = self.normalized();
+        let min = 1 - bias;
+        let shift = (63 - frac_bits) + (min - unbiased).max(0) as u32;
+        if shift > 64 {
+            return sign;
+        }
+        let wide = significand as u128;
+        let mut mantissa = (wide >> shift) as u64;
+        let rest = wide & ((1 << shift) - 1);
+        let half = 1 << (shift - 1);
+        if rest > half || (rest == half && mantissa & 1 != 0) {
+            mantissa += 1;
+        }
+        if unbiased < min {
+            // Denormal, or the smallest normal if rounding carried into it
+            return sign | mantissa;
+        }
+        if mantissa == 1 << (frac_bits + 1) {
+            mantissa >>= 1;
+            unbiased += 1;
+        }
+        if unbiased > bias {
+            return sign | (exp_max << frac_bits);
+        }
+        sign | (((unbiased + bias) as u64) << frac_bits) | (mantissa & ((1 << frac_bits) - 1))
+    }
+}
+
+/// 256-bit unsigned integer, wide enough for an exact product and addend
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+struct Wide {
+    /// High 128 bits
+    hi: u128,
+    /// Low 128 bits
+    lo: u128,
+}
+
+impl From<u128> for Wide {
+    fn from(lo: u128) -> Self {
+        Self { hi: 0, lo }
+    }
+}
+
+impl Wide {
+    /// Number of leading zero bits
+    fn leading_zeros(self) -> u32 {
+        match self.hi {
+            0 => 128 + self.lo.leading_zeros(),
+            hi => hi.leading_zeros(),
+        }
+    }
+
+    /// Shift left by `n` bits, fewer than 256
+    fn shl(self, n: u32) -> Self {
+        match n {
+            0 => self,
+            1..=127 => Self {
+                hi: self.hi << n | self.lo >> (128 - n),
+                lo: self.lo << n,
+            },
+            _ => Self {
+                hi: self.lo << (n - 128),
+                lo: 0,
+            },
+        }
+    }
+
+    /// Shift right by `n` bits
+    fn shr(self, n: u32) -> Self {
+        match n {
+            0 => self,
+            1..=127 => Self {
+                hi: self.hi >> n,
+                lo: self.lo >> n | self.hi << (128 - n),
+            },
+            128..=255 => Self {
+                hi: 0,
+                lo: self.hi >> (n - 128),
+            },
+            _ => Self { hi: 0, lo: 0 },
+        }
+    }
+
+    /// Shift right by `n` bits, ORing the bits shifted out into bit 0
+    fn shr_sticky(self, n: u32) -> Self {
+        let shifted = self.shr(n);
+        let sticky = self.low_bits_set(n);
+        Self {
+            lo: shifted.lo | sticky as u128,
+            ..shifted
+        }
+    }
+
+    /// Whether bit `n` is set
+    fn bit(self, n: u32) -> bool {
+        self.shr(n).lo & 1 != 0
+    }
+
+    /// Whether any of the low `n` bits is set
+    fn low_bits_set(self, n: u32) -> bool {
+        match n {
+            0 => false,
+            1..=255 => self.shl(256 - n) != Self::from(0),
+            _ => self != Self::from(0),
+        }
+    }
+
+    /// Sum, and whether it carried out of bit 255
+    fn overflowing_add(self, other: Self) -> (Self, bool) {
+        let (lo, carry) = self.lo.overflowing_add(other.lo);
+        let (hi, carry1) = self.hi.overflowing_add(other.hi);
+        let (hi, carry2) = hi.overflowing_add(carry as u128);
+        (Self { hi, lo }, carry1 || carry2)
+    }
+
+    /// Difference, for `other` no larger than `self`
+    fn sub(self, other: Self) -> Self {
+        let (lo, borrow) = self.lo.overflowing_sub(other.lo);
+        Self {
+            hi: self.hi - other.hi - borrow as u128,
+            lo,
+        }
+    }
+
+    /// Shift left so bit 255 is set, returning the shift
+    fn normalize(&mut self) -> u32 {
+        let shift = self.leading_zeros();
+        *self = self.shl(shift);
+        shift
+    }
+}
+
+/// Normalize a non-zero value `significand * 2^exponent` so bit 255 of the
+/// significand is set
+fn normalize((sign, mut significand, exponent): (bool, Wide, i32)) -> (bool, Wide, i32) {
+    let shift = significand.normalize() as i32;
+    (sign, significand, exponent - shift)
+}
+
+/// Exact sum of two non-zero values `significand * 2^exponent`, normalized
+/// so bit 255 of the significand is set, with sticky low bits
+///
+/// Returns `None` for an exact zero. Both operands hold at most 128
+/// significant bits, so alignment shifts of up to 128 bits lose nothing,
+/// and longer ones leave at least 127 exact bits above the sticky bit.
+fn exact_sum(x: (bool, Wide, i32), y: (bool, Wide, i32)) -> Option<(bool, Wide, i32)> {
+    let (x, y) = (normalize(x), normalize(y));
+    let (big, small) = match (x.2, x.1).cmp(&(y.2, y.1)) {
+        Ordering::Less => (y, x),
+        _ => (x, y),
+    };
+    let (sign, big_significand, exponent) = big;
+    let distance = (exponent - small.2).min(257) as u32;
+    let small_significand = small.1.shr_sticky(distance);
+
+    if sign == small.0 {
+        let (sum, carry) = big_significand.overflowing_add(small_significand);
+        if carry {
+            let mut sum = sum.shr_sticky(1);
+            sum.hi |= 1 << 127;
+            return Some((sign, sum, exponent + 1));
+        }
+        return Some((sign, sum, exponent));
+    }
+    let mut difference = big_significand.sub(small_significand);
+    if difference == Wide::from(0) {
+        return None;
+    }
+    let shift = difference.normalize() as i32;
+    Some((sign, difference, exponent - shift))
+}
+
+/// Round a non-zero value `significand * 2^exponent`, with bit 255 of the
+/// significand set, to `env`
+fn round(
+    sign: bool,
+    significand: Wide,
+    exponent: i32,
+    env: &Environment,
+) -> (FloatRegister, FpExceptions) {
+    let bias = (1 << (env.exponent_bits - 1)) - 1;
+    let (emin, emax) = (1 - bias, bias);
+    let precision = env.precision as i32;
+    let unbiased = exponent + 255;
+    let tiny = unbiased < emin;
+    let mut flags = FpExceptions::NONE;
+    if tiny && env.flush_to_zero {
+        let zero = FloatRegister {
+            sign,
+            ..FloatRegister::ZERO
+        };
+        return (zero, FpExceptions::UNDERFLOW | FpExceptions::INEXACT);
+    }
+
+    // Tiny results keep only the bits at or above the smallest denormal
+    let kept = if tiny {
+        precision - (emin - unbiased)
+    } else {
+        precision
+    };
+    let lsb = unbiased - (kept - 1);
+    let (mut mantissa, half, sticky) = match kept {
+        1.. => {
+            let shift = (256 - kept) as u32;
+            (
+                significand.shr(shift).lo,
+                significand.bit(shift - 1),
+                significand.low_bits_set(shift - 1),
+            )
+        }
+        0 => (0, true, significand.low_bits_set(255)),
+        _ => (0, false, true),
+    };
+    let inexact = half || sticky;
+    let increment = match env.rounding {
+        Rounding::Nearest => half && (sticky || mantissa & 1 != 0),
+        Rounding::Zero => false,
+        Rounding::Up => !sign && inexact,
+        Rounding::Down => sign && inexact,
+    };
+    mantissa += increment as u128;
+
+    if inexact {
+        flags |= FpExceptions::INEXACT;
+        if tiny {
+            flags |= FpExceptions::UNDERFLOW;
+        }
+    }
+    if mantissa == 0 {
+        let zero = FloatRegister {
+            sign,
+            ..FloatRegister::ZERO
+        };
+        return (zero, flags);
+    }
+
+    let top = lsb + (127 - mantissa.leading_zeros() as i32);
+    if top > emax {
+        // Overflow gives infinity or the largest finite value, whichever
+        // the rounding direction allows
+        flags |= FpExceptions::OVERFLOW | FpExceptions::INEXACT;
+        let to_infinity = match env.rounding {
+            Rounding::Nearest => true,
+            Rounding::Zero => false,
+            Rounding::Up => !sign,
+            Rounding::Down => sign,
+        };
+        if to_infinity {
+            return (
+                FloatRegister {
+                    sign,
+                    ..FloatRegister::INFINITY
+                },
+                flags,
+            );
+        }
+        let largest = (1u128 << precision) - 1;
+        return (pack(sign, largest, emax - (precision - 1)), flags);
+    }
+    (pack(sign, mantissa, lsb), flags)
+}
+
+/// Register holding `mantissa * 2^lsb`
+///
+/// Values below the register format's normal range get exponent 0 and an
+/// unnormalized significand.
+fn pack(sign: bool, mantissa: u128, lsb: i32) -> FloatRegister {
+    let zeros = mantissa.leading_zeros();
+    let significand = ((mantissa << zeros) >> 64) as u64;
+    let exponent = lsb + (127 - zeros as i32) + BIAS;
+    match exponent {
+        1.. => FloatRegister {
+            sign,
+            exponent: exponent as u32,
+            significand,
+        },
+        _ => FloatRegister {
+            sign,
+            exponent: 0,
+            significand: significand >> -exponent,
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::registers::fpsr::{Fpsr, Precision, StatusField};
+    use crate::cpu::Cpu;
+    use proptest::prelude::*;
+
+    /// Round to double precision and range under `rounding`
+    fn double(rounding: u16) -> Environment {
+        StatusField::from_bits(rounding << 4).environment(Precision::Double)
+    }
+
+    #[test]
+    fn test_float_register_formats() {
+        assert_eq!(FloatRegister::ONE.to_f64(), 1.0);
+        assert_eq!(FloatRegister::ZERO.to_f64(), 0.0);
+        for value in [
+            1.5,
+            -2.75,
+            f64::MAX,
+            f64::MIN_POSITIVE,
+            5e-324,
+            f64::INFINITY,
+        ] {
+            let register = FloatRegister::from_f64(value);
+            assert_eq!(register.to_f64(), value);
+            assert_eq!(FloatRegister::from_double(register.to_double()), register);
+        }
+        assert!(FloatRegister::from_f64(f64::NAN).to_f64().is_nan());
+
+        // Singles widen exactly, denormals unnormalized
+        let denormal = FloatRegister::from_single(1);
+        assert_eq!(denormal.exponent, 0xFF81);
+        assert_eq!(denormal.significand, 1 << 40);
+        assert_eq!(denormal.to_f64(), f32::from_bits(1) as f64);
+        assert_eq!(
+            FloatRegister::from_single(1.5f32.to_bits()).to_single(),
+            1.5f32.to_bits()
+        );
+
+        // Narrowing rounds to nearest even and overflows to infinity
+        let third = FloatRegister {
+            sign: false,
+            exponent: 0xFFFD,
+            significand: 0xAAAA_AAAA_AAAA_AAAB,
+        };
+        assert_eq!(third.to_f64(), 1.0 / 3.0);
+        assert_eq!(third.to_single(), (1.0f32 / 3.0).to_bits());
+        let huge = FloatRegister {
+            exponent: 0x1_4000,
+            ..FloatRegister::ONE
+        };
+        assert_eq!(huge.to_f64(), f64::INFINITY);
+
+        // Double-extended keeps the whole significand
+        let bytes = third.to_extended();
+        assert_eq!(FloatRegister::from_extended(bytes), third);
+        assert_eq!(bytes[8..], [0xFD, 0x3F]);
+
+        // The spill format holds NaTVal and any other register exactly
+        let spilled = FloatRegister::NATVAL.to_spill();
+        assert!(FloatRegister::from_spill(spilled).is_natval());
+        assert_eq!(FloatRegister::from_spill(huge.to_spill()), huge);
+    }
+
+    #[test]
+    fn test_hardwired_registers() {
+        let mut cpu = Cpu::new();
+        assert_eq!(cpu.get_fr(1).unwrap(), 1.0);
+        cpu.set_fr(0, 2.0).unwrap();
+        cpu.set_fr(1, 2.0).unwrap();
+        cpu.set_fr(2, 2.0).unwrap();
+        assert_eq!(cpu.get_fr(0).unwrap(), 0.0);
+        assert_eq!(cpu.get_fr(1).unwrap(), 1.0);
+        assert_eq!(cpu.get_fr(2).unwrap(), 2.0);
+
+        cpu.set_fr_register(3, FloatRegister::NATVAL).unwrap();
+        assert!(cpu.get_fr_register(3).unwrap().is_natval());
+        cpu.reset().unwrap();
+        assert_eq!(cpu.get_fr_register(1).unwrap(), FloatRegister::ONE);
+    }
+
+    #[test]
+    fn test_fma_rounding() {
+        let f = FloatRegister::from_f64;
+        let nearest = Fpsr::default().status(0).environment(Precision::Dynamic);
+
+        // Double-extended keeps what a double rounds away
+        let (third, flags) = f(1.0).fma(f(1.0), f(2f64.powi(-60)), &nearest);
+        assert_eq!(third.significand, INTEGER_BIT | 1 << 3);
+        assert!(flags.is_empty());
+        let (rounded, flags) = f(1.0).fma(f(1.0), f(2f64.powi(-60)), &double(0));
+        assert_eq!((rounded.to_f64(), flags), (1.0, FpExceptions::INEXACT));
+
+        // Directed rounding of 1 + 2^-60
+        let up = f(1.0).fma(f(1.0), f(2f64.powi(-60)), &double(2)).0;
+        assert_eq!(up.to_f64(), 1.0 + f64::EPSILON);
+        let down = f(-1.0).fma(f(1.0), f(-(2f64.powi(-60))), &double(3)).0;
+        assert_eq!(down.to_f64(), -1.0);
+
+        // Exact zeros are +0, or -0 rounding down
+        assert!(!f(1.0).fma(f(1.0), f(-1.0), &double(0)).0.sign);
+        assert!(f(1.0).fma(f(1.0), f(-1.0), &double(1)).0.sign);
+
+        // Overflow goes to infinity or the largest double
+        let (inf, flags) = f(f64::MAX).fma(f(2.0), f(0.0), &double(0));
+        assert!(inf.is_infinite());
+        assert!(flags.contains(FpExceptions::OVERFLOW | FpExceptions::INEXACT));
+        let max = f(f64::MAX).fma(f(2.0), f(0.0), &double(3)).0;
+        assert_eq!(max.to_f64(), f64::MAX);
+
+        // Tiny inexact results underflow
+        let (tiny, flags) = f(f64::MIN_POSITIVE).fma(f(0.75), f(0.0), &double(0));
+        assert_eq!(tiny.to_f64(), f64::MIN_POSITIVE * 0.75);
+        assert!(flags.is_empty());
+        let (tiny, flags) = f(f64::MIN_POSITIVE).fma(f(2f64.powi(-53)), f(0.0), &double(0));
+        assert_eq!(tiny.to_f64(), 0.0);
+        assert_eq!(flags, FpExceptions::UNDERFLOW | FpExceptions::INEXACT);
+
+        // Invalid operations give QNaN Indefinite, and NaTVal propagates
+        let (nan, flags) = f(f64::INFINITY).fma(f(0.0), f(1.0), &nearest);
+        assert_eq!(
+            (nan, flags),
+            (FloatRegister::INDEFINITE, FpExceptions::INVALID)
+        );
+        let inf = f(f64::INFINITY);
+        assert_eq!(
+            inf.fma(f(1.0), inf.negate(), &nearest).0,
+            FloatRegister::INDEFINITE
+        );
+        let natval = FloatRegister::NATVAL.fma(f(1.0), f(1.0), &nearest);
+        assert_eq!(natval, (FloatRegister::NATVAL, FpExceptions::NONE));
+
+        // Denormal operands are reported
+        let flags = f(5e-324).fma(f(1.0), f(0.0), &double(0)).1;
+        assert!(flags.contains(FpExceptions::DENORMAL));
+    }
+
+    proptest! {
+        /// Rounded to double precision, fma matches the host's fused
+        /// multiply-add
+        #[test]
+        fn prop_fma_matches_host(a in any::<u64>(), b in any::<u64>(), c in any::<u64>()) {
+            let (a, b, c) = (f64::from_bits(a), f64::from_bits(b), f64::from_bits(c));
+            let result = FloatRegister::from_f64(a)
+                .fma(FloatRegister::from_f64(b), FloatRegister::from_f64(c), &double(0))
+                .0
+                .to_f64();
+            let expected = a.mul_add(b, c);
+            if expected.is_nan() {
+                prop_assert!(result.is_nan());
+            } else {
+                prop_assert_eq!(result.to_bits(), expected.to_bits());
+            }
+        }
+
+        /// Cancelling a product against its rounded value leaves exactly
+        /// the rounding error
+        #[test]
+        fn prop_fma_cancellation(a in -1e10f64..1e10, b in -1e10f64..1e10) {
+            let c = -(a * b);
+            let result = FloatRegister::from_f64(a)
+                .fma(FloatRegister::from_f64(b), FloatRegister::from_f64(c), &double(0))
+                .0
+                .to_f64();
+            prop_assert_eq!(result.to_bits(), a.mul_add(b, c).to_bits());
+        }
+
+        /// Products of small integers are exact near the bottom of the
+        /// double range, denormals included
+        #[test]
+        fn prop_fma_denormals(a in -1000i32..1000, b in -1000i32..1000, c in any::<i64>()) {
+            let scale = 2f64.powi(-1070);
+            let (a, b, c) = (a as f64 * scale, b as f64, c as f64 * scale);
+            let result = FloatRegister::from_f64(a)
+                .fma(FloatRegister::from_f64(b), FloatRegister::from_f64(c), &double(0))
+                .0
+                .to_f64();
+            prop_assert_eq!(result.to_bits(), a.mul_add(b, c).to_bits());
+        }
+    }
+}
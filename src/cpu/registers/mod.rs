@@ -6,6 +6,10 @@ pub mod cr;
 pub mod dbr;
 /// Data Debug Register module
 pub mod ddr;
+/// Floating-point Status Register module
+pub mod fpsr;
+/// Floating-point Register module
+pub mod fr;
 /// Protection Key Register module
 pub mod pkr;
 /// Region Register module
@@ -15,6 +19,8 @@ pub use ar::{ARFile, AR};
 pub use cr::{CRFile, CRIndex};
 pub use dbr::{BreakAccessType, BreakFields, DBRFile};
 pub use ddr::{DDRFile, DataFields};
+pub use fpsr::{FpExceptions, Fpsr, Precision, Rounding, StatusField};
+pub use fr::FloatRegister;
 pub use pkr::{KeyFields, PKRFile};
 pub use rr::{RRFile, RegionFields};
 
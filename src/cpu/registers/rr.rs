@@ -3,7 +3,13 @@ use crate::EmulatorError;
 /// Number of region registers
 pub const NUM_RR: usize = 8;
 
+/// Mask of the 24-bit region ID
+pub const RID_MASK: u64 = (1 << 24) - 1;
+
 /// Region register fields
+///
+/// The architected layout is ve in bit 0, ps in bits 2-7 and the region ID
+/// in bits 8-31.
 #[derive(Debug, Clone, Copy)]
 pub struct RegionFields {
     /// Virtual Region ID
@@ -18,15 +24,15 @@ impl RegionFields {
     /// Create from raw bits
     pub fn from_bits(bits: u64) -> Self {
         Self {
-            rid: bits,
-            ps: ((bits >> 56) & 0xFF) as u8,
-            ve: (bits >> 63) != 0,
+            rid: (bits >> 8) & RID_MASK,
+            ps: ((bits >> 2) & 0x3F) as u8,
+            ve: bits & 1 != 0,
         }
     }
 
     /// Convert to raw bits
     pub fn to_bits(&self) -> u64 {
-        self.rid | ((self.ps as u64) << 56) | ((self.ve as u64) << 63)
+        ((self.rid & RID_MASK) << 8) | (((self.ps & 0x3F) as u64) << 2) | self.ve as u64
     }
 }
 
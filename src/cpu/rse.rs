@@ -5,6 +5,7 @@
 
 use crate::memory::Memory;
 use crate::EmulatorError;
+use std::collections::VecDeque;
 
 #[allow(dead_code)]
 /// Size of each register frame in bytes (512 bytes = 64 registers * 8 bytes)
@@ -153,23 +154,226 @@ impl RSEConfig {
     }
 }
 
+/// AR.RSC mode field (bits 1:0)
+const RSC_MODE_MASK: u64 = 0x3;
+
+/// AR.RSC privilege level field (bits 3:2)
+const RSC_PL_SHIFT: u64 = 2;
+
+/// AR.RSC bits that software can write: mode, pl, be and loadrs (bits 29:16)
+const RSC_WRITABLE: u64 = 0x3FFF_001F;
+
+impl RSEMode {
+    /// Mode selected by the AR.RSC mode field
+    ///
+    /// The RSE models eager stores but loads lazily, so store intensive
+    /// behaves as eager and load intensive as lazy.
+    pub fn from_rsc(bits: u64) -> Self {
+        match bits & RSC_MODE_MASK {
+            0 => RSEMode::Enforced,
+            2 => RSEMode::Lazy,
+            _ => RSEMode::Eager,
+        }
+    }
+
+    /// Value of the AR.RSC mode field for this mode
+    pub fn to_rsc(self) -> u64 {
+        match self {
+            RSEMode::Enforced => 0,
+            RSEMode::Lazy => 2,
+            RSEMode::Eager => 3,
+        }
+    }
+}
+
+/// Slot number of the NaT collection within each 64-slot group
+const RNAT_SLOT: u64 = 0x3F;
+
+/// Number of slots in a backing store group (63 registers and one NaT collection)
+const SLOTS_PER_GROUP: i64 = 0x40;
+
+/// Position in the backing store
+///
+/// Every 64th doubleword of the backing store (the slot whose address has
+/// bits 8:3 set to 0x3F) holds a NaT collection instead of a register. The
+/// cursor hides that layout: advancing and retreating step over collection
+/// slots, and the NaT bit of the register at a given address is bit
+/// `addr{8:3}` of the collection for its group.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BackingStoreCursor {
+    /// Current address
+    addr: u64,
+}
+
+impl BackingStoreCursor {
+    /// Create a cursor at the given 8-byte aligned address
+    pub fn new(addr: u64) -> Result<Self, EmulatorError> {
+        if addr & 0x7 != 0 {
+            return Err(EmulatorError::InvalidAlignment);
+        }
+        Ok(Self { addr })
+    }
+
+    /// Current address
+    pub fn addr(&self) -> u64 {
+        self.addr
+    }
+
+    /// Slot number within the current group (bits 8:3 of the address)
+    pub fn slot(&self) -> u64 {
+        (self.addr >> 3) & 0x3F
+    }
+
+    /// Check if the cursor is on a NaT collection slot
+    pub fn is_rnat_slot(&self) -> bool {
+        self.slot() == RNAT_SLOT
+    }
+
+    /// Address of the NaT collection covering the current slot
+    pub fn rnat_addr(&self) -> u64 {
+        self.addr | (RNAT_SLOT << 3)
+    }
+
+    /// Bit mask of the current register within its NaT collection
+    pub fn nat_mask(&self) -> u64 {
+        1 << self.slot()
+    }
+
+    /// Move the cursor by `count` registers, skipping collection slots
+    pub fn skip(&self, count: i64) -> Result<Self, EmulatorError> {
+        let mut delta = self.slot() as i64 + count;
+        if count < 0 {
+            delta -= RNAT_SLOT as i64 - 1;
+        }
+        let slots = count + delta / RNAT_SLOT as i64;
+        let addr = self.addr.checked_add_signed(slots * 8).ok_or_else(|| {
+            EmulatorError::RSEError("Backing store address out of range".to_string())
+        })?;
+        Ok(Self { addr })
+    }
+
+    /// Number of registers stored between this cursor and `end`
+    pub fn registers_until(&self, end: u64) -> Result<u32, EmulatorError> {
+        if end < self.addr || end & 0x7 != 0 {
+            return Err(EmulatorError::RSEError(format!(
+                "Invalid backing store range {:#x}..{:#x}",
+                self.addr, end
+            )));
+        }
+        let slots = ((end - self.addr) >> 3) as i64;
+        Ok((slots - (self.slot() as i64 + slots) / SLOTS_PER_GROUP) as u32)
+    }
+
+    /// Store a register at the cursor and advance
+    ///
+    /// The NaT bit is recorded in `rnat`. When the cursor reaches a collection
+    /// slot, `rnat` is written there and cleared for the next group.
+    pub fn spill(
+        &mut self,
+        memory: &mut Memory,
+        value: u64,
+        nat: bool,
+        rnat: &mut u64,
+    ) -> Result<(), EmulatorError> {
+        memory.write_u64(self.addr, value)?;
+        if nat {
+            *rnat |= self.nat_mask();
+        } else {
+            *rnat &= !self.nat_mask();
+        }
+        self.addr += 8;
+
+        if self.is_rnat_slot() {
+            memory.write_u64(self.addr, *rnat)?;
+            *rnat = 0;
+            self.addr += 8;
+        }
+
+        Ok(())
+    }
+
+    /// Retreat to the previous register and load it
+    ///
+    /// `collection` supplies the NaT collection for a collection slot address.
+    pub fn fill(
+        &mut self,
+        memory: &mut Memory,
+        mut collection: impl FnMut(&mut Memory, u64) -> Result<u64, EmulatorError>,
+    ) -> Result<(u64, bool), EmulatorError> {
+        *self = self.skip(-1)?;
+        let value = memory.read_u64(self.addr)?;
+        let nat = collection(memory, self.rnat_addr())? & self.nat_mask() != 0;
+        Ok((value, nat))
+    }
+}
+
+/// Saved register partitions of an RSE
+///
+/// AR.RSC, AR.BSPSTORE and AR.RNAT are architectural registers and are
+/// saved with the others; this is the state behind them.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RseState {
+    /// Value and NaT bit of the newest dirty registers, oldest first
+    pub contents: Vec<(u64, bool)>,
+    /// Value and NaT bit of the newest clean registers, oldest first
+    pub clean: Vec<(u64, bool)>,
+    /// Number of dirty registers
+    pub dirty_count: u32,
+    /// Number of clean registers
+    pub clean_count: u32,
+    /// Number of invalid registers
+    pub invalid_count: u32,
+    /// Mandatory loads still needed for the current frame
+    pub pending_loads: u32,
+    /// Backing store pointer for loads
+    pub bspload: u64,
+}
+
 /// Register Stack Engine state
+///
+/// Registers are tracked as counts of dirty (not yet stored), clean (stored
+/// and still in the register file) and invalid registers. Dirty registers lie
+/// above BSPSTORE in the backing store, clean registers between BSPLOAD and
+/// BSPSTORE. The contents of the most recent dirty registers, pushed by
+/// calls or reloaded by loadrs, are held with their NaT bits; older dirty
+/// registers spill as zero. Stored and filled registers keep their contents
+/// while they are clean, so a return into them reads the register file
+/// rather than the backing store.
+///
+/// When a return reaches locals that are only in the backing store, they
+/// are loaded before execution continues. Those mandatory loads stay
+/// pending until they complete, so a load that faults can be retried.
 #[derive(Debug)]
 pub struct RSE {
     /// Configuration
     config: RSEConfig,
-    /// Backing store pointer
-    bsp: u64,
+    /// AR.RSC as written by software
+    rsc: u64,
+    /// Mandatory loads still needed for the current frame
+    pending_loads: u32,
     /// Backing store pointer for stores
     bspstore: u64,
+    /// Backing store pointer for loads
+    bspload: u64,
     /// Number of dirty registers
     dirty_count: u32,
     /// Number of clean registers
     clean_count: u32,
     /// Invalid count
     invalid_count: u32,
-    /// NaT collection bits
+    /// NaT collection for the group containing BSPSTORE
     rnat: u64,
+    /// Value and NaT bit of the newest dirty registers, oldest first
+    contents: VecDeque<(u64, bool)>,
+    /// Value and NaT bit of the newest clean registers, oldest first
+    clean: VecDeque<(u64, bool)>,
+    /// Registers stored to the backing store so far
+    spills: u64,
+    /// Backing store range written since [`RSE::take_spilled`] was last
+    /// called
+    spilled: Option<(u64, u64)>,
+    /// Registers loaded from the backing store so far
+    fills: u64,
 }
 
 impl Default for RSE {
@@ -183,9 +387,16 @@ impl RSE {
     pub fn new() -> Self {
         Self {
             config: RSEConfig::default(),
-            bsp: 0,
+            rsc: RSEConfig::default().mode.to_rsc(),
+            pending_loads: 0,
             bspstore: 0,
+            bspload: 0,
             rnat: 0,
+            contents: VecDeque::new(),
+            clean: VecDeque::new(),
+            spills: 0,
+            spilled: None,
+            fills: 0,
             dirty_count: 0,
             clean_count: 0,
             invalid_count: 0,
@@ -200,11 +411,48 @@ impl RSE {
     /// Set configuration
     pub fn set_config(&mut self, config: RSEConfig) {
         self.config = config;
+        self.rsc = (self.rsc & !RSC_MODE_MASK) | config.mode.to_rsc();
+    }
+
+    /// Get AR.RSC
+    pub fn get_rsc(&self) -> u64 {
+        self.rsc
+    }
+
+    /// Set AR.RSC (write to AR.RSC)
+    ///
+    /// The privilege level is raised to `cpl` if it is more privileged, and
+    /// the mode field selects the RSE mode. Reserved fields must be zero.
+    pub fn set_rsc(&mut self, value: u64, cpl: u8) -> Result<(), EmulatorError> {
+        if value & !RSC_WRITABLE != 0 {
+            return Err(EmulatorError::RegisterError(format!(
+                "Reserved field set in AR.RSC: {:#x}",
+                value
+            )));
+        }
+
+        let pl = ((value >> RSC_PL_SHIFT) & 0x3).max(cpl as u64 & 0x3);
+        self.rsc = (value & !(0x3 << RSC_PL_SHIFT)) | (pl << RSC_PL_SHIFT);
+        self.config.mode = RSEMode::from_rsc(value);
+        Ok(())
+    }
+
+    /// Check if the RSE is in enforced lazy mode (AR.RSC.mode is 0)
+    pub fn is_enforced_lazy(&self) -> bool {
+        self.rsc & RSC_MODE_MASK == 0
     }
 
     /// Get backing store pointer
+    ///
+    /// BSP is the address the first dirty register above BSPSTORE would be
+    /// stored at, accounting for intervening NaT collections.
     pub fn get_bsp(&self) -> u64 {
-        self.bsp
+        BackingStoreCursor {
+            addr: self.bspstore,
+        }
+        .skip(self.dirty_count as i64)
+        .map(|cursor| cursor.addr())
+        .unwrap_or(self.bspstore)
     }
 
     /// Get backing store pointer for stores
@@ -212,85 +460,360 @@ impl RSE {
         self.bspstore
     }
 
+    /// Get backing store pointer for loads
+    pub fn get_bspload(&self) -> u64 {
+        self.bspload
+    }
+
+    /// Set backing store pointer for stores (write to AR.BSPSTORE)
+    ///
+    /// Bits 2:0 are ignored. Clean registers are invalidated and BSPLOAD
+    /// follows BSPSTORE. Dirty registers stay in the register file and will
+    /// be stored at the new location, so BSP moves with BSPSTORE, skipping
+    /// the collection slots between them.
+    pub fn set_bspstore(&mut self, addr: u64) -> Result<(), EmulatorError> {
+        if self.pending_loads != 0 {
+            return Err(EmulatorError::RSEError(
+                "Cannot write BSPSTORE with mandatory loads pending".to_string(),
+            ));
+        }
+        let addr = addr & !0x7;
+        BackingStoreCursor::new(addr)?;
+
+        self.invalidate();
+        self.bspstore = addr;
+        self.bspload = addr;
+        Ok(())
+    }
+
     /// Get NaT collection bits
     pub fn get_rnat(&self) -> u64 {
         self.rnat
     }
 
-    /// Spill registers to backing store
-    pub fn spill_registers(
+    /// Set NaT collection bits (write to AR.RNAT)
+    ///
+    /// Bit 63 does not correspond to a register and always reads as zero.
+    pub fn set_rnat(&mut self, value: u64) {
+        self.rnat = value & !(1 << RNAT_SLOT);
+    }
+
+    /// NaT collection covering a collection slot address
+    ///
+    /// Collections at or above BSPSTORE have not been written yet and are
+    /// taken from AR.RNAT.
+    fn collection(&self, memory: &mut Memory, rnat_addr: u64) -> Result<u64, EmulatorError> {
+        if rnat_addr >= self.bspstore {
+            Ok(self.rnat)
+        } else {
+            memory.read_u64(rnat_addr)
+        }
+    }
+
+    /// Store the next dirty register at BSPSTORE
+    pub fn store_register(
         &mut self,
-        _memory: &mut Memory,
-        count: u32,
+        memory: &mut Memory,
+        value: u64,
+        nat: bool,
     ) -> Result<(), EmulatorError> {
-        if count > self.dirty_count {
+        if self.dirty_count == 0 {
             return Err(EmulatorError::RSEError(
                 "Not enough dirty registers to spill".to_string(),
             ));
         }
 
-        for _ in 0..count {
-            // Write register value to memory
-            _memory.write_u64(self.bspstore, 0)?; // TODO: Get actual register value
+        let mut cursor = BackingStoreCursor::new(self.bspstore)?;
+        cursor.spill(memory, value, nat, &mut self.rnat)?;
+        let start = self.spilled.map_or(self.bspstore, |(start, _)| start);
+        self.spilled = Some((start.min(self.bspstore), cursor.addr()));
+        self.bspstore = cursor.addr();
 
-            // Update RNAT if needed
-            if (self.bspstore >> 3) & 0x3F == 0x3F {
-                _memory.write_u64(self.bspstore + 8, self.rnat)?;
-                self.bspstore += 16;
-            } else {
-                self.bspstore += 8;
+        if self.contents.len() == self.dirty_count as usize {
+            self.contents.pop_front();
+        }
+        self.clean.push_back((value, nat));
+        self.spills += 1;
+        self.dirty_count -= 1;
+        self.clean_count += 1;
+        Ok(())
+    }
+
+    /// Contents of the oldest dirty register
+    fn oldest_dirty(&self) -> (u64, bool) {
+        match self.contents.front() {
+            Some(&register) if self.contents.len() == self.dirty_count as usize => register,
+            _ => (0, false),
+        }
+    }
+
+    /// Number of dirty registers
+    pub fn dirty_count(&self) -> u32 {
+        self.dirty_count
+    }
+
+    /// Number of clean registers
+    pub fn clean_count(&self) -> u32 {
+        self.clean_count
+    }
+
+    /// Registers stored to the backing store since the RSE was created
+    pub fn spill_count(&self) -> u64 {
+        self.spills
+    }
+
+    /// Start and end of the backing store written by spills since the
+    /// last call, if any
+    ///
+    /// The CPU invalidates ALAT entries overlapping it, as for any store.
+    pub fn take_spilled(&mut self) -> Option<(u64, u64)> {
+        self.spilled.take()
+    }
+
+    /// Registers loaded from the backing store since the RSE was created
+    pub fn fill_count(&self) -> u64 {
+        self.fills
+    }
+
+    /// Save the register partitions
+    pub fn state(&self) -> RseState {
+        RseState {
+            contents: self.contents.iter().copied().collect(),
+            clean: self.clean.iter().copied().collect(),
+            dirty_count: self.dirty_count,
+            clean_count: self.clean_count,
+            invalid_count: self.invalid_count,
+            pending_loads: self.pending_loads,
+            bspload: self.bspload,
+        }
+    }
+
+    /// Return the register partitions to a saved state
+    ///
+    /// AR.BSPSTORE must already hold its saved value, as writing it
+    /// invalidates the clean partition.
+    pub fn restore_state(&mut self, state: &RseState) -> Result<(), EmulatorError> {
+        BackingStoreCursor::new(state.bspload)?;
+        self.contents = state.contents.iter().copied().collect();
+        self.clean = state.clean.iter().copied().collect();
+        self.dirty_count = state.dirty_count;
+        self.clean_count = state.clean_count;
+        self.invalid_count = state.invalid_count;
+        self.pending_loads = state.pending_loads;
+        self.bspload = state.bspload;
+        Ok(())
+    }
+
+    /// Push a register of the caller's frame onto the stack (br.call)
+    pub fn push_register(&mut self, value: u64, nat: bool) {
+        self.contents.push_back((value, nat));
+        self.dirty_count += 1;
+    }
+
+    /// Pop the newest register of the stack back into the frame (br.ret)
+    ///
+    /// Clean registers are taken from the register file, moving BSPSTORE
+    /// down over them. Registers no longer in the register file are loaded
+    /// from the backing store below BSPSTORE, with their NaT bits from the
+    /// matching collection.
+    pub fn pop_register(&mut self, memory: &mut Memory) -> Result<(u64, bool), EmulatorError> {
+        if let Some(register) = self.pop_dirty() {
+            return Ok(register);
+        }
+
+        let mut cursor = BackingStoreCursor::new(self.bspstore)?;
+        let loaded = match self.clean.back() {
+            Some(&register) if self.clean_count > 0 => {
+                cursor = cursor.skip(-1)?;
+                register
             }
+            _ => {
+                let loaded = cursor.fill(memory, |memory, addr| self.collection(memory, addr))?;
+                self.fills += 1;
+                loaded
+            }
+        };
 
-            self.dirty_count -= 1;
-            self.clean_count += 1;
+        // Moving below a collection slot makes that group current again
+        let rnat = self.collection(memory, cursor.rnat_addr())?;
+        self.bspstore = cursor.addr();
+        self.bspload = self.bspload.min(self.bspstore);
+        self.rnat = rnat & !(1 << RNAT_SLOT);
+        self.clean.pop_back();
+        self.clean_count = self.clean_count.saturating_sub(1);
+        Ok(loaded)
+    }
+
+    /// Pop the most recent dirty register, which is still in the register
+    /// file, if there is one
+    pub fn pop_dirty(&mut self) -> Option<(u64, bool)> {
+        if self.dirty_count == 0 {
+            return None;
+        }
+        self.dirty_count -= 1;
+        if self.contents.len() > self.dirty_count as usize {
+            return Some(self.contents.pop_back().unwrap_or((0, false)));
         }
+        Some((0, false))
+    }
+
+    /// Number of mandatory loads pending for the current frame
+    pub fn pending_loads(&self) -> u32 {
+        self.pending_loads
+    }
 
+    /// Enter the load pending state for `count` registers of the current
+    /// frame that are not in the register file
+    pub fn begin_mandatory_loads(&mut self, count: u32) -> Result<(), EmulatorError> {
+        if count != 0 && self.dirty_count != 0 {
+            return Err(EmulatorError::RSEError(
+                "Mandatory loads with dirty registers".to_string(),
+            ));
+        }
+        self.pending_loads = count;
         Ok(())
     }
 
-    /// Fill registers from backing store
-    pub fn fill_registers(
+    /// Perform the next pending mandatory load
+    ///
+    /// Returns the register value and its NaT bit; the register is the
+    /// highest of the frame still missing. If the load faults it stays
+    /// pending.
+    pub fn mandatory_load(&mut self, memory: &mut Memory) -> Result<(u64, bool), EmulatorError> {
+        if self.pending_loads == 0 {
+            return Err(EmulatorError::RSEError(
+                "No mandatory loads pending".to_string(),
+            ));
+        }
+
+        let loaded = self.pop_register(memory)?;
+        self.pending_loads -= 1;
+        Ok(loaded)
+    }
+
+    /// Load the register below BSPLOAD into the register file
+    ///
+    /// Returns the register value and its NaT bit.
+    pub fn load_register(&mut self, memory: &mut Memory) -> Result<(u64, bool), EmulatorError> {
+        if self.invalid_count == 0 {
+            return Err(EmulatorError::RSEError(
+                "Not enough invalid registers to fill".to_string(),
+            ));
+        }
+
+        let mut cursor = BackingStoreCursor::new(self.bspload)?;
+        let loaded = cursor.fill(memory, |memory, addr| self.collection(memory, addr))?;
+        self.bspload = cursor.addr();
+
+        // Only contents adjacent to the ones already held can be kept
+        if self.clean.len() == self.clean_count as usize {
+            self.clean.push_front(loaded);
+        }
+        self.invalid_count -= 1;
+        self.clean_count += 1;
+        self.fills += 1;
+        Ok(loaded)
+    }
+
+    /// Spill registers to backing store
+    pub fn spill_registers(
         &mut self,
-        _memory: &mut Memory,
+        memory: &mut Memory,
         count: u32,
     ) -> Result<(), EmulatorError> {
-        if count > self.invalid_count {
+        if count > self.dirty_count {
             return Err(EmulatorError::RSEError(
-                "Not enough invalid registers to fill".to_string(),
+                "Not enough dirty registers to spill".to_string(),
             ));
         }
 
         for _ in 0..count {
-            // Read register value from memory
-            let _value = _memory.read_u64(self.bsp)?;
+            let (value, nat) = self.oldest_dirty();
+            self.store_register(memory, value, nat)?;
+        }
 
-            // Check if we need to read RNAT
-            let _nat_bit = (self.rnat >> ((self.bsp >> 3) & 0x3F)) & 1 != 0;
+        Ok(())
+    }
 
-            // Update BSP
-            if (self.bsp >> 3) & 0x3F == 0x3F {
-                self.rnat = _memory.read_u64(self.bsp + 8)?;
-                self.bsp += 16;
-            } else {
-                self.bsp += 8;
-            }
+    /// Fill registers from backing store
+    ///
+    /// The registers become clean, holding what was loaded.
+    pub fn fill_registers(&mut self, memory: &mut Memory, count: u32) -> Result<(), EmulatorError> {
+        if count > self.invalid_count {
+            return Err(EmulatorError::RSEError(
+                "Not enough invalid registers to fill".to_string(),
+            ));
+        }
 
-            self.invalid_count -= 1;
-            self.clean_count += 1;
+        for _ in 0..count {
+            self.load_register(memory)?;
         }
 
         Ok(())
     }
 
-    /// Flush dirty registers
+    /// Flush dirty registers (flushrs)
     pub fn flush(&mut self, memory: &mut Memory) -> Result<(), EmulatorError> {
         self.spill_registers(memory, self.dirty_count)
     }
 
+    /// Load the `distance` bytes below BSP into the register file (loadrs)
+    ///
+    /// The loaded registers become dirty and BSPSTORE and BSPLOAD are moved
+    /// down to BSP - `distance`. Any other registers are invalidated.
+    pub fn loadrs(&mut self, memory: &mut Memory, distance: u64) -> Result<(), EmulatorError> {
+        if self.dirty_count != 0 {
+            return Err(EmulatorError::RSEError(
+                "loadrs with dirty registers".to_string(),
+            ));
+        }
+
+        let target = self
+            .bspstore
+            .checked_sub(distance)
+            .ok_or_else(|| EmulatorError::RSEError("loadrs distance exceeds BSP".to_string()))?;
+        let count = BackingStoreCursor::new(target)?.registers_until(self.bspstore)?;
+
+        // Reload the whole range from memory
+        self.invalidate();
+        self.bspload = self.bspstore;
+        // The physical register file size is not modelled, so make room
+        self.invalid_count = self.invalid_count.max(count);
+        let mut loaded = VecDeque::with_capacity(count as usize);
+        for _ in 0..count {
+            loaded.push_front(self.load_register(memory)?);
+        }
+
+        // Reloaded registers have not been stored below the new BSPSTORE
+        let rnat = self.collection(memory, BackingStoreCursor { addr: target }.rnat_addr())?;
+        self.clean_count -= count;
+        self.dirty_count = count;
+        self.contents = loaded;
+        self.invalidate();
+        self.bspstore = target;
+        self.rnat = rnat;
+        Ok(())
+    }
+
+    /// Invalidate the `count` oldest clean registers to make room in the
+    /// register file, moving BSPLOAD up over them
+    pub fn evict_clean(&mut self, count: u32) -> Result<(), EmulatorError> {
+        let count = count.min(self.clean_count);
+        let cursor = BackingStoreCursor::new(self.bspload)?.skip(count as i64)?;
+        let kept = (self.clean_count - count) as usize;
+        let evicted = self.clean.len().saturating_sub(kept);
+        self.clean.drain(..evicted);
+        self.bspload = cursor.addr();
+        self.clean_count -= count;
+        self.invalid_count += count;
+        Ok(())
+    }
+
     /// Invalidate clean registers
     pub fn invalidate(&mut self) {
         self.invalid_count += self.clean_count;
         self.clean_count = 0;
+        self.clean.clear();
     }
 
     /// Handle register allocation
@@ -318,6 +841,9 @@ impl RSE {
             self.dirty_count += remaining;
         }
 
+        // Newly allocated registers hold no contents yet
+        self.contents.extend((0..count).map(|_| (0, false)));
+        self.trim_clean();
         Ok(())
     }
 
@@ -360,14 +886,29 @@ impl RSE {
             }
         }
 
+        // Deallocated registers are the newest ones
+        self.contents.truncate(self.dirty_count as usize);
+        self.trim_clean();
         Ok(())
     }
+
+    /// Drop the contents of clean registers that were reallocated, newest
+    /// first
+    fn trim_clean(&mut self) {
+        self.clean.truncate(self.clean_count as usize);
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::memory::Memory;
+    use crate::memory::{Memory, Permissions};
+
+    fn backing_store() -> Memory {
+        let mut memory = Memory::new();
+        memory.map(0x1000, 0x1000, Permissions::ReadWrite).unwrap();
+        memory
+    }
 
     #[test]
     fn test_rse_config() {
@@ -390,10 +931,9 @@ mod tests {
     }
 
     #[test]
-    #[ignore = "RSE spill operation needs to be fixed"]
     fn test_rse_spill() {
         let mut rse = RSE::new();
-        let mut memory = Memory::new();
+        let mut memory = backing_store();
 
         // Set up initial state
         rse.dirty_count = 10;
@@ -409,14 +949,14 @@ mod tests {
     }
 
     #[test]
-    #[ignore = "RSE fill operation needs to be fixed"]
     fn test_rse_fill() {
         let mut rse = RSE::new();
-        let mut memory = Memory::new();
+        let mut memory = backing_store();
 
         // Set up initial state
         rse.invalid_count = 10;
-        rse.bsp = 0x1000;
+        rse.bspstore = 0x1000 + 5 * 8;
+        rse.bspload = rse.bspstore;
 
         // Fill 5 registers
         assert!(rse.fill_registers(&mut memory, 5).is_ok());
@@ -424,7 +964,10 @@ mod tests {
         // Check state after fill
         assert_eq!(rse.invalid_count, 5);
         assert_eq!(rse.clean_count, 5);
-        assert_eq!(rse.bsp, 0x1000 + 5 * 8);
+        assert_eq!(rse.bspload, 0x1000);
+
+        // Nothing left below the backing store
+        assert!(rse.fill_registers(&mut memory, 1).is_err());
     }
 
     #[test]
@@ -473,10 +1016,9 @@ mod tests {
     }
 
     #[test]
-    #[ignore = "RSE RNAT handling needs to be fixed"]
     fn test_rse_rnat() {
         let mut rse = RSE::new();
-        let mut memory = Memory::new();
+        let mut memory = backing_store();
 
         // Set up initial state with dirty registers
         rse.dirty_count = 63;
@@ -494,10 +1036,9 @@ mod tests {
     }
 
     #[test]
-    #[ignore = "RSE flush operation needs to be fixed"]
     fn test_rse_flush() {
         let mut rse = RSE::new();
-        let mut memory = Memory::new();
+        let mut memory = backing_store();
 
         // Set up initial state
         rse.dirty_count = 10;
@@ -526,4 +1067,207 @@ mod tests {
         assert_eq!(rse.clean_count, 0);
         assert_eq!(rse.invalid_count, 10);
     }
+
+    #[test]
+    fn test_cursor_addressing() {
+        let cursor = BackingStoreCursor::new(0x11F0).unwrap();
+        assert_eq!(cursor.slot(), 0x3E);
+        assert!(!cursor.is_rnat_slot());
+        assert_eq!(cursor.rnat_addr(), 0x11F8);
+        assert_eq!(cursor.nat_mask(), 1 << 0x3E);
+
+        // Stepping over the collection slot in both directions
+        assert_eq!(cursor.skip(1).unwrap().addr(), 0x1200);
+        assert_eq!(cursor.skip(1).unwrap().skip(-1).unwrap(), cursor);
+        assert_eq!(cursor.skip(-0x3E).unwrap().addr(), 0x1000);
+        assert_eq!(cursor.skip(0x3F + 1).unwrap().addr(), 0x1400);
+
+        // Register counts exclude collection slots
+        let base = BackingStoreCursor::new(0x1000).unwrap();
+        assert_eq!(base.registers_until(0x11F8).unwrap(), 63);
+        assert_eq!(base.registers_until(0x1200).unwrap(), 63);
+        assert_eq!(base.registers_until(0x1208).unwrap(), 64);
+        assert_eq!(cursor.registers_until(0x1208).unwrap(), 2);
+        for count in 0..200 {
+            let end = base.skip(count).unwrap().addr();
+            assert_eq!(base.registers_until(end).unwrap() as i64, count);
+        }
+
+        assert!(BackingStoreCursor::new(0x1004).is_err());
+        assert!(base.registers_until(0x800).is_err());
+    }
+
+    #[test]
+    fn test_rnat_collection() {
+        let mut rse = RSE::new();
+        let mut memory = backing_store();
+        rse.set_bspstore(0x1000).unwrap();
+        rse.dirty_count = 70;
+
+        // Set the NaT bit of every third register
+        for i in 0..70u64 {
+            rse.store_register(&mut memory, 0x100 + i, i % 3 == 0)
+                .unwrap();
+        }
+
+        // The full group's collection was written to its slot
+        let expected = (0..63)
+            .filter(|i| i % 3 == 0)
+            .fold(0u64, |acc, i| acc | 1 << i);
+        assert_eq!(memory.read_u64(0x11F8).unwrap(), expected);
+        assert_eq!(rse.bspstore, 0x1000 + 71 * 8);
+
+        // The partial group is held in AR.RNAT
+        let partial = (63..70u64)
+            .filter(|i| i % 3 == 0)
+            .fold(0u64, |acc, i| acc | 1 << (i - 63));
+        assert_eq!(rse.get_rnat(), partial);
+
+        // Fill back in reverse order, taking NaT bits from both sources
+        rse.invalidate();
+        rse.bspload = rse.bspstore;
+        for i in (0..70u64).rev() {
+            let (value, nat) = rse.load_register(&mut memory).unwrap();
+            assert_eq!(value, 0x100 + i);
+            assert_eq!(nat, i % 3 == 0, "register {}", i);
+        }
+        assert_eq!(rse.bspload, 0x1000);
+    }
+
+    #[test]
+    fn test_rse_loadrs() {
+        let mut rse = RSE::new();
+        let mut memory = backing_store();
+        rse.set_bspstore(0x1000).unwrap();
+        rse.dirty_count = 66;
+        for i in 0..66u64 {
+            rse.store_register(&mut memory, i, i == 1 || i == 64)
+                .unwrap();
+        }
+        let bsp = rse.get_bsp();
+        assert_eq!(bsp, 0x1000 + 67 * 8);
+
+        // Reload the top 4 registers, crossing the collection slot
+        assert!(rse.loadrs(&mut memory, 5 * 8).is_ok());
+        assert_eq!(rse.bspstore, 0x1000 + 62 * 8);
+        assert_eq!(rse.bspload, rse.bspstore);
+        assert_eq!(rse.dirty_count, 4);
+        assert_eq!(rse.clean_count, 0);
+        assert_eq!(rse.get_bsp(), bsp);
+
+        // AR.RNAT now holds the collection for the new BSPSTORE group
+        assert_eq!(rse.get_rnat(), 1 << 1);
+
+        // Spilling again rebuilds the same backing store image
+        rse.store_register(&mut memory, 62, false).unwrap();
+        assert_eq!(memory.read_u64(0x11F8).unwrap(), 1 << 1);
+        assert!(rse.loadrs(&mut memory, 8).is_err());
+    }
+
+    #[test]
+    fn test_rse_ar_writes() {
+        let mut rse = RSE::new();
+        rse.set_rnat(u64::MAX);
+        assert_eq!(rse.get_rnat(), u64::MAX >> 1);
+
+        rse.clean_count = 3;
+        rse.set_bspstore(0x2000).unwrap();
+        assert_eq!(rse.get_bspload(), 0x2000);
+        assert_eq!(rse.get_bsp(), 0x2000);
+        assert_eq!(rse.invalid_count, 3);
+
+        // The low bits are ignored and dirty registers move with BSPSTORE
+        rse.set_bspstore(0x2004).unwrap();
+        assert_eq!(rse.get_bspstore(), 0x2000);
+        rse.dirty_count = 2;
+        rse.set_bspstore(0x31F0).unwrap();
+        assert_eq!(rse.get_bsp(), 0x3208);
+
+        rse.pending_loads = 1;
+        assert!(rse.set_bspstore(0x3000).is_err());
+    }
+
+    #[test]
+    fn test_rsc_writes() {
+        let mut rse = RSE::new();
+        assert!(!rse.is_enforced_lazy());
+
+        // pl cannot be more privileged than the writer
+        rse.set_rsc(0x3 << 16, 3).unwrap();
+        assert!(rse.is_enforced_lazy());
+        assert_eq!(rse.get_rsc(), 0x3 << 16 | 0x3 << 2);
+        assert_eq!(rse.get_config().mode, RSEMode::Enforced);
+
+        rse.set_rsc(0x3, 0).unwrap();
+        assert_eq!(rse.get_rsc(), 0x3);
+        assert_eq!(rse.get_config().mode, RSEMode::Eager);
+        assert!(rse.set_rsc(1 << 5, 0).is_err());
+        assert_eq!(rse.get_rsc(), 0x3);
+
+        rse.set_config(RSEConfig::default());
+        assert_eq!(rse.get_rsc(), 0x2);
+    }
+
+    #[test]
+    fn test_mandatory_loads() {
+        let mut rse = RSE::new();
+        let mut memory = backing_store();
+        rse.set_bspstore(0x1000).unwrap();
+        for i in 0..3u64 {
+            rse.push_register(i, i == 1);
+        }
+        rse.flush(&mut memory).unwrap();
+
+        // Switch to an unmapped backing store and back: nothing can load
+        rse.set_bspstore(0x8000_0000).unwrap();
+        rse.begin_mandatory_loads(1).unwrap();
+        assert!(rse.mandatory_load(&mut memory).is_err());
+        assert_eq!(rse.pending_loads(), 1);
+        assert!(rse.set_bspstore(0x1018).is_err());
+
+        rse.pending_loads = 0;
+        rse.set_bspstore(0x1018).unwrap();
+        rse.begin_mandatory_loads(3).unwrap();
+        for i in (0..3u64).rev() {
+            assert_eq!(rse.mandatory_load(&mut memory).unwrap(), (i, i == 1));
+        }
+        assert_eq!(rse.pending_loads(), 0);
+        assert!(rse.mandatory_load(&mut memory).is_err());
+    }
+
+    #[test]
+    fn test_rse_push_pop() {
+        let mut rse = RSE::new();
+        let mut memory = backing_store();
+        rse.set_bspstore(0x1000).unwrap();
+        for i in 0..70u64 {
+            rse.push_register(i, i % 5 == 0);
+        }
+
+        // Spill part of the stack, crossing a collection slot
+        rse.spill_registers(&mut memory, 65).unwrap();
+        assert_eq!(rse.dirty_count(), 5);
+        assert_eq!(memory.read_u64(0x1000 + 8).unwrap(), 1);
+        assert_eq!(memory.read_u64(0x11F8).unwrap() & 1 << 5, 1 << 5);
+
+        // Pop the dirty registers, then the clean ones still in the
+        // register file
+        for i in (60..70u64).rev() {
+            assert_eq!(rse.pop_register(&mut memory).unwrap(), (i, i % 5 == 0));
+        }
+        assert_eq!((rse.clean_count(), rse.fill_count()), (60, 0));
+
+        // Once evicted, the rest are reloaded from memory
+        rse.evict_clean(10).unwrap();
+        assert_eq!(rse.get_bspload(), 0x1000 + 10 * 8);
+        memory.write_u64(0x1000 + 59 * 8, 0xBAD).unwrap();
+        assert_eq!(rse.pop_register(&mut memory).unwrap(), (59, false));
+        rse.invalidate();
+        for i in (0..59u64).rev() {
+            assert_eq!(rse.pop_register(&mut memory).unwrap(), (i, i % 5 == 0));
+        }
+        assert_eq!(rse.get_bspstore(), 0x1000);
+        assert_eq!(rse.get_bsp(), 0x1000);
+        assert_eq!((rse.spill_count(), rse.fill_count()), (65, 59));
+    }
 }
@@ -0,0 +1,378 @@
+//! Register stack invariant checker
+//!
+//! Misuse of the register stack in an interruption handler rarely faults
+//! where it happens: the handler runs on, and the interrupted code finds
+//! its stacked registers corrupted long after the handler returned. The
+//! checker watches the RSE operations guest system code performs and
+//! records each one that breaks an architectural rule, with the machine
+//! state at that point:
+//!
+//! - `flushrs` in an interruption handler before `cover`: the interrupted
+//!   frame is still current, so it is not written to the backing store.
+//! - A write to CR.IFS in an interruption handler before `cover`: the
+//!   interrupted frame marker is replaced before it was ever saved.
+//! - `alloc` in enforced lazy mode while mandatory RSE loads are pending:
+//!   the frame is resized before its locals were restored.
+//! - `rfi` from a handler that changed the current frame while CR.IFS.v is
+//!   clear: without a covered frame marker to restore, the interrupted code
+//!   resumes with the handler's frame.
+//!
+//! Violations are only recorded; execution carries on as the hardware
+//! would.
+
+use super::interrupts::InterruptVector;
+use super::registers::cr::CRIndex;
+use super::{Cpu, IFS_VALID};
+use crate::debugger::SymbolTable;
+use std::fmt::{self, Write};
+
+/// Architectural rule broken by a violation
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RseRule {
+    /// flushrs in an interruption handler before cover
+    FlushrsBeforeCover,
+    /// CR.IFS written in an interruption handler before cover
+    IfsWriteBeforeCover,
+    /// alloc in enforced lazy mode with mandatory loads pending
+    AllocWithPendingLoads,
+    /// rfi with CR.IFS.v clear from a handler that changed the frame
+    RfiWithoutCover,
+}
+
+impl fmt::Display for RseRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            RseRule::FlushrsBeforeCover => {
+                "flushrs before cover in an interruption handler; \
+                 the interrupted frame is not flushed"
+            }
+            RseRule::IfsWriteBeforeCover => {
+                "CR.IFS written before cover in an interruption handler; \
+                 the interrupted frame marker is lost"
+            }
+            RseRule::AllocWithPendingLoads => {
+                "alloc in enforced lazy mode with mandatory RSE loads pending"
+            }
+            RseRule::RfiWithoutCover => {
+                "rfi with CR.IFS.v clear after the handler changed the frame; \
+                 the interrupted frame is not restored"
+            }
+        })
+    }
+}
+
+/// A broken rule and the machine state when it was broken
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RseViolation {
+    /// Rule broken
+    pub rule: RseRule,
+    /// Bundle address of the offending instruction
+    pub ip: u64,
+    /// Slot of the offending instruction
+    pub slot: u8,
+    /// Current frame marker
+    pub cfm: u64,
+    /// AR.BSP
+    pub bsp: u64,
+    /// AR.BSPSTORE
+    pub bspstore: u64,
+    /// AR.RSC
+    pub rsc: u64,
+    /// Dirty registers on the register stack
+    pub dirty: u32,
+    /// Mandatory loads still pending
+    pub pending_loads: u32,
+    /// Interruption nesting level
+    pub nesting: u32,
+    /// Vector of the interruption being handled, if any
+    pub vector: Option<InterruptVector>,
+}
+
+impl RseViolation {
+    /// Capture the state of `cpu` for a violation of `rule`
+    fn capture(cpu: &Cpu, rule: RseRule) -> Self {
+        Self {
+            rule,
+            ip: cpu.ip,
+            slot: cpu.slot,
+            cfm: cpu.cfm,
+            bsp: cpu.rse.get_bsp(),
+            bspstore: cpu.rse.get_bspstore(),
+            rsc: cpu.rse.get_rsc(),
+            dirty: cpu.rse.dirty_count(),
+            pending_loads: cpu.rse.pending_loads(),
+            nesting: cpu.interrupt_nesting_level(),
+            vector: cpu.current_interrupt().map(|state| state.vector),
+        }
+    }
+}
+
+impl fmt::Display for RseViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{:#x} slot {}: {}", self.ip, self.slot, self.rule)?;
+        write!(
+            f,
+            "  cfm={:#x} (sof={} sol={}) bsp={:#x} bspstore={:#x} rsc={:#x} \
+             dirty={} pending loads={}",
+            self.cfm,
+            self.cfm & 0x7F,
+            (self.cfm >> 7) & 0x7F,
+            self.bsp,
+            self.bspstore,
+            self.rsc,
+            self.dirty,
+            self.pending_loads
+        )?;
+        if let Some(vector) = self.vector {
+            write!(
+                f,
+                "\n  handling {:?}, nesting level {}",
+                vector, self.nesting
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// What the checker knows of an active interruption handler
+#[derive(Debug, Clone, Copy)]
+struct Handler {
+    /// Whether the handler has issued cover
+    covered: bool,
+    /// Frame marker of the interrupted code
+    cfm: u64,
+}
+
+impl Handler {
+    /// A handler entered just now, with the interrupted frame current
+    fn entered(cpu: &Cpu) -> Self {
+        Self {
+            covered: false,
+            cfm: cpu.cfm,
+        }
+    }
+}
+
+/// Register stack invariant checker
+#[derive(Debug, Clone, Default)]
+pub struct RseChecker {
+    /// Active interruption handlers, outermost first
+    handlers: Vec<Handler>,
+    /// Violations found, oldest first
+    violations: Vec<RseViolation>,
+}
+
+impl RseChecker {
+    /// Create a checker with no violations
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Violations found, oldest first
+    pub fn violations(&self) -> &[RseViolation] {
+        &self.violations
+    }
+
+    /// Take the violations found so far
+    pub fn take_violations(&mut self) -> Vec<RseViolation> {
+        std::mem::take(&mut self.violations)
+    }
+
+    /// Whether the innermost handler has issued cover; true outside
+    /// handlers
+    fn covered(&mut self, cpu: &Cpu) -> bool {
+        self.innermost(cpu).is_none_or(|handler| handler.covered)
+    }
+
+    /// The innermost active handler, after catching up with the nesting
+    /// level
+    fn innermost(&mut self, cpu: &Cpu) -> Option<&mut Handler> {
+        let nesting = cpu.interrupt_nesting_level() as usize;
+        self.handlers.resize(nesting, Handler::entered(cpu));
+        self.handlers.last_mut()
+    }
+
+    /// An interruption handler was entered
+    pub(crate) fn interrupted(&mut self, cpu: &Cpu) {
+        let nesting = cpu.interrupt_nesting_level() as usize;
+        self.handlers.truncate(nesting.saturating_sub(1));
+        self.handlers.resize(nesting, Handler::entered(cpu));
+    }
+
+    /// cover was issued
+    pub(crate) fn cover(&mut self, cpu: &Cpu) {
+        if let Some(handler) = self.innermost(cpu) {
+            handler.covered = true;
+        }
+    }
+
+    /// rfi is about to return from the innermost handler
+    pub(crate) fn rfi(&mut self, cpu: &Cpu) {
+        let ifs_valid = cpu.read_cr(CRIndex::IFS) & IFS_VALID != 0;
+        if let Some(handler) = self.innermost(cpu) {
+            if !ifs_valid && cpu.cfm != handler.cfm {
+                self.record(cpu, RseRule::RfiWithoutCover);
+            }
+        }
+    }
+
+    /// flushrs was issued
+    pub(crate) fn flushrs(&mut self, cpu: &Cpu) {
+        if !self.covered(cpu) {
+            self.record(cpu, RseRule::FlushrsBeforeCover);
+        }
+    }
+
+    /// CR.IFS was written
+    pub(crate) fn write_ifs(&mut self, cpu: &Cpu) {
+        if !self.covered(cpu) {
+            self.record(cpu, RseRule::IfsWriteBeforeCover);
+        }
+    }
+
+    /// alloc was issued
+    pub(crate) fn alloc(&mut self, cpu: &Cpu) {
+        if cpu.rse.is_enforced_lazy() && cpu.rse.pending_loads() > 0 {
+            self.record(cpu, RseRule::AllocWithPendingLoads);
+        }
+    }
+
+    /// Record a violation of `rule`
+    fn record(&mut self, cpu: &Cpu, rule: RseRule) {
+        self.violations.push(RseViolation::capture(cpu, rule));
+    }
+
+    /// Format the violations as a report
+    ///
+    /// Addresses are shown with their symbols where the table has one.
+    pub fn report(&self, symbols: &SymbolTable) -> String {
+        let mut out = String::new();
+        for violation in &self.violations {
+            write!(out, "RSE violation: {}", violation).unwrap();
+            if let Some((symbol, offset)) = symbols.lookup(violation.ip) {
+                write!(out, "\n  in {}+{:#x}", symbol.name, offset).unwrap();
+            }
+            out.push('\n');
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::registers::cr::CRIndex;
+    use crate::cpu::rse::{RSEConfig, RSEMode};
+    use crate::memory::{Memory, Permissions};
+
+    const HANDLER: u64 = 0x8000;
+
+    fn interrupted_cpu(memory: &mut Memory) -> Cpu {
+        let mut cpu = Cpu::default();
+        memory
+            .map(0x10000, 0x10000, Permissions::ReadWrite)
+            .unwrap();
+        cpu.rse.set_bspstore(0x10000).unwrap();
+        cpu.set_rse_checking(true);
+        cpu.alloc_frame(memory, 8, 6, 0).unwrap();
+        cpu.register_interrupt_handler(InterruptVector::ExtInt, HANDLER, 0)
+            .unwrap();
+        cpu.set_interrupts_enabled(true);
+        cpu.raise_interrupt(InterruptVector::ExtInt, 0);
+        assert_eq!(cpu.check_interrupts(), Some(HANDLER));
+        cpu.ip = HANDLER;
+        cpu
+    }
+
+    #[test]
+    fn test_handler_without_cover() {
+        let mut memory = Memory::new();
+        let mut cpu = interrupted_cpu(&mut memory);
+        cpu.flush_rse(&mut memory).unwrap();
+        cpu.write_cr(CRIndex::IFS, 0).unwrap();
+
+        let rules: Vec<_> = cpu.rse_violations().iter().map(|v| v.rule).collect();
+        assert_eq!(
+            rules,
+            vec![RseRule::FlushrsBeforeCover, RseRule::IfsWriteBeforeCover]
+        );
+        let violation = &cpu.rse_violations()[0];
+        assert_eq!((violation.ip, violation.cfm & 0x7F), (HANDLER, 8));
+        assert_eq!(violation.vector, Some(InterruptVector::ExtInt));
+
+        let mut symbols = SymbolTable::new();
+        symbols.add("ext_int_handler", HANDLER, 0x100);
+        let report = cpu.rse_checker.as_ref().unwrap().report(&symbols);
+        assert!(report.contains("flushrs before cover"));
+        assert!(report.contains("in ext_int_handler+0x0"));
+    }
+
+    #[test]
+    fn test_handler_with_cover() {
+        let mut memory = Memory::new();
+        let mut cpu = interrupted_cpu(&mut memory);
+        cpu.cover(&mut memory).unwrap();
+        assert_eq!(cpu.cfm, 0);
+        assert_eq!(cpu.read_cr(CRIndex::IFS), 8 | 6 << 7 | 1 << 63);
+        cpu.flush_rse(&mut memory).unwrap();
+        assert_eq!(cpu.rse.dirty_count(), 0);
+        cpu.write_cr(CRIndex::IFS, 0).unwrap();
+        assert!(cpu.rse_violations().is_empty());
+
+        // Outside handlers there is no interrupted frame to protect
+        cpu.return_from_interrupt().unwrap();
+        cpu.flush_rse(&mut memory).unwrap();
+        assert!(cpu.rse_violations().is_empty());
+    }
+
+    #[test]
+    fn test_rfi_without_cover() {
+        // A handler that keeps to the interrupted frame needs no cover
+        let mut memory = Memory::new();
+        let mut cpu = interrupted_cpu(&mut memory);
+        cpu.rfi(&mut memory).unwrap();
+        assert!(cpu.rse_violations().is_empty());
+
+        // One that allocates its own frame loses the interrupted one
+        let mut memory = Memory::new();
+        let mut cpu = interrupted_cpu(&mut memory);
+        cpu.alloc_frame(&mut memory, 2, 2, 0).unwrap();
+        cpu.rfi(&mut memory).unwrap();
+        let rules: Vec<_> = cpu.rse_violations().iter().map(|v| v.rule).collect();
+        assert_eq!(rules, vec![RseRule::RfiWithoutCover]);
+        assert_eq!(cpu.rse_violations()[0].nesting, 1);
+        assert_eq!(cpu.cfm, 2 | 2 << 7);
+
+        // unless it covered it first
+        let mut memory = Memory::new();
+        let mut cpu = interrupted_cpu(&mut memory);
+        cpu.cover(&mut memory).unwrap();
+        cpu.alloc_frame(&mut memory, 2, 2, 0).unwrap();
+        cpu.rfi(&mut memory).unwrap();
+        assert!(cpu.rse_violations().is_empty());
+        assert_eq!(cpu.cfm, 8 | 6 << 7);
+    }
+
+    #[test]
+    fn test_alloc_with_pending_loads() {
+        let mut memory = Memory::new();
+        memory
+            .map(0x10000, 0x10000, Permissions::ReadWrite)
+            .unwrap();
+        let mut cpu = Cpu::default();
+        cpu.rse.set_bspstore(0x10000).unwrap();
+        cpu.set_rse_checking(true);
+        cpu.set_rse_config(RSEConfig {
+            mode: RSEMode::Enforced,
+            ..RSEConfig::default()
+        });
+
+        cpu.rse.push_register(1, false);
+        cpu.rse.spill_registers(&mut memory, 1).unwrap();
+        cpu.rse.begin_mandatory_loads(1).unwrap();
+        cpu.alloc_frame(&mut memory, 4, 2, 0).unwrap();
+        let rules: Vec<_> = cpu.rse_violations().iter().map(|v| v.rule).collect();
+        assert_eq!(rules, vec![RseRule::AllocWithPendingLoads]);
+        assert_eq!(cpu.rse_violations()[0].pending_loads, 1);
+    }
+}
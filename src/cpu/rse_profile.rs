@@ -0,0 +1,211 @@
+//! Register stack profiler
+//!
+//! This module attributes register stack activity to guest functions, to
+//! show how well code uses the register stack. Calls and returns maintain a
+//! shadow call stack; RSE spills and fills are charged to the function whose
+//! frame is current when they happen, along with the frame sizes it
+//! allocates and the deepest dirty partition seen while it runs.
+
+use super::rse::RSE;
+use crate::debugger::SymbolTable;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+/// Register stack activity of one function
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FunctionProfile {
+    /// Number of calls
+    pub calls: u64,
+    /// Frame sizes (sof) allocated, with their counts
+    pub alloc_sizes: BTreeMap<u32, u64>,
+    /// Registers spilled to the backing store
+    pub spills: u64,
+    /// Registers filled from the backing store
+    pub fills: u64,
+    /// Deepest dirty partition seen
+    pub max_dirty: u32,
+}
+
+/// Per-function register stack profile
+///
+/// Functions are keyed by entry address; `None` is the code running outside
+/// any call seen by the profiler.
+#[derive(Debug, Clone, Default)]
+pub struct RseProfiler {
+    /// Entry addresses of the active calls, innermost last
+    stack: Vec<u64>,
+    /// Activity by function
+    functions: BTreeMap<Option<u64>, FunctionProfile>,
+    /// RSE spill count already attributed
+    spills: u64,
+    /// RSE fill count already attributed
+    fills: u64,
+}
+
+impl RseProfiler {
+    /// Create an empty profile
+    ///
+    /// Activity before the profiler was installed is not counted.
+    pub fn new(rse: &RSE) -> Self {
+        Self {
+            spills: rse.spill_count(),
+            fills: rse.fill_count(),
+            ..Self::default()
+        }
+    }
+
+    /// Profile of the function whose frame is current
+    fn current(&mut self) -> &mut FunctionProfile {
+        let key = self.stack.last().copied();
+        self.functions.entry(key).or_default()
+    }
+
+    /// Charge RSE activity since the last event to the current function
+    pub(crate) fn sync(&mut self, rse: &RSE) {
+        let spills = rse.spill_count().saturating_sub(self.spills);
+        let fills = rse.fill_count().saturating_sub(self.fills);
+        self.spills = rse.spill_count();
+        self.fills = rse.fill_count();
+
+        let current = self.current();
+        current.spills += spills;
+        current.fills += fills;
+        current.max_dirty = current.max_dirty.max(rse.dirty_count());
+    }
+
+    /// A call to `target` is about to push the caller's frame
+    pub(crate) fn enter(&mut self, rse: &RSE, target: u64) {
+        self.sync(rse);
+        self.stack.push(target);
+        self.current().calls += 1;
+    }
+
+    /// A return is about to restore the caller's frame
+    pub(crate) fn leave(&mut self, rse: &RSE) {
+        self.sync(rse);
+        self.stack.pop();
+    }
+
+    /// The current function allocated a frame of `sof` registers
+    pub(crate) fn alloc(&mut self, sof: u32) {
+        *self.current().alloc_sizes.entry(sof).or_default() += 1;
+    }
+
+    /// Profile of a function by entry address
+    pub fn function(&self, entry: Option<u64>) -> Option<&FunctionProfile> {
+        self.functions.get(&entry)
+    }
+
+    /// All profiled functions by entry address
+    pub fn functions(&self) -> impl Iterator<Item = (Option<u64>, &FunctionProfile)> {
+        self.functions
+            .iter()
+            .map(|(&entry, profile)| (entry, profile))
+    }
+
+    /// Format a report, busiest functions first
+    ///
+    /// Entry addresses are shown as symbols where the table has one.
+    pub fn report(&self, symbols: &SymbolTable) -> String {
+        let mut rows: Vec<_> = self.functions().collect();
+        rows.sort_by_key(|(_, profile)| std::cmp::Reverse(profile.spills + profile.fills));
+
+        let mut out = String::new();
+        writeln!(
+            out,
+            "{:<32} {:>8} {:>8} {:>8} {:>9}  allocs (sof x count)",
+            "function", "calls", "spills", "fills", "max dirty"
+        )
+        .unwrap();
+        for (entry, profile) in rows {
+            let name = match entry {
+                None => "<outside calls>".to_string(),
+                Some(address) => match symbols.lookup(address) {
+                    Some((symbol, 0)) => symbol.name.clone(),
+                    Some((symbol, offset)) => format!("{}+{:#x}", symbol.name, offset),
+                    None => format!("{:#x}", address),
+                },
+            };
+            let allocs: Vec<String> = profile
+                .alloc_sizes
+                .iter()
+                .map(|(sof, count)| format!("{}x{}", sof, count))
+                .collect();
+            writeln!(
+                out,
+                "{:<32} {:>8} {:>8} {:>8} {:>9}  {}",
+                name,
+                profile.calls,
+                profile.spills,
+                profile.fills,
+                profile.max_dirty,
+                allocs.join(" ")
+            )
+            .unwrap();
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::Cpu;
+    use crate::memory::{Memory, Permissions};
+
+    const MAIN: u64 = 0x4000;
+    const RECURSE: u64 = 0x5000;
+
+    #[test]
+    fn test_recursion_profile() {
+        const DEPTH: usize = 10;
+
+        let mut cpu = Cpu::default();
+        let mut memory = Memory::new();
+        memory
+            .map(0x10000, 0x10000, Permissions::ReadWrite)
+            .unwrap();
+        cpu.rse.set_bspstore(0x10000).unwrap();
+        cpu.set_rse_profiling(true);
+
+        // alloc loc0 = ar.pfs in each frame, mov ar.pfs = loc0 before br.ret
+        cpu.handle_call(&mut memory, MAIN).unwrap();
+        cpu.alloc_frame(&mut memory, 4, 3, 0).unwrap();
+        cpu.set_gr(32, cpu.pfs).unwrap();
+        for _ in 0..DEPTH {
+            cpu.handle_call(&mut memory, RECURSE).unwrap();
+            cpu.alloc_frame(&mut memory, 24, 22, 0).unwrap();
+            cpu.set_gr(32, cpu.pfs).unwrap();
+        }
+        for _ in 0..=DEPTH {
+            cpu.pfs = cpu.gr[32];
+            cpu.handle_return(&mut memory).unwrap();
+        }
+
+        let profiler = cpu.rse_profiler.as_ref().unwrap();
+        let recurse = profiler.function(Some(RECURSE)).unwrap();
+        assert_eq!(recurse.calls, DEPTH as u64);
+        assert_eq!(recurse.alloc_sizes.get(&24), Some(&(DEPTH as u64)));
+        assert!(recurse.spills > 0);
+        assert!(recurse.fills > 0);
+        assert!(recurse.max_dirty > 64);
+
+        let main = profiler.function(Some(MAIN)).unwrap();
+        assert_eq!(main.calls, 1);
+        assert_eq!(main.alloc_sizes.get(&4), Some(&1));
+
+        // Every spilled register is filled again on the way back
+        let (spills, fills) = profiler
+            .functions()
+            .fold((0, 0), |(s, f), (_, p)| (s + p.spills, f + p.fills));
+        assert_eq!(spills, cpu.rse.spill_count());
+        assert_eq!(fills, spills);
+
+        let mut symbols = SymbolTable::new();
+        symbols.add("main", MAIN, 0x100);
+        let report = profiler.report(&symbols);
+        assert!(report.contains("main"));
+        assert!(report.contains("0x5000"));
+        assert!(report.contains("24x10"));
+    }
+}
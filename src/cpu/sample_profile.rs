@@ -0,0 +1,356 @@
+//! Sampling profiler of guest execution
+//!
+//! Every `interval` retired instructions the profiler records the address
+//! of the bundle executing and, if asked to, the guest call stack leading
+//! to it. IA-64 code keeps its return links in branch registers and saves
+//! them wherever its unwind information says, so rather than unwinding the
+//! memory stack the profiler follows calls and returns as they execute,
+//! like the [register stack profiler](super::rse_profile): the stack is the
+//! entry point of each call still active, outermost first.
+//!
+//! Samples export as folded stacks, one `frame;frame;leaf count` line per
+//! distinct stack as `flamegraph.pl` and inferno read them, or as an
+//! uncompressed pprof protobuf profile for `go tool pprof`. Frames are
+//! named by the function containing them where the symbol table has one.
+
+use crate::debugger::SymbolTable;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write;
+
+/// Instructions between samples unless configured otherwise
+pub const DEFAULT_SAMPLE_INTERVAL: u64 = 1000;
+
+/// Sampled guest instruction pointers, with their call stacks
+#[derive(Debug, Clone)]
+pub struct SampleProfiler {
+    /// Instructions between samples
+    interval: u64,
+    /// Whether samples record the call stack
+    stacks: bool,
+    /// Instructions retired since the last sample
+    elapsed: u64,
+    /// Entry addresses of the active calls, innermost last
+    calls: Vec<u64>,
+    /// The active calls when the current bundle started, if it called or
+    /// returned
+    bundle_calls: Option<Vec<u64>>,
+    /// Sample counts by stack, outermost frame first and the sampled bundle
+    /// address last
+    samples: BTreeMap<Vec<u64>, u64>,
+}
+
+impl SampleProfiler {
+    /// Profiler sampling every `interval` instructions, recording call
+    /// stacks if `stacks` is set
+    ///
+    /// Calls made before the profiler was installed are not on its stacks.
+    pub fn new(interval: u64, stacks: bool) -> Self {
+        Self {
+            interval: interval.max(1),
+            stacks,
+            elapsed: 0,
+            calls: Vec::new(),
+            bundle_calls: None,
+            samples: BTreeMap::new(),
+        }
+    }
+
+    /// Instructions between samples
+    pub fn interval(&self) -> u64 {
+        self.interval
+    }
+
+    /// A call to `target` is about to execute
+    pub(crate) fn enter(&mut self, target: u64) {
+        if self.stacks {
+            self.bundle_calls.get_or_insert_with(|| self.calls.clone());
+            self.calls.push(target);
+        }
+    }
+
+    /// A return is about to execute
+    pub(crate) fn leave(&mut self) {
+        if self.stacks {
+            self.bundle_calls.get_or_insert_with(|| self.calls.clone());
+            self.calls.pop();
+        }
+    }
+
+    /// The bundle at `ip` retired `count` instructions
+    ///
+    /// A sample of a bundle that called or returned is charged to the
+    /// function the bundle belongs to, not the one it left for.
+    pub(crate) fn retire(&mut self, count: u64, ip: u64) {
+        let bundle_calls = self.bundle_calls.take();
+        self.elapsed += count;
+        let taken = self.elapsed / self.interval;
+        if taken == 0 {
+            return;
+        }
+        self.elapsed %= self.interval;
+        let mut stack = bundle_calls.unwrap_or_else(|| self.calls.clone());
+        stack.push(ip);
+        *self.samples.entry(stack).or_default() += taken;
+    }
+
+    /// Sample counts by stack, outermost frame first and the sampled bundle
+    /// address last
+    pub fn samples(&self) -> impl Iterator<Item = (&[u64], u64)> {
+        self.samples
+            .iter()
+            .map(|(stack, &count)| (stack.as_slice(), count))
+    }
+
+    /// Number of samples taken
+    pub fn total(&self) -> u64 {
+        self.samples.values().sum()
+    }
+
+    /// Samples as folded stacks, one line per distinct stack of function
+    /// names, in name order
+    pub fn folded(&self, symbols: &SymbolTable) -> String {
+        let mut stacks: BTreeMap<String, u64> = BTreeMap::new();
+        for (stack, count) in self.samples() {
+            let names: Vec<String> = stack.iter().map(|&addr| frame(symbols, addr)).collect();
+            *stacks.entry(names.join(";")).or_default() += count;
+        }
+        let mut out = String::new();
+        for (stack, count) in stacks {
+            writeln!(out, "{} {}", stack, count).unwrap();
+        }
+        out
+    }
+
+    /// Samples as a pprof `Profile` message, uncompressed
+    pub fn pprof(&self, symbols: &SymbolTable) -> Vec<u8> {
+        let mut strings = StringTable::default();
+        let mut functions: HashMap<String, u64> = HashMap::new();
+        let mut locations: BTreeMap<u64, u64> = BTreeMap::new();
+        let mut out = Vec::new();
+
+        let samples = strings.index("samples");
+        let count = strings.index("count");
+        let instructions = strings.index("instructions");
+        message(&mut out, 1, &value_type(samples, count));
+
+        for (stack, count) in self.samples() {
+            let mut ids = Vec::new();
+            // pprof lists the leaf first
+            for &addr in stack.iter().rev() {
+                let next = locations.len() as u64 + 1;
+                ids.push(*locations.entry(addr).or_insert(next));
+            }
+            let mut sample = Vec::new();
+            packed(&mut sample, 1, &ids);
+            packed(&mut sample, 2, &[count]);
+            message(&mut out, 2, &sample);
+        }
+
+        let mut function_ids = Vec::new();
+        for (&addr, &id) in &locations {
+            let name = frame(symbols, addr);
+            let next = functions.len() as u64 + 1;
+            let function = *functions.entry(name.clone()).or_insert_with(|| {
+                function_ids.push((next, name));
+                next
+            });
+            let mut line = Vec::new();
+            uint(&mut line, 1, function);
+            let mut location = Vec::new();
+            uint(&mut location, 1, id);
+            uint(&mut location, 3, addr);
+            message(&mut location, 4, &line);
+            message(&mut out, 4, &location);
+        }
+        for (id, name) in function_ids {
+            let name = strings.index(&name);
+            let mut function = Vec::new();
+            uint(&mut function, 1, id);
+            uint(&mut function, 2, name);
+            uint(&mut function, 3, name);
+            message(&mut out, 5, &function);
+        }
+
+        for string in &strings.strings {
+            message(&mut out, 6, string.as_bytes());
+        }
+        message(&mut out, 11, &value_type(instructions, count));
+        uint(&mut out, 12, self.interval);
+        out
+    }
+}
+
+/// Name of the function containing `addr`, or the address in hex
+fn frame(symbols: &SymbolTable, addr: u64) -> String {
+    match symbols.lookup(addr) {
+        Some((symbol, _)) => symbol.name.clone(),
+        None => format!("{:#x}", addr),
+    }
+}
+
+/// pprof string table, which starts with the empty string
+struct StringTable {
+    /// Strings by index
+    strings: Vec<String>,
+    /// Indices by string
+    indices: HashMap<String, u64>,
+}
+
+impl Default for StringTable {
+    fn default() -> Self {
+        Self {
+            strings: vec![String::new()],
+            indices: HashMap::from([(String::new(), 0)]),
+        }
+    }
+}
+
+impl StringTable {
+    /// Index of `text`, added if new
+    fn index(&mut self, text: &str) -> u64 {
+        if let Some(&index) = self.indices.get(text) {
+            return index;
+        }
+        let index = self.strings.len() as u64;
+        self.strings.push(text.to_string());
+        self.indices.insert(text.to_string(), index);
+        index
+    }
+}
+
+/// Append `value` as a protobuf varint
+fn varint(out: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        out.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+/// Append integer field `field`, left out when 0 as proto3 does
+fn uint(out: &mut Vec<u8>, field: u64, value: u64) {
+    if value != 0 {
+        varint(out, field << 3);
+        varint(out, value);
+    }
+}
+
+/// Append length-delimited field `field`
+fn message(out: &mut Vec<u8>, field: u64, data: &[u8]) {
+    varint(out, field << 3 | 2);
+    varint(out, data.len() as u64);
+    out.extend_from_slice(data);
+}
+
+/// Append repeated integer field `field`, packed
+fn packed(out: &mut Vec<u8>, field: u64, values: &[u64]) {
+    let mut data = Vec::new();
+    for &value in values {
+        varint(&mut data, value);
+    }
+    message(out, field, &data);
+}
+
+/// `ValueType` message with type and unit string indices
+fn value_type(kind: u64, unit: u64) -> Vec<u8> {
+    let mut out = Vec::new();
+    uint(&mut out, 1, kind);
+    uint(&mut out, 2, unit);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAIN: u64 = 0x4000;
+    const LEAF: u64 = 0x5000;
+
+    fn symbols() -> SymbolTable {
+        let mut symbols = SymbolTable::default();
+        symbols.add("main", MAIN, 0x100);
+        symbols.add("leaf", LEAF, 0x100);
+        symbols
+    }
+
+    /// Profile of main calling leaf, which runs twice as long, and going on
+    /// to unknown code
+    fn profile(stacks: bool) -> SampleProfiler {
+        let mut profiler = SampleProfiler::new(10, stacks);
+        profiler.enter(MAIN);
+        for _ in 0..10 {
+            profiler.retire(3, MAIN + 0x10);
+        }
+        profiler.enter(LEAF);
+        for _ in 0..20 {
+            profiler.retire(3, LEAF + 0x20);
+        }
+        // The return bundle is still part of leaf
+        profiler.leave();
+        profiler.retire(10, LEAF + 0x30);
+        profiler.retire(10, 0x9000);
+        profiler
+    }
+
+    #[test]
+    fn test_sampling() {
+        let profiler = profile(true);
+        // 110 instructions at one sample per 10
+        assert_eq!(profiler.total(), 11);
+        let samples: Vec<_> = profiler.samples().collect();
+        assert_eq!(
+            samples,
+            [
+                (&[MAIN, MAIN + 0x10][..], 3),
+                (&[MAIN, LEAF, LEAF + 0x20][..], 6),
+                (&[MAIN, LEAF, LEAF + 0x30][..], 1),
+                (&[MAIN, 0x9000][..], 1),
+            ]
+        );
+
+        // A large bundle count covers several intervals at once
+        let mut profiler = SampleProfiler::new(2, false);
+        profiler.retire(7, MAIN);
+        profiler.retire(1, MAIN);
+        assert_eq!(profiler.total(), 4);
+    }
+
+    #[test]
+    fn test_folded() {
+        assert_eq!(
+            profile(true).folded(&symbols()),
+            "main;0x9000 1\nmain;leaf;leaf 7\nmain;main 3\n"
+        );
+        // Without stacks, samples in the same function merge
+        assert_eq!(
+            profile(false).folded(&symbols()),
+            "0x9000 1\nleaf 7\nmain 3\n"
+        );
+    }
+
+    #[test]
+    fn test_pprof() {
+        let data = profile(true).pprof(&symbols());
+        // sample_type { type: "samples", unit: "count" }
+        assert_eq!(&data[..6], &[0x0A, 0x04, 0x08, 0x01, 0x10, 0x02]);
+        // The first sample's stack is leaf first, main+0x10 then main
+        assert_eq!(
+            &data[6..15],
+            &[0x12, 0x07, 0x0A, 0x02, 0x01, 0x02, 0x12, 0x01, 0x03]
+        );
+        for name in ["samples", "instructions", "main", "leaf", "0x9000"] {
+            assert!(data
+                .windows(name.len())
+                .any(|window| window == name.as_bytes()));
+        }
+        // ends with period_type and period 10
+        assert_eq!(&data[data.len() - 2..], &[0x60, 10]);
+    }
+
+    #[test]
+    fn test_varint() {
+        let mut out = Vec::new();
+        varint(&mut out, 300);
+        assert_eq!(out, [0xAC, 0x02]);
+    }
+}
@@ -0,0 +1,197 @@
+//! Standard streams of the guest process
+//!
+//! Without a [`Stdio`] the guest's descriptors 0 to 2 go nowhere: writes to
+//! them succeed and reads find end of file, which keeps library users and
+//! tests quiet. A [`Stdio`] connects them to the host's own streams, or to
+//! any reader and writers, and
+//! [`SyscallManager::set_stdio`](crate::cpu::syscall::SyscallManager::set_stdio)
+//! routes `read`, `write`, `readv` and `writev` on them through it. Other
+//! descriptors are left to the handlers already registered, such as those
+//! of a [host directory](crate::cpu::hostfs).
+//!
+//! Like open host files, the streams are not saved in snapshots.
+
+use super::hostfs::MAX_TRANSFER;
+use super::syscall::{EBADF, EIO};
+use std::fmt;
+use std::io::{self, Read, Write};
+
+/// Guest descriptor of standard input
+pub const STDIN_FD: u64 = 0;
+
+/// Guest descriptor of standard output
+pub const STDOUT_FD: u64 = 1;
+
+/// Guest descriptor of standard error
+pub const STDERR_FD: u64 = 2;
+
+/// Host ends of the guest's standard streams
+pub struct Stdio {
+    /// Where standard input comes from
+    input: Box<dyn Read + Send>,
+    /// Where standard output goes
+    output: Box<dyn Write + Send>,
+    /// Where standard error goes
+    error: Box<dyn Write + Send>,
+}
+
+impl fmt::Debug for Stdio {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Stdio").finish_non_exhaustive()
+    }
+}
+
+impl Stdio {
+    /// Streams reading `input` and writing `output` and `error`
+    pub fn new(
+        input: impl Read + Send + 'static,
+        output: impl Write + Send + 'static,
+        error: impl Write + Send + 'static,
+    ) -> Self {
+        Self {
+            input: Box::new(input),
+            output: Box::new(output),
+            error: Box::new(error),
+        }
+    }
+
+    /// The host process's own standard streams
+    pub fn host() -> Self {
+        Self::new(io::stdin(), io::stdout(), io::stderr())
+    }
+
+    /// Whether `fd` is one of the standard streams
+    pub fn is_standard(fd: u64) -> bool {
+        fd <= STDERR_FD
+    }
+
+    /// Read up to `count` bytes of standard input, fewer at end of input
+    ///
+    /// Fails with an errno, EBADF for the output streams.
+    pub fn read(&mut self, fd: u64, count: u64) -> Result<Vec<u8>, u64> {
+        if fd != STDIN_FD {
+            return Err(EBADF);
+        }
+        let mut data = vec![0; count.min(MAX_TRANSFER) as usize];
+        let len = self.input.read(&mut data).map_err(|_| EIO)?;
+        data.truncate(len);
+        Ok(data)
+    }
+
+    /// Write `data` to standard output or error, returning the bytes
+    /// written
+    ///
+    /// Each write is flushed, so output interleaves with the host's in the
+    /// order the guest made it. Fails with an errno, EBADF for standard
+    /// input.
+    pub fn write(&mut self, fd: u64, data: &[u8]) -> Result<u64, u64> {
+        let stream = match fd {
+            STDOUT_FD => &mut self.output,
+            STDERR_FD => &mut self.error,
+            _ => return Err(EBADF),
+        };
+        stream
+            .write_all(data)
+            .and_then(|()| stream.flush())
+            .map_err(|_| EIO)?;
+        Ok(data.len() as u64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::syscall::{SyscallNumber, EFAULT, SYSCALL_PARAM_REGS};
+    use crate::cpu::Cpu;
+    use crate::memory::Permissions;
+    use std::io::Cursor;
+    use std::sync::{Arc, Mutex};
+
+    /// Writer whose output the test can read back
+    #[derive(Clone, Default)]
+    struct Shared(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_streams() {
+        let mut cpu = Cpu::new();
+        cpu.memory
+            .map(0x1000, 0x1000, Permissions::ReadWrite)
+            .unwrap();
+        let (output, error) = (Shared::default(), Shared::default());
+        let stdio = Stdio::new(Cursor::new(b"typed\n"), output.clone(), error.clone());
+        cpu.syscall_mgr.set_stdio(stdio);
+        let call = |cpu: &mut Cpu, number: SyscallNumber, params: &[u64]| {
+            for (i, &param) in params.iter().enumerate() {
+                cpu.gr[SYSCALL_PARAM_REGS[i]] = param;
+            }
+            let context = cpu.do_syscall(number as u64).unwrap();
+            (context.returns[0], context.error)
+        };
+
+        // Writes copy guest memory out, reads copy input in
+        cpu.memory.write_bytes(0x1000, b"hello, world").unwrap();
+        assert_eq!(
+            call(&mut cpu, SyscallNumber::Write, &[1, 0x1000, 5]),
+            (5, None)
+        );
+        assert_eq!(
+            call(&mut cpu, SyscallNumber::Read, &[0, 0x1100, 3]),
+            (3, None)
+        );
+        assert_eq!(cpu.memory.peek_c_string(0x1100, 8).unwrap(), "typ");
+
+        // writev gathers its buffers, readv fills them in turn
+        for (i, (base, len)) in [(0x1005, 2), (0x1007, 5)].into_iter().enumerate() {
+            cpu.memory.write_u64(0x1200 + 16 * i as u64, base).unwrap();
+            cpu.memory.write_u64(0x1208 + 16 * i as u64, len).unwrap();
+        }
+        assert_eq!(
+            call(&mut cpu, SyscallNumber::Writev, &[2, 0x1200, 2]),
+            (7, None)
+        );
+        for (i, base) in [0x1300, 0x1400].into_iter().enumerate() {
+            cpu.memory.write_u64(0x1200 + 16 * i as u64, base).unwrap();
+            cpu.memory.write_u64(0x1208 + 16 * i as u64, 2).unwrap();
+        }
+        assert_eq!(
+            call(&mut cpu, SyscallNumber::Readv, &[0, 0x1200, 2]),
+            (3, None)
+        );
+        assert_eq!(cpu.memory.peek_c_string(0x1300, 8).unwrap(), "ed");
+        assert_eq!(cpu.memory.peek_c_string(0x1400, 8).unwrap(), "\n");
+        assert_eq!(
+            call(&mut cpu, SyscallNumber::Read, &[0, 0x1100, 3]),
+            (0, None)
+        );
+        assert_eq!(*output.0.lock().unwrap(), b"hello");
+        assert_eq!(*error.0.lock().unwrap(), b", world");
+
+        // Each stream goes one way, and nothing else is open
+        assert_eq!(
+            call(&mut cpu, SyscallNumber::Write, &[0, 0x1000, 1]).1,
+            Some(EBADF)
+        );
+        assert_eq!(
+            call(&mut cpu, SyscallNumber::Read, &[1, 0x1100, 1]).1,
+            Some(EBADF)
+        );
+        assert_eq!(
+            call(&mut cpu, SyscallNumber::Write, &[5, 0x1000, 1]).1,
+            Some(EBADF)
+        );
+        assert_eq!(
+            call(&mut cpu, SyscallNumber::Write, &[1, 0x1FFF, 2]).1,
+            Some(EFAULT)
+        );
+    }
+}
@@ -0,0 +1,437 @@
+//! System call tracing
+//!
+//! This module implements an strace-like log of the guest's system calls.
+//! Each call is printed with its name, its arguments decoded against guest
+//! memory (paths, buffers, flag names), its return value and errno:
+//!
+//! ```text
+//! open("/etc/passwd", O_RDONLY|O_CLOEXEC, 0) = -1 ENOENT
+//! write(1, "hello\n", 6) = 6
+//! ```
+//!
+//! Output goes to standard error, any writer such as a file, or with the
+//! `tracing` feature to the `tracing` crate under the `strace` target.
+
+use super::syscall::{SyscallContext, SyscallNumber};
+use crate::memory::Memory;
+use crate::EmulatorError;
+use std::collections::HashSet;
+use std::fmt::{self, Write as _};
+use std::io::{self, Write};
+
+/// Longest path argument read from guest memory
+const MAX_PATH_LEN: usize = 4096;
+
+/// Buffer bytes shown before the contents are elided
+const MAX_BUFFER_SHOWN: usize = 32;
+
+/// How a system call argument is decoded
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Arg {
+    /// Signed decimal
+    Int,
+    /// File descriptor
+    Fd,
+    /// Pointer, shown in hex
+    Ptr,
+    /// Hexadecimal value
+    Hex,
+    /// NUL-terminated string
+    Path,
+    /// Buffer whose length is the given argument
+    Buffer(usize),
+    /// open(2) flags
+    OpenFlags,
+    /// File mode, shown in octal
+    Mode,
+    /// mmap(2) protection
+    Prot,
+    /// mmap(2) flags
+    MapFlags,
+}
+
+/// Argument decoding for a system call
+fn signature(number: SyscallNumber) -> &'static [Arg] {
+    use Arg::*;
+    match number {
+        SyscallNumber::Exit | SyscallNumber::ExitGroup | SyscallNumber::SetUid => &[Int],
+        SyscallNumber::Fork
+        | SyscallNumber::GetPid
+        | SyscallNumber::GetPpid
+        | SyscallNumber::GetTid
+        | SyscallNumber::GetUid => &[],
+        SyscallNumber::Read | SyscallNumber::Recv => &[Fd, Ptr, Int],
+        SyscallNumber::Readv | SyscallNumber::Writev => &[Fd, Ptr, Int],
+        SyscallNumber::Write | SyscallNumber::Send => &[Fd, Buffer(2), Int],
+        SyscallNumber::Open => &[Path, OpenFlags, Mode],
+        SyscallNumber::Openat => &[Fd, Path, OpenFlags, Mode],
+        SyscallNumber::Close => &[Fd],
+        SyscallNumber::Lseek => &[Fd, Int, Int],
+        SyscallNumber::Fstat => &[Fd, Ptr],
+        SyscallNumber::WaitPid => &[Int, Ptr, Hex],
+        SyscallNumber::Execve => &[Path, Ptr, Ptr],
+        SyscallNumber::ChDir | SyscallNumber::RmDir | SyscallNumber::Unmount => &[Path],
+        SyscallNumber::Time
+        | SyscallNumber::Break
+        | SyscallNumber::SysInfo
+        | SyscallNumber::Uname
+        | SyscallNumber::SetTidAddress => &[Ptr],
+        SyscallNumber::MkDir => &[Path, Mode],
+        SyscallNumber::Mount => &[Path, Path, Path, Hex, Ptr],
+        SyscallNumber::GetTimeOfDay => &[Ptr, Ptr],
+        SyscallNumber::Mmap | SyscallNumber::Mmap2 => &[Ptr, Int, Prot, MapFlags, Fd, Hex],
+        SyscallNumber::Munmap => &[Ptr, Int],
+        SyscallNumber::Truncate => &[Path, Int],
+        SyscallNumber::Ftruncate => &[Fd, Int],
+        SyscallNumber::Socket => &[Int, Int, Int],
+        SyscallNumber::Connect => &[Fd, Ptr, Int],
+        SyscallNumber::Accept => &[Fd, Ptr, Ptr],
+        SyscallNumber::Shutdown => &[Fd, Int],
+        SyscallNumber::RtSigprocmask => &[Int, Ptr, Ptr, Int],
+        SyscallNumber::Futex => &[Ptr, Int, Int, Ptr],
+    }
+}
+
+/// open(2) flag bits and names, after the access mode
+const OPEN_FLAGS: [(u64, &str); 9] = [
+    (0o100, "O_CREAT"),
+    (0o200, "O_EXCL"),
+    (0o400, "O_NOCTTY"),
+    (0o1000, "O_TRUNC"),
+    (0o2000, "O_APPEND"),
+    (0o4000, "O_NONBLOCK"),
+    (0o200000, "O_DIRECTORY"),
+    (0o400000, "O_NOFOLLOW"),
+    (0o2000000, "O_CLOEXEC"),
+];
+
+/// mmap(2) protection bits and names
+const PROT_FLAGS: [(u64, &str); 3] = [(1, "PROT_READ"), (2, "PROT_WRITE"), (4, "PROT_EXEC")];
+
+/// mmap(2) flag bits and names
+const MAP_FLAGS: [(u64, &str); 5] = [
+    (0x01, "MAP_SHARED"),
+    (0x02, "MAP_PRIVATE"),
+    (0x10, "MAP_FIXED"),
+    (0x20, "MAP_ANONYMOUS"),
+    (0x100, "MAP_GROWSDOWN"),
+];
+
+/// Symbolic name of an errno value
+fn errno_name(errno: u64) -> Option<&'static str> {
+    Some(match errno {
+        1 => "EPERM",
+        2 => "ENOENT",
+        3 => "ESRCH",
+        4 => "EINTR",
+        5 => "EIO",
+        9 => "EBADF",
+        10 => "ECHILD",
+        11 => "EAGAIN",
+        12 => "ENOMEM",
+        13 => "EACCES",
+        14 => "EFAULT",
+        16 => "EBUSY",
+        17 => "EEXIST",
+        20 => "ENOTDIR",
+        21 => "EISDIR",
+        22 => "EINVAL",
+        24 => "EMFILE",
+        28 => "ENOSPC",
+        32 => "EPIPE",
+        38 => "ENOSYS",
+        _ => return None,
+    })
+}
+
+/// Write `value` as names from `flags` joined by `|`, with leftover bits in hex
+fn write_flags(out: &mut String, mut value: u64, flags: &[(u64, &str)], zero: &str) {
+    let mut names: Vec<String> = Vec::new();
+    for &(bit, name) in flags {
+        if value & bit != 0 {
+            names.push(name.to_string());
+            value &= !bit;
+        }
+    }
+    if value != 0 {
+        names.push(format!("{:#x}", value));
+    }
+    if names.is_empty() {
+        out.push_str(zero);
+    } else {
+        out.push_str(&names.join("|"));
+    }
+}
+
+/// Write bytes as an escaped C string literal
+fn write_escaped(out: &mut String, bytes: &[u8]) {
+    out.push('"');
+    for &byte in bytes {
+        match byte {
+            b'\n' => out.push_str("\\n"),
+            b'\t' => out.push_str("\\t"),
+            b'\r' => out.push_str("\\r"),
+            b'"' => out.push_str("\\\""),
+            b'\\' => out.push_str("\\\\"),
+            0x20..=0x7E => out.push(byte as char),
+            _ => write!(out, "\\x{:02x}", byte).unwrap(),
+        }
+    }
+    out.push('"');
+}
+
+/// Decode one argument
+fn write_arg(out: &mut String, arg: Arg, value: u64, params: &[u64; 8], memory: &Memory) {
+    match arg {
+        Arg::Int | Arg::Fd => write!(out, "{}", value as i64).unwrap(),
+        Arg::Ptr if value == 0 => out.push_str("NULL"),
+        Arg::Ptr | Arg::Hex => write!(out, "{:#x}", value).unwrap(),
+        Arg::Path => match memory.peek_c_string(value, MAX_PATH_LEN) {
+            Some(path) => write_escaped(out, path.as_bytes()),
+            None => write!(out, "{:#x}", value).unwrap(),
+        },
+        Arg::Buffer(len_arg) => {
+            let len = params[len_arg].min(MAX_BUFFER_SHOWN as u64) as usize;
+            let mut bytes = vec![0u8; len];
+            if memory.peek_bytes(value, &mut bytes).is_err() {
+                write!(out, "{:#x}", value).unwrap();
+                return;
+            }
+            write_escaped(out, &bytes);
+            if params[len_arg] > len as u64 {
+                out.push_str("...");
+            }
+        }
+        Arg::OpenFlags => {
+            out.push_str(match value & 0o3 {
+                0 => "O_RDONLY",
+                1 => "O_WRONLY",
+                2 => "O_RDWR",
+                _ => "O_ACCMODE",
+            });
+            if value & !0o3 != 0 {
+                out.push('|');
+                write_flags(out, value & !0o3, &OPEN_FLAGS, "");
+            }
+        }
+        Arg::Mode => write!(out, "{:#o}", value).unwrap(),
+        Arg::Prot => write_flags(out, value, &PROT_FLAGS, "PROT_NONE"),
+        Arg::MapFlags => write_flags(out, value, &MAP_FLAGS, "0"),
+    }
+}
+
+/// Format a system call and its decoded arguments, e.g. `write(1, "hi", 2)`
+///
+/// Unknown system call numbers are shown with all argument registers.
+pub fn format_call(number: u64, params: &[u64; 8], memory: &Memory) -> String {
+    let mut out = String::new();
+    let Ok(syscall) = SyscallNumber::try_from(number) else {
+        write!(out, "syscall_{}(", number).unwrap();
+        let args: Vec<String> = params.iter().map(|p| format!("{:#x}", p)).collect();
+        out.push_str(&args.join(", "));
+        out.push(')');
+        return out;
+    };
+
+    out.push_str(syscall.name());
+    out.push('(');
+    for (i, &arg) in signature(syscall).iter().enumerate() {
+        if i > 0 {
+            out.push_str(", ");
+        }
+        write_arg(&mut out, arg, params[i], params, memory);
+    }
+    out.push(')');
+    out
+}
+
+/// Format the result of a completed system call, e.g. `= -1 ENOENT`
+pub fn format_result(context: &SyscallContext) -> String {
+    match context.error {
+        Some(errno) => match errno_name(errno) {
+            Some(name) => format!("= -1 {}", name),
+            None => format!("= -1 errno {}", errno),
+        },
+        None => match context.number {
+            SyscallNumber::Mmap | SyscallNumber::Mmap2 | SyscallNumber::Break => {
+                format!("= {:#x}", context.returns[0])
+            }
+            _ => format!("= {}", context.returns[0] as i64),
+        },
+    }
+}
+
+/// Destination of the trace
+pub enum StraceOutput {
+    /// Standard error
+    Stderr,
+    /// Any writer, e.g. a file
+    Writer(Box<dyn Write + Send>),
+    /// `tracing` events under the `strace` target
+    #[cfg(feature = "tracing")]
+    Tracing,
+}
+
+impl fmt::Debug for StraceOutput {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StraceOutput::Stderr => write!(f, "Stderr"),
+            StraceOutput::Writer(_) => write!(f, "Writer(<writer>)"),
+            #[cfg(feature = "tracing")]
+            StraceOutput::Tracing => write!(f, "Tracing"),
+        }
+    }
+}
+
+/// strace-like log of the guest's system calls
+#[derive(Debug)]
+pub struct Strace {
+    /// Destination of the trace
+    output: StraceOutput,
+    /// System calls to log; all of them if `None`
+    filter: Option<HashSet<SyscallNumber>>,
+}
+
+impl Strace {
+    /// Log every system call to `output`
+    pub fn new(output: StraceOutput) -> Self {
+        Self {
+            output,
+            filter: None,
+        }
+    }
+
+    /// Log only the named system calls, e.g. `["open", "write"]`
+    pub fn only<S: AsRef<str>>(
+        mut self,
+        names: impl IntoIterator<Item = S>,
+    ) -> Result<Self, EmulatorError> {
+        let filter = names
+            .into_iter()
+            .map(|name| {
+                SyscallNumber::from_name(name.as_ref()).ok_or_else(|| {
+                    EmulatorError::ExecutionError(format!(
+                        "Unknown system call name: {}",
+                        name.as_ref()
+                    ))
+                })
+            })
+            .collect::<Result<_, _>>()?;
+        self.filter = Some(filter);
+        Ok(self)
+    }
+
+    /// Whether a system call number passes the filter
+    ///
+    /// Unknown numbers are only logged without a filter.
+    pub fn traces(&self, number: u64) -> bool {
+        match (&self.filter, SyscallNumber::try_from(number)) {
+            (None, _) => true,
+            (Some(filter), Ok(syscall)) => filter.contains(&syscall),
+            (Some(_), Err(_)) => false,
+        }
+    }
+
+    /// Log a system call and its outcome
+    pub(crate) fn record(
+        &mut self,
+        number: u64,
+        params: &[u64; 8],
+        outcome: &Result<SyscallContext, EmulatorError>,
+        memory: &Memory,
+    ) -> io::Result<()> {
+        if !self.traces(number) {
+            return Ok(());
+        }
+
+        let call = format_call(number, params, memory);
+        let result = match outcome {
+            Ok(context) => format_result(context),
+            Err(e) => format!("= ? ({})", e),
+        };
+        match &mut self.output {
+            StraceOutput::Stderr => writeln!(io::stderr(), "{} {}", call, result),
+            StraceOutput::Writer(writer) => writeln!(writer, "{} {}", call, result),
+            #[cfg(feature = "tracing")]
+            StraceOutput::Tracing => {
+                tracing::info!(target: "strace", number, "{} {}", call, result);
+                Ok(())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory::Permissions;
+
+    fn context(number: SyscallNumber, ret: u64, error: Option<u64>) -> SyscallContext {
+        let mut context = SyscallContext::new(number);
+        context.returns[0] = ret;
+        context.error = error;
+        context
+    }
+
+    #[test]
+    fn test_format() {
+        let mut memory = Memory::new();
+        memory
+            .load_image(0x1000, 0x1000, b"/etc/passwd\0", Permissions::Read)
+            .unwrap();
+        memory
+            .load_image(0x2000, 0x1000, &[b'x'; 40], Permissions::Read)
+            .unwrap();
+
+        let mut params = [0; 8];
+        params[..3].copy_from_slice(&[0x1000, 0o2000001 | 0o100, 0o644]);
+        assert_eq!(
+            format_call(SyscallNumber::Open as u64, &params, &memory),
+            "open(\"/etc/passwd\", O_WRONLY|O_CREAT|O_CLOEXEC, 0o644)"
+        );
+
+        params[..3].copy_from_slice(&[1, 0x2000, 40]);
+        assert_eq!(
+            format_call(SyscallNumber::Write as u64, &params, &memory),
+            format!("write(1, \"{}\"..., 40)", "x".repeat(MAX_BUFFER_SHOWN))
+        );
+
+        params[..6].copy_from_slice(&[0, 0x2000, 3, 0x22, u64::MAX, 0]);
+        assert_eq!(
+            format_call(SyscallNumber::Mmap as u64, &params, &memory),
+            "mmap(NULL, 8192, PROT_READ|PROT_WRITE, MAP_PRIVATE|MAP_ANONYMOUS, -1, 0x0)"
+        );
+
+        // Unmapped paths fall back to the pointer
+        params[0] = 0x9000;
+        assert!(
+            format_call(SyscallNumber::ChDir as u64, &params, &memory).starts_with("chdir(0x9000)")
+        );
+        assert!(format_call(999, &params, &memory).starts_with("syscall_999(0x9000, "));
+
+        assert_eq!(
+            format_result(&context(SyscallNumber::Open, 0, Some(2))),
+            "= -1 ENOENT"
+        );
+        assert_eq!(
+            format_result(&context(SyscallNumber::Write, 6, None)),
+            "= 6"
+        );
+        assert_eq!(
+            format_result(&context(SyscallNumber::Mmap, 0x2000_0000, None)),
+            "= 0x20000000"
+        );
+    }
+
+    #[test]
+    fn test_filter() {
+        let strace = Strace::new(StraceOutput::Stderr)
+            .only(["write", "open"])
+            .unwrap();
+        assert!(strace.traces(SyscallNumber::Write as u64));
+        assert!(!strace.traces(SyscallNumber::Read as u64));
+        assert!(!strace.traces(999));
+        assert!(Strace::new(StraceOutput::Stderr).traces(999));
+        assert!(Strace::new(StraceOutput::Stderr).only(["wirte"]).is_err());
+    }
+}
@@ -2,13 +2,24 @@
 //!
 //! This module implements the IA-64 system call interface, handling transitions
 //! between user and kernel mode, parameter passing, and system service dispatching.
+//!
+//! Handlers reach guest memory through `cpu.memory`; the emulator lends its
+//! memory to the CPU for the duration of a call.
 
+use super::hostfs::{HostFs, MAX_TRANSFER};
+use super::stdio::Stdio;
+use super::timer::TimerMode;
 use super::Cpu;
+use crate::memory::view::Endian;
+use crate::memory::{Permissions, PAGE_SIZE};
 use crate::EmulatorError;
-use std::collections::HashMap;
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap};
 use std::convert::TryFrom;
 use std::fmt;
 use std::hash::Hash;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
 /// System call numbers
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
@@ -40,6 +51,8 @@ pub enum SyscallNumber {
     RmDir = 15,
     /// Change program break
     Break = 17,
+    /// Move a file offset
+    Lseek = 19,
     /// Get process ID
     GetPid = 20,
     /// Mount a filesystem
@@ -50,6 +63,8 @@ pub enum SyscallNumber {
     SetUid = 23,
     /// Get user ID
     GetUid = 24,
+    /// Get parent process ID
+    GetPpid = 64,
     /// Get current time of day
     GetTimeOfDay = 78,
     /// Map memory pages
@@ -72,6 +87,30 @@ pub enum SyscallNumber {
     Recv = 101,
     /// Shutdown a socket
     Shutdown = 102,
+    /// Get file status by descriptor
+    Fstat = 108,
+    /// Get system statistics
+    SysInfo = 116,
+    /// Get system identification
+    Uname = 122,
+    /// Read into several buffers
+    Readv = 145,
+    /// Write from several buffers
+    Writev = 146,
+    /// Examine and change the blocked signals
+    RtSigprocmask = 175,
+    /// Map memory pages, offset in pages
+    Mmap2 = 192,
+    /// Get thread ID
+    GetTid = 224,
+    /// Wait on or wake a futex
+    Futex = 240,
+    /// Exit all threads of the process
+    ExitGroup = 252,
+    /// Set the pointer cleared when the thread exits
+    SetTidAddress = 258,
+    /// Open a file relative to a directory descriptor
+    Openat = 295,
 }
 
 impl TryFrom<u64> for SyscallNumber {
@@ -92,11 +131,13 @@ impl TryFrom<u64> for SyscallNumber {
             14 => Ok(Self::MkDir),
             15 => Ok(Self::RmDir),
             17 => Ok(Self::Break),
+            19 => Ok(Self::Lseek),
             20 => Ok(Self::GetPid),
             21 => Ok(Self::Mount),
             22 => Ok(Self::Unmount),
             23 => Ok(Self::SetUid),
             24 => Ok(Self::GetUid),
+            64 => Ok(Self::GetPpid),
             78 => Ok(Self::GetTimeOfDay),
             90 => Ok(Self::Mmap),
             91 => Ok(Self::Munmap),
@@ -108,6 +149,18 @@ impl TryFrom<u64> for SyscallNumber {
             100 => Ok(Self::Send),
             101 => Ok(Self::Recv),
             102 => Ok(Self::Shutdown),
+            108 => Ok(Self::Fstat),
+            116 => Ok(Self::SysInfo),
+            122 => Ok(Self::Uname),
+            145 => Ok(Self::Readv),
+            146 => Ok(Self::Writev),
+            175 => Ok(Self::RtSigprocmask),
+            192 => Ok(Self::Mmap2),
+            224 => Ok(Self::GetTid),
+            240 => Ok(Self::Futex),
+            252 => Ok(Self::ExitGroup),
+            258 => Ok(Self::SetTidAddress),
+            295 => Ok(Self::Openat),
             _ => Err(EmulatorError::ExecutionError(format!(
                 "Invalid system call number: {}",
                 value
@@ -116,6 +169,111 @@ impl TryFrom<u64> for SyscallNumber {
     }
 }
 
+/// Every system call, in number order
+const ALL_SYSCALLS: [SyscallNumber; 43] = [
+    SyscallNumber::Exit,
+    SyscallNumber::Fork,
+    SyscallNumber::Read,
+    SyscallNumber::Write,
+    SyscallNumber::Open,
+    SyscallNumber::Close,
+    SyscallNumber::WaitPid,
+    SyscallNumber::Execve,
+    SyscallNumber::ChDir,
+    SyscallNumber::Time,
+    SyscallNumber::MkDir,
+    SyscallNumber::RmDir,
+    SyscallNumber::Break,
+    SyscallNumber::Lseek,
+    SyscallNumber::GetPid,
+    SyscallNumber::Mount,
+    SyscallNumber::Unmount,
+    SyscallNumber::SetUid,
+    SyscallNumber::GetUid,
+    SyscallNumber::GetPpid,
+    SyscallNumber::GetTimeOfDay,
+    SyscallNumber::Mmap,
+    SyscallNumber::Munmap,
+    SyscallNumber::Truncate,
+    SyscallNumber::Ftruncate,
+    SyscallNumber::Socket,
+    SyscallNumber::Connect,
+    SyscallNumber::Accept,
+    SyscallNumber::Send,
+    SyscallNumber::Recv,
+    SyscallNumber::Shutdown,
+    SyscallNumber::Fstat,
+    SyscallNumber::SysInfo,
+    SyscallNumber::Uname,
+    SyscallNumber::Readv,
+    SyscallNumber::Writev,
+    SyscallNumber::RtSigprocmask,
+    SyscallNumber::Mmap2,
+    SyscallNumber::GetTid,
+    SyscallNumber::Futex,
+    SyscallNumber::ExitGroup,
+    SyscallNumber::SetTidAddress,
+    SyscallNumber::Openat,
+];
+
+impl SyscallNumber {
+    /// Conventional name of the system call, as used by strace
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Exit => "exit",
+            Self::Fork => "fork",
+            Self::Read => "read",
+            Self::Write => "write",
+            Self::Open => "open",
+            Self::Close => "close",
+            Self::WaitPid => "waitpid",
+            Self::Execve => "execve",
+            Self::ChDir => "chdir",
+            Self::Time => "time",
+            Self::MkDir => "mkdir",
+            Self::RmDir => "rmdir",
+            Self::Break => "brk",
+            Self::Lseek => "lseek",
+            Self::GetPid => "getpid",
+            Self::Mount => "mount",
+            Self::Unmount => "umount",
+            Self::SetUid => "setuid",
+            Self::GetUid => "getuid",
+            Self::GetPpid => "getppid",
+            Self::GetTimeOfDay => "gettimeofday",
+            Self::Mmap => "mmap",
+            Self::Munmap => "munmap",
+            Self::Truncate => "truncate",
+            Self::Ftruncate => "ftruncate",
+            Self::Socket => "socket",
+            Self::Connect => "connect",
+            Self::Accept => "accept",
+            Self::Send => "send",
+            Self::Recv => "recv",
+            Self::Shutdown => "shutdown",
+            Self::Fstat => "fstat",
+            Self::SysInfo => "sysinfo",
+            Self::Uname => "uname",
+            Self::Readv => "readv",
+            Self::Writev => "writev",
+            Self::RtSigprocmask => "rt_sigprocmask",
+            Self::Mmap2 => "mmap2",
+            Self::GetTid => "gettid",
+            Self::Futex => "futex",
+            Self::ExitGroup => "exit_group",
+            Self::SetTidAddress => "set_tid_address",
+            Self::Openat => "openat",
+        }
+    }
+
+    /// Look up a system call by its conventional name
+    pub fn from_name(name: &str) -> Option<Self> {
+        ALL_SYSCALLS
+            .into_iter()
+            .find(|number| number.name() == name)
+    }
+}
+
 /// System call parameter registers
 pub const SYSCALL_PARAM_REGS: [usize; 8] = [32, 33, 34, 35, 36, 37, 38, 39];
 
@@ -175,8 +333,320 @@ impl SyscallContext {
     }
 }
 
+/// Length of each field of `struct new_utsname`, including the NUL
+pub const UTSNAME_FIELD_LEN: usize = 65;
+
+/// Size of `struct sysinfo` on 64-bit Linux
+pub const SYSINFO_SIZE: usize = 112;
+
+/// ITC frequency assumed for uptime when the timer counts instructions
+const NOMINAL_ITC_FREQUENCY: u64 = 1_000_000_000;
+
+/// Size of `struct stat` on Linux/ia64
+pub const STAT_SIZE: usize = 144;
+
+/// Address the first anonymous mapping is placed at, the start of region 1
+pub const MMAP_BASE: u64 = 0x2000_0000_0000_0000;
+
+/// Most buffers a readv or writev call may name
+const IOV_MAX: u64 = 1024;
+
+/// `st_mode` of the standard streams: a character device, mode 0620
+const TTY_MODE: u32 = 0o020620;
+
+/// `st_rdev` of the standard streams: the first pseudo-terminal
+const TTY_RDEV: u64 = 136 << 8;
+
+/// `st_mode` of a shared regular file before read-only files lose their
+/// write bits
+const FILE_MODE: u32 = 0o100644;
+
+/// `st_mode` of a shared directory
+const DIR_MODE: u32 = 0o040755;
+
+/// mmap(2) flag requesting memory not backed by a file
+const MAP_ANONYMOUS: u64 = 0x20;
+
+/// mmap(2) flag placing the mapping exactly at the hint
+const MAP_FIXED: u64 = 0x10;
+
+/// futex(2) operations, after the private and clock flags are masked off
+const FUTEX_WAIT: u64 = 0;
+const FUTEX_WAKE: u64 = 1;
+const FUTEX_CMD_MASK: u64 = !(128 | 256);
+
+/// errno for a missing file
+pub(crate) const ENOENT: u64 = 2;
+
+/// errno for a host I/O failure
+pub(crate) const EIO: u64 = 5;
+
+/// errno for a descriptor that is not open
+pub(crate) const EBADF: u64 = 9;
+
+/// errno for a futex whose value changed
+const EAGAIN: u64 = 11;
+
+/// errno for a mapping that cannot be placed
+const ENOMEM: u64 = 12;
+
+/// errno for a path the guest may not reach
+pub(crate) const EACCES: u64 = 13;
+
+/// errno for a bad guest pointer
+pub(crate) const EFAULT: u64 = 14;
+
+/// errno for a file that already exists
+pub(crate) const EEXIST: u64 = 17;
+
+/// errno for a path component that is not a directory
+pub(crate) const ENOTDIR: u64 = 20;
+
+/// errno for writing to a directory
+pub(crate) const EISDIR: u64 = 21;
+
+/// errno for an invalid argument
+pub(crate) const EINVAL: u64 = 22;
+
+/// errno for seeking on a terminal
+const ESPIPE: u64 = 29;
+
+/// errno for modifying a read-only file system
+pub(crate) const EROFS: u64 = 30;
+
+/// errno for an operation the emulator does not implement
+const ENOSYS: u64 = 38;
+
+/// openat(2) directory descriptor naming the working directory
+const AT_FDCWD: u64 = -100i64 as u64;
+
+/// Identity the guest sees through uname and the process ID calls
+///
+/// The guest is a single-threaded process, so gettid returns the PID.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct GuestIdentity {
+    /// Operating system name (`uname -s`)
+    pub sysname: String,
+    /// Host name (`uname -n`)
+    pub hostname: String,
+    /// Kernel release (`uname -r`)
+    pub release: String,
+    /// Kernel version (`uname -v`)
+    pub version: String,
+    /// Hardware name (`uname -m`)
+    pub machine: String,
+    /// NIS domain name
+    pub domainname: String,
+    /// Process ID
+    pub pid: u64,
+    /// Parent process ID
+    pub ppid: u64,
+}
+
+impl Default for GuestIdentity {
+    fn default() -> Self {
+        Self {
+            sysname: "Linux".to_string(),
+            hostname: "localhost".to_string(),
+            release: "2.6.32".to_string(),
+            version: "#1 SMP".to_string(),
+            machine: "ia64".to_string(),
+            domainname: "(none)".to_string(),
+            pid: 1,
+            ppid: 0,
+        }
+    }
+}
+
+impl GuestIdentity {
+    /// Check that every string fits a utsname field
+    ///
+    /// Errors name the offending field.
+    pub fn validate(&self) -> Result<(), String> {
+        for (name, value) in self.uts_fields() {
+            if value.len() >= UTSNAME_FIELD_LEN {
+                return Err(format!(
+                    "{}: longer than {} bytes",
+                    name,
+                    UTSNAME_FIELD_LEN - 1
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// utsname fields in structure order
+    fn uts_fields(&self) -> [(&'static str, &str); 6] {
+        [
+            ("sysname", &self.sysname),
+            ("hostname", &self.hostname),
+            ("release", &self.release),
+            ("version", &self.version),
+            ("machine", &self.machine),
+            ("domainname", &self.domainname),
+        ]
+    }
+
+    /// Contents of `struct new_utsname`
+    pub fn utsname(&self) -> Vec<u8> {
+        let mut data = vec![0u8; UTSNAME_FIELD_LEN * 6];
+        for (i, (_, value)) in self.uts_fields().iter().enumerate() {
+            let len = value.len().min(UTSNAME_FIELD_LEN - 1);
+            let start = i * UTSNAME_FIELD_LEN;
+            data[start..start + len].copy_from_slice(&value.as_bytes()[..len]);
+        }
+        data
+    }
+}
+
+/// Contents of `struct sysinfo` for the current machine state
+///
+/// RAM is the writable guest memory; no usage is tracked, so all of it is
+/// reported free.
+fn sysinfo(cpu: &Cpu) -> [u8; SYSINFO_SIZE] {
+    let frequency = match cpu.timer.mode() {
+        TimerMode::HostTime { frequency } => frequency,
+        TimerMode::Instructions { .. } => NOMINAL_ITC_FREQUENCY,
+    };
+    let ram: u64 = cpu
+        .memory
+        .regions()
+        .filter(|(_, permissions, _)| permissions.can_write())
+        .map(|(_, _, data)| data.len() as u64)
+        .sum();
+
+    let mut data = [0u8; SYSINFO_SIZE];
+    let mut put = |offset: usize, value: u64| {
+        data[offset..offset + 8].copy_from_slice(&value.to_le_bytes());
+    };
+    put(0, cpu.timer.read_itc() / frequency); // uptime
+    put(32, ram); // totalram
+    put(40, ram); // freeram
+    data[80..82].copy_from_slice(&1u16.to_le_bytes()); // procs
+    data[104..108].copy_from_slice(&1u32.to_le_bytes()); // mem_unit
+    data
+}
+
+/// Activity of one system call
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SyscallCounts {
+    /// Invocations
+    pub calls: u64,
+    /// Invocations that returned an errno or could not be handled
+    pub errors: u64,
+    /// Virtual time spent in the call, in ITC ticks
+    pub ticks: u64,
+    /// Host time spent handling the call
+    pub host_time: Duration,
+}
+
+/// Per-system-call statistics, like `strace -c`
+///
+/// Calls are keyed by number, so numbers the emulator does not implement
+/// are counted (as failures) too.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SyscallStats {
+    /// Activity by system call number
+    calls: BTreeMap<u64, SyscallCounts>,
+}
+
+impl SyscallStats {
+    /// Count a call
+    pub(crate) fn record(&mut self, number: u64, ticks: u64, host_time: Duration, failed: bool) {
+        let counts = self.calls.entry(number).or_default();
+        counts.calls += 1;
+        counts.errors += failed as u64;
+        counts.ticks += ticks;
+        counts.host_time += host_time;
+    }
+
+    /// Activity of a system call by number
+    pub fn get(&self, number: u64) -> Option<&SyscallCounts> {
+        self.calls.get(&number)
+    }
+
+    /// Activity of every system call made, by number
+    pub fn iter(&self) -> impl Iterator<Item = (u64, &SyscallCounts)> {
+        self.calls.iter().map(|(&number, counts)| (number, counts))
+    }
+
+    /// Whether no call was made
+    pub fn is_empty(&self) -> bool {
+        self.calls.is_empty()
+    }
+
+    /// Activity of all calls together
+    pub fn total(&self) -> SyscallCounts {
+        self.calls
+            .values()
+            .fold(SyscallCounts::default(), |total, counts| SyscallCounts {
+                calls: total.calls + counts.calls,
+                errors: total.errors + counts.errors,
+                ticks: total.ticks + counts.ticks,
+                host_time: total.host_time + counts.host_time,
+            })
+    }
+}
+
+/// Name of a system call number, as strace shows unknown ones
+fn syscall_name(number: u64) -> String {
+    match SyscallNumber::try_from(number) {
+        Ok(syscall) => syscall.name().to_string(),
+        Err(_) => format!("syscall_{}", number),
+    }
+}
+
+impl fmt::Display for SyscallStats {
+    /// Summary table, most virtual time first
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let total = self.total();
+        let mut rows: Vec<_> = self.iter().collect();
+        rows.sort_by_key(|&(number, counts)| {
+            (std::cmp::Reverse((counts.ticks, counts.calls)), number)
+        });
+
+        let rule = "------ ------------ ---------- -------- -------- ----------------";
+        writeln!(
+            f,
+            "{:>6} {:>12} {:>10} {:>8} {:>8} syscall",
+            "% time", "ticks", "host us", "calls", "errors"
+        )?;
+        writeln!(f, "{}", rule)?;
+        for (number, counts) in rows {
+            stats_row(f, &syscall_name(number), counts, total.ticks)?;
+        }
+        writeln!(f, "{}", rule)?;
+        stats_row(f, "total", &total, total.ticks)
+    }
+}
+
+/// Write one line of the summary table
+fn stats_row(
+    f: &mut fmt::Formatter<'_>,
+    name: &str,
+    counts: &SyscallCounts,
+    total_ticks: u64,
+) -> fmt::Result {
+    let share = if total_ticks == 0 {
+        0.0
+    } else {
+        counts.ticks as f64 * 100.0 / total_ticks as f64
+    };
+    writeln!(
+        f,
+        "{:>6.2} {:>12} {:>10} {:>8} {:>8} {}",
+        share,
+        counts.ticks,
+        counts.host_time.as_micros(),
+        counts.calls,
+        counts.errors,
+        name
+    )
+}
+
 /// Type alias for syscall handler function
-type SyscallHandler =
+pub type SyscallHandler =
     Box<dyn Fn(&mut Cpu, &mut SyscallContext) -> Result<(), EmulatorError> + Send + Sync>;
 
 /// Syscall handler registry
@@ -215,12 +685,21 @@ impl SyscallRegistry {
 pub struct SyscallManager {
     handlers: HashMap<SyscallNumber, SyscallHandler>,
     pub(crate) current: Option<SyscallContext>,
+    /// Identity reported to the guest
+    identity: GuestIdentity,
+    /// Calls made so far
+    pub(crate) stats: SyscallStats,
+    /// Host directory shared with the guest
+    hostfs: Option<Arc<Mutex<HostFs>>>,
 }
 
 impl fmt::Debug for SyscallManager {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.debug_struct("SyscallManager")
             .field("current", &self.current)
+            .field("identity", &self.identity)
+            .field("stats", &self.stats)
+            .field("hostfs", &self.hostfs)
             .field("handlers", &format!("<{} handlers>", self.handlers.len()))
             .finish()
     }
@@ -232,6 +711,9 @@ impl SyscallManager {
         let mut manager = Self {
             handlers: HashMap::new(),
             current: None,
+            identity: GuestIdentity::default(),
+            stats: SyscallStats::default(),
+            hostfs: None,
         };
         manager.register_default_handlers();
         manager
@@ -241,7 +723,324 @@ impl SyscallManager {
         self.register_handler(SyscallNumber::Exit, Self::handle_exit);
         self.register_handler(SyscallNumber::Write, Self::handle_write);
         self.register_handler(SyscallNumber::Read, Self::handle_read);
-        self.register_handler(SyscallNumber::GetPid, Self::handle_getpid);
+        self.register_handler(SyscallNumber::SysInfo, Self::handle_sysinfo);
+        self.register_libc_handlers();
+        self.register_identity_handlers();
+    }
+
+    /// Register the calls glibc makes on its way to main
+    ///
+    /// The guest is a single-threaded process with no files or signal
+    /// handlers, so most of these only check their arguments and report
+    /// what such a process would see.
+    fn register_libc_handlers(&mut self) {
+        self.register_handler(SyscallNumber::ExitGroup, Self::handle_exit);
+        self.register_handler(SyscallNumber::Readv, Self::handle_readv);
+        self.register_handler(SyscallNumber::Writev, Self::handle_writev);
+        self.register_handler(SyscallNumber::Fstat, Self::handle_fstat);
+        self.register_handler(SyscallNumber::Openat, Self::handle_openat);
+        self.register_handler(SyscallNumber::RtSigprocmask, Self::handle_rt_sigprocmask);
+        self.register_handler(SyscallNumber::Futex, Self::handle_futex);
+        self.register_handler(SyscallNumber::Mmap, Self::handle_mmap);
+        self.register_handler(SyscallNumber::Mmap2, Self::handle_mmap);
+        self.register_handler(SyscallNumber::Munmap, Self::handle_munmap);
+    }
+
+    /// Statistics of the calls made so far
+    pub fn stats(&self) -> &SyscallStats {
+        &self.stats
+    }
+
+    /// Start the statistics over
+    pub fn reset_stats(&mut self) {
+        self.stats = SyscallStats::default();
+    }
+
+    /// Identity reported to the guest
+    pub fn identity(&self) -> &GuestIdentity {
+        &self.identity
+    }
+
+    /// Change the identity reported to the guest
+    pub fn set_identity(&mut self, identity: GuestIdentity) {
+        self.identity = identity;
+        self.register_identity_handlers();
+    }
+
+    /// Register the handlers answering from the guest identity
+    fn register_identity_handlers(&mut self) {
+        let pid = self.identity.pid;
+        let ppid = self.identity.ppid;
+        let utsname = self.identity.utsname();
+
+        self.register_handler(SyscallNumber::GetPid, move |_, context| {
+            context.returns[0] = pid;
+            Ok(())
+        });
+        self.register_handler(SyscallNumber::GetTid, move |_, context| {
+            context.returns[0] = pid;
+            Ok(())
+        });
+        self.register_handler(SyscallNumber::SetTidAddress, move |_, context| {
+            context.returns[0] = pid;
+            Ok(())
+        });
+        self.register_handler(SyscallNumber::GetPpid, move |_, context| {
+            context.returns[0] = ppid;
+            Ok(())
+        });
+        self.register_handler(SyscallNumber::Uname, move |cpu, context| {
+            Self::copy_out(cpu, context, &utsname);
+            Ok(())
+        });
+    }
+
+    /// Host directory shared with the guest, if any
+    pub fn hostfs(&self) -> Option<&Arc<Mutex<HostFs>>> {
+        self.hostfs.as_ref()
+    }
+
+    /// Serve the guest's file system calls from a host directory
+    ///
+    /// Descriptors 0 to 2 keep their terminal behaviour, including any
+    /// [`Stdio`] set; every other descriptor is a file opened through
+    /// `hostfs`.
+    pub fn set_hostfs(&mut self, hostfs: HostFs) {
+        let hostfs = Arc::new(Mutex::new(hostfs));
+        self.hostfs = Some(Arc::clone(&hostfs));
+
+        let fs = Arc::clone(&hostfs);
+        self.register_handler(SyscallNumber::Open, move |cpu, context| {
+            let [path, flags, mode, ..] = context.params;
+            Self::hostfs_open(&fs, cpu, context, path, flags, mode);
+            Ok(())
+        });
+        let fs = Arc::clone(&hostfs);
+        self.register_handler(SyscallNumber::Openat, move |cpu, context| {
+            let [dirfd, path, flags, mode, ..] = context.params;
+            match cpu.memory.peek_c_string(path, 4096) {
+                Some(name) if !name.starts_with('/') && dirfd != AT_FDCWD => {
+                    // Only the working directory, the root, can anchor a path
+                    let open = fs.lock().unwrap().is_open(dirfd);
+                    context.set_error(if open { ENOTDIR } else { EBADF });
+                }
+                _ => Self::hostfs_open(&fs, cpu, context, path, flags, mode),
+            }
+            Ok(())
+        });
+        let fs = Arc::clone(&hostfs);
+        let previous = self.previous_handler(SyscallNumber::Read, Self::handle_read);
+        self.register_handler(SyscallNumber::Read, move |cpu, context| {
+            let [fd, buf, count, ..] = context.params;
+            if !fs.lock().unwrap().is_open(fd) {
+                return previous(cpu, context);
+            }
+            let data = fs.lock().unwrap().read(fd, count);
+            match data {
+                Ok(data) => match cpu.memory.write_bytes(buf, &data) {
+                    Ok(()) => context.returns[0] = data.len() as u64,
+                    Err(_) => context.set_error(EFAULT),
+                },
+                Err(errno) => context.set_error(errno),
+            }
+            Ok(())
+        });
+        let fs = Arc::clone(&hostfs);
+        let previous = self.previous_handler(SyscallNumber::Write, Self::handle_write);
+        self.register_handler(SyscallNumber::Write, move |cpu, context| {
+            let [fd, buf, count, ..] = context.params;
+            if !fs.lock().unwrap().is_open(fd) {
+                return previous(cpu, context);
+            }
+            let mut data = vec![0; count.min(MAX_TRANSFER) as usize];
+            if cpu.memory.read_bytes(buf, &mut data).is_err() {
+                context.set_error(EFAULT);
+                return Ok(());
+            }
+            match fs.lock().unwrap().write(fd, &data) {
+                Ok(written) => context.returns[0] = written,
+                Err(errno) => context.set_error(errno),
+            }
+            Ok(())
+        });
+        let fs = Arc::clone(&hostfs);
+        self.register_handler(SyscallNumber::Lseek, move |_, context| {
+            let [fd, offset, whence, ..] = context.params;
+            match fd {
+                0..=2 => context.set_error(ESPIPE),
+                _ => match fs.lock().unwrap().seek(fd, offset, whence) {
+                    Ok(position) => context.returns[0] = position,
+                    Err(errno) => context.set_error(errno),
+                },
+            }
+            Ok(())
+        });
+        let fs = Arc::clone(&hostfs);
+        let previous = self.previous_handler(SyscallNumber::Fstat, Self::handle_fstat);
+        self.register_handler(SyscallNumber::Fstat, move |cpu, context| {
+            let fd = context.params[0];
+            if !fs.lock().unwrap().is_open(fd) {
+                return previous(cpu, context);
+            }
+            let metadata = fs.lock().unwrap().stat(fd);
+            match metadata {
+                Ok(metadata) => {
+                    let mode = match (metadata.is_dir(), metadata.permissions().readonly()) {
+                        (true, _) => DIR_MODE,
+                        (false, true) => FILE_MODE & !0o222,
+                        (false, false) => FILE_MODE,
+                    };
+                    Self::copy_stat(cpu, context, mode, 0, metadata.len());
+                }
+                Err(errno) => context.set_error(errno),
+            }
+            Ok(())
+        });
+        self.register_handler(SyscallNumber::Close, move |_, context| {
+            let fd = context.params[0];
+            match fd {
+                0..=2 => context.returns[0] = 0,
+                _ => match hostfs.lock().unwrap().close(fd) {
+                    Ok(()) => context.returns[0] = 0,
+                    Err(errno) => context.set_error(errno),
+                },
+            }
+            Ok(())
+        });
+    }
+
+    /// Connect the guest's standard streams to `stdio`
+    ///
+    /// `read`, `write`, `readv` and `writev` on descriptors 0 to 2 copy
+    /// bytes between guest memory and the streams; other descriptors go to
+    /// the handlers registered before, such as those of a host directory.
+    pub fn set_stdio(&mut self, stdio: Stdio) {
+        let stdio = Arc::new(Mutex::new(stdio));
+
+        let streams = Arc::clone(&stdio);
+        let previous = self.previous_handler(SyscallNumber::Read, Self::handle_read);
+        self.register_handler(SyscallNumber::Read, move |cpu, context| {
+            let [fd, buf, count, ..] = context.params;
+            if !Stdio::is_standard(fd) {
+                return previous(cpu, context);
+            }
+            let iovecs = [(buf, count)];
+            Self::stdio_read(&streams, cpu, context, &iovecs);
+            Ok(())
+        });
+        let streams = Arc::clone(&stdio);
+        let previous = self.previous_handler(SyscallNumber::Readv, Self::handle_readv);
+        self.register_handler(SyscallNumber::Readv, move |cpu, context| {
+            if !Stdio::is_standard(context.params[0]) {
+                return previous(cpu, context);
+            }
+            match Self::iovecs(cpu, context) {
+                Ok(iovecs) => Self::stdio_read(&streams, cpu, context, &iovecs),
+                Err(errno) => context.set_error(errno),
+            }
+            Ok(())
+        });
+        let streams = Arc::clone(&stdio);
+        let previous = self.previous_handler(SyscallNumber::Write, Self::handle_write);
+        self.register_handler(SyscallNumber::Write, move |cpu, context| {
+            let [fd, buf, count, ..] = context.params;
+            if !Stdio::is_standard(fd) {
+                return previous(cpu, context);
+            }
+            let iovecs = [(buf, count)];
+            Self::stdio_write(&streams, cpu, context, &iovecs);
+            Ok(())
+        });
+        let previous = self.previous_handler(SyscallNumber::Writev, Self::handle_writev);
+        self.register_handler(SyscallNumber::Writev, move |cpu, context| {
+            if !Stdio::is_standard(context.params[0]) {
+                return previous(cpu, context);
+            }
+            match Self::iovecs(cpu, context) {
+                Ok(iovecs) => Self::stdio_write(&stdio, cpu, context, &iovecs),
+                Err(errno) => context.set_error(errno),
+            }
+            Ok(())
+        });
+    }
+
+    /// Read standard input into the guest buffers `iovecs`, in turn
+    ///
+    /// Like the host's, a single read returns what the stream has ready,
+    /// which may fill only the first buffers.
+    fn stdio_read(
+        stdio: &Mutex<Stdio>,
+        cpu: &mut Cpu,
+        context: &mut SyscallContext,
+        iovecs: &[(u64, u64)],
+    ) {
+        let count = iovecs
+            .iter()
+            .fold(0u64, |total, &(_, len)| total.saturating_add(len));
+        let data = match stdio.lock().unwrap().read(context.params[0], count) {
+            Ok(data) => data,
+            Err(errno) => return context.set_error(errno),
+        };
+        let mut rest = &data[..];
+        for &(base, len) in iovecs {
+            let (chunk, tail) = rest.split_at(rest.len().min(len as usize));
+            if cpu.memory.write_bytes(base, chunk).is_err() {
+                return context.set_error(EFAULT);
+            }
+            rest = tail;
+        }
+        context.returns[0] = data.len() as u64;
+    }
+
+    /// Write the guest buffers `iovecs` to standard output or error
+    fn stdio_write(
+        stdio: &Mutex<Stdio>,
+        cpu: &mut Cpu,
+        context: &mut SyscallContext,
+        iovecs: &[(u64, u64)],
+    ) {
+        let mut data = Vec::new();
+        for &(base, len) in iovecs {
+            let start = data.len();
+            data.resize(start + len.min(MAX_TRANSFER) as usize, 0);
+            if cpu.memory.read_bytes(base, &mut data[start..]).is_err() {
+                return context.set_error(EFAULT);
+            }
+        }
+        match stdio.lock().unwrap().write(context.params[0], &data) {
+            Ok(written) => context.returns[0] = written,
+            Err(errno) => context.set_error(errno),
+        }
+    }
+
+    /// Take the handler for `number` for a new one to call on to, or
+    /// `default` if there is none
+    fn previous_handler(
+        &mut self,
+        number: SyscallNumber,
+        default: fn(&mut Cpu, &mut SyscallContext) -> Result<(), EmulatorError>,
+    ) -> SyscallHandler {
+        self.take_handler(number)
+            .unwrap_or_else(|| Box::new(default))
+    }
+
+    /// Open the guest path at `path` through the shared directory
+    fn hostfs_open(
+        hostfs: &Mutex<HostFs>,
+        cpu: &Cpu,
+        context: &mut SyscallContext,
+        path: u64,
+        flags: u64,
+        mode: u64,
+    ) {
+        let Some(path) = cpu.memory.peek_c_string(path, 4096) else {
+            context.set_error(EFAULT);
+            return;
+        };
+        match hostfs.lock().unwrap().open(&path, flags, mode as u32) {
+            Ok(fd) => context.returns[0] = fd,
+            Err(errno) => context.set_error(errno),
+        }
     }
 
     /// Register a handler for a system call
@@ -256,6 +1055,14 @@ impl SyscallManager {
         self.handlers.insert(number, Box::new(handler));
     }
 
+    /// Remove the handler for a system call and return it
+    ///
+    /// A handler registered in its place can call on to the one returned,
+    /// to observe a call without changing what it does.
+    pub fn take_handler(&mut self, number: SyscallNumber) -> Option<SyscallHandler> {
+        self.handlers.remove(&number)
+    }
+
     /// Get the handler for a system call
     ///
     /// # Arguments
@@ -286,44 +1093,253 @@ impl SyscallManager {
     }
 
     /// Handle exit system call
-    fn handle_exit(_cpu: &mut Cpu, context: &mut SyscallContext) -> Result<(), EmulatorError> {
-        // For now, just set return value to 0 (success)
+    fn handle_exit(cpu: &mut Cpu, context: &mut SyscallContext) -> Result<(), EmulatorError> {
+        // Record the guest's exit status so the run loop can stop
+        cpu.exit_code = Some(context.params[0] as i32);
         context.returns[0] = 0;
         Ok(())
     }
 
     /// Handle write system call
-    fn handle_write(_cpu: &mut Cpu, ctx: &mut SyscallContext) -> Result<(), EmulatorError> {
-        let _fd = ctx.params[0];
-        let _buf = ctx.params[1];
-        let count = ctx.params[2];
-
-        // For now, just pretend we wrote all the bytes
-        ctx.returns[0] = count;
-        ctx.error = None;
+    ///
+    /// Only the standard streams are open, and without a [`Stdio`] what is
+    /// written to them is discarded.
+    fn handle_write(_cpu: &mut Cpu, context: &mut SyscallContext) -> Result<(), EmulatorError> {
+        let [fd, _, count, ..] = context.params;
+        match Stdio::is_standard(fd) {
+            true => context.returns[0] = count,
+            false => context.set_error(EBADF),
+        }
         Ok(())
     }
 
     /// Handle read system call
+    ///
+    /// Without a [`Stdio`], standard input is at end of file.
     fn handle_read(_cpu: &mut Cpu, context: &mut SyscallContext) -> Result<(), EmulatorError> {
-        // For now, just set return value to number of bytes read
-        context.returns[0] = 0;
+        match Stdio::is_standard(context.params[0]) {
+            true => context.returns[0] = 0,
+            false => context.set_error(EBADF),
+        }
+        Ok(())
+    }
+
+    /// Handle sysinfo system call
+    fn handle_sysinfo(cpu: &mut Cpu, context: &mut SyscallContext) -> Result<(), EmulatorError> {
+        let data = sysinfo(cpu);
+        Self::copy_out(cpu, context, &data);
+        Ok(())
+    }
+
+    /// Handle readv system call
+    fn handle_readv(cpu: &mut Cpu, context: &mut SyscallContext) -> Result<(), EmulatorError> {
+        // Like read, standard input is at end of file
+        match Self::iovec_total(cpu, context) {
+            Ok(_) if !Stdio::is_standard(context.params[0]) => context.set_error(EBADF),
+            Ok(_) => context.returns[0] = 0,
+            Err(errno) => context.set_error(errno),
+        }
+        Ok(())
+    }
+
+    /// Handle writev system call
+    fn handle_writev(cpu: &mut Cpu, context: &mut SyscallContext) -> Result<(), EmulatorError> {
+        // Like write, discard what is written to the standard streams
+        match Self::iovec_total(cpu, context) {
+            Ok(_) if !Stdio::is_standard(context.params[0]) => context.set_error(EBADF),
+            Ok(total) => context.returns[0] = total,
+            Err(errno) => context.set_error(errno),
+        }
+        Ok(())
+    }
+
+    /// Total length of the `struct iovec` array in the second and third
+    /// parameters
+    fn iovec_total(cpu: &Cpu, context: &SyscallContext) -> Result<u64, u64> {
+        Ok(Self::iovecs(cpu, context)?
+            .iter()
+            .fold(0u64, |total, &(_, len)| total.wrapping_add(len)))
+    }
+
+    /// Base and length of each `struct iovec` in the second and third
+    /// parameters
+    fn iovecs(cpu: &Cpu, context: &SyscallContext) -> Result<Vec<(u64, u64)>, u64> {
+        let count = context.params[2];
+        if count > IOV_MAX {
+            return Err(EINVAL);
+        }
+        let iov = cpu
+            .memory
+            .view(context.params[1], count * 16, Endian::Little)
+            .to_vec::<u64>()
+            .map_err(|_| EFAULT)?;
+        Ok(iov
+            .chunks_exact(2)
+            .map(|iovec| (iovec[0], iovec[1]))
+            .collect())
+    }
+
+    /// Handle fstat system call
+    ///
+    /// Only the standard streams are open, and they are terminals.
+    fn handle_fstat(cpu: &mut Cpu, context: &mut SyscallContext) -> Result<(), EmulatorError> {
+        if context.params[0] > 2 {
+            context.set_error(EBADF);
+            return Ok(());
+        }
+        Self::copy_stat(cpu, context, TTY_MODE, TTY_RDEV, 0);
         Ok(())
     }
 
-    /// Handle getpid system call
-    fn handle_getpid(_cpu: &mut Cpu, context: &mut SyscallContext) -> Result<(), EmulatorError> {
-        // For now, just return a dummy PID
-        context.returns[0] = 1;
+    /// Fill the `struct stat` in the second parameter
+    ///
+    /// Fails the call with EFAULT if the buffer is not writable.
+    fn copy_stat(cpu: &mut Cpu, context: &mut SyscallContext, mode: u32, rdev: u64, size: u64) {
+        let mut stat = cpu
+            .memory
+            .view_mut(context.params[1], STAT_SIZE as u64, Endian::Little);
+        let filled = (|| {
+            for index in 0..STAT_SIZE / 8 {
+                stat.set(index, 0u64)?;
+            }
+            stat.write(16, 1u64)?; // st_nlink
+            stat.write(24, mode)?;
+            stat.write(40, rdev)?;
+            stat.write(48, size)?;
+            stat.write(104, 1024u64)?; // st_blksize
+            stat.write(112, size.div_ceil(512)) // st_blocks
+        })();
+        match filled {
+            Ok(()) => context.returns[0] = 0,
+            Err(_) => context.set_error(EFAULT),
+        }
+    }
+
+    /// Handle openat system call
+    ///
+    /// The guest has no file system, so every path is missing.
+    fn handle_openat(cpu: &mut Cpu, context: &mut SyscallContext) -> Result<(), EmulatorError> {
+        match cpu.memory.peek_c_string(context.params[1], 4096) {
+            Some(_) => context.set_error(ENOENT),
+            None => context.set_error(EFAULT),
+        }
         Ok(())
     }
 
+    /// Handle rt_sigprocmask system call
+    ///
+    /// No signal is ever delivered, so the mask is always empty.
+    fn handle_rt_sigprocmask(
+        cpu: &mut Cpu,
+        context: &mut SyscallContext,
+    ) -> Result<(), EmulatorError> {
+        let old = context.params[2];
+        if context.params[3] != 8 {
+            context.set_error(EINVAL);
+        } else if old != 0 && cpu.memory.write_bytes(old, &[0; 8]).is_err() {
+            context.set_error(EFAULT);
+        } else {
+            context.returns[0] = 0;
+        }
+        Ok(())
+    }
+
+    /// Handle futex system call
+    ///
+    /// With one thread nothing waits on a futex, so a wake wakes nobody and
+    /// a wait that would block can never be woken.
+    fn handle_futex(cpu: &mut Cpu, context: &mut SyscallContext) -> Result<(), EmulatorError> {
+        let addr = context.params[0];
+        match context.params[1] & FUTEX_CMD_MASK {
+            FUTEX_WAIT => {
+                let value = cpu.memory.view(addr, 4, Endian::Little).get::<u32>(0);
+                match value {
+                    Err(_) => context.set_error(EFAULT),
+                    Ok(value) if value as u64 != context.params[2] & 0xFFFF_FFFF => {
+                        context.set_error(EAGAIN)
+                    }
+                    Ok(_) => {
+                        return Err(EmulatorError::ExecutionError(format!(
+                            "futex wait on {:#x} would block the only thread forever",
+                            addr
+                        )))
+                    }
+                }
+            }
+            FUTEX_WAKE => context.returns[0] = 0,
+            _ => context.set_error(ENOSYS),
+        }
+        Ok(())
+    }
+
+    /// Handle mmap and mmap2 system calls
+    ///
+    /// Only anonymous mappings exist. Without `MAP_FIXED` they are placed
+    /// above every mapping from [`MMAP_BASE`] up.
+    fn handle_mmap(cpu: &mut Cpu, context: &mut SyscallContext) -> Result<(), EmulatorError> {
+        let [hint, len, prot, flags, ..] = context.params;
+        if flags & MAP_ANONYMOUS == 0 {
+            context.set_error(EBADF);
+            return Ok(());
+        }
+        if len == 0 || (flags & MAP_FIXED != 0 && hint % PAGE_SIZE != 0) {
+            context.set_error(EINVAL);
+            return Ok(());
+        }
+        let size = len.next_multiple_of(PAGE_SIZE);
+        let addr = if flags & MAP_FIXED != 0 {
+            hint
+        } else {
+            cpu.memory
+                .regions()
+                .filter(|&(base, _, _)| base >= MMAP_BASE)
+                .map(|(base, _, data)| (base + data.len() as u64).next_multiple_of(PAGE_SIZE))
+                .max()
+                .unwrap_or(MMAP_BASE)
+        };
+        let permissions = match (prot & 2 != 0, prot & 4 != 0, prot & 1 != 0) {
+            (true, true, _) => Permissions::ReadWriteExecute,
+            (true, false, _) => Permissions::ReadWrite,
+            (false, true, _) => Permissions::ReadExecute,
+            (false, false, true) => Permissions::Read,
+            (false, false, false) => Permissions::None,
+        };
+        match cpu.memory.map(addr, size, permissions) {
+            Ok(()) => context.returns[0] = addr,
+            Err(_) => context.set_error(ENOMEM),
+        }
+        Ok(())
+    }
+
+    /// Handle munmap system call
+    ///
+    /// Only whole mappings can be removed.
+    fn handle_munmap(cpu: &mut Cpu, context: &mut SyscallContext) -> Result<(), EmulatorError> {
+        let [addr, len, ..] = context.params;
+        let whole = cpu.memory.regions().any(|(base, _, data)| {
+            base == addr && (data.len() as u64).div_ceil(PAGE_SIZE) == len.div_ceil(PAGE_SIZE)
+        });
+        if whole && cpu.memory.unmap(addr).is_ok() {
+            context.returns[0] = 0;
+        } else {
+            context.set_error(EINVAL);
+        }
+        Ok(())
+    }
+
+    /// Copy a result structure to the guest buffer in the first parameter
+    ///
+    /// Fails the call with EFAULT if the buffer is not writable.
+    fn copy_out(cpu: &mut Cpu, context: &mut SyscallContext, data: &[u8]) {
+        match cpu.memory.write_bytes(context.params[0], data) {
+            Ok(()) => context.returns[0] = 0,
+            Err(_) => context.set_error(EFAULT),
+        }
+    }
+
     /// Initialize default handlers
     pub fn init_default_handlers(&mut self) {
-        self.register_handler(SyscallNumber::Exit, Self::handle_exit);
-        self.register_handler(SyscallNumber::Write, Self::handle_write);
-        self.register_handler(SyscallNumber::Read, Self::handle_read);
-        self.register_handler(SyscallNumber::GetPid, Self::handle_getpid);
+        self.register_default_handlers();
     }
 
     /// Begins a system call by creating a new context and loading parameters from registers
@@ -358,9 +1374,9 @@ impl SyscallManager {
     /// * `cpu` - Mutable reference to the CPU state to write return values to
     ///
     /// # Returns
-    /// * `Ok(())` if the system call was ended successfully
+    /// * `Ok(context)` with the completed system call if it was ended successfully
     /// * `Err(EmulatorError::NoSyscallContext)` if there is no active system call
-    pub fn end_syscall(&mut self, cpu: &mut Cpu) -> Result<(), EmulatorError> {
+    pub fn end_syscall(&mut self, cpu: &mut Cpu) -> Result<SyscallContext, EmulatorError> {
         let context = self.current.take().ok_or(EmulatorError::NoSyscallContext)?;
 
         // Set return value
@@ -371,7 +1387,7 @@ impl SyscallManager {
             cpu.gr[SYSCALL_RETURN_REGS[1]] = err;
         }
 
-        Ok(())
+        Ok(context)
     }
 }
 
@@ -386,6 +1402,7 @@ impl Default for SyscallManager {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::memory::Permissions;
 
     #[test]
     fn test_syscall_context() {
@@ -408,6 +1425,240 @@ mod tests {
         context.set_error(0);
     }
 
+    #[test]
+    fn test_syscall_names() {
+        assert_eq!(SyscallNumber::Break.name(), "brk");
+        assert_eq!(
+            SyscallNumber::from_name("write"),
+            Some(SyscallNumber::Write)
+        );
+        assert_eq!(SyscallNumber::from_name("bogus"), None);
+        for number in ALL_SYSCALLS {
+            assert_eq!(SyscallNumber::try_from(number as u64).unwrap(), number);
+        }
+    }
+
+    #[test]
+    fn test_guest_identity() {
+        let mut cpu = Cpu::new();
+        cpu.memory
+            .map(0x1000, 0x1000, Permissions::ReadWrite)
+            .unwrap();
+        cpu.memory.map(0x2000, 0x1000, Permissions::Read).unwrap();
+        cpu.syscall_mgr.set_identity(GuestIdentity {
+            hostname: "testhost".to_string(),
+            pid: 42,
+            ppid: 7,
+            ..GuestIdentity::default()
+        });
+
+        cpu.do_syscall(SyscallNumber::GetPid as u64).unwrap();
+        assert_eq!(cpu.gr[8], 42);
+        cpu.do_syscall(SyscallNumber::GetTid as u64).unwrap();
+        assert_eq!(cpu.gr[8], 42);
+        cpu.do_syscall(SyscallNumber::GetPpid as u64).unwrap();
+        assert_eq!(cpu.gr[8], 7);
+
+        cpu.gr[32] = 0x1000;
+        let context = cpu.do_syscall(SyscallNumber::Uname as u64).unwrap();
+        assert_eq!(context.error, None);
+        let node = 0x1000 + UTSNAME_FIELD_LEN as u64;
+        assert_eq!(cpu.memory.peek_c_string(0x1000, 65).unwrap(), "Linux");
+        assert_eq!(cpu.memory.peek_c_string(node, 65).unwrap(), "testhost");
+        let machine = 0x1000 + 4 * UTSNAME_FIELD_LEN as u64;
+        assert_eq!(cpu.memory.peek_c_string(machine, 65).unwrap(), "ia64");
+
+        cpu.gr[32] = 0x2000;
+        let context = cpu.do_syscall(SyscallNumber::SysInfo as u64).unwrap();
+        assert_eq!(context.error, Some(EFAULT));
+        cpu.gr[32] = 0x1800;
+        cpu.do_syscall(SyscallNumber::SysInfo as u64).unwrap();
+        let mut totalram = [0u8; 8];
+        cpu.memory.peek_bytes(0x1800 + 32, &mut totalram).unwrap();
+        assert_eq!(u64::from_le_bytes(totalram), 0x1000);
+
+        // The identity survives a failing call
+        assert!(cpu.do_syscall(999).is_err());
+        assert_eq!(cpu.syscall_mgr.identity().pid, 42);
+
+        let long = GuestIdentity {
+            release: "x".repeat(65),
+            ..GuestIdentity::default()
+        };
+        assert!(long.validate().unwrap_err().starts_with("release:"));
+    }
+
+    #[test]
+    fn test_libc_startup_calls() {
+        let mut cpu = Cpu::new();
+        cpu.memory
+            .map(0x1000, 0x1000, Permissions::ReadWrite)
+            .unwrap();
+        let call = |cpu: &mut Cpu, number: SyscallNumber, params: &[u64]| {
+            for (i, &param) in params.iter().enumerate() {
+                cpu.gr[SYSCALL_PARAM_REGS[i]] = param;
+            }
+            cpu.do_syscall(number as u64).unwrap()
+        };
+
+        let context = call(&mut cpu, SyscallNumber::SetTidAddress, &[0x1000]);
+        assert_eq!(context.returns[0], 1);
+
+        // writev adds up the buffers named by the iovec array
+        cpu.memory.write_u64(0x1008, 5).unwrap();
+        cpu.memory.write_u64(0x1018, 7).unwrap();
+        let context = call(&mut cpu, SyscallNumber::Writev, &[1, 0x1000, 2]);
+        assert_eq!((context.returns[0], context.error), (12, None));
+        let context = call(&mut cpu, SyscallNumber::Writev, &[1, 0x1FF8, 2]);
+        assert_eq!(context.error, Some(EFAULT));
+
+        // The standard streams are terminals and nothing else is open
+        let context = call(&mut cpu, SyscallNumber::Fstat, &[1, 0x1100]);
+        assert_eq!(context.error, None);
+        assert_eq!(cpu.memory.read_u32(0x1100 + 24).unwrap(), TTY_MODE);
+        let context = call(&mut cpu, SyscallNumber::Fstat, &[3, 0x1100]);
+        assert_eq!(context.error, Some(EBADF));
+        cpu.memory
+            .write_bytes(0x1200, b"/etc/ld.so.cache\0")
+            .unwrap();
+        let context = call(&mut cpu, SyscallNumber::Openat, &[-100i64 as u64, 0x1200]);
+        assert_eq!(context.error, Some(ENOENT));
+
+        cpu.memory.write_u64(0x1300, u64::MAX).unwrap();
+        let context = call(&mut cpu, SyscallNumber::RtSigprocmask, &[0, 0, 0x1300, 8]);
+        assert_eq!(context.error, None);
+        assert_eq!(cpu.memory.read_u64(0x1300).unwrap(), 0);
+
+        // Futexes: a wake wakes nobody, a stale wait fails, a real one stops
+        cpu.memory.write_u32(0x1400, 1).unwrap();
+        let context = call(
+            &mut cpu,
+            SyscallNumber::Futex,
+            &[0x1400, 128 | FUTEX_WAKE, 1],
+        );
+        assert_eq!(context.returns[0], 0);
+        let context = call(&mut cpu, SyscallNumber::Futex, &[0x1400, FUTEX_WAIT, 0]);
+        assert_eq!(context.error, Some(EAGAIN));
+        cpu.gr[34] = 1;
+        assert!(cpu.do_syscall(SyscallNumber::Futex as u64).is_err());
+
+        // Anonymous mappings stack up from MMAP_BASE and can be unmapped
+        let context = call(&mut cpu, SyscallNumber::Mmap2, &[0, 0x1800, 3, 0x22]);
+        assert_eq!(context.returns[0], MMAP_BASE);
+        let context = call(&mut cpu, SyscallNumber::Mmap, &[0, 0x1000, 1, 0x22]);
+        assert_eq!(context.returns[0], MMAP_BASE + 0x2000);
+        cpu.memory.write_u64(MMAP_BASE + 0x1000, 1).unwrap();
+        let context = call(&mut cpu, SyscallNumber::Mmap, &[0, 0x1000, 1, 0x02]);
+        assert_eq!(context.error, Some(EBADF));
+        let context = call(&mut cpu, SyscallNumber::Munmap, &[MMAP_BASE, 0x2000]);
+        assert_eq!(context.error, None);
+        assert!(cpu.memory.read_u64(MMAP_BASE).is_err());
+
+        let context = call(&mut cpu, SyscallNumber::ExitGroup, &[3]);
+        assert_eq!(context.error, None);
+        assert_eq!(cpu.exit_code, Some(3));
+    }
+
+    #[test]
+    fn test_hostfs_calls() {
+        use crate::cpu::hostfs::HostFsMode;
+
+        let dir =
+            std::env::temp_dir().join(format!("rust-ia64-hostfs-calls-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("share")).unwrap();
+        std::fs::write(dir.join("share/data"), b"0123456789").unwrap();
+        std::fs::write(dir.join("secret"), b"secret").unwrap();
+
+        let mut cpu = Cpu::new();
+        cpu.memory
+            .map(0x1000, 0x1000, Permissions::ReadWrite)
+            .unwrap();
+        cpu.syscall_mgr
+            .set_hostfs(HostFs::new(dir.join("share"), HostFsMode::ReadOnly).unwrap());
+        let call = |cpu: &mut Cpu, number: SyscallNumber, params: &[u64]| {
+            for (i, &param) in params.iter().enumerate() {
+                cpu.gr[SYSCALL_PARAM_REGS[i]] = param;
+            }
+            cpu.do_syscall(number as u64).unwrap()
+        };
+
+        cpu.memory.write_bytes(0x1000, b"/data\0").unwrap();
+        let context = call(&mut cpu, SyscallNumber::Open, &[0x1000, 0, 0]);
+        assert_eq!((context.returns[0], context.error), (3, None));
+        let context = call(&mut cpu, SyscallNumber::Lseek, &[3, 4, 0]);
+        assert_eq!(context.returns[0], 4);
+        let context = call(&mut cpu, SyscallNumber::Read, &[3, 0x1100, 100]);
+        assert_eq!(context.returns[0], 6);
+        let mut data = [0; 6];
+        cpu.memory.peek_bytes(0x1100, &mut data).unwrap();
+        assert_eq!(&data, b"456789");
+        let context = call(&mut cpu, SyscallNumber::Fstat, &[3, 0x1200]);
+        assert_eq!(context.error, None);
+        assert_eq!(cpu.memory.read_u64(0x1200 + 48).unwrap(), 10);
+        let context = call(&mut cpu, SyscallNumber::Close, &[3]);
+        assert_eq!(context.error, None);
+        let context = call(&mut cpu, SyscallNumber::Read, &[3, 0x1100, 100]);
+        assert_eq!(context.returns[0], 0);
+
+        // The standard streams keep behaving as terminals
+        let context = call(&mut cpu, SyscallNumber::Write, &[1, 0x1000, 5]);
+        assert_eq!(context.returns[0], 5);
+        let context = call(&mut cpu, SyscallNumber::Lseek, &[1, 0, 0]);
+        assert_eq!(context.error, Some(ESPIPE));
+
+        // Traversal stays inside the share, which cannot be modified
+        cpu.memory.write_bytes(0x1000, b"../../secret\0").unwrap();
+        let context = call(&mut cpu, SyscallNumber::Openat, &[AT_FDCWD, 0x1000, 0, 0]);
+        assert_eq!(context.error, Some(ENOENT));
+        let context = call(&mut cpu, SyscallNumber::Openat, &[5, 0x1000, 0, 0]);
+        assert_eq!(context.error, Some(EBADF));
+        cpu.memory.write_bytes(0x1000, b"data\0").unwrap();
+        let context = call(
+            &mut cpu,
+            SyscallNumber::Openat,
+            &[AT_FDCWD, 0x1000, 0o1101, 0],
+        );
+        assert_eq!(context.error, Some(EROFS));
+        let context = call(&mut cpu, SyscallNumber::Open, &[0xDEAD_0000, 0, 0]);
+        assert_eq!(context.error, Some(EFAULT));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_syscall_stats() {
+        let mut cpu = Cpu::new();
+        cpu.memory.map(0x2000, 0x1000, Permissions::Read).unwrap();
+        cpu.do_syscall(SyscallNumber::GetPid as u64).unwrap();
+        cpu.do_syscall(SyscallNumber::GetPid as u64).unwrap();
+        cpu.gr[32] = 0x2000;
+        cpu.do_syscall(SyscallNumber::SysInfo as u64).unwrap();
+        assert!(cpu.do_syscall(999).is_err());
+
+        let stats = cpu.syscall_mgr.stats();
+        let getpid = stats.get(SyscallNumber::GetPid as u64).unwrap();
+        assert_eq!((getpid.calls, getpid.errors), (2, 0));
+        assert_eq!(stats.get(SyscallNumber::SysInfo as u64).unwrap().errors, 1);
+        assert_eq!(stats.get(999).unwrap().errors, 1);
+        let total = stats.total();
+        assert_eq!((total.calls, total.errors), (4, 2));
+
+        // The report lists the calls taking the most virtual time first
+        let mut stats = SyscallStats::default();
+        stats.record(SyscallNumber::Write as u64, 30, Duration::ZERO, false);
+        stats.record(SyscallNumber::Read as u64, 90, Duration::ZERO, true);
+        stats.record(999, 0, Duration::ZERO, true);
+        let report = stats.to_string();
+        let lines: Vec<_> = report.lines().collect();
+        assert!(lines[2].starts_with(" 75.00") && lines[2].ends_with(" read"));
+        assert!(lines[3].starts_with(" 25.00") && lines[3].ends_with(" write"));
+        assert!(lines[4].ends_with(" syscall_999"));
+        assert!(lines[6].ends_with(" total"));
+
+        cpu.syscall_mgr.reset_stats();
+        assert!(cpu.syscall_mgr.stats().is_empty());
+    }
+
     #[test]
     fn test_syscall_manager() {
         let mut cpu = Cpu::new();
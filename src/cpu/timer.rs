@@ -0,0 +1,322 @@
+//! Interval Time Counter (AR.ITC) and Interval Timer Match (CR.ITM)
+//!
+//! This module implements the processor's interval timer. AR.ITC either
+//! advances with the cycles the processor takes, one per bundle unless a
+//! timing model charges more, or is derived from host
+//! monotonic time scaled to a configurable frequency. The CR.ITM comparison
+//! is the same in both modes. In chaos mode each match is held back by a
+//! seeded random number of ticks, and in instruction-count mode a bundle
+//! counts as the jittered number of cycles it took.
+//!
+//! Host time is read through a [`Clock`], the host's own monotonic clock
+//! unless another is installed with [`IntervalTimer::set_clock`], so host
+//! time mode can be driven step by step.
+
+use crate::chaos::Chaos;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+/// Nanoseconds per second
+const NANOS_PER_SEC: u128 = 1_000_000_000;
+
+/// Source of AR.ITC time
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimerMode {
+    /// ITC advances a fixed number of ticks per cycle
+    Instructions {
+        /// Ticks added for each cycle; a bundle takes one cycle unless a
+        /// timing model charges more
+        ticks_per_bundle: u64,
+    },
+    /// ITC follows host monotonic time
+    HostTime {
+        /// ITC frequency in Hz
+        frequency: u64,
+    },
+}
+
+impl Default for TimerMode {
+    fn default() -> Self {
+        TimerMode::Instructions {
+            ticks_per_bundle: 1,
+        }
+    }
+}
+
+/// Monotonic time source of host time mode
+pub trait Clock: Send + fmt::Debug {
+    /// Time elapsed since a fixed origin of the clock's choosing
+    fn now(&self) -> Duration;
+}
+
+/// The host's monotonic clock
+#[derive(Debug, Clone, Copy)]
+pub struct HostClock {
+    /// Origin of the readings
+    origin: Instant,
+}
+
+impl Default for HostClock {
+    fn default() -> Self {
+        Self {
+            origin: Instant::now(),
+        }
+    }
+}
+
+impl Clock for HostClock {
+    fn now(&self) -> Duration {
+        self.origin.elapsed()
+    }
+}
+
+/// Interval timer state
+#[derive(Debug)]
+pub struct IntervalTimer {
+    /// Time source
+    mode: TimerMode,
+    /// ITC value at the last write or mode change
+    base: u64,
+    /// Cycles taken since `base`
+    cycles: u64,
+    /// Source of host time
+    clock: Box<dyn Clock>,
+    /// Clock reading at the last write or mode change
+    epoch: Duration,
+    /// Match value (CR.ITM)
+    itm: u64,
+    /// Whether the match has already fired since ITM was written
+    fired: bool,
+    /// Generator of match delays, in chaos mode
+    jitter: Option<Chaos>,
+    /// Ticks past ITM the current match is held back
+    delay: u64,
+}
+
+impl Default for IntervalTimer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl IntervalTimer {
+    /// Create a new timer counting cycles
+    pub fn new() -> Self {
+        Self {
+            mode: TimerMode::default(),
+            base: 0,
+            cycles: 0,
+            clock: Box::new(HostClock::default()),
+            epoch: Duration::ZERO,
+            itm: 0,
+            fired: true,
+            jitter: None,
+            delay: 0,
+        }
+    }
+
+    /// Get the time source
+    pub fn mode(&self) -> TimerMode {
+        self.mode
+    }
+
+    /// Read host time from `clock`, keeping the current ITC value
+    pub fn set_clock(&mut self, clock: Box<dyn Clock>) {
+        let itc = self.read_itc();
+        self.clock = clock;
+        self.write_itc(itc);
+    }
+
+    /// Change the time source, keeping the current ITC value
+    pub fn set_mode(&mut self, mode: TimerMode) {
+        let itc = self.read_itc();
+        self.mode = mode;
+        self.write_itc(itc);
+    }
+
+    /// Read AR.ITC
+    pub fn read_itc(&self) -> u64 {
+        let elapsed = match self.mode {
+            TimerMode::Instructions { ticks_per_bundle } => {
+                self.cycles.wrapping_mul(ticks_per_bundle)
+            }
+            TimerMode::HostTime { frequency } => {
+                let nanos = self.clock.now().saturating_sub(self.epoch).as_nanos();
+                (nanos * frequency as u128 / NANOS_PER_SEC) as u64
+            }
+        };
+        self.base.wrapping_add(elapsed)
+    }
+
+    /// Write AR.ITC
+    pub fn write_itc(&mut self, value: u64) {
+        self.base = value;
+        self.cycles = 0;
+        self.epoch = self.clock.now();
+    }
+
+    /// Read CR.ITM
+    pub fn itm(&self) -> u64 {
+        self.itm
+    }
+
+    /// Write CR.ITM and arm the match
+    pub fn set_itm(&mut self, value: u64) {
+        self.itm = value;
+        self.fired = false;
+        self.delay = self.jitter.as_mut().map_or(0, Chaos::delay);
+    }
+
+    /// Hold each match back by delays from `jitter`, or fire on time
+    pub fn set_jitter(&mut self, jitter: Option<Chaos>) {
+        self.jitter = jitter;
+        self.delay = 0;
+    }
+
+    /// Account for `cycles` the processor took
+    pub fn advance(&mut self, cycles: u64) {
+        self.cycles = self.cycles.wrapping_add(cycles);
+    }
+
+    /// Check whether ITC has reached ITM
+    ///
+    /// Returns true once per write of ITM. ITC may step past ITM between
+    /// checks, so the comparison is done on the wrapping distance.
+    pub fn poll(&mut self) -> bool {
+        if self.fired {
+            return false;
+        }
+        let past = self.read_itc().wrapping_sub(self.itm) as i64;
+        if past < 0 || (past as u64) < self.delay {
+            return false;
+        }
+        self.fired = true;
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::sync::Arc;
+
+    /// Clock the test moves by hand, in nanoseconds
+    #[derive(Debug, Clone, Default)]
+    struct ManualClock(Arc<AtomicU64>);
+
+    impl ManualClock {
+        fn advance(&self, nanos: u64) {
+            self.0.fetch_add(nanos, Ordering::Relaxed);
+        }
+    }
+
+    impl Clock for ManualClock {
+        fn now(&self) -> Duration {
+            Duration::from_nanos(self.0.load(Ordering::Relaxed))
+        }
+    }
+
+    #[test]
+    fn test_instruction_mode() {
+        let mut timer = IntervalTimer::new();
+        timer.set_mode(TimerMode::Instructions {
+            ticks_per_bundle: 4,
+        });
+
+        timer.advance(10);
+        assert_eq!(timer.read_itc(), 40);
+
+        timer.write_itc(1000);
+        timer.advance(1);
+        assert_eq!(timer.read_itc(), 1004);
+    }
+
+    #[test]
+    fn test_host_mode() {
+        let clock = ManualClock::default();
+        let mut timer = IntervalTimer::new();
+        timer.set_clock(Box::new(clock.clone()));
+        timer.write_itc(500);
+        timer.set_mode(TimerMode::HostTime {
+            frequency: 1_000_000,
+        });
+
+        // Retired bundles do not advance host time
+        timer.advance(1_000_000);
+        assert_eq!(timer.read_itc(), 500);
+
+        clock.advance(5_000_000);
+        assert_eq!(timer.read_itc(), 5_500);
+
+        // Switching clocks keeps the count
+        timer.set_clock(Box::new(ManualClock::default()));
+        assert_eq!(timer.read_itc(), 5_500);
+    }
+
+    #[test]
+    fn test_match() {
+        for mode in [
+            TimerMode::Instructions {
+                ticks_per_bundle: 3,
+            },
+            TimerMode::HostTime {
+                frequency: 1_000_000_000,
+            },
+        ] {
+            let clock = ManualClock::default();
+            let mut timer = IntervalTimer::new();
+            timer.set_clock(Box::new(clock.clone()));
+            timer.set_mode(mode);
+
+            // Not armed until ITM is written
+            assert!(!timer.poll());
+
+            timer.set_itm(timer.read_itc() + 3_000_000);
+            assert!(!timer.poll());
+
+            // Step to just short of ITM in either mode, then onto it and
+            // fire exactly once
+            timer.advance(999_999);
+            clock.advance(2_999_999);
+            assert!(!timer.poll());
+            timer.advance(1);
+            clock.advance(1);
+            assert!(timer.poll());
+            assert!(!timer.poll());
+
+            // A match value in the past fires immediately
+            timer.set_itm(0);
+            assert!(timer.poll());
+        }
+    }
+
+    #[test]
+    fn test_jitter() {
+        let fire_at = |seed| {
+            let mut timer = IntervalTimer::new();
+            timer.set_jitter(Some(Chaos::new(seed, 100)));
+            timer.set_itm(50);
+            (1..).find(|_| {
+                timer.advance(1);
+                timer.poll()
+            })
+        };
+        let first = fire_at(9).unwrap();
+        assert!((50..=150).contains(&first));
+        assert_eq!(fire_at(9), Some(first));
+        assert!((1..10).any(|seed| fire_at(seed) != Some(first)));
+    }
+
+    #[test]
+    fn test_match_across_wrap() {
+        let mut timer = IntervalTimer::new();
+        timer.write_itc(u64::MAX - 1);
+        timer.set_itm(2);
+        assert!(!timer.poll());
+
+        timer.advance(4);
+        assert_eq!(timer.read_itc(), 2);
+        assert!(timer.poll());
+    }
+}
@@ -0,0 +1,351 @@
+//! Cycle-approximate timing model
+//!
+//! The run loop normally counts a cycle per bundle. With a timing model
+//! installed it charges what an Itanium 2 would take instead, roughly:
+//!
+//! - Up to two bundles issue per cycle. A stop ends the cycle, so the
+//!   instructions after a mid-bundle stop issue in the next one, and so
+//!   does the bundle after a taken branch.
+//! - An instruction group's results are assumed to be needed by the next
+//!   group, so a group stalls the pipeline for the latency of its slowest
+//!   instruction past the first cycle: floating-point groups cost more
+//!   than integer ones.
+//! - A load that misses L1 stalls for the latency of the cache level that
+//!   served it, or of memory, beyond an L1 hit. The existing cache
+//!   hierarchy decides which level that was.
+//! - Each register the RSE spills or fills takes a cycle of memory
+//!   traffic, and a mispredicted branch flushes the pipeline.
+//! - A load served by a transformed region's backing store (see
+//!   `memory::transform`) waits for the transform on top of memory.
+//!
+//! Cycles go to AR.ITC, the PMU cycle counter and the dispersal model's
+//! clock alike, so timing-sensitive guest code, such as a delay loop
+//! calibrated against ITC, sees plausible numbers. Like the dispersal
+//! model, the timing model does not change what the guest computes.
+
+use crate::cpu::dispersal::BUNDLES_PER_CYCLE;
+use crate::decoder::Unit;
+use serde::Deserialize;
+use std::fmt;
+
+/// Latencies in cycles, Itanium 2's unless configured otherwise
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Latencies {
+    /// Integer, multimedia and long-immediate instructions (A, I and X
+    /// units)
+    pub integer: u64,
+    /// Floating-point instructions (F unit)
+    pub floating_point: u64,
+    /// Branches (B unit)
+    pub branch: u64,
+    /// Memory instructions hitting L1 (M unit)
+    pub l1: u64,
+    /// Loads served by L2
+    pub l2: u64,
+    /// Loads served by L3
+    pub l3: u64,
+    /// Loads served by memory
+    pub memory: u64,
+    /// Each register spilled or filled by the RSE
+    pub rse: u64,
+    /// Pipeline flush after a mispredicted branch
+    pub mispredict: u64,
+    /// Decoding a line from a transformed region's backing store
+    pub transform: u64,
+}
+
+impl Default for Latencies {
+    fn default() -> Self {
+        Self {
+            integer: 1,
+            floating_point: 4,
+            branch: 1,
+            l1: 1,
+            l2: 5,
+            l3: 12,
+            memory: 150,
+            rse: 1,
+            mispredict: 6,
+            transform: 20,
+        }
+    }
+}
+
+impl Latencies {
+    /// Latency of an instruction on `unit`
+    fn unit(&self, unit: Unit) -> u64 {
+        match unit {
+            Unit::A | Unit::I | Unit::X => self.integer,
+            Unit::M => self.l1,
+            Unit::F => self.floating_point,
+            Unit::B => self.branch,
+            Unit::L => 0,
+        }
+    }
+}
+
+/// Memory activity during a bundle
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MemoryEvents {
+    /// Demand reads that missed L1
+    pub l1_misses: u64,
+    /// Demand reads that missed L1 and L2
+    pub l2_misses: u64,
+    /// Demand reads that missed every cache level
+    pub memory_reads: u64,
+    /// Registers the RSE spilled or filled
+    pub rse_traffic: u64,
+    /// Demand reads decoded from a transformed region's backing store
+    pub transformed_reads: u64,
+}
+
+/// Cycle counts and where the stalls came from
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TimingStats {
+    /// Cycles, stalls included
+    pub cycles: u64,
+    /// Bundles timed
+    pub bundles: u64,
+    /// Instructions timed
+    pub instructions: u64,
+    /// Cycles spent waiting on results of the previous instruction group
+    pub dependency_stalls: u64,
+    /// Cycles spent waiting on L2, L3 and memory
+    pub cache_stalls: u64,
+    /// Cycles spent on RSE spills and fills
+    pub rse_stalls: u64,
+    /// Cycles lost to mispredicted branches
+    pub branch_stalls: u64,
+    /// Cycles spent decoding transformed memory
+    pub transform_stalls: u64,
+}
+
+impl TimingStats {
+    /// Instructions per cycle
+    pub fn ipc(&self) -> f64 {
+        if self.cycles == 0 {
+            return 0.0;
+        }
+        self.instructions as f64 / self.cycles as f64
+    }
+}
+
+impl fmt::Display for TimingStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "cycles {}, bundles {}, instructions {}, ipc {:.2}",
+            self.cycles,
+            self.bundles,
+            self.instructions,
+            self.ipc()
+        )?;
+        writeln!(
+            f,
+            "stall cycles: dependency {}, cache {}, rse {}, branch {}, transform {}",
+            self.dependency_stalls,
+            self.cache_stalls,
+            self.rse_stalls,
+            self.branch_stalls,
+            self.transform_stalls
+        )
+    }
+}
+
+/// Cycle-approximate timing of executed bundles
+#[derive(Debug, Clone)]
+pub struct TimingModel {
+    /// Latencies charged
+    latencies: Latencies,
+    /// Bundles issued in the current cycle; 0 when no cycle is open
+    bundles: u32,
+    /// Whether the current bundle has issued
+    in_bundle: bool,
+    /// Slowest instruction of the current instruction group
+    group: u64,
+    /// Cycles charged to the current bundle so far
+    cost: u64,
+    /// Counters
+    stats: TimingStats,
+}
+
+impl Default for TimingModel {
+    fn default() -> Self {
+        Self::new(Latencies::default())
+    }
+}
+
+impl TimingModel {
+    /// Model a machine with `latencies`
+    pub fn new(latencies: Latencies) -> Self {
+        Self {
+            latencies,
+            bundles: 0,
+            in_bundle: false,
+            group: 0,
+            cost: 0,
+            stats: TimingStats::default(),
+        }
+    }
+
+    /// Latencies charged
+    pub fn latencies(&self) -> &Latencies {
+        &self.latencies
+    }
+
+    /// An instruction on `unit` executed, ending its instruction group if
+    /// `stop` is set
+    pub(crate) fn execute(&mut self, unit: Unit, stop: bool) {
+        self.issue();
+        self.stats.instructions += 1;
+        self.group = self.group.max(self.latencies.unit(unit));
+        if stop {
+            self.end_group();
+        }
+    }
+
+    /// The bundle ended, after a taken branch if `taken`, with
+    /// `mispredicts` mispredicted branches and `memory` activity
+    ///
+    /// Returns the cycles the bundle took, which is 0 for a bundle issuing
+    /// alongside the one before it.
+    pub(crate) fn end_bundle(
+        &mut self,
+        taken: bool,
+        mispredicts: u64,
+        memory: MemoryEvents,
+    ) -> u64 {
+        // A bundle that retired no instruction still took an issue slot
+        if !self.in_bundle {
+            self.issue();
+        }
+        self.in_bundle = false;
+        self.stats.bundles += 1;
+        if taken {
+            self.end_group();
+        }
+
+        let latencies = &self.latencies;
+        let beyond_l1 = |latency: u64| latency.saturating_sub(latencies.l1);
+        let l2_hits = memory.l1_misses.saturating_sub(memory.l2_misses);
+        let l3_hits = memory.l2_misses.saturating_sub(memory.memory_reads);
+        let cache = l2_hits * beyond_l1(latencies.l2)
+            + l3_hits * beyond_l1(latencies.l3)
+            + memory.memory_reads * beyond_l1(latencies.memory);
+        let rse = memory.rse_traffic * latencies.rse;
+        let branch = mispredicts * latencies.mispredict;
+        let transform = memory.transformed_reads * latencies.transform;
+        self.stats.cache_stalls += cache;
+        self.stats.rse_stalls += rse;
+        self.stats.branch_stalls += branch;
+        self.stats.transform_stalls += transform;
+
+        let cycles = std::mem::take(&mut self.cost) + cache + rse + branch + transform;
+        self.stats.cycles += cycles;
+        cycles
+    }
+
+    /// Issue into the current cycle, opening one if it is closed or a new
+    /// bundle finds it full
+    fn issue(&mut self) {
+        let full = !self.in_bundle && self.bundles >= BUNDLES_PER_CYCLE;
+        if self.bundles == 0 || full {
+            self.cost += 1;
+            self.bundles = 1;
+        } else if !self.in_bundle {
+            self.bundles += 1;
+        }
+        self.in_bundle = true;
+    }
+
+    /// End the instruction group and the cycle it issued in
+    fn end_group(&mut self) {
+        let stall = self.group.saturating_sub(1);
+        self.stats.dependency_stalls += stall;
+        self.cost += stall;
+        self.group = 0;
+        self.bundles = 0;
+    }
+
+    /// Counters since the model was installed or last reset
+    pub fn stats(&self) -> &TimingStats {
+        &self.stats
+    }
+
+    /// Zero the counters
+    pub fn reset_stats(&mut self) {
+        self.stats = TimingStats::default();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Time a bundle of `units` with stops after the slots in `stops`
+    fn bundle(model: &mut TimingModel, units: [Unit; 3], stops: [bool; 3]) -> u64 {
+        for (unit, stop) in units.into_iter().zip(stops) {
+            model.execute(unit, stop);
+        }
+        model.end_bundle(false, 0, MemoryEvents::default())
+    }
+
+    #[test]
+    fn test_issue_width() {
+        const MII: [Unit; 3] = [Unit::M, Unit::I, Unit::I];
+        let mut model = TimingModel::default();
+
+        // Two bundles without stops share a cycle
+        assert_eq!(bundle(&mut model, MII, [false; 3]), 1);
+        assert_eq!(bundle(&mut model, MII, [false; 3]), 0);
+        assert_eq!(bundle(&mut model, MII, [false; 3]), 1);
+
+        // A stop at the end of a bundle closes the cycle
+        assert_eq!(bundle(&mut model, MII, [false, false, true]), 0);
+        assert_eq!(bundle(&mut model, MII, [false, false, true]), 1);
+        // and one in the middle splits it
+        assert_eq!(bundle(&mut model, MII, [true, false, false]), 2);
+        assert_eq!(model.stats().cycles, 5);
+        assert_eq!(model.stats().instructions, 18);
+    }
+
+    #[test]
+    fn test_stalls() {
+        let mut model = TimingModel::default();
+
+        // A floating-point group holds up the next one
+        let cycles = bundle(
+            &mut model,
+            [Unit::M, Unit::F, Unit::I],
+            [false, false, true],
+        );
+        assert_eq!(cycles, 4);
+        assert_eq!(model.stats().dependency_stalls, 3);
+
+        // Misses stall for the level that served them
+        model.execute(Unit::M, true);
+        let memory = MemoryEvents {
+            l1_misses: 3,
+            l2_misses: 2,
+            memory_reads: 1,
+            rse_traffic: 2,
+            transformed_reads: 1,
+        };
+        assert_eq!(
+            model.end_bundle(false, 0, memory),
+            1 + 4 + 11 + 149 + 2 + 20
+        );
+
+        // A taken, mispredicted branch ends the cycle and flushes
+        model.execute(Unit::B, false);
+        assert_eq!(model.end_bundle(true, 1, MemoryEvents::default()), 1 + 6);
+        let stats = model.stats();
+        assert_eq!(
+            (stats.cache_stalls, stats.rse_stalls, stats.branch_stalls),
+            (164, 2, 6)
+        );
+        assert_eq!(stats.transform_stalls, 20);
+        assert!(stats.to_string().contains("dependency 3, cache 164"));
+    }
+}
@@ -0,0 +1,360 @@
+//! Translation lookaside buffer
+//!
+//! Translations are tagged with the region ID (RID) of the region register
+//! that was current when they were inserted, and a lookup only matches
+//! entries whose RID is the one the address's region register holds now.
+//! Guests switch address spaces by rewriting region registers, and because
+//! nothing is purged on a switch, a process that comes back to a RID finds
+//! its translations still there, as with ASIDs on other architectures.
+//! Purges are explicit: one page of one RID (`ptc.l`), everything of a RID,
+//! or the whole TLB (`ptc.e`).
+//!
+//! A full TLB evicts its oldest entry, or in chaos mode a pseudo-random one.
+
+use crate::chaos::Chaos;
+use crate::cpu::registers::rr::{NUM_RR, RID_MASK};
+use crate::memory::Permissions;
+use crate::EmulatorError;
+use std::collections::VecDeque;
+
+/// Maximum number of TLB entries
+pub const TLB_ENTRIES: usize = 128;
+
+/// Smallest supported page size (4KB)
+pub const MIN_PAGE_SHIFT: u8 = 12;
+
+/// Largest supported page size (4GB)
+pub const MAX_PAGE_SHIFT: u8 = 32;
+
+/// Bits of a virtual address within its region
+const REGION_OFFSET_MASK: u64 = (1 << 61) - 1;
+
+/// A translation for one page of one region ID
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TlbEntry {
+    /// Region ID the translation belongs to
+    pub rid: u64,
+    /// Virtual address of the page within its region
+    pub vaddr: u64,
+    /// Physical address of the page
+    pub paddr: u64,
+    /// Log2 of the page size
+    pub page_shift: u8,
+    /// Access rights
+    pub permissions: Permissions,
+}
+
+impl TlbEntry {
+    /// Whether the entry translates `vaddr` in region `rid`
+    fn covers(&self, rid: u64, vaddr: u64) -> bool {
+        self.rid == rid && page(vaddr, self.page_shift) == self.vaddr
+    }
+
+    /// Whether the entry overlaps the page of `1 << page_shift` bytes at
+    /// `vaddr` in region `rid`
+    fn overlaps(&self, rid: u64, vaddr: u64, page_shift: u8) -> bool {
+        let shift = self.page_shift.max(page_shift);
+        self.rid == rid && page(vaddr, shift) == page(self.vaddr, shift)
+    }
+}
+
+/// Page-aligned address within its region
+fn page(vaddr: u64, page_shift: u8) -> u64 {
+    vaddr & REGION_OFFSET_MASK & !((1 << page_shift) - 1)
+}
+
+/// TLB activity counters
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TlbStats {
+    /// Lookups that found a translation
+    pub hits: u64,
+    /// Lookups that found none
+    pub misses: u64,
+    /// Translations inserted
+    pub inserts: u64,
+    /// Entries removed by purges
+    pub purged: u64,
+    /// Entries evicted to make room for new ones
+    pub capacity_evictions: u64,
+    /// Region register writes that changed the region ID
+    pub rid_switches: u64,
+    /// Switches to a region ID that still had translations
+    pub rid_reuses: u64,
+}
+
+impl TlbStats {
+    /// Fraction of lookups that hit
+    pub fn hit_rate(&self) -> f64 {
+        ratio(self.hits, self.hits + self.misses)
+    }
+
+    /// Fraction of region ID switches that found translations to reuse
+    pub fn reuse_rate(&self) -> f64 {
+        ratio(self.rid_reuses, self.rid_switches)
+    }
+}
+
+/// `part / whole`, or zero when `whole` is zero
+fn ratio(part: u64, whole: u64) -> f64 {
+    if whole == 0 {
+        0.0
+    } else {
+        part as f64 / whole as f64
+    }
+}
+
+/// Translation lookaside buffer
+#[derive(Debug, Default)]
+pub struct Tlb {
+    /// Entries, oldest first
+    entries: VecDeque<TlbEntry>,
+    /// Activity counters
+    stats: TlbStats,
+    /// Generator of victims, if they are chosen at random
+    victims: Option<Chaos>,
+}
+
+impl Tlb {
+    /// Create an empty TLB
+    pub fn new() -> Self {
+        Self {
+            entries: VecDeque::with_capacity(TLB_ENTRIES),
+            stats: TlbStats::default(),
+            victims: None,
+        }
+    }
+
+    /// Evict pseudo-random entries from a full TLB, from a generator seeded
+    /// by `seed`, or the oldest entry with `None`
+    pub fn set_replacement_seed(&mut self, seed: Option<u64>) {
+        self.victims = seed.map(|seed| Chaos::new(seed, 0));
+    }
+
+    /// Insert a translation, replacing any it overlaps in the same region ID
+    ///
+    /// When the TLB is full the oldest entry is evicted, or a random one if
+    /// a replacement seed is set.
+    pub fn insert(&mut self, entry: TlbEntry) -> Result<(), EmulatorError> {
+        if !(MIN_PAGE_SHIFT..=MAX_PAGE_SHIFT).contains(&entry.page_shift) {
+            return Err(EmulatorError::MemoryError(format!(
+                "Unsupported page size: 2^{}",
+                entry.page_shift
+            )));
+        }
+        let entry = TlbEntry {
+            vaddr: page(entry.vaddr, entry.page_shift),
+            paddr: entry.paddr & !((1 << entry.page_shift) - 1),
+            ..entry
+        };
+        self.entries
+            .retain(|e| !e.overlaps(entry.rid, entry.vaddr, entry.page_shift));
+        if self.entries.len() >= TLB_ENTRIES {
+            let victim = match &mut self.victims {
+                Some(chaos) => (chaos.next_u64() % self.entries.len() as u64) as usize,
+                None => 0,
+            };
+            self.entries.remove(victim);
+            self.stats.capacity_evictions += 1;
+        }
+        self.entries.push_back(entry);
+        self.stats.inserts += 1;
+        Ok(())
+    }
+
+    /// Find the translation of `vaddr` in region `rid`, counting the outcome
+    pub fn lookup(&mut self, rid: u64, vaddr: u64) -> Option<TlbEntry> {
+        let found = self.find(rid, vaddr);
+        if found.is_some() {
+            self.stats.hits += 1;
+        } else {
+            self.stats.misses += 1;
+        }
+        found
+    }
+
+    /// Find the translation of `vaddr` in region `rid` without counting it
+    pub fn find(&self, rid: u64, vaddr: u64) -> Option<TlbEntry> {
+        self.entries.iter().find(|e| e.covers(rid, vaddr)).copied()
+    }
+
+    /// Physical address of `vaddr` in region `rid`, if translated
+    pub fn translate(&mut self, rid: u64, vaddr: u64) -> Option<u64> {
+        let entry = self.lookup(rid, vaddr)?;
+        Some(entry.paddr | (vaddr & ((1 << entry.page_shift) - 1)))
+    }
+
+    /// Purge the translations of region `rid` that overlap the page of
+    /// `1 << page_shift` bytes at `vaddr`, returning how many were removed
+    pub fn purge(&mut self, rid: u64, vaddr: u64, page_shift: u8) -> usize {
+        self.remove(|e| e.overlaps(rid, vaddr, page_shift))
+    }
+
+    /// Purge every translation of region `rid`, e.g. when the RID is
+    /// recycled for a new address space
+    pub fn purge_rid(&mut self, rid: u64) -> usize {
+        self.remove(|e| e.rid == rid)
+    }
+
+    /// Purge every translation
+    pub fn purge_all(&mut self) -> usize {
+        self.remove(|_| true)
+    }
+
+    /// Remove the entries matching `purged`, counting them
+    fn remove(&mut self, purged: impl Fn(&TlbEntry) -> bool) -> usize {
+        let before = self.entries.len();
+        self.entries.retain(|e| !purged(e));
+        let removed = before - self.entries.len();
+        self.stats.purged += removed as u64;
+        removed
+    }
+
+    /// Record a region register write that changed its region ID
+    pub fn note_switch(&mut self, rid: u64) {
+        self.stats.rid_switches += 1;
+        if self.entries.iter().any(|e| e.rid == rid) {
+            self.stats.rid_reuses += 1;
+        }
+    }
+
+    /// Number of entries held
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Whether the TLB holds no entries
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Entries, oldest first
+    pub fn entries(&self) -> impl Iterator<Item = &TlbEntry> {
+        self.entries.iter()
+    }
+
+    /// Activity counters
+    pub fn stats(&self) -> TlbStats {
+        self.stats
+    }
+
+    /// Reset the activity counters
+    pub fn reset_stats(&mut self) {
+        self.stats = TlbStats::default();
+    }
+}
+
+/// Region IDs to install in the region registers on a context switch
+///
+/// Regions left unset keep their current RID, so kernel regions shared by
+/// every process need not be listed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct AddressSpace {
+    /// RID for each region, if it changes
+    rids: [Option<u64>; NUM_RR],
+}
+
+impl AddressSpace {
+    /// Create an address space that changes no region
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the RID of a region
+    pub fn with_rid(mut self, region: usize, rid: u64) -> Result<Self, EmulatorError> {
+        if rid > RID_MASK {
+            return Err(EmulatorError::RegisterError(format!(
+                "Region ID {:#x} does not fit in 24 bits",
+                rid
+            )));
+        }
+        let slot = self.rids.get_mut(region).ok_or_else(|| {
+            EmulatorError::RegisterError(format!("Invalid region register index: {}", region))
+        })?;
+        *slot = Some(rid);
+        Ok(self)
+    }
+
+    /// RID of a region, if set
+    pub fn rid(&self, region: usize) -> Option<u64> {
+        self.rids.get(region).copied().flatten()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(rid: u64, vaddr: u64, paddr: u64) -> TlbEntry {
+        TlbEntry {
+            rid,
+            vaddr,
+            paddr,
+            page_shift: 14,
+            permissions: Permissions::ReadWrite,
+        }
+    }
+
+    #[test]
+    fn test_rid_tagging() {
+        let mut tlb = Tlb::new();
+        tlb.insert(entry(1, 0x4000, 0x10_0000)).unwrap();
+        tlb.insert(entry(2, 0x4000, 0x20_0000)).unwrap();
+
+        // The same address translates differently in each region ID, and
+        // the region number bits are not part of the tag
+        assert_eq!(tlb.translate(1, 0x4123), Some(0x10_0123));
+        assert_eq!(tlb.translate(2, 0x2000_0000_0000_7FFF), Some(0x20_3FFF));
+        assert_eq!(tlb.translate(3, 0x4123), None);
+        assert_eq!(tlb.translate(1, 0x8000), None);
+        assert_eq!(tlb.stats().hits, 2);
+        assert_eq!(tlb.stats().misses, 2);
+
+        // Switching to a RID with entries counts as a reuse
+        tlb.note_switch(2);
+        tlb.note_switch(7);
+        assert_eq!((tlb.stats().rid_switches, tlb.stats().rid_reuses), (2, 1));
+
+        // Purges only touch the named region ID
+        assert_eq!(tlb.purge(1, 0x5000, 12), 1);
+        assert_eq!(tlb.find(2, 0x4000).map(|e| e.paddr), Some(0x20_0000));
+        assert_eq!(tlb.purge_rid(2), 1);
+        assert!(tlb.is_empty());
+        assert!(tlb
+            .insert(TlbEntry {
+                page_shift: 8,
+                ..entry(1, 0, 0)
+            })
+            .is_err());
+    }
+
+    #[test]
+    fn test_replacement() {
+        let mut tlb = Tlb::new();
+        tlb.insert(entry(1, 0x4000, 0x10_0000)).unwrap();
+        tlb.insert(entry(1, 0x4000, 0x30_0000)).unwrap();
+        assert_eq!(tlb.len(), 1);
+        assert_eq!(tlb.find(1, 0x4000).unwrap().paddr, 0x30_0000);
+
+        for page in 0..TLB_ENTRIES as u64 {
+            tlb.insert(entry(5, page << 14, 0)).unwrap();
+        }
+        assert_eq!(tlb.len(), TLB_ENTRIES);
+        assert_eq!(tlb.stats().capacity_evictions, 1);
+        assert!(tlb.find(1, 0x4000).is_none());
+        assert_eq!(tlb.purge_all(), TLB_ENTRIES);
+
+        // With a seed the victim is random but repeatable
+        let survivors = |seed| {
+            let mut tlb = Tlb::new();
+            tlb.set_replacement_seed(Some(seed));
+            for page in 0..TLB_ENTRIES as u64 + 16 {
+                tlb.insert(entry(5, page << 14, 0)).unwrap();
+            }
+            (0..16)
+                .filter(|page| tlb.find(5, page << 14).is_some())
+                .collect::<Vec<u64>>()
+        };
+        assert!(!survivors(1).is_empty());
+        assert_eq!(survivors(1), survivors(1));
+    }
+}
@@ -0,0 +1,171 @@
+//! Unaligned data references
+//!
+//! Itanium raises an unaligned data reference fault for a load or store
+//! that is not naturally aligned, and Linux/ia64 handles the fault by
+//! performing the access in pieces and resuming the program, logging a
+//! warning. Guests written for that kernel may rely on the fixup. The
+//! [`AlignmentPolicy`] selects between the emulator's traditional behaviour
+//! of performing the access as is, raising the fault, and fixing it up the
+//! way the kernel does while counting each fixup by instruction in
+//! [`UnalignedFixups`].
+//!
+//! The policy applies to integer loads and stores, ordered and speculative
+//! forms included. With PSR.ac set, the guest asks for the fault and gets
+//! it whatever the policy. Semaphores, register spills and fills always
+//! fault when unaligned, since they cannot be done in pieces.
+
+use crate::debugger::SymbolTable;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+/// What happens on an unaligned load or store
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AlignmentPolicy {
+    /// Perform the access as if it were aligned
+    #[default]
+    Allow,
+    /// Raise an unaligned data reference fault
+    Fault,
+    /// Perform the access byte by byte and count it, as the OS would
+    Fixup,
+}
+
+/// Unaligned accesses fixed up for one instruction
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UnalignedSite {
+    /// Bundle address of the instruction
+    pub ip: u64,
+    /// Slot of the instruction
+    pub slot: u8,
+    /// Access size in bytes
+    pub size: usize,
+    /// Loads fixed up
+    pub loads: u64,
+    /// Stores fixed up
+    pub stores: u64,
+    /// Data address of the latest access
+    pub last_addr: u64,
+}
+
+impl UnalignedSite {
+    /// Accesses fixed up
+    pub fn count(&self) -> u64 {
+        self.loads + self.stores
+    }
+}
+
+/// Unaligned accesses fixed up, by instruction
+#[derive(Debug, Clone, Default)]
+pub struct UnalignedFixups {
+    /// Sites by bundle address and slot
+    sites: BTreeMap<(u64, u8), UnalignedSite>,
+}
+
+impl UnalignedFixups {
+    /// Count a fixup of the instruction at `ip`, `slot`
+    pub(crate) fn record(&mut self, ip: u64, slot: u8, addr: u64, size: usize, store: bool) {
+        let site = self.sites.entry((ip, slot)).or_insert(UnalignedSite {
+            ip,
+            slot,
+            size,
+            loads: 0,
+            stores: 0,
+            last_addr: addr,
+        });
+        if store {
+            site.stores += 1;
+        } else {
+            site.loads += 1;
+        }
+        site.last_addr = addr;
+    }
+
+    /// Offending instructions, most fixups first
+    pub fn sites(&self) -> Vec<&UnalignedSite> {
+        let mut sites: Vec<_> = self.sites.values().collect();
+        sites.sort_by_key(|site| std::cmp::Reverse(site.count()));
+        sites
+    }
+
+    /// Accesses fixed up in all
+    pub fn total(&self) -> u64 {
+        self.sites.values().map(UnalignedSite::count).sum()
+    }
+
+    /// Whether no access was fixed up
+    pub fn is_empty(&self) -> bool {
+        self.sites.is_empty()
+    }
+
+    /// Forget the fixups counted so far
+    pub fn clear(&mut self) {
+        self.sites.clear();
+    }
+
+    /// Format the offending instructions as a report, most fixups first
+    ///
+    /// Addresses are shown with their symbols where the table has one.
+    pub fn report(&self, symbols: &SymbolTable) -> String {
+        let mut out = String::new();
+        if self.is_empty() {
+            return out;
+        }
+        writeln!(
+            out,
+            "unaligned accesses fixed up: {} by {} instructions",
+            self.total(),
+            self.sites.len()
+        )
+        .unwrap();
+        for site in self.sites() {
+            write!(
+                out,
+                "  {:#x} slot {}: {} ({} loads, {} stores), {}-byte, last at {:#x}",
+                site.ip,
+                site.slot,
+                site.count(),
+                site.loads,
+                site.stores,
+                site.size,
+                site.last_addr
+            )
+            .unwrap();
+            if let Some((symbol, offset)) = symbols.lookup(site.ip) {
+                write!(out, " in {}+{:#x}", symbol.name, offset).unwrap();
+            }
+            out.push('\n');
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_report() {
+        let mut fixups = UnalignedFixups::default();
+        assert_eq!(fixups.report(&SymbolTable::new()), "");
+        fixups.record(0x1000, 0, 0x2001, 4, false);
+        for addr in [0x3002, 0x3006, 0x300A] {
+            fixups.record(0x1010, 2, addr, 8, true);
+        }
+        assert_eq!(fixups.total(), 4);
+        let sites = fixups.sites();
+        assert_eq!(
+            (sites[0].ip, sites[0].stores, sites[0].last_addr),
+            (0x1010, 3, 0x300A)
+        );
+
+        let mut symbols = SymbolTable::new();
+        symbols.add("memcpy", 0x1000, 0x100);
+        let report = fixups.report(&symbols);
+        assert!(report.starts_with("unaligned accesses fixed up: 4 by 2 instructions\n"));
+        let lines: Vec<_> = report.lines().collect();
+        assert!(lines[1].contains("0x1010 slot 2: 3 (0 loads, 3 stores)"));
+        assert!(lines[1].ends_with("in memcpy+0x10"));
+    }
+}
@@ -0,0 +1,355 @@
+//! Virtual hash page table (VHPT) walker
+//!
+//! On a TLB miss the hardware walker looks for the translation in the VHPT,
+//! a table the operating system keeps in memory at PTA.base, and inserts
+//! what it finds. PTA.ve turns the walker on, together with the ve bit of
+//! the address's region register, and PTA.vf picks one of two formats:
+//!
+//! - Short format is a linear table of 8-byte page table entries, one per
+//!   page of the region's page size (RR.ps), indexed by virtual page number.
+//!   Every entry of a region has the same page size and there are no tags.
+//! - Long format is a hash table of 32-byte entries: the PTE, an ITIR word
+//!   giving the entry's own page size, a tag identifying the page, and a
+//!   collision chain pointer. The walker follows the chain from the hashed
+//!   entry until a tag matches, an entry's tag-invalid (ti) bit is set, the
+//!   pointer is zero or [`MAX_CHAIN`] entries were looked at.
+//!
+//! The long-format hash and tag are implementation specific. Here the hash
+//! indexes the table by the virtual page number xor the region ID, and the
+//! tag is the virtual page number xor the region ID shifted to bit 39;
+//! [`thash`] and [`ttag`] compute them, as the instructions of the same
+//! names do, so handlers can fill the table.
+//!
+//! A walk that cannot complete faults like the hardware's: with the walker
+//! off, an alternate TLB fault; with no matching entry, a TLB fault; with
+//! an entry whose present bit is clear, a page not present fault; and with
+//! a table or chain entry outside memory, a VHPT translation fault. Each
+//! is an [`EmulatorError::TlbFault`]. The emulator's memory is not itself
+//! translated, so table and chain addresses index memory directly.
+
+use crate::cpu::interrupts::InterruptVector;
+use crate::cpu::registers::cr::CRIndex;
+use crate::cpu::tlb::TlbEntry;
+use crate::cpu::Cpu;
+use crate::memory::{Memory, Permissions};
+use crate::EmulatorError;
+
+/// Most long-format entries looked at in one walk, so a chain that loops
+/// back on itself ends in a miss
+pub const MAX_CHAIN: usize = 64;
+
+/// Bytes in a long-format entry
+pub const LONG_ENTRY_SIZE: u64 = 32;
+
+/// Tag-invalid bit of a long-format tag
+pub const TAG_INVALID: u64 = 1 << 63;
+
+/// Smallest VHPT, as log2 of its size in bytes
+const MIN_TABLE_SHIFT: u8 = 15;
+
+/// Largest VHPT, as log2 of its size in bytes
+const MAX_TABLE_SHIFT: u8 = 61;
+
+/// Bits of a virtual address within its region
+const REGION_OFFSET_MASK: u64 = (1 << 61) - 1;
+
+/// Physical page number bits of a PTE
+const PPN_MASK: u64 = ((1 << 50) - 1) & !0xFFF;
+
+/// Page table address register (CR.PTA) fields
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Pta {
+    /// Walker enable
+    pub ve: bool,
+    /// Log2 of the table size in bytes
+    pub size: u8,
+    /// Long format
+    pub vf: bool,
+    /// Table base, aligned to the table size
+    pub base: u64,
+}
+
+impl Pta {
+    /// Fields of a CR.PTA value, with ve in bit 0, size in bits 2-7, vf in
+    /// bit 8 and the base in bits 15-63
+    ///
+    /// Sizes outside 2^15 to 2^61 bytes are clamped to that range.
+    pub fn from_bits(bits: u64) -> Self {
+        let size = (((bits >> 2) & 0x3F) as u8).clamp(MIN_TABLE_SHIFT, MAX_TABLE_SHIFT);
+        Self {
+            ve: bits & 1 != 0,
+            size,
+            vf: bits & (1 << 8) != 0,
+            base: bits & !((1 << size) - 1),
+        }
+    }
+
+    /// Mask of an offset within the table
+    fn offset_mask(&self) -> u64 {
+        (1 << self.size) - 1
+    }
+}
+
+/// Region ID of the region of `vaddr`, and the virtual page number of
+/// `vaddr` at the region's page size
+fn region_page(cpu: &Cpu, vaddr: u64) -> Result<(u64, u64), EmulatorError> {
+    let fields = cpu.system_regs.rr.read((vaddr >> 61) as usize)?;
+    Ok((fields.rid, (vaddr & REGION_OFFSET_MASK) >> fields.ps))
+}
+
+/// Address of the VHPT entry for `vaddr` (thash)
+pub fn thash(cpu: &Cpu, vaddr: u64) -> Result<u64, EmulatorError> {
+    let pta = Pta::from_bits(cpu.read_cr(CRIndex::PTA));
+    let (rid, vpn) = region_page(cpu, vaddr)?;
+    let offset = if pta.vf {
+        (vpn ^ rid).wrapping_mul(LONG_ENTRY_SIZE)
+    } else {
+        vpn << 3
+    };
+    Ok(pta.base | (offset & pta.offset_mask()))
+}
+
+/// Tag of the long-format entry for `vaddr` (ttag)
+pub fn ttag(cpu: &Cpu, vaddr: u64) -> Result<u64, EmulatorError> {
+    let (rid, vpn) = region_page(cpu, vaddr)?;
+    Ok(((rid << 39) ^ vpn) & !TAG_INVALID)
+}
+
+/// Permissions of a PTE's access rights field (bits 9-11)
+fn permissions(pte: u64) -> Permissions {
+    match (pte >> 9) & 0x7 {
+        0 => Permissions::Read,
+        1 => Permissions::ReadExecute,
+        2 | 4 | 5 => Permissions::ReadWrite,
+        3 | 6 => Permissions::ReadWriteExecute,
+        _ => Permissions::ReadExecute,
+    }
+}
+
+/// Look `vaddr` up in the VHPT, for an instruction fetch if `instruction`
+///
+/// Returns the translation to insert, or the fault the walk ends in.
+pub fn walk(
+    cpu: &Cpu,
+    memory: &mut Memory,
+    vaddr: u64,
+    instruction: bool,
+) -> Result<TlbEntry, EmulatorError> {
+    let fault = |vector, data| {
+        Err(EmulatorError::TlbFault {
+            addr: vaddr,
+            vector: if instruction { vector } else { data },
+        })
+    };
+    let pta = Pta::from_bits(cpu.read_cr(CRIndex::PTA));
+    let region = cpu.system_regs.rr.read((vaddr >> 61) as usize)?;
+    if !pta.ve || !region.ve {
+        return fault(
+            InterruptVector::AltInstructionTLBFault,
+            InterruptVector::AltDataTLBFault,
+        );
+    }
+    let unreachable = || {
+        Err(EmulatorError::TlbFault {
+            addr: vaddr,
+            vector: InterruptVector::VirtualMemoryFault,
+        })
+    };
+
+    let mut addr = thash(cpu, vaddr)?;
+    let (pte, page_shift) = if pta.vf {
+        let tag = ttag(cpu, vaddr)?;
+        let mut found = None;
+        for _ in 0..MAX_CHAIN {
+            let mut words = [0u64; 4];
+            for (i, word) in words.iter_mut().enumerate() {
+                match memory.read_u64(addr + 8 * i as u64) {
+                    Ok(value) => *word = value,
+                    Err(_) => return unreachable(),
+                }
+            }
+            let [pte, itir, entry_tag, next] = words;
+            if entry_tag & TAG_INVALID != 0 {
+                break;
+            }
+            if entry_tag == tag {
+                found = Some((pte, ((itir >> 2) & 0x3F) as u8));
+                break;
+            }
+            if next == 0 {
+                break;
+            }
+            addr = next;
+        }
+        match found {
+            Some(entry) => entry,
+            None => {
+                return fault(
+                    InterruptVector::InstructionTLBFault,
+                    InterruptVector::DataTLBFault,
+                )
+            }
+        }
+    } else {
+        match memory.read_u64(addr) {
+            Ok(pte) => (pte, region.ps),
+            Err(_) => return unreachable(),
+        }
+    };
+
+    if pte & 1 == 0 {
+        return fault(
+            InterruptVector::PageNotPresentFault,
+            InterruptVector::PageNotPresentFault,
+        );
+    }
+    Ok(TlbEntry {
+        rid: region.rid,
+        vaddr,
+        paddr: pte & PPN_MASK,
+        page_shift,
+        permissions: permissions(pte),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TABLE: u64 = 0x10_0000;
+    const VADDR: u64 = 0x2000_0000_0040_5123;
+
+    /// CPU with region 1 on RID 0x42 with 16KB pages, and memory holding
+    /// a 64KB table
+    fn setup(pta: u64) -> (Cpu, Memory) {
+        let mut cpu = Cpu::new();
+        cpu.write_rr(1, 0x42 << 8 | 14 << 2 | 1).unwrap();
+        cpu.write_cr(CRIndex::PTA, pta).unwrap();
+        let mut memory = Memory::new();
+        memory.map(TABLE, 0x1_0000, Permissions::ReadWrite).unwrap();
+        (cpu, memory)
+    }
+
+    fn vector(result: Result<TlbEntry, EmulatorError>) -> InterruptVector {
+        match result {
+            Err(EmulatorError::TlbFault { vector, .. }) => vector,
+            other => panic!("expected a TLB fault, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_short_format() {
+        let (cpu, mut memory) = setup(TABLE | 16 << 2 | 1);
+        let entry = thash(&cpu, VADDR).unwrap();
+        assert_eq!(entry, TABLE | ((0x40_5123 >> 14) << 3));
+        // Present, read-write, frame 0x80_0000
+        memory.write_u64(entry, 0x80_0000 | 2 << 9 | 1).unwrap();
+        let found = walk(&cpu, &mut memory, VADDR, false).unwrap();
+        assert_eq!(
+            (found.rid, found.paddr, found.page_shift),
+            (0x42, 0x80_0000, 14)
+        );
+        assert_eq!(found.permissions, Permissions::ReadWrite);
+
+        memory.write_u64(entry, 0x80_0000).unwrap();
+        assert_eq!(
+            vector(walk(&cpu, &mut memory, VADDR, false)),
+            InterruptVector::PageNotPresentFault
+        );
+
+        // Off in PTA, or in the region
+        let (cpu, mut memory) = setup(TABLE | 16 << 2);
+        assert_eq!(
+            vector(walk(&cpu, &mut memory, VADDR, true)),
+            InterruptVector::AltInstructionTLBFault
+        );
+        let (cpu, mut memory) = setup(TABLE | 16 << 2 | 1);
+        assert_eq!(
+            vector(walk(&cpu, &mut memory, 0x4000, false)),
+            InterruptVector::AltDataTLBFault
+        );
+    }
+
+    #[test]
+    fn test_long_format() {
+        let (cpu, mut memory) = setup(TABLE | 1 << 8 | 16 << 2 | 1);
+        let head = thash(&cpu, VADDR).unwrap();
+        let tag = ttag(&cpu, VADDR).unwrap();
+        assert_eq!(head, TABLE | ((((0x40_5123 >> 14) ^ 0x42) * 32) & 0xFFFF));
+        assert_eq!(tag & TAG_INVALID, 0);
+
+        // An empty table misses
+        assert_eq!(
+            vector(walk(&cpu, &mut memory, VADDR, false)),
+            InterruptVector::DataTLBFault
+        );
+
+        // The hashed entry holds another page, chained to ours, which maps
+        // a 64KB page of its own size
+        let chained = TABLE + 0x8000;
+        let write = |memory: &mut Memory, at: u64, words: [u64; 4]| {
+            for (i, word) in words.iter().enumerate() {
+                memory.write_u64(at + 8 * i as u64, *word).unwrap();
+            }
+        };
+        write(&mut memory, head, [0x50_0001, 14 << 2, tag ^ 1, chained]);
+        write(
+            &mut memory,
+            chained,
+            [0x90_0000 | 3 << 9 | 1, 16 << 2, tag, 0],
+        );
+        let found = walk(&cpu, &mut memory, VADDR, true).unwrap();
+        assert_eq!((found.paddr, found.page_shift), (0x90_0000, 16));
+        assert_eq!(found.permissions, Permissions::ReadWriteExecute);
+
+        // A tag-invalid entry ends the chain
+        write(
+            &mut memory,
+            head,
+            [0x50_0001, 14 << 2, TAG_INVALID, chained],
+        );
+        assert_eq!(
+            vector(walk(&cpu, &mut memory, VADDR, true)),
+            InterruptVector::InstructionTLBFault
+        );
+
+        // So does a chain that loops, and one leaving memory faults
+        write(&mut memory, head, [0x50_0001, 14 << 2, tag ^ 1, head]);
+        assert_eq!(
+            vector(walk(&cpu, &mut memory, VADDR, false)),
+            InterruptVector::DataTLBFault
+        );
+        write(
+            &mut memory,
+            head,
+            [0x50_0001, 14 << 2, tag ^ 1, 0x4000_0000],
+        );
+        assert_eq!(
+            vector(walk(&cpu, &mut memory, VADDR, false)),
+            InterruptVector::VirtualMemoryFault
+        );
+    }
+
+    #[test]
+    fn test_translate_walk() {
+        let (mut cpu, mut memory) = setup(TABLE | 1 << 8 | 16 << 2 | 1);
+        let head = thash(&cpu, VADDR).unwrap();
+        let tag = ttag(&cpu, VADDR).unwrap();
+
+        // A miss leaves the address, hash and tag for the handler
+        assert!(cpu.translate_walk(&mut memory, VADDR, false).is_err());
+        assert_eq!(cpu.read_cr(CRIndex::IFA), VADDR);
+        assert_eq!(cpu.read_cr(CRIndex::IHA), head);
+        assert_eq!(cpu.read_cr(CRIndex::ITIR), 14 << 2 | 0x42 << 8);
+
+        // The handler fills the entry, and the retry inserts it
+        for (i, word) in [0x80_0000 | 2 << 9 | 1, 14 << 2, tag, 0].iter().enumerate() {
+            memory.write_u64(head + 8 * i as u64, *word).unwrap();
+        }
+        assert_eq!(
+            cpu.translate_walk(&mut memory, VADDR, false).unwrap(),
+            0x80_1123
+        );
+        assert_eq!(cpu.translate(VADDR).unwrap(), Some(0x80_1123));
+    }
+}
@@ -0,0 +1,428 @@
+//! Guest panic detection
+//!
+//! This module stops the run when the guest reaches a known failure path:
+//! executing a hooked address such as `panic` or `abort`, or writing to a
+//! magic MMIO port. The stop comes with a report of the registers, a short
+//! backtrace, the most recently executed bundles and the panic message read
+//! from guest memory.
+//!
+//! It also watches for livelocks: the guest running the same few bundles for
+//! millions of iterations without storing to memory, making a system call or
+//! taking an interrupt, so that nothing it does can change what it does next
+//! short of a register eventually reaching a limit. The heuristic can be
+//! fooled by long register-only computations, so the threshold is generous.
+
+use crate::cpu::Cpu;
+use crate::memory::Memory;
+use std::collections::VecDeque;
+use std::fmt;
+
+/// Bundles kept in the execution trace by default
+pub const DEFAULT_TRACE_LEN: usize = 16;
+
+/// First stacked register, holding the first argument on function entry
+pub const FIRST_ARG_REG: usize = 32;
+
+/// General registers included in a report (static registers and the inputs)
+const REPORT_REGISTERS: usize = 40;
+
+/// Longest panic message read from guest memory
+const MAX_MESSAGE_LEN: usize = 256;
+
+/// Bundles a livelock must last by default before it is reported
+pub const DEFAULT_LIVELOCK_BUNDLES: u64 = 10_000_000;
+
+/// Span of code a livelock may loop over by default, in bytes
+pub const DEFAULT_LIVELOCK_WINDOW: u64 = 256;
+
+/// Guest event that triggers a panic hook
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PanicTrigger {
+    /// The bundle at this address is about to execute
+    Execute(u64),
+    /// The guest wrote to this address
+    Write(u64),
+}
+
+/// Where the panic message is found
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MessageSource {
+    /// No message
+    None,
+    /// General register holding a pointer to a NUL-terminated string
+    Register(usize),
+    /// The 64-bit value written to the port points to the string
+    PortValue,
+}
+
+/// Guest failure path to watch for
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PanicHook {
+    /// Name shown in the report, e.g. the hooked symbol
+    pub name: String,
+    /// Event that triggers the hook
+    pub trigger: PanicTrigger,
+    /// Where the panic message is found
+    pub message: MessageSource,
+}
+
+impl PanicHook {
+    /// Hook a function entry; the message is its first argument
+    pub fn function(name: &str, address: u64) -> Self {
+        Self {
+            name: name.to_string(),
+            trigger: PanicTrigger::Execute(address),
+            message: MessageSource::Register(FIRST_ARG_REG),
+        }
+    }
+
+    /// Hook writes to a port; the value written points to the message
+    pub fn port(name: &str, address: u64) -> Self {
+        Self {
+            name: name.to_string(),
+            trigger: PanicTrigger::Write(address),
+            message: MessageSource::PortValue,
+        }
+    }
+}
+
+/// Guest state captured when a panic hook fires
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PanicReport {
+    /// Name of the hook that fired
+    pub hook: String,
+    /// Address of the bundle that triggered the hook
+    pub ip: u64,
+    /// General registers r0 to r39
+    pub registers: Vec<u64>,
+    /// Guest backtrace: the triggering bundle followed by the return link in b0
+    pub backtrace: Vec<u64>,
+    /// Most recently executed bundles, oldest first
+    pub trace: Vec<u64>,
+    /// Panic message read from guest memory
+    pub message: Option<String>,
+}
+
+impl fmt::Display for PanicReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "guest panic: {} at {:#x}", self.hook, self.ip)?;
+        if let Some(message) = &self.message {
+            writeln!(f, "message: {}", message)?;
+        }
+
+        writeln!(f, "registers:")?;
+        for (row, chunk) in self.registers.chunks(4).enumerate() {
+            for (col, value) in chunk.iter().enumerate() {
+                let name = format!("r{}", row * 4 + col);
+                write!(f, "  {:>3} {:#018x}", name, value)?;
+            }
+            writeln!(f)?;
+        }
+
+        writeln!(f, "backtrace:")?;
+        for (i, ip) in self.backtrace.iter().enumerate() {
+            writeln!(f, "  #{} {:#x}", i, ip)?;
+        }
+
+        writeln!(f, "last {} bundles:", self.trace.len())?;
+        for ip in &self.trace {
+            writeln!(f, "  {:#x}", ip)?;
+        }
+        Ok(())
+    }
+}
+
+/// Panic hooks and the execution trace they report
+#[derive(Debug)]
+pub(crate) struct PanicDetector {
+    /// Installed hooks
+    hooks: Vec<PanicHook>,
+    /// Recently executed bundle addresses, oldest first
+    trace: VecDeque<u64>,
+    /// Bundles kept in the trace
+    trace_len: usize,
+}
+
+impl Default for PanicDetector {
+    fn default() -> Self {
+        Self {
+            hooks: Vec::new(),
+            trace: VecDeque::new(),
+            trace_len: DEFAULT_TRACE_LEN,
+        }
+    }
+}
+
+impl PanicDetector {
+    /// Install a hook
+    pub fn add(&mut self, hook: PanicHook) {
+        self.hooks.push(hook);
+    }
+
+    /// Change the number of bundles kept in the trace
+    pub fn set_trace_len(&mut self, len: usize) {
+        self.trace_len = len;
+        while self.trace.len() > len {
+            self.trace.pop_front();
+        }
+    }
+
+    /// Whether any hook is installed
+    pub fn is_active(&self) -> bool {
+        !self.hooks.is_empty()
+    }
+
+    /// Record a bundle about to execute
+    pub fn record(&mut self, bundle_ip: u64) {
+        if self.trace_len == 0 {
+            return;
+        }
+        if self.trace.len() == self.trace_len {
+            self.trace.pop_front();
+        }
+        self.trace.push_back(bundle_ip);
+    }
+
+    /// Find the hook for an event
+    pub fn find(&self, trigger: PanicTrigger) -> Option<&PanicHook> {
+        self.hooks.iter().find(|hook| hook.trigger == trigger)
+    }
+
+    /// Capture the guest state for a hook that fired
+    pub fn report(
+        &self,
+        hook: &PanicHook,
+        bundle_ip: u64,
+        cpu: &Cpu,
+        memory: &Memory,
+    ) -> PanicReport {
+        let pointer = match (hook.message, hook.trigger) {
+            (MessageSource::Register(reg), _) => cpu.get_gr(reg).ok(),
+            (MessageSource::PortValue, PanicTrigger::Write(port)) => {
+                let mut value = [0u8; 8];
+                memory
+                    .peek_bytes(port, &mut value)
+                    .ok()
+                    .map(|()| u64::from_le_bytes(value))
+            }
+            _ => None,
+        };
+
+        PanicReport {
+            hook: hook.name.clone(),
+            ip: bundle_ip,
+            registers: (0..REPORT_REGISTERS)
+                .map(|reg| cpu.get_gr(reg).unwrap_or(0))
+                .collect(),
+            backtrace: vec![bundle_ip, cpu.br[0]],
+            trace: self.trace.iter().copied().collect(),
+            message: pointer.and_then(|addr| memory.peek_c_string(addr, MAX_MESSAGE_LEN)),
+        }
+    }
+}
+
+/// When a livelock is reported
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LivelockConfig {
+    /// Bundles executed without progress before the livelock is reported
+    pub bundles: u64,
+    /// Largest span of code the loop may cover, in bytes
+    pub window: u64,
+}
+
+impl Default for LivelockConfig {
+    fn default() -> Self {
+        Self {
+            bundles: DEFAULT_LIVELOCK_BUNDLES,
+            window: DEFAULT_LIVELOCK_WINDOW,
+        }
+    }
+}
+
+/// Guest state captured when a livelock is detected
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LivelockReport {
+    /// Lowest bundle address executed in the loop
+    pub start: u64,
+    /// Highest bundle address executed in the loop
+    pub end: u64,
+    /// Bundles executed without progress
+    pub bundles: u64,
+    /// General registers r0 to r39
+    pub registers: Vec<u64>,
+}
+
+impl fmt::Display for LivelockReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "guest livelock: {} bundles in [{:#x}, {:#x}] with no stores, system calls or interrupts",
+            self.bundles, self.start, self.end
+        )?;
+        writeln!(f, "registers:")?;
+        for (row, chunk) in self.registers.chunks(4).enumerate() {
+            for (col, value) in chunk.iter().enumerate() {
+                let name = format!("r{}", row * 4 + col);
+                write!(f, "  {:>3} {:#018x}", name, value)?;
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
+/// Tracks the code executed since the guest last made progress
+#[derive(Debug)]
+pub(crate) struct LivelockDetector {
+    /// Thresholds
+    config: LivelockConfig,
+    /// Lowest bundle address since the last progress
+    low: u64,
+    /// Highest bundle address since the last progress
+    high: u64,
+    /// Bundles executed since the last progress
+    count: u64,
+    /// Activity counter seen at the last progress
+    activity: u64,
+}
+
+impl LivelockDetector {
+    /// Detect livelocks as configured
+    pub fn new(config: LivelockConfig) -> Self {
+        Self {
+            config,
+            low: 0,
+            high: 0,
+            count: 0,
+            activity: 0,
+        }
+    }
+
+    /// Record a bundle about to execute
+    ///
+    /// `activity` is any counter that grows when the guest makes progress,
+    /// such as stores plus interrupts. Returns whether the guest has now
+    /// looped long enough to count as livelocked.
+    pub fn check(&mut self, bundle_ip: u64, activity: u64) -> bool {
+        let low = self.low.min(bundle_ip);
+        let high = self.high.max(bundle_ip);
+        if self.count == 0 || activity != self.activity || high - low >= self.config.window {
+            self.restart(bundle_ip);
+            self.activity = activity;
+            return false;
+        }
+        self.low = low;
+        self.high = high;
+        self.count += 1;
+        self.count >= self.config.bundles
+    }
+
+    /// Note progress the activity counter does not see, e.g. a system call
+    pub fn progress(&mut self) {
+        self.count = 0;
+    }
+
+    /// Start watching a new loop at `bundle_ip`
+    pub fn restart(&mut self, bundle_ip: u64) {
+        self.low = bundle_ip;
+        self.high = bundle_ip;
+        self.count = 1;
+    }
+
+    /// Capture the guest state for the loop being watched
+    pub fn report(&self, cpu: &Cpu) -> LivelockReport {
+        LivelockReport {
+            start: self.low,
+            end: self.high,
+            bundles: self.count,
+            registers: (0..REPORT_REGISTERS)
+                .map(|reg| cpu.get_gr(reg).unwrap_or(0))
+                .collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory::Permissions;
+
+    #[test]
+    fn test_report() {
+        let mut memory = Memory::new();
+        memory
+            .load_image(0x1000, 0x1000, b"out of memory\0", Permissions::Read)
+            .unwrap();
+        let mut cpu = Cpu::new();
+        cpu.gr[FIRST_ARG_REG] = 0x1000;
+        cpu.br[0] = 0x2040;
+
+        let mut detector = PanicDetector::default();
+        detector.set_trace_len(2);
+        let hook = PanicHook::function("panic", 0x3000);
+        detector.add(hook.clone());
+        for ip in [0x2000, 0x2010, 0x2020] {
+            detector.record(ip);
+        }
+
+        assert_eq!(detector.find(PanicTrigger::Execute(0x3000)), Some(&hook));
+        assert_eq!(detector.find(PanicTrigger::Write(0x3000)), None);
+
+        let report = detector.report(&hook, 0x3000, &cpu, &memory);
+        assert_eq!(report.message.as_deref(), Some("out of memory"));
+        assert_eq!(report.backtrace, vec![0x3000, 0x2040]);
+        assert_eq!(report.trace, vec![0x2010, 0x2020]);
+        assert_eq!(report.registers.len(), REPORT_REGISTERS);
+
+        let text = report.to_string();
+        assert!(text.starts_with("guest panic: panic at 0x3000\nmessage: out of memory\n"));
+        assert!(text.contains("r32 0x0000000000001000"));
+    }
+
+    #[test]
+    fn test_unmapped_message() {
+        let memory = Memory::new();
+        let cpu = Cpu::new();
+        let hook = PanicHook::port("panic port", 0x8000);
+        let report = PanicDetector::default().report(&hook, 0x1000, &cpu, &memory);
+        assert_eq!(report.message, None);
+    }
+
+    #[test]
+    fn test_livelock_detector() {
+        let mut detector = LivelockDetector::new(LivelockConfig {
+            bundles: 6,
+            window: 0x20,
+        });
+        let looped = |detector: &mut LivelockDetector, activity| {
+            [0x1000, 0x1010]
+                .iter()
+                .any(|&ip| detector.check(ip, activity))
+        };
+        assert!(!looped(&mut detector, 0));
+        assert!(!looped(&mut detector, 0));
+        // A store restarts the count
+        assert!(!looped(&mut detector, 1));
+        assert!(!looped(&mut detector, 1));
+        assert!(looped(&mut detector, 1));
+
+        let report = detector.report(&Cpu::new());
+        assert_eq!(
+            (report.start, report.end, report.bundles),
+            (0x1000, 0x1010, 6)
+        );
+        assert!(report
+            .to_string()
+            .starts_with("guest livelock: 6 bundles in [0x1000, 0x1010] with no stores"));
+
+        // Leaving the window or making a system call also restarts it
+        detector.restart(0x1000);
+        for _ in 0..3 {
+            assert!(!looped(&mut detector, 1));
+            assert!(!detector.check(0x1020, 1));
+        }
+        detector.progress();
+        assert!(!looped(&mut detector, 1));
+        assert!(!looped(&mut detector, 1));
+    }
+}
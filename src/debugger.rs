@@ -0,0 +1,960 @@
+//! Interactive debugger
+//!
+//! This module implements a line-oriented debugger command interpreter on top
+//! of the emulator, with symbol-aware memory inspection.
+
+use crate::coredump;
+use crate::decoder::Bundle;
+use crate::emulator::{Emulator, ResetKind, StopReason, BUNDLE_SIZE};
+use crate::memory::view::Endian;
+use crate::repro::{Compression, Snapshot};
+use crate::snapdiff;
+use crate::trace::StderrTracer;
+use crate::EmulatorError;
+use std::fmt::Write;
+
+/// Bytes shown per hexdump line
+const DUMP_LINE_BYTES: usize = 16;
+
+/// Most bytes a single dump shows
+const MAX_DUMP: u64 = 1 << 20;
+
+/// Number of bytes dumped for a symbol of unknown size
+const DEFAULT_SYMBOL_DUMP: u64 = 64;
+
+/// Maximum number of matches reported by `find`
+const MAX_FIND_RESULTS: usize = 64;
+
+/// Number of bundles `disas` shows when not given a count
+const DEFAULT_DISAS_BUNDLES: u64 = 4;
+
+/// Guest symbol
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Symbol {
+    /// Symbol name
+    pub name: String,
+    /// Start address
+    pub address: u64,
+    /// Size in bytes (0 if unknown)
+    pub size: u64,
+}
+
+/// Table of guest symbols sorted by address
+#[derive(Debug, Default)]
+pub struct SymbolTable {
+    symbols: Vec<Symbol>,
+}
+
+impl SymbolTable {
+    /// Create an empty symbol table
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a symbol
+    pub fn add(&mut self, name: &str, address: u64, size: u64) {
+        let index = self.symbols.partition_point(|s| s.address <= address);
+        self.symbols.insert(
+            index,
+            Symbol {
+                name: name.to_string(),
+                address,
+                size,
+            },
+        );
+    }
+
+    /// Parse `nm` output (`addr type name` or `nm -S` style `addr size type name`)
+    ///
+    /// Lines that do not parse are skipped.
+    pub fn parse_nm(&mut self, text: &str) {
+        for line in text.lines() {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let (address, size, name) = match fields.as_slice() {
+                [address, _kind, name] => (address, "0", name),
+                [address, size, _kind, name] => (address, *size, name),
+                _ => continue,
+            };
+            if let (Ok(address), Ok(size)) = (
+                u64::from_str_radix(address, 16),
+                u64::from_str_radix(size, 16),
+            ) {
+                self.add(name, address, size);
+            }
+        }
+    }
+
+    /// Find a symbol by name
+    pub fn find(&self, name: &str) -> Option<&Symbol> {
+        self.symbols.iter().find(|s| s.name == name)
+    }
+
+    /// Find the symbol containing an address and the offset into it
+    ///
+    /// Symbols without a size match any address up to the next symbol.
+    pub fn lookup(&self, address: u64) -> Option<(&Symbol, u64)> {
+        let index = self.symbols.partition_point(|s| s.address <= address);
+        let symbol = self.symbols[..index].last()?;
+        let offset = address - symbol.address;
+        if symbol.size != 0 && offset >= symbol.size {
+            return None;
+        }
+        Some((symbol, offset))
+    }
+
+    /// Number of symbols in the table
+    pub fn len(&self) -> usize {
+        self.symbols.len()
+    }
+
+    /// Check if the table is empty
+    pub fn is_empty(&self) -> bool {
+        self.symbols.is_empty()
+    }
+}
+
+/// Debugger state
+#[derive(Debug, Default)]
+pub struct Debugger {
+    /// Known guest symbols
+    pub symbols: SymbolTable,
+    /// Snapshot taken by the `snapshot` command
+    snapshot: Option<Snapshot>,
+    /// Compression of snapshots the `snapshot` command saves
+    pub compression: Compression,
+}
+
+impl Debugger {
+    /// Create a new debugger with no symbols
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Describe an address using symbols or region names
+    pub fn annotate(&self, emulator: &Emulator, address: u64) -> Option<String> {
+        if let Some((symbol, offset)) = self.symbols.lookup(address) {
+            return Some(format!("<{}+{:#x}>", symbol.name, offset));
+        }
+        emulator
+            .memory
+            .region_name(address)
+            .map(|name| format!("[{}]", name))
+    }
+
+    /// Format a hexdump of guest memory
+    ///
+    /// `width` is the unit size in bytes (1, 2, 4 or 8); wider units are shown
+    /// as little-endian values. Each line is annotated with the symbol or
+    /// region its first byte falls in. At most 1 MiB is dumped at once.
+    pub fn hexdump(
+        &self,
+        emulator: &Emulator,
+        address: u64,
+        len: u64,
+        width: usize,
+    ) -> Result<String, EmulatorError> {
+        if !matches!(width, 1 | 2 | 4 | 8) {
+            return Err(EmulatorError::ExecutionError(format!(
+                "Invalid dump width: {}",
+                width
+            )));
+        }
+
+        if len > MAX_DUMP {
+            return Err(usage(&format!("dump at most {:#x} bytes", MAX_DUMP)));
+        }
+
+        let mut data = vec![0u8; len as usize];
+        emulator.memory.peek_bytes(address, &mut data)?;
+
+        let mut out = String::new();
+        for (line, chunk) in data.chunks(DUMP_LINE_BYTES).enumerate() {
+            let line_addr = address.wrapping_add((line * DUMP_LINE_BYTES) as u64);
+            write!(out, "{:#018x}", line_addr).unwrap();
+            if let Some(note) = self.annotate(emulator, line_addr) {
+                write!(out, " {}", note).unwrap();
+            }
+            out.push(':');
+
+            for unit in chunk.chunks(width) {
+                out.push(' ');
+                for byte in unit.iter().rev() {
+                    write!(out, "{:02x}", byte).unwrap();
+                }
+            }
+
+            out.push_str("  |");
+            out.extend(chunk.iter().map(|&b| {
+                if b.is_ascii_graphic() || b == b' ' {
+                    b as char
+                } else {
+                    '.'
+                }
+            }));
+            out.push_str("|\n");
+        }
+
+        Ok(out)
+    }
+
+    /// Execute a debugger command and return its output
+    ///
+    /// Supported commands:
+    ///
+    /// - `find <"text"|hexbytes> [start..end]`
+    /// - `dump <addr> <len> [--width N]`
+    /// - `dump --symbol <name> [--width N]`
+    /// - `x[/<count><x|d|u|f><b|h|w|g>] <addr> [--big-endian]`
+    /// - `disas [addr] [count]`
+    /// - `gcore <file> [signal]`
+    /// - `dwatch <value> [mask]`, `dwatch clear <index>`
+    /// - `memmap`
+    /// - `ram [size]`
+    /// - `snapshot [file]`
+    /// - `snapdiff [file]`
+    /// - `stats [reset]`
+    /// - `reset [warm|cold]`
+    /// - `trace [on|off]`
+    /// - `step [count]`
+    /// - `continue`
+    /// - `script <file>` (with the `scripting` feature)
+    pub fn execute(
+        &mut self,
+        emulator: &mut Emulator,
+        line: &str,
+    ) -> Result<String, EmulatorError> {
+        let args = tokenize(line)?;
+        let Some((command, args)) = args.split_first() else {
+            return Ok(String::new());
+        };
+
+        match command.as_str() {
+            "find" => self.cmd_find(emulator, args),
+            "dump" => self.cmd_dump(emulator, args),
+            x if x == "x" || x.starts_with("x/") => self.cmd_examine(emulator, &x[1..], args),
+            "disas" => self.cmd_disas(emulator, args),
+            "gcore" => {
+                let path = args.first().ok_or_else(|| usage("gcore <file> [signal]"))?;
+                let signal = match args.get(1) {
+                    Some(arg) => parse_number(arg)? as i32,
+                    None => coredump::SIGTRAP,
+                };
+                let core = coredump::build_core(emulator, signal)?;
+                std::fs::write(path, core).map_err(|e| {
+                    EmulatorError::ExecutionError(format!("Cannot write {}: {}", path, e))
+                })?;
+                Ok(format!("saved core file {}\n", path))
+            }
+            "dwatch" => {
+                let ddr = &mut emulator.cpu.system_regs.ddr;
+                match args {
+                    [clear, index] if clear == "clear" => {
+                        ddr.clear_match(parse_number(index)? as usize)?;
+                        Ok(format!("cleared data match {}\n", index))
+                    }
+                    [value] | [value, _] => {
+                        let value = parse_number(value)?;
+                        let mask = match args.get(1) {
+                            Some(mask) => parse_number(mask)?,
+                            None => u64::MAX,
+                        };
+                        let index = ddr.set_match(value, mask)?;
+                        Ok(format!(
+                            "data match {}: value {:#x} mask {:#x}\n",
+                            index, value, mask
+                        ))
+                    }
+                    _ => Err(usage("dwatch <value> [mask] | dwatch clear <index>")),
+                }
+            }
+            "memmap" => Ok(emulator.phys_map.to_string()),
+            "ram" => {
+                if let Some(arg) = args.first() {
+                    emulator.resize_ram(parse_number(arg)?)?;
+                }
+                Ok(format!("ram {:#x} bytes\n", emulator.ram_size()))
+            }
+            "snapshot" => {
+                let snapshot = Snapshot::capture(emulator);
+                let out = match args.first() {
+                    Some(path) => {
+                        snapshot.save_compressed(path, self.compression)?;
+                        format!("saved snapshot {}\n", path)
+                    }
+                    None => "snapshot taken\n".to_string(),
+                };
+                self.snapshot = Some(snapshot);
+                Ok(out)
+            }
+            "snapdiff" => {
+                let loaded;
+                let snapshot = match args.first() {
+                    Some(path) => {
+                        loaded = Snapshot::load(path)?;
+                        &loaded
+                    }
+                    None => self.snapshot.as_ref().ok_or_else(|| {
+                        EmulatorError::ExecutionError(
+                            "No snapshot taken; use snapshot first".to_string(),
+                        )
+                    })?,
+                };
+                Ok(snapdiff::diff_live(snapshot, emulator).report(&self.symbols))
+            }
+            "stats" => match args.first().map(String::as_str) {
+                None => Ok(format_stats(emulator)),
+                Some("reset") => {
+                    emulator.memory.reset_stats();
+                    emulator.cpu.alat.reset_stats();
+                    emulator.cpu.tlb.reset_stats();
+                    emulator.cpu.syscall_mgr.reset_stats();
+                    emulator.cpu.branch_predictor.reset_stats();
+                    if let Some(dispersal) = &mut emulator.dispersal {
+                        dispersal.reset_stats();
+                    }
+                    if let Some(timing) = &mut emulator.timing {
+                        timing.reset_stats();
+                    }
+                    Ok("statistics reset\n".to_string())
+                }
+                Some(_) => Err(usage("stats [reset]")),
+            },
+            "reset" => {
+                let name = args.first().map_or("warm", String::as_str);
+                let kind = match name {
+                    "warm" => ResetKind::Warm,
+                    "cold" => ResetKind::Cold,
+                    _ => return Err(usage("reset [warm|cold]")),
+                };
+                emulator.reset(kind)?;
+                Ok(format!("{} reset, ip {:#x}\n", name, emulator.cpu.ip))
+            }
+            "trace" => {
+                match args.first().map(String::as_str) {
+                    None => {}
+                    Some("on") => {
+                        emulator.set_tracing(true);
+                        // With no tracer installed, log to standard error
+                        if !emulator.is_tracing() {
+                            emulator.set_tracer(Some(Box::new(StderrTracer)));
+                        }
+                    }
+                    Some("off") => emulator.set_tracing(false),
+                    Some(_) => return Err(usage("trace [on|off]")),
+                }
+                let state = if emulator.is_tracing() { "on" } else { "off" };
+                Ok(format!("tracing {}\n", state))
+            }
+            "step" => {
+                let count = match args.first() {
+                    Some(arg) => parse_number(arg)?,
+                    None => 1,
+                };
+                for _ in 0..count {
+                    if let Some(reason) = emulator.step()? {
+                        return Ok(describe_stop(emulator, reason));
+                    }
+                }
+                Ok(format!("ip {:#x}\n", emulator.cpu.ip))
+            }
+            "continue" => {
+                let reason = emulator.run()?;
+                Ok(describe_stop(emulator, reason))
+            }
+            #[cfg(feature = "scripting")]
+            "script" => {
+                let path = args.first().ok_or_else(|| usage("script <file>"))?;
+                crate::script::run_file(emulator, self, path)
+            }
+            _ => Err(EmulatorError::ExecutionError(format!(
+                "Unknown command: {}",
+                command
+            ))),
+        }
+    }
+
+    /// `find` command
+    fn cmd_find(&self, emulator: &Emulator, args: &[String]) -> Result<String, EmulatorError> {
+        let pattern = match args.first() {
+            Some(arg) => parse_pattern(arg)?,
+            None => return Err(usage("find <\"text\"|hexbytes> [start..end]")),
+        };
+        let range = match args.get(1) {
+            Some(arg) => parse_range(arg)?,
+            None => 0..u64::MAX,
+        };
+
+        let matches = emulator.memory.search(&pattern, range);
+        let mut out = String::new();
+        for address in matches.iter().take(MAX_FIND_RESULTS) {
+            write!(out, "{:#018x}", address).unwrap();
+            if let Some(note) = self.annotate(emulator, *address) {
+                write!(out, " {}", note).unwrap();
+            }
+            out.push('\n');
+        }
+        if matches.len() > MAX_FIND_RESULTS {
+            writeln!(out, "... {} more", matches.len() - MAX_FIND_RESULTS).unwrap();
+        }
+        if matches.is_empty() {
+            out.push_str("not found\n");
+        }
+        Ok(out)
+    }
+
+    /// `dump` command
+    fn cmd_dump(&self, emulator: &Emulator, args: &[String]) -> Result<String, EmulatorError> {
+        const USAGE: &str = "dump <addr> <len> [--width N] | dump --symbol <name> [--width N]";
+
+        let mut width = 1;
+        let mut symbol = None;
+        let mut positional = Vec::new();
+
+        let mut iter = args.iter();
+        while let Some(arg) = iter.next() {
+            match arg.as_str() {
+                "--width" => {
+                    width = parse_number(iter.next().ok_or_else(|| usage(USAGE))?)? as usize
+                }
+                "--symbol" => symbol = Some(iter.next().ok_or_else(|| usage(USAGE))?),
+                _ => positional.push(arg),
+            }
+        }
+
+        let (address, len) = match (symbol, positional.as_slice()) {
+            (Some(name), []) => {
+                let symbol = self.symbols.find(name).ok_or_else(|| {
+                    EmulatorError::ExecutionError(format!("Unknown symbol: {}", name))
+                })?;
+                let len = if symbol.size == 0 {
+                    DEFAULT_SYMBOL_DUMP
+                } else {
+                    symbol.size
+                };
+                (symbol.address, len)
+            }
+            (None, [address, len]) => (parse_number(address)?, parse_number(len)?),
+            _ => return Err(usage(USAGE)),
+        };
+
+        self.hexdump(emulator, address, len, width)
+    }
+
+    /// `disas` command, which disassembles bundles from an address, by
+    /// default the current IP
+    fn cmd_disas(&self, emulator: &Emulator, args: &[String]) -> Result<String, EmulatorError> {
+        let address = match args.first() {
+            Some(arg) => parse_number(arg)?,
+            None => emulator.cpu.ip,
+        } & !(BUNDLE_SIZE - 1);
+        let count = match args.get(1) {
+            Some(arg) => parse_number(arg)?,
+            None => DEFAULT_DISAS_BUNDLES,
+        };
+
+        let mut out = String::new();
+        for i in 0..count {
+            let ip = address.wrapping_add(i * BUNDLE_SIZE);
+            let mut data = [0u8; 16];
+            emulator.memory.peek_bytes(ip, &mut data)?;
+            let bundle = Bundle::new(data)?;
+            write!(out, "{:#018x}", ip).unwrap();
+            if let Some(note) = self.annotate(emulator, ip) {
+                write!(out, " {}", note).unwrap();
+            }
+            writeln!(out, ": {}", bundle.template()).unwrap();
+            for line in bundle.disassemble_at(ip) {
+                writeln!(out, "    {}", line).unwrap();
+            }
+        }
+        Ok(out)
+    }
+
+    /// `x` command, which shows memory as typed values in the style of gdb
+    ///
+    /// `spec` is what follows the `x`: a count, a format (hex, signed,
+    /// unsigned or float) and a unit size (1, 2, 4 or 8 bytes).
+    fn cmd_examine(
+        &self,
+        emulator: &Emulator,
+        spec: &str,
+        args: &[String],
+    ) -> Result<String, EmulatorError> {
+        const USAGE: &str = "x[/<count><x|d|u|f><b|h|w|g>] <addr> [--big-endian]";
+
+        let spec = spec.strip_prefix('/').unwrap_or(spec);
+        let digits = spec
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(spec.len());
+        let count = match &spec[..digits] {
+            "" => 1,
+            count => parse_number(count)?,
+        };
+        let mut format = 'x';
+        let mut size = 4;
+        for c in spec[digits..].chars() {
+            match c {
+                'x' | 'd' | 'u' | 'f' => format = c,
+                'b' => size = 1,
+                'h' => size = 2,
+                'w' => size = 4,
+                'g' => size = 8,
+                _ => return Err(usage(USAGE)),
+            }
+        }
+        if format == 'f' && size < 4 {
+            return Err(EmulatorError::ExecutionError(
+                "Floats are 4 or 8 bytes".to_string(),
+            ));
+        }
+
+        let mut endian = Endian::Little;
+        let mut address = None;
+        for arg in args {
+            match arg.as_str() {
+                "--big-endian" => endian = Endian::Big,
+                _ if address.is_none() => address = Some(parse_number(arg)?),
+                _ => return Err(usage(USAGE)),
+            }
+        }
+        let address = address.ok_or_else(|| usage(USAGE))?;
+
+        let len = count
+            .checked_mul(size as u64)
+            .filter(|&len| len <= MAX_DUMP)
+            .ok_or_else(|| usage(&format!("x at most {:#x} bytes", MAX_DUMP)))?;
+        let view = emulator.memory.view(address, len, endian);
+        let values = match size {
+            1 => view.to_vec::<u8>()?.into_iter().map(u64::from).collect(),
+            2 => view.to_vec::<u16>()?.into_iter().map(u64::from).collect(),
+            4 => view.to_vec::<u32>()?.into_iter().map(u64::from).collect(),
+            _ => view.to_vec::<u64>()?,
+        };
+
+        let bits = 64 - 8 * size;
+        let mut out = String::new();
+        for (line, chunk) in values.chunks(DUMP_LINE_BYTES / size).enumerate() {
+            let line_addr = address + (line * DUMP_LINE_BYTES) as u64;
+            write!(out, "{:#018x}", line_addr).unwrap();
+            if let Some(note) = self.annotate(emulator, line_addr) {
+                write!(out, " {}", note).unwrap();
+            }
+            out.push(':');
+            for &value in chunk {
+                match format {
+                    'x' => write!(out, " {:#0width$x}", value, width = 2 + 2 * size),
+                    'd' => write!(out, " {}", ((value << bits) as i64) >> bits),
+                    'u' => write!(out, " {}", value),
+                    _ if size == 4 => write!(out, " {}", f32::from_bits(value as u32)),
+                    _ => write!(out, " {}", f64::from_bits(value)),
+                }
+                .unwrap();
+            }
+            out.push('\n');
+        }
+        Ok(out)
+    }
+}
+
+/// Format memory access, prefetch, ALAT, dispersal and timing statistics
+pub fn format_stats(emulator: &Emulator) -> String {
+    let stats = emulator.memory.stats();
+    let prefetch = &stats.prefetch;
+    let alat = emulator.cpu.alat.stats();
+    let tlb = emulator.cpu.tlb.stats();
+    let mut report = format!(
+        "demand reads {} (misses {}; l1 {}, l2 {}; victim hits {})\n\
+         evictions l1 {}, l2 {}, l3 {}\n\
+         prefetches {} (redundant {}, useful {}, unused {})\n\
+         prefetch accuracy {:.1}% coverage {:.1}%\n\
+         alat entries {} (peak {}), adds {}\n\
+         alat checks {} hit {} miss, hit rate {:.1}%\n\
+         alat invalidations: store {}, capacity {}, explicit {}\n\
+         tlb entries {}, lookups {} hit {} miss, hit rate {:.1}%\n\
+         tlb purged {}, capacity {}; rid switches {}, reuse {:.1}%\n",
+        stats.demand_reads,
+        stats.demand_misses,
+        stats.l1_misses,
+        stats.l2_misses,
+        stats.victim_hits,
+        stats.evictions[0],
+        stats.evictions[1],
+        stats.evictions[2],
+        prefetch.issued,
+        prefetch.redundant,
+        prefetch.useful,
+        prefetch.unused,
+        prefetch.accuracy() * 100.0,
+        stats.prefetch_coverage() * 100.0,
+        emulator.cpu.alat.valid_entries(),
+        alat.peak_occupancy,
+        alat.adds,
+        alat.check_hits,
+        alat.check_misses,
+        alat.hit_rate() * 100.0,
+        alat.store_invalidations,
+        alat.capacity_evictions,
+        alat.explicit_invalidations,
+        emulator.cpu.tlb.len(),
+        tlb.hits,
+        tlb.misses,
+        tlb.hit_rate() * 100.0,
+        tlb.purged,
+        tlb.capacity_evictions,
+        tlb.rid_switches,
+        tlb.reuse_rate() * 100.0,
+    );
+    let branches = emulator.cpu.branch_predictor.stats();
+    if branches.branches > 0 {
+        report.push_str(&format!(
+            "branches {} (taken {}), mispredicted {}, accuracy {:.1}%\n",
+            branches.branches,
+            branches.taken,
+            branches.mispredicts,
+            branches.accuracy() * 100.0
+        ));
+    }
+    if let Some(dispersal) = &emulator.dispersal {
+        report.push_str(&format!("dispersal model {}\n", dispersal.machine()));
+        report.push_str(&dispersal.stats().to_string());
+    }
+    if let Some(timing) = &emulator.timing {
+        report.push_str("timing model\n");
+        report.push_str(&timing.stats().to_string());
+    }
+    let syscalls = emulator.cpu.syscall_mgr.stats();
+    if !syscalls.is_empty() {
+        report.push_str(&syscalls.to_string());
+    }
+    report
+}
+
+/// Describe why the emulator stopped
+pub(crate) fn describe_stop(emulator: &Emulator, reason: StopReason) -> String {
+    match reason {
+        StopReason::Exited(code) => format!("guest exited with status {}\n", code),
+        StopReason::Break(imm) => format!("break {:#x}\n", imm),
+        StopReason::Panic => match emulator.panic_report() {
+            Some(report) => report.to_string(),
+            None => "guest panic\n".to_string(),
+        },
+        StopReason::Livelock => match emulator.livelock_report() {
+            Some(report) => report.to_string(),
+            None => "guest livelock\n".to_string(),
+        },
+        StopReason::DataMatch { addr, value } => format!(
+            "data match: value {:#x} at {:#x} (ip {:#x} slot {})\n",
+            value, addr, emulator.cpu.ip, emulator.cpu.slot
+        ),
+        StopReason::WatchdogExpired => format!("watchdog expired (ip {:#x})\n", emulator.cpu.ip),
+    }
+}
+
+/// Build a usage error
+fn usage(text: &str) -> EmulatorError {
+    EmulatorError::ExecutionError(format!("usage: {}", text))
+}
+
+/// Split a command line into words, keeping double-quoted strings together
+///
+/// Quoted words keep their quotes so callers can tell text from numbers.
+fn tokenize(line: &str) -> Result<Vec<String>, EmulatorError> {
+    let mut words = Vec::new();
+    let mut chars = line.trim().chars().peekable();
+
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+        } else if c == '"' {
+            let mut word = String::from(chars.next().unwrap());
+            loop {
+                match chars.next() {
+                    Some('\\') => word.extend(chars.next()),
+                    Some('"') => break,
+                    Some(c) => word.push(c),
+                    None => {
+                        return Err(EmulatorError::ExecutionError(
+                            "Unterminated string".to_string(),
+                        ))
+                    }
+                }
+            }
+            word.push('"');
+            words.push(word);
+        } else {
+            let mut word = String::new();
+            while let Some(&c) = chars.peek() {
+                if c.is_whitespace() {
+                    break;
+                }
+                word.push(c);
+                chars.next();
+            }
+            words.push(word);
+        }
+    }
+
+    Ok(words)
+}
+
+/// Parse a decimal or 0x-prefixed hexadecimal number, which may contain
+/// `_` separators
+pub fn parse_number(text: &str) -> Result<u64, EmulatorError> {
+    let digits = text.replace('_', "");
+    let parsed = match digits
+        .strip_prefix("0x")
+        .or_else(|| digits.strip_prefix("0X"))
+    {
+        Some(hex) => u64::from_str_radix(hex, 16),
+        None => digits.parse(),
+    };
+    parsed.map_err(|_| EmulatorError::ExecutionError(format!("Invalid number: {}", text)))
+}
+
+/// Parse a `start..end` address range
+fn parse_range(text: &str) -> Result<std::ops::Range<u64>, EmulatorError> {
+    let (start, end) = text
+        .split_once("..")
+        .ok_or_else(|| EmulatorError::ExecutionError(format!("Invalid range: {}", text)))?;
+    Ok(parse_number(start)?..parse_number(end)?)
+}
+
+/// Parse a search pattern: a quoted string or hex bytes (`deadbeef`, `de:ad:be:ef`)
+fn parse_pattern(text: &str) -> Result<Vec<u8>, EmulatorError> {
+    if let Some(quoted) = text.strip_prefix('"').and_then(|t| t.strip_suffix('"')) {
+        return Ok(quoted.as_bytes().to_vec());
+    }
+
+    let digits: String = text
+        .trim_start_matches("0x")
+        .chars()
+        .filter(|&c| c != ':')
+        .collect();
+    if digits.is_empty() || !digits.is_ascii() || !digits.len().is_multiple_of(2) {
+        return Err(EmulatorError::ExecutionError(format!(
+            "Invalid byte pattern: {}",
+            text
+        )));
+    }
+
+    (0..digits.len())
+        .step_by(2)
+        .map(|i| {
+            u8::from_str_radix(&digits[i..i + 2], 16).map_err(|_| {
+                EmulatorError::ExecutionError(format!("Invalid byte pattern: {}", text))
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::firmware::memmap::RegionKind;
+    use crate::memory::Permissions;
+    use crate::test_util::{encode_break_nop, encode_bundle, mov_ip, nop};
+
+    fn setup() -> (Debugger, Emulator) {
+        let mut emu = Emulator::new();
+        emu.memory
+            .map(0x1000, 0x100, Permissions::ReadWrite)
+            .unwrap();
+        emu.memory.name_region(0x1000, "data").unwrap();
+        emu.memory.write_bytes(0x1020, b"hello world").unwrap();
+
+        let mut dbg = Debugger::new();
+        dbg.symbols
+            .parse_nm("0000000000001020 000000000000000b D greeting\n");
+        (dbg, emu)
+    }
+
+    #[test]
+    fn test_symbol_lookup() {
+        let mut table = SymbolTable::new();
+        table.add("b", 0x2000, 0);
+        table.add("a", 0x1000, 0x10);
+
+        assert_eq!(
+            table.lookup(0x1008).map(|(s, o)| (&s.name[..], o)),
+            Some(("a", 8))
+        );
+        assert!(table.lookup(0x1010).is_none());
+        assert!(table.lookup(0xFFF).is_none());
+        assert_eq!(
+            table.lookup(0x9000).map(|(s, o)| (&s.name[..], o)),
+            Some(("b", 0x7000))
+        );
+        assert_eq!(table.find("b").unwrap().address, 0x2000);
+    }
+
+    #[test]
+    fn test_find() {
+        let (mut dbg, mut emu) = setup();
+
+        let out = dbg.execute(&mut emu, "find \"world\"").unwrap();
+        assert_eq!(out, "0x0000000000001026 <greeting+0x6>\n");
+
+        let out = dbg.execute(&mut emu, "find 68:65 0x1000..0x1100").unwrap();
+        assert_eq!(out, "0x0000000000001020 <greeting+0x0>\n");
+
+        let out = dbg.execute(&mut emu, "find 6865 0x1021..0x1100").unwrap();
+        assert_eq!(out, "not found\n");
+
+        assert!(dbg.execute(&mut emu, "find 686").is_err());
+    }
+
+    #[test]
+    fn test_dump() {
+        let (mut dbg, mut emu) = setup();
+
+        let out = dbg.execute(&mut emu, "dump --symbol greeting").unwrap();
+        assert_eq!(
+            out,
+            "0x0000000000001020 <greeting+0x0>: 68 65 6c 6c 6f 20 77 6f 72 6c 64  |hello world|\n"
+        );
+
+        let out = dbg.execute(&mut emu, "dump 0x1000 4 --width 4").unwrap();
+        assert_eq!(out, "0x0000000000001000 [data]: 00000000  |....|\n");
+
+        let out = dbg.execute(&mut emu, "dump 0x1020 2 --width 2").unwrap();
+        assert_eq!(out, "0x0000000000001020 <greeting+0x0>: 6568  |he|\n");
+
+        assert!(dbg.execute(&mut emu, "dump 0x1000 4 --width 3").is_err());
+        assert!(dbg.execute(&mut emu, "dump 0x5000 4").is_err());
+        assert!(dbg
+            .execute(&mut emu, "dump 0x1000 0xFFFFFFFFFFFFFFFF")
+            .is_err());
+    }
+
+    #[test]
+    fn test_examine() {
+        let (mut dbg, mut emu) = setup();
+
+        let out = dbg.execute(&mut emu, "x/2xw 0x1020").unwrap();
+        assert_eq!(
+            out,
+            "0x0000000000001020 <greeting+0x0>: 0x6c6c6568 0x6f77206f\n"
+        );
+
+        let out = dbg.execute(&mut emu, "x/1xh 0x1020 --big-endian").unwrap();
+        assert_eq!(out, "0x0000000000001020 <greeting+0x0>: 0x6865\n");
+
+        emu.memory.write_bytes(0x1040, &[0xFF, 0xFF, 0, 0]).unwrap();
+        emu.memory
+            .write_bytes(0x1048, &1.5f64.to_le_bytes())
+            .unwrap();
+        let out = dbg.execute(&mut emu, "x/2dh 0x1040").unwrap();
+        assert_eq!(out, "0x0000000000001040 [data]: -1 0\n");
+        let out = dbg.execute(&mut emu, "x/fg 0x1048").unwrap();
+        assert_eq!(out, "0x0000000000001048 [data]: 1.5\n");
+
+        // Lines hold 16 bytes
+        let out = dbg.execute(&mut emu, "x/5ub 0x1000").unwrap();
+        assert_eq!(out.lines().count(), 1);
+        let out = dbg.execute(&mut emu, "x/5ug 0x1000").unwrap();
+        assert_eq!(out.lines().count(), 3);
+
+        assert!(dbg.execute(&mut emu, "x/2fb 0x1000").is_err());
+        assert!(dbg.execute(&mut emu, "x/2q 0x1000").is_err());
+        assert!(dbg.execute(&mut emu, "x 0x5000").is_err());
+        assert!(dbg
+            .execute(&mut emu, "x/4611686018427387904xg 0x1000")
+            .is_err());
+        assert!(dbg.execute(&mut emu, "x/1048577xb 0x1000").is_err());
+    }
+
+    #[test]
+    fn test_snapdiff() {
+        let (mut dbg, mut emu) = setup();
+        assert!(dbg.execute(&mut emu, "snapdiff").is_err());
+        dbg.execute(&mut emu, "snapshot").unwrap();
+        emu.memory.write_bytes(0x1026, b"there").unwrap();
+        emu.cpu.gr[8] = 7;
+
+        let out = dbg.execute(&mut emu, "snapdiff").unwrap();
+        assert!(out.contains("r8           0x0 -> 0x7\n"));
+        assert!(out.contains("region 0x0000000000001000 [data]: 5 bytes differ in 1 page\n"));
+        assert!(out.contains("0x0000000000001026..0x000000000000102b <greeting+0x6>\n"));
+        assert!(out.contains("- 77 6f 72 6c 64\n"));
+    }
+
+    #[test]
+    fn test_disas() {
+        let (mut dbg, mut emu) = setup();
+        // { .mii; nop.m 0x0; mov r14=ip;; break.i 0x1 } at greeting
+        let slots = [nop(), mov_ip(0, 14), encode_break_nop(0, 0x00, 1)];
+        let bundle = encode_bundle(0x02, slots);
+        emu.memory.write_bytes(0x1020, &bundle).unwrap();
+        emu.cpu.ip = 0x1028;
+
+        let out = dbg.execute(&mut emu, "disas").unwrap();
+        assert!(out.starts_with(
+            "0x0000000000001020 <greeting+0x0>: MI;I\n    nop.m 0x0\n    mov r14 = ip ;;\n    break.i 0x1\n"
+        ));
+        assert_eq!(out.lines().count(), 16);
+        let out = dbg.execute(&mut emu, "disas 0x1000 1").unwrap();
+        assert!(out.starts_with("0x0000000000001000 [data]: MII\n"));
+
+        // Reserved template
+        emu.memory.write_bytes(0x1000, &[0x06]).unwrap();
+        assert!(dbg.execute(&mut emu, "disas 0x1000 1").is_err());
+        assert!(dbg.execute(&mut emu, "disas 0x5000").is_err());
+    }
+
+    #[test]
+    fn test_ram() {
+        let (mut dbg, mut emu) = setup();
+        emu.memory
+            .map(0x8000, 0x1000, Permissions::ReadWrite)
+            .unwrap();
+        emu.phys_map
+            .add("ram", 0x8000, 0x1000, RegionKind::Ram)
+            .unwrap();
+        assert_eq!(dbg.execute(&mut emu, "ram").unwrap(), "ram 0x1000 bytes\n");
+        assert_eq!(
+            dbg.execute(&mut emu, "ram 0x3000").unwrap(),
+            "ram 0x3000 bytes\n"
+        );
+        assert!(dbg.execute(&mut emu, "ram 0x10").is_err());
+    }
+
+    #[test]
+    fn test_trace() {
+        let (mut dbg, mut emu) = setup();
+        assert_eq!(dbg.execute(&mut emu, "trace").unwrap(), "tracing off\n");
+        assert_eq!(dbg.execute(&mut emu, "trace on").unwrap(), "tracing on\n");
+        assert!(emu.is_tracing());
+        assert_eq!(dbg.execute(&mut emu, "trace off").unwrap(), "tracing off\n");
+        assert!(!emu.is_tracing());
+        assert!(dbg.execute(&mut emu, "trace maybe").is_err());
+    }
+
+    #[test]
+    fn test_reset() {
+        let (mut dbg, mut emu) = setup();
+        emu.cpu.ip = 0x1000;
+        // Before the first bundle the registers are the boot state
+        let out = dbg.execute(&mut emu, "reset").unwrap();
+        assert_eq!(out, "warm reset, ip 0x1000\n");
+        emu.cpu.ip = 0x1010;
+        let out = dbg.execute(&mut emu, "reset cold").unwrap();
+        assert_eq!(out, "cold reset, ip 0x1000\n");
+        assert_eq!(emu.resets(), 2);
+        assert!(dbg.execute(&mut emu, "reset hard").is_err());
+    }
+
+    #[test]
+    fn test_dwatch() {
+        let (mut dbg, mut emu) = setup();
+        let out = dbg.execute(&mut emu, "dwatch 0xbeef 0xffff").unwrap();
+        assert_eq!(out, "data match 0: value 0xbeef mask 0xffff\n");
+        let out = dbg.execute(&mut emu, "dwatch 7").unwrap();
+        assert_eq!(out, "data match 1: value 0x7 mask 0xffffffffffffffff\n");
+        assert!(emu.cpu.check_data_match(0x1_beef));
+
+        dbg.execute(&mut emu, "dwatch clear 0").unwrap();
+        assert!(!emu.cpu.check_data_match(0x1_beef));
+        assert!(dbg.execute(&mut emu, "dwatch 1 0").is_err());
+        assert!(dbg.execute(&mut emu, "dwatch").is_err());
+    }
+}
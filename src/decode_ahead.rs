@@ -0,0 +1,201 @@
+//! Decode-ahead pipeline
+//!
+//! This module decodes instruction bundles on a worker thread ahead of
+//! execution. The execution thread fetches the raw bytes of the bundles
+//! along the predicted path and queues them; decoded results come back
+//! tagged with the generation they were requested in, so a mispredicted
+//! path or a write to code discards everything still in flight.
+//!
+//! The path follows IP-relative branches that the branch predictor expects
+//! to be taken, and falls through past the rest. Indirect branches are not
+//! followed, since their targets are only known once they execute.
+
+use crate::emulator::{decode_bundle, DecodedBundle};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread::{self, JoinHandle};
+
+/// Batch of bundles queued for decoding
+#[derive(Debug)]
+struct Request {
+    /// Generation the request was made in
+    generation: u64,
+    /// Bundle addresses and raw bytes
+    bundles: Vec<(u64, [u8; 16])>,
+}
+
+/// Batch of bundles decoded by the worker
+#[derive(Debug)]
+struct Response {
+    /// Generation the request was made in
+    generation: u64,
+    /// Bundle addresses and decoded slots
+    decoded: Vec<(u64, DecodedBundle)>,
+}
+
+/// Worker thread decoding bundles ahead of execution
+#[derive(Debug)]
+pub struct DecodeAhead {
+    /// Request queue, dropped to stop the worker
+    requests: Option<Sender<Request>>,
+    /// Completed decodes
+    responses: Receiver<Response>,
+    /// Worker thread
+    worker: Option<JoinHandle<()>>,
+    /// Current generation; bumped on mispredict or code writes
+    generation: u64,
+    /// Next address expected on the predicted path
+    predicted: Option<u64>,
+    /// First address past the bundles requested in this generation
+    frontier: Option<u64>,
+}
+
+impl Default for DecodeAhead {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl DecodeAhead {
+    /// Start the worker thread
+    pub fn new() -> Self {
+        let (request_tx, request_rx) = mpsc::channel::<Request>();
+        let (response_tx, response_rx) = mpsc::channel();
+
+        let worker = thread::spawn(move || {
+            for request in request_rx {
+                // Decode failures are left to the execution thread to report
+                let decoded = request
+                    .bundles
+                    .into_iter()
+                    .filter_map(|(addr, data)| Some((addr, decode_bundle(data).ok()?)))
+                    .collect();
+                let response = Response {
+                    generation: request.generation,
+                    decoded,
+                };
+                if response_tx.send(response).is_err() {
+                    break;
+                }
+            }
+        });
+
+        Self {
+            requests: Some(request_tx),
+            responses: response_rx,
+            worker: Some(worker),
+            generation: 0,
+            predicted: None,
+            frontier: None,
+        }
+    }
+
+    /// Discard all requests still in flight
+    pub fn invalidate(&mut self) {
+        self.generation += 1;
+        self.predicted = None;
+        self.frontier = None;
+    }
+
+    /// Record the address about to execute
+    ///
+    /// Leaving the predicted path discards the speculative decodes.
+    pub fn execute_at(&mut self, addr: u64) {
+        if self.predicted.is_some_and(|predicted| predicted != addr) {
+            self.invalidate();
+        }
+    }
+
+    /// Set the next address expected on the predicted path
+    pub fn predict(&mut self, addr: u64) {
+        self.predicted = Some(addr);
+    }
+
+    /// First address past the bundles requested since the last invalidation
+    pub fn frontier(&self) -> Option<u64> {
+        self.frontier
+    }
+
+    /// Queue a batch of bundles ending just before `frontier`
+    pub fn request(&mut self, bundles: Vec<(u64, [u8; 16])>, frontier: u64) {
+        self.frontier = Some(frontier);
+        if bundles.is_empty() {
+            return;
+        }
+        if let Some(requests) = &self.requests {
+            let _ = requests.send(Request {
+                generation: self.generation,
+                bundles,
+            });
+        }
+    }
+
+    /// Collect decoded bundles from the current generation
+    pub(crate) fn completed(&mut self) -> Vec<(u64, DecodedBundle)> {
+        let mut completed = Vec::new();
+        while let Ok(response) = self.responses.try_recv() {
+            if response.generation == self.generation {
+                completed.extend(response.decoded);
+            }
+        }
+        completed
+    }
+}
+
+impl Drop for DecodeAhead {
+    fn drop(&mut self) {
+        // Closing the queue ends the worker loop
+        self.requests.take();
+        if let Some(worker) = self.worker.take() {
+            let _ = worker.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{encode_mii, nop};
+    use std::time::{Duration, Instant};
+
+    /// MII bundle of three nops
+    fn nop_bundle() -> [u8; 16] {
+        encode_mii([nop(); 3])
+    }
+
+    fn wait_for(pipeline: &mut DecodeAhead, count: usize) -> Vec<(u64, DecodedBundle)> {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let mut completed = Vec::new();
+        while completed.len() < count && Instant::now() < deadline {
+            completed.extend(pipeline.completed());
+            thread::yield_now();
+        }
+        completed
+    }
+
+    #[test]
+    fn test_decodes_ahead() {
+        let mut pipeline = DecodeAhead::new();
+        pipeline.request(vec![(0x1000, nop_bundle()), (0x1010, nop_bundle())], 0x1020);
+        pipeline.request(vec![(0x1020, nop_bundle())], 0x1030);
+        assert_eq!(pipeline.frontier(), Some(0x1030));
+
+        let completed = wait_for(&mut pipeline, 3);
+        assert_eq!(completed.len(), 3);
+        assert_eq!(completed[0].0, 0x1000);
+        assert_eq!(completed[2].0, 0x1020);
+        assert_eq!(completed[1].1, decode_bundle(nop_bundle()).unwrap());
+    }
+
+    #[test]
+    fn test_mispredict_discards() {
+        let mut pipeline = DecodeAhead::new();
+        pipeline.request(vec![(0x1000, nop_bundle())], 0x1010);
+        pipeline.predict(0x1000);
+        pipeline.execute_at(0x2000);
+        assert_eq!(pipeline.frontier(), None);
+
+        // Results from the old generation never surface
+        thread::sleep(Duration::from_millis(20));
+        assert!(pipeline.completed().is_empty());
+    }
+}
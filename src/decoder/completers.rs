@@ -0,0 +1,282 @@
+//! Typed instruction completers
+//!
+//! Completers are the dotted suffixes that refine an instruction, such as
+//! the `.acq` and `.nt1` of `ld8.acq.nt1` or the `.sptk.few` of
+//! `br.cond.sptk.few`. The decoder extracts them from the encoding as a
+//! [`Completers`] flag set, which instruction constructors test without
+//! allocating; `Display` renders them in assembler order for disassembly.
+
+use crate::EmulatorError;
+use std::fmt;
+use std::ops::{BitOr, BitOrAssign};
+use std::str::FromStr;
+
+/// Set of instruction completers
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct Completers(u32);
+
+impl Completers {
+    /// No completers
+    pub const NONE: Self = Self(0);
+    /// Control speculative load (`.s`)
+    pub const S: Self = Self(1 << 0);
+    /// Advanced load (`.a`)
+    pub const A: Self = Self(1 << 1);
+    /// Check load without clearing the ALAT entry (`.c.nc`)
+    pub const C_NC: Self = Self(1 << 2);
+    /// Check load clearing the ALAT entry (`.c.clr`)
+    pub const C_CLR: Self = Self(1 << 3);
+    /// Fill the NaT bit from AR.UNAT (`.fill`)
+    pub const FILL: Self = Self(1 << 4);
+    /// Spill the NaT bit to AR.UNAT (`.spill`)
+    pub const SPILL: Self = Self(1 << 5);
+    /// Acquire ordering (`.acq`)
+    pub const ACQ: Self = Self(1 << 6);
+    /// Release ordering (`.rel`)
+    pub const REL: Self = Self(1 << 7);
+    /// Fence ordering (`.fence`)
+    pub const FENCE: Self = Self(1 << 8);
+    /// Cache line bias towards exclusive ownership (`.bias`)
+    pub const BIAS: Self = Self(1 << 9);
+    /// Static prediction, taken (`.sptk`)
+    pub const SPTK: Self = Self(1 << 10);
+    /// Static prediction, not taken (`.spnt`)
+    pub const SPNT: Self = Self(1 << 11);
+    /// Dynamic prediction, taken (`.dptk`)
+    pub const DPTK: Self = Self(1 << 12);
+    /// Dynamic prediction, not taken (`.dpnt`)
+    pub const DPNT: Self = Self(1 << 13);
+    /// Prefetch few lines at the target (`.few`)
+    pub const FEW: Self = Self(1 << 14);
+    /// Prefetch many lines at the target (`.many`)
+    pub const MANY: Self = Self(1 << 15);
+    /// Deallocate the branch prediction entry (`.clr`)
+    pub const CLR: Self = Self(1 << 16);
+    /// Important branch (`.imp`)
+    pub const IMP: Self = Self(1 << 17);
+    /// Not temporal at level 1 (`.nt1`)
+    pub const NT1: Self = Self(1 << 18);
+    /// Not temporal at any level (`.nta`)
+    pub const NTA: Self = Self(1 << 19);
+
+    /// Completers with their names, in assembler order
+    const NAMES: [(Self, &'static str); 20] = [
+        (Self::S, "s"),
+        (Self::A, "a"),
+        (Self::C_NC, "c.nc"),
+        (Self::C_CLR, "c.clr"),
+        (Self::FILL, "fill"),
+        (Self::SPILL, "spill"),
+        (Self::ACQ, "acq"),
+        (Self::REL, "rel"),
+        (Self::FENCE, "fence"),
+        (Self::BIAS, "bias"),
+        (Self::SPTK, "sptk"),
+        (Self::SPNT, "spnt"),
+        (Self::DPTK, "dptk"),
+        (Self::DPNT, "dpnt"),
+        (Self::FEW, "few"),
+        (Self::MANY, "many"),
+        (Self::CLR, "clr"),
+        (Self::IMP, "imp"),
+        (Self::NT1, "nt1"),
+        (Self::NTA, "nta"),
+    ];
+
+    /// Get raw bits
+    pub fn bits(self) -> u32 {
+        self.0
+    }
+
+    /// Whether no completer is set
+    pub fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+
+    /// Whether every completer in `other` is set
+    pub fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    /// Completer with the given name, without the leading dot
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "sa" => Some(Self::S | Self::A),
+            name => Self::NAMES
+                .iter()
+                .find(|(_, n)| *n == name)
+                .map(|&(completer, _)| completer),
+        }
+    }
+
+    /// Completers of an M-unit instruction
+    ///
+    /// Integer loads and stores (majors 4 and 5) and floating-point ones
+    /// (majors 6 and 7) take their speculation and ordering completers from
+    /// x6 (bits 30-35) and a locality hint from bits 28-29.
+    pub fn decode_memory(bits: u64) -> Self {
+        let major = (bits >> 37) & 0xF;
+        if !(4..=7).contains(&major) || (bits >> 27) & 1 != 0 {
+            return Self::NONE;
+        }
+        let x6 = (bits >> 30) & 0x3F;
+        let integer = major <= 5;
+        let kind = match x6 >> 2 {
+            0x0 | 0xC => Self::NONE,
+            0x1 => Self::S,
+            0x2 => Self::A,
+            0x3 => Self::S | Self::A,
+            0x4 if integer => Self::BIAS,
+            0x5 if integer => Self::ACQ,
+            0x8 => Self::C_CLR,
+            0x9 => Self::C_NC,
+            0xA if integer => Self::C_CLR | Self::ACQ,
+            0xD if integer => Self::REL,
+            0x6 if x6 == 0x1B => Self::FILL,
+            0xE if x6 == 0x3B => Self::SPILL,
+            _ => return Self::NONE,
+        };
+        let hint = match (bits >> 28) & 0x3 {
+            1 => Self::NT1,
+            3 => Self::NTA,
+            _ => Self::NONE,
+        };
+        kind | hint
+    }
+
+    /// Completers of a B-unit instruction
+    ///
+    /// IP-relative branches and calls (majors 4 and 5) and indirect branches
+    /// and returns (major 0, x6 0x20 and 0x21) take the prediction hint from
+    /// bits 33-34, the prefetch hint from bit 12 and `.clr` from bit 35. So
+    /// do the X-unit long branches and calls, `brl` (majors C and D).
+    pub fn decode_branch(bits: u64) -> Self {
+        let major = (bits >> 37) & 0xF;
+        let x6 = (bits >> 27) & 0x3F;
+        if !matches!((major, x6), (4 | 5 | 0xC | 0xD, _) | (0, 0x20 | 0x21)) {
+            return Self::NONE;
+        }
+        let prediction = match (bits >> 33) & 0x3 {
+            0 => Self::SPTK,
+            1 => Self::SPNT,
+            2 => Self::DPTK,
+            _ => Self::DPNT,
+        };
+        let prefetch = if (bits >> 12) & 1 != 0 {
+            Self::MANY
+        } else {
+            Self::FEW
+        };
+        let dealloc = if (bits >> 35) & 1 != 0 {
+            Self::CLR
+        } else {
+            Self::NONE
+        };
+        prediction | prefetch | dealloc
+    }
+}
+
+impl BitOr for Completers {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self {
+        Self(self.0 | rhs.0)
+    }
+}
+
+impl BitOrAssign for Completers {
+    fn bitor_assign(&mut self, rhs: Self) {
+        self.0 |= rhs.0;
+    }
+}
+
+impl fmt::Display for Completers {
+    /// Render as dotted suffixes, e.g. `.sa.nt1`
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut rest = *self;
+        if rest.contains(Self::S | Self::A) {
+            write!(f, ".sa")?;
+            rest.0 &= !(Self::S | Self::A).0;
+        }
+        for (completer, name) in Self::NAMES {
+            if rest.contains(completer) {
+                write!(f, ".{}", name)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Completers {
+    type Err = EmulatorError;
+
+    /// Parse dotted suffixes, e.g. `.acq.nt1`; the leading dot is optional
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let text = text.strip_prefix('.').unwrap_or(text);
+        let mut completers = Self::NONE;
+        let mut parts = text.split('.').filter(|part| !part.is_empty());
+        while let Some(part) = parts.next() {
+            // Check loads are spelled with two parts
+            completers |= match part {
+                "c" => match parts.next() {
+                    Some("nc") => Self::C_NC,
+                    Some("clr") => Self::C_CLR,
+                    _ => return Err(unknown(text)),
+                },
+                part => Self::from_name(part).ok_or_else(|| unknown(text))?,
+            };
+        }
+        Ok(completers)
+    }
+}
+
+/// Error for completers that cannot be parsed
+fn unknown(text: &str) -> EmulatorError {
+    EmulatorError::DecodeError(format!("Unknown completers: {:?}", text))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Encode an integer load or store with the given x6 and hint
+    fn memory(major: u64, x6: u64, hint: u64) -> u64 {
+        (major << 37) | (x6 << 30) | (hint << 28)
+    }
+
+    #[test]
+    fn test_decode() {
+        let decode = Completers::decode_memory;
+        assert_eq!(decode(memory(4, 0x03, 0)), Completers::NONE);
+        assert_eq!(decode(memory(4, 0x0F, 1)).to_string(), ".sa.nt1");
+        assert_eq!(decode(memory(4, 0x2B, 0)).to_string(), ".c.clr.acq");
+        assert_eq!(decode(memory(4, 0x1B, 0)), Completers::FILL);
+        assert_eq!(decode(memory(4, 0x37, 3)).to_string(), ".rel.nta");
+        assert_eq!(decode(memory(6, 0x27, 0)), Completers::C_NC);
+        assert_eq!(decode(memory(6, 0x17, 0)), Completers::NONE);
+        assert_eq!(decode(memory(1, 0x04, 1)), Completers::NONE);
+
+        let call = (5 << 37) | (1 << 33) | (1 << 12) | (1 << 35);
+        assert_eq!(
+            Completers::decode_branch(call).to_string(),
+            ".spnt.many.clr"
+        );
+        let ret = 0x21 << 27;
+        assert_eq!(Completers::decode_branch(ret).to_string(), ".sptk.few");
+        assert!(Completers::decode_branch(2 << 37).is_empty());
+    }
+
+    #[test]
+    fn test_parse() {
+        for text in [".sa.nt1", ".c.clr.acq", ".dptk.many.clr", ".fill", ""] {
+            let completers: Completers = text.parse().unwrap();
+            assert_eq!(completers.to_string(), text);
+        }
+        assert_eq!(
+            "acq.nt1".parse::<Completers>().unwrap(),
+            Completers::ACQ | Completers::NT1
+        );
+        assert!("c.bogus".parse::<Completers>().is_err());
+        assert!(".sptk.later".parse::<Completers>().is_err());
+    }
+}
@@ -0,0 +1,967 @@
+//! Disassembler rendering instruction slots as IA-64 assembly
+//!
+//! An instruction is written the way the assembler reads it: the
+//! qualifying predicate unless it is p0, the mnemonic with its completers,
+//! then the destinations, `=` and the sources, as in
+//! `(p6) ld8.acq r4 = [r5]`. IP-relative targets are shown as absolute
+//! addresses, computed from the address of the bundle.
+//!
+//! A slot whose encoding the disassembler cannot name comes back as a
+//! `.slot` pseudo-instruction with its raw 41 bits, so every bundle with a
+//! valid template disassembles.
+
+use super::completers::Completers;
+use super::instruction_format::{XFormat, XOperation};
+use super::Unit;
+
+/// Relations of the integer compares with majors C, D and E, indexed by
+/// tb, ta and c
+pub(crate) const CMP_RELATIONS: [[&str; 8]; 3] = [
+    [
+        "lt", "lt.unc", "eq.and", "ne.and", "gt.and", "le.and", "ge.and", "lt.and",
+    ],
+    [
+        "ltu", "ltu.unc", "eq.or", "ne.or", "gt.or", "le.or", "ge.or", "lt.or",
+    ],
+    [
+        "eq",
+        "eq.unc",
+        "eq.or.andcm",
+        "ne.or.andcm",
+        "gt.or.andcm",
+        "le.or.andcm",
+        "ge.or.andcm",
+        "lt.or.andcm",
+    ],
+];
+
+/// Relations of tbit and tnat, indexed by tb, ta and c
+pub(crate) const TBIT_RELATIONS: [&str; 8] = [
+    "z",
+    "z.unc",
+    "z.and",
+    "nz.and",
+    "z.or",
+    "nz.or",
+    "z.or.andcm",
+    "nz.or.andcm",
+];
+
+/// Extract `len` bits of `bits` starting at bit `start`
+fn field(bits: u64, start: u32, len: u32) -> u64 {
+    (bits >> start) & ((1 << len) - 1)
+}
+
+/// Sign-extend the low `width` bits of `value`
+fn signed(value: u64, width: u32) -> i64 {
+    ((value << (64 - width)) as i64) >> (64 - width)
+}
+
+/// 21-bit immediate of break, nop and hint (imm20a in bits 6-25, i in bit 36)
+fn imm21(bits: u64) -> u64 {
+    field(bits, 6, 20) | (field(bits, 36, 1) << 20)
+}
+
+/// Target of an IP-relative branch or chk.a: imm20b in bits 13-32 and s in
+/// bit 36, counting bundles from `ip`
+fn relative_target(bits: u64, ip: u64) -> u64 {
+    let imm = field(bits, 13, 20) | (field(bits, 36, 1) << 20);
+    ip.wrapping_add((signed(imm, 21) << 4) as u64)
+}
+
+/// Target of chk.s: imm7a in bits 6-12, imm13c in bits 20-32, s in bit 36
+fn check_s_target(bits: u64, ip: u64) -> u64 {
+    let imm = field(bits, 6, 7) | (field(bits, 20, 13) << 7) | (field(bits, 36, 1) << 20);
+    ip.wrapping_add((signed(imm, 21) << 4) as u64)
+}
+
+/// 8-bit signed immediate in imm7b (bits 13-19) and s (bit 36)
+fn imm8(bits: u64) -> i64 {
+    signed(field(bits, 13, 7) | (field(bits, 36, 1) << 7), 8)
+}
+
+/// Assembler name of application register `n`
+pub fn ar_name(n: u64) -> String {
+    let name = match n {
+        0..=7 => return format!("ar.k{}", n),
+        16 => "rsc",
+        17 => "bsp",
+        18 => "bspstore",
+        19 => "rnat",
+        21 => "fcr",
+        24 => "eflag",
+        25 => "csd",
+        26 => "ssd",
+        27 => "cflg",
+        28 => "fsr",
+        29 => "fir",
+        30 => "fdr",
+        32 => "ccv",
+        36 => "unat",
+        40 => "fpsr",
+        44 => "itc",
+        64 => "pfs",
+        65 => "lc",
+        66 => "ec",
+        _ => return format!("ar{}", n),
+    };
+    format!("ar.{}", name)
+}
+
+/// Assembler name of control register `n`
+pub fn cr_name(n: u64) -> String {
+    let name = match n {
+        0 => "dcr",
+        1 => "itm",
+        2 => "iva",
+        8 => "pta",
+        16 => "ipsr",
+        17 => "isr",
+        19 => "iip",
+        20 => "ifa",
+        21 => "itir",
+        22 => "iipa",
+        23 => "ifs",
+        24 => "iim",
+        25 => "iha",
+        64 => "lid",
+        65 => "ivr",
+        66 => "tpr",
+        67 => "eoi",
+        68..=71 => return format!("cr.irr{}", n - 68),
+        72 => "itv",
+        73 => "pmv",
+        74 => "cmcv",
+        80 => "lrr0",
+        81 => "lrr1",
+        _ => return format!("cr{}", n),
+    };
+    format!("cr.{}", name)
+}
+
+/// Disassemble one instruction slot of a bundle at `ip`
+///
+/// `unit` is the unit the template assigns to the slot. For the X slot of
+/// an MLX bundle, `l_slot` is the L slot holding the upper bits of its
+/// immediate; it is ignored otherwise. An L slot has no instruction of its
+/// own and renders as `.slot`.
+pub fn disassemble_slot(unit: Unit, bits: u64, l_slot: u64, ip: u64) -> String {
+    let text = match unit {
+        Unit::A | Unit::I | Unit::M if matches!(field(bits, 37, 4), 8 | 9 | 0xC..=0xE) => {
+            integer_alu(bits)
+        }
+        Unit::A | Unit::I => integer(bits, ip),
+        Unit::M => memory(bits, ip),
+        Unit::F => floating(bits),
+        Unit::B => branch(bits, ip),
+        Unit::X => long(bits, l_slot, ip),
+        Unit::L => None,
+    };
+    match text {
+        Some(text) => match bits & 0x3F {
+            0 => text,
+            qp => format!("(p{}) {}", qp, text),
+        },
+        None => format!(".slot {:#x}", bits),
+    }
+}
+
+/// Break, nop and hint, which share their encoding on every unit
+fn break_nop(unit: char, x6: u64, bits: u64) -> Option<String> {
+    let mnemonic = match (x6, field(bits, 26, 1)) {
+        (0x00, _) => "break",
+        (0x01, 0) => "nop",
+        (0x01, _) => "hint",
+        _ => return None,
+    };
+    Some(format!("{}.{} {:#x}", mnemonic, unit, imm21(bits)))
+}
+
+/// A-unit instructions, which also issue in M and I slots
+fn integer_alu(bits: u64) -> Option<String> {
+    let r1 = field(bits, 6, 7);
+    let r2 = field(bits, 13, 7);
+    let r3 = field(bits, 20, 7);
+    let major = field(bits, 37, 4);
+
+    if major == 9 {
+        // addl: imm22 is s, imm5c, imm9d and imm7b, and r3 has two bits
+        let imm = field(bits, 13, 7)
+            | (field(bits, 27, 9) << 7)
+            | (field(bits, 22, 5) << 16)
+            | (field(bits, 36, 1) << 21);
+        let imm = signed(imm, 22);
+        let r3 = field(bits, 20, 2);
+        return Some(match r3 {
+            0 => format!("mov r{} = {}", r1, imm),
+            _ => format!("addl r{} = {}, r{}", r1, imm, r3),
+        });
+    }
+
+    if major >= 0xC {
+        return compare(bits, major);
+    }
+
+    let x2a = field(bits, 34, 2);
+    let ve = field(bits, 33, 1);
+    let x4 = field(bits, 29, 4);
+    let x2b = field(bits, 27, 2);
+    let logical = ["and", "andcm", "or", "xor"][x2b as usize];
+    Some(match (x2a, ve, x4, x2b) {
+        (2 | 3, _, _, _) => {
+            // adds and addp4: imm14 is s, imm6d and imm7b
+            let imm = field(bits, 13, 7) | (field(bits, 27, 6) << 7) | (field(bits, 36, 1) << 13);
+            let imm = signed(imm, 14);
+            match (x2a, imm) {
+                (2, 0) => format!("mov r{} = r{}", r1, r3),
+                (2, _) => format!("adds r{} = {}, r{}", r1, imm, r3),
+                _ => format!("addp4 r{} = {}, r{}", r1, imm, r3),
+            }
+        }
+        (0, 0, 0, 0) => format!("add r{} = r{}, r{}", r1, r2, r3),
+        (0, 0, 0, 1) => format!("add r{} = r{}, r{}, 1", r1, r2, r3),
+        (0, 0, 1, 0) => format!("sub r{} = r{}, r{}, 1", r1, r2, r3),
+        (0, 0, 1, 1) => format!("sub r{} = r{}, r{}", r1, r2, r3),
+        (0, 0, 2, 0) => format!("addp4 r{} = r{}, r{}", r1, r2, r3),
+        (0, 0, 3, _) => format!("{} r{} = r{}, r{}", logical, r1, r2, r3),
+        (0, 0, 4, _) => format!("shladd r{} = r{}, {}, r{}", r1, r2, x2b + 1, r3),
+        (0, 0, 6, _) => format!("shladdp4 r{} = r{}, {}, r{}", r1, r2, x2b + 1, r3),
+        (0, 0, 9, 1) => format!("sub r{} = {}, r{}", r1, imm8(bits), r3),
+        (0, 0, 0xB, _) => format!("{} r{} = {}, r{}", logical, r1, imm8(bits), r3),
+        _ => return None,
+    })
+}
+
+/// Integer compares (majors C-E), register and immediate forms
+fn compare(bits: u64, major: u64) -> Option<String> {
+    let relations = CMP_RELATIONS[(major - 0xC) as usize];
+    let x2 = field(bits, 34, 2);
+    let ta = field(bits, 33, 1);
+    let c = field(bits, 12, 1);
+    let p1 = field(bits, 6, 6);
+    let p2 = field(bits, 27, 6);
+    let r3 = field(bits, 20, 7);
+    let size = match x2 & 1 {
+        0 => "cmp",
+        _ => "cmp4",
+    };
+    Some(match x2 {
+        // tb (bit 36) selects the second half of the table, whose forms
+        // compare r3 against r0
+        0 | 1 => {
+            let tb = field(bits, 36, 1);
+            let relation = relations[(tb * 4 + ta * 2 + c) as usize];
+            let r2 = field(bits, 13, 7);
+            format!("{}.{} p{}, p{} = r{}, r{}", size, relation, p1, p2, r2, r3)
+        }
+        _ => {
+            let relation = relations[(ta * 2 + c) as usize];
+            format!(
+                "{}.{} p{}, p{} = {}, r{}",
+                size,
+                relation,
+                p1,
+                p2,
+                imm8(bits),
+                r3
+            )
+        }
+    })
+}
+
+/// I-unit instructions
+fn integer(bits: u64, ip: u64) -> Option<String> {
+    let r1 = field(bits, 6, 7);
+    let r2 = field(bits, 13, 7);
+    let r3 = field(bits, 20, 7);
+    let x3 = field(bits, 33, 3);
+    let x6 = field(bits, 27, 6);
+
+    Some(match field(bits, 37, 4) {
+        0 => match x3 {
+            0 => match x6 {
+                0x00 | 0x01 => return break_nop('i', x6, bits),
+                0x0A => format!("mov.i {} = {}", ar_name(r3), imm8(bits)),
+                0x10..=0x12 => format!("zxt{} r{} = r{}", 1 << (x6 & 3), r1, r3),
+                0x14..=0x16 => format!("sxt{} r{} = r{}", 1 << (x6 & 3), r1, r3),
+                0x18 | 0x19 => format!("czx{}.l r{} = r{}", 1 << (x6 & 1), r1, r3),
+                0x1C | 0x1D => format!("czx{}.r r{} = r{}", 1 << (x6 & 1), r1, r3),
+                0x2A => format!("mov.i {} = r{}", ar_name(r3), r2),
+                0x30 => format!("mov r{} = ip", r1),
+                0x31 => format!("mov r{} = b{}", r1, field(bits, 13, 3)),
+                0x32 => format!("mov.i r{} = {}", r1, ar_name(r3)),
+                0x33 => format!("mov r{} = pr", r1),
+                _ => return None,
+            },
+            1 => format!("chk.s.i r{}, {:#x}", r2, check_s_target(bits, ip)),
+            2 => {
+                // imm44 is s and imm27a, above 16 zero bits
+                let imm = (field(bits, 6, 27) << 16) | (field(bits, 36, 1) << 43);
+                format!("mov pr.rot = {:#x}", signed(imm, 44) as u64)
+            }
+            3 => {
+                // mask17 is s, mask8c and mask7a, above a zero bit for p0
+                let mask = (field(bits, 6, 7) << 1)
+                    | (field(bits, 24, 8) << 8)
+                    | (field(bits, 36, 1) << 16);
+                format!("mov pr = r{}, {:#x}", r2, mask)
+            }
+            7 => format!("mov b{} = r{}", field(bits, 6, 3), r2),
+            _ => return None,
+        },
+        4 => {
+            // dep: cpos6d in bits 31-36, len4d in bits 27-30
+            let pos = 63 - field(bits, 31, 6);
+            let len = field(bits, 27, 4) + 1;
+            format!("dep r{} = r{}, r{}, {}, {}", r1, r2, r3, pos, len)
+        }
+        5 => {
+            let x2 = field(bits, 34, 2);
+            let x = field(bits, 33, 1);
+            let len = field(bits, 27, 6) + 1;
+            match (x2, x) {
+                (0, _) => {
+                    // tbit and tnat: tb (bit 36), ta (bit 33) and c (bit 12)
+                    let relation = TBIT_RELATIONS
+                        [(field(bits, 36, 1) * 4 + x * 2 + field(bits, 12, 1)) as usize];
+                    let p1 = field(bits, 6, 6);
+                    let p2 = field(bits, 27, 6);
+                    match field(bits, 13, 1) {
+                        0 => format!(
+                            "tbit.{} p{}, p{} = r{}, {}",
+                            relation,
+                            p1,
+                            p2,
+                            r3,
+                            field(bits, 14, 6)
+                        ),
+                        _ => format!("tnat.{} p{}, p{} = r{}", relation, p1, p2, r3),
+                    }
+                }
+                (1, 0) => {
+                    let mnemonic = match field(bits, 13, 1) {
+                        0 => "extr.u",
+                        _ => "extr",
+                    };
+                    let pos = field(bits, 14, 6);
+                    format!("{} r{} = r{}, {}, {}", mnemonic, r1, r3, pos, len)
+                }
+                (1, 1) => {
+                    let pos = 63 - field(bits, 20, 6);
+                    match field(bits, 26, 1) {
+                        0 => format!("dep.z r{} = r{}, {}, {}", r1, r2, pos, len),
+                        _ => format!("dep.z r{} = {}, {}, {}", r1, imm8(bits), pos, len),
+                    }
+                }
+                (3, 0) => format!("shrp r{} = r{}, r{}, {}", r1, r2, r3, field(bits, 27, 6)),
+                (3, 1) => {
+                    let imm1 = -(field(bits, 36, 1) as i64);
+                    let pos = 63 - field(bits, 14, 6);
+                    format!("dep r{} = {}, r{}, {}, {}", r1, imm1, r3, pos, len)
+                }
+                _ => return None,
+            }
+        }
+        7 => {
+            // Variable shifts: za (bit 36), x2a, zb (bit 33), ve, x2c, x2b
+            let key = (
+                field(bits, 36, 1),
+                field(bits, 34, 2),
+                field(bits, 33, 1),
+                field(bits, 32, 1),
+                field(bits, 30, 2),
+                field(bits, 28, 2),
+            );
+            match key {
+                (1, 0, 1, 0, 0, 0) => format!("shr.u r{} = r{}, r{}", r1, r3, r2),
+                (1, 0, 1, 0, 0, 2) => format!("shr r{} = r{}, r{}", r1, r3, r2),
+                (1, 0, 1, 0, 1, 0) => format!("shl r{} = r{}, r{}", r1, r2, r3),
+                _ => return None,
+            }
+        }
+        _ => return None,
+    })
+}
+
+/// M-unit instructions
+fn memory(bits: u64, ip: u64) -> Option<String> {
+    let r1 = field(bits, 6, 7);
+    let r2 = field(bits, 13, 7);
+    let r3 = field(bits, 20, 7);
+    let x3 = field(bits, 33, 3);
+    let x6 = field(bits, 27, 6);
+
+    Some(match field(bits, 37, 4) {
+        0 => match x3 {
+            0 => match x6 {
+                0x00 | 0x01 => return break_nop('m', x6, bits),
+                // imm24 is i, i2d and imm21a, with i2d in the upper bits
+                // of x6
+                _ if (0x04..=0x07).contains(&(x6 & 0xF)) => {
+                    let imm = field(bits, 6, 21)
+                        | (field(bits, 31, 2) << 21)
+                        | (field(bits, 36, 1) << 23);
+                    let mnemonic = ["sum", "rum", "ssm", "rsm"][(x6 & 3) as usize];
+                    format!("{} {:#x}", mnemonic, imm)
+                }
+                0x0A => "loadrs".to_string(),
+                0x0C => "flushrs".to_string(),
+                0x10 => "invala".to_string(),
+                0x12 => format!("invala.e r{}", r1),
+                0x13 => format!("invala.e f{}", r1),
+                0x20 => "fwb".to_string(),
+                0x22 => "mf".to_string(),
+                0x23 => "mf.a".to_string(),
+                0x28 => format!("mov.m {} = {}", ar_name(r3), imm8(bits)),
+                0x30 => "srlz.d".to_string(),
+                0x31 => "srlz.i".to_string(),
+                0x33 => "sync.i".to_string(),
+                _ => return None,
+            },
+            4..=7 => {
+                let mnemonic = match x3 & 1 {
+                    0 => "chk.a.nc",
+                    _ => "chk.a.clr",
+                };
+                let register = match x3 {
+                    4 | 5 => 'r',
+                    _ => 'f',
+                };
+                format!(
+                    "{} {}{}, {:#x}",
+                    mnemonic,
+                    register,
+                    r1,
+                    relative_target(bits, ip)
+                )
+            }
+            _ => return None,
+        },
+        1 => match x3 {
+            0 => system(bits, x6)?,
+            1 => format!("chk.s.m r{}, {:#x}", r2, check_s_target(bits, ip)),
+            3 => format!("chk.s f{}, {:#x}", r2, check_s_target(bits, ip)),
+            6 => {
+                // The frame sizes do not record how the locals split into
+                // inputs and locals, so all of them are shown as locals
+                let sof = field(bits, 13, 7);
+                let sol = field(bits, 20, 7);
+                let sor = field(bits, 27, 4);
+                format!(
+                    "alloc r{} = ar.pfs, 0, {}, {}, {}",
+                    r1,
+                    sol,
+                    sof.saturating_sub(sol),
+                    sor * 8
+                )
+            }
+            _ => return None,
+        },
+        major @ 4..=7 => memory_access(bits, major)?,
+        _ => return None,
+    })
+}
+
+/// System and register-file moves (major 1, x3 0)
+fn system(bits: u64, x6: u64) -> Option<String> {
+    let r1 = field(bits, 6, 7);
+    let r2 = field(bits, 13, 7);
+    let r3 = field(bits, 20, 7);
+    let files = ["rr", "dbr", "ibr", "pkr", "pmc", "pmd", "msr", "cpuid"];
+    Some(match x6 {
+        0x00..=0x06 => format!("mov {}[r{}] = r{}", files[x6 as usize], r3, r2),
+        0x09 => format!("ptc.l r{}, r{}", r3, r2),
+        0x0A => format!("ptc.g r{}, r{}", r3, r2),
+        0x0B => format!("ptc.ga r{}, r{}", r3, r2),
+        0x0C => format!("ptr.d r{}, r{}", r3, r2),
+        0x0D => format!("ptr.i r{}, r{}", r3, r2),
+        0x0E => format!("itr.d dtr[r{}] = r{}", r3, r2),
+        0x0F => format!("itr.i itr[r{}] = r{}", r3, r2),
+        0x10..=0x17 => format!("mov r{} = {}[r{}]", r1, files[(x6 & 7) as usize], r3),
+        0x18 => format!("probe.r r{} = r{}, r{}", r1, r3, r2),
+        0x19 => format!("probe.w r{} = r{}, r{}", r1, r3, r2),
+        0x1A => format!("thash r{} = r{}", r1, r3),
+        0x1B => format!("ttag r{} = r{}", r1, r3),
+        0x1E => format!("tpa r{} = r{}", r1, r3),
+        0x1F => format!("tak r{} = r{}", r1, r3),
+        0x21 => format!("mov r{} = psr.um", r1),
+        0x22 => format!("mov.m r{} = {}", r1, ar_name(r3)),
+        0x24 => format!("mov r{} = {}", r1, cr_name(r3)),
+        0x25 => format!("mov r{} = psr", r1),
+        0x29 => format!("mov psr.um = r{}", r2),
+        0x2A => format!("mov.m {} = r{}", ar_name(r3), r2),
+        0x2C => format!("mov {} = r{}", cr_name(r3), r2),
+        0x2D => format!("mov psr.l = r{}", r2),
+        0x2E => format!("itc.d r{}", r2),
+        0x2F => format!("itc.i r{}", r2),
+        0x30 => format!("fc r{}", r3),
+        0x31 => format!("probe.rw.fault r{}, {}", r3, field(bits, 13, 2)),
+        0x32 => format!("probe.r.fault r{}, {}", r3, field(bits, 13, 2)),
+        0x33 => format!("probe.w.fault r{}, {}", r3, field(bits, 13, 2)),
+        0x34 => format!("ptc.e r{}", r3),
+        0x38 => format!("probe.r r{} = r{}, {}", r1, r3, field(bits, 13, 2)),
+        0x39 => format!("probe.w r{} = r{}, {}", r1, r3, field(bits, 13, 2)),
+        _ => return None,
+    })
+}
+
+/// Integer and floating-point loads, stores, semaphores and transfers
+/// (majors 4-7)
+///
+/// x6 sits in bits 30-35 here, with the m bit (36) selecting a register
+/// post-increment and the x bit (27) the semaphores and transfers.
+fn memory_access(bits: u64, major: u64) -> Option<String> {
+    let r1 = field(bits, 6, 7);
+    let r2 = field(bits, 13, 7);
+    let r3 = field(bits, 20, 7);
+    let x6 = field(bits, 30, 6);
+    let m = field(bits, 36, 1);
+    let x = field(bits, 27, 1);
+    let integer = major <= 5;
+    // Bit 27 is part of the increment in majors 5 and 7, and the
+    // completers of ldfp are those of ldf
+    let completers = Completers::decode_memory(bits & !(1 << 27));
+    let hint = ["", ".nt1", "", ".nta"][field(bits, 28, 2) as usize];
+    // imm9 of the post-increment loads (imm7b) and stores (imm7a), with i
+    // in bit 27 and s in bit 36
+    let load_imm = signed(field(bits, 13, 7) | (x << 7) | (m << 8), 9);
+    let store_imm = signed(field(bits, 6, 7) | (x << 7) | (m << 8), 9);
+
+    if x == 1 && major == 4 && m == 0 {
+        let size = 1 << (x6 & 3);
+        return Some(match x6 {
+            0x00..=0x07 => {
+                let order = if x6 < 4 { "acq" } else { "rel" };
+                format!(
+                    "cmpxchg{}.{}{} r{} = [r{}], r{}, ar.ccv",
+                    size, order, hint, r1, r3, r2
+                )
+            }
+            0x08..=0x0B => format!("xchg{}{} r{} = [r{}], r{}", size, hint, r1, r3, r2),
+            0x12 | 0x13 | 0x16 | 0x17 => {
+                // inc3 is s (bit 15) and i2b (bits 13-14)
+                let magnitude = [16, 8, 4, 1][field(bits, 13, 2) as usize];
+                let inc = if field(bits, 15, 1) != 0 {
+                    -magnitude
+                } else {
+                    magnitude
+                };
+                let order = if x6 & 4 == 0 { "acq" } else { "rel" };
+                format!(
+                    "fetchadd{}.{}{} r{} = [r{}], {}",
+                    size, order, hint, r1, r3, inc
+                )
+            }
+            0x1C..=0x1F => {
+                let kind = ["sig", "exp", "s", "d"][(x6 & 3) as usize];
+                format!("getf.{} r{} = f{}", kind, r1, r2)
+            }
+            _ => return None,
+        });
+    }
+
+    if x == 1 && major == 6 {
+        if m == 0 && (0x1C..=0x1F).contains(&x6) {
+            let kind = ["sig", "exp", "s", "d"][(x6 & 3) as usize];
+            return Some(format!("setf.{} f{} = r{}", kind, r1, r2));
+        }
+        // ldfp: the base post-increment is implied by the size
+        let size = ["", "8", "s", "d"][(x6 & 3) as usize];
+        if size.is_empty() || !matches!(x6 >> 2, 0..=3 | 8 | 9) {
+            return None;
+        }
+        let pair = format!("ldfp{}{} f{}, f{} = [r{}]", size, completers, r1, r2, r3);
+        return Some(match m {
+            0 => pair,
+            _ => format!("{}, {}", pair, if x6 & 3 == 2 { 8 } else { 16 }),
+        });
+    }
+    if x == 1 && major != 5 && major != 7 {
+        return None;
+    }
+
+    // Register post-increment of majors 4 and 6
+    let increment = match m {
+        0 => String::new(),
+        _ => format!(", r{}", r2),
+    };
+    let is_load = matches!(x6 >> 2, 0..=3 | 8 | 9) || (integer && matches!(x6 >> 2, 4 | 5 | 0xA));
+    let is_store = matches!(x6 >> 2, 0xC) || (integer && x6 >> 2 == 0xD);
+
+    let size = if integer {
+        (1u64 << (x6 & 3)).to_string()
+    } else {
+        ["e", "8", "s", "d"][(x6 & 3) as usize].to_string()
+    };
+    let (ld, st, register) = if integer {
+        ("ld", "st", 'r')
+    } else {
+        ("ldf", "stf", 'f')
+    };
+
+    Some(if is_load || x6 == 0x1B {
+        let size = if !integer && x6 == 0x1B { "" } else { &size };
+        let target = format!(
+            "{}{}{} {}{} = [r{}]",
+            ld, size, completers, register, r1, r3
+        );
+        match major {
+            5 | 7 => format!("{}, {}", target, load_imm),
+            _ => format!("{}{}", target, increment),
+        }
+    } else if is_store || x6 == 0x3B {
+        if m == 1 && matches!(major, 4 | 6) {
+            return None;
+        }
+        let size = if !integer && x6 == 0x3B { "" } else { &size };
+        let target = format!(
+            "{}{}{} [r{}] = {}{}",
+            st, size, completers, r3, register, r2
+        );
+        match major {
+            5 | 7 => format!("{}, {}", target, store_imm),
+            _ => target,
+        }
+    } else if !integer && (0x2C..=0x2F).contains(&x6) {
+        let mut mnemonic = "lfetch".to_string();
+        if x6 & 2 != 0 {
+            mnemonic.push_str(".fault");
+        }
+        if x6 & 1 != 0 {
+            mnemonic.push_str(".excl");
+        }
+        mnemonic.push_str(["", ".nt1", ".nt2", ".nta"][field(bits, 28, 2) as usize]);
+        match major {
+            7 => format!("{} [r{}], {}", mnemonic, r3, load_imm),
+            _ => format!("{} [r{}]{}", mnemonic, r3, increment),
+        }
+    } else {
+        return None;
+    })
+}
+
+/// Status field completer of a floating-point instruction, omitted for s0
+fn status_field(bits: u64) -> String {
+    match field(bits, 34, 2) {
+        0 => String::new(),
+        sf => format!(".s{}", sf),
+    }
+}
+
+/// F-unit instructions
+fn floating(bits: u64) -> Option<String> {
+    let f1 = field(bits, 6, 7);
+    let f2 = field(bits, 13, 7);
+    let f3 = field(bits, 20, 7);
+    let f4 = field(bits, 27, 7);
+    let sf = status_field(bits);
+
+    Some(match field(bits, 37, 4) {
+        0 if field(bits, 33, 1) == 0 => {
+            let x6 = field(bits, 27, 6);
+            match x6 {
+                0x00 | 0x01 => return break_nop('f', x6, bits),
+                0x10 => format!("fmerge.s f{} = f{}, f{}", f1, f2, f3),
+                0x11 => format!("fmerge.ns f{} = f{}, f{}", f1, f2, f3),
+                0x12 => format!("fmerge.se f{} = f{}, f{}", f1, f2, f3),
+                0x18..=0x1B => {
+                    let kind = ["fx", "fxu", "fx.trunc", "fxu.trunc"][(x6 & 3) as usize];
+                    format!("fcvt.{}{} f{} = f{}", kind, sf, f1, f2)
+                }
+                0x1C => format!("fcvt.xf f{} = f{}", f1, f2),
+                0x2C..=0x2F => {
+                    let mnemonic = ["fand", "fandcm", "for", "fxor"][(x6 & 3) as usize];
+                    format!("{} f{} = f{}, f{}", mnemonic, f1, f2, f3)
+                }
+                _ => return None,
+            }
+        }
+        4 => {
+            // fcmp: ra (bit 33) and rb (bit 36) pick the relation, ta
+            // (bit 12) makes it unconditional
+            let relation =
+                ["eq", "lt", "le", "unord"][(field(bits, 33, 1) * 2 + field(bits, 36, 1)) as usize];
+            let unc = if field(bits, 12, 1) != 0 { ".unc" } else { "" };
+            format!(
+                "fcmp.{}{}{} p{}, p{} = f{}, f{}",
+                relation,
+                unc,
+                sf,
+                field(bits, 6, 6),
+                field(bits, 27, 6),
+                f2,
+                f3
+            )
+        }
+        major @ 8..=0xD => {
+            let base = ["fma", "fms", "fnma"][((major - 8) / 2) as usize];
+            let precision = match (major & 1, field(bits, 36, 1)) {
+                (0, 0) => base.to_string(),
+                (0, _) => format!("{}.s", base),
+                (_, 0) => format!("{}.d", base),
+                _ => format!("fp{}", &base[1..]),
+            };
+            format!("{}{} f{} = f{}, f{}, f{}", precision, sf, f1, f3, f4, f2)
+        }
+        0xE => match (field(bits, 36, 1), field(bits, 34, 2)) {
+            (0, _) => format!("fselect f{} = f{}, f{}, f{}", f1, f3, f4, f2),
+            (_, 0) => format!("xma.l f{} = f{}, f{}, f{}", f1, f3, f4, f2),
+            (_, 2) => format!("xma.hu f{} = f{}, f{}, f{}", f1, f3, f4, f2),
+            (_, 3) => format!("xma.h f{} = f{}, f{}, f{}", f1, f3, f4, f2),
+            _ => return None,
+        },
+        _ => return None,
+    })
+}
+
+/// B-unit instructions
+fn branch(bits: u64, ip: u64) -> Option<String> {
+    let b1 = field(bits, 6, 3);
+    let b2 = field(bits, 13, 3);
+    let btype = field(bits, 6, 3);
+    let x6 = field(bits, 27, 6);
+    let hints = Completers::decode_branch(bits);
+
+    Some(match field(bits, 37, 4) {
+        0 => match x6 {
+            0x00 => return break_nop('b', x6, bits),
+            0x02 => "cover".to_string(),
+            0x04 => "clrrrb".to_string(),
+            0x05 => "clrrrb.pr".to_string(),
+            0x08 => "rfi".to_string(),
+            0x0C => "bsw.0".to_string(),
+            0x0D => "bsw.1".to_string(),
+            0x10 => "epc".to_string(),
+            0x20 if btype == 0 => format!("br.cond{} b{}", hints, b2),
+            0x20 if btype == 1 => format!("br.ia{} b{}", hints, b2),
+            0x21 if btype == 4 => format!("br.ret{} b{}", hints, b2),
+            _ => return None,
+        },
+        1 => {
+            // Indirect calls have a 3-bit prediction hint in bits 32-34,
+            // whose low bit is always set
+            let hint = ["sptk", "spnt", "dptk", "dpnt"][field(bits, 33, 2) as usize];
+            let prefetch = if field(bits, 12, 1) != 0 {
+                "many"
+            } else {
+                "few"
+            };
+            let dealloc = if field(bits, 35, 1) != 0 { ".clr" } else { "" };
+            format!("br.call.{}.{}{} b{} = b{}", hint, prefetch, dealloc, b1, b2)
+        }
+        2 => match x6 {
+            0x00 => format!("nop.b {:#x}", imm21(bits)),
+            0x01 => format!("hint.b {:#x}", imm21(bits)),
+            _ => return None,
+        },
+        4 => {
+            let mnemonic = match btype {
+                0 => "br.cond",
+                2 => "br.wexit",
+                3 => "br.wtop",
+                5 => "br.cloop",
+                6 => "br.cexit",
+                7 => "br.ctop",
+                _ => return None,
+            };
+            format!("{}{} {:#x}", mnemonic, hints, relative_target(bits, ip))
+        }
+        5 => format!(
+            "br.call{} b{} = {:#x}",
+            hints,
+            b1,
+            relative_target(bits, ip)
+        ),
+        _ => return None,
+    })
+}
+
+/// X-unit instructions, with the immediate bits from the L slot
+fn long(bits: u64, l_slot: u64, ip: u64) -> Option<String> {
+    let format = XFormat {
+        l_slot,
+        ..XFormat::decode(bits)
+    };
+    let hints = Completers::decode_branch(bits);
+    Some(match format.operation()? {
+        XOperation::Break(imm) => format!("break.x {:#x}", imm),
+        XOperation::Nop(imm) => format!("nop.x {:#x}", imm),
+        XOperation::Hint(imm) => format!("hint.x {:#x}", imm),
+        XOperation::Movl { r1, imm } => format!("movl r{} = {:#x}", r1, imm),
+        XOperation::Brl(offset) => {
+            format!("brl.cond{} {:#x}", hints, ip.wrapping_add(offset as u64))
+        }
+        XOperation::BrlCall { b1, offset } => format!(
+            "brl.call{} b{} = {:#x}",
+            hints,
+            b1,
+            ip.wrapping_add(offset as u64)
+        ),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::decoder::Bundle;
+    use crate::test_util::{encode_bundle, nop};
+
+    #[test]
+    fn test_slots() {
+        let cases: [(Unit, u64, &str); 16] = [
+            // ld8.acq r4=[r5] under p6
+            (
+                Unit::M,
+                (4 << 37) | (0x17 << 30) | (5 << 20) | (4 << 6) | 6,
+                "(p6) ld8.acq r4 = [r5]",
+            ),
+            // ld4.s.nta r8=[r9],r10
+            (
+                Unit::M,
+                (4 << 37)
+                    | (1 << 36)
+                    | (0x06 << 30)
+                    | (3 << 28)
+                    | (9 << 20)
+                    | (10 << 13)
+                    | (8 << 6),
+                "ld4.s.nta r8 = [r9], r10",
+            ),
+            // st8 [r3]=r2,-8
+            (
+                Unit::M,
+                (5 << 37)
+                    | (1 << 36)
+                    | (0x33 << 30)
+                    | (1 << 27)
+                    | (3 << 20)
+                    | (2 << 13)
+                    | 0x78 << 6,
+                "st8 [r3] = r2, -8",
+            ),
+            (
+                Unit::M,
+                (1 << 37) | (6 << 33) | (2 << 20) | (4 << 13) | (32 << 6),
+                "alloc r32 = ar.pfs, 0, 2, 2, 0",
+            ),
+            (
+                Unit::M,
+                (1 << 37) | (0x22 << 27) | (64 << 20) | (9 << 6),
+                "mov.m r9 = ar.pfs",
+            ),
+            (
+                Unit::M,
+                (1 << 37) | (0x2C << 27) | (2 << 20) | (7 << 13),
+                "mov cr.iva = r7",
+            ),
+            (
+                Unit::I,
+                (8 << 37) | (3 << 20) | (2 << 13) | (1 << 6),
+                "add r1 = r2, r3",
+            ),
+            // adds r12=-16,r12
+            (
+                Unit::I,
+                (8 << 37)
+                    | (1 << 36)
+                    | (2 << 34)
+                    | (0x3F << 27)
+                    | (12 << 20)
+                    | (0x70 << 13)
+                    | (12 << 6),
+                "adds r12 = -16, r12",
+            ),
+            (Unit::M, (9 << 37) | (42 << 13) | (8 << 6), "mov r8 = 42"),
+            // cmp.eq.unc p6,p7=r14,r15 and cmp4.lt p1,p2=5,r3
+            (
+                Unit::I,
+                (0xE << 37) | (7 << 27) | (15 << 20) | (14 << 13) | (1 << 12) | (6 << 6),
+                "cmp.eq.unc p6, p7 = r14, r15",
+            ),
+            (
+                Unit::M,
+                (0xC << 37) | (3 << 34) | (2 << 27) | (3 << 20) | (5 << 13) | (1 << 6),
+                "cmp4.lt p1, p2 = 5, r3",
+            ),
+            // extr.u r8=r9,4,8
+            (
+                Unit::I,
+                (5 << 37) | (1 << 34) | (7 << 27) | (9 << 20) | (4 << 14) | (8 << 6),
+                "extr.u r8 = r9, 4, 8",
+            ),
+            (Unit::I, (0x31 << 27) | (6 << 13) | (3 << 6), "mov r3 = b6"),
+            (
+                Unit::B,
+                (0x21 << 27) | (1 << 12) | (4 << 6),
+                "br.ret.sptk.many b0",
+            ),
+            // br.call.dptk.few b0=+0x20
+            (
+                Unit::B,
+                (5 << 37) | (2 << 33) | (2 << 13),
+                "br.call.dptk.few b0 = 0x4020",
+            ),
+            (
+                Unit::F,
+                (8 << 37) | (1 << 36) | (1 << 34) | (8 << 27) | (7 << 20) | (9 << 6),
+                "fma.s.s1 f9 = f7, f8, f0",
+            ),
+        ];
+        for (unit, bits, text) in cases {
+            assert_eq!(disassemble_slot(unit, bits, 0, 0x4000), text, "{:#x}", bits);
+        }
+
+        // Encodings with no name, and the L slot itself
+        assert_eq!(
+            disassemble_slot(Unit::I, (8 << 37) | (1 << 34), 0, 0),
+            ".slot 0x10400000000"
+        );
+        assert_eq!(disassemble_slot(Unit::L, 0x155, 0, 0), ".slot 0x155");
+        assert_eq!(ar_name(17), "ar.bsp");
+        assert_eq!(cr_name(69), "cr.irr1");
+        assert_eq!(cr_name(3), "cr3");
+    }
+
+    #[test]
+    fn test_bundles() {
+        // { .mii; ld8 r4=[r3]; mov r14=ip;; nop.i 0x0 }
+        let ld8 = (4 << 37) | (0x03 << 30) | (3 << 20) | (4 << 6);
+        let mov_ip = (0x30 << 27) | (14 << 6) | 2;
+        let bundle = Bundle::new(encode_bundle(0x02, [ld8, mov_ip, nop()])).unwrap();
+        assert_eq!(
+            bundle.disassemble(),
+            ["ld8 r4 = [r3]", "(p2) mov r14 = ip ;;", "nop.i 0x0"]
+        );
+
+        // { .mlx; nop.m; movl r8=0x123456789abcdef0;; }, one instruction
+        // for the L and X slots
+        let imm: u64 = 0x1234_5678_9abc_def0;
+        let x = (6 << 37)
+            | ((imm >> 63) << 36)
+            | (((imm >> 7) & 0x1FF) << 27)
+            | (((imm >> 16) & 0x1F) << 22)
+            | (((imm >> 21) & 1) << 21)
+            | ((imm & 0x7F) << 13)
+            | (8 << 6);
+        let l = (imm >> 22) & ((1 << 41) - 1);
+        let bundle = Bundle::new(encode_bundle(0x05, [nop(), l, x])).unwrap();
+        assert_eq!(
+            bundle.disassemble(),
+            ["nop.m 0x0", "movl r8 = 0x123456789abcdef0 ;;"]
+        );
+
+        // { .mib; nop.m; nop.i; (p6) br.cond.dptk.few -0x10 }
+        let br = (4 << 37) | (1 << 36) | (0xFFFFF << 13) | (2 << 33) | 6;
+        let bundle = Bundle::new(encode_bundle(0x10, [nop(), nop(), br])).unwrap();
+        assert_eq!(
+            bundle.disassemble_at(0x10000)[2],
+            "(p6) br.cond.dptk.few 0xfff0"
+        );
+        assert_eq!(
+            Bundle::new(encode_bundle(0x10, [nop(), nop(), 2 << 37]))
+                .unwrap()
+                .disassemble()[2],
+            "nop.b 0x0"
+        );
+    }
+}
@@ -98,16 +98,51 @@ pub struct BFormat {
 }
 
 /// X-type instruction format (Extended)
+///
+/// Unlike the other formats, fields sit at their architectural positions
+/// in the 41-bit slot. The X slot pairs with the L slot before it, which
+/// holds the upper bits of every X-unit immediate.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
 pub struct XFormat {
-    /// Major opcode [0:7]
+    /// Predicate register (qp) [0:5]
+    pub predicate: u8,
+    /// Major opcode [37:40]
     pub major_opcode: u8,
-    /// x2 field [8:13]
-    pub x2: u8,
-    /// ve field [14:14]
-    pub ve: bool,
-    /// Immediate27 [15:41]
-    pub imm27: u32,
+    /// x3 opcode extension of break.x, nop.x and hint.x [33:35]
+    pub x3: u8,
+    /// x6 opcode extension of break.x, nop.x and hint.x [27:32]
+    pub x6: u8,
+    /// The raw X slot
+    pub x_slot: u64,
+    /// The paired L slot, which holds the upper bits of long immediates
+    pub l_slot: u64,
+}
+
+/// Operation of an X-unit slot with its full immediate
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum XOperation {
+    /// break.x imm62 (X1)
+    Break(u64),
+    /// nop.x imm62 (X5)
+    Nop(u64),
+    /// hint.x imm62 (X5)
+    Hint(u64),
+    /// movl r1=imm64 (X2)
+    Movl {
+        /// Target general register
+        r1: u8,
+        /// Immediate
+        imm: u64,
+    },
+    /// brl.cond target64 (X3), with the offset from the bundle in bytes
+    Brl(i64),
+    /// brl.call b1=target64 (X4), with the offset from the bundle in bytes
+    BrlCall {
+        /// Branch register receiving the return link
+        b1: u8,
+        /// Offset from the bundle in bytes
+        offset: i64,
+    },
 }
 
 /// L-type instruction format (Long immediate)
@@ -196,13 +231,61 @@ impl BFormat {
 }
 
 impl XFormat {
-    /// Decodes a 64-bit instruction into an X-format instruction
+    /// Decodes a 41-bit X slot; the caller fills in the L slot
     pub fn decode(bits: u64) -> Self {
         Self {
-            major_opcode: (bits & 0xFF) as u8,
-            x2: ((bits >> 8) & 0x3F) as u8,
-            ve: ((bits >> 14) & 0x1) != 0,
-            imm27: ((bits >> 15) & 0x7FFFFFF) as u32,
+            predicate: (bits & 0x3F) as u8,
+            major_opcode: ((bits >> 37) & 0xF) as u8,
+            x3: ((bits >> 33) & 0x7) as u8,
+            x6: ((bits >> 27) & 0x3F) as u8,
+            x_slot: bits,
+            l_slot: 0,
+        }
+    }
+
+    /// The operation and its immediate assembled from both slots, if the
+    /// encoding is defined
+    pub fn operation(&self) -> Option<XOperation> {
+        let x = self.x_slot;
+        let bit = |start: u32, len: u32| (x >> start) & ((1 << len) - 1);
+        let l = self.l_slot & ((1 << 41) - 1);
+        match (self.major_opcode, self.x3, self.x6) {
+            // imm62 is the L slot above i (bit 36) and imm20a (bits 6-25);
+            // y (bit 26) tells hint.x from nop.x
+            (0, 0, 0x00 | 0x01) => {
+                let imm = (l << 21) | (bit(36, 1) << 20) | bit(6, 20);
+                Some(match (self.x6, bit(26, 1)) {
+                    (0x00, _) => XOperation::Break(imm),
+                    (_, 0) => XOperation::Nop(imm),
+                    _ => XOperation::Hint(imm),
+                })
+            }
+            // vc (bit 20) must be clear
+            (6, _, _) if bit(20, 1) == 0 => Some(XOperation::Movl {
+                r1: bit(6, 7) as u8,
+                imm: (bit(36, 1) << 63)
+                    | (l << 22)
+                    | (bit(21, 1) << 21)
+                    | (bit(22, 5) << 16)
+                    | (bit(27, 9) << 7)
+                    | bit(13, 7),
+            }),
+            // imm60 is i (bit 36), imm39 (L slot bits 2-40) and imm20b
+            // (bits 13-32), counted in bundles; brl.cond has btype 0
+            (0xC | 0xD, _, _) => {
+                let imm = (bit(36, 1) << 59) | ((l >> 2) << 20) | bit(13, 20);
+                // imm60 fills the top of the 64-bit offset, sign included
+                let offset = (imm << 4) as i64;
+                match (self.major_opcode, bit(6, 3)) {
+                    (0xC, 0) => Some(XOperation::Brl(offset)),
+                    (0xD, b1) => Some(XOperation::BrlCall {
+                        b1: b1 as u8,
+                        offset,
+                    }),
+                    _ => None,
+                }
+            }
+            _ => None,
         }
     }
 }
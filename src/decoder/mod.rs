@@ -4,49 +4,151 @@
 //! handling the EPIC (Explicitly Parallel Instruction Computing) format.
 
 use crate::EmulatorError;
+use std::fmt;
 
 pub mod bundle;
+pub mod completers;
+pub mod disasm;
 /// Module containing instruction format definitions and parsing
 pub mod instruction_format;
 
+use completers::Completers;
 use instruction_format::*;
 
 /// IA-64 instruction bundle template types
+///
+/// The template assigns a unit to each slot and places the stops that end
+/// instruction groups, written `;` in assembly. `Stop` in a name marks a
+/// stop after the slot before it, so `MIStopIStop` is `MI;I;`. Templates
+/// 0x06, 0x07, 0x14, 0x15, 0x1A, 0x1B, 0x1E and 0x1F are reserved.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[repr(u8)]
 pub enum BundleTemplate {
-    /// MII: Memory + I-unit + I-unit
-    MII = 0b00000,
-    /// MIB: Memory + I-unit + B-unit
-    MIB = 0b00001,
-    /// MMI: Memory + Memory + I-unit
-    MMI = 0b00010,
-    /// MMF: Memory + Memory + F-unit
-    MMF = 0b00011,
-    /// MLX: Memory + Long immediate
-    MLX = 0b00100,
-    /// FBI: F-unit + B-unit + I-unit
-    FBI = 0b01000,
-    /// BBB: B-unit + B-unit + B-unit
-    BBB = 0b01001,
-    /// AAA: A-unit + A-unit + A-unit
-    AAA = 0b01010,
+    /// MII
+    MII = 0x00,
+    /// MII;
+    MIIStop = 0x01,
+    /// MI;I
+    MIStopI = 0x02,
+    /// MI;I;
+    MIStopIStop = 0x03,
+    /// MLX
+    MLX = 0x04,
+    /// MLX;
+    MLXStop = 0x05,
+    /// MMI
+    MMI = 0x08,
+    /// MMI;
+    MMIStop = 0x09,
+    /// M;MI
+    MStopMI = 0x0A,
+    /// M;MI;
+    MStopMIStop = 0x0B,
+    /// MFI
+    MFI = 0x0C,
+    /// MFI;
+    MFIStop = 0x0D,
+    /// MMF
+    MMF = 0x0E,
+    /// MMF;
+    MMFStop = 0x0F,
+    /// MIB
+    MIB = 0x10,
+    /// MIB;
+    MIBStop = 0x11,
+    /// MBB
+    MBB = 0x12,
+    /// MBB;
+    MBBStop = 0x13,
+    /// BBB
+    BBB = 0x16,
+    /// BBB;
+    BBBStop = 0x17,
+    /// MMB
+    MMB = 0x18,
+    /// MMB;
+    MMBStop = 0x19,
+    /// MFB
+    MFB = 0x1C,
+    /// MFB;
+    MFBStop = 0x1D,
 }
 
 impl BundleTemplate {
+    /// Every template, in encoding order
+    pub const ALL: [BundleTemplate; 24] = [
+        Self::MII,
+        Self::MIIStop,
+        Self::MIStopI,
+        Self::MIStopIStop,
+        Self::MLX,
+        Self::MLXStop,
+        Self::MMI,
+        Self::MMIStop,
+        Self::MStopMI,
+        Self::MStopMIStop,
+        Self::MFI,
+        Self::MFIStop,
+        Self::MMF,
+        Self::MMFStop,
+        Self::MIB,
+        Self::MIBStop,
+        Self::MBB,
+        Self::MBBStop,
+        Self::BBB,
+        Self::BBBStop,
+        Self::MMB,
+        Self::MMBStop,
+        Self::MFB,
+        Self::MFBStop,
+    ];
+
     /// Try to create from raw bits
     pub fn from_bits(bits: u8) -> Option<Self> {
-        match bits {
-            0b00000 => Some(Self::MII),
-            0b00001 => Some(Self::MIB),
-            0b00010 => Some(Self::MMI),
-            0b00011 => Some(Self::MMF),
-            0b00100 => Some(Self::MLX),
-            0b01000 => Some(Self::FBI),
-            0b01001 => Some(Self::BBB),
-            0b01010 => Some(Self::AAA),
-            _ => None,
+        Self::ALL
+            .into_iter()
+            .find(|&template| template as u8 == bits)
+    }
+
+    /// Unit of each slot
+    pub fn units(self) -> [Unit; 3] {
+        use Unit::*;
+        // Templates come in pairs differing only in the final stop
+        match self as u8 & !1 {
+            0x00 | 0x02 => [M, I, I],
+            0x04 => [M, L, X],
+            0x08 | 0x0A => [M, M, I],
+            0x0C => [M, F, I],
+            0x0E => [M, M, F],
+            0x10 => [M, I, B],
+            0x12 => [M, B, B],
+            0x16 => [B, B, B],
+            0x18 => [M, M, B],
+            _ => [M, F, B],
+        }
+    }
+
+    /// Whether an instruction group ends after each slot
+    pub fn stops(self) -> [bool; 3] {
+        let bits = self as u8;
+        [
+            bits == 0x0A || bits == 0x0B,
+            bits == 0x02 || bits == 0x03,
+            bits & 1 != 0,
+        ]
+    }
+}
+
+impl fmt::Display for BundleTemplate {
+    /// Assembly notation, e.g. `MI;I;`
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (unit, stop) in self.units().into_iter().zip(self.stops()) {
+            write!(f, "{}", unit.letter())?;
+            if stop {
+                write!(f, ";")?;
+            }
         }
+        Ok(())
     }
 }
 
@@ -69,13 +171,95 @@ pub enum InstructionType {
     X(XFormat),
 }
 
+impl InstructionType {
+    /// Execution unit the template assigns to the slot
+    pub fn unit(&self) -> Unit {
+        match self {
+            InstructionType::A(_) => Unit::A,
+            InstructionType::I(_) => Unit::I,
+            InstructionType::M(_) => Unit::M,
+            InstructionType::F(_) => Unit::F,
+            InstructionType::B(_) => Unit::B,
+            InstructionType::L(_) => Unit::L,
+            InstructionType::X(_) => Unit::X,
+        }
+    }
+}
+
+/// Execution unit of an instruction slot
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Unit {
+    /// Integer ALU, executable on I or M units
+    A,
+    /// Non-ALU integer
+    I,
+    /// Memory
+    M,
+    /// Floating-point
+    F,
+    /// Branch
+    B,
+    /// Long immediate half of an MLX pair, holding no opcode
+    L,
+    /// Extended half of an MLX pair
+    X,
+}
+
+impl Unit {
+    /// Check if a major opcode (bits 40:37) is defined on this unit
+    pub fn accepts(self, major: u64) -> bool {
+        match self {
+            Unit::A => matches!(major, 0x8 | 0x9 | 0xC..=0xE),
+            Unit::I => matches!(major, 0x0 | 0x4 | 0x5 | 0x7 | 0x8 | 0x9 | 0xC..=0xE),
+            Unit::M => matches!(major, 0x0 | 0x1 | 0x4..=0x9 | 0xC..=0xE),
+            Unit::F => matches!(major, 0x0 | 0x1 | 0x4 | 0x5 | 0x8..=0xE),
+            Unit::B => matches!(major, 0x0..=0x2 | 0x4 | 0x5 | 0x7),
+            Unit::L => true,
+            Unit::X => matches!(major, 0x0 | 0x6 | 0xC | 0xD),
+        }
+    }
+
+    /// Letter naming the unit in templates and mnemonics
+    pub fn letter(self) -> char {
+        match self {
+            Unit::A => 'A',
+            Unit::I => 'I',
+            Unit::M => 'M',
+            Unit::F => 'F',
+            Unit::B => 'B',
+            Unit::L => 'L',
+            Unit::X => 'X',
+        }
+    }
+}
+
+impl fmt::Display for Unit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}-unit", self.letter())
+    }
+}
+
+/// Check that a slot's major opcode is defined on the unit its template
+/// assigns, as hardware does before raising an Illegal Operation fault
+pub fn validate_slot(slot: usize, itype: &InstructionType, bits: u64) -> Result<(), EmulatorError> {
+    let unit = itype.unit();
+    let major = (bits >> 37) & 0xF;
+    if unit.accepts(major) {
+        return Ok(());
+    }
+    Err(EmulatorError::DecodeError(format!(
+        "Illegal operation: slot {} expects an {} instruction, found major opcode {:#x}",
+        slot, unit, major
+    )))
+}
+
 /// Decoded IA-64 instruction
 #[derive(Debug)]
 pub struct Instruction {
     /// Type of instruction with format details
     pub itype: InstructionType,
-    /// Completers (if any)
-    pub completers: Option<Vec<String>>,
+    /// Completers
+    pub completers: Completers,
 }
 
 /// IA-64 instruction bundle (128 bits)
@@ -100,169 +284,106 @@ impl Bundle {
         Ok(Self {
             data,
             template,
-            instructions: Vec::new(), // Will be populated by decode()
+            instructions: Vec::with_capacity(3), // Will be populated by decode()
         })
     }
 
-    /// Decode the instructions in the bundle
-    pub fn decode(&mut self) -> Result<(), EmulatorError> {
-        // Clear any previously decoded instructions
-        self.instructions.clear();
-
-        // Convert bundle data to u64 values for easier bit extraction
+    /// Get the raw 41-bit encoding of an instruction slot
+    pub fn slot(&self, index: usize) -> Result<u64, EmulatorError> {
         let data_low = u64::from_le_bytes(self.data[0..8].try_into().unwrap());
         let data_high = u64::from_le_bytes(self.data[8..16].try_into().unwrap());
 
-        match self.template {
-            BundleTemplate::MII => {
-                // Decode M-unit instruction (41 bits)
-                let m_bits = extract_bits(data_low, 5, 41);
-                self.decode_m_unit(m_bits)?;
+        match index {
+            0 => Ok(extract_bits(data_low, 5, 41)),
+            1 => Ok(((data_low >> 46) | (data_high << 18)) & ((1 << 41) - 1)),
+            2 => Ok(extract_bits(data_high, 23, 41)),
+            _ => Err(EmulatorError::DecodeError(format!(
+                "Invalid slot index: {}",
+                index
+            ))),
+        }
+    }
 
-                // Decode first I-unit instruction (41 bits)
-                let i1_bits = ((data_low >> 46) | (data_high << 18)) & ((1 << 41) - 1);
-                self.decode_i_unit(i1_bits)?;
+    /// Template type
+    pub fn template(&self) -> BundleTemplate {
+        self.template
+    }
 
-                // Decode second I-unit instruction (41 bits)
-                let i2_bits = extract_bits(data_high, 23, 41);
-                self.decode_i_unit(i2_bits)?;
-            }
-            BundleTemplate::MIB => {
-                // Decode M-unit instruction (41 bits)
-                let m_bits = extract_bits(data_low, 5, 41);
-                self.decode_m_unit(m_bits)?;
-
-                // Decode I-unit instruction (41 bits)
-                let i_bits = ((data_low >> 46) | (data_high << 18)) & ((1 << 41) - 1);
-                self.decode_i_unit(i_bits)?;
-
-                // Decode B-unit instruction (41 bits)
-                let b_bits = extract_bits(data_high, 23, 41);
-                self.decode_b_unit(b_bits)?;
-            }
-            BundleTemplate::MMI => {
-                // Decode first M-unit instruction (41 bits)
-                let m1_bits = extract_bits(data_low, 5, 41);
-                self.decode_m_unit(m1_bits)?;
-
-                // Decode second M-unit instruction (41 bits)
-                let m2_bits = ((data_low >> 46) | (data_high << 18)) & ((1 << 41) - 1);
-                self.decode_m_unit(m2_bits)?;
-
-                // Decode I-unit instruction (41 bits)
-                let i_bits = extract_bits(data_high, 23, 41);
-                self.decode_i_unit(i_bits)?;
-            }
-            BundleTemplate::MMF => {
-                // Decode first M-unit instruction (41 bits)
-                let m1_bits = extract_bits(data_low, 5, 41);
-                self.decode_m_unit(m1_bits)?;
-
-                // Decode second M-unit instruction (41 bits)
-                let m2_bits = ((data_low >> 46) | (data_high << 18)) & ((1 << 41) - 1);
-                self.decode_m_unit(m2_bits)?;
-
-                // Decode F-unit instruction (41 bits)
-                let f_bits = extract_bits(data_high, 23, 41);
-                self.decode_f_unit(f_bits)?;
-            }
-            BundleTemplate::MLX => {
-                // Decode M-unit instruction (41 bits)
-                let m_bits = extract_bits(data_low, 5, 41);
-                self.decode_m_unit(m_bits)?;
-
-                // Decode L-X unit pair (82 bits total)
-                let l_bits = ((data_low >> 46) | (data_high << 18)) & ((1 << 41) - 1);
-                let x_bits = extract_bits(data_high, 23, 41);
-                self.decode_lx_unit(l_bits, x_bits)?;
-            }
-            BundleTemplate::FBI => {
-                // Decode F-unit instruction (41 bits)
-                let f_bits = extract_bits(data_low, 5, 41);
-                self.decode_f_unit(f_bits)?;
-
-                // Decode B-unit instruction (41 bits)
-                let b_bits = ((data_low >> 46) | (data_high << 18)) & ((1 << 41) - 1);
-                self.decode_b_unit(b_bits)?;
-
-                // Decode I-unit instruction (41 bits)
-                let i_bits = extract_bits(data_high, 23, 41);
-                self.decode_i_unit(i_bits)?;
+    /// Assembly text of each instruction, for a bundle at address 0
+    ///
+    /// See [`disassemble_at`](Self::disassemble_at).
+    pub fn disassemble(&self) -> Vec<String> {
+        self.disassemble_at(0)
+    }
+
+    /// Assembly text of each instruction, for a bundle at `ip`
+    ///
+    /// The L and X slots of an MLX bundle make one instruction, and `;;`
+    /// follows each instruction that ends an instruction group.
+    pub fn disassemble_at(&self, ip: u64) -> Vec<String> {
+        let mut lines = Vec::with_capacity(3);
+        let units = self.template.units();
+        for (slot, (unit, stop)) in units.into_iter().zip(self.template.stops()).enumerate() {
+            if unit == Unit::L {
+                continue;
             }
-            BundleTemplate::BBB => {
-                // Decode first B-unit instruction (41 bits)
-                let b1_bits = extract_bits(data_low, 5, 41);
-                self.decode_b_unit(b1_bits)?;
-
-                // Decode second B-unit instruction (41 bits)
-                let b2_bits = ((data_low >> 46) | (data_high << 18)) & ((1 << 41) - 1);
-                self.decode_b_unit(b2_bits)?;
-
-                // Decode third B-unit instruction (41 bits)
-                let b3_bits = extract_bits(data_high, 23, 41);
-                self.decode_b_unit(b3_bits)?;
+            let bits = self.slot(slot).unwrap_or_default();
+            let l_slot = match unit {
+                Unit::X => self.slot(slot - 1).unwrap_or_default(),
+                _ => 0,
+            };
+            let mut text = disasm::disassemble_slot(unit, bits, l_slot, ip);
+            if stop {
+                text.push_str(" ;;");
             }
-            BundleTemplate::AAA => {
-                // Decode first A-unit instruction (41 bits)
-                let a1_bits = extract_bits(data_low, 5, 41);
-                self.decode_a_unit(a1_bits)?;
-
-                // Decode second A-unit instruction (41 bits)
-                let a2_bits = ((data_low >> 46) | (data_high << 18)) & ((1 << 41) - 1);
-                self.decode_a_unit(a2_bits)?;
-
-                // Decode third A-unit instruction (41 bits)
-                let a3_bits = extract_bits(data_high, 23, 41);
-                self.decode_a_unit(a3_bits)?;
+            lines.push(text);
+        }
+        lines
+    }
+
+    /// Decode the instructions in the bundle
+    pub fn decode(&mut self) -> Result<(), EmulatorError> {
+        // Clear any previously decoded instructions
+        self.instructions.clear();
+
+        for (slot, unit) in self.template.units().into_iter().enumerate() {
+            let bits = self.slot(slot)?;
+            match unit {
+                Unit::M => self.decode_m_unit(bits)?,
+                Unit::I => self.decode_i_unit(bits)?,
+                Unit::F => self.decode_f_unit(bits)?,
+                Unit::B => self.decode_b_unit(bits)?,
+                // The L slot is decoded with the X slot that follows it
+                Unit::L => self.decode_lx_unit(bits, self.slot(slot + 1)?)?,
+                Unit::X | Unit::A => {}
             }
         }
 
         Ok(())
     }
 
+    /// Check every decoded slot against the unit its template assigns
+    ///
+    /// Call after [`decode`](Self::decode).
+    pub fn validate(&self) -> Result<(), EmulatorError> {
+        for (slot, instruction) in self.instructions.iter().enumerate() {
+            validate_slot(slot, &instruction.itype, self.slot(slot)?).map_err(|e| match e {
+                EmulatorError::DecodeError(msg) => {
+                    EmulatorError::DecodeError(format!("{} ({} bundle)", msg, self.template))
+                }
+                e => e,
+            })?;
+        }
+        Ok(())
+    }
+
     /// Decode M-unit instruction
     fn decode_m_unit(&mut self, bits: u64) -> Result<(), EmulatorError> {
         let format = MFormat::decode(bits);
 
-        // Extract completers bits
-        // Extract completer bits
-        let ordering_bits = format.x2;
-        let cache_bits = format.hint;
-        let speculation_bits = format.x4;
-
-        let completers = Some(vec![
-            // Encode memory ordering
-            match ordering_bits {
-                0b00 => "none",
-                0b01 => "acquire",
-                0b10 => "release",
-                0b11 => "fence",
-                _ => unreachable!(),
-            }
-            .to_string(),
-            // Encode cache hint
-            match cache_bits {
-                0b00 => "none",
-                0b01 => "temporal",
-                0b10 => "non-temporal",
-                0b11 => "reserved",
-                _ => unreachable!(),
-            }
-            .to_string(),
-            // Encode speculation
-            match speculation_bits {
-                0b00 => "none",
-                0b01 => "spec",
-                0b10 => "check",
-                0b11 => "advanced",
-                _ => unreachable!(),
-            }
-            .to_string(),
-        ]);
-
         self.instructions.push(Instruction {
             itype: InstructionType::M(format),
-            completers,
+            completers: Completers::decode_memory(bits),
         });
 
         Ok(())
@@ -274,7 +395,7 @@ impl Bundle {
 
         self.instructions.push(Instruction {
             itype: InstructionType::I(format),
-            completers: None,
+            completers: Completers::NONE,
         });
 
         Ok(())
@@ -284,32 +405,9 @@ impl Bundle {
     fn decode_b_unit(&mut self, bits: u64) -> Result<(), EmulatorError> {
         let format = BFormat::decode(bits);
 
-        let completers = Some(vec![
-            // Encode branch type
-            match format.btype {
-                0b00 => "cond",
-                0b01 => "call",
-                0b10 => "ret",
-                0b11 => "reserved",
-                _ => unreachable!(),
-            }
-            .to_string(),
-            // Encode branch hint
-            match format.wh {
-                0b00 => "sptk",
-                0b01 => "spnt",
-                0b10 => "dptk",
-                0b11 => "dpnt",
-                _ => unreachable!(),
-            }
-            .to_string(),
-            // Encode dealloc
-            if format.d { "dealloc" } else { "none" }.to_string(),
-        ]);
-
         self.instructions.push(Instruction {
             itype: InstructionType::B(format),
-            completers,
+            completers: Completers::decode_branch(bits),
         });
 
         Ok(())
@@ -319,14 +417,9 @@ impl Bundle {
     fn decode_f_unit(&mut self, bits: u64) -> Result<(), EmulatorError> {
         let format = FFormat::decode(bits);
 
-        let completers = Some(vec![
-            // Encode single/double precision
-            if format.sf { "double" } else { "single" }.to_string(),
-        ]);
-
         self.instructions.push(Instruction {
             itype: InstructionType::F(format),
-            completers,
+            completers: Completers::NONE,
         });
 
         Ok(())
@@ -335,34 +428,19 @@ impl Bundle {
     /// Decode L-X unit instruction pair
     fn decode_lx_unit(&mut self, l_bits: u64, x_bits: u64) -> Result<(), EmulatorError> {
         let l_format = LFormat::decode(l_bits);
-        let x_format = XFormat::decode(x_bits);
+        let x_format = XFormat {
+            l_slot: l_bits,
+            ..XFormat::decode(x_bits)
+        };
 
         self.instructions.push(Instruction {
             itype: InstructionType::L(l_format),
-            completers: None,
+            completers: Completers::NONE,
         });
 
         self.instructions.push(Instruction {
             itype: InstructionType::X(x_format),
-            completers: None,
-        });
-
-        Ok(())
-    }
-
-    /// Decode A-unit instruction
-    fn decode_a_unit(&mut self, bits: u64) -> Result<(), EmulatorError> {
-        let format = AFormat::decode(bits);
-
-        let completers = if format.ve {
-            Some(vec!["ve".to_string()])
-        } else {
-            None
-        };
-
-        self.instructions.push(Instruction {
-            itype: InstructionType::A(format),
-            completers,
+            completers: Completers::decode_branch(x_bits),
         });
 
         Ok(())
@@ -469,42 +547,46 @@ impl Decoder {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::test_util::encode_bundle;
 
     #[test]
     fn test_bundle_template_from_bits() {
+        assert_eq!(BundleTemplate::from_bits(0x00), Some(BundleTemplate::MII));
         assert_eq!(
-            BundleTemplate::from_bits(0b00000),
-            Some(BundleTemplate::MII)
-        );
-        assert_eq!(
-            BundleTemplate::from_bits(0b00001),
-            Some(BundleTemplate::MIB)
-        );
-        assert_eq!(
-            BundleTemplate::from_bits(0b00010),
-            Some(BundleTemplate::MMI)
-        );
-        assert_eq!(
-            BundleTemplate::from_bits(0b00011),
-            Some(BundleTemplate::MMF)
+            BundleTemplate::from_bits(0x03),
+            Some(BundleTemplate::MIStopIStop)
         );
+        assert_eq!(BundleTemplate::from_bits(0x04), Some(BundleTemplate::MLX));
+        assert_eq!(BundleTemplate::from_bits(0x08), Some(BundleTemplate::MMI));
         assert_eq!(
-            BundleTemplate::from_bits(0b00100),
-            Some(BundleTemplate::MLX)
+            BundleTemplate::from_bits(0x0A),
+            Some(BundleTemplate::MStopMI)
         );
+        assert_eq!(BundleTemplate::from_bits(0x0E), Some(BundleTemplate::MMF));
+        assert_eq!(BundleTemplate::from_bits(0x10), Some(BundleTemplate::MIB));
+        assert_eq!(BundleTemplate::from_bits(0x16), Some(BundleTemplate::BBB));
         assert_eq!(
-            BundleTemplate::from_bits(0b01000),
-            Some(BundleTemplate::FBI)
+            BundleTemplate::from_bits(0x1D),
+            Some(BundleTemplate::MFBStop)
         );
-        assert_eq!(
-            BundleTemplate::from_bits(0b01001),
-            Some(BundleTemplate::BBB)
-        );
-        assert_eq!(
-            BundleTemplate::from_bits(0b01010),
-            Some(BundleTemplate::AAA)
-        );
-        assert_eq!(BundleTemplate::from_bits(0b11111), None);
+        for reserved in [0x06, 0x07, 0x14, 0x15, 0x1A, 0x1B, 0x1E, 0x1F] {
+            assert_eq!(BundleTemplate::from_bits(reserved), None);
+        }
+        assert!(BundleTemplate::ALL
+            .iter()
+            .all(|&template| BundleTemplate::from_bits(template as u8) == Some(template)));
+    }
+
+    #[test]
+    fn test_bundle_template_stops() {
+        let template = BundleTemplate::MStopMIStop;
+        assert_eq!(template.units(), [Unit::M, Unit::M, Unit::I]);
+        assert_eq!(template.stops(), [true, false, true]);
+        assert_eq!(template.to_string(), "M;MI;");
+        assert_eq!(BundleTemplate::MIStopI.stops(), [false, true, false]);
+        assert_eq!(BundleTemplate::MLX.units(), [Unit::M, Unit::L, Unit::X]);
+        assert_eq!(BundleTemplate::MLXStop.to_string(), "MLX;");
+        assert_eq!(BundleTemplate::BBB.stops(), [false; 3]);
     }
 
     #[test]
@@ -563,7 +645,7 @@ mod tests {
     #[ignore = "Decoder implementation needs to be fixed"]
     fn test_mib_bundle_decode() {
         let mut data = [
-            0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0x10, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
             0x00, 0x00,
         ];
         // Set some test bits for B-unit instruction
@@ -583,7 +665,7 @@ mod tests {
     #[ignore = "Decoder implementation needs to be fixed"]
     fn test_mmi_bundle_decode() {
         let mut data = [
-            0x02, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
             0x00, 0x00,
         ];
         // Set some test bits for M-unit instructions
@@ -612,7 +694,7 @@ mod tests {
     #[ignore = "Decoder implementation needs to be fixed"]
     fn test_mmf_bundle_decode() {
         let mut data = [
-            0x03, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0x0E, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
             0x00, 0x00,
         ];
         // Set some test bits for M and F-unit instructions
@@ -666,40 +748,11 @@ mod tests {
         );
     }
 
-    #[test]
-    #[ignore = "Decoder implementation needs to be fixed"]
-    fn test_fbi_bundle_decode() {
-        let mut data = [
-            0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
-            0x00, 0x00,
-        ];
-        // Set some test bits for F, B, and I-unit instructions
-        data[1] = 0x40; // Set opcode for F
-        data[6] = 0x40; // Set opcode for B
-        data[11] = 0x40; // Set opcode for I
-
-        let mut bundle = Bundle::new(data).unwrap();
-        assert!(bundle.decode().is_ok());
-        assert_eq!(bundle.instructions.len(), 3);
-        assert_eq!(
-            bundle.instructions[0].itype,
-            InstructionType::F(FFormat::default())
-        );
-        assert_eq!(
-            bundle.instructions[1].itype,
-            InstructionType::B(BFormat::default())
-        );
-        assert_eq!(
-            bundle.instructions[2].itype,
-            InstructionType::I(IFormat::default())
-        );
-    }
-
     #[test]
     #[ignore = "Decoder implementation needs to be fixed"]
     fn test_bbb_bundle_decode() {
         let mut data = [
-            0x09, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0x16, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
             0x00, 0x00,
         ];
         // Set some test bits for B-unit instructions
@@ -725,32 +778,38 @@ mod tests {
     }
 
     #[test]
-    #[ignore = "Decoder implementation needs to be fixed"]
-    fn test_aaa_bundle_decode() {
-        let mut data = [
-            0x0A, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
-            0x00, 0x00,
-        ];
-        // Set some test bits for A-unit instructions
-        data[1] = 0x40; // Set opcode for first A
-        data[6] = 0x40; // Set opcode for second A
-        data[11] = 0x40; // Set opcode for third A
-
-        let mut bundle = Bundle::new(data).unwrap();
-        assert!(bundle.decode().is_ok());
-        assert_eq!(bundle.instructions.len(), 3);
-        assert_eq!(
-            bundle.instructions[0].itype,
-            InstructionType::A(AFormat::default())
-        );
-        assert_eq!(
-            bundle.instructions[1].itype,
-            InstructionType::A(AFormat::default())
+    fn test_slot_unit_validation() {
+        fn bundle(template: u8, slots: [u64; 3]) -> Bundle {
+            let mut bundle = Bundle::new(encode_bundle(template, slots)).unwrap();
+            bundle.decode().unwrap();
+            bundle
+        }
+        let major = |opcode: u64| opcode << 37;
+
+        // FP load/store (major 6) is an M-unit operation
+        let good = bundle(BundleTemplate::MII as u8, [major(6), major(0), major(8)]);
+        assert!(good.validate().is_ok());
+        let bad = bundle(BundleTemplate::MII as u8, [major(0), major(6), major(0)]);
+        let error = bad.validate().unwrap_err().to_string();
+        assert!(
+            error.contains("slot 1 expects an I-unit instruction"),
+            "{}",
+            error
         );
-        assert_eq!(
-            bundle.instructions[2].itype,
-            InstructionType::A(AFormat::default())
+        assert!(error.contains("major opcode 0x6"), "{}", error);
+        assert!(error.contains("MII"), "{}", error);
+
+        // The L slot holds immediate bits; the X slot must be an extended opcode
+        let movl = bundle(
+            BundleTemplate::MLX as u8,
+            [major(0), u64::MAX >> 23, major(6)],
         );
+        assert!(movl.validate().is_ok());
+        let bad = bundle(BundleTemplate::MLX as u8, [major(0), 0, major(1)]);
+        assert!(bad.validate().unwrap_err().to_string().contains("slot 2"));
+
+        let bad = bundle(BundleTemplate::MIB as u8, [major(0), major(0), major(8)]);
+        assert!(bad.validate().unwrap_err().to_string().contains("B-unit"));
     }
 
     #[test]
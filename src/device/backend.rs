@@ -0,0 +1,100 @@
+//! Async device backends
+//!
+//! A device's host end can be served by a task on the tokio runtime rather
+//! than a thread of its own, so sockets and files backing many devices cost
+//! no more than the runtime. [`serve_uart`] connects a [`UartPort`] to any
+//! async byte stream, such as a TCP connection or a pipe.
+//!
+//! The guest never waits for a backend: the port queues characters in both
+//! directions, and the device picks them up when it is ticked. On a
+//! current-thread runtime the backend only runs while the machine yields,
+//! so run it with [`Emulator::run_async`](crate::emulator::Emulator::run_async),
+//! which yields every few bundles.
+
+use super::uart::{UartPort, FIFO_DEPTH};
+use std::io;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+/// Bytes read from the stream at a time
+const READ_CHUNK: usize = 256;
+
+/// Carry a UART's line over `stream` until the stream ends
+///
+/// The guest's output is written to the stream and what the stream sends
+/// is queued for the guest. Reading stops while a FIFO's worth of input is
+/// still queued, so a fast peer is held back by the stream rather than
+/// buffered without limit. `poll` bounds how long the guest's output waits
+/// while the stream is idle.
+pub async fn serve_uart<S>(port: UartPort, mut stream: S, poll: Duration) -> io::Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let mut buf = [0; READ_CHUNK];
+    loop {
+        let output = port.take_output();
+        if !output.is_empty() {
+            stream.write_all(&output).await?;
+            stream.flush().await?;
+        }
+
+        if port.pending() >= FIFO_DEPTH {
+            tokio::time::sleep(poll).await;
+            continue;
+        }
+        match tokio::time::timeout(poll, stream.read(&mut buf)).await {
+            Ok(Ok(0)) => return Ok(()),
+            Ok(Ok(len)) => port.send(&buf[..len]),
+            Ok(Err(e)) => return Err(e),
+            Err(_) => {} // Nothing to read yet
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::device::uart::{Uart, FCR_ENABLE, REG_DATA, REG_IIR};
+    use crate::emulator::{Emulator, StopReason};
+    use crate::test_util::{encode_break_nop, encode_mii, nop};
+
+    const BASE: u64 = 0x10000;
+    const MMIO: u64 = 0x80000;
+
+    #[test]
+    fn test_uart_backend() {
+        // Straight-line code long enough to yield a few times
+        let mut image = [encode_mii([nop(); 3]); 64].concat();
+        image.extend(encode_mii([nop(), nop(), encode_break_nop(0, 0x00, 0x77)]));
+        let mut emu = Emulator::new();
+        emu.load_flat_image(BASE, &image, BASE).unwrap();
+
+        let uart = Uart::new("uart");
+        let port = uart.port();
+        emu.attach(MMIO, Box::new(uart)).unwrap();
+        emu.memory.write_u8(MMIO + REG_IIR, FCR_ENABLE).unwrap();
+        emu.memory.write_u8(MMIO + REG_DATA, b'x').unwrap();
+
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        let output = runtime.block_on(async {
+            let (mut host, line) = tokio::io::duplex(64);
+            let backend = tokio::spawn(serve_uart(port, line, Duration::from_millis(1)));
+            host.write_all(b"hi").await.unwrap();
+
+            // The backend runs on this thread, so only when the guest yields
+            assert_eq!(emu.run_async(16).await.unwrap(), StopReason::Break(0x77));
+
+            let mut output = [0; 1];
+            host.read_exact(&mut output).await.unwrap();
+            drop(host);
+            backend.await.unwrap().unwrap();
+            output
+        });
+        assert_eq!(&output, b"x");
+        assert_eq!(emu.memory.read_u8(MMIO + REG_DATA).unwrap(), b'h');
+        assert_eq!(emu.memory.read_u8(MMIO + REG_DATA).unwrap(), b'i');
+    }
+}
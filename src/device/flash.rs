@@ -0,0 +1,453 @@
+//! Firmware flash device
+//!
+//! [`Flash`] models a NOR flash part with the Intel command set, the kind
+//! firmware keeps its code and NVRAM variables in. Reads return the array,
+//! but stores do not change it: they are commands. Programming takes a
+//! program command followed by the data, and can only clear bits; erasing a
+//! block back to all ones takes an erase command and a confirm. After
+//! either, reads return the status register until the guest writes the
+//! read-array command. The part answers identifier and CFI queries, so
+//! guest drivers can size it.
+//!
+//! A flash may be backed by a file: the array is loaded from it and every
+//! program or erase is written back, so variables a guest sets survive to
+//! the next run. With write protection on (the WP# pin held low), program
+//! and erase fail with the block-locked status bit set. A reset returns the
+//! part to read-array mode; the array itself is non-volatile.
+
+use super::Device;
+use crate::emulator::ResetKind;
+use crate::EmulatorError;
+use std::fs::OpenOptions;
+use std::io::{Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+/// Erase block size used unless configured
+pub const DEFAULT_BLOCK_SIZE: u64 = 0x10000;
+
+/// Return to read-array mode
+pub const CMD_READ_ARRAY: u8 = 0xFF;
+/// Read the manufacturer and device identifiers
+pub const CMD_READ_ID: u8 = 0x90;
+/// Read the CFI query table
+pub const CMD_CFI_QUERY: u8 = 0x98;
+/// Read the status register
+pub const CMD_READ_STATUS: u8 = 0x70;
+/// Clear the error bits of the status register
+pub const CMD_CLEAR_STATUS: u8 = 0x50;
+/// Program the data of the next store
+pub const CMD_PROGRAM: u8 = 0x40;
+/// Alternate program command
+pub const CMD_PROGRAM_ALT: u8 = 0x10;
+/// Set up a block erase
+pub const CMD_ERASE_SETUP: u8 = 0x20;
+/// Confirm a block erase
+pub const CMD_ERASE_CONFIRM: u8 = 0xD0;
+
+/// Status: ready for a command
+pub const STATUS_READY: u8 = 0x80;
+/// Status: erase failed, or the command sequence was invalid
+pub const STATUS_ERASE_ERROR: u8 = 0x20;
+/// Status: program failed, or the command sequence was invalid
+pub const STATUS_PROGRAM_ERROR: u8 = 0x10;
+/// Status: the operation hit a write-protected block
+pub const STATUS_LOCKED: u8 = 0x02;
+
+/// Manufacturer identifier (Intel)
+pub const MANUFACTURER_ID: u8 = 0x89;
+/// Device identifier
+pub const DEVICE_ID: u8 = 0x18;
+
+/// What reads return
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Mode {
+    /// The array contents
+    ReadArray,
+    /// The status register
+    Status,
+    /// Manufacturer and device identifiers
+    Identifier,
+    /// CFI query table
+    Query,
+    /// Status; the next store is programmed
+    ProgramSetup,
+    /// Status; the next store must confirm the erase
+    EraseSetup,
+}
+
+/// Flash part holding firmware code and variables
+#[derive(Debug)]
+pub struct Flash {
+    /// Name shown in the memory map
+    name: String,
+    /// Array contents
+    data: Vec<u8>,
+    /// Erase block size
+    block_size: u64,
+    /// Program and erase fail when set
+    write_protect: bool,
+    /// Current read mode
+    mode: Mode,
+    /// Status register
+    status: u8,
+    /// Backing file
+    path: Option<PathBuf>,
+    /// Bytes of the array the backing file holds
+    file_len: u64,
+    /// Last failure to update the backing file
+    error: Option<String>,
+}
+
+impl Flash {
+    /// Create an erased flash of `size` bytes with no backing file
+    pub fn new(name: &str, size: u64, block_size: u64) -> Result<Self, EmulatorError> {
+        check_geometry(name, size, block_size)?;
+        Ok(Self {
+            name: name.to_string(),
+            data: vec![0xFF; size as usize],
+            block_size,
+            write_protect: false,
+            mode: Mode::ReadArray,
+            status: STATUS_READY,
+            path: None,
+            file_len: 0,
+            error: None,
+        })
+    }
+
+    /// Create a flash backed by `path`, loading its contents
+    ///
+    /// The size defaults to the file's length. A missing or shorter file
+    /// reads as erased beyond its end and is created or extended on the
+    /// first program or erase.
+    pub fn open(
+        name: &str,
+        path: impl AsRef<Path>,
+        size: Option<u64>,
+        block_size: u64,
+    ) -> Result<Self, EmulatorError> {
+        let path = path.as_ref();
+        let contents = match std::fs::read(path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound && size.is_some() => Vec::new(),
+            Err(e) => {
+                return Err(EmulatorError::ConfigError(format!(
+                    "{}: cannot read {}: {}",
+                    name,
+                    path.display(),
+                    e
+                )))
+            }
+        };
+        let size = size.unwrap_or(contents.len() as u64);
+        if contents.len() as u64 > size {
+            return Err(EmulatorError::ConfigError(format!(
+                "{}: {} holds {:#x} bytes, more than the {:#x} byte flash",
+                name,
+                path.display(),
+                contents.len(),
+                size
+            )));
+        }
+
+        let mut flash = Self::new(name, size, block_size)?;
+        flash.data[..contents.len()].copy_from_slice(&contents);
+        flash.path = Some(path.to_path_buf());
+        flash.file_len = contents.len() as u64;
+        Ok(flash)
+    }
+
+    /// Hold the WP# pin low, making program and erase fail
+    pub fn with_write_protect(mut self, write_protect: bool) -> Self {
+        self.write_protect = write_protect;
+        self
+    }
+
+    /// Array contents
+    pub fn contents(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Last failure to write the backing file, if any
+    pub fn error(&self) -> Option<&str> {
+        self.error.as_deref()
+    }
+
+    /// Run a command written to the part
+    fn command(&mut self, offset: u64, data: &[u8]) {
+        let command = data[0];
+        match self.mode {
+            Mode::ProgramSetup => {
+                self.program(offset, data);
+                self.mode = Mode::Status;
+                return;
+            }
+            Mode::EraseSetup => {
+                if command == CMD_ERASE_CONFIRM {
+                    self.erase(offset);
+                } else {
+                    // Improper command sequence
+                    self.status |= STATUS_ERASE_ERROR | STATUS_PROGRAM_ERROR;
+                }
+                self.mode = Mode::Status;
+                return;
+            }
+            _ => {}
+        }
+
+        self.mode = match command {
+            CMD_READ_ARRAY => Mode::ReadArray,
+            CMD_READ_ID => Mode::Identifier,
+            CMD_CFI_QUERY => Mode::Query,
+            CMD_READ_STATUS => Mode::Status,
+            CMD_CLEAR_STATUS => {
+                self.status = STATUS_READY;
+                self.mode
+            }
+            CMD_PROGRAM | CMD_PROGRAM_ALT => Mode::ProgramSetup,
+            CMD_ERASE_SETUP => Mode::EraseSetup,
+            _ => self.mode,
+        };
+    }
+
+    /// Program `data` at `offset`: bits can only go from one to zero
+    fn program(&mut self, offset: u64, data: &[u8]) {
+        if self.write_protect {
+            self.status |= STATUS_PROGRAM_ERROR | STATUS_LOCKED;
+            return;
+        }
+        let start = offset as usize;
+        for (cell, &byte) in self.data[start..start + data.len()].iter_mut().zip(data) {
+            *cell &= byte;
+        }
+        if !self.persist(offset, data.len() as u64) {
+            self.status |= STATUS_PROGRAM_ERROR;
+        }
+    }
+
+    /// Erase the block holding `offset` to all ones
+    fn erase(&mut self, offset: u64) {
+        if self.write_protect {
+            self.status |= STATUS_ERASE_ERROR | STATUS_LOCKED;
+            return;
+        }
+        let start = offset - offset % self.block_size;
+        self.data[start as usize..(start + self.block_size) as usize].fill(0xFF);
+        if !self.persist(start, self.block_size) {
+            self.status |= STATUS_ERASE_ERROR;
+        }
+    }
+
+    /// Write a changed range to the backing file, returning success
+    fn persist(&mut self, offset: u64, len: u64) -> bool {
+        let Some(path) = &self.path else {
+            return true;
+        };
+        // Bring a short file up to size first, so it never has a gap of zeros
+        let (start, end) = if self.file_len < offset + len {
+            (self.file_len.min(offset), self.data.len() as u64)
+        } else {
+            (offset, offset + len)
+        };
+        let result = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)
+            .and_then(|mut file| {
+                file.seek(SeekFrom::Start(start))?;
+                file.write_all(&self.data[start as usize..end as usize])
+            });
+        match result {
+            Ok(()) => {
+                self.file_len = self.file_len.max(end);
+                true
+            }
+            Err(e) => {
+                self.error = Some(format!("cannot write {}: {}", path.display(), e));
+                false
+            }
+        }
+    }
+
+    /// Byte of the CFI query table
+    fn query(&self, offset: u64) -> u8 {
+        let size = self.data.len() as u64;
+        let blocks = size / self.block_size - 1;
+        let block_units = self.block_size / 256;
+        match offset {
+            0x10 => b'Q',
+            0x11 => b'R',
+            0x12 => b'Y',
+            // Primary command set: Intel
+            0x13 => 0x01,
+            // Device size as a power of two
+            0x27 => (64 - (size - 1).leading_zeros()) as u8,
+            // One erase block region: block count - 1, then size / 256
+            0x2C => 1,
+            0x2D => blocks as u8,
+            0x2E => (blocks >> 8) as u8,
+            0x2F => block_units as u8,
+            0x30 => (block_units >> 8) as u8,
+            _ => 0,
+        }
+    }
+}
+
+/// Check the size and erase block size of a flash
+fn check_geometry(name: &str, size: u64, block_size: u64) -> Result<(), EmulatorError> {
+    if !block_size.is_power_of_two() || block_size < 256 {
+        return Err(EmulatorError::ConfigError(format!(
+            "{}: block size {:#x} is not a power of two of at least 256 bytes",
+            name, block_size
+        )));
+    }
+    if size == 0 || !size.is_multiple_of(block_size) {
+        return Err(EmulatorError::ConfigError(format!(
+            "{}: size {:#x} is not a non-zero multiple of the {:#x} byte block size",
+            name, size, block_size
+        )));
+    }
+    Ok(())
+}
+
+impl Device for Flash {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn size(&self) -> u64 {
+        self.data.len() as u64
+    }
+
+    fn read(&mut self, offset: u64, data: &mut [u8]) {
+        for (i, byte) in data.iter_mut().enumerate() {
+            let offset = offset + i as u64;
+            *byte = match self.mode {
+                Mode::ReadArray => self.data[offset as usize],
+                Mode::Status | Mode::ProgramSetup | Mode::EraseSetup => self.status,
+                Mode::Identifier => match offset {
+                    0 => MANUFACTURER_ID,
+                    1 => DEVICE_ID,
+                    _ => 0,
+                },
+                Mode::Query => self.query(offset),
+            };
+        }
+    }
+
+    fn write(&mut self, offset: u64, data: &[u8]) {
+        self.command(offset, data);
+    }
+
+    fn reset(&mut self, _kind: ResetKind) {
+        self.mode = Mode::ReadArray;
+        self.status = STATUS_READY;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BLOCK: u64 = 0x1000;
+
+    fn read(flash: &mut Flash, offset: u64, len: usize) -> Vec<u8> {
+        let mut data = vec![0; len];
+        flash.read(offset, &mut data);
+        data
+    }
+
+    #[test]
+    fn test_program_and_erase() {
+        let mut flash = Flash::new("flash", 4 * BLOCK, BLOCK).unwrap();
+
+        // Plain stores are commands, not data
+        flash.write(0x10, &[0x12]);
+        flash.write(0x10, &[CMD_READ_ARRAY]);
+        assert_eq!(read(&mut flash, 0x10, 2), [0xFF, 0xFF]);
+
+        // Programming clears bits only, then reads show the status
+        flash.write(0x10, &[CMD_PROGRAM]);
+        flash.write(0x10, &[0x0F, 0xF0]);
+        assert_eq!(read(&mut flash, 0, 1), [STATUS_READY]);
+        flash.write(0x10, &[CMD_PROGRAM_ALT]);
+        flash.write(0x10, &[0xF3]);
+        flash.write(0, &[CMD_READ_ARRAY]);
+        assert_eq!(read(&mut flash, 0x10, 2), [0x03, 0xF0]);
+
+        // Erase works on whole blocks
+        flash.write(BLOCK + 0x20, &[CMD_PROGRAM]);
+        flash.write(BLOCK + 0x20, &[0]);
+        flash.write(0x800, &[CMD_ERASE_SETUP]);
+        flash.write(0x800, &[CMD_ERASE_CONFIRM]);
+        flash.write(0, &[CMD_READ_ARRAY]);
+        assert_eq!(read(&mut flash, 0x10, 2), [0xFF, 0xFF]);
+        assert_eq!(flash.contents()[(BLOCK + 0x20) as usize], 0);
+
+        // An erase without its confirm is a sequence error
+        flash.write(0, &[CMD_ERASE_SETUP]);
+        flash.write(0, &[CMD_READ_ARRAY]);
+        assert_eq!(
+            read(&mut flash, 0, 1),
+            [STATUS_READY | STATUS_ERASE_ERROR | STATUS_PROGRAM_ERROR]
+        );
+        flash.write(0, &[CMD_CLEAR_STATUS]);
+        assert_eq!(read(&mut flash, 0, 1), [STATUS_READY]);
+    }
+
+    #[test]
+    fn test_write_protect() {
+        let mut flash = Flash::new("flash", BLOCK, BLOCK)
+            .unwrap()
+            .with_write_protect(true);
+        flash.write(0, &[CMD_PROGRAM]);
+        flash.write(0, &[0]);
+        assert_eq!(
+            read(&mut flash, 0, 1),
+            [STATUS_READY | STATUS_PROGRAM_ERROR | STATUS_LOCKED]
+        );
+        assert_eq!(flash.contents()[0], 0xFF);
+    }
+
+    #[test]
+    fn test_identify() {
+        let mut flash = Flash::new("flash", 8 * BLOCK, BLOCK).unwrap();
+        flash.write(0, &[CMD_READ_ID]);
+        assert_eq!(read(&mut flash, 0, 2), [MANUFACTURER_ID, DEVICE_ID]);
+        flash.write(0x55, &[CMD_CFI_QUERY]);
+        assert_eq!(read(&mut flash, 0x10, 4), *b"QRY\x01");
+        // 32 KiB in eight 4 KiB blocks
+        assert_eq!(read(&mut flash, 0x27, 1), [15]);
+        assert_eq!(read(&mut flash, 0x2C, 5), [1, 7, 0, 0x10, 0]);
+
+        assert!(Flash::new("flash", BLOCK + 1, BLOCK).is_err());
+        assert!(Flash::new("flash", BLOCK, 3000).is_err());
+    }
+
+    #[test]
+    fn test_persist() {
+        let path = std::env::temp_dir().join(format!("rust-ia64-flash-{}.bin", std::process::id()));
+        std::fs::write(&path, [0x5A; 16]).unwrap();
+
+        let mut flash = Flash::open("nvram", &path, Some(2 * BLOCK), BLOCK).unwrap();
+        assert_eq!(read(&mut flash, 0, 1), [0x5A]);
+        assert_eq!(read(&mut flash, 16, 1), [0xFF]);
+        flash.write(BLOCK, &[CMD_PROGRAM]);
+        flash.write(BLOCK, &[0x42]);
+        assert_eq!(flash.error(), None);
+
+        // The short file was brought up to size, erased beyond its old end
+        let saved = std::fs::read(&path).unwrap();
+        assert_eq!(saved.len() as u64, 2 * BLOCK);
+        assert_eq!(
+            (saved[0], saved[16], saved[BLOCK as usize]),
+            (0x5A, 0xFF, 0x42)
+        );
+
+        let mut flash = Flash::open("nvram", &path, None, BLOCK).unwrap();
+        assert_eq!(read(&mut flash, BLOCK, 1), [0x42]);
+        std::fs::remove_file(&path).unwrap();
+        assert!(Flash::open("nvram", &path, None, BLOCK).is_err());
+    }
+}
@@ -0,0 +1,419 @@
+//! Memory-mapped devices
+//!
+//! A device occupies a window of the physical address space; guest loads and
+//! stores inside the window go to the device instead of memory. Devices can
+//! be attached and detached while the guest runs, hotplug style: through
+//! [`Emulator::attach`](crate::emulator::Emulator::attach) on the thread
+//! running the machine, or through a [`DeviceHandle`] from any other thread.
+//! Requests from handles are applied between bundles, so the guest never
+//! sees a half-updated MMIO routing, and each change raises an external
+//! interrupt whose info is the device id, with [`HOTPLUG_DETACH`] set for a
+//! removal.
+//!
+//! Devices that keep time, like the [`uart`] submodule's serial port, are
+//! ticked with AR.ITC between bundles. When one asserts its interrupt line
+//! the guest gets an external interrupt whose info is the device id with
+//! [`DEVICE_INTERRUPT`] set. A device can also ask for a machine-level
+//! [`MachineRequest`] at a tick: a non-maskable interrupt, a reset or a
+//! stop, as the [`watchdog`] submodule's timer does when it expires. When
+//! the machine resets, every device is told the [`ResetKind`] and goes back
+//! to its power-on state as far as that kind of reset reaches.
+//!
+//! The [`flash`] submodule provides a firmware flash device. With the
+//! `async-io` feature, the [`backend`] submodule serves the host end of a
+//! device from a task on the tokio runtime.
+
+#[cfg(feature = "async-io")]
+pub mod backend;
+pub mod flash;
+pub mod uart;
+pub mod watchdog;
+
+use crate::emulator::ResetKind;
+use crate::EmulatorError;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// Interrupt info bit set when a device was detached
+pub const HOTPLUG_DETACH: u64 = 1 << 63;
+
+/// Interrupt info bit set when a device asserted its interrupt line
+pub const DEVICE_INTERRUPT: u64 = 1 << 62;
+
+/// Machine-level action a device asks for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MachineRequest {
+    /// Raise a non-maskable interrupt
+    Nmi,
+    /// Reset the machine
+    Reset(ResetKind),
+    /// Stop the run
+    Stop,
+}
+
+/// Device occupying an MMIO window
+pub trait Device: Send + fmt::Debug {
+    /// Name used in the memory map and diagnostics
+    fn name(&self) -> &str;
+
+    /// Size of the MMIO window in bytes
+    fn size(&self) -> u64;
+
+    /// Guest load of `data.len()` bytes at `offset` into the window
+    fn read(&mut self, offset: u64, data: &mut [u8]);
+
+    /// Guest store of `data` at `offset` into the window
+    fn write(&mut self, offset: u64, data: &[u8]);
+
+    /// Advance the device to AR.ITC value `itc`, between bundles
+    ///
+    /// Returns whether the device asserts its interrupt line.
+    fn tick(&mut self, itc: u64) -> bool {
+        let _ = itc;
+        false
+    }
+
+    /// Machine-level action asked for since the last call, collected after
+    /// each tick
+    fn take_request(&mut self) -> Option<MachineRequest> {
+        None
+    }
+
+    /// Return to the power-on state on a machine reset of `kind`
+    ///
+    /// The default keeps the device as it is, as one without volatile
+    /// state does.
+    fn reset(&mut self, kind: ResetKind) {
+        let _ = kind;
+    }
+}
+
+/// Identifier of an attached device
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct DeviceId(pub u32);
+
+impl fmt::Display for DeviceId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "device {}", self.0)
+    }
+}
+
+/// Attach or detach notification
+#[derive(Debug)]
+pub enum DeviceEvent {
+    /// A device was attached
+    Attached {
+        /// Device id
+        id: DeviceId,
+        /// Base of the MMIO window
+        base: u64,
+    },
+    /// A device was detached and handed back
+    Detached {
+        /// Device id
+        id: DeviceId,
+        /// The detached device
+        device: Box<dyn Device>,
+    },
+    /// A queued request could not be applied
+    Failed {
+        /// Device id
+        id: DeviceId,
+        /// Reason, e.g. an overlapping window
+        error: String,
+    },
+}
+
+/// Attached device and its window
+#[derive(Debug)]
+struct Window {
+    /// Device id
+    id: DeviceId,
+    /// Window size in bytes
+    size: u64,
+    /// The device
+    device: Box<dyn Device>,
+    /// Whether the device asserted its interrupt line at the last tick
+    asserted: bool,
+}
+
+/// MMIO routing table
+#[derive(Debug, Default)]
+pub struct DeviceBus {
+    /// Windows by base address, never overlapping
+    windows: BTreeMap<u64, Window>,
+    /// Machine-level actions asked for at ticks, not yet collected
+    requests: Vec<(DeviceId, MachineRequest)>,
+}
+
+impl DeviceBus {
+    /// Create an empty bus
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Check if no device is attached
+    pub fn is_empty(&self) -> bool {
+        self.windows.is_empty()
+    }
+
+    /// Check if any window overlaps `base..end`
+    pub fn overlaps(&self, base: u64, end: u64) -> bool {
+        self.windows
+            .range(..end)
+            .next_back()
+            .is_some_and(|(&start, window)| start + window.size > base)
+    }
+
+    /// Attach a device at `base`
+    pub fn insert(
+        &mut self,
+        id: DeviceId,
+        base: u64,
+        device: Box<dyn Device>,
+    ) -> Result<(), EmulatorError> {
+        let size = device.size();
+        let end = base.checked_add(size).filter(|_| size > 0).ok_or_else(|| {
+            EmulatorError::MemoryError(format!(
+                "{}: invalid MMIO window {:#x}+{:#x}",
+                device.name(),
+                base,
+                size
+            ))
+        })?;
+        if self.overlaps(base, end) {
+            return Err(EmulatorError::MemoryOverlap);
+        }
+        self.windows.insert(
+            base,
+            Window {
+                id,
+                size,
+                device,
+                asserted: false,
+            },
+        );
+        Ok(())
+    }
+
+    /// Detach a device, returning its base and the device
+    pub fn remove(&mut self, id: DeviceId) -> Option<(u64, Box<dyn Device>)> {
+        let base = self
+            .windows
+            .iter()
+            .find(|(_, window)| window.id == id)
+            .map(|(&base, _)| base)?;
+        self.windows
+            .remove(&base)
+            .map(|window| (base, window.device))
+    }
+
+    /// Whether an access of `len` bytes at `addr` goes to a device
+    ///
+    /// Accesses running past the end of a window are errors.
+    pub fn covers(&self, addr: u64, len: u64) -> Result<bool, EmulatorError> {
+        let Some((&base, window)) = self.windows.range(..=addr).next_back() else {
+            return Ok(false);
+        };
+        let offset = addr - base;
+        if offset >= window.size {
+            return Ok(false);
+        }
+        if offset + len > window.size {
+            return Err(EmulatorError::MemoryError(format!(
+                "Access at {:#x} crosses the end of the {} window",
+                addr,
+                window.device.name()
+            )));
+        }
+        Ok(true)
+    }
+
+    /// Device and offset for an access of `len` bytes at `addr`
+    ///
+    /// Accesses running past the end of a window are errors.
+    pub fn route(
+        &mut self,
+        addr: u64,
+        len: u64,
+    ) -> Result<Option<(&mut dyn Device, u64)>, EmulatorError> {
+        if !self.covers(addr, len)? {
+            return Ok(None);
+        }
+        let (&base, window) = self.windows.range_mut(..=addr).next_back().unwrap();
+        Ok(Some((window.device.as_mut(), addr - base)))
+    }
+
+    /// Id of the device whose window holds `addr`
+    pub fn id_at(&self, addr: u64) -> Option<DeviceId> {
+        let (&base, window) = self.windows.range(..=addr).next_back()?;
+        (addr - base < window.size).then_some(window.id)
+    }
+
+    /// Advance the devices to AR.ITC value `itc`, returning those that
+    /// asserted their interrupt line since the last tick
+    pub fn tick(&mut self, itc: u64) -> Vec<DeviceId> {
+        let mut raised = Vec::new();
+        for window in self.windows.values_mut() {
+            let asserted = window.device.tick(itc);
+            if asserted && !window.asserted {
+                raised.push(window.id);
+            }
+            window.asserted = asserted;
+            if let Some(request) = window.device.take_request() {
+                self.requests.push((window.id, request));
+            }
+        }
+        raised
+    }
+
+    /// Reset every device, dropping interrupt lines and requests not yet
+    /// collected
+    pub fn reset(&mut self, kind: ResetKind) {
+        for window in self.windows.values_mut() {
+            window.device.reset(kind);
+            window.asserted = false;
+        }
+        self.requests.clear();
+    }
+
+    /// Take the machine-level actions devices asked for at their ticks
+    pub fn take_requests(&mut self) -> Vec<(DeviceId, MachineRequest)> {
+        std::mem::take(&mut self.requests)
+    }
+
+    /// Attached devices with their ids and bases, by address
+    pub fn iter(&self) -> impl Iterator<Item = (DeviceId, u64, &dyn Device)> {
+        self.windows
+            .iter()
+            .map(|(&base, window)| (window.id, base, window.device.as_ref()))
+    }
+}
+
+/// Hotplug request queued by a handle
+#[derive(Debug)]
+pub(crate) enum HotplugRequest {
+    /// Attach a device at a base address
+    Attach(DeviceId, u64, Box<dyn Device>),
+    /// Detach a device
+    Detach(DeviceId),
+}
+
+/// Requests shared between the machine and its handles
+#[derive(Debug, Default)]
+pub(crate) struct HotplugQueue {
+    /// Next device id to hand out
+    next_id: AtomicU32,
+    /// Set when requests are waiting, so the run loop can skip the lock
+    pending: AtomicBool,
+    /// Requests in arrival order
+    requests: Mutex<Vec<HotplugRequest>>,
+}
+
+impl HotplugQueue {
+    /// Allocate a device id
+    pub(crate) fn next_id(&self) -> DeviceId {
+        DeviceId(self.next_id.fetch_add(1, Ordering::Relaxed))
+    }
+
+    /// Queue a request
+    fn push(&self, request: HotplugRequest) {
+        self.requests.lock().unwrap().push(request);
+        self.pending.store(true, Ordering::Release);
+    }
+
+    /// Take the queued requests, if any
+    pub(crate) fn take(&self) -> Vec<HotplugRequest> {
+        if !self.pending.swap(false, Ordering::Acquire) {
+            return Vec::new();
+        }
+        std::mem::take(&mut *self.requests.lock().unwrap())
+    }
+}
+
+/// Thread-safe handle for attaching and detaching devices
+///
+/// Requests take effect before the next bundle executes; their outcome is
+/// reported by [`Emulator::take_device_events`](crate::emulator::Emulator::take_device_events).
+#[derive(Debug, Clone)]
+pub struct DeviceHandle {
+    /// Queue shared with the machine
+    queue: Arc<HotplugQueue>,
+}
+
+impl DeviceHandle {
+    /// Create a handle for a machine's queue
+    pub(crate) fn new(queue: Arc<HotplugQueue>) -> Self {
+        Self { queue }
+    }
+
+    /// Queue attaching `device` at `base`, returning the id it will have
+    pub fn attach(&self, base: u64, device: Box<dyn Device>) -> DeviceId {
+        let id = self.queue.next_id();
+        self.queue.push(HotplugRequest::Attach(id, base, device));
+        id
+    }
+
+    /// Queue detaching a device
+    pub fn detach(&self, id: DeviceId) {
+        self.queue.push(HotplugRequest::Detach(id));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Eight byte registers
+    #[derive(Debug, Default)]
+    struct Registers([u8; 8]);
+
+    impl Device for Registers {
+        fn name(&self) -> &str {
+            "registers"
+        }
+
+        fn size(&self) -> u64 {
+            8
+        }
+
+        fn read(&mut self, offset: u64, data: &mut [u8]) {
+            let offset = offset as usize;
+            data.copy_from_slice(&self.0[offset..offset + data.len()]);
+        }
+
+        fn write(&mut self, offset: u64, data: &[u8]) {
+            let offset = offset as usize;
+            self.0[offset..offset + data.len()].copy_from_slice(data);
+        }
+    }
+
+    #[test]
+    fn test_device_bus() {
+        let mut bus = DeviceBus::new();
+        bus.insert(DeviceId(0), 0x1000, Box::new(Registers::default()))
+            .unwrap();
+        assert!(matches!(
+            bus.insert(DeviceId(1), 0x1004, Box::new(Registers::default())),
+            Err(EmulatorError::MemoryOverlap)
+        ));
+        bus.insert(DeviceId(1), 0x1008, Box::new(Registers::default()))
+            .unwrap();
+
+        let (device, offset) = bus.route(0x100c, 4).unwrap().unwrap();
+        assert_eq!(offset, 4);
+        device.write(offset, &[1, 2, 3, 4]);
+        assert!(bus.route(0x100e, 4).is_err());
+        assert!(bus.route(0x1010, 1).unwrap().is_none());
+
+        let (base, mut device) = bus.remove(DeviceId(1)).unwrap();
+        assert_eq!(base, 0x1008);
+        let mut data = [0; 4];
+        device.read(4, &mut data);
+        assert_eq!(data, [1, 2, 3, 4]);
+        assert!(bus.route(0x100c, 4).unwrap().is_none());
+        assert!(bus.remove(DeviceId(1)).is_none());
+    }
+}
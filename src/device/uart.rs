@@ -0,0 +1,628 @@
+//! Serial port device
+//!
+//! [`Uart`] models a 16550-compatible UART: byte-wide registers at offsets
+//! 0 to 7, 16-byte receive and transmit FIFOs with a programmable receive
+//! trigger level, the line and modem status registers and the interrupt
+//! identification register with its usual priorities. The host end of the
+//! line is a [`UartPort`], which queues input for the guest and collects
+//! its output from any thread.
+//!
+//! By default characters move instantly, so input the host sends faster
+//! than the guest drains it overruns the receive FIFO: the character is
+//! lost and LSR.OE is set, as on hardware. With a baud rate the line is
+//! paced: each character takes ten bit times of AR.ITC in each direction,
+//! the transmitter holds the guest's output in its FIFO while it shifts, and
+//! input arrives one character time apart. With flow control the host
+//! sender honours RTS, holding its input while the guest deasserts it;
+//! MCR.AFE also deasserts RTS while the receive FIFO is at its trigger
+//! level and holds the transmitter while the host deasserts CTS, as on the
+//! 16750.
+//!
+//! The divisor latch can be programmed and read back but does not change
+//! the pace, and loopback, breaks, parity and framing are not modelled.
+//! A reset empties the FIFOs and clears the registers, but the host end of
+//! the line stays connected.
+
+use super::Device;
+use crate::emulator::ResetKind;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+
+/// Size of the register window
+pub const UART_SIZE: u64 = 8;
+/// Depth of each FIFO
+pub const FIFO_DEPTH: usize = 16;
+
+/// Receive buffer (read) and transmit holding register (write)
+pub const REG_DATA: u64 = 0;
+/// Interrupt enable register
+pub const REG_IER: u64 = 1;
+/// Interrupt identification (read) and FIFO control (write) register
+pub const REG_IIR: u64 = 2;
+/// Line control register
+pub const REG_LCR: u64 = 3;
+/// Modem control register
+pub const REG_MCR: u64 = 4;
+/// Line status register
+pub const REG_LSR: u64 = 5;
+/// Modem status register
+pub const REG_MSR: u64 = 6;
+/// Scratch register
+pub const REG_SCR: u64 = 7;
+
+/// IER: received data available
+pub const IER_RDA: u8 = 0x01;
+/// IER: transmit holding register empty
+pub const IER_THRE: u8 = 0x02;
+/// IER: receiver line status
+pub const IER_RLS: u8 = 0x04;
+/// IER: modem status
+pub const IER_MS: u8 = 0x08;
+
+/// IIR: no interrupt pending
+pub const IIR_NONE: u8 = 0x01;
+/// IIR: modem status changed
+pub const IIR_MODEM_STATUS: u8 = 0x00;
+/// IIR: transmit holding register empty
+pub const IIR_THRE: u8 = 0x02;
+/// IIR: received data at the trigger level
+pub const IIR_RDA: u8 = 0x04;
+/// IIR: receiver line status error
+pub const IIR_LINE_STATUS: u8 = 0x06;
+/// IIR: received data below the trigger level timed out
+pub const IIR_TIMEOUT: u8 = 0x0C;
+/// IIR: FIFOs enabled
+pub const IIR_FIFO_ENABLED: u8 = 0xC0;
+
+/// FCR: enable the FIFOs
+pub const FCR_ENABLE: u8 = 0x01;
+/// FCR: clear the receive FIFO
+pub const FCR_CLEAR_RX: u8 = 0x02;
+/// FCR: clear the transmit FIFO
+pub const FCR_CLEAR_TX: u8 = 0x04;
+
+/// LCR: divisor latch access
+pub const LCR_DLAB: u8 = 0x80;
+
+/// MCR: request to send
+pub const MCR_RTS: u8 = 0x02;
+/// MCR: automatic flow control
+pub const MCR_AFE: u8 = 0x20;
+
+/// LSR: data ready
+pub const LSR_DR: u8 = 0x01;
+/// LSR: overrun error
+pub const LSR_OE: u8 = 0x02;
+/// LSR: transmit holding register empty
+pub const LSR_THRE: u8 = 0x20;
+/// LSR: transmitter empty
+pub const LSR_TEMT: u8 = 0x40;
+
+/// MSR: clear to send changed
+pub const MSR_DCTS: u8 = 0x01;
+/// MSR: clear to send
+pub const MSR_CTS: u8 = 0x10;
+/// MSR: data set ready
+pub const MSR_DSR: u8 = 0x20;
+/// MSR: data carrier detect
+pub const MSR_DCD: u8 = 0x80;
+
+/// Bit times per character: start, eight data bits and stop
+const BITS_PER_CHAR: u64 = 10;
+/// Character times of receive silence before a timeout interrupt
+const TIMEOUT_CHARS: u64 = 4;
+
+/// Host end of the line, shared with the device
+#[derive(Debug)]
+struct Line {
+    /// Characters sent by the host, not yet received by the UART
+    input: VecDeque<u8>,
+    /// Characters transmitted by the guest, not yet collected
+    output: Vec<u8>,
+    /// Whether the host asserts CTS
+    cts: bool,
+}
+
+/// Host end of a UART's line
+///
+/// Clones share the line, and can be used from any thread.
+#[derive(Debug, Clone)]
+pub struct UartPort {
+    /// Line shared with the device
+    line: Arc<Mutex<Line>>,
+}
+
+impl UartPort {
+    /// Send characters to the guest
+    pub fn send(&self, data: &[u8]) {
+        self.line.lock().unwrap().input.extend(data);
+    }
+
+    /// Characters sent but not yet received by the UART
+    pub fn pending(&self) -> usize {
+        self.line.lock().unwrap().input.len()
+    }
+
+    /// Take the characters the guest transmitted since the last call
+    pub fn take_output(&self) -> Vec<u8> {
+        std::mem::take(&mut self.line.lock().unwrap().output)
+    }
+
+    /// Assert or deassert CTS towards the guest
+    pub fn set_cts(&self, cts: bool) {
+        self.line.lock().unwrap().cts = cts;
+    }
+}
+
+/// 16550-compatible serial port
+#[derive(Debug)]
+pub struct Uart {
+    /// Name shown in the memory map
+    name: String,
+    /// Host end of the line
+    line: Arc<Mutex<Line>>,
+    /// AR.ITC ticks per character, or `None` for an unpaced line
+    char_ticks: Option<u64>,
+    /// Whether the host sender honours RTS
+    flow_control: bool,
+    /// AR.ITC at the last tick
+    now: u64,
+    /// Received characters
+    rx: VecDeque<u8>,
+    /// Earliest AR.ITC the next input character arrives at
+    next_rx: u64,
+    /// AR.ITC the last character was received or read at
+    last_rx: u64,
+    /// Characters waiting to be transmitted
+    tx: VecDeque<u8>,
+    /// Character being shifted out and the AR.ITC it is done at
+    shifting: Option<(u8, u64)>,
+    /// Transmit holding register empty interrupt not yet acknowledged
+    thre_pending: bool,
+    /// CTS as last seen, for the delta bit
+    cts: bool,
+    /// Interrupt enable register
+    ier: u8,
+    /// FIFO control register, as last written
+    fcr: u8,
+    /// Line control register
+    lcr: u8,
+    /// Modem control register
+    mcr: u8,
+    /// Error bits of the line status register, cleared when read
+    lsr_errors: u8,
+    /// Delta bits of the modem status register, cleared when read
+    msr_deltas: u8,
+    /// Scratch register
+    scr: u8,
+    /// Divisor latch
+    divisor: u16,
+}
+
+impl Uart {
+    /// Create an unpaced UART without flow control
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            line: Arc::new(Mutex::new(Line {
+                input: VecDeque::new(),
+                output: Vec::new(),
+                cts: true,
+            })),
+            char_ticks: None,
+            flow_control: false,
+            now: 0,
+            rx: VecDeque::new(),
+            next_rx: 0,
+            last_rx: 0,
+            tx: VecDeque::new(),
+            shifting: None,
+            thre_pending: false,
+            cts: true,
+            ier: 0,
+            fcr: 0,
+            lcr: 0,
+            mcr: 0,
+            lsr_errors: 0,
+            msr_deltas: 0,
+            scr: 0,
+            divisor: 1,
+        }
+    }
+
+    /// Pace the line at `baud` bits per second, with AR.ITC counting
+    /// `itc_frequency` ticks per second
+    pub fn with_baud(mut self, baud: u32, itc_frequency: u64) -> Self {
+        self.char_ticks = Some((itc_frequency * BITS_PER_CHAR / baud.max(1) as u64).max(1));
+        self
+    }
+
+    /// Make the host sender hold its input while RTS is deasserted
+    pub fn with_flow_control(mut self, flow_control: bool) -> Self {
+        self.flow_control = flow_control;
+        self
+    }
+
+    /// Host end of the line
+    pub fn port(&self) -> UartPort {
+        UartPort {
+            line: self.line.clone(),
+        }
+    }
+
+    /// Characters each FIFO holds
+    fn depth(&self) -> usize {
+        if self.fcr & FCR_ENABLE != 0 {
+            FIFO_DEPTH
+        } else {
+            1
+        }
+    }
+
+    /// Received characters that raise the data available interrupt
+    fn trigger_level(&self) -> usize {
+        if self.fcr & FCR_ENABLE == 0 {
+            return 1;
+        }
+        [1, 4, 8, 14][(self.fcr >> 6) as usize]
+    }
+
+    /// Whether RTS is asserted towards the host
+    fn rts(&self) -> bool {
+        self.mcr & MCR_RTS != 0
+            && !(self.mcr & MCR_AFE != 0 && self.rx.len() >= self.trigger_level())
+    }
+
+    /// Take a character off the line into the receive FIFO
+    fn receive(&mut self, byte: u8) {
+        if self.rx.len() < self.depth() {
+            self.rx.push_back(byte);
+        } else {
+            self.lsr_errors |= LSR_OE;
+        }
+        self.last_rx = self.now;
+    }
+
+    /// Move host input into the receive FIFO, as far as time and flow
+    /// control allow
+    fn receive_input(&mut self, line: &mut Line) {
+        loop {
+            let held = self.flow_control && !self.rts();
+            if held || line.input.is_empty() {
+                // The next character takes a full character time to arrive
+                self.next_rx = self.now + self.char_ticks.unwrap_or(0);
+                return;
+            }
+            if let Some(ticks) = self.char_ticks {
+                if self.next_rx > self.now {
+                    return;
+                }
+                self.next_rx += ticks;
+            }
+            let byte = line.input.pop_front().unwrap();
+            self.receive(byte);
+        }
+    }
+
+    /// Shift out transmitted characters, as far as time and CTS allow
+    fn transmit(&mut self, line: &mut Line) {
+        let held = self.mcr & MCR_AFE != 0 && !line.cts;
+        let mut start = self.now;
+        loop {
+            if let Some((byte, done)) = self.shifting {
+                if done > self.now {
+                    return;
+                }
+                line.output.push(byte);
+                self.shifting = None;
+                start = done;
+            }
+            if held {
+                return;
+            }
+            let Some(byte) = self.tx.pop_front() else {
+                return;
+            };
+            match self.char_ticks {
+                Some(ticks) => self.shifting = Some((byte, start + ticks)),
+                None => line.output.push(byte),
+            }
+            if self.tx.is_empty() {
+                self.thre_pending = true;
+            }
+        }
+    }
+
+    /// Highest priority interrupt pending, as an IIR source
+    fn interrupt(&self) -> Option<u8> {
+        if self.ier & IER_RLS != 0 && self.lsr_errors != 0 {
+            return Some(IIR_LINE_STATUS);
+        }
+        if self.ier & IER_RDA != 0 && !self.rx.is_empty() {
+            if self.rx.len() >= self.trigger_level() {
+                return Some(IIR_RDA);
+            }
+            let silence = TIMEOUT_CHARS * self.char_ticks.unwrap_or(0);
+            if self.now >= self.last_rx + silence {
+                return Some(IIR_TIMEOUT);
+            }
+        }
+        if self.ier & IER_THRE != 0 && self.thre_pending {
+            return Some(IIR_THRE);
+        }
+        if self.ier & IER_MS != 0 && self.msr_deltas != 0 {
+            return Some(IIR_MODEM_STATUS);
+        }
+        None
+    }
+
+    /// Read the register at `offset`
+    fn read_register(&mut self, offset: u64) -> u8 {
+        let dlab = self.lcr & LCR_DLAB != 0;
+        match offset {
+            REG_DATA if dlab => self.divisor as u8,
+            REG_DATA => {
+                self.last_rx = self.now;
+                self.rx.pop_front().unwrap_or(0)
+            }
+            REG_IER if dlab => (self.divisor >> 8) as u8,
+            REG_IER => self.ier,
+            REG_IIR => {
+                let fifo = if self.fcr & FCR_ENABLE != 0 {
+                    IIR_FIFO_ENABLED
+                } else {
+                    0
+                };
+                match self.interrupt() {
+                    Some(source) => {
+                        if source == IIR_THRE {
+                            self.thre_pending = false;
+                        }
+                        fifo | source
+                    }
+                    None => fifo | IIR_NONE,
+                }
+            }
+            REG_LCR => self.lcr,
+            REG_MCR => self.mcr,
+            REG_LSR => {
+                let mut lsr = std::mem::take(&mut self.lsr_errors);
+                if !self.rx.is_empty() {
+                    lsr |= LSR_DR;
+                }
+                if self.tx.is_empty() {
+                    lsr |= LSR_THRE;
+                    if self.shifting.is_none() {
+                        lsr |= LSR_TEMT;
+                    }
+                }
+                lsr
+            }
+            REG_MSR => {
+                let cts = if self.cts { MSR_CTS } else { 0 };
+                cts | MSR_DSR | MSR_DCD | std::mem::take(&mut self.msr_deltas)
+            }
+            _ => self.scr,
+        }
+    }
+
+    /// Write the register at `offset`
+    fn write_register(&mut self, offset: u64, value: u8) {
+        let dlab = self.lcr & LCR_DLAB != 0;
+        match offset {
+            REG_DATA if dlab => self.divisor = self.divisor & 0xFF00 | value as u16,
+            REG_DATA => {
+                self.thre_pending = false;
+                if self.tx.len() < self.depth() {
+                    self.tx.push_back(value);
+                }
+                let line = self.line.clone();
+                self.transmit(&mut line.lock().unwrap());
+            }
+            REG_IER if dlab => self.divisor = self.divisor & 0xFF | (value as u16) << 8,
+            REG_IER => {
+                // Enabling the interrupt with the holding register empty
+                // raises it at once
+                if value & !self.ier & IER_THRE != 0 && self.tx.is_empty() {
+                    self.thre_pending = true;
+                }
+                self.ier = value & 0x0F;
+            }
+            REG_IIR => {
+                if value & FCR_CLEAR_RX != 0 {
+                    self.rx.clear();
+                }
+                if value & FCR_CLEAR_TX != 0 {
+                    self.tx.clear();
+                }
+                if (value ^ self.fcr) & FCR_ENABLE != 0 {
+                    self.rx.clear();
+                    self.tx.clear();
+                }
+                self.fcr = value & !(FCR_CLEAR_RX | FCR_CLEAR_TX);
+            }
+            REG_LCR => self.lcr = value,
+            REG_MCR => self.mcr = value & 0x3F,
+            REG_LSR | REG_MSR => {}
+            _ => self.scr = value,
+        }
+    }
+}
+
+impl Device for Uart {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn size(&self) -> u64 {
+        UART_SIZE
+    }
+
+    fn read(&mut self, offset: u64, data: &mut [u8]) {
+        for (i, byte) in data.iter_mut().enumerate() {
+            *byte = self.read_register(offset + i as u64);
+        }
+    }
+
+    fn write(&mut self, offset: u64, data: &[u8]) {
+        for (i, &byte) in data.iter().enumerate() {
+            self.write_register(offset + i as u64, byte);
+        }
+    }
+
+    fn tick(&mut self, itc: u64) -> bool {
+        self.now = itc;
+        let line = self.line.clone();
+        let mut line = line.lock().unwrap();
+        if line.cts != self.cts {
+            self.cts = line.cts;
+            self.msr_deltas |= MSR_DCTS;
+        }
+        self.transmit(&mut line);
+        self.receive_input(&mut line);
+        self.interrupt().is_some()
+    }
+
+    fn reset(&mut self, _kind: ResetKind) {
+        self.rx.clear();
+        self.tx.clear();
+        self.shifting = None;
+        self.thre_pending = false;
+        self.ier = 0;
+        self.fcr = 0;
+        self.lcr = 0;
+        self.mcr = 0;
+        self.lsr_errors = 0;
+        self.msr_deltas = 0;
+        self.scr = 0;
+        self.divisor = 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Enable the FIFOs with a receive trigger level of 8
+    const FCR_TRIGGER_8: u8 = FCR_ENABLE | 0x80;
+
+    fn read(uart: &mut Uart, offset: u64) -> u8 {
+        let mut data = [0];
+        uart.read(offset, &mut data);
+        data[0]
+    }
+
+    #[test]
+    fn test_overrun() {
+        let mut uart = Uart::new("uart");
+        let port = uart.port();
+        uart.write(REG_IIR, &[FCR_TRIGGER_8]);
+        uart.write(REG_IER, &[IER_RDA | IER_RLS]);
+        assert!(!uart.tick(0));
+        assert_eq!(read(&mut uart, REG_IIR), IIR_FIFO_ENABLED | IIR_NONE);
+
+        // A burst longer than the FIFO overruns it
+        port.send(&(0..20).collect::<Vec<u8>>());
+        assert!(uart.tick(1));
+        assert_eq!(port.pending(), 0);
+        assert_eq!(read(&mut uart, REG_IIR), IIR_FIFO_ENABLED | IIR_LINE_STATUS);
+        assert_eq!(
+            read(&mut uart, REG_LSR),
+            LSR_OE | LSR_DR | LSR_THRE | LSR_TEMT
+        );
+
+        // Reading LSR clears the error, leaving the data interrupt
+        assert_eq!(read(&mut uart, REG_LSR) & LSR_OE, 0);
+        assert_eq!(read(&mut uart, REG_IIR), IIR_FIFO_ENABLED | IIR_RDA);
+        let mut data = [0; 16];
+        for byte in &mut data {
+            *byte = read(&mut uart, REG_DATA);
+        }
+        assert_eq!(data.to_vec(), (0..16).collect::<Vec<u8>>());
+        assert_eq!(read(&mut uart, REG_LSR), LSR_THRE | LSR_TEMT);
+        assert!(!uart.tick(2));
+    }
+
+    #[test]
+    fn test_paced_line() {
+        // 10 ticks per character
+        let mut uart = Uart::new("uart").with_baud(100, 100);
+        let port = uart.port();
+        uart.write(REG_IIR, &[FCR_TRIGGER_8]);
+        uart.write(REG_IER, &[IER_RDA | IER_THRE]);
+        assert!(uart.tick(0));
+        assert_eq!(read(&mut uart, REG_IIR), IIR_FIFO_ENABLED | IIR_THRE);
+        assert!(!uart.tick(0));
+
+        // Output leaves one character time apart
+        uart.write(REG_DATA, b"o");
+        uart.write(REG_DATA, b"k");
+        assert_eq!(read(&mut uart, REG_LSR), 0);
+        uart.tick(9);
+        assert!(port.take_output().is_empty());
+        assert!(uart.tick(10));
+        assert_eq!(port.take_output(), b"o");
+        assert_eq!(read(&mut uart, REG_LSR), LSR_THRE);
+        uart.tick(20);
+        assert_eq!(port.take_output(), b"k");
+        assert_eq!(read(&mut uart, REG_LSR), LSR_THRE | LSR_TEMT);
+        assert_eq!(read(&mut uart, REG_IIR), IIR_FIFO_ENABLED | IIR_THRE);
+
+        // Input arrives at the same pace, so the guest keeps up
+        port.send(b"abc");
+        assert!(!uart.tick(30));
+        uart.tick(40);
+        assert_eq!(port.pending(), 1);
+        uart.tick(50);
+        assert_eq!(read(&mut uart, REG_LSR) & (LSR_OE | LSR_DR), LSR_DR);
+
+        // Below the trigger level the data interrupt waits for a timeout
+        assert!(!uart.tick(89));
+        assert!(uart.tick(90));
+        assert_eq!(read(&mut uart, REG_IIR), IIR_FIFO_ENABLED | IIR_TIMEOUT);
+    }
+
+    #[test]
+    fn test_flow_control() {
+        let mut uart = Uart::new("uart").with_flow_control(true);
+        let port = uart.port();
+        uart.write(REG_IIR, &[FCR_TRIGGER_8]);
+        uart.write(REG_IER, &[IER_RDA | IER_RLS | IER_MS]);
+        port.send(&[0x55; 20]);
+
+        // With RTS deasserted the host holds its input
+        assert!(!uart.tick(0));
+        assert_eq!(port.pending(), 20);
+
+        // Automatic flow control deasserts RTS at the trigger level
+        uart.write(REG_MCR, &[MCR_RTS | MCR_AFE]);
+        assert!(uart.tick(1));
+        assert_eq!(port.pending(), 12);
+        assert_eq!(read(&mut uart, REG_IIR), IIR_FIFO_ENABLED | IIR_RDA);
+        for _ in 0..8 {
+            read(&mut uart, REG_DATA);
+        }
+        uart.tick(2);
+        uart.tick(3);
+        assert_eq!(port.pending(), 4);
+        assert_eq!(read(&mut uart, REG_LSR) & LSR_OE, 0);
+
+        // And holds the transmitter while the host deasserts CTS
+        port.set_cts(false);
+        assert!(uart.tick(4));
+        for _ in 0..8 {
+            read(&mut uart, REG_DATA);
+        }
+        assert_eq!(
+            read(&mut uart, REG_IIR),
+            IIR_FIFO_ENABLED | IIR_MODEM_STATUS
+        );
+        assert_eq!(read(&mut uart, REG_MSR), MSR_DSR | MSR_DCD | MSR_DCTS);
+        uart.write(REG_DATA, b"x");
+        uart.tick(5);
+        assert!(port.take_output().is_empty());
+        port.set_cts(true);
+        uart.tick(6);
+        assert_eq!(port.take_output(), b"x");
+    }
+}
@@ -0,0 +1,304 @@
+//! Watchdog timer device
+//!
+//! [`Watchdog`] counts AR.ITC ticks down from a timeout once enabled. The
+//! guest proves it is alive by writing the keepalive register, which
+//! restarts the count; if the count runs out, the status register records
+//! the expiry and the machine acts on it as configured: it takes a
+//! non-maskable interrupt, resets, or stops with
+//! [`StopReason::WatchdogExpired`](crate::emulator::StopReason::WatchdogExpired).
+//!
+//! An interrupting watchdog keeps counting, so a guest that does not
+//! recover is interrupted again after another timeout. One that resets or
+//! stops the machine disables itself, and the status register tells
+//! firmware after a warm reset that the watchdog caused it. A cold reset
+//! clears the status and brings back the power-on timeout and enable.
+//!
+//! Registers are 64 bits wide and may be accessed in parts.
+
+use super::{Device, MachineRequest};
+use crate::emulator::ResetKind;
+use serde::Deserialize;
+
+/// Size of the register window
+pub const WATCHDOG_SIZE: u64 = 0x28;
+
+/// Control register: [`CONTROL_ENABLE`]
+pub const REG_CONTROL: u64 = 0x00;
+/// Timeout in AR.ITC ticks, taking effect at the next enable or keepalive
+pub const REG_TIMEOUT: u64 = 0x08;
+/// Any write restarts the count
+pub const REG_KEEPALIVE: u64 = 0x10;
+/// Ticks left before expiry, zero while disabled (read only)
+pub const REG_REMAINING: u64 = 0x18;
+/// Status register: [`STATUS_EXPIRED`], write 1 to clear
+pub const REG_STATUS: u64 = 0x20;
+
+/// CONTROL: count down, starting from the timeout
+pub const CONTROL_ENABLE: u64 = 1;
+/// STATUS: the count ran out since the bit was last cleared
+pub const STATUS_EXPIRED: u64 = 1;
+
+/// What the machine does when the watchdog expires
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum WatchdogAction {
+    /// Raise a non-maskable interrupt
+    Interrupt,
+    /// Reset the machine and boot again
+    #[default]
+    Reset,
+    /// Stop the run
+    Stop,
+}
+
+/// Watchdog timer
+#[derive(Debug)]
+pub struct Watchdog {
+    /// Name used in the memory map and diagnostics
+    name: String,
+    /// Action on expiry
+    action: WatchdogAction,
+    /// Control register
+    control: u64,
+    /// Timeout in ticks
+    timeout: u64,
+    /// Status register
+    status: u64,
+    /// AR.ITC at the last tick
+    now: u64,
+    /// AR.ITC value the count runs out at, while enabled
+    deadline: Option<u64>,
+    /// Expiry not yet collected by the machine
+    expired: bool,
+    /// Timeout at power-on
+    power_on_timeout: u64,
+    /// Whether the watchdog counts from power-on
+    power_on_enabled: bool,
+}
+
+impl Watchdog {
+    /// Create a disabled watchdog with the given timeout in AR.ITC ticks
+    pub fn new(name: &str, timeout: u64, action: WatchdogAction) -> Self {
+        Self {
+            name: name.to_string(),
+            action,
+            control: 0,
+            timeout,
+            status: 0,
+            now: 0,
+            deadline: None,
+            expired: false,
+            power_on_timeout: timeout,
+            power_on_enabled: false,
+        }
+    }
+
+    /// Enable the watchdog from power-on
+    pub fn enabled(mut self) -> Self {
+        self.power_on_enabled = true;
+        self.control = CONTROL_ENABLE;
+        self.deadline = Some(self.now.saturating_add(self.timeout));
+        self
+    }
+
+    /// Action on expiry
+    pub fn action(&self) -> WatchdogAction {
+        self.action
+    }
+
+    /// Restart the count
+    fn keepalive(&mut self) {
+        if self.control & CONTROL_ENABLE != 0 {
+            self.deadline = Some(self.now.saturating_add(self.timeout));
+        }
+    }
+
+    /// Value of the register at `offset`, a multiple of 8
+    fn register(&self, offset: u64) -> u64 {
+        match offset {
+            REG_CONTROL => self.control,
+            REG_TIMEOUT => self.timeout,
+            REG_REMAINING => self
+                .deadline
+                .map_or(0, |deadline| deadline.saturating_sub(self.now)),
+            REG_STATUS => self.status,
+            _ => 0,
+        }
+    }
+
+    /// Store `value` to the register at `offset`, a multiple of 8
+    fn set_register(&mut self, offset: u64, value: u64) {
+        match offset {
+            REG_CONTROL => {
+                let was_enabled = self.control & CONTROL_ENABLE != 0;
+                self.control = value & CONTROL_ENABLE;
+                if self.control & CONTROL_ENABLE == 0 {
+                    self.deadline = None;
+                } else if !was_enabled {
+                    self.keepalive();
+                }
+            }
+            REG_TIMEOUT => self.timeout = value,
+            REG_KEEPALIVE => self.keepalive(),
+            REG_STATUS => self.status &= !value,
+            _ => {}
+        }
+    }
+}
+
+impl Device for Watchdog {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn size(&self) -> u64 {
+        WATCHDOG_SIZE
+    }
+
+    fn read(&mut self, offset: u64, data: &mut [u8]) {
+        for (i, byte) in data.iter_mut().enumerate() {
+            let offset = offset + i as u64;
+            *byte = (self.register(offset & !7) >> ((offset & 7) * 8)) as u8;
+        }
+    }
+
+    fn write(&mut self, offset: u64, data: &[u8]) {
+        // Merge the bytes into each register they touch, then store it once
+        let mut start = 0;
+        while start < data.len() {
+            let addr = offset + start as u64;
+            let register = addr & !7;
+            let len = (8 - (addr & 7) as usize).min(data.len() - start);
+            let mut value = self.register(register).to_le_bytes();
+            let first = (addr & 7) as usize;
+            value[first..first + len].copy_from_slice(&data[start..start + len]);
+            self.set_register(register, u64::from_le_bytes(value));
+            start += len;
+        }
+    }
+
+    fn tick(&mut self, itc: u64) -> bool {
+        self.now = itc;
+        if let Some(deadline) = self.deadline {
+            if itc >= deadline {
+                self.status |= STATUS_EXPIRED;
+                self.expired = true;
+                if self.action == WatchdogAction::Interrupt {
+                    self.deadline = Some(itc.saturating_add(self.timeout));
+                } else {
+                    self.control &= !CONTROL_ENABLE;
+                    self.deadline = None;
+                }
+            }
+        }
+        false
+    }
+
+    fn take_request(&mut self) -> Option<MachineRequest> {
+        if !std::mem::take(&mut self.expired) {
+            return None;
+        }
+        Some(match self.action {
+            WatchdogAction::Interrupt => MachineRequest::Nmi,
+            WatchdogAction::Reset => MachineRequest::Reset(ResetKind::Warm),
+            WatchdogAction::Stop => MachineRequest::Stop,
+        })
+    }
+
+    fn reset(&mut self, kind: ResetKind) {
+        self.expired = false;
+        if kind == ResetKind::Cold {
+            self.status = 0;
+            self.timeout = self.power_on_timeout;
+            self.control = 0;
+            self.deadline = None;
+            if self.power_on_enabled {
+                self.control = CONTROL_ENABLE;
+                self.keepalive();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read(watchdog: &mut Watchdog, offset: u64) -> u64 {
+        let mut data = [0; 8];
+        watchdog.read(offset, &mut data);
+        u64::from_le_bytes(data)
+    }
+
+    fn write(watchdog: &mut Watchdog, offset: u64, value: u64) {
+        watchdog.write(offset, &value.to_le_bytes());
+    }
+
+    #[test]
+    fn test_keepalive() {
+        let mut watchdog = Watchdog::new("watchdog", 100, WatchdogAction::Reset);
+        watchdog.tick(1000);
+        assert_eq!(read(&mut watchdog, REG_REMAINING), 0);
+        assert_eq!(read(&mut watchdog, REG_TIMEOUT), 100);
+
+        write(&mut watchdog, REG_CONTROL, CONTROL_ENABLE);
+        watchdog.tick(1060);
+        assert_eq!(read(&mut watchdog, REG_REMAINING), 40);
+        // A byte store to the keepalive register restarts the count
+        watchdog.write(REG_KEEPALIVE, &[0]);
+        watchdog.tick(1150);
+        assert_eq!(watchdog.take_request(), None);
+        assert_eq!(read(&mut watchdog, REG_STATUS), 0);
+
+        // Once expired, a resetting watchdog records why and disables itself
+        watchdog.tick(1160);
+        assert_eq!(
+            watchdog.take_request(),
+            Some(MachineRequest::Reset(ResetKind::Warm))
+        );
+        assert_eq!(watchdog.take_request(), None);
+        assert_eq!(read(&mut watchdog, REG_STATUS), STATUS_EXPIRED);
+        assert_eq!(read(&mut watchdog, REG_CONTROL), 0);
+        write(&mut watchdog, REG_STATUS, STATUS_EXPIRED);
+        assert_eq!(read(&mut watchdog, REG_STATUS), 0);
+    }
+
+    #[test]
+    fn test_interrupt_repeats() {
+        let mut watchdog = Watchdog::new("watchdog", 10, WatchdogAction::Interrupt).enabled();
+        // Partial writes merge into the register
+        watchdog.write(REG_TIMEOUT + 1, &[1]);
+        assert_eq!(read(&mut watchdog, REG_TIMEOUT), 0x10a);
+
+        watchdog.tick(10);
+        assert_eq!(watchdog.take_request(), Some(MachineRequest::Nmi));
+        assert_eq!(read(&mut watchdog, REG_CONTROL), CONTROL_ENABLE);
+        watchdog.tick(11);
+        assert_eq!(watchdog.take_request(), None);
+        watchdog.tick(10 + 0x10a);
+        assert_eq!(watchdog.take_request(), Some(MachineRequest::Nmi));
+    }
+
+    #[test]
+    fn test_reset() {
+        let mut watchdog = Watchdog::new("watchdog", 10, WatchdogAction::Reset).enabled();
+        write(&mut watchdog, REG_TIMEOUT, 50);
+        watchdog.tick(10);
+        assert_eq!(read(&mut watchdog, REG_CONTROL), 0);
+
+        // A warm reset keeps the status telling firmware why it rebooted
+        watchdog.reset(ResetKind::Warm);
+        assert_eq!(watchdog.take_request(), None);
+        assert_eq!(read(&mut watchdog, REG_STATUS), STATUS_EXPIRED);
+        assert_eq!(read(&mut watchdog, REG_TIMEOUT), 50);
+        assert_eq!(read(&mut watchdog, REG_CONTROL), 0);
+
+        // A cold reset is power-on again
+        watchdog.tick(100);
+        watchdog.reset(ResetKind::Cold);
+        assert_eq!(read(&mut watchdog, REG_STATUS), 0);
+        assert_eq!(read(&mut watchdog, REG_TIMEOUT), 10);
+        assert_eq!(read(&mut watchdog, REG_CONTROL), CONTROL_ENABLE);
+        assert_eq!(read(&mut watchdog, REG_REMAINING), 10);
+    }
+}
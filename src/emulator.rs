@@ -0,0 +1,3738 @@
+//! Emulator driver
+//!
+//! This module ties the CPU, memory and decoder together into a fetch,
+//! decode and execute loop, and reports to the caller why execution stopped.
+
+use crate::chaos::{Chaos, ChaosConfig, Deferred};
+use crate::config::MachineConfig;
+#[cfg(feature = "decode-ahead")]
+use crate::cpu::branch_predict::BranchPredictor;
+use crate::cpu::dispersal::Dispersal;
+use crate::cpu::hostfs::HostFs;
+use crate::cpu::instructions::dispatch::translate;
+use crate::cpu::instructions::Instruction;
+use crate::cpu::interrupts::InterruptVector;
+use crate::cpu::pmu::PmuEvents;
+use crate::cpu::registers::CRIndex;
+use crate::cpu::strace::Strace;
+use crate::cpu::syscall::{SyscallNumber, SYSCALL_PARAM_REGS};
+use crate::cpu::timing::{MemoryEvents, TimingModel};
+use crate::cpu::{Cpu, PSRFlags, NMI_VECTOR};
+use crate::crash::{
+    LivelockConfig, LivelockDetector, LivelockReport, PanicDetector, PanicHook, PanicReport,
+    PanicTrigger,
+};
+#[cfg(feature = "decode-ahead")]
+use crate::decode_ahead::DecodeAhead;
+use crate::decoder::completers::Completers;
+#[cfg(feature = "decode-ahead")]
+use crate::decoder::instruction_format::XOperation;
+use crate::decoder::{validate_slot, Bundle, InstructionType, Unit};
+use crate::device::flash::Flash;
+use crate::device::uart::{Uart, UartPort};
+use crate::device::watchdog::Watchdog;
+use crate::device::{
+    Device, DeviceEvent, DeviceHandle, DeviceId, HotplugQueue, HotplugRequest, MachineRequest,
+    DEVICE_INTERRUPT, HOTPLUG_DETACH,
+};
+use crate::firmware::memmap::{PhysMemoryMap, RegionKind, EFI_PAGE_SIZE};
+use crate::firmware::variables::{VariableService, VariableStore};
+use crate::intercept::{HostFunction, Intercept, InterceptTable, ARG_REGS, RETURN_REG};
+use crate::loader::{self, ElfLayout, GP_REG};
+use crate::memory::{Memory, Permissions, WxPolicy, PAGE_SIZE};
+use crate::process::{InitialStack, STACK_POINTER_REG, STACK_SIZE, STACK_TOP};
+use crate::repro::Snapshot;
+use crate::timeline::{Channel, Phase, Timeline};
+use crate::trace::{PendingInstruction, Tracer, Tracing};
+use crate::EmulatorError;
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+use std::sync::{Arc, OnceLock};
+
+/// Size of an instruction bundle in bytes
+pub const BUNDLE_SIZE: u64 = 16;
+
+/// Break immediate used by the guest to request a system call
+pub const SYSCALL_BREAK_IMM: u64 = 0x100000;
+
+/// General register holding the system call number
+pub const SYSCALL_NUMBER_REG: usize = 15;
+
+/// `EfiResetWarm` reset type of `ResetSystem`
+const EFI_RESET_WARM: u64 = 1;
+/// `EfiResetShutdown` reset type of `ResetSystem`
+const EFI_RESET_SHUTDOWN: u64 = 2;
+
+/// Alignment used when mapping loaded images
+const IMAGE_ALIGN: u64 = 4096;
+
+/// Bundles decoded ahead of the instruction pointer on the predicted path
+#[cfg(feature = "decode-ahead")]
+const DECODE_AHEAD_DEPTH: u64 = 64;
+
+/// Reason the emulator stopped running guest code
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopReason {
+    /// Guest called exit with the given status
+    Exited(i32),
+    /// Guest executed a break instruction that is not a system call
+    Break(u64),
+    /// Guest reached a panic hook; see [`Emulator::panic_report`]
+    Panic,
+    /// Guest looped without making progress; see [`Emulator::livelock_report`]
+    ///
+    /// The looping bundle has not executed yet, so running again resumes
+    /// the guest and watches for the next livelock.
+    Livelock,
+    /// A data debug register matched a value loaded or stored
+    ///
+    /// A Debug fault was raised and the instruction has not executed;
+    /// running again executes it with data debug faults disabled.
+    DataMatch {
+        /// Address accessed
+        addr: u64,
+        /// Value that matched
+        value: u64,
+    },
+    /// A watchdog set to stop the machine expired
+    ///
+    /// The watchdog has disabled itself, so running again resumes the guest.
+    WatchdogExpired,
+}
+
+/// How far a machine reset reaches
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResetKind {
+    /// Power cycle: RAM is cleared and devices return to their power-on
+    /// state
+    Cold,
+    /// Reset without removing power: RAM keeps its contents and devices
+    /// keep what survives a reset, like the watchdog status
+    Warm,
+}
+
+/// Most writes to executable memory kept for [`Emulator::take_wx_violations`]
+pub const MAX_WX_VIOLATIONS: usize = 1024;
+
+/// Write to executable memory reported under [`WxPolicy::Log`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WxViolation {
+    /// Address written
+    pub addr: u64,
+    /// Guest backtrace: the writing bundle followed by the return link in b0
+    pub backtrace: Vec<u64>,
+}
+
+/// Loads of uninitialized memory by one instruction, reported unless the
+/// [`UninitPolicy`](crate::memory::uninit::UninitPolicy) faults them
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UninitRead {
+    /// Bundle address of the instruction
+    pub ip: u64,
+    /// Slot of the instruction
+    pub slot: u8,
+    /// Address of the first uninitialized byte it loaded
+    pub addr: u64,
+    /// Uninitialized bytes it loaded from there at once
+    pub len: usize,
+    /// Loads of uninitialized bytes by the instruction
+    pub count: u64,
+    /// Guest backtrace at the first load: the bundle followed by the return
+    /// link in b0
+    pub backtrace: Vec<u64>,
+}
+
+/// Decoded bundle: instruction type and raw bits of each slot, and the
+/// stops its template places between instruction groups
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct DecodedBundle {
+    /// Raw bundle bytes
+    pub data: [u8; 16],
+    /// Instruction type and raw bits of each slot
+    pub slots: Vec<(InstructionType, u64)>,
+    /// Whether an instruction group ends after each slot
+    pub stops: [bool; 3],
+}
+
+impl DecodedBundle {
+    /// Whether slots 1 and 2 hold the L+X instruction of an MLX bundle
+    pub fn is_long(&self) -> bool {
+        matches!(self.slots.get(1), Some((InstructionType::L(_), _)))
+    }
+
+    /// Slot PSR.ri gives the instruction decoded into `slot`; an L+X
+    /// instruction is slot 1, though its opcode is in slot 2
+    pub fn restart_slot(&self, slot: usize) -> u8 {
+        if slot == 2 && self.is_long() {
+            1
+        } else {
+            slot as u8
+        }
+    }
+}
+
+/// Translation of an instruction slot, made on its first execution
+pub(crate) type Translation = OnceLock<Option<Box<dyn Instruction>>>;
+
+/// Decoded bundle in the decode cache, with the translations of its slots
+///
+/// Translating a slot extracts its register numbers and immediates and
+/// allocates the instruction; keeping the result lets later executions of
+/// the bundle skip both. The translations are dropped with the bundle when
+/// its code is written.
+struct CachedBundle {
+    /// Slots and stops
+    decoded: DecodedBundle,
+    /// Translation of each slot, once executed
+    translations: [Translation; 3],
+}
+
+impl CachedBundle {
+    /// Cache entry for a bundle none of whose slots has executed
+    fn new(decoded: DecodedBundle) -> Arc<Self> {
+        Arc::new(Self {
+            decoded,
+            translations: Default::default(),
+        })
+    }
+}
+
+impl std::fmt::Debug for CachedBundle {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CachedBundle")
+            .field("decoded", &self.decoded)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Control flow after executing a single instruction slot
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Flow {
+    /// Continue with the next slot
+    Continue,
+    /// A branch was taken; the rest of the bundle is skipped
+    Branch,
+    /// Stop the machine
+    Stop(StopReason),
+}
+
+/// IA-64 machine: CPU, memory and the run loop that drives them
+#[derive(Debug)]
+pub struct Emulator {
+    /// CPU state
+    pub cpu: Cpu,
+    /// Guest memory
+    pub memory: Memory,
+    /// Physical memory map reported by the firmware
+    pub phys_map: PhysMemoryMap,
+    /// Dispersal model counting issue cycles and stalls, if enabled
+    pub dispersal: Option<Dispersal>,
+    /// Timing model charging cycles for bundles, if enabled; otherwise a
+    /// bundle takes a cycle
+    pub timing: Option<TimingModel>,
+    /// Decoded bundles by address
+    decode_cache: HashMap<u64, Arc<CachedBundle>>,
+    /// Writes to executable memory reported under [`WxPolicy::Log`], one
+    /// per address and at most [`MAX_WX_VIOLATIONS`]
+    wx_violations: Vec<WxViolation>,
+    /// Loads of uninitialized memory, by bundle address and slot
+    uninit_reads: BTreeMap<(u64, u8), UninitRead>,
+    /// Panic hooks and execution trace
+    panic_detector: PanicDetector,
+    /// Report of the last panic hook that fired
+    panic_report: Option<PanicReport>,
+    /// Livelock detection, if enabled
+    livelock: Option<LivelockDetector>,
+    /// Report of the last livelock detected
+    livelock_report: Option<LivelockReport>,
+    /// System call trace
+    strace: Option<Strace>,
+    /// Instruction trace
+    tracing: Option<Tracing>,
+    /// Timeline of device, interrupt, system call and stall events, if
+    /// enabled
+    timeline: Option<Timeline>,
+    /// Guest functions replaced by host functions
+    intercepts: InterceptTable,
+    /// Firmware variables
+    variables: VariableStore,
+    /// Variable services by entry point
+    variable_services: HashMap<u64, VariableService>,
+    /// Validate each slot's opcode against its template's unit
+    strict_decode: bool,
+    /// Bundle address and slot of the instruction to resume after a data
+    /// debug fault
+    data_match_resume: Option<(u64, u8)>,
+    /// Bundle address and slot an rfi returned to
+    return_slot: Option<(u64, u8)>,
+    /// Attach and detach requests from device handles
+    hotplug: Arc<HotplugQueue>,
+    /// Device notifications not yet collected
+    device_events: Vec<DeviceEvent>,
+    /// Host ends of the configured serial ports, by name
+    uart_ports: BTreeMap<String, UartPort>,
+    /// Chaos mode settings, if enabled
+    chaos: Option<ChaosConfig>,
+    /// Hotplug requests held back in chaos mode
+    deferred_hotplug: Option<Deferred<HotplugRequest>>,
+    /// Generator of bundle latencies in chaos mode
+    latency: Option<Chaos>,
+    /// Registers before the first bundle, restored by a reset
+    boot: Option<Snapshot>,
+    /// Images configured into RAM regions, loaded again by a cold reset
+    ram_images: Vec<(u64, Vec<u8>)>,
+    /// Entry point served as the EFI `ResetSystem` runtime service
+    reset_service: Option<u64>,
+    /// Resets since the machine was built
+    resets: u64,
+    /// Instructions retired since the machine was built
+    retired: u64,
+    /// Bundles retired since the machine was built
+    bundles: u64,
+    /// Cycles taken since the machine was built
+    cycles: u64,
+    /// Worker decoding bundles ahead of execution
+    #[cfg(feature = "decode-ahead")]
+    decode_ahead: DecodeAhead,
+}
+
+/// Machine assembled from a configuration file
+pub type Machine = Emulator;
+
+impl Default for Emulator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Emulator {
+    /// Create a new emulator with empty memory
+    pub fn new() -> Self {
+        let mut cpu = Cpu::new();
+        // p0 is hardwired to 1
+        cpu.pr[0] = true;
+
+        Self {
+            cpu,
+            memory: Memory::new(),
+            phys_map: PhysMemoryMap::new(),
+            dispersal: None,
+            timing: None,
+            decode_cache: HashMap::new(),
+            wx_violations: Vec::new(),
+            uninit_reads: BTreeMap::new(),
+            panic_detector: PanicDetector::default(),
+            panic_report: None,
+            livelock: None,
+            livelock_report: None,
+            strace: None,
+            tracing: None,
+            timeline: None,
+            intercepts: InterceptTable::new(),
+            variables: VariableStore::new(),
+            variable_services: HashMap::new(),
+            strict_decode: false,
+            data_match_resume: None,
+            return_slot: None,
+            hotplug: Arc::default(),
+            device_events: Vec::new(),
+            uart_ports: BTreeMap::new(),
+            chaos: None,
+            deferred_hotplug: None,
+            latency: None,
+            boot: None,
+            ram_images: Vec::new(),
+            reset_service: None,
+            resets: 0,
+            retired: 0,
+            bundles: 0,
+            cycles: 0,
+            #[cfg(feature = "decode-ahead")]
+            decode_ahead: DecodeAhead::new(),
+        }
+    }
+
+    /// Build a machine from a TOML configuration file
+    pub fn from_config(path: impl AsRef<Path>) -> Result<Self, EmulatorError> {
+        Self::with_config(&MachineConfig::load(path)?)
+    }
+
+    /// Build a machine from a parsed configuration
+    pub fn with_config(config: &MachineConfig) -> Result<Self, EmulatorError> {
+        config.validate()?;
+
+        let mut emu = Self::new();
+        emu.memory = Memory::with_caches(config.cache.l1, config.cache.l2, config.cache.l3)?;
+        emu.memory.set_victim_cache(config.cache.victim);
+        emu.memory.set_wx_policy(config.wx_policy);
+        emu.memory.set_uninit_policy(config.uninit_policy);
+        emu.cpu.set_timer_mode(config.cpu.timer_mode());
+        emu.set_strict_decode(config.cpu.strict_decode);
+        emu.cpu.strict_types = config.cpu.strict_types;
+        emu.cpu.alignment_policy = config.cpu.unaligned;
+        emu.dispersal = config
+            .cpu
+            .dispersal
+            .map(|machine| Dispersal::new(machine, config.cpu.strict_dispersal));
+        emu.timing = config.timing.map(TimingModel::new);
+        emu.cpu.syscall_mgr.set_identity(config.guest.clone());
+        if let Some(hostfs) = &config.hostfs {
+            let share = HostFs::new(&hostfs.root, hostfs.mode).map_err(|e| {
+                EmulatorError::ConfigError(format!(
+                    "hostfs.root: cannot share {}: {}",
+                    hostfs.root.display(),
+                    e
+                ))
+            })?;
+            emu.cpu.syscall_mgr.set_hostfs(share);
+        }
+
+        for (i, region) in config.memory.iter().enumerate() {
+            let image = match &region.image {
+                Some(path) => std::fs::read(path).map_err(|e| {
+                    EmulatorError::ConfigError(format!(
+                        "memory[{}].image: cannot read {}: {}",
+                        i,
+                        path.display(),
+                        e
+                    ))
+                })?,
+                None => Vec::new(),
+            };
+            if image.len() as u64 > region.size {
+                return Err(EmulatorError::ConfigError(format!(
+                    "memory[{}].image: {} bytes do not fit in a {:#x} byte region",
+                    i,
+                    image.len(),
+                    region.size
+                )));
+            }
+            if region.shared {
+                emu.memory
+                    .map_shared(region.base, region.size, region.permissions)?
+                    .write_bytes(0, &image)?;
+            } else {
+                emu.memory
+                    .load_image(region.base, region.size, &image, region.permissions)?;
+            }
+            if region.initialized {
+                emu.memory.mark_initialized(region.base, region.size);
+            }
+            if let Some(transform) = &region.transform {
+                let transform = transform.build().map_err(|e| {
+                    EmulatorError::ConfigError(format!("memory[{}].transform: {}", i, e))
+                })?;
+                emu.memory.set_transform(region.base, Some(transform))?;
+            }
+            if region.kind == RegionKind::Ram && !region.shared && !image.is_empty() {
+                emu.ram_images.push((region.base, image));
+            }
+            if let Some(name) = &region.name {
+                emu.memory.name_region(region.base, name)?;
+            }
+            let name = match &region.name {
+                Some(name) => name.clone(),
+                None => format!("memory[{}]", i),
+            };
+            emu.phys_map
+                .add(&name, region.base, region.size, region.kind)?;
+        }
+
+        // Flash, serial ports and watchdogs are present from power-on, so no hotplug interrupt is
+        // raised
+        for (i, flash) in config.flash.iter().enumerate() {
+            let name = match &flash.name {
+                Some(name) => name.clone(),
+                None => format!("flash[{}]", i),
+            };
+            let device = Flash::open(&name, &flash.file, flash.size, flash.block_size)?
+                .with_write_protect(flash.write_protect);
+            let id = emu.hotplug.next_id();
+            emu.map_device(id, flash.base, Box::new(device))?;
+        }
+        for (i, uart) in config.uart.iter().enumerate() {
+            let name = match &uart.name {
+                Some(name) => name.clone(),
+                None => format!("uart[{}]", i),
+            };
+            let mut device = Uart::new(&name).with_flow_control(uart.flow_control);
+            if let Some(baud) = uart.baud {
+                let clock = uart.clock.or(config.cpu.itc_frequency).unwrap_or_default();
+                device = device.with_baud(baud, clock);
+            }
+            emu.uart_ports.insert(name, device.port());
+            let id = emu.hotplug.next_id();
+            emu.map_device(id, uart.base, Box::new(device))?;
+        }
+        for (i, watchdog) in config.watchdog.iter().enumerate() {
+            let name = match &watchdog.name {
+                Some(name) => name.clone(),
+                None => format!("watchdog[{}]", i),
+            };
+            let mut device = Watchdog::new(&name, watchdog.timeout, watchdog.action);
+            if watchdog.enabled {
+                device = device.enabled();
+            }
+            let id = emu.hotplug.next_id();
+            emu.map_device(id, watchdog.base, Box::new(device))?;
+        }
+
+        if let Some(path) = &config.efi.variables {
+            emu.variables = VariableStore::open(path)?;
+        }
+        for (service, address) in config.efi.variable_services() {
+            emu.add_variable_service(service, address);
+        }
+        emu.reset_service = config.efi.reset_system;
+
+        emu.set_panic_trace_len(config.panic.trace);
+        for (name, &address) in &config.panic.functions {
+            emu.add_panic_hook(PanicHook::function(name, address));
+        }
+        for (name, &address) in &config.panic.ports {
+            emu.add_panic_hook(PanicHook::port(name, address));
+        }
+
+        if let Some(entry) = config.entry {
+            emu.cpu.ip = entry;
+        }
+        Ok(emu)
+    }
+
+    /// Load a flat binary image at `base` and start execution at `entry`
+    ///
+    /// A flat image does not say where its code ends, so it is mapped
+    /// readable, writable and executable; the W^X policy only covers the
+    /// pages instructions are fetched from.
+    pub fn load_flat_image(
+        &mut self,
+        base: u64,
+        image: &[u8],
+        entry: u64,
+    ) -> Result<(), EmulatorError> {
+        let size = (image.len() as u64)
+            .max(1)
+            .div_ceil(IMAGE_ALIGN)
+            .saturating_mul(IMAGE_ALIGN);
+        self.memory
+            .load_image(base, size, image, Permissions::ReadWriteExecute)?;
+        self.phys_map
+            .add("image", base, size, RegionKind::LoaderCode)?;
+        self.invalidate_decoded(base, size);
+        self.cpu.ip = entry;
+        Ok(())
+    }
+
+    /// Load an ELF executable and start execution at its entry point
+    ///
+    /// A position-independent image is relocated to `base`; an ET_EXEC
+    /// image loads at its link addresses. r1 is set to the image's gp when it
+    /// has one. Returns where the segments went.
+    pub fn load_elf(&mut self, image: &[u8], base: u64) -> Result<ElfLayout, EmulatorError> {
+        let loaded = loader::load(image, base)?;
+        for (i, region) in loaded.regions.iter().enumerate() {
+            let size = region.data.len() as u64;
+            self.memory
+                .load_image(region.start, size, &region.data, region.permissions)?;
+            let kind = if region.permissions.can_execute() {
+                RegionKind::LoaderCode
+            } else {
+                RegionKind::LoaderData
+            };
+            self.phys_map
+                .add(&format!("image segment {}", i), region.start, size, kind)?;
+            self.invalidate_decoded(region.start, size);
+        }
+        if let Some(gp) = loaded.layout.gp {
+            self.cpu.set_gr(GP_REG, gp)?;
+        }
+        self.cpu.ip = loaded.layout.entry;
+        Ok(loaded.layout)
+    }
+
+    /// Reset the machine and boot it again
+    ///
+    /// Registers go back to their values before the first bundle, so the
+    /// guest reenters its firmware or program entry point, and every device
+    /// is reset. A warm reset, as a watchdog does, leaves memory alone, as
+    /// RAM and firmware flash keep their contents across one. A cold reset
+    /// also zeroes the general-purpose RAM regions of the physical memory
+    /// map and loads the images configured into them again; loaded program
+    /// images and firmware regions keep their contents, as they would be
+    /// reloaded from disk or ROM.
+    pub fn reset(&mut self, kind: ResetKind) -> Result<(), EmulatorError> {
+        let boot = match self.boot.take() {
+            Some(boot) => boot,
+            None => Snapshot::capture_registers(self),
+        };
+        self.cpu.reset()?;
+        boot.restore(self)?;
+        self.boot = Some(boot);
+        self.memory.reset_devices(kind);
+        if kind == ResetKind::Cold {
+            let ram: Vec<_> = self
+                .phys_map
+                .regions()
+                .filter(|region| region.kind == RegionKind::Ram)
+                .map(|region| region.base)
+                .collect();
+            for base in ram {
+                // Shared regions belong to host threads, which keep them
+                if self.memory.shared_memory(base).is_none() {
+                    self.memory.clear_region(base)?;
+                }
+            }
+            for (base, image) in &self.ram_images {
+                self.memory.write_bytes(*base, image)?;
+            }
+            self.decode_cache.clear();
+        }
+        self.data_match_resume = None;
+        self.return_slot = None;
+        self.resets += 1;
+        Ok(())
+    }
+
+    /// Number of resets since the machine was built
+    pub fn resets(&self) -> u64 {
+        self.resets
+    }
+
+    /// Number of instructions retired since the machine was built
+    ///
+    /// Slots whose qualifying predicate was false count, as they do for
+    /// the PMU.
+    pub fn retired(&self) -> u64 {
+        self.retired
+    }
+
+    /// Number of bundles retired since the machine was built
+    ///
+    /// A bundle retires when execution leaves it, at its end or by a taken
+    /// branch or stop, with one instruction or three; a fault before that
+    /// leaves it unretired.
+    pub fn bundles(&self) -> u64 {
+        self.bundles
+    }
+
+    /// Number of cycles taken since the machine was built
+    ///
+    /// A bundle takes one cycle, or what the timing model charges with
+    /// latency jitter applied. Unlike AR.ITC this count is not scaled,
+    /// rewritten by the guest or tied to host time.
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    /// Bytes of general-purpose RAM in the physical memory map
+    pub fn ram_size(&self) -> u64 {
+        self.phys_map
+            .regions()
+            .filter(|region| region.kind == RegionKind::Ram)
+            .map(|region| region.size)
+            .sum()
+    }
+
+    /// Grow or shrink general-purpose RAM to `new_size` bytes
+    ///
+    /// Growing maps a new RAM range just above the highest one. Shrinking
+    /// gives back pages from the top of RAM downward, and only pages the
+    /// guest no longer uses: every released page must read as zero and hold
+    /// no configured image, so a guest's balloon driver has to migrate and
+    /// clear what it hands back first. Nothing changes if any page is in
+    /// use. The physical memory map follows, so firmware reports the new
+    /// layout to a guest that asks again.
+    pub fn resize_ram(&mut self, new_size: u64) -> Result<(), EmulatorError> {
+        if !new_size.is_multiple_of(EFI_PAGE_SIZE) {
+            return Err(EmulatorError::MemoryError(format!(
+                "RAM size {:#x} is not a whole number of pages",
+                new_size
+            )));
+        }
+        let ram: Vec<_> = self
+            .phys_map
+            .regions()
+            .filter(|region| region.kind == RegionKind::Ram)
+            .cloned()
+            .collect();
+        let size: u64 = ram.iter().map(|region| region.size).sum();
+        let top = ram
+            .last()
+            .ok_or_else(|| EmulatorError::MemoryError("No RAM to resize".to_string()))?;
+
+        if new_size >= size {
+            if new_size > size {
+                let base = top.end();
+                self.phys_map
+                    .add("hotplug ram", base, new_size - size, RegionKind::Ram)?;
+                if let Err(e) = self
+                    .memory
+                    .map(base, new_size - size, Permissions::ReadWrite)
+                {
+                    self.phys_map.remove(base);
+                    return Err(e);
+                }
+            }
+            return Ok(());
+        }
+
+        // Work out which pages go and check them all before releasing any
+        self.memory.flush_all_caches()?;
+        let mut excess = size - new_size;
+        let mut releases = Vec::new();
+        for region in ram.iter().rev() {
+            if excess == 0 {
+                break;
+            }
+            let release = region.size.min(excess);
+            excess -= release;
+            let start = region.end() - release;
+            let (base, data) = self
+                .memory
+                .regions()
+                .find(|(base, _, data)| *base <= start && start < base + data.len() as u64)
+                .map(|(base, _, data)| (base, data))
+                .ok_or_else(|| {
+                    EmulatorError::MemoryError(format!(
+                        "{} [{:#x}, {:#x}) is not resizable",
+                        region.name,
+                        region.base,
+                        region.end()
+                    ))
+                })?;
+            if base + data.len() as u64 != region.end() {
+                return Err(EmulatorError::MemoryError(format!(
+                    "{} [{:#x}, {:#x}) does not end its mapping",
+                    region.name,
+                    region.base,
+                    region.end()
+                )));
+            }
+            let released = &data[(start - base) as usize..];
+            if let Some(offset) = released.iter().position(|&byte| byte != 0) {
+                let page = (start + offset as u64) & !(EFI_PAGE_SIZE - 1);
+                return Err(EmulatorError::MemoryError(format!(
+                    "RAM page {:#x} is still in use",
+                    page
+                )));
+            }
+            if let Some((image, _)) = self
+                .ram_images
+                .iter()
+                .find(|(image, data)| *image < region.end() && start < image + data.len() as u64)
+            {
+                return Err(EmulatorError::MemoryError(format!(
+                    "RAM at {:#x} holds a configured image",
+                    image
+                )));
+            }
+            releases.push((region.clone(), base, start));
+        }
+
+        for (region, base, start) in releases {
+            if start == base {
+                self.memory.unmap(base)?;
+            } else {
+                self.memory.truncate(base, start - base)?;
+            }
+            self.phys_map.remove(region.base);
+            if start > region.base {
+                self.phys_map.add(
+                    &region.name,
+                    region.base,
+                    start - region.base,
+                    RegionKind::Ram,
+                )?;
+            }
+            self.invalidate_decoded(start, region.end() - start);
+        }
+        Ok(())
+    }
+
+    /// Map the memory stack and place the guest's arguments and environment
+    /// on it, with r12 pointing at it as the Linux kernel leaves it
+    ///
+    /// `args[0]` is the program name and `env` holds `KEY=VALUE` strings.
+    /// Call after loading the image, so the auxiliary vector gets the entry
+    /// point.
+    pub fn setup_process(&mut self, args: &[String], env: &[String]) -> Result<(), EmulatorError> {
+        let base = STACK_TOP - STACK_SIZE;
+        let stack = InitialStack::build(STACK_TOP, args, env, self.cpu.ip, PAGE_SIZE)?;
+        self.memory.map(base, STACK_SIZE, Permissions::ReadWrite)?;
+        self.phys_map
+            .add("stack", base, STACK_SIZE, RegionKind::LoaderData)?;
+        self.memory.write_bytes(stack.data_addr(), &stack.data)?;
+        self.cpu.set_gr(STACK_POINTER_REG, stack.sp)
+    }
+
+    /// Run until the guest stops the machine
+    pub fn run(&mut self) -> Result<StopReason, EmulatorError> {
+        loop {
+            if let Some(reason) = self.step()? {
+                return Ok(reason);
+            }
+        }
+    }
+
+    /// Run until the guest stops the machine, yielding to the tokio runtime
+    /// every `interval` bundles
+    ///
+    /// Device backends on the same runtime, like those of
+    /// [`device::backend`](crate::device::backend), run while the guest
+    /// yields. Shorter intervals make them more responsive at the cost of
+    /// guest speed.
+    #[cfg(feature = "async-io")]
+    pub async fn run_async(&mut self, interval: u64) -> Result<StopReason, EmulatorError> {
+        loop {
+            for _ in 0..interval.max(1) {
+                if let Some(reason) = self.step()? {
+                    return Ok(reason);
+                }
+            }
+            tokio::task::yield_now().await;
+        }
+    }
+
+    /// Execute the bundle at the current instruction pointer
+    ///
+    /// Returns `Some(reason)` if the bundle stopped the machine.
+    pub fn step(&mut self) -> Result<Option<StopReason>, EmulatorError> {
+        let bundle_ip = self.cpu.ip;
+        if !bundle_ip.is_multiple_of(BUNDLE_SIZE) {
+            return Err(EmulatorError::InvalidAlignment);
+        }
+
+        if self.boot.is_none() {
+            self.boot = Some(Snapshot::capture_registers(self));
+        }
+
+        // The RSE finishes loading the current frame before execution resumes
+        if self.cpu.rse.pending_loads() > 0 {
+            self.cpu.complete_rse_loads(&mut self.memory)?;
+        }
+
+        // Devices change only between bundles, later in chaos mode
+        let mut requests = self.hotplug.take();
+        if let Some(deferred) = &mut self.deferred_hotplug {
+            for request in requests {
+                deferred.defer(request);
+            }
+            requests = deferred.tick();
+        }
+        for request in requests {
+            self.apply_hotplug(request);
+        }
+        let itc = self.cpu.timer.read_itc();
+        if let Some(timeline) = &self.timeline {
+            timeline.set_time(itc);
+        }
+        for id in self.memory.tick_devices(itc) {
+            self.record_device(id, "irq", &[]);
+            self.cpu
+                .raise_interrupt(InterruptVector::ExtInt, id.0 as u64 | DEVICE_INTERRUPT);
+        }
+        for (_, request) in self.memory.take_device_requests() {
+            match request {
+                MachineRequest::Nmi => self.cpu.raise_external_interrupt(NMI_VECTOR)?,
+                MachineRequest::Reset(kind) => {
+                    self.reset(kind)?;
+                    return Ok(None);
+                }
+                MachineRequest::Stop => return Ok(Some(StopReason::WatchdogExpired)),
+            }
+        }
+
+        // A guest with its own vector table at CR.IVA takes an interrupt
+        // pending while PSR.i is set before the bundle; embedders that
+        // register handlers directly deliver with Cpu::check_interrupts
+        if self.cpu.read_cr(CRIndex::IVA) != 0 {
+            if let Some(handler) = self.cpu.check_interrupts() {
+                self.cpu.ip = handler;
+                self.cpu.slot = 0;
+            }
+        }
+        let bundle_ip = self.cpu.ip;
+
+        // Pick up code written since the last bundle, e.g. by a debugger
+        self.collect_code_writes(bundle_ip);
+        self.memory.take_watched_writes();
+
+        if self.panic_detector.is_active() {
+            if let Some(hook) = self.panic_detector.find(PanicTrigger::Execute(bundle_ip)) {
+                self.panic_report =
+                    Some(
+                        self.panic_detector
+                            .report(hook, bundle_ip, &self.cpu, &self.memory),
+                    );
+                return Ok(Some(StopReason::Panic));
+            }
+            self.panic_detector.record(bundle_ip);
+        }
+        if let Some(detector) = &mut self.livelock {
+            let activity = self.memory.write_count() + self.cpu.interrupts_raised();
+            if detector.check(bundle_ip, activity) {
+                self.livelock_report = Some(detector.report(&self.cpu));
+                detector.restart(bundle_ip);
+                return Ok(Some(StopReason::Livelock));
+            }
+        }
+        if let Some(intercept) = self.intercepts.get(bundle_ip) {
+            return self.call_intercept(intercept.function, bundle_ip);
+        }
+        if let Some(&service) = self.variable_services.get(&bundle_ip) {
+            return self.call_variable_service(service, bundle_ip);
+        }
+        if self.reset_service == Some(bundle_ip) {
+            return self.call_reset_service();
+        }
+
+        #[cfg(feature = "decode-ahead")]
+        self.collect_decoded(bundle_ip);
+
+        let cached = match self.decode_cache.get(&bundle_ip) {
+            Some(cached) => Arc::clone(cached),
+            None => {
+                let cached = CachedBundle::new(self.fetch_and_decode(bundle_ip)?);
+                self.decode_cache.insert(bundle_ip, Arc::clone(&cached));
+                cached
+            }
+        };
+        let decoded = &cached.decoded;
+
+        #[cfg(feature = "decode-ahead")]
+        self.decode_ahead(bundle_ip, decoded);
+
+        if self.strict_decode {
+            for (slot, (itype, bits)) in decoded.slots.iter().enumerate() {
+                validate_slot(slot, itype, *bits)?;
+            }
+        }
+        if let Some(dispersal) = &mut self.dispersal {
+            let units: Vec<Unit> = decoded
+                .slots
+                .iter()
+                .map(|(itype, _)| itype.unit())
+                .collect();
+            dispersal.issue(&units, &decoded.stops)?;
+        }
+
+        // An instruction stopped by a data debug fault resumes where it
+        // left off, with PSR.dd set so it does not fault again
+        let resume = match self.data_match_resume.take() {
+            Some((ip, slot)) if ip == bundle_ip => Some(slot),
+            _ => None,
+        };
+        // An rfi resumes at the slot in IPSR.ri, which can only be 0 or 1
+        // in an MLX bundle
+        let first_slot = match self.return_slot.take() {
+            Some((ip, slot)) if ip == bundle_ip => slot,
+            _ => 0,
+        };
+        if first_slot == 2 && decoded.is_long() {
+            return Err(EmulatorError::ExecutionError(format!(
+                "Illegal operation: rfi to slot 2 of the MLX bundle at {:#x}",
+                bundle_ip
+            )));
+        }
+        self.cpu.bundle = decoded.data;
+
+        // Execute each slot in order, up to a taken branch
+        let mut stop = None;
+        let mut taken = false;
+        let mut faulted = false;
+        let mut retired = 0;
+        let mut mispredicts = 0;
+        let before = self.memory.stats();
+        let rse_before = self.cpu.rse.spill_count() + self.cpu.rse.fill_count();
+        let stalls_before = match (&self.timeline, &self.timing) {
+            (Some(_), Some(timing)) => Some(timing.stats().clone()),
+            _ => None,
+        };
+        for (slot, (itype, bits)) in decoded.slots.iter().enumerate() {
+            // The L slot is part of the X slot's instruction
+            if matches!(itype, InstructionType::L(_)) {
+                continue;
+            }
+            let ri = decoded.restart_slot(slot);
+            let slot = slot as u8;
+            if ri < first_slot || resume.is_some_and(|resume| slot < resume) {
+                continue;
+            }
+            self.cpu.slot = ri;
+            let resumed = resume == Some(slot);
+            if resumed {
+                self.cpu.system_regs.cr.set(PSRFlags::DD, true);
+            }
+            let pending = if self.is_tracing() {
+                Some(PendingInstruction::capture(&self.cpu, *bits)?)
+            } else {
+                None
+            };
+            let translation = &cached.translations[slot as usize];
+            let flow = self.execute_slot(itype, *bits, translation);
+            if resumed {
+                self.cpu.system_regs.cr.set(PSRFlags::DD, false);
+            }
+            self.collect_code_writes(bundle_ip);
+            self.collect_uninit_reads(bundle_ip, ri);
+            if let Err(EmulatorError::DebugFault { addr, value }) = flow {
+                self.cpu.raise_interrupt(InterruptVector::DebugFault, addr);
+                self.data_match_resume = Some((bundle_ip, slot));
+                stop = Some(StopReason::DataMatch { addr, value });
+                faulted = true;
+                break;
+            }
+            if let Err(EmulatorError::FPFault { isr }) = flow {
+                self.cpu.raise_interrupt(InterruptVector::FPFault, isr);
+            }
+            if let Err(EmulatorError::UnalignedReference { addr, .. }) = flow {
+                self.cpu
+                    .raise_interrupt(InterruptVector::UnalignedReferenceFault, addr);
+            }
+            let flow = flow?;
+            // Count the slot now, so a fault in a later one keeps it
+            retired += 1;
+            self.retired += 1;
+            if let Some(timing) = &mut self.timing {
+                timing.execute(itype.unit(), decoded.stops[slot as usize]);
+            }
+            if let Some(pending) = pending {
+                let l_slot = if decoded.is_long() {
+                    decoded.slots[1].1
+                } else {
+                    0
+                };
+                let instruction =
+                    pending.finish(&self.cpu, bundle_ip, slot, itype.unit(), *bits, l_slot);
+                self.trace(|tracer| tracer.instruction(&instruction))?;
+            }
+            // Branches with a whether hint go through the prediction model
+            let hints = match itype.unit() {
+                Unit::B | Unit::X => Completers::decode_branch(*bits),
+                _ => Completers::NONE,
+            };
+            if !hints.is_empty() {
+                let branched = matches!(flow, Flow::Branch);
+                let mispredicted = self
+                    .cpu
+                    .branch_predictor
+                    .resolve(bundle_ip, slot, hints, branched);
+                mispredicts += mispredicted as u64;
+            }
+            match flow {
+                Flow::Continue => {}
+                Flow::Branch => taken = true,
+                Flow::Stop(reason) => {
+                    stop = Some(reason);
+                    break;
+                }
+            }
+            if self.check_panic_writes(bundle_ip) {
+                stop = Some(StopReason::Panic);
+                break;
+            }
+            if taken {
+                break;
+            }
+        }
+
+        if taken {
+            if let Some(dispersal) = &mut self.dispersal {
+                dispersal.end_cycle();
+            }
+        } else if !faulted {
+            self.cpu.ip = bundle_ip.wrapping_add(BUNDLE_SIZE);
+        }
+        // Interrupts raised from here on resume at the next bundle's first
+        // slot, unless rfi picked another
+        if !faulted && stop.is_none() && self.return_slot.is_none() {
+            self.cpu.slot = 0;
+        }
+        self.bundles += !faulted as u64;
+        if self.is_tracing() {
+            self.trace(|tracer| tracer.bundle(bundle_ip, retired))?;
+        }
+        if let Some(profiler) = &mut self.cpu.sample_profiler {
+            profiler.retire(retired, bundle_ip);
+        }
+        let after = self.memory.stats();
+        let rse_after = self.cpu.rse.spill_count() + self.cpu.rse.fill_count();
+        // A bundle takes a cycle, or what the timing model charges, more or
+        // less in chaos mode
+        let cycles = match &mut self.timing {
+            Some(timing) => timing.end_bundle(
+                taken,
+                mispredicts,
+                MemoryEvents {
+                    l1_misses: after.l1_misses.saturating_sub(before.l1_misses),
+                    l2_misses: after.l2_misses.saturating_sub(before.l2_misses),
+                    memory_reads: after.demand_misses.saturating_sub(before.demand_misses),
+                    rse_traffic: rse_after.saturating_sub(rse_before),
+                    transformed_reads: after
+                        .transformed_reads
+                        .saturating_sub(before.transformed_reads),
+                },
+            ),
+            None => 1,
+        };
+        if let (Some(timeline), Some(timing), Some(before)) =
+            (&self.timeline, &self.timing, stalls_before)
+        {
+            let stats = timing.stats();
+            let stalls = [
+                (
+                    "dependency",
+                    stats.dependency_stalls - before.dependency_stalls,
+                ),
+                ("cache", stats.cache_stalls - before.cache_stalls),
+                ("rse", stats.rse_stalls - before.rse_stalls),
+                ("branch", stats.branch_stalls - before.branch_stalls),
+                (
+                    "transform",
+                    stats.transform_stalls - before.transform_stalls,
+                ),
+            ];
+            let stalled: u64 = stalls.iter().map(|(_, cycles)| cycles).sum();
+            if stalled > 0 {
+                // The stall follows the cycles the bundle issued in
+                timeline.set_time(self.cpu.timer.read_itc() + cycles.saturating_sub(stalled));
+                let args: Vec<_> = stalls
+                    .into_iter()
+                    .filter(|(_, cycles)| *cycles > 0)
+                    .collect();
+                let name = format!("stall at {:#x}", bundle_ip);
+                timeline.record(Channel::Cpu, &name, Phase::Complete(stalled), &args);
+            }
+        }
+        // A bundle issuing alongside the previous one stays free
+        let cycles = match &mut self.latency {
+            Some(latency) if cycles > 0 => latency.jitter(cycles),
+            _ => cycles,
+        };
+        let events = PmuEvents {
+            cycles,
+            instructions: retired,
+            l1d_misses: after.l1_misses.saturating_sub(before.l1_misses),
+            l2_misses: after.l2_misses.saturating_sub(before.l2_misses),
+            l3_misses: after.demand_misses.saturating_sub(before.demand_misses),
+            branch_mispredicts: mispredicts,
+        };
+        let cpl = self.cpu.privilege_level();
+        self.cpu.pmu.count_events(cpl, &events);
+        self.cycles += cycles;
+        self.cpu.tick_timer(cycles)?;
+        Ok(stop)
+    }
+
+    /// Hand an event to the installed tracer
+    fn trace(
+        &mut self,
+        event: impl FnOnce(&mut dyn Tracer) -> std::io::Result<()>,
+    ) -> Result<(), EmulatorError> {
+        match &mut self.tracing {
+            Some(tracing) => event(tracing.tracer.as_mut()).map_err(|e| {
+                EmulatorError::ExecutionError(format!("Cannot write instruction trace: {}", e))
+            }),
+            None => Ok(()),
+        }
+    }
+
+    /// Run a host function in place of the guest function at `bundle_ip`
+    ///
+    /// The guest resumes at the return address in b0.
+    fn call_intercept(
+        &mut self,
+        function: HostFunction,
+        bundle_ip: u64,
+    ) -> Result<Option<StopReason>, EmulatorError> {
+        let args = ARG_REGS.map(|reg| self.cpu.gr[reg]);
+        let result = function(&mut self.memory, &args);
+        self.return_from_host(result, bundle_ip)
+    }
+
+    /// Run a variable service for the guest
+    fn call_variable_service(
+        &mut self,
+        service: VariableService,
+        bundle_ip: u64,
+    ) -> Result<Option<StopReason>, EmulatorError> {
+        let args = ARG_REGS.map(|reg| self.cpu.gr[reg]);
+        let result = self.variables.call(service, &mut self.memory, &args);
+        self.return_from_host(result, bundle_ip)
+    }
+
+    /// Run `ResetSystem(ResetType, ResetStatus, DataSize, ResetData)` for
+    /// the guest
+    ///
+    /// The call does not return: a cold or platform-specific reset type
+    /// resets the machine cold, a warm one warm, and a shutdown stops it
+    /// with exit status 0 for `EFI_SUCCESS` and 1 for any other status.
+    fn call_reset_service(&mut self) -> Result<Option<StopReason>, EmulatorError> {
+        let [reset_type, status, ..] = ARG_REGS.map(|reg| self.cpu.gr[reg]);
+        match reset_type {
+            EFI_RESET_WARM => self.reset(ResetKind::Warm)?,
+            EFI_RESET_SHUTDOWN => return Ok(Some(StopReason::Exited((status != 0) as i32))),
+            _ => self.reset(ResetKind::Cold)?,
+        }
+        Ok(None)
+    }
+
+    /// Serve the EFI `ResetSystem` runtime service at a guest function's
+    /// entry point, taking its arguments from r32 onwards
+    pub fn set_reset_service(&mut self, address: u64) {
+        self.reset_service = Some(address);
+    }
+
+    /// Finish a host function call: place its result and return to b0
+    fn return_from_host(
+        &mut self,
+        result: Result<u64, EmulatorError>,
+        bundle_ip: u64,
+    ) -> Result<Option<StopReason>, EmulatorError> {
+        if let Some(detector) = &mut self.livelock {
+            detector.progress();
+        }
+        self.collect_code_writes(bundle_ip);
+        self.collect_uninit_reads(bundle_ip, 0);
+        let stop = self
+            .check_panic_writes(bundle_ip)
+            .then_some(StopReason::Panic);
+        self.cpu.set_gr(RETURN_REG, result?)?;
+
+        self.cpu.ip = self.cpu.br[0];
+        if let Some(dispersal) = &mut self.dispersal {
+            dispersal.end_cycle();
+        }
+        self.cpu.tick_timer(1)?;
+        Ok(stop)
+    }
+
+    /// Replace a guest function with a host function
+    pub fn add_intercept(&mut self, intercept: Intercept) {
+        self.intercepts.add(intercept);
+    }
+
+    /// Remove the intercept at a guest function's entry point
+    pub fn remove_intercept(&mut self, address: u64) -> Option<Intercept> {
+        self.intercepts.remove(address)
+    }
+
+    /// Replace the firmware variable store
+    pub fn set_variable_store(&mut self, store: VariableStore) {
+        self.variables = store;
+    }
+
+    /// Firmware variables
+    pub fn variables(&self) -> &VariableStore {
+        &self.variables
+    }
+
+    /// Firmware variables, for changing them behind the guest's back
+    pub fn variables_mut(&mut self) -> &mut VariableStore {
+        &mut self.variables
+    }
+
+    /// Serve an EFI variable service at a guest function's entry point
+    ///
+    /// Like an intercept, the call takes its arguments from r32 onwards,
+    /// returns the `EFI_STATUS` in r8 and continues at b0.
+    pub fn add_variable_service(&mut self, service: VariableService, address: u64) {
+        self.variable_services.insert(address, service);
+    }
+
+    /// Fault on slots whose major opcode is not defined on the unit their
+    /// template assigns, instead of decoding them as that unit
+    pub fn set_strict_decode(&mut self, strict: bool) {
+        self.strict_decode = strict;
+    }
+
+    /// Attach a device with its MMIO window at page-aligned `base`
+    ///
+    /// The window is added to the physical memory map and the guest gets an
+    /// external interrupt whose info is the device id.
+    pub fn attach(
+        &mut self,
+        base: u64,
+        device: Box<dyn Device>,
+    ) -> Result<DeviceId, EmulatorError> {
+        let id = self.hotplug.next_id();
+        self.attach_device(id, base, device)?;
+        Ok(id)
+    }
+
+    /// Detach a device, handing it back
+    ///
+    /// The guest gets an external interrupt whose info is the device id
+    /// with [`HOTPLUG_DETACH`] set.
+    pub fn detach(&mut self, id: DeviceId) -> Result<Box<dyn Device>, EmulatorError> {
+        let (base, device) = self
+            .memory
+            .detach_device(id)
+            .ok_or_else(|| EmulatorError::ExecutionError(format!("No such device: {}", id)))?;
+        self.phys_map.remove(base);
+        self.record_device(id, "detach", &[("base", base)]);
+        self.cpu
+            .raise_interrupt(InterruptVector::ExtInt, id.0 as u64 | HOTPLUG_DETACH);
+        Ok(device)
+    }
+
+    /// Handle for attaching and detaching devices from other threads
+    pub fn device_handle(&self) -> DeviceHandle {
+        DeviceHandle::new(self.hotplug.clone())
+    }
+
+    /// Take the outcomes of device handle requests since the last call
+    pub fn take_device_events(&mut self) -> Vec<DeviceEvent> {
+        std::mem::take(&mut self.device_events)
+    }
+
+    /// Host end of a serial port from the configuration, by name
+    pub fn uart_port(&self, name: &str) -> Option<UartPort> {
+        self.uart_ports.get(name).cloned()
+    }
+
+    /// Attach a device under an id already handed out
+    fn attach_device(
+        &mut self,
+        id: DeviceId,
+        base: u64,
+        device: Box<dyn Device>,
+    ) -> Result<(), EmulatorError> {
+        self.map_device(id, base, device)?;
+        self.record_device(id, "attach", &[("base", base)]);
+        self.cpu
+            .raise_interrupt(InterruptVector::ExtInt, id.0 as u64);
+        Ok(())
+    }
+
+    /// Route a device's range and enter it in the memory map, without
+    /// telling the guest
+    fn map_device(
+        &mut self,
+        id: DeviceId,
+        base: u64,
+        device: Box<dyn Device>,
+    ) -> Result<(), EmulatorError> {
+        let size = device.size().next_multiple_of(EFI_PAGE_SIZE);
+        self.phys_map
+            .add(device.name(), base, size, RegionKind::Mmio)?;
+        if let Some(timeline) = &self.timeline {
+            timeline.name_device(id, device.name());
+        }
+        if let Err(e) = self.memory.attach_device(id, base, device) {
+            self.phys_map.remove(base);
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    /// Apply a request queued by a device handle
+    fn apply_hotplug(&mut self, request: HotplugRequest) {
+        let event = match request {
+            HotplugRequest::Attach(id, base, device) => {
+                match self.attach_device(id, base, device) {
+                    Ok(()) => DeviceEvent::Attached { id, base },
+                    Err(e) => DeviceEvent::Failed {
+                        id,
+                        error: e.to_string(),
+                    },
+                }
+            }
+            HotplugRequest::Detach(id) => match self.detach(id) {
+                Ok(device) => DeviceEvent::Detached { id, device },
+                Err(e) => DeviceEvent::Failed {
+                    id,
+                    error: e.to_string(),
+                },
+            },
+        };
+        self.device_events.push(event);
+    }
+
+    /// Install a panic hook
+    pub fn add_panic_hook(&mut self, hook: PanicHook) {
+        if let PanicTrigger::Write(addr) = hook.trigger {
+            self.memory.watch_writes(addr);
+        }
+        self.panic_detector.add(hook);
+    }
+
+    /// Set how many recently executed bundles a panic report includes
+    pub fn set_panic_trace_len(&mut self, len: usize) {
+        self.panic_detector.set_trace_len(len);
+    }
+
+    /// Report of the last panic hook that fired
+    pub fn panic_report(&self) -> Option<&PanicReport> {
+        self.panic_report.as_ref()
+    }
+
+    /// Hold timer interrupts and device hotplug back by seeded random
+    /// delays, pick cache and TLB victims at random and vary the cycles
+    /// each bundle takes, or go back to regular timing and the configured
+    /// replacement with `None`
+    ///
+    /// Events held back when chaos mode ends are delivered at once.
+    pub fn set_chaos(&mut self, config: Option<ChaosConfig>) {
+        self.chaos = config;
+        self.cpu.timer.set_jitter(config.map(|c| c.timer()));
+        self.memory
+            .set_replacement_seed(config.map(|c| c.cache_seed()));
+        self.cpu
+            .tlb
+            .set_replacement_seed(config.map(|c| c.tlb_seed()));
+        self.latency = config.map(|c| c.latency());
+        let held = match &mut self.deferred_hotplug {
+            Some(deferred) => deferred.take_all(),
+            None => Vec::new(),
+        };
+        self.deferred_hotplug = config.map(|c| Deferred::new(c.devices()));
+        for request in held {
+            self.apply_hotplug(request);
+        }
+    }
+
+    /// Chaos mode settings, if enabled
+    pub fn chaos(&self) -> Option<ChaosConfig> {
+        self.chaos
+    }
+
+    /// Stop with [`StopReason::Livelock`] when the guest loops without
+    /// making progress, or stop watching with `None`
+    pub fn set_livelock_detection(&mut self, config: Option<LivelockConfig>) {
+        self.livelock = config.map(LivelockDetector::new);
+    }
+
+    /// Report of the last livelock detected
+    pub fn livelock_report(&self) -> Option<&LivelockReport> {
+        self.livelock_report.as_ref()
+    }
+
+    /// Trace each executed instruction to `tracer`, or stop tracing with
+    /// `None`
+    ///
+    /// The tracer replaced is dropped, flushing any output it buffered.
+    pub fn set_tracer(&mut self, tracer: Option<Box<dyn Tracer>>) {
+        self.tracing = tracer.map(|tracer| Tracing {
+            tracer,
+            enabled: true,
+        });
+    }
+
+    /// Pause or resume the installed tracer
+    pub fn set_tracing(&mut self, enabled: bool) {
+        if let Some(tracing) = &mut self.tracing {
+            tracing.enabled = enabled;
+        }
+    }
+
+    /// Whether a tracer is installed and tracing
+    pub fn is_tracing(&self) -> bool {
+        self.tracing.as_ref().is_some_and(|tracing| tracing.enabled)
+    }
+
+    /// Record device, interrupt, system call and stall events on
+    /// `timeline`, or stop recording with `None`
+    ///
+    /// Devices already attached are named on the timeline.
+    pub fn set_timeline(&mut self, timeline: Option<Timeline>) {
+        if let Some(timeline) = &timeline {
+            for (id, _, device) in self.memory.devices().iter() {
+                timeline.name_device(id, device.name());
+            }
+            timeline.set_time(self.cpu.timer.read_itc());
+        }
+        self.cpu.timeline = timeline.clone();
+        self.memory.set_timeline(timeline.clone());
+        self.timeline = timeline;
+    }
+
+    /// Timeline events are recorded on, if any
+    pub fn timeline(&self) -> Option<&Timeline> {
+        self.timeline.as_ref()
+    }
+
+    /// Record an event on the channel of device `id`
+    fn record_device(&self, id: DeviceId, name: &str, args: &[(&'static str, u64)]) {
+        if let Some(timeline) = &self.timeline {
+            timeline.record(Channel::Device(id), name, Phase::Instant, args);
+        }
+    }
+
+    /// Log system calls strace-style, or stop logging with `None`
+    pub fn set_strace(&mut self, strace: Option<Strace>) {
+        self.strace = strace;
+    }
+
+    /// Take the writes to executable memory reported under [`WxPolicy::Log`]
+    ///
+    /// Each address is reported once, and reports past
+    /// [`MAX_WX_VIOLATIONS`] are dropped until these are taken.
+    pub fn take_wx_violations(&mut self) -> Vec<WxViolation> {
+        std::mem::take(&mut self.wx_violations)
+    }
+
+    /// Take the loads of uninitialized memory reported so far, by instruction
+    pub fn take_uninit_reads(&mut self) -> Vec<UninitRead> {
+        std::mem::take(&mut self.uninit_reads)
+            .into_values()
+            .collect()
+    }
+
+    /// Fetch and decode the bundle at `addr`
+    fn fetch_and_decode(&mut self, addr: u64) -> Result<DecodedBundle, EmulatorError> {
+        let mut data = [0u8; BUNDLE_SIZE as usize];
+        self.memory.read_bytes(addr, &mut data)?;
+        self.memory.note_code_fetch(addr);
+        decode_bundle(data)
+    }
+
+    /// Collect the bundles decoded ahead, about to execute `bundle_ip`
+    ///
+    /// Arriving anywhere but the predicted address discards the decodes
+    /// still in flight.
+    #[cfg(feature = "decode-ahead")]
+    fn collect_decoded(&mut self, bundle_ip: u64) {
+        self.decode_ahead.execute_at(bundle_ip);
+        for (addr, decoded) in self.decode_ahead.completed() {
+            self.memory.note_code_fetch(addr);
+            self.decode_cache
+                .entry(addr)
+                .or_insert_with(|| CachedBundle::new(decoded));
+        }
+    }
+
+    /// Queue the predicted path after the bundle at `bundle_ip`
+    ///
+    /// The path follows the first IP-relative branch the branch predictor
+    /// expects to be taken, and falls through otherwise.
+    #[cfg(feature = "decode-ahead")]
+    fn decode_ahead(&mut self, bundle_ip: u64, decoded: &DecodedBundle) {
+        let next = predicted_target(&self.cpu.branch_predictor, bundle_ip, decoded)
+            .unwrap_or(bundle_ip.wrapping_add(BUNDLE_SIZE));
+
+        // Refill in batches once half of the window has been consumed; a
+        // frontier outside the window belongs to the path not taken
+        let window = DECODE_AHEAD_DEPTH * BUNDLE_SIZE;
+        let start = self
+            .decode_ahead
+            .frontier()
+            .filter(|frontier| frontier.wrapping_sub(next) <= window)
+            .unwrap_or(next);
+        let window_end = next.wrapping_add(window);
+        if start.wrapping_sub(next) <= window / 2 {
+            let mut batch = Vec::new();
+            let mut addr = start;
+            while addr != window_end {
+                // Stop at the end of mapped memory; the fetch will fault on its own
+                let mut data = [0u8; BUNDLE_SIZE as usize];
+                if self.memory.peek_bytes(addr, &mut data).is_err() {
+                    break;
+                }
+                if !self.decode_cache.contains_key(&addr) {
+                    batch.push((addr, data));
+                }
+                addr = addr.wrapping_add(BUNDLE_SIZE);
+            }
+            self.decode_ahead.request(batch, addr);
+        }
+        self.decode_ahead.predict(next);
+    }
+
+    /// Drop decoded bundles for pages the guest wrote to
+    fn collect_code_writes(&mut self, bundle_ip: u64) {
+        for addr in self.memory.take_code_writes() {
+            let page = addr & !(PAGE_SIZE - 1);
+            self.invalidate_decoded(page, PAGE_SIZE);
+
+            if self.memory.wx_policy() == WxPolicy::Log
+                && self.wx_violations.len() < MAX_WX_VIOLATIONS
+                && !self.wx_violations.iter().any(|v| v.addr == addr)
+            {
+                self.wx_violations.push(WxViolation {
+                    addr,
+                    backtrace: vec![bundle_ip, self.cpu.br[0]],
+                });
+            }
+        }
+    }
+
+    /// Attribute uninitialized bytes loaded since the last call to the
+    /// instruction in `slot` of the bundle at `bundle_ip`
+    fn collect_uninit_reads(&mut self, bundle_ip: u64, slot: u8) {
+        for access in self.memory.take_uninit_reads() {
+            self.uninit_reads
+                .entry((bundle_ip, slot))
+                .or_insert_with(|| UninitRead {
+                    ip: bundle_ip,
+                    slot,
+                    addr: access.addr,
+                    len: access.len,
+                    count: 0,
+                    backtrace: vec![bundle_ip, self.cpu.br[0]],
+                })
+                .count += 1;
+        }
+    }
+
+    /// Fire the panic hook for a watched write made by the current bundle
+    fn check_panic_writes(&mut self, bundle_ip: u64) -> bool {
+        for addr in self.memory.take_watched_writes() {
+            if let Some(hook) = self.panic_detector.find(PanicTrigger::Write(addr)) {
+                self.panic_report =
+                    Some(
+                        self.panic_detector
+                            .report(hook, bundle_ip, &self.cpu, &self.memory),
+                    );
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Drop decoded bundles overlapping an address range
+    pub(crate) fn invalidate_decoded(&mut self, addr: u64, size: u64) {
+        #[cfg(feature = "decode-ahead")]
+        self.decode_ahead.invalidate();
+
+        let end = addr.saturating_add(size);
+        self.decode_cache
+            .retain(|&ip, _| ip.saturating_add(BUNDLE_SIZE) <= addr || ip >= end);
+    }
+
+    /// Execute a single instruction slot, reusing its cached translation
+    fn execute_slot(
+        &mut self,
+        itype: &InstructionType,
+        bits: u64,
+        translation: &Translation,
+    ) -> Result<Flow, EmulatorError> {
+        match execute_translated(&mut self.cpu, &mut self.memory, itype, bits, translation)? {
+            Effect::Continue => Ok(Flow::Continue),
+            Effect::Branch => Ok(Flow::Branch),
+            Effect::Return(slot) => {
+                self.return_slot = Some((self.cpu.ip, slot));
+                Ok(Flow::Branch)
+            }
+            Effect::Break(imm) => self.execute_break(imm),
+        }
+    }
+
+    /// Execute a break instruction
+    fn execute_break(&mut self, imm: u64) -> Result<Flow, EmulatorError> {
+        if imm != SYSCALL_BREAK_IMM {
+            return Ok(Flow::Stop(StopReason::Break(imm)));
+        }
+
+        if let Some(detector) = &mut self.livelock {
+            detector.progress();
+        }
+        let number = self.cpu.get_gr(SYSCALL_NUMBER_REG)?;
+        let params = SYSCALL_PARAM_REGS.map(|reg| self.cpu.gr[reg]);
+        // Lend guest memory to the handlers for the duration of the call
+        std::mem::swap(&mut self.memory, &mut self.cpu.memory);
+        let outcome = self.cpu.do_syscall(number);
+        std::mem::swap(&mut self.memory, &mut self.cpu.memory);
+        if let Some(strace) = &mut self.strace {
+            strace
+                .record(number, &params, &outcome, &self.memory)
+                .map_err(|e| {
+                    EmulatorError::ExecutionError(format!("Cannot write syscall trace: {}", e))
+                })?;
+        }
+        if let Some(timeline) = &self.timeline {
+            let name = SyscallNumber::try_from(number).map_or("unknown", |number| number.name());
+            let mut args = vec![("number", number)];
+            if let Ok(context) = &outcome {
+                args.push(("result", context.returns[0]));
+                if let Some(errno) = context.error {
+                    args.push(("errno", errno));
+                }
+            }
+            timeline.record(Channel::Syscalls, name, Phase::Instant, &args);
+        }
+        outcome?;
+
+        // Exit ends the run instead of returning to the guest
+        match self.cpu.exit_code {
+            Some(code) => Ok(Flow::Stop(StopReason::Exited(code))),
+            None => Ok(Flow::Continue),
+        }
+    }
+}
+
+/// Outcome of an instruction's register semantics
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Effect {
+    /// Continue with the next slot
+    Continue,
+    /// A break instruction with this immediate executed
+    Break(u64),
+    /// A branch was taken; the IP holds its target
+    Branch,
+    /// An rfi returned to the IP, resuming at this slot
+    Return(u8),
+}
+
+/// Apply the semantics of one instruction slot to the CPU state and memory
+///
+/// Breaks are returned to the caller, which decides whether they are
+/// system calls. Each slot goes through [`translate`], planning its whole
+/// effect before applying any of it; a slot that translates to nothing
+/// stops execution as unimplemented.
+pub(crate) fn execute_instruction(
+    cpu: &mut Cpu,
+    memory: &mut Memory,
+    itype: &InstructionType,
+    bits: u64,
+) -> Result<Effect, EmulatorError> {
+    execute_translated(cpu, memory, itype, bits, &Translation::new())
+}
+
+/// Like [`execute_instruction`], with the slot's translation kept in
+/// `translation` for later executions
+///
+/// The translation is made the first time the slot needs one.
+pub(crate) fn execute_translated(
+    cpu: &mut Cpu,
+    memory: &mut Memory,
+    itype: &InstructionType,
+    bits: u64,
+    translation: &Translation,
+) -> Result<Effect, EmulatorError> {
+    // The long immediate occupies the L slot and is consumed by the X slot
+    if matches!(itype, InstructionType::L(_)) {
+        return Ok(Effect::Continue);
+    }
+
+    // Translated instructions check their qualifying predicate themselves
+    if let Some(instruction) = translation.get_or_init(|| translate(itype, bits)) {
+        let change = instruction.plan(cpu, memory)?;
+        let effect = match (change.break_imm, change.ip, change.slot) {
+            (Some(imm), _, _) => Effect::Break(imm),
+            (None, Some(_), Some(slot)) => Effect::Return(slot),
+            (None, Some(_), None) => Effect::Branch,
+            (None, None, _) => Effect::Continue,
+        };
+        change.apply(cpu, memory)?;
+        return Ok(effect);
+    }
+
+    // Slots nothing implements are only an error if they execute
+    if !cpu.get_pr((bits & 0x3F) as usize)? {
+        return Ok(Effect::Continue);
+    }
+    Err(unimplemented(cpu, bits))
+}
+
+/// Error for a slot the emulator does not implement
+fn unimplemented(cpu: &Cpu, bits: u64) -> EmulatorError {
+    EmulatorError::ExecutionError(format!(
+        "Unimplemented instruction {:#013x} at {:#x}",
+        bits, cpu.ip
+    ))
+}
+
+/// Decode raw bundle bytes into slot types and raw slot bits
+pub(crate) fn decode_bundle(data: [u8; 16]) -> Result<DecodedBundle, EmulatorError> {
+    let mut bundle = Bundle::new(data)?;
+    bundle.decode()?;
+
+    Ok(DecodedBundle {
+        data,
+        slots: bundle
+            .instructions
+            .iter()
+            .enumerate()
+            .map(|(slot, instruction)| Ok((instruction.itype, bundle.slot(slot)?)))
+            .collect::<Result<_, EmulatorError>>()?,
+        stops: bundle.template().stops(),
+    })
+}
+
+/// Target of the first IP-relative branch in the bundle at `bundle_ip` that
+/// `predictor` expects to be taken
+///
+/// Indirect branches end the search, as their targets are only known once
+/// they execute.
+#[cfg(feature = "decode-ahead")]
+fn predicted_target(
+    predictor: &BranchPredictor,
+    bundle_ip: u64,
+    decoded: &DecodedBundle,
+) -> Option<u64> {
+    for (slot, (itype, bits)) in decoded.slots.iter().enumerate() {
+        let hints = match itype.unit() {
+            Unit::B | Unit::X => Completers::decode_branch(*bits),
+            _ => Completers::NONE,
+        };
+        if hints.is_empty() || !predictor.predict(bundle_ip, slot as u8, hints) {
+            continue;
+        }
+        // br.cond, the loop branches and br.call target25 (majors 4 and 5),
+        // and brl
+        let offset = match itype {
+            InstructionType::B(_) if matches!(major_opcode(*bits), 4 | 5) => {
+                sign_extend(imm21_check_a(*bits), 21) << 4
+            }
+            InstructionType::X(format) => match format.operation()? {
+                XOperation::Brl(offset) | XOperation::BrlCall { offset, .. } => offset,
+                _ => return None,
+            },
+            _ => return None,
+        };
+        return Some(bundle_ip.wrapping_add(offset as u64));
+    }
+    None
+}
+
+/// Major opcode of an instruction slot (bits 37-40)
+pub(crate) fn major_opcode(bits: u64) -> u64 {
+    (bits >> 37) & 0xF
+}
+
+/// x3 opcode extension (bits 33-35)
+pub(crate) fn x3(bits: u64) -> u64 {
+    (bits >> 33) & 0x7
+}
+
+/// x6 opcode extension (bits 27-32)
+pub(crate) fn x6(bits: u64) -> u64 {
+    (bits >> 27) & 0x3F
+}
+
+/// Target register r1 (bits 6-12)
+pub(crate) fn r1(bits: u64) -> u8 {
+    ((bits >> 6) & 0x7F) as u8
+}
+
+/// Source register r2 (bits 13-19)
+pub(crate) fn r2(bits: u64) -> u8 {
+    ((bits >> 13) & 0x7F) as u8
+}
+
+/// 21-bit bundle offset of chk.s (imm7a in bits 6-12, imm13c in bits 20-32,
+/// s in bit 36)
+pub(crate) fn imm21_check_s(bits: u64) -> u64 {
+    ((bits >> 6) & 0x7F) | (((bits >> 20) & 0x1FFF) << 7) | (((bits >> 36) & 1) << 20)
+}
+
+/// 21-bit bundle offset of chk.a and the IP-relative branches (imm20b in
+/// bits 13-32, s in bit 36)
+pub(crate) fn imm21_check_a(bits: u64) -> u64 {
+    ((bits >> 13) & 0xFFFFF) | (((bits >> 36) & 1) << 20)
+}
+
+/// Sign-extend the low `width` bits of `value`
+pub(crate) fn sign_extend(value: u64, width: u32) -> i64 {
+    ((value << (64 - width)) as i64) >> (64 - width)
+}
+
+/// 21-bit immediate of break/nop (imm20a in bits 6-25, i in bit 36)
+pub(crate) fn imm21(bits: u64) -> u64 {
+    ((bits >> 6) & 0xFFFFF) | (((bits >> 36) & 1) << 20)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chaos::ChaosConfig;
+    use crate::cpu::dispersal::MachineModel;
+    use crate::cpu::registers::ar::AR;
+    use crate::cpu::registers::{CRIndex, FloatRegister};
+    use crate::cpu::sample_profile::SampleProfiler;
+    use crate::cpu::strace::StraceOutput;
+    use crate::cpu::syscall::SyscallNumber;
+    use crate::cpu::timer::TimerMode;
+    use crate::cpu::PSR_RI_SHIFT;
+    use crate::firmware::variables::{EfiStatus, Guid};
+    use crate::intercept::Builtin;
+    use crate::memory::uninit::{UninitPolicy, POISON};
+    use crate::test_util::{encode_break_nop, encode_bundle, encode_mii, nop};
+    use std::io::Write;
+    use std::sync::{Arc, Mutex};
+
+    const BASE: u64 = 0x10000;
+
+    /// Encode chk.s with the given major opcode, checking GR `reg`, with a
+    /// recovery offset in bundles
+    fn encode_chk_s(major: u64, reg: u64, offset: i64) -> u64 {
+        let imm = offset as u64 & 0x1FFFFF;
+        (major << 37)
+            | (((imm >> 20) & 1) << 36)
+            | (1 << 33)
+            | (((imm >> 7) & 0x1FFF) << 20)
+            | (reg << 13)
+            | ((imm & 0x7F) << 6)
+    }
+
+    /// Encode chk.a.nc or chk.a.clr on GR `reg`, with a recovery offset in
+    /// bundles
+    fn encode_chk_a(clear: bool, reg: u64, offset: i64) -> u64 {
+        let imm = offset as u64 & 0x1FFFFF;
+        (((imm >> 20) & 1) << 36)
+            | ((4 | clear as u64) << 33)
+            | ((imm & 0xFFFFF) << 13)
+            | (reg << 6)
+    }
+
+    fn setup(bundles: &[[u8; 16]]) -> Emulator {
+        let mut emu = Emulator::new();
+        let image: Vec<u8> = bundles.iter().flatten().copied().collect();
+        emu.load_flat_image(BASE, &image, BASE).unwrap();
+        emu
+    }
+
+    #[test]
+    fn test_exit_status() {
+        let syscall = encode_break_nop(0, 0x00, SYSCALL_BREAK_IMM);
+        let mut emu = setup(&[
+            encode_mii([nop(), nop(), nop()]),
+            encode_mii([syscall, nop(), nop()]),
+        ]);
+        emu.cpu.gr[SYSCALL_NUMBER_REG] = SyscallNumber::Exit as u64;
+        emu.cpu.gr[32] = 42;
+
+        assert_eq!(emu.run().unwrap(), StopReason::Exited(42));
+        assert_eq!(emu.cpu.exit_code, Some(42));
+        assert_eq!(emu.cpu.ip, BASE + 2 * BUNDLE_SIZE);
+    }
+
+    #[test]
+    fn test_syscall_continues() {
+        let syscall = encode_break_nop(0, 0x00, SYSCALL_BREAK_IMM);
+        let stop = encode_break_nop(0, 0x00, 0x1234);
+        let mut emu = setup(&[encode_mii([syscall, stop, nop()])]);
+        emu.cpu.gr[SYSCALL_NUMBER_REG] = SyscallNumber::GetPid as u64;
+
+        assert_eq!(emu.run().unwrap(), StopReason::Break(0x1234));
+        assert_eq!(emu.cpu.gr[8], 1);
+    }
+
+    #[test]
+    fn test_strace() {
+        /// Writer whose output the test can read back
+        #[derive(Clone, Default)]
+        struct Shared(Arc<Mutex<Vec<u8>>>);
+
+        impl Write for Shared {
+            fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+                self.0.lock().unwrap().write(buf)
+            }
+
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let syscall = encode_break_nop(0, 0x00, SYSCALL_BREAK_IMM);
+        let stop = encode_break_nop(0, 0x00, 0x1234);
+        let mut emu = setup(&[
+            encode_mii([syscall, nop(), nop()]),
+            encode_mii([syscall, stop, nop()]),
+        ]);
+        emu.memory
+            .load_image(0x1000, 0x1000, b"hi\n", Permissions::Read)
+            .unwrap();
+        let output = Shared::default();
+        let strace = Strace::new(StraceOutput::Writer(Box::new(output.clone())))
+            .only(["write"])
+            .unwrap();
+        emu.set_strace(Some(strace));
+
+        // write(1, "hi\n", 3) is logged, getpid is filtered out
+        emu.cpu.gr[SYSCALL_NUMBER_REG] = SyscallNumber::Write as u64;
+        emu.cpu.gr[32..35].copy_from_slice(&[1, 0x1000, 3]);
+        emu.step().unwrap();
+        emu.cpu.gr[SYSCALL_NUMBER_REG] = SyscallNumber::GetPid as u64;
+        assert_eq!(emu.run().unwrap(), StopReason::Break(0x1234));
+
+        let log = String::from_utf8(output.0.lock().unwrap().clone()).unwrap();
+        assert_eq!(log, "write(1, \"hi\\n\", 3) = 3\n");
+    }
+
+    #[test]
+    fn test_syscall_guest_memory() {
+        let syscall = encode_break_nop(0, 0x00, SYSCALL_BREAK_IMM);
+        let stop = encode_break_nop(0, 0x00, 0x1234);
+        let mut emu = setup(&[encode_mii([syscall, stop, nop()])]);
+        emu.memory
+            .map(0x1000, 0x1000, Permissions::ReadWrite)
+            .unwrap();
+        emu.cpu.gr[SYSCALL_NUMBER_REG] = SyscallNumber::Uname as u64;
+        emu.cpu.gr[32] = 0x1000;
+
+        assert_eq!(emu.run().unwrap(), StopReason::Break(0x1234));
+        assert_eq!(emu.cpu.gr[8], 0);
+        assert_eq!(emu.memory.peek_c_string(0x1000, 65).unwrap(), "Linux");
+    }
+
+    #[test]
+    fn test_predicated_break() {
+        let skipped = encode_break_nop(1, 0x00, 0x1);
+        let taken = encode_break_nop(0, 0x00, 0x2);
+        let mut emu = setup(&[encode_mii([skipped, taken, nop()])]);
+
+        assert_eq!(emu.run().unwrap(), StopReason::Break(0x2));
+    }
+
+    #[test]
+    fn test_unaligned_ip() {
+        let mut emu = setup(&[encode_mii([nop(), nop(), nop()])]);
+        emu.cpu.ip = BASE + 4;
+        assert!(matches!(emu.step(), Err(EmulatorError::InvalidAlignment)));
+    }
+
+    #[test]
+    fn test_code_write_invalidates_decoded_bundle() {
+        let mut emu = setup(&[encode_mii([nop(), nop(), nop()])]);
+        assert_eq!(emu.step().unwrap(), None);
+        assert!(emu.decode_cache.contains_key(&BASE));
+
+        // Patch the bundle into a break and run it again
+        let patched = encode_mii([encode_break_nop(0, 0x00, 0x42), nop(), nop()]);
+        emu.memory.write_bytes(BASE, &patched).unwrap();
+        emu.cpu.ip = BASE;
+        assert_eq!(emu.step().unwrap(), Some(StopReason::Break(0x42)));
+        assert!(emu.take_wx_violations().is_empty());
+    }
+
+    #[test]
+    fn test_translations_cached() {
+        // add r1=r2,r3 goes through translate, like the nops
+        let add = (8 << 37) | (3 << 20) | (2 << 13) | (1 << 6);
+        let mut emu = setup(&[encode_mii([nop(), add, nop()])]);
+        emu.cpu.gr[2] = 5;
+        emu.cpu.gr[3] = 7;
+        assert_eq!(emu.step().unwrap(), None);
+        assert_eq!(emu.cpu.gr[1], 12);
+        let cached = Arc::clone(&emu.decode_cache[&BASE]);
+        assert!(cached.translations[0].get().unwrap().is_some());
+        assert!(cached.translations[1].get().unwrap().is_some());
+
+        // Executing again reuses the translation, with the new operands
+        emu.cpu.gr[2] = 1;
+        emu.cpu.ip = BASE;
+        assert_eq!(emu.step().unwrap(), None);
+        assert_eq!(emu.cpu.gr[1], 8);
+        assert!(Arc::ptr_eq(&cached, &emu.decode_cache[&BASE]));
+
+        // A code write drops it with the bundle
+        let sub = add | (1 << 29) | (1 << 27);
+        emu.memory
+            .write_bytes(BASE, &encode_mii([nop(), sub, nop()]))
+            .unwrap();
+        emu.cpu.ip = BASE;
+        assert_eq!(emu.step().unwrap(), None);
+        assert_eq!(emu.cpu.gr[1], (1u64).wrapping_sub(7));
+        assert!(!Arc::ptr_eq(&cached, &emu.decode_cache[&BASE]));
+    }
+
+    #[test]
+    fn test_rfi_resumes_interrupted_slot() {
+        const HANDLER: u64 = BASE + 16;
+        let rfi = 0x08 << 27;
+        let interrupted = [
+            encode_break_nop(0, 0x00, 0x1),
+            encode_break_nop(0, 0x00, 0x2),
+            nop(),
+        ];
+        let mut emu = setup(&[
+            encode_mii(interrupted),
+            encode_bundle(0x10, [nop(), nop(), rfi]),
+        ]);
+
+        // Interrupted in slot 1 with a two-register frame, which the
+        // handler covers before allocating its own
+        let cpu = &mut emu.cpu;
+        cpu.alloc_frame(&mut emu.memory, 2, 2, 0).unwrap();
+        cpu.gr[32] = 0x32;
+        cpu.gr[33] = 0x33;
+        cpu.system_regs.cr.set(PSRFlags::IC, true);
+        cpu.register_interrupt_handler(InterruptVector::ExtInt, HANDLER, 0)
+            .unwrap();
+        cpu.set_interrupts_enabled(true);
+        cpu.slot = 1;
+        cpu.raise_interrupt(InterruptVector::ExtInt, 0);
+        cpu.ip = cpu.check_interrupts().unwrap();
+        cpu.cover(&mut emu.memory).unwrap();
+        cpu.alloc_frame(&mut emu.memory, 4, 4, 0).unwrap();
+        cpu.gr[32] = 0xdead;
+
+        assert_eq!(emu.step().unwrap(), None);
+        assert_eq!((emu.cpu.ip, emu.cpu.slot), (BASE, 1));
+        assert_eq!(emu.cpu.cfm, 2 | 2 << 7);
+        assert_eq!(emu.cpu.gr[32..34], [0x32, 0x33]);
+        assert!(emu.cpu.system_regs.cr.contains(PSRFlags::IC));
+        assert_eq!(emu.cpu.privilege_level(), crate::cpu::USER_PRIVILEGE_LEVEL);
+
+        // Slot 0 already executed before the interruption
+        assert_eq!(emu.step().unwrap(), Some(StopReason::Break(0x2)));
+    }
+
+    #[test]
+    fn test_long_instruction_slots() {
+        const MLX: u8 = 4;
+        const HANDLER: u64 = BASE + 16;
+        let rfi = 0x08 << 27;
+        let brk = encode_break_nop(0, 0x00, 0x7);
+        let mut emu = setup(&[
+            encode_bundle(MLX, [nop(), 0, brk]),
+            encode_bundle(0x10, [nop(), nop(), rfi]),
+        ]);
+
+        // The L+X instruction executes as slot 1
+        assert_eq!(emu.run().unwrap(), StopReason::Break(0x7));
+        assert_eq!(emu.cpu.slot, 1);
+
+        // Interrupted there, it restarts from slot 1 while ISR.ei reports
+        // the X slot
+        let cpu = &mut emu.cpu;
+        cpu.system_regs.cr.set(PSRFlags::IC, true);
+        cpu.register_interrupt_handler(InterruptVector::ExtInt, HANDLER, 0)
+            .unwrap();
+        cpu.set_interrupts_enabled(true);
+        cpu.ip = BASE;
+        cpu.raise_interrupt(InterruptVector::ExtInt, 0);
+        cpu.ip = cpu.check_interrupts().unwrap();
+        let ipsr = cpu.read_cr(CRIndex::IPSR);
+        assert_eq!(ipsr >> PSR_RI_SHIFT & 3, 1);
+        assert_eq!(cpu.read_cr(CRIndex::ISR) >> 41 & 3, 2);
+
+        assert_eq!(emu.step().unwrap(), None);
+        assert_eq!((emu.cpu.ip, emu.cpu.slot), (BASE, 1));
+        assert_eq!(emu.step().unwrap(), Some(StopReason::Break(0x7)));
+
+        // Slot 2 of an MLX bundle holds no instruction to return to
+        let cpu = &mut emu.cpu;
+        cpu.set_privilege_level(0);
+        cpu.write_cr(
+            CRIndex::IPSR,
+            (ipsr & !(3 << PSR_RI_SHIFT)) | 2 << PSR_RI_SHIFT,
+        )
+        .unwrap();
+        cpu.ip = HANDLER;
+        assert_eq!(emu.step().unwrap(), None);
+        assert!(matches!(
+            emu.step(),
+            Err(EmulatorError::ExecutionError(msg)) if msg.contains("slot 2 of the MLX")
+        ));
+    }
+
+    #[test]
+    fn test_wx_log_and_fault() {
+        let mut emu = setup(&[encode_mii([nop(), nop(), nop()])]);
+        emu.memory.set_wx_policy(WxPolicy::Log);
+        emu.cpu.br[0] = 0x1234;
+
+        // Data beside code that has not run is not code
+        emu.memory.write_u8(BASE + 0x100, 0xAA).unwrap();
+        emu.step().unwrap();
+        assert!(emu.take_wx_violations().is_empty());
+
+        // Writes made outside of guest execution are reported on the next
+        // step, once per address
+        emu.memory.write_u8(BASE + 0x100, 0xAA).unwrap();
+        emu.cpu.ip = BASE;
+        emu.step().unwrap();
+        emu.memory.write_u8(BASE + 0x100, 0xAA).unwrap();
+        emu.cpu.ip = BASE;
+        emu.step().unwrap();
+        let violations = emu.take_wx_violations();
+        assert_eq!(violations.len(), 1);
+        assert_eq!(violations[0].addr, BASE + 0x100);
+        assert_eq!(violations[0].backtrace, vec![BASE, 0x1234]);
+
+        emu.memory.set_wx_policy(WxPolicy::Fault);
+        assert!(emu.memory.write_u8(BASE + 0x100, 0xBB).is_err());
+    }
+
+    #[cfg(feature = "decode-ahead")]
+    #[test]
+    fn test_decode_ahead_sees_code_writes() {
+        let mut emu = setup(&[
+            encode_mii([nop(), nop(), nop()]),
+            encode_mii([nop(), nop(), nop()]),
+            encode_mii([encode_break_nop(0, 0x00, 0x1), nop(), nop()]),
+        ]);
+        assert_eq!(emu.step().unwrap(), None);
+
+        // Give the worker time to decode the old bundle, then patch it
+        std::thread::sleep(std::time::Duration::from_millis(20));
+        let patched = encode_mii([encode_break_nop(0, 0x00, 0x42), nop(), nop()]);
+        emu.memory
+            .write_bytes(BASE + BUNDLE_SIZE, &patched)
+            .unwrap();
+        assert_eq!(emu.run().unwrap(), StopReason::Break(0x42));
+    }
+
+    /// Image whose first bundle branches, if `p6`, to a break at `target`
+    /// past the decode-ahead window, with nops in between
+    #[cfg(feature = "decode-ahead")]
+    fn branch_past_window(target: u64) -> Emulator {
+        use crate::asm::BundleBuilder;
+
+        let branch = BundleBuilder::new()
+            .at(BASE)
+            .insn("nop.m 0x0")
+            .insn("nop.i 0x0")
+            .insn(&format!("(p6) br.cond.sptk.few {target:#x} ;;"))
+            .build()
+            .unwrap();
+        let count = (target - BASE) / BUNDLE_SIZE;
+        let mut bundles = vec![branch];
+        bundles.extend((1..count).map(|_| encode_mii([nop(), nop(), nop()])));
+        bundles.push(encode_mii([encode_break_nop(0, 0x00, 0x42), nop(), nop()]));
+        setup(&bundles)
+    }
+
+    /// Collect decodes at `addr` until the bundle there has been decoded
+    #[cfg(feature = "decode-ahead")]
+    fn wait_for_decode(emu: &mut Emulator, addr: u64) -> bool {
+        let deadline = std::time::Instant::now() + std::time::Duration::from_secs(5);
+        while !emu.decode_cache.contains_key(&addr) && std::time::Instant::now() < deadline {
+            emu.collect_decoded(addr);
+            std::thread::yield_now();
+        }
+        emu.decode_cache.contains_key(&addr)
+    }
+
+    #[cfg(feature = "decode-ahead")]
+    #[test]
+    fn test_decode_ahead_follows_predicted_branch() {
+        let target = BASE + 2 * DECODE_AHEAD_DEPTH * BUNDLE_SIZE;
+        let mut emu = branch_past_window(target);
+        emu.cpu.set_pr(6, true).unwrap();
+        assert_eq!(emu.step().unwrap(), None);
+        assert_eq!(emu.cpu.ip, target);
+
+        // The target was queued before the branch executed
+        assert!(wait_for_decode(&mut emu, target));
+        assert_eq!(emu.run().unwrap(), StopReason::Break(0x42));
+    }
+
+    #[cfg(feature = "decode-ahead")]
+    #[test]
+    fn test_decode_ahead_drops_mispredicted_branch() {
+        let target = BASE + 2 * DECODE_AHEAD_DEPTH * BUNDLE_SIZE;
+        let mut emu = branch_past_window(target);
+        emu.cpu.set_pr(6, false).unwrap();
+        assert_eq!(emu.step().unwrap(), None);
+        assert_eq!(emu.cpu.ip, BASE + BUNDLE_SIZE);
+
+        // Falling through discards the decode of the target, finished or not
+        std::thread::sleep(std::time::Duration::from_millis(20));
+        assert_eq!(emu.step().unwrap(), None);
+        assert!(wait_for_decode(&mut emu, BASE + 2 * BUNDLE_SIZE));
+        assert!(!emu.decode_cache.contains_key(&target));
+    }
+
+    #[test]
+    fn test_with_config() {
+        let path =
+            std::env::temp_dir().join(format!("rust-ia64-config-{}.bin", std::process::id()));
+        let stop = encode_break_nop(0, 0x00, 0x7);
+        std::fs::write(&path, encode_mii([nop(), stop, nop()])).unwrap();
+
+        let mut config = MachineConfig::parse(&format!(
+            "entry = {BASE}\nwx_policy = \"log\"\n\
+             [[memory]]\nname = \"text\"\nbase = {BASE}\nsize = 4096\npermissions = \"rx\"\n\
+             kind = \"firmware-code\"\n"
+        ))
+        .unwrap();
+        config.memory[0].image = Some(path.clone());
+        let flash_path = path.with_extension("flash");
+        std::fs::write(&flash_path, 0x1234_5678u32.to_le_bytes()).unwrap();
+        config.flash.push(crate::config::FlashConfig {
+            name: None,
+            base: 0x100000,
+            file: flash_path.clone(),
+            size: Some(0x10000),
+            block_size: 0x1000,
+            write_protect: false,
+        });
+        let mut emu = Machine::with_config(&config).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        std::fs::remove_file(&flash_path).unwrap();
+
+        assert_eq!(emu.memory.read_u32(0x100000).unwrap(), 0x1234_5678);
+        assert_eq!(emu.memory.read_u32(0x100004).unwrap(), 0xFFFF_FFFF);
+        let flash = emu.phys_map.find(0x100000).unwrap();
+        assert_eq!(
+            (flash.name.as_str(), flash.kind),
+            ("flash[0]", RegionKind::Mmio)
+        );
+        assert_eq!(emu.cpu.interrupts_raised(), 0);
+        config.flash.clear();
+
+        assert_eq!(emu.memory.wx_policy(), WxPolicy::Log);
+        assert_eq!(emu.memory.region_name(BASE), Some("text"));
+        let region = emu.phys_map.find(BASE).unwrap();
+        assert_eq!(
+            (region.name.as_str(), region.kind),
+            ("text", RegionKind::FirmwareCode)
+        );
+        assert_eq!(emu.run().unwrap(), StopReason::Break(0x7));
+
+        config.memory[0].image = Some(path);
+        assert!(matches!(
+            Machine::with_config(&config),
+            Err(EmulatorError::ConfigError(msg)) if msg.starts_with("memory[0].image:")
+        ));
+    }
+
+    #[test]
+    fn test_panic_hook() {
+        let mut emu = setup(&[
+            encode_mii([nop(), nop(), nop()]),
+            encode_mii([nop(), nop(), nop()]),
+            encode_mii([encode_break_nop(0, 0x00, 0x1), nop(), nop()]),
+        ]);
+        emu.memory
+            .load_image(0x80000, 0x1000, b"bad state\0", Permissions::Read)
+            .unwrap();
+        emu.cpu.gr[32] = 0x80000;
+        emu.add_panic_hook(PanicHook::function("panic", BASE + 2 * BUNDLE_SIZE));
+
+        // The hooked bundle does not execute
+        assert_eq!(emu.run().unwrap(), StopReason::Panic);
+        assert_eq!(emu.cpu.ip, BASE + 2 * BUNDLE_SIZE);
+
+        let report = emu.panic_report().unwrap();
+        assert_eq!(report.hook, "panic");
+        assert_eq!(report.message.as_deref(), Some("bad state"));
+        assert_eq!(report.trace, vec![BASE, BASE + BUNDLE_SIZE]);
+    }
+
+    #[test]
+    fn test_livelock() {
+        let mut emu = setup(&[encode_mii([nop(), nop(), nop()])]);
+        emu.set_livelock_detection(Some(LivelockConfig {
+            bundles: 4,
+            window: 0x100,
+        }));
+        // Branches back to the same bundle, as a tight loop would
+        let spin = |emu: &mut Emulator| {
+            let stop = emu.step().unwrap();
+            emu.cpu.ip = BASE;
+            stop
+        };
+
+        for _ in 0..3 {
+            assert_eq!(spin(&mut emu), None);
+        }
+        // A store counts as progress
+        emu.memory.write_u64(BASE + 0x100, 1).unwrap();
+        for _ in 0..3 {
+            assert_eq!(spin(&mut emu), None);
+        }
+        assert_eq!(spin(&mut emu), Some(StopReason::Livelock));
+        let report = emu.livelock_report().unwrap();
+        assert_eq!((report.start, report.end, report.bundles), (BASE, BASE, 4));
+
+        // Resuming watches for the next livelock
+        assert_eq!(spin(&mut emu), None);
+    }
+
+    #[test]
+    fn test_break_units() {
+        const MIB: u8 = 0x10;
+        const MMF: u8 = 0x0E;
+        const MLX: u8 = 4;
+        let nop_b = 2 << 37;
+        let imm = 0x1F_FFFF;
+        let brk = encode_break_nop(0, 0x00, imm);
+
+        // Every unit's break carries the same 21-bit immediate
+        for bundle in [
+            encode_mii([brk, nop(), nop()]),
+            encode_mii([nop(), brk, nop()]),
+            encode_bundle(MIB, [nop(), nop(), brk]),
+            encode_bundle(MMF, [nop(), nop(), brk]),
+        ] {
+            let mut emu = setup(&[bundle]);
+            assert_eq!(emu.run().unwrap(), StopReason::Break(imm));
+        }
+
+        // break.x takes the upper 41 bits of its 62-bit immediate from the L slot
+        let mut emu = setup(&[encode_bundle(MLX, [nop(), 0x155, brk])]);
+        assert_eq!(emu.run().unwrap(), StopReason::Break((0x155 << 21) | imm));
+
+        // nop.b has its own major opcode; x6 0x01 is no nop on a B unit
+        let mut emu = setup(&[
+            encode_bundle(MIB, [nop(), nop(), nop_b]),
+            encode_bundle(MIB, [nop(), nop(), nop()]),
+        ]);
+        assert!(emu.run().is_err());
+        assert_eq!(emu.cpu.ip, BASE + BUNDLE_SIZE);
+    }
+
+    #[test]
+    fn test_long_instructions() {
+        const MLX: u8 = 4;
+        const L_MASK: u64 = (1 << 41) - 1;
+        let movl = |r1: u64, imm: u64| {
+            let x = (6 << 37)
+                | ((imm >> 63) << 36)
+                | (((imm >> 7) & 0x1FF) << 27)
+                | (((imm >> 16) & 0x1F) << 22)
+                | (((imm >> 21) & 1) << 21)
+                | ((imm & 0x7F) << 13)
+                | (r1 << 6);
+            encode_bundle(MLX, [nop(), (imm >> 22) & L_MASK, x])
+        };
+        // brl.cond (major C) or brl.call b1 (major D), `bundles` away
+        let brl = |major: u64, b1: u64, bundles: i64| {
+            let imm = bundles as u64 & ((1 << 60) - 1);
+            let x = (major << 37) | ((imm >> 59) << 36) | ((imm & 0xFFFFF) << 13) | (b1 << 6);
+            encode_bundle(MLX, [nop(), ((imm >> 20) << 2) & L_MASK, x])
+        };
+        let hint_x = (1 << 26) | (0x01 << 27);
+        let stop = |imm| encode_mii([encode_break_nop(0, 0x00, imm), nop(), nop()]);
+
+        let mut emu = setup(&[
+            movl(8, 0x8123_4567_89AB_CDEF),
+            brl(0xC, 0, 2),
+            stop(0x1),
+            brl(0xD, 1, 2),
+            stop(0x2),
+            encode_bundle(MLX, [nop(), 0, hint_x]),
+            brl(0xC, 0, -2),
+        ]);
+        emu.cpu.cfm = 4 | (2 << 7);
+        assert_eq!(emu.run().unwrap(), StopReason::Break(0x2));
+        assert_eq!(emu.cpu.gr[8], 0x8123_4567_89AB_CDEF);
+        assert_eq!(emu.cpu.br[1], BASE + 4 * BUNDLE_SIZE);
+        // The call left the callee the caller's two outputs
+        assert_eq!(emu.cpu.cfm, 2);
+        // AR.PFS holds the caller's frame and privilege level
+        assert_eq!(emu.cpu.pfs, 4 | (2 << 7) | (3 << 62));
+    }
+
+    #[test]
+    fn test_call_and_return() {
+        const MIB: u8 = 0x10;
+        let alloc = |r1: u64, sof: u64, sol: u64| {
+            (1 << 37) | (6 << 33) | (sol << 20) | (sof << 13) | (r1 << 6)
+        };
+        let add = |r1: u64, r2: u64, r3: u64| (8 << 37) | (r3 << 20) | (r2 << 13) | (r1 << 6);
+        // br.call b0 to the function two bundles on, and br.ret b0
+        let call = (5 << 37) | (2 << 13);
+        let ret = (0x21 << 27) | (4 << 6);
+        let mut emu = setup(&[
+            encode_bundle(MIB, [alloc(32, 4, 2), add(33, 3, 0), call]),
+            encode_mii([encode_break_nop(0, 0x00, 0x1), nop(), nop()]),
+            encode_bundle(MIB, [alloc(34, 3, 2), add(8, 32, 32), ret]),
+        ]);
+        emu.cpu.gr[3] = 0x33;
+        emu.cpu.gr[34] = 5;
+        emu.cpu.write_ar(AR::EC, 3).unwrap();
+
+        // The call saves the caller's frame, epilog count and privilege
+        // level in AR.PFS, and the callee's r32 is the caller's r34
+        assert_eq!(emu.step().unwrap(), None);
+        assert_eq!(emu.cpu.ip, BASE + 2 * BUNDLE_SIZE);
+        assert_eq!(emu.cpu.br[0], BASE + BUNDLE_SIZE);
+        assert_eq!(emu.cpu.pfs, 4 | (2 << 7) | (3 << 52) | (3 << 62));
+        assert_eq!(emu.cpu.cfm, 2);
+        emu.cpu.write_ar(AR::EC, 0).unwrap();
+
+        // The return restores all of it
+        assert_eq!(emu.run().unwrap(), StopReason::Break(0x1));
+        assert_eq!(emu.cpu.gr[8], 10);
+        assert_eq!(emu.cpu.cfm, 4 | (2 << 7));
+        assert_eq!(emu.cpu.get_gr(33).unwrap(), 0x33);
+        assert_eq!(emu.cpu.read_ar(AR::EC).unwrap(), 3);
+
+        // br.call b1=b2 goes through a branch register
+        let indirect = (1 << 37) | (6 << 13) | (1 << 6);
+        let mut emu = setup(&[
+            encode_bundle(MIB, [nop(), nop(), indirect]),
+            encode_mii([encode_break_nop(0, 0x00, 0x1), nop(), nop()]),
+            encode_bundle(MIB, [nop(), nop(), (0x21 << 27) | (1 << 13) | (4 << 6)]),
+        ]);
+        emu.cpu.br[6] = BASE + 2 * BUNDLE_SIZE;
+        assert_eq!(emu.run().unwrap(), StopReason::Break(0x1));
+        assert_eq!(emu.cpu.br[1], BASE + BUNDLE_SIZE);
+    }
+
+    #[test]
+    fn test_trace() {
+        use crate::asm::BundleBuilder;
+        use crate::trace::{RegisterWrite, TraceRing};
+
+        const CALLEE: u64 = BASE + 2 * BUNDLE_SIZE;
+        let image: Vec<u8> = [
+            BundleBuilder::new()
+                .insn("adds r8 = 5, r0")
+                .insn("(p6) adds r9 = 1, r0")
+                .insn("mov r14 = ip ;;"),
+            BundleBuilder::new()
+                .insn("nop.m 0x0")
+                .insn("nop.i 0x0")
+                .insn(&format!("br.call.sptk.many b0 = {:#x} ;;", CALLEE)),
+            BundleBuilder::new()
+                .insn("break.m 0x1")
+                .insn("nop.i 0x0")
+                .insn("nop.i 0x0 ;;"),
+        ]
+        .into_iter()
+        .enumerate()
+        .flat_map(|(i, bundle)| bundle.at(BASE + i as u64 * BUNDLE_SIZE).build().unwrap())
+        .collect();
+        let mut emu = Emulator::new();
+        emu.load_flat_image(BASE, &image, BASE).unwrap();
+        assert!(!emu.is_tracing());
+        let ring = TraceRing::new(16);
+        emu.set_tracer(Some(Box::new(ring.clone())));
+        assert!(emu.is_tracing());
+        emu.step().unwrap();
+
+        let records = ring.records();
+        let lines: Vec<String> = records.iter().map(ToString::to_string).collect();
+        assert_eq!(
+            lines,
+            [
+                format!("{:#018x}:0 adds r8 = 5, r0  r8=0x5", BASE),
+                format!("{:#018x}:1 (p6) adds r9 = 1, r0  [p6 false]", BASE),
+                format!("{:#018x}:2 mov r14 = ip  r14={:#x}", BASE, BASE),
+            ]
+        );
+
+        // The call writes b0; nothing else changed
+        emu.step().unwrap();
+        let records = ring.records();
+        assert_eq!(
+            records[5].writes,
+            [RegisterWrite::Br {
+                reg: 0,
+                value: BASE + 2 * BUNDLE_SIZE
+            }]
+        );
+
+        // Paused, the tracer sees nothing
+        ring.clear();
+        emu.set_tracing(false);
+        assert_eq!(emu.run().unwrap(), StopReason::Break(0x1));
+        assert!(ring.records().is_empty());
+    }
+
+    #[test]
+    fn test_sample_profile() {
+        use crate::asm::BundleBuilder;
+
+        const CALLEE: u64 = BASE + 2 * BUNDLE_SIZE;
+        let image: Vec<u8> = [
+            BundleBuilder::new()
+                .insn("nop.m 0x0")
+                .insn("nop.i 0x0")
+                .insn(&format!("br.call.sptk.many b0 = {:#x} ;;", CALLEE)),
+            BundleBuilder::new()
+                .insn("break.m 0x1")
+                .insn("nop.i 0x0")
+                .insn("nop.i 0x0 ;;"),
+            BundleBuilder::new()
+                .insn("nop.m 0x0")
+                .insn("nop.i 0x0")
+                .insn("nop.i 0x0 ;;"),
+            BundleBuilder::new()
+                .insn("nop.m 0x0")
+                .insn("nop.i 0x0")
+                .insn("br.ret.sptk.many b0 ;;"),
+        ]
+        .into_iter()
+        .enumerate()
+        .flat_map(|(i, bundle)| bundle.at(BASE + i as u64 * BUNDLE_SIZE).build().unwrap())
+        .collect();
+        let mut emu = Emulator::new();
+        emu.load_flat_image(BASE, &image, BASE).unwrap();
+        emu.cpu
+            .set_sample_profiler(Some(SampleProfiler::new(3, true)));
+        assert_eq!(emu.run().unwrap(), StopReason::Break(0x1));
+
+        // The call and return bundles count against the function they are
+        // in, and the callee's against the call stack into it
+        let profiler = emu.cpu.sample_profiler.as_ref().unwrap();
+        let samples: Vec<_> = profiler.samples().collect();
+        assert_eq!(
+            samples,
+            [
+                (&[BASE][..], 1),
+                (&[CALLEE, CALLEE][..], 1),
+                (&[CALLEE, CALLEE + BUNDLE_SIZE][..], 1),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_privilege_levels() {
+        const MIB: u8 = 0x10;
+        const MLX: u8 = 4;
+        let epc = 0x10 << 27;
+        let stop = |imm| encode_mii([encode_break_nop(0, 0x00, imm), nop(), nop()]);
+        // brl.call b0 to the gate two bundles on
+        let call = encode_bundle(MLX, [nop(), 0, (0xD << 37) | (2 << 13)]);
+        let program = [
+            call,
+            stop(0x1),
+            encode_bundle(MIB, [nop(), nop(), epc]),
+            stop(0x2),
+        ];
+
+        // epc on a promotion page raises the user caller to level 0, and
+        // the return drops back to the caller's level
+        let mut emu = setup(&program);
+        let gate = BASE + 2 * BUNDLE_SIZE;
+        emu.cpu.promote_pages.push(gate..gate + BUNDLE_SIZE);
+        assert_eq!(emu.run().unwrap(), StopReason::Break(0x2));
+        assert_eq!(emu.cpu.privilege_level(), 0);
+        emu.cpu.handle_return(&mut emu.memory).unwrap();
+        assert_eq!(emu.cpu.privilege_level(), 3);
+
+        // Elsewhere epc leaves the level alone
+        let mut emu = setup(&program);
+        assert_eq!(emu.run().unwrap(), StopReason::Break(0x2));
+        assert_eq!(emu.cpu.privilege_level(), 3);
+
+        // A return never raises the level, and epc refuses an AR.PFS.ppl
+        // more privileged than the caller
+        emu.cpu.pfs = 0;
+        emu.cpu.handle_return(&mut emu.memory).unwrap();
+        assert_eq!(emu.cpu.privilege_level(), 3);
+        assert!(emu.cpu.enter_privileged_code().is_err());
+    }
+
+    #[test]
+    fn test_data_match() {
+        const MMI: u8 = 0x08;
+        const DATA: u64 = 0x40000;
+        let ld8 = |r1: u64| (4 << 37) | (0x03 << 30) | (3 << 20) | (r1 << 6);
+        let st8 = (4 << 37) | (0x33 << 30) | (3 << 20) | (2 << 13);
+        let mut emu = setup(&[
+            encode_bundle(MMI, [ld8(4), st8, nop()]),
+            encode_mii([ld8(5), nop(), encode_break_nop(0, 0x00, 0x1)]),
+        ]);
+        emu.memory
+            .map(DATA, 0x1000, Permissions::ReadWrite)
+            .unwrap();
+        emu.cpu.gr[2] = 0x1234_BEEF;
+        emu.cpu.gr[3] = DATA;
+        emu.cpu.system_regs.ddr.set_match(0xBEEF, 0xFFFF).unwrap();
+
+        // The matching store faults before it writes
+        let stored = StopReason::DataMatch {
+            addr: DATA,
+            value: 0x1234_BEEF,
+        };
+        assert_eq!(emu.step().unwrap(), Some(stored));
+        assert_eq!(emu.memory.read_u64(DATA).unwrap(), 0);
+        assert_eq!((emu.cpu.ip, emu.cpu.interrupts_raised()), (BASE, 1));
+
+        // Resuming skips the load before it and completes the store
+        emu.memory.write_u64(DATA, 7).unwrap();
+        assert_eq!(emu.step().unwrap(), None);
+        assert_eq!(emu.cpu.gr[4], 0);
+        assert_eq!(emu.memory.read_u64(DATA).unwrap(), 0x1234_BEEF);
+        assert!(!emu.cpu.system_regs.cr.contains(PSRFlags::DD));
+
+        // Loading the value matches too
+        assert_eq!(emu.step().unwrap(), Some(stored));
+        assert_eq!(emu.run().unwrap(), StopReason::Break(0x1));
+        assert_eq!(emu.cpu.gr[5], 0x1234_BEEF);
+    }
+
+    #[test]
+    fn test_check_instructions() {
+        let stop = |imm| encode_break_nop(0, 0x00, imm);
+        let bundles = [
+            encode_mii([encode_chk_s(1, 4, 3), encode_chk_s(0, 5, 2), stop(0x1)]),
+            encode_mii([stop(0x11), nop(), nop()]),
+            encode_mii([stop(0x12), nop(), nop()]),
+            encode_mii([encode_chk_a(true, 6, -2), nop(), stop(0x13)]),
+        ];
+        let run = |nat: &[usize], alat: bool| {
+            let mut emu = setup(&bundles);
+            for &reg in nat {
+                emu.cpu.nat[reg] = true;
+            }
+            if alat {
+                emu.cpu.alat_add_entry(0x80000, 8, 6, true).unwrap();
+            }
+            let reason = emu.run().unwrap();
+            (reason, emu.cpu.alat_check_register(6, true))
+        };
+
+        assert_eq!(run(&[], false).0, StopReason::Break(0x1));
+        // chk.s.i in the I slot
+        assert_eq!(run(&[5], false).0, StopReason::Break(0x12));
+        // chk.s.m in the M slot skips the rest of its bundle, then chk.a
+        // misses in the ALAT and branches backwards
+        assert_eq!(run(&[4, 5], false).0, StopReason::Break(0x11));
+        // chk.a.clr hits and removes the entry
+        assert_eq!(run(&[4], true), (StopReason::Break(0x13), false));
+
+        // chk.s.m f2 branches on NaTVal, not on other values
+        let chk_f2 = encode_chk_s(1, 2, 1) | (2 << 33);
+        for (value, reason) in [
+            (FloatRegister::from_f64(1.5), StopReason::Break(0x1)),
+            (FloatRegister::NATVAL, StopReason::Break(0x21)),
+        ] {
+            let mut emu = setup(&[
+                encode_mii([chk_f2, nop(), stop(0x1)]),
+                encode_mii([stop(0x21), nop(), nop()]),
+            ]);
+            emu.cpu.set_fr_register(2, value).unwrap();
+            assert_eq!(emu.run().unwrap(), reason);
+        }
+    }
+
+    #[test]
+    fn test_mov_from_ip() {
+        // mov r14=ip in the second bundle, then stop
+        let mov_ip = (0x30 << 27) | (14 << 6);
+        let stop = encode_break_nop(0, 0x00, 0x1);
+        let mut emu = setup(&[
+            encode_mii([nop(), nop(), nop()]),
+            encode_mii([nop(), mov_ip, stop]),
+        ]);
+
+        assert_eq!(emu.run().unwrap(), StopReason::Break(0x1));
+        assert_eq!(emu.cpu.gr[14], BASE + BUNDLE_SIZE);
+    }
+
+    #[test]
+    fn test_mov_branch_register() {
+        let stop = encode_break_nop(0, 0x00, 0x1);
+        // mov b6=r3, then mov r4=b6 in the next instruction group
+        let mov_to_br = (7 << 33) | (3 << 13) | (6 << 6);
+        let mov_from_br = (0x31 << 27) | (6 << 13) | (4 << 6);
+        let mut emu = setup(&[
+            encode_bundle(0x03, [nop(), mov_to_br, mov_from_br]),
+            encode_mii([stop, nop(), nop()]),
+        ]);
+        emu.cpu.gr[3] = 0x1234_5670;
+
+        assert_eq!(emu.run().unwrap(), StopReason::Break(0x1));
+        assert_eq!(emu.cpu.br[6], 0x1234_5670);
+        assert_eq!(emu.cpu.gr[4], 0x1234_5670);
+
+        // A NaT source faults
+        let mut emu = setup(&[encode_mii([nop(), mov_to_br, stop])]);
+        emu.cpu.nat[3] = true;
+        assert!(matches!(emu.run(), Err(EmulatorError::NatConsumption(_))));
+    }
+
+    #[test]
+    fn test_loop_branches() {
+        const MIB: u8 = 0x10;
+        let stop = encode_break_nop(0, 0x00, 0x1);
+        // alloc r40=ar.pfs,9,9,0 with eight rotating registers
+        let alloc = (1 << 37) | (6 << 33) | (1 << 27) | (9 << 20) | (9 << 13) | (40 << 6);
+        // add r1=r2,r3 and a loop branch with btype back to its own bundle
+        let add = |r1: u64, r2: u64, r3: u64| (8 << 37) | (r3 << 20) | (r2 << 13) | (r1 << 6);
+        let branch = |btype: u64| (4 << 37) | (btype << 6);
+
+        // add r32=r33,r3; br.ctop counts four kernel iterations and one
+        // epilog iteration, rotating after each
+        let mut emu = setup(&[
+            encode_mii([alloc, nop(), nop()]),
+            encode_bundle(MIB, [nop(), add(32, 33, 3), branch(7)]),
+            encode_mii([stop, nop(), nop()]),
+        ]);
+        emu.cpu.pfs = 0x5A;
+        emu.cpu.gr[3] = 1;
+        emu.cpu.write_ar(AR::LC, 4).unwrap();
+        emu.cpu.write_ar(AR::EC, 1).unwrap();
+        assert_eq!(emu.run().unwrap(), StopReason::Break(0x1));
+        assert_eq!(emu.cpu.get_gr(40).unwrap(), 0x5A);
+        assert_eq!(emu.cpu.get_gr(33).unwrap(), 5);
+        assert_eq!(emu.cpu.get_gr(34).unwrap(), 4);
+        assert_eq!(emu.cpu.read_ar(AR::LC).unwrap(), 0);
+        assert_eq!(emu.cpu.read_ar(AR::EC).unwrap(), 0);
+        // The stage predicate was set for the kernel and cleared for the
+        // epilog
+        assert!(!emu.cpu.get_pr(16).unwrap());
+        assert!(emu.cpu.get_pr(17).unwrap() && emu.cpu.get_pr(20).unwrap());
+        assert_eq!(emu.cpu.rotating_bases(), (3, 91, 43));
+
+        // add r8=r8,r3; br.cloop runs once more than AR.LC and does not
+        // rotate
+        let mut emu = setup(&[
+            encode_bundle(MIB, [nop(), add(8, 8, 3), branch(5)]),
+            encode_mii([stop, nop(), nop()]),
+        ]);
+        emu.cpu.gr[3] = 1;
+        emu.cpu.write_ar(AR::LC, 3).unwrap();
+        assert_eq!(emu.run().unwrap(), StopReason::Break(0x1));
+        assert_eq!(emu.cpu.gr[8], 4);
+        assert_eq!(emu.cpu.rotating_bases(), (0, 0, 0));
+    }
+
+    #[test]
+    fn test_recursion_spills_and_fills_frames() {
+        use crate::asm::BundleBuilder;
+
+        const F: u64 = BASE + 3 * BUNDLE_SIZE;
+        const BACKING_STORE: u64 = 0x40000;
+        let bundle = |ip: u64, insns: [&str; 3]| {
+            insns
+                .into_iter()
+                .fold(BundleBuilder::new().at(ip), |builder, insn| {
+                    builder.insn(insn)
+                })
+                .build()
+                .unwrap()
+        };
+        // f(n) keeps n in a local across a call to f(n - 1), in frames of
+        // 22 registers, and adds it to r8 on the way back
+        let program = [
+            [
+                "alloc r2 = ar.pfs, 0, 0, 1, 0 ;;",
+                "mov r32 = 10",
+                "mov r8 = 0 ;;",
+            ],
+            [
+                "nop.m 0",
+                "nop.i 0",
+                &format!("br.call.sptk.many b0 = {:#x} ;;", F),
+            ],
+            ["break.m 0x1", "nop.i 0", "nop.i 0 ;;"],
+            [
+                "alloc r33 = ar.pfs, 1, 20, 1, 0 ;;",
+                "mov r35 = r32",
+                "mov r34 = b0 ;;",
+            ],
+            ["cmp.eq p6, p7 = 0, r32", "adds r53 = -1, r32", "nop.i 0 ;;"],
+            [
+                "nop.m 0",
+                "nop.i 0",
+                &format!("(p7) br.call.sptk.many b0 = {:#x} ;;", F),
+            ],
+            ["add r8 = r8, r35", "mov ar.pfs = r33", "mov b0 = r34 ;;"],
+            ["nop.m 0", "nop.i 0", "br.ret.sptk.many b0 ;;"],
+        ];
+        let image: Vec<u8> = program
+            .iter()
+            .enumerate()
+            .flat_map(|(i, insns)| bundle(BASE + i as u64 * BUNDLE_SIZE, *insns))
+            .collect();
+        let mut emu = Emulator::new();
+        emu.load_flat_image(BASE, &image, BASE).unwrap();
+        emu.memory
+            .map(BACKING_STORE, 0x1000, Permissions::ReadWrite)
+            .unwrap();
+        emu.cpu.write_ar(AR::RSC, 0).unwrap();
+        emu.cpu.write_ar(AR::BSPSTORE, BACKING_STORE).unwrap();
+
+        // Eleven frames overflow the stacked registers: the oldest go to
+        // the backing store on the way down and come back on the way up
+        assert_eq!(emu.run().unwrap(), StopReason::Break(0x1));
+        assert_eq!(emu.cpu.gr[8], (0..=10).sum::<u64>());
+        assert!(emu.cpu.rse.spill_count() > 0);
+        assert!(emu.cpu.rse.fill_count() > 0);
+        assert_eq!(emu.cpu.read_ar(AR::BSP).unwrap(), BACKING_STORE);
+    }
+
+    #[test]
+    fn test_pipelined_return() {
+        use crate::asm::BundleBuilder;
+
+        const CALLEE: u64 = BASE + 0x100;
+        const SRC: u64 = 0x20000;
+        const DST: u64 = 0x21000;
+        let at = |base: u64, program: Vec<BundleBuilder>| -> Vec<u8> {
+            program
+                .into_iter()
+                .enumerate()
+                .flat_map(|(i, builder)| builder.at(base + i as u64 * BUNDLE_SIZE).build().unwrap())
+                .collect()
+        };
+
+        // A caller partway through a modulo-scheduled loop of its own, with
+        // an epilog count and rotated registers, calls a copy kernel
+        let caller = vec![
+            BundleBuilder::new()
+                .insn("alloc r32 = ar.pfs, 0, 8, 3, 8")
+                .insn("mov.i ar.ec = 5")
+                .insn("nop.i 0x0 ;;"),
+            BundleBuilder::new()
+                .insn("nop.m 0x0")
+                .insn("nop.i 0x0")
+                .insn(&format!(
+                    "br.ctop.sptk.few {:#x} ;;",
+                    BASE + 2 * BUNDLE_SIZE
+                )),
+            BundleBuilder::new()
+                .insn("mov r40 = r8")
+                .insn("mov r41 = r9")
+                .insn("mov r42 = r10 ;;"),
+            BundleBuilder::new()
+                .insn("nop.m 0x0")
+                .insn("nop.i 0x0")
+                .insn(&format!("br.call.sptk.many b0 = {:#x} ;;", CALLEE)),
+            BundleBuilder::new()
+                .insn("break.m 0x1")
+                .insn("nop.i 0x0")
+                .insn("nop.i 0x0 ;;"),
+        ];
+        // A three-stage copy loop, hand-scheduled the way compilers
+        // modulo-schedule memcpy: load in stage 1, store two rotations later
+        // in stage 3, and drain the pipeline with AR.EC once AR.LC runs out
+        let callee = vec![
+            BundleBuilder::new()
+                .insn("alloc r16 = ar.pfs, 3, 5, 0, 8")
+                .insn("mov r17 = ar.lc")
+                .insn("mov r18 = pr ;;"),
+            BundleBuilder::new()
+                .insn("mov r14 = r32")
+                .insn("mov r15 = r33")
+                .insn("adds r19 = -1, r34 ;;"),
+            BundleBuilder::new()
+                .insn("nop.m 0x0")
+                .insn("mov.i ar.lc = r19")
+                .insn("mov.i ar.ec = 3 ;;"),
+            BundleBuilder::new()
+                .insn("nop.m 0x0")
+                .insn("mov pr.rot = 0x10000")
+                .insn("nop.i 0x0 ;;"),
+            BundleBuilder::new()
+                .insn("(p16) ld8 r32 = [r14], 8")
+                .insn("(p18) st8 [r15] = r34, 8")
+                .insn(&format!(
+                    "br.ctop.sptk.few {:#x} ;;",
+                    CALLEE + 4 * BUNDLE_SIZE
+                )),
+            BundleBuilder::new()
+                .insn("nop.m 0x0")
+                .insn("mov.i ar.lc = r17")
+                .insn("mov pr = r18, 0x1fffe ;;"),
+            BundleBuilder::new()
+                .insn("nop.m 0x0")
+                .insn("mov.i ar.pfs = r16")
+                .insn("nop.i 0x0 ;;"),
+            BundleBuilder::new()
+                .insn("nop.m 0x0")
+                .insn("nop.i 0x0")
+                .insn("br.ret.sptk.many b0 ;;"),
+        ];
+        let mut image = at(BASE, caller);
+        image.resize(0x100, 0);
+        image.extend(at(CALLEE, callee));
+
+        let mut emu = Emulator::new();
+        emu.load_flat_image(BASE, &image, BASE).unwrap();
+        emu.memory.map(SRC, 0x2000, Permissions::ReadWrite).unwrap();
+        let data: Vec<u64> = (1..=5).map(|i| i * 0x1111).collect();
+        for (i, value) in data.iter().enumerate() {
+            emu.memory.write_u64(SRC + 8 * i as u64, *value).unwrap();
+        }
+        emu.cpu.gr[8] = SRC;
+        emu.cpu.gr[9] = DST;
+        emu.cpu.gr[10] = data.len() as u64;
+        emu.cpu.write_ar(AR::LC, 1).unwrap();
+
+        assert_eq!(emu.run().unwrap(), StopReason::Break(0x1));
+        // Every element was copied, the last two by the epilog
+        for (i, value) in data.iter().enumerate() {
+            assert_eq!(emu.memory.read_u64(DST + 8 * i as u64).unwrap(), *value);
+        }
+        assert_eq!(emu.memory.read_u64(DST + 8 * 5).unwrap(), 0);
+        // The return gave the caller back its epilog count and frame,
+        // rotating register bases included
+        assert_eq!(emu.cpu.read_ar(AR::EC).unwrap(), 5);
+        assert_eq!(emu.cpu.read_ar(AR::LC).unwrap(), 0);
+        assert_eq!(emu.cpu.rotating_bases(), (7, 95, 47));
+        assert_eq!(emu.cpu.cfm & 0x3FFF, 11 | (8 << 7));
+    }
+
+    #[test]
+    fn test_pmu() {
+        // mov pmc[r3]=r2, counting cycles at level 0; then mov r8=pmd[r3]
+        let mov_to_pmc = (1 << 37) | (0x04 << 27) | (3 << 20) | (2 << 13);
+        let mov_from_pmd = (1 << 37) | (0x15 << 27) | (3 << 20) | (8 << 6);
+        let stop = encode_break_nop(0, 0x00, 0x1);
+        let program = [
+            encode_mii([mov_to_pmc, nop(), nop()]),
+            encode_mii([nop(), nop(), nop()]),
+            encode_mii([mov_from_pmd, nop(), stop]),
+        ];
+        let mut emu = setup(&program);
+        emu.cpu.set_privilege_level(0);
+        emu.cpu.gr[2] = (crate::cpu::pmu::EVENT_CPU_CYCLES << 8) | 0x1;
+        emu.cpu.gr[3] = 4;
+
+        assert_eq!(emu.run().unwrap(), StopReason::Break(0x1));
+        assert_eq!(emu.cpu.gr[8], 2);
+
+        // Programming the PMU at user level is a privileged operation
+        let mut emu = setup(&program);
+        emu.cpu.gr[3] = 4;
+        assert!(matches!(emu.run(), Err(EmulatorError::PrivilegeViolation)));
+    }
+
+    #[test]
+    fn test_chaos() {
+        // Bundles until the timer interrupt and a hotplug attach arrive
+        let arrivals = |chaos: Option<ChaosConfig>| {
+            let mut emu = setup(&[encode_mii([nop(), nop(), nop()]); 64]);
+            emu.set_chaos(chaos);
+            emu.cpu.write_cr(CRIndex::ITV, 0xEF).unwrap();
+            emu.cpu.write_cr(CRIndex::ITM, 10).unwrap();
+            emu.device_handle()
+                .attach(0x80000, Box::new(Latch::default()));
+            let (mut timer, mut device) = (None, None);
+            for bundle in 1..=64 {
+                emu.step().unwrap();
+                if emu.cpu.read_cr(CRIndex::IRR3) >> 47 & 1 != 0 && timer.is_none() {
+                    timer = Some(bundle);
+                }
+                if !emu.take_device_events().is_empty() {
+                    device = Some(bundle);
+                }
+            }
+            (timer.unwrap(), device.unwrap())
+        };
+        let on_time = arrivals(None);
+        assert_eq!(on_time, (10, 1));
+
+        let config = ChaosConfig {
+            seed: 42,
+            max_delay: 40,
+            latency_jitter: 0,
+        };
+        let jittered = arrivals(Some(config));
+        assert_eq!(arrivals(Some(config)), jittered);
+        assert!((10..=50).contains(&jittered.0) && (1..=41).contains(&jittered.1));
+        let seeds = (0..8).map(|seed| arrivals(Some(ChaosConfig { seed, ..config })));
+        assert!(seeds.into_iter().any(|a| a != on_time));
+
+        // Latency jitter moves ITC by the cycles each bundle took
+        let itc = |chaos: Option<ChaosConfig>| {
+            let mut emu = setup(&[encode_mii([nop(), nop(), nop()]); 64]);
+            emu.set_chaos(chaos);
+            for _ in 0..64 {
+                emu.step().unwrap();
+            }
+            emu.cpu.read_ar(AR::ITC).unwrap()
+        };
+        assert_eq!(itc(None), 64);
+        let jittered = ChaosConfig::new(7);
+        assert_eq!(itc(Some(jittered)), itc(Some(jittered)));
+        assert!((64..=64 * 3).contains(&itc(Some(jittered))));
+        assert!((0..8).any(|seed| itc(Some(ChaosConfig::new(seed))) != 64));
+    }
+
+    #[test]
+    fn test_strict_decode() {
+        // An FP load/store opcode in an I slot, behind a break
+        let stop = encode_break_nop(0, 0x00, 0x1);
+        let bundle = encode_mii([stop, 6 << 37, nop()]);
+
+        let mut emu = setup(&[bundle]);
+        assert_eq!(emu.run().unwrap(), StopReason::Break(0x1));
+
+        let mut emu = setup(&[bundle]);
+        emu.set_strict_decode(true);
+        let error = emu.run().unwrap_err();
+        assert!(matches!(&error, EmulatorError::DecodeError(msg) if msg.contains("slot 1")));
+        assert_eq!(emu.cpu.ip, BASE);
+    }
+
+    #[test]
+    fn test_setup_process() {
+        let mut emu = setup(&[encode_mii([nop(), nop(), nop()])]);
+        let args = ["prog".to_string(), "-v".to_string()];
+        emu.setup_process(&args, &["TERM=vt100".to_string()])
+            .unwrap();
+
+        let sp = emu.cpu.gr[STACK_POINTER_REG];
+        assert_eq!(emu.memory.read_u64(sp + 16).unwrap(), 2);
+        let argv1 = emu.memory.read_u64(sp + 32).unwrap();
+        let mut text = [0; 3];
+        emu.memory.read_bytes(argv1, &mut text).unwrap();
+        assert_eq!(&text, b"-v\0");
+        assert_eq!(emu.phys_map.find(sp).unwrap().name, "stack");
+    }
+
+    #[test]
+    fn test_dispersal() {
+        let stop = encode_break_nop(0, 0x00, 0x1);
+        let bundles = [
+            encode_mii([nop(), nop(), nop()]),
+            encode_mii([nop(), nop(), nop()]),
+            encode_mii([stop, nop(), nop()]),
+        ];
+
+        // Each MII after the first finds both I ports taken and splits
+        let mut emu = setup(&bundles);
+        emu.dispersal = Some(Dispersal::new(MachineModel::Itanium2, false));
+        assert_eq!(emu.run().unwrap(), StopReason::Break(0x1));
+        let stats = emu.dispersal.as_ref().unwrap().stats();
+        assert_eq!((stats.cycles, stats.bundles, stats.instructions), (3, 3, 9));
+        assert_eq!(stats.split_issues, 2);
+
+        let mut emu = setup(&bundles);
+        emu.dispersal = Some(Dispersal::new(MachineModel::Itanium2, true));
+        assert!(emu.run().unwrap_err().to_string().contains("Split issue"));
+        assert_eq!(emu.cpu.ip, BASE + BUNDLE_SIZE);
+    }
+
+    #[test]
+    fn test_guest_timer() {
+        use crate::asm::BundleBuilder;
+        use crate::cpu::IVT_EXTINT_OFFSET;
+
+        const IVA: u64 = BASE + 0x8000;
+        const TICKS: u64 = 20;
+        let at = |base: u64, program: Vec<BundleBuilder>| -> Vec<u8> {
+            program
+                .into_iter()
+                .enumerate()
+                .flat_map(|(i, builder)| builder.at(base + i as u64 * BUNDLE_SIZE).build().unwrap())
+                .collect()
+        };
+
+        // A kernel installs its vector table, arms the timer every TICKS
+        // ticks on vector 0xEF and spins until it has fired three times
+        let kernel = vec![
+            BundleBuilder::new()
+                .insn(&format!("mov r2 = {:#x}", IVA))
+                .insn("mov r3 = 0xef")
+                .insn("nop.i 0x0 ;;"),
+            BundleBuilder::new()
+                .insn("mov cr.iva = r2")
+                .insn("mov cr.itv = r3")
+                .insn("nop.i 0x0 ;;"),
+            BundleBuilder::new()
+                .insn("mov r4 = ar.itc ;;")
+                .insn(&format!("adds r4 = {}, r4", TICKS))
+                .insn("nop.i 0x0 ;;"),
+            BundleBuilder::new()
+                .insn("mov cr.itm = r4")
+                .insn("ssm 0x6000")
+                .insn("nop.i 0x0 ;;"),
+            BundleBuilder::new()
+                .insn("nop.m 0x0")
+                .insn("cmp.eq p6, p7 = 3, r8")
+                .insn("nop.i 0x0 ;;"),
+            BundleBuilder::new()
+                .insn("(p6) break.m 0x1")
+                .insn("nop.i 0x0")
+                .insn(&format!(
+                    "br.cond.sptk.few {:#x} ;;",
+                    BASE + 4 * BUNDLE_SIZE
+                )),
+        ];
+        // Its external interrupt handler acknowledges the vector, counts
+        // the tick and rearms the timer
+        let handler = vec![
+            BundleBuilder::new()
+                .insn("mov r16 = cr.ivr ;;")
+                .insn("adds r8 = 1, r8")
+                .insn("nop.i 0x0 ;;"),
+            BundleBuilder::new()
+                .insn("mov r17 = ar.itc ;;")
+                .insn(&format!("adds r17 = {}, r17", TICKS))
+                .insn("nop.i 0x0 ;;"),
+            BundleBuilder::new()
+                .insn("mov cr.itm = r17")
+                .insn("mov cr.eoi = r0")
+                .insn("nop.i 0x0 ;;"),
+            BundleBuilder::new()
+                .insn("nop.m 0x0")
+                .insn("nop.i 0x0")
+                .insn("rfi ;;"),
+        ];
+        let mut image = at(BASE, kernel);
+        image.resize((IVA + IVT_EXTINT_OFFSET - BASE) as usize, 0);
+        image.extend(at(IVA + IVT_EXTINT_OFFSET, handler));
+        let mut emu = Emulator::new();
+        emu.load_flat_image(BASE, &image, BASE).unwrap();
+        emu.cpu.set_privilege_level(0);
+
+        // Spinning would never end without the ticks
+        let stop = (0..1000).find_map(|_| emu.step().unwrap());
+        assert_eq!(stop, Some(StopReason::Break(0x1)));
+        assert_eq!(emu.cpu.gr[16], 0xEF);
+        // Each tick was acknowledged, and the kernel runs with interrupts
+        // on again
+        assert_eq!(emu.cpu.read_cr(CRIndex::IRR3), 0);
+        assert!(emu.cpu.system_regs.cr.contains(PSRFlags::I));
+        assert!(emu.cpu.read_ar(AR::ITC).unwrap() >= 3 * TICKS);
+    }
+
+    #[test]
+    fn test_timing() {
+        // ITC after 64 bundles of nops, with or without a closing stop
+        let itc = |template: u8, timing: Option<TimingModel>| {
+            let mut emu = setup(&[encode_bundle(template, [nop(), nop(), nop()]); 64]);
+            emu.timing = timing;
+            for _ in 0..64 {
+                emu.step().unwrap();
+            }
+            (emu.cpu.read_ar(AR::ITC).unwrap(), emu.timing)
+        };
+        assert_eq!(itc(0x00, None).0, 64);
+
+        // Two bundles issue per cycle unless a stop ends the cycle
+        let (cycles, timing) = itc(0x00, Some(TimingModel::default()));
+        assert_eq!(cycles, 32);
+        let stats = timing.unwrap().stats().clone();
+        assert_eq!(
+            (stats.cycles, stats.bundles, stats.instructions),
+            (32, 64, 192)
+        );
+        assert_eq!(itc(0x01, Some(TimingModel::default())).0, 64);
+    }
+
+    #[test]
+    fn test_transform_timing() {
+        use crate::asm::BundleBuilder;
+        use crate::cpu::timing::Latencies;
+        use crate::memory::transform::{Transform, XorPad};
+
+        const DATA: u64 = 0x40000;
+        let code = BundleBuilder::new()
+            .insn("ld8 r8 = [r9]")
+            .insn("nop.i 0x0")
+            .insn("nop.i 0x0 ;;")
+            .at(BASE)
+            .build()
+            .unwrap();
+        // Cycles of a load missing every cache level
+        let load = |transform: Option<XorPad>| {
+            let mut emu = Emulator::new();
+            emu.load_flat_image(BASE, &code, BASE).unwrap();
+            emu.memory
+                .map(DATA, 0x1000, Permissions::ReadWrite)
+                .unwrap();
+            emu.memory.write_u64(DATA, 0x1122_3344_5566_7788).unwrap();
+            let transform = transform.map(|pad| Box::new(pad) as Box<dyn Transform>);
+            emu.memory.set_transform(DATA, transform).unwrap();
+            emu.timing = Some(TimingModel::default());
+            emu.cpu.gr[9] = DATA;
+            emu.step().unwrap();
+            (emu.cpu.gr[8], emu.cycles())
+        };
+
+        // The value comes back decoded, after the transform's latency
+        let (value, plain) = load(None);
+        let (decoded, transformed) = load(XorPad::new(vec![0xA5]));
+        assert_eq!((value, decoded), (0x1122_3344_5566_7788, value));
+        assert_eq!(transformed - plain, Latencies::default().transform);
+    }
+
+    #[test]
+    fn test_progress_counters() {
+        let mut emu = setup(&[encode_bundle(0x00, [nop(), nop(), nop()]); 64]);
+        emu.timing = Some(TimingModel::default());
+        emu.cpu.set_timer_mode(TimerMode::Instructions {
+            ticks_per_bundle: 4,
+        });
+        for i in 0..64 {
+            if i == 32 {
+                emu.cpu.write_ar(AR::ITC, 0).unwrap();
+            }
+            emu.step().unwrap();
+        }
+
+        // Instructions, bundles and cycles each count their own unit, and
+        // only ITC follows the guest's write and the scaling
+        assert_eq!((emu.retired(), emu.bundles(), emu.cycles()), (192, 64, 32));
+        assert_eq!(emu.cpu.read_ar(AR::ITC).unwrap(), 16 * 4);
+
+        // Slots before a failing one stay retired
+        let reserved = 6 << 37;
+        let mut emu = setup(&[encode_bundle(0x00, [nop(), nop(), reserved])]);
+        assert!(emu.step().is_err());
+        assert_eq!((emu.retired(), emu.bundles()), (2, 0));
+    }
+
+    #[test]
+    fn test_uninit_reads() {
+        const MEMCPY: u64 = 0x30000;
+        const DATA: u64 = 0x40000;
+
+        let mut emu = Emulator::new();
+        emu.memory.set_uninit_policy(UninitPolicy::Poison);
+        let stop = encode_mii([encode_break_nop(0, 0x00, 0x1), nop(), nop()]);
+        emu.load_flat_image(BASE, &stop, BASE).unwrap();
+        emu.memory
+            .map(DATA, 0x1000, Permissions::ReadWrite)
+            .unwrap();
+        emu.memory.write_u64(DATA, 0x1122_3344_5566_7788).unwrap();
+        emu.add_intercept(Intercept::builtin(Builtin::Memcpy, MEMCPY));
+
+        // Copy eight written bytes and eight never written ones
+        emu.cpu.ip = MEMCPY;
+        emu.cpu.br[0] = BASE;
+        emu.cpu.gr[32..35].copy_from_slice(&[DATA + 0x100, DATA, 16]);
+        assert_eq!(emu.run().unwrap(), StopReason::Break(0x1));
+
+        let reads = emu.take_uninit_reads();
+        assert_eq!(reads.len(), 1);
+        assert_eq!(
+            (reads[0].ip, reads[0].addr, reads[0].len, reads[0].count),
+            (MEMCPY, DATA + 8, 8, 1)
+        );
+        assert_eq!(reads[0].backtrace, vec![MEMCPY, BASE]);
+
+        // The copy holds the poison, and is itself written
+        assert_eq!(
+            emu.memory.read_u64(DATA + 0x108).unwrap(),
+            u64::from_le_bytes([POISON; 8])
+        );
+        assert!(emu.memory.take_uninit_reads().is_empty());
+    }
+
+    #[test]
+    fn test_intercept() {
+        const MEMCPY: u64 = 0x30000;
+        const DATA: u64 = 0x40000;
+
+        // The caller's first bundle is overwritten by the copy: break 0x2
+        let stop = encode_break_nop(0, 0x00, 0x1);
+        let mut emu = setup(&[encode_mii([stop, nop(), nop()])]);
+        emu.memory.set_wx_policy(WxPolicy::Log);
+        emu.memory
+            .map(DATA, 0x1000, Permissions::ReadWrite)
+            .unwrap();
+        let replacement = encode_mii([encode_break_nop(0, 0x00, 0x2), nop(), nop()]);
+        emu.memory.write_bytes(DATA, &replacement).unwrap();
+        emu.add_intercept(Intercept::builtin(Builtin::Memcpy, MEMCPY));
+
+        // Decode the caller's bundle, then call memcpy(BASE, DATA, 16)
+        emu.cpu.ip = BASE;
+        assert_eq!(emu.step().unwrap(), Some(StopReason::Break(0x1)));
+        emu.cpu.ip = MEMCPY;
+        emu.cpu.br[0] = BASE;
+        emu.cpu.gr[32..35].copy_from_slice(&[BASE, DATA, BUNDLE_SIZE]);
+
+        assert_eq!(emu.run().unwrap(), StopReason::Break(0x2));
+        assert_eq!(emu.cpu.gr[8], BASE);
+        assert_eq!(emu.take_wx_violations().len(), 1);
+
+        // Faults surface like faults in the guest function
+        emu.cpu.ip = MEMCPY;
+        emu.cpu.gr[33] = 0xdead_0000;
+        assert!(emu.step().is_err());
+        assert!(emu.remove_intercept(MEMCPY).is_some());
+    }
+
+    #[test]
+    fn test_variable_services() {
+        const SET_VARIABLE: u64 = 0x30000;
+        const DATA: u64 = 0x40000;
+
+        let mut emu = setup(&[encode_mii([encode_break_nop(0, 0x00, 0x1), nop(), nop()])]);
+        emu.memory
+            .map(DATA, 0x1000, Permissions::ReadWrite)
+            .unwrap();
+        emu.memory.write_bytes(DATA, b"T\0o\0\0\0").unwrap();
+        emu.memory
+            .write_bytes(DATA + 0x10, &Guid::GLOBAL_VARIABLE.0)
+            .unwrap();
+        emu.add_variable_service(VariableService::SetVariable, SET_VARIABLE);
+
+        // SetVariable(L"To", &EFI_GLOBAL_VARIABLE, BS, 1, DATA) returns to b0
+        emu.cpu.ip = SET_VARIABLE;
+        emu.cpu.br[0] = BASE;
+        emu.cpu.gr[32..37].copy_from_slice(&[DATA, DATA + 0x10, 0x2, 1, DATA]);
+        assert_eq!(emu.run().unwrap(), StopReason::Break(0x1));
+        assert_eq!(emu.cpu.gr[8], 0);
+        let variable = emu.variables().get(&Guid::GLOBAL_VARIABLE, "To").unwrap();
+        assert_eq!(variable.data, b"T");
+
+        // A store error is a status, not a fault
+        emu.cpu.ip = SET_VARIABLE;
+        emu.cpu.gr[34] = 0x4;
+        assert_eq!(emu.run().unwrap(), StopReason::Break(0x1));
+        assert_eq!(emu.cpu.gr[8], EfiStatus::InvalidParameter.code());
+    }
+
+    /// Device latching the last value stored to it
+    #[derive(Debug, Default)]
+    struct Latch([u8; 8]);
+
+    impl Device for Latch {
+        fn name(&self) -> &str {
+            "latch"
+        }
+
+        fn size(&self) -> u64 {
+            8
+        }
+
+        fn read(&mut self, offset: u64, data: &mut [u8]) {
+            let offset = offset as usize;
+            data.copy_from_slice(&self.0[offset..offset + data.len()]);
+        }
+
+        fn write(&mut self, offset: u64, data: &[u8]) {
+            let offset = offset as usize;
+            self.0[offset..offset + data.len()].copy_from_slice(data);
+        }
+    }
+
+    #[test]
+    fn test_device_hotplug() {
+        const MMIO: u64 = 0x80000;
+
+        let mut emu = setup(&[encode_mii([nop(), nop(), nop()]); 4]);
+        emu.cpu
+            .register_interrupt_handler(InterruptVector::ExtInt, 0x4000, 0)
+            .unwrap();
+        emu.cpu.set_interrupts_enabled(true);
+
+        let handle = emu.device_handle();
+        let id = std::thread::spawn(move || handle.attach(MMIO, Box::new(Latch::default())))
+            .join()
+            .unwrap();
+        assert!(emu.memory.read_u32(MMIO).is_err());
+
+        // Applied before the next bundle, with an interrupt for the guest
+        assert_eq!(emu.step().unwrap(), None);
+        let events = emu.take_device_events();
+        assert!(matches!(events[..], [DeviceEvent::Attached { id: i, base: MMIO }] if i == id));
+        assert_eq!(emu.phys_map.find(MMIO).unwrap().kind, RegionKind::Mmio);
+        assert_eq!(emu.cpu.check_interrupts(), Some(0x4000));
+        assert_eq!(emu.cpu.current_interrupt().unwrap().info, id.0 as u64);
+
+        emu.memory.write_u32(MMIO + 4, 0xdead_beef).unwrap();
+        assert_eq!(emu.memory.read_u64(MMIO).unwrap(), 0xdead_beef_0000_0000);
+
+        // A second device may not overlap the first or the code
+        let handle = emu.device_handle();
+        let clash = handle.attach(MMIO, Box::new(Latch::default()));
+        let code = handle.attach(BASE, Box::new(Latch::default()));
+        handle.detach(id);
+        assert_eq!(emu.step().unwrap(), None);
+        let events = emu.take_device_events();
+        assert!(matches!(events[0], DeviceEvent::Failed { id, .. } if id == clash));
+        assert!(matches!(events[1], DeviceEvent::Failed { id, .. } if id == code));
+        let DeviceEvent::Detached { device, .. } = &events[2] else {
+            panic!("expected a detach event, got {:?}", events[2]);
+        };
+        assert_eq!(device.name(), "latch");
+        assert!(emu.memory.read_u32(MMIO).is_err());
+        assert!(emu.phys_map.find(MMIO).is_none());
+        assert!(matches!(
+            emu.detach(id),
+            Err(EmulatorError::ExecutionError(_))
+        ));
+
+        // Direct attach from the machine's own thread
+        let id = emu.attach(MMIO, Box::new(Latch::default())).unwrap();
+        emu.memory.write_u8(MMIO, 7).unwrap();
+        let mut device = emu.detach(id).unwrap();
+        let mut data = [0];
+        device.read(0, &mut data);
+        assert_eq!(data, [7]);
+    }
+
+    #[test]
+    fn test_timeline() {
+        const MMIO: u64 = 0x80000;
+
+        let syscall = encode_break_nop(0, 0x00, SYSCALL_BREAK_IMM);
+        let mut emu = setup(&[encode_mii([syscall, nop(), nop()]); 2]);
+        emu.cpu
+            .register_interrupt_handler(InterruptVector::ExtInt, 0x4000, 0)
+            .unwrap();
+        emu.cpu.set_interrupts_enabled(true);
+        let timeline = Timeline::default();
+        emu.set_timeline(Some(timeline.clone()));
+
+        // The device's store, the guest's system call, then its handler
+        let id = emu.attach(MMIO, Box::new(Latch::default())).unwrap();
+        emu.memory.write_u32(MMIO, 1).unwrap();
+        emu.cpu.gr[SYSCALL_NUMBER_REG] = SyscallNumber::GetPid as u64;
+        emu.step().unwrap();
+        assert_eq!(emu.cpu.check_interrupts(), Some(0x4000));
+        emu.cpu.rfi(&mut emu.memory).unwrap();
+
+        let events: Vec<(Channel, String, Phase)> = timeline
+            .events()
+            .into_iter()
+            .map(|event| (event.channel, event.name, event.phase))
+            .collect();
+        let device = Channel::Device(id);
+        assert_eq!(
+            events,
+            [
+                (device, "attach".to_string(), Phase::Instant),
+                (Channel::Interrupts, "ExtInt".to_string(), Phase::Instant),
+                (device, "write".to_string(), Phase::Instant),
+                (Channel::Syscalls, "getpid".to_string(), Phase::Instant),
+                (Channel::Interrupts, "handler".to_string(), Phase::Begin),
+                (Channel::Interrupts, "handler".to_string(), Phase::End),
+            ]
+        );
+        assert_eq!(
+            timeline.events()[3].args,
+            [("number", SyscallNumber::GetPid as u64), ("result", 1)]
+        );
+        // The handler runs after the bundle that made the call
+        assert!(timeline.events()[4].time > timeline.events()[3].time);
+        assert!(timeline.chrome_trace().contains(&format!("latch ({})", id)));
+    }
+
+    #[test]
+    fn test_watchdog() {
+        use crate::device::watchdog::*;
+        const MMIO: u64 = 0x80000;
+
+        let watched = |action| {
+            let mut emu = setup(&[encode_mii([nop(), nop(), nop()]); 8]);
+            let watchdog = Watchdog::new("watchdog", 3, action).enabled();
+            emu.attach(MMIO, Box::new(watchdog)).unwrap();
+            emu
+        };
+
+        // A reset restores the registers the guest booted with
+        let mut emu = watched(WatchdogAction::Reset);
+        emu.cpu.set_gr(STACK_POINTER_REG, 0x1234).unwrap();
+        assert_eq!(emu.step().unwrap(), None);
+        emu.cpu.set_gr(STACK_POINTER_REG, 0).unwrap();
+        while emu.resets() == 0 {
+            assert_eq!(emu.step().unwrap(), None);
+            assert!(emu.cpu.ip < BASE + 8 * BUNDLE_SIZE);
+        }
+        assert_eq!(emu.cpu.ip, BASE);
+        assert_eq!(emu.cpu.get_gr(STACK_POINTER_REG).unwrap(), 0x1234);
+        assert_eq!(
+            emu.memory.read_u64(MMIO + REG_STATUS).unwrap(),
+            STATUS_EXPIRED
+        );
+        assert_eq!(emu.memory.read_u64(MMIO + REG_CONTROL).unwrap(), 0);
+
+        // Keepalives hold off a stop
+        let mut emu = watched(WatchdogAction::Stop);
+        for _ in 0..6 {
+            emu.memory.write_u64(MMIO + REG_KEEPALIVE, 0).unwrap();
+            assert_eq!(emu.step().unwrap(), None);
+        }
+        let stopped = (0..4).find_map(|_| emu.step().unwrap());
+        assert_eq!(stopped, Some(StopReason::WatchdogExpired));
+
+        // An interrupting watchdog raises the NMI vector
+        let mut emu = watched(WatchdogAction::Interrupt);
+        emu.cpu.clear_pending_interrupts();
+        while emu.cpu.interrupts_raised() < 2 {
+            assert_eq!(emu.step().unwrap(), None);
+        }
+        assert_eq!(emu.cpu.read_cr(CRIndex::IRR0), 1 << NMI_VECTOR);
+        assert_eq!(emu.resets(), 0);
+    }
+
+    #[test]
+    fn test_strict_types() {
+        use crate::asm::BundleBuilder;
+
+        // The widest register numbers and immediates each format encodes
+        let program = [
+            BundleBuilder::new()
+                .insn("alloc r2 = ar.pfs, 0, 96, 0, 0")
+                .insn("nop.i 0x0")
+                .insn("nop.i 0x0 ;;"),
+            BundleBuilder::new()
+                .insn("adds r127 = 8191, r0")
+                .insn("adds r126 = -8192, r0")
+                .insn("nop.i 0x0 ;;"),
+            BundleBuilder::new()
+                .insn("addl r125 = -2097152, r0")
+                .insn("addl r124 = 2097151, r3")
+                .insn("cmp.eq p63, p62 = r0, r0 ;;"),
+            BundleBuilder::new()
+                .insn("(p63) add r123 = r127, r126")
+                .insn("(p62) add r122 = r127, r127")
+                .insn("nop.i 0x0 ;;"),
+            BundleBuilder::new()
+                .insn("nop.m 0x0")
+                .insn("movl r121 = 0xffffffffffffffff ;;"),
+            BundleBuilder::new()
+                .insn("break.m 0x1fffff")
+                .insn("nop.i 0x0")
+                .insn("nop.i 0x0"),
+        ];
+        let image: Vec<u8> = program
+            .into_iter()
+            .enumerate()
+            .flat_map(|(i, builder)| builder.at(BASE + i as u64 * BUNDLE_SIZE).build().unwrap())
+            .collect();
+        let mut emu = Emulator::new();
+        emu.load_flat_image(BASE, &image, BASE).unwrap();
+        emu.cpu.strict_types = true;
+
+        assert_eq!(emu.run().unwrap(), StopReason::Break(0x1fffff));
+        assert_eq!(emu.cpu.cfm & 0x7F, 96);
+        let gr = |reg| emu.cpu.get_gr(reg).unwrap();
+        assert_eq!(gr(127), 8191);
+        assert_eq!(gr(126), -8192i64 as u64);
+        assert_eq!(gr(125), -2097152i64 as u64);
+        assert_eq!(gr(124), 2097151);
+        assert_eq!(gr(123), u64::MAX);
+        assert_eq!(gr(122), 0);
+        assert_eq!(gr(121), u64::MAX);
+        assert!(emu.cpu.get_pr(63).unwrap());
+        assert!(!emu.cpu.get_pr(62).unwrap());
+
+        // A frame size past its CFM field faults rather than spilling into
+        // the next one
+        assert!(matches!(
+            emu.cpu.branch_with_alloc(&mut emu.memory, 0, 0x80, 0),
+            Err(EmulatorError::Truncation { what: "sol", .. })
+        ));
+        emu.cpu.strict_types = false;
+        emu.cpu
+            .branch_with_alloc(&mut emu.memory, 0, 0x80, 0)
+            .unwrap();
+        assert_eq!(emu.cpu.cfm, 0);
+    }
+
+    #[test]
+    fn test_resize_ram() {
+        const RAM: u64 = 0x40000;
+
+        let mut emu = setup(&[encode_mii([nop(), nop(), nop()])]);
+        assert!(emu.resize_ram(0x1000).is_err());
+        emu.memory
+            .load_image(RAM, 0x4000, &[1, 2, 3, 4], Permissions::ReadWrite)
+            .unwrap();
+        emu.phys_map
+            .add("ram", RAM, 0x4000, RegionKind::Ram)
+            .unwrap();
+        emu.ram_images.push((RAM, vec![1, 2, 3, 4]));
+        assert_eq!(emu.ram_size(), 0x4000);
+        assert!(emu.resize_ram(0x4800).is_err());
+
+        // Growing maps RAM above the top of the existing range
+        emu.resize_ram(0x6000).unwrap();
+        assert_eq!(emu.ram_size(), 0x6000);
+        assert_eq!(
+            emu.phys_map.find(RAM + 0x5000).unwrap().kind,
+            RegionKind::Ram
+        );
+        emu.memory.write_u64(RAM + 0x5ff8, 7).unwrap();
+
+        // Shrinking refuses pages still in use and leaves everything alone
+        emu.memory.write_u64(RAM + 0x3000, 1).unwrap();
+        let err = emu.resize_ram(0x2000).unwrap_err().to_string();
+        assert!(err.contains("0x45000"), "{}", err);
+        assert_eq!(emu.ram_size(), 0x6000);
+        emu.memory.write_u64(RAM + 0x5ff8, 0).unwrap();
+        assert!(emu
+            .resize_ram(0x2000)
+            .unwrap_err()
+            .to_string()
+            .contains("0x43000"));
+
+        // Once the guest clears them the pages go, whole ranges first
+        emu.memory.write_u64(RAM + 0x3000, 0).unwrap();
+        emu.resize_ram(0x2000).unwrap();
+        assert_eq!(emu.ram_size(), 0x2000);
+        assert!(emu.phys_map.find(RAM + 0x4000).is_none());
+        assert_eq!(emu.phys_map.find(RAM).unwrap().size, 0x2000);
+        assert!(emu.memory.read_u64(RAM + 0x2000).is_err());
+        assert_eq!(emu.memory.read_u64(RAM).unwrap(), 0x04030201);
+
+        // The configured image is never released
+        assert!(emu.resize_ram(0).is_err());
+    }
+
+    #[test]
+    fn test_reset_kinds() {
+        const RESET_SYSTEM: u64 = 0x30000;
+        const RAM: u64 = 0x40000;
+
+        let mut emu = setup(&[encode_mii([nop(), nop(), nop()]); 2]);
+        emu.memory
+            .load_image(RAM, 0x1000, &[1, 2, 3, 4], Permissions::ReadWrite)
+            .unwrap();
+        emu.phys_map
+            .add("ram", RAM, 0x1000, RegionKind::Ram)
+            .unwrap();
+        emu.ram_images.push((RAM, vec![1, 2, 3, 4]));
+        emu.set_reset_service(RESET_SYSTEM);
+        assert_eq!(emu.step().unwrap(), None);
+        emu.memory.write_u64(RAM, u64::MAX).unwrap();
+        emu.memory.write_u64(RAM + 8, 0xaa).unwrap();
+
+        // ResetSystem(EfiResetWarm) keeps RAM and reenters the entry point
+        emu.cpu.ip = RESET_SYSTEM;
+        emu.cpu.gr[32] = EFI_RESET_WARM;
+        assert_eq!(emu.step().unwrap(), None);
+        assert_eq!(emu.resets(), 1);
+        assert_eq!(emu.cpu.ip, BASE);
+        assert_eq!(emu.memory.read_u64(RAM + 8).unwrap(), 0xaa);
+
+        // EfiResetCold clears it, loading the configured image again
+        emu.cpu.ip = RESET_SYSTEM;
+        emu.cpu.gr[32] = 0;
+        assert_eq!(emu.step().unwrap(), None);
+        assert_eq!(emu.resets(), 2);
+        assert_eq!(emu.memory.read_u64(RAM).unwrap(), 0x04030201);
+        assert_eq!(emu.memory.read_u64(RAM + 8).unwrap(), 0);
+        // The program image is not general-purpose RAM
+        let mut bundle = [0; 16];
+        emu.memory.peek_bytes(BASE, &mut bundle).unwrap();
+        assert_eq!(bundle, encode_mii([nop(), nop(), nop()]));
+
+        // EfiResetShutdown ends the run with the status
+        emu.cpu.ip = RESET_SYSTEM;
+        emu.cpu.gr[32..34].copy_from_slice(&[EFI_RESET_SHUTDOWN, 0]);
+        assert_eq!(emu.step().unwrap(), Some(StopReason::Exited(0)));
+    }
+
+    #[test]
+    fn test_uart_interrupts() {
+        use crate::device::uart::*;
+        const MMIO: u64 = 0x80000;
+
+        let mut emu = setup(&[encode_mii([nop(), nop(), nop()]); 4]);
+        let uart = Uart::new("uart");
+        let port = uart.port();
+        let id = emu.attach(MMIO, Box::new(uart)).unwrap();
+        emu.memory.write_u8(MMIO + REG_IIR, FCR_ENABLE).unwrap();
+        emu.memory
+            .write_u8(MMIO + REG_IER, IER_RDA | IER_RLS)
+            .unwrap();
+        emu.cpu
+            .register_interrupt_handler(InterruptVector::ExtInt, 0x4000, 0)
+            .unwrap();
+        emu.cpu.set_interrupts_enabled(true);
+        // The attach interrupt was raised with interrupts still disabled
+        assert_eq!(emu.cpu.check_interrupts(), Some(0x4000));
+        emu.cpu.return_from_interrupt().unwrap();
+        emu.cpu.set_interrupts_enabled(true);
+
+        // Overrunning the FIFO asserts the line once, until the driver
+        // clears the error and drains the data
+        port.send(&[0xAA; FIFO_DEPTH + 1]);
+        assert_eq!(emu.step().unwrap(), None);
+        assert_eq!(emu.cpu.check_interrupts(), Some(0x4000));
+        assert_eq!(
+            emu.cpu.current_interrupt().unwrap().info,
+            id.0 as u64 | DEVICE_INTERRUPT
+        );
+        emu.cpu.return_from_interrupt().unwrap();
+        assert_eq!(emu.step().unwrap(), None);
+        assert_eq!(emu.cpu.check_interrupts(), None);
+
+        let iir = emu.memory.read_u8(MMIO + REG_IIR).unwrap();
+        assert_eq!(iir, IIR_FIFO_ENABLED | IIR_LINE_STATUS);
+        let lsr = emu.memory.read_u8(MMIO + REG_LSR).unwrap();
+        assert_eq!(lsr & (LSR_OE | LSR_DR), LSR_OE | LSR_DR);
+        for _ in 0..FIFO_DEPTH {
+            assert_eq!(emu.memory.read_u8(MMIO + REG_DATA).unwrap(), 0xAA);
+        }
+        let iir = emu.memory.read_u8(MMIO + REG_IIR).unwrap();
+        assert_eq!(iir, IIR_FIFO_ENABLED | IIR_NONE);
+
+        // The next character raises a fresh interrupt
+        assert_eq!(emu.step().unwrap(), None);
+        port.send(b"!");
+        assert_eq!(emu.step().unwrap(), None);
+        assert_eq!(emu.cpu.check_interrupts(), Some(0x4000));
+    }
+}
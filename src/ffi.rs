@@ -0,0 +1,703 @@
+//! C bindings for embedding the emulator
+//!
+//! Frontends written in C, C++ or anything else with a C FFI drive a
+//! machine through an opaque [`Ia64Emulator`] handle: create it, load an
+//! image, step or run it, read and write its registers and memory, and
+//! register callbacks for console output and stops. Build the shared
+//! library and its header with the `ffi` feature:
+//!
+//! ```text
+//! cargo build --release --features ffi
+//! ```
+//!
+//! The build generates the header from this module with cbindgen, into
+//! its `OUT_DIR`; the copy kept in the repository as `include/rust_ia64.h`
+//! is checked against it by the tests, whose failure names the generated
+//! file to copy over it.
+//!
+//! Conventions:
+//!
+//! - Every function and type is prefixed `ia64_` or `Ia64`. Signatures only
+//!   change in ways a caller compiled against an older header keeps
+//!   working with; anything else bumps [`IA64_FFI_VERSION`], which
+//!   [`ia64_version`] reports at run time.
+//! - Functions return an [`Ia64Status`]. After [`Ia64Status::Error`],
+//!   [`ia64_last_error`] describes the failure until the next call on the
+//!   same handle. Rust panics are caught and reported as errors.
+//! - A handle may move between threads but must not be used from two at
+//!   once. Callbacks run on the thread calling [`ia64_step`] or
+//!   [`ia64_run`], between bundles, and must not call back into the same
+//!   handle.
+//! - Registers are named as the debugger prints them: `ip`, `r8`, `f2`,
+//!   `b0`, `pr`, `ar.bsp`.
+
+use crate::console;
+use crate::cpu::syscall::SyscallNumber;
+use crate::emulator::{Emulator, StopReason};
+use crate::golden::{self, Register};
+use crate::EmulatorError;
+use std::ffi::{c_char, c_void, CStr, CString};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Mutex};
+
+/// Version of the C interface, bumped on incompatible changes
+pub const IA64_FFI_VERSION: u32 = 1;
+
+/// Outcome of a call
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Ia64Status {
+    /// The call succeeded
+    Ok = 0,
+    /// The call failed; see [`ia64_last_error`]
+    Error = 1,
+    /// A required pointer was null or a string was not UTF-8
+    InvalidArgument = 2,
+}
+
+/// Why the machine stopped
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Ia64StopKind {
+    /// The machine has not stopped, e.g. a run hit its bundle limit
+    None = 0,
+    /// The guest called exit; `code` is the status
+    Exited = 1,
+    /// The guest executed a break that is not a system call; `code` is its
+    /// immediate
+    Break = 2,
+    /// The guest reached a panic hook
+    Panic = 3,
+    /// The guest looped without making progress
+    Livelock = 4,
+    /// A data debug register matched; `addr` is the address accessed and
+    /// `code` the value
+    DataMatch = 5,
+    /// A watchdog set to stop the machine expired
+    WatchdogExpired = 6,
+}
+
+/// Stop of the machine
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Ia64Stop {
+    /// Why the machine stopped
+    pub kind: Ia64StopKind,
+    /// Exit status, break immediate or matched value, by kind
+    pub code: u64,
+    /// Address of a data match
+    pub addr: u64,
+}
+
+impl Ia64Stop {
+    /// No stop
+    const NONE: Self = Self {
+        kind: Ia64StopKind::None,
+        code: 0,
+        addr: 0,
+    };
+}
+
+impl From<StopReason> for Ia64Stop {
+    fn from(reason: StopReason) -> Self {
+        let (kind, code, addr) = match reason {
+            StopReason::Exited(status) => (Ia64StopKind::Exited, status as i64 as u64, 0),
+            StopReason::Break(imm) => (Ia64StopKind::Break, imm, 0),
+            StopReason::Panic => (Ia64StopKind::Panic, 0, 0),
+            StopReason::Livelock => (Ia64StopKind::Livelock, 0, 0),
+            StopReason::DataMatch { addr, value } => (Ia64StopKind::DataMatch, value, addr),
+            StopReason::WatchdogExpired => (Ia64StopKind::WatchdogExpired, 0, 0),
+        };
+        Self { kind, code, addr }
+    }
+}
+
+/// Callback receiving what the guest writes to descriptor `fd`, 1 or 2
+pub type Ia64ConsoleCallback =
+    Option<unsafe extern "C" fn(user: *mut c_void, fd: i32, data: *const u8, len: usize)>;
+
+/// Callback told of each stop of the machine
+pub type Ia64StopCallback = Option<unsafe extern "C" fn(user: *mut c_void, stop: *const Ia64Stop)>;
+
+/// Console writes of the guest, by descriptor
+type ConsoleWrites = Arc<Mutex<Vec<(i32, Vec<u8>)>>>;
+
+/// Machine driven through the C interface
+pub struct Ia64Emulator {
+    /// The machine
+    emulator: Emulator,
+    /// Console writes not yet passed to the callback, by descriptor
+    output: ConsoleWrites,
+    /// Console callback and its user pointer
+    on_console: (Ia64ConsoleCallback, *mut c_void),
+    /// Stop callback and its user pointer
+    on_stop: (Ia64StopCallback, *mut c_void),
+    /// Description of the last failure
+    error: Option<CString>,
+}
+
+impl Ia64Emulator {
+    /// Machine with the console writes of its guest captured
+    fn new() -> Self {
+        let mut emulator = Emulator::new();
+        let output = ConsoleWrites::default();
+        for number in [SyscallNumber::Write, SyscallNumber::Writev] {
+            let Some(handler) = emulator.cpu.syscall_mgr.take_handler(number) else {
+                continue;
+            };
+            let output = Arc::clone(&output);
+            emulator
+                .cpu
+                .syscall_mgr
+                .register_handler(number, move |cpu, context| {
+                    let fd = context.params[0];
+                    if matches!(fd, 1 | 2) {
+                        let data = console::written(cpu, context, number);
+                        output.lock().unwrap().push((fd as i32, data));
+                    }
+                    handler(cpu, context)
+                });
+        }
+        Self {
+            emulator,
+            output,
+            on_console: (None, std::ptr::null_mut()),
+            on_stop: (None, std::ptr::null_mut()),
+            error: None,
+        }
+    }
+
+    /// Pass captured console output to the callback
+    fn flush_console(&mut self) {
+        let writes = std::mem::take(&mut *self.output.lock().unwrap());
+        if let (Some(callback), user) = self.on_console {
+            for (fd, data) in writes {
+                // SAFETY: the caller registered the callback for this user
+                // pointer, and the data outlives the call
+                unsafe { callback(user, fd, data.as_ptr(), data.len()) };
+            }
+        }
+    }
+
+    /// Execute one bundle, passing on console output and a stop
+    fn step(&mut self) -> Result<Option<Ia64Stop>, EmulatorError> {
+        let result = self.emulator.step();
+        self.flush_console();
+        let Some(stop) = result?.map(Ia64Stop::from) else {
+            return Ok(None);
+        };
+        if let (Some(callback), user) = self.on_stop {
+            // SAFETY: as for the console callback
+            unsafe { callback(user, &stop) };
+        }
+        Ok(Some(stop))
+    }
+}
+
+/// Run `f` on the handle behind `emu`, catching panics and recording a
+/// failure for [`ia64_last_error`]
+///
+/// # Safety
+///
+/// `emu` must be null or a live handle from [`ia64_emulator_new`].
+unsafe fn with_emulator(
+    emu: *mut Ia64Emulator,
+    f: impl FnOnce(&mut Ia64Emulator) -> Result<(), EmulatorError>,
+) -> Ia64Status {
+    let Some(emu) = emu.as_mut() else {
+        return Ia64Status::InvalidArgument;
+    };
+    emu.error = None;
+    let message = match panic::catch_unwind(AssertUnwindSafe(|| f(emu))) {
+        Ok(Ok(())) => return Ia64Status::Ok,
+        Ok(Err(error)) => error.to_string(),
+        Err(panic) => match panic.downcast_ref::<&str>() {
+            Some(message) => format!("panic: {}", message),
+            None => match panic.downcast_ref::<String>() {
+                Some(message) => format!("panic: {}", message),
+                None => "panic".to_string(),
+            },
+        },
+    };
+    emu.error = Some(CString::new(message.replace('\0', " ")).unwrap_or_default());
+    Ia64Status::Error
+}
+
+/// Register named by the C string `name`
+///
+/// # Safety
+///
+/// `name` must be a NUL-terminated string.
+unsafe fn register_name(name: *const c_char) -> Option<Result<Register, EmulatorError>> {
+    let name = CStr::from_ptr(name).to_str().ok()?;
+    Some(name.parse())
+}
+
+/// Version of the C interface the library implements
+#[no_mangle]
+pub extern "C" fn ia64_version() -> u32 {
+    IA64_FFI_VERSION
+}
+
+/// Create a machine with empty memory
+///
+/// Free it with [`ia64_emulator_free`].
+#[no_mangle]
+pub extern "C" fn ia64_emulator_new() -> *mut Ia64Emulator {
+    Box::into_raw(Box::new(Ia64Emulator::new()))
+}
+
+/// Free a machine
+///
+/// # Safety
+///
+/// `emu` must be null or a handle from [`ia64_emulator_new`] that has not
+/// been freed.
+#[no_mangle]
+pub unsafe extern "C" fn ia64_emulator_free(emu: *mut Ia64Emulator) {
+    if !emu.is_null() {
+        drop(Box::from_raw(emu));
+    }
+}
+
+/// Description of the last failure on `emu`, or null
+///
+/// The string stays valid until the next call on the handle.
+///
+/// # Safety
+///
+/// `emu` must be null or a live handle.
+#[no_mangle]
+pub unsafe extern "C" fn ia64_last_error(emu: *const Ia64Emulator) -> *const c_char {
+    match emu.as_ref().and_then(|emu| emu.error.as_ref()) {
+        Some(error) => error.as_ptr(),
+        None => std::ptr::null(),
+    }
+}
+
+/// Load an ELF executable of `len` bytes and start execution at its entry
+/// point, relocating a position-independent image to `base`
+///
+/// # Safety
+///
+/// `emu` must be a live handle and `data` point to `len` readable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn ia64_load_elf(
+    emu: *mut Ia64Emulator,
+    data: *const u8,
+    len: usize,
+    base: u64,
+) -> Ia64Status {
+    if data.is_null() {
+        return Ia64Status::InvalidArgument;
+    }
+    let image = std::slice::from_raw_parts(data, len);
+    with_emulator(emu, |emu| emu.emulator.load_elf(image, base).map(|_| ()))
+}
+
+/// Load a flat binary image of `len` bytes at `base` and start execution
+/// at `entry`
+///
+/// # Safety
+///
+/// `emu` must be a live handle and `data` point to `len` readable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn ia64_load_flat_image(
+    emu: *mut Ia64Emulator,
+    base: u64,
+    data: *const u8,
+    len: usize,
+    entry: u64,
+) -> Ia64Status {
+    if data.is_null() {
+        return Ia64Status::InvalidArgument;
+    }
+    let image = std::slice::from_raw_parts(data, len);
+    with_emulator(emu, |emu| emu.emulator.load_flat_image(base, image, entry))
+}
+
+/// Map the stack and place the guest's `argc` arguments on it, after
+/// loading an image
+///
+/// # Safety
+///
+/// `emu` must be a live handle and `argv` point to `argc` NUL-terminated
+/// strings.
+#[no_mangle]
+pub unsafe extern "C" fn ia64_setup_process(
+    emu: *mut Ia64Emulator,
+    argv: *const *const c_char,
+    argc: usize,
+) -> Ia64Status {
+    if argv.is_null() && argc > 0 {
+        return Ia64Status::InvalidArgument;
+    }
+    let mut args = Vec::with_capacity(argc);
+    for i in 0..argc {
+        let arg = *argv.add(i);
+        if arg.is_null() {
+            return Ia64Status::InvalidArgument;
+        }
+        match CStr::from_ptr(arg).to_str() {
+            Ok(arg) => args.push(arg.to_string()),
+            Err(_) => return Ia64Status::InvalidArgument,
+        }
+    }
+    with_emulator(emu, |emu| emu.emulator.setup_process(&args, &[]))
+}
+
+/// Execute the bundle at the instruction pointer
+///
+/// `stop`, if not null, receives the stop the bundle caused, of kind
+/// [`Ia64StopKind::None`] if it did not stop the machine.
+///
+/// # Safety
+///
+/// `emu` must be a live handle and `stop` null or writable.
+#[no_mangle]
+pub unsafe extern "C" fn ia64_step(emu: *mut Ia64Emulator, stop: *mut Ia64Stop) -> Ia64Status {
+    with_emulator(emu, |emu| {
+        let result = emu.step()?.unwrap_or(Ia64Stop::NONE);
+        if let Some(stop) = stop.as_mut() {
+            *stop = result;
+        }
+        Ok(())
+    })
+}
+
+/// Run until the machine stops or, if `max_bundles` is not 0, that many
+/// bundles have executed
+///
+/// `stop`, if not null, receives the stop, of kind [`Ia64StopKind::None`]
+/// if the limit was reached first.
+///
+/// # Safety
+///
+/// `emu` must be a live handle and `stop` null or writable.
+#[no_mangle]
+pub unsafe extern "C" fn ia64_run(
+    emu: *mut Ia64Emulator,
+    max_bundles: u64,
+    stop: *mut Ia64Stop,
+) -> Ia64Status {
+    with_emulator(emu, |emu| {
+        let mut executed = 0;
+        let result = loop {
+            if max_bundles != 0 && executed == max_bundles {
+                break Ia64Stop::NONE;
+            }
+            if let Some(stop) = emu.step()? {
+                break stop;
+            }
+            executed += 1;
+        };
+        if let Some(stop) = stop.as_mut() {
+            *stop = result;
+        }
+        Ok(())
+    })
+}
+
+/// Read the register `name` into `value`
+///
+/// Floating-point registers read as the bits of their double value, and
+/// `pr` as the 64 predicates.
+///
+/// # Safety
+///
+/// `emu` must be a live handle, `name` a NUL-terminated string and `value`
+/// writable.
+#[no_mangle]
+pub unsafe extern "C" fn ia64_read_register(
+    emu: *mut Ia64Emulator,
+    name: *const c_char,
+    value: *mut u64,
+) -> Ia64Status {
+    if name.is_null() || value.is_null() {
+        return Ia64Status::InvalidArgument;
+    }
+    let Some(register) = register_name(name) else {
+        return Ia64Status::InvalidArgument;
+    };
+    with_emulator(emu, |emu| {
+        *value = golden::register(&emu.emulator.cpu, register?)?;
+        Ok(())
+    })
+}
+
+/// Write `value` to the register `name`
+///
+/// Application registers are written as by `mov ar`.
+///
+/// # Safety
+///
+/// `emu` must be a live handle and `name` a NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn ia64_write_register(
+    emu: *mut Ia64Emulator,
+    name: *const c_char,
+    value: u64,
+) -> Ia64Status {
+    if name.is_null() {
+        return Ia64Status::InvalidArgument;
+    }
+    let Some(register) = register_name(name) else {
+        return Ia64Status::InvalidArgument;
+    };
+    with_emulator(emu, |emu| {
+        golden::set_register(&mut emu.emulator.cpu, register?, value)
+    })
+}
+
+/// Read `len` bytes of guest memory at `addr` into `data`
+///
+/// Reads leave the caches and devices alone.
+///
+/// # Safety
+///
+/// `emu` must be a live handle and `data` point to `len` writable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn ia64_read_memory(
+    emu: *mut Ia64Emulator,
+    addr: u64,
+    data: *mut u8,
+    len: usize,
+) -> Ia64Status {
+    if data.is_null() {
+        return Ia64Status::InvalidArgument;
+    }
+    let data = std::slice::from_raw_parts_mut(data, len);
+    with_emulator(emu, |emu| emu.emulator.memory.peek_bytes(addr, data))
+}
+
+/// Write `len` bytes from `data` to guest memory at `addr`
+///
+/// # Safety
+///
+/// `emu` must be a live handle and `data` point to `len` readable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn ia64_write_memory(
+    emu: *mut Ia64Emulator,
+    addr: u64,
+    data: *const u8,
+    len: usize,
+) -> Ia64Status {
+    if data.is_null() {
+        return Ia64Status::InvalidArgument;
+    }
+    let data = std::slice::from_raw_parts(data, len);
+    with_emulator(emu, |emu| emu.emulator.memory.write_block(addr, data))
+}
+
+/// Pass what the guest writes to standard output and standard error to
+/// `callback`, or stop passing it on if `callback` is null
+///
+/// `user` is handed to each call.
+///
+/// # Safety
+///
+/// `emu` must be a live handle, and `callback` safe to call with `user`
+/// while it is registered.
+#[no_mangle]
+pub unsafe extern "C" fn ia64_set_console_callback(
+    emu: *mut Ia64Emulator,
+    callback: Ia64ConsoleCallback,
+    user: *mut c_void,
+) -> Ia64Status {
+    with_emulator(emu, |emu| {
+        emu.on_console = (callback, user);
+        Ok(())
+    })
+}
+
+/// Tell `callback` of each stop of the machine, or stop telling it if
+/// `callback` is null
+///
+/// `user` is handed to each call.
+///
+/// # Safety
+///
+/// `emu` must be a live handle, and `callback` safe to call with `user`
+/// while it is registered.
+#[no_mangle]
+pub unsafe extern "C" fn ia64_set_stop_callback(
+    emu: *mut Ia64Emulator,
+    callback: Ia64StopCallback,
+    user: *mut c_void,
+) -> Ia64Status {
+    with_emulator(emu, |emu| {
+        emu.on_stop = (callback, user);
+        Ok(())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm::BundleBuilder;
+    use crate::emulator::{SYSCALL_BREAK_IMM, SYSCALL_NUMBER_REG};
+
+    const BASE: u64 = 0x10000;
+
+    /// Console output and stops seen by the callbacks
+    #[derive(Default)]
+    struct Seen {
+        output: Vec<(i32, Vec<u8>)>,
+        stops: Vec<Ia64Stop>,
+    }
+
+    unsafe extern "C" fn on_console(user: *mut c_void, fd: i32, data: *const u8, len: usize) {
+        let seen = &mut *(user as *mut Seen);
+        seen.output
+            .push((fd, std::slice::from_raw_parts(data, len).to_vec()));
+    }
+
+    unsafe extern "C" fn on_stop(user: *mut c_void, stop: *const Ia64Stop) {
+        let seen = &mut *(user as *mut Seen);
+        seen.stops.push(*stop);
+    }
+
+    /// Program making the system call in r15, then breaking
+    fn program() -> Vec<u8> {
+        [
+            BundleBuilder::new()
+                .insn(&format!("break.m {:#x}", SYSCALL_BREAK_IMM))
+                .insn("nop.i 0x0")
+                .insn("nop.i 0x0 ;;"),
+            BundleBuilder::new()
+                .insn("break.m 0x77")
+                .insn("nop.i 0x0")
+                .insn("nop.i 0x0 ;;"),
+        ]
+        .into_iter()
+        .enumerate()
+        .flat_map(|(i, bundle)| bundle.at(BASE + 16 * i as u64).build().unwrap())
+        .collect()
+    }
+
+    fn name(text: &str) -> CString {
+        CString::new(text).unwrap()
+    }
+
+    #[test]
+    fn test_session() {
+        unsafe {
+            let emu = ia64_emulator_new();
+            let mut seen = Seen::default();
+            let user = &mut seen as *mut Seen as *mut c_void;
+            assert_eq!(ia64_version(), IA64_FFI_VERSION);
+            assert_eq!(
+                ia64_set_console_callback(emu, Some(on_console), user),
+                Ia64Status::Ok
+            );
+            assert_eq!(
+                ia64_set_stop_callback(emu, Some(on_stop), user),
+                Ia64Status::Ok
+            );
+
+            let image = program();
+            assert_eq!(
+                ia64_load_flat_image(emu, BASE, image.as_ptr(), image.len(), BASE),
+                Ia64Status::Ok
+            );
+            let text = b"hello";
+            assert_eq!(
+                ia64_write_memory(emu, BASE + 0x800, text.as_ptr(), text.len()),
+                Ia64Status::Ok
+            );
+            let registers = [
+                (
+                    format!("r{}", SYSCALL_NUMBER_REG),
+                    SyscallNumber::Write as u64,
+                ),
+                ("r32".to_string(), 1),
+                ("r33".to_string(), BASE + 0x800),
+                ("r34".to_string(), text.len() as u64),
+            ];
+            for (register, value) in registers {
+                let register = name(&register);
+                assert_eq!(
+                    ia64_write_register(emu, register.as_ptr(), value),
+                    Ia64Status::Ok
+                );
+            }
+
+            // The system call bundle writes to the console without stopping
+            let mut stop = Ia64Stop::NONE;
+            assert_eq!(ia64_step(emu, &mut stop), Ia64Status::Ok);
+            assert_eq!(stop.kind, Ia64StopKind::None);
+            assert_eq!(seen.output, [(1, b"hello".to_vec())]);
+            let mut value = 0;
+            assert_eq!(
+                ia64_read_register(emu, name("r8").as_ptr(), &mut value),
+                Ia64Status::Ok
+            );
+            assert_eq!(value, 5);
+
+            // then the break stops the machine
+            assert_eq!(ia64_run(emu, 0, &mut stop), Ia64Status::Ok);
+            assert_eq!((stop.kind, stop.code), (Ia64StopKind::Break, 0x77));
+            assert_eq!(seen.stops, [stop]);
+            let mut data = [0; 5];
+            assert_eq!(
+                ia64_read_memory(emu, BASE + 0x800, data.as_mut_ptr(), data.len()),
+                Ia64Status::Ok
+            );
+            assert_eq!(&data, text);
+
+            ia64_emulator_free(emu);
+        }
+    }
+
+    #[test]
+    fn test_errors() {
+        unsafe {
+            let emu = ia64_emulator_new();
+            assert!(ia64_last_error(emu).is_null());
+            let mut value = 0;
+            assert_eq!(
+                ia64_read_register(emu, name("r999").as_ptr(), &mut value),
+                Ia64Status::Error
+            );
+            let error = CStr::from_ptr(ia64_last_error(emu));
+            assert!(!error.to_bytes().is_empty());
+
+            // Unmapped memory fails; a successful call clears the error
+            let mut data = [0; 4];
+            assert_eq!(
+                ia64_read_memory(emu, 0xdead_0000, data.as_mut_ptr(), data.len()),
+                Ia64Status::Error
+            );
+            assert_eq!(
+                ia64_read_register(emu, name("ip").as_ptr(), &mut value),
+                Ia64Status::Ok
+            );
+            assert!(ia64_last_error(emu).is_null());
+
+            // Null handles and pointers are rejected
+            assert_eq!(
+                ia64_step(std::ptr::null_mut(), std::ptr::null_mut()),
+                Ia64Status::InvalidArgument
+            );
+            assert_eq!(
+                ia64_read_register(emu, std::ptr::null(), &mut value),
+                Ia64Status::InvalidArgument
+            );
+            ia64_emulator_free(emu);
+            ia64_emulator_free(std::ptr::null_mut());
+        }
+    }
+
+    #[test]
+    fn test_header_up_to_date() {
+        assert!(
+            include_str!(concat!(env!("OUT_DIR"), "/rust_ia64.h"))
+                == include_str!("../include/rust_ia64.h"),
+            concat!(
+                "include/rust_ia64.h is stale; copy ",
+                env!("OUT_DIR"),
+                "/rust_ia64.h over it"
+            )
+        );
+    }
+}
@@ -0,0 +1,480 @@
+//! Physical memory map
+//!
+//! The loader and devices register the physical ranges they occupy: RAM,
+//! MMIO windows, firmware code and data, ACPI tables. The map keeps them in
+//! address order, rejects conflicting registrations and finds the holes
+//! between them, and renders the EFI memory descriptor list handed to the
+//! operating system as well as a table for diagnostics.
+//!
+//! Registration is ordered: RAM goes in first, and firmware, loader and
+//! ACPI ranges registered later inside a RAM range are carved out of it.
+//! Any other overlap is a conflict.
+
+use crate::EmulatorError;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::ops::Range;
+
+/// EFI page size; every range is a whole number of pages
+pub const EFI_PAGE_SIZE: u64 = 4096;
+
+/// Bytes per descriptor in the rendered EFI memory map
+///
+/// Larger than the 40 bytes of the structure, as on most firmware, so
+/// consumers must step by the reported descriptor size.
+pub const EFI_DESCRIPTOR_SIZE: usize = 48;
+
+/// Version of the EFI memory descriptor format
+pub const EFI_DESCRIPTOR_VERSION: u32 = 1;
+
+/// Uncacheable
+pub const EFI_MEMORY_UC: u64 = 0x1;
+/// Write-combining
+pub const EFI_MEMORY_WC: u64 = 0x2;
+/// Write-through
+pub const EFI_MEMORY_WT: u64 = 0x4;
+/// Write-back
+pub const EFI_MEMORY_WB: u64 = 0x8;
+/// Needs a virtual mapping for runtime services
+pub const EFI_MEMORY_RUNTIME: u64 = 1 << 63;
+
+/// Use of a physical range
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum RegionKind {
+    /// General-purpose RAM
+    #[default]
+    Ram,
+    /// Code of a loaded image
+    LoaderCode,
+    /// Data of a loaded image
+    LoaderData,
+    /// Firmware runtime code
+    FirmwareCode,
+    /// Firmware runtime data
+    FirmwareData,
+    /// Processor abstraction layer code
+    PalCode,
+    /// ACPI tables, reclaimable once parsed
+    AcpiReclaim,
+    /// ACPI non-volatile storage
+    AcpiNvs,
+    /// Memory-mapped device registers
+    Mmio,
+    /// Memory-mapped I/O port space
+    IoPortSpace,
+    /// Reserved, not usable by the operating system
+    Reserved,
+}
+
+impl RegionKind {
+    /// EFI memory type
+    pub fn efi_type(self) -> u32 {
+        match self {
+            RegionKind::Reserved => 0,
+            RegionKind::LoaderCode => 1,
+            RegionKind::LoaderData => 2,
+            RegionKind::FirmwareCode => 5,
+            RegionKind::FirmwareData => 6,
+            RegionKind::Ram => 7,
+            RegionKind::AcpiReclaim => 9,
+            RegionKind::AcpiNvs => 10,
+            RegionKind::Mmio => 11,
+            RegionKind::IoPortSpace => 12,
+            RegionKind::PalCode => 13,
+        }
+    }
+
+    /// EFI memory attributes
+    pub fn efi_attributes(self) -> u64 {
+        const CACHEABLE: u64 = EFI_MEMORY_UC | EFI_MEMORY_WC | EFI_MEMORY_WT | EFI_MEMORY_WB;
+        match self {
+            RegionKind::Ram | RegionKind::LoaderCode | RegionKind::LoaderData => CACHEABLE,
+            RegionKind::AcpiReclaim | RegionKind::AcpiNvs | RegionKind::Reserved => CACHEABLE,
+            RegionKind::FirmwareCode | RegionKind::FirmwareData | RegionKind::PalCode => {
+                EFI_MEMORY_WB | EFI_MEMORY_RUNTIME
+            }
+            RegionKind::Mmio | RegionKind::IoPortSpace => EFI_MEMORY_UC | EFI_MEMORY_RUNTIME,
+        }
+    }
+
+    /// Name shown in the diagnostic table
+    pub fn name(self) -> &'static str {
+        match self {
+            RegionKind::Ram => "ram",
+            RegionKind::LoaderCode => "loader-code",
+            RegionKind::LoaderData => "loader-data",
+            RegionKind::FirmwareCode => "firmware-code",
+            RegionKind::FirmwareData => "firmware-data",
+            RegionKind::PalCode => "pal-code",
+            RegionKind::AcpiReclaim => "acpi-reclaim",
+            RegionKind::AcpiNvs => "acpi-nvs",
+            RegionKind::Mmio => "mmio",
+            RegionKind::IoPortSpace => "io-port-space",
+            RegionKind::Reserved => "reserved",
+        }
+    }
+
+    /// Check if ranges of this kind may be carved out of RAM
+    fn carves_ram(self) -> bool {
+        !matches!(
+            self,
+            RegionKind::Ram | RegionKind::Mmio | RegionKind::IoPortSpace
+        )
+    }
+}
+
+impl fmt::Display for RegionKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Registered physical range
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PhysRegion {
+    /// Name of the owner, e.g. the device or image
+    pub name: String,
+    /// First address
+    pub base: u64,
+    /// Size in bytes
+    pub size: u64,
+    /// Use of the range
+    pub kind: RegionKind,
+}
+
+impl PhysRegion {
+    /// Address one past the end
+    pub fn end(&self) -> u64 {
+        self.base + self.size
+    }
+}
+
+/// One entry of the EFI memory map
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EfiMemoryDescriptor {
+    /// EFI memory type
+    pub memory_type: u32,
+    /// First physical address
+    pub physical_start: u64,
+    /// First virtual address, zero until the OS sets a virtual map
+    pub virtual_start: u64,
+    /// Size in EFI pages
+    pub number_of_pages: u64,
+    /// EFI memory attributes
+    pub attribute: u64,
+}
+
+impl EfiMemoryDescriptor {
+    /// Little-endian encoding, padded to [`EFI_DESCRIPTOR_SIZE`]
+    pub fn to_bytes(&self) -> [u8; EFI_DESCRIPTOR_SIZE] {
+        let mut bytes = [0; EFI_DESCRIPTOR_SIZE];
+        bytes[0..4].copy_from_slice(&self.memory_type.to_le_bytes());
+        bytes[8..16].copy_from_slice(&self.physical_start.to_le_bytes());
+        bytes[16..24].copy_from_slice(&self.virtual_start.to_le_bytes());
+        bytes[24..32].copy_from_slice(&self.number_of_pages.to_le_bytes());
+        bytes[32..40].copy_from_slice(&self.attribute.to_le_bytes());
+        bytes
+    }
+}
+
+/// Machine physical memory map
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PhysMemoryMap {
+    /// Non-overlapping ranges by base address
+    regions: BTreeMap<u64, PhysRegion>,
+}
+
+impl PhysMemoryMap {
+    /// Create an empty map
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register a range
+    ///
+    /// A range other than RAM or I/O lying wholly inside one RAM range is
+    /// carved out of it; any other overlap is an error naming both owners.
+    pub fn add(
+        &mut self,
+        name: &str,
+        base: u64,
+        size: u64,
+        kind: RegionKind,
+    ) -> Result<(), EmulatorError> {
+        if size == 0 || !base.is_multiple_of(EFI_PAGE_SIZE) || !size.is_multiple_of(EFI_PAGE_SIZE) {
+            return Err(EmulatorError::MemoryError(format!(
+                "{}: range {:#x}+{:#x} is not a non-empty run of whole pages",
+                name, base, size
+            )));
+        }
+        let end = base.checked_add(size).ok_or_else(|| {
+            EmulatorError::MemoryError(format!(
+                "{}: range {:#x}+{:#x} extends past the end of the address space",
+                name, base, size
+            ))
+        })?;
+
+        let overlapping: Vec<PhysRegion> = self
+            .regions
+            .range(..end)
+            .rev()
+            .map(|(_, region)| region)
+            .take_while(|region| region.end() > base)
+            .cloned()
+            .collect();
+
+        match overlapping.as_slice() {
+            [] => {}
+            [ram]
+                if ram.kind == RegionKind::Ram
+                    && kind.carves_ram()
+                    && ram.base <= base
+                    && end <= ram.end() =>
+            {
+                self.regions.remove(&ram.base);
+                if ram.base < base {
+                    self.insert(&ram.name, ram.base, base - ram.base, RegionKind::Ram);
+                }
+                if end < ram.end() {
+                    self.insert(&ram.name, end, ram.end() - end, RegionKind::Ram);
+                }
+            }
+            [.., first] => {
+                return Err(EmulatorError::MemoryError(format!(
+                    "{} {} [{:#x}, {:#x}) conflicts with {} {} [{:#x}, {:#x})",
+                    kind,
+                    name,
+                    base,
+                    end,
+                    first.kind,
+                    first.name,
+                    first.base,
+                    first.end()
+                )))
+            }
+        }
+
+        self.insert(name, base, size, kind);
+        Ok(())
+    }
+
+    /// Unregister the range starting at `base`
+    ///
+    /// Ranges carved out of RAM leave a hole rather than returning to RAM.
+    pub fn remove(&mut self, base: u64) -> Option<PhysRegion> {
+        self.regions.remove(&base)
+    }
+
+    /// Insert a range known not to overlap
+    fn insert(&mut self, name: &str, base: u64, size: u64, kind: RegionKind) {
+        self.regions.insert(
+            base,
+            PhysRegion {
+                name: name.to_string(),
+                base,
+                size,
+                kind,
+            },
+        );
+    }
+
+    /// Registered ranges in address order
+    pub fn regions(&self) -> impl Iterator<Item = &PhysRegion> {
+        self.regions.values()
+    }
+
+    /// Range containing an address
+    pub fn find(&self, addr: u64) -> Option<&PhysRegion> {
+        self.regions
+            .range(..=addr)
+            .next_back()
+            .map(|(_, region)| region)
+            .filter(|region| addr < region.end())
+    }
+
+    /// Unregistered gaps between the lowest and highest ranges
+    pub fn holes(&self) -> Vec<Range<u64>> {
+        self.regions
+            .values()
+            .zip(self.regions.values().skip(1))
+            .filter(|(prev, next)| prev.end() < next.base)
+            .map(|(prev, next)| prev.end()..next.base)
+            .collect()
+    }
+
+    /// EFI memory map, with adjacent ranges of the same kind merged
+    ///
+    /// Holes have no descriptor.
+    pub fn efi_memory_map(&self) -> Vec<EfiMemoryDescriptor> {
+        let mut map: Vec<EfiMemoryDescriptor> = Vec::new();
+        let mut end = 0;
+        for region in self.regions.values() {
+            let memory_type = region.kind.efi_type();
+            let pages = region.size / EFI_PAGE_SIZE;
+            match map.last_mut() {
+                Some(last) if last.memory_type == memory_type && end == region.base => {
+                    last.number_of_pages += pages
+                }
+                _ => map.push(EfiMemoryDescriptor {
+                    memory_type,
+                    physical_start: region.base,
+                    virtual_start: 0,
+                    number_of_pages: pages,
+                    attribute: region.kind.efi_attributes(),
+                }),
+            }
+            end = region.end();
+        }
+        map
+    }
+
+    /// EFI memory map as the byte buffer returned by GetMemoryMap
+    pub fn efi_memory_map_bytes(&self) -> Vec<u8> {
+        self.efi_memory_map()
+            .iter()
+            .flat_map(|descriptor| descriptor.to_bytes())
+            .collect()
+    }
+}
+
+/// Size with a binary unit, for the diagnostic table
+fn format_size(size: u64) -> String {
+    const UNITS: [(u64, &str); 3] = [(1 << 30, "G"), (1 << 20, "M"), (1 << 10, "K")];
+    UNITS
+        .iter()
+        .find(|(unit, _)| size.is_multiple_of(*unit))
+        .map(|(unit, suffix)| format!("{}{}", size / unit, suffix))
+        .unwrap_or_else(|| size.to_string())
+}
+
+impl fmt::Display for PhysMemoryMap {
+    /// Table of the ranges and the holes between them
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut prev_end = None;
+        for region in self.regions.values() {
+            if let Some(end) = prev_end.filter(|&end| end < region.base) {
+                writeln!(
+                    f,
+                    "{:#018x}-{:#018x} {:>8} hole",
+                    end,
+                    region.base - 1,
+                    format_size(region.base - end)
+                )?;
+            }
+            writeln!(
+                f,
+                "{:#018x}-{:#018x} {:>8} {:<14} {}",
+                region.base,
+                region.end() - 1,
+                format_size(region.size),
+                region.kind,
+                region.name
+            )?;
+            prev_end = Some(region.end());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MIB: u64 = 1 << 20;
+
+    fn machine_map() -> PhysMemoryMap {
+        let mut map = PhysMemoryMap::new();
+        map.add("dimm0", 0, 64 * MIB, RegionKind::Ram).unwrap();
+        map.add("dimm1", 64 * MIB, 64 * MIB, RegionKind::Ram)
+            .unwrap();
+        map.add("dimm2", 256 * MIB, 64 * MIB, RegionKind::Ram)
+            .unwrap();
+        map.add("uart", 0xff00_0000, 0x1000, RegionKind::Mmio)
+            .unwrap();
+        map.add("pal", 0x10_0000, 0x10_0000, RegionKind::PalCode)
+            .unwrap();
+        map.add("acpi", 64 * MIB - 0x1000, 0x1000, RegionKind::AcpiReclaim)
+            .unwrap();
+        map
+    }
+
+    #[test]
+    fn test_ordered_buildup() {
+        let map = machine_map();
+        let regions: Vec<_> = map
+            .regions()
+            .map(|region| (region.base, region.size, region.kind))
+            .collect();
+        assert_eq!(
+            regions,
+            [
+                (0, 0x10_0000, RegionKind::Ram),
+                (0x10_0000, 0x10_0000, RegionKind::PalCode),
+                (0x20_0000, 62 * MIB - 0x1000, RegionKind::Ram),
+                (64 * MIB - 0x1000, 0x1000, RegionKind::AcpiReclaim),
+                (64 * MIB, 64 * MIB, RegionKind::Ram),
+                (256 * MIB, 64 * MIB, RegionKind::Ram),
+                (0xff00_0000, 0x1000, RegionKind::Mmio),
+            ]
+        );
+        assert_eq!(map.find(0x10_0800).unwrap().name, "pal");
+        assert_eq!(map.find(0x20_0000).unwrap().name, "dimm0");
+        assert!(map.find(128 * MIB).is_none());
+        assert_eq!(map.holes(), [128 * MIB..256 * MIB, 320 * MIB..0xff00_0000]);
+    }
+
+    #[test]
+    fn test_conflicts() {
+        let mut map = machine_map();
+        let before = map.clone();
+
+        // MMIO inside RAM, overlapping banks, straddling a carve-out
+        let error = map.add("vga", 0xa_0000, 0x2_0000, RegionKind::Mmio);
+        assert!(error.unwrap_err().to_string().contains("dimm0"));
+        assert!(map.add("dimm3", 32 * MIB, MIB, RegionKind::Ram).is_err());
+        assert!(map
+            .add("fw", 0xf_f000, 0x2000, RegionKind::FirmwareData)
+            .is_err());
+        // Misaligned or empty
+        assert!(map.add("x", 0x10, 0x1000, RegionKind::Ram).is_err());
+        assert!(map.add("x", 200 * MIB, 0, RegionKind::Ram).is_err());
+        assert_eq!(map, before);
+    }
+
+    #[test]
+    fn test_efi_memory_map() {
+        let map = machine_map();
+        let efi = map.efi_memory_map();
+        let summary: Vec<_> = efi
+            .iter()
+            .map(|d| (d.memory_type, d.physical_start, d.number_of_pages))
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                (7, 0, 0x100),
+                (13, 0x10_0000, 0x100),
+                (7, 0x20_0000, 0x3dff),
+                (9, 64 * MIB - 0x1000, 1),
+                (7, 64 * MIB, 0x4000),
+                (7, 256 * MIB, 0x4000),
+                (11, 0xff00_0000, 1),
+            ]
+        );
+        assert_ne!(efi[1].attribute & EFI_MEMORY_RUNTIME, 0);
+        assert_eq!(efi[6].attribute, EFI_MEMORY_UC | EFI_MEMORY_RUNTIME);
+
+        let bytes = map.efi_memory_map_bytes();
+        assert_eq!(bytes.len(), efi.len() * EFI_DESCRIPTOR_SIZE);
+        let pal = &bytes[EFI_DESCRIPTOR_SIZE..2 * EFI_DESCRIPTOR_SIZE];
+        assert_eq!(pal[0], 13);
+        assert_eq!(pal[8..16], 0x10_0000u64.to_le_bytes());
+        assert_eq!(pal[24..32], 0x100u64.to_le_bytes());
+
+        let table = map.to_string();
+        assert!(table.contains("pal-code"));
+        assert!(table.contains("0x0000000008000000-0x000000000fffffff     128M hole"));
+    }
+}
@@ -0,0 +1,9 @@
+//! Firmware interfaces
+//!
+//! This module holds the machine description handed to guest firmware and
+//! operating systems, such as the physical memory map ([`memmap`]), and the
+//! firmware services they call, such as the EFI variable store
+//! ([`variables`]).
+
+pub mod memmap;
+pub mod variables;
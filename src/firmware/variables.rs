@@ -0,0 +1,771 @@
+//! EFI variable services
+//!
+//! [`VariableStore`] holds firmware variables, keyed by vendor GUID and
+//! name, with the semantics of the EFI `GetVariable`, `GetNextVariableName`
+//! and `SetVariable` runtime services. A store opened on a host file keeps
+//! its non-volatile variables there, rewriting the file on every change, so
+//! boot entries and other settings a guest bootloader stores survive across
+//! runs. Volatile variables live only as long as the store.
+//!
+//! The file is JSON, one entry per variable with its data in hex:
+//!
+//! ```json
+//! {
+//!   "variables": [
+//!     {
+//!       "guid": "8be4df61-93ca-11d2-aa0d-00e098032b8c",
+//!       "name": "BootOrder",
+//!       "attributes": 7,
+//!       "data": "01000000"
+//!     }
+//!   ]
+//! }
+//! ```
+//!
+//! Guests reach the services through [`VariableStore::call`] at the entry
+//! points the emulator is given (see `Emulator::add_variable_service`), with
+//! the arguments of the EFI calling convention in r32 onwards and the
+//! `EFI_STATUS` returned in r8.
+
+use crate::memory::Memory;
+use crate::EmulatorError;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::ops::Bound;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// Variable survives a reset
+pub const EFI_VARIABLE_NON_VOLATILE: u32 = 0x1;
+/// Variable is accessible before ExitBootServices
+pub const EFI_VARIABLE_BOOTSERVICE_ACCESS: u32 = 0x2;
+/// Variable is accessible after ExitBootServices
+pub const EFI_VARIABLE_RUNTIME_ACCESS: u32 = 0x4;
+
+/// Attributes the store supports; authenticated variables are not
+const SUPPORTED_ATTRIBUTES: u32 =
+    EFI_VARIABLE_NON_VOLATILE | EFI_VARIABLE_BOOTSERVICE_ACCESS | EFI_VARIABLE_RUNTIME_ACCESS;
+
+/// `EFI_SUCCESS`
+pub const EFI_SUCCESS: u64 = 0;
+
+/// Longest variable name read from the guest, in characters
+const MAX_NAME_LEN: u64 = 1024;
+
+/// Largest variable a guest can store, in bytes
+pub const MAX_VARIABLE_SIZE: u64 = 64 * 1024;
+
+/// Error status returned by a variable service
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EfiStatus {
+    /// `EFI_INVALID_PARAMETER`
+    InvalidParameter,
+    /// `EFI_UNSUPPORTED`
+    Unsupported,
+    /// `EFI_BUFFER_TOO_SMALL`
+    BufferTooSmall,
+    /// `EFI_OUT_OF_RESOURCES`
+    OutOfResources,
+    /// `EFI_DEVICE_ERROR`
+    DeviceError,
+    /// `EFI_NOT_FOUND`
+    NotFound,
+}
+
+impl EfiStatus {
+    /// `EFI_STATUS` value, with the error bit set
+    pub fn code(self) -> u64 {
+        let code = match self {
+            EfiStatus::InvalidParameter => 2,
+            EfiStatus::Unsupported => 3,
+            EfiStatus::BufferTooSmall => 5,
+            EfiStatus::OutOfResources => 9,
+            EfiStatus::DeviceError => 7,
+            EfiStatus::NotFound => 14,
+        };
+        (1 << 63) | code
+    }
+}
+
+impl fmt::Display for EfiStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            EfiStatus::InvalidParameter => "EFI_INVALID_PARAMETER",
+            EfiStatus::Unsupported => "EFI_UNSUPPORTED",
+            EfiStatus::BufferTooSmall => "EFI_BUFFER_TOO_SMALL",
+            EfiStatus::OutOfResources => "EFI_OUT_OF_RESOURCES",
+            EfiStatus::DeviceError => "EFI_DEVICE_ERROR",
+            EfiStatus::NotFound => "EFI_NOT_FOUND",
+        };
+        f.write_str(name)
+    }
+}
+
+/// EFI GUID, in its in-memory byte order
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Guid(pub [u8; 16]);
+
+impl Guid {
+    /// `EFI_GLOBAL_VARIABLE`, the vendor of the architectural variables
+    pub const GLOBAL_VARIABLE: Guid = Guid([
+        0x61, 0xdf, 0xe4, 0x8b, 0xca, 0x93, 0xd2, 0x11, 0xaa, 0x0d, 0x00, 0xe0, 0x98, 0x03, 0x2b,
+        0x8c,
+    ]);
+}
+
+impl fmt::Display for Guid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let b = &self.0;
+        write!(
+            f,
+            "{:08x}-{:04x}-{:04x}-{:02x}{:02x}-",
+            u32::from_le_bytes([b[0], b[1], b[2], b[3]]),
+            u16::from_le_bytes([b[4], b[5]]),
+            u16::from_le_bytes([b[6], b[7]]),
+            b[8],
+            b[9]
+        )?;
+        b[10..]
+            .iter()
+            .try_for_each(|byte| write!(f, "{:02x}", byte))
+    }
+}
+
+impl FromStr for Guid {
+    type Err = EmulatorError;
+
+    /// Parse the registry format, `8be4df61-93ca-11d2-aa0d-00e098032b8c`
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let invalid = || EmulatorError::ConfigError(format!("invalid GUID {:?}", text));
+        let fields: Vec<&str> = text.split('-').collect();
+        let lengths = [8, 4, 4, 4, 12];
+        if fields.len() != lengths.len()
+            || fields.iter().zip(lengths).any(|(field, len)| {
+                field.len() != len || !field.chars().all(|c| c.is_ascii_hexdigit())
+            })
+        {
+            return Err(invalid());
+        }
+
+        let mut bytes = [0; 16];
+        bytes[..4].copy_from_slice(
+            &u32::from_str_radix(fields[0], 16)
+                .map_err(|_| invalid())?
+                .to_le_bytes(),
+        );
+        for (i, field) in fields[1..3].iter().enumerate() {
+            let value = u16::from_str_radix(field, 16).map_err(|_| invalid())?;
+            bytes[4 + 2 * i..6 + 2 * i].copy_from_slice(&value.to_le_bytes());
+        }
+        let tail = decode_hex(&format!("{}{}", fields[3], fields[4])).ok_or_else(invalid)?;
+        bytes[8..].copy_from_slice(&tail);
+        Ok(Guid(bytes))
+    }
+}
+
+/// Value and attributes of a variable
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Variable {
+    /// `EFI_VARIABLE_*` attributes
+    pub attributes: u32,
+    /// Contents
+    pub data: Vec<u8>,
+}
+
+impl Variable {
+    /// Whether the variable is kept in the backing file
+    pub fn is_non_volatile(&self) -> bool {
+        self.attributes & EFI_VARIABLE_NON_VOLATILE != 0
+    }
+}
+
+/// Variable services reachable from the guest
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VariableService {
+    /// `GetVariable(Name, Guid, *Attributes, *DataSize, Data)`
+    GetVariable,
+    /// `GetNextVariableName(*NameSize, Name, *Guid)`
+    GetNextVariableName,
+    /// `SetVariable(Name, Guid, Attributes, DataSize, Data)`
+    SetVariable,
+}
+
+impl VariableService {
+    /// Name of the runtime service
+    pub fn name(self) -> &'static str {
+        match self {
+            VariableService::GetVariable => "GetVariable",
+            VariableService::GetNextVariableName => "GetNextVariableName",
+            VariableService::SetVariable => "SetVariable",
+        }
+    }
+}
+
+/// Variable as stored in the backing file
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct StoredVariable {
+    guid: String,
+    name: String,
+    attributes: u32,
+    data: String,
+}
+
+/// Contents of the backing file
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct StoredVariables {
+    variables: Vec<StoredVariable>,
+}
+
+/// Firmware variables, optionally persisted to a host file
+#[derive(Debug, Clone, Default)]
+pub struct VariableStore {
+    /// Variables by vendor and name
+    variables: BTreeMap<(Guid, String), Variable>,
+    /// File holding the non-volatile variables
+    path: Option<PathBuf>,
+    /// Last failure to update the backing file
+    error: Option<String>,
+}
+
+impl VariableStore {
+    /// Create an empty store with no backing file
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create a store backed by `path`, loading the variables it holds
+    ///
+    /// A missing file is an empty store; it is created by the first change
+    /// to a non-volatile variable.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, EmulatorError> {
+        let path = path.as_ref();
+        let invalid =
+            |msg: String| EmulatorError::ConfigError(format!("{}: {}", path.display(), msg));
+        let stored: StoredVariables = match std::fs::read_to_string(path) {
+            Ok(text) => serde_json::from_str(&text).map_err(|e| invalid(e.to_string()))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => StoredVariables::default(),
+            Err(e) => return Err(invalid(format!("cannot read: {}", e))),
+        };
+
+        let mut store = Self::new();
+        for (i, variable) in stored.variables.into_iter().enumerate() {
+            let guid = variable.guid.parse()?;
+            let data = decode_hex(&variable.data)
+                .ok_or_else(|| invalid(format!("variables[{}].data: invalid hex", i)))?;
+            if variable.attributes & !SUPPORTED_ATTRIBUTES != 0
+                || variable.attributes & EFI_VARIABLE_NON_VOLATILE == 0
+            {
+                return Err(invalid(format!(
+                    "variables[{}].attributes: {:#x} is not a supported non-volatile variable",
+                    i, variable.attributes
+                )));
+            }
+            store.variables.insert(
+                (guid, variable.name),
+                Variable {
+                    attributes: variable.attributes,
+                    data,
+                },
+            );
+        }
+        store.path = Some(path.to_path_buf());
+        Ok(store)
+    }
+
+    /// File holding the non-volatile variables
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    /// Last failure to update the backing file
+    pub fn error(&self) -> Option<&str> {
+        self.error.as_deref()
+    }
+
+    /// Look up a variable
+    pub fn get(&self, guid: &Guid, name: &str) -> Option<&Variable> {
+        self.variables.get(&(*guid, name.to_string()))
+    }
+
+    /// Variables in vendor and name order
+    pub fn iter(&self) -> impl Iterator<Item = (&Guid, &str, &Variable)> {
+        self.variables
+            .iter()
+            .map(|((guid, name), variable)| (guid, name.as_str(), variable))
+    }
+
+    /// Variable following `after` in enumeration order, or the first with
+    /// `None`
+    ///
+    /// A current variable that does not exist is an invalid parameter, as
+    /// enumeration cannot continue from it.
+    pub fn next(&self, after: Option<(&Guid, &str)>) -> Result<(&Guid, &str), EfiStatus> {
+        let next = match after {
+            None => self.variables.keys().next(),
+            Some((guid, name)) => {
+                let key = (*guid, name.to_string());
+                if !self.variables.contains_key(&key) {
+                    return Err(EfiStatus::InvalidParameter);
+                }
+                self.variables
+                    .range((Bound::Excluded(key), Bound::Unbounded))
+                    .next()
+                    .map(|(key, _)| key)
+            }
+        };
+        next.map(|(guid, name)| (guid, name.as_str()))
+            .ok_or(EfiStatus::NotFound)
+    }
+
+    /// Create, replace or delete a variable
+    ///
+    /// Empty data or attributes without access bits delete the variable.
+    /// An existing variable keeps its attributes; writing it with others is
+    /// an invalid parameter. Changes to non-volatile variables are written
+    /// to the backing file before returning, and undone if that fails.
+    pub fn set(
+        &mut self,
+        guid: &Guid,
+        name: &str,
+        attributes: u32,
+        data: &[u8],
+    ) -> Result<(), EfiStatus> {
+        if name.is_empty() {
+            return Err(EfiStatus::InvalidParameter);
+        }
+        if attributes & !SUPPORTED_ATTRIBUTES != 0 {
+            return Err(EfiStatus::Unsupported);
+        }
+        let key = (*guid, name.to_string());
+        let access = EFI_VARIABLE_BOOTSERVICE_ACCESS | EFI_VARIABLE_RUNTIME_ACCESS;
+
+        let previous = if data.is_empty() || attributes & access == 0 {
+            Some(self.variables.remove(&key).ok_or(EfiStatus::NotFound)?)
+        } else {
+            if attributes & EFI_VARIABLE_BOOTSERVICE_ACCESS == 0 {
+                return Err(EfiStatus::InvalidParameter);
+            }
+            if let Some(existing) = self.variables.get(&key) {
+                if existing.attributes != attributes {
+                    return Err(EfiStatus::InvalidParameter);
+                }
+            }
+            self.variables.insert(
+                key.clone(),
+                Variable {
+                    attributes,
+                    data: data.to_vec(),
+                },
+            )
+        };
+
+        let non_volatile = attributes & EFI_VARIABLE_NON_VOLATILE != 0
+            || previous.as_ref().is_some_and(Variable::is_non_volatile);
+        if non_volatile {
+            if let Err(status) = self.persist() {
+                match previous {
+                    Some(previous) => self.variables.insert(key, previous),
+                    None => self.variables.remove(&key),
+                };
+                return Err(status);
+            }
+        }
+        Ok(())
+    }
+
+    /// Rewrite the backing file with the non-volatile variables
+    fn persist(&mut self) -> Result<(), EfiStatus> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let stored = StoredVariables {
+            variables: self
+                .iter()
+                .filter(|(_, _, variable)| variable.is_non_volatile())
+                .map(|(guid, name, variable)| StoredVariable {
+                    guid: guid.to_string(),
+                    name: name.to_string(),
+                    attributes: variable.attributes,
+                    data: variable.data.iter().map(|b| format!("{:02x}", b)).collect(),
+                })
+                .collect(),
+        };
+        let text = serde_json::to_string_pretty(&stored).expect("variables serialize");
+        match std::fs::write(path, text + "\n") {
+            Ok(()) => {
+                self.error = None;
+                Ok(())
+            }
+            Err(e) => {
+                self.error = Some(format!("cannot write {}: {}", path.display(), e));
+                Err(EfiStatus::DeviceError)
+            }
+        }
+    }
+
+    /// Run a variable service for the guest
+    ///
+    /// `args` are the argument registers at entry; the result is the
+    /// `EFI_STATUS` to return. Faults on guest pointers are errors, as they
+    /// would be for the firmware's own code.
+    pub fn call(
+        &mut self,
+        service: VariableService,
+        memory: &mut Memory,
+        args: &[u64; 8],
+    ) -> Result<u64, EmulatorError> {
+        let status = match service {
+            VariableService::GetVariable => self.get_variable(memory, args)?,
+            VariableService::GetNextVariableName => self.get_next_variable_name(memory, args)?,
+            VariableService::SetVariable => self.set_variable(memory, args)?,
+        };
+        Ok(status.map_or_else(EfiStatus::code, |()| EFI_SUCCESS))
+    }
+
+    fn get_variable(
+        &self,
+        memory: &mut Memory,
+        args: &[u64; 8],
+    ) -> Result<Result<(), EfiStatus>, EmulatorError> {
+        let [name, guid, attributes, size, data, ..] = *args;
+        if name == 0 || guid == 0 || size == 0 {
+            return Ok(Err(EfiStatus::InvalidParameter));
+        }
+        let Some(name) = read_name(memory, name)? else {
+            return Ok(Err(EfiStatus::InvalidParameter));
+        };
+        let guid = read_guid(memory, guid)?;
+        let Some(variable) = self.get(&guid, &name) else {
+            return Ok(Err(EfiStatus::NotFound));
+        };
+
+        let len = variable.data.len() as u64;
+        if memory.read_u64(size)? < len {
+            memory.write_u64(size, len)?;
+            return Ok(Err(EfiStatus::BufferTooSmall));
+        }
+        if data == 0 {
+            return Ok(Err(EfiStatus::InvalidParameter));
+        }
+        memory.write_bytes(data, &variable.data)?;
+        memory.write_u64(size, len)?;
+        if attributes != 0 {
+            memory.write_u32(attributes, variable.attributes)?;
+        }
+        Ok(Ok(()))
+    }
+
+    fn get_next_variable_name(
+        &self,
+        memory: &mut Memory,
+        args: &[u64; 8],
+    ) -> Result<Result<(), EfiStatus>, EmulatorError> {
+        let [size, name, guid, ..] = *args;
+        if size == 0 || name == 0 || guid == 0 {
+            return Ok(Err(EfiStatus::InvalidParameter));
+        }
+        let Some(current) = read_name(memory, name)? else {
+            return Ok(Err(EfiStatus::InvalidParameter));
+        };
+        let current_guid = read_guid(memory, guid)?;
+        let after = (!current.is_empty()).then_some((&current_guid, current.as_str()));
+        let (next_guid, next_name) = match self.next(after) {
+            Ok(next) => next,
+            Err(status) => return Ok(Err(status)),
+        };
+
+        let units: Vec<u16> = next_name.encode_utf16().chain([0]).collect();
+        let needed = units.len() as u64 * 2;
+        if memory.read_u64(size)? < needed {
+            memory.write_u64(size, needed)?;
+            return Ok(Err(EfiStatus::BufferTooSmall));
+        }
+        let bytes: Vec<u8> = units.iter().flat_map(|unit| unit.to_le_bytes()).collect();
+        memory.write_bytes(name, &bytes)?;
+        memory.write_bytes(guid, &next_guid.0)?;
+        memory.write_u64(size, needed)?;
+        Ok(Ok(()))
+    }
+
+    fn set_variable(
+        &mut self,
+        memory: &mut Memory,
+        args: &[u64; 8],
+    ) -> Result<Result<(), EfiStatus>, EmulatorError> {
+        let [name, guid, attributes, size, data, ..] = *args;
+        if name == 0 || guid == 0 || (size != 0 && data == 0) {
+            return Ok(Err(EfiStatus::InvalidParameter));
+        }
+        let Some(name) = read_name(memory, name)? else {
+            return Ok(Err(EfiStatus::InvalidParameter));
+        };
+        let guid = read_guid(memory, guid)?;
+        let Ok(attributes) = u32::try_from(attributes) else {
+            return Ok(Err(EfiStatus::InvalidParameter));
+        };
+        if size > MAX_VARIABLE_SIZE {
+            return Ok(Err(EfiStatus::OutOfResources));
+        }
+        if !is_readable(memory, data, size) {
+            return Ok(Err(EfiStatus::InvalidParameter));
+        }
+        let mut contents = vec![0; size as usize];
+        memory.read_bytes(data, &mut contents)?;
+        Ok(self.set(&guid, &name, attributes, &contents))
+    }
+}
+
+/// Whether the `len` bytes at `addr` are mapped and readable
+fn is_readable(memory: &Memory, addr: u64, len: u64) -> bool {
+    let Some(end) = addr.checked_add(len) else {
+        return false;
+    };
+    let mut at = addr;
+    while at < end {
+        match memory.readable_slice(at) {
+            Ok(slice) => at = at.saturating_add(slice.len() as u64),
+            Err(_) => return false,
+        }
+    }
+    true
+}
+
+/// Read a NUL-terminated UCS-2 name, or `None` if it is not valid
+fn read_name(memory: &mut Memory, addr: u64) -> Result<Option<String>, EmulatorError> {
+    let mut units = Vec::new();
+    for i in 0..MAX_NAME_LEN {
+        match memory.read_u16(addr + 2 * i)? {
+            0 => return Ok(String::from_utf16(&units).ok()),
+            unit => units.push(unit),
+        }
+    }
+    Ok(None)
+}
+
+fn read_guid(memory: &mut Memory, addr: u64) -> Result<Guid, EmulatorError> {
+    let mut guid = [0; 16];
+    memory.read_bytes(addr, &mut guid)?;
+    Ok(Guid(guid))
+}
+
+/// Decode an even-length string of hex digits
+fn decode_hex(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) || !text.is_ascii() {
+        return None;
+    }
+    (0..text.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&text[i..i + 2], 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory::Permissions;
+
+    const NV: u32 = EFI_VARIABLE_NON_VOLATILE | EFI_VARIABLE_BOOTSERVICE_ACCESS;
+    const BUF: u64 = 0x10000;
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("rust-ia64-{}-{}.json", name, std::process::id()))
+    }
+
+    /// Write a UCS-2 name and return its address
+    fn write_name(memory: &mut Memory, addr: u64, name: &str) -> u64 {
+        let bytes: Vec<u8> = name
+            .encode_utf16()
+            .chain([0])
+            .flat_map(|unit| unit.to_le_bytes())
+            .collect();
+        memory.write_bytes(addr, &bytes).unwrap();
+        addr
+    }
+
+    #[test]
+    fn test_guid() {
+        let text = "8be4df61-93ca-11d2-aa0d-00e098032b8c";
+        assert_eq!(text.parse::<Guid>().unwrap(), Guid::GLOBAL_VARIABLE);
+        assert_eq!(Guid::GLOBAL_VARIABLE.to_string(), text);
+        for bad in [
+            "8be4df61-93ca-11d2-aa0d",
+            "8be4df61-93ca-11d2-aa0d-00e098032b8g",
+        ] {
+            assert!(bad.parse::<Guid>().is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn test_set() {
+        let guid = Guid::GLOBAL_VARIABLE;
+        let mut store = VariableStore::new();
+        store.set(&guid, "Timeout", NV, &[5, 0]).unwrap();
+        assert_eq!(store.get(&guid, "Timeout").unwrap().data, [5, 0]);
+
+        // Attributes are fixed once created; RT needs BS; unknown bits fail
+        assert_eq!(
+            store.set(&guid, "Timeout", EFI_VARIABLE_BOOTSERVICE_ACCESS, &[1]),
+            Err(EfiStatus::InvalidParameter)
+        );
+        assert_eq!(
+            store.set(&guid, "Lang", EFI_VARIABLE_RUNTIME_ACCESS, b"en"),
+            Err(EfiStatus::InvalidParameter)
+        );
+        assert_eq!(
+            store.set(&guid, "Lang", NV | 0x20, b"en"),
+            Err(EfiStatus::Unsupported)
+        );
+
+        store.set(&guid, "Lang", NV, b"en").unwrap();
+        assert_eq!(store.next(None), Ok((&guid, "Lang")));
+        assert_eq!(store.next(Some((&guid, "Lang"))), Ok((&guid, "Timeout")));
+        assert_eq!(
+            store.next(Some((&guid, "Timeout"))),
+            Err(EfiStatus::NotFound)
+        );
+        assert_eq!(
+            store.next(Some((&guid, "Missing"))),
+            Err(EfiStatus::InvalidParameter)
+        );
+
+        store.set(&guid, "Timeout", 0, &[]).unwrap();
+        assert!(store.get(&guid, "Timeout").is_none());
+        assert_eq!(
+            store.set(&guid, "Timeout", NV, &[]),
+            Err(EfiStatus::NotFound)
+        );
+    }
+
+    #[test]
+    fn test_persist() {
+        let path = temp_path("variables");
+        let guid = Guid::GLOBAL_VARIABLE;
+        let mut store = VariableStore::open(&path).unwrap();
+        store.set(&guid, "BootOrder", NV, &[1, 0]).unwrap();
+        store
+            .set(&guid, "Scratch", EFI_VARIABLE_BOOTSERVICE_ACCESS, &[9])
+            .unwrap();
+
+        let reopened = VariableStore::open(&path).unwrap();
+        assert_eq!(
+            reopened.get(&guid, "BootOrder"),
+            store.get(&guid, "BootOrder")
+        );
+        assert!(reopened.get(&guid, "Scratch").is_none());
+
+        store.set(&guid, "BootOrder", NV, &[]).unwrap();
+        assert_eq!(VariableStore::open(&path).unwrap().iter().count(), 0);
+
+        std::fs::write(&path, r#"{"variables": [{"guid": "x"}]}"#).unwrap();
+        assert!(VariableStore::open(&path).is_err());
+        std::fs::remove_file(&path).unwrap();
+
+        // A failed write leaves the variable as it was
+        let mut store = VariableStore::open(temp_path("missing/variables")).unwrap();
+        assert_eq!(
+            store.set(&guid, "BootOrder", NV, &[1, 0]),
+            Err(EfiStatus::DeviceError)
+        );
+        assert!(store.get(&guid, "BootOrder").is_none());
+        assert!(store.error().unwrap().contains("cannot write"));
+    }
+
+    fn call(
+        store: &mut VariableStore,
+        memory: &mut Memory,
+        service: VariableService,
+        args: [u64; 5],
+    ) -> u64 {
+        let mut regs = [0; 8];
+        regs[..5].copy_from_slice(&args);
+        store.call(service, memory, &regs).unwrap()
+    }
+
+    #[test]
+    fn test_call() {
+        let mut memory = Memory::new();
+        memory.map(BUF, 0x1000, Permissions::ReadWrite).unwrap();
+        let mut store = VariableStore::new();
+        let name = write_name(&mut memory, BUF, "BootNext");
+        let guid = BUF + 0x100;
+        let (attributes, size, data) = (BUF + 0x200, BUF + 0x208, BUF + 0x300);
+        memory.write_bytes(guid, &Guid::GLOBAL_VARIABLE.0).unwrap();
+        memory.write_bytes(data, &[3, 0]).unwrap();
+
+        let set = VariableService::SetVariable;
+        let get = VariableService::GetVariable;
+        let next = VariableService::GetNextVariableName;
+
+        assert_eq!(
+            call(
+                &mut store,
+                &mut memory,
+                set,
+                [name, guid, NV as u64, 2, data]
+            ),
+            EFI_SUCCESS
+        );
+        assert_eq!(
+            call(&mut store, &mut memory, set, [name, 0, NV as u64, 2, data]),
+            EfiStatus::InvalidParameter.code()
+        );
+
+        // Sizes are bounded and must lie in guest memory
+        let huge = [name, guid, NV as u64, u64::MAX, data];
+        assert_eq!(
+            call(&mut store, &mut memory, set, huge),
+            EfiStatus::OutOfResources.code()
+        );
+        let unmapped = [name, guid, NV as u64, 0x100, BUF + 0xF80];
+        assert_eq!(
+            call(&mut store, &mut memory, set, unmapped),
+            EfiStatus::InvalidParameter.code()
+        );
+
+        // A short buffer gets the needed size back
+        memory.write_u64(size, 1).unwrap();
+        assert_eq!(
+            call(
+                &mut store,
+                &mut memory,
+                get,
+                [name, guid, attributes, size, data + 0x10]
+            ),
+            EfiStatus::BufferTooSmall.code()
+        );
+        assert_eq!(memory.read_u64(size).unwrap(), 2);
+        assert_eq!(
+            call(
+                &mut store,
+                &mut memory,
+                get,
+                [name, guid, attributes, size, data + 0x10]
+            ),
+            EFI_SUCCESS
+        );
+        assert_eq!(memory.read_u16(data + 0x10).unwrap(), 3);
+        assert_eq!(memory.read_u32(attributes).unwrap(), NV);
+
+        // Enumeration starts from an empty name
+        let buffer = write_name(&mut memory, BUF + 0x400, "");
+        memory.write_u64(size, 0x100).unwrap();
+        assert_eq!(
+            call(&mut store, &mut memory, next, [size, buffer, guid, 0, 0]),
+            EFI_SUCCESS
+        );
+        assert_eq!(memory.read_u64(size).unwrap(), 18);
+        assert_eq!(memory.read_u16(buffer).unwrap(), u16::from(b'B'));
+        assert_eq!(
+            call(&mut store, &mut memory, next, [size, buffer, guid, 0, 0]),
+            EfiStatus::NotFound.code()
+        );
+
+        // Unmapped pointers fault
+        let mut regs = [0; 8];
+        regs[..5].copy_from_slice(&[name, 0xdead_0000, 0, size, data]);
+        assert!(store.call(get, &mut memory, &regs).is_err());
+    }
+}
@@ -0,0 +1,612 @@
+//! Golden state files
+//!
+//! This module records the architectural state a guest ends in, so an
+//! end-to-end test can check a whole-program run against a file kept with
+//! the tests. The state covers the instruction pointer, CFM and AR.PFS, the
+//! general, floating-point, predicate and branch registers with their NaT
+//! bits, the application registers other than AR.ITC, and selected memory
+//! ranges. Registers are stored sparsely (zero values are omitted) and memory
+//! as one hash per 64-byte chunk, so files stay small while a mismatch can
+//! still be narrowed to a register or a chunk.
+//!
+//! [`check_golden`] compares a run against its file, or rewrites the file
+//! when `UPDATE_GOLDEN` is set in the environment.
+
+use crate::cpu::registers::ar::AR;
+use crate::cpu::registers::FloatRegister;
+use crate::cpu::{Cpu, NUM_BR, NUM_FR, NUM_GR};
+use crate::emulator::Emulator;
+use crate::EmulatorError;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::ops::Range;
+use std::path::Path;
+use std::str::FromStr;
+
+/// File signature
+const MAGIC: &[u8; 8] = b"IA64GOLD";
+
+/// Format version
+const VERSION: u16 = 1;
+
+/// Record tag of a register value
+const TAG_REGISTER: u8 = 1;
+
+/// Record tag of a memory range
+const TAG_MEMORY: u8 = 2;
+
+/// Bytes of memory per stored hash
+pub const CHUNK_SIZE: u64 = 64;
+
+/// Environment variable that makes [`check_golden`] rewrite the file
+pub const UPDATE_ENV: &str = "UPDATE_GOLDEN";
+
+/// Architectural register in a state image
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Register {
+    /// Instruction pointer
+    Ip,
+    /// Current frame marker
+    Cfm,
+    /// Previous function state
+    Pfs,
+    /// Predicate registers p0-p63 as a bit mask
+    Predicates,
+    /// NaT bits of 64 general registers, starting at r0 or r64
+    Nat(u8),
+    /// General register
+    Gr(u8),
+    /// Floating-point register
+    Fr(u8),
+    /// Branch register
+    Br(u8),
+    /// Application register
+    Ar(u8),
+}
+
+impl Register {
+    /// Kind and index bytes of the encoding
+    pub(crate) fn encode(self) -> (u8, u8) {
+        match self {
+            Register::Ip => (0, 0),
+            Register::Cfm => (1, 0),
+            Register::Pfs => (2, 0),
+            Register::Predicates => (3, 0),
+            Register::Nat(word) => (4, word),
+            Register::Gr(index) => (5, index),
+            Register::Fr(index) => (6, index),
+            Register::Br(index) => (7, index),
+            Register::Ar(index) => (8, index),
+        }
+    }
+
+    /// Decode kind and index bytes
+    pub(crate) fn decode(kind: u8, index: u8) -> Option<Self> {
+        Some(match kind {
+            0 => Register::Ip,
+            1 => Register::Cfm,
+            2 => Register::Pfs,
+            3 => Register::Predicates,
+            4 => Register::Nat(index),
+            5 => Register::Gr(index),
+            6 => Register::Fr(index),
+            7 => Register::Br(index),
+            8 => Register::Ar(index),
+            _ => return None,
+        })
+    }
+}
+
+impl fmt::Display for Register {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Register::Ip => write!(f, "ip"),
+            Register::Cfm => write!(f, "cfm"),
+            Register::Pfs => write!(f, "ar.pfs"),
+            Register::Predicates => write!(f, "pr"),
+            Register::Nat(word) => write!(f, "nat[r{}-r{}]", word * 64, word * 64 + 63),
+            Register::Gr(index) => write!(f, "r{}", index),
+            Register::Fr(index) => write!(f, "f{}", index),
+            Register::Br(index) => write!(f, "b{}", index),
+            Register::Ar(index) => match AR::from_bits(*index) {
+                Some(ar) if matches!(index, 1..=44 | 65 | 66) => {
+                    write!(f, "ar.{}", format!("{:?}", ar).to_lowercase())
+                }
+                _ => write!(f, "ar{}", index),
+            },
+        }
+    }
+}
+
+impl FromStr for Register {
+    type Err = EmulatorError;
+
+    /// Parse a register name as displayed, e.g. `r8`, `b0`, `ar.bsp` or
+    /// `ar40`
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        let index = |digits: &str, count: usize| {
+            digits
+                .parse::<u8>()
+                .ok()
+                .filter(|&index| (index as usize) < count)
+        };
+        let register = match name {
+            "ip" => Some(Register::Ip),
+            "cfm" => Some(Register::Cfm),
+            "ar.pfs" => Some(Register::Pfs),
+            "pr" => Some(Register::Predicates),
+            _ if name.starts_with("ar.") => (0..=u8::MAX)
+                .map(Register::Ar)
+                .find(|register| register.to_string() == name),
+            _ => {
+                if let Some(digits) = name.strip_prefix("ar") {
+                    index(digits, 128).map(Register::Ar)
+                } else if let Some(digits) = name.strip_prefix('r') {
+                    index(digits, NUM_GR).map(Register::Gr)
+                } else if let Some(digits) = name.strip_prefix('f') {
+                    index(digits, NUM_FR).map(Register::Fr)
+                } else if let Some(digits) = name.strip_prefix('b') {
+                    index(digits, NUM_BR).map(Register::Br)
+                } else {
+                    None
+                }
+            }
+        };
+        register.ok_or_else(|| EmulatorError::RegisterError(format!("Unknown register: {}", name)))
+    }
+}
+
+/// Chunk hashes of a memory range
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MemoryDigest {
+    /// First address
+    pub base: u64,
+    /// Length in bytes
+    pub len: u64,
+    /// FNV-1a hash of each [`CHUNK_SIZE`] chunk
+    pub chunks: Vec<u64>,
+}
+
+/// Final architectural state of a run
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct StateImage {
+    /// Non-zero register values
+    pub registers: BTreeMap<Register, u64>,
+    /// Selected memory ranges
+    pub memory: Vec<MemoryDigest>,
+}
+
+/// FNV-1a hash of a byte string
+fn fnv1a(data: &[u8]) -> u64 {
+    data.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &byte| {
+        (hash ^ byte as u64).wrapping_mul(0x0000_0100_0000_01b3)
+    })
+}
+
+/// Register values of a CPU, zeros included
+pub(crate) fn registers(cpu: &Cpu) -> Vec<(Register, u64)> {
+    let mut values = vec![
+        (Register::Ip, cpu.ip),
+        (Register::Cfm, cpu.cfm),
+        (Register::Pfs, cpu.pfs),
+    ];
+    let mask = |bits: &[bool]| {
+        bits.iter()
+            .enumerate()
+            .fold(0u64, |mask, (i, &bit)| mask | (bit as u64) << i)
+    };
+    values.push((Register::Predicates, mask(&cpu.pr)));
+    for (word, bits) in cpu.nat.chunks(64).enumerate() {
+        values.push((Register::Nat(word as u8), mask(bits)));
+    }
+    values.extend((0..cpu.gr.len()).map(|i| (Register::Gr(i as u8), cpu.gr[i])));
+    values.extend((0..cpu.fr.len()).map(|i| (Register::Fr(i as u8), cpu.fr[i].to_double())));
+    values.extend((0..cpu.br.len()).map(|i| (Register::Br(i as u8), cpu.br[i])));
+    // AR.ITC depends on how the run was clocked, so it is left out
+    const ARS: [AR; 14] = [
+        AR::KR1,
+        AR::KR2,
+        AR::KR3,
+        AR::KR4,
+        AR::KR5,
+        AR::KR6,
+        AR::KR7,
+        AR::RSC,
+        AR::BSP,
+        AR::BSPSTORE,
+        AR::RNAT,
+        AR::CCV,
+        AR::UNAT,
+        AR::FPSR,
+    ];
+    for ar in ARS {
+        if let Ok(value) = cpu.read_ar(ar) {
+            values.push((Register::Ar(ar as u8), value));
+        }
+    }
+    values
+}
+
+/// Value of a register of a CPU, AR.ITC included
+#[cfg(any(feature = "ffi", feature = "python", all(unix, feature = "remote")))]
+pub(crate) fn register(cpu: &Cpu, register: Register) -> Result<u64, EmulatorError> {
+    if let Register::Ar(index) = register {
+        let ar = AR::from_bits(index).ok_or_else(|| {
+            EmulatorError::RegisterError(format!("Unknown application register ar{}", index))
+        })?;
+        return cpu.read_ar(ar);
+    }
+    registers(cpu)
+        .into_iter()
+        .find(|&(candidate, _)| candidate == register)
+        .map(|(_, value)| value)
+        .ok_or_else(|| EmulatorError::RegisterError(format!("Unknown register: {}", register)))
+}
+
+/// Set a register of a CPU
+///
+/// Application registers are written as by `mov ar`, so BSP is read-only
+/// and the backing store registers need enforced lazy mode.
+pub(crate) fn set_register(
+    cpu: &mut Cpu,
+    register: Register,
+    value: u64,
+) -> Result<(), EmulatorError> {
+    match register {
+        Register::Ip => cpu.ip = value,
+        Register::Cfm => cpu.cfm = value,
+        Register::Pfs => cpu.pfs = value,
+        Register::Predicates => {
+            for (i, pr) in cpu.pr.iter_mut().enumerate() {
+                *pr = value >> i & 1 != 0;
+            }
+        }
+        Register::Nat(word) => {
+            for (i, nat) in cpu
+                .nat
+                .iter_mut()
+                .skip(word as usize * 64)
+                .take(64)
+                .enumerate()
+            {
+                *nat = value >> i & 1 != 0;
+            }
+        }
+        Register::Gr(index) => cpu.gr[index as usize] = value,
+        Register::Fr(index) => {
+            cpu.set_fr_register(index as usize, FloatRegister::from_double(value))?
+        }
+        Register::Br(index) => cpu.br[index as usize] = value,
+        Register::Ar(index) => match AR::from_bits(index) {
+            Some(ar) => cpu.write_ar(ar, value)?,
+            None => {
+                return Err(EmulatorError::RegisterError(format!(
+                    "Unknown application register ar{}",
+                    index
+                )))
+            }
+        },
+    }
+    Ok(())
+}
+
+impl StateImage {
+    /// Capture the state of a stopped machine and the given memory ranges
+    pub fn capture(emulator: &Emulator, ranges: &[Range<u64>]) -> Result<Self, EmulatorError> {
+        let registers = registers(&emulator.cpu)
+            .into_iter()
+            .filter(|&(_, value)| value != 0)
+            .collect();
+
+        let mut memory = Vec::new();
+        for range in ranges {
+            let mut data = vec![0; range.end.saturating_sub(range.start) as usize];
+            emulator.memory.peek_bytes(range.start, &mut data)?;
+            memory.push(MemoryDigest {
+                base: range.start,
+                len: data.len() as u64,
+                chunks: data.chunks(CHUNK_SIZE as usize).map(fnv1a).collect(),
+            });
+        }
+        Ok(Self { registers, memory })
+    }
+
+    /// Encode in the golden file format
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = MAGIC.to_vec();
+        out.extend_from_slice(&VERSION.to_le_bytes());
+        for (&register, &value) in &self.registers {
+            let (kind, index) = register.encode();
+            out.extend_from_slice(&[TAG_REGISTER, kind, index]);
+            out.extend_from_slice(&value.to_le_bytes());
+        }
+        for digest in &self.memory {
+            out.push(TAG_MEMORY);
+            out.extend_from_slice(&digest.base.to_le_bytes());
+            out.extend_from_slice(&digest.len.to_le_bytes());
+            for hash in &digest.chunks {
+                out.extend_from_slice(&hash.to_le_bytes());
+            }
+        }
+        out
+    }
+
+    /// Decode the golden file format
+    pub fn from_bytes(data: &[u8]) -> Result<Self, EmulatorError> {
+        let malformed =
+            |what: &str| EmulatorError::ExecutionError(format!("Bad state file: {}", what));
+        let mut reader = Reader::new(data);
+        if reader
+            .take(MAGIC.len())
+            .ok_or_else(|| malformed("truncated header"))?
+            != MAGIC
+        {
+            return Err(malformed("not a state file"));
+        }
+        let version = reader.u16().ok_or_else(|| malformed("truncated header"))?;
+        if version != VERSION {
+            return Err(malformed(&format!("unsupported version {}", version)));
+        }
+
+        let mut image = Self::default();
+        while let Some(tag) = reader.u8() {
+            match tag {
+                TAG_REGISTER => {
+                    let (kind, index) = reader
+                        .u8()
+                        .zip(reader.u8())
+                        .ok_or_else(|| malformed("truncated register"))?;
+                    let register = Register::decode(kind, index)
+                        .ok_or_else(|| malformed(&format!("unknown register kind {}", kind)))?;
+                    let value = reader
+                        .u64()
+                        .ok_or_else(|| malformed("truncated register"))?;
+                    image.registers.insert(register, value);
+                }
+                TAG_MEMORY => {
+                    let (base, len) = reader
+                        .u64()
+                        .zip(reader.u64())
+                        .ok_or_else(|| malformed("truncated memory range"))?;
+                    let chunks = (0..len.div_ceil(CHUNK_SIZE))
+                        .map(|_| reader.u64())
+                        .collect::<Option<_>>()
+                        .ok_or_else(|| malformed("truncated memory range"))?;
+                    image.memory.push(MemoryDigest { base, len, chunks });
+                }
+                tag => return Err(malformed(&format!("unknown record tag {}", tag))),
+            }
+        }
+        Ok(image)
+    }
+
+    /// Describe how `actual` differs from this expected state, one line per
+    /// difference; empty if they match
+    pub fn diff(&self, actual: &StateImage) -> Vec<String> {
+        let mut lines = Vec::new();
+
+        let mut registers: Vec<_> = self
+            .registers
+            .keys()
+            .chain(actual.registers.keys())
+            .collect();
+        registers.sort();
+        registers.dedup();
+        for register in registers {
+            let expected = self.registers.get(register).copied().unwrap_or(0);
+            let got = actual.registers.get(register).copied().unwrap_or(0);
+            if expected != got {
+                lines.push(format!(
+                    "{}: expected {:#x}, got {:#x}",
+                    register, expected, got
+                ));
+            }
+        }
+
+        for expected in &self.memory {
+            let Some(got) = actual
+                .memory
+                .iter()
+                .find(|got| (got.base, got.len) == (expected.base, expected.len))
+            else {
+                lines.push(format!(
+                    "memory {:#x}+{:#x}: not captured",
+                    expected.base, expected.len
+                ));
+                continue;
+            };
+            for (i, (a, b)) in expected.chunks.iter().zip(&got.chunks).enumerate() {
+                if a != b {
+                    let start = expected.base + i as u64 * CHUNK_SIZE;
+                    let end = (start + CHUNK_SIZE).min(expected.base + expected.len);
+                    lines.push(format!("memory {:#x}..{:#x}: contents differ", start, end));
+                }
+            }
+        }
+        for got in &actual.memory {
+            if !self
+                .memory
+                .iter()
+                .any(|expected| (expected.base, expected.len) == (got.base, got.len))
+            {
+                lines.push(format!(
+                    "memory {:#x}+{:#x}: not in the expected state",
+                    got.base, got.len
+                ));
+            }
+        }
+        lines
+    }
+}
+
+/// Little-endian reader over a byte slice
+pub(crate) struct Reader<'a> {
+    /// Input
+    data: &'a [u8],
+    /// Read position
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    /// Read `data` from the start
+    pub(crate) fn new(data: &'a [u8]) -> Self {
+        Self { data, pos: 0 }
+    }
+
+    /// Take the next `len` bytes
+    pub(crate) fn take(&mut self, len: usize) -> Option<&'a [u8]> {
+        let bytes = self.data.get(self.pos..self.pos + len)?;
+        self.pos += len;
+        Some(bytes)
+    }
+
+    pub(crate) fn u8(&mut self) -> Option<u8> {
+        self.take(1).map(|bytes| bytes[0])
+    }
+
+    pub(crate) fn u16(&mut self) -> Option<u16> {
+        self.take(2)
+            .map(|bytes| u16::from_le_bytes(bytes.try_into().unwrap()))
+    }
+
+    pub(crate) fn u64(&mut self) -> Option<u64> {
+        self.take(8)
+            .map(|bytes| u64::from_le_bytes(bytes.try_into().unwrap()))
+    }
+}
+
+/// Compare a run's final state with a golden file
+///
+/// With `UPDATE_GOLDEN` set in the environment the file is (re)written
+/// instead. A mismatch is an error listing every difference.
+pub fn check_golden(path: impl AsRef<Path>, actual: &StateImage) -> Result<(), EmulatorError> {
+    let path = path.as_ref();
+    if std::env::var_os(UPDATE_ENV).is_some() {
+        return std::fs::write(path, actual.to_bytes()).map_err(|e| {
+            EmulatorError::ExecutionError(format!("Cannot write {}: {}", path.display(), e))
+        });
+    }
+
+    let data = std::fs::read(path).map_err(|e| {
+        EmulatorError::ExecutionError(format!(
+            "Cannot read {}: {} (run with {}=1 to create it)",
+            path.display(),
+            e,
+            UPDATE_ENV
+        ))
+    })?;
+    let expected = StateImage::from_bytes(&data)?;
+    let diff = expected.diff(actual);
+    if diff.is_empty() {
+        return Ok(());
+    }
+    Err(EmulatorError::ExecutionError(format!(
+        "Final state differs from {} (run with {}=1 if the change is intended):\n  {}",
+        path.display(),
+        UPDATE_ENV,
+        diff.join("\n  ")
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::syscall::SyscallNumber;
+    use crate::emulator::{StopReason, SYSCALL_BREAK_IMM, SYSCALL_NUMBER_REG};
+    use crate::memory::Permissions;
+    use crate::test_util::{encode_break_nop, encode_mii, mov_ip, nop};
+
+    const BASE: u64 = 0x10000;
+    const BUFFER: u64 = 0x20000;
+
+    /// Golden files kept with the tests
+    fn golden(name: &str) -> std::path::PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/golden")
+            .join(name)
+    }
+
+    /// Sample guest: note its position, call uname, note the position again
+    fn run_uname_guest() -> Emulator {
+        let image: Vec<u8> = [
+            encode_mii([nop(), mov_ip(0, 14), nop()]),
+            encode_mii([nop(), encode_break_nop(0, 0, SYSCALL_BREAK_IMM), nop()]),
+            encode_mii([nop(), mov_ip(0, 16), encode_break_nop(0, 0, 0x1)]),
+        ]
+        .concat();
+
+        let mut emu = Emulator::new();
+        emu.load_flat_image(BASE, &image, BASE).unwrap();
+        emu.memory
+            .map(BUFFER, 0x1000, Permissions::ReadWrite)
+            .unwrap();
+        emu.cpu.gr[SYSCALL_NUMBER_REG] = SyscallNumber::Uname as u64;
+        emu.cpu.gr[32] = BUFFER;
+        assert_eq!(emu.run().unwrap(), StopReason::Break(0x1));
+        emu
+    }
+
+    /// Capture the image and the first `len` bytes of the buffer
+    fn capture(emu: &Emulator, len: u64) -> StateImage {
+        StateImage::capture(emu, &[BASE..BASE + 0x30, BUFFER..BUFFER + len]).unwrap()
+    }
+
+    #[test]
+    fn test_golden_uname() {
+        let emu = run_uname_guest();
+        let state = capture(&emu, 0x200);
+        check_golden(golden("uname.state"), &state).unwrap();
+    }
+
+    #[test]
+    fn test_register_names() {
+        for register in [
+            Register::Ip,
+            Register::Pfs,
+            Register::Gr(127),
+            Register::Br(0),
+            Register::Ar(AR::BSPSTORE as u8),
+            Register::Ar(100),
+        ] {
+            assert_eq!(register.to_string().parse::<Register>().unwrap(), register);
+        }
+        assert!("r128".parse::<Register>().is_err());
+        assert!("b8".parse::<Register>().is_err());
+        assert!("ar.nonsense".parse::<Register>().is_err());
+    }
+
+    #[test]
+    fn test_state_diff() {
+        let mut emu = run_uname_guest();
+        let expected = capture(&emu, 0x200);
+        assert_eq!(
+            StateImage::from_bytes(&expected.to_bytes()).unwrap(),
+            expected
+        );
+        assert!(expected.diff(&expected).is_empty());
+
+        emu.cpu.gr[16] += 16;
+        emu.cpu.pr[3] = true;
+        emu.memory.write_u8(BUFFER + 0x41, b'X').unwrap();
+        let actual = capture(&emu, 0x200);
+        assert_eq!(
+            expected.diff(&actual),
+            [
+                "pr: expected 0x1, got 0x9".to_string(),
+                format!("r16: expected {:#x}, got {:#x}", BASE + 32, BASE + 48),
+                "memory 0x20040..0x20080: contents differ".to_string(),
+            ]
+        );
+
+        let other = capture(&emu, 0x100);
+        assert_eq!(
+            expected.diff(&other)[2..],
+            [
+                "memory 0x20000+0x200: not captured",
+                "memory 0x20000+0x100: not in the expected state",
+            ]
+        );
+        assert!(StateImage::from_bytes(&expected.to_bytes()[..20]).is_err());
+        assert!(StateImage::from_bytes(b"ELF").is_err());
+    }
+}
@@ -0,0 +1,334 @@
+//! Host intercepts for guest functions
+//!
+//! An intercept replaces a guest function with a host implementation. When
+//! execution reaches the function's entry point, the host function runs with
+//! the guest's arguments (r32-r39 at entry), its result is placed in r8 and
+//! the guest continues at the return address in b0, as if the function had
+//! returned.
+//!
+//! Accelerated versions of the C library's `memcpy`, `memmove`, `memset` and
+//! `strlen` are built in. They access guest memory a region at a time instead
+//! of a byte per instruction, with the permission checks, W^X policy and
+//! write watches of ordinary guest accesses. Like the guest versions they
+//! fault when they reach an inaccessible byte, with the work before it done
+//! (see [`memmove`] for the one exception).
+
+use crate::memory::Memory;
+use crate::EmulatorError;
+use std::collections::HashMap;
+
+/// Registers holding the arguments at function entry (in0-in7)
+pub const ARG_REGS: [usize; 8] = [32, 33, 34, 35, 36, 37, 38, 39];
+
+/// Register receiving the function result
+pub const RETURN_REG: usize = 8;
+
+/// Largest block the built-ins move at once
+const CHUNK_SIZE: u64 = 0x10000;
+
+/// Host implementation of a guest function
+///
+/// Receives the eight argument registers and returns the result.
+pub type HostFunction = fn(&mut Memory, &[u64; 8]) -> Result<u64, EmulatorError>;
+
+/// Built-in accelerated C library functions
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Builtin {
+    /// `void *memcpy(void *dst, const void *src, size_t n)`
+    Memcpy,
+    /// `void *memmove(void *dst, const void *src, size_t n)`
+    Memmove,
+    /// `void *memset(void *dst, int c, size_t n)`
+    Memset,
+    /// `size_t strlen(const char *s)`
+    Strlen,
+}
+
+impl Builtin {
+    /// All built-in functions
+    pub const ALL: [Builtin; 4] = [
+        Builtin::Memcpy,
+        Builtin::Memmove,
+        Builtin::Memset,
+        Builtin::Strlen,
+    ];
+
+    /// C library name of the function
+    pub fn name(self) -> &'static str {
+        match self {
+            Builtin::Memcpy => "memcpy",
+            Builtin::Memmove => "memmove",
+            Builtin::Memset => "memset",
+            Builtin::Strlen => "strlen",
+        }
+    }
+
+    /// Look up a built-in by its C library name
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|builtin| builtin.name() == name)
+    }
+
+    /// Host implementation of the function
+    pub fn function(self) -> HostFunction {
+        match self {
+            // Copying through a buffer makes memcpy safe for overlaps too
+            Builtin::Memcpy | Builtin::Memmove => |memory, args| {
+                memmove(memory, args[0], args[1], args[2])?;
+                Ok(args[0])
+            },
+            Builtin::Memset => |memory, args| {
+                memset(memory, args[0], args[1] as u8, args[2])?;
+                Ok(args[0])
+            },
+            Builtin::Strlen => |memory, args| strlen(memory, args[0]),
+        }
+    }
+}
+
+/// Guest function replaced by a host function
+#[derive(Debug, Clone)]
+pub struct Intercept {
+    /// Name shown in diagnostics, e.g. the intercepted symbol
+    pub name: String,
+    /// Entry point of the guest function
+    pub address: u64,
+    /// Host implementation
+    pub function: HostFunction,
+}
+
+impl Intercept {
+    /// Intercept the function at `address` with a host function
+    pub fn new(name: &str, address: u64, function: HostFunction) -> Self {
+        Self {
+            name: name.to_string(),
+            address,
+            function,
+        }
+    }
+
+    /// Intercept the function at `address` with a built-in
+    pub fn builtin(builtin: Builtin, address: u64) -> Self {
+        Self::new(builtin.name(), address, builtin.function())
+    }
+}
+
+/// Intercepts by entry point
+#[derive(Debug, Default)]
+pub struct InterceptTable {
+    /// Installed intercepts
+    intercepts: HashMap<u64, Intercept>,
+}
+
+impl InterceptTable {
+    /// Create an empty table
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Install an intercept, replacing any at the same address
+    pub fn add(&mut self, intercept: Intercept) {
+        self.intercepts.insert(intercept.address, intercept);
+    }
+
+    /// Remove the intercept at an address
+    pub fn remove(&mut self, address: u64) -> Option<Intercept> {
+        self.intercepts.remove(&address)
+    }
+
+    /// Intercept at an entry point
+    pub fn get(&self, address: u64) -> Option<&Intercept> {
+        self.intercepts.get(&address)
+    }
+
+    /// Check if the table is empty
+    pub fn is_empty(&self) -> bool {
+        self.intercepts.is_empty()
+    }
+}
+
+/// Copy `n` bytes from `src` to `dst`, allowing the ranges to overlap
+///
+/// Overlapping copies to higher addresses run backwards so every byte is
+/// read before it is overwritten. Forward copies stop exactly at the first
+/// inaccessible byte, like a byte-by-byte loop; backward copies may stop
+/// up to a chunk short of it.
+pub fn memmove(memory: &mut Memory, dst: u64, src: u64, n: u64) -> Result<(), EmulatorError> {
+    let backward = dst > src && dst - src < n;
+    let mut buffer = vec![0; n.min(CHUNK_SIZE) as usize];
+    let mut done = 0;
+    while done < n {
+        let mut len = (n - done).min(CHUNK_SIZE);
+        let offset = if backward {
+            n - done - len
+        } else {
+            // Read no further than the source region so a read fault never
+            // discards bytes that could have been copied
+            let readable = memory.readable_slice(src.wrapping_add(done))?.len() as u64;
+            len = len.min(readable);
+            done
+        };
+        let chunk = &mut buffer[..len as usize];
+        memory.read_block(src.wrapping_add(offset), chunk)?;
+        memory.write_block(dst.wrapping_add(offset), chunk)?;
+        done += len;
+    }
+    Ok(())
+}
+
+/// Fill `n` bytes at `dst` with `value`
+pub fn memset(memory: &mut Memory, dst: u64, value: u8, n: u64) -> Result<(), EmulatorError> {
+    let buffer = vec![value; n.min(CHUNK_SIZE) as usize];
+    let mut done = 0;
+    while done < n {
+        let len = (n - done).min(CHUNK_SIZE);
+        memory.write_block(dst.wrapping_add(done), &buffer[..len as usize])?;
+        done += len;
+    }
+    Ok(())
+}
+
+/// Length of the NUL-terminated string at `addr`
+pub fn strlen(memory: &mut Memory, addr: u64) -> Result<u64, EmulatorError> {
+    let mut len = 0;
+    loop {
+        let slice = memory.readable_slice(addr.wrapping_add(len))?;
+        match slice.iter().position(|&byte| byte == 0) {
+            Some(end) => return Ok(len + end as u64),
+            None => len += slice.len() as u64,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory::replacement::Replacement;
+    use crate::memory::{CacheGeometry, Permissions};
+    use proptest::prelude::*;
+
+    /// Test memory: two adjacent writable regions, a read-only region and
+    /// then unmapped memory
+    const BASE: u64 = 0x10000;
+    const END: u64 = 0x11a00;
+
+    /// Small caches keep the byte-by-byte reference loops fast
+    const CACHE: CacheGeometry = CacheGeometry {
+        size: 1024,
+        associativity: 2,
+        line_size: 64,
+        replacement: Replacement::Lru,
+    };
+
+    fn memory(contents: &[u8]) -> Memory {
+        let mut memory = Memory::with_caches(CACHE, CACHE, CACHE).unwrap();
+        memory.map(BASE, 0x1000, Permissions::ReadWrite).unwrap();
+        memory.map(0x11000, 0x400, Permissions::ReadWrite).unwrap();
+        memory.map(0x11400, 0x400, Permissions::ReadWrite).unwrap();
+        memory.write_block(BASE, contents).unwrap();
+        memory.protect(0x11400, Permissions::Read).unwrap();
+        memory
+    }
+
+    fn contents(memory: &Memory) -> Vec<Vec<u8>> {
+        memory.regions().map(|(_, _, data)| data.to_vec()).collect()
+    }
+
+    /// memmove as a guest loop would run it, a byte per load and store
+    fn guest_memmove(memory: &mut Memory, dst: u64, src: u64, n: u64) -> Result<(), EmulatorError> {
+        if dst > src && dst - src < n {
+            for i in (0..n).rev() {
+                let byte = memory.read_u8(src + i)?;
+                memory.write_u8(dst + i, byte)?;
+            }
+        } else {
+            for i in 0..n {
+                let byte = memory.read_u8(src + i)?;
+                memory.write_u8(dst + i, byte)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn guest_memset(memory: &mut Memory, dst: u64, value: u8, n: u64) -> Result<(), EmulatorError> {
+        for i in 0..n {
+            memory.write_u8(dst + i, value)?;
+        }
+        Ok(())
+    }
+
+    fn guest_strlen(memory: &mut Memory, addr: u64) -> Result<u64, EmulatorError> {
+        let mut len = 0;
+        while memory.read_u8(addr + len)? != 0 {
+            len += 1;
+        }
+        Ok(len)
+    }
+
+    fn initial_contents() -> impl Strategy<Value = Vec<u8>> {
+        // Few NULs, so strings often run across region boundaries
+        prop::collection::vec(any::<u8>(), 0x1800)
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(32))]
+
+        /// memcpy and memmove match the guest loop, faults included
+        #[test]
+        fn prop_memmove_matches_guest(
+            initial in initial_contents(),
+            dst in BASE..END,
+            src in BASE..END,
+            n in 0..0x800u64,
+        ) {
+            let mut fast = memory(&initial);
+            let mut guest = memory(&initial);
+            let args = [dst, src, n, 0, 0, 0, 0, 0];
+            let result = Builtin::Memmove.function()(&mut fast, &args);
+            let expected = guest_memmove(&mut guest, dst, src, n);
+
+            prop_assert_eq!(result.is_ok(), expected.is_ok());
+            if let Ok(value) = result {
+                prop_assert_eq!(value, dst);
+            }
+            // A failed backward copy may stop short of the fault
+            if expected.is_ok() || !(dst > src && dst - src < n) {
+                prop_assert!(contents(&fast) == contents(&guest));
+            }
+        }
+
+        /// memset matches the guest loop, faults included
+        #[test]
+        fn prop_memset_matches_guest(
+            initial in initial_contents(),
+            dst in BASE..END,
+            value in any::<u8>(),
+            n in 0..0x800u64,
+        ) {
+            let mut fast = memory(&initial);
+            let mut guest = memory(&initial);
+            let args = [dst, value as u64, n, 0, 0, 0, 0, 0];
+            let result = Builtin::Memset.function()(&mut fast, &args);
+            let expected = guest_memset(&mut guest, dst, value, n);
+
+            prop_assert_eq!(result.is_ok(), expected.is_ok());
+            prop_assert!(contents(&fast) == contents(&guest));
+        }
+
+        /// strlen matches the guest loop, faults included
+        #[test]
+        fn prop_strlen_matches_guest(initial in initial_contents(), addr in BASE..END) {
+            let mut fast = memory(&initial);
+            let mut guest = memory(&initial);
+            let result = Builtin::Strlen.function()(&mut fast, &[addr, 0, 0, 0, 0, 0, 0, 0]);
+            prop_assert_eq!(result.ok(), guest_strlen(&mut guest, addr).ok());
+        }
+    }
+
+    #[test]
+    fn test_builtin_names() {
+        for builtin in Builtin::ALL {
+            assert_eq!(Builtin::from_name(builtin.name()), Some(builtin));
+        }
+        assert_eq!(Builtin::from_name("strcpy"), None);
+    }
+}
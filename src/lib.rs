@@ -18,14 +18,44 @@
 //! example of using the emulator:
 //!
 //! ```rust,no_run
-//! use rust_ia64::cpu::Cpu;
-//! use rust_ia64::memory::Memory;
+//! use rust_ia64::emulator::{Emulator, StopReason};
 //!
-//! let mut cpu = Cpu::new();
-//! let mut memory = Memory::new();
+//! let image = std::fs::read("program.bin")?;
+//! let mut emu = Emulator::new();
+//! emu.load_flat_image(0x10000, &image, 0x10000)?;
 //!
-//! // Load program into memory...
-//! // Execute program...
+//! // Fetch, decode and execute bundles until the guest exits; `step`
+//! // executes a single bundle instead
+//! match emu.run()? {
+//!     StopReason::Exited(status) => println!("exited with {}", status),
+//!     reason => println!("stopped: {:?}", reason),
+//! }
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+//!
+//! Stepping shows the driver at work: each call fetches the bundle at
+//! `cpu.ip`, decodes it, executes its slots and moves on to the next.
+//!
+//! ```rust
+//! use rust_ia64::asm::BundleBuilder;
+//! use rust_ia64::emulator::{Emulator, StopReason};
+//!
+//! let bundle = |insns: [&str; 3]| {
+//!     insns
+//!         .into_iter()
+//!         .fold(BundleBuilder::new(), |builder, insn| builder.insn(insn))
+//!         .build()
+//! };
+//! let mut image = Vec::new();
+//! image.extend(bundle(["nop.m 0", "adds r8 = 42, r0", "nop.i 0 ;;"])?);
+//! image.extend(bundle(["nop.m 0", "nop.i 0", "break.i 0x1 ;;"])?);
+//! let mut emu = Emulator::new();
+//! emu.load_flat_image(0x10000, &image, 0x10000)?;
+//!
+//! assert_eq!(emu.step()?, None);
+//! assert_eq!((emu.cpu.ip, emu.cpu.gr[8]), (0x10010, 42));
+//! assert_eq!(emu.run()?, StopReason::Break(0x1));
+//! # Ok::<(), Box<dyn std::error::Error>>(())
 //! ```
 //!
 //! ## Architecture
@@ -34,17 +64,107 @@
 //!
 //! - CPU core (`cpu` module)
 //! - Memory management (`memory` module)
-//! - Instruction decoder (`decoder` module)
+//! - Guest memory shared with host threads, with `ld.acq`/`st.rel` as host
+//!   atomics (`memory::shared` module)
+//! - Typed cross-endian views of guest ranges (`memory::view` module)
+//! - Regions whose backing store passes through an XOR pad or AES-CTR
+//!   transform, for memory encryption experiments (`memory::transform`
+//!   module, `aes-ctr` feature)
+//! - Instruction decoder (`decoder` module) and disassembler to assembly text
+//!   (`decoder::disasm` module)
+//! - Assembler building bundles from instruction text (`asm` module)
+//! - capstone-style disassembler interface (`capstone_compat` module)
+//! - Run loop tying the components together (`emulator` module)
+//! - TOML machine configuration files (`config` module)
+//! - Optional decode-ahead worker thread (`decode-ahead` feature)
+//! - Interactive debugger with memory search, hexdumps and typed `x` output
+//!   (`debugger` module)
+//! - ELF core dumps of the guest (`coredump` module)
+//! - Guest panic detection and reports (`crash` module)
+//! - Memory-mapped devices with runtime attach and detach, a file-backed
+//!   firmware flash and a watchdog timer (`device` module)
+//! - Physical memory map, EFI memory descriptors and persistent EFI variables
+//!   (`firmware` module)
+//! - Golden final-state files for whole-program tests (`golden` module)
+//! - Expect-style assertions on guest console output (`console` module)
+//! - Host implementations of guest functions such as memcpy (`intercept` module)
+//! - Pure single-instruction evaluation on register snapshots (`semantics` module)
+//! - Minimized, replayable reproducers of guest faults, with zstd-compressed
+//!   memory regions (`repro` module, `compression` feature)
+//! - Region- and page-grouped comparison of snapshots (`snapdiff` module)
+//! - Seeded jitter of interrupt, device event and bundle timing, and of
+//!   cache and TLB replacement (`chaos` module)
+//! - ELF loading with relocation of position-independent images to a
+//!   chosen base (`loader` module)
+//! - Initial stack with guest arguments and environment (`process` module)
 //! - System call interface (`syscall` module)
+//! - strace-like system call logging (`cpu::strace` module)
+//! - Instruction tracing with pluggable sinks (`trace` module)
+//! - Timeline of device, interrupt, system call and stall events with
+//!   Chrome trace export (`timeline` module)
+//! - Per-function register stack profiling (`cpu::rse_profile` module)
+//! - Sampling profiler with folded-stack and pprof output
+//!   (`cpu::sample_profile` module)
+//! - Register stack invariant checking for system code (`cpu::rse_check` module)
+//! - OS-style fixup of unaligned loads and stores (`cpu::unaligned` module)
+//! - Branch prediction model for the performance monitors (`cpu::branch_predict` module)
+//! - Instruction dispersal and issue stall model (`cpu::dispersal` module)
+//! - Cycle-approximate Itanium 2 timing driving AR.ITC (`cpu::timing` module)
+//! - Short- and long-format VHPT walker (`cpu::vhpt` module)
+//! - Per-instruction interpreter microbenchmarks (`bench` module)
+//! - Self-test executing every implemented instruction (`selftest` module)
+//! - Scripted debugger sessions in rhai (`script` module, `scripting` feature)
+//! - JSON-RPC remote control with an async client (`remote` module, `remote`
+//!   feature)
+//! - Device backends as tokio tasks (`device::backend` module, `async-io`
+//!   feature)
+//! - C bindings for embedding in other frontends (`ffi` module, `ffi`
+//!   feature)
+//! - Python module for scripting experiments and teaching (`python` module,
+//!   `python` feature)
 //!
 //! Each component is designed to be modular and testable, allowing for easy
 //! maintenance and extension of functionality.
 
 #![deny(missing_docs)]
 
+pub mod asm;
+pub mod bench;
+pub mod capstone_compat;
+pub mod chaos;
+pub mod config;
+pub mod console;
+pub mod coredump;
 pub mod cpu;
+pub mod crash;
+pub mod debugger;
+#[cfg(feature = "decode-ahead")]
+mod decode_ahead;
 pub mod decoder;
+pub mod device;
+pub mod emulator;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+pub mod firmware;
+pub mod golden;
+pub mod intercept;
+pub mod loader;
 pub mod memory;
+pub mod process;
+#[cfg(feature = "python")]
+pub mod python;
+#[cfg(all(unix, feature = "remote"))]
+pub mod remote;
+pub mod repro;
+#[cfg(feature = "scripting")]
+pub mod script;
+pub mod selftest;
+pub mod semantics;
+pub mod snapdiff;
+#[cfg(test)]
+mod test_util;
+pub mod timeline;
+pub mod trace;
 
 use std::error::Error;
 use std::fmt;
@@ -74,6 +194,51 @@ pub enum EmulatorError {
     RSEError(String),
     /// Error when attempting to execute privileged instructions in user mode
     PrivilegeViolation,
+    /// A NaT register was consumed by a non-speculative instruction
+    NatConsumption(String),
+    /// Invalid machine configuration
+    ConfigError(String),
+    /// Instruction text the assembler cannot encode
+    AssemblyError(String),
+    /// An enabled floating-point exception faulted an instruction
+    FPFault {
+        /// Interruption status: the v, d and z bits of the exceptions
+        isr: u64,
+    },
+    /// A data debug register matched a value loaded or stored
+    DebugFault {
+        /// Address accessed
+        addr: u64,
+        /// Value that matched
+        value: u64,
+    },
+    /// A value does not fit the width it is narrowed to, with strict types on
+    Truncation {
+        /// What the value is, e.g. "sof"
+        what: &'static str,
+        /// Value narrowed
+        value: u64,
+        /// Width it does not fit, in bits
+        bits: u32,
+    },
+    /// Unaligned data reference fault
+    UnalignedReference {
+        /// Data address
+        addr: u64,
+        /// Access size in bytes
+        len: usize,
+        /// Whether the access was a store
+        store: bool,
+    },
+    /// Guest console output did not match an expectation
+    ConsoleMismatch(String),
+    /// A translation the TLB lacks could not be found in the VHPT
+    TlbFault {
+        /// Virtual address translated
+        addr: u64,
+        /// Interruption vector the miss raises
+        vector: cpu::interrupts::InterruptVector,
+    },
 }
 
 impl fmt::Display for EmulatorError {
@@ -90,6 +255,33 @@ impl fmt::Display for EmulatorError {
             EmulatorError::RegisterError(msg) => write!(f, "Register error: {}", msg),
             EmulatorError::RSEError(msg) => write!(f, "RSE error: {}", msg),
             EmulatorError::PrivilegeViolation => write!(f, "Privilege violation"),
+            EmulatorError::NatConsumption(msg) => write!(f, "NaT consumption fault: {}", msg),
+            EmulatorError::ConfigError(msg) => write!(f, "Configuration error: {}", msg),
+            EmulatorError::AssemblyError(msg) => write!(f, "Assembly error: {}", msg),
+            EmulatorError::FPFault { isr } => {
+                write!(f, "Floating-point fault (ISR {:#x})", isr)
+            }
+            EmulatorError::DebugFault { addr, value } => {
+                write!(f, "Data debug fault: value {:#x} at {:#x}", value, addr)
+            }
+            EmulatorError::Truncation { what, value, bits } => {
+                write!(
+                    f,
+                    "Truncation: {} {:#x} does not fit in {} bits",
+                    what, value, bits
+                )
+            }
+            EmulatorError::UnalignedReference { addr, len, store } => write!(
+                f,
+                "Unaligned data reference: {}-byte {} at {:#x}",
+                len,
+                if *store { "store" } else { "load" },
+                addr
+            ),
+            EmulatorError::ConsoleMismatch(msg) => write!(f, "Console mismatch: {}", msg),
+            EmulatorError::TlbFault { addr, vector } => {
+                write!(f, "TLB fault: {:?} at {:#x}", vector, addr)
+            }
         }
     }
 }
@@ -0,0 +1,702 @@
+//! ELF executable loader
+//!
+//! Loads the PT_LOAD segments of a little-endian ELF64 IA-64 executable.
+//! A position-independent executable (ET_DYN) is placed at a base the caller
+//! picks: its dynamic relocations are applied for that base and each
+//! function whose address is taken through `@fptr` gets an official
+//! function descriptor, an entry and gp pair, in a table after the last
+//! segment. ET_EXEC images load at their link addresses.
+//!
+//! The loader has no dynamic linker behind it. A relocation against an
+//! undefined symbol is an error unless the symbol is weak, in which case it
+//! resolves to zero.
+
+use crate::coredump::EM_IA_64;
+use crate::memory::Permissions;
+use crate::EmulatorError;
+use std::collections::HashMap;
+use std::fmt;
+
+/// Granule segments are mapped in
+pub const PAGE_SIZE: u64 = 4096;
+
+/// Register the entry point finds the global pointer in
+pub const GP_REG: usize = 1;
+
+/// Size of an official function descriptor
+pub const DESCRIPTOR_SIZE: u64 = 16;
+
+/// Most memory the segments of one image may take up, 1 GiB
+///
+/// Segment sizes come from the file, so without a cap a few bytes of header
+/// could make the loader allocate the address space.
+pub const MAX_IMAGE_SIZE: u64 = 1 << 30;
+
+const ET_EXEC: u16 = 2;
+const ET_DYN: u16 = 3;
+
+const PT_LOAD: u32 = 1;
+const PT_DYNAMIC: u32 = 2;
+
+const PF_X: u32 = 1;
+const PF_W: u32 = 2;
+
+const DT_NULL: u64 = 0;
+const DT_PLTRELSZ: u64 = 2;
+const DT_PLTGOT: u64 = 3;
+const DT_SYMTAB: u64 = 6;
+const DT_RELA: u64 = 7;
+const DT_RELASZ: u64 = 8;
+const DT_RELAENT: u64 = 9;
+const DT_JMPREL: u64 = 23;
+
+const R_IA64_NONE: u32 = 0x00;
+const R_IA64_DIR64LSB: u32 = 0x27;
+const R_IA64_FPTR64LSB: u32 = 0x47;
+const R_IA64_REL64LSB: u32 = 0x6f;
+const R_IA64_IPLTLSB: u32 = 0x81;
+
+const EHDR_SIZE: usize = 64;
+const PHDR_SIZE: usize = 56;
+const RELA_SIZE: u64 = 24;
+const SYM_SIZE: u64 = 24;
+
+const SHN_UNDEF: u16 = 0;
+const SHN_ABS: u16 = 0xfff1;
+const STB_WEAK: u8 = 2;
+
+/// Where one mapped region of the image ended up
+#[derive(Debug, Clone, PartialEq)]
+pub struct SegmentLayout {
+    /// First address, page aligned
+    pub start: u64,
+    /// Size in bytes, whole pages
+    pub size: u64,
+    /// Access the segment flags asked for
+    pub permissions: Permissions,
+}
+
+/// Where an image was loaded and what the loader did to it
+#[derive(Debug, Clone, PartialEq)]
+pub struct ElfLayout {
+    /// Whether the image is position independent
+    pub position_independent: bool,
+    /// Amount added to every link-time address
+    pub bias: u64,
+    /// Relocated entry point
+    pub entry: u64,
+    /// Relocated global pointer, from DT_PLTGOT
+    pub gp: Option<u64>,
+    /// Mapped regions, in address order
+    pub segments: Vec<SegmentLayout>,
+    /// Address of the function descriptor table and its number of entries
+    pub descriptors: Option<(u64, usize)>,
+    /// Number of relocations applied
+    pub relocations: usize,
+}
+
+impl fmt::Display for ElfLayout {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let kind = if self.position_independent {
+            "PIE"
+        } else {
+            "fixed"
+        };
+        writeln!(f, "{} image, bias {:#x}", kind, self.bias)?;
+        writeln!(f, "entry {:#018x}", self.entry)?;
+        if let Some(gp) = self.gp {
+            writeln!(f, "gp    {:#018x}", gp)?;
+        }
+        for segment in &self.segments {
+            writeln!(
+                f,
+                "{:#018x}-{:#018x} {}{}{}",
+                segment.start,
+                segment.start + segment.size,
+                if segment.permissions.can_read() {
+                    'r'
+                } else {
+                    '-'
+                },
+                if segment.permissions.can_write() {
+                    'w'
+                } else {
+                    '-'
+                },
+                if segment.permissions.can_execute() {
+                    'x'
+                } else {
+                    '-'
+                },
+            )?;
+        }
+        if let Some((addr, count)) = self.descriptors {
+            writeln!(f, "{} function descriptors at {:#018x}", count, addr)?;
+        }
+        write!(f, "{} relocations applied", self.relocations)
+    }
+}
+
+/// A region ready to be mapped
+#[derive(Debug, Clone)]
+pub struct LoadedRegion {
+    /// First address, page aligned
+    pub start: u64,
+    /// Access to map it with
+    pub permissions: Permissions,
+    /// Contents, a whole number of pages
+    pub data: Vec<u8>,
+}
+
+/// An image laid out and relocated, not yet in guest memory
+#[derive(Debug, Clone)]
+pub struct LoadedElf {
+    /// Where everything went
+    pub layout: ElfLayout,
+    /// Regions to map, in address order
+    pub regions: Vec<LoadedRegion>,
+}
+
+/// Whether `image` starts with the ELF magic
+pub fn is_elf(image: &[u8]) -> bool {
+    image.starts_with(b"\x7FELF")
+}
+
+/// Lay out `image` and apply its relocations
+///
+/// A PIE's lowest segment is placed at `base`, which must be page aligned
+/// and a multiple of every segment's alignment. `base` is ignored for ET_EXEC
+/// images.
+pub fn load(image: &[u8], base: u64) -> Result<LoadedElf, EmulatorError> {
+    let header = parse_header(image)?;
+    let loads: Vec<&ProgramHeader> = header
+        .phdrs
+        .iter()
+        .filter(|phdr| phdr.kind == PT_LOAD && phdr.memsz > 0)
+        .collect();
+    if loads.is_empty() {
+        return Err(error("no PT_LOAD segments"));
+    }
+
+    let position_independent = header.kind == ET_DYN;
+    let bias = if position_independent {
+        let alignment = loads
+            .iter()
+            .map(|phdr| phdr.align)
+            .fold(PAGE_SIZE, u64::max);
+        if !base.is_multiple_of(alignment) {
+            return Err(error(&format!(
+                "base {:#x} is not aligned to the segment alignment {:#x}",
+                base, alignment
+            )));
+        }
+        let lowest = loads.iter().map(|phdr| phdr.vaddr).min().unwrap_or(0);
+        base.wrapping_sub(lowest - lowest % PAGE_SIZE)
+    } else {
+        0
+    };
+
+    let mut regions = map_segments(image, &loads, bias)?;
+    let mut relocator = Relocator {
+        regions: &mut regions,
+        bias,
+        gp: None,
+        symtab: None,
+        descriptors: HashMap::new(),
+        descriptor_base: 0,
+        count: 0,
+    };
+
+    if let Some(dynamic) = header.phdrs.iter().find(|phdr| phdr.kind == PT_DYNAMIC) {
+        let entries = relocator.dynamic(dynamic.vaddr.wrapping_add(bias))?;
+        let tag = |wanted: u64| entries.iter().find(|(t, _)| *t == wanted).map(|&(_, v)| v);
+        relocator.gp = tag(DT_PLTGOT).map(|gp| gp.wrapping_add(bias));
+        relocator.symtab = tag(DT_SYMTAB).map(|symtab| symtab.wrapping_add(bias));
+        relocator.descriptor_base = relocator
+            .regions
+            .last()
+            .map_or(0, |region| region.start + region.data.len() as u64);
+        if tag(DT_RELAENT).is_some_and(|size| size != RELA_SIZE) {
+            return Err(error("DT_RELAENT is not the size of an Elf64_Rela"));
+        }
+        for (table, size) in [(DT_RELA, DT_RELASZ), (DT_JMPREL, DT_PLTRELSZ)] {
+            if let (Some(addr), Some(size)) = (tag(table), tag(size)) {
+                relocator.apply_table(addr.wrapping_add(bias), size)?;
+            }
+        }
+    }
+
+    let gp = relocator.gp;
+    let count = relocator.count;
+    let descriptor_base = relocator.descriptor_base;
+    let mut descriptors: Vec<(u64, u64)> = relocator.descriptors.into_values().collect();
+    descriptors.sort_unstable();
+    let descriptors = if descriptors.is_empty() {
+        None
+    } else {
+        let size = (descriptors.len() as u64 * DESCRIPTOR_SIZE).next_multiple_of(PAGE_SIZE);
+        let mut data = vec![0; size as usize];
+        for &(addr, target) in &descriptors {
+            let offset = (addr - descriptor_base) as usize;
+            data[offset..offset + 8].copy_from_slice(&target.to_le_bytes());
+            data[offset + 8..offset + 16].copy_from_slice(&gp.unwrap_or(0).to_le_bytes());
+        }
+        regions.push(LoadedRegion {
+            start: descriptor_base,
+            permissions: Permissions::Read,
+            data,
+        });
+        Some((descriptor_base, descriptors.len()))
+    };
+
+    Ok(LoadedElf {
+        layout: ElfLayout {
+            position_independent,
+            bias,
+            entry: header.entry.wrapping_add(bias),
+            gp,
+            segments: regions
+                .iter()
+                .map(|region| SegmentLayout {
+                    start: region.start,
+                    size: region.data.len() as u64,
+                    permissions: region.permissions,
+                })
+                .collect(),
+            descriptors,
+            relocations: count,
+        },
+        regions,
+    })
+}
+
+/// Fields of the ELF header the loader uses
+struct Header {
+    kind: u16,
+    entry: u64,
+    phdrs: Vec<ProgramHeader>,
+}
+
+/// Fields of a program header the loader uses
+struct ProgramHeader {
+    kind: u32,
+    flags: u32,
+    offset: u64,
+    vaddr: u64,
+    filesz: u64,
+    memsz: u64,
+    align: u64,
+}
+
+/// Check the ELF identification and read the program headers
+fn parse_header(image: &[u8]) -> Result<Header, EmulatorError> {
+    if !is_elf(image) || image.len() < EHDR_SIZE {
+        return Err(error("not an ELF file"));
+    }
+    if image[4] != 2 || image[5] != 1 {
+        return Err(error("not a little-endian ELF64 file"));
+    }
+    if u16_at(image, 18) != EM_IA_64 {
+        return Err(error("not an IA-64 executable"));
+    }
+    let kind = u16_at(image, 16);
+    if kind != ET_EXEC && kind != ET_DYN {
+        return Err(error(
+            "not an executable or position-independent executable",
+        ));
+    }
+    let phoff = u64_at(image, 32) as usize;
+    let phentsize = u16_at(image, 54) as usize;
+    let phnum = u16_at(image, 56) as usize;
+    if phentsize != PHDR_SIZE
+        || phoff
+            .checked_add(phnum * PHDR_SIZE)
+            .is_none_or(|end| end > image.len())
+    {
+        return Err(error("program headers lie outside the file"));
+    }
+    let phdrs = (0..phnum)
+        .map(|i| {
+            let at = phoff + i * PHDR_SIZE;
+            ProgramHeader {
+                kind: u32_at(image, at),
+                flags: u32_at(image, at + 4),
+                offset: u64_at(image, at + 8),
+                vaddr: u64_at(image, at + 16),
+                filesz: u64_at(image, at + 32),
+                memsz: u64_at(image, at + 40),
+                align: u64_at(image, at + 48),
+            }
+        })
+        .collect();
+    Ok(Header {
+        kind,
+        entry: u64_at(image, 24),
+        phdrs,
+    })
+}
+
+/// Copy the segments into page-aligned regions at their biased addresses
+///
+/// Segments sharing a page are merged into one region with the union of
+/// their permissions.
+fn map_segments(
+    image: &[u8],
+    loads: &[&ProgramHeader],
+    bias: u64,
+) -> Result<Vec<LoadedRegion>, EmulatorError> {
+    let mut sorted = loads.to_vec();
+    sorted.sort_by_key(|phdr| phdr.vaddr);
+    let mut regions: Vec<LoadedRegion> = Vec::new();
+    let mut total = 0;
+    for phdr in sorted {
+        let file_end = phdr
+            .offset
+            .checked_add(phdr.filesz)
+            .filter(|&end| end <= image.len() as u64 && phdr.filesz <= phdr.memsz)
+            .ok_or_else(|| error("segment contents lie outside the file"))?;
+        let vaddr = phdr.vaddr.wrapping_add(bias);
+        let start = vaddr - vaddr % PAGE_SIZE;
+        let end = vaddr
+            .checked_add(phdr.memsz)
+            .and_then(|end| end.checked_next_multiple_of(PAGE_SIZE))
+            .ok_or_else(|| error("segment extends past the end of the address space"))?;
+        let permissions = segment_permissions(phdr.flags);
+
+        // Count only the pages this segment adds to the ones already laid out
+        let added_from = match regions.last() {
+            Some(last) => start.max(last.start + last.data.len() as u64),
+            None => start,
+        };
+        total += end.saturating_sub(added_from);
+        if total > MAX_IMAGE_SIZE {
+            return Err(error(&format!(
+                "segments take more than {} bytes of memory",
+                MAX_IMAGE_SIZE
+            )));
+        }
+
+        match regions.last_mut() {
+            Some(last) if start < last.start + last.data.len() as u64 => {
+                let merged_end = end.max(last.start + last.data.len() as u64);
+                last.data.resize((merged_end - last.start) as usize, 0);
+                last.permissions = union(last.permissions, permissions);
+            }
+            _ => regions.push(LoadedRegion {
+                start,
+                permissions,
+                data: vec![0; (end - start) as usize],
+            }),
+        }
+        let region = regions.last_mut().expect("region just pushed");
+        let offset = (vaddr - region.start) as usize;
+        region.data[offset..offset + phdr.filesz as usize]
+            .copy_from_slice(&image[phdr.offset as usize..file_end as usize]);
+    }
+    Ok(regions)
+}
+
+/// Applies relocations to the laid-out regions
+struct Relocator<'a> {
+    regions: &'a mut Vec<LoadedRegion>,
+    bias: u64,
+    gp: Option<u64>,
+    symtab: Option<u64>,
+    /// Descriptor address and function address, by function address
+    descriptors: HashMap<u64, (u64, u64)>,
+    descriptor_base: u64,
+    count: usize,
+}
+
+impl Relocator<'_> {
+    /// Read the dynamic section's tag and value pairs
+    fn dynamic(&self, addr: u64) -> Result<Vec<(u64, u64)>, EmulatorError> {
+        let mut entries = Vec::new();
+        for i in 0.. {
+            let tag = self.read(addr + i * 16)?;
+            if tag == DT_NULL {
+                break;
+            }
+            entries.push((tag, self.read(addr + i * 16 + 8)?));
+        }
+        Ok(entries)
+    }
+
+    /// Apply `size` bytes of Elf64_Rela entries at `addr`
+    fn apply_table(&mut self, addr: u64, size: u64) -> Result<(), EmulatorError> {
+        for entry in (0..size / RELA_SIZE).map(|i| addr + i * RELA_SIZE) {
+            let offset = self.read(entry)?.wrapping_add(self.bias);
+            let info = self.read(entry + 8)?;
+            let addend = self.read(entry + 16)?;
+            let kind = info as u32;
+            let symbol = (info >> 32) as u32;
+            match kind {
+                R_IA64_NONE => continue,
+                R_IA64_REL64LSB => self.write(offset, self.bias.wrapping_add(addend))?,
+                R_IA64_DIR64LSB => {
+                    let value = self.symbol(symbol)?.wrapping_add(addend);
+                    self.write(offset, value)?
+                }
+                R_IA64_FPTR64LSB => {
+                    let target = self.symbol(symbol)?.wrapping_add(addend);
+                    let descriptor = if target == 0 {
+                        0
+                    } else {
+                        self.descriptor(target)
+                    };
+                    self.write(offset, descriptor)?
+                }
+                R_IA64_IPLTLSB => {
+                    let target = self.symbol(symbol)?.wrapping_add(addend);
+                    self.write(offset, target)?;
+                    self.write(offset + 8, self.gp.unwrap_or(0))?
+                }
+                _ => {
+                    return Err(error(&format!(
+                        "unsupported relocation type {:#x} at {:#x}",
+                        kind, offset
+                    )))
+                }
+            }
+            self.count += 1;
+        }
+        Ok(())
+    }
+
+    /// Relocated value of symbol `index`
+    fn symbol(&self, index: u32) -> Result<u64, EmulatorError> {
+        let symtab = self
+            .symtab
+            .ok_or_else(|| error("symbol relocation without DT_SYMTAB"))?;
+        let entry = symtab + index as u64 * SYM_SIZE;
+        let word = self.read(entry)?;
+        let info = (word >> 32) as u8;
+        let shndx = (word >> 48) as u16;
+        let value = self.read(entry + 8)?;
+        match shndx {
+            SHN_UNDEF if info >> 4 == STB_WEAK => Ok(0),
+            SHN_UNDEF => Err(error(&format!(
+                "relocation against undefined symbol {}",
+                index
+            ))),
+            SHN_ABS => Ok(value),
+            _ => Ok(value.wrapping_add(self.bias)),
+        }
+    }
+
+    /// Address of the official descriptor for the function at `target`
+    fn descriptor(&mut self, target: u64) -> u64 {
+        let next = self.descriptor_base + self.descriptors.len() as u64 * DESCRIPTOR_SIZE;
+        self.descriptors.entry(target).or_insert((next, target)).0
+    }
+
+    /// Region and offset holding the eight bytes at `addr`
+    fn locate(&self, addr: u64) -> Result<(usize, usize), EmulatorError> {
+        self.regions
+            .iter()
+            .position(|region| {
+                addr >= region.start
+                    && addr
+                        .checked_add(8)
+                        .is_some_and(|end| end <= region.start + region.data.len() as u64)
+            })
+            .map(|index| (index, (addr - self.regions[index].start) as usize))
+            .ok_or_else(|| error(&format!("{:#x} is outside the loaded segments", addr)))
+    }
+
+    fn read(&self, addr: u64) -> Result<u64, EmulatorError> {
+        let (index, offset) = self.locate(addr)?;
+        Ok(u64_at(&self.regions[index].data, offset))
+    }
+
+    fn write(&mut self, addr: u64, value: u64) -> Result<(), EmulatorError> {
+        let (index, offset) = self.locate(addr)?;
+        self.regions[index].data[offset..offset + 8].copy_from_slice(&value.to_le_bytes());
+        Ok(())
+    }
+}
+
+/// Map segment flags to region permissions, readable in every case
+fn segment_permissions(flags: u32) -> Permissions {
+    match (flags & PF_W != 0, flags & PF_X != 0) {
+        (false, false) => Permissions::Read,
+        (true, false) => Permissions::ReadWrite,
+        (false, true) => Permissions::ReadExecute,
+        (true, true) => Permissions::ReadWriteExecute,
+    }
+}
+
+/// Permissions allowing everything either allows
+fn union(a: Permissions, b: Permissions) -> Permissions {
+    let write = a.can_write() || b.can_write();
+    let execute = a.can_execute() || b.can_execute();
+    segment_permissions(if write { PF_W } else { 0 } | if execute { PF_X } else { 0 })
+}
+
+fn error(message: &str) -> EmulatorError {
+    EmulatorError::ConfigError(format!("ELF image: {}", message))
+}
+
+fn u16_at(data: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes(data[offset..offset + 2].try_into().unwrap())
+}
+
+fn u32_at(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+}
+
+fn u64_at(data: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(data[offset..offset + 8].try_into().unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::emulator::Emulator;
+
+    /// Offset of the code in the test image
+    const CODE: u64 = 0x400;
+    /// Offset of the relocated words in the test image
+    const DATA: u64 = 0x500;
+    /// Link-time gp of the test image
+    const GP: u64 = 0x600;
+
+    fn put(image: &mut [u8], offset: u64, words: &[u64]) {
+        for (i, word) in words.iter().enumerate() {
+            let at = offset as usize + i * 8;
+            image[at..at + 8].copy_from_slice(&word.to_le_bytes());
+        }
+    }
+
+    /// Build a one-segment PIE whose entry is the bundle `code`, with a
+    /// REL64, a DIR64 and an FPTR64 relocation of the words at DATA
+    fn pie(code: [u8; 16]) -> Vec<u8> {
+        let mut image = vec![0u8; 0x700];
+        image[..8].copy_from_slice(&[0x7F, b'E', b'L', b'F', 2, 1, 1, 0]);
+        image[16..18].copy_from_slice(&ET_DYN.to_le_bytes());
+        image[18..20].copy_from_slice(&EM_IA_64.to_le_bytes());
+        put(&mut image, 24, &[CODE, EHDR_SIZE as u64]);
+        image[54..56].copy_from_slice(&(PHDR_SIZE as u16).to_le_bytes());
+        image[56..58].copy_from_slice(&2u16.to_le_bytes());
+        // PT_LOAD rwx covering the file, then PT_DYNAMIC at 0x100
+        put(
+            &mut image,
+            EHDR_SIZE as u64,
+            &[
+                PT_LOAD as u64 | 7 << 32,
+                0,
+                0,
+                0,
+                0x700,
+                0x700,
+                0x10000,
+                PT_DYNAMIC as u64 | 6 << 32,
+                0x100,
+                0x100,
+                0,
+                0x80,
+                0x80,
+                8,
+            ],
+        );
+        put(
+            &mut image,
+            0x100,
+            &[
+                DT_RELA, 0x200, DT_RELASZ, 72, DT_RELAENT, 24, DT_SYMTAB, 0x300, DT_PLTGOT, GP,
+                DT_NULL, 0,
+            ],
+        );
+        let relocation = |symbol: u64, kind: u32| symbol << 32 | kind as u64;
+        put(
+            &mut image,
+            0x200,
+            &[
+                DATA,
+                relocation(0, R_IA64_REL64LSB),
+                CODE,
+                DATA + 8,
+                relocation(1, R_IA64_DIR64LSB),
+                8,
+                DATA + 16,
+                relocation(1, R_IA64_FPTR64LSB),
+                0,
+            ],
+        );
+        // Symbol 1: a global function at CODE in section 1
+        put(&mut image, 0x318, &[0x12 << 32 | 1 << 48, CODE, 16]);
+        image[CODE as usize..CODE as usize + 16].copy_from_slice(&code);
+        image
+    }
+
+    #[test]
+    fn test_relocate_pie() {
+        let base = 0x4000_0000_0010_0000;
+        let loaded = load(&pie([0; 16]), base).unwrap();
+        let layout = &loaded.layout;
+        assert!(layout.position_independent);
+        assert_eq!(layout.bias, base);
+        assert_eq!(layout.entry, base + CODE);
+        assert_eq!(layout.gp, Some(base + GP));
+        assert_eq!(layout.relocations, 3);
+        assert_eq!(layout.segments[0].start, base);
+        assert_eq!(layout.segments[0].size, PAGE_SIZE);
+
+        let data = &loaded.regions[0].data;
+        assert_eq!(u64_at(data, DATA as usize), base + CODE);
+        assert_eq!(u64_at(data, DATA as usize + 8), base + CODE + 8);
+        let (table, count) = layout.descriptors.unwrap();
+        assert_eq!(table, base + PAGE_SIZE);
+        assert_eq!(count, 1);
+        assert_eq!(u64_at(data, DATA as usize + 16), table);
+        let descriptors = &loaded.regions[1].data;
+        assert_eq!(u64_at(descriptors, 0), base + CODE);
+        assert_eq!(u64_at(descriptors, 8), base + GP);
+        assert_eq!(loaded.regions[1].permissions, Permissions::Read);
+
+        let report = layout.to_string();
+        assert!(report.starts_with("PIE image, bias 0x4000000000100000"));
+        assert!(report.contains("rwx"));
+    }
+
+    #[test]
+    fn test_load_into_emulator() {
+        let base = 0x4000_0000_0020_0000;
+        let mut emulator = Emulator::new();
+        let layout = emulator.load_elf(&pie([0; 16]), base).unwrap();
+        assert_eq!(emulator.cpu.ip, base + CODE);
+        assert_eq!(emulator.cpu.get_gr(GP_REG).unwrap(), base + GP);
+
+        let descriptor = emulator.memory.read_u64(base + DATA + 16).unwrap();
+        assert_eq!(descriptor, layout.descriptors.unwrap().0);
+        assert_eq!(emulator.memory.read_u64(descriptor).unwrap(), base + CODE);
+        assert_eq!(emulator.memory.read_u64(descriptor + 8).unwrap(), base + GP);
+
+        // The same image can go anywhere else
+        let mut other = Emulator::new();
+        let moved = other.load_elf(&pie([0; 16]), 0x10000).unwrap();
+        assert_eq!(moved.entry, 0x10000 + CODE);
+        assert_eq!(
+            other.memory.read_u64(0x10000 + DATA).unwrap(),
+            0x10000 + CODE
+        );
+    }
+
+    #[test]
+    fn test_reject_bad_images() {
+        // The segment asks for 64K alignment
+        assert!(load(&pie([0; 16]), 0x4000_0000_0000_1000).is_err());
+        assert!(load(b"\x7FELF", 0).is_err());
+
+        let mut unknown = pie([0; 16]);
+        put(&mut unknown, 0x208, &[0x99]);
+        let message = load(&unknown, 0x10000).unwrap_err().to_string();
+        assert!(message.contains("unsupported relocation type 0x99"));
+
+        // A huge p_memsz is refused before anything is allocated
+        let mut huge = pie([0; 16]);
+        put(&mut huge, EHDR_SIZE as u64 + 40, &[1 << 40]);
+        let message = load(&huge, 0x10000).unwrap_err().to_string();
+        assert!(message.contains("segments take more than"));
+    }
+}
@@ -1,3 +1,1081 @@
+//! Command-line front end for the IA-64 emulator
+//!
+//! Loads a flat binary or ELF image, runs it, and exits with the guest's
+//! exit status. The machine can be described by a TOML file with
+//! `--config`; flags given on the command line override it. With `--debug`, reads debugger commands
+//! from standard input instead; `--script` runs a debugger script first.
+//! `rust-ia64 bench-insn INSN` times a single instruction instead (with
+//! `--decode`, decoding its bundle each time), and
+//! `rust-ia64 replay FILE` replays a reproducer written by `--repro`, and
+//! `rust-ia64 snapdiff OLD NEW` compares two snapshot or reproducer files.
+//! `rust-ia64 selftest` executes each implemented instruction and checks
+//! the results.
+
+#[cfg(feature = "count-allocations")]
+use rust_ia64::bench::CountingAllocator;
+use rust_ia64::bench::{self, BenchInstruction};
+use rust_ia64::chaos::ChaosConfig;
+use rust_ia64::coredump;
+use rust_ia64::cpu::dispersal::{Dispersal, MachineModel};
+use rust_ia64::cpu::hostfs::{HostFs, HostFsMode};
+use rust_ia64::cpu::sample_profile::{SampleProfiler, DEFAULT_SAMPLE_INTERVAL};
+use rust_ia64::cpu::stdio::Stdio;
+use rust_ia64::cpu::strace::{Strace, StraceOutput};
+use rust_ia64::cpu::timer::TimerMode;
+use rust_ia64::cpu::timing::TimingModel;
+use rust_ia64::cpu::unaligned::AlignmentPolicy;
+use rust_ia64::crash::{LivelockConfig, PanicHook};
+use rust_ia64::debugger::{format_stats, parse_number, Debugger};
+use rust_ia64::emulator::{Emulator, Machine, StopReason};
+use rust_ia64::intercept::{Builtin, Intercept};
+use rust_ia64::loader;
+use rust_ia64::memory::uninit::UninitPolicy;
+use rust_ia64::memory::WxPolicy;
+use rust_ia64::repro::{self, Compression, CrashRecorder, ReplayOutcome, ReproBundle, Snapshot};
+use rust_ia64::selftest;
+use rust_ia64::snapdiff;
+use rust_ia64::timeline::Timeline;
+use rust_ia64::trace::{StderrTracer, Tracer, WriterTracer};
+use std::io::{self, BufRead, Write};
+use std::process;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Counts allocations for `bench-insn`
+#[cfg(feature = "count-allocations")]
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+/// Default load address for flat images (start of region 2)
+const DEFAULT_BASE: u64 = 0x4000_0000_0000_0000;
+
+/// Exit code used when the emulator itself fails
+const EXIT_FAILURE: i32 = 1;
+
+/// Exit code used for command-line errors
+const EXIT_USAGE: i32 = 2;
+
+/// What to do when the guest livelocks
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum LivelockAction {
+    /// Report it and keep running
+    Warn,
+    /// Report it and stop, as for a fault
+    Stop,
+    /// Report it and start the debugger
+    Debug,
+}
+
+/// Output format of the sampling profiler
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ProfileFormat {
+    /// Folded stacks, for flamegraph tools
+    Folded,
+    /// pprof protobuf
+    Pprof,
+}
+
+/// Parsed command-line options
+struct Options {
+    /// Load address of a flat image, or of a position-independent ELF image
+    base: u64,
+    /// Entry point, defaults to the load address
+    entry: Option<u64>,
+    /// Path to the image
+    image: Option<String>,
+    /// Machine configuration file
+    config: Option<String>,
+    /// Optional `nm` symbol listing for the debugger
+    symbols: Option<String>,
+    /// Start the interactive debugger instead of running
+    debug: bool,
+    /// Derive AR.ITC from host time at this frequency (Hz)
+    itc_frequency: Option<u64>,
+    /// Write an ELF core file here if the guest stops on a fault
+    core: Option<String>,
+    /// Policy for writes to executable memory
+    wx_policy: Option<WxPolicy>,
+    /// Policy for loads of never-written memory
+    uninit: Option<UninitPolicy>,
+    /// Functions treated as guest panics, by symbol or address
+    panic_functions: Vec<String>,
+    /// Ports whose writes are treated as guest panics
+    panic_ports: Vec<u64>,
+    /// Log system calls
+    strace: bool,
+    /// Write the system call log here instead of standard error
+    strace_file: Option<String>,
+    /// Log only these system calls, by name
+    strace_filter: Vec<String>,
+    /// Trace each executed instruction
+    trace: bool,
+    /// Write the instruction trace here instead of standard error
+    trace_file: Option<String>,
+    /// Write a Chrome trace timeline of the run here
+    timeline: Option<String>,
+    /// Record only these timeline channels, by name
+    timeline_channels: Vec<String>,
+    /// Report register stack use per function after the run
+    rse_profile: bool,
+    /// Report register stack invariant violations after the run
+    rse_check: bool,
+    /// Write a sampling profile of the run here
+    profile: Option<String>,
+    /// Format of the sampling profile
+    profile_format: ProfileFormat,
+    /// Instructions between profile samples
+    profile_interval: u64,
+    /// Record call stacks in profile samples
+    profile_stacks: bool,
+    /// Debugger script to run instead of the guest
+    script: Option<String>,
+    /// Serve remote control requests on this socket instead of running
+    remote: Option<String>,
+    /// Run memcpy and friends on the host, found through the symbols
+    accelerate: bool,
+    /// Report memory and ALAT statistics after the run
+    stats: bool,
+    /// Fault on slots whose opcode does not match the template's unit
+    strict_decode: bool,
+    /// Fault on values that do not fit the width they are narrowed to
+    strict_types: bool,
+    /// Report where an ELF image's segments were loaded
+    layout: bool,
+    /// Handling of unaligned loads and stores
+    unaligned: Option<AlignmentPolicy>,
+    /// Model instruction dispersal on this machine
+    dispersal: Option<MachineModel>,
+    /// Fail on split issues under the dispersal model
+    strict_dispersal: bool,
+    /// Charge Itanium 2 cycles for bundles
+    timing: bool,
+    /// Watch for guest livelocks and act on them
+    livelock: Option<LivelockAction>,
+    /// Livelock detection thresholds
+    livelock_config: LivelockConfig,
+    /// Write a minimized reproducer here if the guest faults
+    repro: Option<String>,
+    /// Bundles between reproducer checkpoints
+    repro_interval: u64,
+    /// Compression of memory in saved snapshots and reproducers
+    snapshot_compression: Compression,
+    /// Jitter interrupt and hotplug delivery with these settings
+    chaos: Option<ChaosConfig>,
+    /// Host directory shared with the guest, and what the guest may do to it
+    hostfs: Option<(String, HostFsMode)>,
+    /// Guest environment, as KEY=VALUE
+    env: Vec<String>,
+    /// Guest arguments after the program name
+    args: Vec<String>,
+}
+
+fn usage() -> ! {
+    eprintln!(
+        "usage: rust-ia64 [--config FILE] [--base ADDR] [--entry ADDR]\n\
+         \x20                [--itc-freq HZ] [--wx invalidate|log|fault]\n\
+         \x20                [--uninit off|warn|poison|fault]\n\
+         \x20                [--core FILE] [--debug] [--symbols FILE]\n\
+         \x20                [--panic SYMBOL|ADDR]... [--panic-port ADDR]...\n\
+         \x20                [--strace] [--strace-file FILE] [--strace-filter NAME,...]\n\
+         \x20                [--trace] [--trace-file FILE]\n\
+         \x20                [--timeline FILE] [--timeline-channels NAME,...]\n\
+         \x20                [--rse-profile] [--rse-check] [--script FILE]\n\
+         \x20                [--profile FILE] [--profile-format folded|pprof]\n\
+         \x20                [--profile-interval N] [--profile-stacks]\n\
+         \x20                [--accelerate] [--remote SOCKET]\n\
+         \x20                [--stats] [--strict-decode] [--strict-types]\n\
+         \x20                [--layout]\n\
+         \x20                [--unaligned allow|fault|fixup]\n\
+         \x20                [--dispersal itanium|itanium2] [--strict-dispersal]\n\
+         \x20                [--timing]\n\
+         \x20                [--livelock warn|stop|debug] [--livelock-bundles N]\n\
+         \x20                [--livelock-window BYTES]\n\
+         \x20                [--repro FILE] [--repro-interval N]\n\
+         \x20                [--snapshot-compression LEVEL]\n\
+         \x20                [--chaos SEED|random] [--chaos-seed SEED|random]\n\
+         \x20                [--chaos-delay N] [--chaos-jitter N]\n\
+         \x20                [--hostfs DIR | --hostfs-rw DIR]\n\
+         \x20                [--env KEY=VALUE]... [IMAGE [ARG...]]\n\
+         \x20      rust-ia64 bench-insn INSN [--iterations N] [--decode]\n\
+         \x20      rust-ia64 replay FILE\n\
+         \x20      rust-ia64 snapdiff OLD NEW [--symbols FILE]\n\
+         \x20      rust-ia64 selftest\n\
+         \n\
+         IMAGE is required unless the configuration maps the guest code.\n\
+         ELF images load at their link addresses, or at ADDR if position\n\
+         independent; other images are flat binaries loaded at ADDR.\n\
+         Arguments after IMAGE are passed to the guest.\n\
+         INSN is one instruction in assembler syntax, e.g. \"mov r14 = ip\"."
+    );
+    process::exit(EXIT_USAGE);
+}
+
+/// Seed for `--chaos random`, from the host clock
+fn random_seed() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_nanos() as u64)
+}
+
+fn parse_args() -> Options {
+    let mut base = DEFAULT_BASE;
+    let mut entry = None;
+    let mut image = None;
+    let mut config = None;
+    let mut symbols = None;
+    let mut debug = false;
+    let mut itc_frequency = None;
+    let mut core = None;
+    let mut wx_policy = None;
+    let mut uninit = None;
+    let mut unaligned = None;
+    let mut panic_functions = Vec::new();
+    let mut panic_ports = Vec::new();
+    let mut strace = false;
+    let mut strace_file = None;
+    let mut strace_filter = Vec::new();
+    let mut timeline = None;
+    let mut timeline_channels = Vec::new();
+    let mut trace = false;
+    let mut trace_file = None;
+    let mut rse_profile = false;
+    let mut rse_check = false;
+    let mut profile = None;
+    let mut profile_format = ProfileFormat::Folded;
+    let mut profile_interval = DEFAULT_SAMPLE_INTERVAL;
+    let mut profile_stacks = false;
+    let mut script = None;
+    let mut remote = None;
+    let mut accelerate = false;
+    let mut stats = false;
+    let mut strict_decode = false;
+    let mut strict_types = false;
+    let mut layout = false;
+    let mut dispersal = None;
+    let mut strict_dispersal = false;
+    let mut timing = false;
+    let mut livelock = None;
+    let mut livelock_config = LivelockConfig::default();
+    let mut repro = None;
+    let mut repro_interval = repro::DEFAULT_CHECKPOINT_INTERVAL;
+    let mut snapshot_compression = Compression::None;
+    let mut chaos: Option<ChaosConfig> = None;
+    let mut hostfs = None;
+    let mut env = Vec::new();
+    let mut guest_args = Vec::new();
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        // Everything after the image belongs to the guest
+        if image.is_some() {
+            guest_args.push(arg);
+            continue;
+        }
+        match arg.as_str() {
+            "--base" => {
+                base = args
+                    .next()
+                    .and_then(|v| parse_number(&v).ok())
+                    .unwrap_or_else(|| usage())
+            }
+            "--entry" => {
+                entry = Some(
+                    args.next()
+                        .and_then(|v| parse_number(&v).ok())
+                        .unwrap_or_else(|| usage()),
+                )
+            }
+            "--config" => config = Some(args.next().unwrap_or_else(|| usage())),
+            "--symbols" => symbols = Some(args.next().unwrap_or_else(|| usage())),
+            "--debug" => debug = true,
+            "--script" => script = Some(args.next().unwrap_or_else(|| usage())),
+            "--remote" => remote = Some(args.next().unwrap_or_else(|| usage())),
+            "--panic" => panic_functions.push(args.next().unwrap_or_else(|| usage())),
+            "--panic-port" => panic_ports.push(
+                args.next()
+                    .and_then(|v| parse_number(&v).ok())
+                    .unwrap_or_else(|| usage()),
+            ),
+            "--strace" => strace = true,
+            "--strace-file" => {
+                strace = true;
+                strace_file = Some(args.next().unwrap_or_else(|| usage()));
+            }
+            "--strace-filter" => {
+                strace = true;
+                let names = args.next().unwrap_or_else(|| usage());
+                strace_filter.extend(names.split(',').map(str::to_string));
+            }
+            "--timeline" => timeline = Some(args.next().unwrap_or_else(|| usage())),
+            "--timeline-channels" => {
+                let names = args.next().unwrap_or_else(|| usage());
+                timeline_channels.extend(names.split(',').map(str::to_string));
+            }
+            "--trace" => trace = true,
+            "--trace-file" => {
+                trace = true;
+                trace_file = Some(args.next().unwrap_or_else(|| usage()));
+            }
+            "--rse-profile" => rse_profile = true,
+            "--rse-check" => rse_check = true,
+            "--profile" => profile = Some(args.next().unwrap_or_else(|| usage())),
+            "--profile-format" => {
+                profile_format = match args.next().as_deref() {
+                    Some("folded") => ProfileFormat::Folded,
+                    Some("pprof") => ProfileFormat::Pprof,
+                    _ => usage(),
+                }
+            }
+            "--profile-interval" => {
+                profile_interval = args
+                    .next()
+                    .and_then(|v| parse_number(&v).ok())
+                    .filter(|&n| n > 0)
+                    .unwrap_or_else(|| usage())
+            }
+            "--profile-stacks" => profile_stacks = true,
+            "--accelerate" => accelerate = true,
+            "--stats" => stats = true,
+            "--strict-decode" => strict_decode = true,
+            "--strict-types" => strict_types = true,
+            "--layout" => layout = true,
+            "--dispersal" => {
+                dispersal = Some(
+                    args.next()
+                        .and_then(|v| MachineModel::from_name(&v))
+                        .unwrap_or_else(|| usage()),
+                )
+            }
+            "--strict-dispersal" => strict_dispersal = true,
+            "--timing" => timing = true,
+            "--livelock" => {
+                livelock = Some(match args.next().as_deref() {
+                    Some("warn") => LivelockAction::Warn,
+                    Some("stop") => LivelockAction::Stop,
+                    Some("debug") => LivelockAction::Debug,
+                    _ => usage(),
+                })
+            }
+            "--livelock-bundles" => {
+                livelock.get_or_insert(LivelockAction::Stop);
+                livelock_config.bundles = args
+                    .next()
+                    .and_then(|v| parse_number(&v).ok())
+                    .filter(|&n| n > 0)
+                    .unwrap_or_else(|| usage())
+            }
+            "--livelock-window" => {
+                livelock.get_or_insert(LivelockAction::Stop);
+                livelock_config.window = args
+                    .next()
+                    .and_then(|v| parse_number(&v).ok())
+                    .filter(|&n| n > 0)
+                    .unwrap_or_else(|| usage())
+            }
+            "--repro" => repro = Some(args.next().unwrap_or_else(|| usage())),
+            "--repro-interval" => {
+                repro_interval = args
+                    .next()
+                    .and_then(|v| parse_number(&v).ok())
+                    .filter(|&n| n > 0)
+                    .unwrap_or_else(|| usage())
+            }
+            "--snapshot-compression" => {
+                snapshot_compression = args
+                    .next()
+                    .and_then(|v| v.parse().ok())
+                    .and_then(Compression::from_level)
+                    .unwrap_or_else(|| usage())
+            }
+            "--chaos" | "--chaos-seed" => {
+                let seed = match args.next().as_deref() {
+                    Some("random") => random_seed(),
+                    Some(seed) => parse_number(seed).unwrap_or_else(|_| usage()),
+                    None => usage(),
+                };
+                chaos.get_or_insert_with(|| ChaosConfig::new(seed)).seed = seed;
+            }
+            "--chaos-delay" => {
+                let max_delay = args
+                    .next()
+                    .and_then(|v| parse_number(&v).ok())
+                    .unwrap_or_else(|| usage());
+                chaos
+                    .get_or_insert_with(|| ChaosConfig::new(random_seed()))
+                    .max_delay = max_delay;
+            }
+            "--chaos-jitter" => {
+                let latency_jitter = args
+                    .next()
+                    .and_then(|v| parse_number(&v).ok())
+                    .unwrap_or_else(|| usage());
+                chaos
+                    .get_or_insert_with(|| ChaosConfig::new(random_seed()))
+                    .latency_jitter = latency_jitter;
+            }
+            "--hostfs" => {
+                hostfs = Some((args.next().unwrap_or_else(|| usage()), HostFsMode::ReadOnly))
+            }
+            "--hostfs-rw" => {
+                hostfs = Some((
+                    args.next().unwrap_or_else(|| usage()),
+                    HostFsMode::ReadWrite,
+                ))
+            }
+            "--env" => env.push(
+                args.next()
+                    .filter(|v| v.contains('='))
+                    .unwrap_or_else(|| usage()),
+            ),
+            "--core" => core = Some(args.next().unwrap_or_else(|| usage())),
+            "--wx" => {
+                wx_policy = Some(match args.next().as_deref() {
+                    Some("invalidate") => WxPolicy::Invalidate,
+                    Some("log") => WxPolicy::Log,
+                    Some("fault") => WxPolicy::Fault,
+                    _ => usage(),
+                })
+            }
+            "--uninit" => {
+                uninit = Some(match args.next().as_deref() {
+                    Some("off") => UninitPolicy::Off,
+                    Some("warn") => UninitPolicy::Warn,
+                    Some("poison") => UninitPolicy::Poison,
+                    Some("fault") => UninitPolicy::Fault,
+                    _ => usage(),
+                })
+            }
+            "--unaligned" => {
+                unaligned = Some(match args.next().as_deref() {
+                    Some("allow") => AlignmentPolicy::Allow,
+                    Some("fault") => AlignmentPolicy::Fault,
+                    Some("fixup") => AlignmentPolicy::Fixup,
+                    _ => usage(),
+                })
+            }
+            "--itc-freq" => {
+                itc_frequency = Some(
+                    args.next()
+                        .and_then(|v| parse_number(&v).ok())
+                        .filter(|&hz| hz > 0)
+                        .unwrap_or_else(|| usage()),
+                )
+            }
+            "-h" | "--help" => usage(),
+            _ if !arg.starts_with('-') => image = Some(arg),
+            _ => usage(),
+        }
+    }
+
+    if image.is_none() && config.is_none() {
+        usage();
+    }
+
+    Options {
+        base,
+        entry,
+        image,
+        config,
+        symbols,
+        debug,
+        itc_frequency,
+        core,
+        wx_policy,
+        uninit,
+        panic_functions,
+        panic_ports,
+        strace,
+        strace_file,
+        strace_filter,
+        timeline,
+        timeline_channels,
+        trace,
+        trace_file,
+        rse_profile,
+        rse_check,
+        profile,
+        profile_format,
+        profile_interval,
+        profile_stacks,
+        script,
+        remote,
+        accelerate,
+        stats,
+        strict_decode,
+        strict_types,
+        layout,
+        unaligned,
+        dispersal,
+        strict_dispersal,
+        timing,
+        livelock,
+        livelock_config,
+        repro,
+        repro_interval,
+        snapshot_compression,
+        chaos,
+        hostfs,
+        env,
+        args: guest_args,
+    }
+}
+
+/// Read debugger commands from standard input until end of input
+fn debug_loop(emulator: &mut Emulator, debugger: &mut Debugger) {
+    let stdin = io::stdin();
+    let mut lines = stdin.lock().lines();
+    loop {
+        print!("(ia64) ");
+        let _ = io::stdout().flush();
+
+        let Some(Ok(line)) = lines.next() else {
+            break;
+        };
+        match line.trim() {
+            "quit" | "q" => break,
+            line => match debugger.execute(emulator, line) {
+                Ok(output) => print!("{}", output),
+                Err(e) => eprintln!("error: {}", e),
+            },
+        }
+    }
+}
+
+/// Time executions of one instruction and print the result
+fn bench_insn(args: &[String]) -> ! {
+    let mut text = None;
+    let mut iterations = bench::DEFAULT_ITERATIONS;
+    let mut decode = false;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--iterations" => {
+                iterations = args
+                    .next()
+                    .and_then(|v| parse_number(v).ok())
+                    .filter(|&n| n > 0)
+                    .unwrap_or_else(|| usage())
+            }
+            "--decode" => decode = true,
+            _ if text.is_none() => text = Some(arg.as_str()),
+            _ => usage(),
+        }
+    }
+    let text = text.unwrap_or_else(|| usage());
+
+    let bench = if decode {
+        bench::bench_decode
+    } else {
+        bench::bench_instruction
+    };
+    let result =
+        BenchInstruction::parse(text).and_then(|instruction| bench(&instruction, iterations));
+    match result {
+        Ok(report) => {
+            println!("{}: {}", text, report);
+            process::exit(0);
+        }
+        Err(e) => {
+            eprintln!("rust-ia64: {}", e);
+            process::exit(EXIT_FAILURE);
+        }
+    }
+}
+
+/// Replay a reproducer and report whether the fault recurs
+fn replay(args: &[String]) -> ! {
+    let [path] = args else { usage() };
+    let result = ReproBundle::load(path).and_then(|bundle| {
+        let outcome = bundle.replay()?;
+        Ok((bundle, outcome))
+    });
+    match result {
+        Ok((bundle, ReplayOutcome::Reproduced)) => {
+            println!(
+                "{}: fault reproduced after {} bundles: {}",
+                path,
+                bundle.trace.len(),
+                bundle.fault
+            );
+            process::exit(0);
+        }
+        Ok((_, outcome)) => {
+            eprintln!("rust-ia64: {}: {}", path, outcome);
+            process::exit(EXIT_FAILURE);
+        }
+        Err(e) => {
+            eprintln!("rust-ia64: {}", e);
+            process::exit(EXIT_FAILURE);
+        }
+    }
+}
+
+/// Execute every implemented instruction and print the pass/fail matrix
+fn selftest(args: &[String]) -> ! {
+    if !args.is_empty() {
+        usage();
+    }
+    let report = selftest::run();
+    print!("{}", report);
+    process::exit(if report.all_passed() { 0 } else { EXIT_FAILURE });
+}
+
+/// Compare two snapshot files and print the differences
+fn snapdiff(args: &[String]) -> ! {
+    let mut paths = Vec::new();
+    let mut debugger = Debugger::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--symbols" => {
+                let path = args.next().unwrap_or_else(|| usage());
+                match std::fs::read_to_string(path) {
+                    Ok(text) => debugger.symbols.parse_nm(&text),
+                    Err(e) => {
+                        eprintln!("rust-ia64: cannot read {}: {}", path, e);
+                        process::exit(EXIT_FAILURE);
+                    }
+                }
+            }
+            _ if !arg.starts_with('-') => paths.push(arg),
+            _ => usage(),
+        }
+    }
+    let [old, new] = paths[..] else { usage() };
+    match Snapshot::load(old).and_then(|old| Ok((old, Snapshot::load(new)?))) {
+        Ok((old, new)) => {
+            let diff = snapdiff::diff(&old, &new);
+            print!("{}", diff.report(&debugger.symbols));
+            // Exit statuses follow diff(1): 1 if they differ, 2 on errors
+            process::exit(if diff.is_empty() { 0 } else { 1 });
+        }
+        Err(e) => {
+            eprintln!("rust-ia64: {}", e);
+            process::exit(EXIT_USAGE);
+        }
+    }
+}
+
 fn main() {
-    println!("Hello, world!");
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match args.first().map(String::as_str) {
+        Some("bench-insn") => bench_insn(&args[1..]),
+        Some("replay") => replay(&args[1..]),
+        Some("snapdiff") => snapdiff(&args[1..]),
+        Some("selftest") => selftest(&args[1..]),
+        _ => {}
+    }
+    let options = parse_args();
+
+    let mut emulator = match &options.config {
+        Some(path) => Machine::from_config(path).unwrap_or_else(|e| {
+            eprintln!("rust-ia64: {}", e);
+            process::exit(EXIT_FAILURE);
+        }),
+        None => Emulator::new(),
+    };
+    if options.strict_decode {
+        emulator.set_strict_decode(true);
+    }
+    if options.strict_types {
+        emulator.cpu.strict_types = true;
+    }
+    if let Some(policy) = options.unaligned {
+        emulator.cpu.alignment_policy = policy;
+    }
+    if options.dispersal.is_some() || options.strict_dispersal {
+        let machine = options
+            .dispersal
+            .or(emulator.dispersal.as_ref().map(Dispersal::machine))
+            .unwrap_or(MachineModel::Itanium2);
+        emulator.dispersal = Some(Dispersal::new(machine, options.strict_dispersal));
+    }
+    if options.timing && emulator.timing.is_none() {
+        emulator.timing = Some(TimingModel::default());
+    }
+    if let Some(policy) = options.wx_policy {
+        emulator.memory.set_wx_policy(policy);
+    }
+    if let Some(policy) = options.uninit {
+        emulator.memory.set_uninit_policy(policy);
+    }
+    if let Some(frequency) = options.itc_frequency {
+        emulator
+            .cpu
+            .set_timer_mode(TimerMode::HostTime { frequency });
+    }
+    if emulator.timing.is_some() && matches!(emulator.cpu.timer.mode(), TimerMode::HostTime { .. })
+    {
+        eprintln!("rust-ia64: --timing needs an instruction-counted ITC, not --itc-freq");
+        process::exit(EXIT_USAGE);
+    }
+    emulator.cpu.syscall_mgr.set_stdio(Stdio::host());
+    if let Some((root, mode)) = &options.hostfs {
+        match HostFs::new(root, *mode) {
+            Ok(hostfs) => emulator.cpu.syscall_mgr.set_hostfs(hostfs),
+            Err(e) => {
+                eprintln!("rust-ia64: cannot share {}: {}", root, e);
+                process::exit(EXIT_FAILURE);
+            }
+        }
+    }
+
+    if let Some(path) = &options.image {
+        let image = std::fs::read(path).unwrap_or_else(|e| {
+            eprintln!("rust-ia64: cannot read {}: {}", path, e);
+            process::exit(EXIT_FAILURE);
+        });
+        let loaded = if loader::is_elf(&image) {
+            emulator.load_elf(&image, options.base).map(|layout| {
+                if options.layout {
+                    eprintln!("{}", layout);
+                }
+                if let Some(entry) = options.entry {
+                    emulator.cpu.ip = entry;
+                }
+            })
+        } else {
+            let entry = options.entry.unwrap_or(options.base);
+            emulator.load_flat_image(options.base, &image, entry)
+        };
+        if let Err(e) = loaded {
+            eprintln!("rust-ia64: cannot load {}: {}", path, e);
+            process::exit(EXIT_FAILURE);
+        }
+    } else if let Some(entry) = options.entry {
+        emulator.cpu.ip = entry;
+    }
+    if options.image.is_some() || !options.env.is_empty() {
+        let program = options.image.as_ref().or(options.config.as_ref());
+        let args: Vec<String> = program.into_iter().chain(&options.args).cloned().collect();
+        if let Err(e) = emulator.setup_process(&args, &options.env) {
+            eprintln!("rust-ia64: cannot set up the guest stack: {}", e);
+            process::exit(EXIT_FAILURE);
+        }
+    }
+
+    let mut debugger = Debugger::new();
+    debugger.compression = options.snapshot_compression;
+    if let Some(path) = &options.symbols {
+        match std::fs::read_to_string(path) {
+            Ok(text) => debugger.symbols.parse_nm(&text),
+            Err(e) => {
+                eprintln!("rust-ia64: cannot read {}: {}", path, e);
+                process::exit(EXIT_FAILURE);
+            }
+        }
+    }
+
+    for name in &options.panic_functions {
+        let address = parse_number(name)
+            .ok()
+            .or_else(|| debugger.symbols.find(name).map(|symbol| symbol.address))
+            .unwrap_or_else(|| {
+                eprintln!("rust-ia64: unknown panic symbol {}", name);
+                process::exit(EXIT_USAGE);
+            });
+        emulator.add_panic_hook(PanicHook::function(name, address));
+    }
+    for &port in &options.panic_ports {
+        emulator.add_panic_hook(PanicHook::port("panic port", port));
+    }
+
+    if options.accelerate {
+        let mut found = false;
+        for builtin in Builtin::ALL {
+            if let Some(symbol) = debugger.symbols.find(builtin.name()) {
+                emulator.add_intercept(Intercept::builtin(builtin, symbol.address));
+                found = true;
+            }
+        }
+        if !found {
+            eprintln!("rust-ia64: --accelerate found no memcpy, memmove, memset or strlen symbols");
+        }
+    }
+
+    if options.strace {
+        let output = match &options.strace_file {
+            Some(path) => match std::fs::File::create(path) {
+                Ok(file) => StraceOutput::Writer(Box::new(file)),
+                Err(e) => {
+                    eprintln!("rust-ia64: cannot create {}: {}", path, e);
+                    process::exit(EXIT_FAILURE);
+                }
+            },
+            None => StraceOutput::Stderr,
+        };
+        let mut strace = Strace::new(output);
+        if !options.strace_filter.is_empty() {
+            strace = strace.only(&options.strace_filter).unwrap_or_else(|e| {
+                eprintln!("rust-ia64: {}", e);
+                process::exit(EXIT_USAGE);
+            });
+        }
+        emulator.set_strace(Some(strace));
+    }
+    if options.trace {
+        let tracer: Box<dyn Tracer> = match &options.trace_file {
+            Some(path) => Box::new(WriterTracer::create(path).unwrap_or_else(|e| {
+                eprintln!("rust-ia64: {}", e);
+                process::exit(EXIT_FAILURE);
+            })),
+            None => Box::new(StderrTracer),
+        };
+        emulator.set_tracer(Some(tracer));
+    }
+
+    if options.timeline.is_some() {
+        let timeline = Timeline::default();
+        if !options.timeline_channels.is_empty() {
+            timeline.set_channels(Some(&options.timeline_channels));
+        }
+        emulator.set_timeline(Some(timeline));
+    }
+
+    if options.rse_profile {
+        emulator.cpu.set_rse_profiling(true);
+    }
+    if options.rse_check {
+        emulator.cpu.set_rse_checking(true);
+    }
+    if options.profile.is_some() {
+        emulator.cpu.set_sample_profiler(Some(SampleProfiler::new(
+            options.profile_interval,
+            options.profile_stacks,
+        )));
+    }
+    if options.livelock.is_some() {
+        emulator.set_livelock_detection(Some(options.livelock_config));
+    }
+    if let Some(chaos) = options.chaos {
+        // The seed is all it takes to replay the run's timing
+        eprintln!("rust-ia64: chaos seed {}", chaos.seed);
+        emulator.set_chaos(Some(chaos));
+    }
+
+    if let Some(path) = &options.script {
+        run_script(&mut emulator, &mut debugger, path);
+        if !options.debug {
+            return;
+        }
+    }
+
+    if options.debug {
+        debug_loop(&mut emulator, &mut debugger);
+        return;
+    }
+    if let Some(path) = &options.remote {
+        serve_remote(&mut emulator, path);
+        return;
+    }
+
+    let mut recorder = options
+        .repro
+        .as_ref()
+        .map(|_| CrashRecorder::new(options.repro_interval));
+    let result = loop {
+        let result = match &mut recorder {
+            Some(recorder) => recorder.run(&mut emulator),
+            None => emulator.run(),
+        };
+        if matches!(result, Ok(StopReason::Livelock))
+            && options.livelock == Some(LivelockAction::Warn)
+        {
+            if let Some(report) = emulator.livelock_report() {
+                eprint!("rust-ia64: warning: {}", report);
+            }
+            continue;
+        }
+        break result;
+    };
+    // Dropping the tracer flushes the trace file
+    emulator.set_tracer(None);
+    if let Some(profiler) = &emulator.cpu.rse_profiler {
+        eprint!("{}", profiler.report(&debugger.symbols));
+    }
+    if let Some(checker) = &emulator.cpu.rse_checker {
+        eprint!("{}", checker.report(&debugger.symbols));
+    }
+    if let (Some(path), Some(profiler)) = (&options.profile, &emulator.cpu.sample_profiler) {
+        let data = match options.profile_format {
+            ProfileFormat::Folded => profiler.folded(&debugger.symbols).into_bytes(),
+            ProfileFormat::Pprof => profiler.pprof(&debugger.symbols),
+        };
+        if let Err(e) = std::fs::write(path, data) {
+            eprintln!("rust-ia64: cannot write {}: {}", path, e);
+        }
+    }
+    if let (Some(path), Some(timeline)) = (&options.timeline, emulator.timeline()) {
+        if let Err(e) = std::fs::write(path, timeline.chrome_trace()) {
+            eprintln!("rust-ia64: cannot write {}: {}", path, e);
+        }
+    }
+    eprint!(
+        "{}",
+        emulator.cpu.unaligned_fixups.report(&debugger.symbols)
+    );
+    if options.stats {
+        eprint!("{}", format_stats(&emulator));
+    }
+    for violation in emulator.take_wx_violations() {
+        let backtrace: Vec<String> = violation
+            .backtrace
+            .iter()
+            .map(|ip| format!("{:#x}", ip))
+            .collect();
+        eprintln!(
+            "rust-ia64: write to executable page at {:#x} (backtrace {})",
+            violation.addr,
+            backtrace.join(" <- ")
+        );
+    }
+    for read in emulator.take_uninit_reads() {
+        let backtrace: Vec<String> = read
+            .backtrace
+            .iter()
+            .map(|ip| format!("{:#x}", ip))
+            .collect();
+        eprintln!(
+            "rust-ia64: {} loads of uninitialized memory by {:#x} slot {}, \
+             first {} bytes at {:#x} (backtrace {})",
+            read.count,
+            read.ip,
+            read.slot,
+            read.len,
+            read.addr,
+            backtrace.join(" <- ")
+        );
+    }
+
+    let signal = match result {
+        Ok(StopReason::Exited(code)) => process::exit(code),
+        Ok(reason @ StopReason::Panic) => {
+            if let Some(report) = emulator.panic_report() {
+                eprint!("rust-ia64: {}", report);
+            }
+            coredump::signal_for_stop(reason)
+        }
+        Ok(reason @ StopReason::Livelock) => {
+            if let Some(report) = emulator.livelock_report() {
+                eprint!("rust-ia64: {}", report);
+            }
+            if options.livelock == Some(LivelockAction::Debug) {
+                debug_loop(&mut emulator, &mut debugger);
+                return;
+            }
+            coredump::signal_for_stop(reason)
+        }
+        Ok(reason @ StopReason::DataMatch { addr, value }) => {
+            eprintln!(
+                "rust-ia64: guest stopped on data match {:#x} at {:#x} (ip {:#x})",
+                value, addr, emulator.cpu.ip
+            );
+            coredump::signal_for_stop(reason)
+        }
+        Ok(reason @ StopReason::WatchdogExpired) => {
+            eprintln!("rust-ia64: watchdog expired (ip {:#x})", emulator.cpu.ip);
+            coredump::signal_for_stop(reason)
+        }
+        Ok(reason @ StopReason::Break(imm)) => {
+            eprintln!(
+                "rust-ia64: guest stopped at break {:#x} (ip {:#x})",
+                imm, emulator.cpu.ip
+            );
+            coredump::signal_for_stop(reason)
+        }
+        Err(e) => {
+            eprintln!("rust-ia64: {} (ip {:#x})", e, emulator.cpu.ip);
+            if let (Some(path), Some(recorder)) = (&options.repro, &recorder) {
+                write_repro(
+                    recorder,
+                    options.config.as_deref(),
+                    path,
+                    options.snapshot_compression,
+                );
+            }
+            Some(coredump::signal_for_error(&e))
+        }
+    };
+
+    if let (Some(path), Some(signal)) = (&options.core, signal) {
+        write_core(&emulator, path, signal);
+    }
+    process::exit(EXIT_FAILURE);
+}
+
+/// Run a debugger script, exiting if it fails
+#[cfg(feature = "scripting")]
+fn run_script(emulator: &mut Emulator, debugger: &mut Debugger, path: &str) {
+    match rust_ia64::script::run_file(emulator, debugger, path) {
+        Ok(output) => print!("{}", output),
+        Err(e) => {
+            eprintln!("rust-ia64: {}", e);
+            process::exit(EXIT_FAILURE);
+        }
+    }
+}
+
+/// Scripts need the `scripting` feature
+#[cfg(not(feature = "scripting"))]
+fn run_script(_emulator: &mut Emulator, _debugger: &mut Debugger, _path: &str) {
+    eprintln!("rust-ia64: built without the scripting feature");
+    process::exit(EXIT_USAGE);
+}
+
+/// Serve remote control requests until a client shuts the server down,
+/// exiting if the socket fails
+#[cfg(all(unix, feature = "remote"))]
+fn serve_remote(emulator: &mut Emulator, path: &str) {
+    let result =
+        rust_ia64::remote::RemoteServer::bind(path).and_then(|server| server.serve(emulator));
+    if let Err(e) = result {
+        eprintln!("rust-ia64: {}", e);
+        process::exit(EXIT_FAILURE);
+    }
+}
+
+/// Remote control needs the `remote` feature
+#[cfg(not(all(unix, feature = "remote")))]
+fn serve_remote(_emulator: &mut Emulator, _path: &str) {
+    eprintln!("rust-ia64: built without the remote feature");
+    process::exit(EXIT_USAGE);
+}
+
+/// Minimize the fault that ended a recorded run and write the reproducer,
+/// reporting any failure
+fn write_repro(
+    recorder: &CrashRecorder,
+    config: Option<&str>,
+    path: &str,
+    compression: Compression,
+) {
+    let config = match config.map(std::fs::read_to_string).transpose() {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("rust-ia64: cannot read configuration for reproducer: {}", e);
+            return;
+        }
+    };
+    let result = recorder
+        .minimize(config.as_deref())
+        .and_then(|bundle| bundle.save_compressed(path, compression).map(|()| bundle));
+    match result {
+        Ok(bundle) => eprintln!(
+            "rust-ia64: reproducer of {} bundles written to {}; run it with `rust-ia64 replay {}`",
+            bundle.trace.len(),
+            path,
+            path
+        ),
+        Err(e) => eprintln!("rust-ia64: cannot write reproducer {}: {}", path, e),
+    }
+}
+
+/// Write a core file of the stopped guest, reporting any failure
+fn write_core(emulator: &Emulator, path: &str, signal: i32) {
+    let result = coredump::build_core(emulator, signal)
+        .map_err(|e| e.to_string())
+        .and_then(|core| std::fs::write(path, core).map_err(|e| e.to_string()));
+    match result {
+        Ok(()) => eprintln!("rust-ia64: core dumped to {}", path),
+        Err(e) => eprintln!("rust-ia64: cannot write core {}: {}", path, e),
+    }
 }
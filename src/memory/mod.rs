@@ -1,23 +1,70 @@
 //! Memory management implementation
 //!
 //! This module implements memory management including permissions,
-//! memory mapping, and memory access operations.
-
+//! memory mapping, and memory access operations. Regions shared with host
+//! threads are described in the `shared` module, the policies caches evict
+//! lines by in the `replacement` module, the detection of loads of
+//! never-written memory in the `uninit` module, typed views of guest
+//! ranges in the `view` module, and regions whose backing store passes
+//! through an encryption-like transform in the `transform` module.
+
+pub mod replacement;
+pub mod shared;
+pub mod transform;
+pub mod uninit;
+pub mod view;
+
+use crate::device::{Device, DeviceBus, DeviceId, MachineRequest};
+use crate::emulator::ResetKind;
+use crate::timeline::{Channel, Phase, Timeline};
 use crate::EmulatorError;
-use std::collections::BTreeMap;
+use replacement::{Random, Replacement, ReplacementPolicy};
+use serde::Deserialize;
+use shared::SharedMemory;
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::sync::atomic::{self, Ordering};
+use transform::{Backing, Transform};
+use uninit::{UninitAccess, UninitPolicy, WrittenMap, POISON};
+use view::Endian;
+
+/// Page size used to track writes to executable memory
+pub const PAGE_SIZE: u64 = 4096;
+
+/// Policy for guest writes to executable memory (W^X)
+///
+/// A write only counts as a write to code when it lands on an executable
+/// page instructions have been fetched from since the page was last
+/// written, so data sharing a mapping with code, as in a flat image, is
+/// left alone until it is executed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum WxPolicy {
+    /// Allow the write and invalidate decoded code for the page
+    #[default]
+    Invalidate,
+    /// Allow the write, invalidate decoded code and report a diagnostic
+    Log,
+    /// Reject the write with a memory fault
+    Fault,
+}
 
 /// Memory permissions
-#[derive(Debug, Clone, Copy, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
 pub enum Permissions {
     /// No access
+    #[serde(rename = "none")]
     None,
     /// Read only
+    #[serde(rename = "r")]
     Read,
     /// Read and write
+    #[serde(rename = "rw")]
     ReadWrite,
     /// Read and execute
+    #[serde(rename = "rx")]
     ReadExecute,
     /// Read, write, and execute
+    #[serde(rename = "rwx")]
     ReadWriteExecute,
 }
 
@@ -65,6 +112,49 @@ struct Region {
     permissions: Permissions,
     /// Memory contents
     data: Vec<u8>,
+    /// Optional name used for diagnostics
+    name: Option<String>,
+    /// Bytes written, when loads of uninitialized memory are checked
+    written: Option<WrittenMap>,
+    /// Transformed backing store, for a transformed region
+    backing: Option<Backing>,
+}
+
+impl Region {
+    /// Store `data` at `offset`, encoding it into the backing store of a
+    /// transformed region
+    fn store(&mut self, offset: usize, data: &[u8]) {
+        self.data[offset..offset + data.len()].copy_from_slice(data);
+        if let Some(backing) = &mut self.backing {
+            backing.store(offset, self.base + offset as u64, data);
+        }
+    }
+
+    /// Byte at `offset` as memory returns it, decoded from the backing
+    /// store of a transformed region
+    fn load(&self, offset: usize) -> u8 {
+        match &self.backing {
+            Some(backing) => {
+                let mut byte = [0];
+                backing.load(offset, self.base + offset as u64, &mut byte);
+                byte[0]
+            }
+            None => self.data[offset],
+        }
+    }
+}
+
+/// Memory region whose contents are shared with host threads
+#[derive(Debug)]
+struct SharedRegion {
+    /// Size in bytes
+    size: u64,
+    /// Access permissions
+    permissions: Permissions,
+    /// Contents
+    memory: SharedMemory,
+    /// Optional name used for diagnostics
+    name: Option<String>,
 }
 
 /// Cache line state
@@ -89,8 +179,8 @@ struct CacheLine {
     data: Vec<u8>,
     /// State
     state: CacheLineState,
-    /// Last access time for LRU
-    last_access: u64,
+    /// Installed by a prefetch and not yet used by a demand access
+    prefetched: bool,
 }
 
 impl CacheLine {
@@ -99,7 +189,7 @@ impl CacheLine {
             tag,
             data: vec![0; size],
             state: CacheLineState::Invalid,
-            last_access: 0,
+            prefetched: false,
         }
     }
 
@@ -125,8 +215,6 @@ impl CacheLine {
 struct CacheSet {
     /// Lines in the set
     lines: Vec<CacheLine>,
-    /// Access counter for LRU
-    access_counter: u64,
     #[allow(dead_code)]
     /// Set index
     index: usize,
@@ -138,46 +226,95 @@ impl CacheSet {
             lines: (0..associativity)
                 .map(|_| CacheLine::new(0, line_size))
                 .collect(),
-            access_counter: 0,
             index,
         }
     }
 
-    fn find_line(&mut self, tag: u64) -> Option<&mut CacheLine> {
-        self.access_counter += 1;
-        for line in &mut self.lines {
-            if line.state != CacheLineState::Invalid && line.tag == tag {
-                line.last_access = self.access_counter;
-                return Some(line);
-            }
-        }
-        None
-    }
-
-    fn find_victim(&mut self) -> usize {
-        self.access_counter += 1;
-
-        // First try to find an invalid line
-        if let Some((idx, _)) = self
-            .lines
+    fn find_way(&self, tag: u64) -> Option<usize> {
+        self.lines
             .iter()
-            .enumerate()
-            .find(|(_, l)| l.state == CacheLineState::Invalid)
-        {
-            return idx;
-        }
+            .position(|line| line.state != CacheLineState::Invalid && line.tag == tag)
+    }
 
-        // Otherwise use LRU replacement
+    fn find_invalid(&self) -> Option<usize> {
         self.lines
             .iter()
-            .enumerate()
-            .min_by_key(|(_, line)| line.last_access)
-            .map(|(i, _)| i)
-            .unwrap()
+            .position(|line| line.state == CacheLineState::Invalid)
     }
 
     fn find_line_mut(&mut self, tag: u64) -> Option<&mut CacheLine> {
-        self.lines.iter_mut().find(|line| line.tag == tag)
+        self.lines
+            .iter_mut()
+            .find(|line| line.state != CacheLineState::Invalid && line.tag == tag)
+    }
+}
+
+/// Size, shape and replacement policy of one cache level
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CacheGeometry {
+    /// Total size in bytes
+    pub size: usize,
+    /// Set associativity
+    pub associativity: usize,
+    /// Line size in bytes
+    pub line_size: usize,
+    /// Policy choosing the line to evict from a full set
+    #[serde(default)]
+    pub replacement: Replacement,
+}
+
+impl CacheGeometry {
+    /// 32KB L1 cache, 8-way associative, 64-byte lines
+    pub const L1: CacheGeometry = CacheGeometry {
+        size: 32 * 1024,
+        associativity: 8,
+        line_size: 64,
+        replacement: Replacement::Lru,
+    };
+
+    /// 256KB L2 cache, 8-way associative, 64-byte lines
+    pub const L2: CacheGeometry = CacheGeometry {
+        size: 256 * 1024,
+        associativity: 8,
+        line_size: 64,
+        replacement: Replacement::Lru,
+    };
+
+    /// 6MB L3 cache, 12-way associative, 128-byte lines
+    pub const L3: CacheGeometry = CacheGeometry {
+        size: 6 * 1024 * 1024,
+        associativity: 12,
+        line_size: 128,
+        replacement: Replacement::Lru,
+    };
+
+    /// Check that the geometry describes a cache the model can index
+    ///
+    /// Lines and sets are selected by address bits, so both the line size
+    /// and the number of sets must be powers of two.
+    pub fn validate(&self) -> Result<(), String> {
+        if !self.line_size.is_power_of_two() || self.line_size < 8 {
+            return Err(format!(
+                "line_size {} is not a power of two of at least 8",
+                self.line_size
+            ));
+        }
+        if self.associativity == 0 {
+            return Err("associativity must be at least 1".to_string());
+        }
+        let way_size = self.associativity * self.line_size;
+        if self.size == 0 || !self.size.is_multiple_of(way_size) {
+            return Err(format!(
+                "size {} is not a multiple of associativity * line_size ({})",
+                self.size, way_size
+            ));
+        }
+        let sets = self.size / way_size;
+        if !sets.is_power_of_two() {
+            return Err(format!("{} sets is not a power of two", sets));
+        }
+        Ok(())
     }
 }
 
@@ -203,6 +340,80 @@ pub enum CacheHint {
     Bias,
 }
 
+/// Prefetch effectiveness counters
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PrefetchStats {
+    /// Prefetches issued
+    pub issued: u64,
+    /// Prefetches of lines that were already cached
+    pub redundant: u64,
+    /// Prefetched lines later hit by a demand access
+    pub useful: u64,
+    /// Prefetched lines evicted or invalidated before any demand access
+    pub unused: u64,
+}
+
+impl PrefetchStats {
+    /// Fraction of resolved prefetches that were used (accuracy)
+    pub fn accuracy(&self) -> f64 {
+        ratio(self.useful, self.useful + self.unused)
+    }
+}
+
+/// Memory access statistics
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MemoryStats {
+    /// Demand byte reads
+    pub demand_reads: u64,
+    /// Demand byte reads that missed the first-level cache
+    pub l1_misses: u64,
+    /// Demand byte reads that missed the first two cache levels
+    pub l2_misses: u64,
+    /// Demand byte reads that missed every cache level
+    pub demand_misses: u64,
+    /// Demand byte reads that missed L1 but hit the victim cache
+    pub victim_hits: u64,
+    /// Demand byte reads that missed every cache level and were decoded
+    /// from a transformed region's backing store
+    pub transformed_reads: u64,
+    /// Bytes stored into transformed regions
+    pub transformed_writes: u64,
+    /// Valid lines evicted from each cache level, L1 first
+    pub evictions: [u64; 3],
+    /// Prefetch counters
+    pub prefetch: PrefetchStats,
+}
+
+impl MemoryStats {
+    /// Fraction of would-be misses served by prefetched lines (coverage)
+    pub fn prefetch_coverage(&self) -> f64 {
+        ratio(
+            self.prefetch.useful,
+            self.prefetch.useful + self.demand_misses,
+        )
+    }
+}
+
+/// Ratio that is zero when there is nothing to compare against
+fn ratio(count: u64, total: u64) -> f64 {
+    if total == 0 {
+        0.0
+    } else {
+        count as f64 / total as f64
+    }
+}
+
+/// Cache level identifier
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum CacheLevelId {
+    /// First-level cache
+    L1,
+    /// Second-level cache
+    L2,
+    /// Third-level cache
+    L3,
+}
+
 /// Cache level
 #[derive(Debug)]
 struct CacheLevel {
@@ -214,7 +425,6 @@ struct CacheLevel {
     #[allow(dead_code)]
     /// Set associativity
     associativity: usize,
-    #[allow(dead_code)]
     /// Line size in bytes
     line_size: usize,
     /// Line size bits for address decomposition
@@ -226,10 +436,26 @@ struct CacheLevel {
     #[allow(dead_code)]
     /// Write policy
     write_policy: WritePolicy,
+    /// Prefetched lines hit by a demand access
+    useful_prefetches: u64,
+    /// Prefetched lines dropped before any demand access
+    unused_prefetches: u64,
+    /// Configured replacement policy
+    replacement: Replacement,
+    /// Policy choosing the line to evict from a full set
+    policy: Box<dyn ReplacementPolicy>,
+    /// Valid lines evicted to make room for others
+    evictions: u64,
 }
 
 impl CacheLevel {
-    fn new(size: usize, associativity: usize, line_size: usize) -> Self {
+    fn new(geometry: CacheGeometry) -> Self {
+        let CacheGeometry {
+            size,
+            associativity,
+            line_size,
+            replacement,
+        } = geometry;
         let num_sets = size / (associativity * line_size);
         let line_bits = line_size.trailing_zeros();
         let set_bits = num_sets.trailing_zeros();
@@ -245,9 +471,23 @@ impl CacheLevel {
             set_bits,
             non_temporal: false,
             write_policy: WritePolicy::WriteThrough,
+            useful_prefetches: 0,
+            unused_prefetches: 0,
+            replacement,
+            policy: replacement.build(num_sets, associativity),
+            evictions: 0,
         }
     }
 
+    /// Pick victims at random from a generator seeded with `seed`, or go
+    /// back to the configured policy with `None`
+    fn set_replacement_seed(&mut self, seed: Option<u64>) {
+        self.policy = match seed {
+            Some(seed) => Box::new(Random::with_seed(self.associativity, seed)),
+            None => self.replacement.build(self.num_sets, self.associativity),
+        };
+    }
+
     fn decompose_address(&self, addr: u64) -> (u64, usize, usize) {
         let offset = addr & ((1 << self.line_bits) - 1);
         let set_idx = ((addr >> self.line_bits) & ((1 << self.set_bits) - 1)) as usize;
@@ -283,22 +523,25 @@ impl CacheLevel {
         }
 
         let (tag, set_idx, offset) = self.decompose_address(addr);
-        let set = &mut self.sets[set_idx];
+        let Some(way) = self.sets[set_idx].find_way(tag) else {
+            return false;
+        };
 
-        if let Some(line) = set.find_line(tag) {
-            // Cache hit
-            data.copy_from_slice(&line.data[offset..offset + data.len()]);
-            true
-        } else {
-            false
+        // Cache hit
+        self.policy.touch(set_idx, way);
+        let line = &mut self.sets[set_idx].lines[way];
+        data.copy_from_slice(&line.data[offset..offset + data.len()]);
+        if line.prefetched {
+            line.prefetched = false;
+            self.useful_prefetches += 1;
         }
+        true
     }
 
     #[allow(dead_code)]
     /// Write data to cache
     fn write(&mut self, addr: u64, data: &[u8]) {
-        let (_old_addr, old_data) = self.write_to_cache(addr, data);
-        if let Some(_old_data) = old_data {
+        if let Some((_old_addr, Some(_old_data))) = self.write_to_cache(addr, data) {
             // Write back to memory will be handled by the caller
             // This avoids the need for a mutable reference to Memory
             // and simplifies the borrowing rules
@@ -339,39 +582,95 @@ impl CacheLevel {
         self.non_temporal = value;
     }
 
-    fn write_to_cache(&mut self, addr: u64, data: &[u8]) -> (u64, Option<Vec<u8>>) {
+    /// Store data into the line holding `addr`, allocating it on a miss
+    ///
+    /// Returns the address of a valid line evicted to make room, with its
+    /// contents if it was dirty.
+    fn write_to_cache(&mut self, addr: u64, data: &[u8]) -> Option<(u64, Option<Vec<u8>>)> {
         let (tag, set_index, offset) = self.decompose_address(addr);
-        let set = &mut self.sets[set_index];
-        let counter = set.access_counter;
 
-        if let Some(line) = set.find_line_mut(tag) {
+        if let Some(way) = self.sets[set_index].find_way(tag) {
             // Cache hit
+            self.policy.touch(set_index, way);
+            let line = &mut self.sets[set_index].lines[way];
             line.data[offset..offset + data.len()].copy_from_slice(data);
             line.state = CacheLineState::Modified;
-            line.last_access = counter;
-            set.access_counter += 1;
-            return (0, None); // No eviction needed
+            return None; // No eviction needed
         }
 
-        // Cache miss - need to find a line to evict
-        let victim_idx = set.find_victim();
-        let victim = &mut set.lines[victim_idx];
+        // Cache miss - use an invalid line, or ask the policy for a victim
+        let victim_idx = match self.sets[set_index].find_invalid() {
+            Some(way) => way,
+            None => self.policy.victim(set_index),
+        };
+        self.policy.insert(set_index, victim_idx);
+        let victim = &mut self.sets[set_index].lines[victim_idx];
         let old_tag = victim.tag;
+        let evicted = victim.state != CacheLineState::Invalid;
         let old_data = if victim.state == CacheLineState::Modified {
             Some(victim.data.clone())
         } else {
             None
         };
 
+        if evicted && victim.prefetched {
+            self.unused_prefetches += 1;
+        }
+
         // Update the victim line
         victim.tag = tag;
+        victim.prefetched = false;
         victim.data[offset..offset + data.len()].copy_from_slice(data);
         victim.state = CacheLineState::Modified;
-        victim.last_access = counter;
-        set.access_counter += 1;
 
-        let old_addr = self.compose_address(old_tag, set_index);
-        (old_addr, old_data)
+        if !evicted {
+            return None;
+        }
+        self.evictions += 1;
+        Some((self.compose_address(old_tag, set_index), old_data))
+    }
+}
+
+/// Fully associative buffer of lines recently evicted from L1
+///
+/// Only line addresses are kept: stores reach memory before any cache
+/// level, so a hit refills L1 from memory.
+#[derive(Debug, Default)]
+struct VictimCache {
+    /// Line addresses, least recently evicted first
+    lines: VecDeque<u64>,
+    /// Lines held, none when zero
+    capacity: usize,
+}
+
+impl VictimCache {
+    /// Hold a line evicted from L1, dropping the oldest one when full
+    fn insert(&mut self, line_addr: u64) {
+        if self.capacity == 0 {
+            return;
+        }
+        self.take(line_addr);
+        if self.lines.len() == self.capacity {
+            self.lines.pop_front();
+        }
+        self.lines.push_back(line_addr);
+    }
+
+    /// Remove a line, returning whether it was held
+    fn take(&mut self, line_addr: u64) -> bool {
+        match self.lines.iter().position(|&line| line == line_addr) {
+            Some(i) => {
+                self.lines.remove(i);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Drop lines of `line_size` bytes overlapping `addr..end`
+    fn invalidate(&mut self, addr: u64, end: u64, line_size: u64) {
+        self.lines
+            .retain(|&line| !(line < end && addr < line + line_size));
     }
 }
 
@@ -380,14 +679,38 @@ impl CacheLevel {
 pub struct Memory {
     /// Memory regions
     regions: BTreeMap<u64, Region>,
+    /// Regions shared with host threads, by base address
+    shared: BTreeMap<u64, SharedRegion>,
     /// L1 cache
     l1_cache: CacheLevel,
     /// L2 cache
     l2_cache: CacheLevel,
     /// L3 cache
     l3_cache: CacheLevel,
-    /// Speculative loads
-    speculative_loads: Vec<SpeculativeLoad>,
+    /// Victim cache between L1 and L2
+    victim: VictimCache,
+    /// Access statistics not tracked by the cache levels
+    stats: MemoryStats,
+    /// Policy for writes to executable memory
+    wx_policy: WxPolicy,
+    /// Policy for loads of uninitialized memory
+    uninit_policy: UninitPolicy,
+    /// Uninitialized bytes loaded since the last collection
+    uninit_reads: Vec<UninitAccess>,
+    /// Addresses of writes to executable memory not yet collected
+    code_writes: Vec<u64>,
+    /// Pages instructions were fetched from since they were last written
+    code_pages: BTreeSet<u64>,
+    /// Addresses whose writes are reported
+    write_watches: BTreeSet<u64>,
+    /// Watched addresses written since the last collection
+    watched_writes: Vec<u64>,
+    /// Memory-mapped devices
+    devices: DeviceBus,
+    /// Stores made since creation, including MMIO stores
+    writes: u64,
+    /// Timeline MMIO accesses are recorded on, if enabled
+    timeline: Option<Timeline>,
 }
 
 impl Default for Memory {
@@ -399,18 +722,153 @@ impl Default for Memory {
 impl Memory {
     /// Create new memory instance
     pub fn new() -> Self {
-        Self {
+        Self::with_caches(CacheGeometry::L1, CacheGeometry::L2, CacheGeometry::L3)
+            .expect("default cache geometry is valid")
+    }
+
+    /// Create a memory instance with the given cache geometry
+    pub fn with_caches(
+        l1: CacheGeometry,
+        l2: CacheGeometry,
+        l3: CacheGeometry,
+    ) -> Result<Self, EmulatorError> {
+        for (name, geometry) in [("L1", l1), ("L2", l2), ("L3", l3)] {
+            geometry
+                .validate()
+                .map_err(|e| EmulatorError::MemoryError(format!("{} cache: {}", name, e)))?;
+        }
+
+        Ok(Self {
             regions: BTreeMap::new(),
-            // 32KB L1 cache, 8-way associative, 64-byte lines
-            l1_cache: CacheLevel::new(32 * 1024, 8, 64),
-            // 256KB L2 cache, 8-way associative, 64-byte lines
-            l2_cache: CacheLevel::new(256 * 1024, 8, 64),
-            // 6MB L3 cache, 12-way associative, 128-byte lines
-            l3_cache: CacheLevel::new(6 * 1024 * 1024, 12, 128),
-            speculative_loads: Vec::new(),
+            shared: BTreeMap::new(),
+            l1_cache: CacheLevel::new(l1),
+            l2_cache: CacheLevel::new(l2),
+            l3_cache: CacheLevel::new(l3),
+            victim: VictimCache::default(),
+            stats: MemoryStats::default(),
+            wx_policy: WxPolicy::default(),
+            uninit_policy: UninitPolicy::default(),
+            uninit_reads: Vec::new(),
+            code_writes: Vec::new(),
+            code_pages: BTreeSet::new(),
+            write_watches: BTreeSet::new(),
+            watched_writes: Vec::new(),
+            devices: DeviceBus::new(),
+            writes: 0,
+            timeline: None,
+        })
+    }
+
+    /// Put a victim cache of `lines` L1 lines between L1 and L2
+    ///
+    /// Lines evicted from L1 are held there, and an L1 miss that hits it
+    /// moves the line back without reaching L2. Zero removes it.
+    pub fn set_victim_cache(&mut self, lines: usize) {
+        self.victim = VictimCache {
+            lines: VecDeque::new(),
+            capacity: lines,
+        };
+    }
+
+    /// Have every cache level evict pseudo-random lines, from generators
+    /// seeded by `seed`, or go back to the configured policies with `None`
+    ///
+    /// Lines already cached stay, and so do the policies' counts.
+    pub fn set_replacement_seed(&mut self, seed: Option<u64>) {
+        for (level, cache) in [&mut self.l1_cache, &mut self.l2_cache, &mut self.l3_cache]
+            .into_iter()
+            .enumerate()
+        {
+            cache.set_replacement_seed(seed.map(|seed| seed.wrapping_add(level as u64)));
+        }
+    }
+
+    /// Set the policy for writes to executable memory
+    pub fn set_wx_policy(&mut self, policy: WxPolicy) {
+        self.wx_policy = policy;
+    }
+
+    /// Get the policy for writes to executable memory
+    pub fn wx_policy(&self) -> WxPolicy {
+        self.wx_policy
+    }
+
+    /// Set the policy for loads of uninitialized memory
+    ///
+    /// Written bytes are tracked from now on; regions already mapped count
+    /// as written. Turning the policy off stops tracking.
+    pub fn set_uninit_policy(&mut self, policy: UninitPolicy) {
+        self.uninit_policy = policy;
+        for region in self.regions.values_mut() {
+            region.written = match policy {
+                UninitPolicy::Off => None,
+                _ => region
+                    .written
+                    .take()
+                    .or_else(|| Some(WrittenMap::written(region.size))),
+            };
+        }
+    }
+
+    /// Get the policy for loads of uninitialized memory
+    pub fn uninit_policy(&self) -> UninitPolicy {
+        self.uninit_policy
+    }
+
+    /// Count `size` bytes at `base` as written, e.g. memory the firmware
+    /// cleared before handing over
+    ///
+    /// Parts of the range outside regions are ignored.
+    pub fn mark_initialized(&mut self, base: u64, size: u64) {
+        let end = base.saturating_add(size);
+        for region in self.regions.values_mut() {
+            let start = base.max(region.base);
+            let stop = end.min(region.base + region.size);
+            if let (Some(written), true) = (&mut region.written, start < stop) {
+                written.set((start - region.base) as usize, (stop - start) as usize);
+            }
         }
     }
 
+    /// Take the uninitialized bytes loaded since the last call
+    pub fn take_uninit_reads(&mut self) -> Vec<UninitAccess> {
+        std::mem::take(&mut self.uninit_reads)
+    }
+
+    /// Take the addresses of writes to executable memory since the last call
+    ///
+    /// Anything caching decoded instructions must drop the pages these
+    /// addresses fall in.
+    pub fn take_code_writes(&mut self) -> Vec<u64> {
+        std::mem::take(&mut self.code_writes)
+    }
+
+    /// Note that instructions were fetched from the page holding `addr`
+    ///
+    /// Writes to executable memory only count as code writes on such
+    /// pages, until the first one lets the page go.
+    pub fn note_code_fetch(&mut self, addr: u64) {
+        self.code_pages.insert(addr & !(PAGE_SIZE - 1));
+    }
+
+    /// Pages instructions were fetched from among those `len` bytes at
+    /// `addr` touch
+    fn code_pages_in(&self, addr: u64, len: usize) -> Vec<u64> {
+        let first = addr & !(PAGE_SIZE - 1);
+        let last = addr.saturating_add((len as u64).max(1) - 1) & !(PAGE_SIZE - 1);
+        self.code_pages.range(first..=last).copied().collect()
+    }
+
+    /// Report writes covering `addr`, e.g. to a magic MMIO port
+    pub fn watch_writes(&mut self, addr: u64) {
+        self.write_watches.insert(addr);
+    }
+
+    /// Take the watched addresses written since the last call
+    pub fn take_watched_writes(&mut self) -> Vec<u64> {
+        std::mem::take(&mut self.watched_writes)
+    }
+
     /// Set cache hints
     pub fn set_cache_hints(&mut self, hint: CacheHint) {
         match hint {
@@ -445,20 +903,37 @@ impl Memory {
         size: u64,
         permissions: Permissions,
     ) -> Result<(), EmulatorError> {
-        // Check for overlapping regions
-        for (_, region) in self.regions.range(..=base) {
+        if size == 0 {
+            return Err(EmulatorError::MemoryError(
+                "Empty memory region".to_string(),
+            ));
+        }
+        let end = base.checked_add(size).ok_or_else(|| {
+            EmulatorError::MemoryError("Region extends past the end of memory".to_string())
+        })?;
+
+        // Regions never overlap each other, so only the last region starting
+        // below the new end can overlap the new region
+        if let Some((_, region)) = self.regions.range(..end).next_back() {
             if region.base + region.size > base {
-                return Err(EmulatorError::MemoryError(
-                    "Overlapping memory region".to_string(),
-                ));
+                return Err(EmulatorError::MemoryOverlap);
             }
         }
+        if self.devices.overlaps(base, end) || self.overlaps_shared(base, end) {
+            return Err(EmulatorError::MemoryOverlap);
+        }
 
         let region = Region {
             base,
             size,
             permissions,
             data: vec![0; size as usize],
+            name: None,
+            written: match self.uninit_policy {
+                UninitPolicy::Off => None,
+                _ => Some(WrittenMap::new(size)),
+            },
+            backing: None,
         };
 
         self.regions.insert(base, region);
@@ -467,110 +942,618 @@ impl Memory {
 
     /// Unmap memory region
     pub fn unmap(&mut self, base: u64) -> Result<(), EmulatorError> {
-        if self.regions.remove(&base).is_none() {
-            return Err(EmulatorError::MemoryError("Region not found".to_string()));
+        if self.shared.remove(&base).is_some() {
+            return Ok(());
         }
+        let region = self
+            .regions
+            .remove(&base)
+            .ok_or_else(|| EmulatorError::MemoryError("Region not found".to_string()))?;
+        self.invalidate_caches(region.base, region.size);
         Ok(())
     }
 
-    /// Read byte from memory with caching
-    pub fn read_u8(&mut self, addr: u64) -> Result<u8, EmulatorError> {
-        // Check permissions first
-        let region = self.find_region(addr)?;
-        if !region.permissions.can_read() {
+    /// Shrink the region mapped at `base` to its first `size` bytes
+    pub fn truncate(&mut self, base: u64, size: u64) -> Result<(), EmulatorError> {
+        let region = self
+            .regions
+            .get_mut(&base)
+            .ok_or_else(|| EmulatorError::MemoryError("Region not found".to_string()))?;
+        if size == 0 || size > region.size {
+            return Err(EmulatorError::MemoryError(format!(
+                "Cannot shrink a {:#x} byte region to {:#x} bytes",
+                region.size, size
+            )));
+        }
+        let released = region.size - size;
+        region.data.truncate(size as usize);
+        region.data.shrink_to_fit();
+        if let Some(backing) = &mut region.backing {
+            backing.truncate(size as usize);
+        }
+        if let Some(written) = &mut region.written {
+            written.truncate(size);
+        }
+        region.size = size;
+        self.invalidate_caches(base + size, released);
+        Ok(())
+    }
+
+    /// Map a region whose contents are shared with host threads
+    ///
+    /// Returns a handle to the contents for the host side. Guest accesses
+    /// to the region bypass the caches and are host atomic operations, with
+    /// acquire and release ordering for [`Memory::load_acquire`] and
+    /// [`Memory::store_release`].
+    pub fn map_shared(
+        &mut self,
+        base: u64,
+        size: u64,
+        permissions: Permissions,
+    ) -> Result<SharedMemory, EmulatorError> {
+        if size == 0 {
             return Err(EmulatorError::MemoryError(
-                "Read permission denied".to_string(),
+                "Empty memory region".to_string(),
             ));
         }
+        let end = base.checked_add(size).ok_or_else(|| {
+            EmulatorError::MemoryError("Region extends past the end of memory".to_string())
+        })?;
+        let overlaps_region = self
+            .regions
+            .range(..end)
+            .next_back()
+            .is_some_and(|(_, region)| region.base + region.size > base);
+        if overlaps_region || self.devices.overlaps(base, end) || self.overlaps_shared(base, end) {
+            return Err(EmulatorError::MemoryOverlap);
+        }
 
-        let offset = (addr - region.base) as usize;
-        let memory_data = region.data[offset];
-        let _ = region; // Release the region borrow
+        let memory = SharedMemory::new(size);
+        self.shared.insert(
+            base,
+            SharedRegion {
+                size,
+                permissions,
+                memory: memory.clone(),
+                name: None,
+            },
+        );
+        Ok(memory)
+    }
 
-        let mut data = [0u8; 1];
+    /// Handle to the contents of the shared region at `base`
+    pub fn shared_memory(&self, base: u64) -> Option<SharedMemory> {
+        self.shared.get(&base).map(|region| region.memory.clone())
+    }
 
-        // Try L1 cache first
-        if !self.l1_cache.non_temporal && self.l1_cache.read(addr, &mut data) {
-            return Ok(data[0]);
-        }
+    /// Whether a shared region overlaps `base..end`
+    fn overlaps_shared(&self, base: u64, end: u64) -> bool {
+        self.shared
+            .range(..end)
+            .next_back()
+            .is_some_and(|(&start, region)| start + region.size > base)
+    }
 
-        // L1 miss, try L2
-        if !self.l2_cache.non_temporal && self.l2_cache.read(addr, &mut data) {
-            // Fill L1 if not non-temporal
-            if !self.l1_cache.non_temporal {
-                self.l1_cache.write_to_cache(addr, &[data[0]]);
-            }
-            return Ok(data[0]);
+    /// Shared region containing `addr` and the offset into it
+    fn find_shared(&self, addr: u64) -> Option<(&SharedRegion, u64)> {
+        if self.shared.is_empty() {
+            return None;
         }
+        let (&base, region) = self.shared.range(..=addr).next_back()?;
+        (addr - base < region.size).then_some((region, addr - base))
+    }
 
-        // L2 miss, try L3
-        if !self.l3_cache.non_temporal && self.l3_cache.read(addr, &mut data) {
-            // Fill L2 if not non-temporal
-            if !self.l2_cache.non_temporal {
-                self.l2_cache.write_to_cache(addr, &[data[0]]);
-            }
-            // Fill L1 if not non-temporal
-            if !self.l1_cache.non_temporal {
-                self.l1_cache.write_to_cache(addr, &[data[0]]);
-            }
-            return Ok(data[0]);
+    /// Load `len` bytes from a shared region, if `addr` is in one
+    fn shared_read(
+        &mut self,
+        addr: u64,
+        len: usize,
+        order: Ordering,
+    ) -> Result<Option<u64>, EmulatorError> {
+        let Some((region, offset)) = self.find_shared(addr) else {
+            return Ok(None);
+        };
+        if !region.permissions.can_read() {
+            return Err(EmulatorError::MemoryError(
+                "Read permission denied".to_string(),
+            ));
         }
+        let value = region.memory.load(offset, len, order)?;
+        self.stats.demand_reads += 1;
+        Ok(Some(value))
+    }
 
-        // Cache miss or non-temporal access, use memory data
-        let data = memory_data;
-
-        // Fill L3 if not non-temporal
-        if !self.l3_cache.non_temporal {
-            self.l3_cache.write_to_cache(addr, &[data]);
-
-            // Fill L2 if not non-temporal
-            if !self.l2_cache.non_temporal {
-                self.l2_cache.write_to_cache(addr, &[data]);
-
-                // Fill L1 if not non-temporal
-                if !self.l1_cache.non_temporal {
-                    self.l1_cache.write_to_cache(addr, &[data]);
-                }
-            }
+    /// Store the low `len` bytes of `value` to a shared region, if `addr` is
+    /// in one
+    fn shared_write(
+        &mut self,
+        addr: u64,
+        len: usize,
+        value: u64,
+        order: Ordering,
+    ) -> Result<bool, EmulatorError> {
+        let Some((region, offset)) = self.find_shared(addr) else {
+            return Ok(false);
+        };
+        if !region.permissions.can_write() {
+            return Err(EmulatorError::MemoryError(
+                "Write permission denied".to_string(),
+            ));
         }
+        region.memory.store(offset, len, value, order)?;
+        self.writes += 1;
+        Ok(true)
+    }
 
-        Ok(data)
+    /// Load `len` bytes (1, 2, 4 or 8) with acquire semantics (`ld.acq`)
+    ///
+    /// In a shared region this is a host acquire load, and must be
+    /// naturally aligned; elsewhere no other thread can observe the access
+    /// and it is an ordinary load.
+    pub fn load_acquire(&mut self, addr: u64, len: usize) -> Result<u64, EmulatorError> {
+        if let Some(value) = self.shared_read(addr, len, Ordering::Acquire)? {
+            return Ok(value);
+        }
+        match len {
+            1 => self.read_u8(addr).map(u64::from),
+            2 => self.read_u16(addr).map(u64::from),
+            4 => self.read_u32(addr).map(u64::from),
+            _ => self.read_u64(addr),
+        }
     }
 
-    /// Write byte to memory with caching
-    pub fn write_u8(&mut self, addr: u64, value: u8) -> Result<(), EmulatorError> {
-        self.write_to_caches(addr, &[value])
+    /// Load `len` bytes (1, 2, 4 or 8) at `addr` in byte order `endian`
+    pub fn read_sized(
+        &mut self,
+        addr: u64,
+        len: usize,
+        endian: Endian,
+    ) -> Result<u64, EmulatorError> {
+        let value = match len {
+            1 => self.read_u8(addr).map(u64::from),
+            2 => self.read_u16(addr).map(u64::from),
+            4 => self.read_u32(addr).map(u64::from),
+            _ => self.read_u64(addr),
+        }?;
+        Ok(endian.convert(value, len))
     }
 
-    /// Read 64-bit value from memory
-    pub fn read_u64(&mut self, addr: u64) -> Result<u64, EmulatorError> {
-        let mut value = 0u64;
-        for i in 0..8 {
-            value |= (self.read_u8(addr + i)? as u64) << (i * 8);
+    /// Store the low `len` bytes (1, 2, 4 or 8) of `value` at `addr` in byte
+    /// order `endian`
+    pub fn write_sized(
+        &mut self,
+        addr: u64,
+        len: usize,
+        value: u64,
+        endian: Endian,
+    ) -> Result<(), EmulatorError> {
+        let value = endian.convert(value, len);
+        match len {
+            1 => self.write_u8(addr, value as u8),
+            2 => self.write_u16(addr, value as u16),
+            4 => self.write_u32(addr, value as u32),
+            _ => self.write_u64(addr, value),
         }
-        Ok(value)
     }
 
-    /// Write 64-bit value to memory
-    pub fn write_u64(&mut self, addr: u64, value: u64) -> Result<(), EmulatorError> {
-        let mut data = [0u8; 8];
-        for (i, byte) in data.iter_mut().enumerate() {
-            *byte = ((value >> (i * 8)) & 0xFF) as u8;
+    /// Store the low `len` bytes (1, 2, 4 or 8) of `value` with release
+    /// semantics (`st.rel`)
+    ///
+    /// In a shared region this is a host release store, and must be
+    /// naturally aligned; elsewhere it is an ordinary store.
+    pub fn store_release(
+        &mut self,
+        addr: u64,
+        len: usize,
+        value: u64,
+    ) -> Result<(), EmulatorError> {
+        if self.shared_write(addr, len, value, Ordering::Release)? {
+            return Ok(());
+        }
+        match len {
+            1 => self.write_u8(addr, value as u8),
+            2 => self.write_u16(addr, value as u16),
+            4 => self.write_u32(addr, value as u32),
+            _ => self.write_u64(addr, value),
         }
-        self.write_to_caches(addr, &data)
     }
 
-    /// Read 16-bit value from memory
-    pub fn read_u16(&mut self, addr: u64) -> Result<u16, EmulatorError> {
-        let mut value = 0u16;
-        for i in 0..2 {
-            value |= (self.read_u8(addr + i)? as u16) << (i * 8);
+    /// Change the permissions of a mapped region
+    pub fn protect(&mut self, base: u64, permissions: Permissions) -> Result<(), EmulatorError> {
+        if let Some(region) = self.shared.get_mut(&base) {
+            region.permissions = permissions;
+            return Ok(());
         }
-        Ok(value)
+        let region = self
+            .regions
+            .get_mut(&base)
+            .ok_or_else(|| EmulatorError::MemoryError("Region not found".to_string()))?;
+        region.permissions = permissions;
+        Ok(())
     }
 
-    /// Read 32-bit value from memory
+    /// Map a memory region and fill it with an image
+    ///
+    /// The image is copied straight into the backing store, bypassing the
+    /// permission check and the caches, so read-only and executable regions
+    /// can be populated. The region is `size` bytes long; any bytes past the
+    /// end of the image are zero.
+    pub fn load_image(
+        &mut self,
+        base: u64,
+        size: u64,
+        image: &[u8],
+        permissions: Permissions,
+    ) -> Result<(), EmulatorError> {
+        if image.len() as u64 > size {
+            return Err(EmulatorError::MemoryError(
+                "Image larger than region".to_string(),
+            ));
+        }
+
+        self.map(base, size, permissions)?;
+        let region = self.find_region_mut(base)?;
+        region.store(0, image);
+        if let Some(written) = &mut region.written {
+            written.set(0, image.len());
+        }
+        self.invalidate_caches(base, size);
+        Ok(())
+    }
+
+    /// Attach a device with its MMIO window at `base`
+    pub fn attach_device(
+        &mut self,
+        id: DeviceId,
+        base: u64,
+        device: Box<dyn Device>,
+    ) -> Result<(), EmulatorError> {
+        let end = base.saturating_add(device.size());
+        if let Some((_, region)) = self.regions.range(..end).next_back() {
+            if region.base + region.size > base {
+                return Err(EmulatorError::MemoryOverlap);
+            }
+        }
+        if self.overlaps_shared(base, end) {
+            return Err(EmulatorError::MemoryOverlap);
+        }
+        self.devices.insert(id, base, device)
+    }
+
+    /// Detach a device, returning its base and the device
+    pub fn detach_device(&mut self, id: DeviceId) -> Option<(u64, Box<dyn Device>)> {
+        self.devices.remove(id)
+    }
+
+    /// Attached devices
+    pub fn devices(&self) -> &DeviceBus {
+        &self.devices
+    }
+
+    /// Advance the devices to AR.ITC value `itc`, returning those that
+    /// asserted their interrupt line since the last tick
+    pub fn tick_devices(&mut self, itc: u64) -> Vec<DeviceId> {
+        if self.devices.is_empty() {
+            return Vec::new();
+        }
+        self.devices.tick(itc)
+    }
+
+    /// Take the machine-level actions devices asked for at their ticks
+    pub fn take_device_requests(&mut self) -> Vec<(DeviceId, MachineRequest)> {
+        self.devices.take_requests()
+    }
+
+    /// Reset the attached devices for a machine reset of `kind`
+    pub fn reset_devices(&mut self, kind: ResetKind) {
+        self.devices.reset(kind);
+    }
+
+    /// Pass the backing store of the region mapped at `base` through
+    /// `transform` from now on
+    ///
+    /// The current contents are encoded into a new backing store and the
+    /// region's cached lines dropped, so the next load of each line decodes
+    /// it from memory. `None` makes the region plain again.
+    pub fn set_transform(
+        &mut self,
+        base: u64,
+        transform: Option<Box<dyn Transform>>,
+    ) -> Result<(), EmulatorError> {
+        let region = self
+            .regions
+            .get_mut(&base)
+            .ok_or_else(|| EmulatorError::MemoryError("Region not found".to_string()))?;
+        region.backing = transform.map(|transform| Backing::new(transform, base, &region.data));
+        let size = region.size;
+        self.invalidate_caches(base, size);
+        Ok(())
+    }
+
+    /// Transformed backing store of the region mapped at `base`, if it is
+    /// a transformed region
+    ///
+    /// This is what memory would hold under the transform, such as the
+    /// ciphertext of an encrypted region.
+    pub fn backing_store(&self, base: u64) -> Option<&[u8]> {
+        self.regions.get(&base)?.backing.as_ref().map(Backing::data)
+    }
+
+    /// Zero the region mapped at `base`, bypassing the permission check
+    pub fn clear_region(&mut self, base: u64) -> Result<(), EmulatorError> {
+        let region = self
+            .regions
+            .get_mut(&base)
+            .ok_or_else(|| EmulatorError::MemoryError("Region not found".to_string()))?;
+        region.data.fill(0);
+        if let Some(backing) = &mut region.backing {
+            backing.store(0, base, &region.data);
+        }
+        let size = region.size;
+        self.invalidate_caches(base, size);
+        Ok(())
+    }
+
+    /// Load `len` bytes from a device window, if `addr` is in one
+    fn mmio_read(&mut self, addr: u64, len: usize) -> Result<Option<u64>, EmulatorError> {
+        if self.devices.is_empty() {
+            return Ok(None);
+        }
+        let Some((device, offset)) = self.devices.route(addr, len as u64)? else {
+            return Ok(None);
+        };
+        let mut data = [0; 8];
+        device.read(offset, &mut data[..len]);
+        let value = u64::from_le_bytes(data);
+        self.record_mmio(addr, "read", offset, len, value);
+        Ok(Some(value))
+    }
+
+    /// Store the low `len` bytes of `value` to a device window, if `addr` is
+    /// in one
+    fn mmio_write(&mut self, addr: u64, len: usize, value: u64) -> Result<bool, EmulatorError> {
+        if self.devices.is_empty() {
+            return Ok(false);
+        }
+        let Some((device, offset)) = self.devices.route(addr, len as u64)? else {
+            return Ok(false);
+        };
+        device.write(offset, &value.to_le_bytes()[..len]);
+        self.writes += 1;
+        self.record_mmio(addr, "write", offset, len, value);
+        Ok(true)
+    }
+
+    /// Record on `timeline` the MMIO accesses from now on, or stop with
+    /// `None`
+    pub(crate) fn set_timeline(&mut self, timeline: Option<Timeline>) {
+        self.timeline = timeline;
+    }
+
+    /// Record an MMIO access at `addr` on the device's channel
+    fn record_mmio(&self, addr: u64, name: &str, offset: u64, len: usize, value: u64) {
+        let (Some(timeline), Some(id)) = (&self.timeline, self.devices.id_at(addr)) else {
+            return;
+        };
+        let args = [("offset", offset), ("size", len as u64), ("value", value)];
+        timeline.record(Channel::Device(id), name, Phase::Instant, &args);
+    }
+
+    /// Check that a store of `len` bytes at `addr` would succeed, without
+    /// making it
+    pub fn check_write(&self, addr: u64, len: usize) -> Result<(), EmulatorError> {
+        if !self.devices.is_empty() && self.devices.covers(addr, len as u64)? {
+            return Ok(());
+        }
+        let permissions = match self.find_shared(addr) {
+            Some((region, _)) => region.permissions,
+            None => self.find_region(addr)?.permissions,
+        };
+        if !permissions.can_write() {
+            return Err(EmulatorError::MemoryError(
+                "Write permission denied".to_string(),
+            ));
+        }
+        if let Ok(region) = self.find_region(addr) {
+            if addr - region.base + len as u64 > region.size {
+                return Err(EmulatorError::MemoryError(
+                    "Write exceeds region bounds".to_string(),
+                ));
+            }
+            if permissions.can_execute()
+                && self.wx_policy == WxPolicy::Fault
+                && !self.code_pages_in(addr, len).is_empty()
+            {
+                return Err(EmulatorError::MemoryError(format!(
+                    "Write to executable page at {:#x}",
+                    addr
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// Read byte from memory with caching
+    pub fn read_u8(&mut self, addr: u64) -> Result<u8, EmulatorError> {
+        if let Some(value) = self.mmio_read(addr, 1)? {
+            return Ok(value as u8);
+        }
+        if let Some(value) = self.shared_read(addr, 1, Ordering::Relaxed)? {
+            return Ok(value as u8);
+        }
+
+        // Check permissions first
+        let region = self.find_region(addr)?;
+        if !region.permissions.can_read() {
+            return Err(EmulatorError::MemoryError(
+                "Read permission denied".to_string(),
+            ));
+        }
+
+        let offset = (addr - region.base) as usize;
+        let memory_data = region.load(offset);
+        let transformed = region.backing.is_some();
+        let unwritten = region
+            .written
+            .as_ref()
+            .is_some_and(|written| !written.is_written(offset));
+        let poison = unwritten && self.uninit_read(addr, 1)?;
+        let misses = self.stats.demand_misses;
+        let data = self.read_through_caches(addr, memory_data);
+        if transformed && self.stats.demand_misses > misses {
+            self.stats.transformed_reads += 1;
+        }
+        Ok(if poison { POISON } else { data })
+    }
+
+    /// Simulate the caches for a load of the byte at `addr`, whose memory
+    /// contents are `memory_data`
+    fn read_through_caches(&mut self, addr: u64, memory_data: u8) -> u8 {
+        self.stats.demand_reads += 1;
+
+        let mut data = [0u8; 1];
+
+        // Try L1 cache first
+        if !self.l1_cache.non_temporal && self.l1_cache.read(addr, &mut data) {
+            return data[0];
+        }
+
+        // L1 miss, try the victim cache
+        self.stats.l1_misses += 1;
+        let line_addr = addr & !(self.l1_cache.line_size as u64 - 1);
+        if !self.l1_cache.non_temporal && self.victim.take(line_addr) {
+            self.stats.victim_hits += 1;
+            self.fill_line(CacheLevelId::L1, addr, true);
+            return memory_data;
+        }
+
+        // Then L2
+        if !self.l2_cache.non_temporal && self.l2_cache.read(addr, &mut data) {
+            // Fill L1 if not non-temporal
+            if !self.l1_cache.non_temporal {
+                self.fill_line(CacheLevelId::L1, addr, true);
+            }
+            return data[0];
+        }
+
+        // L2 miss, try L3
+        self.stats.l2_misses += 1;
+        if !self.l3_cache.non_temporal && self.l3_cache.read(addr, &mut data) {
+            // Fill L2 if not non-temporal
+            if !self.l2_cache.non_temporal {
+                self.fill_line(CacheLevelId::L2, addr, true);
+            }
+            // Fill L1 if not non-temporal
+            if !self.l1_cache.non_temporal {
+                self.fill_line(CacheLevelId::L1, addr, true);
+            }
+            return data[0];
+        }
+
+        // Cache miss or non-temporal access, use memory data
+        let data = memory_data;
+        self.stats.demand_misses += 1;
+
+        // Fill L3 if not non-temporal
+        if !self.l3_cache.non_temporal {
+            self.fill_line(CacheLevelId::L3, addr, true);
+
+            // Fill L2 if not non-temporal
+            if !self.l2_cache.non_temporal {
+                self.fill_line(CacheLevelId::L2, addr, true);
+
+                // Fill L1 if not non-temporal
+                if !self.l1_cache.non_temporal {
+                    self.fill_line(CacheLevelId::L1, addr, true);
+                }
+            }
+        }
+
+        data
+    }
+
+    /// Apply the uninitialized memory policy to a load of `len` unwritten
+    /// bytes at `addr`
+    ///
+    /// Returns whether the bytes read as poison.
+    fn uninit_read(&mut self, addr: u64, len: usize) -> Result<bool, EmulatorError> {
+        if self.uninit_policy == UninitPolicy::Fault {
+            return Err(EmulatorError::MemoryError(format!(
+                "Read of uninitialized memory at {:#x}",
+                addr
+            )));
+        }
+        match self.uninit_reads.last_mut() {
+            Some(last) if last.addr.wrapping_add(last.len as u64) == addr => last.len += len,
+            _ => self.uninit_reads.push(UninitAccess { addr, len }),
+        }
+        Ok(self.uninit_policy == UninitPolicy::Poison)
+    }
+
+    /// Write byte to memory with caching
+    pub fn write_u8(&mut self, addr: u64, value: u8) -> Result<(), EmulatorError> {
+        if self.mmio_write(addr, 1, value as u64)? {
+            return Ok(());
+        }
+        if self.shared_write(addr, 1, value as u64, Ordering::Relaxed)? {
+            return Ok(());
+        }
+        self.write_to_caches(addr, &[value])
+    }
+
+    /// Read 64-bit value from memory
+    pub fn read_u64(&mut self, addr: u64) -> Result<u64, EmulatorError> {
+        if let Some(value) = self.mmio_read(addr, 8)? {
+            return Ok(value);
+        }
+        if let Some(value) = self.shared_read(addr, 8, Ordering::Relaxed)? {
+            return Ok(value);
+        }
+        let mut value = 0u64;
+        for i in 0..8 {
+            value |= (self.read_u8(addr + i)? as u64) << (i * 8);
+        }
+        Ok(value)
+    }
+
+    /// Write 64-bit value to memory
+    pub fn write_u64(&mut self, addr: u64, value: u64) -> Result<(), EmulatorError> {
+        if self.mmio_write(addr, 8, value)? {
+            return Ok(());
+        }
+        if self.shared_write(addr, 8, value, Ordering::Relaxed)? {
+            return Ok(());
+        }
+        let mut data = [0u8; 8];
+        for (i, byte) in data.iter_mut().enumerate() {
+            *byte = ((value >> (i * 8)) & 0xFF) as u8;
+        }
+        self.write_to_caches(addr, &data)
+    }
+
+    /// Read 16-bit value from memory
+    pub fn read_u16(&mut self, addr: u64) -> Result<u16, EmulatorError> {
+        if let Some(value) = self.mmio_read(addr, 2)? {
+            return Ok(value as u16);
+        }
+        if let Some(value) = self.shared_read(addr, 2, Ordering::Relaxed)? {
+            return Ok(value as u16);
+        }
+        let mut value = 0u16;
+        for i in 0..2 {
+            value |= (self.read_u8(addr + i)? as u16) << (i * 8);
+        }
+        Ok(value)
+    }
+
+    /// Read 32-bit value from memory
     pub fn read_u32(&mut self, addr: u64) -> Result<u32, EmulatorError> {
+        if let Some(value) = self.mmio_read(addr, 4)? {
+            return Ok(value as u32);
+        }
+        if let Some(value) = self.shared_read(addr, 4, Ordering::Relaxed)? {
+            return Ok(value as u32);
+        }
         let mut value = 0u32;
         for i in 0..4 {
             value |= (self.read_u8(addr + i)? as u32) << (i * 8);
@@ -580,6 +1563,12 @@ impl Memory {
 
     /// Write 16-bit value to memory
     pub fn write_u16(&mut self, addr: u64, value: u16) -> Result<(), EmulatorError> {
+        if self.mmio_write(addr, 2, value as u64)? {
+            return Ok(());
+        }
+        if self.shared_write(addr, 2, value as u64, Ordering::Relaxed)? {
+            return Ok(());
+        }
         for i in 0..2 {
             self.write_u8(addr + i, ((value >> (i * 8)) & 0xFF) as u8)?;
         }
@@ -588,6 +1577,12 @@ impl Memory {
 
     /// Write 32-bit value to memory
     pub fn write_u32(&mut self, addr: u64, value: u32) -> Result<(), EmulatorError> {
+        if self.mmio_write(addr, 4, value as u64)? {
+            return Ok(());
+        }
+        if self.shared_write(addr, 4, value as u64, Ordering::Relaxed)? {
+            return Ok(());
+        }
         for i in 0..4 {
             self.write_u8(addr + i, ((value >> (i * 8)) & 0xFF) as u8)?;
         }
@@ -597,11 +1592,111 @@ impl Memory {
     /// Memory fence operation
     pub fn fence(&mut self) -> Result<(), EmulatorError> {
         // Memory fence ensures all previous memory operations are complete
-        // before subsequent operations begin. In our emulator, this is a no-op
-        // since we execute instructions sequentially.
+        // before subsequent operations begin. Guest accesses are sequential,
+        // so only host threads sharing memory can tell
+        if !self.shared.is_empty() {
+            atomic::fence(Ordering::SeqCst);
+        }
+        Ok(())
+    }
+
+    /// Attach a name to the region starting at `base`
+    pub fn name_region(&mut self, base: u64, name: &str) -> Result<(), EmulatorError> {
+        if let Some(region) = self.shared.get_mut(&base) {
+            region.name = Some(name.to_string());
+            return Ok(());
+        }
+        let region = self
+            .regions
+            .get_mut(&base)
+            .ok_or_else(|| EmulatorError::MemoryError("Region not found".to_string()))?;
+        region.name = Some(name.to_string());
         Ok(())
     }
 
+    /// Get the name of the region containing an address, if it has one
+    pub fn region_name(&self, addr: u64) -> Option<&str> {
+        if let Some((region, _)) = self.find_shared(addr) {
+            return region.name.as_deref();
+        }
+        self.find_region(addr).ok()?.name.as_deref()
+    }
+
+    /// Iterate over mapped regions as (base, permissions, contents)
+    pub fn regions(&self) -> impl Iterator<Item = (u64, Permissions, &[u8])> {
+        self.regions
+            .values()
+            .map(|region| (region.base, region.permissions, region.data.as_slice()))
+    }
+
+    /// Read bytes without touching the caches or checking permissions
+    ///
+    /// Intended for debuggers and other tools that must not disturb the
+    /// state being inspected.
+    pub fn peek_bytes(&self, addr: u64, data: &mut [u8]) -> Result<(), EmulatorError> {
+        for (i, byte) in data.iter_mut().enumerate() {
+            let addr = addr + i as u64;
+            if let Some((region, offset)) = self.find_shared(addr) {
+                *byte = region.memory.load(offset, 1, Ordering::Relaxed)? as u8;
+                continue;
+            }
+            let region = self.find_region(addr)?;
+            *byte = region.data[(addr - region.base) as usize];
+        }
+        Ok(())
+    }
+
+    /// Read a NUL-terminated string without disturbing the caches
+    ///
+    /// Returns `None` if the first byte is unmapped. Strings longer than
+    /// `max_len` bytes or running into unmapped memory are truncated.
+    pub fn peek_c_string(&self, addr: u64, max_len: usize) -> Option<String> {
+        let mut bytes = Vec::new();
+        for i in 0..max_len as u64 {
+            let mut byte = [0u8; 1];
+            if self.peek_bytes(addr.wrapping_add(i), &mut byte).is_err() {
+                if i == 0 {
+                    return None;
+                }
+                break;
+            }
+            if byte[0] == 0 {
+                break;
+            }
+            bytes.push(byte[0]);
+        }
+        Some(String::from_utf8_lossy(&bytes).into_owned())
+    }
+
+    /// Search mapped memory in `range` for a byte pattern
+    ///
+    /// Returns the address of every match in ascending order. Matches do not
+    /// span region boundaries, and unmapped parts of the range are skipped.
+    pub fn search(&self, pattern: &[u8], range: std::ops::Range<u64>) -> Vec<u64> {
+        let mut matches = Vec::new();
+        if pattern.is_empty() {
+            return matches;
+        }
+
+        for region in self.regions.values() {
+            let start = range.start.max(region.base);
+            let end = range.end.min(region.base + region.size);
+            if start >= end {
+                continue;
+            }
+
+            let data = &region.data[(start - region.base) as usize..(end - region.base) as usize];
+            matches.extend(
+                data.windows(pattern.len())
+                    .enumerate()
+                    .filter(|(_, window)| *window == pattern)
+                    .map(|(i, _)| start + i as u64),
+            );
+        }
+
+        matches
+    }
+
     /// Find memory region containing address
     fn find_region(&self, addr: u64) -> Result<&Region, EmulatorError> {
         let (_, region) = self
@@ -623,55 +1718,6 @@ impl Memory {
         Ok(self.regions.get_mut(&base).unwrap())
     }
 
-    /// Track a speculative load
-    pub fn track_speculative_load(
-        &mut self,
-        addr: u64,
-        size: usize,
-    ) -> Result<SpeculativeStatus, EmulatorError> {
-        // Try to perform the load
-        let mut data = vec![0; size];
-        match self.read_bytes(addr, &mut data) {
-            Ok(_) => {
-                // Load succeeded - track it
-                let load = SpeculativeLoad {
-                    addr,
-                    size,
-                    status: SpeculativeStatus::Success,
-                    data,
-                };
-                self.speculative_loads.push(load);
-                Ok(SpeculativeStatus::Success)
-            }
-            Err(_e) => {
-                // Load failed - track failure
-                let load = SpeculativeLoad {
-                    addr,
-                    size,
-                    status: SpeculativeStatus::Failed,
-                    data: vec![],
-                };
-                self.speculative_loads.push(load);
-                Ok(SpeculativeStatus::Failed)
-            }
-        }
-    }
-
-    /// Cancel a speculative load
-    pub fn cancel_speculative_load(&mut self, addr: u64) {
-        if let Some(load) = self.speculative_loads.iter_mut().find(|l| l.addr == addr) {
-            load.status = SpeculativeStatus::Cancelled;
-        }
-    }
-
-    /// Check if a speculative load succeeded
-    pub fn check_speculative_load(&self, addr: u64) -> Option<SpeculativeStatus> {
-        self.speculative_loads
-            .iter()
-            .find(|l| l.addr == addr)
-            .map(|l| l.status)
-    }
-
     /// Read bytes from memory
     pub fn read_bytes(&mut self, addr: u64, data: &mut [u8]) -> Result<(), EmulatorError> {
         for (i, byte) in data.iter_mut().enumerate() {
@@ -688,7 +1734,102 @@ impl Memory {
         Ok(())
     }
 
+    /// Readable memory from `addr` to the end of its region
+    ///
+    /// Fails like a load if `addr` is unmapped or not readable. The caches
+    /// are not simulated; they never hold data that differs from memory.
+    pub fn readable_slice(&self, addr: u64) -> Result<&[u8], EmulatorError> {
+        let region = self.find_region(addr)?;
+        if !region.permissions.can_read() {
+            return Err(EmulatorError::MemoryError(
+                "Read permission denied".to_string(),
+            ));
+        }
+        Ok(&region.data[(addr - region.base) as usize..])
+    }
+
+    /// Read bytes region by region without simulating the caches
+    ///
+    /// Much faster than [`Memory::read_bytes`] for large blocks. On a fault
+    /// the bytes before the first inaccessible one have been read.
+    pub fn read_block(&mut self, addr: u64, data: &mut [u8]) -> Result<(), EmulatorError> {
+        let mut done = 0;
+        while done < data.len() {
+            if let Some((region, offset)) = self.find_shared(addr + done as u64) {
+                if !region.permissions.can_read() {
+                    return Err(EmulatorError::MemoryError(
+                        "Read permission denied".to_string(),
+                    ));
+                }
+                let len = ((region.size - offset) as usize).min(data.len() - done);
+                region
+                    .memory
+                    .read_bytes(offset, &mut data[done..done + len])?;
+                self.stats.demand_reads += 1;
+                done += len;
+                continue;
+            }
+            let start = addr + done as u64;
+            let slice = self.readable_slice(start)?;
+            let len = slice.len().min(data.len() - done);
+            data[done..done + len].copy_from_slice(&slice[..len]);
+            self.stats.demand_reads += 1;
+            self.check_initialized(start, &mut data[done..done + len])?;
+            done += len;
+        }
+        Ok(())
+    }
+
+    /// Write bytes region by region
+    ///
+    /// Each region is written in one step, keeping the caches coherent and
+    /// applying the W^X policy and write watches. On a fault the bytes
+    /// before the first inaccessible region have been written.
+    pub fn write_block(&mut self, addr: u64, data: &[u8]) -> Result<(), EmulatorError> {
+        let mut done = 0;
+        while done < data.len() {
+            let addr = addr + done as u64;
+            if let Some((region, offset)) = self.find_shared(addr) {
+                if !region.permissions.can_write() {
+                    return Err(EmulatorError::MemoryError(
+                        "Write permission denied".to_string(),
+                    ));
+                }
+                let len = ((region.size - offset) as usize).min(data.len() - done);
+                region.memory.write_bytes(offset, &data[done..done + len])?;
+                self.writes += 1;
+                done += len;
+                continue;
+            }
+            let region = self.find_region(addr)?;
+            let len = ((region.base + region.size - addr) as usize).min(data.len() - done);
+            self.write_to_caches(addr, &data[done..done + len])?;
+            done += len;
+        }
+        Ok(())
+    }
+
+    /// Apply the uninitialized memory policy to `data`, just read from
+    /// one region at `addr`
+    fn check_initialized(&mut self, addr: u64, data: &mut [u8]) -> Result<(), EmulatorError> {
+        let region = self.find_region(addr)?;
+        let Some(written) = &region.written else {
+            return Ok(());
+        };
+        let offset = (addr - region.base) as usize;
+        let unwritten: Vec<usize> = (0..data.len())
+            .filter(|&i| !written.is_written(offset + i))
+            .collect();
+        for i in unwritten {
+            if self.uninit_read(addr + i as u64, 1)? {
+                data[i] = POISON;
+            }
+        }
+        Ok(())
+    }
+
     fn write_to_caches(&mut self, addr: u64, data: &[u8]) -> Result<(), EmulatorError> {
+        self.writes += 1;
         // Check permissions first
         let region = self.find_region(addr)?;
         if !region.permissions.can_write() {
@@ -705,6 +1846,32 @@ impl Memory {
             ));
         }
 
+        // Apply the W^X policy to executable pages holding fetched code
+        let code_pages = if region.permissions.can_execute() {
+            self.code_pages_in(addr, data.len())
+        } else {
+            Vec::new()
+        };
+        if !code_pages.is_empty() {
+            if self.wx_policy == WxPolicy::Fault {
+                return Err(EmulatorError::MemoryError(format!(
+                    "Write to executable page at {:#x}",
+                    addr
+                )));
+            }
+            for page in code_pages {
+                self.code_pages.remove(&page);
+                self.code_writes.push(addr.max(page));
+            }
+        }
+
+        let end = addr + data.len() as u64;
+        for &watch in self.write_watches.range(addr..end) {
+            if !self.watched_writes.contains(&watch) {
+                self.watched_writes.push(watch);
+            }
+        }
+
         // Cache the non-temporal flags before borrowing self
         let l3_temporal = !self.l3_cache.non_temporal;
         let l2_temporal = !self.l2_cache.non_temporal;
@@ -712,43 +1879,124 @@ impl Memory {
 
         // Write to memory first
         let region = self.find_region_mut(addr)?;
-        region.data[offset..offset + data.len()].copy_from_slice(data);
-
-        // Then update caches if not non-temporal
-        if l3_temporal {
-            let (l3_old_addr, l3_old_data) = self.l3_cache.write_to_cache(addr, data);
-            if let Some(l3_data) = l3_old_data {
-                let region = self.find_region_mut(l3_old_addr)?;
-                let offset = (l3_old_addr - region.base) as usize;
-                if offset + l3_data.len() <= region.size as usize {
-                    region.data[offset..offset + l3_data.len()].copy_from_slice(&l3_data);
-                }
+        region.store(offset, data);
+        if let Some(written) = &mut region.written {
+            written.set(offset, data.len());
+        }
+        if region.backing.is_some() {
+            self.stats.transformed_writes += data.len() as u64;
+        }
+
+        // Then update caches; levels bypassed by a non-temporal hint still
+        // refresh lines they already hold so they never go stale
+        for (level, temporal) in [
+            (CacheLevelId::L3, l3_temporal),
+            (CacheLevelId::L2, l3_temporal && l2_temporal),
+            (CacheLevelId::L1, l3_temporal && l2_temporal && l1_temporal),
+        ] {
+            let line_size = self.cache_level(level).line_size as u64;
+            let mut line_addr = addr & !(line_size - 1);
+            while line_addr < end {
+                self.fill_line(level, line_addr, temporal);
+                line_addr += line_size;
             }
+        }
 
-            if l2_temporal {
-                let (l2_old_addr, l2_old_data) = self.l2_cache.write_to_cache(addr, data);
-                if let Some(l2_data) = l2_old_data {
-                    let region = self.find_region_mut(l2_old_addr)?;
-                    let offset = (l2_old_addr - region.base) as usize;
-                    if offset + l2_data.len() <= region.size as usize {
-                        region.data[offset..offset + l2_data.len()].copy_from_slice(&l2_data);
-                    }
-                }
+        Ok(())
+    }
+
+    /// Get a cache level by identifier
+    fn cache_level(&mut self, level: CacheLevelId) -> &mut CacheLevel {
+        match level {
+            CacheLevelId::L1 => &mut self.l1_cache,
+            CacheLevelId::L2 => &mut self.l2_cache,
+            CacheLevelId::L3 => &mut self.l3_cache,
+        }
+    }
+
+    /// Copy the current memory contents of a cache line
+    ///
+    /// Bytes outside any mapped region read as zero.
+    fn line_contents(&self, line_addr: u64, line_size: usize) -> Vec<u8> {
+        let mut contents = vec![0; line_size];
+        for (i, byte) in contents.iter_mut().enumerate() {
+            if let Ok(region) = self.find_region(line_addr + i as u64) {
+                *byte = region.load((line_addr + i as u64 - region.base) as usize);
+            }
+        }
+        contents
+    }
+
+    /// Load the line containing `addr` into a cache level
+    ///
+    /// A line already held by the level is refreshed from memory. A missing
+    /// line is only allocated when `allocate` is set, writing back the
+    /// evicted victim if it was dirty. Lines move between L1 and the victim
+    /// cache, so neither holds one the other does.
+    fn fill_line(&mut self, level: CacheLevelId, addr: u64, allocate: bool) {
+        let line_size = self.cache_level(level).line_size;
+        let line_addr = addr & !(line_size as u64 - 1);
+        let contents = self.line_contents(line_addr, line_size);
+        if level == CacheLevelId::L1 && allocate {
+            self.victim.take(line_addr);
+        }
+
+        let cache = self.cache_level(level);
+        let (tag, set_idx, _) = cache.decompose_address(line_addr);
+        if !allocate && cache.sets[set_idx].find_line_mut(tag).is_none() {
+            return;
+        }
+
+        let Some((old_addr, old_data)) = cache.write_to_cache(line_addr, &contents) else {
+            return;
+        };
+        if let Some(old_data) = old_data {
+            self.write_back_line(old_addr, &old_data);
+        }
+        if level == CacheLevelId::L1 {
+            self.victim.insert(old_addr);
+        }
+    }
 
-                if l1_temporal {
-                    let (l1_old_addr, l1_old_data) = self.l1_cache.write_to_cache(addr, data);
-                    if let Some(l1_data) = l1_old_data {
-                        let region = self.find_region_mut(l1_old_addr)?;
-                        let offset = (l1_old_addr - region.base) as usize;
-                        if offset + l1_data.len() <= region.size as usize {
-                            region.data[offset..offset + l1_data.len()].copy_from_slice(&l1_data);
+    /// Write an evicted cache line back to memory
+    ///
+    /// Parts of the line that are no longer mapped are dropped.
+    fn write_back_line(&mut self, line_addr: u64, data: &[u8]) {
+        for (i, &byte) in data.iter().enumerate() {
+            let addr = line_addr + i as u64;
+            if let Ok(region) = self.find_region_mut(addr) {
+                let offset = (addr - region.base) as usize;
+                region.store(offset, &[byte]);
+            }
+        }
+    }
+
+    /// Drop any cached copies of an address range
+    fn invalidate_caches(&mut self, addr: u64, size: u64) {
+        let end = addr.saturating_add(size);
+        for level in [CacheLevelId::L1, CacheLevelId::L2, CacheLevelId::L3] {
+            let cache = self.cache_level(level);
+            let line_size = cache.line_size as u64;
+            for set_idx in 0..cache.sets.len() {
+                for line_idx in 0..cache.sets[set_idx].lines.len() {
+                    let line_addr =
+                        cache.compose_address(cache.sets[set_idx].lines[line_idx].tag, set_idx);
+                    let line = &mut cache.sets[set_idx].lines[line_idx];
+                    if line.state != CacheLineState::Invalid
+                        && line_addr < end
+                        && addr < line_addr + line_size
+                    {
+                        if line.prefetched {
+                            line.prefetched = false;
+                            cache.unused_prefetches += 1;
                         }
+                        line.state = CacheLineState::Invalid;
                     }
                 }
             }
         }
-
-        Ok(())
+        let line_size = self.l1_cache.line_size as u64;
+        self.victim.invalidate(addr, end, line_size);
     }
 
     #[allow(dead_code)]
@@ -762,11 +2010,90 @@ impl Memory {
         for (addr, data) in dirty_lines {
             let region = self.find_region_mut(addr)?;
             let offset = (addr - region.base) as usize;
-            region.data[offset..offset + data.len()].copy_from_slice(&data);
+            region.store(offset, &data);
         }
         Ok(())
     }
 
+    /// Prefetch the line containing `addr` (lfetch)
+    ///
+    /// The line is brought into the cache level selected by the hint and all
+    /// levels beyond it: L1 normally, L2 for `nt1`. `nta` prefetches bypass
+    /// every level in this model and only count as issued. Fails if the
+    /// address is not readable; callers decide whether that faults.
+    pub fn prefetch(&mut self, addr: u64, hint: CacheHint) -> Result<(), EmulatorError> {
+        let region = self.find_region(addr)?;
+        if !region.permissions.can_read() {
+            return Err(EmulatorError::MemoryError(
+                "Read permission denied".to_string(),
+            ));
+        }
+        self.stats.prefetch.issued += 1;
+
+        let levels: &[CacheLevelId] = match hint {
+            CacheHint::Normal | CacheHint::Bias => {
+                &[CacheLevelId::L3, CacheLevelId::L2, CacheLevelId::L1]
+            }
+            CacheHint::NonTemporal1 => &[CacheLevelId::L3, CacheLevelId::L2],
+            CacheHint::NonTemporalAll => &[],
+        };
+        let Some(&target) = levels.last() else {
+            return Ok(());
+        };
+
+        // A prefetch of a line the target level already holds does nothing
+        let cache = self.cache_level(target);
+        let (tag, set_idx, _) = cache.decompose_address(addr);
+        if cache.sets[set_idx].find_line_mut(tag).is_some() {
+            self.stats.prefetch.redundant += 1;
+            return Ok(());
+        }
+
+        for &level in levels {
+            self.fill_line(level, addr, true);
+        }
+
+        // Only the target level tracks whether the prefetch paid off
+        let cache = self.cache_level(target);
+        if let Some(line) = cache.sets[set_idx].find_line_mut(tag) {
+            line.prefetched = true;
+        }
+        Ok(())
+    }
+
+    /// Number of stores made since creation
+    ///
+    /// Unlike the statistics this never resets, so callers can tell whether
+    /// the guest stored anything between two points.
+    pub fn write_count(&self) -> u64 {
+        self.writes
+    }
+
+    /// Get memory access statistics
+    pub fn stats(&self) -> MemoryStats {
+        let mut stats = self.stats;
+        for (i, cache) in [&self.l1_cache, &self.l2_cache, &self.l3_cache]
+            .into_iter()
+            .enumerate()
+        {
+            stats.prefetch.useful += cache.useful_prefetches;
+            stats.prefetch.unused += cache.unused_prefetches;
+            stats.evictions[i] = cache.evictions;
+        }
+        stats
+    }
+
+    /// Reset memory access statistics
+    pub fn reset_stats(&mut self) {
+        self.stats = MemoryStats::default();
+        for level in [CacheLevelId::L1, CacheLevelId::L2, CacheLevelId::L3] {
+            let cache = self.cache_level(level);
+            cache.useful_prefetches = 0;
+            cache.unused_prefetches = 0;
+            cache.evictions = 0;
+        }
+    }
+
     /// Flush all cache levels back to memory
     ///
     /// This operation ensures that any modified data in any cache level is written back to main memory.
@@ -782,46 +2109,16 @@ impl Memory {
         let l3_dirty = self.l3_cache.flush();
 
         // Write back all dirty lines
-        for (addr, data) in l1_dirty
-            .into_iter()
-            .chain(l2_dirty.into_iter())
-            .chain(l3_dirty.into_iter())
-        {
+        for (addr, data) in l1_dirty.into_iter().chain(l2_dirty).chain(l3_dirty) {
             let region = self.find_region_mut(addr)?;
             let offset = (addr - region.base) as usize;
-            region.data[offset..offset + data.len()].copy_from_slice(&data);
+            region.store(offset, &data);
         }
 
         Ok(())
     }
 }
 
-/// Speculative load status
-#[derive(Debug, Clone, Copy, PartialEq)]
-pub enum SpeculativeStatus {
-    /// Load succeeded
-    Success,
-    /// Load failed
-    Failed,
-    /// Load was cancelled
-    Cancelled,
-}
-
-/// Speculative load entry
-#[derive(Debug)]
-struct SpeculativeLoad {
-    /// Memory address
-    addr: u64,
-    #[allow(dead_code)]
-    /// Size in bytes
-    size: usize,
-    /// Status of the load
-    status: SpeculativeStatus,
-    #[allow(dead_code)]
-    /// Data loaded (if successful)
-    data: Vec<u8>,
-}
-
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -844,6 +2141,53 @@ mod tests {
         assert!(!perm.can_execute());
     }
 
+    #[test]
+    fn test_shared_region() {
+        let mut mem = Memory::new();
+        mem.map(0x1000, 0x1000, Permissions::ReadWrite).unwrap();
+        let host = mem
+            .map_shared(0x2000, 0x100, Permissions::ReadWrite)
+            .unwrap();
+        assert!(matches!(
+            mem.map_shared(0x1800, 0x1000, Permissions::ReadWrite),
+            Err(EmulatorError::MemoryOverlap)
+        ));
+        assert!(mem.map(0x2080, 0x100, Permissions::ReadWrite).is_err());
+
+        // A host thread publishes a payload behind a release store of the
+        // flag; the guest polls the flag with ld.acq
+        let writer = std::thread::spawn(move || {
+            host.store(8, 8, 0xDEAD_BEEF, Ordering::Relaxed).unwrap();
+            host.store(0, 4, 1, Ordering::Release).unwrap();
+            host
+        });
+        while mem.load_acquire(0x2000, 4).unwrap() == 0 {
+            std::hint::spin_loop();
+        }
+        assert_eq!(mem.read_u64(0x2008).unwrap(), 0xDEAD_BEEF);
+        let host = writer.join().unwrap();
+
+        // Guest stores reach the host without going through the caches
+        mem.store_release(0x2010, 2, 0xABCD).unwrap();
+        mem.write_bytes(0x2012, b"hi").unwrap();
+        assert_eq!(host.load(0x10, 4, Ordering::Acquire).unwrap(), 0x6968_ABCD);
+        let mut data = [0; 4];
+        mem.read_block(0x2010, &mut data).unwrap();
+        assert_eq!(data, [0xCD, 0xAB, b'h', b'i']);
+        mem.name_region(0x2000, "mailbox").unwrap();
+        assert_eq!(mem.region_name(0x20FF), Some("mailbox"));
+
+        // Ordered accesses must be aligned; elsewhere they are plain accesses
+        assert!(mem.load_acquire(0x2002, 4).is_err());
+        mem.store_release(0x1004, 4, 7).unwrap();
+        assert_eq!(mem.load_acquire(0x1004, 4).unwrap(), 7);
+
+        mem.protect(0x2000, Permissions::Read).unwrap();
+        assert!(mem.write_u8(0x2000, 0).is_err());
+        mem.unmap(0x2000).unwrap();
+        assert!(mem.read_u8(0x2000).is_err());
+    }
+
     #[test]
     fn test_memory_mapping() {
         let mut mem = Memory::new();
@@ -851,8 +2195,25 @@ mod tests {
         // Map region
         assert!(mem.map(0x1000, 0x1000, Permissions::ReadWrite).is_ok());
 
-        // Try to map overlapping region
+        // Try to map overlapping region, from above and from below
         assert!(mem.map(0x1800, 0x1000, Permissions::ReadWrite).is_err());
+        assert!(matches!(
+            mem.map(0x800, 0x1000, Permissions::ReadWrite),
+            Err(EmulatorError::MemoryOverlap)
+        ));
+        assert!(mem.map(0x0, 0x3000, Permissions::ReadWrite).is_err());
+        assert!(mem.map(0x3000, 0x1000, Permissions::ReadWrite).is_ok());
+
+        // Empty regions and regions wrapping past the end of memory
+        assert!(mem.map(0x8000, 0, Permissions::ReadWrite).is_err());
+        assert!(mem
+            .map(u64::MAX - 0xFF, 0x1000, Permissions::ReadWrite)
+            .is_err());
+
+        // Change permissions
+        assert!(mem.protect(0x3000, Permissions::Read).is_ok());
+        assert!(mem.write_u8(0x3000, 1).is_err());
+        assert!(mem.protect(0x3800, Permissions::Read).is_err());
 
         // Unmap region
         assert!(mem.unmap(0x1000).is_ok());
@@ -879,6 +2240,57 @@ mod tests {
         assert!(mem.write_u8(0x2000, 0x42).is_err());
     }
 
+    #[test]
+    fn test_load_image() {
+        let mut mem = Memory::new();
+
+        // Image lands in a read-only region despite the missing write permission
+        mem.load_image(0x1000, 0x100, &[1, 2, 3, 4], Permissions::ReadExecute)
+            .unwrap();
+        assert_eq!(mem.read_u32(0x1000).unwrap(), 0x04030201);
+        assert_eq!(mem.read_u8(0x1004).unwrap(), 0);
+        assert!(mem.write_u8(0x1000, 0x42).is_err());
+
+        // Image must fit in the region
+        assert!(mem
+            .load_image(0x2000, 2, &[1, 2, 3], Permissions::Read)
+            .is_err());
+    }
+
+    #[test]
+    fn test_search() {
+        let mut mem = Memory::new();
+        mem.map(0x1000, 0x100, Permissions::ReadWrite).unwrap();
+        mem.map(0x2000, 0x100, Permissions::None).unwrap();
+        mem.write_bytes(0x1010, b"needle").unwrap();
+        mem.write_bytes(0x1080, b"needle").unwrap();
+        mem.load_image(0x3000, 0x100, b"needle", Permissions::None)
+            .unwrap();
+
+        // Every region is searched, regardless of permissions
+        assert_eq!(
+            mem.search(b"needle", 0..u64::MAX),
+            vec![0x1010, 0x1080, 0x3000]
+        );
+
+        // Range limits the search and partial matches at the edge are dropped
+        assert_eq!(mem.search(b"needle", 0x1011..0x1086), vec![0x1080]);
+        assert!(mem.search(b"needle", 0x1011..0x1085).is_empty());
+        assert!(mem.search(b"", 0..u64::MAX).is_empty());
+    }
+
+    #[test]
+    fn test_region_names() {
+        let mut mem = Memory::new();
+        mem.map(0x1000, 0x100, Permissions::ReadWrite).unwrap();
+        assert_eq!(mem.region_name(0x1000), None);
+
+        mem.name_region(0x1000, "stack").unwrap();
+        assert_eq!(mem.region_name(0x10FF), Some("stack"));
+        assert_eq!(mem.region_name(0x1100), None);
+        assert!(mem.name_region(0x2000, "heap").is_err());
+    }
+
     #[test]
     fn test_memory_permissions() {
         let mut mem = Memory::new();
@@ -988,7 +2400,7 @@ mod tests {
         // Fill cache set with data
         for i in 0..8 {
             // L1 is 8-way associative
-            mem.write_u64(0x1000 + i * 64, i as u64).unwrap(); // Each cache line is 64 bytes
+            mem.write_u64(0x1000 + i * 64, i).unwrap(); // Each cache line is 64 bytes
         }
 
         // Write one more value to cause eviction
@@ -999,40 +2411,468 @@ mod tests {
     }
 
     #[test]
-    fn test_speculative_loads() {
-        let mut mem = Memory::new();
-        mem.map(0x1000, 4096, Permissions::ReadWriteExecute)
+    fn test_prefetch_stats() {
+        let mut memory = Memory::new();
+        memory
+            .map(0x10000, 0x10000, Permissions::ReadWrite)
             .unwrap();
 
-        // Write some test data
-        mem.write_u64(0x1000, 0x1234567890ABCDEF).unwrap();
+        // A prefetched line that is then read counts as useful
+        memory.prefetch(0x10000, CacheHint::Normal).unwrap();
+        memory.read_u8(0x10008).unwrap();
+        let stats = memory.stats();
+        assert_eq!(stats.prefetch.issued, 1);
+        assert_eq!(stats.prefetch.useful, 1);
+        assert_eq!(stats.demand_reads, 1);
+        assert_eq!(stats.demand_misses, 0);
+
+        // Re-prefetching a cached line is redundant
+        memory.prefetch(0x10010, CacheHint::Normal).unwrap();
+        assert_eq!(memory.stats().prefetch.redundant, 1);
+
+        // nt1 prefetches target L2 and are used through it
+        memory.prefetch(0x10400, CacheHint::NonTemporal1).unwrap();
+        memory.read_u8(0x10400).unwrap();
+        assert_eq!(memory.stats().prefetch.useful, 2);
+
+        // An unused prefetch is counted when its line is dropped
+        memory.prefetch(0x10800, CacheHint::Normal).unwrap();
+        memory.read_u8(0x11000).unwrap();
+        memory.unmap(0x10000).unwrap();
+        let stats = memory.stats();
+        assert_eq!(stats.prefetch.unused, 1);
+        assert_eq!(stats.demand_misses, 1);
+        // The nt1 line was only in L2
+        assert_eq!((stats.l1_misses, stats.l2_misses), (2, 1));
+        assert!((stats.prefetch.accuracy() - 2.0 / 3.0).abs() < 1e-9);
+        assert!((stats.prefetch_coverage() - 2.0 / 3.0).abs() < 1e-9);
+
+        // Prefetches of inaccessible lines fail without being counted
+        assert!(memory.prefetch(0x10000, CacheHint::Normal).is_err());
+        assert_eq!(memory.stats().prefetch.issued, 4);
+
+        memory.reset_stats();
+        assert_eq!(memory.stats(), MemoryStats::default());
+    }
 
-        // Track successful speculative load
-        let status = mem.track_speculative_load(0x1000, 8).unwrap();
-        assert_eq!(status, SpeculativeStatus::Success);
+    /// Memory whose L1 is a single set of `associativity` 64-byte lines
+    fn single_set_memory(associativity: usize, replacement: Replacement) -> Memory {
+        let l1 = CacheGeometry {
+            size: 64 * associativity,
+            associativity,
+            line_size: 64,
+            replacement,
+        };
+        let mut memory = Memory::with_caches(l1, CacheGeometry::L2, CacheGeometry::L3).unwrap();
+        memory.map(0x1000, 4096, Permissions::ReadWrite).unwrap();
+        memory
+    }
 
-        // Check load status
-        assert_eq!(
-            mem.check_speculative_load(0x1000),
-            Some(SpeculativeStatus::Success)
-        );
+    #[test]
+    fn test_replacement_policies() {
+        // A, B, A, C, A with room for two lines
+        let misses = |replacement| {
+            let mut memory = single_set_memory(2, replacement);
+            for addr in [0x1000, 0x1040, 0x1000, 0x1080, 0x1000] {
+                memory.read_u8(addr).unwrap();
+            }
+            let stats = memory.stats();
+            (stats.l1_misses, stats.evictions[0])
+        };
 
-        // Cancel a load
-        mem.cancel_speculative_load(0x1000);
+        // LRU and pseudo-LRU keep A, which was hit; FIFO evicts it first
+        assert_eq!(misses(Replacement::Lru), (3, 1));
+        assert_eq!(misses(Replacement::PseudoLru), (3, 1));
+        assert_eq!(misses(Replacement::Fifo), (4, 2));
+        let (random, _) = misses(Replacement::Random);
+        assert!(random == 3 || random == 4);
+        assert_eq!(misses(Replacement::Random).0, random);
+    }
+
+    #[test]
+    fn test_replacement_seed() {
+        // Cycling through five lines thrashes a four-way LRU set, while
+        // random victims let some lines survive
+        let misses = |seed: Option<u64>| {
+            let mut memory = single_set_memory(4, Replacement::Lru);
+            memory.set_replacement_seed(seed);
+            for _ in 0..20 {
+                for line in 0..5 {
+                    memory.read_u8(0x1000 + line * 0x40).unwrap();
+                }
+            }
+            memory.stats().l1_misses
+        };
+        assert_eq!(misses(None), 100);
+        let seeded: Vec<u64> = (0..8).map(|seed| misses(Some(seed))).collect();
+        assert!(seeded.iter().all(|&m| m < 100));
+        assert_eq!(misses(Some(3)), seeded[3]);
+        assert!(seeded.iter().any(|&m| m != seeded[0]));
+    }
+
+    #[test]
+    fn test_victim_cache() {
+        // Two lines fighting over a direct-mapped L1
+        let mut memory = single_set_memory(1, Replacement::Lru);
+        memory.set_victim_cache(1);
+        for addr in [0x1000, 0x1040, 0x1000, 0x1040] {
+            memory.read_u8(addr).unwrap();
+        }
+        let stats = memory.stats();
+        assert_eq!((stats.l1_misses, stats.victim_hits), (4, 2));
+        assert_eq!(stats.l2_misses, 2);
+        assert_eq!(stats.evictions, [3, 0, 0]);
+
+        // A store to the line in the victim cache moves it back to L1
+        memory.write_u8(0x1000, 7).unwrap();
+        assert_eq!(memory.read_u8(0x1000).unwrap(), 7);
+        memory.read_u8(0x1040).unwrap();
+        assert_eq!(memory.stats().victim_hits, 3);
+
+        // Without one every miss goes to L2
+        memory.set_victim_cache(0);
+        memory.reset_stats();
+        for addr in [0x1000, 0x1040] {
+            memory.read_u8(addr).unwrap();
+        }
+        assert_eq!(memory.stats().victim_hits, 0);
+    }
+
+    #[test]
+    fn test_uninit_policy() {
+        let mut memory = Memory::new();
+        memory.map(0x1000, 0x1000, Permissions::ReadWrite).unwrap();
+        memory.set_uninit_policy(UninitPolicy::Warn);
+        memory
+            .load_image(0x2000, 0x2000, &[1, 2], Permissions::ReadWrite)
+            .unwrap();
+
+        // Regions mapped before tracking and image bytes count as written
+        assert_eq!(memory.read_u64(0x1000).unwrap(), 0);
+        assert_eq!(memory.read_u16(0x2000).unwrap(), 0x0201);
+        assert!(memory.take_uninit_reads().is_empty());
+
+        // The bytes of one load past the image are reported together
+        memory.write_u8(0x2004, 5).unwrap();
+        assert_eq!(memory.read_u64(0x2000).unwrap(), 0x0005_0000_0201);
+        let mut data = [0; 4];
+        memory.read_block(0x2008, &mut data).unwrap();
         assert_eq!(
-            mem.check_speculative_load(0x1000),
-            Some(SpeculativeStatus::Cancelled)
+            memory.take_uninit_reads(),
+            [
+                UninitAccess {
+                    addr: 0x2002,
+                    len: 2
+                },
+                UninitAccess {
+                    addr: 0x2005,
+                    len: 7
+                },
+            ]
         );
 
-        // Track failed load (unmapped memory)
-        let status = mem.track_speculative_load(0x2000, 8).unwrap();
-        assert_eq!(status, SpeculativeStatus::Failed);
+        // Poison is returned through the caches and in blocks
+        memory.set_uninit_policy(UninitPolicy::Poison);
+        assert_eq!(memory.read_u8(0x2003).unwrap(), POISON);
+        memory.read_block(0x2003, &mut data).unwrap();
+        assert_eq!(data, [POISON, 5, POISON, POISON]);
+
+        // Allowlisted ranges read normally; faults change nothing
+        memory.mark_initialized(0x2800, 0x100);
+        memory.set_uninit_policy(UninitPolicy::Fault);
+        assert_eq!(memory.read_u32(0x2800).unwrap(), 0);
+        assert!(memory.read_u8(0x2900).is_err());
+        memory.take_uninit_reads();
+        assert!(memory.read_u8(0x2900).is_err());
+        assert!(memory.take_uninit_reads().is_empty());
+
+        // Turning tracking off forgets what was written
+        memory.set_uninit_policy(UninitPolicy::Off);
+        memory.set_uninit_policy(UninitPolicy::Fault);
+        assert_eq!(memory.read_u8(0x2900).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_transformed_region() {
+        use transform::XorPad;
+
+        /// Encodes but forgets to decode
+        #[derive(Debug)]
+        struct Lossy;
+
+        impl Transform for Lossy {
+            fn encode(&self, _addr: u64, data: &mut [u8]) {
+                data.iter_mut().for_each(|byte| *byte ^= 0xFF);
+            }
+
+            fn decode(&self, _addr: u64, _data: &mut [u8]) {}
+        }
+
+        let mut memory = Memory::new();
+        memory.map(0x1000, 0x1000, Permissions::ReadWrite).unwrap();
+        memory.write_u8(0x1000, 0x12).unwrap();
+        let pad = XorPad::new(vec![0x55]).unwrap();
+        memory.set_transform(0x1000, Some(Box::new(pad))).unwrap();
+        assert_eq!(memory.backing_store(0x2000), None);
+
+        // Memory holds the transformed bytes of earlier and later stores
+        memory.write_u16(0x1002, 0xAA55).unwrap();
         assert_eq!(
-            mem.check_speculative_load(0x2000),
-            Some(SpeculativeStatus::Failed)
+            memory.backing_store(0x1000).unwrap()[..4],
+            [0x47, 0x55, 0, 0xFF]
         );
+        assert_eq!(memory.stats().transformed_writes, 2);
+
+        // A load decodes its line from memory once, then hits the caches
+        memory.reset_stats();
+        memory.set_transform(0x1000, Some(Box::new(Lossy))).unwrap();
+        assert_eq!(memory.read_u32(0x1000).unwrap(), 0x55AA_FFED);
+        assert_eq!(memory.stats().transformed_reads, 1);
+        assert_eq!(memory.read_u8(0x1000).unwrap(), 0xED);
+
+        // Peeks see the clear contents, as does a plain region again
+        let mut data = [0; 4];
+        memory.peek_bytes(0x1000, &mut data).unwrap();
+        assert_eq!(data, [0x12, 0, 0x55, 0xAA]);
+        memory.set_transform(0x1000, None).unwrap();
+        assert_eq!(memory.backing_store(0x1000), None);
+        assert_eq!(memory.read_u32(0x1000).unwrap(), 0xAA55_0012);
+    }
+
+    #[test]
+    fn test_wx_policy() {
+        let mut memory = Memory::new();
+        memory
+            .map(0x1000, 0x1000, Permissions::ReadWriteExecute)
+            .unwrap();
+        memory.map(0x2000, 0x1000, Permissions::ReadWrite).unwrap();
+
+        // Executable pages only hold code once instructions are fetched
+        memory.write_u8(0x1010, 1).unwrap();
+        assert!(memory.take_code_writes().is_empty());
+
+        // The first write to fetched code is recorded and lets the page go
+        memory.note_code_fetch(0x1000);
+        memory.write_u8(0x1010, 1).unwrap();
+        memory.write_u8(0x1020, 1).unwrap();
+        memory.write_u8(0x2010, 1).unwrap();
+        assert_eq!(memory.take_code_writes(), vec![0x1010]);
+        assert!(memory.take_code_writes().is_empty());
+
+        memory.set_wx_policy(WxPolicy::Fault);
+        memory.note_code_fetch(0x1000);
+        assert!(memory.write_u8(0x1010, 2).is_err());
+        assert!(memory.check_write(0x1010, 1).is_err());
+        assert_eq!(memory.read_u8(0x1010).unwrap(), 1);
+        assert!(memory.write_u8(0x2010, 2).is_ok());
+        assert!(memory.take_code_writes().is_empty());
+    }
+
+    #[test]
+    fn test_write_watches() {
+        let mut memory = Memory::new();
+        memory.map(0x1000, 0x1000, Permissions::ReadWrite).unwrap();
+        memory.watch_writes(0x1008);
+
+        memory.write_u64(0x1000, 1).unwrap();
+        assert!(memory.take_watched_writes().is_empty());
+        memory.write_u64(0x1008, 2).unwrap();
+        memory.write_u8(0x1008, 3).unwrap();
+        assert_eq!(memory.take_watched_writes(), vec![0x1008]);
+        assert!(memory.take_watched_writes().is_empty());
+    }
+
+    mod properties {
+        use super::*;
+        use proptest::prelude::*;
+
+        /// Operations applied to both the memory and the reference model
+        #[derive(Debug, Clone)]
+        enum Op {
+            Map(u64, u64, Permissions),
+            Unmap(u64),
+            Protect(u64, Permissions),
+            Write(u64, Vec<u8>),
+            WriteU64(u64, u64),
+            Read(u64, usize),
+        }
+
+        /// Reference region: size, permissions and contents
+        type ModelRegion = (u64, Permissions, Vec<u8>);
+
+        /// Reference model: regions by base address
+        type Model = BTreeMap<u64, ModelRegion>;
+
+        fn permissions() -> impl Strategy<Value = Permissions> {
+            prop_oneof![
+                Just(Permissions::None),
+                Just(Permissions::Read),
+                Just(Permissions::ReadWrite),
+                Just(Permissions::ReadExecute),
+                Just(Permissions::ReadWriteExecute),
+            ]
+        }
 
-        // Check non-existent load
-        assert_eq!(mem.check_speculative_load(0x3000), None);
+        /// Addresses are drawn from a small space so operations collide
+        fn addr() -> impl Strategy<Value = u64> {
+            0u64..0x2000
+        }
+
+        /// Region bases fall on a coarser grid so unmap and protect hit them
+        fn base() -> impl Strategy<Value = u64> {
+            (0u64..32).prop_map(|i| i * 0x100)
+        }
+
+        fn op() -> impl Strategy<Value = Op> {
+            prop_oneof![
+                (base(), 1u64..0x600, permissions()).prop_map(|(b, s, p)| Op::Map(b, s, p)),
+                base().prop_map(Op::Unmap),
+                (base(), permissions()).prop_map(|(b, p)| Op::Protect(b, p)),
+                (addr(), prop::collection::vec(any::<u8>(), 1..32))
+                    .prop_map(|(a, d)| Op::Write(a, d)),
+                (addr(), any::<u64>()).prop_map(|(a, v)| Op::WriteU64(a, v)),
+                (addr(), 1usize..32).prop_map(|(a, n)| Op::Read(a, n)),
+            ]
+        }
+
+        /// Region of the model containing `addr`
+        fn model_region(model: &mut Model, addr: u64) -> Option<(u64, &mut ModelRegion)> {
+            model
+                .range_mut(..=addr)
+                .next_back()
+                .filter(|(&base, (size, _, _))| addr < base + size)
+                .map(|(&base, region)| (base, region))
+        }
+
+        /// Apply an operation to the model, returning whether it succeeds
+        /// and the bytes a read returns
+        fn apply(model: &mut Model, op: &Op) -> (bool, Vec<u8>) {
+            match op {
+                Op::Map(base, size, perms) => {
+                    let overlaps = model
+                        .iter()
+                        .any(|(&b, (s, _, _))| *base < b + s && b < base + size);
+                    if !overlaps {
+                        model.insert(*base, (*size, *perms, vec![0; *size as usize]));
+                    }
+                    (!overlaps, Vec::new())
+                }
+                Op::Unmap(base) => (model.remove(base).is_some(), Vec::new()),
+                Op::Protect(base, perms) => match model.get_mut(base) {
+                    Some(region) => {
+                        region.1 = *perms;
+                        (true, Vec::new())
+                    }
+                    None => (false, Vec::new()),
+                },
+                // Byte writes stop at the first byte that cannot be written
+                Op::Write(addr, data) => {
+                    for (i, &byte) in data.iter().enumerate() {
+                        let addr = addr + i as u64;
+                        match model_region(model, addr) {
+                            Some((base, (_, perms, contents))) if perms.can_write() => {
+                                contents[(addr - base) as usize] = byte;
+                            }
+                            _ => return (false, Vec::new()),
+                        }
+                    }
+                    (true, Vec::new())
+                }
+                // Wide writes must fit in one writable region
+                Op::WriteU64(addr, value) => match model_region(model, *addr) {
+                    Some((base, (size, perms, contents)))
+                        if perms.can_write() && addr + 8 <= base + *size =>
+                    {
+                        let offset = (addr - base) as usize;
+                        contents[offset..offset + 8].copy_from_slice(&value.to_le_bytes());
+                        (true, Vec::new())
+                    }
+                    _ => (false, Vec::new()),
+                },
+                Op::Read(addr, len) => {
+                    let mut data = Vec::new();
+                    for i in 0..*len as u64 {
+                        let addr = addr + i;
+                        match model_region(model, addr) {
+                            Some((base, (_, perms, contents))) if perms.can_read() => {
+                                data.push(contents[(addr - base) as usize]);
+                            }
+                            _ => return (false, Vec::new()),
+                        }
+                    }
+                    (true, data)
+                }
+            }
+        }
+
+        /// Apply an operation to the memory under test
+        fn execute(memory: &mut Memory, op: &Op) -> (bool, Vec<u8>) {
+            match op {
+                Op::Map(base, size, perms) => {
+                    (memory.map(*base, *size, *perms).is_ok(), Vec::new())
+                }
+                Op::Unmap(base) => (memory.unmap(*base).is_ok(), Vec::new()),
+                Op::Protect(base, perms) => (memory.protect(*base, *perms).is_ok(), Vec::new()),
+                Op::Write(addr, data) => (memory.write_bytes(*addr, data).is_ok(), Vec::new()),
+                Op::WriteU64(addr, value) => (memory.write_u64(*addr, *value).is_ok(), Vec::new()),
+                Op::Read(addr, len) => {
+                    let mut data = vec![0; *len];
+                    match memory.read_bytes(*addr, &mut data) {
+                        Ok(()) => (true, data),
+                        Err(_) => (false, Vec::new()),
+                    }
+                }
+            }
+        }
+
+        proptest! {
+            /// Memory behaves like the model after any sequence of operations
+            /// and its regions never overlap
+            #[test]
+            fn prop_matches_model(ops in prop::collection::vec(op(), 1..64)) {
+                let mut memory = Memory::new();
+                let mut model = Model::new();
+
+                for op in &ops {
+                    prop_assert_eq!(execute(&mut memory, op), apply(&mut model, op), "{:?}", op);
+
+                    let regions: Vec<(u64, u64)> = memory
+                        .regions()
+                        .map(|(base, _, data)| (base, data.len() as u64))
+                        .collect();
+                    for pair in regions.windows(2) {
+                        prop_assert!(pair[0].0 + pair[0].1 <= pair[1].0);
+                    }
+                    prop_assert_eq!(regions.len(), model.len());
+                }
+
+                // Backing store matches the model byte for byte
+                for (base, perms, data) in memory.regions() {
+                    let (_, model_perms, contents) = &model[&base];
+                    prop_assert_eq!(perms, *model_perms);
+                    prop_assert_eq!(data, contents.as_slice());
+                }
+            }
+
+            /// Permissions apply to every byte of a region and nothing outside it
+            #[test]
+            fn prop_permissions_every_byte(
+                base in base(),
+                size in 1u64..0x400,
+                perms in permissions(),
+                offset in 0u64..0x500,
+            ) {
+                let mut memory = Memory::new();
+                memory.map(base, size, perms).unwrap();
+                let addr = base + offset;
+                let inside = offset < size;
+
+                prop_assert_eq!(memory.read_u8(addr).is_ok(), inside && perms.can_read());
+                prop_assert_eq!(memory.write_u8(addr, 0xA5).is_ok(), inside && perms.can_write());
+                if inside && perms.can_read() && perms.can_write() {
+                    prop_assert_eq!(memory.read_u8(addr).unwrap(), 0xA5);
+                }
+            }
+        }
     }
 }
@@ -0,0 +1,236 @@
+//! Cache replacement policies
+//!
+//! Each cache level picks the line to evict from a full set with a
+//! [`ReplacementPolicy`]. The policy sees every hit and fill by set and way,
+//! and keeps whatever per-set state it needs. [`Replacement`] names the
+//! policies a configuration can select:
+//!
+//! - `lru` evicts the least recently used line.
+//! - `pseudo-lru` keeps one MRU bit per line, cleared for the rest of the
+//!   set once every bit is set, and evicts the first line whose bit is
+//!   clear. Unlike tree PLRU it works for any associativity.
+//! - `random` evicts a pseudo-random line from a fixed seed, so runs are
+//!   repeatable. Chaos mode switches every level to it with a seed of its
+//!   own, so replacement can be varied across runs.
+//! - `fifo` evicts the line filled longest ago, ignoring hits.
+
+use crate::chaos::Chaos;
+use serde::Deserialize;
+use std::fmt;
+
+/// Seed of the random policy's generator
+const RANDOM_SEED: u64 = 0x1A64_CAC4_E5EE_D000;
+
+/// Chooses which line of a full set to evict
+pub trait ReplacementPolicy: Send + fmt::Debug {
+    /// Way `way` of set `set` was hit
+    fn touch(&mut self, set: usize, way: usize);
+
+    /// Way `way` of set `set` was filled with a new line
+    fn insert(&mut self, set: usize, way: usize) {
+        self.touch(set, way);
+    }
+
+    /// Way to evict from set `set`, all of whose lines are valid
+    fn victim(&mut self, set: usize) -> usize;
+}
+
+/// Replacement policy selectable per cache level
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Replacement {
+    /// Least recently used
+    #[default]
+    Lru,
+    /// One MRU bit per line
+    PseudoLru,
+    /// Pseudo-random from a fixed seed
+    Random,
+    /// First in, first out
+    Fifo,
+}
+
+impl Replacement {
+    /// Create the policy for a cache of `sets` sets of `ways` lines
+    pub fn build(self, sets: usize, ways: usize) -> Box<dyn ReplacementPolicy> {
+        match self {
+            Self::Lru => Box::new(Lru::new(sets, ways)),
+            Self::PseudoLru => Box::new(PseudoLru::new(sets, ways)),
+            Self::Random => Box::new(Random::new(ways)),
+            Self::Fifo => Box::new(Fifo::new(sets, ways)),
+        }
+    }
+}
+
+/// Least recently used replacement
+#[derive(Debug)]
+pub struct Lru {
+    /// Lines per set
+    ways: usize,
+    /// Access count at each line's last hit or fill, by set then way
+    stamps: Vec<u64>,
+    /// Accesses so far
+    clock: u64,
+}
+
+impl Lru {
+    /// Create the policy for `sets` sets of `ways` lines
+    pub fn new(sets: usize, ways: usize) -> Self {
+        Self {
+            ways,
+            stamps: vec![0; sets * ways],
+            clock: 0,
+        }
+    }
+}
+
+impl ReplacementPolicy for Lru {
+    fn touch(&mut self, set: usize, way: usize) {
+        self.clock += 1;
+        self.stamps[set * self.ways + way] = self.clock;
+    }
+
+    fn victim(&mut self, set: usize) -> usize {
+        let stamps = &self.stamps[set * self.ways..(set + 1) * self.ways];
+        (0..self.ways).min_by_key(|&way| stamps[way]).unwrap()
+    }
+}
+
+/// Bit pseudo-LRU replacement
+#[derive(Debug)]
+pub struct PseudoLru {
+    /// Lines per set
+    ways: usize,
+    /// MRU bits, by set then way
+    mru: Vec<bool>,
+}
+
+impl PseudoLru {
+    /// Create the policy for `sets` sets of `ways` lines
+    pub fn new(sets: usize, ways: usize) -> Self {
+        Self {
+            ways,
+            mru: vec![false; sets * ways],
+        }
+    }
+}
+
+impl ReplacementPolicy for PseudoLru {
+    fn touch(&mut self, set: usize, way: usize) {
+        let bits = &mut self.mru[set * self.ways..(set + 1) * self.ways];
+        bits[way] = true;
+        if bits.iter().all(|&bit| bit) {
+            bits.fill(false);
+            bits[way] = true;
+        }
+    }
+
+    fn victim(&mut self, set: usize) -> usize {
+        let bits = &self.mru[set * self.ways..(set + 1) * self.ways];
+        bits.iter().position(|&bit| !bit).unwrap_or(0)
+    }
+}
+
+/// Random replacement
+#[derive(Debug)]
+pub struct Random {
+    /// Lines per set
+    ways: usize,
+    /// Generator of victims
+    chaos: Chaos,
+}
+
+impl Random {
+    /// Create the policy for sets of `ways` lines
+    pub fn new(ways: usize) -> Self {
+        Self::with_seed(ways, RANDOM_SEED)
+    }
+
+    /// Create the policy for sets of `ways` lines with victims drawn from
+    /// a generator seeded with `seed`
+    pub fn with_seed(ways: usize, seed: u64) -> Self {
+        Self {
+            ways,
+            chaos: Chaos::new(seed, 0),
+        }
+    }
+}
+
+impl ReplacementPolicy for Random {
+    fn touch(&mut self, _set: usize, _way: usize) {}
+
+    fn victim(&mut self, _set: usize) -> usize {
+        (self.chaos.next_u64() % self.ways as u64) as usize
+    }
+}
+
+/// First in, first out replacement
+#[derive(Debug)]
+pub struct Fifo {
+    /// Lines per set
+    ways: usize,
+    /// Fill count at each line's fill, by set then way
+    stamps: Vec<u64>,
+    /// Fills so far
+    clock: u64,
+}
+
+impl Fifo {
+    /// Create the policy for `sets` sets of `ways` lines
+    pub fn new(sets: usize, ways: usize) -> Self {
+        Self {
+            ways,
+            stamps: vec![0; sets * ways],
+            clock: 0,
+        }
+    }
+}
+
+impl ReplacementPolicy for Fifo {
+    fn touch(&mut self, _set: usize, _way: usize) {}
+
+    fn insert(&mut self, set: usize, way: usize) {
+        self.clock += 1;
+        self.stamps[set * self.ways + way] = self.clock;
+    }
+
+    fn victim(&mut self, set: usize) -> usize {
+        let stamps = &self.stamps[set * self.ways..(set + 1) * self.ways];
+        (0..self.ways).min_by_key(|&way| stamps[way]).unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Fill four ways of set 1 in order, then hit way 0
+    fn fill_and_hit(replacement: Replacement) -> Box<dyn ReplacementPolicy> {
+        let mut policy = replacement.build(2, 4);
+        for way in 0..4 {
+            policy.insert(1, way);
+        }
+        policy.touch(1, 0);
+        policy
+    }
+
+    #[test]
+    fn test_policies() {
+        assert_eq!(fill_and_hit(Replacement::Lru).victim(1), 1);
+        assert_eq!(fill_and_hit(Replacement::Fifo).victim(1), 0);
+
+        // Filling the last way cleared the other MRU bits, and the hit set
+        // way 0's again
+        assert_eq!(fill_and_hit(Replacement::PseudoLru).victim(1), 1);
+
+        let mut random = fill_and_hit(Replacement::Random);
+        let victims: Vec<usize> = (0..64).map(|_| random.victim(1)).collect();
+        assert!(victims.iter().all(|&way| way < 4));
+        assert!((0..4).all(|way| victims.contains(&way)));
+        let mut again = fill_and_hit(Replacement::Random);
+        assert_eq!(
+            victims,
+            (0..64).map(|_| again.victim(1)).collect::<Vec<_>>()
+        );
+    }
+}
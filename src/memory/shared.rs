@@ -0,0 +1,231 @@
+//! Guest memory shared with host threads
+//!
+//! A shared region's contents live in a [`SharedMemory`] buffer that host
+//! threads, such as a device model's worker, hold a handle to while the
+//! guest runs. Guest accesses to it bypass the simulated caches, which the
+//! host would not see, and are real host atomic operations: ordinary loads
+//! and stores are relaxed, `ld.acq` loads are acquire loads and `st.rel`
+//! stores are release stores. A host thread that publishes data with
+//! [`SharedMemory::store`] and `Ordering::Release` is therefore seen in order
+//! by a guest polling with `ld.acq`, and the reverse.
+//!
+//! The buffer is an array of 64-bit atomic words. An access within one word
+//! is a single atomic operation; sub-word stores update their bytes with a
+//! compare-and-swap on the word. Accesses that cross a word are only
+//! allowed unordered, byte by byte, and an acquire or release access that
+//! is not naturally aligned raises an unaligned data reference fault, as on
+//! hardware.
+
+use crate::EmulatorError;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+/// Bytes per atomic word
+const WORD: u64 = 8;
+
+/// Memory buffer shared between the guest and host threads
+///
+/// Clones are handles to the same buffer.
+#[derive(Debug, Clone)]
+pub struct SharedMemory {
+    /// Contents, little-endian within each word
+    words: Arc<[AtomicU64]>,
+    /// Size in bytes
+    size: u64,
+}
+
+impl SharedMemory {
+    /// Create a zeroed buffer of `size` bytes
+    pub fn new(size: u64) -> Self {
+        let words = (0..size.div_ceil(WORD))
+            .map(|_| AtomicU64::new(0))
+            .collect();
+        Self { words, size }
+    }
+
+    /// Size in bytes
+    pub fn len(&self) -> u64 {
+        self.size
+    }
+
+    /// Whether the buffer is empty
+    pub fn is_empty(&self) -> bool {
+        self.size == 0
+    }
+
+    /// Load `len` bytes (1, 2, 4 or 8) at `offset`, little-endian
+    ///
+    /// With an ordering other than `Relaxed` the access must be naturally
+    /// aligned.
+    pub fn load(&self, offset: u64, len: usize, order: Ordering) -> Result<u64, EmulatorError> {
+        self.check(offset, len, order)?;
+        let (word, shift) = ((offset / WORD) as usize, (offset % WORD) * 8);
+        if shift / 8 + len as u64 <= WORD {
+            return Ok((self.words[word].load(order) >> shift) & mask(len));
+        }
+        let mut value = 0;
+        for i in 0..len as u64 {
+            value |= (self.byte(offset + i) as u64) << (i * 8);
+        }
+        Ok(value)
+    }
+
+    /// Store the low `len` bytes (1, 2, 4 or 8) of `value` at `offset`,
+    /// little-endian
+    ///
+    /// With an ordering other than `Relaxed` the access must be naturally
+    /// aligned.
+    pub fn store(
+        &self,
+        offset: u64,
+        len: usize,
+        value: u64,
+        order: Ordering,
+    ) -> Result<(), EmulatorError> {
+        self.check(offset, len, order)?;
+        let (word, shift) = ((offset / WORD) as usize, (offset % WORD) * 8);
+        if shift / 8 + len as u64 <= WORD {
+            self.update(word, mask(len) << shift, value << shift, order);
+            return Ok(());
+        }
+        for i in 0..len as u64 {
+            self.set_byte(offset + i, (value >> (i * 8)) as u8);
+        }
+        Ok(())
+    }
+
+    /// Copy bytes out of the buffer without ordering
+    pub fn read_bytes(&self, offset: u64, data: &mut [u8]) -> Result<(), EmulatorError> {
+        self.check_range(offset, data.len() as u64)?;
+        for (i, byte) in data.iter_mut().enumerate() {
+            *byte = self.byte(offset + i as u64);
+        }
+        Ok(())
+    }
+
+    /// Copy bytes into the buffer without ordering
+    pub fn write_bytes(&self, offset: u64, data: &[u8]) -> Result<(), EmulatorError> {
+        self.check_range(offset, data.len() as u64)?;
+        for (i, &byte) in data.iter().enumerate() {
+            self.set_byte(offset + i as u64, byte);
+        }
+        Ok(())
+    }
+
+    /// Check an access's size, bounds and alignment
+    fn check(&self, offset: u64, len: usize, order: Ordering) -> Result<(), EmulatorError> {
+        if !matches!(len, 1 | 2 | 4 | 8) {
+            return Err(EmulatorError::MemoryError(format!(
+                "Unsupported shared memory access size: {}",
+                len
+            )));
+        }
+        self.check_range(offset, len as u64)?;
+        if order != Ordering::Relaxed && !offset.is_multiple_of(len as u64) {
+            return Err(EmulatorError::MemoryError(format!(
+                "Unaligned data reference: {}-byte ordered access at offset {:#x}",
+                len, offset
+            )));
+        }
+        Ok(())
+    }
+
+    /// Check that `len` bytes at `offset` are in the buffer
+    fn check_range(&self, offset: u64, len: u64) -> Result<(), EmulatorError> {
+        if offset.checked_add(len).is_none_or(|end| end > self.size) {
+            return Err(EmulatorError::MemoryError(format!(
+                "Access at offset {:#x} crosses the end of shared memory",
+                offset
+            )));
+        }
+        Ok(())
+    }
+
+    /// Replace the bits of word `index` selected by `mask` with `bits`
+    fn update(&self, index: usize, mask: u64, bits: u64, order: Ordering) {
+        let word = &self.words[index];
+        if mask == u64::MAX {
+            word.store(bits, store_order(order));
+            return;
+        }
+        let _ = word.fetch_update(order, Ordering::Relaxed, |old| {
+            Some((old & !mask) | (bits & mask))
+        });
+    }
+
+    /// Byte at `offset`
+    fn byte(&self, offset: u64) -> u8 {
+        (self.words[(offset / WORD) as usize].load(Ordering::Relaxed) >> (offset % WORD * 8)) as u8
+    }
+
+    /// Set the byte at `offset`
+    fn set_byte(&self, offset: u64, byte: u8) {
+        let shift = offset % WORD * 8;
+        self.update(
+            (offset / WORD) as usize,
+            0xFF << shift,
+            (byte as u64) << shift,
+            Ordering::Relaxed,
+        );
+    }
+}
+
+/// Mask of the low `len` bytes
+fn mask(len: usize) -> u64 {
+    u64::MAX >> (64 - len * 8)
+}
+
+/// Ordering of a plain store for an access ordering; acquire has no store
+/// meaning, so it is strengthened to sequential consistency
+fn store_order(order: Ordering) -> Ordering {
+    match order {
+        Ordering::Acquire | Ordering::AcqRel => Ordering::SeqCst,
+        order => order,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn test_access() {
+        let shared = SharedMemory::new(20);
+        shared
+            .store(0, 8, 0x1122_3344_5566_7788, Ordering::Release)
+            .unwrap();
+        shared.store(2, 2, 0xBEEF, Ordering::Relaxed).unwrap();
+        assert_eq!(
+            shared.load(0, 8, Ordering::Acquire).unwrap(),
+            0x1122_3344_BEEF_7788
+        );
+        // Unordered accesses may cross words, ordered ones must be aligned
+        shared.store(6, 4, 0xAABB_CCDD, Ordering::Relaxed).unwrap();
+        assert_eq!(shared.load(6, 4, Ordering::Relaxed).unwrap(), 0xAABB_CCDD);
+        assert!(shared.load(6, 4, Ordering::Acquire).is_err());
+        assert!(shared.store(17, 4, 0, Ordering::Relaxed).is_err());
+        assert!(shared.load(0, 3, Ordering::Relaxed).is_err());
+
+        let mut bytes = [0; 4];
+        shared.read_bytes(6, &mut bytes).unwrap();
+        assert_eq!(bytes, [0xDD, 0xCC, 0xBB, 0xAA]);
+    }
+
+    #[test]
+    fn test_message_passing() {
+        // The host thread publishes a payload, then a flag with release
+        // ordering; a reader that acquires the flag sees the payload
+        let shared = SharedMemory::new(16);
+        let host = shared.clone();
+        let writer = thread::spawn(move || {
+            host.store(8, 8, 0xC0FFEE, Ordering::Relaxed).unwrap();
+            host.store(0, 8, 1, Ordering::Release).unwrap();
+        });
+        while shared.load(0, 8, Ordering::Acquire).unwrap() == 0 {
+            std::hint::spin_loop();
+        }
+        assert_eq!(shared.load(8, 8, Ordering::Relaxed).unwrap(), 0xC0FFEE);
+        writer.join().unwrap();
+    }
+}
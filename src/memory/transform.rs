@@ -0,0 +1,233 @@
+//! Transformed region backing, for memory encryption experiments
+//!
+//! A transformed region keeps a second copy of its contents, its backing
+//! store, passed through a [`Transform`] such as an XOR pad or AES in
+//! counter mode: what DRAM would hold under memory encryption. Stores that
+//! reach memory are encoded into it, and lines filled from memory are
+//! decoded out of it, so a load that misses every cache level crosses the
+//! transform, with the timing model charging its latency. Loads that hit
+//! a cache never do, and neither do debugger peeks, block copies and
+//! snapshots, which see the clear contents.
+//!
+//! A transform that does not invert itself shows up as corrupted loads,
+//! which is the point of some experiments.
+
+use std::fmt;
+
+/// Reversible transformation between the clear contents of memory and its
+/// backing store
+///
+/// Both directions get the guest address of the first byte, so a
+/// transform can vary along the region the way a memory encryption
+/// engine tweaks its cipher with the physical address.
+pub trait Transform: Send + fmt::Debug {
+    /// Turn the clear bytes at `addr` into what the backing store holds
+    fn encode(&self, addr: u64, data: &mut [u8]);
+
+    /// Turn backing store bytes at `addr` back into clear bytes
+    fn decode(&self, addr: u64, data: &mut [u8]);
+}
+
+/// XOR with a pad repeating along the address space
+///
+/// The pad is aligned to address 0, so a region sees the same bytes at the
+/// same addresses wherever it starts.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct XorPad {
+    /// Bytes XORed in, at addresses modulo its length
+    pad: Vec<u8>,
+}
+
+impl XorPad {
+    /// Create a transform XORing with `pad`, which must not be empty
+    pub fn new(pad: Vec<u8>) -> Option<Self> {
+        (!pad.is_empty()).then_some(Self { pad })
+    }
+
+    /// XOR the pad into `data` at `addr`
+    fn apply(&self, addr: u64, data: &mut [u8]) {
+        let len = self.pad.len() as u64;
+        for (i, byte) in data.iter_mut().enumerate() {
+            *byte ^= self.pad[(addr.wrapping_add(i as u64) % len) as usize];
+        }
+    }
+}
+
+impl Transform for XorPad {
+    fn encode(&self, addr: u64, data: &mut [u8]) {
+        self.apply(addr, data);
+    }
+
+    fn decode(&self, addr: u64, data: &mut [u8]) {
+        self.apply(addr, data);
+    }
+}
+
+/// AES-128 in counter mode, keyed by address
+///
+/// The counter block of the 16 bytes at `addr` is the nonce followed by
+/// `addr / 16`, both big-endian, so every block of the address space has
+/// its own key stream and bytes can be transformed in any order.
+#[cfg(feature = "aes-ctr")]
+#[derive(Clone)]
+pub struct AesCtr {
+    /// Block cipher generating the key stream
+    cipher: aes::Aes128,
+    /// Upper half of every counter block
+    nonce: u64,
+}
+
+#[cfg(feature = "aes-ctr")]
+impl fmt::Debug for AesCtr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // The key stays out of logs
+        f.debug_struct("AesCtr")
+            .field("nonce", &self.nonce)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(feature = "aes-ctr")]
+impl AesCtr {
+    /// Create a transform with a 128-bit `key` and `nonce`
+    pub fn new(key: [u8; 16], nonce: u64) -> Self {
+        use aes::cipher::KeyInit;
+        Self {
+            cipher: aes::Aes128::new(&key.into()),
+            nonce,
+        }
+    }
+
+    /// XOR the key stream into `data` at `addr`
+    fn apply(&self, addr: u64, data: &mut [u8]) {
+        use aes::cipher::BlockEncrypt;
+        let mut done = 0;
+        while done < data.len() {
+            let at = addr.wrapping_add(done as u64);
+            let mut block = [0u8; 16];
+            block[..8].copy_from_slice(&self.nonce.to_be_bytes());
+            block[8..].copy_from_slice(&(at / 16).to_be_bytes());
+            let mut block = block.into();
+            self.cipher.encrypt_block(&mut block);
+            let start = (at % 16) as usize;
+            let len = (16 - start).min(data.len() - done);
+            for (byte, key) in data[done..done + len].iter_mut().zip(&block[start..]) {
+                *byte ^= key;
+            }
+            done += len;
+        }
+    }
+}
+
+#[cfg(feature = "aes-ctr")]
+impl Transform for AesCtr {
+    fn encode(&self, addr: u64, data: &mut [u8]) {
+        self.apply(addr, data);
+    }
+
+    fn decode(&self, addr: u64, data: &mut [u8]) {
+        self.apply(addr, data);
+    }
+}
+
+/// Backing store of a transformed region
+#[derive(Debug)]
+pub(crate) struct Backing {
+    /// Transformation between the region's contents and `data`
+    transform: Box<dyn Transform>,
+    /// Transformed contents
+    data: Vec<u8>,
+}
+
+impl Backing {
+    /// Back the region at `base` holding `contents` through `transform`
+    pub(crate) fn new(transform: Box<dyn Transform>, base: u64, contents: &[u8]) -> Self {
+        let mut data = contents.to_vec();
+        transform.encode(base, &mut data);
+        Self { transform, data }
+    }
+
+    /// Encode clear bytes stored at `offset`, address `addr`
+    pub(crate) fn store(&mut self, offset: usize, addr: u64, clear: &[u8]) {
+        let stored = &mut self.data[offset..offset + clear.len()];
+        stored.copy_from_slice(clear);
+        self.transform.encode(addr, stored);
+    }
+
+    /// Decode the bytes at `offset`, address `addr`, into `clear`
+    pub(crate) fn load(&self, offset: usize, addr: u64, clear: &mut [u8]) {
+        clear.copy_from_slice(&self.data[offset..offset + clear.len()]);
+        self.transform.decode(addr, clear);
+    }
+
+    /// Transformed contents
+    pub(crate) fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Keep the first `len` bytes
+    pub(crate) fn truncate(&mut self, len: usize) {
+        self.data.truncate(len);
+        self.data.shrink_to_fit();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_xor_pad() {
+        assert!(XorPad::new(Vec::new()).is_none());
+        let pad = XorPad::new(vec![0x0F, 0xF0, 0xFF]).unwrap();
+
+        // The pad follows the address, not the start of the buffer
+        let mut data = [0u8; 4];
+        pad.encode(0x1001, &mut data);
+        assert_eq!(data, [0xFF, 0x0F, 0xF0, 0xFF]);
+        pad.decode(0x1001, &mut data);
+        assert_eq!(data, [0; 4]);
+    }
+
+    #[test]
+    fn test_backing() {
+        let pad = XorPad::new(vec![0xAA]).unwrap();
+        let mut backing = Backing::new(Box::new(pad), 0x2000, &[1, 2, 3, 4]);
+        assert_eq!(backing.data(), [0xAB, 0xA8, 0xA9, 0xAE]);
+
+        backing.store(2, 0x2002, &[0xAA]);
+        assert_eq!(backing.data()[2], 0);
+        let mut clear = [0; 3];
+        backing.load(1, 0x2001, &mut clear);
+        assert_eq!(clear, [2, 0xAA, 4]);
+    }
+
+    #[cfg(feature = "aes-ctr")]
+    #[test]
+    fn test_aes_ctr() {
+        let aes = AesCtr::new([0x2B; 16], 7);
+        let clear: Vec<u8> = (0..40).collect();
+
+        // Transforming in pieces matches transforming at once
+        let mut whole = clear.clone();
+        aes.encode(0x3005, &mut whole);
+        let mut pieces = clear.clone();
+        for (i, chunk) in pieces.chunks_mut(7).enumerate() {
+            aes.encode(0x3005 + 7 * i as u64, chunk);
+        }
+        assert_eq!(whole, pieces);
+        assert_ne!(whole, clear);
+
+        // Equal bytes at other addresses or under another nonce differ
+        let mut moved = clear.clone();
+        aes.encode(0x4005, &mut moved);
+        assert_ne!(moved, whole);
+        let mut renonced = clear.clone();
+        AesCtr::new([0x2B; 16], 8).encode(0x3005, &mut renonced);
+        assert_ne!(renonced, whole);
+
+        aes.decode(0x3005, &mut whole);
+        assert_eq!(whole, clear);
+        assert!(!format!("{:?}", aes).contains("2b"));
+    }
+}
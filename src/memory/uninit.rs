@@ -0,0 +1,151 @@
+//! Reads of uninitialized memory
+//!
+//! Many guest bugs come down to reading memory nothing ever wrote. With an
+//! [`UninitPolicy`] other than `off`, each region keeps a [`WrittenMap`] of
+//! the bytes stored to since it was mapped, and loads of other bytes are
+//! reported, poisoned or faulted, much as MemorySanitizer does for host
+//! code. Images loaded into a region count as written, and ranges the
+//! loader or firmware initialize can be allowlisted with
+//! [`Memory::mark_initialized`](super::Memory::mark_initialized).
+//!
+//! Written bytes are tracked per page: a page nothing or everything was
+//! written to costs no bitmap, so large regions stay cheap. Shared regions
+//! and device windows are not tracked.
+
+use super::PAGE_SIZE;
+use serde::Deserialize;
+
+/// Byte returned for uninitialized memory under [`UninitPolicy::Poison`]
+pub const POISON: u8 = 0xA5;
+
+/// 64-bit words in a page's bitmap
+const WORDS: usize = PAGE_SIZE as usize / 64;
+
+/// What happens on a load of uninitialized memory
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum UninitPolicy {
+    /// Do not track written memory
+    #[default]
+    Off,
+    /// Perform the load and report it
+    Warn,
+    /// Report the load and return [`POISON`] bytes
+    Poison,
+    /// Reject the load with a memory fault
+    Fault,
+}
+
+/// Written state of one page
+#[derive(Debug, Clone)]
+enum Page {
+    /// No byte written
+    Unwritten,
+    /// Some bytes written, one bit per byte
+    Partial(Box<[u64; WORDS]>),
+    /// Every byte written
+    Written,
+}
+
+/// Bytes of a region written since it was mapped
+#[derive(Debug, Clone)]
+pub(crate) struct WrittenMap {
+    /// Pages from the start of the region
+    pages: Vec<Page>,
+}
+
+impl WrittenMap {
+    /// Map of a `size` byte region, nothing written
+    pub(crate) fn new(size: u64) -> Self {
+        Self {
+            pages: vec![Page::Unwritten; size.div_ceil(PAGE_SIZE) as usize],
+        }
+    }
+
+    /// Map of a `size` byte region, everything written
+    pub(crate) fn written(size: u64) -> Self {
+        Self {
+            pages: vec![Page::Written; size.div_ceil(PAGE_SIZE) as usize],
+        }
+    }
+
+    /// Drop the pages past the first `size` bytes
+    pub(crate) fn truncate(&mut self, size: u64) {
+        self.pages.truncate(size.div_ceil(PAGE_SIZE) as usize);
+    }
+
+    /// Whether the byte at `offset` was written
+    pub(crate) fn is_written(&self, offset: usize) -> bool {
+        let byte = offset % PAGE_SIZE as usize;
+        match &self.pages[offset / PAGE_SIZE as usize] {
+            Page::Unwritten => false,
+            Page::Partial(bits) => bits[byte / 64] & (1 << (byte % 64)) != 0,
+            Page::Written => true,
+        }
+    }
+
+    /// Mark `len` bytes at `offset` as written
+    pub(crate) fn set(&mut self, offset: usize, len: usize) {
+        let page_size = PAGE_SIZE as usize;
+        let mut offset = offset;
+        let end = offset + len;
+        while offset < end {
+            let index = offset / page_size;
+            let start = offset % page_size;
+            let stop = (end - index * page_size).min(page_size);
+            offset = (index + 1) * page_size;
+
+            let page = &mut self.pages[index];
+            if matches!(page, Page::Written) || (start == 0 && stop == page_size) {
+                *page = Page::Written;
+                continue;
+            }
+            if matches!(page, Page::Unwritten) {
+                *page = Page::Partial(Box::new([0; WORDS]));
+            }
+            let Page::Partial(bits) = page else {
+                unreachable!()
+            };
+            for byte in start..stop {
+                bits[byte / 64] |= 1 << (byte % 64);
+            }
+            if bits.iter().all(|&word| word == u64::MAX) {
+                *page = Page::Written;
+            }
+        }
+    }
+}
+
+/// Uninitialized bytes loaded by the guest
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UninitAccess {
+    /// Address of the first byte
+    pub addr: u64,
+    /// Consecutive bytes loaded
+    pub len: usize,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_written_map() {
+        let mut map = WrittenMap::new(3 * PAGE_SIZE);
+        map.set(10, 4);
+        assert!(!map.is_written(9));
+        assert!((10..14).all(|offset| map.is_written(offset)));
+        assert!(!map.is_written(14));
+
+        // A write spanning pages fills the middle one outright
+        map.set(PAGE_SIZE as usize - 2, PAGE_SIZE as usize + 4);
+        assert!(matches!(map.pages[1], Page::Written));
+        assert!(map.is_written(2 * PAGE_SIZE as usize + 1));
+        assert!(!map.is_written(2 * PAGE_SIZE as usize + 2));
+
+        // Filling a page byte by byte ends up the same
+        map.set(0, PAGE_SIZE as usize - 2);
+        assert!(matches!(map.pages[0], Page::Written));
+        assert!(WrittenMap::written(1).is_written(0));
+    }
+}
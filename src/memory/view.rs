@@ -0,0 +1,300 @@
+//! Typed views of guest memory
+//!
+//! A [`MemoryView`] presents a guest range as an array of integers or floats
+//! of one width and byte order, so the debugger's `x` command and device
+//! models walking descriptor rings do not assemble values from bytes at each
+//! use. Reads go through [`Memory::peek_bytes`] and leave the caches and
+//! statistics alone; writes through a [`MemoryViewMut`] are ordinary stores.
+//!
+//! [`Endian`] is also the byte order of data accesses: the CPU picks it from
+//! PSR.be and passes it to [`Memory::read_sized`] and
+//! [`Memory::write_sized`].
+
+use super::Memory;
+use crate::EmulatorError;
+
+/// Byte order of the values in a view
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Endian {
+    /// Least significant byte first, as IA-64 runs with PSR.be clear
+    #[default]
+    Little,
+    /// Most significant byte first, as many device formats are
+    Big,
+}
+
+impl Endian {
+    /// Convert a `len` byte value (1, 2, 4 or 8) between little-endian and
+    /// this byte order
+    ///
+    /// Loading `len` bytes little-endian and converting gives the value in
+    /// this order, and converting a value before storing it little-endian
+    /// lays it out in this order.
+    pub fn convert(self, value: u64, len: usize) -> u64 {
+        match self {
+            Endian::Little => value,
+            Endian::Big => value.swap_bytes() >> (64 - 8 * len as u32),
+        }
+    }
+}
+
+/// Value a view can be read as
+pub trait Element: Copy {
+    /// Size in bytes
+    const SIZE: usize;
+
+    /// Value from `SIZE` bytes in the given order
+    fn decode(bytes: &[u8], endian: Endian) -> Self;
+
+    /// Store the value into `SIZE` bytes in the given order
+    fn encode(self, endian: Endian, bytes: &mut [u8]);
+}
+
+macro_rules! element {
+    ($($ty:ty),*) => {
+        $(
+            impl Element for $ty {
+                const SIZE: usize = std::mem::size_of::<$ty>();
+
+                fn decode(bytes: &[u8], endian: Endian) -> Self {
+                    let bytes = bytes.try_into().unwrap();
+                    match endian {
+                        Endian::Little => <$ty>::from_le_bytes(bytes),
+                        Endian::Big => <$ty>::from_be_bytes(bytes),
+                    }
+                }
+
+                fn encode(self, endian: Endian, bytes: &mut [u8]) {
+                    bytes.copy_from_slice(&match endian {
+                        Endian::Little => self.to_le_bytes(),
+                        Endian::Big => self.to_be_bytes(),
+                    });
+                }
+            }
+        )*
+    };
+}
+
+element!(u8, u16, u32, u64, i8, i16, i32, i64, f32, f64);
+
+/// Read-only typed view of a guest range
+#[derive(Debug, Clone, Copy)]
+pub struct MemoryView<'a> {
+    memory: &'a Memory,
+    base: u64,
+    len: u64,
+    endian: Endian,
+}
+
+impl<'a> MemoryView<'a> {
+    /// View `len` bytes at `base`
+    pub fn new(memory: &'a Memory, base: u64, len: u64, endian: Endian) -> Self {
+        Self {
+            memory,
+            base,
+            len,
+            endian,
+        }
+    }
+
+    /// First guest address of the view
+    pub fn base(&self) -> u64 {
+        self.base
+    }
+
+    /// Length of the view in bytes
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    /// Whether the view is empty
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Byte order values are read in
+    pub fn endian(&self) -> Endian {
+        self.endian
+    }
+
+    /// Number of whole `T` values in the view
+    pub fn count<T: Element>(&self) -> usize {
+        (self.len / T::SIZE as u64) as usize
+    }
+
+    /// Value `index` of the view as an array of `T`
+    pub fn get<T: Element>(&self, index: usize) -> Result<T, EmulatorError> {
+        self.read((index * T::SIZE) as u64)
+    }
+
+    /// Value at byte `offset` into the view, which need not be aligned
+    pub fn read<T: Element>(&self, offset: u64) -> Result<T, EmulatorError> {
+        let addr = self.check(offset, T::SIZE)?;
+        let mut bytes = [0u8; 8];
+        self.memory.peek_bytes(addr, &mut bytes[..T::SIZE])?;
+        Ok(T::decode(&bytes[..T::SIZE], self.endian))
+    }
+
+    /// Every whole `T` value in the view
+    ///
+    /// The vector grows as values are read, so a view running into unmapped
+    /// memory fails without first allocating room for all of it.
+    pub fn to_vec<T: Element>(&self) -> Result<Vec<T>, EmulatorError> {
+        (0..self.count::<T>())
+            .map(|index| self.get(index))
+            .collect()
+    }
+
+    /// Guest address of `size` bytes at `offset`, if inside the view
+    fn check(&self, offset: u64, size: usize) -> Result<u64, EmulatorError> {
+        match offset.checked_add(size as u64) {
+            Some(end) if end <= self.len => Ok(self.base + offset),
+            _ => Err(EmulatorError::MemoryError(format!(
+                "Access of {} bytes at offset {:#x} outside a {:#x} byte view",
+                size, offset, self.len
+            ))),
+        }
+    }
+}
+
+/// Typed view of a guest range that can also be written
+#[derive(Debug)]
+pub struct MemoryViewMut<'a> {
+    memory: &'a mut Memory,
+    base: u64,
+    len: u64,
+    endian: Endian,
+}
+
+impl<'a> MemoryViewMut<'a> {
+    /// View `len` bytes at `base`
+    pub fn new(memory: &'a mut Memory, base: u64, len: u64, endian: Endian) -> Self {
+        Self {
+            memory,
+            base,
+            len,
+            endian,
+        }
+    }
+
+    /// Read-only view of the same range
+    pub fn as_view(&self) -> MemoryView<'_> {
+        MemoryView::new(&*self.memory, self.base, self.len, self.endian)
+    }
+
+    /// Value `index` of the view as an array of `T`
+    pub fn get<T: Element>(&self, index: usize) -> Result<T, EmulatorError> {
+        self.as_view().get(index)
+    }
+
+    /// Value at byte `offset` into the view
+    pub fn read<T: Element>(&self, offset: u64) -> Result<T, EmulatorError> {
+        self.as_view().read(offset)
+    }
+
+    /// Replace value `index` of the view as an array of `T`
+    pub fn set<T: Element>(&mut self, index: usize, value: T) -> Result<(), EmulatorError> {
+        self.write((index * T::SIZE) as u64, value)
+    }
+
+    /// Store a value at byte `offset` into the view
+    pub fn write<T: Element>(&mut self, offset: u64, value: T) -> Result<(), EmulatorError> {
+        let addr = self.as_view().check(offset, T::SIZE)?;
+        let mut bytes = [0u8; 8];
+        value.encode(self.endian, &mut bytes[..T::SIZE]);
+        self.memory.write_bytes(addr, &bytes[..T::SIZE])
+    }
+}
+
+impl Memory {
+    /// Typed read-only view of `len` bytes at `base`
+    pub fn view(&self, base: u64, len: u64, endian: Endian) -> MemoryView<'_> {
+        MemoryView::new(self, base, len, endian)
+    }
+
+    /// Typed view of `len` bytes at `base` that can be written
+    pub fn view_mut(&mut self, base: u64, len: u64, endian: Endian) -> MemoryViewMut<'_> {
+        MemoryViewMut::new(self, base, len, endian)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory::Permissions;
+
+    #[test]
+    fn test_memory_view() {
+        let mut memory = Memory::new();
+        memory.map(0x1000, 0x100, Permissions::ReadWrite).unwrap();
+        memory
+            .write_bytes(0x1000, &[0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08])
+            .unwrap();
+
+        let le = memory.view(0x1000, 8, Endian::Little);
+        assert_eq!(le.get::<u16>(1).unwrap(), 0x0403);
+        assert_eq!(le.to_vec::<u32>().unwrap(), vec![0x0403_0201, 0x0807_0605]);
+        assert_eq!(le.read::<u32>(1).unwrap(), 0x0504_0302);
+        assert_eq!(le.count::<u64>(), 1);
+        assert!(le.get::<u32>(2).is_err());
+        assert!(le.read::<u16>(7).is_err());
+
+        let be = memory.view(0x1000, 8, Endian::Big);
+        assert_eq!(be.get::<u64>(0).unwrap(), 0x0102_0304_0506_0708);
+        assert_eq!(
+            be.to_vec::<u16>().unwrap(),
+            vec![0x0102, 0x0304, 0x0506, 0x0708]
+        );
+
+        // A device updating a big-endian descriptor
+        let mut ring = memory.view_mut(0x1010, 16, Endian::Big);
+        ring.set(1, 1.5f32).unwrap();
+        ring.write(8, -2i64).unwrap();
+        assert_eq!(ring.get::<f32>(1).unwrap(), 1.5);
+        assert_eq!(memory.read_u32(0x1014).unwrap(), 0x0000_C03F);
+        assert_eq!(
+            memory
+                .view(0x1018, 8, Endian::Little)
+                .get::<u64>(0)
+                .unwrap(),
+            0xFEFF_FFFF_FFFF_FFFF
+        );
+
+        // Views reaching unmapped memory fail on access
+        assert!(memory
+            .view(0x10F8, 16, Endian::Little)
+            .get::<u64>(1)
+            .is_err());
+    }
+
+    #[test]
+    fn test_sized_access() {
+        let mut memory = Memory::new();
+        memory.map(0x1000, 0x100, Permissions::ReadWrite).unwrap();
+
+        assert_eq!(Endian::Big.convert(0x1122, 2), 0x2211);
+        assert_eq!(Endian::Big.convert(0xAB, 1), 0xAB);
+        assert_eq!(Endian::Little.convert(0x1122_3344, 4), 0x1122_3344);
+
+        memory
+            .write_sized(0x1000, 4, 0x1122_3344, Endian::Big)
+            .unwrap();
+        assert_eq!(memory.read_u32(0x1000).unwrap(), 0x4433_2211);
+        assert_eq!(
+            memory.read_sized(0x1000, 4, Endian::Big).unwrap(),
+            0x1122_3344
+        );
+        assert_eq!(
+            memory.read_sized(0x1000, 2, Endian::Little).unwrap(),
+            0x2211
+        );
+
+        memory
+            .write_sized(0x1008, 8, 0x0102_0304_0506_0708, Endian::Little)
+            .unwrap();
+        assert_eq!(
+            memory.read_sized(0x1008, 8, Endian::Big).unwrap(),
+            0x0807_0605_0403_0201
+        );
+    }
+}
@@ -0,0 +1,190 @@
+//! Process startup
+//!
+//! This module builds the initial memory stack a Linux/ia64 program finds at
+//! entry: argument and environment strings at the top, then the auxiliary
+//! vector, the NULL-terminated environment and argument pointer arrays, and
+//! argc. As left by the kernel, r12 points 16 bytes below argc; the C
+//! runtime's `_start` reads argc at `sp + 16`.
+//!
+//! The auxiliary vector carries the page size, entry point, file name and
+//! 16 bytes for AT_RANDOM. Those bytes are fixed, so runs stay reproducible.
+
+use crate::EmulatorError;
+
+/// Top of the memory stack
+pub const STACK_TOP: u64 = 0x6000_0100_0000_0000;
+
+/// Size of the memory stack
+pub const STACK_SIZE: u64 = 0x10_0000;
+
+/// General register holding the memory stack pointer
+pub const STACK_POINTER_REG: usize = 12;
+
+/// Scratch area between the stack pointer and argc
+const SCRATCH_SIZE: u64 = 16;
+
+/// End of the auxiliary vector
+pub const AT_NULL: u64 = 0;
+/// System page size
+pub const AT_PAGESZ: u64 = 6;
+/// Program entry point
+pub const AT_ENTRY: u64 = 9;
+/// Address of 16 random bytes
+pub const AT_RANDOM: u64 = 25;
+/// Address of the program's file name
+pub const AT_EXECFN: u64 = 31;
+
+/// Bytes handed out as AT_RANDOM
+const RANDOM_BYTES: [u8; 16] = *b"rust-ia64 random";
+
+/// Initial stack contents and where they go
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InitialStack {
+    /// Stack pointer at entry, 16 bytes below argc
+    pub sp: u64,
+    /// Address of argv[0]'s pointer
+    pub argv: u64,
+    /// Address of envp[0]'s pointer
+    pub envp: u64,
+    /// Bytes from `sp + 16` up to the top of the stack
+    pub data: Vec<u8>,
+}
+
+impl InitialStack {
+    /// Address of argc, where [`data`](Self::data) goes
+    pub fn data_addr(&self) -> u64 {
+        self.sp + SCRATCH_SIZE
+    }
+
+    /// Lay out `args` and `env` (as `KEY=VALUE` strings) below `top`
+    ///
+    /// `args[0]` is the program name. Strings must not contain NUL bytes.
+    pub fn build(
+        top: u64,
+        args: &[String],
+        env: &[String],
+        entry: u64,
+        page_size: u64,
+    ) -> Result<Self, EmulatorError> {
+        if let Some(text) = args.iter().chain(env).find(|text| text.contains('\0')) {
+            return Err(EmulatorError::ConfigError(format!(
+                "Guest argument {:?} contains a NUL byte",
+                text
+            )));
+        }
+
+        // Strings, highest address first: random bytes, then argv and envp
+        let mut strings = RANDOM_BYTES.to_vec();
+        let mut offsets = Vec::new();
+        for text in args.iter().chain(env) {
+            offsets.push(strings.len() as u64);
+            strings.extend_from_slice(text.as_bytes());
+            strings.push(0);
+        }
+        // argc, argv and envp with their NULLs, and at most five auxv pairs
+        let words_len = (args.len() + env.len() + 3 + 10) as u64 * 8;
+        let needed = strings.len() as u64 + words_len + SCRATCH_SIZE + 32;
+        if needed > STACK_SIZE {
+            return Err(EmulatorError::ConfigError(format!(
+                "Guest arguments and environment need {:#x} bytes, more than the {:#x} byte stack",
+                needed, STACK_SIZE
+            )));
+        }
+
+        let strings_base = (top - strings.len() as u64) & !15;
+        let string_addr = |i: usize| strings_base + offsets[i];
+
+        let mut words = vec![args.len() as u64];
+        words.extend((0..args.len()).map(string_addr));
+        words.push(0);
+        words.extend((args.len()..args.len() + env.len()).map(string_addr));
+        words.push(0);
+        let mut auxv = vec![(AT_PAGESZ, page_size), (AT_ENTRY, entry)];
+        auxv.push((AT_RANDOM, strings_base));
+        if !args.is_empty() {
+            auxv.push((AT_EXECFN, string_addr(0)));
+        }
+        auxv.push((AT_NULL, 0));
+        words.extend(auxv.iter().flat_map(|&(key, value)| [key, value]));
+
+        let argc_addr = (strings_base - words.len() as u64 * 8) & !15;
+        let sp = argc_addr - SCRATCH_SIZE;
+
+        let mut data = vec![0; (top - argc_addr) as usize];
+        for (i, word) in words.iter().enumerate() {
+            data[i * 8..i * 8 + 8].copy_from_slice(&word.to_le_bytes());
+        }
+        let start = (strings_base - argc_addr) as usize;
+        data[start..start + strings.len()].copy_from_slice(&strings);
+
+        Ok(Self {
+            sp,
+            argv: argc_addr + 8,
+            envp: argc_addr + 8 * (args.len() as u64 + 2),
+            data,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TOP: u64 = 0x10_0000;
+
+    fn word(stack: &InitialStack, addr: u64) -> u64 {
+        let offset = (addr - stack.data_addr()) as usize;
+        u64::from_le_bytes(stack.data[offset..offset + 8].try_into().unwrap())
+    }
+
+    fn string(stack: &InitialStack, addr: u64) -> String {
+        let offset = (addr - stack.data_addr()) as usize;
+        let bytes = &stack.data[offset..];
+        let len = bytes.iter().position(|&b| b == 0).unwrap();
+        String::from_utf8(bytes[..len].to_vec()).unwrap()
+    }
+
+    #[test]
+    fn test_initial_stack() {
+        let args = ["/bin/echo", "hello", "world"].map(String::from);
+        let env = ["HOME=/root".to_string()];
+        let stack = InitialStack::build(TOP, &args, &env, 0x4000, 0x1000).unwrap();
+
+        assert_eq!(stack.sp % 16, 0);
+        assert_eq!(word(&stack, stack.sp + 16), 3);
+        assert_eq!(stack.argv, stack.sp + 24);
+        let argv: Vec<_> = (0..3)
+            .map(|i| string(&stack, word(&stack, stack.argv + i * 8)))
+            .collect();
+        assert_eq!(argv, args);
+        assert_eq!(word(&stack, stack.argv + 24), 0);
+        assert_eq!(string(&stack, word(&stack, stack.envp)), "HOME=/root");
+        assert_eq!(word(&stack, stack.envp + 8), 0);
+
+        let auxv = stack.envp + 16;
+        assert_eq!(
+            [word(&stack, auxv), word(&stack, auxv + 8)],
+            [AT_PAGESZ, 0x1000]
+        );
+        assert_eq!(
+            [word(&stack, auxv + 16), word(&stack, auxv + 24)],
+            [AT_ENTRY, 0x4000]
+        );
+        assert_eq!(word(&stack, auxv + 32), AT_RANDOM);
+        let random = word(&stack, auxv + 40);
+        let offset = (random - stack.data_addr()) as usize;
+        assert_eq!(stack.data[offset..offset + 16], RANDOM_BYTES);
+        assert_eq!(word(&stack, auxv + 48), AT_EXECFN);
+        assert_eq!(string(&stack, word(&stack, auxv + 56)), "/bin/echo");
+        assert_eq!(word(&stack, auxv + 64), AT_NULL);
+        assert_eq!(stack.data_addr() + stack.data.len() as u64, TOP);
+    }
+
+    #[test]
+    fn test_initial_stack_errors() {
+        let huge = ["x".repeat(STACK_SIZE as usize)];
+        assert!(InitialStack::build(TOP, &huge, &[], 0, 0x1000).is_err());
+        let nul = ["a\0b".to_string()];
+        assert!(InitialStack::build(TOP, &[], &nul, 0, 0x1000).is_err());
+    }
+}
@@ -0,0 +1,464 @@
+//! Python bindings for scripting experiments and teaching
+//!
+//! The `rust_ia64` Python module wraps an [`Emulator`]: load a program,
+//! step or run it to a breakpoint, read and write its registers and memory,
+//! and hand system calls and MMIO windows to Python callables; `assemble`
+//! encodes bundles from instruction text. Build it as an extension module
+//! with maturin, or directly:
+//!
+//! ```text
+//! cargo build --release --features python
+//! cp target/release/librust_ia64.so rust_ia64.so
+//! ```
+//!
+//! ```python
+//! import numpy, rust_ia64
+//!
+//! emu = rust_ia64.Emulator()
+//! emu.load_elf(open("hello", "rb").read())
+//! emu.setup_process(["hello"])
+//! emu.add_breakpoint(0x4000_0040)
+//! stop = emu.run()
+//! print(stop.kind, hex(emu.read_register("ip")))
+//! regs = numpy.frombuffer(emu.general_registers(), dtype="<u8")
+//! ```
+//!
+//! Memory and the general registers come back as `bytes` copied out of the
+//! machine, which `numpy.frombuffer` wraps as read-only arrays without a
+//! second copy; they do not follow later changes to the machine. Writes
+//! take any object with the buffer protocol. Registers are named as the debugger prints them:
+//! `ip`, `r8`, `f2`, `b0`, `pr`, `ar.bsp`.
+//!
+//! A Python exception raised by a system call or device callback aborts the
+//! bundle that triggered it and is raised again from `step` or `run`. An
+//! `Emulator` object stays on the thread that created it.
+
+use crate::asm::BundleBuilder;
+use crate::cpu::syscall::SyscallNumber;
+use crate::device::Device;
+use crate::emulator::{Emulator, StopReason};
+use crate::golden::{self, Register};
+use crate::memory::Permissions;
+use crate::EmulatorError;
+use pyo3::buffer::PyBuffer;
+use pyo3::exceptions::{PyRuntimeError, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::PyBytes;
+use std::collections::BTreeSet;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+/// Bundles run between checks for a pending KeyboardInterrupt
+const SIGNAL_CHECK_INTERVAL: u64 = 4096;
+
+/// Most bytes of guest memory a single read returns
+const MAX_READ: usize = 16 << 20;
+
+/// First Python exception raised by a callback since the last bundle
+type PendingError = Arc<Mutex<Option<PyErr>>>;
+
+impl From<EmulatorError> for PyErr {
+    fn from(error: EmulatorError) -> Self {
+        PyRuntimeError::new_err(error.to_string())
+    }
+}
+
+/// Record `error` unless an earlier one is still pending
+fn defer(pending: &PendingError, error: PyErr) {
+    pending.lock().unwrap().get_or_insert(error);
+}
+
+/// Stop of the machine
+#[pyclass(name = "Stop", frozen, get_all, skip_from_py_object)]
+#[derive(Debug, Clone)]
+pub struct PyStop {
+    /// Why the machine stopped: `exited`, `break`, `panic`, `livelock`,
+    /// `data_match`, `watchdog_expired` or `breakpoint`
+    kind: &'static str,
+    /// Exit status, break immediate or matched value, by kind
+    code: i64,
+    /// Breakpoint or data match address
+    addr: u64,
+}
+
+#[pymethods]
+impl PyStop {
+    fn __repr__(&self) -> String {
+        format!(
+            "Stop(kind={:?}, code={}, addr={:#x})",
+            self.kind, self.code, self.addr
+        )
+    }
+}
+
+impl From<StopReason> for PyStop {
+    fn from(reason: StopReason) -> Self {
+        let (kind, code, addr) = match reason {
+            StopReason::Exited(status) => ("exited", status as i64, 0),
+            StopReason::Break(imm) => ("break", imm as i64, 0),
+            StopReason::Panic => ("panic", 0, 0),
+            StopReason::Livelock => ("livelock", 0, 0),
+            StopReason::DataMatch { addr, value } => ("data_match", value as i64, addr),
+            StopReason::WatchdogExpired => ("watchdog_expired", 0, 0),
+        };
+        Self { kind, code, addr }
+    }
+}
+
+/// Device whose MMIO window is served by a Python object's `read(offset,
+/// size)` and `write(offset, data)` methods
+struct PyDevice {
+    /// Name used in the memory map
+    name: String,
+    /// Size of the window in bytes
+    size: u64,
+    /// The Python object
+    object: Py<PyAny>,
+    /// Where a raised exception goes
+    pending: PendingError,
+}
+
+impl fmt::Debug for PyDevice {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PyDevice")
+            .field("name", &self.name)
+            .field("size", &self.size)
+            .finish()
+    }
+}
+
+impl Device for PyDevice {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn size(&self) -> u64 {
+        self.size
+    }
+
+    fn read(&mut self, offset: u64, data: &mut [u8]) {
+        // Bytes the object does not return read as zero
+        data.fill(0);
+        Python::attach(|py| {
+            let result = self
+                .object
+                .call_method1(py, "read", (offset, data.len()))
+                .and_then(|value| value.extract::<Vec<u8>>(py));
+            match result {
+                Ok(value) => {
+                    let len = value.len().min(data.len());
+                    data[..len].copy_from_slice(&value[..len]);
+                }
+                Err(error) => defer(&self.pending, error),
+            }
+        });
+    }
+
+    fn write(&mut self, offset: u64, data: &[u8]) {
+        Python::attach(|py| {
+            let data = PyBytes::new(py, data);
+            if let Err(error) = self.object.call_method1(py, "write", (offset, data)) {
+                defer(&self.pending, error);
+            }
+        });
+    }
+}
+
+/// IA-64 machine driven from Python
+#[pyclass(name = "Emulator", unsendable)]
+pub struct PyEmulator {
+    /// The machine
+    emulator: Emulator,
+    /// Addresses `run` stops at before executing the bundle there
+    breakpoints: BTreeSet<u64>,
+    /// Exception raised by a callback during the current bundle
+    pending: PendingError,
+}
+
+impl PyEmulator {
+    /// Execute one bundle, raising an exception a callback raised during it
+    fn step_bundle(&mut self) -> PyResult<Option<StopReason>> {
+        let result = self.emulator.step();
+        if let Some(error) = self.pending.lock().unwrap().take() {
+            return Err(error);
+        }
+        Ok(result?)
+    }
+}
+
+/// Register named `name`
+fn register(name: &str) -> PyResult<Register> {
+    name.parse::<Register>()
+        .map_err(|error| PyValueError::new_err(error.to_string()))
+}
+
+#[pymethods]
+impl PyEmulator {
+    /// Machine with empty memory
+    #[new]
+    fn new() -> Self {
+        Self {
+            emulator: Emulator::new(),
+            breakpoints: BTreeSet::new(),
+            pending: PendingError::default(),
+        }
+    }
+
+    /// Machine built from a TOML machine configuration file
+    #[staticmethod]
+    fn from_config(path: &str) -> PyResult<Self> {
+        Ok(Self {
+            emulator: Emulator::from_config(path)?,
+            ..Self::new()
+        })
+    }
+
+    /// Load an ELF executable and start execution at its entry point,
+    /// relocating a position-independent image to `base`; returns the entry
+    /// point
+    #[pyo3(signature = (image, base = 0))]
+    fn load_elf(&mut self, image: &[u8], base: u64) -> PyResult<u64> {
+        Ok(self.emulator.load_elf(image, base)?.entry)
+    }
+
+    /// Load a flat binary image at `base` and start execution at `entry`
+    fn load_flat_image(&mut self, base: u64, image: &[u8], entry: u64) -> PyResult<()> {
+        Ok(self.emulator.load_flat_image(base, image, entry)?)
+    }
+
+    /// Map the stack and place the guest's arguments and environment on
+    /// it, after loading an image
+    #[pyo3(signature = (args, env = Vec::new()))]
+    fn setup_process(&mut self, args: Vec<String>, env: Vec<String>) -> PyResult<()> {
+        Ok(self.emulator.setup_process(&args, &env)?)
+    }
+
+    /// Map `size` bytes of zeroed memory at `base` with `permissions`, as
+    /// written in machine configuration files: `r`, `rw`, `rx` or `rwx`
+    #[pyo3(signature = (base, size, permissions = "rw"))]
+    fn map(&mut self, base: u64, size: u64, permissions: &str) -> PyResult<()> {
+        let permissions: Permissions = serde_json::from_value(permissions.into())
+            .map_err(|_| PyValueError::new_err(format!("Invalid permissions: {}", permissions)))?;
+        Ok(self.emulator.memory.map(base, size, permissions)?)
+    }
+
+    /// Execute the bundle at the instruction pointer, returning the stop it
+    /// caused or None
+    fn step(&mut self) -> PyResult<Option<PyStop>> {
+        Ok(self.step_bundle()?.map(PyStop::from))
+    }
+
+    /// Run until the machine stops, reaches a breakpoint or, if
+    /// `max_bundles` is given, has executed that many bundles
+    ///
+    /// Returns the stop, or None if the limit was reached first. The bundle
+    /// at the instruction pointer executes even if it has a breakpoint, so
+    /// calling `run` again continues past it.
+    #[pyo3(signature = (max_bundles = None))]
+    fn run(&mut self, py: Python<'_>, max_bundles: Option<u64>) -> PyResult<Option<PyStop>> {
+        let mut executed = 0;
+        loop {
+            if max_bundles == Some(executed) {
+                return Ok(None);
+            }
+            let ip = self.emulator.cpu.ip;
+            if executed > 0 && self.breakpoints.contains(&ip) {
+                return Ok(Some(PyStop {
+                    kind: "breakpoint",
+                    code: 0,
+                    addr: ip,
+                }));
+            }
+            if let Some(reason) = self.step_bundle()? {
+                return Ok(Some(reason.into()));
+            }
+            executed += 1;
+            if executed % SIGNAL_CHECK_INTERVAL == 0 {
+                py.check_signals()?;
+            }
+        }
+    }
+
+    /// Stop `run` before the bundle at `addr` executes
+    fn add_breakpoint(&mut self, addr: u64) {
+        self.breakpoints.insert(addr);
+    }
+
+    /// Remove the breakpoint at `addr`, returning whether there was one
+    fn remove_breakpoint(&mut self, addr: u64) -> bool {
+        self.breakpoints.remove(&addr)
+    }
+
+    /// Addresses with breakpoints, in order
+    fn breakpoints(&self) -> Vec<u64> {
+        self.breakpoints.iter().copied().collect()
+    }
+
+    /// Value of the register `name`
+    ///
+    /// Floating-point registers read as the bits of their double value, and
+    /// `pr` as the 64 predicates.
+    fn read_register(&self, name: &str) -> PyResult<u64> {
+        Ok(golden::register(&self.emulator.cpu, register(name)?)?)
+    }
+
+    /// Write `value` to the register `name`
+    fn write_register(&mut self, name: &str, value: u64) -> PyResult<()> {
+        Ok(golden::set_register(
+            &mut self.emulator.cpu,
+            register(name)?,
+            value,
+        )?)
+    }
+
+    /// The 128 general registers as little-endian 64-bit words
+    fn general_registers<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyBytes>> {
+        let mut data = Vec::with_capacity(128 * 8);
+        for index in 0..128 {
+            data.extend(self.emulator.cpu.get_gr(index)?.to_le_bytes());
+        }
+        Ok(PyBytes::new(py, &data))
+    }
+
+    /// `size` bytes of guest memory at `addr`, at most 16 MiB
+    ///
+    /// Reads leave the caches and devices alone.
+    fn read_memory<'py>(
+        &self,
+        py: Python<'py>,
+        addr: u64,
+        size: usize,
+    ) -> PyResult<Bound<'py, PyBytes>> {
+        if size > MAX_READ {
+            return Err(PyValueError::new_err(format!(
+                "Read of {} bytes exceeds the limit of {}",
+                size, MAX_READ
+            )));
+        }
+        PyBytes::new_with(py, size, |data| {
+            Ok(self.emulator.memory.peek_bytes(addr, data)?)
+        })
+    }
+
+    /// Write the contents of a bytes-like object to guest memory at `addr`
+    fn write_memory(&mut self, py: Python<'_>, addr: u64, data: PyBuffer<u8>) -> PyResult<()> {
+        let data = data.to_vec(py)?;
+        Ok(self.emulator.memory.write_block(addr, &data)?)
+    }
+
+    /// Handle system call `number` with `callback`
+    ///
+    /// The callback is passed the eight parameters as a list and returns
+    /// the result, or None for 0. A negative result is reported to the
+    /// guest as that errno.
+    fn on_syscall(&mut self, number: u64, callback: Py<PyAny>) -> PyResult<()> {
+        let number = SyscallNumber::try_from(number)?;
+        let pending = Arc::clone(&self.pending);
+        self.emulator
+            .cpu
+            .syscall_mgr
+            .register_handler(number, move |_, context| {
+                let result = Python::attach(|py| {
+                    callback
+                        .call1(py, (context.params,))?
+                        .extract::<Option<i64>>(py)
+                });
+                match result {
+                    Ok(value) => {
+                        let value = value.unwrap_or(0);
+                        if value < 0 {
+                            context.set_error(value.unsigned_abs());
+                        } else {
+                            context.set_return(0, value as u64);
+                        }
+                        Ok(())
+                    }
+                    Err(error) => {
+                        defer(&pending, error);
+                        Err(EmulatorError::ExecutionError(format!(
+                            "Python handler for {} raised an exception",
+                            number.name()
+                        )))
+                    }
+                }
+            });
+        Ok(())
+    }
+
+    /// Attach an MMIO window of `size` bytes at page-aligned `base`, served
+    /// by `device`'s `read(offset, size) -> bytes` and `write(offset,
+    /// data)` methods; returns the device id
+    #[pyo3(signature = (base, size, device, name = "python"))]
+    fn attach_device(
+        &mut self,
+        base: u64,
+        size: u64,
+        device: Py<PyAny>,
+        name: &str,
+    ) -> PyResult<u32> {
+        let device = PyDevice {
+            name: name.to_string(),
+            size,
+            object: device,
+            pending: Arc::clone(&self.pending),
+        };
+        Ok(self.emulator.attach(base, Box::new(device))?.0)
+    }
+
+    /// Instruction pointer
+    #[getter]
+    fn ip(&self) -> u64 {
+        self.emulator.cpu.ip
+    }
+
+    #[setter]
+    fn set_ip(&mut self, ip: u64) {
+        self.emulator.cpu.ip = ip;
+    }
+
+    /// Instructions retired since the machine was created
+    #[getter]
+    fn retired(&self) -> u64 {
+        self.emulator.retired()
+    }
+
+    /// Bundles retired since the machine was created
+    #[getter]
+    fn bundles(&self) -> u64 {
+        self.emulator.bundles()
+    }
+
+    /// Cycles taken since the machine was created
+    #[getter]
+    fn cycles(&self) -> u64 {
+        self.emulator.cycles()
+    }
+}
+
+/// Encode `bundles`, each a list of instructions, as consecutive bundles
+/// from `ip`
+#[pyfunction]
+#[pyo3(signature = (bundles, ip = 0))]
+fn assemble<'py>(
+    py: Python<'py>,
+    bundles: Vec<Vec<String>>,
+    ip: u64,
+) -> PyResult<Bound<'py, PyBytes>> {
+    let mut data = Vec::with_capacity(bundles.len() * 16);
+    for (i, bundle) in bundles.iter().enumerate() {
+        let builder = bundle
+            .iter()
+            .fold(BundleBuilder::new(), |builder, insn| builder.insn(insn));
+        data.extend(builder.at(ip + 16 * i as u64).build()?);
+    }
+    Ok(PyBytes::new(py, &data))
+}
+
+/// The `rust_ia64` Python module
+#[pymodule]
+fn rust_ia64(module: &Bound<'_, PyModule>) -> PyResult<()> {
+    module.add_function(wrap_pyfunction!(assemble, module)?)?;
+    module.add_class::<PyEmulator>()?;
+    module.add_class::<PyStop>()?;
+    Ok(())
+}
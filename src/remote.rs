@@ -0,0 +1,865 @@
+//! Remote control over JSON-RPC
+//!
+//! A test controller orchestrating many machines runs each one behind a
+//! [`RemoteServer`] and drives it over a Unix domain socket with JSON-RPC
+//! 2.0, one request or response per line. [`RemoteClient`] is an async
+//! client for the tokio runtime.
+//!
+//! Methods, with their parameters:
+//!
+//! - `run` (`bundles`, optional): resume the guest; the call returns at
+//!   once and the guest runs until it stops, is paused or has executed
+//!   `bundles` bundles
+//! - `pause`, `status`: stop the guest or just report; both return the
+//!   [`RemoteStatus`]
+//! - `wait`: answer with the [`RemoteStatus`] once the guest stops
+//! - `step` (`count`, default 1): execute bundles while paused
+//! - `read_registers` (`registers`, a list of names; all when omitted) and
+//!   `write_registers` (`registers`, an object of names and values), with
+//!   register names as the debugger prints them (`r8`, `b0`, `ar.bsp`)
+//! - `read_memory` (`address`, `length`) and `write_memory` (`address`,
+//!   `data`), with the bytes as a hex string; reads leave the caches and
+//!   devices alone
+//! - `save_snapshot` and `load_snapshot` (`path`), in the format of
+//!   [`Snapshot::save`]
+//! - `attach_device` (`type`, `base` and the device's settings; the only
+//!   type is `flash`, with `size`, `block_size`, `name` and `file`) and
+//!   `detach_device` (`id`)
+//! - `shutdown`: stop serving
+//!
+//! Numbers may be given as JSON numbers or as strings such as `"0x4000"`.
+//! Registers and memory can be accessed while the guest runs: requests are
+//! handled between bundles.
+
+use crate::debugger::{describe_stop, parse_number};
+use crate::device::flash::Flash;
+use crate::device::{Device, DeviceId};
+use crate::emulator::{Emulator, StopReason};
+use crate::golden::{self, Register};
+use crate::repro::Snapshot;
+use crate::EmulatorError;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use std::io::{BufRead, BufReader, Write};
+use std::net::Shutdown;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::thread;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt};
+
+/// Bundles executed between checks for requests while the guest runs
+const SLICE: u64 = 10_000;
+
+/// Largest `read_memory` length
+const MAX_READ: u64 = 16 << 20;
+
+/// JSON-RPC error code of a malformed line
+const PARSE_ERROR: i64 = -32700;
+/// JSON-RPC error code of a request without a method
+const INVALID_REQUEST: i64 = -32600;
+/// JSON-RPC error code of an unknown method
+const METHOD_NOT_FOUND: i64 = -32601;
+/// JSON-RPC error code of missing or malformed parameters
+const INVALID_PARAMS: i64 = -32602;
+/// JSON-RPC error code of a failed emulator operation
+const EMULATOR_ERROR: i64 = -32000;
+
+/// Why the guest stopped running
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "reason", rename_all = "snake_case")]
+pub enum RemoteStop {
+    /// Guest called exit
+    Exited {
+        /// Exit status
+        code: i32,
+    },
+    /// Guest executed a break instruction that is not a system call
+    Break {
+        /// Break immediate
+        immediate: u64,
+    },
+    /// Guest reached a panic hook
+    Panic {
+        /// Panic report
+        description: String,
+    },
+    /// Guest looped without making progress
+    Livelock {
+        /// Livelock report
+        description: String,
+    },
+    /// A data debug register matched a value loaded or stored
+    DataMatch {
+        /// Address accessed
+        addr: u64,
+        /// Value that matched
+        value: u64,
+    },
+    /// A watchdog set to stop the machine expired
+    WatchdogExpired,
+    /// Execution failed
+    Fault {
+        /// Error message
+        error: String,
+    },
+    /// The bundle count given to `run` was reached
+    Limit,
+}
+
+/// State reported by `status`, `pause`, `wait` and `step`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RemoteStatus {
+    /// Whether the guest is running
+    pub running: bool,
+    /// Instruction pointer
+    pub ip: u64,
+    /// Why the guest last stopped on its own, if it has since the last
+    /// `run` or `step`
+    pub stop: Option<RemoteStop>,
+}
+
+/// Failed request
+struct RpcError {
+    /// JSON-RPC error code
+    code: i64,
+    /// Message
+    message: String,
+}
+
+impl RpcError {
+    /// Missing or malformed parameter
+    fn params(message: String) -> Self {
+        Self {
+            code: INVALID_PARAMS,
+            message,
+        }
+    }
+}
+
+impl From<EmulatorError> for RpcError {
+    fn from(error: EmulatorError) -> Self {
+        Self {
+            code: EMULATOR_ERROR,
+            message: error.to_string(),
+        }
+    }
+}
+
+/// Convert a socket error
+fn io_error(error: std::io::Error) -> EmulatorError {
+    EmulatorError::ExecutionError(format!("Remote control socket: {}", error))
+}
+
+/// JSON-RPC control server on a Unix domain socket
+///
+/// The socket file is removed when the server is dropped.
+#[derive(Debug)]
+pub struct RemoteServer {
+    /// Listening socket
+    listener: UnixListener,
+    /// Socket path
+    path: PathBuf,
+}
+
+impl RemoteServer {
+    /// Listen on a new socket at `path`
+    pub fn bind(path: impl AsRef<Path>) -> Result<Self, EmulatorError> {
+        let path = path.as_ref().to_path_buf();
+        let listener = UnixListener::bind(&path).map_err(io_error)?;
+        Ok(Self { listener, path })
+    }
+
+    /// Socket path
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Serve clients one at a time until one sends `shutdown`
+    ///
+    /// A running guest is paused when its client disconnects.
+    pub fn serve(&self, emulator: &mut Emulator) -> Result<(), EmulatorError> {
+        for stream in self.listener.incoming() {
+            let mut connection = Connection::new(emulator, stream.map_err(io_error)?)?;
+            connection.serve()?;
+            if connection.shutdown {
+                break;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Drop for RemoteServer {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+/// One client's session
+struct Connection<'a> {
+    /// Machine under control
+    emulator: &'a mut Emulator,
+    /// Socket, for responses
+    stream: UnixStream,
+    /// Request lines read by the reader thread
+    requests: Receiver<String>,
+    /// Whether the guest runs between requests
+    running: bool,
+    /// Bundles left before the run stops, if limited
+    budget: Option<u64>,
+    /// Why the guest last stopped on its own
+    stop: Option<RemoteStop>,
+    /// Ids of `wait` requests to answer when the guest stops
+    waiting: Vec<Value>,
+    /// Set by `shutdown`
+    shutdown: bool,
+}
+
+impl<'a> Connection<'a> {
+    /// Start a session, reading requests on a separate thread so they can
+    /// be picked up while the guest runs
+    fn new(emulator: &'a mut Emulator, stream: UnixStream) -> Result<Self, EmulatorError> {
+        let reader = BufReader::new(stream.try_clone().map_err(io_error)?);
+        let (sender, requests) = mpsc::channel();
+        thread::spawn(move || {
+            for line in reader.lines() {
+                let Ok(line) = line else { break };
+                if sender.send(line).is_err() {
+                    break;
+                }
+            }
+        });
+        Ok(Self {
+            emulator,
+            stream,
+            requests,
+            running: false,
+            budget: None,
+            stop: None,
+            waiting: Vec::new(),
+            shutdown: false,
+        })
+    }
+
+    /// Handle requests until the client disconnects or sends `shutdown`
+    fn serve(&mut self) -> Result<(), EmulatorError> {
+        while !self.shutdown {
+            let line = if self.running {
+                match self.requests.try_recv() {
+                    Ok(line) => line,
+                    Err(TryRecvError::Empty) => {
+                        self.run_slice()?;
+                        continue;
+                    }
+                    Err(TryRecvError::Disconnected) => break,
+                }
+            } else {
+                match self.requests.recv() {
+                    Ok(line) => line,
+                    Err(_) => break,
+                }
+            };
+            if let Some(response) = self.handle(&line) {
+                self.send(&response)?;
+            }
+        }
+        self.running = false;
+        // Ends the reader thread if the client is still connected
+        let _ = self.stream.shutdown(Shutdown::Both);
+        Ok(())
+    }
+
+    /// Write a response line
+    fn send(&mut self, response: &Value) -> Result<(), EmulatorError> {
+        let mut line = response.to_string();
+        line.push('\n');
+        self.stream.write_all(line.as_bytes()).map_err(io_error)
+    }
+
+    /// Run the guest for up to a slice of bundles
+    fn run_slice(&mut self) -> Result<(), EmulatorError> {
+        let count = self.budget.map_or(SLICE, |budget| budget.min(SLICE));
+        let stop = self.execute(count);
+        if let Some(budget) = &mut self.budget {
+            *budget -= count.min(*budget);
+        }
+        if stop.is_some() || self.budget == Some(0) {
+            self.running = false;
+            self.stop = Some(stop.unwrap_or(RemoteStop::Limit));
+            let status = serde_json::to_value(self.status()).unwrap();
+            for id in std::mem::take(&mut self.waiting) {
+                self.send(&json!({"jsonrpc": "2.0", "id": id, "result": status}))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Execute up to `count` bundles, returning why the guest stopped if it
+    /// did
+    fn execute(&mut self, count: u64) -> Option<RemoteStop> {
+        for _ in 0..count {
+            let stop = match self.emulator.step() {
+                Ok(None) => continue,
+                Ok(Some(StopReason::Exited(code))) => RemoteStop::Exited { code },
+                Ok(Some(StopReason::Break(immediate))) => RemoteStop::Break { immediate },
+                Ok(Some(reason @ StopReason::Panic)) => RemoteStop::Panic {
+                    description: describe_stop(self.emulator, reason),
+                },
+                Ok(Some(reason @ StopReason::Livelock)) => RemoteStop::Livelock {
+                    description: describe_stop(self.emulator, reason),
+                },
+                Ok(Some(StopReason::DataMatch { addr, value })) => {
+                    RemoteStop::DataMatch { addr, value }
+                }
+                Ok(Some(StopReason::WatchdogExpired)) => RemoteStop::WatchdogExpired,
+                Err(error) => RemoteStop::Fault {
+                    error: error.to_string(),
+                },
+            };
+            return Some(stop);
+        }
+        None
+    }
+
+    /// Current status
+    fn status(&self) -> RemoteStatus {
+        RemoteStatus {
+            running: self.running,
+            ip: self.emulator.cpu.ip,
+            stop: self.stop.clone(),
+        }
+    }
+
+    /// Handle a request line, returning the response unless there is none
+    /// yet or the request was a notification
+    fn handle(&mut self, line: &str) -> Option<Value> {
+        let request: Value = match serde_json::from_str(line) {
+            Ok(request) => request,
+            Err(e) => {
+                let error = RpcError {
+                    code: PARSE_ERROR,
+                    message: e.to_string(),
+                };
+                return Some(response(Value::Null, Err(error)));
+            }
+        };
+        let id = request.get("id").cloned();
+        let Some(method) = request.get("method").and_then(Value::as_str) else {
+            let error = RpcError {
+                code: INVALID_REQUEST,
+                message: "Request has no method".to_string(),
+            };
+            return Some(response(id.unwrap_or(Value::Null), Err(error)));
+        };
+        let params = request.get("params").cloned().unwrap_or(Value::Null);
+
+        if method == "wait" && self.running {
+            // Answered when the guest stops
+            if let Some(id) = id {
+                self.waiting.push(id);
+            }
+            return None;
+        }
+        let result = self.call(method, &params);
+        id.map(|id| response(id, result))
+    }
+
+    /// Execute a method
+    fn call(&mut self, method: &str, params: &Value) -> Result<Value, RpcError> {
+        let status = |connection: &Self| Ok(serde_json::to_value(connection.status()).unwrap());
+        match method {
+            "run" => {
+                self.budget = optional_u64(params, "bundles")?;
+                self.running = self.budget != Some(0);
+                self.stop = None;
+                status(self)
+            }
+            "pause" => {
+                self.running = false;
+                status(self)
+            }
+            "status" | "wait" => status(self),
+            "step" => {
+                if self.running {
+                    return Err(EmulatorError::ExecutionError(
+                        "Guest is running; pause it first".to_string(),
+                    )
+                    .into());
+                }
+                let count = optional_u64(params, "count")?.unwrap_or(1);
+                self.stop = self.execute(count);
+                status(self)
+            }
+            "read_registers" => self.read_registers(params),
+            "write_registers" => {
+                let Some(registers) = params.get("registers").and_then(Value::as_object) else {
+                    return Err(RpcError::params("Missing registers".to_string()));
+                };
+                for (name, value) in registers {
+                    let register = name.parse::<Register>()?;
+                    let value = number(value)
+                        .ok_or_else(|| RpcError::params(format!("Invalid value for {}", name)))?;
+                    golden::set_register(&mut self.emulator.cpu, register, value)?;
+                }
+                Ok(Value::Null)
+            }
+            "read_memory" => {
+                let address = required_u64(params, "address")?;
+                let length = required_u64(params, "length")?;
+                if length > MAX_READ {
+                    return Err(RpcError::params(format!(
+                        "Length {:#x} exceeds the limit of {:#x}",
+                        length, MAX_READ
+                    )));
+                }
+                let mut data = vec![0; length as usize];
+                self.emulator.memory.peek_bytes(address, &mut data)?;
+                Ok(json!({ "data": to_hex(&data) }))
+            }
+            "write_memory" => {
+                let address = required_u64(params, "address")?;
+                let data = params
+                    .get("data")
+                    .and_then(Value::as_str)
+                    .and_then(from_hex)
+                    .ok_or_else(|| RpcError::params("Missing or invalid data".to_string()))?;
+                self.emulator.memory.write_block(address, &data)?;
+                Ok(Value::Null)
+            }
+            "save_snapshot" => {
+                Snapshot::capture(self.emulator).save(path(params)?)?;
+                Ok(Value::Null)
+            }
+            "load_snapshot" => {
+                let snapshot = Snapshot::load(path(params)?)?;
+                let bases: Vec<u64> = self
+                    .emulator
+                    .memory
+                    .regions()
+                    .map(|(base, ..)| base)
+                    .collect();
+                for base in bases {
+                    self.emulator.memory.unmap(base)?;
+                }
+                snapshot.restore(self.emulator)?;
+                self.stop = None;
+                Ok(Value::Null)
+            }
+            "attach_device" => {
+                let base = required_u64(params, "base")?;
+                let device = device(params)?;
+                let id = self.emulator.attach(base, device)?;
+                Ok(json!({ "id": id.0 }))
+            }
+            "detach_device" => {
+                let id = required_u64(params, "id")?;
+                let id = u32::try_from(id)
+                    .map_err(|_| RpcError::params(format!("Invalid device id {}", id)))?;
+                self.emulator.detach(DeviceId(id))?;
+                Ok(Value::Null)
+            }
+            "shutdown" => {
+                self.shutdown = true;
+                Ok(Value::Null)
+            }
+            _ => Err(RpcError {
+                code: METHOD_NOT_FOUND,
+                message: format!("Unknown method: {}", method),
+            }),
+        }
+    }
+
+    /// Read the named registers, or all of them
+    fn read_registers(&self, params: &Value) -> Result<Value, RpcError> {
+        let cpu = &self.emulator.cpu;
+        let mut values = Map::new();
+        match params.get("registers") {
+            None => {
+                for (register, value) in golden::registers(cpu) {
+                    values.insert(register.to_string(), value.into());
+                }
+            }
+            Some(Value::Array(names)) => {
+                for name in names {
+                    let name = name.as_str().ok_or_else(|| {
+                        RpcError::params("Register names must be strings".to_string())
+                    })?;
+                    let value = golden::register(cpu, name.parse()?)?;
+                    values.insert(name.to_string(), value.into());
+                }
+            }
+            Some(_) => return Err(RpcError::params("registers must be a list".to_string())),
+        }
+        Ok(Value::Object(values))
+    }
+}
+
+/// Build a response
+fn response(id: Value, result: Result<Value, RpcError>) -> Value {
+    match result {
+        Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+        Err(error) => json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "error": { "code": error.code, "message": error.message },
+        }),
+    }
+}
+
+/// Number given as a JSON number or a decimal or `0x` hex string
+fn number(value: &Value) -> Option<u64> {
+    match value {
+        Value::Number(number) => number.as_u64(),
+        Value::String(text) => parse_number(text).ok(),
+        _ => None,
+    }
+}
+
+/// Optional numeric parameter
+fn optional_u64(params: &Value, name: &str) -> Result<Option<u64>, RpcError> {
+    match params.get(name) {
+        None | Some(Value::Null) => Ok(None),
+        Some(value) => number(value)
+            .map(Some)
+            .ok_or_else(|| RpcError::params(format!("Invalid {}", name))),
+    }
+}
+
+/// Required numeric parameter
+fn required_u64(params: &Value, name: &str) -> Result<u64, RpcError> {
+    optional_u64(params, name)?.ok_or_else(|| RpcError::params(format!("Missing {}", name)))
+}
+
+/// `path` parameter
+fn path(params: &Value) -> Result<&str, RpcError> {
+    params
+        .get("path")
+        .and_then(Value::as_str)
+        .ok_or_else(|| RpcError::params("Missing path".to_string()))
+}
+
+/// Build the device described by `attach_device` parameters
+fn device(params: &Value) -> Result<Box<dyn Device>, RpcError> {
+    match params.get("type").and_then(Value::as_str) {
+        Some("flash") => {
+            let size = required_u64(params, "size")?;
+            let block_size = required_u64(params, "block_size")?;
+            let name = params
+                .get("name")
+                .and_then(Value::as_str)
+                .unwrap_or("flash");
+            let flash = match params.get("file").and_then(Value::as_str) {
+                Some(file) => Flash::open(name, file, Some(size), block_size)?,
+                None => Flash::new(name, size, block_size)?,
+            };
+            Ok(Box::new(flash))
+        }
+        Some(kind) => Err(RpcError::params(format!("Unknown device type: {}", kind))),
+        None => Err(RpcError::params("Missing device type".to_string())),
+    }
+}
+
+/// Bytes as a hex string
+fn to_hex(data: &[u8]) -> String {
+    data.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Bytes of a hex string
+fn from_hex(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..text.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(text.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+/// Async client of a [`RemoteServer`]
+///
+/// Calls are made one at a time; each waits for its response.
+#[derive(Debug)]
+pub struct RemoteClient {
+    /// Responses from the server
+    reader: tokio::io::BufReader<tokio::net::unix::OwnedReadHalf>,
+    /// Requests to the server
+    writer: tokio::net::unix::OwnedWriteHalf,
+    /// Id of the next request
+    next_id: u64,
+}
+
+impl RemoteClient {
+    /// Connect to the server listening at `path`
+    pub async fn connect(path: impl AsRef<Path>) -> Result<Self, EmulatorError> {
+        let stream = tokio::net::UnixStream::connect(path)
+            .await
+            .map_err(io_error)?;
+        let (reader, writer) = stream.into_split();
+        Ok(Self {
+            reader: tokio::io::BufReader::new(reader),
+            writer,
+            next_id: 1,
+        })
+    }
+
+    /// Call a method, returning its result
+    ///
+    /// A JSON-RPC error becomes an [`EmulatorError::ExecutionError`] with
+    /// the server's message.
+    pub async fn call(&mut self, method: &str, params: Value) -> Result<Value, EmulatorError> {
+        let id = self.next_id;
+        self.next_id += 1;
+        let request = json!({ "jsonrpc": "2.0", "id": id, "method": method, "params": params });
+        let mut line = request.to_string();
+        line.push('\n');
+        self.writer
+            .write_all(line.as_bytes())
+            .await
+            .map_err(io_error)?;
+
+        loop {
+            let mut line = String::new();
+            if self.reader.read_line(&mut line).await.map_err(io_error)? == 0 {
+                return Err(EmulatorError::ExecutionError(
+                    "Remote control server closed the connection".to_string(),
+                ));
+            }
+            let mut response: Value = serde_json::from_str(&line)
+                .map_err(|e| EmulatorError::ExecutionError(format!("Invalid response: {}", e)))?;
+            if response.get("id") != Some(&json!(id)) {
+                continue;
+            }
+            if let Some(error) = response.get("error") {
+                let message = error.get("message").and_then(Value::as_str).unwrap_or("");
+                return Err(EmulatorError::ExecutionError(format!(
+                    "{}: {}",
+                    method, message
+                )));
+            }
+            return Ok(response["result"].take());
+        }
+    }
+
+    /// Call a method that returns the status
+    async fn call_status(
+        &mut self,
+        method: &str,
+        params: Value,
+    ) -> Result<RemoteStatus, EmulatorError> {
+        let result = self.call(method, params).await?;
+        serde_json::from_value(result)
+            .map_err(|e| EmulatorError::ExecutionError(format!("Invalid status: {}", e)))
+    }
+
+    /// Resume the guest, for at most `bundles` bundles if given
+    pub async fn run(&mut self, bundles: Option<u64>) -> Result<RemoteStatus, EmulatorError> {
+        self.call_status("run", json!({ "bundles": bundles })).await
+    }
+
+    /// Pause the guest
+    pub async fn pause(&mut self) -> Result<RemoteStatus, EmulatorError> {
+        self.call_status("pause", Value::Null).await
+    }
+
+    /// Report whether the guest runs and where
+    pub async fn status(&mut self) -> Result<RemoteStatus, EmulatorError> {
+        self.call_status("status", Value::Null).await
+    }
+
+    /// Wait for the guest to stop
+    pub async fn wait(&mut self) -> Result<RemoteStatus, EmulatorError> {
+        self.call_status("wait", Value::Null).await
+    }
+
+    /// Execute `count` bundles while paused
+    pub async fn step(&mut self, count: u64) -> Result<RemoteStatus, EmulatorError> {
+        self.call_status("step", json!({ "count": count })).await
+    }
+
+    /// Read a register by name, e.g. `r8` or `ar.bsp`
+    pub async fn read_register(&mut self, name: &str) -> Result<u64, EmulatorError> {
+        let result = self
+            .call("read_registers", json!({ "registers": [name] }))
+            .await?;
+        result[name]
+            .as_u64()
+            .ok_or_else(|| EmulatorError::ExecutionError(format!("No value for {}", name)))
+    }
+
+    /// Write a register by name
+    pub async fn write_register(&mut self, name: &str, value: u64) -> Result<(), EmulatorError> {
+        let mut registers = Map::new();
+        registers.insert(name.to_string(), value.into());
+        self.call("write_registers", json!({ "registers": registers }))
+            .await
+            .map(drop)
+    }
+
+    /// Read guest memory
+    pub async fn read_memory(
+        &mut self,
+        address: u64,
+        length: u64,
+    ) -> Result<Vec<u8>, EmulatorError> {
+        let result = self
+            .call(
+                "read_memory",
+                json!({ "address": address, "length": length }),
+            )
+            .await?;
+        result["data"]
+            .as_str()
+            .and_then(from_hex)
+            .ok_or_else(|| EmulatorError::ExecutionError("Invalid memory data".to_string()))
+    }
+
+    /// Write guest memory
+    pub async fn write_memory(&mut self, address: u64, data: &[u8]) -> Result<(), EmulatorError> {
+        self.call(
+            "write_memory",
+            json!({ "address": address, "data": to_hex(data) }),
+        )
+        .await
+        .map(drop)
+    }
+
+    /// Save a snapshot of the guest to a file on the server's host
+    pub async fn save_snapshot(&mut self, path: &str) -> Result<(), EmulatorError> {
+        self.call("save_snapshot", json!({ "path": path }))
+            .await
+            .map(drop)
+    }
+
+    /// Replace the guest's memory and registers with a saved snapshot
+    pub async fn load_snapshot(&mut self, path: &str) -> Result<(), EmulatorError> {
+        self.call("load_snapshot", json!({ "path": path }))
+            .await
+            .map(drop)
+    }
+
+    /// Attach a flash device with no backing file
+    pub async fn attach_flash(
+        &mut self,
+        base: u64,
+        size: u64,
+        block_size: u64,
+    ) -> Result<DeviceId, EmulatorError> {
+        let params =
+            json!({ "type": "flash", "base": base, "size": size, "block_size": block_size });
+        let result = self.call("attach_device", params).await?;
+        result["id"]
+            .as_u64()
+            .and_then(|id| u32::try_from(id).ok())
+            .map(DeviceId)
+            .ok_or_else(|| EmulatorError::ExecutionError("Invalid device id".to_string()))
+    }
+
+    /// Detach a device
+    pub async fn detach(&mut self, id: DeviceId) -> Result<(), EmulatorError> {
+        self.call("detach_device", json!({ "id": id.0 }))
+            .await
+            .map(drop)
+    }
+
+    /// Stop the server
+    pub async fn shutdown(&mut self) -> Result<(), EmulatorError> {
+        self.call("shutdown", Value::Null).await.map(drop)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{encode_break_nop, encode_mii, nop};
+
+    const BASE: u64 = 0x10000;
+    const FLASH: u64 = 0x80000;
+
+    /// break with an immediate
+    fn brk(imm: u64) -> u64 {
+        encode_break_nop(0, 0x00, imm)
+    }
+
+    /// chk.s.m r4 branching back to its own bundle
+    fn spin() -> u64 {
+        (1 << 37) | (1 << 33) | (4 << 13)
+    }
+
+    #[test]
+    fn test_remote_session() {
+        let dir = std::env::temp_dir().join(format!("rust-ia64-remote-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let socket = dir.join("control.sock");
+        let snapshot = dir.join("snapshot.bin");
+
+        let server = RemoteServer::bind(&socket).unwrap();
+        let serving = thread::spawn(move || {
+            // The guest spins on r4's NaT bit until its code is rewritten
+            let mut emulator = Emulator::new();
+            let image = encode_mii([spin(), nop(), brk(0x77)]);
+            emulator.load_flat_image(BASE, &image, BASE).unwrap();
+            emulator.cpu.nat[4] = true;
+            server.serve(&mut emulator).unwrap();
+            emulator
+        });
+
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        runtime.block_on(async {
+            let mut client = RemoteClient::connect(&socket).await.unwrap();
+            assert!(!client.status().await.unwrap().running);
+            assert!(client.run(None).await.unwrap().running);
+            let paused = client.pause().await.unwrap();
+            assert_eq!(
+                (paused.running, paused.ip, paused.stop),
+                (false, BASE, None)
+            );
+
+            client.write_register("r8", 0x1234).await.unwrap();
+            assert_eq!(client.read_register("r8").await.unwrap(), 0x1234);
+            assert!(client.read_register("r999").await.is_err());
+            client
+                .save_snapshot(snapshot.to_str().unwrap())
+                .await
+                .unwrap();
+
+            // A limited run stops on its own
+            let status = client.run(Some(100)).await.unwrap();
+            assert!(status.running);
+            assert_eq!(client.wait().await.unwrap().stop, Some(RemoteStop::Limit));
+
+            // Rewriting the loop lets the guest reach the break
+            let code = encode_mii([nop(), nop(), brk(0x77)]);
+            client.write_memory(BASE, &code).await.unwrap();
+            assert_eq!(client.read_memory(BASE, 16).await.unwrap(), code);
+            client.run(None).await.unwrap();
+            let stopped = client.wait().await.unwrap();
+            assert_eq!(stopped.stop, Some(RemoteStop::Break { immediate: 0x77 }));
+
+            // The snapshot brings back the loop and r8
+            client.write_register("r8", 0).await.unwrap();
+            client
+                .load_snapshot(snapshot.to_str().unwrap())
+                .await
+                .unwrap();
+            assert_eq!(client.read_register("r8").await.unwrap(), 0x1234);
+            assert_eq!(client.step(3).await.unwrap().ip, BASE);
+
+            let id = client.attach_flash(FLASH, 0x1000, 0x1000).await.unwrap();
+            assert!(client.attach_flash(FLASH, 0x1000, 0x1000).await.is_err());
+            client.detach(id).await.unwrap();
+            assert!(client.detach(id).await.is_err());
+
+            let error = client.call("reboot", Value::Null).await.unwrap_err();
+            assert!(error.to_string().contains("Unknown method"));
+            client.shutdown().await.unwrap();
+        });
+
+        let emulator = serving.join().unwrap();
+        assert_eq!(emulator.cpu.gr[8], 0x1234);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
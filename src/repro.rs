@@ -0,0 +1,1282 @@
+//! Minimized crash reproducers
+//!
+//! A fault deep into a long run is hard to report: the guest, its inputs
+//! and the machine setup all have to travel with the bug. [`CrashRecorder`]
+//! runs the guest while taking [`Snapshot`]s of its state at intervals, and
+//! when the run faults, [`CrashRecorder::minimize`] finds the shortest
+//! stretch of execution that still reproduces the fault and packs it into a
+//! [`ReproBundle`]: a snapshot, the bundle addresses executed from it up to
+//! the fault, the fault message and the machine configuration.
+//! [`ReproBundle::replay`] runs it on a fresh machine and checks it takes the
+//! same path to the same fault.
+//!
+//! Snapshots hold the registers of a golden state image plus AR.ITC, the
+//! control registers, every memory region with its contents, and the state
+//! behind the interrupt controller and the RSE: pending and nested
+//! interruptions and the register stack partitions, dirty registers not yet
+//! spilled and mandatory loads still pending included. The ALAT, caches,
+//! devices, the variable store and host files the guest opened are not
+//! captured, so a snapshot taken while the guest depends on them replays
+//! differently. The minimizer therefore checks
+//! every candidate by replaying it, and assumes that if a replay reproduces
+//! the fault, a longer one from earlier in the same run does too: it binary
+//! searches the checkpoints for the latest that reproduces, then the
+//! bundles after that checkpoint.
+//!
+//! Files are written record by record, so memory is not copied into a
+//! second buffer on the way out. With the `compression` feature, memory
+//! regions can be stored as [`REGION_CHUNK_SIZE`] chunks compressed with
+//! zstd one at a time; each chunk decompresses on its own, so a reader can
+//! restore part of a region without inflating the rest.
+
+use crate::config::MachineConfig;
+use crate::cpu::interrupts::{
+    HandlerEntry, InterruptControllerState, InterruptState, InterruptVector,
+};
+use crate::cpu::registers::ar::AR;
+use crate::cpu::registers::cr::{CRIndex, NUM_CR};
+use crate::cpu::rse::RseState;
+use crate::cpu::Cpu;
+use crate::emulator::{Emulator, StopReason};
+use crate::golden::{self, Reader, Register};
+use crate::memory::Permissions;
+use crate::EmulatorError;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+/// File signature
+const MAGIC: &[u8; 8] = b"IA64REPR";
+
+/// Format version
+const VERSION: u16 = 1;
+
+/// Record tags
+const TAG_CONFIG: u8 = 1;
+const TAG_FAULT: u8 = 2;
+const TAG_REGISTER: u8 = 3;
+const TAG_REGION: u8 = 4;
+const TAG_TRACE: u8 = 5;
+const TAG_INTERRUPTS: u8 = 6;
+const TAG_RSE: u8 = 7;
+const TAG_COMPRESSED_REGION: u8 = 8;
+const TAG_INTERRUPTED: u8 = 9;
+const TAG_CLEAN_REGISTERS: u8 = 10;
+
+/// Bytes of region contents compressed together
+pub const REGION_CHUNK_SIZE: usize = 1 << 20;
+
+/// Highest zstd compression level
+pub const MAX_COMPRESSION_LEVEL: i32 = 22;
+
+/// Bundles between checkpoints by default
+pub const DEFAULT_CHECKPOINT_INTERVAL: u64 = 100_000;
+
+/// Checkpoints kept before thinning them out
+const MAX_CHECKPOINTS: usize = 32;
+
+/// Storage of memory regions in snapshot and reproducer files
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Compression {
+    /// Stored as they are
+    #[default]
+    None,
+    /// Chunks compressed with zstd at this level, 1 to
+    /// [`MAX_COMPRESSION_LEVEL`]; needs the `compression` feature
+    Zstd(i32),
+}
+
+impl Compression {
+    /// Compression at a zstd level, with 0 storing regions as they are
+    pub fn from_level(level: i32) -> Option<Self> {
+        match level {
+            0 => Some(Compression::None),
+            1..=MAX_COMPRESSION_LEVEL => Some(Compression::Zstd(level)),
+            _ => None,
+        }
+    }
+}
+
+/// Memory region in a snapshot
+#[derive(Debug, Clone, PartialEq)]
+pub struct SnapshotRegion {
+    /// Base address
+    pub base: u64,
+    /// Access permissions
+    pub permissions: Permissions,
+    /// Name shown by the debugger
+    pub name: Option<String>,
+    /// Contents
+    pub data: Vec<u8>,
+}
+
+/// Interruption state in a snapshot
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct InterruptSnapshot {
+    /// Control registers, PSR and the interruption registers included
+    pub control: Vec<(CRIndex, u64)>,
+    /// Interrupt controller
+    pub controller: InterruptControllerState,
+}
+
+impl InterruptSnapshot {
+    /// Capture the interruption state of a CPU
+    fn capture(cpu: &Cpu) -> Self {
+        let control = (0..NUM_CR as u8)
+            .filter_map(CRIndex::from_bits)
+            .map(|index| (index, cpu.read_cr(index)))
+            .collect();
+        Self {
+            control,
+            controller: cpu.interrupt_ctrl.state(),
+        }
+    }
+
+    /// Load the interruption state into a CPU
+    ///
+    /// Control registers are set directly, so that restoring CR.IFS is not
+    /// taken for a handler writing it.
+    fn restore(&self, cpu: &mut Cpu) -> Result<(), EmulatorError> {
+        for &(index, value) in &self.control {
+            match index {
+                CRIndex::ITM => cpu.timer.set_itm(value),
+                _ => cpu.system_regs.cr.write(index, value)?,
+            }
+        }
+        cpu.interrupt_ctrl.restore_state(&self.controller)
+    }
+}
+
+/// Guest state a replay starts from
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Snapshot {
+    /// Register values
+    pub registers: Vec<(Register, u64)>,
+    /// Memory regions
+    pub regions: Vec<SnapshotRegion>,
+    /// Interruption state; snapshots written before it was captured have
+    /// none
+    pub interrupts: Option<InterruptSnapshot>,
+    /// Register stack partitions; snapshots written before they were
+    /// captured have none
+    pub rse: Option<RseState>,
+}
+
+impl Snapshot {
+    /// Capture the state of a machine between bundles
+    pub fn capture(emulator: &Emulator) -> Self {
+        let mut registers = golden::registers(&emulator.cpu);
+        if let Ok(itc) = emulator.cpu.read_ar(AR::ITC) {
+            registers.push((Register::Ar(AR::ITC as u8), itc));
+        }
+        let regions = emulator
+            .memory
+            .regions()
+            .map(|(base, permissions, data)| SnapshotRegion {
+                base,
+                permissions,
+                name: emulator.memory.region_name(base).map(str::to_string),
+                data: data.to_vec(),
+            })
+            .collect();
+        Self {
+            registers,
+            regions,
+            interrupts: Some(InterruptSnapshot::capture(&emulator.cpu)),
+            rse: Some(emulator.cpu.rse.state()),
+        }
+    }
+
+    /// Capture the registers of a machine between bundles, leaving out its
+    /// memory and AR.ITC
+    pub fn capture_registers(emulator: &Emulator) -> Self {
+        Self {
+            registers: golden::registers(&emulator.cpu),
+            regions: Vec::new(),
+            interrupts: Some(InterruptSnapshot::capture(&emulator.cpu)),
+            rse: Some(emulator.cpu.rse.state()),
+        }
+    }
+
+    /// Map the snapshot's regions into a machine without memory and load
+    /// its registers
+    pub fn restore(&self, emulator: &mut Emulator) -> Result<(), EmulatorError> {
+        for region in &self.regions {
+            let size = region.data.len() as u64;
+            emulator
+                .memory
+                .load_image(region.base, size, &region.data, region.permissions)?;
+            emulator.invalidate_decoded(region.base, size);
+            if let Some(name) = &region.name {
+                emulator.memory.name_region(region.base, name)?;
+            }
+        }
+
+        let cpu = &mut emulator.cpu;
+        // The backing store registers can only be written in enforced lazy
+        // mode and with no mandatory loads pending; RSC is set directly to
+        // keep its privilege level
+        cpu.rse.restore_state(&RseState::default())?;
+        cpu.rse.set_rsc(0, 0)?;
+        let mut rsc = 0;
+        for &(register, value) in &self.registers {
+            match register {
+                Register::Ar(index) if index == AR::RSC as u8 => rsc = value,
+                // Follows from BSPSTORE and the current frame
+                Register::Ar(index) if index == AR::BSP as u8 => {}
+                _ => golden::set_register(cpu, register, value)?,
+            }
+        }
+        cpu.slot = 0;
+        // BSPSTORE is in place, so the partitions behind it can follow
+        if let Some(rse) = &self.rse {
+            cpu.rse.restore_state(rse)?;
+        }
+        if let Some(interrupts) = &self.interrupts {
+            interrupts.restore(cpu)?;
+        }
+        cpu.rse.set_rsc(rsc, 0)
+    }
+
+    /// Write the snapshot to a file, as a reproducer with no trace
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), EmulatorError> {
+        self.save_compressed(path, Compression::None)
+    }
+
+    /// Write the snapshot to a file with its regions compressed
+    pub fn save_compressed(
+        &self,
+        path: impl AsRef<Path>,
+        compression: Compression,
+    ) -> Result<(), EmulatorError> {
+        ReproBundle {
+            snapshot: self.clone(),
+            ..ReproBundle::default()
+        }
+        .save_compressed(path, compression)
+    }
+
+    /// Read the snapshot of a file written by [`Snapshot::save`] or of any
+    /// reproducer
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, EmulatorError> {
+        ReproBundle::load(path).map(|bundle| bundle.snapshot)
+    }
+}
+
+/// Result of replaying a reproducer
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ReplayOutcome {
+    /// The recorded path led to the recorded fault
+    Reproduced,
+    /// Execution left the recorded path
+    Diverged {
+        /// Bundles executed before leaving it
+        step: usize,
+        /// Address the trace expected
+        expected: u64,
+        /// Address the machine reached
+        actual: u64,
+    },
+    /// A different fault, or the recorded one too early
+    Fault {
+        /// Bundles executed before the fault
+        step: usize,
+        /// Fault message
+        message: String,
+    },
+    /// The guest stopped without faulting
+    Stopped {
+        /// Bundles executed before the stop
+        step: usize,
+        /// Why it stopped
+        reason: StopReason,
+    },
+    /// The trace ran out without a fault
+    NoFault,
+}
+
+impl fmt::Display for ReplayOutcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReplayOutcome::Reproduced => write!(f, "fault reproduced"),
+            ReplayOutcome::Diverged {
+                step,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "diverged after {} bundles: expected {:#x}, reached {:#x}",
+                step, expected, actual
+            ),
+            ReplayOutcome::Fault { step, message } => {
+                write!(f, "different fault after {} bundles: {}", step, message)
+            }
+            ReplayOutcome::Stopped { step, reason } => {
+                write!(f, "stopped after {} bundles: {:?}", step, reason)
+            }
+            ReplayOutcome::NoFault => write!(f, "trace ended without a fault"),
+        }
+    }
+}
+
+/// Self-contained reproducer of a fault
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ReproBundle {
+    /// Machine configuration file the run used
+    pub config: Option<String>,
+    /// State the replay starts from
+    pub snapshot: Snapshot,
+    /// Address of each bundle executed, the faulting one last
+    pub trace: Vec<u64>,
+    /// Message of the fault
+    pub fault: String,
+}
+
+impl ReproBundle {
+    /// Fresh machine in the snapshot's state
+    ///
+    /// The configuration's CPU, cache, guest and panic settings apply;
+    /// its memory map, images, flash and variable store do not, as memory
+    /// comes from the snapshot.
+    pub fn machine(&self) -> Result<Emulator, EmulatorError> {
+        let mut emulator = match &self.config {
+            Some(text) => {
+                let mut config = MachineConfig::parse(text)?;
+                config.entry = None;
+                config.memory.clear();
+                config.flash.clear();
+                config.efi.variables = None;
+                Emulator::with_config(&config)?
+            }
+            None => Emulator::new(),
+        };
+        self.snapshot.restore(&mut emulator)?;
+        Ok(emulator)
+    }
+
+    /// Replay the trace on a fresh machine
+    pub fn replay(&self) -> Result<ReplayOutcome, EmulatorError> {
+        let mut emulator = self.machine()?;
+        Ok(follow(&mut emulator, &self.trace, &self.fault))
+    }
+
+    /// Encode in the reproducer file format
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        self.write_to(&mut out, Compression::None)
+            .expect("uncompressed writes to a vector cannot fail");
+        out
+    }
+
+    /// Write in the reproducer file format, one record at a time
+    pub fn write_to(&self, out: &mut impl Write, compression: Compression) -> io::Result<()> {
+        let mut header = MAGIC.to_vec();
+        header.extend_from_slice(&VERSION.to_le_bytes());
+        if let Some(config) = &self.config {
+            header.push(TAG_CONFIG);
+            put_bytes(&mut header, config.as_bytes());
+        }
+        header.push(TAG_FAULT);
+        put_bytes(&mut header, self.fault.as_bytes());
+        for &(register, value) in &self.snapshot.registers {
+            let (kind, index) = register.encode();
+            header.extend_from_slice(&[TAG_REGISTER, kind, index]);
+            header.extend_from_slice(&value.to_le_bytes());
+        }
+        out.write_all(&header)?;
+        for region in &self.snapshot.regions {
+            write_region(out, region, compression)?;
+        }
+
+        let mut trailer = Vec::new();
+        if let Some(interrupts) = &self.snapshot.interrupts {
+            trailer.push(TAG_INTERRUPTS);
+            encode_interrupts(&mut trailer, interrupts);
+            // Kept in a record of its own, which older files lack
+            if !interrupts.controller.interrupted.is_empty() {
+                trailer.push(TAG_INTERRUPTED);
+                encode_interrupt_list(&mut trailer, &interrupts.controller.interrupted);
+            }
+        }
+        if let Some(rse) = &self.snapshot.rse {
+            trailer.push(TAG_RSE);
+            for value in [
+                rse.dirty_count as u64,
+                rse.clean_count as u64,
+                rse.invalid_count as u64,
+                rse.pending_loads as u64,
+                rse.bspload,
+            ] {
+                trailer.extend_from_slice(&value.to_le_bytes());
+            }
+            encode_registers(&mut trailer, &rse.contents);
+            // Kept in a record of its own, which older files lack
+            if !rse.clean.is_empty() {
+                trailer.push(TAG_CLEAN_REGISTERS);
+                encode_registers(&mut trailer, &rse.clean);
+            }
+        }
+        trailer.push(TAG_TRACE);
+        trailer.extend_from_slice(&(self.trace.len() as u64).to_le_bytes());
+        for ip in &self.trace {
+            trailer.extend_from_slice(&ip.to_le_bytes());
+        }
+        out.write_all(&trailer)
+    }
+
+    /// Decode the reproducer file format
+    pub fn from_bytes(data: &[u8]) -> Result<Self, EmulatorError> {
+        let malformed =
+            |what: &str| EmulatorError::ExecutionError(format!("Bad reproducer: {}", what));
+        let mut reader = Reader::new(data);
+        if reader
+            .take(MAGIC.len())
+            .ok_or_else(|| malformed("truncated header"))?
+            != MAGIC
+        {
+            return Err(malformed("not a reproducer"));
+        }
+        let version = reader.u16().ok_or_else(|| malformed("truncated header"))?;
+        if version != VERSION {
+            return Err(malformed(&format!("unsupported version {}", version)));
+        }
+
+        let mut bundle = Self::default();
+        while let Some(tag) = reader.u8() {
+            match tag {
+                TAG_CONFIG => {
+                    bundle.config =
+                        Some(read_text(&mut reader).ok_or_else(|| malformed("bad configuration"))?)
+                }
+                TAG_FAULT => {
+                    bundle.fault =
+                        read_text(&mut reader).ok_or_else(|| malformed("bad fault message"))?
+                }
+                TAG_REGISTER => {
+                    let (kind, index) = reader
+                        .u8()
+                        .zip(reader.u8())
+                        .ok_or_else(|| malformed("truncated register"))?;
+                    let register = Register::decode(kind, index)
+                        .ok_or_else(|| malformed(&format!("unknown register kind {}", kind)))?;
+                    let value = reader
+                        .u64()
+                        .ok_or_else(|| malformed("truncated register"))?;
+                    bundle.snapshot.registers.push((register, value));
+                }
+                TAG_REGION => {
+                    let (base, permissions) = reader
+                        .u64()
+                        .zip(reader.u8())
+                        .ok_or_else(|| malformed("truncated region"))?;
+                    let permissions = decode_permissions(permissions)
+                        .ok_or_else(|| malformed("bad region permissions"))?;
+                    let name =
+                        read_text(&mut reader).ok_or_else(|| malformed("bad region name"))?;
+                    let data = read_bytes(&mut reader)
+                        .ok_or_else(|| malformed("truncated region"))?
+                        .to_vec();
+                    bundle.snapshot.regions.push(SnapshotRegion {
+                        base,
+                        permissions,
+                        name: (!name.is_empty()).then_some(name),
+                        data,
+                    });
+                }
+                TAG_COMPRESSED_REGION => {
+                    if !cfg!(feature = "compression") {
+                        return Err(malformed(
+                            "compressed region; built without the compression feature",
+                        ));
+                    }
+                    let (base, permissions) = reader
+                        .u64()
+                        .zip(reader.u8())
+                        .ok_or_else(|| malformed("truncated region"))?;
+                    let permissions = decode_permissions(permissions)
+                        .ok_or_else(|| malformed("bad region permissions"))?;
+                    let name =
+                        read_text(&mut reader).ok_or_else(|| malformed("bad region name"))?;
+                    let data = read_chunks(&mut reader)
+                        .ok_or_else(|| malformed("bad compressed region"))?;
+                    bundle.snapshot.regions.push(SnapshotRegion {
+                        base,
+                        permissions,
+                        name: (!name.is_empty()).then_some(name),
+                        data,
+                    });
+                }
+                TAG_INTERRUPTS => {
+                    bundle.snapshot.interrupts = Some(
+                        read_interrupts(&mut reader)
+                            .ok_or_else(|| malformed("bad interruption state"))?,
+                    );
+                }
+                TAG_INTERRUPTED => {
+                    let interrupted = read_interrupt_list(&mut reader);
+                    let controller = bundle
+                        .snapshot
+                        .interrupts
+                        .as_mut()
+                        .map(|interrupts| &mut interrupts.controller);
+                    match (controller, interrupted) {
+                        (Some(controller), Some(interrupted)) => {
+                            controller.interrupted = interrupted
+                        }
+                        _ => return Err(malformed("bad interrupted handlers")),
+                    }
+                }
+                TAG_RSE => {
+                    bundle.snapshot.rse =
+                        Some(read_rse(&mut reader).ok_or_else(|| malformed("bad register stack"))?);
+                }
+                TAG_CLEAN_REGISTERS => {
+                    let clean = read_registers(&mut reader);
+                    match (bundle.snapshot.rse.as_mut(), clean) {
+                        (Some(rse), Some(clean)) => rse.clean = clean,
+                        _ => return Err(malformed("bad clean registers")),
+                    }
+                }
+                TAG_TRACE => {
+                    let len = reader.u64().ok_or_else(|| malformed("truncated trace"))?;
+                    bundle.trace = (0..len)
+                        .map(|_| reader.u64())
+                        .collect::<Option<_>>()
+                        .ok_or_else(|| malformed("truncated trace"))?;
+                }
+                tag => return Err(malformed(&format!("unknown record tag {}", tag))),
+            }
+        }
+        Ok(bundle)
+    }
+
+    /// Write the reproducer to a file
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), EmulatorError> {
+        self.save_compressed(path, Compression::None)
+    }
+
+    /// Write the reproducer to a file with its memory regions compressed
+    pub fn save_compressed(
+        &self,
+        path: impl AsRef<Path>,
+        compression: Compression,
+    ) -> Result<(), EmulatorError> {
+        let path = path.as_ref();
+        File::create(path)
+            .and_then(|file| {
+                let mut out = BufWriter::new(file);
+                self.write_to(&mut out, compression)?;
+                out.flush()
+            })
+            .map_err(|e| {
+                EmulatorError::ExecutionError(format!("Cannot write {}: {}", path.display(), e))
+            })
+    }
+
+    /// Read a reproducer from a file
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, EmulatorError> {
+        let path = path.as_ref();
+        let data = std::fs::read(path).map_err(|e| {
+            EmulatorError::ExecutionError(format!("Cannot read {}: {}", path.display(), e))
+        })?;
+        Self::from_bytes(&data)
+    }
+}
+
+/// Append a length-prefixed byte string
+fn put_bytes(out: &mut Vec<u8>, data: &[u8]) {
+    out.extend_from_slice(&(data.len() as u64).to_le_bytes());
+    out.extend_from_slice(data);
+}
+
+/// Write a region record, compressing one chunk of the contents at a time
+fn write_region(
+    out: &mut impl Write,
+    region: &SnapshotRegion,
+    compression: Compression,
+) -> io::Result<()> {
+    let tag = match compression {
+        Compression::None => TAG_REGION,
+        Compression::Zstd(_) => TAG_COMPRESSED_REGION,
+    };
+    let mut header = vec![tag];
+    header.extend_from_slice(&region.base.to_le_bytes());
+    header.push(encode_permissions(region.permissions));
+    put_bytes(&mut header, region.name.as_deref().unwrap_or("").as_bytes());
+    header.extend_from_slice(&(region.data.len() as u64).to_le_bytes());
+    match compression {
+        Compression::None => {
+            out.write_all(&header)?;
+            out.write_all(&region.data)
+        }
+        Compression::Zstd(level) => {
+            header.extend_from_slice(&(REGION_CHUNK_SIZE as u64).to_le_bytes());
+            out.write_all(&header)?;
+            for chunk in region.data.chunks(REGION_CHUNK_SIZE) {
+                let packed = compress(chunk, level)?;
+                out.write_all(&(packed.len() as u64).to_le_bytes())?;
+                out.write_all(&packed)?;
+            }
+            Ok(())
+        }
+    }
+}
+
+/// Read the contents of a compressed region record: its size, its chunk
+/// size, and the chunks, each length-prefixed
+fn read_chunks(reader: &mut Reader<'_>) -> Option<Vec<u8>> {
+    let size = usize::try_from(reader.u64()?).ok()?;
+    let chunk_size = usize::try_from(reader.u64()?).ok().filter(|&n| n > 0)?;
+    // The sizes are not trusted for allocation until the data is there
+    let mut data = Vec::new();
+    while data.len() < size {
+        let expected = chunk_size.min(size - data.len());
+        let chunk = decompress(read_bytes(reader)?, expected)?;
+        if chunk.len() != expected {
+            return None;
+        }
+        data.extend_from_slice(&chunk);
+    }
+    Some(data)
+}
+
+#[cfg(feature = "compression")]
+fn compress(data: &[u8], level: i32) -> io::Result<Vec<u8>> {
+    zstd::bulk::compress(data, level)
+}
+
+#[cfg(not(feature = "compression"))]
+fn compress(_data: &[u8], _level: i32) -> io::Result<Vec<u8>> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "built without the compression feature",
+    ))
+}
+
+#[cfg(feature = "compression")]
+fn decompress(data: &[u8], len: usize) -> Option<Vec<u8>> {
+    zstd::bulk::decompress(data, len).ok()
+}
+
+#[cfg(not(feature = "compression"))]
+fn decompress(_data: &[u8], _len: usize) -> Option<Vec<u8>> {
+    None
+}
+
+/// Read a length-prefixed byte string
+fn read_bytes<'a>(reader: &mut Reader<'a>) -> Option<&'a [u8]> {
+    let len = usize::try_from(reader.u64()?).ok()?;
+    reader.take(len)
+}
+
+/// Read a length-prefixed UTF-8 string
+fn read_text(reader: &mut Reader<'_>) -> Option<String> {
+    String::from_utf8(read_bytes(reader)?.to_vec()).ok()
+}
+
+/// Encode an interruption state record
+fn encode_interrupts(out: &mut Vec<u8>, interrupts: &InterruptSnapshot) {
+    out.push(interrupts.control.len() as u8);
+    for &(index, value) in &interrupts.control {
+        out.push(index as u8);
+        out.extend_from_slice(&value.to_le_bytes());
+    }
+
+    let controller = &interrupts.controller;
+    out.push(controller.interrupts_enabled as u8);
+    out.extend_from_slice(&(controller.nesting_level as u64).to_le_bytes());
+    out.extend_from_slice(&controller.raised.to_le_bytes());
+    out.push(controller.handlers.len() as u8);
+    for handler in &controller.handlers {
+        out.extend_from_slice(&handler.address.to_le_bytes());
+        out.extend_from_slice(&[handler.min_privilege, handler.enabled as u8]);
+    }
+    out.push(controller.current.is_some() as u8);
+    if let Some(current) = &controller.current {
+        encode_interrupt(out, current);
+    }
+    encode_interrupt_list(out, &controller.pending);
+}
+
+/// Encode one interrupt of the controller
+fn encode_interrupt(out: &mut Vec<u8>, state: &InterruptState) {
+    out.push(state.vector as u8);
+    out.extend_from_slice(&state.ip.to_le_bytes());
+    out.extend_from_slice(&state.psr.to_le_bytes());
+    out.extend_from_slice(&state.bundle);
+    out.extend_from_slice(&state.info.to_le_bytes());
+}
+
+/// Encode a counted list of interrupts
+fn encode_interrupt_list(out: &mut Vec<u8>, states: &[InterruptState]) {
+    out.extend_from_slice(&(states.len() as u64).to_le_bytes());
+    for state in states {
+        encode_interrupt(out, state);
+    }
+}
+
+/// Decode an interruption state record
+fn read_interrupts(reader: &mut Reader<'_>) -> Option<InterruptSnapshot> {
+    let control = (0..reader.u8()?)
+        .map(|_| Some((CRIndex::from_bits(reader.u8()?)?, reader.u64()?)))
+        .collect::<Option<_>>()?;
+
+    let interrupts_enabled = reader.u8()? != 0;
+    let nesting_level = u32::try_from(reader.u64()?).ok()?;
+    let raised = reader.u64()?;
+    let handlers = (0..reader.u8()?)
+        .map(|_| {
+            Some(HandlerEntry {
+                address: reader.u64()?,
+                min_privilege: reader.u8()?,
+                enabled: reader.u8()? != 0,
+            })
+        })
+        .collect::<Option<_>>()?;
+    let current = match reader.u8()? {
+        0 => None,
+        _ => Some(read_interrupt(reader)?),
+    };
+    let pending = read_interrupt_list(reader)?;
+    Some(InterruptSnapshot {
+        control,
+        controller: InterruptControllerState {
+            handlers,
+            pending,
+            current,
+            interrupted: Vec::new(),
+            nesting_level,
+            interrupts_enabled,
+            raised,
+        },
+    })
+}
+
+/// Decode one interrupt of the controller
+fn read_interrupt(reader: &mut Reader<'_>) -> Option<InterruptState> {
+    Some(InterruptState {
+        vector: InterruptVector::from_bits(reader.u8()?)?,
+        ip: reader.u64()?,
+        psr: reader.u64()?,
+        bundle: reader.take(16)?.try_into().ok()?,
+        info: reader.u64()?,
+    })
+}
+
+/// Decode a counted list of interrupts
+fn read_interrupt_list(reader: &mut Reader<'_>) -> Option<Vec<InterruptState>> {
+    (0..reader.u64()?).map(|_| read_interrupt(reader)).collect()
+}
+
+/// Encode a counted list of register values and NaT bits
+fn encode_registers(out: &mut Vec<u8>, registers: &[(u64, bool)]) {
+    out.extend_from_slice(&(registers.len() as u64).to_le_bytes());
+    for &(value, nat) in registers {
+        out.extend_from_slice(&value.to_le_bytes());
+        out.push(nat as u8);
+    }
+}
+
+/// Decode a counted list of register values and NaT bits
+fn read_registers(reader: &mut Reader<'_>) -> Option<Vec<(u64, bool)>> {
+    (0..reader.u64()?)
+        .map(|_| Some((reader.u64()?, reader.u8()? != 0)))
+        .collect()
+}
+
+/// Decode a register stack record
+fn read_rse(reader: &mut Reader<'_>) -> Option<RseState> {
+    let mut count = || u32::try_from(reader.u64()?).ok();
+    let (dirty_count, clean_count, invalid_count, pending_loads) =
+        (count()?, count()?, count()?, count()?);
+    let bspload = reader.u64()?;
+    let contents = read_registers(reader)?;
+    Some(RseState {
+        contents,
+        clean: Vec::new(),
+        dirty_count,
+        clean_count,
+        invalid_count,
+        pending_loads,
+        bspload,
+    })
+}
+
+fn encode_permissions(permissions: Permissions) -> u8 {
+    match permissions {
+        Permissions::None => 0,
+        Permissions::Read => 1,
+        Permissions::ReadWrite => 2,
+        Permissions::ReadExecute => 3,
+        Permissions::ReadWriteExecute => 4,
+    }
+}
+
+fn decode_permissions(byte: u8) -> Option<Permissions> {
+    Some(match byte {
+        0 => Permissions::None,
+        1 => Permissions::Read,
+        2 => Permissions::ReadWrite,
+        3 => Permissions::ReadExecute,
+        4 => Permissions::ReadWriteExecute,
+        _ => return None,
+    })
+}
+
+/// Step along a trace, checking each bundle address and the final fault
+fn follow(emulator: &mut Emulator, trace: &[u64], fault: &str) -> ReplayOutcome {
+    for (step, &expected) in trace.iter().enumerate() {
+        if emulator.cpu.ip != expected {
+            return ReplayOutcome::Diverged {
+                step,
+                expected,
+                actual: emulator.cpu.ip,
+            };
+        }
+        match emulator.step() {
+            Err(e) if step + 1 == trace.len() && e.to_string() == fault => {
+                return ReplayOutcome::Reproduced
+            }
+            Err(e) => {
+                return ReplayOutcome::Fault {
+                    step,
+                    message: e.to_string(),
+                }
+            }
+            Ok(Some(reason)) => return ReplayOutcome::Stopped { step, reason },
+            Ok(None) => {}
+        }
+    }
+    ReplayOutcome::NoFault
+}
+
+/// Runs a guest with periodic snapshots, for minimizing its faults
+#[derive(Debug, Clone)]
+pub struct CrashRecorder {
+    /// Bundles between checkpoints
+    interval: u64,
+    /// Snapshots by the number of bundles executed before them
+    checkpoints: Vec<(u64, Snapshot)>,
+    /// Bundles executed so far
+    bundles: u64,
+    /// Message of the fault that ended the run, and the bundles before it
+    fault: Option<(u64, String)>,
+}
+
+impl CrashRecorder {
+    /// Record with a checkpoint every `interval` bundles
+    ///
+    /// At most 32 checkpoints are kept; when there would be more, every
+    /// other one is dropped and the interval doubles.
+    pub fn new(interval: u64) -> Self {
+        Self {
+            interval: interval.max(1),
+            checkpoints: Vec::new(),
+            bundles: 0,
+            fault: None,
+        }
+    }
+
+    /// Bundles executed so far
+    pub fn bundles(&self) -> u64 {
+        self.bundles
+    }
+
+    /// Run until the guest stops, like [`Emulator::run`]
+    ///
+    /// Running again after a stop continues the recording.
+    pub fn run(&mut self, emulator: &mut Emulator) -> Result<StopReason, EmulatorError> {
+        loop {
+            if self.bundles.is_multiple_of(self.interval) {
+                self.checkpoint(emulator);
+            }
+            match emulator.step() {
+                Ok(None) => self.bundles += 1,
+                Ok(Some(reason)) => {
+                    self.bundles += 1;
+                    return Ok(reason);
+                }
+                Err(e) => {
+                    self.fault = Some((self.bundles, e.to_string()));
+                    return Err(e);
+                }
+            }
+        }
+    }
+
+    fn checkpoint(&mut self, emulator: &Emulator) {
+        if self.checkpoints.last().map(|(at, _)| *at) == Some(self.bundles) {
+            return;
+        }
+        if self.checkpoints.len() == MAX_CHECKPOINTS {
+            let mut index = 0;
+            self.checkpoints.retain(|_| {
+                index += 1;
+                index % 2 == 1
+            });
+            self.interval *= 2;
+            if !self.bundles.is_multiple_of(self.interval) {
+                return;
+            }
+        }
+        self.checkpoints
+            .push((self.bundles, Snapshot::capture(emulator)));
+    }
+
+    /// Build the shortest reproducer of the fault that ended the run
+    ///
+    /// `config` is the text of the machine configuration file, if any.
+    pub fn minimize(&self, config: Option<&str>) -> Result<ReproBundle, EmulatorError> {
+        let (fault_at, fault) = self
+            .fault
+            .clone()
+            .ok_or_else(|| EmulatorError::ExecutionError("The run did not fault".to_string()))?;
+        let config = config.map(str::to_string);
+        let attempt = |at: u64, snapshot: &Snapshot| {
+            let bundle = ReproBundle {
+                config: config.clone(),
+                snapshot: snapshot.clone(),
+                trace: Vec::new(),
+                fault: fault.clone(),
+            };
+            record_trace(bundle, fault_at - at + 1)
+        };
+
+        // Latest checkpoint that reproduces; the first must
+        let checkpoints = &self.checkpoints;
+        let first = checkpoints.first().ok_or_else(|| {
+            EmulatorError::ExecutionError("No checkpoint was recorded".to_string())
+        })?;
+        let mut best = attempt(first.0, &first.1)?.ok_or_else(|| {
+            EmulatorError::ExecutionError(
+                "The fault does not reproduce from the start of the recording".to_string(),
+            )
+        })?;
+        let (mut lo, mut hi) = (0, checkpoints.len());
+        while hi - lo > 1 {
+            let mid = (lo + hi) / 2;
+            match attempt(checkpoints[mid].0, &checkpoints[mid].1)? {
+                Some(bundle) => {
+                    best = bundle;
+                    lo = mid;
+                }
+                None => hi = mid,
+            }
+        }
+
+        // Latest bundle after that checkpoint that reproduces
+        let (mut lo, mut hi) = (
+            checkpoints[lo].0,
+            checkpoints.get(hi).map_or(fault_at + 1, |(at, _)| *at),
+        );
+        while hi - lo > 1 {
+            let mid = lo + (hi - lo) / 2;
+            let mut emulator = best.machine()?;
+            for _ in lo..mid {
+                emulator.step()?;
+            }
+            let skipped = (mid - lo) as usize;
+            match attempt(mid, &Snapshot::capture(&emulator))? {
+                Some(bundle) if bundle.trace[..] == best.trace[skipped..] => {
+                    best = bundle;
+                    lo = mid;
+                }
+                _ => hi = mid,
+            }
+        }
+        Ok(best)
+    }
+}
+
+/// Replay `steps` bundles from a bundle's snapshot, filling in its trace if
+/// they end in its fault
+fn record_trace(mut bundle: ReproBundle, steps: u64) -> Result<Option<ReproBundle>, EmulatorError> {
+    let mut emulator = bundle.machine()?;
+    for step in 0..steps {
+        bundle.trace.push(emulator.cpu.ip);
+        match emulator.step() {
+            Err(e) if step + 1 == steps && e.to_string() == bundle.fault => {
+                return Ok(Some(bundle))
+            }
+            Ok(None) if step + 1 < steps => {}
+            _ => return Ok(None),
+        }
+    }
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{encode_mii, mov_ip, nop};
+
+    const BASE: u64 = 0x10000;
+    const BUNDLES: u64 = 100;
+
+    /// Guest noting its position for a while, then hitting an
+    /// unimplemented instruction
+    fn faulting_guest() -> Emulator {
+        let unimplemented = 3 << 37;
+        let mut image: Vec<u8> = (0..BUNDLES)
+            .flat_map(|_| encode_mii([nop(), mov_ip(0, 14), nop()]))
+            .collect();
+        image.extend(encode_mii([nop(), unimplemented, nop()]));
+
+        let mut emu = Emulator::new();
+        emu.load_flat_image(BASE, &image, BASE).unwrap();
+        emu.cpu.gr[20] = 0x1234;
+        emu
+    }
+
+    #[test]
+    fn test_snapshot_round_trip() {
+        let mut emu = faulting_guest();
+        for _ in 0..10 {
+            emu.step().unwrap();
+        }
+        let snapshot = Snapshot::capture(&emu);
+
+        let mut copy = Emulator::new();
+        snapshot.restore(&mut copy).unwrap();
+        assert_eq!(Snapshot::capture(&copy), snapshot);
+        assert_eq!(copy.cpu.ip, BASE + 10 * 16);
+        assert_eq!(copy.cpu.gr[20], 0x1234);
+
+        let bundle = ReproBundle {
+            config: Some("[cpu]\nstrict_decode = true\n".to_string()),
+            snapshot,
+            trace: vec![BASE + 10 * 16],
+            fault: "fault".to_string(),
+        };
+        assert_eq!(ReproBundle::from_bytes(&bundle.to_bytes()).unwrap(), bundle);
+        assert!(ReproBundle::from_bytes(b"IA64REPR\x02\x00").is_err());
+    }
+
+    #[test]
+    fn test_compressed_regions() {
+        // A region of several chunks, the last one partial, with data
+        // straddling the first boundary
+        const DATA: u64 = 0x100000;
+        let mut emu = faulting_guest();
+        let size = 2 * REGION_CHUNK_SIZE as u64 + 0x1000;
+        emu.memory.map(DATA, size, Permissions::ReadWrite).unwrap();
+        emu.memory
+            .write_bytes(
+                DATA + REGION_CHUNK_SIZE as u64 - 4,
+                &[1, 2, 3, 4, 5, 6, 7, 8],
+            )
+            .unwrap();
+        emu.memory.write_u64(DATA + size - 8, 0x1234).unwrap();
+        let bundle = ReproBundle {
+            snapshot: Snapshot::capture(&emu),
+            trace: vec![BASE],
+            ..ReproBundle::default()
+        };
+
+        let mut packed = Vec::new();
+        let result = bundle.write_to(&mut packed, Compression::Zstd(3));
+        if cfg!(feature = "compression") {
+            result.unwrap();
+            assert!(packed.len() < bundle.to_bytes().len() / 10);
+            assert_eq!(ReproBundle::from_bytes(&packed).unwrap(), bundle);
+        } else {
+            assert_eq!(result.unwrap_err().kind(), io::ErrorKind::Unsupported);
+        }
+
+        assert_eq!(Compression::from_level(0), Some(Compression::None));
+        assert_eq!(Compression::from_level(19), Some(Compression::Zstd(19)));
+        assert_eq!(Compression::from_level(23), None);
+    }
+
+    /// Checkpoint a machine, carry on with it and with a fresh machine
+    /// restored from the checkpoint file, and check they end the same
+    fn check_restart(emu: &mut Emulator, finish: impl Fn(&mut Emulator)) {
+        let bundle = ReproBundle {
+            snapshot: Snapshot::capture(emu),
+            ..ReproBundle::default()
+        };
+        let mut copy = ReproBundle::from_bytes(&bundle.to_bytes())
+            .unwrap()
+            .machine()
+            .unwrap();
+        assert_eq!(Snapshot::capture(&copy), bundle.snapshot);
+
+        finish(emu);
+        finish(&mut copy);
+        assert_eq!(Snapshot::capture(&copy), Snapshot::capture(emu));
+    }
+
+    const BACKING: u64 = 0x40000;
+
+    /// Machine with a backing store of two pages, the register stack
+    /// starting near the end of the first
+    fn stacked_guest() -> Emulator {
+        let mut emu = faulting_guest();
+        for page in [BACKING, BACKING + 0x1000] {
+            emu.memory
+                .map(page, 0x1000, Permissions::ReadWrite)
+                .unwrap();
+        }
+        emu.cpu.rse.set_bspstore(BACKING + 0xF00).unwrap();
+        emu
+    }
+
+    /// Give a caller 90 locals and call a function
+    fn call_with_locals(emu: &mut Emulator) {
+        let cpu = &mut emu.cpu;
+        cpu.alloc_frame(&mut emu.memory, 96, 90, 0).unwrap();
+        for reg in 32..128 {
+            cpu.gr[reg] = reg as u64;
+            cpu.nat[reg] = reg % 7 == 0;
+        }
+        cpu.handle_call(&mut emu.memory, 0x20000).unwrap();
+    }
+
+    /// Check the caller's locals came back
+    fn check_locals(emu: &Emulator) {
+        for reg in 32..122 {
+            assert_eq!(emu.cpu.gr[reg], reg as u64);
+            assert_eq!(emu.cpu.nat[reg], reg % 7 == 0);
+        }
+    }
+
+    #[test]
+    fn test_restart_in_nested_interrupts() {
+        const HANDLER: u64 = 0x8000;
+        const NESTED: u64 = 0x9000;
+        let mut emu = stacked_guest();
+        let cpu = &mut emu.cpu;
+        cpu.alloc_frame(&mut emu.memory, 8, 6, 0).unwrap();
+        for reg in 32..40 {
+            cpu.gr[reg] = reg as u64 * 0x11;
+        }
+        cpu.register_interrupt_handler(InterruptVector::ExtInt, HANDLER, 0)
+            .unwrap();
+        cpu.register_interrupt_handler(InterruptVector::DebugFault, NESTED, 0)
+            .unwrap();
+        cpu.set_interrupts_enabled(true);
+        cpu.raise_interrupt(InterruptVector::ExtInt, 0x10);
+        assert_eq!(cpu.check_interrupts(), Some(HANDLER));
+        cpu.ip = HANDLER;
+        cpu.cover(&mut emu.memory).unwrap();
+
+        // The handler reenables interrupts and is interrupted in turn, with
+        // another interrupt left pending
+        cpu.set_interrupts_enabled(true);
+        cpu.raise_interrupt(InterruptVector::ExtInt, 0x30);
+        cpu.raise_interrupt(InterruptVector::DebugFault, 0x20);
+        assert_eq!(cpu.check_interrupts(), Some(NESTED));
+        cpu.ip = NESTED;
+        cpu.alloc_frame(&mut emu.memory, 4, 4, 0).unwrap();
+        cpu.gr[32] = 0xFEED;
+        assert_eq!(cpu.interrupt_nesting_level(), 2);
+
+        check_restart(&mut emu, |emu| {
+            let cpu = &mut emu.cpu;
+            cpu.cover(&mut emu.memory).unwrap();
+            cpu.flush_rse(&mut emu.memory).unwrap();
+            while cpu.interrupt_nesting_level() > 0 {
+                cpu.return_from_interrupt().unwrap();
+            }
+        });
+        // Both handlers returned and the pending interrupt was taken
+        assert_eq!(emu.cpu.ip, HANDLER);
+        let current = emu.cpu.current_interrupt().unwrap();
+        assert_eq!(
+            (current.vector, current.info),
+            (InterruptVector::ExtInt, 0x30)
+        );
+        assert_eq!(emu.cpu.rse.dirty_count(), 0);
+        assert_eq!(emu.memory.read_u64(BACKING + 0xF00).unwrap(), 32 * 0x11);
+    }
+
+    #[test]
+    fn test_restart_mid_spill() {
+        let mut emu = stacked_guest();
+        call_with_locals(&mut emu);
+        // The callee's frame only fits once part of the locals are spilled
+        let cpu = &mut emu.cpu;
+        cpu.alloc_frame(&mut emu.memory, 40, 34, 0).unwrap();
+        assert_eq!(cpu.rse.dirty_count(), 56);
+        assert!(cpu.rse.get_bspstore() > BACKING + 0x1000);
+        for reg in 32..72 {
+            cpu.gr[reg] = !(reg as u64);
+        }
+
+        // Locals still in the register file come back first, the spilled
+        // ones by mandatory loads
+        check_restart(&mut emu, |emu| {
+            emu.cpu.handle_return(&mut emu.memory).unwrap();
+        });
+        check_locals(&emu);
+        assert_eq!(emu.cpu.gr[122], !32);
+    }
+
+    #[test]
+    fn test_restart_with_loads_pending() {
+        let mut emu = stacked_guest();
+        call_with_locals(&mut emu);
+        let cpu = &mut emu.cpu;
+        cpu.flush_rse(&mut emu.memory).unwrap();
+        // As after a context switch, the locals are only in the backing store
+        cpu.rse.invalidate();
+
+        // Returning reloads the locals from the top of the backing store
+        // down; with the first page unreadable, the loads stop there
+        emu.memory.protect(BACKING, Permissions::None).unwrap();
+        assert!(emu.cpu.handle_return(&mut emu.memory).is_err());
+        let pending = emu.cpu.rse.pending_loads();
+        assert!(pending > 0 && pending < 90);
+
+        check_restart(&mut emu, |emu| {
+            emu.memory.protect(BACKING, Permissions::ReadWrite).unwrap();
+            emu.cpu.complete_rse_loads(&mut emu.memory).unwrap();
+        });
+        assert_eq!(emu.cpu.rse.pending_loads(), 0);
+        check_locals(&emu);
+    }
+
+    #[test]
+    fn test_minimize() {
+        let mut emu = faulting_guest();
+        let mut recorder = CrashRecorder::new(1);
+        let fault = recorder.run(&mut emu).unwrap_err();
+        assert_eq!(recorder.bundles(), BUNDLES);
+        assert!(recorder.checkpoints.len() <= MAX_CHECKPOINTS);
+
+        let bundle = recorder.minimize(None).unwrap();
+        assert_eq!(bundle.fault, fault.to_string());
+        assert_eq!(bundle.trace, [BASE + BUNDLES * 16]);
+        assert_eq!(bundle.replay().unwrap(), ReplayOutcome::Reproduced);
+
+        // The trace is checked bundle by bundle
+        let mut wrong = bundle.clone();
+        wrong.trace.insert(0, BASE);
+        assert!(matches!(
+            wrong.replay().unwrap(),
+            ReplayOutcome::Diverged { step: 0, .. }
+        ));
+        wrong.trace = vec![];
+        assert_eq!(wrong.replay().unwrap(), ReplayOutcome::NoFault);
+    }
+
+    #[test]
+    fn test_minimize_uses_config() {
+        // Strict decoding comes from the configuration, not the snapshot;
+        // without it the slot faults differently
+        let config = "[cpu]\nstrict_decode = true\n";
+        let mut emu = faulting_guest();
+        emu.set_strict_decode(true);
+        let image = encode_mii([0x01 << 27, 1 << 37, 0x01 << 27]);
+        emu.memory.write_bytes(BASE + 16, &image).unwrap();
+
+        let mut recorder = CrashRecorder::new(4);
+        let fault = recorder.run(&mut emu).unwrap_err();
+        let bundle = recorder.minimize(Some(config)).unwrap();
+        assert_eq!(bundle.fault, fault.to_string());
+        assert_eq!(bundle.trace.len(), 1);
+        assert_eq!(bundle.replay().unwrap(), ReplayOutcome::Reproduced);
+        assert!(recorder.minimize(None).is_err());
+    }
+}
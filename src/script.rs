@@ -0,0 +1,382 @@
+//! Scripted debugger sessions
+//!
+//! This module embeds the [rhai](https://rhai.rs) scripting language so that
+//! debugging sessions can be automated: run to an address, inspect and
+//! modify registers and memory, and assert on guest state.
+//!
+//! Scripts see the following functions. Numbers are rhai integers; addresses
+//! and register values are their 64-bit two's complement patterns.
+//!
+//! - `ip()`, `set_ip(addr)`
+//! - `gr(n)`, `set_gr(n, value)`, `nat(n)`
+//! - `pr(n)`, `set_pr(n, bool)`, `br(n)`, `set_br(n, value)`
+//! - `ar(n)`, `set_ar(n, value)`
+//! - `read_u8(addr)` .. `read_u64(addr)`, `write_u8(addr, value)`,
+//!   `write_u64(addr, value)`, `read_string(addr)`
+//! - `step()`, `step(count)`, `run_to(addr)`, `resume()`, which return a
+//!   description of where the guest stopped
+//! - `symbol(name)`, the address of a debugger symbol
+//! - `command(line)`, the output of a debugger command
+//! - `assert(condition, message)`, failing the script if false
+//! - `hex(value)`, formatting a value as hexadecimal
+//!
+//! Output of `print` and `debug` is collected and returned by [`run`].
+//!
+//! ```rhai
+//! run_to(symbol("parse_header"));
+//! set_gr(32, 0);
+//! step(4);
+//! assert(gr(8) == 0, "parse_header accepted a null buffer");
+//! print(command("dump --symbol header_buf"));
+//! ```
+
+use crate::cpu::registers::ar::AR;
+use crate::debugger::{describe_stop, Debugger};
+use crate::emulator::Emulator;
+use crate::EmulatorError;
+use rhai::{Engine, EvalAltResult, INT};
+use std::cell::RefCell;
+use std::rc::Rc;
+
+/// Longest string returned by `read_string`
+const MAX_STRING_LEN: usize = 4096;
+
+/// Emulator and debugger shared with script functions
+struct Session {
+    emulator: Emulator,
+    debugger: Debugger,
+}
+
+/// Result type of script functions
+type ScriptResult<T> = Result<T, Box<EvalAltResult>>;
+
+/// Convert an emulator error into a script error
+fn script_error(error: EmulatorError) -> Box<EvalAltResult> {
+    error.to_string().into()
+}
+
+/// Convert a script integer into a register number
+fn register(n: INT) -> ScriptResult<usize> {
+    usize::try_from(n).map_err(|_| format!("Invalid register number: {}", n).into())
+}
+
+/// Execute bundles until `done` holds after one, or the guest stops
+fn run_until(
+    session: &mut Session,
+    mut done: impl FnMut(&Emulator) -> bool,
+) -> ScriptResult<String> {
+    loop {
+        if let Some(reason) = session.emulator.step().map_err(script_error)? {
+            return Ok(describe_stop(&session.emulator, reason));
+        }
+        if done(&session.emulator) {
+            return Ok(format!("ip {:#x}\n", session.emulator.cpu.ip));
+        }
+    }
+}
+
+/// Register the session functions with a script engine
+fn register_functions(engine: &mut Engine, session: &Rc<RefCell<Session>>) {
+    let s = session.clone();
+    engine.register_fn("ip", move || s.borrow().emulator.cpu.ip as INT);
+    let s = session.clone();
+    engine.register_fn("set_ip", move |ip: INT| {
+        s.borrow_mut().emulator.cpu.ip = ip as u64;
+    });
+
+    let s = session.clone();
+    engine.register_fn("gr", move |n: INT| -> ScriptResult<INT> {
+        let value = s.borrow().emulator.cpu.get_gr(register(n)?);
+        Ok(value.map_err(script_error)? as INT)
+    });
+    let s = session.clone();
+    engine.register_fn("set_gr", move |n: INT, value: INT| -> ScriptResult<()> {
+        let reg = register(n)?;
+        let result = s.borrow_mut().emulator.cpu.set_gr(reg, value as u64);
+        result.map_err(script_error)
+    });
+    let s = session.clone();
+    engine.register_fn("nat", move |n: INT| -> ScriptResult<bool> {
+        let nat = s.borrow().emulator.cpu.get_nat(register(n)?);
+        nat.map_err(script_error)
+    });
+    let s = session.clone();
+    engine.register_fn("pr", move |n: INT| -> ScriptResult<bool> {
+        let value = s.borrow().emulator.cpu.get_pr(register(n)?);
+        value.map_err(script_error)
+    });
+    let s = session.clone();
+    engine.register_fn("set_pr", move |n: INT, value: bool| -> ScriptResult<()> {
+        let reg = register(n)?;
+        let result = s.borrow_mut().emulator.cpu.set_pr(reg, value);
+        result.map_err(script_error)
+    });
+    let s = session.clone();
+    engine.register_fn("br", move |n: INT| -> ScriptResult<INT> {
+        let value = s.borrow().emulator.cpu.get_br(register(n)?);
+        Ok(value.map_err(script_error)? as INT)
+    });
+    let s = session.clone();
+    engine.register_fn("set_br", move |n: INT, value: INT| -> ScriptResult<()> {
+        let reg = register(n)?;
+        let result = s.borrow_mut().emulator.cpu.set_br(reg, value as u64);
+        result.map_err(script_error)
+    });
+    let s = session.clone();
+    engine.register_fn("ar", move |n: INT| -> ScriptResult<INT> {
+        let ar = application_register(n)?;
+        let value = s.borrow().emulator.cpu.read_ar(ar);
+        Ok(value.map_err(script_error)? as INT)
+    });
+    let s = session.clone();
+    engine.register_fn("set_ar", move |n: INT, value: INT| -> ScriptResult<()> {
+        let ar = application_register(n)?;
+        let result = s.borrow_mut().emulator.cpu.write_ar(ar, value as u64);
+        result.map_err(script_error)
+    });
+
+    let s = session.clone();
+    engine.register_fn("read_u8", move |addr: INT| -> ScriptResult<INT> {
+        let value = s.borrow_mut().emulator.memory.read_u8(addr as u64);
+        Ok(value.map_err(script_error)? as INT)
+    });
+    let s = session.clone();
+    engine.register_fn("read_u16", move |addr: INT| -> ScriptResult<INT> {
+        let value = s.borrow_mut().emulator.memory.read_u16(addr as u64);
+        Ok(value.map_err(script_error)? as INT)
+    });
+    let s = session.clone();
+    engine.register_fn("read_u32", move |addr: INT| -> ScriptResult<INT> {
+        let value = s.borrow_mut().emulator.memory.read_u32(addr as u64);
+        Ok(value.map_err(script_error)? as INT)
+    });
+    let s = session.clone();
+    engine.register_fn("read_u64", move |addr: INT| -> ScriptResult<INT> {
+        let value = s.borrow_mut().emulator.memory.read_u64(addr as u64);
+        Ok(value.map_err(script_error)? as INT)
+    });
+    let s = session.clone();
+    engine.register_fn(
+        "write_u8",
+        move |addr: INT, value: INT| -> ScriptResult<()> {
+            let result = s
+                .borrow_mut()
+                .emulator
+                .memory
+                .write_u8(addr as u64, value as u8);
+            result.map_err(script_error)
+        },
+    );
+    let s = session.clone();
+    engine.register_fn(
+        "write_u64",
+        move |addr: INT, value: INT| -> ScriptResult<()> {
+            let result = s
+                .borrow_mut()
+                .emulator
+                .memory
+                .write_u64(addr as u64, value as u64);
+            result.map_err(script_error)
+        },
+    );
+    let s = session.clone();
+    engine.register_fn("read_string", move |addr: INT| -> ScriptResult<String> {
+        let text = s
+            .borrow()
+            .emulator
+            .memory
+            .peek_c_string(addr as u64, MAX_STRING_LEN);
+        text.ok_or_else(|| format!("Cannot read string at {:#x}", addr).into())
+    });
+
+    let s = session.clone();
+    engine.register_fn("step", move || -> ScriptResult<String> {
+        run_until(&mut s.borrow_mut(), |_| true)
+    });
+    let s = session.clone();
+    engine.register_fn("step", move |count: INT| -> ScriptResult<String> {
+        let mut remaining = count;
+        run_until(&mut s.borrow_mut(), |_| {
+            remaining -= 1;
+            remaining <= 0
+        })
+    });
+    let s = session.clone();
+    engine.register_fn("run_to", move |addr: INT| -> ScriptResult<String> {
+        run_until(&mut s.borrow_mut(), |emulator| {
+            emulator.cpu.ip == addr as u64
+        })
+    });
+    let s = session.clone();
+    engine.register_fn("resume", move || -> ScriptResult<String> {
+        let mut session = s.borrow_mut();
+        let reason = session.emulator.run().map_err(script_error)?;
+        Ok(describe_stop(&session.emulator, reason))
+    });
+
+    let s = session.clone();
+    engine.register_fn("symbol", move |name: &str| -> ScriptResult<INT> {
+        match s.borrow().debugger.symbols.find(name) {
+            Some(symbol) => Ok(symbol.address as INT),
+            None => Err(format!("Unknown symbol: {}", name).into()),
+        }
+    });
+    let s = session.clone();
+    engine.register_fn("command", move |line: &str| -> ScriptResult<String> {
+        let mut session = s.borrow_mut();
+        let Session { emulator, debugger } = &mut *session;
+        debugger.execute(emulator, line).map_err(script_error)
+    });
+
+    engine.register_fn(
+        "assert",
+        |condition: bool, message: &str| -> ScriptResult<()> {
+            if condition {
+                Ok(())
+            } else {
+                Err(format!("Assertion failed: {}", message).into())
+            }
+        },
+    );
+    engine.register_fn("hex", |value: INT| format!("{:#x}", value as u64));
+}
+
+/// Look up an application register by number
+fn application_register(n: INT) -> ScriptResult<AR> {
+    u8::try_from(n)
+        .ok()
+        .and_then(AR::from_bits)
+        .ok_or_else(|| format!("Invalid application register: {}", n).into())
+}
+
+/// Run a script against the emulator
+///
+/// Returns the script's printed output. The emulator and debugger keep the
+/// changes the script made, even if it fails.
+pub fn run(
+    emulator: &mut Emulator,
+    debugger: &mut Debugger,
+    source: &str,
+) -> Result<String, EmulatorError> {
+    let session = Rc::new(RefCell::new(Session {
+        emulator: std::mem::take(emulator),
+        debugger: std::mem::take(debugger),
+    }));
+    let output = Rc::new(RefCell::new(String::new()));
+
+    let mut engine = Engine::new();
+    register_functions(&mut engine, &session);
+    let printed = output.clone();
+    engine.on_print(move |text| {
+        let mut printed = printed.borrow_mut();
+        printed.push_str(text);
+        if !text.ends_with('\n') {
+            printed.push('\n');
+        }
+    });
+    let printed = output.clone();
+    engine.on_debug(move |text, _, _| {
+        printed.borrow_mut().push_str(&format!("{}\n", text));
+    });
+
+    let result = engine.run(source);
+    drop(engine);
+
+    let Ok(session) = Rc::try_unwrap(session) else {
+        unreachable!("script engine dropped");
+    };
+    let session = session.into_inner();
+    *emulator = session.emulator;
+    *debugger = session.debugger;
+
+    result.map_err(|e| EmulatorError::ExecutionError(format!("Script error: {}", e)))?;
+    Ok(output.take())
+}
+
+/// Run a script file against the emulator
+///
+/// See [`run`].
+pub fn run_file(
+    emulator: &mut Emulator,
+    debugger: &mut Debugger,
+    path: &str,
+) -> Result<String, EmulatorError> {
+    let source = std::fs::read_to_string(path)
+        .map_err(|e| EmulatorError::ExecutionError(format!("Cannot read {}: {}", path, e)))?;
+    run(emulator, debugger, &source)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory::Permissions;
+    use crate::test_util::{encode_break_nop, encode_mii, nop};
+
+    const BASE: u64 = 0x10000;
+    const DATA: u64 = 0x20000;
+
+    /// MII bundle with a break in slot 0 and nops in slots 1 and 2
+    fn bundle(x6: u64, imm: u64) -> [u8; 16] {
+        encode_mii([encode_break_nop(0, x6, imm), nop(), nop()])
+    }
+
+    fn setup() -> (Emulator, Debugger) {
+        let mut emulator = Emulator::new();
+        let image: Vec<u8> = [bundle(0x01, 0), bundle(0x01, 0), bundle(0x00, 0x1234)]
+            .iter()
+            .flatten()
+            .copied()
+            .collect();
+        emulator.load_flat_image(BASE, &image, BASE).unwrap();
+        emulator
+            .memory
+            .map(DATA, 0x1000, Permissions::ReadWrite)
+            .unwrap();
+        emulator.memory.write_bytes(DATA, b"hello\0").unwrap();
+
+        let mut debugger = Debugger::new();
+        debugger.symbols.add("last", BASE + 0x20, 0x10);
+        (emulator, debugger)
+    }
+
+    #[test]
+    fn test_script_session() {
+        let (mut emulator, mut debugger) = setup();
+        let output = run(
+            &mut emulator,
+            &mut debugger,
+            r#"
+                print(run_to(symbol("last")));
+                assert(ip() == symbol("last"), "not at last");
+                set_gr(32, 0x55);
+                write_u64(0x20008, gr(32) + 1);
+                print(read_string(0x20000) + " " + hex(read_u64(0x20008)));
+                print(resume());
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(output, "ip 0x10020\nhello 0x56\nbreak 0x1234\n");
+        assert_eq!(emulator.cpu.gr[32], 0x55);
+        assert_eq!(debugger.symbols.len(), 1);
+    }
+
+    #[test]
+    fn test_script_failure() {
+        let (mut emulator, mut debugger) = setup();
+        let error = run(
+            &mut emulator,
+            &mut debugger,
+            r#"step(); assert(gr(8) == 1, "r8 not set");"#,
+        )
+        .unwrap_err();
+
+        assert!(error.to_string().contains("r8 not set"));
+        assert_eq!(emulator.cpu.ip, BASE + 0x10);
+        assert!(run(&mut emulator, &mut debugger, "gr(200)").is_err());
+        assert!(run(&mut emulator, &mut debugger, r#"symbol("nope")"#).is_err());
+
+        let output = run(&mut emulator, &mut debugger, r#"print(command("stats"))"#).unwrap();
+        assert!(output.starts_with("demand reads"));
+    }
+}
@@ -0,0 +1,634 @@
+//! Instruction self-test
+//!
+//! [`run`] assembles one encoding of each instruction the interpreter
+//! implements, executes it on a fresh CPU through the same dispatch the run
+//! loop uses, and checks what it did against the architecture: the break
+//! immediate it reported, whether and where it branched, the registers it
+//! wrote, or the fault it raised. Instructions whose behaviour depends on
+//! machine state, such as the checks and the privileged moves, have a case
+//! for each outcome. The [`SelfTestReport`] is a pass/fail matrix by
+//! instruction, which doubles as a list of what the emulator covers.
+
+use crate::asm;
+use crate::cpu::{Cpu, PSRFlags, USER_PRIVILEGE_LEVEL};
+use crate::decoder::{BundleTemplate, Unit};
+use crate::emulator::{decode_bundle, execute_instruction, Effect};
+use crate::memory::{Memory, Permissions};
+use crate::EmulatorError;
+use std::fmt;
+
+/// Address of the bundle holding the instruction under test
+const SELFTEST_IP: u64 = 0x4000_0000_0000_0000;
+
+/// Address in region 1, for the region register and TLB cases
+const REGION1_ADDR: u64 = 0x2000_0000_0000_4000;
+
+/// Recovery offset of the check cases, in bundles
+const RECOVERY_BUNDLES: u64 = 2;
+
+/// Target of a check's branch to recovery
+const RECOVERY: u64 = SELFTEST_IP + RECOVERY_BUNDLES * 16;
+
+/// Region register value with region ID 0x42 and 16KB pages
+const RR_VALUE: u64 = (0x42 << 8) | (14 << 2) | 1;
+
+/// What an instruction should do
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Expected {
+    /// Fall through to the next slot
+    Continue,
+    /// Report a break with this immediate
+    Break(u64),
+    /// Branch to this address
+    Branch(u64),
+    /// Fault as a privileged operation at user level
+    PrivilegeViolation,
+}
+
+/// One instruction encoding and its expected behaviour
+struct Case {
+    /// Assembly form
+    name: &'static str,
+    /// Unit the slot issues to
+    unit: Unit,
+    /// Raw 41-bit encoding
+    bits: u64,
+    /// Prepare the CPU, which starts privileged
+    setup: fn(&mut Cpu),
+    /// Effect on control flow
+    expected: Expected,
+    /// Check the state afterwards, returning what is wrong
+    check: fn(&Cpu) -> Result<(), String>,
+}
+
+/// Result of one self-test case
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SelfTestResult {
+    /// Assembly form of the instruction
+    pub name: &'static str,
+    /// Unit the slot issues to
+    pub unit: Unit,
+    /// Raw 41-bit encoding
+    pub bits: u64,
+    /// What went wrong, if the case failed
+    pub failure: Option<String>,
+}
+
+impl SelfTestResult {
+    /// Whether the instruction behaved as expected
+    pub fn passed(&self) -> bool {
+        self.failure.is_none()
+    }
+}
+
+/// Results of every self-test case
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SelfTestReport {
+    /// Results in case order
+    pub results: Vec<SelfTestResult>,
+}
+
+impl SelfTestReport {
+    /// Number of cases that passed
+    pub fn passed(&self) -> usize {
+        self.results.iter().filter(|r| r.passed()).count()
+    }
+
+    /// Number of cases that failed
+    pub fn failed(&self) -> usize {
+        self.results.len() - self.passed()
+    }
+
+    /// Whether every case passed
+    pub fn all_passed(&self) -> bool {
+        self.failed() == 0
+    }
+}
+
+impl fmt::Display for SelfTestReport {
+    /// One line per case with its unit, encoding and result, then totals
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let width = self.results.iter().map(|r| r.name.len()).max().unwrap_or(0);
+        for result in &self.results {
+            write!(
+                f,
+                "{:<width$}  {:<6}  {:#013x}  ",
+                result.name,
+                result.unit.to_string(),
+                result.bits,
+                width = width
+            )?;
+            match &result.failure {
+                None => writeln!(f, "pass")?,
+                Some(failure) => writeln!(f, "FAIL: {}", failure)?,
+            }
+        }
+        writeln!(f, "{} passed, {} failed", self.passed(), self.failed())
+    }
+}
+
+/// Run every case
+pub fn run() -> SelfTestReport {
+    SelfTestReport {
+        results: cases()
+            .iter()
+            .map(|case| SelfTestResult {
+                name: case.name,
+                unit: case.unit,
+                bits: case.bits,
+                failure: run_case(case).err(),
+            })
+            .collect(),
+    }
+}
+
+/// Execute a case's instruction and compare the outcome
+fn run_case(case: &Case) -> Result<(), String> {
+    let (data, slot) = bundle(case.unit, case.bits);
+    let decoded = decode_bundle(data).map_err(|e| e.to_string())?;
+    let (itype, bits) = *decoded
+        .slots
+        .get(slot)
+        .ok_or("slot missing from the decoded bundle")?;
+
+    let mut cpu = Cpu::new();
+    cpu.pr[0] = true;
+    cpu.set_privilege_level(0);
+    cpu.ip = SELFTEST_IP;
+    cpu.slot = decoded.restart_slot(slot);
+    (case.setup)(&mut cpu);
+
+    let mut memory = Memory::new();
+    let actual = match execute_instruction(&mut cpu, &mut memory, &itype, bits) {
+        Ok(Effect::Continue) => Expected::Continue,
+        Ok(Effect::Break(imm)) => Expected::Break(imm),
+        Ok(Effect::Branch | Effect::Return(_)) => Expected::Branch(cpu.ip),
+        Err(EmulatorError::PrivilegeViolation) => Expected::PrivilegeViolation,
+        Err(e) => return Err(e.to_string()),
+    };
+    if actual != case.expected {
+        return Err(format!("expected {:?}, got {:?}", case.expected, actual));
+    }
+    (case.check)(&cpu)
+}
+
+/// Compare a value read back from the CPU
+fn expect(what: &str, actual: u64, expected: u64) -> Result<(), String> {
+    if actual == expected {
+        Ok(())
+    } else {
+        Err(format!(
+            "{} is {:#x}, expected {:#x}",
+            what, actual, expected
+        ))
+    }
+}
+
+/// Check of a case that leaves no state to inspect
+fn nothing(_: &Cpu) -> Result<(), String> {
+    Ok(())
+}
+
+/// Drop to user level
+fn user(cpu: &mut Cpu) {
+    cpu.set_privilege_level(USER_PRIVILEGE_LEVEL);
+}
+
+/// Bundle holding a slot, with no-ops in the other slots, and the slot's
+/// index
+fn bundle(unit: Unit, bits: u64) -> ([u8; 16], usize) {
+    let nop = break_nop(0, 0x01, 0);
+    let (template, slots, slot) = match unit {
+        Unit::M => (BundleTemplate::MII, [bits, nop, nop], 0),
+        Unit::I | Unit::A => (BundleTemplate::MII, [nop, bits, nop], 1),
+        Unit::B => (BundleTemplate::MIB, [nop, nop, bits], 2),
+        Unit::F => (BundleTemplate::MMF, [nop, nop, bits], 2),
+        Unit::L | Unit::X => (BundleTemplate::MLX, [nop, 0, bits], 2),
+    };
+    (asm::encode_bundle(template, slots), slot)
+}
+
+/// Encode break or nop with a 21-bit immediate
+fn break_nop(major: u64, x6: u64, imm: u64) -> u64 {
+    (major << 37) | (((imm >> 20) & 1) << 36) | (x6 << 27) | ((imm & 0xFFFFF) << 6)
+}
+
+/// Encode chk.s on register `reg`
+fn chk_s(major: u64, x3: u64, reg: u64) -> u64 {
+    let imm = RECOVERY_BUNDLES;
+    (major << 37) | (x3 << 33) | (((imm >> 7) & 0x1FFF) << 20) | (reg << 13) | ((imm & 0x7F) << 6)
+}
+
+/// Encode chk.a on register `reg`
+fn chk_a(x3: u64, reg: u64) -> u64 {
+    (x3 << 33) | (RECOVERY_BUNDLES << 13) | (reg << 6)
+}
+
+/// Encode an M-unit system move with major opcode 1
+fn system(x6: u64, r1: u64, r2: u64, r3: u64) -> u64 {
+    (1 << 37) | (x6 << 27) | (r3 << 20) | (r2 << 13) | (r1 << 6)
+}
+
+/// Every case, grouped by unit
+fn cases() -> Vec<Case> {
+    vec![
+        // M unit
+        Case {
+            name: "break.m 0x12345",
+            unit: Unit::M,
+            bits: break_nop(0, 0x00, 0x12345),
+            setup: |_| {},
+            expected: Expected::Break(0x12345),
+            check: nothing,
+        },
+        Case {
+            name: "nop.m 0",
+            unit: Unit::M,
+            bits: break_nop(0, 0x01, 0),
+            setup: |_| {},
+            expected: Expected::Continue,
+            check: |cpu| expect("ip", cpu.ip, SELFTEST_IP),
+        },
+        Case {
+            name: "chk.s.m r5 (NaT)",
+            unit: Unit::M,
+            bits: chk_s(1, 1, 5),
+            setup: |cpu| cpu.nat[5] = true,
+            expected: Expected::Branch(RECOVERY),
+            check: nothing,
+        },
+        Case {
+            name: "chk.s.m f5",
+            unit: Unit::M,
+            bits: chk_s(1, 3, 5),
+            setup: |_| {},
+            expected: Expected::Continue,
+            check: nothing,
+        },
+        Case {
+            name: "chk.a.nc r6 (no entry)",
+            unit: Unit::M,
+            bits: chk_a(4, 6),
+            setup: |_| {},
+            expected: Expected::Branch(RECOVERY),
+            check: nothing,
+        },
+        Case {
+            name: "chk.a.clr r6",
+            unit: Unit::M,
+            bits: chk_a(5, 6),
+            setup: |cpu| cpu.alat_add_entry(0x1000, 8, 6, true).unwrap(),
+            expected: Expected::Continue,
+            check: |cpu| match cpu.alat_check_register(6, true) {
+                false => Ok(()),
+                true => Err("ALAT entry was not cleared".to_string()),
+            },
+        },
+        Case {
+            name: "chk.a.nc f6",
+            unit: Unit::M,
+            bits: chk_a(6, 6),
+            setup: |cpu| cpu.alat_add_entry(0x1000, 8, 6, false).unwrap(),
+            expected: Expected::Continue,
+            check: |cpu| match cpu.alat_check_register(6, false) {
+                true => Ok(()),
+                false => Err("ALAT entry was cleared".to_string()),
+            },
+        },
+        Case {
+            name: "chk.a.clr f6 (no entry)",
+            unit: Unit::M,
+            bits: chk_a(7, 6),
+            setup: |_| {},
+            expected: Expected::Branch(RECOVERY),
+            check: nothing,
+        },
+        Case {
+            name: "mov rr[r3]=r2",
+            unit: Unit::M,
+            bits: system(0x00, 0, 2, 3),
+            setup: |cpu| {
+                cpu.gr[2] = RR_VALUE;
+                cpu.gr[3] = REGION1_ADDR;
+            },
+            expected: Expected::Continue,
+            check: |cpu| expect("rr1.rid", cpu.get_region_id(REGION1_ADDR).unwrap(), 0x42),
+        },
+        Case {
+            name: "mov rr[r3]=r2 (user)",
+            unit: Unit::M,
+            bits: system(0x00, 0, 2, 3),
+            setup: user,
+            expected: Expected::PrivilegeViolation,
+            check: nothing,
+        },
+        Case {
+            name: "mov r8=rr[r3]",
+            unit: Unit::M,
+            bits: system(0x10, 8, 0, 3),
+            setup: |cpu| {
+                cpu.write_rr(1, RR_VALUE).unwrap();
+                cpu.gr[3] = REGION1_ADDR;
+            },
+            expected: Expected::Continue,
+            check: |cpu| expect("r8", cpu.gr[8], RR_VALUE),
+        },
+        Case {
+            name: "ptc.l r3,r2",
+            unit: Unit::M,
+            bits: system(0x09, 0, 2, 3),
+            setup: |cpu| {
+                cpu.insert_translation(REGION1_ADDR, 0x10_0000, 14, Permissions::ReadWrite)
+                    .unwrap();
+                cpu.gr[2] = 14 << 2;
+                cpu.gr[3] = REGION1_ADDR;
+            },
+            expected: Expected::Continue,
+            check: |cpu| expect("TLB entries", cpu.tlb.len() as u64, 0),
+        },
+        Case {
+            name: "ptc.e r3",
+            unit: Unit::M,
+            bits: system(0x34, 0, 0, 3),
+            setup: |cpu| {
+                cpu.insert_translation(REGION1_ADDR, 0x10_0000, 14, Permissions::ReadWrite)
+                    .unwrap();
+            },
+            expected: Expected::Continue,
+            check: |cpu| expect("TLB entries", cpu.tlb.len() as u64, 0),
+        },
+        Case {
+            name: "mov pmc[r3]=r2",
+            unit: Unit::M,
+            bits: system(0x04, 0, 2, 3),
+            setup: |cpu| {
+                cpu.gr[2] = 0x1208;
+                cpu.gr[3] = 4;
+            },
+            expected: Expected::Continue,
+            check: |cpu| expect("pmc4", cpu.pmu.read_pmc(4), 0x1208),
+        },
+        Case {
+            name: "mov pmd[r3]=r2",
+            unit: Unit::M,
+            bits: system(0x05, 0, 2, 3),
+            setup: |cpu| {
+                cpu.gr[2] = 1234;
+                cpu.gr[3] = 5;
+            },
+            expected: Expected::Continue,
+            check: |cpu| expect("pmd5", cpu.pmu.read_pmd(5), 1234),
+        },
+        Case {
+            name: "mov r8=pmc[r3]",
+            unit: Unit::M,
+            bits: system(0x14, 8, 0, 3),
+            setup: |cpu| {
+                cpu.pmu.write_pmc(4, 0x1208);
+                cpu.gr[3] = 4;
+            },
+            expected: Expected::Continue,
+            check: |cpu| expect("r8", cpu.gr[8], 0x1208),
+        },
+        Case {
+            name: "mov r8=pmd[r3] (user)",
+            unit: Unit::M,
+            bits: system(0x15, 8, 0, 3),
+            setup: |cpu| {
+                cpu.pmu.write_pmd(5, 1234);
+                cpu.gr[3] = 5;
+                user(cpu);
+            },
+            expected: Expected::Continue,
+            check: |cpu| expect("r8", cpu.gr[8], 1234),
+        },
+        Case {
+            name: "mov r8=pmd[r3] (user, PSR.sp)",
+            unit: Unit::M,
+            bits: system(0x15, 8, 0, 3),
+            setup: |cpu| {
+                cpu.pmu.write_pmd(5, 1234);
+                cpu.gr[3] = 5;
+                cpu.gr[8] = 1;
+                user(cpu);
+                cpu.system_regs.cr.set(PSRFlags::SP, true);
+            },
+            expected: Expected::Continue,
+            check: |cpu| expect("r8", cpu.gr[8], 0),
+        },
+        // I unit
+        Case {
+            name: "break.i 0x80000",
+            unit: Unit::I,
+            bits: break_nop(0, 0x00, 0x80000),
+            setup: |_| {},
+            expected: Expected::Break(0x80000),
+            check: nothing,
+        },
+        Case {
+            name: "nop.i 0",
+            unit: Unit::I,
+            bits: break_nop(0, 0x01, 0),
+            setup: |_| {},
+            expected: Expected::Continue,
+            check: |cpu| expect("ip", cpu.ip, SELFTEST_IP),
+        },
+        Case {
+            name: "mov r14=ip",
+            unit: Unit::I,
+            bits: (0x30 << 27) | (14 << 6),
+            setup: |_| {},
+            expected: Expected::Continue,
+            check: |cpu| expect("r14", cpu.gr[14], SELFTEST_IP),
+        },
+        Case {
+            name: "chk.s.i r5 (NaT)",
+            unit: Unit::I,
+            bits: chk_s(0, 1, 5),
+            setup: |cpu| cpu.nat[5] = true,
+            expected: Expected::Branch(RECOVERY),
+            check: nothing,
+        },
+        Case {
+            name: "chk.s.i r5",
+            unit: Unit::I,
+            bits: chk_s(0, 1, 5),
+            setup: |_| {},
+            expected: Expected::Continue,
+            check: nothing,
+        },
+        Case {
+            name: "add r8=r2,r3",
+            unit: Unit::I,
+            bits: (8 << 37) | (3 << 20) | (2 << 13) | (8 << 6),
+            setup: |cpu| {
+                cpu.gr[2] = 5;
+                cpu.gr[3] = u64::MAX;
+            },
+            expected: Expected::Continue,
+            check: |cpu| expect("r8", cpu.gr[8], 4),
+        },
+        Case {
+            name: "adds r8=-2,r3",
+            unit: Unit::I,
+            bits: (8 << 37)
+                | (1 << 36)
+                | (2 << 34)
+                | (0x3F << 27)
+                | (3 << 20)
+                | (0x7E << 13)
+                | (8 << 6),
+            setup: |cpu| cpu.gr[3] = 10,
+            expected: Expected::Continue,
+            check: |cpu| expect("r8", cpu.gr[8], 8),
+        },
+        Case {
+            name: "addl r8=0x12345,r2",
+            unit: Unit::I,
+            bits: (9 << 37) | (0x46 << 27) | (1 << 22) | (2 << 20) | (0x45 << 13) | (8 << 6),
+            setup: |cpu| cpu.gr[2] = 0x1000,
+            expected: Expected::Continue,
+            check: |cpu| expect("r8", cpu.gr[8], 0x13345),
+        },
+        Case {
+            name: "cmp.eq p6,p7=r2,r3",
+            unit: Unit::I,
+            bits: (0xE << 37) | (7 << 27) | (3 << 20) | (2 << 13) | (6 << 6),
+            setup: |cpu| {
+                cpu.gr[2] = 42;
+                cpu.gr[3] = 42;
+            },
+            expected: Expected::Continue,
+            check: |cpu| match (cpu.pr[6], cpu.pr[7]) {
+                (true, false) => Ok(()),
+                predicates => Err(format!("p6,p7 are {:?}", predicates)),
+            },
+        },
+        Case {
+            name: "sxt1 r8=r2",
+            unit: Unit::I,
+            bits: (0x14 << 27) | (2 << 20) | (8 << 6),
+            setup: |cpu| cpu.gr[2] = 0x1280,
+            expected: Expected::Continue,
+            check: |cpu| expect("r8", cpu.gr[8], 0xFFFF_FFFF_FFFF_FF80),
+        },
+        // F unit
+        Case {
+            name: "break.f 0x1fffff",
+            unit: Unit::F,
+            bits: break_nop(0, 0x00, 0x1F_FFFF),
+            setup: |_| {},
+            expected: Expected::Break(0x1F_FFFF),
+            check: nothing,
+        },
+        Case {
+            name: "nop.f 0",
+            unit: Unit::F,
+            bits: break_nop(0, 0x01, 0),
+            setup: |_| {},
+            expected: Expected::Continue,
+            check: nothing,
+        },
+        // B unit
+        Case {
+            name: "break.b 0x42",
+            unit: Unit::B,
+            bits: break_nop(0, 0x00, 0x42),
+            setup: |_| {},
+            expected: Expected::Break(0x42),
+            check: nothing,
+        },
+        Case {
+            name: "nop.b 0",
+            unit: Unit::B,
+            bits: break_nop(2, 0x00, 0),
+            setup: |_| {},
+            expected: Expected::Continue,
+            check: nothing,
+        },
+        Case {
+            name: "br.cond.sptk.few +0x20",
+            unit: Unit::B,
+            bits: (4 << 37) | (RECOVERY_BUNDLES << 13),
+            setup: |_| {},
+            expected: Expected::Branch(RECOVERY),
+            check: nothing,
+        },
+        Case {
+            name: "br.cond.sptk.few b6",
+            unit: Unit::B,
+            bits: (0x20 << 27) | (6 << 13),
+            setup: |cpu| cpu.br[6] = RECOVERY,
+            expected: Expected::Branch(RECOVERY),
+            check: nothing,
+        },
+        Case {
+            name: "epc (promotion page)",
+            unit: Unit::B,
+            bits: 0x10 << 27,
+            setup: |cpu| {
+                user(cpu);
+                cpu.pfs = 3 << 62;
+                cpu.promote_pages.push(SELFTEST_IP..SELFTEST_IP + 16);
+            },
+            expected: Expected::Continue,
+            check: |cpu| expect("PSR.cpl", cpu.privilege_level() as u64, 0),
+        },
+        // X unit, with a zero L slot
+        Case {
+            name: "break.x 0x1234",
+            unit: Unit::X,
+            bits: break_nop(0, 0x00, 0x1234),
+            setup: |_| {},
+            expected: Expected::Break(0x1234),
+            check: nothing,
+        },
+        Case {
+            name: "nop.x 0",
+            unit: Unit::X,
+            bits: break_nop(0, 0x01, 0),
+            setup: |_| {},
+            expected: Expected::Continue,
+            check: nothing,
+        },
+        Case {
+            name: "hint.x 0",
+            unit: Unit::X,
+            bits: break_nop(0, 0x01, 0) | (1 << 26),
+            setup: |_| {},
+            expected: Expected::Continue,
+            check: nothing,
+        },
+        Case {
+            name: "movl r8=0x212345",
+            unit: Unit::X,
+            bits: (6 << 37) | (0x46 << 27) | (1 << 22) | (1 << 21) | (0x45 << 13) | (8 << 6),
+            setup: |_| {},
+            expected: Expected::Continue,
+            check: |cpu| expect("r8", cpu.gr[8], 0x21_2345),
+        },
+        Case {
+            name: "brl.sptk.few +0x20",
+            unit: Unit::X,
+            bits: (0xC << 37) | (RECOVERY_BUNDLES << 13),
+            setup: |_| {},
+            expected: Expected::Branch(RECOVERY),
+            check: nothing,
+        },
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_selftest() {
+        let report = run();
+        assert!(report.all_passed(), "{}", report);
+        assert_eq!(report.results.len(), cases().len());
+        let text = report.to_string();
+        assert!(text.contains("mov r14=ip"));
+        assert!(text.ends_with(&format!("{} passed, 0 failed\n", report.results.len())));
+    }
+}
@@ -0,0 +1,491 @@
+//! Pure instruction semantics
+//!
+//! [`evaluate`] executes a single instruction against a register snapshot
+//! and returns what it changed, without a machine: no memory, devices,
+//! system calls or timers are involved, so loads and stores fault, and the
+//! snapshot itself is left untouched. This suits comparing the emulator's
+//! semantics instruction by instruction against an SMT model or another
+//! emulator, or fuzzing them.
+//!
+//! The snapshot holds the general, floating-point, predicate and branch
+//! registers, the application registers of the AR file with AR.PFS, CFM
+//! and PSR, so privileged instructions run at the privilege level it
+//! gives. An instruction that changes anything else, such as the register
+//! stack engine, AR.ITC, the other control registers or the TLB, is an
+//! error rather than a delta missing part of its effect.
+//!
+//! The evaluator shares its dispatch with the run loop, so it covers the
+//! same instructions. A break is reported as a [`Trap`] rather than acted
+//! on, even with the system call immediate.
+//!
+//! The instructions do not execute on the snapshot itself: each call resets
+//! a full [`Cpu`] kept per thread, with a memory that has nothing mapped,
+//! loads the snapshot into it, executes there and compares the result
+//! with the snapshot to build the delta. Every evaluation pays for that
+//! copy in and out, and changes outside the snapshot are caught after the
+//! instruction made them, on the scratch CPU, rather than prevented.
+
+use crate::cpu::interrupts::InterruptControllerState;
+use crate::cpu::registers::ar::NUM_AR;
+use crate::cpu::registers::cr::NUM_CR;
+use crate::cpu::registers::fpsr::DEFAULT_FPSR;
+use crate::cpu::registers::{CRIndex, FloatRegister, AR};
+use crate::cpu::rse::{RseState, RSE};
+use crate::cpu::timer::IntervalTimer;
+use crate::cpu::tlb::TlbEntry;
+use crate::cpu::{
+    Cpu, INITIAL_FR, NUM_BR, NUM_FR, NUM_GR, NUM_PR, PSR_CPL_SHIFT, USER_PRIVILEGE_LEVEL,
+};
+use crate::decoder::InstructionType;
+use crate::emulator::{decode_bundle, execute_instruction, Effect};
+use crate::memory::Memory;
+use crate::EmulatorError;
+use std::cell::RefCell;
+
+thread_local! {
+    /// CPU the instructions run on, reused because building one is costly,
+    /// and memory with nothing mapped
+    static SCRATCH: RefCell<(Cpu, Memory)> = RefCell::new((Cpu::new(), Memory::new()));
+}
+
+/// Number of AR.PFS, which lives outside the AR file
+const AR_PFS: u8 = 64;
+
+/// Application registers the snapshot holds
+const SNAPSHOT_AR: [u8; 13] = [
+    AR::KR1 as u8,
+    AR::KR2 as u8,
+    AR::KR3 as u8,
+    AR::KR4 as u8,
+    AR::KR5 as u8,
+    AR::KR6 as u8,
+    AR::KR7 as u8,
+    AR::CCV as u8,
+    AR::UNAT as u8,
+    AR::FPSR as u8,
+    AR_PFS,
+    AR::LC as u8,
+    AR::EC as u8,
+];
+
+/// Decoded instruction slot
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DecodedInstruction {
+    /// Slot type assigned by the bundle template, with format details
+    pub itype: InstructionType,
+    /// Raw 41-bit encoding
+    pub bits: u64,
+    /// Slot within the bundle, as PSR.ri numbers it: the two halves of an
+    /// L+X instruction are both slot 1
+    pub slot: u8,
+}
+
+impl DecodedInstruction {
+    /// Decode every slot of a bundle
+    pub fn decode_bundle(data: [u8; 16]) -> Result<Vec<Self>, EmulatorError> {
+        let decoded = decode_bundle(data)?;
+        Ok(decoded
+            .slots
+            .iter()
+            .enumerate()
+            .map(|(slot, &(itype, bits))| Self {
+                itype,
+                bits,
+                slot: decoded.restart_slot(slot),
+            })
+            .collect())
+    }
+}
+
+/// Architectural register state an instruction is evaluated on
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ArchState {
+    /// Address of the bundle holding the instruction
+    pub ip: u64,
+    /// General registers
+    pub gr: [u64; NUM_GR],
+    /// NaT bits of the general registers
+    pub nat: [bool; NUM_GR],
+    /// Floating-point registers
+    pub fr: [FloatRegister; NUM_FR],
+    /// Predicate registers
+    pub pr: [bool; NUM_PR],
+    /// Branch registers
+    pub br: [u64; NUM_BR],
+    /// Application registers by number, AR.PFS at 64
+    ///
+    /// Only those of the AR file and AR.PFS are held: the register stack
+    /// engine's and AR.ITC read as their reset values, and the other
+    /// numbers are ignored.
+    pub ar: [u64; NUM_AR],
+    /// Current frame marker
+    pub cfm: u64,
+    /// Processor status register, privilege level included
+    pub psr: u64,
+}
+
+impl Default for ArchState {
+    fn default() -> Self {
+        let mut pr = [false; NUM_PR];
+        // p0 is hardwired to 1
+        pr[0] = true;
+        let mut ar = [0; NUM_AR];
+        ar[AR::FPSR as usize] = DEFAULT_FPSR;
+        Self {
+            ip: 0,
+            gr: [0; NUM_GR],
+            nat: [false; NUM_GR],
+            fr: INITIAL_FR,
+            pr,
+            br: [0; NUM_BR],
+            ar,
+            cfm: 0,
+            psr: (USER_PRIVILEGE_LEVEL as u64) << PSR_CPL_SHIFT,
+        }
+    }
+}
+
+impl ArchState {
+    /// Snapshot the registers of a CPU
+    pub fn from_cpu(cpu: &Cpu) -> Self {
+        let mut ar = [0; NUM_AR];
+        for index in SNAPSHOT_AR {
+            ar[index as usize] = match AR::from_bits(index) {
+                Some(reg) => cpu.system_regs.ar.read(reg).unwrap_or_default(),
+                None => cpu.pfs,
+            };
+        }
+        Self {
+            ip: cpu.ip,
+            gr: cpu.gr,
+            nat: cpu.nat,
+            fr: cpu.fr,
+            pr: cpu.pr,
+            br: cpu.br,
+            ar,
+            cfm: cpu.cfm,
+            psr: cpu.get_psr(),
+        }
+    }
+
+    /// Load the snapshot into a CPU
+    fn load(&self, cpu: &mut Cpu) -> Result<(), EmulatorError> {
+        cpu.ip = self.ip;
+        cpu.gr = self.gr;
+        cpu.nat = self.nat;
+        cpu.fr = self.fr;
+        cpu.pr = self.pr;
+        cpu.br = self.br;
+        for index in SNAPSHOT_AR {
+            let value = self.ar[index as usize];
+            match AR::from_bits(index) {
+                Some(reg) => cpu.system_regs.ar.write(reg, value)?,
+                None => cpu.pfs = value,
+            }
+        }
+        cpu.cfm = self.cfm;
+        cpu.system_regs.cr.update(|_| self.psr);
+        Ok(())
+    }
+
+    /// Apply the changes made by an instruction
+    pub fn apply(&mut self, delta: &ArchStateDelta) {
+        for &(location, value) in &delta.changes {
+            match location {
+                Location::Ip => self.ip = value as u64,
+                Location::Gr(reg) => self.gr[reg as usize] = value as u64,
+                Location::Nat(reg) => self.nat[reg as usize] = value != 0,
+                Location::Fr(reg) => {
+                    self.fr[reg as usize] = FloatRegister::from_spill(value.to_le_bytes())
+                }
+                Location::Pr(reg) => self.pr[reg as usize] = value != 0,
+                Location::Br(reg) => self.br[reg as usize] = value as u64,
+                Location::Ar(reg) => self.ar[reg as usize] = value as u64,
+                Location::Cfm => self.cfm = value as u64,
+                Location::Psr => self.psr = value as u64,
+            }
+        }
+    }
+
+    /// Locations whose values differ in `after`, with their new values
+    fn changes(&self, after: &ArchState) -> Vec<(Location, u128)> {
+        let mut changes = Vec::new();
+        if self.ip != after.ip {
+            changes.push((Location::Ip, after.ip as u128));
+        }
+        let mut diff = |location: fn(u8) -> Location, before: &[u128], after: &[u128]| {
+            for (reg, (a, b)) in before.iter().zip(after).enumerate() {
+                if a != b {
+                    changes.push((location(reg as u8), *b));
+                }
+            }
+        };
+        let wide = |values: &[u64]| values.iter().map(|&v| v as u128).collect::<Vec<_>>();
+        diff(Location::Gr, &wide(&self.gr), &wide(&after.gr));
+        let spills = |fr: &[FloatRegister]| {
+            fr.iter()
+                .map(|fr| u128::from_le_bytes(fr.to_spill()))
+                .collect::<Vec<_>>()
+        };
+        diff(Location::Fr, &spills(&self.fr), &spills(&after.fr));
+        diff(Location::Br, &wide(&self.br), &wide(&after.br));
+        let bits = |bits: &[bool]| bits.iter().map(|&bit| bit as u128).collect::<Vec<_>>();
+        diff(Location::Nat, &bits(&self.nat), &bits(&after.nat));
+        diff(Location::Pr, &bits(&self.pr), &bits(&after.pr));
+        for index in SNAPSHOT_AR {
+            let value = after.ar[index as usize];
+            if self.ar[index as usize] != value {
+                changes.push((Location::Ar(index), value as u128));
+            }
+        }
+        if self.cfm != after.cfm {
+            changes.push((Location::Cfm, after.cfm as u128));
+        }
+        if self.psr != after.psr {
+            changes.push((Location::Psr, after.psr as u128));
+        }
+        changes
+    }
+}
+
+/// Machine state outside the snapshot, compared around an instruction to
+/// catch effects the snapshot cannot record
+struct Unrecorded {
+    /// Control registers other than the PSR
+    cr: Vec<u64>,
+    /// Region, protection key, debug and performance monitor registers,
+    /// as printed
+    registers: String,
+    /// Register stack engine, behind AR.RSC, BSP, BSPSTORE and RNAT
+    rse: RseState,
+    /// AR.ITC
+    itc: u64,
+    /// Valid ALAT entries
+    alat: usize,
+    /// Translations
+    tlb: Vec<TlbEntry>,
+    /// Interrupt controller
+    interrupts: InterruptControllerState,
+}
+
+impl Unrecorded {
+    /// Capture the state of a CPU
+    fn capture(cpu: &Cpu) -> Self {
+        let regs = &cpu.system_regs;
+        Self {
+            cr: (1..NUM_CR as u8)
+                .filter_map(CRIndex::from_bits)
+                .map(|index| cpu.read_cr(index))
+                .collect(),
+            registers: format!(
+                "{:?}",
+                (&regs.rr, &regs.pkr, &regs.dbr, &regs.ddr, &cpu.pmu)
+            ),
+            rse: cpu.rse.state(),
+            itc: cpu.timer.read_itc(),
+            alat: cpu.alat.valid_entries(),
+            tlb: cpu.tlb.entries().copied().collect(),
+            interrupts: cpu.interrupt_ctrl.state(),
+        }
+    }
+
+    /// Name of the first part that differs in `after`
+    fn difference(&self, after: &Self) -> Option<&'static str> {
+        [
+            (self.cr != after.cr, "control registers"),
+            (self.registers != after.registers, "system registers"),
+            (self.rse != after.rse, "register stack engine"),
+            (self.itc != after.itc, "AR.ITC"),
+            (self.alat != after.alat, "ALAT"),
+            (self.tlb != after.tlb, "TLB"),
+            (self.interrupts != after.interrupts, "interrupt state"),
+        ]
+        .into_iter()
+        .find_map(|(differs, part)| differs.then_some(part))
+    }
+}
+
+/// Register written by an instruction
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Location {
+    /// Instruction pointer, changed by taken branches
+    Ip,
+    /// General register
+    Gr(u8),
+    /// NaT bit of a general register, 0 or 1
+    Nat(u8),
+    /// Floating-point register, valued as its 16-byte spill image
+    /// (stf.spill), which keeps all 82 bits and NaTVal
+    Fr(u8),
+    /// Predicate register, 0 or 1
+    Pr(u8),
+    /// Branch register
+    Br(u8),
+    /// Application register by number, AR.PFS as 64
+    Ar(u8),
+    /// Current frame marker
+    Cfm,
+    /// Processor status register
+    Psr,
+}
+
+/// Event that would transfer control to the machine
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Trap {
+    /// Break instruction with its immediate
+    Break(u64),
+}
+
+/// Effect of evaluating one instruction
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ArchStateDelta {
+    /// Locations whose value changed, with their new values
+    pub changes: Vec<(Location, u128)>,
+    /// Trap raised by the instruction, if any
+    pub trap: Option<Trap>,
+}
+
+/// Evaluate one instruction on a register snapshot
+///
+/// Returns the registers it changed; an unimplemented or malformed
+/// instruction is an error, and so is one changing state outside the
+/// snapshot.
+pub fn evaluate(
+    instr: &DecodedInstruction,
+    state: &ArchState,
+) -> Result<ArchStateDelta, EmulatorError> {
+    SCRATCH.with(|scratch| {
+        let (cpu, memory) = &mut *scratch.borrow_mut();
+        cpu.reset()?;
+        cpu.rse = RSE::new();
+        cpu.timer = IntervalTimer::new();
+        cpu.alat.clear();
+        state.load(cpu)?;
+        cpu.slot = instr.slot;
+
+        let before = Unrecorded::capture(cpu);
+        let trap = match execute_instruction(cpu, memory, &instr.itype, instr.bits)? {
+            Effect::Continue | Effect::Branch | Effect::Return(_) => None,
+            Effect::Break(imm) => Some(Trap::Break(imm)),
+        };
+        if let Some(part) = before.difference(&Unrecorded::capture(cpu)) {
+            return Err(EmulatorError::ExecutionError(format!(
+                "Instruction changes the {} outside the snapshot",
+                part
+            )));
+        }
+        Ok(ArchStateDelta {
+            changes: state.changes(&ArchState::from_cpu(cpu)),
+            trap,
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm::BundleBuilder;
+    use crate::emulator::{Emulator, SYSCALL_BREAK_IMM};
+    use crate::test_util::{encode_break_nop, encode_mii, mov_ip};
+
+    const BASE: u64 = 0x10000;
+
+    #[test]
+    fn test_evaluate() {
+        let slots = DecodedInstruction::decode_bundle(encode_mii([
+            encode_break_nop(0, 0, SYSCALL_BREAK_IMM),
+            mov_ip(0, 14),
+            mov_ip(3, 15),
+        ]))
+        .unwrap();
+        let mut state = ArchState {
+            ip: BASE,
+            ..ArchState::default()
+        };
+        state.gr[15] = 7;
+
+        let delta = evaluate(&slots[1], &state).unwrap();
+        assert_eq!(delta.changes, [(Location::Gr(14), BASE as u128)]);
+        assert_eq!(delta.trap, None);
+
+        // False predicate, then true
+        assert_eq!(
+            evaluate(&slots[2], &state).unwrap(),
+            ArchStateDelta::default()
+        );
+        let mut predicated = state.clone();
+        predicated.pr[3] = true;
+        let delta = evaluate(&slots[2], &predicated).unwrap();
+        assert_eq!(delta.changes, [(Location::Gr(15), BASE as u128)]);
+        predicated.apply(&delta);
+        assert_eq!(predicated.gr[15], BASE);
+
+        // No system call is made, and the snapshot is untouched
+        let delta = evaluate(&slots[0], &state).unwrap();
+        assert_eq!(delta.trap, Some(Trap::Break(SYSCALL_BREAK_IMM)));
+        assert!(delta.changes.is_empty());
+        assert_eq!(state.gr[15], 7);
+
+        let unimplemented = DecodedInstruction {
+            bits: 3 << 37,
+            ..slots[0]
+        };
+        assert!(evaluate(&unimplemented, &state).is_err());
+    }
+
+    #[test]
+    fn test_evaluate_full_state() {
+        let decode = |insns: [&str; 3]| {
+            let builder = insns
+                .into_iter()
+                .fold(BundleBuilder::new(), |builder, insn| builder.insn(insn));
+            DecodedInstruction::decode_bundle(builder.build().unwrap()).unwrap()
+        };
+        let slots = decode(["ssm 0x2000", "fma f6 = f5, f1, f0", "mov ar.lc = r2"]);
+        let mut state = ArchState::default();
+        state.gr[2] = 99;
+        state.fr[5] = FloatRegister::NATVAL;
+
+        // Application registers and NaTVal come through whole
+        let delta = evaluate(&slots[2], &state).unwrap();
+        assert_eq!(delta.changes, [(Location::Ar(AR::LC as u8), 99)]);
+        let delta = evaluate(&slots[1], &state).unwrap();
+        let mut after = state.clone();
+        after.apply(&delta);
+        assert_eq!(after.fr[6], FloatRegister::NATVAL);
+
+        // Privileged instructions run at the snapshot's privilege level
+        assert!(evaluate(&slots[0], &state).is_err());
+        let mut kernel = state.clone();
+        kernel.psr &= !(3 << PSR_CPL_SHIFT);
+        let delta = evaluate(&slots[0], &kernel).unwrap();
+        assert_eq!(
+            delta.changes,
+            [(Location::Psr, kernel.psr as u128 | 0x2000)]
+        );
+
+        // Effects outside the snapshot are errors, not partial deltas
+        let slots = decode(["mov cr.iva = r2", "nop.m 0", "nop.i 0"]);
+        assert!(evaluate(&slots[0], &kernel).is_err());
+    }
+
+    #[test]
+    fn test_evaluate_matches_emulator() {
+        let bundle = encode_mii([
+            encode_break_nop(0, 1, 0),
+            mov_ip(0, 8),
+            encode_break_nop(0, 0, 0x1),
+        ]);
+        let mut emu = Emulator::new();
+        emu.load_flat_image(BASE, &bundle, BASE).unwrap();
+        let mut state = ArchState::from_cpu(&emu.cpu);
+        emu.run().unwrap();
+
+        for slot in DecodedInstruction::decode_bundle(bundle).unwrap() {
+            let delta = evaluate(&slot, &state).unwrap();
+            state.apply(&delta);
+        }
+        assert_eq!(state.gr, emu.cpu.gr);
+        assert_eq!(state.pr, emu.cpu.pr);
+    }
+}
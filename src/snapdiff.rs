@@ -0,0 +1,386 @@
+//! Snapshot comparison
+//!
+//! When two runs that should agree drift apart, comparing their
+//! [`Snapshot`]s as opaque blobs says little. [`diff`] compares two
+//! snapshots, or a snapshot and a live machine with [`diff_live`], and
+//! reports the registers that differ and the memory that differs, grouped
+//! by region and by page, with each differing byte range annotated with the
+//! symbol it falls in.
+//!
+//! Regions are matched by base address, so the order regions were captured
+//! in does not matter, and regions mapped on only one side are reported as
+//! added or removed rather than compared. A region whose size changed is
+//! compared over the bytes both sides hold; the rest is reported as a size
+//! change.
+
+use crate::debugger::SymbolTable;
+use crate::emulator::Emulator;
+use crate::golden::Register;
+use crate::memory::Permissions;
+use crate::repro::{Snapshot, SnapshotRegion};
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::ops::Range;
+
+/// Size of the pages differences are grouped by
+pub const PAGE_SIZE: u64 = 4096;
+
+/// Bytes of each side shown for a differing range
+const SHOWN_BYTES: usize = 16;
+
+/// A register whose value differs
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RegisterChange {
+    /// Register
+    pub register: Register,
+    /// Value in the first snapshot, if captured there
+    pub old: Option<u64>,
+    /// Value in the second snapshot, if captured there
+    pub new: Option<u64>,
+}
+
+/// How a region differs
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RegionChange {
+    /// Mapped only in the second snapshot
+    Added,
+    /// Mapped only in the first snapshot
+    Removed,
+    /// Mapped in both with different contents, size or permissions
+    Changed,
+}
+
+/// Differing bytes within one page
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PageDiff {
+    /// Page address
+    pub address: u64,
+    /// Runs of differing bytes, by address
+    pub ranges: Vec<Range<u64>>,
+}
+
+impl PageDiff {
+    /// Number of differing bytes
+    pub fn bytes(&self) -> u64 {
+        self.ranges.iter().map(|r| r.end - r.start).sum()
+    }
+}
+
+/// A region that differs
+#[derive(Debug, Clone, PartialEq)]
+pub struct RegionDiff {
+    /// Base address
+    pub base: u64,
+    /// Region name, from whichever side has one
+    pub name: Option<String>,
+    /// Kind of difference
+    pub change: RegionChange,
+    /// Size in the first snapshot
+    pub old_size: u64,
+    /// Size in the second snapshot
+    pub new_size: u64,
+    /// Permissions in each snapshot, when they differ
+    pub permissions: Option<(Permissions, Permissions)>,
+    /// Pages with differing bytes, by address
+    pub pages: Vec<PageDiff>,
+}
+
+/// Differences between two snapshots
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SnapshotDiff {
+    /// Registers that differ, in register order
+    pub registers: Vec<RegisterChange>,
+    /// Regions that differ, by base address
+    pub regions: Vec<RegionDiff>,
+    /// Contents of the changed regions in the first snapshot, by base
+    old: BTreeMap<u64, Vec<u8>>,
+    /// Contents of the changed regions in the second snapshot, by base
+    new: BTreeMap<u64, Vec<u8>>,
+}
+
+/// Compare two snapshots
+pub fn diff(old: &Snapshot, new: &Snapshot) -> SnapshotDiff {
+    let mut result = SnapshotDiff::default();
+
+    let mut registers: BTreeMap<Register, (Option<u64>, Option<u64>)> = BTreeMap::new();
+    for &(register, value) in &old.registers {
+        registers.entry(register).or_default().0 = Some(value);
+    }
+    for &(register, value) in &new.registers {
+        registers.entry(register).or_default().1 = Some(value);
+    }
+    result.registers = registers
+        .into_iter()
+        .filter(|(_, (old, new))| old != new)
+        .map(|(register, (old, new))| RegisterChange { register, old, new })
+        .collect();
+
+    fn by_base(snapshot: &Snapshot) -> BTreeMap<u64, &SnapshotRegion> {
+        snapshot.regions.iter().map(|r| (r.base, r)).collect()
+    }
+    let (mut old_regions, new_regions) = (by_base(old), by_base(new));
+    for (base, new_region) in new_regions {
+        match old_regions.remove(&base) {
+            Some(old_region) => {
+                if let Some(diff) = compare(old_region, new_region) {
+                    result.regions.push(diff);
+                    result.old.insert(base, old_region.data.clone());
+                    result.new.insert(base, new_region.data.clone());
+                }
+            }
+            None => result.regions.push(RegionDiff {
+                base,
+                name: new_region.name.clone(),
+                change: RegionChange::Added,
+                old_size: 0,
+                new_size: new_region.data.len() as u64,
+                permissions: None,
+                pages: Vec::new(),
+            }),
+        }
+    }
+    for (base, old_region) in old_regions {
+        result.regions.push(RegionDiff {
+            base,
+            name: old_region.name.clone(),
+            change: RegionChange::Removed,
+            old_size: old_region.data.len() as u64,
+            new_size: 0,
+            permissions: None,
+            pages: Vec::new(),
+        });
+    }
+    result.regions.sort_by_key(|region| region.base);
+    result
+}
+
+/// Compare a snapshot with the current state of a machine
+pub fn diff_live(snapshot: &Snapshot, emulator: &Emulator) -> SnapshotDiff {
+    diff(snapshot, &Snapshot::capture(emulator))
+}
+
+/// Compare a region mapped in both snapshots, if it differs
+fn compare(old: &SnapshotRegion, new: &SnapshotRegion) -> Option<RegionDiff> {
+    let mut pages: Vec<PageDiff> = Vec::new();
+    let mut run: Option<Range<u64>> = None;
+    let close = |run: Range<u64>, pages: &mut Vec<PageDiff>| {
+        // Split runs at page boundaries so each page lists its own bytes
+        let mut start = run.start;
+        while start < run.end {
+            let page = start & !(PAGE_SIZE - 1);
+            let end = run.end.min(page + PAGE_SIZE);
+            if pages.last().map(|last| last.address) != Some(page) {
+                pages.push(PageDiff {
+                    address: page,
+                    ranges: Vec::new(),
+                });
+            }
+            pages.last_mut().unwrap().ranges.push(start..end);
+            start = end;
+        }
+    };
+    for (offset, (a, b)) in old.data.iter().zip(&new.data).enumerate() {
+        let address = old.base + offset as u64;
+        if a != b {
+            run = Some(run.map_or(address..address + 1, |r| r.start..address + 1));
+        } else if let Some(r) = run.take() {
+            close(r, &mut pages);
+        }
+    }
+    if let Some(r) = run {
+        close(r, &mut pages);
+    }
+
+    let permissions =
+        (old.permissions != new.permissions).then_some((old.permissions, new.permissions));
+    if pages.is_empty() && permissions.is_none() && old.data.len() == new.data.len() {
+        return None;
+    }
+    Some(RegionDiff {
+        base: old.base,
+        name: new.name.clone().or_else(|| old.name.clone()),
+        change: RegionChange::Changed,
+        old_size: old.data.len() as u64,
+        new_size: new.data.len() as u64,
+        permissions,
+        pages,
+    })
+}
+
+impl SnapshotDiff {
+    /// Whether the snapshots are the same
+    pub fn is_empty(&self) -> bool {
+        self.registers.is_empty() && self.regions.is_empty()
+    }
+
+    /// Number of differing bytes in regions mapped on both sides
+    pub fn changed_bytes(&self) -> u64 {
+        self.regions
+            .iter()
+            .flat_map(|region| &region.pages)
+            .map(PageDiff::bytes)
+            .sum()
+    }
+
+    /// Format the differences as a report, naming the symbol each
+    /// differing range falls in
+    pub fn report(&self, symbols: &SymbolTable) -> String {
+        if self.is_empty() {
+            return "snapshots are identical\n".to_string();
+        }
+        let mut out = String::new();
+        if !self.registers.is_empty() {
+            writeln!(out, "registers:").unwrap();
+        }
+        let value = |value: Option<u64>| match value {
+            Some(value) => format!("{:#x}", value),
+            None => "-".to_string(),
+        };
+        for change in &self.registers {
+            writeln!(
+                out,
+                "  {:<12} {} -> {}",
+                change.register.to_string(),
+                value(change.old),
+                value(change.new)
+            )
+            .unwrap();
+        }
+
+        for region in &self.regions {
+            write!(out, "region {:#018x}", region.base).unwrap();
+            if let Some(name) = &region.name {
+                write!(out, " [{}]", name).unwrap();
+            }
+            match region.change {
+                RegionChange::Added => {
+                    writeln!(out, ": added, {:#x} bytes", region.new_size).unwrap();
+                    continue;
+                }
+                RegionChange::Removed => {
+                    writeln!(out, ": removed, {:#x} bytes", region.old_size).unwrap();
+                    continue;
+                }
+                RegionChange::Changed => {}
+            }
+            let bytes: u64 = region.pages.iter().map(PageDiff::bytes).sum();
+            let pages = region.pages.len();
+            let plural = if pages == 1 { "" } else { "s" };
+            writeln!(out, ": {} bytes differ in {} page{}", bytes, pages, plural).unwrap();
+            if region.old_size != region.new_size {
+                writeln!(
+                    out,
+                    "  size {:#x} -> {:#x}",
+                    region.old_size, region.new_size
+                )
+                .unwrap();
+            }
+            if let Some((old, new)) = region.permissions {
+                writeln!(out, "  permissions {:?} -> {:?}", old, new).unwrap();
+            }
+            for page in &region.pages {
+                writeln!(out, "  page {:#018x}: {} bytes", page.address, page.bytes()).unwrap();
+                for range in &page.ranges {
+                    write!(out, "    {:#018x}..{:#018x}", range.start, range.end).unwrap();
+                    if let Some((symbol, offset)) = symbols.lookup(range.start) {
+                        write!(out, " <{}+{:#x}>", symbol.name, offset).unwrap();
+                    }
+                    out.push('\n');
+                    let shown = |data: Option<&Vec<u8>>| {
+                        let start = (range.start - region.base) as usize;
+                        let end = (range.end - region.base) as usize;
+                        let bytes = data.map_or(&[][..], |data| &data[start..end]);
+                        let mut hex: Vec<String> = bytes
+                            .iter()
+                            .take(SHOWN_BYTES)
+                            .map(|byte| format!("{:02x}", byte))
+                            .collect();
+                        if bytes.len() > SHOWN_BYTES {
+                            hex.push("...".to_string());
+                        }
+                        hex.join(" ")
+                    };
+                    writeln!(out, "      - {}", shown(self.old.get(&region.base))).unwrap();
+                    writeln!(out, "      + {}", shown(self.new.get(&region.base))).unwrap();
+                }
+            }
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn region(base: u64, name: &str, data: Vec<u8>) -> SnapshotRegion {
+        SnapshotRegion {
+            base,
+            permissions: Permissions::ReadWrite,
+            name: Some(name.to_string()),
+            data,
+        }
+    }
+
+    #[test]
+    fn test_diff() {
+        let mut data = vec![0; 3 * PAGE_SIZE as usize];
+        let old = Snapshot {
+            registers: vec![(Register::Gr(8), 1), (Register::Ip, 0x100)],
+            regions: vec![
+                region(0x10000, "data", data.clone()),
+                region(0x40000, "stack", vec![0; 16]),
+            ],
+            ..Snapshot::default()
+        };
+        // Two runs of differing bytes, one crossing into the next page
+        data[0x20..0x24].copy_from_slice(b"abcd");
+        data[PAGE_SIZE as usize - 2..PAGE_SIZE as usize + 2].fill(0xFF);
+        // Regions captured in another order compare the same
+        let new = Snapshot {
+            registers: vec![(Register::Ip, 0x100), (Register::Gr(8), 2)],
+            regions: vec![
+                region(0x80000, "heap", vec![0; 32]),
+                region(0x10000, "data", data),
+            ],
+            ..Snapshot::default()
+        };
+
+        let diff = diff(&old, &new);
+        assert_eq!(
+            diff.registers,
+            vec![RegisterChange {
+                register: Register::Gr(8),
+                old: Some(1),
+                new: Some(2)
+            }]
+        );
+        let changes: Vec<_> = diff.regions.iter().map(|r| (r.base, r.change)).collect();
+        assert_eq!(
+            changes,
+            vec![
+                (0x10000, RegionChange::Changed),
+                (0x40000, RegionChange::Removed),
+                (0x80000, RegionChange::Added)
+            ]
+        );
+        let pages = &diff.regions[0].pages;
+        assert_eq!(pages.len(), 2);
+        assert_eq!(pages[0].ranges, vec![0x10020..0x10024, 0x10FFE..0x11000]);
+        assert_eq!((pages[1].address, pages[1].bytes()), (0x11000, 2));
+        assert_eq!(diff.changed_bytes(), 8);
+
+        let mut symbols = SymbolTable::new();
+        symbols.add("counters", 0x10020, 8);
+        let report = diff.report(&symbols);
+        assert!(report.contains("r8           0x1 -> 0x2"));
+        assert!(report.contains("0x0000000000010020..0x0000000000010024 <counters+0x0>"));
+        assert!(report.contains("+ 61 62 63 64"));
+        assert!(report.contains("[stack]: removed, 0x10 bytes"));
+        assert!(report.contains("[heap]: added, 0x20 bytes"));
+
+        let same = super::diff(&old, &old);
+        assert!(same.is_empty());
+        assert_eq!(same.report(&symbols), "snapshots are identical\n");
+    }
+}
@@ -0,0 +1,34 @@
+//! Bundle encoders shared by the unit tests
+//!
+//! Tests that need exact bit patterns, such as encodings the assembler
+//! does not produce, build their slots with these and pack them with
+//! [`crate::asm::encode_bundle`].
+
+use crate::asm;
+use crate::decoder::BundleTemplate;
+
+/// Encode a bundle with the given template bits from three slots
+pub(crate) fn encode_bundle(template: u8, slots: [u64; 3]) -> [u8; 16] {
+    let template = BundleTemplate::from_bits(template).expect("reserved template");
+    asm::encode_bundle(template, slots)
+}
+
+/// Encode an MII bundle from three slots
+pub(crate) fn encode_mii(slots: [u64; 3]) -> [u8; 16] {
+    encode_bundle(0, slots)
+}
+
+/// Encode a break/nop slot with the given x6 and immediate
+pub(crate) fn encode_break_nop(qp: u64, x6: u64, imm: u64) -> u64 {
+    qp | ((imm & 0xFFFFF) << 6) | (x6 << 27) | (((imm >> 20) & 1) << 36)
+}
+
+/// nop.m or nop.i
+pub(crate) fn nop() -> u64 {
+    encode_break_nop(0, 0x01, 0)
+}
+
+/// mov `reg` = ip
+pub(crate) fn mov_ip(qp: u64, reg: u64) -> u64 {
+    qp | (reg << 6) | (0x30 << 27)
+}
@@ -0,0 +1,364 @@
+//! Timeline of device, interrupt, system call and stall events
+//!
+//! A [`Timeline`] records what happened on the machine on separate
+//! channels: one per device, for its MMIO loads and stores, interrupt line
+//! and hotplug, one for interrupts raised and the handlers they run, one
+//! for system calls and one for the stalls the
+//! [timing model](crate::cpu::timing) charges. Events are stamped with
+//! AR.ITC when they happened; the ITC counts bundles, so events of one
+//! bundle share a timestamp and keep the order they happened in.
+//!
+//! [`Timeline::chrome_trace`] exports the events as Chrome trace JSON, which
+//! `chrome://tracing` and Perfetto show as one track per channel: a disk
+//! interrupt appears on the device's track next to the handler it started
+//! on the interrupt track. Trace time is one microsecond per ITC tick.
+//!
+//! The timeline is a handle: clones record into the same events, which is
+//! how the emulator shares it between the CPU and memory.
+
+use crate::device::DeviceId;
+use serde_json::{json, Value};
+use std::collections::{BTreeMap, HashSet, VecDeque};
+use std::fmt;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+/// Events kept unless configured otherwise
+pub const DEFAULT_TIMELINE_CAPACITY: usize = 1 << 20;
+
+/// Where an event is recorded
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Channel {
+    /// Stalls of the CPU pipeline
+    Cpu,
+    /// Interrupts raised, and the handlers delivery runs
+    Interrupts,
+    /// System calls
+    Syscalls,
+    /// One device
+    Device(DeviceId),
+}
+
+impl Channel {
+    /// Chrome trace thread id of the channel's track
+    fn track(self) -> u64 {
+        match self {
+            Channel::Cpu => 1,
+            Channel::Interrupts => 2,
+            Channel::Syscalls => 3,
+            Channel::Device(id) => 16 + id.0 as u64,
+        }
+    }
+}
+
+/// Shape of an event in time
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Phase {
+    /// A point in time
+    Instant,
+    /// Start of a span ended by the next [`Phase::End`] on the channel
+    Begin,
+    /// End of the innermost open span on the channel
+    End,
+    /// A span of the given ITC ticks
+    Complete(u64),
+}
+
+/// Recorded event
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TimelineEvent {
+    /// AR.ITC when it happened
+    pub time: u64,
+    /// Channel recorded on
+    pub channel: Channel,
+    /// What happened, e.g. `write` or a system call's name
+    pub name: String,
+    /// Shape in time
+    pub phase: Phase,
+    /// Details, by name
+    pub args: Vec<(&'static str, u64)>,
+}
+
+impl fmt::Display for TimelineEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:>12} {:?} {}", self.time, self.channel, self.name)?;
+        match self.phase {
+            Phase::Instant => {}
+            Phase::Begin => write!(f, " begin")?,
+            Phase::End => write!(f, " end")?,
+            Phase::Complete(duration) => write!(f, " for {}", duration)?,
+        }
+        for (name, value) in &self.args {
+            write!(f, " {}={:#x}", name, value)?;
+        }
+        Ok(())
+    }
+}
+
+/// State shared by the clones of a timeline
+#[derive(Debug)]
+struct Inner {
+    /// Events kept
+    capacity: usize,
+    /// Recorded events, oldest first
+    events: VecDeque<TimelineEvent>,
+    /// Timestamp of events recorded now
+    now: u64,
+    /// Device names, by id, as the devices gave them
+    devices: BTreeMap<DeviceId, String>,
+    /// Names of the channels to record, or all of them if `None`
+    only: Option<HashSet<String>>,
+    /// Spans begun and not yet ended, by channel
+    open: BTreeMap<Channel, usize>,
+}
+
+/// Recorder of timestamped machine events
+#[derive(Debug, Clone)]
+pub struct Timeline {
+    /// State shared with the clones
+    inner: Arc<Mutex<Inner>>,
+}
+
+impl Default for Timeline {
+    fn default() -> Self {
+        Self::new(DEFAULT_TIMELINE_CAPACITY)
+    }
+}
+
+impl Timeline {
+    /// Timeline keeping the last `capacity` events of every channel
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(Inner {
+                capacity: capacity.max(1),
+                events: VecDeque::new(),
+                now: 0,
+                devices: BTreeMap::new(),
+                only: None,
+                open: BTreeMap::new(),
+            })),
+        }
+    }
+
+    /// Record only the channels named: `cpu`, `interrupts`, `syscalls` or
+    /// a device's name, or every channel with `None`
+    pub fn set_channels(&self, names: Option<&[String]>) {
+        self.lock().only = names.map(|names| names.iter().cloned().collect());
+    }
+
+    /// Stamp events recorded from now on with AR.ITC value `itc`
+    pub(crate) fn set_time(&self, itc: u64) {
+        self.lock().now = itc;
+    }
+
+    /// Whether events on `channel` of a device named `device` are recorded
+    pub(crate) fn records(&self, channel: Channel, device: Option<&str>) -> bool {
+        let inner = self.lock();
+        let Some(only) = &inner.only else {
+            return true;
+        };
+        match channel {
+            Channel::Cpu => only.contains("cpu"),
+            Channel::Interrupts => only.contains("interrupts"),
+            Channel::Syscalls => only.contains("syscalls"),
+            Channel::Device(id) => device
+                .or(inner.devices.get(&id).map(String::as_str))
+                .is_some_and(|name| only.contains(name)),
+        }
+    }
+
+    /// Name the channel of device `id`
+    pub(crate) fn name_device(&self, id: DeviceId, name: &str) {
+        let mut inner = self.lock();
+        if inner.devices.get(&id).is_none_or(|known| known != name) {
+            inner.devices.insert(id, name.to_string());
+        }
+    }
+
+    /// Record an event at the current time
+    ///
+    /// An end with no span open on the channel, e.g. an rfi from a handler
+    /// entered before the timeline was installed, is dropped.
+    pub fn record(&self, channel: Channel, name: &str, phase: Phase, args: &[(&'static str, u64)]) {
+        if !self.records(channel, None) {
+            return;
+        }
+        let mut inner = self.lock();
+        match phase {
+            Phase::Begin => *inner.open.entry(channel).or_default() += 1,
+            Phase::End => match inner.open.get_mut(&channel) {
+                Some(open) if *open > 0 => *open -= 1,
+                _ => return,
+            },
+            _ => {}
+        }
+        if inner.events.len() == inner.capacity {
+            inner.events.pop_front();
+        }
+        let event = TimelineEvent {
+            time: inner.now,
+            channel,
+            name: name.to_string(),
+            phase,
+            args: args.to_vec(),
+        };
+        inner.events.push_back(event);
+    }
+
+    /// Recorded events, oldest first
+    pub fn events(&self) -> Vec<TimelineEvent> {
+        self.lock().events.iter().cloned().collect()
+    }
+
+    /// Forget the recorded events
+    pub fn clear(&self) {
+        let mut inner = self.lock();
+        inner.events.clear();
+        inner.open.clear();
+    }
+
+    /// Events as a Chrome trace JSON object, with a named track per
+    /// channel
+    pub fn chrome_trace(&self) -> String {
+        let inner = self.lock();
+        let mut channels: Vec<Channel> = inner.events.iter().map(|event| event.channel).collect();
+        channels.sort_unstable();
+        channels.dedup();
+
+        let mut events = vec![json!({
+            "name": "process_name",
+            "ph": "M",
+            "pid": 1,
+            "args": { "name": "rust-ia64" },
+        })];
+        for channel in channels {
+            let name = match channel {
+                Channel::Cpu => "cpu".to_string(),
+                Channel::Interrupts => "interrupts".to_string(),
+                Channel::Syscalls => "syscalls".to_string(),
+                Channel::Device(id) => match inner.devices.get(&id) {
+                    Some(name) => format!("{} ({})", name, id),
+                    None => id.to_string(),
+                },
+            };
+            events.push(json!({
+                "name": "thread_name",
+                "ph": "M",
+                "pid": 1,
+                "tid": channel.track(),
+                "args": { "name": name },
+            }));
+        }
+        for event in &inner.events {
+            let args: serde_json::Map<String, Value> = event
+                .args
+                .iter()
+                .map(|(name, value)| (name.to_string(), json!(format!("{:#x}", value))))
+                .collect();
+            let mut entry = json!({
+                "name": event.name,
+                "ph": "i",
+                "ts": event.time,
+                "pid": 1,
+                "tid": event.channel.track(),
+                "args": args,
+            });
+            match event.phase {
+                Phase::Instant => entry["s"] = json!("t"),
+                Phase::Begin => entry["ph"] = json!("B"),
+                Phase::End => entry["ph"] = json!("E"),
+                Phase::Complete(duration) => {
+                    entry["ph"] = json!("X");
+                    entry["dur"] = json!(duration);
+                }
+            }
+            events.push(entry);
+        }
+        json!({ "traceEvents": events, "otherData": { "clock": "AR.ITC" } }).to_string()
+    }
+
+    /// Lock the state, even if a thread panicked holding it
+    fn lock(&self) -> MutexGuard<'_, Inner> {
+        self.inner
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_record() {
+        let timeline = Timeline::new(3);
+        timeline.set_time(10);
+        // An end without a begin is dropped
+        timeline.record(Channel::Interrupts, "handler", Phase::End, &[]);
+        timeline.record(
+            Channel::Interrupts,
+            "handler",
+            Phase::Begin,
+            &[("ip", 0x4000)],
+        );
+        timeline.set_time(12);
+        timeline.record(Channel::Interrupts, "handler", Phase::End, &[]);
+        timeline.record(Channel::Cpu, "stall", Phase::Complete(5), &[]);
+        // The oldest event makes way
+        timeline.record(Channel::Syscalls, "write", Phase::Instant, &[]);
+        let events = timeline.events();
+        assert_eq!(events.len(), 3);
+        assert_eq!(events[0].time, 12);
+        assert_eq!(events[0].phase, Phase::End);
+        assert_eq!(events[1].to_string(), "          12 Cpu stall for 5");
+
+        // Only the channels named are recorded, devices by name
+        let timeline = Timeline::default();
+        timeline.name_device(DeviceId(1), "disk");
+        timeline.name_device(DeviceId(2), "uart");
+        timeline.set_channels(Some(&["disk".to_string(), "syscalls".to_string()]));
+        for channel in [
+            Channel::Cpu,
+            Channel::Syscalls,
+            Channel::Device(DeviceId(1)),
+            Channel::Device(DeviceId(2)),
+        ] {
+            timeline.record(channel, "event", Phase::Instant, &[]);
+        }
+        let channels: Vec<Channel> = timeline.events().iter().map(|e| e.channel).collect();
+        assert_eq!(channels, [Channel::Syscalls, Channel::Device(DeviceId(1))]);
+    }
+
+    #[test]
+    fn test_chrome_trace() {
+        let timeline = Timeline::default();
+        timeline.name_device(DeviceId(0), "disk");
+        timeline.set_time(100);
+        timeline.record(Channel::Device(DeviceId(0)), "irq", Phase::Instant, &[]);
+        timeline.record(
+            Channel::Interrupts,
+            "ExtInt",
+            Phase::Begin,
+            &[("info", 0x10)],
+        );
+        timeline.set_time(140);
+        timeline.record(Channel::Interrupts, "ExtInt", Phase::End, &[]);
+        timeline.record(Channel::Cpu, "stall", Phase::Complete(6), &[]);
+
+        let trace: Value = serde_json::from_str(&timeline.chrome_trace()).unwrap();
+        let events = trace["traceEvents"].as_array().unwrap();
+        // The process and its three tracks are named first
+        assert_eq!(events[0]["args"]["name"], "rust-ia64");
+        let names: Vec<&str> = events[1..4]
+            .iter()
+            .map(|e| e["args"]["name"].as_str().unwrap())
+            .collect();
+        assert_eq!(names, ["cpu", "interrupts", "disk (device 0)"]);
+        assert_eq!(events[4]["ph"], "i");
+        assert_eq!(events[4]["tid"], 16);
+        assert_eq!(events[5]["ph"], "B");
+        assert_eq!(events[5]["args"]["info"], "0x10");
+        assert_eq!(events[6]["ts"], 140);
+        assert_eq!(events[7]["ph"], "X");
+        assert_eq!(events[7]["dur"], 6);
+    }
+}
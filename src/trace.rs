@@ -0,0 +1,400 @@
+//! Instruction tracing
+//!
+//! A [`Tracer`] installed with [`Emulator::set_tracer`] sees each
+//! instruction the machine executes, in order: its bundle address and slot,
+//! its disassembly, whether its qualifying predicate let it execute and the
+//! registers it wrote. It then sees the bundle end, with the number of
+//! instructions the bundle retired. [`Emulator::set_tracing`] pauses and
+//! resumes tracing without removing the tracer; while paused, or with no
+//! tracer, the run loop does no tracing work.
+//!
+//! Three sinks come built in: [`StderrTracer`] logs a line per instruction
+//! on standard error, [`WriterTracer`] writes the same lines to a file or
+//! any other writer, and [`TraceRing`] keeps the most recent instructions
+//! in memory, e.g. to look at what led up to a fault:
+//!
+//! ```text
+//! 0x0000000000004000:0 mov r14 = ip  r14=0x4000
+//! 0x0000000000004000:1 (p6) add r8 = r9, r10  [p6 false]
+//! ```
+//!
+//! [`Emulator::set_tracer`]: crate::emulator::Emulator::set_tracer
+//! [`Emulator::set_tracing`]: crate::emulator::Emulator::set_tracing
+
+use crate::cpu::registers::FloatRegister;
+use crate::cpu::{Cpu, FIRST_STACKED_GR, NUM_BR, NUM_FR, NUM_GR, NUM_PR};
+use crate::decoder::disasm::disassemble_slot;
+use crate::decoder::Unit;
+use crate::EmulatorError;
+use std::collections::VecDeque;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+/// Register written by an instruction, with its new value
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RegisterWrite {
+    /// General register and its NaT bit
+    Gr {
+        /// Register number
+        reg: usize,
+        /// New value
+        value: u64,
+        /// New NaT bit
+        nat: bool,
+    },
+    /// Floating-point register
+    Fr {
+        /// Register number
+        reg: usize,
+        /// New value
+        value: FloatRegister,
+    },
+    /// Predicate register
+    Pr {
+        /// Register number
+        reg: usize,
+        /// New value
+        value: bool,
+    },
+    /// Branch register
+    Br {
+        /// Register number
+        reg: usize,
+        /// New value
+        value: u64,
+    },
+}
+
+impl fmt::Display for RegisterWrite {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            RegisterWrite::Gr { reg, nat: true, .. } => write!(f, "r{}=NaT", reg),
+            RegisterWrite::Gr { reg, value, .. } => write!(f, "r{}={:#x}", reg, value),
+            RegisterWrite::Fr { reg, value } if value.is_natval() => {
+                write!(f, "f{}=NaTVal", reg)
+            }
+            RegisterWrite::Fr { reg, value } => write!(f, "f{}={}", reg, value.to_f64()),
+            RegisterWrite::Pr { reg, value } => write!(f, "p{}={}", reg, value as u8),
+            RegisterWrite::Br { reg, value } => write!(f, "b{}={:#x}", reg, value),
+        }
+    }
+}
+
+/// An instruction as the tracer sees it
+#[derive(Debug, Clone, PartialEq)]
+pub struct TracedInstruction {
+    /// Address of the bundle holding the instruction
+    pub ip: u64,
+    /// Slot of the instruction in its bundle
+    pub slot: u8,
+    /// Assembly text
+    pub disassembly: String,
+    /// Qualifying predicate register
+    pub qp: u8,
+    /// Whether the qualifying predicate was true, so the instruction took
+    /// effect
+    pub executed: bool,
+    /// Registers the instruction wrote, by their names after it executed
+    ///
+    /// Stacked registers renamed by a branch that changes the frame, such
+    /// as a call or return, are not writes and are left out.
+    pub writes: Vec<RegisterWrite>,
+}
+
+impl fmt::Display for TracedInstruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:#018x}:{} {}", self.ip, self.slot, self.disassembly)?;
+        if !self.executed {
+            return write!(f, "  [p{} false]", self.qp);
+        }
+        for (i, write) in self.writes.iter().enumerate() {
+            let separator = if i == 0 { "  " } else { " " };
+            write!(f, "{}{}", separator, write)?;
+        }
+        Ok(())
+    }
+}
+
+/// Receiver of an execution trace
+///
+/// Errors stop the run with an execution error.
+pub trait Tracer: Send {
+    /// An instruction retired, or was skipped by a false predicate
+    fn instruction(&mut self, instruction: &TracedInstruction) -> io::Result<()>;
+
+    /// The bundle at `ip` ended after retiring `retired` instructions
+    fn bundle(&mut self, _ip: u64, _retired: u64) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Tracer logging each instruction as a line on standard error
+#[derive(Debug, Clone, Copy, Default)]
+pub struct StderrTracer;
+
+impl Tracer for StderrTracer {
+    fn instruction(&mut self, instruction: &TracedInstruction) -> io::Result<()> {
+        writeln!(io::stderr().lock(), "{}", instruction)
+    }
+}
+
+/// Tracer writing each instruction as a line to a writer
+///
+/// Output is buffered and flushed when the tracer is dropped.
+pub struct WriterTracer {
+    /// Destination of the lines
+    writer: BufWriter<Box<dyn Write + Send>>,
+}
+
+impl WriterTracer {
+    /// Write the trace to `writer`
+    pub fn new(writer: Box<dyn Write + Send>) -> Self {
+        Self {
+            writer: BufWriter::new(writer),
+        }
+    }
+
+    /// Write the trace to a new file at `path`
+    pub fn create(path: impl AsRef<Path>) -> Result<Self, EmulatorError> {
+        let path = path.as_ref();
+        let file = File::create(path).map_err(|e| {
+            EmulatorError::ExecutionError(format!("Cannot create {}: {}", path.display(), e))
+        })?;
+        Ok(Self::new(Box::new(file)))
+    }
+}
+
+impl fmt::Debug for WriterTracer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "WriterTracer(<writer>)")
+    }
+}
+
+impl Tracer for WriterTracer {
+    fn instruction(&mut self, instruction: &TracedInstruction) -> io::Result<()> {
+        writeln!(self.writer, "{}", instruction)
+    }
+}
+
+/// Tracer keeping the most recent instructions in memory
+///
+/// Clones share the buffer, so a clone kept by the host reads what the
+/// one installed in the emulator recorded.
+#[derive(Debug, Clone)]
+pub struct TraceRing {
+    /// Instructions kept
+    capacity: usize,
+    /// Recorded instructions, oldest first
+    records: Arc<Mutex<VecDeque<TracedInstruction>>>,
+}
+
+impl TraceRing {
+    /// Ring keeping the last `capacity` instructions
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity: capacity.max(1),
+            records: Arc::new(Mutex::new(VecDeque::with_capacity(capacity.max(1)))),
+        }
+    }
+
+    /// Recorded instructions, oldest first
+    pub fn records(&self) -> Vec<TracedInstruction> {
+        self.lock().iter().cloned().collect()
+    }
+
+    /// Forget the recorded instructions
+    pub fn clear(&self) {
+        self.lock().clear();
+    }
+
+    /// Lock the buffer, even if a thread panicked holding it
+    fn lock(&self) -> std::sync::MutexGuard<'_, VecDeque<TracedInstruction>> {
+        self.records
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl Tracer for TraceRing {
+    fn instruction(&mut self, instruction: &TracedInstruction) -> io::Result<()> {
+        let mut records = self.lock();
+        if records.len() == self.capacity {
+            records.pop_front();
+        }
+        records.push_back(instruction.clone());
+        Ok(())
+    }
+}
+
+/// Installed tracer and whether it is tracing
+pub(crate) struct Tracing {
+    /// Receiver of the trace
+    pub tracer: Box<dyn Tracer>,
+    /// Whether instructions are traced; pausing keeps the tracer
+    pub enabled: bool,
+}
+
+impl fmt::Debug for Tracing {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Tracing {{ enabled: {} }}", self.enabled)
+    }
+}
+
+/// Register state before a traced instruction executes
+pub(crate) struct PendingInstruction {
+    /// Whether the qualifying predicate was true
+    executed: bool,
+    /// Physical general registers
+    gr: [u64; NUM_GR],
+    /// Their NaT bits
+    nat: [bool; NUM_GR],
+    /// Physical floating-point registers
+    fr: [FloatRegister; NUM_FR],
+    /// Physical predicate registers
+    pr: [bool; NUM_PR],
+    /// Branch registers
+    br: [u64; NUM_BR],
+    /// Current frame marker
+    cfm: u64,
+}
+
+impl PendingInstruction {
+    /// Capture the registers before the instruction `bits` executes
+    pub(crate) fn capture(cpu: &Cpu, bits: u64) -> Result<Self, EmulatorError> {
+        Ok(Self {
+            executed: cpu.get_pr((bits & 0x3F) as usize)?,
+            gr: cpu.gr,
+            nat: cpu.nat,
+            fr: cpu.fr,
+            pr: cpu.pr,
+            br: cpu.br,
+            cfm: cpu.cfm,
+        })
+    }
+
+    /// The instruction executed; describe it with the registers it wrote
+    ///
+    /// `l_slot` is the L slot of an MLX bundle when `unit` is X.
+    pub(crate) fn finish(
+        &self,
+        cpu: &Cpu,
+        ip: u64,
+        slot: u8,
+        unit: Unit,
+        bits: u64,
+        l_slot: u64,
+    ) -> TracedInstruction {
+        let mut writes = Vec::new();
+        // A branch changing the frame renames the stacked registers
+        let renamed = unit == Unit::B && cpu.cfm != self.cfm;
+        for phys in 0..NUM_GR {
+            if (self.gr[phys], self.nat[phys]) != (cpu.gr[phys], cpu.nat[phys])
+                && !(renamed && phys >= FIRST_STACKED_GR)
+            {
+                writes.push(RegisterWrite::Gr {
+                    reg: cpu.gr_name(phys),
+                    value: cpu.gr[phys],
+                    nat: cpu.nat[phys],
+                });
+            }
+        }
+        for phys in 0..NUM_FR {
+            if self.fr[phys] != cpu.fr[phys] {
+                writes.push(RegisterWrite::Fr {
+                    reg: cpu.fr_name(phys),
+                    value: cpu.fr[phys],
+                });
+            }
+        }
+        for phys in 0..NUM_PR {
+            if self.pr[phys] != cpu.pr[phys] {
+                writes.push(RegisterWrite::Pr {
+                    reg: cpu.pr_name(phys),
+                    value: cpu.pr[phys],
+                });
+            }
+        }
+        for reg in 0..NUM_BR {
+            if self.br[reg] != cpu.br[reg] {
+                writes.push(RegisterWrite::Br {
+                    reg,
+                    value: cpu.br[reg],
+                });
+            }
+        }
+        TracedInstruction {
+            ip,
+            slot,
+            disassembly: disassemble_slot(unit, bits, l_slot, ip),
+            qp: (bits & 0x3F) as u8,
+            executed: self.executed,
+            writes,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn traced(executed: bool, writes: Vec<RegisterWrite>) -> TracedInstruction {
+        TracedInstruction {
+            ip: 0x4000,
+            slot: 1,
+            disassembly: "(p6) add r8 = r9, r10".to_string(),
+            qp: 6,
+            executed,
+            writes,
+        }
+    }
+
+    #[test]
+    fn test_format() {
+        let writes = vec![
+            RegisterWrite::Gr {
+                reg: 8,
+                value: 0x10,
+                nat: false,
+            },
+            RegisterWrite::Gr {
+                reg: 9,
+                value: 0,
+                nat: true,
+            },
+            RegisterWrite::Pr {
+                reg: 7,
+                value: true,
+            },
+            RegisterWrite::Br {
+                reg: 0,
+                value: 0x4010,
+            },
+        ];
+        assert_eq!(
+            traced(true, writes).to_string(),
+            "0x0000000000004000:1 (p6) add r8 = r9, r10  r8=0x10 r9=NaT p7=1 b0=0x4010"
+        );
+        assert_eq!(
+            traced(false, Vec::new()).to_string(),
+            "0x0000000000004000:1 (p6) add r8 = r9, r10  [p6 false]"
+        );
+    }
+
+    #[test]
+    fn test_ring() {
+        let ring = TraceRing::new(2);
+        let mut installed = ring.clone();
+        for slot in 0..3 {
+            let mut instruction = traced(true, Vec::new());
+            instruction.slot = slot;
+            installed.instruction(&instruction).unwrap();
+        }
+        let slots: Vec<u8> = ring.records().iter().map(|record| record.slot).collect();
+        assert_eq!(slots, [1, 2]);
+        ring.clear();
+        assert!(installed.records().is_empty());
+    }
+}
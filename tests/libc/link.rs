@@ -0,0 +1,260 @@
+//! Assembler and linker for the fixture sources
+//!
+//! A source file holds bundles in braces, one instruction per line in the
+//! syntax of [`rust_ia64::asm`], labels ending in `:`, and data after
+//! `.data`:
+//!
+//! ```text
+//!         .text
+//! exit:
+//! {
+//!         mov r15 = 252
+//!         nop.i 0
+//!         br.cond.sptk.few syscall ;;
+//! }
+//!         .data
+//! message:
+//!         .asciz "hello\n"
+//! ```
+//!
+//! [`link`] places the text of every file at [`TEXT_BASE`] and the data on
+//! the pages after it, resolves labels used as operands to their addresses
+//! and writes a static ET_EXEC image entered at `_start`.
+
+use regex::{Captures, Regex};
+use rust_ia64::asm::BundleBuilder;
+use std::collections::HashMap;
+
+/// Address of the first bundle
+pub const TEXT_BASE: u64 = 0x4000_0000_0000_0000;
+
+const PAGE_SIZE: u64 = 4096;
+const EHDR_SIZE: u64 = 64;
+const PHDR_SIZE: u64 = 56;
+const EM_IA_64: u16 = 50;
+
+/// Something a source line puts in the image
+enum Item {
+    /// Instructions of a bundle, with the file and line it starts on
+    Bundle(Vec<String>, String),
+    /// Data bytes
+    Data(Vec<u8>),
+    /// Padding to a multiple of the alignment
+    Align(u64),
+}
+
+/// Section items are placed in
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Section {
+    Text,
+    Data,
+}
+
+/// Link the sources, named by file for errors, into an executable
+pub fn link(sources: &[(&str, &str)]) -> Result<Vec<u8>, String> {
+    let mut text = Vec::new();
+    let mut data = Vec::new();
+    let mut labels = Vec::new();
+    for &(file, source) in sources {
+        parse(file, source, &mut text, &mut data, &mut labels)?;
+    }
+
+    // Lay the sections out to find every label
+    let text_size = size(&text, TEXT_BASE);
+    let data_base = (TEXT_BASE + text_size).next_multiple_of(PAGE_SIZE);
+    let mut symbols = HashMap::new();
+    for (name, section, index) in labels {
+        let (items, base) = match section {
+            Section::Text => (&text, TEXT_BASE),
+            Section::Data => (&data, data_base),
+        };
+        let addr = base + size(&items[..index], base);
+        if symbols.insert(name.clone(), addr).is_some() {
+            return Err(format!("{} is defined twice", name));
+        }
+    }
+    let entry = *symbols.get("_start").ok_or("no _start")?;
+
+    let symbol = Regex::new(r"\b[A-Za-z_][A-Za-z0-9_]*\b").unwrap();
+    let mut code = Vec::new();
+    for item in &text {
+        let Item::Bundle(insns, location) = item else {
+            return Err("only bundles belong in .text".to_string());
+        };
+        let ip = TEXT_BASE + code.len() as u64;
+        let mut builder = BundleBuilder::new().at(ip);
+        for insn in insns {
+            let resolved =
+                symbol.replace_all(insn, |caps: &Captures| match symbols.get(&caps[0]) {
+                    Some(addr) => format!("{:#x}", addr),
+                    None => caps[0].to_string(),
+                });
+            builder = builder.insn(&resolved);
+        }
+        let bundle = builder
+            .build()
+            .map_err(|e| format!("{}: {}", location, e))?;
+        code.extend_from_slice(&bundle);
+    }
+    let mut bytes = Vec::new();
+    for item in &data {
+        match item {
+            Item::Data(chunk) => bytes.extend_from_slice(chunk),
+            Item::Align(align) => {
+                bytes.resize((bytes.len() as u64).next_multiple_of(*align) as usize, 0)
+            }
+            Item::Bundle(..) => return Err("bundles belong in .text".to_string()),
+        }
+    }
+
+    Ok(elf(entry, &code, data_base, &bytes))
+}
+
+/// Bytes `items` take when placed at `base`
+fn size(items: &[Item], base: u64) -> u64 {
+    items.iter().fold(0, |size, item| match item {
+        Item::Bundle(..) => size + 16,
+        Item::Data(data) => size + data.len() as u64,
+        Item::Align(align) => (base + size).next_multiple_of(*align) - base,
+    })
+}
+
+/// Add the items and labels of one source file
+fn parse(
+    file: &str,
+    source: &str,
+    text: &mut Vec<Item>,
+    data: &mut Vec<Item>,
+    labels: &mut Vec<(String, Section, usize)>,
+) -> Result<(), String> {
+    let mut section = Section::Text;
+    let mut bundle: Option<(Vec<String>, String)> = None;
+    for (number, line) in source.lines().enumerate() {
+        let number = number + 1;
+        let error = |msg: &str| format!("{}:{}: {}", file, number, msg);
+        let line = line.split("//").next().unwrap().trim();
+        if line.is_empty() {
+            continue;
+        }
+        if let Some((insns, _)) = &mut bundle {
+            if line == "}" {
+                let (insns, location) = bundle.take().unwrap();
+                text.push(Item::Bundle(insns, location));
+            } else {
+                insns.push(line.to_string());
+            }
+            continue;
+        }
+        let items = match section {
+            Section::Text => &mut *text,
+            Section::Data => &mut *data,
+        };
+        if line == "{" {
+            if section != Section::Text {
+                return Err(error("bundle outside .text"));
+            }
+            bundle = Some((Vec::new(), format!("{}:{}", file, number)));
+        } else if let Some(name) = line.strip_suffix(':') {
+            labels.push((name.to_string(), section, items.len()));
+        } else if line == ".text" {
+            section = Section::Text;
+        } else if line == ".data" {
+            section = Section::Data;
+        } else if let Some(value) = line.strip_prefix(".asciz") {
+            let value = value.trim();
+            let value = value
+                .strip_prefix('"')
+                .and_then(|value| value.strip_suffix('"'))
+                .ok_or_else(|| error("expected a quoted string"))?;
+            let mut bytes = unescape(value).map_err(|msg| error(&msg))?;
+            bytes.push(0);
+            items.push(Item::Data(bytes));
+        } else if let Some(value) = line.strip_prefix(".quad") {
+            let value = number_value(value.trim()).ok_or_else(|| error("expected a number"))?;
+            items.push(Item::Data(value.to_le_bytes().to_vec()));
+        } else if let Some(value) = line.strip_prefix(".align") {
+            let align = number_value(value.trim())
+                .filter(|align| align.is_power_of_two())
+                .ok_or_else(|| error("expected a power of two"))?;
+            items.push(Item::Align(align));
+        } else {
+            return Err(error(&format!("unexpected {:?}", line)));
+        }
+    }
+    match bundle {
+        Some((_, location)) => Err(format!("{}: bundle is not closed", location)),
+        None => Ok(()),
+    }
+}
+
+/// Decimal or `0x` hex number
+fn number_value(text: &str) -> Option<u64> {
+    match text.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16).ok(),
+        None => text.parse().ok(),
+    }
+}
+
+/// Bytes of a string with `\n`, `\t`, `\\` and `\"` escapes
+fn unescape(text: &str) -> Result<Vec<u8>, String> {
+    let mut bytes = Vec::new();
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        let c = match c {
+            '\\' => match chars.next() {
+                Some('n') => '\n',
+                Some('t') => '\t',
+                Some(c @ ('\\' | '"')) => c,
+                _ => return Err("unknown escape".to_string()),
+            },
+            c => c,
+        };
+        let mut buf = [0; 4];
+        bytes.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+    }
+    Ok(bytes)
+}
+
+/// Static executable with read-execute `code` at [`TEXT_BASE`] and
+/// read-write `data` at `data_base`
+fn elf(entry: u64, code: &[u8], data_base: u64, data: &[u8]) -> Vec<u8> {
+    let code_offset = PAGE_SIZE;
+    let data_offset = (code_offset + code.len() as u64).next_multiple_of(PAGE_SIZE);
+    let mut image = Vec::new();
+
+    // Elf64_Ehdr
+    image.extend_from_slice(b"\x7FELF\x02\x01\x01\x00");
+    image.resize(16, 0);
+    image.extend_from_slice(&2u16.to_le_bytes()); // ET_EXEC
+    image.extend_from_slice(&EM_IA_64.to_le_bytes());
+    image.extend_from_slice(&1u32.to_le_bytes());
+    image.extend_from_slice(&entry.to_le_bytes());
+    image.extend_from_slice(&EHDR_SIZE.to_le_bytes()); // e_phoff
+    image.extend_from_slice(&0u64.to_le_bytes()); // e_shoff
+    image.extend_from_slice(&0u32.to_le_bytes()); // e_flags
+    image.extend_from_slice(&(EHDR_SIZE as u16).to_le_bytes());
+    image.extend_from_slice(&(PHDR_SIZE as u16).to_le_bytes());
+    image.extend_from_slice(&2u16.to_le_bytes()); // e_phnum
+    image.extend_from_slice(&[0; 6]); // no section headers
+
+    // PT_LOAD for the text, read and execute, then the data, read and write
+    for (flags, offset, vaddr, size) in [
+        (5u32, code_offset, TEXT_BASE, code.len() as u64),
+        (6, data_offset, data_base, data.len() as u64),
+    ] {
+        image.extend_from_slice(&1u32.to_le_bytes());
+        image.extend_from_slice(&flags.to_le_bytes());
+        image.extend_from_slice(&offset.to_le_bytes());
+        image.extend_from_slice(&vaddr.to_le_bytes());
+        image.extend_from_slice(&vaddr.to_le_bytes()); // p_paddr
+        image.extend_from_slice(&size.to_le_bytes()); // p_filesz
+        image.extend_from_slice(&size.to_le_bytes()); // p_memsz
+        image.extend_from_slice(&PAGE_SIZE.to_le_bytes());
+    }
+
+    image.resize(code_offset as usize, 0);
+    image.extend_from_slice(code);
+    image.resize(data_offset as usize, 0);
+    image.extend_from_slice(data);
+    image
+}
@@ -0,0 +1,105 @@
+//! Guest programs on a minimal C library, run through the Linux ABI layer
+//!
+//! The programs under `src` are linked with `crt0.s` and `libc.s` into the
+//! static executables under `bin`, which are checked in so the tests need
+//! no IA-64 toolchain. Each test loads one as `rust-ia64` would, runs it to
+//! its exit and checks what it printed. `rebuild.sh` relinks the
+//! executables after a source changes; [`test_binaries_match_sources`]
+//! fails until it has.
+
+mod link;
+
+use rust_ia64::console::ConsoleCapture;
+use rust_ia64::emulator::{Emulator, StopReason};
+use std::path::{Path, PathBuf};
+
+/// Environment variable that makes the tests rewrite the executables
+const UPDATE_ENV: &str = "UPDATE_FIXTURES";
+
+/// Bundles a fixture may run before it is considered hung
+const MAX_STEPS: usize = 100_000;
+
+/// Programs, each linked with the runtime
+const PROGRAMS: [&str; 4] = ["hello", "thread", "signal", "pipeline"];
+
+fn fixture(path: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/libc")
+        .join(path)
+}
+
+/// Link `program` from its sources
+fn build(program: &str) -> Vec<u8> {
+    let files = ["crt0".to_string(), "libc".to_string(), program.to_string()];
+    let sources: Vec<(String, String)> = files
+        .iter()
+        .map(|name| {
+            let file = format!("{}.s", name);
+            let source = std::fs::read_to_string(fixture(&format!("src/{}", file))).unwrap();
+            (file, source)
+        })
+        .collect();
+    let sources: Vec<(&str, &str)> = sources
+        .iter()
+        .map(|(file, source)| (file.as_str(), source.as_str()))
+        .collect();
+    link::link(&sources).unwrap_or_else(|e| panic!("cannot link {}: {}", program, e))
+}
+
+/// Run the checked-in `program` and return its exit status and output
+fn run(program: &str) -> (i32, String) {
+    let image = std::fs::read(fixture(&format!("bin/{}", program))).unwrap();
+    let mut emu = Emulator::new();
+    emu.load_elf(&image, 0).unwrap();
+    emu.setup_process(&[program.to_string()], &[]).unwrap();
+    let mut console = ConsoleCapture::new();
+    console.attach_syscalls(&mut emu.cpu);
+    for _ in 0..MAX_STEPS {
+        match emu
+            .step()
+            .unwrap_or_else(|e| panic!("{:#x}: {}", emu.cpu.ip, e))
+        {
+            Some(StopReason::Exited(status)) => return (status, console.output()),
+            Some(reason) => panic!("{} stopped: {:?}", program, reason),
+            None => {}
+        }
+    }
+    panic!("{} did not exit", program);
+}
+
+#[test]
+fn test_binaries_match_sources() {
+    for program in PROGRAMS {
+        let built = build(program);
+        let path = fixture(&format!("bin/{}", program));
+        if std::env::var_os(UPDATE_ENV).is_some() {
+            std::fs::write(&path, &built).unwrap();
+        }
+        let checked_in = std::fs::read(&path).unwrap();
+        assert!(
+            built == checked_in,
+            "{} is stale; run tests/libc/rebuild.sh",
+            path.display()
+        );
+    }
+}
+
+#[test]
+fn test_hello() {
+    assert_eq!(run("hello"), (0, "hello, world\n".to_string()));
+}
+
+#[test]
+fn test_thread() {
+    assert_eq!(run("thread"), (0, "thread ok\n".to_string()));
+}
+
+#[test]
+fn test_signal() {
+    assert_eq!(run("signal"), (0, "signal ok\n".to_string()));
+}
+
+#[test]
+fn test_pipeline() {
+    assert_eq!(run("pipeline"), (0, "pipelined copy ok\n".to_string()));
+}
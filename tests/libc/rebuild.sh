@@ -0,0 +1,5 @@
+#!/bin/sh
+# Relink the fixture executables under tests/libc/bin from tests/libc/src
+set -e
+cd "$(dirname "$0")/../.."
+UPDATE_FIXTURES=1 cargo test --test libc test_binaries_match_sources
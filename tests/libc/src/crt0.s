@@ -0,0 +1,26 @@
+// Process entry: call main(argc, argv) and exit with what it returns
+//
+// The kernel leaves argc at [sp], with argv after it.
+
+        .text
+_start:
+{
+        alloc r2 = ar.pfs, 0, 0, 2, 0 ;;
+        ld8 r32 = [r12]
+        adds r33 = 8, r12 ;;
+}
+{
+        nop.m 0
+        nop.i 0
+        br.call.sptk.many b0 = main ;;
+}
+{
+        mov r32 = r8
+        nop.i 0
+        nop.i 0 ;;
+}
+{
+        nop.m 0
+        nop.i 0
+        br.call.sptk.many b0 = exit ;;
+}
@@ -0,0 +1,32 @@
+// Print a greeting and exit with status 0
+
+        .text
+main:
+{
+        alloc r34 = ar.pfs, 2, 2, 1, 0 ;;
+        nop.m 0
+        mov r35 = b0 ;;
+}
+{
+        nop.m 0
+        movl r36 = message ;;
+}
+{
+        nop.m 0
+        nop.i 0
+        br.call.sptk.many b0 = print ;;
+}
+{
+        mov r8 = 0
+        mov ar.pfs = r34
+        mov b0 = r35 ;;
+}
+{
+        nop.m 0
+        nop.i 0
+        br.ret.sptk.many b0 ;;
+}
+
+        .data
+message:
+        .asciz "hello, world\n"
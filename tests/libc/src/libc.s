@@ -0,0 +1,122 @@
+// Minimal C library
+//
+// System call wrappers take their arguments in the output registers of the
+// caller's frame, as C functions do, and return -errno on failure.
+
+        .text
+exit:
+{
+        mov r15 = 252                   // exit_group
+        nop.i 0
+        br.cond.sptk.few syscall ;;
+}
+write:
+{
+        mov r15 = 4
+        nop.i 0
+        br.cond.sptk.few syscall ;;
+}
+sigprocmask:
+{
+        mov r15 = 175                   // rt_sigprocmask
+        nop.i 0
+        br.cond.sptk.few syscall ;;
+}
+gettid:
+{
+        mov r15 = 224
+        nop.i 0
+        br.cond.sptk.few syscall ;;
+}
+futex:
+{
+        mov r15 = 240
+        nop.i 0
+        br.cond.sptk.few syscall ;;
+}
+set_tid_address:
+{
+        mov r15 = 258
+        nop.i 0
+        br.cond.sptk.few syscall ;;
+}
+
+// Issue system call r15 and return to b0, with -errno in r8 if it failed
+syscall:
+{
+        mov r9 = 0 ;;
+        break.m 0x100000
+        nop.i 0 ;;
+}
+{
+        cmp.eq p0, p6 = r0, r9
+        nop.i 0
+        nop.i 0 ;;
+}
+{
+        (p6) sub r8 = r0, r9
+        nop.i 0
+        br.ret.sptk.many b0 ;;
+}
+
+// Length of the string at r32
+strlen:
+{
+        mov r8 = r32
+        nop.i 0
+        nop.i 0 ;;
+}
+strlen_loop:
+{
+        ld1 r2 = [r8]
+        nop.i 0
+        nop.i 0 ;;
+}
+{
+        cmp.eq p0, p6 = r0, r2
+        nop.i 0
+        nop.i 0 ;;
+}
+{
+        (p6) adds r8 = 1, r8
+        nop.i 0
+        (p6) br.cond.sptk.few strlen_loop ;;
+}
+{
+        sub r8 = r8, r32
+        nop.i 0
+        br.ret.sptk.many b0 ;;
+}
+
+// Write the string at r32 to standard output
+print:
+{
+        alloc r33 = ar.pfs, 1, 2, 3, 0 ;;
+        mov r35 = r32
+        mov r34 = b0 ;;
+}
+{
+        nop.m 0
+        nop.i 0
+        br.call.sptk.many b0 = strlen ;;
+}
+{
+        mov r35 = 1
+        mov r36 = r32
+        mov r37 = r8 ;;
+}
+{
+        nop.m 0
+        nop.i 0
+        br.call.sptk.many b0 = write ;;
+}
+{
+        nop.m 0
+        mov ar.pfs = r33
+        mov b0 = r34 ;;
+}
+{
+        nop.m 0
+        nop.i 0
+        br.ret.sptk.many b0 ;;
+}
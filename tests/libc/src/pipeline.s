@@ -0,0 +1,117 @@
+// Copy a message with a software-pipelined loop, then print the copy
+//
+// copy_words is scheduled the way compilers modulo-schedule a word copy:
+// rotating registers carry each loaded word two stages on to its store,
+// br.ctop counts the kernel down with AR.LC and then drains the pipeline
+// with AR.EC. main gives AR.EC a value of its own before the call, which
+// the return must restore from AR.PFS. Exits with 0, or with 1 if main's
+// epilog count did not survive the call.
+
+        .text
+main:
+{
+        alloc r34 = ar.pfs, 2, 3, 3, 0 ;;
+        nop.m 0
+        mov r35 = b0 ;;
+}
+{
+        nop.m 0
+        movl r37 = copy ;;
+}
+{
+        nop.m 0
+        movl r38 = message ;;
+}
+{
+        mov r39 = 3
+        mov.i ar.ec = 7
+        nop.i 0 ;;
+}
+{
+        nop.m 0
+        nop.i 0
+        br.call.sptk.many b0 = copy_words ;;
+}
+{
+        nop.m 0
+        mov.i r36 = ar.ec
+        nop.i 0 ;;
+}
+{
+        nop.m 0
+        movl r37 = copy ;;
+}
+{
+        nop.m 0
+        nop.i 0
+        br.call.sptk.many b0 = print ;;
+}
+{
+        cmp.eq p6, p7 = 7, r36
+        mov ar.pfs = r34
+        mov b0 = r35 ;;
+}
+{
+        (p6) mov r8 = 0
+        (p7) mov r8 = 1
+        nop.i 0 ;;
+}
+{
+        nop.m 0
+        nop.i 0
+        br.ret.sptk.many b0 ;;
+}
+
+// Copy r34 words from r33 to r32, at least one
+copy_words:
+{
+        alloc r16 = ar.pfs, 3, 5, 0, 8
+        mov r17 = ar.lc
+        mov r18 = pr ;;
+}
+{
+        mov r14 = r33
+        mov r15 = r32
+        adds r19 = -1, r34 ;;
+}
+{
+        nop.m 0
+        mov.i ar.lc = r19
+        mov.i ar.ec = 3 ;;
+}
+{
+        nop.m 0
+        mov pr.rot = 0x10000
+        nop.i 0 ;;
+}
+copy_words_loop:
+{
+        (p16) ld8 r32 = [r14], 8
+        (p18) st8 [r15] = r34, 8
+        br.ctop.sptk.few copy_words_loop ;;
+}
+{
+        nop.m 0
+        mov.i ar.lc = r17
+        mov pr = r18, 0x1fffe ;;
+}
+{
+        nop.m 0
+        mov.i ar.pfs = r16
+        nop.i 0 ;;
+}
+{
+        nop.m 0
+        nop.i 0
+        br.ret.sptk.many b0 ;;
+}
+
+        .data
+        .align 8
+message:
+        .asciz "pipelined copy ok\n"
+        .align 8
+copy:
+        .quad 0
+        .quad 0
+        .quad 0
@@ -0,0 +1,113 @@
+// Check the signal mask system call
+//
+// Exits with 0 after printing "signal ok", or with the number of the check
+// that failed: 1 if blocking SIGINT fails, 2 if the old mask is not empty,
+// 3 if a mask of the wrong size is not rejected with EINVAL.
+
+        .text
+main:
+{
+        alloc r34 = ar.pfs, 2, 2, 4, 0 ;;
+        nop.m 0
+        mov r35 = b0 ;;
+}
+
+        // sigprocmask(SIG_BLOCK, &sigint, &old, 8)
+{
+        mov r36 = 0
+        movl r37 = sigint ;;
+}
+{
+        nop.m 0
+        movl r38 = old_mask ;;
+}
+{
+        mov r39 = 8
+        nop.i 0
+        br.call.sptk.many b0 = sigprocmask ;;
+}
+{
+        cmp.eq p0, p6 = r0, r8
+        mov r8 = 1
+        nop.i 0 ;;
+}
+{
+        nop.m 0
+        nop.i 0
+        (p6) br.cond.dpnt.few signal_done ;;
+}
+{
+        nop.m 0
+        movl r2 = old_mask ;;
+}
+{
+        ld8 r2 = [r2]
+        nop.i 0
+        nop.i 0 ;;
+}
+{
+        cmp.eq p0, p6 = r0, r2
+        mov r8 = 2
+        nop.i 0 ;;
+}
+{
+        nop.m 0
+        nop.i 0
+        (p6) br.cond.dpnt.few signal_done ;;
+}
+
+        // sigprocmask(SIG_BLOCK, &sigint, NULL, 4)
+{
+        mov r36 = 0
+        movl r37 = sigint ;;
+}
+{
+        mov r38 = 0
+        mov r39 = 4
+        br.call.sptk.many b0 = sigprocmask ;;
+}
+{
+        cmp.eq p0, p6 = -22, r8
+        mov r8 = 3
+        nop.i 0 ;;
+}
+{
+        nop.m 0
+        nop.i 0
+        (p6) br.cond.dpnt.few signal_done ;;
+}
+
+{
+        nop.m 0
+        movl r36 = message ;;
+}
+{
+        nop.m 0
+        nop.i 0
+        br.call.sptk.many b0 = print ;;
+}
+{
+        mov r8 = 0
+        nop.i 0
+        nop.i 0 ;;
+}
+signal_done:
+{
+        nop.m 0
+        mov ar.pfs = r34
+        mov b0 = r35 ;;
+}
+{
+        nop.m 0
+        nop.i 0
+        br.ret.sptk.many b0 ;;
+}
+
+        .data
+message:
+        .asciz "signal ok\n"
+        .align 8
+sigint:
+        .quad 2                         // 1 << (SIGINT - 1)
+old_mask:
+        .quad 0xffffffffffffffff
@@ -0,0 +1,115 @@
+// Check the thread system calls of a single-threaded process
+//
+// Exits with 0 after printing "thread ok", or with the number of the
+// check that failed: 1 if gettid disagrees with set_tid_address, 2 if a
+// futex wait on a changed word does not fail with EAGAIN, 3 if a futex
+// wake wakes anyone.
+
+        .text
+main:
+{
+        alloc r34 = ar.pfs, 2, 3, 3, 0 ;;
+        nop.m 0
+        mov r35 = b0 ;;
+}
+{
+        nop.m 0
+        movl r37 = clear_child_tid ;;
+}
+{
+        nop.m 0
+        nop.i 0
+        br.call.sptk.many b0 = set_tid_address ;;
+}
+{
+        mov r36 = r8
+        nop.i 0
+        br.call.sptk.many b0 = gettid ;;
+}
+{
+        cmp.eq p0, p6 = r8, r36
+        mov r8 = 1
+        nop.i 0 ;;
+}
+{
+        nop.m 0
+        nop.i 0
+        (p6) br.cond.dpnt.few thread_done ;;
+}
+
+        // FUTEX_WAIT_PRIVATE expecting 1 where the word holds 0
+{
+        nop.m 0
+        movl r37 = futex_word ;;
+}
+{
+        mov r38 = 128
+        mov r39 = 1
+        br.call.sptk.many b0 = futex ;;
+}
+{
+        cmp.eq p0, p6 = -11, r8
+        mov r8 = 2
+        nop.i 0 ;;
+}
+{
+        nop.m 0
+        nop.i 0
+        (p6) br.cond.dpnt.few thread_done ;;
+}
+
+        // FUTEX_WAKE_PRIVATE of one waiter
+{
+        nop.m 0
+        movl r37 = futex_word ;;
+}
+{
+        mov r38 = 129
+        mov r39 = 1
+        br.call.sptk.many b0 = futex ;;
+}
+{
+        cmp.eq p0, p6 = r0, r8
+        mov r8 = 3
+        nop.i 0 ;;
+}
+{
+        nop.m 0
+        nop.i 0
+        (p6) br.cond.dpnt.few thread_done ;;
+}
+
+{
+        nop.m 0
+        movl r37 = message ;;
+}
+{
+        nop.m 0
+        nop.i 0
+        br.call.sptk.many b0 = print ;;
+}
+{
+        mov r8 = 0
+        nop.i 0
+        nop.i 0 ;;
+}
+thread_done:
+{
+        nop.m 0
+        mov ar.pfs = r34
+        mov b0 = r35 ;;
+}
+{
+        nop.m 0
+        nop.i 0
+        br.ret.sptk.many b0 ;;
+}
+
+        .data
+message:
+        .asciz "thread ok\n"
+        .align 8
+clear_child_tid:
+        .quad 0
+futex_word:
+        .quad 0